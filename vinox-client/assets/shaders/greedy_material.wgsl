@@ -0,0 +1,68 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::fog
+#import bevy_pbr::pbr_functions
+#import bevy_pbr::pbr_ambient
+
+// Has to match GREEDY_CELL, GREEDY_PAD and GREEDY_TILE in meshing.rs
+const CELL: f32 = 32.0;
+const PAD: f32 = 8.0;
+const TILE: f32 = 16.0;
+
+struct GreedyMaterial {
+    atlas_size: vec2<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> material: GreedyMaterial;
+@group(1) @binding(1)
+var base_color_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var base_color_sampler: sampler;
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // The tile's corner in pixels, then how far across the quad this is, wrapped into the tile
+    let corner = floor(in.uv / CELL);
+    let across = fract(in.uv - corner * CELL - PAD);
+    let texel = corner + clamp(across, vec2<f32>(0.001), vec2<f32>(0.999)) * TILE;
+    // Always the full size texture, the wrap would throw the mip level off at every block edge
+    var color = textureSampleLevel(base_color_texture, base_color_sampler, texel / material.atlas_size, 0.0);
+#ifdef VERTEX_COLORS
+    color = color * in.color;
+#endif
+    if color.a < 0.5 {
+        discard;
+    }
+
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = color;
+    pbr_input.material.perceptual_roughness = 1.0;
+    pbr_input.frag_coord = in.frag_coord;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = prepare_world_normal(in.world_normal, false, in.is_front);
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = normalize(pbr_input.world_normal);
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+
+    var output_color = pbr(pbr_input);
+    if fog.mode != FOG_MODE_OFF {
+        output_color = apply_fog(output_color, in.world_position.xyz, view.world_position.xyz);
+    }
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color);
+#endif
+    return output_color;
+}
@@ -0,0 +1,20 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+struct XrayMaterial {
+    color: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> material: XrayMaterial;
+
+struct FragmentInput {
+    #import bevy_pbr::mesh_vertex_output
+}
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // Shade the faces a little so the boxes still read as boxes without any lighting
+    let shade = 0.7 + 0.3 * abs(in.world_normal.y);
+    return vec4<f32>(material.color.rgb * shade, material.color.a);
+}
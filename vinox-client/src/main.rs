@@ -1,6 +1,7 @@
 pub mod states;
 use bevy::{
     // diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    log::LogPlugin,
     pbr::wireframe::WireframePlugin,
     prelude::*,
     render::{
@@ -13,17 +14,19 @@ use bevy_quinnet::client::QuinnetClientPlugin;
 use bevy_tweening::TweeningPlugin;
 use directories::*;
 use fs_extra::dir::{copy, CopyOptions};
-use ron::de::from_reader;
 use states::{
-    components::{save_game_options, GameOptions, GameState, ProjectPath},
-    game::{plugin::GamePlugin, rendering::meshing::BasicMaterial},
+    audio::MixerPlugin,
+    components::{load_game_options, GameState, ProjectPath},
+    crash::{init_logging, install_panic_hook, CrashPlugin, CrashReportDir, PreviousCrash},
+    game::{
+        plugin::GamePlugin,
+        rendering::meshing::{BasicMaterial, GreedyMaterial},
+        world::finder::XrayMaterial,
+    },
     loading::plugin::LoadingPlugin,
     menu::plugin::MenuPlugin,
 };
-use std::{
-    fs::{create_dir_all, File},
-    path::PathBuf,
-};
+use std::{fs::create_dir_all, path::PathBuf};
 
 fn main() {
     // Eventually I will implement my own recursive copy and also not delete the assets directory for now though we will completely.
@@ -48,12 +51,13 @@ fn main() {
         path.push("assets");
         path
     };
-    let final_options = if let Some(game_options) = load_game_options(asset_path.clone()) {
-        game_options
-    } else {
-        save_game_options(GameOptions::default(), asset_path.clone());
-        GameOptions::default()
-    };
+    let crash_dir = asset_path
+        .parent()
+        .unwrap_or(&asset_path)
+        .join("crash-reports");
+    init_logging();
+    install_panic_hook(crash_dir.clone());
+    let final_options = load_game_options(&asset_path);
     App::new()
         .add_plugins(
             DefaultPlugins
@@ -81,14 +85,20 @@ fn main() {
                         features: WgpuFeatures::POLYGON_MODE_LINE,
                         ..default()
                     },
-                }),
+                })
+                // Logging is set up by init_logging so crash reports get the tail of it
+                .disable::<LogPlugin>(),
         )
         .add_plugin(WireframePlugin)
         // .add_plugin(LogDiagnosticsPlugin::default())
         // .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .insert_resource(PreviousCrash::load(&crash_dir))
+        .insert_resource(CrashReportDir(crash_dir))
         .insert_resource(ProjectPath(asset_path))
         .insert_resource(final_options)
         .add_plugin(MaterialPlugin::<BasicMaterial>::default())
+        .add_plugin(MaterialPlugin::<GreedyMaterial>::default())
+        .add_plugin(MaterialPlugin::<XrayMaterial>::default())
         .insert_resource(Msaa::Off)
         .add_plugin(QuinnetClientPlugin::default())
         .add_plugin(TweeningPlugin)
@@ -96,22 +106,7 @@ fn main() {
         .add_plugin(MenuPlugin)
         .add_plugin(LoadingPlugin)
         .add_plugin(GamePlugin)
+        .add_plugin(CrashPlugin)
+        .add_plugin(MixerPlugin)
         .run();
 }
-
-fn load_game_options(path: PathBuf) -> Option<GameOptions> {
-    let final_path = path.join("config.ron");
-    if let Ok(f) = File::open(final_path) {
-        let config: Option<GameOptions> = match from_reader(f) {
-            Ok(x) => Some(x),
-            Err(e) => {
-                println!("Failed to load config: {e}");
-                None
-            }
-        };
-        config
-    } else {
-        println!("No such directory!");
-        None
-    }
-}
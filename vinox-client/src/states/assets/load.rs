@@ -4,7 +4,10 @@ use std::collections::HashMap;
 #[derive(Resource, Default, Clone)]
 pub struct LoadableAssets {
     pub block_textures: HashMap<String, [Handle<Image>; 6]>,
+    // Keyed by the block they grow on, they go in the block atlas too
+    pub decoration_textures: HashMap<String, Handle<Image>>,
     pub item_textures: HashMap<String, Handle<Image>>,
+    pub hud_textures: HashMap<String, Handle<Image>>,
     pub entity_models: HashMap<String, Handle<Scene>>,
     pub block_atlas: Handle<TextureAtlas>,
 }
@@ -0,0 +1,622 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::{audio::AudioSink, math::DVec3, prelude::*};
+use serde::{Deserialize, Serialize};
+use vinox_common::{
+    physics::collision::raycast::{accumulate_world, blocking_weight},
+    world::chunks::{ecs::ChunkManager, positions::WorldOffset},
+};
+
+use super::{
+    components::{GameOptions, GameState},
+    game::input::player::FPSCamera,
+    menu::ui::InOptions,
+};
+
+// A sound whose sink never shows up, most likely a source that failed to load, is let go after this
+pub const PENDING_SECONDS: f32 = 10.0;
+// Closer than this a sound is heard around whatever is in the way, no ray needed
+pub const CLEAR_DISTANCE: f64 = 2.0;
+// Loops are checked again this often, one-shots only when they start
+pub const OCCLUSION_INTERVAL: f32 = 0.5;
+// Rays cast a frame across every sound, the nearest get them first
+pub const OCCLUSION_RAYS: usize = 8;
+// Past this many blocks in the way a sound is as muffled as it gets
+pub const MAX_OCCLUDERS: f32 = 8.0;
+// What's left of a sound behind MAX_OCCLUDERS blocks
+pub const FULLY_OCCLUDED: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SoundCategory {
+    BlockEffects,
+    Footsteps,
+    Ambience,
+    Ui,
+    Music,
+    // Nothing plays on it yet, kept so saved volumes line up once something does
+    Voice,
+}
+
+impl SoundCategory {
+    pub const ALL: [SoundCategory; 6] = [
+        SoundCategory::BlockEffects,
+        SoundCategory::Footsteps,
+        SoundCategory::Ambience,
+        SoundCategory::Ui,
+        SoundCategory::Music,
+        SoundCategory::Voice,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SoundCategory::BlockEffects => "Blocks",
+            SoundCategory::Footsteps => "Footsteps",
+            SoundCategory::Ambience => "Ambience",
+            SoundCategory::Ui => "Interface",
+            SoundCategory::Music => "Music",
+            SoundCategory::Voice => "Voice",
+        }
+    }
+
+    // Sounds from the world, the menu muffle applies to these
+    pub fn is_world(self) -> bool {
+        !matches!(self, SoundCategory::Ui | SoundCategory::Music)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioOptions {
+    pub master: f32,
+    // Missing categories play at full volume
+    pub categories: BTreeMap<SoundCategory, f32>,
+    // World sounds get multiplied by this while a menu is up
+    pub menu_muffle: f32,
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            categories: SoundCategory::ALL
+                .into_iter()
+                .map(|category| (category, 1.0))
+                .collect(),
+            menu_muffle: 0.3,
+        }
+    }
+}
+
+impl AudioOptions {
+    pub fn category(&self, category: SoundCategory) -> f32 {
+        self.categories.get(&category).copied().unwrap_or(1.0)
+    }
+
+    pub fn category_mut(&mut self, category: SoundCategory) -> &mut f32 {
+        self.categories.entry(category).or_insert(1.0)
+    }
+}
+
+// A sound on `trigger` pulls `target` down by `depth` for `hold` seconds, then eases it back
+// over `release`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuckRule {
+    pub trigger: SoundCategory,
+    pub target: SoundCategory,
+    pub depth: f32,
+    pub hold: f32,
+    pub release: f32,
+}
+
+pub const DUCK_RULES: &[DuckRule] = &[DuckRule {
+    trigger: SoundCategory::Ui,
+    target: SoundCategory::Ambience,
+    depth: 0.5,
+    hold: 0.5,
+    release: 0.25,
+}];
+
+impl DuckRule {
+    pub fn factor(&self, elapsed: f32) -> f32 {
+        if elapsed < self.hold {
+            return 1.0 - self.depth;
+        }
+        let t = ((elapsed - self.hold) / self.release.max(f32::EPSILON)).clamp(0.0, 1.0);
+        1.0 - self.depth * (1.0 - t * t * (3.0 - 2.0 * t))
+    }
+
+    pub fn done(&self, elapsed: f32) -> bool {
+        elapsed >= self.hold + self.release
+    }
+}
+
+// The volume left after this many blocks' worth of occluders, see blocking_weight. The first
+// walls take away the most. Bevy's sinks can't filter so there's no lowpass to go with it
+pub fn occlusion_volume(occluders: f32) -> f32 {
+    let t = (occluders / MAX_OCCLUDERS).clamp(0.0, 1.0);
+    FULLY_OCCLUDED + (1.0 - FULLY_OCCLUDED) * (1.0 - t).powi(2)
+}
+
+pub struct PlaySound {
+    pub source: Handle<AudioSource>,
+    pub category: SoundCategory,
+    pub volume: f32,
+    pub looped: bool,
+    // In world space, None for sounds that aren't anywhere like the interface's
+    pub position: Option<DVec3>,
+}
+
+impl PlaySound {
+    pub fn new(source: Handle<AudioSource>, category: SoundCategory) -> Self {
+        Self {
+            source,
+            category,
+            volume: 1.0,
+            looped: false,
+            position: None,
+        }
+    }
+
+    pub fn looped(self) -> Self {
+        Self {
+            looped: true,
+            ..self
+        }
+    }
+
+    pub fn at(self, position: DVec3) -> Self {
+        Self {
+            position: Some(position),
+            ..self
+        }
+    }
+}
+
+// A sound that comes from somewhere in the world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Emitter {
+    pub position: DVec3,
+    pub looped: bool,
+}
+
+struct Tracked<T> {
+    sink: T,
+    volume: f32,
+    queued_at: f32,
+    seen: bool,
+    emitter: Option<Emitter>,
+    // Volume left after what's between it and the listener, 1 until it's been checked
+    occlusion: f32,
+    occluded_at: Option<f32>,
+}
+
+// Everything still playing by category, so volume changes reach loops that started long ago.
+// Generic over the handle so it can be tested without an audio device
+pub struct ActiveSounds<T> {
+    sounds: HashMap<SoundCategory, Vec<Tracked<T>>>,
+}
+
+impl<T> Default for ActiveSounds<T> {
+    fn default() -> Self {
+        Self {
+            sounds: HashMap::new(),
+        }
+    }
+}
+
+impl<T> ActiveSounds<T> {
+    pub fn insert(
+        &mut self,
+        category: SoundCategory,
+        sink: T,
+        volume: f32,
+        now: f32,
+        emitter: Option<Emitter>,
+    ) {
+        self.sounds.entry(category).or_default().push(Tracked {
+            sink,
+            volume,
+            queued_at: now,
+            seen: false,
+            emitter,
+            occlusion: 1.0,
+            occluded_at: None,
+        });
+    }
+
+    // Sounds that just started are due a check, loops again every OCCLUSION_INTERVAL. Nearest
+    // first and no more than `rays` of them, the rest wait for a later frame. `occluders` is how
+    // much is between the listener and a position
+    pub fn occlude(
+        &mut self,
+        listener: DVec3,
+        now: f32,
+        rays: usize,
+        mut occluders: impl FnMut(DVec3) -> f32,
+    ) {
+        let mut due: Vec<(f64, &mut Tracked<T>)> = self
+            .sounds
+            .values_mut()
+            .flatten()
+            .filter_map(|sound| {
+                let emitter = sound.emitter?;
+                let due = match sound.occluded_at {
+                    None => true,
+                    Some(at) => emitter.looped && now - at >= OCCLUSION_INTERVAL,
+                };
+                due.then(|| (listener.distance(emitter.position), sound))
+            })
+            .collect();
+        due.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        let mut cast = 0;
+        for (distance, sound) in due {
+            if distance <= CLEAR_DISTANCE {
+                sound.occlusion = 1.0;
+            } else if cast < rays {
+                cast += 1;
+                let position = sound.emitter.map_or(listener, |emitter| emitter.position);
+                sound.occlusion = occlusion_volume(occluders(position));
+            } else {
+                continue;
+            }
+            sound.occluded_at = Some(now);
+        }
+    }
+
+    // `finished` is None while the sink doesn't exist, which is normal until its source loads
+    pub fn prune(&mut self, now: f32, mut finished: impl FnMut(&T) -> Option<bool>) {
+        for sounds in self.sounds.values_mut() {
+            sounds.retain_mut(|sound| match finished(&sound.sink) {
+                Some(finished) => {
+                    sound.seen = true;
+                    !finished
+                }
+                None => !sound.seen && now - sound.queued_at < PENDING_SECONDS,
+            });
+        }
+        self.sounds.retain(|_, sounds| !sounds.is_empty());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SoundCategory, &T, f32)> {
+        self.sounds.iter().flat_map(|(category, sounds)| {
+            sounds
+                .iter()
+                .map(|sound| (*category, &sound.sink, sound.volume * sound.occlusion))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.sounds.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sounds.is_empty()
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Mixer {
+    pub sounds: ActiveSounds<Handle<AudioSink>>,
+    // When each duck rule last went off, by its index in DUCK_RULES
+    ducks: HashMap<usize, f32>,
+    pub muffled: bool,
+}
+
+impl Mixer {
+    pub fn trigger(&mut self, category: SoundCategory, now: f32) {
+        for (index, rule) in DUCK_RULES.iter().enumerate() {
+            if rule.trigger == category {
+                self.ducks.insert(index, now);
+            }
+        }
+    }
+
+    pub fn duck(&self, category: SoundCategory, now: f32) -> f32 {
+        self.ducks
+            .iter()
+            .filter(|(index, _)| DUCK_RULES[**index].target == category)
+            .map(|(index, started)| DUCK_RULES[*index].factor(now - started))
+            .fold(1.0, f32::min)
+    }
+
+    // master × category × duck × muffle
+    pub fn volume(&self, options: &AudioOptions, category: SoundCategory, now: f32) -> f32 {
+        let muffle = if self.muffled && category.is_world() {
+            options.menu_muffle
+        } else {
+            1.0
+        };
+        options.master * options.category(category) * self.duck(category, now) * muffle
+    }
+
+    fn forget_ducks(&mut self, now: f32) {
+        self.ducks
+            .retain(|index, started| !DUCK_RULES[*index].done(now - *started));
+    }
+}
+
+pub fn play_sounds(
+    mut events: EventReader<PlaySound>,
+    audio: Res<Audio>,
+    sinks: Res<Assets<AudioSink>>,
+    mut mixer: ResMut<Mixer>,
+    options: Res<GameOptions>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds();
+    for event in events.iter() {
+        mixer.trigger(event.category, now);
+        let settings = if event.looped {
+            PlaybackSettings::LOOP
+        } else {
+            PlaybackSettings::ONCE
+        };
+        let volume = event.volume * mixer.volume(&options.audio, event.category, now);
+        let sink = audio.play_with_settings(event.source.clone(), settings.with_volume(volume));
+        // Strong so the sink sticks around until we're done with it
+        let sink = sinks.get_handle(sink);
+        let emitter = event.position.map(|position| Emitter {
+            position,
+            looped: event.looped,
+        });
+        mixer
+            .sounds
+            .insert(event.category, sink, event.volume, now, emitter);
+    }
+}
+
+// From the camera to each sound through the loaded world, the voxel the sound is in doesn't
+// count since that's usually the block making it
+pub fn occlude_sounds(
+    mut mixer: ResMut<Mixer>,
+    camera: Query<&GlobalTransform, With<FPSCamera>>,
+    chunk_manager: ChunkManager,
+    offset: Res<WorldOffset>,
+    time: Res<Time>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let listener = offset.to_world(camera.translation());
+    mixer.sounds.occlude(
+        listener,
+        time.elapsed_seconds(),
+        OCCLUSION_RAYS,
+        |position| {
+            let source = position.floor().as_ivec3();
+            let span = position - listener;
+            accumulate_world(
+                listener,
+                span.as_vec3(),
+                span.length() as f32,
+                MAX_OCCLUDERS,
+                &chunk_manager,
+                |voxel, descriptor| {
+                    if voxel == source {
+                        0.0
+                    } else {
+                        blocking_weight(descriptor)
+                    }
+                },
+            )
+        },
+    );
+}
+
+// Menus muffle the world, in game or on the title screen
+pub fn update_muffle(
+    mut mixer: ResMut<Mixer>,
+    in_options: Res<InOptions>,
+    state: Res<State<GameState>>,
+) {
+    mixer.muffled = **in_options || state.0 != GameState::Game;
+}
+
+pub fn apply_volumes(
+    mut mixer: ResMut<Mixer>,
+    sinks: Res<Assets<AudioSink>>,
+    options: Res<GameOptions>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds();
+    mixer.forget_ducks(now);
+    mixer
+        .sounds
+        .prune(now, |sink| sinks.get(sink).map(AudioSink::empty));
+    for (category, sink, volume) in mixer.sounds.iter() {
+        if let Some(sink) = sinks.get(sink) {
+            sink.set_volume(volume * mixer.volume(&options.audio, category, now));
+        }
+    }
+}
+
+pub struct MixerPlugin;
+
+impl Plugin for MixerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Mixer::default())
+            .add_event::<PlaySound>()
+            .add_systems((play_sounds, update_muffle, apply_volumes).chain())
+            // Before the volumes go out so a new sound never starts unmuffled behind a wall
+            .add_system(
+                occlude_sounds
+                    .after(play_sounds)
+                    .before(apply_volumes)
+                    .run_if(in_state(GameState::Game)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_multiplies_every_layer() {
+        let mut options = AudioOptions {
+            master: 0.8,
+            ..Default::default()
+        };
+        *options.category_mut(SoundCategory::Ambience) = 0.5;
+        let mut mixer = Mixer::default();
+        assert_eq!(mixer.volume(&options, SoundCategory::Ambience, 0.0), 0.4);
+        assert_eq!(mixer.volume(&options, SoundCategory::Music, 0.0), 0.8);
+
+        mixer.trigger(SoundCategory::Ui, 1.0);
+        assert_eq!(mixer.volume(&options, SoundCategory::Ambience, 1.2), 0.2);
+        // Only what the rule targets gets ducked
+        assert_eq!(mixer.volume(&options, SoundCategory::Footsteps, 1.2), 0.8);
+
+        mixer.muffled = true;
+        let muffled = 0.2 * options.menu_muffle;
+        assert!((mixer.volume(&options, SoundCategory::Ambience, 1.2) - muffled).abs() < 1e-6);
+        assert_eq!(mixer.volume(&options, SoundCategory::Ui, 1.2), 0.8);
+        assert_eq!(mixer.volume(&options, SoundCategory::Music, 1.2), 0.8);
+    }
+
+    #[test]
+    fn ducks_hold_then_ease_back() {
+        let rule = DUCK_RULES[0];
+        assert_eq!(rule.factor(0.0), 0.5);
+        assert_eq!(rule.factor(rule.hold - 0.01), 0.5);
+        let mut last = rule.factor(rule.hold);
+        for step in 1..=10 {
+            let factor = rule.factor(rule.hold + rule.release * step as f32 / 10.0);
+            assert!(factor >= last);
+            last = factor;
+        }
+        assert_eq!(last, 1.0);
+        // Halfway through the release it's halfway back
+        assert!((rule.factor(rule.hold + rule.release / 2.0) - 0.75).abs() < 1e-6);
+        assert!(!rule.done(rule.hold));
+        assert!(rule.done(rule.hold + rule.release));
+
+        let mut mixer = Mixer::default();
+        mixer.trigger(SoundCategory::Ui, 0.0);
+        mixer.forget_ducks(rule.hold + rule.release);
+        assert!(mixer.ducks.is_empty());
+        assert_eq!(mixer.duck(SoundCategory::Ambience, 0.0), 1.0);
+    }
+
+    #[test]
+    fn finished_sounds_are_let_go() {
+        // true once finished, missing while its source is still loading
+        let mut sinks: HashMap<u32, bool> = HashMap::new();
+        let mut sounds = ActiveSounds::default();
+        for sink in 0..100 {
+            sounds.insert(SoundCategory::BlockEffects, sink, 1.0, 0.0, None);
+            sinks.insert(sink, false);
+        }
+        sounds.insert(SoundCategory::Music, 100, 1.0, 0.0, None);
+        // Never loads
+        sounds.insert(SoundCategory::Ui, 101, 1.0, 0.0, None);
+        sounds.prune(1.0, |sink| sinks.get(sink).copied());
+        assert_eq!(sounds.len(), 102);
+
+        for sink in 0..100 {
+            sinks.insert(sink, true);
+        }
+        sinks.insert(100, false);
+        sounds.prune(2.0, |sink| sinks.get(sink).copied());
+        assert_eq!(sounds.len(), 2);
+
+        // The loop keeps playing, the one that never loaded times out
+        sounds.prune(PENDING_SECONDS, |sink| sinks.get(sink).copied());
+        let left: Vec<_> = sounds.iter().map(|(_, sink, _)| *sink).collect();
+        assert_eq!(left, vec![100]);
+
+        // A sink that disappears after playing counts as done too
+        sinks.remove(&100);
+        sounds.prune(PENDING_SECONDS, |sink| sinks.get(sink).copied());
+        assert!(sounds.is_empty());
+    }
+
+    #[test]
+    fn more_in_the_way_is_never_louder() {
+        assert_eq!(occlusion_volume(0.0), 1.0);
+        let mut last = 1.0;
+        for step in 1..=100 {
+            let volume = occlusion_volume(step as f32 * 0.1);
+            assert!(volume <= last, "{volume} after {last}");
+            last = volume;
+        }
+        assert_eq!(occlusion_volume(MAX_OCCLUDERS), FULLY_OCCLUDED);
+        assert_eq!(occlusion_volume(MAX_OCCLUDERS * 3.0), FULLY_OCCLUDED);
+        // A single wall is already noticeable, a slab or glass less so
+        assert!(occlusion_volume(1.0) < 0.9);
+        assert!(occlusion_volume(0.5) > occlusion_volume(1.0));
+    }
+
+    #[test]
+    fn occlusion_rays_go_to_the_nearest_first() {
+        let mut sounds = ActiveSounds::default();
+        let emitter = |x: f64, looped: bool| {
+            Some(Emitter {
+                position: DVec3::new(x, 0.0, 0.0),
+                looped,
+            })
+        };
+        // Walled off the same at every distance
+        let walls = |_: DVec3| 2.0;
+        for (sink, x) in [(0, 30.0), (1, 10.0), (2, 20.0)] {
+            sounds.insert(
+                SoundCategory::BlockEffects,
+                sink,
+                1.0,
+                0.0,
+                emitter(x, false),
+            );
+        }
+        sounds.insert(SoundCategory::Ambience, 3, 1.0, 0.0, emitter(40.0, true));
+        // Right next to the listener and without a position, neither needs a ray
+        sounds.insert(
+            SoundCategory::BlockEffects,
+            4,
+            1.0,
+            0.0,
+            emitter(1.5, false),
+        );
+        sounds.insert(SoundCategory::Ui, 5, 1.0, 0.0, None);
+        let volumes = |sounds: &ActiveSounds<u32>| {
+            let mut volumes: Vec<(u32, f32)> = sounds
+                .iter()
+                .map(|(_, sink, volume)| (*sink, volume))
+                .collect();
+            volumes.sort_by_key(|(sink, _)| *sink);
+            volumes
+                .into_iter()
+                .map(|(_, volume)| volume)
+                .collect::<Vec<_>>()
+        };
+
+        let mut cast = Vec::new();
+        sounds.occlude(DVec3::ZERO, 0.0, 2, |position| {
+            cast.push(position.x);
+            walls(position)
+        });
+        assert_eq!(cast, vec![10.0, 20.0]);
+        let muffled = occlusion_volume(2.0);
+        assert_eq!(volumes(&sounds), vec![1.0, muffled, muffled, 1.0, 1.0, 1.0]);
+
+        // The rest get theirs next frame, nothing gets checked twice
+        cast.clear();
+        sounds.occlude(DVec3::ZERO, 0.1, 2, |position| {
+            cast.push(position.x);
+            walls(position)
+        });
+        assert_eq!(cast, vec![30.0, 40.0]);
+        cast.clear();
+        sounds.occlude(DVec3::ZERO, 0.2, 2, |position| {
+            cast.push(position.x);
+            walls(position)
+        });
+        assert!(cast.is_empty());
+        // Only the loop comes up again
+        sounds.occlude(DVec3::ZERO, 1.0, 8, |position| {
+            cast.push(position.x);
+            0.0
+        });
+        assert_eq!(cast, vec![40.0]);
+        assert_eq!(volumes(&sounds)[3], 1.0);
+    }
+}
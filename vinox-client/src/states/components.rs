@@ -1,11 +1,37 @@
 use ron::ser::{to_string_pretty, PrettyConfig};
-use std::io::Write;
-use std::{fs::File, path::PathBuf};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
 
 use leafwing_input_manager::prelude::*;
 
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
+use vinox_common::world::chunks::storage::{
+    HORIZONTAL_DISTANCE, MAX_VIEW_DISTANCE, MIN_VIEW_DISTANCE,
+};
+
+use super::{
+    audio::AudioOptions,
+    game::{
+        input::look::RAW_INPUT_SUPPORTED,
+        networking::chunk_cache::DEFAULT_CACHE_MIB,
+        rendering::autotune::DEFAULT_FRAME_TARGET,
+        ui::{crosshair::CrosshairStyle, notifications::NotificationRoutes},
+    },
+};
+
+// What the options sliders allow, a hand edited config gets pulled back into them on load
+pub const FOV_RANGE: RangeInclusive<f32> = 60.0..=120.0;
+pub const SENSITIVITY_RANGE: RangeInclusive<f32> = 0.1..=5.0;
+pub const FRAME_TARGET_RANGE: RangeInclusive<f32> = 4.0..=50.0;
+pub const VIEW_DISTANCE_RANGE: RangeInclusive<usize> = MIN_VIEW_DISTANCE..=MAX_VIEW_DISTANCE;
+pub const DECORATION_DENSITY_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+pub const THIRD_PERSON_RANGE: RangeInclusive<f32> = 1.0..=8.0;
+pub const FLY_SPEED_RANGE: RangeInclusive<f32> = 0.5..=4.0;
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct ProjectPath(pub PathBuf);
@@ -18,10 +44,22 @@ pub enum GameState {
     Game,
 }
 
+// The broad stages of a game frame, chained in this order
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameSet {
+    Input,
+    Networking,
+    WorldUpdate,
+    Meshing,
+    RenderPrep,
+    Ui,
+}
+
 #[derive(Default, Component, Clone)]
 pub struct Menu;
+// Anything spawned while connected, all of it is despawned when we leave the game
 #[derive(Default, Component, Clone)]
-pub struct Game;
+pub struct SessionScoped;
 #[derive(Default, Component, Clone)]
 pub struct Loading;
 
@@ -39,17 +77,97 @@ pub enum GameActions {
     SecondaryInteract,
     Run,
     Inventory,
+    Sneak,
+    ToggleFly,
+    Palette,
+    Profiler,
+    DropItem,
+    SelectVariant,
+    Encyclopedia,
+    // Held while placing to keep building on one line, with Run on one plane
+    BuildLock,
+    // The targeted block's item, with ctrl an exact copy of the block
+    PickBlock,
+    // Secondary interact with the utility slot, whatever is selected
+    QuickUse,
+    // First person, then behind, then in front
+    ToggleCamera,
+    // Flying through blocks, where the server allows it
+    ToggleNoclip,
 }
 
 #[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GameOptions {
     pub input: InputMap<GameActions>,
     pub fov: f32,
+    // Blocks out from the eyes the camera sits in third person, closer when something's in the way
+    pub third_person_distance: f32,
+    // Chunks loaded out from the player horizontally, the fog moves with it
+    pub view_distance: usize,
+    // Multiplies how far a count of mouse movement turns the camera, see input::look
+    pub mouse_sensitivity: f32,
+    // Mouse look from the device instead of the OS pointer, so pointer speed and acceleration
+    // settings don't apply. Only makes a difference where the cursor can't be locked
+    pub raw_mouse_input: bool,
+    // Eases the mouse look in over a few frames instead of turning by exactly what it moved
+    pub mouse_smoothing: bool,
+    // Multiplies how fast flying goes sideways, up and down stay the same
+    pub fly_speed: f32,
     pub dark_theme: bool,
     pub user_name: String,
     pub standard_bar: bool,
     pub meshes_frame: usize,
+    // Chunk meshes being built off the main thread at once, 0 is one per CPU
+    pub mesh_tasks: usize,
     pub vsync: bool,
+    pub show_hud: bool,
+    pub hud_scale: f32,
+    // Multiplies every text size, on top of egui's scale factor
+    pub ui_text_scale: f32,
+    pub reduce_motion: bool,
+    // Widens the FOV a little while sprinting. Reduce motion turns it off too
+    pub sprint_fov: bool,
+    // Held item on the left. Other players see the arm mirrored too
+    pub left_handed: bool,
+    // Darkens corners where blocks meet, changing it remeshes every loaded chunk
+    pub ambient_occlusion: bool,
+    // New chunks rise into place instead of popping in
+    pub chunk_fade_in: bool,
+    // Merges matching faces of plain opaque blocks into bigger quads, far fewer vertices on flat
+    // ground. Changing it remeshes every loaded chunk
+    pub greedy_meshing: bool,
+    // Grass tufts and the like on top of blocks that have them, only ever part of the mesh.
+    // The density multiplies each block's own, changing either remeshes every loaded chunk
+    pub decorations: bool,
+    pub decoration_density: f32,
+    // Turns the fade-in, ambient occlusion, meshes per frame and the view distance down while
+    // frames take longer than frame_target_ms, without changing them here
+    pub auto_tune: bool,
+    pub frame_target_ms: f32,
+    // Seconds to wait for the server to connect and take our join before giving up
+    pub connect_timeout: f32,
+    // Seconds /find results stay highlighted unless cleared first
+    pub find_highlight: f32,
+    // Seconds clicks are ignored for after closing a UI, focusing the window or respawning
+    pub interaction_grace: f32,
+    // Where each kind of chat line shows up
+    pub notifications: NotificationRoutes,
+    // Muted player names, keyed by the server address they were muted on
+    pub muted: HashMap<String, Vec<String>>,
+    // Soft cap on estimated GPU memory in MiB, 0 works it out from the system's memory
+    pub memory_cap_mib: u32,
+    // Keeps chunks from servers on disk so rejoining only downloads what changed
+    pub chunk_cache: bool,
+    // Least recently used chunks go once the cache is over this
+    pub chunk_cache_mib: u32,
+    pub crosshair: CrosshairStyle,
+    pub audio: AudioOptions,
+    // First time tips from hints.ron, each one only ever shows once
+    pub hints: bool,
+    pub shown_hints: BTreeSet<String>,
+    // Hotbar slot from 0 to 8 that QuickUse acts with, a torch or food kept at hand
+    pub utility_slot: Option<usize>,
 }
 
 impl Default for GameOptions {
@@ -63,19 +181,65 @@ impl Default for GameOptions {
             (KeyCode::E, GameActions::Inventory),
             (KeyCode::Space, GameActions::Jump),
             (KeyCode::LShift, GameActions::Run),
+            (KeyCode::LControl, GameActions::Sneak),
+            (KeyCode::G, GameActions::ToggleFly),
+            (KeyCode::C, GameActions::Palette),
+            (KeyCode::Q, GameActions::DropItem),
+            (KeyCode::V, GameActions::SelectVariant),
+            (KeyCode::J, GameActions::Encyclopedia),
+            (KeyCode::LAlt, GameActions::BuildLock),
+            (KeyCode::X, GameActions::QuickUse),
+            (KeyCode::F5, GameActions::ToggleCamera),
+            (KeyCode::N, GameActions::ToggleNoclip),
         ]);
 
         input.insert(MouseButton::Left, GameActions::PrimaryInteract);
         input.insert(MouseButton::Right, GameActions::SecondaryInteract);
+        input.insert(MouseButton::Middle, GameActions::PickBlock);
+        input.insert(GamepadButtonType::LeftTrigger, GameActions::SelectVariant);
+        input.insert_chord([KeyCode::F3, KeyCode::P], GameActions::Profiler);
 
         GameOptions {
             input,
             fov: 70.0,
+            third_person_distance: 4.0,
+            view_distance: HORIZONTAL_DISTANCE,
+            mouse_sensitivity: 1.0,
+            raw_mouse_input: RAW_INPUT_SUPPORTED,
+            mouse_smoothing: false,
+            fly_speed: 1.0,
             dark_theme: true,
             user_name: "User".to_string(),
             standard_bar: true,
             meshes_frame: 256,
+            mesh_tasks: 0,
             vsync: true,
+            show_hud: true,
+            hud_scale: 1.0,
+            ui_text_scale: 1.0,
+            reduce_motion: false,
+            sprint_fov: true,
+            left_handed: false,
+            ambient_occlusion: true,
+            chunk_fade_in: true,
+            greedy_meshing: false,
+            decorations: true,
+            decoration_density: 1.0,
+            auto_tune: false,
+            frame_target_ms: DEFAULT_FRAME_TARGET,
+            connect_timeout: 10.0,
+            find_highlight: 30.0,
+            interaction_grace: 0.15,
+            notifications: NotificationRoutes::default(),
+            muted: HashMap::new(),
+            memory_cap_mib: 0,
+            chunk_cache: false,
+            chunk_cache_mib: DEFAULT_CACHE_MIB,
+            crosshair: CrosshairStyle::default(),
+            audio: AudioOptions::default(),
+            hints: true,
+            shown_hints: BTreeSet::new(),
+            utility_slot: None,
         }
     }
 }
@@ -86,14 +250,147 @@ pub fn despawn_with<T: Component>(mut commands: Commands, q: Query<Entity, With<
     }
 }
 
+// Written next to the old file and moved over it, quitting halfway through a save can't leave
+// a config that doesn't parse
 pub fn save_game_options(options: GameOptions, path: PathBuf) {
     let final_path = path.join("config.ron");
-    if let Ok(mut output) = File::create(final_path) {
-        let pretty = PrettyConfig::new()
-            .depth_limit(2)
-            .separate_tuple_members(true)
-            .enumerate_arrays(true);
-        let s = to_string_pretty(&options, pretty).ok().unwrap();
-        write!(output, "{s}").ok();
+    let temp_path = path.join("config.ron.tmp");
+    let pretty = PrettyConfig::new()
+        .depth_limit(2)
+        .separate_tuple_members(true)
+        .enumerate_arrays(true);
+    let Ok(s) = to_string_pretty(&options, pretty) else {
+        return;
+    };
+    if let Err(e) = fs::write(&temp_path, s).and_then(|_| fs::rename(&temp_path, &final_path)) {
+        println!("Couldn't save the config: {e}");
+    }
+}
+
+// Missing gets the defaults written out. One that doesn't parse is moved to config.ron.bad
+// instead of being saved over, so hand edits aren't lost
+pub fn load_game_options(path: &Path) -> GameOptions {
+    let final_path = path.join("config.ron");
+    let Ok(text) = fs::read_to_string(&final_path) else {
+        save_game_options(GameOptions::default(), path.to_path_buf());
+        return GameOptions::default();
+    };
+    match ron::from_str::<GameOptions>(&text) {
+        Ok(mut options) => {
+            options.fov = options.fov.clamp(*FOV_RANGE.start(), *FOV_RANGE.end());
+            options.third_person_distance = options
+                .third_person_distance
+                .clamp(*THIRD_PERSON_RANGE.start(), *THIRD_PERSON_RANGE.end());
+            options.fly_speed = options
+                .fly_speed
+                .clamp(*FLY_SPEED_RANGE.start(), *FLY_SPEED_RANGE.end());
+            options.view_distance = options
+                .view_distance
+                .clamp(*VIEW_DISTANCE_RANGE.start(), *VIEW_DISTANCE_RANGE.end());
+            options.mouse_sensitivity = options
+                .mouse_sensitivity
+                .clamp(*SENSITIVITY_RANGE.start(), *SENSITIVITY_RANGE.end());
+            options.frame_target_ms = options
+                .frame_target_ms
+                .clamp(*FRAME_TARGET_RANGE.start(), *FRAME_TARGET_RANGE.end());
+            options.decoration_density = options.decoration_density.clamp(
+                *DECORATION_DENSITY_RANGE.start(),
+                *DECORATION_DENSITY_RANGE.end(),
+            );
+            options
+        }
+        Err(e) => {
+            println!("Failed to load config, using the defaults: {e}");
+            fs::rename(&final_path, path.join("config.ron.bad")).ok();
+            save_game_options(GameOptions::default(), path.to_path_buf());
+            GameOptions::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vinox-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn as_ron(options: &GameOptions) -> String {
+        ron::to_string(options).unwrap()
+    }
+
+    #[test]
+    fn options_round_trip() {
+        let dir = config_dir("options");
+        let mut options = GameOptions {
+            fov: 95.0,
+            mouse_sensitivity: 0.4,
+            vsync: false,
+            standard_bar: false,
+            shown_hints: BTreeSet::from(["movement".to_string()]),
+            ..Default::default()
+        };
+        options.input.clear_action(GameActions::Jump);
+        options
+            .input
+            .insert(MouseButton::Other(4), GameActions::Jump);
+        options.input.clear_action(GameActions::Forward);
+        options.input.insert(KeyCode::Up, GameActions::Forward);
+        options
+            .input
+            .insert_chord([KeyCode::LControl, KeyCode::Q], GameActions::DropItem);
+        save_game_options(options.clone(), dir.clone());
+        let loaded = load_game_options(&dir);
+        assert_eq!(loaded.input, options.input);
+        assert_eq!(as_ron(&loaded), as_ron(&options));
+        assert!(!dir.join("config.ron.tmp").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn bad_configs_fall_back_to_the_defaults() {
+        let dir = config_dir("bad-options");
+        // Missing, the defaults get written out
+        assert_eq!(
+            as_ron(&load_game_options(&dir)),
+            as_ron(&GameOptions::default())
+        );
+        assert!(dir.join("config.ron").exists());
+
+        fs::write(dir.join("config.ron"), "(fov: 90.0, input: oops").unwrap();
+        assert_eq!(
+            as_ron(&load_game_options(&dir)),
+            as_ron(&GameOptions::default())
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("config.ron.bad")).unwrap(),
+            "(fov: 90.0, input: oops"
+        );
+
+        // Anything left out keeps its default
+        fs::write(dir.join("config.ron"), "(fov: 100.0)").unwrap();
+        let loaded = load_game_options(&dir);
+        assert_eq!(loaded.fov, 100.0);
+        assert_eq!(loaded.input, GameOptions::default().input);
+
+        fs::write(
+            dir.join("config.ron"),
+            "(fov: 500.0, mouse_sensitivity: 0.0, view_distance: 100, decoration_density: -1.0)",
+        )
+        .unwrap();
+        let loaded = load_game_options(&dir);
+        assert_eq!(loaded.fov, *FOV_RANGE.end());
+        assert_eq!(loaded.mouse_sensitivity, *SENSITIVITY_RANGE.start());
+        assert_eq!(loaded.view_distance, *VIEW_DISTANCE_RANGE.end());
+        assert_eq!(loaded.decoration_density, *DECORATION_DENSITY_RANGE.start());
+
+        // Configs saved under the old wider range come back at the new minimum
+        fs::write(dir.join("config.ron"), "(fov: 30.0)").unwrap();
+        assert_eq!(load_game_options(&dir).fov, *FOV_RANGE.start());
+        fs::remove_dir_all(&dir).ok();
     }
 }
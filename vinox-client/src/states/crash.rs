@@ -0,0 +1,505 @@
+use std::{
+    any::{Any, TypeId},
+    backtrace::Backtrace,
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{HashSet, VecDeque},
+    fmt::{self, Write as _},
+    fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, PoisonError,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    ecs::{archetype::ArchetypeComponentId, component::ComponentId, query::Access},
+    prelude::*,
+    utils::tracing::{
+        field::{Field, Visit},
+        subscriber, Event, Subscriber,
+    },
+};
+use bevy_egui::{egui, EguiContexts};
+use tracing_log::LogTracer;
+use tracing_subscriber::{layer::Context, prelude::*, EnvFilter, Layer, Registry};
+
+use super::{components::GameState, game::session::SessionApp};
+
+// How much of the log goes into a crash report
+pub const LOG_LINES: usize = 200;
+// Holds the file name of a report nobody has looked at yet
+const UNSEEN_MARKER: &str = "unseen";
+
+// Only systems wrapped with recoverable() in the main world can be caught. Panics in
+// task pool work (chunk building, meshing), the render world or anything unwrapped still
+// reach bevy's executor and take the whole client down, those go through the panic hook
+// and leave a report behind instead. Edits go to the server as they're made so there's no
+// local journal to flush first.
+static CAUGHT: Mutex<Vec<SubsystemCrash>> = Mutex::new(Vec::new());
+static LOG_TAIL: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static REPORT_WRITTEN: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static IN_RECOVERABLE: Cell<bool> = const { Cell::new(false) };
+    static LAST_BACKTRACE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubsystemCrash {
+    pub set: String,
+    pub system: String,
+    pub message: String,
+    pub backtrace: String,
+}
+
+// Everything caught this session, the banner shows whatever hasn't been dismissed
+#[derive(Resource, Default, Debug)]
+pub struct CrashReport {
+    pub crashes: Vec<SubsystemCrash>,
+    pub dismissed: usize,
+}
+
+// Sets that panicked, checked by the run condition every recoverable set gets
+#[derive(Resource, Default, Debug, Deref, DerefMut)]
+pub struct PoisonedSets(pub HashSet<String>);
+
+#[derive(Resource, Clone, Deref)]
+pub struct CrashReportDir(pub PathBuf);
+
+// A report left behind by the last run, shown on the menu until dismissed
+#[derive(Resource, Default)]
+pub struct PreviousCrash {
+    pub report: Option<String>,
+    pub expanded: bool,
+}
+
+impl PreviousCrash {
+    pub fn load(dir: &Path) -> Self {
+        let report = fs::read_to_string(dir.join(UNSEEN_MARKER))
+            .ok()
+            .and_then(|name| fs::read_to_string(dir.join(name.trim())).ok());
+        PreviousCrash {
+            report,
+            expanded: false,
+        }
+    }
+}
+
+fn set_key(set: &dyn SystemSet) -> String {
+    format!("{set:?}")
+}
+
+pub trait RecoverableApp {
+    // Systems in the set that are wrapped with recoverable() disable the set when they panic
+    fn recoverable_set(&mut self, set: impl SystemSet) -> &mut Self;
+}
+
+impl RecoverableApp for App {
+    fn recoverable_set(&mut self, set: impl SystemSet) -> &mut Self {
+        let key = set_key(&set);
+        self.init_resource::<PoisonedSets>()
+            .configure_set(set.run_if(move |poisoned: Res<PoisonedSets>| !poisoned.contains(&key)))
+    }
+}
+
+pub trait RecoverableSystem<Marker>: IntoSystem<(), (), Marker> + Sized {
+    fn recoverable(self, set: impl SystemSet) -> Recoverable<Self::System> {
+        Recoverable {
+            set: set_key(&set),
+            system: IntoSystem::into_system(self),
+        }
+    }
+}
+
+impl<Marker, T: IntoSystem<(), (), Marker>> RecoverableSystem<Marker> for T {}
+
+// Runs the inner system under catch_unwind so a panic poisons its set instead of
+// reaching the executor
+pub struct Recoverable<S> {
+    set: String,
+    system: S,
+}
+
+impl<S: System<In = (), Out = ()>> Recoverable<S> {
+    fn catch(&mut self, run: impl FnOnce(&mut S)) {
+        IN_RECOVERABLE.with(|flag| flag.set(true));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| run(&mut self.system)));
+        IN_RECOVERABLE.with(|flag| flag.set(false));
+        if let Err(payload) = result {
+            let crash = SubsystemCrash {
+                set: self.set.clone(),
+                system: self.system.name().to_string(),
+                message: panic_message(&*payload),
+                backtrace: LAST_BACKTRACE
+                    .with(|last| last.borrow_mut().take())
+                    .unwrap_or_default(),
+            };
+            error!(
+                "{} panicked and {} was disabled: {}",
+                crash.system, crash.set, crash.message
+            );
+            CAUGHT
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(crash);
+        }
+    }
+}
+
+impl<S: System<In = (), Out = ()>> System for Recoverable<S> {
+    type In = ();
+    type Out = ();
+
+    fn name(&self) -> Cow<'static, str> {
+        self.system.name()
+    }
+
+    fn type_id(&self) -> TypeId {
+        self.system.type_id()
+    }
+
+    fn component_access(&self) -> &Access<ComponentId> {
+        self.system.component_access()
+    }
+
+    fn archetype_component_access(&self) -> &Access<ArchetypeComponentId> {
+        self.system.archetype_component_access()
+    }
+
+    fn is_send(&self) -> bool {
+        self.system.is_send()
+    }
+
+    fn is_exclusive(&self) -> bool {
+        self.system.is_exclusive()
+    }
+
+    unsafe fn run_unsafe(&mut self, input: (), world: &World) {
+        // SAFETY: the executor upholds the same guarantees for us as for the inner system
+        self.catch(|system| unsafe { system.run_unsafe(input, world) });
+    }
+
+    fn run(&mut self, input: (), world: &mut World) {
+        self.catch(|system| system.run(input, world));
+    }
+
+    fn apply_buffers(&mut self, world: &mut World) {
+        self.system.apply_buffers(world);
+    }
+
+    fn initialize(&mut self, world: &mut World) {
+        self.system.initialize(world);
+    }
+
+    fn update_archetype_component_access(&mut self, world: &World) {
+        self.system.update_archetype_component_access(world);
+    }
+
+    fn check_change_tick(&mut self, change_tick: u32) {
+        self.system.check_change_tick(change_tick);
+    }
+
+    fn default_system_sets(&self) -> Vec<Box<dyn SystemSet>> {
+        self.system.default_system_sets()
+    }
+
+    fn get_last_change_tick(&self) -> u32 {
+        self.system.get_last_change_tick()
+    }
+
+    fn set_last_change_tick(&mut self, last_change_tick: u32) {
+        self.system.set_last_change_tick(last_change_tick);
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+pub fn collect_crashes(mut report: ResMut<CrashReport>, mut poisoned: ResMut<PoisonedSets>) {
+    let caught = std::mem::take(&mut *CAUGHT.lock().unwrap_or_else(PoisonError::into_inner));
+    for crash in caught {
+        poisoned.insert(crash.set.clone());
+        report.crashes.push(crash);
+    }
+}
+
+pub fn crash_banner(mut contexts: EguiContexts, mut report: ResMut<CrashReport>) {
+    if report.dismissed >= report.crashes.len() {
+        return;
+    }
+    let mut dismissed = false;
+    egui::Window::new("Subsystem crashed")
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 48.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            for crash in &report.crashes[report.dismissed..] {
+                ui.colored_label(
+                    egui::Color32::from_rgb(243, 139, 168),
+                    format!(
+                        "A subsystem crashed and was disabled: {} ({})",
+                        crash.set, crash.system
+                    ),
+                );
+                ui.label(&crash.message);
+            }
+            dismissed = ui.button("Dismiss").clicked();
+        });
+    if dismissed {
+        report.dismissed = report.crashes.len();
+    }
+}
+
+pub fn previous_crash_notice(
+    mut contexts: EguiContexts,
+    mut previous: ResMut<PreviousCrash>,
+    dir: Res<CrashReportDir>,
+) {
+    let Some(report) = previous.report.clone() else {
+        return;
+    };
+    let mut dismissed = false;
+    egui::Window::new("Previous session crashed")
+        .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -16.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("The last session ended in a crash and left a report behind.");
+            ui.horizontal(|ui| {
+                let label = if previous.expanded {
+                    "Hide report"
+                } else {
+                    "View report"
+                };
+                if ui.button(label).clicked() {
+                    previous.expanded = !previous.expanded;
+                }
+                if ui.button("Copy to clipboard").clicked() {
+                    ui.output_mut(|output| output.copied_text = report.clone());
+                }
+                dismissed = ui.button("Dismiss").clicked();
+            });
+            if previous.expanded {
+                egui::ScrollArea::vertical()
+                    .max_height(320.0)
+                    .show(ui, |ui| ui.monospace(&report));
+            }
+        });
+    if dismissed {
+        fs::remove_file(dir.join(UNSEEN_MARKER)).ok();
+        previous.report = None;
+    }
+}
+
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.0, " {value:?}").ok();
+        } else {
+            write!(self.0, " {}={value:?}", field.name()).ok();
+        }
+    }
+}
+
+// Keeps the newest log lines around for crash reports
+pub struct LogTail;
+
+impl<S: Subscriber> Layer<S> for LogTail {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut line = format!("{} {}:", metadata.level(), metadata.target());
+        event.record(&mut LineVisitor(&mut line));
+        let mut tail = LOG_TAIL.lock().unwrap_or_else(PoisonError::into_inner);
+        if tail.len() == LOG_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(line);
+    }
+}
+
+// Stands in for bevy's LogPlugin, which has no way to add a layer of our own
+pub fn init_logging() {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info,wgpu=error"));
+    let subscriber = Registry::default()
+        .with(filter)
+        .with(tracing_subscriber::fmt::Layer::default())
+        .with(LogTail);
+    LogTracer::init().ok();
+    if subscriber::set_global_default(subscriber).is_err() {
+        eprintln!("A logger was already set, crash reports won't have log lines");
+    }
+}
+
+pub fn format_report(timestamp: u64, message: &str, backtrace: &str, log: &[String]) -> String {
+    let mut report = String::new();
+    writeln!(report, "Vinox crash report").ok();
+    writeln!(report, "Version: {}", env!("CARGO_PKG_VERSION")).ok();
+    writeln!(report, "Time: {timestamp}").ok();
+    writeln!(report, "Panic: {message}").ok();
+    writeln!(report).ok();
+    writeln!(report, "Backtrace:").ok();
+    writeln!(report, "{}", backtrace.trim_end()).ok();
+    writeln!(report).ok();
+    writeln!(report, "Last {} log lines:", log.len()).ok();
+    for line in log {
+        writeln!(report, "{line}").ok();
+    }
+    report
+}
+
+// Writes <timestamp>.txt and marks it unseen so the next launch brings it up
+pub fn write_report(dir: &Path, timestamp: u64, report: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let name = format!("{timestamp}.txt");
+    let path = dir.join(&name);
+    fs::write(&path, report)?;
+    fs::write(dir.join(UNSEEN_MARKER), name)?;
+    Ok(path)
+}
+
+pub fn install_panic_hook(dir: PathBuf) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let backtrace = Backtrace::force_capture().to_string();
+        if IN_RECOVERABLE.with(Cell::get) {
+            // The wrapper picks this up once the unwind reaches it
+            LAST_BACKTRACE.with(|last| *last.borrow_mut() = Some(backtrace));
+            return;
+        }
+        // The executor panics again after a system does, only the first one is interesting
+        if !REPORT_WRITTEN.swap(true, Ordering::SeqCst) {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or_default();
+            let log: Vec<String> = LOG_TAIL
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .cloned()
+                .collect();
+            let report = format_report(timestamp, &info.to_string(), &backtrace, &log);
+            match write_report(&dir, timestamp, &report) {
+                Ok(path) => eprintln!("Crash report written to {}", path.display()),
+                Err(e) => eprintln!("Couldn't write a crash report: {e}"),
+            }
+        }
+        default_hook(info);
+    }));
+}
+
+pub struct CrashPlugin;
+
+impl Plugin for CrashPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CrashReport>()
+            .init_resource::<PoisonedSets>()
+            .reset_on_exit::<CrashReport>()
+            .reset_on_exit::<PoisonedSets>()
+            .add_system(collect_crashes.in_base_set(CoreSet::Last))
+            .add_system(crash_banner.in_set(OnUpdate(GameState::Game)))
+            .add_system(previous_crash_notice.in_set(OnUpdate(GameState::Menu)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+    enum TestSet {
+        Fragile,
+        Sturdy,
+    }
+
+    #[derive(Resource, Default)]
+    struct Runs {
+        fragile: u32,
+        neighbour: u32,
+        sturdy: u32,
+    }
+
+    fn fragile(mut runs: ResMut<Runs>) {
+        runs.fragile += 1;
+        if runs.fragile == 2 {
+            panic!("kaboom");
+        }
+    }
+
+    fn neighbour(mut runs: ResMut<Runs>) {
+        runs.neighbour += 1;
+    }
+
+    fn sturdy(mut runs: ResMut<Runs>) {
+        runs.sturdy += 1;
+    }
+
+    #[test]
+    fn poisoned_sets_stop_running() {
+        let mut app = App::new();
+        app.init_resource::<Runs>()
+            .init_resource::<CrashReport>()
+            .recoverable_set(TestSet::Fragile)
+            .add_systems(
+                (
+                    fragile.recoverable(TestSet::Fragile),
+                    neighbour.recoverable(TestSet::Fragile),
+                )
+                    .chain()
+                    .in_set(TestSet::Fragile),
+            )
+            .add_system(sturdy.in_set(TestSet::Sturdy))
+            .add_system(collect_crashes.in_base_set(CoreSet::Last));
+        for _ in 0..4 {
+            app.update();
+        }
+
+        let runs = app.world.resource::<Runs>();
+        assert_eq!(runs.fragile, 2);
+        // The rest of the set still ran the frame it crashed, then never again
+        assert_eq!(runs.neighbour, 2);
+        assert_eq!(runs.sturdy, 4);
+        assert!(app.world.resource::<PoisonedSets>().contains("Fragile"));
+        let report = app.world.resource::<CrashReport>();
+        assert_eq!(report.crashes.len(), 1);
+        assert_eq!(report.crashes[0].message, "kaboom");
+        assert!(report.crashes[0].system.ends_with("fragile"));
+    }
+
+    #[test]
+    fn report_file_format() {
+        let log: Vec<String> = (0..3)
+            .map(|line| format!("INFO vinox: line {line}"))
+            .collect();
+        let report = format_report(1700000000, "oh no", "0: main\n1: start\n", &log);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "Vinox crash report");
+        assert_eq!(lines[2], "Time: 1700000000");
+        assert_eq!(lines[3], "Panic: oh no");
+        assert_eq!(&lines[5..8], ["Backtrace:", "0: main", "1: start"]);
+        assert_eq!(lines[9], "Last 3 log lines:");
+        assert_eq!(lines.last(), Some(&"INFO vinox: line 2"));
+
+        let dir = std::env::temp_dir().join(format!("vinox-crash-{}", std::process::id()));
+        let path = write_report(&dir, 1700000000, &report).unwrap();
+        assert_eq!(path.file_name().unwrap(), "1700000000.txt");
+        assert_eq!(PreviousCrash::load(&dir).report.as_deref(), Some(&*report));
+        fs::remove_file(dir.join(UNSEEN_MARKER)).unwrap();
+        assert!(PreviousCrash::load(&dir).report.is_none());
+        fs::remove_dir_all(dir).ok();
+    }
+}
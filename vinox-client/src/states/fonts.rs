@@ -0,0 +1,228 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, FontData, FontDefinitions, FontFamily, FontId, TextStyle},
+    EguiContexts,
+};
+
+use super::components::ProjectPath;
+
+pub const UI_TEXT_SCALE: RangeInclusive<f32> = 0.75..=2.0;
+// Drawn instead of characters no font has, DejaVu Sans has it
+pub const FALLBACK_GLYPH: char = '\u{FFFD}';
+// Every ttf and otf in here is a fallback, a CJK font dropped in here covers CJK names
+pub const FONTS_DIR: &str = "fonts";
+
+// Sizes at a text scale of 1. Text ends up at these times ui_text_scale times egui's scale
+// factor, the slash toggle only ever changes the last one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextSizes {
+    pub body: f32,
+    pub heading: f32,
+    pub button: f32,
+}
+
+impl TextSizes {
+    pub const MENU: Self = Self {
+        body: 16.0,
+        heading: 36.0,
+        button: 26.0,
+    };
+    pub const GAME: Self = Self {
+        body: 18.0,
+        heading: 20.0,
+        button: 18.0,
+    };
+    pub const CONSOLE: Self = Self {
+        body: 16.0,
+        heading: 20.0,
+        button: 16.0,
+    };
+}
+
+// Names of the fallback fonts that got installed, in the order they're tried
+#[derive(Resource, Default, Debug)]
+pub struct UiFonts {
+    pub fallbacks: Vec<String>,
+}
+
+pub fn text_styles(sizes: TextSizes, scale: f32) -> BTreeMap<TextStyle, FontId> {
+    let scale = scale.clamp(*UI_TEXT_SCALE.start(), *UI_TEXT_SCALE.end());
+    BTreeMap::from([
+        (TextStyle::Small, FontId::proportional(sizes.body * scale)),
+        (TextStyle::Body, FontId::proportional(sizes.body * scale)),
+        (
+            TextStyle::Heading,
+            FontId::proportional(sizes.heading * scale),
+        ),
+        (TextStyle::Monospace, FontId::monospace(sizes.body * scale)),
+        (
+            TextStyle::Button,
+            FontId::proportional(sizes.button * scale),
+        ),
+    ])
+}
+
+pub fn set_text_styles(ctx: &egui::Context, sizes: TextSizes, scale: f32) {
+    ctx.set_style(egui::Style {
+        text_styles: text_styles(sizes, scale),
+        ..Default::default()
+    });
+}
+
+// egui's own fonts first so Latin text and emoji look the same as before, the fallbacks
+// only get asked for what those don't have
+pub fn font_definitions(fallbacks: Vec<(String, Vec<u8>)>) -> FontDefinitions {
+    let mut fonts = FontDefinitions::default();
+    for (name, data) in fallbacks {
+        fonts
+            .font_data
+            .insert(name.clone(), FontData::from_owned(data));
+        for family in [FontFamily::Proportional, FontFamily::Monospace] {
+            fonts.families.entry(family).or_default().push(name.clone());
+        }
+    }
+    fonts
+}
+
+pub fn load_fallback_fonts(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    extension.eq_ignore_ascii_case("ttf") || extension.eq_ignore_ascii_case("otf")
+                })
+        })
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().to_string();
+            match fs::read(&path) {
+                Ok(data) => Some((name, data)),
+                Err(e) => {
+                    warn!("Failed to read font {}: {e}", path.display());
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn install_fonts(
+    mut contexts: EguiContexts,
+    project_path: Res<ProjectPath>,
+    mut ui_fonts: ResMut<UiFonts>,
+) {
+    let fallbacks = load_fallback_fonts(&project_path.join(FONTS_DIR));
+    ui_fonts.fallbacks = fallbacks.iter().map(|(name, _)| name.clone()).collect();
+    contexts.ctx_mut().set_fonts(font_definitions(fallbacks));
+}
+
+// Characters no installed font can draw become FALLBACK_GLYPH instead of egui's empty box.
+// Joiners and variation selectors are dropped, egui draws emoji sequences one by one anyway
+pub fn with_fallback_glyphs(text: &str, has_glyph: impl Fn(char) -> bool) -> Cow<str> {
+    let invisible = |c: char| matches!(c, '\u{200D}' | '\u{FE0E}' | '\u{FE0F}');
+    let drawable = |c: char| c.is_whitespace() || c.is_control() || has_glyph(c);
+    if text.chars().all(|c| !invisible(c) && drawable(c)) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(
+        text.chars()
+            .filter(|c| !invisible(*c))
+            .map(|c| if drawable(c) { c } else { FALLBACK_GLYPH })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn style_table_scales_every_style() {
+        for scale in [0.75, 1.0, 1.5, 2.0] {
+            let styles = text_styles(TextSizes::MENU, scale);
+            assert_eq!(styles.len(), 5);
+            assert_eq!(styles[&TextStyle::Body], FontId::proportional(16.0 * scale));
+            assert_eq!(
+                styles[&TextStyle::Small],
+                FontId::proportional(16.0 * scale)
+            );
+            assert_eq!(
+                styles[&TextStyle::Heading],
+                FontId::proportional(36.0 * scale)
+            );
+            assert_eq!(
+                styles[&TextStyle::Monospace],
+                FontId::monospace(16.0 * scale)
+            );
+            assert_eq!(
+                styles[&TextStyle::Button],
+                FontId::proportional(26.0 * scale)
+            );
+        }
+        // Out of range scales from a hand edited config are clamped
+        assert_eq!(
+            text_styles(TextSizes::GAME, 10.0)[&TextStyle::Body],
+            FontId::proportional(36.0)
+        );
+        assert_eq!(
+            text_styles(TextSizes::CONSOLE, 0.0)[&TextStyle::Heading],
+            FontId::proportional(15.0)
+        );
+    }
+
+    #[test]
+    fn fallbacks_come_after_the_defaults() {
+        let defaults = FontDefinitions::default();
+        let fonts = font_definitions(vec![
+            ("DejaVuSans".to_string(), vec![0]),
+            ("NotoSansCJK".to_string(), vec![1]),
+        ]);
+        for family in [FontFamily::Proportional, FontFamily::Monospace] {
+            let names = &fonts.families[&family];
+            let default_names = &defaults.families[&family];
+            assert_eq!(&names[..default_names.len()], &default_names[..]);
+            assert_eq!(
+                &names[default_names.len()..],
+                &["DejaVuSans".to_string(), "NotoSansCJK".to_string()]
+            );
+        }
+        assert!(fonts.font_data.contains_key("NotoSansCJK"));
+    }
+
+    #[test]
+    fn missing_glyphs_get_the_fallback() {
+        let latin_only = |c: char| c.is_ascii();
+        assert!(matches!(
+            with_fallback_glyphs("plain text", latin_only),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            with_fallback_glyphs("hi 名前", latin_only),
+            "hi \u{FFFD}\u{FFFD}"
+        );
+        assert_eq!(with_fallback_glyphs("a\tb\n", latin_only), "a\tb\n");
+        // The heart has a glyph, its variation selector just goes
+        let with_heart = |c: char| c.is_ascii() || c == '❤';
+        assert_eq!(with_fallback_glyphs("❤\u{FE0F}!", with_heart), "❤!");
+        assert_eq!(
+            with_fallback_glyphs("👨\u{200D}👩", with_heart),
+            "\u{FFFD}\u{FFFD}"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use vinox_common::{
+    ecs::{arrange::InventoryOp, bundles::Inventory},
+    networking::{intents::IntentChannel, protocol::ClientMessage},
+};
+
+use crate::states::game::{networking::connection::NetClient, world::chunks::ControlledPlayer};
+
+// An op that's gone this long without an ack is sent again along with everything after it
+pub const RESEND_SECONDS: f32 = 3.0;
+
+// A rearrangement the UI already made to the local inventory, it only has to be sent
+pub struct ArrangeEvent(pub InventoryOp);
+
+// What the server had to say about the ops we sent
+pub enum ArrangeSyncEvent {
+    Ack { seq: u32 },
+    Resend { from: u32 },
+    Resync,
+    // Our resync held more than the server has, its copy replaces what we hold
+    Correct(Box<Inventory>),
+}
+
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ArrangeIntents(pub IntentChannel<InventoryOp>);
+
+fn send_op(client: &mut NetClient, seq: u32, op: &InventoryOp) {
+    client.send(ClientMessage::InventoryOp {
+        seq,
+        op: op.clone(),
+    });
+}
+
+pub fn send_arrangements(
+    mut intents: ResMut<ArrangeIntents>,
+    mut arranged: EventReader<ArrangeEvent>,
+    mut sync: EventReader<ArrangeSyncEvent>,
+    mut player: Query<&mut Inventory, With<ControlledPlayer>>,
+    mut client: NetClient,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds();
+    let mut resync = false;
+    for evt in sync.iter() {
+        match evt {
+            ArrangeSyncEvent::Ack { seq } => intents.ack_through(*seq),
+            ArrangeSyncEvent::Resend { from } => match intents.resend_from(*from, now) {
+                Some(resend) => {
+                    for pending in resend {
+                        send_op(&mut client, pending.seq, &pending.intent);
+                    }
+                }
+                None => resync = true,
+            },
+            ArrangeSyncEvent::Resync => resync = true,
+            ArrangeSyncEvent::Correct(corrected) => {
+                // Which slot is selected and whether it's open stay ours
+                if let Ok(mut inventory) = player.get_single_mut() {
+                    inventory.hotbar = corrected.hotbar.clone();
+                    inventory.slots = corrected.slots.clone();
+                }
+            }
+        }
+    }
+    for ArrangeEvent(op) in arranged.iter() {
+        let seq = intents.push(op.clone(), now);
+        send_op(&mut client, seq, op);
+    }
+    // Either the ops or their ack got lost, the server skips whatever it already has
+    let overdue = intents
+        .oldest_sent()
+        .is_some_and(|sent| now - sent > RESEND_SECONDS);
+    if overdue && !resync {
+        let oldest = intents.iter().next().map(|pending| pending.seq);
+        if let Some(resend) = oldest.and_then(|oldest| intents.resend_from(oldest, now)) {
+            for pending in resend {
+                send_op(&mut client, pending.seq, &pending.intent);
+            }
+        }
+    }
+    if resync {
+        let Ok(inventory) = player.get_single() else {
+            return;
+        };
+        let seq = intents.resynced();
+        client.send(ClientMessage::InventoryResync {
+            seq,
+            inventory: Box::new(inventory.clone()),
+        });
+    }
+}
@@ -0,0 +1,242 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use vinox_common::{
+    ecs::bundles::PlayerBundleBuilder,
+    networking::protocol::PlayerFlags,
+    physics::collision::raycast::raycast_world,
+    world::chunks::{ecs::ChunkManager, positions::WorldOffset},
+};
+
+use crate::states::{
+    components::{GameActions, GameOptions},
+    game::world::{
+        chunks::ControlledPlayer,
+        remote_players::{POSE_BLEND_RATE, SNEAK_DROP},
+    },
+};
+
+use super::{look::CursorGrab, player::FPSCamera};
+
+// Above the player's feet. First person looks from here and third person looks around it
+pub const EYE_OFFSET: Vec3 = Vec3::new(0.0, 1.8, 0.0);
+// Kept between the camera and whatever it backed into so the near plane doesn't cut into it
+pub const CAMERA_WALL_MARGIN: f32 = 0.2;
+
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    #[default]
+    FirstPerson,
+    // Behind the player looking the same way they are
+    ThirdPersonBack,
+    // Out in front looking back at them
+    ThirdPersonFront,
+}
+
+impl CameraMode {
+    pub fn next(self) -> Self {
+        match self {
+            CameraMode::FirstPerson => CameraMode::ThirdPersonBack,
+            CameraMode::ThirdPersonBack => CameraMode::ThirdPersonFront,
+            CameraMode::ThirdPersonFront => CameraMode::FirstPerson,
+        }
+    }
+
+    // Which way along the look the camera moves out from the eyes
+    fn side(self) -> f32 {
+        match self {
+            CameraMode::FirstPerson => 0.0,
+            CameraMode::ThirdPersonBack => -1.0,
+            CameraMode::ThirdPersonFront => 1.0,
+        }
+    }
+}
+
+// From 0 to 1, how far the eyes have sunk for sneaking. Eased the same as everyone else sees the
+// model crouch
+#[derive(Resource, Debug, Default)]
+pub struct Crouch(pub f32);
+
+impl Crouch {
+    pub fn ease(&mut self, sneaking: bool, delta: f32, reduce_motion: bool) {
+        let target = if sneaking { 1.0 } else { 0.0 };
+        if reduce_motion {
+            self.0 = target;
+        } else {
+            self.0 += (target - self.0) * (1.0 - (-POSE_BLEND_RATE * delta).exp());
+        }
+    }
+
+    // Above the player's feet
+    pub fn eye(&self) -> Vec3 {
+        EYE_OFFSET - Vec3::Y * SNEAK_DROP * self.0
+    }
+}
+
+// The local player's own model, only spawned while it can be seen
+#[derive(Component)]
+pub struct LocalPlayerModel;
+
+// Short of the first thing the ray from the eyes hits, never past what was asked for
+pub fn camera_distance(wanted: f32, hit: Option<f32>) -> f32 {
+    hit.map_or(wanted, |toi| (toi - CAMERA_WALL_MARGIN).clamp(0.0, wanted))
+}
+
+// Relative to the player. `look` is where the eyes point
+pub fn camera_transform(mode: CameraMode, eye: Vec3, look: Quat, distance: f32) -> Transform {
+    let forward = look * Vec3::NEG_Z;
+    let transform = Transform::from_translation(eye + forward * mode.side() * distance);
+    match mode {
+        CameraMode::ThirdPersonFront => transform.with_rotation(look * Quat::from_rotation_y(PI)),
+        _ => transform.with_rotation(look),
+    }
+}
+
+pub fn toggle_camera(
+    player: Query<&ActionState<GameActions>, With<ControlledPlayer>>,
+    mut mode: ResMut<CameraMode>,
+    grab: Res<CursorGrab>,
+) {
+    if let Ok(action_state) = player.get_single() {
+        if grab.is_grabbed() && action_state.just_pressed(GameActions::ToggleCamera) {
+            *mode = mode.next();
+        }
+    }
+}
+
+// The camera is the same entity in every mode so FOV and fog carry over. Third person casts out
+// from the eyes in world space and stops short of terrain instead of seeing through it
+pub fn place_camera(
+    player: Query<(&Transform, &PlayerFlags), With<ControlledPlayer>>,
+    mut camera: Query<(&FPSCamera, &mut Transform), Without<ControlledPlayer>>,
+    (mode, options, offset): (Res<CameraMode>, Res<GameOptions>, Res<WorldOffset>),
+    (mut crouch, time): (ResMut<Crouch>, Res<Time>),
+    chunk_manager: ChunkManager,
+) {
+    let (Ok((player_transform, flags)), Ok((fps_camera, mut transform))) =
+        (player.get_single(), camera.get_single_mut())
+    else {
+        return;
+    };
+    crouch.ease(
+        flags.sneaking(),
+        time.delta_seconds(),
+        options.reduce_motion,
+    );
+    let eye = crouch.eye();
+    let look = fps_camera.look().rotation;
+    let distance = if *mode == CameraMode::FirstPerson {
+        0.0
+    } else {
+        let hit = raycast_world(
+            offset.to_world(player_transform.translation + eye),
+            look * Vec3::NEG_Z * mode.side(),
+            options.third_person_distance,
+            &chunk_manager,
+        );
+        camera_distance(options.third_person_distance, hit.map(|(.., toi)| toi))
+    };
+    let placed = camera_transform(*mode, eye, look, distance);
+    if *transform != placed {
+        *transform = placed;
+    }
+}
+
+// The local player never had a model, first person would be looking out from inside it
+pub fn show_local_model(
+    mut commands: Commands,
+    player: Query<Entity, With<ControlledPlayer>>,
+    camera: Query<&FPSCamera>,
+    mut models: Query<(Entity, &mut Transform), With<LocalPlayerModel>>,
+    mode: Res<CameraMode>,
+    player_builder: Res<PlayerBundleBuilder>,
+) {
+    if *mode == CameraMode::FirstPerson {
+        for (entity, _) in models.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+    let (Ok(player), Ok(fps_camera)) = (player.get_single(), camera.get_single()) else {
+        return;
+    };
+    // Turned the same way everyone else sees it turned
+    let (_, yaw, _) = fps_camera.look().rotation.to_euler(EulerRot::XYZ);
+    let rotation = Quat::from_euler(EulerRot::XYZ, 0.0, yaw, 0.0);
+    if let Ok((_, mut transform)) = models.get_single_mut() {
+        transform.rotation = rotation;
+        return;
+    }
+    commands.entity(player).with_children(|c| {
+        c.spawn((
+            SceneBundle {
+                scene: player_builder.default_model.clone(),
+                transform: Transform::from_rotation(rotation),
+                ..default()
+            },
+            LocalPlayerModel,
+        ));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_cycle_back_to_first_person() {
+        let mut mode = CameraMode::default();
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            mode = mode.next();
+            seen.push(mode);
+        }
+        assert_eq!(
+            seen,
+            vec![
+                CameraMode::ThirdPersonBack,
+                CameraMode::ThirdPersonFront,
+                CameraMode::FirstPerson
+            ]
+        );
+    }
+
+    #[test]
+    fn third_person_backs_off_walls_and_faces_the_player() {
+        assert_eq!(camera_distance(4.0, None), 4.0);
+        assert!((camera_distance(4.0, Some(1.5)) - 1.3).abs() < 1e-5);
+        // Right up against it the camera goes no closer than the eyes
+        assert_eq!(camera_distance(4.0, Some(0.1)), 0.0);
+
+        let look = FPSCamera::default().look().rotation;
+        let first = camera_transform(CameraMode::FirstPerson, EYE_OFFSET, look, 4.0);
+        assert_eq!(first.translation, EYE_OFFSET);
+        assert_eq!(first.rotation, look);
+
+        let back = camera_transform(CameraMode::ThirdPersonBack, EYE_OFFSET, look, 4.0);
+        assert!((back.translation.distance(EYE_OFFSET) - 4.0).abs() < 1e-4);
+        // The eyes are straight ahead of it
+        let to_eyes = (EYE_OFFSET - back.translation).normalize();
+        assert!(to_eyes.dot(back.forward()) > 0.999);
+
+        let front = camera_transform(CameraMode::ThirdPersonFront, EYE_OFFSET, look, 4.0);
+        let to_eyes = (EYE_OFFSET - front.translation).normalize();
+        assert!(to_eyes.dot(front.forward()) > 0.999);
+        assert!(front.forward().dot(look * Vec3::NEG_Z) < -0.999);
+    }
+
+    #[test]
+    fn sneaking_lowers_the_eyes() {
+        let mut crouch = Crouch::default();
+        crouch.ease(true, 1.0 / 60.0, false);
+        assert!(crouch.0 > 0.0 && crouch.0 < 0.5);
+        for _ in 0..60 {
+            crouch.ease(true, 1.0 / 60.0, false);
+        }
+        assert!((crouch.eye().y - (EYE_OFFSET.y - SNEAK_DROP)).abs() < 0.01);
+        // Reduce motion snaps straight back up
+        crouch.ease(false, 1.0 / 60.0, true);
+        assert_eq!(crouch.eye(), EYE_OFFSET);
+    }
+}
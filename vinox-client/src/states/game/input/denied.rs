@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use vinox_common::{
+    networking::{intents::IntentChannel, protocol::DenyReason},
+    world::chunks::positions::voxel_to_global_voxel,
+};
+
+use crate::states::{
+    audio::{PlaySound, SoundCategory},
+    game::{
+        networking::syncing::{HighLightCube, HIGHLIGHT_COLOR},
+        rendering::meshing::BasicMaterial,
+        ui::actionbar::ActionBar,
+        world::chunks::SetBlockEvent,
+    },
+};
+
+use super::{
+    mining::{crack_color, BreakProgress},
+    player::TargetedBlock,
+};
+
+// An edit the server hasn't answered by now got lost, it can't be denied anymore
+pub const PENDING_SECONDS: f32 = 5.0;
+pub const FLASH_SECONDS: f32 = 0.3;
+pub const DENIED_SOUND: &str = "sounds/denied.ogg";
+pub const BLOCKED_HIGHLIGHT_COLOR: Color = Color::rgba(1.2, 0.3, 0.3, 1.0);
+
+pub struct BlockDeniedEvent {
+    pub voxel: IVec3,
+    pub reason: DenyReason,
+}
+
+// What the player gets told about one of their own edits being put back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Feedback {
+    pub reason: DenyReason,
+    pub message: Option<&'static str>,
+}
+
+// Only reasons the player can do something about get a line of text, the sound and flash are
+// enough for the rest
+pub fn message(reason: DenyReason) -> Option<&'static str> {
+    match reason {
+        DenyReason::TooFast => None,
+        DenyReason::Occupied => Some("Something is already in the way"),
+        DenyReason::Forbidden => Some("The server won't place that copy"),
+        DenyReason::OutsideBuildLimit => Some("That's past the build height limit"),
+    }
+}
+
+// Edits this client predicted and sent off. The server answers each voxel on its own so they
+// settle by voxel rather than by seq
+#[derive(Resource, Debug, Default)]
+pub struct PendingEdits(IntentChannel<IVec3>);
+
+impl PendingEdits {
+    pub fn push(&mut self, voxel: IVec3, now: f32) {
+        self.0.push(voxel, now);
+    }
+
+    // Settles the oldest edit at the voxel, false when none of ours were waiting there
+    pub fn take(&mut self, voxel: IVec3) -> bool {
+        self.0.settle(|pending| *pending == voxel).is_some()
+    }
+
+    // A correction for someone else's edit or a world update doesn't concern the player
+    pub fn deny(&mut self, voxel: IVec3, reason: DenyReason) -> Option<Feedback> {
+        self.take(voxel).then(|| Feedback {
+            reason,
+            message: message(reason),
+        })
+    }
+
+    pub fn expire(&mut self, now: f32) {
+        self.0.expire(now, PENDING_SECONDS);
+    }
+}
+
+// When the crosshair went red
+#[derive(Resource, Debug, Default)]
+pub struct DeniedFlash(pub Option<f32>);
+
+impl DeniedFlash {
+    // 1 right after a denial, down to 0 once the flash is over
+    pub fn strength(&self, now: f32) -> f32 {
+        self.0.map_or(0.0, |started| {
+            (1.0 - (now - started) / FLASH_SECONDS).max(0.0)
+        })
+    }
+}
+
+pub fn report_denials(
+    mut pending: ResMut<PendingEdits>,
+    mut denials: EventReader<BlockDeniedEvent>,
+    mut blocks: EventReader<SetBlockEvent>,
+    (mut flash, mut action_bar): (ResMut<DeniedFlash>, ResMut<ActionBar>),
+    mut sounds: EventWriter<PlaySound>,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds();
+    let mut denied = HashSet::new();
+    let mut sound = false;
+    for denial in denials.iter() {
+        denied.insert(denial.voxel);
+        let Some(feedback) = pending.deny(denial.voxel, denial.reason) else {
+            continue;
+        };
+        sound = true;
+        flash.0 = Some(now);
+        if let Some(message) = feedback.message {
+            action_bar.show(message, now);
+        }
+    }
+    // Everything else coming back for one of our voxels means the server took the edit
+    for block in blocks.iter() {
+        let voxel = voxel_to_global_voxel(block.voxel_pos, block.chunk_pos);
+        if !denied.contains(&voxel) {
+            pending.take(voxel);
+        }
+    }
+    pending.expire(now);
+    if flash.strength(now) <= 0.0 {
+        flash.0 = None;
+    }
+    // One sound for a burst, holding the button down shouldn't stack them
+    if sound {
+        sounds.send(PlaySound::new(
+            asset_server.load(DENIED_SOUND),
+            SoundCategory::BlockEffects,
+        ));
+    }
+}
+
+// The outline goes red over anything the held item isn't allowed to change, and darkens while a
+// slow block is being broken
+pub fn tint_highlight(
+    targeted: Res<TargetedBlock>,
+    mining: Res<BreakProgress>,
+    cube: Query<&Handle<BasicMaterial>, With<HighLightCube>>,
+    mut materials: ResMut<Assets<BasicMaterial>>,
+) {
+    let blocked = targeted.0.as_ref().is_some_and(|target| target.blocked);
+    let color = crack_color(
+        if blocked {
+            BLOCKED_HIGHLIGHT_COLOR
+        } else {
+            HIGHLIGHT_COLOR
+        },
+        mining.progress(),
+    );
+    for handle in cube.iter() {
+        // Touching the material every frame would send it to the GPU again each time
+        if materials
+            .get(handle)
+            .is_some_and(|material| material.color != color)
+        {
+            if let Some(material) = materials.get_mut(handle) {
+                material.color = color;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_our_own_edits_get_feedback() {
+        let mut pending = PendingEdits::default();
+        let ours = IVec3::new(4, 70, -2);
+        pending.push(ours, 0.0);
+
+        // Someone else's block put back next to ours
+        assert_eq!(
+            pending.deny(IVec3::new(5, 70, -2), DenyReason::Occupied),
+            None
+        );
+        assert_eq!(
+            pending.deny(ours, DenyReason::Occupied),
+            Some(Feedback {
+                reason: DenyReason::Occupied,
+                message: Some("Something is already in the way"),
+            })
+        );
+        // Already settled, a second correction there is just a world update
+        assert_eq!(pending.deny(ours, DenyReason::Occupied), None);
+
+        pending.push(ours, 1.0);
+        let feedback = pending.deny(ours, DenyReason::TooFast).unwrap();
+        assert_eq!(feedback.message, None);
+    }
+
+    #[test]
+    fn acked_and_lost_edits_stop_being_pending() {
+        let mut pending = PendingEdits::default();
+        let voxel = IVec3::new(0, 64, 0);
+        pending.push(voxel, 0.0);
+        pending.push(voxel, 0.5);
+        // Placed then broken, the first answer settles the placement only
+        assert!(pending.take(voxel));
+        assert!(pending.deny(voxel, DenyReason::TooFast).is_some());
+        assert!(!pending.take(voxel));
+
+        pending.push(voxel, 1.0);
+        pending.expire(1.0 + PENDING_SECONDS);
+        assert_eq!(pending.deny(voxel, DenyReason::Occupied), None);
+    }
+
+    #[test]
+    fn the_flash_fades_out() {
+        let flash = DeniedFlash(Some(2.0));
+        assert_eq!(flash.strength(2.0), 1.0);
+        assert!(flash.strength(2.0 + FLASH_SECONDS / 2.0) > 0.0);
+        assert_eq!(flash.strength(2.0 + FLASH_SECONDS), 0.0);
+        assert_eq!(DeniedFlash::default().strength(2.0), 0.0);
+    }
+}
@@ -0,0 +1,214 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+use leafwing_input_manager::prelude::*;
+use vinox_common::{
+    ecs::bundles::{Inventory, InventorySection, SlotRef},
+    networking::protocol::ClientMessage,
+    storage::items::descriptor::{ItemData, MAX_STACK_SIZE},
+    world::chunks::storage::{name_to_identifier, ItemTable},
+};
+
+use crate::states::{
+    components::GameActions,
+    game::{
+        input::gate::InteractionGate, networking::connection::NetClient, ui::plugin::InUi,
+        world::chunks::ControlledPlayer, world::critters::DroppedItemModel,
+    },
+};
+
+// Holding the key this long throws whatever is left of the stack
+pub const HOLD_FOR_STACK: f32 = 0.5;
+pub const PICKUP_RADIUS: f32 = 1.5;
+// Until the server answers the item is still lying there, don't ask for it every frame
+pub const PICKUP_RETRY: f32 = 0.5;
+
+// Slot under the cursor while a UI is open. The UI sets it every frame something is hovered and
+// dropping clears it again, input runs first so it always sees the last frame's
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct HoveredSlot(pub Option<SlotRef>);
+
+pub struct DropResultEvent {
+    pub slot: SlotRef,
+    pub item: ItemData,
+    pub refund: u32,
+}
+
+pub struct PickedUpEvent {
+    pub item: ItemData,
+}
+
+fn max_stack_size(item: &ItemData, item_table: &ItemTable) -> u32 {
+    item_table
+        .get(&name_to_identifier(
+            item.namespace.clone(),
+            item.name.clone(),
+        ))
+        .and_then(|descriptor| descriptor.max_stack_size)
+        .unwrap_or(MAX_STACK_SIZE)
+}
+
+// Takes up to count out of the slot, handing back the stack as it was and how many came out.
+// None for an empty or missing slot
+pub fn take_from_slot(
+    inventory: &mut Inventory,
+    slot: SlotRef,
+    count: u32,
+) -> Option<(ItemData, u32)> {
+    let contents = inventory.slot_mut(slot)?;
+    let item = contents.as_mut()?;
+    let taken = count.min(item.stack_size);
+    if taken == 0 {
+        return None;
+    }
+    let before = item.clone();
+    item.stack_size -= taken;
+    if item.stack_size == 0 {
+        *contents = None;
+    }
+    Some((before, taken))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn drop_items(
+    mut player: Query<(&ActionState<GameActions>, &mut Inventory), With<ControlledPlayer>>,
+    mut hovered: ResMut<HoveredSlot>,
+    in_ui: Res<InUi>,
+    keys: Res<Input<KeyCode>>,
+    mut contexts: EguiContexts,
+    mut client: NetClient,
+    mut threw_stack: Local<bool>,
+    (mut gate, time): (ResMut<InteractionGate>, Res<Time>),
+) {
+    let hovered = hovered.take();
+    let Ok((action_state, mut inventory)) = player.get_single_mut() else {
+        return;
+    };
+    // Typing a Q into chat shouldn't throw anything
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    if !gate.allow(action_state, time.elapsed_seconds()) {
+        return;
+    }
+    let slot = if inventory.open || **in_ui {
+        hovered
+    } else {
+        Some(SlotRef {
+            section: InventorySection::Hotbar,
+            bar: *inventory.current_bar,
+            slot: *inventory.current_item,
+        })
+    };
+    let Some(slot) = slot else {
+        return;
+    };
+    let count = if action_state.just_pressed(GameActions::DropItem) {
+        *threw_stack = false;
+        if keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+            *threw_stack = true;
+            u32::MAX
+        } else {
+            1
+        }
+    } else if action_state.pressed(GameActions::DropItem)
+        && !*threw_stack
+        && action_state
+            .current_duration(GameActions::DropItem)
+            .as_secs_f32()
+            >= HOLD_FOR_STACK
+    {
+        *threw_stack = true;
+        u32::MAX
+    } else {
+        return;
+    };
+    // Taken out now so it feels instant, the DropResult puts back anything the server kept
+    if let Some((item, count)) = take_from_slot(&mut inventory, slot, count) {
+        client.send(ClientMessage::DropItem { slot, count, item });
+    }
+}
+
+pub fn reconcile_drops(
+    mut events: EventReader<DropResultEvent>,
+    mut player: Query<&mut Inventory, With<ControlledPlayer>>,
+    item_table: Res<ItemTable>,
+) {
+    let Ok(mut inventory) = player.get_single_mut() else {
+        events.clear();
+        return;
+    };
+    for event in events.iter().filter(|event| event.refund > 0) {
+        let max_stack_size = max_stack_size(&event.item, &item_table);
+        // Back where it came from if nothing else moved in, otherwise wherever it fits
+        match inventory.slot_mut(event.slot) {
+            Some(contents @ None) => {
+                *contents = Some(ItemData {
+                    stack_size: event.refund,
+                    ..event.item.clone()
+                });
+            }
+            Some(Some(existing))
+                if existing.can_stack_with(&event.item)
+                    && existing.stack_size + event.refund <= max_stack_size =>
+            {
+                existing.stack_size += event.refund;
+            }
+            _ => {
+                inventory.add_stack(
+                    &ItemData {
+                        stack_size: event.refund,
+                        ..event.item.clone()
+                    },
+                    max_stack_size,
+                );
+            }
+        }
+    }
+}
+
+// Asks for items we walk over, only ones that fit so the server never hands us more than we hold
+pub fn pick_up_items(
+    mut dropped_items: Query<(&mut DroppedItemModel, &GlobalTransform)>,
+    player: Query<(&Transform, &Inventory), With<ControlledPlayer>>,
+    item_table: Res<ItemTable>,
+    time: Res<Time>,
+    mut client: NetClient,
+) {
+    let Ok((player_transform, inventory)) = player.get_single() else {
+        return;
+    };
+    let center = player_transform.translation + Vec3::Y * 0.9;
+    let now = time.elapsed_seconds();
+    let mut room = inventory.clone();
+    for (mut model, transform) in dropped_items.iter_mut() {
+        if transform.translation().distance(center) > PICKUP_RADIUS
+            || model
+                .requested_at
+                .is_some_and(|requested_at| now - requested_at < PICKUP_RETRY)
+        {
+            continue;
+        }
+        if room.add_stack(&model.item, max_stack_size(&model.item, &item_table)) > 0 {
+            continue;
+        }
+        model.requested_at = Some(now);
+        client.send(ClientMessage::PickUp {
+            entity: model.server_entity,
+        });
+    }
+}
+
+pub fn receive_pickups(
+    mut events: EventReader<PickedUpEvent>,
+    mut player: Query<&mut Inventory, With<ControlledPlayer>>,
+    item_table: Res<ItemTable>,
+) {
+    let Ok(mut inventory) = player.get_single_mut() else {
+        events.clear();
+        return;
+    };
+    for event in events.iter() {
+        // Only asked for when it fit, something would have to fill up in between to lose any
+        inventory.add_stack(&event.item, max_stack_size(&event.item, &item_table));
+    }
+}
@@ -0,0 +1,169 @@
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use vinox_common::{
+    ecs::time::GameInstant,
+    physics::{
+        movement::{MovementConfig, MovementState},
+        simulate::CollidesWithWorld,
+    },
+};
+
+use crate::states::{
+    components::GameActions,
+    game::{
+        networking::components::{Capabilities, ChatLine, ChatMessages},
+        world::chunks::ControlledPlayer,
+    },
+};
+
+use super::look::CursorGrab;
+
+// Two presses of Jump closer together than this toggle flying, same as ToggleFly
+pub const DOUBLE_TAP_SECONDS: f32 = 0.3;
+
+// The last press of Jump that could still start a double tap
+#[derive(Resource, Default)]
+pub struct JumpTaps(Option<GameInstant>);
+
+impl JumpTaps {
+    // True on the second press of a double tap. That press doesn't count as the first of another,
+    // so mashing Jump doesn't flicker in and out of flying
+    pub fn press(&mut self, now: GameInstant) -> bool {
+        match self.0.take() {
+            Some(last) if (now - last).as_secs() <= DOUBLE_TAP_SECONDS => true,
+            _ => {
+                self.0 = Some(now);
+                false
+            }
+        }
+    }
+}
+
+// Wanted by the player, only has an effect while flying on a server that allows it
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Noclip(pub bool);
+
+// The server's config with the fly speed option on top of it
+pub fn flying_config(config: &MovementConfig, fly_speed: f32) -> MovementConfig {
+    let mut config = config.clone();
+    config.flying.max_speed *= fly_speed;
+    config.flying.acceleration *= fly_speed;
+    config
+}
+
+pub fn passes_through_blocks(
+    noclip: bool,
+    capabilities: &Capabilities,
+    state: MovementState,
+) -> bool {
+    noclip && capabilities.noclip && state == MovementState::Flying
+}
+
+// Drops the player out of the air when the server takes flying away, and turns world collision
+// off only while noclip is wanted, allowed and flying
+#[allow(clippy::type_complexity)]
+pub fn apply_flight(
+    mut commands: Commands,
+    mut player: Query<
+        (
+            Entity,
+            &mut MovementState,
+            &ActionState<GameActions>,
+            Option<&CollidesWithWorld>,
+        ),
+        With<ControlledPlayer>,
+    >,
+    (capabilities, mut noclip, grab): (Res<Capabilities>, ResMut<Noclip>, Res<CursorGrab>),
+    mut messages: ResMut<ChatMessages>,
+) {
+    let Ok((entity, mut state, action_state, collides)) = player.get_single_mut() else {
+        return;
+    };
+    if grab.is_grabbed() {
+        if action_state.just_pressed(GameActions::ToggleFly) && !capabilities.fly {
+            messages.push(ChatLine::console("This server doesn't allow flying"));
+        }
+        if action_state.just_pressed(GameActions::ToggleNoclip) {
+            if capabilities.noclip {
+                **noclip = !**noclip;
+                messages.push(ChatLine::console(if **noclip {
+                    "Noclip on, flying goes through blocks"
+                } else {
+                    "Noclip off"
+                }));
+            } else {
+                messages.push(ChatLine::console("This server doesn't allow noclip"));
+            }
+        }
+    }
+    if *state == MovementState::Flying && !capabilities.fly {
+        *state = MovementState::Airborne;
+        messages.push(ChatLine::console("Flying isn't allowed anymore"));
+    }
+    match (
+        passes_through_blocks(**noclip, &capabilities, *state),
+        collides.is_some(),
+    ) {
+        (true, true) => {
+            commands.entity(entity).remove::<CollidesWithWorld>();
+        }
+        (false, false) => {
+            commands.entity(entity).insert(CollidesWithWorld);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vinox_common::ecs::time::GameDuration;
+
+    use super::*;
+
+    #[test]
+    fn double_taps_toggle_once() {
+        let mut taps = JumpTaps::default();
+        let start = GameInstant::default();
+        let at = |secs: f32| start + GameDuration::from_secs(secs);
+        assert!(!taps.press(at(0.0)));
+        assert!(taps.press(at(0.2)));
+        // A third quick press starts over instead of toggling back
+        assert!(!taps.press(at(0.3)));
+        // Too slow
+        assert!(!taps.press(at(1.0)));
+        assert!(taps.press(at(1.25)));
+    }
+
+    #[test]
+    fn noclip_needs_permission_and_flying() {
+        let allowed = Capabilities {
+            fly: true,
+            noclip: true,
+            ..default()
+        };
+        assert!(passes_through_blocks(true, &allowed, MovementState::Flying));
+        assert!(!passes_through_blocks(
+            false,
+            &allowed,
+            MovementState::Flying
+        ));
+        assert!(!passes_through_blocks(
+            true,
+            &allowed,
+            MovementState::Grounded
+        ));
+        let flight_only = Capabilities {
+            fly: true,
+            ..default()
+        };
+        assert!(!passes_through_blocks(
+            true,
+            &flight_only,
+            MovementState::Flying
+        ));
+
+        let config = flying_config(&MovementConfig::default(), 2.0);
+        assert_eq!(config.flying.max_speed, 20.0);
+        assert_eq!(config.grounded, MovementConfig::default().grounded);
+    }
+}
@@ -0,0 +1,127 @@
+use bevy::{prelude::*, window::WindowFocused};
+use leafwing_input_manager::prelude::*;
+
+use crate::states::components::{GameActions, GameOptions};
+
+use super::{look::CursorGrab, player::TeleportEvent};
+
+// Everything that acts on the world, the gate holds them all back together
+pub const GATED: [GameActions; 4] = [
+    GameActions::PrimaryInteract,
+    GameActions::SecondaryInteract,
+    GameActions::DropItem,
+    GameActions::QuickUse,
+];
+
+// Keeps the click that closed a UI, focused the window or came through a respawn from also
+// landing in the world. After a close nothing gets through for `grace` seconds, and anything
+// pressed before it or during it has to be let go first
+#[derive(Resource, Debug, Clone, Default)]
+pub struct InteractionGate {
+    pub grace: f32,
+    closed_at: Option<f32>,
+    released: bool,
+}
+
+impl InteractionGate {
+    pub fn new(grace: f32) -> Self {
+        Self {
+            grace,
+            ..Default::default()
+        }
+    }
+
+    pub fn close(&mut self, now: f32) {
+        self.closed_at = Some(now);
+        self.released = false;
+    }
+
+    pub fn allow(&mut self, action_state: &ActionState<GameActions>, now: f32) -> bool {
+        let Some(closed_at) = self.closed_at else {
+            return true;
+        };
+        let pressed = GATED.iter().any(|action| action_state.pressed(*action));
+        if now - closed_at < self.grace {
+            self.released = !pressed;
+            return false;
+        }
+        if !self.released {
+            if pressed {
+                return false;
+            }
+            self.released = true;
+        }
+        self.closed_at = None;
+        true
+    }
+}
+
+// Back to playing is whenever the cursor gets grabbed again, whatever UI it was
+pub fn update_interaction_gate(
+    mut gate: ResMut<InteractionGate>,
+    grab: Res<CursorGrab>,
+    (mut focused, mut teleports): (EventReader<WindowFocused>, EventReader<TeleportEvent>),
+    options: Res<GameOptions>,
+    time: Res<Time>,
+    mut was_grabbed: Local<bool>,
+) {
+    let now = time.elapsed_seconds();
+    gate.grace = options.interaction_grace;
+    let grabbed = grab.is_grabbed();
+    let regained_focus = focused.iter().any(|event| event.focused);
+    let respawned = teleports.iter().count() > 0;
+    if (grabbed && !*was_grabbed) || regained_focus || respawned {
+        gate.close(now);
+    }
+    *was_grabbed = grabbed;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE: f32 = 0.15;
+
+    #[test]
+    fn held_across_the_boundary_needs_a_release() {
+        let mut gate = InteractionGate::new(GRACE);
+        let mut action_state = ActionState::<GameActions>::default();
+        action_state.press(GameActions::PrimaryInteract);
+        assert!(gate.allow(&action_state, 0.0));
+
+        gate.close(1.0);
+        assert!(!gate.allow(&action_state, 1.05));
+        // Still held long after the window
+        assert!(!gate.allow(&action_state, 2.0));
+        action_state.release(GameActions::PrimaryInteract);
+        assert!(gate.allow(&action_state, 2.1));
+        action_state.press(GameActions::PrimaryInteract);
+        assert!(gate.allow(&action_state, 2.2));
+    }
+
+    #[test]
+    fn fresh_clicks_wait_out_the_window() {
+        let mut gate = InteractionGate::new(GRACE);
+        let mut action_state = ActionState::<GameActions>::default();
+        gate.close(1.0);
+        assert!(!gate.allow(&action_state, 1.02));
+        action_state.press(GameActions::SecondaryInteract);
+        assert!(!gate.allow(&action_state, 1.1));
+        // Pressed inside the window, so it doesn't count once the window is over either
+        assert!(!gate.allow(&action_state, 1.2));
+        action_state.release(GameActions::SecondaryInteract);
+        assert!(gate.allow(&action_state, 1.25));
+    }
+
+    #[test]
+    fn fresh_clicks_after_the_window_go_through() {
+        let mut gate = InteractionGate::new(GRACE);
+        let mut action_state = ActionState::<GameActions>::default();
+        gate.close(1.0);
+        assert!(!gate.allow(&action_state, 1.05));
+        assert!(!gate.allow(&action_state, 1.1));
+        action_state.press(GameActions::DropItem);
+        assert!(gate.allow(&action_state, 1.2));
+        assert!(gate.allow(&action_state, 1.3));
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use vinox_common::{
+    ecs::time::{GameDuration, GameInstant},
+    storage::items::descriptor::UseTiming,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemUse {
+    pub slot: (usize, usize),
+    pub identifier: Option<String>,
+    pub primary: bool,
+    pub started: GameInstant,
+    pub duration: GameDuration,
+}
+
+// What the interact system saw this frame for the selected slot
+pub struct UseInput<'a> {
+    pub slot: (usize, usize),
+    pub identifier: Option<&'a str>,
+    pub timing: UseTiming,
+    pub primary_pressed: bool,
+    pub secondary_pressed: bool,
+    pub primary_held: bool,
+    pub secondary_held: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct ItemUseState {
+    pub in_progress: Option<ItemUse>,
+    // When each hotbar slot went on cooldown and for how long
+    cooldowns: HashMap<(usize, usize), (GameInstant, GameDuration)>,
+}
+
+impl ItemUseState {
+    // Some(primary) on the frame a use goes through, that's when the ClientMessage gets sent
+    pub fn update(&mut self, input: UseInput, now: GameInstant) -> Option<bool> {
+        if let Some(current) = &self.in_progress {
+            let held = if current.primary {
+                input.primary_held
+            } else {
+                input.secondary_held
+            };
+            // Letting go or picking another slot or stack throws the use away
+            if !held
+                || current.slot != input.slot
+                || current.identifier.as_deref() != input.identifier
+            {
+                self.in_progress = None;
+            } else if now - current.started >= current.duration {
+                let primary = current.primary;
+                self.in_progress = None;
+                self.start_cooldown(input.slot, input.timing, now);
+                return Some(primary);
+            } else {
+                return None;
+            }
+        }
+
+        let primary = if input.primary_pressed {
+            true
+        } else if input.secondary_pressed {
+            false
+        } else {
+            return None;
+        };
+        if self.cooldown_left(input.slot, now) > GameDuration::ZERO {
+            return None;
+        }
+        if input.timing.is_instant() {
+            self.start_cooldown(input.slot, input.timing, now);
+            return Some(primary);
+        }
+        self.in_progress = Some(ItemUse {
+            slot: input.slot,
+            identifier: input.identifier.map(str::to_string),
+            primary,
+            started: now,
+            duration: input.timing.duration,
+        });
+        None
+    }
+
+    fn start_cooldown(&mut self, slot: (usize, usize), timing: UseTiming, now: GameInstant) {
+        if timing.cooldown > GameDuration::ZERO {
+            self.cooldowns.insert(slot, (now, timing.cooldown));
+        }
+    }
+
+    pub fn cooldown_left(&self, slot: (usize, usize), now: GameInstant) -> GameDuration {
+        match self.cooldowns.get(&slot) {
+            Some((started, cooldown)) => (*started + *cooldown) - now,
+            None => GameDuration::ZERO,
+        }
+    }
+
+    // 1 right after a use and 0 once the slot is ready, drives the sweep over the hotbar icon
+    pub fn cooldown_fraction(&self, slot: (usize, usize), now: GameInstant) -> f32 {
+        match self.cooldowns.get(&slot) {
+            Some((_, cooldown)) if cooldown.as_secs() > 0.0 => {
+                self.cooldown_left(slot, now).as_secs() / cooldown.as_secs()
+            }
+            _ => 0.0,
+        }
+    }
+
+    // How far along the held use is, for the charge fill
+    pub fn charge(&self, slot: (usize, usize), now: GameInstant) -> Option<f32> {
+        let current = self.in_progress.as_ref()?;
+        if current.slot != slot || current.duration.as_secs() <= 0.0 {
+            return None;
+        }
+        Some(((now - current.started).as_secs() / current.duration.as_secs()).min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BREAD: UseTiming = UseTiming {
+        duration: GameDuration::from_millis(1000),
+        cooldown: GameDuration::ZERO,
+    };
+
+    fn at(millis: u64) -> GameInstant {
+        GameInstant::default() + GameDuration::from_millis(millis)
+    }
+
+    fn input(timing: UseTiming, pressed: bool, held: bool) -> UseInput<'static> {
+        UseInput {
+            slot: (0, 0),
+            identifier: Some("vinox:bread"),
+            timing,
+            primary_pressed: false,
+            secondary_pressed: pressed,
+            primary_held: false,
+            secondary_held: held,
+        }
+    }
+
+    #[test]
+    fn releasing_early_cancels() {
+        let mut state = ItemUseState::default();
+        assert_eq!(state.update(input(BREAD, true, true), at(0)), None);
+        assert_eq!(state.update(input(BREAD, false, true), at(500)), None);
+        assert_eq!(state.charge((0, 0), at(500)), Some(0.5));
+        assert_eq!(state.update(input(BREAD, false, false), at(600)), None);
+        assert!(state.in_progress.is_none());
+        // Holding on past the duration after letting go does nothing
+        assert_eq!(state.update(input(BREAD, false, true), at(1200)), None);
+
+        assert_eq!(state.update(input(BREAD, true, true), at(2000)), None);
+        assert_eq!(
+            state.update(input(BREAD, false, true), at(3000)),
+            Some(false)
+        );
+        assert!(state.in_progress.is_none());
+    }
+
+    #[test]
+    fn switching_slots_cancels() {
+        let mut state = ItemUseState::default();
+        state.update(input(BREAD, true, true), at(0));
+        let mut moved = input(BREAD, false, true);
+        moved.slot = (0, 1);
+        moved.identifier = Some("vinox:stone");
+        moved.timing = UseTiming::default();
+        assert_eq!(state.update(moved, at(1500)), None);
+        assert!(state.in_progress.is_none());
+        // Coming back doesn't resume the old use either
+        assert_eq!(state.update(input(BREAD, false, true), at(1600)), None);
+
+        // Same slot but the stack under it was swapped
+        state.update(input(BREAD, true, true), at(2000));
+        let mut swapped = input(BREAD, false, true);
+        swapped.identifier = Some("vinox:apple");
+        assert_eq!(state.update(swapped, at(3500)), None);
+        assert!(state.in_progress.is_none());
+    }
+
+    #[test]
+    fn cooldown_gates_reuse() {
+        let shovel = UseTiming {
+            duration: GameDuration::ZERO,
+            cooldown: GameDuration::from_millis(250),
+        };
+        let mut state = ItemUseState::default();
+        let swing = |pressed| UseInput {
+            primary_pressed: pressed,
+            primary_held: pressed,
+            ..input(shovel, false, false)
+        };
+        assert_eq!(state.update(swing(true), at(0)), Some(true));
+        assert_eq!(state.cooldown_fraction((0, 0), at(0)), 1.0);
+        assert_eq!(state.update(swing(true), at(100)), None);
+        assert_eq!(state.update(swing(true), at(250)), Some(true));
+        // Other slots have their own cooldown
+        let mut other = swing(true);
+        other.slot = (1, 0);
+        assert_eq!(state.update(other, at(260)), Some(true));
+
+        // No timing at all fires on every press, same as before cooldowns existed
+        let mut state = ItemUseState::default();
+        for millis in 0..5 {
+            assert_eq!(
+                state.update(
+                    UseInput {
+                        timing: UseTiming::default(),
+                        ..swing(true)
+                    },
+                    at(millis)
+                ),
+                Some(true)
+            );
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use bevy::{
+    input::mouse::MouseMotion,
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+
+use crate::states::components::GameOptions;
+
+// Everything that differs between platforms about the mouse lives here, the rest of the game
+// only ever sees LookDelta and CursorGrab.
+//
+// Sensitivity 1 turns RADIANS_PER_COUNT for every count the mouse reports, which is what mouse
+// look always did on raw motion, so raw input keeps everyone's old sensitivity. Pointer movement
+// is taken in physical pixels instead of logical ones, which the OS moves one per count with its
+// pointer speed at the default and acceleration off, so the slider means the same either way and
+// on every monitor whatever its scale factor
+pub const RADIANS_PER_COUNT: f32 = 0.003;
+// How far behind the mouse smoothed look lags, in seconds
+pub const LOOK_SMOOTHING_SECONDS: f32 = 0.05;
+// Frames a release has to be asked for in a row before the cursor is actually let go
+pub const UNGRAB_DEBOUNCE_FRAMES: u8 = 2;
+// Desktops report motion straight from the device, anywhere else only the pointer is certain
+pub const RAW_INPUT_SUPPORTED: bool = cfg!(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd"
+));
+
+// How far the mouse moved this frame in counts, x right and y down
+pub struct LookDelta(pub Vec2);
+
+// Averages how fast the mouse is going rather than how far it went each frame, so it settles on
+// the same turn rate at any frame rate and the total still comes out the same once it stops
+#[derive(Resource, Debug, Default)]
+pub struct LookSmoothing {
+    velocity: Vec2,
+}
+
+impl LookSmoothing {
+    // Counts moved this frame in, counts to turn by out
+    pub fn smooth(&mut self, delta: Vec2, seconds: f32) -> Vec2 {
+        if seconds <= 0.0 {
+            return delta;
+        }
+        let amount = 1.0 - (-seconds / LOOK_SMOOTHING_SECONDS).exp();
+        self.velocity += (delta / seconds - self.velocity) * amount;
+        self.velocity * seconds
+    }
+
+    pub fn reset(&mut self) {
+        self.velocity = Vec2::ZERO;
+    }
+}
+
+// Locked holds the cursor still by itself. Windows and X11 can only confine it, so there it gets
+// put back in the middle of the window every frame
+pub fn platform_grab_mode() -> CursorGrabMode {
+    if cfg!(target_os = "macos") {
+        CursorGrabMode::Locked
+    } else {
+        CursorGrabMode::Confined
+    }
+}
+
+// Logical pixels from the window centre to counts. Bevy puts the window origin bottom left
+pub fn pointer_counts(offset: Vec2, scale_factor: f64) -> Vec2 {
+    Vec2::new(offset.x, -offset.y) * scale_factor as f32
+}
+
+// Yaw and pitch to turn by, in radians
+pub fn look_angles(delta: Vec2, sensitivity: f32) -> Vec2 {
+    delta * sensitivity * RADIANS_PER_COUNT
+}
+
+// Grabbing goes through straight away. Letting go waits a couple of frames so a UI that closes
+// as soon as it opened doesn't flash the cursor
+#[derive(Debug, Default)]
+pub struct GrabDebounce {
+    applied: bool,
+    releasing: u8,
+}
+
+impl GrabDebounce {
+    // What the window has to change to this frame, if anything
+    pub fn update(&mut self, wanted: bool) -> Option<bool> {
+        if wanted {
+            self.releasing = 0;
+            return (!self.applied).then(|| {
+                self.applied = true;
+                true
+            });
+        }
+        if !self.applied {
+            return None;
+        }
+        self.releasing += 1;
+        (self.releasing >= UNGRAB_DEBOUNCE_FRAMES).then(|| {
+            self.applied = false;
+            self.releasing = 0;
+            false
+        })
+    }
+
+    pub fn applied(&self) -> bool {
+        self.applied
+    }
+}
+
+// Whether the game wants the mouse. Only apply_cursor_grab touches the window
+#[derive(Resource)]
+pub struct CursorGrab {
+    wanted: bool,
+    mode: CursorGrabMode,
+    debounce: GrabDebounce,
+}
+
+impl Default for CursorGrab {
+    fn default() -> Self {
+        Self {
+            wanted: false,
+            mode: platform_grab_mode(),
+            debounce: GrabDebounce::default(),
+        }
+    }
+}
+
+impl CursorGrab {
+    pub fn grab(&mut self) {
+        self.wanted = true;
+    }
+
+    pub fn release(&mut self) {
+        self.wanted = false;
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        self.wanted
+    }
+
+    fn recentres(&self) -> bool {
+        self.mode == CursorGrabMode::Confined && self.debounce.applied()
+    }
+}
+
+fn centre(window: &Window) -> Vec2 {
+    Vec2::new(window.width() / 2.0, window.height() / 2.0)
+}
+
+// Runs before the replay so recordings hold what look actually used
+pub fn read_look_delta(
+    mut motion: EventReader<MouseMotion>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    grab: Res<CursorGrab>,
+    options: Res<GameOptions>,
+    mut look: EventWriter<LookDelta>,
+) {
+    let raw = motion
+        .iter()
+        .fold(Vec2::ZERO, |sum, motion| sum + motion.delta);
+    let Ok(mut window) = windows.get_single_mut() else {
+        return;
+    };
+    // Moving the cursor of a window in the background would drag it away from whatever has focus
+    if !grab.recentres() || !window.focused {
+        // A locked cursor never moves, raw motion is all there is
+        if raw != Vec2::ZERO {
+            look.send(LookDelta(raw));
+        }
+        return;
+    }
+    let centre = centre(&window);
+    let pointer = window.cursor_position().map_or(Vec2::ZERO, |position| {
+        pointer_counts(position - centre, window.scale_factor())
+    });
+    if pointer != Vec2::ZERO {
+        window.set_cursor_position(Some(centre));
+    }
+    let delta = if options.raw_mouse_input {
+        raw
+    } else {
+        pointer
+    };
+    if delta != Vec2::ZERO {
+        look.send(LookDelta(delta));
+    }
+}
+
+pub fn apply_cursor_grab(
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut grab: ResMut<CursorGrab>,
+) {
+    let Ok(mut window) = windows.get_single_mut() else {
+        return;
+    };
+    let wanted = grab.wanted;
+    let Some(grabbed) = grab.debounce.update(wanted) else {
+        return;
+    };
+    // A confined cursor is wherever it was left. Grabbing from there would turn by the offset on
+    // the first frame and letting go would show it there
+    if grab.mode == CursorGrabMode::Confined {
+        let centre = centre(&window);
+        window.set_cursor_position(Some(centre));
+    }
+    window.cursor.grab_mode = if grabbed {
+        grab.mode
+    } else {
+        CursorGrabMode::None
+    };
+    window.cursor.visible = !grabbed;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_movement_turns_the_same_on_any_monitor() {
+        // Two inches of movement at 800 dpi is 1600 counts, or physical pixels on the pointer
+        let raw = look_angles(Vec2::new(1600.0, 0.0), 1.0);
+        let normal = look_angles(pointer_counts(Vec2::new(1600.0, 0.0), 1.0), 1.0);
+        // The same physical pixels on a 2x display are half as many logical ones
+        let hidpi = look_angles(pointer_counts(Vec2::new(800.0, 0.0), 2.0), 1.0);
+        assert!((raw.x - 4.8).abs() < 1e-5 && raw.y == 0.0);
+        assert_eq!(normal, raw);
+        assert_eq!(hidpi, raw);
+    }
+
+    #[test]
+    fn smoothing_spreads_a_flick_out_without_losing_any() {
+        let mut smoothing = LookSmoothing::default();
+        let first = smoothing.smooth(Vec2::new(100.0, 0.0), 1.0 / 60.0);
+        assert!(first.x > 0.0 && first.x < 100.0, "{first}");
+        let mut total = first;
+        for _ in 0..120 {
+            total += smoothing.smooth(Vec2::ZERO, 1.0 / 60.0);
+        }
+        assert!((total.x - 100.0).abs() < 0.1, "{total}");
+
+        // Once it catches up, half the frame rate turns just as fast
+        let mut slow = LookSmoothing::default();
+        let mut fast = LookSmoothing::default();
+        let (mut slow_turn, mut fast_turn) = (0.0, 0.0);
+        for _ in 0..30 {
+            slow_turn = slow.smooth(Vec2::new(20.0, 0.0), 1.0 / 30.0).x * 30.0;
+            fast.smooth(Vec2::new(10.0, 0.0), 1.0 / 60.0);
+            fast_turn = fast.smooth(Vec2::new(10.0, 0.0), 1.0 / 60.0).x * 60.0;
+        }
+        assert!((slow_turn - 1200.0).abs() < 1.0, "{slow_turn}");
+        assert!((fast_turn - 1200.0).abs() < 1.0, "{fast_turn}");
+        // Moving up is a smaller window y but a negative look y, like raw motion
+        assert_eq!(
+            pointer_counts(Vec2::new(0.0, 10.0), 1.5),
+            Vec2::new(0.0, -15.0)
+        );
+        assert_eq!(look_angles(Vec2::ONE, 2.0), Vec2::splat(0.006));
+    }
+
+    #[test]
+    fn one_frame_releases_never_reach_the_window() {
+        let mut debounce = GrabDebounce::default();
+        assert_eq!(debounce.update(false), None);
+        assert_eq!(debounce.update(true), Some(true));
+        assert_eq!(debounce.update(true), None);
+
+        // A UI that opened and closed again straight away
+        assert_eq!(debounce.update(false), None);
+        assert_eq!(debounce.update(true), None);
+        assert!(debounce.applied());
+
+        // Held long enough, it lets go once
+        for _ in 1..UNGRAB_DEBOUNCE_FRAMES {
+            assert_eq!(debounce.update(false), None);
+        }
+        assert_eq!(debounce.update(false), Some(false));
+        assert_eq!(debounce.update(false), None);
+        // Grabbing again doesn't wait
+        assert_eq!(debounce.update(true), Some(true));
+    }
+}
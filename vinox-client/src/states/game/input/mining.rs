@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+
+// The outline fades to this as a slow block gets closer to breaking
+pub const CRACK_COLOR: Color = Color::rgba(0.2, 0.15, 0.1, 1.0);
+
+// How far along breaking the targeted block is, for blocks with a break_time. Anything else
+// breaks on the click and never shows up here
+#[derive(Resource, Debug, Clone, Default)]
+pub struct BreakProgress {
+    voxel: Option<IVec3>,
+    progress: f32,
+}
+
+impl BreakProgress {
+    // Another `delta` seconds of holding on `target`, true once it has been held for the whole
+    // `break_time`. Looking somewhere else or letting go starts it over
+    pub fn advance(&mut self, target: IVec3, held: bool, delta: f32, break_time: f32) -> bool {
+        if !held {
+            self.reset();
+            return false;
+        }
+        if self.voxel != Some(target) {
+            self.voxel = Some(target);
+            self.progress = 0.0;
+        }
+        self.progress += delta / break_time;
+        if self.progress < 1.0 {
+            return false;
+        }
+        // The next block along gets its own full break time
+        self.reset();
+        true
+    }
+
+    pub fn reset(&mut self) {
+        self.voxel = None;
+        self.progress = 0.0;
+    }
+
+    // 0 to 1, 0 when nothing is being broken
+    pub fn progress(&self) -> f32 {
+        self.progress.clamp(0.0, 1.0)
+    }
+}
+
+pub fn crack_color(base: Color, progress: f32) -> Color {
+    let t = progress.clamp(0.0, 1.0);
+    let [r, g, b, a] = base.as_rgba_f32();
+    let [cr, cg, cb, ca] = CRACK_COLOR.as_rgba_f32();
+    Color::rgba(
+        r + (cr - r) * t,
+        g + (cg - g) * t,
+        b + (cb - b) * t,
+        a + (ca - a) * t,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holding_on_one_voxel_breaks_it_after_the_break_time() {
+        let mut mining = BreakProgress::default();
+        let stone = IVec3::new(3, 60, -7);
+        for _ in 0..9 {
+            assert!(!mining.advance(stone, true, 0.1, 1.0));
+        }
+        assert!((mining.progress() - 0.9).abs() < 1e-5);
+        assert!(mining.advance(stone, true, 0.1 + 1e-4, 1.0));
+        // Done, the block behind it starts from nothing
+        assert_eq!(mining.progress(), 0.0);
+        assert!(!mining.advance(stone, true, 0.5, 1.0));
+        assert_eq!(mining.progress(), 0.5);
+    }
+
+    #[test]
+    fn looking_away_or_letting_go_starts_over() {
+        let mut mining = BreakProgress::default();
+        let (first, second) = (IVec3::new(0, 64, 0), IVec3::new(1, 64, 0));
+        assert!(!mining.advance(first, true, 0.75, 1.0));
+        assert!(!mining.advance(second, true, 0.5, 1.0));
+        assert_eq!(mining.progress(), 0.5);
+
+        assert!(!mining.advance(second, false, 0.5, 1.0));
+        assert_eq!(mining.progress(), 0.0);
+        assert!(!mining.advance(second, true, 0.75, 1.0));
+        assert_eq!(mining.progress(), 0.75);
+    }
+
+    #[test]
+    fn crack_tint_darkens_with_progress() {
+        let base = Color::rgba(1.1, 1.1, 1.1, 1.0);
+        assert_eq!(crack_color(base, 0.0), base);
+        assert_eq!(crack_color(base, 1.0), CRACK_COLOR);
+        let halfway = crack_color(base, 0.5).r();
+        assert!(halfway < base.r() && halfway > CRACK_COLOR.r());
+    }
+}
@@ -1,2 +1,15 @@
+pub mod arrange;
+pub mod camera;
+pub mod denied;
+pub mod drop;
+pub mod flight;
+pub mod gate;
+pub mod item_use;
+pub mod look;
+pub mod mining;
 pub mod player;
 pub mod plugin;
+pub mod seat;
+pub mod template;
+pub mod tools;
+pub mod variant;
@@ -2,48 +2,132 @@ use leafwing_input_manager::prelude::*;
 use std::f32::consts::{FRAC_PI_2, PI};
 
 use bevy::{
-    input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
-    math::Vec3A,
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    math::{DVec3, Vec3A},
     prelude::*,
     render::{
         camera::CameraProjection,
         primitives::{Aabb, Frustum},
     },
-    window::{CursorGrabMode, PresentMode, PrimaryWindow},
+    window::PresentMode,
 };
-use bevy_quinnet::client::Client;
+use bevy_egui::EguiContexts;
 use vinox_common::{
-    ecs::bundles::Inventory,
-    networking::protocol::ClientMessage,
-    physics::{collision::raycast::raycast_world, simulate::Velocity},
+    ecs::{
+        bundles::{Inventory, InventorySection, SlotRef},
+        gameplay::GameplayRules,
+        time::GameClock,
+    },
+    networking::protocol::{ClientMessage, DenyReason, PlayerFlags},
+    physics::{
+        collision::raycast::raycast_world,
+        movement::{block_flags, step_movement, MovementConfig, MovementInput, MovementState},
+        simulate::Velocity,
+    },
     storage::{blocks::descriptor::BlockGeometry, items::descriptor::ItemData},
-    world::chunks::{
-        ecs::{ChunkManager, CurrentChunks},
-        positions::{relative_voxel_to_world, voxel_to_world, world_to_chunk, world_to_voxel},
-        positions::{voxel_to_global_voxel, ChunkPos},
-        storage::{
-            self, name_to_identifier, trim_geo_identifier, BlockData, ItemTable, CHUNK_SIZE,
-            HORIZONTAL_DISTANCE,
+    world::{
+        chunks::{
+            ecs::ChunkManager,
+            positions::{global_voxel_positions, world_to_chunk, world_to_chunk_f64},
+            positions::{voxel_to_global_voxel, ChunkPos, WorldOffset},
+            storage::{
+                self, name_to_identifier, trim_geo_identifier, BlockData, BlockTable, ItemTable,
+                CHUNK_SIZE,
+            },
         },
+        frames::{can_hold_frame, displayed_item, frame_use, is_display_frame},
+        placement::{BuildLock, BuildLockMode},
+        seats::is_seat,
+        spawn::is_sleepable,
     },
 };
 
 use crate::states::{
-    components::{GameActions, GameOptions},
+    components::{GameActions, GameOptions, SessionScoped},
     game::{
+        input::{
+            camera::{Crouch, EYE_OFFSET},
+            denied::{BlockDeniedEvent, DeniedFlash, PendingEdits},
+            flight::{flying_config, JumpTaps},
+            gate::InteractionGate,
+            item_use::{ItemUseState, UseInput},
+            look::{look_angles, CursorGrab, LookDelta, LookSmoothing},
+            mining::BreakProgress,
+            template::HeldBlockTemplate,
+            variant::{PlacementVariant, VariantMenu},
+        },
+        networking::components::Capabilities,
+        networking::connection::NetClient,
         networking::syncing::HighLightCube,
-        ui::{dropdown::ConsoleOpen, plugin::InUi},
-        world::chunks::ControlledPlayer,
+        rendering::{
+            transitions::BlockEditEvent,
+            view_model::{held_item, view_model_camera},
+        },
+        ui::{
+            dropdown::ConsoleOpen, encyclopedia::EncyclopediaState, palette::PaletteState,
+            plugin::InUi,
+        },
+        world::{chunks::ControlledPlayer, frames::frame_request, origin::RenderSpace},
     },
-    menu::ui::InOptions,
+    menu::ui::{InOptions, Rebinding},
 };
 
+// The server moving us, for respawns. In world space, it can be anywhere
+pub struct TeleportEvent {
+    pub translation: DVec3,
+}
+
+// What the camera is looking at, from the one raycast interact makes a frame. Anything else that
+// cares reads this instead of casting again
+#[derive(Resource, Default, Clone, Debug)]
+pub struct TargetedBlock(pub Option<BlockTarget>);
+
+#[derive(Clone, Debug)]
+pub struct BlockTarget {
+    pub voxel: IVec3,
+    pub block: BlockData,
+    // What the held item would do here is past the build limits
+    pub blocked: bool,
+}
+
+// What a right click would put down and where, for the ghost. Set alongside TargetedBlock
+#[derive(Resource, Default, Clone, Debug)]
+pub struct PlacementPreview(pub Option<PreviewBlock>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreviewBlock {
+    pub voxel: IVec3,
+    pub block: BlockData,
+    // Clicking would do nothing, it's inside the player or past the build limits
+    pub blocked: bool,
+}
+
+// Set by the first placement made with BuildLock held, cleared when it's let go
+#[derive(Resource, Default, Clone, Debug)]
+pub struct BuildLockState(pub Option<BuildLock>);
+
+// Reset when the session ends so the next one gets a camera of its own
+#[derive(Resource, Default)]
+pub struct CameraSpawned(pub bool);
+
 #[derive(Component)]
 pub struct FPSCamera {
     pub phi: f32,
     pub theta: f32,
 }
 
+impl FPSCamera {
+    // Where the eyes point, the camera itself only faces the same way in first person
+    pub fn look(&self) -> Transform {
+        let looking_at = Vec3::new(
+            10.0 * self.phi.cos() * self.theta.sin(),
+            10.0 * self.theta.cos(),
+            10.0 * self.phi.sin() * self.theta.sin(),
+        );
+        Transform::from_translation(EYE_OFFSET).looking_at(looking_at, Vec3::Y)
+    }
+}
+
 impl Default for FPSCamera {
     fn default() -> Self {
         FPSCamera {
@@ -81,63 +165,85 @@ pub fn update_vsync(options: Res<GameOptions>, mut windows: Query<&mut Window>)
     }
 }
 
-pub fn update_fov(mut camera: Query<(&mut Projection, &mut Frustum)>, options: Res<GameOptions>) {
-    if let Ok((mut projection, mut frustum)) = camera.get_single_mut() {
-        if options.is_changed() {
-            let perspective_projection = PerspectiveProjection {
-                fov: options.fov.to_radians(),
-                near: 0.001,
-                far: 1000.0,
-                aspect_ratio: 1.0,
-            };
-            let view_projection = perspective_projection.get_projection_matrix();
-            *frustum = Frustum::from_view_projection(
-                &view_projection,
-                // &Vec3::ZERO,
-                // &Vec3::Z,
-                // perspective_projection.far(),
-            );
-            *projection = Projection::Perspective(perspective_projection);
+// Degrees added to the FOV while sprinting, eased toward at FOV_KICK_RATE a second
+pub const SPRINT_FOV_KICK: f32 = 10.0;
+pub const FOV_KICK_RATE: f32 = 8.0;
+
+// How far into the sprint FOV kick the camera is, in degrees on top of the slider
+#[derive(Resource, Debug, Default)]
+pub struct FovKick(pub f32);
+
+impl FovKick {
+    // Snaps the last little bit so the projection stops being rebuilt once it's there
+    pub fn ease(&mut self, target: f32, delta: f32) {
+        self.0 += (target - self.0) * (1.0 - (-FOV_KICK_RATE * delta).exp());
+        if (target - self.0).abs() < 0.01 {
+            self.0 = target;
         }
     }
 }
 
+// The frustum has to match or culling goes by the old FOV
+pub fn world_projection(fov: f32) -> (Projection, Frustum) {
+    let perspective_projection = PerspectiveProjection {
+        fov: fov.to_radians(),
+        near: 0.001,
+        far: 1000.0,
+        aspect_ratio: 1.0,
+    };
+    let view_projection = perspective_projection.get_projection_matrix();
+    let frustum = Frustum::from_view_projection(
+        &view_projection,
+        // &Vec3::ZERO,
+        // &Vec3::Z,
+        // perspective_projection.far(),
+    );
+    (Projection::Perspective(perspective_projection), frustum)
+}
+
+// Only the world camera, the view model keeps its own FOV
+pub fn update_fov(
+    mut camera: Query<(&mut Projection, &mut Frustum), With<FPSCamera>>,
+    player: Query<&PlayerFlags, With<ControlledPlayer>>,
+    mut kick: ResMut<FovKick>,
+    (options, time): (Res<GameOptions>, Res<Time>),
+) {
+    let sprinting = options.sprint_fov
+        && !options.reduce_motion
+        && player.get_single().is_ok_and(|flags| flags.sprinting());
+    let before = kick.0;
+    kick.ease(
+        if sprinting { SPRINT_FOV_KICK } else { 0.0 },
+        time.delta_seconds(),
+    );
+    if !options.is_changed() && kick.0 == before {
+        return;
+    }
+    if let Ok((mut projection, mut frustum)) = camera.get_single_mut() {
+        (*projection, *frustum) = world_projection(options.fov + kick.0);
+    }
+}
+
 pub fn spawn_camera(
     mut commands: Commands,
     player_entity: Query<Entity, With<ControlledPlayer>>,
-    mut local: Local<bool>,
-    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut spawned: ResMut<CameraSpawned>,
+    mut grab: ResMut<CursorGrab>,
     options: Res<GameOptions>,
 ) {
-    if *local {
+    if spawned.0 {
         return;
     }
     if let Ok(player_entity) = player_entity.get_single() {
-        let Ok(mut window) = windows.get_single_mut() else {
-            return;
-        };
-        window.cursor.grab_mode = CursorGrabMode::Locked;
-        window.cursor.visible = false;
+        grab.grab();
 
-        *local = true;
+        spawned.0 = true;
         let camera = {
-            let perspective_projection = PerspectiveProjection {
-                fov: options.fov.to_radians(),
-                near: 0.001,
-                far: 1000.0,
-                aspect_ratio: 1.0,
-            };
-            let view_projection = perspective_projection.get_projection_matrix();
-            let frustum = Frustum::from_view_projection(
-                &view_projection,
-                // &Vec3::ZERO,
-                // &Vec3::Z,
-                // perspective_projection.far(),
-            );
+            let (projection, frustum) = world_projection(options.fov);
             Camera3dBundle {
-                projection: Projection::Perspective(perspective_projection),
+                projection,
                 frustum,
-                transform: Transform::from_translation(Vec3::new(0.0, 1.8, 0.0)),
+                transform: Transform::from_translation(EYE_OFFSET),
                 // camera: Camera {
                 //     hdr: true,
                 //     ..Default::default()
@@ -153,111 +259,141 @@ pub fn spawn_camera(
             ));
             c.spawn((
                 FPSCamera::default(),
+                SessionScoped,
                 camera,
+                // So the held item under the view model camera gets drawn
+                VisibilityBundle::default(),
                 FogSettings {
                     color: Color::rgba(0.1, 0.1, 0.1, 1.0),
                     directional_light_color: Color::WHITE,
                     directional_light_exponent: 10.0,
-                    falloff: FogFalloff::Linear {
-                        start: (HORIZONTAL_DISTANCE * CHUNK_SIZE) as f32
-                            - (CHUNK_SIZE * (HORIZONTAL_DISTANCE / 3)) as f32,
-                        end: (HORIZONTAL_DISTANCE * CHUNK_SIZE) as f32 + (CHUNK_SIZE) as f32,
-                    },
+                    falloff: view_fog(options.view_distance),
                 },
-            ));
+            ))
+            .with_children(|c| {
+                c.spawn(view_model_camera()).with_children(|c| {
+                    c.spawn(held_item());
+                });
+            });
         });
     }
 }
 
-#[derive(Resource)]
-pub struct MouseSensitivity(pub f32);
+// Thickens over the last third of the view radius so the edge of the loaded world fades out
+pub fn view_fog(horizontal: usize) -> FogFalloff {
+    FogFalloff::Linear {
+        start: (horizontal * CHUNK_SIZE) as f32 - (CHUNK_SIZE * (horizontal / 3)) as f32,
+        end: (horizontal * CHUNK_SIZE) as f32 + (CHUNK_SIZE) as f32,
+    }
+}
 
 #[allow(clippy::too_many_arguments)]
 pub fn handle_movement(
     mut player: Query<&mut FPSCamera>,
     mut player_position: Query<
-        (&mut Transform, &mut Velocity, &ActionState<GameActions>),
+        (
+            &Transform,
+            &mut Velocity,
+            &mut MovementState,
+            &mut PlayerFlags,
+            &ActionState<GameActions>,
+        ),
         With<ControlledPlayer>,
     >,
-    mut camera_transform: Query<&mut Transform, (With<Camera>, Without<ControlledPlayer>)>,
-    mut mouse_events: EventReader<MouseMotion>,
-    mouse_sensitivity: Res<MouseSensitivity>,
-    windows: Query<&Window, With<PrimaryWindow>>,
-    mut stationary_frames: Local<i32>,
-    current_chunks: Res<CurrentChunks>,
-    time: Res<Time>,
+    mut look_events: EventReader<LookDelta>,
+    (grab, options, mut smoothing): (Res<CursorGrab>, Res<GameOptions>, ResMut<LookSmoothing>),
+    variant_menu: Res<VariantMenu>,
+    chunk_manager: ChunkManager,
+    movement_config: Res<MovementConfig>,
+    (capabilities, mut taps): (Res<Capabilities>, ResMut<JumpTaps>),
+    clock: Res<GameClock>,
+    offset: Res<WorldOffset>,
 ) {
-    let Ok(window) = windows.get_single() else {
-        return;
-    };
-    let Ok(mut transform) = camera_transform.get_single_mut() else {
+    let Ok(mut fps_camera) = player.get_single_mut() else {
         return;
     };
-    // Update camera look
-    if window.cursor.grab_mode == CursorGrabMode::Locked {
-        if let Ok(mut fps_camera) = player.get_single_mut() {
-            for MouseMotion { delta } in mouse_events.iter() {
-                fps_camera.phi += delta.x * mouse_sensitivity.0 * 0.003;
-                fps_camera.theta = (fps_camera.theta + delta.y * mouse_sensitivity.0 * 0.003)
-                    .clamp(0.00005, PI - 0.00005);
+    // Update camera look, place_camera puts the camera itself where the mode wants it
+    if grab.is_grabbed() {
+        let mut delta = Vec2::ZERO;
+        for LookDelta(moved) in look_events.iter() {
+            // The variant menu has the mouse while it's open
+            if variant_menu.open {
+                continue;
             }
-            let looking_at = Vec3::new(
-                10.0 * fps_camera.phi.cos() * fps_camera.theta.sin(),
-                10.0 * fps_camera.theta.cos(),
-                10.0 * fps_camera.phi.sin() * fps_camera.theta.sin(),
-            );
-            transform.look_at(looking_at, Vec3::new(0.0, 1.0, 0.0));
+            delta += *moved;
+        }
+        if options.mouse_smoothing {
+            delta = smoothing.smooth(delta, clock.delta_seconds());
         }
+        let turn = look_angles(delta, options.mouse_sensitivity);
+        fps_camera.phi += turn.x;
+        fps_camera.theta = (fps_camera.theta + turn.y).clamp(0.00005, PI - 0.00005);
+    } else {
+        smoothing.reset();
     }
+    // Moving goes by the look so third person steers the same as first
+    let transform = fps_camera.look();
     // Update velocity with movement input
-    if let Ok((translation, mut velocity, action_state)) = player_position.get_single_mut() {
-        let mut movement = Vec3::ZERO;
-
-        if velocity.0.y.abs() < 0.001 && *stationary_frames < 10 {
-            *stationary_frames += 4;
-        } else if *stationary_frames >= 0 {
-            *stationary_frames -= 1;
+    if let Ok((player_transform, mut velocity, mut movement_state, mut flags, action_state)) =
+        player_position.get_single_mut()
+    {
+        let chunk_pos = offset.chunk_to_world(world_to_chunk(player_transform.translation));
+        if chunk_manager
+            .current_chunks
+            .get_entity(ChunkPos(chunk_pos))
+            .is_none()
+        {
+            return;
         }
-
-        let gravity = 35.0 * Vec3::NEG_Y;
-        velocity.0 += gravity * time.delta().as_secs_f32().clamp(0.0, 0.1);
-
-        let chunk_pos = world_to_chunk(translation.translation);
-        if window.cursor.grab_mode == CursorGrabMode::Locked {
-            if current_chunks.get_entity(ChunkPos(chunk_pos)).is_none() {
-                return;
-            }
-
+        let mut input = MovementInput::default();
+        if grab.is_grabbed() {
+            let mut direction = Vec3::ZERO;
             if action_state.pressed(GameActions::Forward) {
                 let mut fwd = transform.forward();
                 fwd.y = 0.0;
-                let fwd = fwd.normalize();
-                movement += fwd;
+                direction += fwd.normalize();
             }
             if action_state.pressed(GameActions::Left) {
-                movement += transform.left()
+                direction += transform.left()
             }
             if action_state.pressed(GameActions::Right) {
-                movement += transform.right()
+                direction += transform.right()
             }
             if action_state.pressed(GameActions::Backward) {
                 let mut back = transform.back();
                 back.y = 0.0;
-                let back = back.normalize();
-                movement += back;
-            }
-            movement = movement.normalize_or_zero();
-            if action_state.pressed(GameActions::Run) {
-                movement *= 10.0;
-            } else {
-                movement *= 5.0;
-            }
-            if action_state.pressed(GameActions::Jump) && *stationary_frames > 2 {
-                *stationary_frames = 0;
-                velocity.0.y = 10.0;
+                direction += back.normalize();
             }
+            let double_tap =
+                action_state.just_pressed(GameActions::Jump) && taps.press(clock.now());
+            input = MovementInput {
+                direction: direction.normalize_or_zero(),
+                jump: action_state.pressed(GameActions::Jump),
+                sneak: action_state.pressed(GameActions::Sneak),
+                sprint: action_state.pressed(GameActions::Run),
+                // Denied quietly here, apply_flight says why
+                toggle_fly: capabilities.fly
+                    && (action_state.just_pressed(GameActions::ToggleFly) || double_tap),
+            };
+        }
+        velocity.0 = step_movement(
+            &mut movement_state,
+            &flying_config(&movement_config, options.fly_speed),
+            &input,
+            player_transform.translation,
+            velocity.0,
+            clock.delta_seconds(),
+            |pos| {
+                chunk_manager
+                    .get_block(offset.voxel_to_world(pos))
+                    .map(|block| block_flags(&block, &chunk_manager.block_table))
+            },
+        );
+        // Sent along with the position for everyone else to animate with
+        let now = PlayerFlags::from_movement(*movement_state, &input);
+        if *flags != now {
+            *flags = now;
         }
-        velocity.0 = Vec3::new(movement.x, velocity.0.y, movement.z);
     }
 }
 
@@ -269,14 +405,114 @@ fn norm_to_bar(item: usize) -> Option<(usize, usize)> {
     Some(((f_item / 3.0).floor() as usize, item.rem_euclid(3)))
 }
 
+// The utility slot while QuickUse is held, None leaves it to the selected slot
+pub fn quick_use_slot(utility_slot: Option<usize>, quick_use: bool) -> Option<(usize, usize)> {
+    utility_slot.filter(|_| quick_use).and_then(norm_to_bar)
+}
+
+// Placing where the player stands is skipped. `point` is the block that was clicked, both in
+// render space
+pub fn clear_of_player(point: Vec3, player: Vec3) -> bool {
+    (point.x <= player.x - 0.5 || point.x >= player.x + 0.5)
+        || (point.z <= player.z - 0.5 || point.z >= player.z + 0.5)
+        || (point.y <= player.y - 1.0 || point.y >= player.y + 1.0)
+}
+
+// The block that really goes down: the chosen variant where the item has one, facing by the face
+// it went against and then by where the player stands. A template goes down as it was picked
+pub fn placed_block(
+    mut block: BlockData,
+    variant: Option<&BlockGeometry>,
+    templated: bool,
+    normal: IVec3,
+    (player, point): (Vec3, Vec3),
+    block_table: &BlockTable,
+) -> BlockData {
+    if templated {
+        return block;
+    }
+    // Items without the chosen variant place their own block
+    if let Some(variant_name) = variant
+        .map(|geometry| geometry.geo_new_block(block.name.clone()))
+        .filter(|variant_name| {
+            block_table.contains_key(&name_to_identifier(
+                block.namespace.clone(),
+                variant_name.clone(),
+            ))
+        })
+    {
+        block.name = variant_name;
+    }
+    let descriptor = block_table.get_or_unknown(&name_to_identifier(
+        block.namespace.clone(),
+        block.name.clone(),
+    ));
+    if !descriptor.has_direction.unwrap_or(false) {
+        return block;
+    }
+    match normal.x {
+        -1 => block.direction = Some(storage::Direction::West),
+        1 => block.direction = Some(storage::Direction::East),
+        _ => {}
+    }
+    match normal.y {
+        -1 => block.top = Some(true),
+        1 => block.top = Some(false),
+        // Stairs need tops and bottoms
+        _ => {}
+    }
+    match normal.z {
+        -1 => block.direction = Some(storage::Direction::South),
+        1 => block.direction = Some(storage::Direction::North),
+        _ => {}
+    }
+    if !descriptor.exclusive_direction.unwrap_or(false) {
+        let difference = player - point;
+        if block.direction.is_none() {
+            block.direction = Some(if difference.x > difference.z {
+                if difference.x < 0.0 {
+                    storage::Direction::West
+                } else {
+                    storage::Direction::East
+                }
+            } else if difference.z < 0.0 {
+                storage::Direction::South
+            } else {
+                storage::Direction::North
+            });
+        }
+        if block.top.is_none() {
+            block.top = Some(difference.y > 0.0);
+        }
+    }
+    block
+}
+
+fn break_block(
+    chunk_manager: &mut ChunkManager,
+    block_edits: &mut EventWriter<BlockEditEvent>,
+    voxel: IVec3,
+) {
+    let air = BlockData::new("vinox".to_string(), "air".to_string());
+    if let Some(before) = chunk_manager.get_block(voxel) {
+        block_edits.send(BlockEditEvent {
+            voxel,
+            before,
+            after: air.clone(),
+            local: true,
+        });
+    }
+    chunk_manager.set_block(voxel, air);
+}
+
 //TODO: Overhaul of inventory and crafting to be reliant on server.
 #[allow(clippy::too_many_arguments)]
 #[allow(clippy::type_complexity)]
 pub fn interact(
     _commands: Commands,
-    mut windows: Query<&mut Window, With<PrimaryWindow>>,
-    camera_query: Query<&GlobalTransform, With<Camera>>,
-    mut client: ResMut<Client>,
+    grab: Res<CursorGrab>,
+    camera_query: Query<&FPSCamera>,
+    mut client: NetClient,
     mut player: Query<
         (&Transform, &ActionState<GameActions>, &mut Inventory),
         With<ControlledPlayer>,
@@ -291,17 +527,45 @@ pub fn interact(
     mut chunk_manager: ChunkManager,
     item_table: Res<ItemTable>,
     mut temp_bar: Local<Option<usize>>,
-    mut item_type: Local<BlockGeometry>,
     mut norm_item: Local<usize>,
     mut scroll_evr: EventReader<MouseWheel>,
     keys: Res<Input<KeyCode>>,
     options: Res<GameOptions>,
+    (mut use_state, clock, mut block_edits, mut denials, rules): (
+        ResMut<ItemUseState>,
+        Res<GameClock>,
+        EventWriter<BlockEditEvent>,
+        EventWriter<BlockDeniedEvent>,
+        Res<GameplayRules>,
+    ),
+    (variant, variant_menu, template, mut flash, mut mining, mut preview): (
+        Res<PlacementVariant>,
+        Res<VariantMenu>,
+        Res<HeldBlockTemplate>,
+        ResMut<DeniedFlash>,
+        ResMut<BreakProgress>,
+        ResMut<PlacementPreview>,
+    ),
+    (offset, mut targeted, mut build_lock, mut gate, mut pending, time, crouch): (
+        Res<WorldOffset>,
+        ResMut<TargetedBlock>,
+        ResMut<BuildLockState>,
+        ResMut<InteractionGate>,
+        ResMut<PendingEdits>,
+        Res<Time>,
+        Res<Crouch>,
+    ),
 ) {
-    let window = windows.single_mut();
-    if window.cursor.grab_mode != CursorGrabMode::Locked {
+    targeted.0 = None;
+    preview.0 = None;
+    if !grab.is_grabbed() || variant_menu.open {
+        mining.reset();
         return;
     }
     if let Ok((player_transform, action_state, mut inventory)) = player.get_single_mut() {
+        if !action_state.pressed(GameActions::BuildLock) {
+            build_lock.0 = None;
+        }
         for ev in scroll_evr.iter() {
             match ev.unit {
                 MouseScrollUnit::Line => {
@@ -344,32 +608,6 @@ pub fn interact(
                 }
             }
         }
-        //Temporary
-        if keys.just_pressed(KeyCode::J) {
-            *item_type = BlockGeometry::Block;
-        }
-        if keys.just_pressed(KeyCode::K) {
-            *item_type = BlockGeometry::Stairs;
-        }
-        if keys.just_pressed(KeyCode::F) {
-            *item_type = BlockGeometry::Slab;
-        }
-        if keys.just_pressed(KeyCode::L) {
-            *item_type = BlockGeometry::BorderedBlock;
-        }
-        if keys.just_pressed(KeyCode::U) {
-            *item_type = BlockGeometry::Cross;
-        }
-        if keys.just_pressed(KeyCode::I) {
-            *item_type = BlockGeometry::Flat;
-        }
-        if keys.just_pressed(KeyCode::N) {
-            *item_type = BlockGeometry::Fence;
-        }
-        if keys.just_pressed(KeyCode::P) {
-            *item_type = BlockGeometry::Custom("vinox:pole".to_string());
-        }
-
         if !options.standard_bar {
             if keys.just_pressed(KeyCode::Key1) {
                 if temp_bar.is_some() {
@@ -476,9 +714,17 @@ pub fn interact(
             }
         }
 
-        let cur_item = inventory.clone().current_item;
-        let cur_bar = inventory.clone().current_bar;
-        let item_data = inventory.clone().hotbar[*cur_bar][*cur_item].clone();
+        let selected = (*inventory.current_bar, *inventory.current_item);
+        // Breaking always goes with the selected slot
+        let quick_slot = quick_use_slot(
+            options.utility_slot,
+            action_state.pressed(GameActions::QuickUse)
+                && !action_state.pressed(GameActions::PrimaryInteract),
+        );
+        let (cur_bar, cur_item) = quick_slot.unwrap_or(selected);
+        let item_data = inventory.hotbar[cur_bar][cur_item].clone();
+        // Nothing to use, an empty hand would sit down or take from a frame instead
+        let empty_utility = quick_slot.is_some_and(|slot| slot != selected) && item_data.is_none();
         let place_item = if let Some(item) = item_data.clone() {
             if let Some(item_descriptor) = item_table.get(&name_to_identifier(
                 item.namespace.clone(),
@@ -495,189 +741,298 @@ pub fn interact(
         } else {
             None
         };
+        // A held copy goes down exactly as it was picked, the slot's item isn't used up
+        let templated = template.held_in((cur_bar, cur_item));
+        let place_item = if templated {
+            template.block.clone()
+        } else {
+            place_item
+        };
 
-        let mouse_left = action_state.just_pressed(GameActions::PrimaryInteract);
-        let mouse_right = action_state.just_pressed(GameActions::SecondaryInteract);
-        if let Ok(camera_transform) = camera_query.get_single() {
-            // Then cast the ray.
+        let held_identifier = item_data
+            .as_ref()
+            .map(|item| name_to_identifier(item.namespace.clone(), item.name.clone()));
+        // Goes along with breaks so the server can wear down what it holds there
+        let tool = item_data.as_ref().map(|_| SlotRef {
+            section: InventorySection::Hotbar,
+            bar: cur_bar,
+            slot: cur_item,
+        });
+        let allowed = gate.allow(action_state, time.elapsed_seconds());
+        let quick_pressed =
+            quick_slot.is_some() && action_state.just_pressed(GameActions::QuickUse);
+        let quick_held = quick_slot.is_some() && !empty_utility;
+        if allowed && quick_pressed && empty_utility {
+            flash.0 = Some(time.elapsed_seconds());
+        }
+        let timing = held_identifier
+            .as_ref()
+            .and_then(|identifier| item_table.get(identifier))
+            .map(|descriptor| descriptor.use_timing())
+            .unwrap_or_default();
+        let used = use_state.update(
+            UseInput {
+                slot: (cur_bar, cur_item),
+                identifier: held_identifier.as_deref(),
+                timing,
+                primary_pressed: allowed && action_state.just_pressed(GameActions::PrimaryInteract),
+                secondary_pressed: allowed
+                    && (action_state.just_pressed(GameActions::SecondaryInteract) || quick_pressed)
+                    && !empty_utility,
+                primary_held: allowed && action_state.pressed(GameActions::PrimaryInteract),
+                secondary_held: allowed
+                    && (action_state.pressed(GameActions::SecondaryInteract) || quick_held),
+            },
+            clock.now(),
+        );
+        let mouse_left = used == Some(true);
+        let mouse_right = used == Some(false);
+        if let Ok(fps_camera) = camera_query.get_single() {
+            // Then cast the ray from the eyes, wherever the camera is. It runs in world space so
+            // the hit is exact however far out we are
             let hit = raycast_world(
-                camera_transform.translation(),
-                camera_transform.forward(),
+                offset.to_world(player_transform.translation + crouch.eye()),
+                fps_camera.look().forward(),
                 50.0,
                 &chunk_manager,
             );
             if let Some((chunk_pos, voxel_pos, normal, _)) = hit {
-                let point = voxel_to_world(voxel_pos.as_vec3().as_uvec3(), *chunk_pos);
+                let hit_voxel = voxel_to_global_voxel(voxel_pos, *chunk_pos);
+                // Only for drawing and comparing against the player, both in render space
+                let point = offset.voxel_to_render(hit_voxel).as_vec3();
+                let placement = match build_lock.0 {
+                    Some(lock) => lock.snap_placement(hit_voxel + normal.as_ivec3(), |voxel| {
+                        chunk_manager
+                            .get_block(voxel)
+                            .is_some_and(|block| !block.is_empty(&chunk_manager.block_table))
+                    }),
+                    None => Some(hit_voxel + normal.as_ivec3()),
+                };
+                // Holding a block builds next to the hit, anything else breaks it
+                let edited = if place_item.is_some() {
+                    placement
+                } else {
+                    Some(hit_voxel)
+                };
+                targeted.0 = chunk_manager.get_block(hit_voxel).map(|block| BlockTarget {
+                    voxel: hit_voxel,
+                    block,
+                    blocked: edited.is_some_and(|voxel| !rules.within_build_limits(voxel)),
+                });
 
                 if let Ok((mut block_transform, mut block_visibility)) =
                     cube_position.get_single_mut()
                 {
-                    if *block_visibility == Visibility::Hidden {
-                        *block_visibility = Visibility::Visible;
+                    // While locked the cube shows where the block will really go
+                    let shown = match build_lock.0 {
+                        Some(_) if place_item.is_some() => {
+                            placement.map(|voxel| offset.voxel_to_render(voxel).as_vec3())
+                        }
+                        _ => Some(point),
+                    };
+                    match shown {
+                        Some(shown) => {
+                            if *block_visibility == Visibility::Hidden {
+                                *block_visibility = Visibility::Visible;
+                            }
+                            block_transform.translation = shown + Vec3::splat(0.5);
+                        }
+                        None => *block_visibility = Visibility::Hidden,
                     }
-                    block_transform.translation = point + Vec3::splat(0.5);
                 }
-                if mouse_left || (mouse_right && place_item.is_some()) {
+                let hit_block = chunk_manager.get_block(hit_voxel);
+                // Slow blocks go once the button has been held on them long enough, the rest on
+                // the click
+                let break_now = match hit_block
+                    .as_ref()
+                    .and_then(|block| block.break_time(&chunk_manager.block_table))
+                {
+                    Some(break_time) => mining.advance(
+                        hit_voxel,
+                        allowed
+                            && action_state.pressed(GameActions::PrimaryInteract)
+                            && rules.within_build_limits(hit_voxel),
+                        time.delta_seconds(),
+                        break_time,
+                    ),
+                    None => {
+                        mining.reset();
+                        mouse_left
+                    }
+                };
+                // Seats only take an empty hand, and sneaking builds on them instead
+                let sit = item_data.is_none() && !action_state.pressed(GameActions::Sneak);
+                let use_block = mouse_right
+                    && hit_block.as_ref().is_some_and(|block| {
+                        is_sleepable(block, &chunk_manager.block_table)
+                            || (sit && is_seat(block, &chunk_manager.block_table))
+                    });
+                // Frames take, give back or turn the item unless we're sneaking to build on them
+                let frame = hit_block
+                    .as_ref()
+                    .filter(|block| {
+                        mouse_right && is_display_frame(block, &chunk_manager.block_table)
+                    })
+                    .and_then(|block| {
+                        let action = frame_use(
+                            block,
+                            item_data.is_some(),
+                            action_state.pressed(GameActions::Sneak),
+                        )?;
+                        frame_request(
+                            action,
+                            &mut inventory,
+                            SlotRef {
+                                section: InventorySection::Hotbar,
+                                bar: cur_bar,
+                                slot: cur_item,
+                            },
+                            displayed_item(block),
+                            &item_table,
+                        )
+                    });
+                // Frames only hang on solid blocks
+                let supported = place_item.as_ref().is_none_or(|block| {
+                    !is_display_frame(block, &chunk_manager.block_table)
+                        || hit_block
+                            .as_ref()
+                            .is_some_and(|hit| can_hold_frame(hit, &chunk_manager.block_table))
+                });
+                // Beds and frames take the click instead of getting built on
+                let uses_hit = hit_block.as_ref().is_some_and(|block| {
+                    is_sleepable(block, &chunk_manager.block_table)
+                        || (is_display_frame(block, &chunk_manager.block_table)
+                            && frame_use(
+                                block,
+                                item_data.is_some(),
+                                action_state.pressed(GameActions::Sneak),
+                            )
+                            .is_some())
+                });
+                preview.0 = place_item
+                    .clone()
+                    .filter(|_| supported && !uses_hit)
+                    .zip(placement)
+                    .map(|(block, voxel)| PreviewBlock {
+                        voxel,
+                        block: placed_block(
+                            block,
+                            variant.0.as_ref(),
+                            templated,
+                            normal.as_ivec3(),
+                            (player_transform.translation, point),
+                            &chunk_manager.block_table,
+                        ),
+                        blocked: !clear_of_player(point, player_transform.translation)
+                            || !rules.within_build_limits(voxel),
+                    });
+                // Turned down here just as the server would, there's no need to wait on it
+                let outside_limits = if mouse_left {
+                    Some(hit_voxel)
+                } else if mouse_right && place_item.is_some() && supported {
+                    placement
+                } else {
+                    None
+                }
+                .filter(|voxel| !rules.within_build_limits(*voxel));
+                if let Some(request) = frame {
+                    client.send(ClientMessage::UseFrame {
+                        voxel: hit_voxel,
+                        request,
+                    });
+                } else if use_block {
+                    // Beds set where we respawn and seats sit us down instead of getting a block
+                    // placed on them
+                    client.send(ClientMessage::UseBlock { voxel: hit_voxel });
+                } else if let Some(voxel) = outside_limits {
+                    pending.push(voxel, time.elapsed_seconds());
+                    denials.send(BlockDeniedEvent {
+                        voxel,
+                        reason: DenyReason::OutsideBuildLimit,
+                    });
+                } else if break_now
+                    || (mouse_right && place_item.is_some() && placement.is_some() && supported)
+                {
                     if mouse_right {
-                        inventory.item_decrement("hotbar", *cur_bar, *cur_item);
-
-                        if (point.x <= player_transform.translation.x - 0.5
-                            || point.x >= player_transform.translation.x + 0.5)
-                            || (point.z <= player_transform.translation.z - 0.5
-                                || point.z >= player_transform.translation.z + 0.5)
-                            || (point.y <= player_transform.translation.y - 1.0
-                                || point.y >= player_transform.translation.y + 1.0)
-                        {
-                            let (chunk_pos, voxel_pos) = world_to_voxel(relative_voxel_to_world(
-                                voxel_pos.as_vec3().as_ivec3() + normal.as_ivec3(),
-                                *chunk_pos,
-                            ));
-                            if let Some(mut modified_item) = place_item.clone() {
-                                modified_item.name = if chunk_manager
-                                    .block_table
-                                    .get(&name_to_identifier(
-                                        modified_item.namespace.clone(),
-                                        item_type.geo_new_block(modified_item.name.clone()),
-                                    ))
-                                    .is_some()
-                                {
-                                    item_type.geo_new_block(modified_item.name.clone())
-                                } else {
-                                    place_item.clone().unwrap().name
-                                };
+                        if !templated {
+                            inventory.item_decrement("hotbar", cur_bar, cur_item);
+                        }
+
+                        if clear_of_player(point, player_transform.translation) {
+                            let (chunk_pos, voxel_pos) = global_voxel_positions(placement.unwrap());
+                            if let Some(modified_item) = place_item.clone().map(|block| {
+                                placed_block(
+                                    block,
+                                    variant.0.as_ref(),
+                                    templated,
+                                    normal.as_ivec3(),
+                                    (player_transform.translation, point),
+                                    &chunk_manager.block_table,
+                                )
+                            }) {
                                 let normal = normal.as_ivec3();
-                                if chunk_manager
-                                    .block_table
-                                    .get(&name_to_identifier(
-                                        modified_item.namespace.clone(),
-                                        modified_item.name.clone(),
-                                    ))
-                                    .unwrap()
-                                    .has_direction
-                                    .unwrap_or(false)
+                                let voxel = voxel_to_global_voxel(voxel_pos, chunk_pos);
+                                let after = place_item.unwrap();
+                                if let Some(before) = chunk_manager.get_block(voxel) {
+                                    block_edits.send(BlockEditEvent {
+                                        voxel,
+                                        before,
+                                        after: after.clone(),
+                                        local: true,
+                                    });
+                                }
+                                chunk_manager.set_block(voxel, after);
+                                if build_lock.0.is_none()
+                                    && action_state.pressed(GameActions::BuildLock)
                                 {
-                                    match normal.x {
-                                        -1 => {
-                                            modified_item.direction = Some(storage::Direction::West)
-                                        }
-                                        1 => {
-                                            modified_item.direction = Some(storage::Direction::East)
-                                        }
-                                        _ => {}
-                                    }
-                                    match normal.y {
-                                        -1 => {
-                                            modified_item.top = Some(true);
-                                        }
-                                        1 => {
-                                            modified_item.top = Some(false);
-                                        }
-                                        _ => {
-                                            // modified_item.top = Some(false);
-                                            // Stairs need tops and bottoms
-                                        }
-                                    }
-                                    match normal.z {
-                                        -1 => {
-                                            modified_item.direction =
-                                                Some(storage::Direction::South)
-                                        }
-                                        1 => {
-                                            modified_item.direction =
-                                                Some(storage::Direction::North)
-                                        }
-                                        _ => {}
-                                    }
-
-                                    if !chunk_manager
-                                        .block_table
-                                        .get(&name_to_identifier(
-                                            modified_item.namespace.clone(),
-                                            modified_item.name.clone(),
-                                        ))
-                                        .unwrap()
-                                        .exclusive_direction
-                                        .unwrap_or(false)
-                                    {
-                                        if modified_item.direction.is_none() {
-                                            let difference = player_transform.translation - point;
-                                            if difference.x > difference.z {
-                                                if difference.x < 0.0 {
-                                                    modified_item.direction =
-                                                        Some(storage::Direction::West)
-                                                } else {
-                                                    modified_item.direction =
-                                                        Some(storage::Direction::East)
-                                                }
-                                            } else if difference.z < 0.0 {
-                                                modified_item.direction =
-                                                    Some(storage::Direction::South)
-                                            } else {
-                                                modified_item.direction =
-                                                    Some(storage::Direction::North)
-                                            }
-                                        }
-                                        if modified_item.top.is_none() {
-                                            let difference = player_transform.translation - point;
-                                            if difference.y > 0.0 {
-                                                modified_item.top = Some(true);
-                                            } else {
-                                                modified_item.top = Some(false);
-                                            }
-                                        }
-                                    }
+                                    let mode = if action_state.pressed(GameActions::Run) {
+                                        BuildLockMode::Plane
+                                    } else {
+                                        BuildLockMode::Line
+                                    };
+                                    build_lock.0 = Some(BuildLock::new(voxel, normal, mode));
                                 }
-
-                                chunk_manager.set_block(
-                                    voxel_to_global_voxel(voxel_pos, chunk_pos),
-                                    place_item.unwrap(),
-                                );
-                                client.connection_mut().try_send_message(
+                                pending.push(voxel, time.elapsed_seconds());
+                                let voxel_pos = voxel_pos.to_array().map(|axis| axis as u8);
+                                client.send(if templated {
+                                    ClientMessage::PlaceTemplate {
+                                        chunk_pos,
+                                        voxel_pos,
+                                        block: modified_item,
+                                    }
+                                } else {
                                     ClientMessage::SentBlock {
                                         chunk_pos,
-                                        voxel_pos: [
-                                            voxel_pos.x as u8,
-                                            voxel_pos.y as u8,
-                                            voxel_pos.z as u8,
-                                        ],
+                                        voxel_pos,
                                         block_type: modified_item,
-                                    },
-                                );
+                                        item: held_identifier.clone(),
+                                        tool: None,
+                                        slot: Some(SlotRef {
+                                            section: InventorySection::Hotbar,
+                                            bar: cur_bar,
+                                            slot: cur_item,
+                                        }),
+                                    }
+                                });
                             }
                         }
-                    } else if mouse_left {
+                    } else if break_now {
                         if let Some(identifier) = chunk_manager
                             .get_identifier(voxel_to_global_voxel(voxel_pos, *chunk_pos))
                         {
                             let identifier = trim_geo_identifier(identifier);
                             if let Some(item_def) = item_table.get(&identifier) {
                                 if inventory.add_item(item_def).is_ok() {
-                                    chunk_manager.set_block(
+                                    break_block(
+                                        &mut chunk_manager,
+                                        &mut block_edits,
                                         voxel_to_global_voxel(voxel_pos, *chunk_pos),
-                                        BlockData::new("vinox".to_string(), "air".to_string()),
                                     );
-                                    client.connection_mut().try_send_message(
-                                        ClientMessage::SentBlock {
-                                            chunk_pos: *chunk_pos,
-                                            voxel_pos: [
-                                                voxel_pos.x as u8,
-                                                voxel_pos.y as u8,
-                                                voxel_pos.z as u8,
-                                            ],
-                                            block_type: BlockData::new(
-                                                "vinox".to_string(),
-                                                "air".to_string(),
-                                            ),
-                                        },
+                                    pending.push(
+                                        voxel_to_global_voxel(voxel_pos, *chunk_pos),
+                                        time.elapsed_seconds(),
                                     );
-                                }
-                            } else {
-                                chunk_manager.set_block(
-                                    voxel_to_global_voxel(voxel_pos, *chunk_pos),
-                                    BlockData::new("vinox".to_string(), "air".to_string()),
-                                );
-                                client.connection_mut().try_send_message(
-                                    ClientMessage::SentBlock {
+                                    client.send(ClientMessage::SentBlock {
                                         chunk_pos: *chunk_pos,
                                         voxel_pos: [
                                             voxel_pos.x as u8,
@@ -688,21 +1043,74 @@ pub fn interact(
                                             "vinox".to_string(),
                                             "air".to_string(),
                                         ),
-                                    },
+                                        item: held_identifier.clone(),
+                                        tool,
+                                        slot: None,
+                                    });
+                                }
+                            } else {
+                                break_block(
+                                    &mut chunk_manager,
+                                    &mut block_edits,
+                                    voxel_to_global_voxel(voxel_pos, *chunk_pos),
+                                );
+                                pending.push(
+                                    voxel_to_global_voxel(voxel_pos, *chunk_pos),
+                                    time.elapsed_seconds(),
                                 );
+                                client.send(ClientMessage::SentBlock {
+                                    chunk_pos: *chunk_pos,
+                                    voxel_pos: [
+                                        voxel_pos.x as u8,
+                                        voxel_pos.y as u8,
+                                        voxel_pos.z as u8,
+                                    ],
+                                    block_type: BlockData::new(
+                                        "vinox".to_string(),
+                                        "air".to_string(),
+                                    ),
+                                    item: held_identifier.clone(),
+                                    tool,
+                                    slot: None,
+                                });
                             }
                         }
                     }
                 }
-            } else if let Ok((_, mut block_visibility)) = cube_position.get_single_mut() {
-                if *block_visibility == Visibility::Visible {
-                    *block_visibility = Visibility::Hidden;
+            } else {
+                mining.reset();
+                if let Ok((_, mut block_visibility)) = cube_position.get_single_mut() {
+                    if *block_visibility == Visibility::Visible {
+                        *block_visibility = Visibility::Hidden;
+                    }
                 }
             }
         }
     }
 }
 
+// Physics moves the AABB and the transform follows it, so that's what gets moved. Far jumps
+// rebase first so the player lands near the render origin
+pub fn teleport_player(
+    mut events: EventReader<TeleportEvent>,
+    mut space: RenderSpace,
+    mut player: Query<(Entity, &mut Velocity), With<ControlledPlayer>>,
+) {
+    let Ok((entity, mut velocity)) = player.get_single_mut() else {
+        return;
+    };
+    let Some(event) = events.iter().last() else {
+        return;
+    };
+    let target = world_to_chunk_f64(event.translation);
+    if space.needs_rebase(target) {
+        space.rebase(target);
+    }
+    let translation = space.offset().to_render(event.translation);
+    space.place_body(entity, translation);
+    velocity.0 = Vec3::ZERO;
+}
+
 // Update main position based on the AABB
 pub fn update_visual_position(mut player: Query<(&Aabb, &mut Transform), With<ControlledPlayer>>) {
     if let Ok((aabb, mut transform)) = player.get_single_mut() {
@@ -710,59 +1118,55 @@ pub fn update_visual_position(mut player: Query<(&Aabb, &mut Transform), With<Co
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn cursor_grab_system(
     mut inventory: Query<(&mut Inventory, &ActionState<GameActions>), With<ControlledPlayer>>,
-    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut grab: ResMut<CursorGrab>,
     mut in_ui: ResMut<InUi>,
     mut is_open: ResMut<ConsoleOpen>,
     btn: Res<Input<MouseButton>>,
     key: Res<Input<KeyCode>>,
-    mut in_options: ResMut<InOptions>,
+    (mut in_options, rebinding): (ResMut<InOptions>, Res<Rebinding>),
+    mut palette: ResMut<PaletteState>,
+    mut encyclopedia: ResMut<EncyclopediaState>,
 ) {
-    let mut window = windows.single_mut();
     if let Ok((mut inventory, action_state)) = inventory.get_single_mut() {
         if action_state.just_pressed(GameActions::Inventory) {
-            if window.cursor.grab_mode == CursorGrabMode::None && inventory.open {
-                window.cursor.grab_mode = CursorGrabMode::Locked;
-                window.cursor.visible = false;
+            if !grab.is_grabbed() && inventory.open {
+                grab.grab();
                 inventory.open = !inventory.open;
                 **in_ui = !**in_ui;
             } else if !**in_ui {
-                let window_center: Option<Vec2> =
-                    Some(Vec2::new(window.width() / 2.0, window.height() / 2.0));
-                window.set_cursor_position(window_center);
-                window.cursor.grab_mode = CursorGrabMode::None;
-                window.cursor.visible = true;
+                grab.release();
                 inventory.open = !inventory.open;
                 **in_ui = !**in_ui;
             }
         }
 
         if btn.just_pressed(MouseButton::Left) && !in_ui.0 {
-            window.cursor.grab_mode = CursorGrabMode::Locked;
-            window.cursor.visible = false;
+            grab.grab();
             **is_open = false;
             inventory.open = false;
+            palette.open = false;
+            encyclopedia.open = false;
         }
 
-        if key.just_pressed(KeyCode::Escape) {
-            if window.cursor.grab_mode == CursorGrabMode::None {
-                window.cursor.grab_mode = CursorGrabMode::Locked;
-                window.cursor.visible = false;
+        // Escape cancels listening for a new binding, the options stay open
+        if key.just_pressed(KeyCode::Escape) && rebinding.is_none() {
+            if !grab.is_grabbed() {
+                grab.grab();
                 if **in_options {
                     **in_options = !**in_options;
                 }
             } else {
-                let window_center: Option<Vec2> =
-                    Some(Vec2::new(window.width() / 2.0, window.height() / 2.0));
-                window.set_cursor_position(window_center);
-                window.cursor.grab_mode = CursorGrabMode::None;
-                window.cursor.visible = true;
+                grab.release();
                 **in_options = !**in_options;
             }
             if **in_ui {
                 **is_open = false;
                 inventory.open = false;
+                palette.open = false;
+                encyclopedia.open = false;
             }
             **in_ui = !**in_ui;
         }
@@ -772,21 +1176,15 @@ pub fn cursor_grab_system(
 pub fn ui_input(
     mut is_open: ResMut<ConsoleOpen>,
     mut in_ui: ResMut<InUi>,
-    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut grab: ResMut<CursorGrab>,
     player_actions: Query<&ActionState<GameActions>, With<ControlledPlayer>>,
 ) {
-    let mut window = windows.single_mut();
     if let Ok(action_state) = player_actions.get_single() {
         if action_state.just_pressed(GameActions::Chat) && !**in_ui {
-            let window_center: Option<Vec2> =
-                Some(Vec2::new(window.width() / 2.0, window.height() / 2.0));
-            window.set_cursor_position(window_center);
-            if window.cursor.grab_mode == CursorGrabMode::None {
-                window.cursor.grab_mode = CursorGrabMode::Locked;
-                window.cursor.visible = false;
+            if grab.is_grabbed() {
+                grab.release();
             } else {
-                window.cursor.grab_mode = CursorGrabMode::None;
-                window.cursor.visible = true;
+                grab.grab();
             }
 
             **is_open = !**is_open;
@@ -794,3 +1192,185 @@ pub fn ui_input(
         }
     }
 }
+
+pub fn palette_input(
+    mut palette: ResMut<PaletteState>,
+    mut in_ui: ResMut<InUi>,
+    mut grab: ResMut<CursorGrab>,
+    player_actions: Query<&ActionState<GameActions>, With<ControlledPlayer>>,
+    capabilities: Res<Capabilities>,
+    mut contexts: EguiContexts,
+) {
+    if palette.open && !capabilities.creative {
+        palette.open = false;
+        **in_ui = false;
+    }
+    if let Ok(action_state) = player_actions.get_single() {
+        if !action_state.just_pressed(GameActions::Palette) || !capabilities.creative {
+            return;
+        }
+        // Typing a C into the search box shouldn't close it, escape still does
+        if palette.open && contexts.ctx_mut().wants_keyboard_input() {
+            return;
+        }
+        if palette.open {
+            grab.grab();
+            palette.open = false;
+            **in_ui = false;
+        } else if !**in_ui {
+            grab.release();
+            palette.open = true;
+            **in_ui = true;
+        }
+    }
+}
+
+pub fn encyclopedia_input(
+    mut encyclopedia: ResMut<EncyclopediaState>,
+    mut in_ui: ResMut<InUi>,
+    mut grab: ResMut<CursorGrab>,
+    player_actions: Query<&ActionState<GameActions>, With<ControlledPlayer>>,
+    mut contexts: EguiContexts,
+) {
+    let Ok(action_state) = player_actions.get_single() else {
+        return;
+    };
+    if !action_state.just_pressed(GameActions::Encyclopedia) {
+        return;
+    }
+    // Same as the palette, typing into the search box doesn't close it
+    if encyclopedia.open && contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    if encyclopedia.open {
+        grab.grab();
+        encyclopedia.open = false;
+        **in_ui = false;
+    } else if !**in_ui {
+        grab.release();
+        encyclopedia.open = true;
+        **in_ui = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::storage::blocks::descriptor::BlockDescriptor;
+
+    #[test]
+    fn previews_place_what_a_click_would() {
+        let mut block_table = BlockTable::default();
+        let stairs = BlockGeometry::Stairs.geo_new_block("cobblestone".to_string());
+        for name in ["cobblestone".to_string(), stairs.clone()] {
+            block_table.insert(
+                name_to_identifier("vinox".to_string(), name),
+                BlockDescriptor {
+                    has_direction: Some(true),
+                    ..Default::default()
+                },
+            );
+        }
+        let cobblestone = BlockData::new("vinox".to_string(), "cobblestone".to_string());
+        // Against the east face of a block below the eyes
+        let player = Vec3::new(3.5, 1.0, 0.5);
+        let point = Vec3::new(0.0, 0.0, 0.0);
+        let placed = placed_block(
+            cobblestone.clone(),
+            Some(&BlockGeometry::Stairs),
+            false,
+            IVec3::X,
+            (player, point),
+            &block_table,
+        );
+        assert_eq!(placed.name, stairs);
+        assert_eq!(placed.direction, Some(storage::Direction::East));
+        assert_eq!(placed.top, Some(true));
+        // A variant that doesn't exist for the block places the block itself
+        let plain = placed_block(
+            cobblestone.clone(),
+            Some(&BlockGeometry::Slab),
+            false,
+            IVec3::X,
+            (player, point),
+            &block_table,
+        );
+        assert_eq!(plain.name, "cobblestone");
+        let template = placed_block(
+            cobblestone.clone(),
+            Some(&BlockGeometry::Stairs),
+            true,
+            IVec3::X,
+            (player, point),
+            &block_table,
+        );
+        assert_eq!(template, cobblestone);
+
+        assert!(!clear_of_player(Vec3::new(3.2, 1.5, 0.5), player));
+        assert!(clear_of_player(Vec3::new(3.2, 2.0, 0.5), player));
+        assert!(clear_of_player(point, player));
+    }
+
+    #[test]
+    fn quick_use_takes_from_the_utility_slot() {
+        assert_eq!(quick_use_slot(Some(7), true), Some((2, 1)));
+        assert_eq!(quick_use_slot(Some(7), false), None);
+        assert_eq!(quick_use_slot(None, true), None);
+        assert_eq!(quick_use_slot(Some(9), true), None);
+
+        let mut inventory = Inventory::default();
+        let stone = ItemData {
+            namespace: "vinox".to_string(),
+            name: "stone".to_string(),
+            stack_size: 5,
+            ..Default::default()
+        };
+        inventory.hotbar[0][0] = Some(stone.clone());
+        inventory.hotbar[2][1] = Some(ItemData {
+            name: "torch".to_string(),
+            ..stone
+        });
+        let (bar, slot) = quick_use_slot(Some(7), true).unwrap_or((0, 0));
+        // What goes to the server with the placement
+        let acting = SlotRef {
+            section: InventorySection::Hotbar,
+            bar,
+            slot,
+        };
+        assert!(inventory.use_one(acting, "vinox:torch"));
+        assert_eq!(inventory.hotbar[2][1].as_ref().unwrap().stack_size, 4);
+        assert_eq!(inventory.hotbar[0][0].as_ref().unwrap().stack_size, 5);
+        assert_eq!((*inventory.current_bar, *inventory.current_item), (0, 0));
+    }
+
+    #[test]
+    fn sprint_kick_eases_on_top_of_the_slider() {
+        let mut kick = FovKick::default();
+        kick.ease(SPRINT_FOV_KICK, 1.0 / 60.0);
+        assert!(kick.0 > 0.0 && kick.0 < SPRINT_FOV_KICK / 2.0, "{}", kick.0);
+        for _ in 0..60 {
+            kick.ease(SPRINT_FOV_KICK, 1.0 / 60.0);
+        }
+        // Settles exactly so the projection stops changing
+        assert_eq!(kick.0, SPRINT_FOV_KICK);
+
+        let (Projection::Perspective(projection), frustum) = world_projection(80.0 + kick.0) else {
+            panic!("not a perspective projection");
+        };
+        assert!((projection.fov - 90f32.to_radians()).abs() < 1e-6);
+        let (_, narrower) = world_projection(80.0);
+        // A point just inside the wider view but outside the slider's
+        let edge = Vec3::new(0.0, 0.0, -10.0) + Vec3::Y * 10.0 * 42f32.to_radians().tan();
+        let sphere = bevy::render::primitives::Sphere {
+            center: edge.into(),
+            radius: 0.0,
+        };
+        assert!(frustum.intersects_sphere(&sphere, false));
+        assert!(!narrower.intersects_sphere(&sphere, false));
+
+        for _ in 0..60 {
+            kick.ease(0.0, 1.0 / 60.0);
+        }
+        assert_eq!(kick.0, 0.0);
+    }
+}
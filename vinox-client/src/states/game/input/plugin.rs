@@ -1,29 +1,170 @@
 use bevy::prelude::*;
+use leafwing_input_manager::plugin::InputManagerSystem;
 
-use crate::states::components::GameState;
+use crate::states::{
+    components::{GameSet, GameState},
+    game::session::SessionApp,
+};
 
+use super::arrange::{send_arrangements, ArrangeEvent, ArrangeIntents, ArrangeSyncEvent};
+use super::camera::{place_camera, show_local_model, toggle_camera, CameraMode, Crouch};
+use super::denied::{report_denials, tint_highlight, BlockDeniedEvent, DeniedFlash, PendingEdits};
+use super::drop::{
+    drop_items, pick_up_items, receive_pickups, reconcile_drops, DropResultEvent, HoveredSlot,
+    PickedUpEvent,
+};
+use super::flight::{apply_flight, JumpTaps, Noclip};
+use super::gate::{update_interaction_gate, InteractionGate};
+use super::item_use::ItemUseState;
+use super::look::{apply_cursor_grab, read_look_delta, CursorGrab, LookDelta, LookSmoothing};
+use super::mining::BreakProgress;
 use super::player::{
-    cursor_grab_system, handle_movement, interact, spawn_camera, ui_input, update_fov,
-    update_input, update_visual_position, update_vsync, MouseSensitivity,
+    cursor_grab_system, encyclopedia_input, handle_movement, interact, palette_input, spawn_camera,
+    teleport_player, ui_input, update_fov, update_input, update_visual_position, update_vsync,
+    BuildLockState, CameraSpawned, FovKick, PlacementPreview, TargetedBlock, TeleportEvent,
 };
+use super::seat::{apply_seated, request_dismount, SeatedEvent};
+use super::template::{pick_block, receive_templates, HeldBlockTemplate, TemplateEvent};
+use super::tools::{
+    apply_tool_wear, rename_held, report_selection, RenameHeldEvent, ToolWornEvent,
+};
+use super::variant::{variant_menu, PlacementVariant, VariantMenu};
 
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(MouseSensitivity(1.0)).add_systems(
-            (
-                spawn_camera,
-                handle_movement,
-                interact,
-                update_visual_position,
-                cursor_grab_system.after(interact),
-                update_fov,
-                update_input,
-                update_vsync,
-                ui_input,
+        app.insert_resource(CameraSpawned::default())
+            .insert_resource(ItemUseState::default())
+            .insert_resource(HoveredSlot::default())
+            .insert_resource(PlacementVariant::default())
+            .insert_resource(VariantMenu::default())
+            .insert_resource(TargetedBlock::default())
+            .insert_resource(PlacementPreview::default())
+            .insert_resource(BuildLockState::default())
+            .insert_resource(InteractionGate::default())
+            .insert_resource(PendingEdits::default())
+            .insert_resource(DeniedFlash::default())
+            .insert_resource(ArrangeIntents::default())
+            .insert_resource(CameraMode::default())
+            .init_resource::<HeldBlockTemplate>()
+            .init_resource::<CursorGrab>()
+            .init_resource::<BreakProgress>()
+            .init_resource::<FovKick>()
+            .init_resource::<LookSmoothing>()
+            .init_resource::<JumpTaps>()
+            .init_resource::<Crouch>()
+            .init_resource::<Noclip>()
+            .add_event::<DropResultEvent>()
+            .add_event::<PickedUpEvent>()
+            .add_event::<TeleportEvent>()
+            .add_event::<ToolWornEvent>()
+            .add_event::<RenameHeldEvent>()
+            .add_event::<BlockDeniedEvent>()
+            .add_event::<ArrangeEvent>()
+            .add_event::<ArrangeSyncEvent>()
+            .add_event::<SeatedEvent>()
+            .add_event::<LookDelta>()
+            .add_event::<TemplateEvent>()
+            .reset_on_exit::<CameraSpawned>()
+            .reset_on_exit::<ItemUseState>()
+            .reset_on_exit::<HoveredSlot>()
+            .reset_on_exit::<PlacementVariant>()
+            .reset_on_exit::<VariantMenu>()
+            .reset_on_exit::<TargetedBlock>()
+            .reset_on_exit::<PlacementPreview>()
+            .reset_on_exit::<BuildLockState>()
+            .reset_on_exit::<InteractionGate>()
+            .reset_on_exit::<PendingEdits>()
+            .reset_on_exit::<DeniedFlash>()
+            .reset_on_exit::<ArrangeIntents>()
+            .reset_on_exit::<CameraMode>()
+            .reset_on_exit::<HeldBlockTemplate>()
+            .reset_on_exit::<CursorGrab>()
+            .reset_on_exit::<BreakProgress>()
+            .reset_on_exit::<FovKick>()
+            .reset_on_exit::<LookSmoothing>()
+            .reset_on_exit::<JumpTaps>()
+            .reset_on_exit::<Crouch>()
+            .reset_on_exit::<Noclip>()
+            // spawn_camera darkens the background for the fog
+            .reset_on_exit::<ClearColor>()
+            .add_systems(
+                (
+                    spawn_camera,
+                    handle_movement.after(teleport_player).after(variant_menu),
+                    teleport_player,
+                    interact.after(variant_menu).after(update_interaction_gate),
+                    update_interaction_gate,
+                    update_visual_position,
+                    cursor_grab_system.after(interact),
+                    update_fov,
+                    update_input,
+                    update_vsync,
+                    ui_input,
+                    palette_input.after(cursor_grab_system),
+                    drop_items.after(interact).after(update_interaction_gate),
+                    reconcile_drops,
+                    pick_up_items,
+                    receive_pickups,
+                )
+                    .in_set(GameSet::Input)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_systems(
+                (
+                    apply_tool_wear,
+                    rename_held,
+                    report_denials.after(interact),
+                    tint_highlight.after(interact),
+                    send_arrangements,
+                    apply_seated.before(teleport_player),
+                    request_dismount.after(handle_movement),
+                    // Sees the block interact just targeted, and the slot it just selected
+                    pick_block.after(interact),
+                    receive_templates.before(pick_block),
+                    toggle_camera,
+                    place_camera.after(handle_movement).after(toggle_camera),
+                    show_local_model.after(toggle_camera),
+                    apply_flight.after(handle_movement),
+                )
+                    .in_set(GameSet::Input)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                variant_menu
+                    .in_set(GameSet::Input)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                report_selection
+                    .after(interact)
+                    .in_set(GameSet::Input)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                encyclopedia_input
+                    .after(cursor_grab_system)
+                    .in_set(GameSet::Input)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            // Everything that wants the cursor grabbed or let go has had its say by now
+            .add_system(
+                apply_cursor_grab
+                    .after(spawn_camera)
+                    .after(cursor_grab_system)
+                    .after(ui_input)
+                    .after(palette_input)
+                    .after(encyclopedia_input)
+                    .in_set(GameSet::Input)
+                    .in_set(OnUpdate(GameState::Game)),
             )
-                .in_set(OnUpdate(GameState::Game)),
-        );
+            // Before the replay records or replaces it
+            .add_system(
+                read_look_delta
+                    .in_base_set(CoreSet::PreUpdate)
+                    .before(InputManagerSystem::ManualControl)
+                    .run_if(in_state(GameState::Game)),
+            );
     }
 }
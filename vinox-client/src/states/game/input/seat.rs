@@ -0,0 +1,53 @@
+use bevy::{math::DVec3, prelude::*};
+use leafwing_input_manager::prelude::*;
+use vinox_common::{networking::protocol::ClientMessage, physics::movement::MovementState};
+
+use crate::states::{
+    components::GameActions,
+    game::{networking::connection::NetClient, world::chunks::ControlledPlayer},
+};
+
+use super::{look::CursorGrab, player::TeleportEvent};
+
+// The server sat us down at a seat in world space, or let us back up with None
+pub struct SeatedEvent {
+    pub seat: Option<DVec3>,
+}
+
+pub fn apply_seated(
+    mut events: EventReader<SeatedEvent>,
+    mut player: Query<&mut MovementState, With<ControlledPlayer>>,
+    mut teleports: EventWriter<TeleportEvent>,
+) {
+    let Ok(mut state) = player.get_single_mut() else {
+        return;
+    };
+    for evt in events.iter() {
+        match evt.seat {
+            Some(translation) => {
+                *state = MovementState::Seated;
+                teleports.send(TeleportEvent { translation });
+            }
+            // The teleport off the seat came just before this
+            None => *state = MovementState::Airborne,
+        }
+    }
+}
+
+// Getting up is the server's call too, it knows where there's room to stand
+pub fn request_dismount(
+    player: Query<(&MovementState, &ActionState<GameActions>), With<ControlledPlayer>>,
+    grab: Res<CursorGrab>,
+    mut client: NetClient,
+) {
+    let Ok((state, action_state)) = player.get_single() else {
+        return;
+    };
+    if *state == MovementState::Seated
+        && grab.is_grabbed()
+        && (action_state.just_pressed(GameActions::Jump)
+            || action_state.just_pressed(GameActions::Sneak))
+    {
+        client.send(ClientMessage::Dismount);
+    }
+}
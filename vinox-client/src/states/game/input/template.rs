@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use vinox_common::{
+    ecs::bundles::Inventory,
+    networking::protocol::ClientMessage,
+    world::chunks::{
+        positions::global_voxel_positions,
+        storage::{name_to_identifier, trim_geo_identifier, BlockData, ItemTable},
+    },
+};
+
+use crate::states::{
+    components::GameActions,
+    game::{
+        networking::{components::Capabilities, connection::NetClient},
+        world::chunks::ControlledPlayer,
+    },
+};
+
+use super::{look::CursorGrab, player::TargetedBlock};
+
+// The server's answer to a ctrl+pick
+pub struct TemplateEvent {
+    pub block: BlockData,
+}
+
+// An exact copy of a block, placed as it is instead of whatever the held item would make
+#[derive(Resource, Default, Debug)]
+pub struct HeldBlockTemplate {
+    pub block: Option<BlockData>,
+    // Selected when it was asked for, moving off it lets the template go
+    slot: (usize, usize),
+}
+
+impl HeldBlockTemplate {
+    pub fn held_in(&self, slot: (usize, usize)) -> bool {
+        self.block.is_some() && self.slot == slot
+    }
+
+    // Lets go once the selected slot isn't the one it was picked into
+    fn follow(&mut self, slot: (usize, usize)) {
+        if self.slot != slot {
+            self.block = None;
+        }
+    }
+}
+
+// Picking gives the block's plain item, with ctrl it asks the server for an exact copy. Both are
+// creative only, the server checks again
+#[allow(clippy::too_many_arguments)]
+pub fn pick_block(
+    player: Query<(&ActionState<GameActions>, &Inventory), With<ControlledPlayer>>,
+    grab: Res<CursorGrab>,
+    keys: Res<Input<KeyCode>>,
+    targeted: Res<TargetedBlock>,
+    capabilities: Res<Capabilities>,
+    item_table: Res<ItemTable>,
+    mut template: ResMut<HeldBlockTemplate>,
+    mut client: NetClient,
+) {
+    let Ok((action_state, inventory)) = player.get_single() else {
+        return;
+    };
+    let slot = (*inventory.current_bar, *inventory.current_item);
+    template.follow(slot);
+    if !grab.is_grabbed() || !action_state.just_pressed(GameActions::PickBlock) {
+        return;
+    }
+    // Picking again puts the copy away
+    if template.block.take().is_some() || !capabilities.creative {
+        return;
+    }
+    let Some(target) = &targeted.0 else {
+        return;
+    };
+    if keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        template.slot = slot;
+        let (chunk_pos, voxel_pos) = global_voxel_positions(target.voxel);
+        client.send(ClientMessage::PickBlockFull {
+            chunk_pos,
+            voxel_pos: voxel_pos.to_array().map(|axis| axis as u8),
+        });
+    } else {
+        // Stairs, slabs and the rest come from the item of the block they're made of
+        let identifier = trim_geo_identifier(name_to_identifier(
+            target.block.namespace.clone(),
+            target.block.name.clone(),
+        ));
+        if item_table.contains_key(&identifier) {
+            client.send(ClientMessage::PickItem { identifier });
+        }
+    }
+}
+
+pub fn receive_templates(
+    mut events: EventReader<TemplateEvent>,
+    mut template: ResMut<HeldBlockTemplate>,
+) {
+    for evt in events.iter() {
+        template.block = Some(evt.block.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switching_slots_lets_go() {
+        let mut template = HeldBlockTemplate {
+            block: Some(BlockData::default()),
+            slot: (0, 3),
+        };
+        template.follow((0, 3));
+        assert!(template.held_in((0, 3)));
+        assert!(!template.held_in((1, 3)));
+        template.follow((1, 3));
+        assert!(template.block.is_none());
+        // Coming back doesn't bring it back
+        template.follow((0, 3));
+        assert!(!template.held_in((0, 3)));
+    }
+}
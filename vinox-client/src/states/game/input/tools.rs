@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use vinox_common::{
+    ecs::bundles::{Inventory, SlotRef},
+    networking::protocol::ClientMessage,
+    storage::items::descriptor::{wear_slot, ItemData},
+};
+
+use crate::states::game::{
+    networking::{
+        components::{ChatLine, ChatMessages},
+        connection::NetClient,
+    },
+    ui::inventory::item_label,
+    world::chunks::ControlledPlayer,
+};
+
+// The server's take on the tool a break was made with, worn is None when it broke
+pub struct ToolWornEvent {
+    pub slot: SlotRef,
+    pub tool: ItemData,
+    pub worn: Option<ItemData>,
+}
+
+pub struct RenameHeldEvent {
+    pub name: Option<String>,
+}
+
+pub fn apply_tool_wear(
+    mut events: EventReader<ToolWornEvent>,
+    mut player: Query<&mut Inventory, With<ControlledPlayer>>,
+    mut messages: ResMut<ChatMessages>,
+) {
+    let Ok(mut inventory) = player.get_single_mut() else {
+        events.clear();
+        return;
+    };
+    for event in events.iter() {
+        let Some(slot) = inventory.slot_mut(event.slot) else {
+            continue;
+        };
+        // Swapped out since the break, whatever is there now wasn't used
+        let used = slot.as_ref().is_some_and(|held| {
+            held.namespace == event.tool.namespace && held.name == event.tool.name
+        });
+        if !used {
+            continue;
+        }
+        if let Some(broke) = wear_slot(slot, event.worn.as_ref()) {
+            let broke = item_label(&broke);
+            messages.push(ChatLine::toast(format!("Your {broke} broke")));
+        }
+    }
+}
+
+pub fn rename_held(
+    mut events: EventReader<RenameHeldEvent>,
+    mut player: Query<&mut Inventory, With<ControlledPlayer>>,
+    mut messages: ResMut<ChatMessages>,
+) {
+    let Ok(mut inventory) = player.get_single_mut() else {
+        events.clear();
+        return;
+    };
+    for event in events.iter() {
+        let (bar, slot) = (*inventory.current_bar, *inventory.current_item);
+        match inventory.hotbar[bar][slot].as_mut() {
+            Some(held) => held.rename(event.name.clone()),
+            None => messages.push(ChatLine::console("There's nothing in your hand to rename")),
+        }
+    }
+}
+
+// The server renames and wears down whatever it thinks is held, so it hears about every switch
+pub fn report_selection(
+    mut client: NetClient,
+    mut reported: Local<Option<(Entity, SlotRef)>>,
+    player: Query<(Entity, &Inventory), (With<ControlledPlayer>, Changed<Inventory>)>,
+) {
+    let Ok((entity, inventory)) = player.get_single() else {
+        return;
+    };
+    let held = inventory.held_slot();
+    if *reported == Some((entity, held)) {
+        return;
+    }
+    *reported = Some((entity, held));
+    client.send(ClientMessage::SelectSlot {
+        bar: held.bar as u8,
+        slot: held.slot as u8,
+    });
+}
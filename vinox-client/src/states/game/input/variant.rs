@@ -0,0 +1,181 @@
+use leafwing_input_manager::prelude::*;
+
+use bevy::prelude::*;
+use vinox_common::{
+    ecs::bundles::Inventory,
+    storage::{blocks::descriptor::BlockGeometry, items::descriptor::ItemData},
+    world::chunks::storage::{name_to_identifier, BlockTable, ItemTable},
+};
+
+use crate::states::{
+    components::GameActions,
+    game::{
+        ui::{
+            palette::display_name,
+            plugin::InUi,
+            radial::{segment_at, RADIAL_DEADZONE, RADIAL_RADIUS},
+        },
+        world::chunks::ControlledPlayer,
+    },
+};
+
+use super::look::LookDelta;
+
+// The stick snaps back on its own so only a real push counts
+pub const STICK_DEADZONE: f32 = 0.5;
+
+// Geometry blocks get placed with, None is the block the item places as is
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct PlacementVariant(pub Option<BlockGeometry>);
+
+#[derive(Resource, Default)]
+pub struct VariantMenu {
+    pub open: bool,
+    // The item it was opened for, it stands in for the base block
+    pub held: Option<ItemData>,
+    pub variants: Vec<Option<BlockGeometry>>,
+    // Mouse movement since it opened, y up, kept inside the menu
+    pub pointer: Vec2,
+    pub hovered: Option<usize>,
+}
+
+pub fn variant_label(variant: &Option<BlockGeometry>, block_name: &str) -> String {
+    match variant {
+        Some(geometry) => display_name(&geometry.get_geo_name()),
+        None => display_name(block_name),
+    }
+}
+
+// The base block first, then every variant of it the table actually has
+pub fn available_variants(
+    namespace: &str,
+    name: &str,
+    block_table: &BlockTable,
+) -> Vec<Option<BlockGeometry>> {
+    let mut variants: Vec<(String, BlockGeometry)> = block_table
+        .values()
+        .filter(|descriptor| descriptor.namespace == namespace)
+        .filter_map(|descriptor| {
+            let geometry = descriptor.geometry.clone()?;
+            // Only variants whose name is what placement builds from the geometry
+            (geometry.geo_new_block(name.to_string()) == descriptor.name)
+                .then(|| (descriptor.name.clone(), geometry))
+        })
+        .collect();
+    variants.sort_by(|(name, _), (other_name, _)| name.cmp(other_name));
+    std::iter::once(None)
+        .chain(variants.into_iter().map(|(_, geometry)| Some(geometry)))
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn variant_menu(
+    mut menu: ResMut<VariantMenu>,
+    mut variant: ResMut<PlacementVariant>,
+    player: Query<(&ActionState<GameActions>, &Inventory), With<ControlledPlayer>>,
+    (item_table, block_table): (Res<ItemTable>, Res<BlockTable>),
+    mut look_events: EventReader<LookDelta>,
+    (gamepads, axes): (Res<Gamepads>, Res<Axis<GamepadAxis>>),
+    in_ui: Res<InUi>,
+) {
+    let Ok((action_state, inventory)) = player.get_single() else {
+        return;
+    };
+    if action_state.just_pressed(GameActions::SelectVariant) && !**in_ui {
+        let held = inventory.hotbar[*inventory.current_bar][*inventory.current_item]
+            .as_ref()
+            .filter(|item| {
+                item_table
+                    .get(&name_to_identifier(
+                        item.namespace.clone(),
+                        item.name.clone(),
+                    ))
+                    .is_some_and(|descriptor| descriptor.associated_block.is_some())
+            });
+        menu.variants = held
+            .map(|item| available_variants(&item.namespace, &item.name, &block_table))
+            .unwrap_or_default();
+        menu.held = held.cloned();
+        // Nothing to choose between with only the base geometry
+        menu.open = menu.variants.len() > 1;
+        menu.pointer = Vec2::ZERO;
+        menu.hovered = None;
+    }
+    if !menu.open {
+        look_events.clear();
+        return;
+    }
+
+    for LookDelta(delta) in look_events.iter() {
+        menu.pointer += Vec2::new(delta.x, -delta.y);
+    }
+    for gamepad in gamepads.iter() {
+        let stick = Vec2::new(
+            axes.get(GamepadAxis::new(gamepad, GamepadAxisType::RightStickX))
+                .unwrap_or(0.0),
+            axes.get(GamepadAxis::new(gamepad, GamepadAxisType::RightStickY))
+                .unwrap_or(0.0),
+        );
+        if stick.length() > STICK_DEADZONE {
+            menu.pointer = stick.normalize() * RADIAL_RADIUS;
+        }
+    }
+    menu.pointer = menu.pointer.clamp_length_max(RADIAL_RADIUS);
+    menu.hovered = segment_at(
+        menu.pointer,
+        menu.variants.len(),
+        RADIAL_RADIUS * RADIAL_DEADZONE,
+    );
+
+    if !action_state.pressed(GameActions::SelectVariant) {
+        // Letting go in the middle keeps whatever was there before
+        if let Some(hovered) = menu.hovered {
+            **variant = menu.variants[hovered].clone();
+        }
+        menu.open = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::storage::blocks::descriptor::BlockDescriptor;
+
+    #[test]
+    fn only_offers_variants_that_resolve() {
+        let mut block_table = BlockTable::default();
+        for (namespace, name, geometry) in [
+            ("vinox", "stone", None),
+            ("vinox", "stone.stair", Some(BlockGeometry::Stairs)),
+            ("vinox", "stone.slab", Some(BlockGeometry::Slab)),
+            // Wrong suffix for its geometry, placement would never find it
+            ("vinox", "stone.fence", Some(BlockGeometry::Slab)),
+            ("modded", "stone.flat", Some(BlockGeometry::Flat)),
+            ("vinox", "cobblestone.slab", Some(BlockGeometry::Slab)),
+            ("vinox", "dirt", Some(BlockGeometry::Block)),
+        ] {
+            block_table.insert(
+                format!("{namespace}:{name}"),
+                BlockDescriptor {
+                    namespace: namespace.to_string(),
+                    name: name.to_string(),
+                    geometry,
+                    ..Default::default()
+                },
+            );
+        }
+        assert_eq!(
+            available_variants("vinox", "stone", &block_table),
+            vec![None, Some(BlockGeometry::Slab), Some(BlockGeometry::Stairs)]
+        );
+        assert_eq!(
+            available_variants("vinox", "dirt", &block_table),
+            vec![None]
+        );
+        assert_eq!(variant_label(&None, "mossy_stone"), "Mossy Stone");
+        assert_eq!(
+            variant_label(&Some(BlockGeometry::BorderedBlock), "stone"),
+            "Border Block"
+        );
+    }
+}
@@ -2,5 +2,6 @@ pub mod input;
 pub mod networking;
 pub mod plugin;
 pub mod rendering;
+pub mod session;
 pub mod ui;
 pub mod world;
@@ -0,0 +1,602 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{IoTaskPool, Task},
+};
+use futures_lite::future;
+use tokio::sync::mpsc::{Receiver, Sender};
+use vinox_common::{
+    ecs::rng::hash_bytes,
+    networking::protocol::{CachedChunk, ClientMessage, ServerMessage, CACHED_CHUNKS_PER_MESSAGE},
+    world::chunks::{positions::DimensionId, storage::BlockTable},
+};
+
+use crate::states::{
+    components::ProjectPath,
+    game::{
+        rendering::memory::MIB,
+        world::chunks::{ChunkBiomeMap, CreateChunkEvent},
+    },
+};
+
+use super::{
+    components::ContentReport, connection::NetClient, replay::ReplayRecorder, syncing::level_chunk,
+};
+
+pub const DEFAULT_CACHE_MIB: u32 = 256;
+
+pub type CacheKey = (DimensionId, IVec3);
+
+// Every server gets a folder by address, holding one folder for the world behind it
+pub fn chunk_caches_dir(project_path: &ProjectPath) -> PathBuf {
+    project_path
+        .0
+        .parent()
+        .unwrap_or(&project_path.0)
+        .join("chunk_cache")
+}
+
+// Addresses and world ids come from outside, neither gets to climb out of the cache
+fn folder_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// Everything the index needs is in the name, so opening the cache is just a directory listing
+pub fn file_name((dimension, pos): CacheKey, hash: u64) -> String {
+    format!(
+        "{}.{}.{}.{}.{hash:016x}.chunk",
+        *dimension, pos.x, pos.y, pos.z
+    )
+}
+
+pub fn parse_file_name(name: &str) -> Option<(CacheKey, u64)> {
+    let mut parts = name.strip_suffix(".chunk")?.split('.');
+    let dimension = DimensionId(parts.next()?.parse().ok()?);
+    let mut coords = [0; 3];
+    for coord in coords.iter_mut() {
+        *coord = parts.next()?.parse().ok()?;
+    }
+    let hash = u64::from_str_radix(parts.next()?, 16).ok()?;
+    parts
+        .next()
+        .is_none()
+        .then_some(((dimension, IVec3::from_array(coords)), hash))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IndexEntry {
+    hash: u64,
+    size: u64,
+    used: u64,
+}
+
+// What's on disk, least recently used goes first once the total is over budget
+#[derive(Debug, Default)]
+pub struct CacheIndex {
+    entries: HashMap<CacheKey, IndexEntry>,
+    // By when each entry was last used, oldest first
+    order: BTreeMap<u64, CacheKey>,
+    clock: u64,
+    total: u64,
+    budget: u64,
+}
+
+impl CacheIndex {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            ..default()
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hash(&self, key: CacheKey) -> Option<u64> {
+        self.entries.get(&key).map(|entry| entry.hash)
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    // Returns the entries that have to go to make room, as files to delete. An entry bigger
+    // than the whole budget isn't kept at all
+    pub fn insert(&mut self, key: CacheKey, hash: u64, size: u64) -> Vec<(CacheKey, u64)> {
+        let mut dropped = Vec::new();
+        // The same hash is the same file, it gets written over rather than deleted
+        if let Some(old) = self.remove(key) {
+            if old != hash {
+                dropped.push((key, old));
+            }
+        }
+        if size > self.budget {
+            return dropped;
+        }
+        while self.total + size > self.budget {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.total -= entry.size;
+                dropped.push((oldest, entry.hash));
+            }
+        }
+        let used = self.tick();
+        self.entries.insert(key, IndexEntry { hash, size, used });
+        self.order.insert(used, key);
+        self.total += size;
+        dropped
+    }
+
+    pub fn touch(&mut self, key: CacheKey) {
+        let used = self.tick();
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.order.remove(&entry.used);
+            entry.used = used;
+            self.order.insert(used, key);
+        }
+    }
+
+    pub fn remove(&mut self, key: CacheKey) -> Option<u64> {
+        let entry = self.entries.remove(&key)?;
+        self.order.remove(&entry.used);
+        self.total -= entry.size;
+        Some(entry.hash)
+    }
+
+    // Most recently used first, the server only takes so many
+    pub fn claims(&self) -> Vec<CachedChunk> {
+        self.order
+            .values()
+            .rev()
+            .filter_map(|key| {
+                let (dimension, pos) = *key;
+                Some(CachedChunk {
+                    dimension,
+                    pos,
+                    hash: self.hash(*key)?,
+                })
+            })
+            .collect()
+    }
+}
+
+// Always ends with a done batch, even with nothing cached the server is waiting on it
+pub fn claim_messages(claims: &[CachedChunk]) -> Vec<ClientMessage> {
+    let batches = claims.len().div_ceil(CACHED_CHUNKS_PER_MESSAGE).max(1);
+    (0..batches)
+        .map(|batch| {
+            let start = (batch * CACHED_CHUNKS_PER_MESSAGE).min(claims.len());
+            let end = (start + CACHED_CHUNKS_PER_MESSAGE).min(claims.len());
+            ClientMessage::CachedChunks {
+                chunks: claims[start..end].to_vec(),
+                done: batch + 1 == batches,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheFile {
+    pub key: CacheKey,
+    pub hash: u64,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+// Only the world the server is running now can have anything valid, older ones behind the same
+// address go. Whatever can't be read as an entry goes too
+pub fn scan(server_dir: &Path, world: &str) -> Vec<CacheFile> {
+    for entry in fs::read_dir(server_dir).into_iter().flatten().flatten() {
+        if entry.file_name() != world {
+            let path = entry.path();
+            if path.is_dir() {
+                fs::remove_dir_all(path).ok();
+            } else {
+                fs::remove_file(path).ok();
+            }
+        }
+    }
+    let dir = server_dir.join(world);
+    fs::create_dir_all(&dir).ok();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+        let parsed = entry.file_name().to_str().and_then(parse_file_name);
+        let (Some((key, hash)), Ok(metadata)) = (parsed, entry.metadata()) else {
+            fs::remove_file(entry.path()).ok();
+            continue;
+        };
+        files.push(CacheFile {
+            key,
+            hash,
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+        });
+    }
+    files.sort_by_key(|file| file.modified);
+    files
+}
+
+// None for anything that isn't exactly what was cached, a torn write or a flipped bit is a miss
+pub fn read_entry(path: &Path, hash: u64) -> Option<Vec<u8>> {
+    fs::read(path)
+        .ok()
+        .filter(|payload| hash_bytes(payload) == hash)
+}
+
+fn delete_files(files: Vec<PathBuf>) {
+    if files.is_empty() {
+        return;
+    }
+    IoTaskPool::get()
+        .spawn(async move {
+            for file in files {
+                fs::remove_file(file).ok();
+            }
+        })
+        .detach();
+}
+
+pub struct CacheRead {
+    pub key: CacheKey,
+    pub hash: u64,
+    pub payload: Option<Vec<u8>>,
+}
+
+enum CacheState {
+    Closed,
+    // The server sent no world id, nothing can be trusted but it still waits for a list
+    Unkeyed,
+    // Listing what's on disk
+    Opening {
+        dir: PathBuf,
+        budget: u64,
+        task: Task<Vec<CacheFile>>,
+    },
+    Open {
+        dir: PathBuf,
+        index: CacheIndex,
+    },
+}
+
+// Chunks from earlier visits to the same server and world, opt in with GameOptions::chunk_cache.
+// Every read and write happens on the IO pool
+#[derive(Resource)]
+pub struct ChunkCache {
+    state: CacheState,
+    tx: Sender<CacheRead>,
+    rx: Receiver<CacheRead>,
+}
+
+impl Default for ChunkCache {
+    fn default() -> Self {
+        let (tx, rx) = tokio::sync::mpsc::channel(256);
+        Self {
+            state: CacheState::Closed,
+            tx,
+            rx,
+        }
+    }
+}
+
+impl ChunkCache {
+    // Once the server's world is known, claims go out when the listing is done
+    pub fn open(&mut self, project_path: &ProjectPath, address: &str, world_id: &str, mib: u32) {
+        if world_id.is_empty() {
+            self.state = CacheState::Unkeyed;
+            return;
+        }
+        let server_dir = chunk_caches_dir(project_path).join(folder_name(address));
+        let world = folder_name(world_id);
+        let dir = server_dir.join(&world);
+        let task = IoTaskPool::get().spawn(async move { scan(&server_dir, &world) });
+        self.state = CacheState::Opening {
+            dir,
+            budget: mib as u64 * MIB,
+            task,
+        };
+    }
+
+    // What to tell the server, once
+    fn poll_open(&mut self) -> Option<Vec<CachedChunk>> {
+        match &mut self.state {
+            CacheState::Unkeyed => {
+                self.state = CacheState::Closed;
+                Some(Vec::new())
+            }
+            CacheState::Opening { dir, budget, task } => {
+                let files = future::block_on(future::poll_once(task))?;
+                let dir = dir.clone();
+                let mut index = CacheIndex::new(*budget);
+                let mut dropped = Vec::new();
+                for file in files {
+                    dropped.extend(index.insert(file.key, file.hash, file.size));
+                    // Too big for the budget now it was turned down
+                    if index.hash(file.key) != Some(file.hash) {
+                        dropped.push((file.key, file.hash));
+                    }
+                }
+                delete_files(
+                    dropped
+                        .into_iter()
+                        .map(|(key, hash)| dir.join(file_name(key, hash)))
+                        .collect(),
+                );
+                let claims = index.claims();
+                self.state = CacheState::Open { dir, index };
+                Some(claims)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, dimension: DimensionId, pos: IVec3, hash: u64, payload: &[u8]) {
+        let CacheState::Open { dir, index } = &mut self.state else {
+            return;
+        };
+        let key = (dimension, pos);
+        let dropped: Vec<PathBuf> = index
+            .insert(key, hash, payload.len() as u64)
+            .into_iter()
+            .map(|(key, hash)| dir.join(file_name(key, hash)))
+            .collect();
+        let write = (index.hash(key) == Some(hash))
+            .then(|| (dir.join(file_name(key, hash)), payload.to_vec()));
+        IoTaskPool::get()
+            .spawn(async move {
+                for file in dropped {
+                    fs::remove_file(file).ok();
+                }
+                if let Some((path, payload)) = write {
+                    fs::write(path, payload).ok();
+                }
+            })
+            .detach();
+    }
+
+    // False when it isn't cached after all, the server has to send it in full
+    pub fn load(&mut self, dimension: DimensionId, pos: IVec3) -> bool {
+        let CacheState::Open { dir, index } = &mut self.state else {
+            return false;
+        };
+        let key = (dimension, pos);
+        let Some(hash) = index.hash(key) else {
+            return false;
+        };
+        index.touch(key);
+        let path = dir.join(file_name(key, hash));
+        let tx = self.tx.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                let payload = read_entry(&path, hash);
+                tx.send(CacheRead { key, hash, payload }).await.ok();
+            })
+            .detach();
+        true
+    }
+
+    fn forget(&mut self, key: CacheKey, hash: u64) {
+        let CacheState::Open { dir, index } = &mut self.state else {
+            return;
+        };
+        if index.hash(key) == Some(hash) {
+            index.remove(key);
+        }
+        delete_files(vec![dir.join(file_name(key, hash))]);
+    }
+}
+
+pub fn clear_chunk_cache(project_path: &ProjectPath) {
+    let dir = chunk_caches_dir(project_path);
+    IoTaskPool::get()
+        .spawn(async move {
+            fs::remove_dir_all(dir).ok();
+        })
+        .detach();
+}
+
+pub fn send_cache_claims(mut cache: ResMut<ChunkCache>, mut client: NetClient) {
+    let Some(claims) = cache.poll_open() else {
+        return;
+    };
+    for message in claim_messages(&claims) {
+        client.send(message);
+    }
+}
+
+pub fn receive_cached_chunks(
+    mut cache: ResMut<ChunkCache>,
+    mut client: NetClient,
+    mut chunk_event: EventWriter<CreateChunkEvent>,
+    (content, block_table, biomes): (Res<ContentReport>, Res<BlockTable>, Res<ChunkBiomeMap>),
+    recorder: Option<Res<ReplayRecorder>>,
+) {
+    while let Ok(read) = cache.rx.try_recv() {
+        let (dimension, pos) = read.key;
+        let raw_chunk = read
+            .payload
+            .as_deref()
+            .and_then(|payload| level_chunk(payload, &content, &block_table));
+        let (Some(raw_chunk), Some(payload)) = (raw_chunk, read.payload) else {
+            cache.forget(read.key, read.hash);
+            client.send(ClientMessage::ChunkCacheMiss { pos, dimension });
+            continue;
+        };
+        // Playback has no cache, the recording gets it as though it came from the server
+        if let Some(recorder) = &recorder {
+            recorder.message(&ServerMessage::LevelData {
+                chunk_data: payload,
+                pos,
+                dimension,
+                hash: read.hash,
+                biomes: biomes.get(&pos).cloned().unwrap_or_default(),
+            });
+        }
+        chunk_event.send(CreateChunkEvent {
+            raw_chunk,
+            pos,
+            dimension,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIB: u64 = 1024;
+
+    fn key(x: i32) -> CacheKey {
+        (DimensionId(0), IVec3::new(x, -2, 3))
+    }
+
+    #[test]
+    fn eviction_keeps_the_total_under_budget() {
+        let mut index = CacheIndex::new(10 * KIB);
+        for x in 0..4 {
+            assert!(index.insert(key(x), x as u64, 2 * KIB).is_empty());
+        }
+        assert_eq!(index.total(), 8 * KIB);
+
+        // Using the oldest makes the next one the oldest
+        index.touch(key(0));
+        assert_eq!(index.insert(key(4), 4, 4 * KIB), vec![(key(1), 1)]);
+        assert_eq!(index.total(), 10 * KIB);
+        assert_eq!(index.len(), 4);
+
+        // A new hash for the same chunk replaces it and the old file goes
+        assert_eq!(index.insert(key(2), 20, KIB), vec![(key(2), 2)]);
+        assert_eq!(index.total(), 9 * KIB);
+        assert_eq!(index.hash(key(2)), Some(20));
+        // The same hash is the same file, nothing to delete
+        assert!(index.insert(key(2), 20, KIB).is_empty());
+        assert_eq!(index.total(), 9 * KIB);
+
+        // Bigger than everything else put together
+        let dropped = index.insert(key(5), 5, 9 * KIB);
+        assert_eq!(dropped.len(), 4);
+        assert_eq!(index.total(), 9 * KIB);
+        assert_eq!(index.claims().len(), 1);
+        // Bigger than the whole budget isn't kept
+        let dropped = index.insert(key(6), 6, 11 * KIB);
+        assert!(dropped.is_empty());
+        assert_eq!(index.hash(key(6)), None);
+        assert_eq!(index.total(), 9 * KIB);
+
+        assert_eq!(index.remove(key(5)), Some(5));
+        assert_eq!(index.remove(key(5)), None);
+        assert_eq!(index.total(), 0);
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn claims_go_out_newest_first_and_always_finish() {
+        let mut index = CacheIndex::new(u64::MAX);
+        for x in 0..3 {
+            index.insert(key(x), x as u64, 1);
+        }
+        index.touch(key(0));
+        let order: Vec<u64> = index.claims().iter().map(|claim| claim.hash).collect();
+        assert_eq!(order, vec![0, 2, 1]);
+
+        let done = |message: &ClientMessage| match message {
+            ClientMessage::CachedChunks { chunks, done } => (chunks.len(), *done),
+            _ => panic!("not a claim"),
+        };
+        let empty = claim_messages(&[]);
+        assert_eq!(empty.iter().map(done).collect::<Vec<_>>(), vec![(0, true)]);
+        let claims = vec![index.claims()[0]; CACHED_CHUNKS_PER_MESSAGE * 2 + 1];
+        let batches: Vec<_> = claim_messages(&claims).iter().map(done).collect();
+        assert_eq!(
+            batches,
+            vec![
+                (CACHED_CHUNKS_PER_MESSAGE, false),
+                (CACHED_CHUNKS_PER_MESSAGE, false),
+                (1, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn corrupt_entries_are_misses() {
+        let root = std::env::temp_dir().join(format!("vinox-chunk-cache-{}", std::process::id()));
+        let server = root.join("127_0_0_1_25565");
+        fs::create_dir_all(server.join("old-world")).unwrap();
+        fs::create_dir_all(server.join("world")).unwrap();
+        let payload = b"not really zstd".to_vec();
+        let hash = hash_bytes(&payload);
+        let good = server.join("world").join(file_name(key(1), hash));
+        let flipped = server.join("world").join(file_name(key(2), hash));
+        let short = server.join("world").join(file_name(key(3), hash));
+        fs::write(&good, &payload).unwrap();
+        let mut bad = payload.clone();
+        bad[0] ^= 1;
+        fs::write(&flipped, &bad).unwrap();
+        fs::write(&short, &payload[..4]).unwrap();
+        fs::write(server.join("world").join("junk.tmp"), b"?").unwrap();
+
+        let mut found: Vec<_> = scan(&server, "world")
+            .into_iter()
+            .map(|file| file.key)
+            .collect();
+        found.sort_by_key(|key| key.1.x);
+        assert_eq!(found, vec![key(1), key(2), key(3)]);
+        // Other worlds and anything that isn't an entry are cleared out
+        assert!(!server.join("old-world").exists());
+        assert!(!server.join("world").join("junk.tmp").exists());
+
+        assert_eq!(read_entry(&good, hash), Some(payload));
+        assert_eq!(read_entry(&flipped, hash), None);
+        assert_eq!(read_entry(&short, hash), None);
+        assert_eq!(read_entry(&server.join("gone.chunk"), hash), None);
+        // Intact on disk but not a chunk, decoding turns it down too
+        let content = ContentReport::default();
+        let block_table = BlockTable::default();
+        assert!(level_chunk(&read_entry(&good, hash).unwrap(), &content, &block_table).is_none());
+
+        fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn names_round_trip_and_stay_inside_the_cache() {
+        for (key, hash) in [
+            ((DimensionId(0), IVec3::new(0, 0, 0)), 0),
+            ((DimensionId(3), IVec3::new(-40, 7, i32::MIN)), u64::MAX),
+        ] {
+            assert_eq!(parse_file_name(&file_name(key, hash)), Some((key, hash)));
+        }
+        assert_eq!(parse_file_name("0.1.2.3.00ff.chunk.tmp"), None);
+        assert_eq!(parse_file_name("0.1.2.00ff.chunk"), None);
+        assert_eq!(parse_file_name("0.1.2.3.4.00ff.chunk"), None);
+        assert_eq!(folder_name("../../etc"), "______etc");
+        assert_eq!(
+            folder_name("play.example.com:25565"),
+            "play_example_com_25565"
+        );
+    }
+}
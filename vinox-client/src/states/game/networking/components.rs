@@ -1,13 +1,231 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
 
 use bevy::prelude::*;
+use vinox_common::{
+    networking::protocol::{
+        ChatCategory, JoinRejection, ServerHealth, ServerMessage, PROTOCOL_VERSION,
+    },
+    storage::content::ContentDiff,
+};
 
-#[derive(Resource, Default, Deref, DerefMut)]
-pub struct ChatMessages(pub Vec<(String, String)>);
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatLine {
+    pub user_name: String,
+    pub message: String,
+    pub category: ChatCategory,
+    // Sent by us, never worth a popup
+    pub own: bool,
+}
+
+impl ChatLine {
+    pub fn new(
+        user_name: impl Into<String>,
+        message: impl Into<String>,
+        category: ChatCategory,
+    ) -> Self {
+        Self {
+            user_name: user_name.into(),
+            message: message.into(),
+            category,
+            own: false,
+        }
+    }
+
+    // Replies from commands the client handles itself
+    pub fn console(message: impl Into<String>) -> Self {
+        Self::new("Console", message, ChatCategory::CommandOutput)
+    }
+
+    pub fn toast(message: impl Into<String>) -> Self {
+        Self::new("", message, ChatCategory::Toast)
+    }
+}
+
+// Lines the console keeps, older ones go once there are more than this
+pub const CHAT_HISTORY: usize = 500;
+
+// The last CHAT_HISTORY lines this session, stored whether or not the console is open. Whether
+// one is shown and where is decided when it's drawn or routed. Lines are numbered from the start
+// of the session so a number stays good after older lines are dropped
+#[derive(Resource, Default)]
+pub struct ChatMessages {
+    lines: VecDeque<ChatLine>,
+    dropped: usize,
+}
+
+impl ChatMessages {
+    pub fn push(&mut self, line: ChatLine) {
+        self.lines.push_back(line);
+        if self.lines.len() > CHAT_HISTORY {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    // Lines ever pushed, which is also the number the next one gets
+    pub fn pushed(&self) -> usize {
+        self.dropped + self.lines.len()
+    }
+
+    // None once the line has been dropped
+    pub fn get_mut(&mut self, number: usize) -> Option<&mut ChatLine> {
+        self.lines.get_mut(number.checked_sub(self.dropped)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ChatLine> {
+        self.lines.iter()
+    }
+
+    // From line number on, whatever is left of them
+    pub fn since(&self, number: usize) -> impl Iterator<Item = &ChatLine> {
+        self.lines.iter().skip(number.saturating_sub(self.dropped))
+    }
+}
+
+impl Extend<ChatLine> for ChatMessages {
+    fn extend<T: IntoIterator<Item = ChatLine>>(&mut self, lines: T) {
+        for line in lines {
+            self.push(line);
+        }
+    }
+}
 
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct ClientData(pub u64);
 
+// Granted by the server after joining
+#[derive(Resource, Default, Debug)]
+pub struct Capabilities {
+    pub creative: bool,
+    // Whether flying is allowed and, while flying, going through blocks
+    pub fly: bool,
+    pub noclip: bool,
+}
+
+// Last health the server told us about, it only says when it changes
+#[derive(Resource, Default, Debug, Deref, DerefMut)]
+pub struct ServerStatus(pub ServerHealth);
+
+// Identifiers listed per kind of difference, the full list goes to the log
+pub const CONTENT_LINES: usize = 5;
+
+// How our content differs from the server's, only ever non-empty when it let us in anyway
+#[derive(Resource, Default, Debug, Deref, DerefMut)]
+pub struct ContentReport(pub ContentDiff);
+
+// The server's world seed, for placing what only exists on the client
+#[derive(Resource, Default, Debug, Clone, Copy, Deref, DerefMut)]
+pub struct WorldSeed(pub u32);
+
+// Why a connection attempt gave up, shown on the loading screen
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionFailure {
+    DnsFailed(String),
+    Refused,
+    TimedOut,
+    VersionMismatch { server: u32 },
+    ServerFull,
+    Denied(String),
+    // The server's blocks, items, recipes or geometry aren't the same as ours
+    ContentMismatch(ContentDiff),
+    // A replay recorded with other content would play out differently
+    ReplayContent(ContentDiff),
+    Other(String),
+}
+
+impl ConnectionFailure {
+    pub fn reason(&self) -> String {
+        match self {
+            ConnectionFailure::DnsFailed(address) => {
+                format!("Couldn't find a server called {address}")
+            }
+            ConnectionFailure::Refused => "The server closed the connection".to_string(),
+            ConnectionFailure::TimedOut => "The server didn't answer in time".to_string(),
+            ConnectionFailure::VersionMismatch { server } => format!(
+                "The server speaks protocol version {server} but this client speaks {PROTOCOL_VERSION}"
+            ),
+            ConnectionFailure::ServerFull => "The server is full".to_string(),
+            ConnectionFailure::Denied(reason) => format!("The server turned you away: {reason}"),
+            ConnectionFailure::ContentMismatch(diff) => {
+                let mut reason = "The server's content doesn't match yours".to_string();
+                for line in diff.summary(CONTENT_LINES) {
+                    reason.push('\n');
+                    reason.push_str(&line);
+                }
+                reason
+            }
+            ConnectionFailure::ReplayContent(diff) => {
+                let mut reason =
+                    "This replay was recorded with different content, it can't play back the same"
+                        .to_string();
+                for line in diff.summary(CONTENT_LINES) {
+                    reason.push('\n');
+                    reason.push_str(&line);
+                }
+                reason
+            }
+            ConnectionFailure::Other(error) => format!("Couldn't connect: {error}"),
+        }
+    }
+}
+
+impl From<JoinRejection> for ConnectionFailure {
+    fn from(rejection: JoinRejection) -> Self {
+        match rejection {
+            JoinRejection::VersionMismatch { server } => {
+                ConnectionFailure::VersionMismatch { server }
+            }
+            JoinRejection::ServerFull => ConnectionFailure::ServerFull,
+            JoinRejection::Denied { reason } => ConnectionFailure::Denied(reason),
+        }
+    }
+}
+
+// Where the connection to the server is at. Loading waits for Joined as well as for assets,
+// started is the raw elapsed time the attempt began at
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub enum ConnectionPhase {
+    #[default]
+    Idle,
+    Connecting {
+        started: Duration,
+    },
+    // Connected, waiting on the server to take our join
+    Authenticating {
+        started: Duration,
+    },
+    Joined,
+    Failed(ConnectionFailure),
+}
+
+impl ConnectionPhase {
+    pub fn started(&self) -> Option<Duration> {
+        match self {
+            ConnectionPhase::Connecting { started }
+            | ConnectionPhase::Authenticating { started } => Some(*started),
+            _ => None,
+        }
+    }
+
+    // Both halves share one timeout so a server that accepts and then says nothing still fails
+    pub fn timed_out(&self, now: Duration, timeout: Duration) -> Option<ConnectionFailure> {
+        let started = self.started()?;
+        (now.saturating_sub(started) >= timeout).then_some(ConnectionFailure::TimedOut)
+    }
+
+    // The connection dropping mid attempt is the server hanging up on us
+    pub fn lost(&self) -> Option<ConnectionFailure> {
+        self.started().map(|_| ConnectionFailure::Refused)
+    }
+}
+
+// Arrived while loading after the server let us in, handed to the game before anything newer
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct PendingMessages(pub VecDeque<ServerMessage>);
+
 #[derive(Debug)]
 pub struct PlayerInfo {
     pub client_entity: Entity,
@@ -21,3 +239,33 @@ pub struct ClientLobby {
 
 #[derive(Default, Resource, Deref, DerefMut)]
 pub struct NetworkMapping(pub HashMap<Entity, Entity>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chat_keeps_the_latest_lines_and_their_numbers() {
+        let mut messages = ChatMessages::default();
+        let first = messages.pushed();
+        messages.push(ChatLine::console("first"));
+        messages.extend((1..CHAT_HISTORY).map(|n| ChatLine::console(n.to_string())));
+        assert_eq!(messages.iter().count(), CHAT_HISTORY);
+        assert_eq!(messages.get_mut(first).unwrap().message, "first");
+
+        let routed = messages.pushed();
+        messages.extend((0..10).map(|n| ChatLine::console(format!("new {n}"))));
+        assert_eq!(messages.iter().count(), CHAT_HISTORY);
+        assert_eq!(messages.pushed(), CHAT_HISTORY + 10);
+        assert!(messages.get_mut(first).is_none());
+        assert_eq!(messages.iter().next().unwrap().message, "10");
+        let new: Vec<&str> = messages
+            .since(routed)
+            .map(|line| line.message.as_str())
+            .collect();
+        assert_eq!(new.len(), 10);
+        assert_eq!(new[0], "new 0");
+        // Lines that were dropped before they could be routed are just gone
+        assert_eq!(messages.since(0).count(), CHAT_HISTORY);
+    }
+}
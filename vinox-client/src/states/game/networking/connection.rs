@@ -0,0 +1,119 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_quinnet::{client::Client, shared::channel::ChannelId};
+use vinox_common::networking::protocol::{ClientMessage, ServerMessage};
+
+use super::{
+    components::PendingMessages,
+    replay::{ReplayPlayback, ReplayRecorder},
+};
+
+#[cfg(any(debug_assertions, feature = "netsim"))]
+use {super::netsim::NetsimQueues, vinox_common::networking::netsim::NetworkConditions};
+
+// Everything to and from the server after joining goes through here so the network
+// conditioner can sit in between. The join handshake talks to the Client directly
+#[derive(SystemParam)]
+pub struct NetClient<'w> {
+    client: ResMut<'w, Client>,
+    pending: ResMut<'w, PendingMessages>,
+    // Everything received goes into the recording while there is one
+    recorder: Option<Res<'w, ReplayRecorder>>,
+    // Played back offline, there is no connection to send on
+    playback: Option<Res<'w, ReplayPlayback>>,
+    #[cfg(any(debug_assertions, feature = "netsim"))]
+    conditions: Res<'w, NetworkConditions>,
+    #[cfg(any(debug_assertions, feature = "netsim"))]
+    queues: ResMut<'w, NetsimQueues>,
+    #[cfg(any(debug_assertions, feature = "netsim"))]
+    time: Res<'w, Time>,
+}
+
+impl<'w> NetClient<'w> {
+    pub fn receive(&mut self) -> Option<ServerMessage> {
+        let message = match self.pending.pop_front() {
+            Some(message) => message,
+            None if self.playback.is_some() => return None,
+            None => self.poll()?,
+        };
+        if let Some(recorder) = &self.recorder {
+            recorder.message(&message);
+        }
+        Some(message)
+    }
+}
+
+#[cfg(not(any(debug_assertions, feature = "netsim")))]
+impl<'w> NetClient<'w> {
+    pub fn send(&mut self, message: ClientMessage) {
+        if self.playback.is_some() {
+            return;
+        }
+        self.client.connection_mut().try_send_message(message);
+    }
+
+    pub fn send_on(&mut self, channel: ChannelId, message: ClientMessage) {
+        if self.playback.is_some() {
+            return;
+        }
+        self.client
+            .connection_mut()
+            .try_send_message_on(channel, message);
+    }
+
+    fn poll(&mut self) -> Option<ServerMessage> {
+        self.client
+            .connection_mut()
+            .try_receive_message::<ServerMessage>()
+    }
+}
+
+#[cfg(any(debug_assertions, feature = "netsim"))]
+impl<'w> NetClient<'w> {
+    pub fn send(&mut self, message: ClientMessage) {
+        self.queue(None, message);
+    }
+
+    pub fn send_on(&mut self, channel: ChannelId, message: ClientMessage) {
+        self.queue(Some(channel), message);
+    }
+
+    fn queue(&mut self, channel: Option<ChannelId>, message: ClientMessage) {
+        if self.playback.is_some() {
+            return;
+        }
+        let now = self.time.raw_elapsed();
+        self.queues
+            .outgoing
+            .push(&self.conditions.outgoing, now, (channel, message));
+        self.flush();
+    }
+
+    fn poll(&mut self) -> Option<ServerMessage> {
+        let now = self.time.raw_elapsed();
+        while let Some(message) = self
+            .client
+            .connection_mut()
+            .try_receive_message::<ServerMessage>()
+        {
+            self.queues
+                .incoming
+                .push(&self.conditions.incoming, now, message);
+        }
+        self.queues.incoming.pop(now)
+    }
+
+    // Sends whatever has waited long enough, runs every frame so nothing sits in the queue
+    // just because nothing new was sent
+    pub fn flush(&mut self) {
+        let now = self.time.raw_elapsed();
+        while let Some((channel, message)) = self.queues.outgoing.pop(now) {
+            match channel {
+                Some(channel) => self
+                    .client
+                    .connection_mut()
+                    .try_send_message_on(channel, message),
+                None => self.client.connection_mut().try_send_message(message),
+            }
+        }
+    }
+}
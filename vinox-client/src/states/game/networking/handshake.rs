@@ -0,0 +1,283 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use bevy_quinnet::{
+    client::{
+        certificate::CertificateVerificationMode,
+        connection::{ConnectionConfiguration, ConnectionEvent, ConnectionLostEvent},
+        Client,
+    },
+    shared::channel::ChannelId,
+};
+use vinox_common::{
+    networking::protocol::{
+        ChatCategory, ClientMessage, NetworkIP, ServerMessage, PROTOCOL_VERSION,
+    },
+    storage::content::{ContentDiff, ContentManifest, ContentPolicy},
+};
+
+use crate::states::components::{GameOptions, ProjectPath};
+
+use super::{
+    chunk_cache::ChunkCache,
+    components::{
+        ChatLine, ChatMessages, ClientData, ConnectionFailure, ConnectionPhase, ContentReport,
+        PendingMessages, WorldSeed, CONTENT_LINES,
+    },
+    replay::ReplayPlayback,
+};
+
+pub const DEFAULT_PORT: u16 = 25565;
+
+// Takes an address with or without a port. Names go through the system resolver, which blocks,
+// but typed IPs never touch it
+pub fn resolve(address: &str) -> Result<SocketAddr, ConnectionFailure> {
+    let address = address.trim();
+    if let Ok(socket) = address.parse::<SocketAddr>() {
+        return Ok(socket);
+    }
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, DEFAULT_PORT));
+    }
+    // The server only listens on IPv4 so don't let localhost become ::1
+    if address == "localhost" {
+        return Ok(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DEFAULT_PORT));
+    }
+    let resolved: Vec<SocketAddr> = if address.contains(':') {
+        address.to_socket_addrs().map(Iterator::collect)
+    } else {
+        (address, DEFAULT_PORT)
+            .to_socket_addrs()
+            .map(Iterator::collect)
+    }
+    .unwrap_or_default();
+    resolved
+        .iter()
+        .find(|socket| socket.is_ipv4())
+        .or(resolved.first())
+        .copied()
+        .ok_or_else(|| ConnectionFailure::DnsFailed(address.to_string()))
+}
+
+// Starts over from nothing, for entering Loading and for Retry
+pub fn connect(address: &str, client: &mut Client, now: Duration) -> ConnectionPhase {
+    client.close_all_connections().ok();
+    let server = match resolve(address) {
+        Ok(server) => server,
+        Err(failure) => return ConnectionPhase::Failed(failure),
+    };
+    let local: IpAddr = if server.is_ipv4() {
+        Ipv4Addr::UNSPECIFIED.into()
+    } else {
+        Ipv6Addr::UNSPECIFIED.into()
+    };
+    match client.open_connection(
+        ConnectionConfiguration::from_ips(server.ip(), server.port(), local, 0),
+        CertificateVerificationMode::SkipVerification,
+    ) {
+        Ok(_) => ConnectionPhase::Connecting { started: now },
+        Err(error) => ConnectionPhase::Failed(ConnectionFailure::Other(error.to_string())),
+    }
+}
+
+//TODO: Right now we are building the client only as a multiplayer client. This is fine but eventually we need to have singleplayer.
+// To achieve this we will just have the client start up a server. But for now I am just going to use a dedicated one for testing
+pub fn start_connection(
+    ip: Res<NetworkIP>,
+    mut client: ResMut<Client>,
+    mut phase: ResMut<ConnectionPhase>,
+    mut client_data: ResMut<ClientData>,
+    time: Res<Time>,
+    (playback, local_content): (Option<Res<ReplayPlayback>>, Res<ContentManifest>),
+) {
+    if let Some(playback) = playback {
+        **client_data = playback.client_id();
+        *phase = playback.join(&local_content);
+        return;
+    }
+    **client_data = 0;
+    *phase = connect(&ip, &mut client, time.raw_elapsed());
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn watch_connection(
+    mut client: ResMut<Client>,
+    mut phase: ResMut<ConnectionPhase>,
+    mut client_data: ResMut<ClientData>,
+    mut pending: ResMut<PendingMessages>,
+    mut connected: EventReader<ConnectionEvent>,
+    mut lost: EventReader<ConnectionLostEvent>,
+    options: Res<GameOptions>,
+    time: Res<Time>,
+    (local_content, mut report, mut world_seed): (
+        Res<ContentManifest>,
+        ResMut<ContentReport>,
+        ResMut<WorldSeed>,
+    ),
+    (mut chunk_cache, ip, project_path): (ResMut<ChunkCache>, Res<NetworkIP>, Res<ProjectPath>),
+) {
+    if connected.iter().count() > 0 {
+        if let ConnectionPhase::Connecting { started } = *phase {
+            client
+                .connection_mut()
+                .set_default_channel(ChannelId::UnorderedReliable);
+            // Nothing held from an attempt before a retry belongs to this one
+            pending.clear();
+            *phase = ConnectionPhase::Authenticating { started };
+        }
+    }
+    if lost.iter().count() > 0 {
+        if let Some(failure) = phase.lost() {
+            *phase = ConnectionPhase::Failed(failure);
+            return;
+        }
+    }
+    let timeout = Duration::from_secs_f32(options.connect_timeout.max(1.0));
+    if let Some(failure) = phase.timed_out(time.raw_elapsed(), timeout) {
+        client.close_all_connections().ok();
+        *phase = ConnectionPhase::Failed(failure);
+        return;
+    }
+    if !matches!(*phase, ConnectionPhase::Authenticating { .. }) {
+        return;
+    }
+    while let Some(message) = client
+        .connection_mut()
+        .try_receive_message::<ServerMessage>()
+    {
+        match message {
+            ServerMessage::ClientId { id } => {
+                **client_data = id;
+                client
+                    .connection_mut()
+                    .try_send_message(ClientMessage::Join {
+                        user_name: options.user_name.clone(),
+                        id,
+                        protocol: PROTOCOL_VERSION,
+                        chunk_cache: options.chunk_cache,
+                        view_distance: options.view_distance as u8,
+                    });
+            }
+            ServerMessage::JoinRejected { reason } => {
+                client.close_all_connections().ok();
+                *phase = ConnectionPhase::Failed(reason.into());
+                return;
+            }
+            // Sent as soon as the join is taken, the only thing that says we're in
+            ServerMessage::ContentManifest {
+                manifest,
+                policy,
+                world_id,
+                seed,
+            } => {
+                let diff = ContentDiff::between(&manifest, &local_content);
+                if !diff.is_empty() {
+                    println!("Content differs from the server's: {diff:?}");
+                }
+                if !diff.is_empty() && policy == ContentPolicy::Strict {
+                    client.close_all_connections().ok();
+                    *phase = ConnectionPhase::Failed(ConnectionFailure::ContentMismatch(diff));
+                    return;
+                }
+                **report = diff;
+                **world_seed = seed;
+                if options.chunk_cache {
+                    chunk_cache.open(&project_path, &ip, &world_id, options.chunk_cache_mib);
+                }
+                *phase = ConnectionPhase::Joined;
+                return;
+            }
+            // The channel is unordered so some of the world can beat the manifest here, it waits
+            // until the join is taken
+            message if **client_data != 0 => pending.push_back(message),
+            _ => {}
+        }
+    }
+}
+
+// The server let us in with different content, the player should still know why things look off
+pub fn announce_content_mismatch(report: Res<ContentReport>, mut messages: ResMut<ChatMessages>) {
+    if report.is_empty() {
+        return;
+    }
+    messages.push(ChatLine::new(
+        "Server",
+        "Your content doesn't match the server's, some blocks and items may be missing or wrong",
+        ChatCategory::System,
+    ));
+    for line in report.summary(CONTENT_LINES) {
+        messages.push(ChatLine::new("Server", line, ChatCategory::System));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::networking::protocol::JoinRejection;
+
+    #[test]
+    fn failures_are_classified() {
+        assert_eq!(
+            resolve("127.0.0.1"),
+            Ok(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DEFAULT_PORT))
+        );
+        assert_eq!(resolve(" 10.0.0.2:4000 ").unwrap().port(), 4000);
+        assert!(resolve("[::1]:25565").unwrap().is_ipv6());
+        assert!(resolve("localhost").unwrap().is_ipv4());
+        assert_eq!(
+            resolve("no-such-server.invalid"),
+            Err(ConnectionFailure::DnsFailed(
+                "no-such-server.invalid".to_string()
+            ))
+        );
+
+        let started = Duration::from_secs(5);
+        let timeout = Duration::from_secs(10);
+        let phase = ConnectionPhase::Authenticating { started };
+        assert_eq!(phase.timed_out(Duration::from_secs(14), timeout), None);
+        assert_eq!(
+            phase.timed_out(Duration::from_secs(15), timeout),
+            Some(ConnectionFailure::TimedOut)
+        );
+        assert_eq!(phase.lost(), Some(ConnectionFailure::Refused));
+        // Nothing left to time out or lose once joined or failed
+        assert_eq!(
+            ConnectionPhase::Joined.timed_out(Duration::MAX, timeout),
+            None
+        );
+        assert_eq!(
+            ConnectionPhase::Failed(ConnectionFailure::ServerFull).lost(),
+            None
+        );
+
+        for (rejection, failure, mentions) in [
+            (
+                JoinRejection::VersionMismatch { server: 7 },
+                ConnectionFailure::VersionMismatch { server: 7 },
+                "version 7",
+            ),
+            (
+                JoinRejection::ServerFull,
+                ConnectionFailure::ServerFull,
+                "full",
+            ),
+            (
+                JoinRejection::Denied {
+                    reason: "not on the whitelist".to_string(),
+                },
+                ConnectionFailure::Denied("not on the whitelist".to_string()),
+                "not on the whitelist",
+            ),
+        ] {
+            let mapped = ConnectionFailure::from(rejection);
+            assert!(mapped.reason().contains(mentions), "{}", mapped.reason());
+            assert_eq!(mapped, failure);
+        }
+        assert!(ConnectionFailure::DnsFailed("example".to_string())
+            .reason()
+            .contains("example"));
+    }
+}
@@ -1,3 +1,10 @@
+pub mod chunk_cache;
 pub mod components;
+pub mod connection;
+pub mod handshake;
+#[cfg(any(debug_assertions, feature = "netsim"))]
+pub mod netsim;
 pub mod plugin;
+pub mod position;
+pub mod replay;
 pub mod syncing;
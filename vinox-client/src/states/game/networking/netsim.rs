@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use bevy_quinnet::shared::channel::ChannelId;
+use vinox_common::networking::{
+    netsim::{parse_netsim, Conditioner, NetworkConditions},
+    protocol::{ClientMessage, ServerMessage},
+};
+
+use crate::states::{
+    components::{GameSet, GameState},
+    crash::RecoverableSystem,
+    game::session::SessionApp,
+};
+
+use super::{connection::NetClient, syncing::get_messages};
+
+// Messages held back by the conditioner, thrown away with the session
+#[derive(Resource, Default)]
+pub struct NetsimQueues {
+    // No channel is the connection's default one
+    pub outgoing: Conditioner<(Option<ChannelId>, ClientMessage)>,
+    pub incoming: Conditioner<ServerMessage>,
+}
+
+// Some(reply) when the console line was a /netsim command
+pub fn netsim_command(message: &str, conditions: &mut NetworkConditions) -> Option<String> {
+    let args = message.strip_prefix("/netsim")?;
+    if !args.is_empty() && !args.starts_with(' ') {
+        return None;
+    }
+    Some(match parse_netsim(args, conditions) {
+        Ok(()) if conditions.is_active() => format!(
+            "Network conditions: out {}, in {}",
+            conditions.outgoing, conditions.incoming
+        ),
+        Ok(()) => "Network conditions off".to_string(),
+        Err(error) => format!(
+            "{error}. Usage: /netsim [in|out] [latency ms] [jitter ms] [loss percent] [reorder bool] [off]"
+        ),
+    })
+}
+
+pub fn flush_outgoing(mut client: NetClient) {
+    client.flush();
+}
+
+// Always on screen while anything is simulated so nobody forgets and chases phantom lag
+pub fn netsim_banner(mut contexts: EguiContexts, conditions: Res<NetworkConditions>) {
+    if !conditions.is_active() {
+        return;
+    }
+    egui::Area::new("netsim_banner")
+        .anchor(egui::Align2::LEFT_TOP, [8.0, 8.0])
+        .interactable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            let color = egui::Color32::from_rgb(243, 139, 168);
+            ui.label(
+                egui::RichText::new("NETWORK SIMULATION ACTIVE")
+                    .color(color)
+                    .strong()
+                    .size(20.0),
+            );
+            for (direction, link) in [("Out", conditions.outgoing), ("In", conditions.incoming)] {
+                if link.is_active() {
+                    ui.colored_label(color, format!("{direction}: {link}"));
+                }
+            }
+        });
+}
+
+pub struct NetsimPlugin;
+
+impl Plugin for NetsimPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkConditions>()
+            .init_resource::<NetsimQueues>()
+            .reset_on_exit::<NetsimQueues>()
+            .add_system(
+                flush_outgoing
+                    .after(get_messages)
+                    .in_set(GameSet::Networking)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                netsim_banner
+                    .recoverable(GameSet::Ui)
+                    .in_set(GameSet::Ui)
+                    .in_set(OnUpdate(GameState::Game)),
+            );
+    }
+}
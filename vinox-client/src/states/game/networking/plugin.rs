@@ -1,11 +1,21 @@
 use bevy::prelude::*;
-use vinox_common::networking::protocol::EntityBuffer;
+use vinox_common::{ecs::gameplay::GameplayRules, networking::protocol::EntityBuffer};
 
-use crate::states::components::GameState;
+use crate::states::{
+    components::{GameSet, GameState},
+    game::session::SessionApp,
+};
 
 use super::{
-    components::{ChatMessages, ClientLobby, NetworkMapping},
-    syncing::{client_send_naive_position, get_id, get_messages, lerp_new_location},
+    chunk_cache::{receive_cached_chunks, send_cache_claims, ChunkCache},
+    components::{
+        Capabilities, ChatMessages, ClientLobby, ConnectionPhase, ContentReport, NetworkMapping,
+        PendingMessages, ServerStatus, WorldSeed,
+    },
+    handshake::announce_content_mismatch,
+    position::{send_position, PositionSender},
+    replay::ReplayPlugin,
+    syncing::{get_messages, lerp_new_location},
 };
 
 pub struct NetworkingPlugin;
@@ -16,13 +26,53 @@ impl Plugin for NetworkingPlugin {
             .insert_resource(NetworkMapping::default())
             .insert_resource(EntityBuffer::default())
             .insert_resource(ChatMessages::default())
+            .insert_resource(Capabilities::default())
+            .insert_resource(ServerStatus::default())
+            .insert_resource(ConnectionPhase::default())
+            .insert_resource(PendingMessages::default())
+            .insert_resource(ContentReport::default())
+            .init_resource::<WorldSeed>()
+            .init_resource::<PositionSender>()
+            .init_resource::<ChunkCache>()
+            .init_resource::<GameplayRules>()
+            .reset_on_exit::<ClientLobby>()
+            .reset_on_exit::<NetworkMapping>()
+            .reset_on_exit::<EntityBuffer>()
+            .reset_on_exit::<ChatMessages>()
+            .reset_on_exit::<Capabilities>()
+            .reset_on_exit::<ServerStatus>()
+            .reset_on_exit::<ConnectionPhase>()
+            .reset_on_exit::<PendingMessages>()
+            .reset_on_exit::<ContentReport>()
+            .reset_on_exit::<WorldSeed>()
+            .reset_on_exit::<PositionSender>()
+            .reset_on_exit::<ChunkCache>()
+            .reset_on_exit::<GameplayRules>()
+            .add_system(announce_content_mismatch.in_schedule(OnEnter(GameState::Game)))
+            .add_systems(
+                // Before any new teleport is read, so it goes out once the player has moved
+                (
+                    send_position.before(get_messages),
+                    get_messages,
+                    lerp_new_location,
+                )
+                    .in_set(GameSet::Networking)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            // The server holds off on chunks until the claims are in, which can be while loading
             .add_system(
-                client_send_naive_position
-                    .in_schedule(CoreSchedule::FixedUpdate)
+                send_cache_claims
+                    .before(get_messages)
+                    .run_if(in_state(GameState::Loading).or_else(in_state(GameState::Game))),
+            )
+            .add_system(
+                receive_cached_chunks
+                    .after(get_messages)
+                    .in_set(GameSet::Networking)
                     .in_set(OnUpdate(GameState::Game)),
             )
-            .add_systems(
-                (get_messages, lerp_new_location, get_id).in_set(OnUpdate(GameState::Game)),
-            );
+            .add_plugin(ReplayPlugin);
+        #[cfg(any(debug_assertions, feature = "netsim"))]
+        app.add_plugin(super::netsim::NetsimPlugin);
     }
 }
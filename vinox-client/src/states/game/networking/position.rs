@@ -0,0 +1,189 @@
+use bevy::prelude::*;
+use bevy_quinnet::shared::channel::ChannelId;
+use vinox_common::{
+    networking::protocol::{ClientMessage, PackedPose, PlayerFlags, ANGLE_STEPS, POSITION_STEPS},
+    physics::movement::MovementState,
+    world::chunks::positions::WorldOffset,
+};
+
+use crate::states::{
+    components::GameOptions,
+    game::{
+        input::player::{FPSCamera, TeleportEvent},
+        world::chunks::ControlledPlayer,
+    },
+};
+
+use super::connection::NetClient;
+
+// 20 a second while moving, slowing down to one a second once the player stands still
+pub const FULL_RATE_SECONDS: f32 = 1.0 / 20.0;
+pub const KEEPALIVE_SECONDS: f32 = 1.0;
+// Anything smaller is standing still, in the packed steps so both sides agree
+pub const POSITION_EPSILON: i32 = (POSITION_STEPS / 64.0) as i32;
+pub const ANGLE_EPSILON: i32 = (ANGLE_STEPS / 2.0) as i32;
+// Frames rarely land exactly on the interval, this keeps 20 a second from turning into 15
+const SLACK_SECONDS: f32 = 0.002;
+
+pub fn moved(from: PackedPose, to: PackedPose) -> bool {
+    let turned = |from: i16, to: i16| {
+        let difference = (to as i32 - from as i32).rem_euclid(3600);
+        difference.min(3600 - difference) > ANGLE_EPSILON
+    };
+    from.position
+        .iter()
+        .zip(to.position)
+        .any(|(from, to)| (to - from).abs() > POSITION_EPSILON)
+        || turned(from.yaw, to.yaw)
+        || turned(from.head_pitch, to.head_pitch)
+}
+
+// Decides when the player's pose goes out. Moving sends at the full rate, every send without
+// movement waits twice as long for the next down to the keepalive, and a new movement state or
+// anything urgent goes right away
+#[derive(Resource, Debug, Default)]
+pub struct PositionSender {
+    last: Option<(PackedPose, MovementState, f32)>,
+    interval: f32,
+}
+
+impl PositionSender {
+    pub fn due(&mut self, pose: PackedPose, state: MovementState, urgent: bool, now: f32) -> bool {
+        let (moving, changed, waited) = match self.last {
+            None => (true, true, f32::INFINITY),
+            Some((last_pose, last_state, sent)) => {
+                (moved(last_pose, pose), last_state != state, now - sent)
+            }
+        };
+        if moving || changed {
+            self.interval = FULL_RATE_SECONDS;
+        }
+        if !(urgent || changed || waited + SLACK_SECONDS >= self.interval) {
+            return false;
+        }
+        if !moving && !changed {
+            self.interval = (self.interval * 2.0).min(KEEPALIVE_SECONDS);
+        }
+        self.last = Some((pose, state, now));
+        true
+    }
+}
+
+pub fn send_position(
+    player: Query<(&Transform, &MovementState, Ref<PlayerFlags>), With<ControlledPlayer>>,
+    camera: Query<&FPSCamera>,
+    mut teleports: EventReader<TeleportEvent>,
+    mut sender: ResMut<PositionSender>,
+    mut client: NetClient,
+    (offset, options, time): (Res<WorldOffset>, Res<GameOptions>, Res<Time>),
+) {
+    // Read every frame so a teleport isn't still waiting once the player spawns. Switching hands
+    // goes right away too, an idle player would otherwise take a second to show it
+    let urgent = teleports.iter().count() > 0 || options.is_changed();
+    let (Ok((transform, state, flags)), Ok(fps_camera)) =
+        (player.get_single(), camera.get_single())
+    else {
+        return;
+    };
+    // So does starting or stopping a sneak, standing still doesn't hide it
+    let urgent = urgent || flags.is_changed();
+    // Where the eyes point, in front of the player the camera faces the other way
+    let (head_pitch, yaw, _) = fps_camera.look().rotation.to_euler(EulerRot::XYZ);
+    let pose = PackedPose::pack(offset.to_world(transform.translation), yaw, head_pitch);
+    if sender.due(pose, *state, urgent, time.elapsed_seconds()) {
+        client.send_on(
+            ChannelId::Unreliable,
+            ClientMessage::Position {
+                pose,
+                left_handed: options.left_handed,
+                flags: *flags,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::DVec3;
+
+    fn pose(x: f64, yaw: f32) -> PackedPose {
+        PackedPose::pack(DVec3::new(x, 64.0, 0.0), yaw, 0.0)
+    }
+
+    // Seconds at which a player standing still from `start` gets sent, at 60 frames a second
+    fn idle_sends(sender: &mut PositionSender, start: f32, until: f32) -> Vec<f32> {
+        let mut sends = Vec::new();
+        let mut now = start;
+        while now < until {
+            if sender.due(pose(0.0, 0.0), MovementState::Grounded, false, now) {
+                sends.push(now);
+            }
+            now += 1.0 / 60.0;
+        }
+        sends
+    }
+
+    #[test]
+    fn moving_sends_at_the_full_rate() {
+        let mut sender = PositionSender::default();
+        let mut sends = 0;
+        for frame in 0..60 {
+            let now = frame as f32 / 60.0;
+            // Walking along at a few blocks a second
+            if sender.due(
+                pose(now as f64 * 4.0, 0.0),
+                MovementState::Grounded,
+                false,
+                now,
+            ) {
+                sends += 1;
+            }
+        }
+        assert_eq!(sends, 20);
+
+        // Turning the head counts as moving, wiggling under the epsilons doesn't
+        assert!(moved(pose(0.0, 0.0), pose(0.0, 1f32.to_radians())));
+        assert!(!moved(pose(0.0, 0.0), pose(0.01, 0.02f32.to_radians())));
+        // Across the wrap from 179.9 to -179.9 degrees is a tiny turn
+        assert!(!moved(
+            pose(0.0, 179.9f32.to_radians()),
+            pose(0.0, -179.9f32.to_radians())
+        ));
+    }
+
+    #[test]
+    fn standing_still_decays_to_the_keepalive() {
+        let mut sender = PositionSender::default();
+        assert!(sender.due(pose(0.0, 0.0), MovementState::Grounded, false, 0.0));
+        let sends = idle_sends(&mut sender, 1.0 / 60.0, 10.0);
+        let gaps: Vec<f32> = sends
+            .windows(2)
+            .map(|pair| ((pair[1] - pair[0]) * 100.0).round() / 100.0)
+            .collect();
+        // Settles quickly, then backs off to once a second and stays there
+        assert!(sends[0] < 0.1);
+        assert!(gaps.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!(gaps.iter().all(|gap| *gap <= KEEPALIVE_SECONDS + 0.02));
+        assert!(gaps.iter().rev().take(5).all(|gap| *gap >= 0.99));
+        assert!(sends.len() < 16);
+
+        // Moving again goes straight back to the full rate
+        let now = 10.0;
+        assert!(sender.due(pose(1.0, 0.0), MovementState::Grounded, false, now));
+        assert!(!sender.due(pose(1.2, 0.0), MovementState::Grounded, false, now + 0.02));
+        assert!(sender.due(pose(1.4, 0.0), MovementState::Grounded, false, now + 0.05));
+    }
+
+    #[test]
+    fn state_changes_and_teleports_go_right_away() {
+        let mut sender = PositionSender::default();
+        assert!(sender.due(pose(0.0, 0.0), MovementState::Grounded, false, 0.0));
+        // Jumping, right after a send
+        assert!(sender.due(pose(0.0, 0.0), MovementState::Airborne, false, 0.01));
+        // Falling into water
+        assert!(sender.due(pose(0.05, 0.0), MovementState::Swimming, false, 0.02));
+        assert!(!sender.due(pose(0.05, 0.0), MovementState::Swimming, false, 0.03));
+        assert!(sender.due(pose(0.05, 0.0), MovementState::Swimming, true, 0.03));
+    }
+}
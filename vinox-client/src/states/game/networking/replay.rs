@@ -0,0 +1,948 @@
+use std::{
+    env, fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use leafwing_input_manager::{plugin::InputManagerSystem, prelude::*};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use vinox_common::{
+    ecs::bundles::Inventory,
+    networking::protocol::{ServerMessage, PROTOCOL_VERSION},
+    storage::content::{canonical_hash, ContentDiff, ContentManifest},
+    world::chunks::positions::WorldOffset,
+};
+
+use crate::states::{
+    components::{GameActions, GameSet, GameState, ProjectPath},
+    game::{
+        input::look::LookDelta,
+        session::SessionApp,
+        world::{
+            chunks::{ControlledPlayer, PlayerChunk},
+            schematic::valid_name,
+        },
+    },
+};
+
+use super::components::{
+    ChatLine, ChatMessages, ClientData, ConnectionFailure, ConnectionPhase, PendingMessages,
+};
+
+pub const REPLAY_MAGIC: &[u8; 4] = b"VRPL";
+pub const REPLAY_VERSION: u32 = 1;
+pub const REPLAY_EXTENSION: &str = "vreplay";
+// Chunks are the biggest thing the server sends and they're far under this, a longer frame is a
+// torn length
+const MAX_FRAME_BYTES: u32 = 1 << 24;
+pub const CHECKPOINT_SECONDS: f32 = 1.0;
+// Frames don't land at the same moments twice so physics drifts a little on a good replay too
+pub const CHECKPOINT_TOLERANCE: f64 = 0.05;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    NotAReplay,
+    Version { found: u32 },
+    Protocol { found: u32 },
+    Corrupt(String),
+    Io(String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::NotAReplay => write!(f, "not a replay file"),
+            ReplayError::Version { found } => write!(
+                f,
+                "recorded by a different version of the game (replay format {found}, this game reads {REPLAY_VERSION})"
+            ),
+            ReplayError::Protocol { found } => write!(
+                f,
+                "recorded with protocol version {found} but this client speaks {PROTOCOL_VERSION}"
+            ),
+            ReplayError::Corrupt(reason) => write!(f, "file is corrupt: {reason}"),
+            ReplayError::Io(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplayHeader {
+    pub protocol: u32,
+    // Which of the players in the recording is us
+    pub client_id: u64,
+    // All of it rather than the hash, so a mismatch can say what's different
+    pub content: ContentManifest,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplayFooter {
+    pub duration: f32,
+    pub version: String,
+    pub content_hash: u64,
+}
+
+// What the simulation should look like at a moment, compared between recording and playback
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    pub translation: [f64; 3],
+    pub chunk: [i32; 3],
+    pub inventory: u64,
+}
+
+impl Checkpoint {
+    pub fn capture(
+        transform: &Transform,
+        chunk: &PlayerChunk,
+        inventory: &Inventory,
+        offset: &WorldOffset,
+    ) -> Self {
+        Self {
+            translation: offset.to_world(transform.translation).to_array(),
+            chunk: chunk.chunk_pos.to_array(),
+            inventory: canonical_hash(inventory),
+        }
+    }
+
+    // The parts that don't match, empty when they're close enough
+    pub fn mismatches(&self, other: &Checkpoint) -> Vec<&'static str> {
+        let mut mismatches = Vec::new();
+        if self
+            .translation
+            .iter()
+            .zip(other.translation)
+            .any(|(a, b)| (a - b).abs() > CHECKPOINT_TOLERANCE)
+        {
+            mismatches.push("position");
+        }
+        if self.chunk != other.chunk {
+            mismatches.push("chunk");
+        }
+        if self.inventory != other.inventory {
+            mismatches.push("inventory");
+        }
+        mismatches
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [x, y, z] = self.translation;
+        let [cx, cy, cz] = self.chunk;
+        write!(
+            f,
+            "at {x:.3} {y:.3} {z:.3} in chunk {cx} {cy} {cz}, inventory {:016x}",
+            self.inventory
+        )
+    }
+}
+
+// Seconds are since the first frame of the recording
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ReplayFrame {
+    Message {
+        at: f32,
+        message: ServerMessage,
+    },
+    // Only written when something changed, the last one holds until the next
+    Input {
+        at: f32,
+        pressed: u32,
+        // Summed LookDelta, in counts
+        mouse: [f32; 2],
+    },
+    Checkpoint {
+        at: f32,
+        checkpoint: Checkpoint,
+    },
+    End(ReplayFooter),
+}
+
+impl ReplayFrame {
+    pub fn at(&self) -> f32 {
+        match self {
+            ReplayFrame::Message { at, .. }
+            | ReplayFrame::Input { at, .. }
+            | ReplayFrame::Checkpoint { at, .. } => *at,
+            ReplayFrame::End(footer) => footer.duration,
+        }
+    }
+}
+
+// One bit per action that's held, in the order they're declared
+pub fn input_digest(action_state: &ActionState<GameActions>) -> u32 {
+    GameActions::variants()
+        .enumerate()
+        .filter(|(_, action)| action_state.pressed(*action))
+        .fold(0, |digest, (bit, _)| digest | 1 << bit)
+}
+
+// Magic and format version, then the header and every frame after it as a little endian
+// length and the bincode of it
+pub fn write_header(out: &mut impl Write, header: &ReplayHeader) -> io::Result<()> {
+    out.write_all(REPLAY_MAGIC)?;
+    out.write_all(&REPLAY_VERSION.to_le_bytes())?;
+    write_frame(out, header)
+}
+
+pub fn write_frame(out: &mut impl Write, frame: &impl Serialize) -> io::Result<()> {
+    let bytes =
+        bincode::serialize(frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    out.write_all(&bytes)
+}
+
+// None at the end of the file or wherever it got cut off
+fn read_frame(input: &mut impl Read) -> Option<Vec<u8>> {
+    let mut len = [0; 4];
+    input.read_exact(&mut len).ok()?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_FRAME_BYTES {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    input
+        .by_ref()
+        .take(len as u64)
+        .read_to_end(&mut bytes)
+        .ok()?;
+    (bytes.len() == len as usize).then_some(bytes)
+}
+
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub frames: Vec<ReplayFrame>,
+    // Missing when the game crashed or quit without stopping the recording
+    pub footer: Option<ReplayFooter>,
+}
+
+impl Replay {
+    pub fn truncated(&self) -> bool {
+        self.footer.is_none()
+    }
+}
+
+// A file that stops partway still plays up to the last whole frame
+pub fn read_replay(mut input: impl Read) -> Result<Replay, ReplayError> {
+    let mut magic = [0; 4];
+    input
+        .read_exact(&mut magic)
+        .map_err(|_| ReplayError::NotAReplay)?;
+    if &magic != REPLAY_MAGIC {
+        return Err(ReplayError::NotAReplay);
+    }
+    let mut version = [0; 4];
+    input
+        .read_exact(&mut version)
+        .map_err(|_| ReplayError::Corrupt("the header is cut off".to_string()))?;
+    let version = u32::from_le_bytes(version);
+    if version != REPLAY_VERSION {
+        return Err(ReplayError::Version { found: version });
+    }
+    let header: ReplayHeader = read_frame(&mut input)
+        .and_then(|bytes| bincode::deserialize(&bytes).ok())
+        .ok_or_else(|| ReplayError::Corrupt("the header is cut off".to_string()))?;
+    if header.protocol != PROTOCOL_VERSION {
+        return Err(ReplayError::Protocol {
+            found: header.protocol,
+        });
+    }
+    let mut frames = Vec::new();
+    let mut footer = None;
+    while let Some(bytes) = read_frame(&mut input) {
+        let Ok(frame) = bincode::deserialize::<ReplayFrame>(&bytes) else {
+            break;
+        };
+        if let ReplayFrame::End(end) = frame {
+            if end.content_hash != canonical_hash(&header.content) {
+                return Err(ReplayError::Corrupt(
+                    "the footer doesn't match the header".to_string(),
+                ));
+            }
+            footer = Some(end);
+            break;
+        }
+        frames.push(frame);
+    }
+    Ok(Replay {
+        header,
+        frames,
+        footer,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Divergence {
+    pub at: f32,
+    pub recorded: Checkpoint,
+    pub replayed: Checkpoint,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Diverged at {:.1}s ({}):\n  recorded {}\n  replayed {}",
+            self.at,
+            self.recorded.mismatches(&self.replayed).join(", "),
+            self.recorded,
+            self.replayed
+        )
+    }
+}
+
+// Pairs them up in order, the replay may not have reached every recorded one
+pub fn first_divergence(
+    recorded: &[(f32, Checkpoint)],
+    replayed: &[Checkpoint],
+) -> Option<Divergence> {
+    recorded
+        .iter()
+        .zip(replayed)
+        .find(|((_, recorded), replayed)| !recorded.mismatches(replayed).is_empty())
+        .map(|((at, recorded), replayed)| Divergence {
+            at: *at,
+            recorded: *recorded,
+            replayed: *replayed,
+        })
+}
+
+// Frames go to a thread that owns the file so the game never waits on the disk. Dropping it
+// writes the footer, the thread flushes once the channel closes
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    pub path: PathBuf,
+    frames: UnboundedSender<ReplayFrame>,
+    started: Instant,
+    content_hash: u64,
+    last_input: Option<u32>,
+    last_checkpoint: Option<f32>,
+}
+
+impl ReplayRecorder {
+    pub fn start(path: PathBuf, header: &ReplayHeader) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut out = BufWriter::new(File::create(&path)?);
+        write_header(&mut out, header)?;
+        let (frames, mut rx) = unbounded_channel();
+        std::thread::spawn(move || {
+            while let Some(frame) = rx.blocking_recv() {
+                if write_frame(&mut out, &frame).is_err() {
+                    break;
+                }
+            }
+            out.flush().ok();
+        });
+        Ok(Self {
+            path,
+            frames,
+            started: Instant::now(),
+            content_hash: canonical_hash(&header.content),
+            last_input: None,
+            last_checkpoint: None,
+        })
+    }
+
+    pub fn at(&self) -> f32 {
+        self.started.elapsed().as_secs_f32()
+    }
+
+    fn record(&self, frame: ReplayFrame) {
+        self.frames.send(frame).ok();
+    }
+
+    pub fn message(&self, message: &ServerMessage) {
+        self.record(ReplayFrame::Message {
+            at: self.at(),
+            message: message.clone(),
+        });
+    }
+}
+
+impl Drop for ReplayRecorder {
+    fn drop(&mut self) {
+        self.record(ReplayFrame::End(ReplayFooter {
+            duration: self.at(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            content_hash: self.content_hash,
+        }));
+    }
+}
+
+// Offline stand in for the server, NetClient sends nothing and only hands out what this feeds it
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    pub path: PathBuf,
+    pub replay: Replay,
+    speed: f32,
+    next: usize,
+    started: Option<f64>,
+    pressed: u32,
+    recorded: Vec<(f32, Checkpoint)>,
+    replayed: Vec<Checkpoint>,
+    reported: bool,
+    finished: bool,
+    // A chunk dump rather than a recording, see chunk_dump
+    inspecting: bool,
+}
+
+impl ReplayPlayback {
+    pub fn open(path: PathBuf, speed: f32) -> Result<Self, ReplayError> {
+        let file = File::open(&path).map_err(|e| ReplayError::Io(e.to_string()))?;
+        Ok(Self::new(
+            path,
+            read_replay(io::BufReader::new(file))?,
+            speed,
+        ))
+    }
+
+    pub fn new(path: PathBuf, replay: Replay, speed: f32) -> Self {
+        Self {
+            path,
+            replay,
+            speed: speed.max(0.01),
+            next: 0,
+            started: None,
+            pressed: 0,
+            recorded: Vec::new(),
+            replayed: Vec::new(),
+            reported: false,
+            finished: false,
+            inspecting: false,
+        }
+    }
+
+    // Nothing was recorded to compare against, so any content will do and the mouse and
+    // keyboard stay ours
+    pub fn inspect(path: PathBuf, replay: Replay) -> Self {
+        Self {
+            inspecting: true,
+            ..Self::new(path, replay, 1.0)
+        }
+    }
+
+    // Stands in for the join, content that isn't what it was recorded with can't replay the same
+    pub fn join(&self, local: &ContentManifest) -> ConnectionPhase {
+        let diff = ContentDiff::between(&self.replay.header.content, local);
+        if diff.is_empty() || self.inspecting {
+            ConnectionPhase::Joined
+        } else {
+            println!("Replay content differs from ours: {diff:?}");
+            ConnectionPhase::Failed(ConnectionFailure::ReplayContent(diff))
+        }
+    }
+
+    pub fn client_id(&self) -> u64 {
+        self.replay.header.client_id
+    }
+
+    // Everything recorded up to `now` seconds into playback, sped up or slowed down
+    pub fn advance(&mut self, now: f32) -> Vec<ReplayFrame> {
+        let until = now * self.speed;
+        let start = self.next;
+        while self
+            .replay
+            .frames
+            .get(self.next)
+            .is_some_and(|frame| frame.at() <= until)
+        {
+            self.next += 1;
+        }
+        self.replay.frames[start..self.next].to_vec()
+    }
+
+    pub fn done(&self) -> bool {
+        self.next >= self.replay.frames.len()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayCommand {
+    // None names it after the time it started
+    Start(Option<String>),
+    Stop,
+}
+
+pub struct ReplayEvent(pub ReplayCommand);
+
+const USAGE: &str = "Usage: /replay start [name] or /replay stop";
+
+// None when the line isn't a /replay at all, otherwise the command or what was wrong with it
+pub fn parse_replay(line: &str) -> Option<Result<ReplayCommand, String>> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("/replay") {
+        return None;
+    }
+    let args: Vec<&str> = words.collect();
+    Some(match args.as_slice() {
+        ["start"] => Ok(ReplayCommand::Start(None)),
+        ["start", name] if valid_name(name) => Ok(ReplayCommand::Start(Some(name.to_string()))),
+        ["start", _] => Err("Replay names are letters, digits, - and _".to_string()),
+        ["stop"] => Ok(ReplayCommand::Stop),
+        _ => Err(USAGE.to_string()),
+    })
+}
+
+pub fn replays_dir(project_path: &ProjectPath) -> PathBuf {
+    project_path
+        .0
+        .parent()
+        .unwrap_or(&project_path.0)
+        .join("replays")
+}
+
+pub fn replay_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.{REPLAY_EXTENSION}"))
+}
+
+fn start_recording(
+    name: Option<&str>,
+    project_path: &ProjectPath,
+    client_id: u64,
+    content: &ContentManifest,
+) -> io::Result<ReplayRecorder> {
+    let name = name.map(str::to_string).unwrap_or_else(|| {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+        format!("replay-{seconds}")
+    });
+    let header = ReplayHeader {
+        protocol: PROTOCOL_VERSION,
+        client_id,
+        content: content.clone(),
+    };
+    ReplayRecorder::start(replay_path(&replays_dir(project_path), &name), &header)
+}
+
+// Asked for with --record [name], so the recording has the world from the moment we joined
+#[derive(Resource, Debug, Default)]
+pub struct RecordOnJoin(pub Option<String>);
+
+pub fn record_on_join(
+    mut commands: Commands,
+    record: Option<Res<RecordOnJoin>>,
+    playback: Option<Res<ReplayPlayback>>,
+    (client_data, content, project_path): (Res<ClientData>, Res<ContentManifest>, Res<ProjectPath>),
+) {
+    let (Some(record), None) = (record, playback) else {
+        return;
+    };
+    match start_recording(record.0.as_deref(), &project_path, **client_data, &content) {
+        Ok(recorder) => {
+            println!("Recording to {}", recorder.path.display());
+            commands.insert_resource(recorder);
+        }
+        Err(e) => println!("Couldn't start recording: {e}"),
+    }
+}
+
+pub fn handle_replay(
+    mut commands: Commands,
+    mut events: EventReader<ReplayEvent>,
+    mut messages: ResMut<ChatMessages>,
+    (recorder, playback): (Option<Res<ReplayRecorder>>, Option<Res<ReplayPlayback>>),
+    (client_data, content, project_path): (Res<ClientData>, Res<ContentManifest>, Res<ProjectPath>),
+) {
+    for ReplayEvent(command) in events.iter() {
+        let reply = match (command, &recorder) {
+            _ if playback.is_some() => "Can't record while watching a replay".to_string(),
+            (ReplayCommand::Start(_), Some(recorder)) => {
+                format!("Already recording to {}", recorder.path.display())
+            }
+            (ReplayCommand::Start(name), None) => {
+                match start_recording(name.as_deref(), &project_path, **client_data, &content) {
+                    // What's already loaded isn't in it, --record catches the whole session
+                    Ok(recorder) => {
+                        let reply = format!(
+                            "Recording to {}, chunks loaded before now aren't in it",
+                            recorder.path.display()
+                        );
+                        commands.insert_resource(recorder);
+                        reply
+                    }
+                    Err(e) => format!("Couldn't start recording: {e}"),
+                }
+            }
+            (ReplayCommand::Stop, Some(recorder)) => {
+                commands.remove_resource::<ReplayRecorder>();
+                format!(
+                    "Saved {:.0}s of replay to {}",
+                    recorder.at(),
+                    recorder.path.display()
+                )
+            }
+            (ReplayCommand::Stop, None) => "Not recording".to_string(),
+        };
+        messages.push(ChatLine::console(reply));
+    }
+}
+
+// Runs right after the input manager reads the devices, so the same point in the frame gets
+// recorded and played back
+#[allow(clippy::type_complexity)]
+pub fn record_frame(
+    recorder: Option<ResMut<ReplayRecorder>>,
+    player: Query<(&ActionState<GameActions>, &Transform, &Inventory), With<ControlledPlayer>>,
+    mut look: EventReader<LookDelta>,
+    player_chunk: Res<PlayerChunk>,
+    offset: Res<WorldOffset>,
+) {
+    let delta = look
+        .iter()
+        .fold(Vec2::ZERO, |sum, LookDelta(delta)| sum + *delta);
+    let (Some(mut recorder), Ok((action_state, transform, inventory))) =
+        (recorder, player.get_single())
+    else {
+        return;
+    };
+    let at = recorder.at();
+    let pressed = input_digest(action_state);
+    if recorder.last_input != Some(pressed) || delta != Vec2::ZERO {
+        recorder.last_input = Some(pressed);
+        recorder.record(ReplayFrame::Input {
+            at,
+            pressed,
+            mouse: delta.to_array(),
+        });
+    }
+    if recorder
+        .last_checkpoint
+        .is_none_or(|last| at - last >= CHECKPOINT_SECONDS)
+    {
+        recorder.last_checkpoint = Some(at);
+        recorder.record(ReplayFrame::Checkpoint {
+            at,
+            checkpoint: Checkpoint::capture(transform, &player_chunk, inventory, &offset),
+        });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn play_frames(
+    playback: Option<ResMut<ReplayPlayback>>,
+    mut pending: ResMut<PendingMessages>,
+    mut player: Query<
+        (&mut ActionState<GameActions>, &Transform, &Inventory),
+        With<ControlledPlayer>,
+    >,
+    mut look: ResMut<Events<LookDelta>>,
+    mut messages: ResMut<ChatMessages>,
+    (player_chunk, offset, time): (Res<PlayerChunk>, Res<WorldOffset>, Res<Time>),
+) {
+    let Some(mut playback) = playback else {
+        return;
+    };
+    // The real mouse and keyboard don't get a say
+    if !playback.inspecting {
+        look.clear();
+    }
+    let now = time.raw_elapsed_seconds_f64();
+    let started = *playback.started.get_or_insert(now);
+    let mut player = player.get_single_mut().ok();
+    for frame in playback.advance((now - started) as f32) {
+        match frame {
+            ReplayFrame::Message { message, .. } => pending.push_back(message),
+            ReplayFrame::Input {
+                pressed,
+                mouse: delta,
+                ..
+            } => {
+                playback.pressed = pressed;
+                look.send(LookDelta(Vec2::from_array(delta)));
+            }
+            ReplayFrame::Checkpoint { at, checkpoint } => {
+                let Some((_, transform, inventory)) = &player else {
+                    continue;
+                };
+                let replayed = Checkpoint::capture(transform, &player_chunk, inventory, &offset);
+                playback.recorded.push((at, checkpoint));
+                playback.replayed.push(replayed);
+                if !playback.reported {
+                    if let Some(divergence) =
+                        first_divergence(&playback.recorded, &playback.replayed)
+                    {
+                        playback.reported = true;
+                        println!("{divergence}");
+                        messages.push(ChatLine::console(divergence.to_string()));
+                    }
+                }
+            }
+            ReplayFrame::End(_) => {}
+        }
+    }
+    if playback.inspecting {
+        return;
+    }
+    if let Some((action_state, _, _)) = &mut player {
+        for (bit, action) in GameActions::variants().enumerate() {
+            if playback.pressed & 1 << bit != 0 {
+                action_state.press(action);
+            } else {
+                action_state.release(action);
+            }
+        }
+    }
+    if playback.done() && !playback.finished {
+        playback.finished = true;
+        let mut summary = format!(
+            "Replay finished, {} checkpoints compared",
+            playback.replayed.len()
+        );
+        if !playback.reported {
+            summary.push_str(", all matched");
+        }
+        if playback.replay.truncated() {
+            summary.push_str(". The file was cut off, it ends early");
+        }
+        println!("{summary}");
+        messages.push(ChatLine::console(summary));
+    }
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let args: Vec<String> = env::args().collect();
+        let value = |flag: &str| {
+            let idx = args.iter().position(|arg| arg == flag)?;
+            args.get(idx + 1).filter(|value| !value.starts_with("--"))
+        };
+        if let Some(path) = value("--replay") {
+            let speed = value("--replay-speed")
+                .and_then(|speed| speed.parse().ok())
+                .unwrap_or(1.0);
+            match ReplayPlayback::open(PathBuf::from(path), speed) {
+                Ok(playback) => {
+                    if playback.replay.truncated() {
+                        println!("{path} was cut off, it plays up to where it stops");
+                    }
+                    app.insert_resource(playback)
+                        .insert_resource(NextState(Some(GameState::Loading)));
+                }
+                Err(e) => {
+                    eprintln!("Couldn't play {path}: {e}");
+                    std::process::exit(1);
+                }
+            }
+        } else if args.iter().any(|arg| arg == "--record") {
+            app.insert_resource(RecordOnJoin(
+                value("--record").filter(|name| valid_name(name)).cloned(),
+            ));
+        }
+
+        app.add_event::<ReplayEvent>()
+            // Stopping by leaving the game finishes the file like /replay stop does, and a
+            // replay only plays once
+            .on_session_end(|world| {
+                world.remove_resource::<ReplayRecorder>();
+                world.remove_resource::<ReplayPlayback>();
+            })
+            .add_system(record_on_join.in_schedule(OnEnter(GameState::Game)))
+            .add_system(
+                handle_replay
+                    .in_set(GameSet::Networking)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_systems(
+                (play_frames, record_frame)
+                    .chain()
+                    .in_base_set(CoreSet::PreUpdate)
+                    .in_set(InputManagerSystem::ManualControl)
+                    .distributive_run_if(in_state(GameState::Game)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> ReplayHeader {
+        ReplayHeader {
+            protocol: PROTOCOL_VERSION,
+            client_id: 7,
+            content: ContentManifest::default(),
+        }
+    }
+
+    fn checkpoint(x: f64, inventory: u64) -> Checkpoint {
+        Checkpoint {
+            translation: [x, 64.0, 0.0],
+            chunk: [0, 2, 0],
+            inventory,
+        }
+    }
+
+    fn recording(frames: &[ReplayFrame]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, &header()).unwrap();
+        for frame in frames {
+            write_frame(&mut bytes, frame).unwrap();
+        }
+        bytes
+    }
+
+    fn frames() -> Vec<ReplayFrame> {
+        vec![
+            ReplayFrame::Message {
+                at: 0.0,
+                message: ServerMessage::ChatMessage {
+                    user_name: "Console".to_string(),
+                    message: "hello".to_string(),
+                    id: 0,
+                    category: Default::default(),
+                },
+            },
+            ReplayFrame::Input {
+                at: 0.016,
+                pressed: 0b101,
+                mouse: [1.5, -2.0],
+            },
+            ReplayFrame::Checkpoint {
+                at: 1.0,
+                checkpoint: checkpoint(3.25, 42),
+            },
+            ReplayFrame::End(ReplayFooter {
+                duration: 1.5,
+                version: "0.1.0".to_string(),
+                content_hash: canonical_hash(&ContentManifest::default()),
+            }),
+        ]
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let replay = read_replay(recording(&frames()).as_slice()).unwrap();
+        assert_eq!(replay.header, header());
+        assert!(!replay.truncated());
+        assert_eq!(replay.footer.as_ref().unwrap().duration, 1.5);
+        assert_eq!(replay.frames.len(), 3);
+        assert!(matches!(
+            &replay.frames[0],
+            ReplayFrame::Message {
+                message: ServerMessage::ChatMessage { message, .. },
+                ..
+            } if message == "hello"
+        ));
+        assert!(matches!(
+            replay.frames[1],
+            ReplayFrame::Input {
+                pressed: 0b101,
+                mouse: [x, y],
+                ..
+            } if x == 1.5 && y == -2.0
+        ));
+        assert!(matches!(
+            replay.frames[2],
+            ReplayFrame::Checkpoint { at, checkpoint: c } if at == 1.0 && c == checkpoint(3.25, 42)
+        ));
+
+        // Playback hands frames out as their time comes, faster with a higher speed
+        let mut playback = ReplayPlayback::new(PathBuf::new(), replay, 2.0);
+        assert_eq!(playback.advance(0.0).len(), 1);
+        assert_eq!(playback.advance(0.25).len(), 1);
+        assert!(!playback.done());
+        assert_eq!(playback.advance(0.5).len(), 1);
+        assert!(playback.done());
+    }
+
+    #[test]
+    fn the_first_mismatching_checkpoint_is_reported() {
+        let recorded = vec![
+            (1.0, checkpoint(0.0, 1)),
+            (2.0, checkpoint(1.0, 1)),
+            (3.0, checkpoint(2.0, 2)),
+            (4.0, checkpoint(3.0, 2)),
+        ];
+        // Drifting under the tolerance is fine
+        let replayed = [
+            checkpoint(0.01, 1),
+            checkpoint(1.02, 1),
+            checkpoint(2.0, 3),
+            checkpoint(9.0, 2),
+        ];
+        let divergence = first_divergence(&recorded, &replayed).unwrap();
+        assert_eq!(divergence.at, 3.0);
+        assert_eq!(divergence.recorded, checkpoint(2.0, 2));
+        assert_eq!(divergence.replayed, checkpoint(2.0, 3));
+        assert_eq!(
+            divergence.recorded.mismatches(&divergence.replayed),
+            ["inventory"]
+        );
+        let report = divergence.to_string();
+        assert!(report.contains("inventory"));
+        assert!(report.contains("0000000000000002") && report.contains("0000000000000003"));
+
+        assert_eq!(first_divergence(&recorded, &replayed[..2]), None);
+        let mut moved = checkpoint(0.0, 1);
+        moved.chunk = [1, 2, 0];
+        assert_eq!(checkpoint(0.0, 1).mismatches(&moved), ["chunk"]);
+    }
+
+    #[test]
+    fn truncated_files_play_up_to_the_cut() {
+        let bytes = recording(&frames());
+        // Without the footer, partway through the checkpoint, and partway through its length
+        let footer_start = recording(&frames()[..3]).len();
+        for cut in [footer_start, footer_start - 5, footer_start - 54] {
+            let replay = read_replay(&bytes[..cut]).unwrap();
+            assert!(replay.truncated());
+            assert!(replay.frames.len() <= 3);
+        }
+        let replay = read_replay(&bytes[..footer_start - 5]).unwrap();
+        assert_eq!(replay.frames.len(), 2);
+
+        // A header that's cut off or isn't ours at all can't play
+        let header_len = recording(&[]).len();
+        assert!(matches!(
+            read_replay(&bytes[..header_len - 1]),
+            Err(ReplayError::Corrupt(_))
+        ));
+        assert_eq!(
+            read_replay(&b"VRP"[..]).err(),
+            Some(ReplayError::NotAReplay)
+        );
+        assert_eq!(
+            read_replay(&b"VSCH\x01\0\0\0"[..]).err(),
+            Some(ReplayError::NotAReplay)
+        );
+        let mut newer = bytes.clone();
+        newer[4] = 9;
+        assert_eq!(
+            read_replay(newer.as_slice()).err(),
+            Some(ReplayError::Version { found: 9 })
+        );
+        // A length no frame could have is a tear, not an allocation
+        let mut torn = recording(&frames()[..1]);
+        torn.extend(u32::MAX.to_le_bytes());
+        torn.extend([0; 16]);
+        let replay = read_replay(torn.as_slice()).unwrap();
+        assert!(replay.truncated());
+        assert_eq!(replay.frames.len(), 1);
+    }
+
+    #[test]
+    fn replay_commands() {
+        assert_eq!(parse_replay("/schem list"), None);
+        assert_eq!(
+            parse_replay("/replay start"),
+            Some(Ok(ReplayCommand::Start(None)))
+        );
+        assert_eq!(
+            parse_replay("/replay start desync_1"),
+            Some(Ok(ReplayCommand::Start(Some("desync_1".to_string()))))
+        );
+        assert_eq!(parse_replay("/replay stop"), Some(Ok(ReplayCommand::Stop)));
+        assert!(matches!(parse_replay("/replay start ../x"), Some(Err(_))));
+        assert!(matches!(parse_replay("/replay"), Some(Err(_))));
+    }
+}
@@ -1,14 +1,40 @@
-use super::components::{ChatMessages, ClientData, ClientLobby, NetworkMapping, PlayerInfo};
+use super::{
+    chunk_cache::ChunkCache,
+    components::{
+        Capabilities, ChatLine, ChatMessages, ClientData, ClientLobby, ContentReport,
+        NetworkMapping, PlayerInfo, ServerStatus,
+    },
+    connection::NetClient,
+};
 use crate::states::{
-    components::{GameActions, GameOptions},
+    components::{GameActions, GameOptions, SessionScoped},
     game::{
+        input::{
+            arrange::ArrangeSyncEvent,
+            denied::BlockDeniedEvent,
+            drop::{DropResultEvent, PickedUpEvent},
+            player::TeleportEvent,
+            seat::SeatedEvent,
+            template::TemplateEvent,
+            tools::{RenameHeldEvent, ToolWornEvent},
+        },
         rendering::meshing::BasicMaterial,
-        ui::dropdown::Toast,
-        world::chunks::{ControlledPlayer, CreateChunkEvent, SetBlockEvent},
+        ui::{
+            crafting::{CraftResultEvent, RecipesUnlockedEvent},
+            hud::StatsUpdateEvent,
+            palette::GiveStackEvent,
+        },
+        world::{
+            chunks::{
+                ChangeDimensionEvent, ChunkBiomeMap, ControlledPlayer, CreateChunkEvent,
+                SetBlockEvent, UnloadChunkEvent,
+            },
+            critters::EntityCreateEvent,
+            remote_players::RemotePose,
+        },
     },
 };
 use bevy::prelude::*;
-use bevy_quinnet::client::*;
 use bevy_tweening::{
     lens::{TransformPositionLens, TransformRotationLens},
     *,
@@ -16,66 +42,131 @@ use bevy_tweening::{
 use leafwing_input_manager::prelude::*;
 use std::{io::Cursor, time::Duration};
 use vinox_common::{
-    ecs::bundles::PlayerBundleBuilder,
-    networking::protocol::{ClientMessage, EntityBuffer, ServerMessage},
-    physics::simulate::{CollidesWithWorld, Velocity},
-    world::chunks::storage::RawChunk,
+    ecs::{
+        bundles::{Health, Hunger, PlayerBundleBuilder},
+        gameplay::GameplayRules,
+    },
+    networking::protocol::{
+        ChatCategory, ClientMessage, EntityBuffer, LeftHanded, PlayerFlags, ServerMessage,
+    },
+    physics::{
+        movement::MovementState,
+        simulate::{CollidesWithWorld, Velocity},
+    },
+    storage::content::MISSING_BLOCK,
+    world::chunks::{
+        positions::{voxel_to_global_voxel, WorldOffset},
+        storage::{name_to_identifier, BlockData, BlockTable, RawChunk},
+    },
 };
 use zstd::stream::copy_decode;
 
 #[derive(Component)]
 pub struct HighLightCube;
 
-pub fn get_id(
-    mut client: ResMut<Client>,
-    mut client_data: ResMut<ClientData>,
-    mut has_connected: Local<bool>,
-    options: Res<GameOptions>,
-) {
-    if *has_connected {
-    } else {
-        while let Some(message) = client
-            .connection_mut()
-            .try_receive_message::<ServerMessage>()
-        {
-            if let ServerMessage::ClientId { id } = message {
-                **client_data = id;
-                client
-                    .connection_mut()
-                    .try_send_message(ClientMessage::Join {
-                        user_name: options.user_name.clone(),
-                        id,
-                    });
-                *has_connected = true;
-            }
-        }
+pub const HIGHLIGHT_COLOR: Color = Color::rgba(1.1, 1.1, 1.1, 1.0);
+
+// Stands in for blocks the server has and we don't, only when it let us in with them
+pub fn missing_block() -> BlockData {
+    BlockData::new(MISSING_BLOCK.0.to_string(), MISSING_BLOCK.1.to_string())
+}
+
+// What LevelData carries as a chunk, None when it doesn't decode
+pub fn level_chunk(
+    payload: &[u8],
+    content: &ContentReport,
+    block_table: &BlockTable,
+) -> Option<RawChunk> {
+    let mut temp_output = Cursor::new(Vec::new());
+    copy_decode(payload, &mut temp_output).ok()?;
+    let mut level_data: RawChunk = bincode::deserialize(temp_output.get_ref()).ok()?;
+    if content.missing_blocks() {
+        level_data.replace_unknown(block_table, &missing_block());
     }
+    Some(level_data)
+}
+
+// What UniformChunk carries as a chunk
+pub fn uniform_chunk(
+    block: BlockData,
+    content: &ContentReport,
+    block_table: &BlockTable,
+) -> RawChunk {
+    let identifier = name_to_identifier(block.namespace.clone(), block.name.clone());
+    if content.missing_blocks() && !block_table.contains_key(&identifier) {
+        return RawChunk::uniform(missing_block(), block_table);
+    }
+    RawChunk::uniform(block, block_table)
 }
 
 #[allow(clippy::clone_on_copy)]
 #[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
 pub fn get_messages(
     mut cmd1: Commands,
     mut cmd2: Commands,
-    mut client: ResMut<Client>,
-    (client_data, options): (Res<ClientData>, Res<GameOptions>),
+    mut client: NetClient,
+    (client_data, options, mut capabilities, mut server_status, offset, mut rules): (
+        Res<ClientData>,
+        Res<GameOptions>,
+        ResMut<Capabilities>,
+        ResMut<ServerStatus>,
+        Res<WorldOffset>,
+        ResMut<GameplayRules>,
+    ),
+    (content, block_table, mut chunk_cache, mut chunk_biomes): (
+        Res<ContentReport>,
+        Res<BlockTable>,
+        ResMut<ChunkCache>,
+        ResMut<ChunkBiomeMap>,
+    ),
     mut lobby: ResMut<ClientLobby>,
     mut network_mapping: ResMut<NetworkMapping>,
     mut entity_buffer: ResMut<EntityBuffer>,
     player_builder: Res<PlayerBundleBuilder>,
-    mut chunk_event: EventWriter<CreateChunkEvent>,
-    mut block_event: EventWriter<SetBlockEvent>,
+    (mut chunk_event, mut unload_event): (
+        EventWriter<CreateChunkEvent>,
+        EventWriter<UnloadChunkEvent>,
+    ),
+    (mut block_event, mut denied_event, mut arrange_event, mut seated_event, mut template_event): (
+        EventWriter<SetBlockEvent>,
+        EventWriter<BlockDeniedEvent>,
+        EventWriter<ArrangeSyncEvent>,
+        EventWriter<SeatedEvent>,
+        EventWriter<TemplateEvent>,
+    ),
+    (
+        mut entity_event,
+        mut dimension_event,
+        mut stats_event,
+        mut stack_event,
+        mut unlocked_event,
+        mut craft_event,
+        mut drop_event,
+        mut pickup_event,
+        mut teleport_event,
+        mut tool_worn_event,
+        mut rename_event,
+    ): (
+        EventWriter<EntityCreateEvent>,
+        EventWriter<ChangeDimensionEvent>,
+        EventWriter<StatsUpdateEvent>,
+        EventWriter<GiveStackEvent>,
+        EventWriter<RecipesUnlockedEvent>,
+        EventWriter<CraftResultEvent>,
+        EventWriter<DropResultEvent>,
+        EventWriter<PickedUpEvent>,
+        EventWriter<TeleportEvent>,
+        EventWriter<ToolWornEvent>,
+        EventWriter<RenameHeldEvent>,
+    ),
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<BasicMaterial>>,
     asset_server: Res<AssetServer>,
     mut messages: ResMut<ChatMessages>,
-    mut toast: ResMut<Toast>,
 ) {
     if **client_data != 0 {
-        while let Some(message) = client
-            .connection_mut()
-            .try_receive_message::<ServerMessage>()
-        {
+        while let Some(message) = client.receive() {
             match message {
                 ServerMessage::PlayerCreate {
                     id,
@@ -87,13 +178,15 @@ pub fn get_messages(
                     init,
                     inventory,
                 } => {
-                    let mut client_entity = cmd1.spawn_empty();
+                    let world_translation = translation;
+                    let translation = offset.to_render(translation);
+                    let mut client_entity = cmd1.spawn(SessionScoped);
                     if **client_data == id {
                         println!("You connected.");
                         cmd2.spawn(MaterialMeshBundle {
                             mesh: meshes.add(Mesh::from(shape::Cube { size: 1.001 })),
                             material: materials.add(BasicMaterial {
-                                color: Color::rgba(1.1, 1.1, 1.1, 1.0),
+                                color: HIGHLIGHT_COLOR,
                                 color_texture: Some(asset_server.load("outline.png")),
                                 alpha_mode: AlphaMode::Blend,
                                 discard_pix: 0,
@@ -103,7 +196,7 @@ pub fn get_messages(
                             ),
                             ..default()
                         })
-                        .insert(HighLightCube);
+                        .insert((HighLightCube, SessionScoped));
 
                         client_entity
                             .insert(player_builder.build(
@@ -119,12 +212,22 @@ pub fn get_messages(
                             })
                             .insert(*inventory)
                             .insert(CollidesWithWorld)
-                            .insert(Velocity(Vec3::ZERO));
+                            .insert(Velocity(Vec3::ZERO))
+                            .insert(Health::default())
+                            .insert(Hunger::default())
+                            .insert(MovementState::default())
+                            .insert(PlayerFlags::default());
+                        // Spawning can be anywhere, this rebases onto it first thing
+                        teleport_event.send(TeleportEvent {
+                            translation: world_translation,
+                        });
                     } else {
                         if init {
-                            toast
-                                .basic(format!("Player {user_name} connected."))
-                                .set_duration(Some(Duration::from_secs(3)));
+                            messages.push(ChatLine::new(
+                                "",
+                                format!("Player {user_name} connected."),
+                                ChatCategory::System,
+                            ));
                         }
                         client_entity.insert(player_builder.build(
                             translation,
@@ -137,7 +240,8 @@ pub fn get_messages(
                                 Transform::from_translation(translation)
                                     .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.0, yaw, 0.0)),
                             )
-                            .insert(*inventory);
+                            .insert(*inventory)
+                            .insert(RemotePose::default());
                     }
 
                     let player_info = PlayerInfo {
@@ -162,41 +266,166 @@ pub fn get_messages(
                     chunk_pos,
                     voxel_pos,
                     block_type,
-                } => block_event.send(SetBlockEvent {
-                    chunk_pos,
-                    voxel_pos: UVec3::new(
+                    dimension,
+                    denied,
+                } => {
+                    let voxel_pos = UVec3::new(
                         voxel_pos[0] as u32,
                         voxel_pos[1] as u32,
                         voxel_pos[2] as u32,
-                    ),
-                    block_type,
-                }),
+                    );
+                    if let Some(reason) = denied {
+                        denied_event.send(BlockDeniedEvent {
+                            voxel: voxel_to_global_voxel(voxel_pos, chunk_pos),
+                            reason,
+                        });
+                    }
+                    block_event.send(SetBlockEvent {
+                        chunk_pos,
+                        voxel_pos,
+                        block_type: if block_table.contains_key(&name_to_identifier(
+                            block_type.namespace.clone(),
+                            block_type.name.clone(),
+                        )) {
+                            block_type
+                        } else {
+                            missing_block()
+                        },
+                        dimension,
+                    });
+                }
                 ServerMessage::NetworkedEntities { networked_entities } => {
                     let arr_len = entity_buffer.entities.len() - 1;
                     entity_buffer.entities.rotate_left(1);
                     entity_buffer.entities[arr_len] = networked_entities;
                 }
-                ServerMessage::LevelData { chunk_data, pos } => {
-                    let mut temp_output = Cursor::new(Vec::new());
-                    copy_decode(&chunk_data[..], &mut temp_output).unwrap();
-                    let level_data: RawChunk = bincode::deserialize(temp_output.get_ref()).unwrap();
+                ServerMessage::EntityCreate {
+                    entity,
+                    kind,
+                    translation,
+                    yaw,
+                    item,
+                } => entity_event.send(EntityCreateEvent {
+                    entity,
+                    kind,
+                    translation: offset.to_render(translation),
+                    yaw,
+                    item,
+                }),
+                ServerMessage::EntityRemove { entity } => {
+                    if let Some(client_entity) = network_mapping.remove(&entity) {
+                        cmd1.entity(client_entity).despawn_recursive();
+                    }
+                }
+                ServerMessage::LevelData {
+                    chunk_data,
+                    pos,
+                    dimension,
+                    hash,
+                    biomes,
+                } => {
+                    let Some(level_data) = level_chunk(&chunk_data, &content, &block_table) else {
+                        println!("Couldn't decode chunk {pos}");
+                        continue;
+                    };
+                    chunk_cache.store(dimension, pos, hash, &chunk_data);
+                    chunk_biomes.insert(pos, biomes);
                     chunk_event.send(CreateChunkEvent {
                         raw_chunk: level_data,
                         pos,
+                        dimension,
                     });
                 }
+                // Comes back as a CreateChunkEvent once it's read, see chunk_cache
+                ServerMessage::ChunkStillValid {
+                    pos,
+                    dimension,
+                    biomes,
+                } => {
+                    chunk_biomes.insert(pos, biomes);
+                    if !chunk_cache.load(dimension, pos) {
+                        client.send(ClientMessage::ChunkCacheMiss { pos, dimension });
+                    }
+                }
+                ServerMessage::UniformChunk {
+                    pos,
+                    dimension,
+                    block,
+                    biomes,
+                } => {
+                    chunk_biomes.insert(pos, biomes);
+                    chunk_event.send(CreateChunkEvent {
+                        raw_chunk: uniform_chunk(block, &content, &block_table),
+                        pos,
+                        dimension,
+                    });
+                }
+                ServerMessage::UnloadChunk { pos, dimension } => {
+                    unload_event.send(UnloadChunkEvent { pos, dimension })
+                }
+                ServerMessage::ChangeDimension { dimension } => {
+                    dimension_event.send(ChangeDimensionEvent { dimension })
+                }
+                ServerMessage::PlayerStats { health, hunger } => {
+                    stats_event.send(StatsUpdateEvent { health, hunger })
+                }
+                ServerMessage::Capabilities { creative } => capabilities.creative = creative,
+                ServerMessage::GameplayRules { rules: received } => *rules = received,
+                ServerMessage::GiveStack { item } => stack_event.send(GiveStackEvent { item }),
+                ServerMessage::RecipesUnlocked { ids, announce } => {
+                    unlocked_event.send(RecipesUnlockedEvent { ids, announce })
+                }
+                ServerMessage::CraftResult { recipe, accepted } => {
+                    craft_event.send(CraftResultEvent { recipe, accepted })
+                }
+                ServerMessage::DropResult {
+                    slot,
+                    item,
+                    requested,
+                    dropped,
+                } => drop_event.send(DropResultEvent {
+                    slot,
+                    item,
+                    refund: requested.saturating_sub(dropped),
+                }),
+                ServerMessage::PickedUp { item } => pickup_event.send(PickedUpEvent { item }),
+                ServerMessage::ToolWorn { slot, tool, worn } => {
+                    tool_worn_event.send(ToolWornEvent { slot, tool, worn })
+                }
+                ServerMessage::RenameHeld { name } => rename_event.send(RenameHeldEvent { name }),
+                ServerMessage::InventoryAck { seq } => {
+                    arrange_event.send(ArrangeSyncEvent::Ack { seq })
+                }
+                ServerMessage::ResendInventoryOps { from } => {
+                    arrange_event.send(ArrangeSyncEvent::Resend { from })
+                }
+                ServerMessage::RequestInventoryResync => {
+                    arrange_event.send(ArrangeSyncEvent::Resync)
+                }
+                ServerMessage::InventoryCorrection { inventory } => {
+                    arrange_event.send(ArrangeSyncEvent::Correct(inventory))
+                }
+                ServerMessage::ServerLoad { health } => **server_status = health,
+                ServerMessage::Teleport { translation } => {
+                    teleport_event.send(TeleportEvent { translation })
+                }
+                ServerMessage::Seated { seat } => seated_event.send(SeatedEvent { seat }),
+                ServerMessage::BlockTemplate { block } => {
+                    template_event.send(TemplateEvent { block })
+                }
+                ServerMessage::FlightPermission { fly, noclip } => {
+                    capabilities.fly = fly;
+                    capabilities.noclip = noclip;
+                }
                 ServerMessage::ChatMessage {
                     user_name,
                     message,
                     id,
-                } => {
-                    messages.push((user_name.clone(), message.clone()));
-                    if id != **client_data {
-                        toast
-                            .basic(format!("{user_name}: {message}"))
-                            .set_duration(Some(Duration::from_secs(3)));
-                    }
-                }
+                    category,
+                } => messages.push(ChatLine {
+                    own: id == **client_data,
+                    ..ChatLine::new(user_name, message, category)
+                }),
                 _ => {}
             }
         }
@@ -210,10 +439,11 @@ pub fn lerp_new_location(
     network_mapping: ResMut<NetworkMapping>,
     client_data: ResMut<ClientData>,
     transform_query: Query<&Transform>,
+    offset: Res<WorldOffset>,
 ) {
     for i in 0..entity_buffer.entities[0].entities.len() {
         if let Some(entity) = network_mapping.get(&entity_buffer.entities[0].entities[i]) {
-            let translation = entity_buffer.entities[0].translations[i];
+            let translation = offset.to_render(entity_buffer.entities[0].translations[i]);
             let rotation =
                 Quat::from_euler(EulerRot::XYZ, 0.0, entity_buffer.entities[0].yaws[i], 0.0);
             let transform = Transform {
@@ -248,31 +478,25 @@ pub fn lerp_new_location(
                             .unwrap()
                             .insert(Animator::new(track));
                     }
+                    // Nothing reads it yet, it's there for when remote players swing an arm
+                    let left_handed = entity_buffer.entities[0].left_handed.get(i) == Some(&true);
+                    // What the pose blends toward, see remote_players
+                    let flags = entity_buffer.entities[0]
+                        .flags
+                        .get(i)
+                        .copied()
+                        .unwrap_or_default();
+                    if let Some(mut remote) = commands.get_entity(*entity) {
+                        if left_handed {
+                            remote.insert(LeftHanded);
+                        } else {
+                            remote.remove::<LeftHanded>();
+                        }
+                        remote.insert(flags);
+                    }
                 } else {
                 }
             }
         }
     }
 }
-
-pub fn client_send_naive_position(
-    mut transform_query: Query<&mut Transform, With<ControlledPlayer>>,
-    mut camera_query: Query<&mut Transform, (With<Camera>, Without<ControlledPlayer>)>,
-    mut client: ResMut<Client>,
-) {
-    if let Ok(transform) = transform_query.get_single_mut() {
-        if let Ok(camera_transform) = camera_query.get_single_mut() {
-            client
-                .connection_mut()
-                .send_message_on(
-                    bevy_quinnet::shared::channel::ChannelId::Unreliable,
-                    ClientMessage::Position {
-                        player_pos: transform.translation,
-                        yaw: camera_transform.rotation.to_euler(EulerRot::XYZ).1,
-                        head_pitch: camera_transform.rotation.to_euler(EulerRot::XYZ).0,
-                    },
-                )
-                .unwrap();
-        }
-    }
-}
@@ -1,29 +1,56 @@
-use crate::states::components::{despawn_with, Game, GameActions, GameState};
+use crate::states::components::{GameActions, GameSet};
 
 use bevy::prelude::*;
 use leafwing_input_manager::prelude::*;
+use vinox_common::ecs::time::GameClockPlugin;
 use vinox_common::physics::plugin::PhysicsPlugin;
 use vinox_common::world::chunks::light::LightPlugin;
 
 use super::{
-    input::plugin::InputPlugin, networking::plugin::NetworkingPlugin,
-    rendering::plugin::RenderingPlugin, ui::plugin::UiPlugin, world::chunks::ChunkPlugin,
+    input::plugin::InputPlugin,
+    networking::plugin::NetworkingPlugin,
+    rendering::plugin::RenderingPlugin,
+    session::SessionPlugin,
+    ui::plugin::UiPlugin,
+    world::{
+        chunk_dump::ChunkDumpPlugin, chunks::ChunkPlugin, critters::CritterPlugin,
+        finder::FinderPlugin, frames::FramePlugin, remote_players::RemotePlayerPlugin,
+        schematic::SchematicPlugin,
+    },
 };
 
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(InputManagerPlugin::<GameActions>::default())
-            .add_plugin(RenderingPlugin)
-            .add_plugin(ChunkPlugin)
-            .add_plugin(NetworkingPlugin)
-            .add_plugin(InputPlugin)
-            .add_plugin(PhysicsPlugin)
-            .add_plugin(UiPlugin)
-            .add_plugin(LightPlugin)
-            // .add_plugin(LogDiagnosticsPlugin::default())
-            // .add_plugin(FrameTimeDiagnosticsPlugin::default())
-            .add_system(despawn_with::<Game>.in_schedule(OnExit(GameState::Game)));
+        app.configure_sets(
+            (
+                GameSet::Input,
+                GameSet::Networking,
+                GameSet::WorldUpdate,
+                GameSet::Meshing,
+                GameSet::RenderPrep,
+                GameSet::Ui,
+            )
+                .chain(),
+        )
+        .add_plugin(SessionPlugin)
+        .add_plugin(InputManagerPlugin::<GameActions>::default())
+        .add_plugin(RenderingPlugin)
+        .add_plugin(ChunkPlugin)
+        .add_plugin(CritterPlugin)
+        .add_plugin(RemotePlayerPlugin)
+        .add_plugin(FinderPlugin)
+        .add_plugin(SchematicPlugin)
+        .add_plugin(ChunkDumpPlugin)
+        .add_plugin(FramePlugin)
+        .add_plugin(NetworkingPlugin)
+        .add_plugin(InputPlugin)
+        .add_plugin(GameClockPlugin)
+        .add_plugin(PhysicsPlugin)
+        .add_plugin(UiPlugin)
+        // .add_plugin(LogDiagnosticsPlugin::default())
+        // .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(LightPlugin);
     }
 }
@@ -0,0 +1,573 @@
+use bevy::prelude::*;
+use vinox_common::{
+    storage::biomes::descriptor::Atmosphere,
+    world::chunks::{
+        ecs::{ChunkManager, ViewRadius},
+        positions::{global_voxel_positions, world_to_global_voxel, ChunkPos, WorldOffset},
+        storage::{BiomeTable, CHUNK_SIZE},
+    },
+};
+
+use crate::states::{
+    components::GameOptions,
+    game::{
+        input::player::{view_fog, FPSCamera},
+        ui::hud::{underwater_color, UNDERWATER_COLOR},
+        world::chunks::ChunkBiomeMap,
+    },
+};
+
+use super::{autotune::AutoTune, memory::MemoryBudget};
+
+// Going darker has to be quick, whatever is in the dark can't wait for the player's eyes. Going
+// brighter takes its time like eyes adjusting
+pub const DARKEN_SECS: f32 = 0.3;
+pub const BRIGHTEN_SECS: f32 = 1.5;
+// How much brighter the world is with the brightest light right next to the camera
+pub const LIGHT_EXPOSURE: f32 = 0.25;
+pub const MAX_LIGHT: u8 = 15;
+pub const UNDERWATER_FOG_DENSITY: f32 = 6.0;
+pub const UNDERWATER_EXPOSURE: f32 = 0.85;
+// Blocks between the columns whose biomes get blended, crossing into a biome fades over this far
+pub const BIOME_BLEND_SPACING: f64 = 8.0;
+
+// Everything about how the view looks that changes with where the camera is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ambience {
+    // Divides the fog distances, 2 brings it in to half as far
+    pub fog_density: f32,
+    pub fog_color: Vec3,
+    // What's drawn behind everything
+    pub sky_color: Vec3,
+    // Multiplies the ambient light the world is lit by
+    pub exposure: f32,
+    // How much of the underwater overlay shows, 0 to 1
+    pub underwater: f32,
+    pub underwater_color: Vec3,
+}
+
+impl Default for Ambience {
+    fn default() -> Self {
+        Self {
+            fog_density: 1.0,
+            fog_color: Vec3::splat(0.1),
+            sky_color: Vec3::splat(0.1),
+            exposure: 1.0,
+            underwater: 0.0,
+            underwater_color: Vec3::from(UNDERWATER_COLOR),
+        }
+    }
+}
+
+// The biomes around the camera blended into one, see Atmosphere
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiomeAtmosphere {
+    pub fog_tint: Vec3,
+    pub fog_density: f32,
+    pub sky_tint: Vec3,
+}
+
+impl Default for BiomeAtmosphere {
+    fn default() -> Self {
+        Self::from(&Atmosphere::default())
+    }
+}
+
+impl From<&Atmosphere> for BiomeAtmosphere {
+    fn from(atmosphere: &Atmosphere) -> Self {
+        Self {
+            fog_tint: Vec3::from(atmosphere.fog_tint),
+            fog_density: atmosphere.fog_density,
+            sky_tint: Vec3::from(atmosphere.sky_tint),
+        }
+    }
+}
+
+impl BiomeAtmosphere {
+    // Weights are shared out again over the ones given, nothing given leaves it neutral
+    pub fn blend(weighted: impl IntoIterator<Item = (f32, BiomeAtmosphere)>) -> Self {
+        let mut blended = Self {
+            fog_tint: Vec3::ZERO,
+            fog_density: 0.0,
+            sky_tint: Vec3::ZERO,
+        };
+        let mut total = 0.0;
+        for (weight, atmosphere) in weighted {
+            if weight <= 0.0 {
+                continue;
+            }
+            blended.fog_tint += atmosphere.fog_tint * weight;
+            blended.fog_density += atmosphere.fog_density * weight;
+            blended.sky_tint += atmosphere.sky_tint * weight;
+            total += weight;
+        }
+        if total <= 0.0 {
+            return Self::default();
+        }
+        Self {
+            fog_tint: blended.fog_tint / total,
+            fog_density: blended.fog_density / total,
+            sky_tint: blended.sky_tint / total,
+        }
+    }
+}
+
+// The four columns on the blend grid around a position and how much each counts, bilinear so it
+// moves smoothly from one to the next
+pub fn biome_blend_weights(x: f64, z: f64) -> [(IVec2, f32); 4] {
+    let (x, z) = (x / BIOME_BLEND_SPACING, z / BIOME_BLEND_SPACING);
+    let (cell_x, cell_z) = (x.floor(), z.floor());
+    let (fx, fz) = ((x - cell_x) as f32, (z - cell_z) as f32);
+    let corner = |dx: i32, dz: i32| {
+        IVec2::new(
+            (cell_x as i32 + dx) * BIOME_BLEND_SPACING as i32,
+            (cell_z as i32 + dz) * BIOME_BLEND_SPACING as i32,
+        )
+    };
+    [
+        (corner(0, 0), (1.0 - fx) * (1.0 - fz)),
+        (corner(1, 0), fx * (1.0 - fz)),
+        (corner(0, 1), (1.0 - fx) * fz),
+        (corner(1, 1), fx * fz),
+    ]
+}
+
+// What the camera is in, sampled every frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AmbienceInputs {
+    // Torchlight at the camera, 0 to MAX_LIGHT
+    pub light: u8,
+    // Color of the fluid the camera is in
+    pub fluid: Option<[f32; 3]>,
+    pub biome: BiomeAtmosphere,
+}
+
+// Builds what the view should look like, one stage after another on what the ones before left:
+// 1. The defaults, what the view looks like out in the open. The fog distances themselves come
+//    from the view radius when it's applied
+// 2. The biome tints the fog and sky and thickens the fog
+// 3. Light around the camera brightens the world, the fog and the sky
+// 4. Underwater, last because the water's fog replaces whatever color came before it. Exposure
+//    and density multiply so they keep what the earlier stages did
+pub fn compose(inputs: &AmbienceInputs) -> Ambience {
+    let mut ambience = Ambience::default();
+
+    ambience.fog_color *= inputs.biome.fog_tint;
+    ambience.sky_color *= inputs.biome.sky_tint;
+    ambience.fog_density *= inputs.biome.fog_density;
+
+    let lit = inputs.light.min(MAX_LIGHT) as f32 / MAX_LIGHT as f32;
+    ambience.exposure *= 1.0 + LIGHT_EXPOSURE * lit;
+    ambience.fog_color *= 1.0 + LIGHT_EXPOSURE * lit;
+    ambience.sky_color *= 1.0 + LIGHT_EXPOSURE * lit;
+
+    if let Some(fluid) = inputs.fluid {
+        ambience.fog_density *= UNDERWATER_FOG_DENSITY;
+        ambience.fog_color = Vec3::from(fluid);
+        ambience.sky_color = Vec3::from(fluid);
+        ambience.exposure *= UNDERWATER_EXPOSURE;
+        ambience.underwater = 1.0;
+        ambience.underwater_color = Vec3::from(fluid);
+    }
+    ambience
+}
+
+// Seconds for the view to get most of the way to a change, about 63% of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adaptation {
+    pub darken: f32,
+    pub brighten: f32,
+}
+
+impl Adaptation {
+    // Reduced motion halves them rather than snapping, a sudden flash is worse than a quick fade
+    pub fn from_options(options: &GameOptions) -> Self {
+        let scale = if options.reduce_motion { 0.5 } else { 1.0 };
+        Self {
+            darken: DARKEN_SECS * scale,
+            brighten: BRIGHTEN_SECS * scale,
+        }
+    }
+}
+
+// Share of the way to the target covered in dt. Exponential, so ten short frames get as far as
+// one long one, and never more than all of it however long the frame
+pub fn approach_fraction(dt: f32, time_constant: f32) -> f32 {
+    if time_constant <= 0.0 {
+        return 1.0;
+    }
+    (1.0 - (-dt.max(0.0) / time_constant).exp()).clamp(0.0, 1.0)
+}
+
+// darker_when_lower says which way this value darkens the view
+pub fn adapt(
+    current: f32,
+    target: f32,
+    dt: f32,
+    speed: Adaptation,
+    darker_when_lower: bool,
+) -> f32 {
+    let darker = (target < current) == darker_when_lower;
+    let time_constant = if darker { speed.darken } else { speed.brighten };
+    let fraction = approach_fraction(dt, time_constant);
+    // Rounding can't carry it past the target either
+    (current + (target - current) * fraction).clamp(current.min(target), current.max(target))
+}
+
+fn adapt_color(current: Vec3, target: Vec3, dt: f32, speed: Adaptation) -> Vec3 {
+    Vec3::new(
+        adapt(current.x, target.x, dt, speed, true),
+        adapt(current.y, target.y, dt, speed, true),
+        adapt(current.z, target.z, dt, speed, true),
+    )
+}
+
+impl Ambience {
+    pub fn adapt(&self, target: &Ambience, dt: f32, speed: Adaptation) -> Ambience {
+        Ambience {
+            fog_density: adapt(self.fog_density, target.fog_density, dt, speed, false),
+            fog_color: adapt_color(self.fog_color, target.fog_color, dt, speed),
+            sky_color: adapt_color(self.sky_color, target.sky_color, dt, speed),
+            exposure: adapt(self.exposure, target.exposure, dt, speed, true),
+            underwater: adapt(self.underwater, target.underwater, dt, speed, false),
+            underwater_color: adapt_color(
+                self.underwater_color,
+                target.underwater_color,
+                dt,
+                speed,
+            ),
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct ViewAmbience {
+    pub target: Ambience,
+    // None until the first frame of a session, which starts out at the target
+    applied: Option<Ambience>,
+}
+
+impl ViewAmbience {
+    pub fn applied(&self) -> Ambience {
+        self.applied.unwrap_or(self.target)
+    }
+}
+
+// Columns in chunks that haven't come in yet are left out of the blend rather than counted as
+// neutral, the fog would change at the edge of the loaded world otherwise
+fn column_atmosphere(
+    column: IVec2,
+    chunk_y: i32,
+    chunk_biomes: &ChunkBiomeMap,
+    biome_table: &BiomeTable,
+) -> Option<BiomeAtmosphere> {
+    let size = CHUNK_SIZE as i32;
+    let chunk = IVec3::new(
+        column.x.div_euclid(size),
+        chunk_y,
+        column.y.div_euclid(size),
+    );
+    let identifier = chunk_biomes.get(&chunk)?.get(
+        column.x.rem_euclid(size) as usize,
+        column.y.rem_euclid(size) as usize,
+    )?;
+    // A biome we don't have is still a biome, just one without its own look
+    Some(
+        biome_table
+            .get(identifier)
+            .map(|biome| BiomeAtmosphere::from(&biome.atmosphere))
+            .unwrap_or_default(),
+    )
+}
+
+pub fn sample_ambience(
+    camera: Query<&GlobalTransform, With<FPSCamera>>,
+    chunk_manager: ChunkManager,
+    (offset, chunk_biomes, biome_table): (Res<WorldOffset>, Res<ChunkBiomeMap>, Res<BiomeTable>),
+    mut ambience: ResMut<ViewAmbience>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let voxel = offset.voxel_to_world(world_to_global_voxel(camera.translation()));
+    let (chunk_pos, local) = global_voxel_positions(voxel);
+    let position = offset.to_world(camera.translation());
+    let biome = BiomeAtmosphere::blend(
+        biome_blend_weights(position.x, position.z)
+            .into_iter()
+            .filter_map(|(column, weight)| {
+                column_atmosphere(column, chunk_pos.y, &chunk_biomes, &biome_table)
+                    .map(|atmosphere| (weight, atmosphere))
+            }),
+    );
+    let light = chunk_manager
+        .current_chunks
+        .get_entity(ChunkPos(chunk_pos))
+        .and_then(|entity| chunk_manager.chunk_query.get(entity).ok())
+        .map_or(0, |chunk| chunk.get_torchlight(local.x, local.y, local.z));
+    let fluid = chunk_manager.get_block(voxel).and_then(|block| {
+        chunk_manager
+            .block_table
+            .get(&format!("{}:{}", block.namespace, block.name))
+            .and_then(|descriptor| underwater_color(descriptor, voxel))
+    });
+    ambience.target = compose(&AmbienceInputs {
+        light,
+        fluid,
+        biome,
+    });
+}
+
+pub fn smooth_ambience(
+    mut ambience: ResMut<ViewAmbience>,
+    time: Res<Time>,
+    options: Res<GameOptions>,
+) {
+    let target = ambience.target;
+    let applied = match ambience.applied {
+        Some(applied) => applied.adapt(
+            &target,
+            time.delta_seconds(),
+            Adaptation::from_options(&options),
+        ),
+        None => target,
+    };
+    ambience.applied = Some(applied);
+}
+
+// Fog thicker than the view radius would show the edge of the loaded world, so it only closes in
+fn thicken(falloff: FogFalloff, density: f32) -> FogFalloff {
+    let density = density.max(1.0);
+    match falloff {
+        FogFalloff::Linear { start, end } => FogFalloff::Linear {
+            start: start / density,
+            end: end / density,
+        },
+        falloff => falloff,
+    }
+}
+
+pub fn apply_ambience(
+    ambience: Res<ViewAmbience>,
+    mut fog: Query<&mut FogSettings>,
+    mut clear_color: ResMut<ClearColor>,
+    mut ambient_light: ResMut<AmbientLight>,
+    (budget, tune, view_radius): (Res<MemoryBudget>, Res<AutoTune>, Res<ViewRadius>),
+) {
+    let applied = ambience.applied();
+    let color = Color::rgb(
+        applied.fog_color.x,
+        applied.fog_color.y,
+        applied.fog_color.z,
+    );
+    let sky = Color::rgb(
+        applied.sky_color.x,
+        applied.sky_color.y,
+        applied.sky_color.z,
+    );
+    let radius = budget.view_radius(tune.view_radius(view_radius.horizontal));
+    for mut fog in fog.iter_mut() {
+        fog.color = color;
+        fog.falloff = thicken(view_fog(radius as usize), applied.fog_density);
+    }
+    clear_color.0 = sky;
+    ambient_light.brightness = applied.exposure;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEED: Adaptation = Adaptation {
+        darken: DARKEN_SECS,
+        brighten: BRIGHTEN_SECS,
+    };
+
+    #[test]
+    fn darkening_is_faster_than_brightening() {
+        let darkened = adapt(1.0, 0.0, DARKEN_SECS, SPEED, true);
+        let brightened = adapt(0.0, 1.0, DARKEN_SECS, SPEED, true);
+        assert!((darkened - (-1.0f32).exp()).abs() < 1e-6);
+        assert!((brightened - (1.0 - (-0.2f32).exp())).abs() < 1e-6);
+        // Fog closing in is the dark way for density
+        let thicker = adapt(1.0, 2.0, 0.1, SPEED, false) - 1.0;
+        let thinner = 2.0 - adapt(2.0, 1.0, 0.1, SPEED, false);
+        assert!(thicker > thinner);
+
+        // Ten short frames land where one long one does
+        let mut stepped = 1.0;
+        for _ in 0..10 {
+            stepped = adapt(stepped, 0.0, DARKEN_SECS / 10.0, SPEED, true);
+        }
+        assert!((stepped - darkened).abs() < 1e-5);
+
+        let mut options = GameOptions::default();
+        options.reduce_motion = true;
+        let reduced = Adaptation::from_options(&options);
+        assert_eq!(reduced.darken, DARKEN_SECS / 2.0);
+        assert_eq!(reduced.brighten, BRIGHTEN_SECS / 2.0);
+        // Faster, but still a fade
+        let halved = adapt(1.0, 0.0, DARKEN_SECS / 2.0, reduced, true);
+        assert_eq!(halved, darkened);
+        assert!(halved > 0.0);
+    }
+
+    #[test]
+    fn long_frames_never_overshoot() {
+        for dt in [1e9, f32::MAX, f32::INFINITY] {
+            assert_eq!(adapt(1.0, 0.25, dt, SPEED, true), 0.25);
+            assert_eq!(adapt(0.25, 1.0, dt, SPEED, true), 1.0);
+        }
+        // A clock going backwards or nowhere doesn't move anything
+        for dt in [-5.0, 0.0, f32::NAN] {
+            assert_eq!(adapt(1.0, 0.25, dt, SPEED, true), 1.0);
+        }
+        let dark = compose(&AmbienceInputs::default());
+        let lit_underwater = compose(&AmbienceInputs {
+            light: MAX_LIGHT,
+            fluid: Some([0.1, 0.5, 0.3]),
+            ..Default::default()
+        });
+        for dt in [0.016, 0.5, 3.0, 1e6] {
+            let step = dark.adapt(&lit_underwater, dt, SPEED);
+            for (value, from, to) in [
+                (
+                    step.fog_density,
+                    dark.fog_density,
+                    lit_underwater.fog_density,
+                ),
+                (step.exposure, dark.exposure, lit_underwater.exposure),
+                (step.underwater, dark.underwater, lit_underwater.underwater),
+                (
+                    step.fog_color.y,
+                    dark.fog_color.y,
+                    lit_underwater.fog_color.y,
+                ),
+            ] {
+                assert!(
+                    value >= from.min(to) && value <= from.max(to),
+                    "{value} at {dt}"
+                );
+            }
+        }
+        assert_eq!(dark.adapt(&lit_underwater, 1e6, SPEED), lit_underwater);
+    }
+
+    #[test]
+    fn stages_compose_in_order() {
+        let open = compose(&AmbienceInputs::default());
+        assert_eq!(open, Ambience::default());
+
+        let lit = compose(&AmbienceInputs {
+            light: MAX_LIGHT,
+            ..Default::default()
+        });
+        assert_eq!(lit.exposure, 1.0 + LIGHT_EXPOSURE);
+        assert_eq!(lit.fog_color, open.fog_color * (1.0 + LIGHT_EXPOSURE));
+
+        let water = [0.2, 0.35, 0.8];
+        let everything = compose(&AmbienceInputs {
+            light: MAX_LIGHT,
+            fluid: Some(water),
+            ..Default::default()
+        });
+        // The water's fog color wins over the light's, the multipliers stack
+        assert_eq!(everything.fog_color, Vec3::from(water));
+        assert_eq!(everything.sky_color, Vec3::from(water));
+        assert_eq!(everything.exposure, lit.exposure * UNDERWATER_EXPOSURE);
+        assert_eq!(everything.fog_density, UNDERWATER_FOG_DENSITY);
+        assert_eq!(everything.underwater, 1.0);
+        assert_eq!(everything.underwater_color, Vec3::from(water));
+
+        // Light past the top of the range counts as the top
+        assert_eq!(
+            compose(&AmbienceInputs {
+                light: 200,
+                ..Default::default()
+            }),
+            lit
+        );
+    }
+
+    #[test]
+    fn biome_tints_stack_under_the_light() {
+        let swamp = BiomeAtmosphere {
+            fog_tint: Vec3::new(0.6, 0.9, 0.5),
+            fog_density: 2.0,
+            sky_tint: Vec3::new(0.7, 0.8, 0.6),
+        };
+        let open = compose(&AmbienceInputs::default());
+        let tinted = compose(&AmbienceInputs {
+            light: MAX_LIGHT,
+            biome: swamp,
+            ..Default::default()
+        });
+        let lit = 1.0 + LIGHT_EXPOSURE;
+        assert_eq!(tinted.fog_color, open.fog_color * swamp.fog_tint * lit);
+        assert_eq!(tinted.sky_color, open.sky_color * swamp.sky_tint * lit);
+        assert_eq!(tinted.fog_density, 2.0);
+        // The water still covers the biome's colors but not its density
+        let submerged = compose(&AmbienceInputs {
+            fluid: Some([0.2, 0.35, 0.8]),
+            biome: swamp,
+            ..Default::default()
+        });
+        assert_eq!(submerged.fog_density, 2.0 * UNDERWATER_FOG_DENSITY);
+        assert_eq!(submerged.sky_color, Vec3::new(0.2, 0.35, 0.8));
+    }
+
+    #[test]
+    fn biome_borders_blend_without_a_jump() {
+        // Grid lines, cell middles and both sides of zero
+        for (x, z) in [
+            (0.0, 0.0),
+            (8.0, -8.0),
+            (-0.001, 0.001),
+            (3.5, -12.25),
+            (-1000.75, 77.0),
+            (15.999, 16.0),
+        ] {
+            let weights = biome_blend_weights(x, z);
+            let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+            assert!((total - 1.0).abs() < 1e-5, "{total} at {x},{z}");
+            for (column, weight) in weights {
+                assert!(weight >= 0.0);
+                assert!((column.x as f64 - x).abs() <= BIOME_BLEND_SPACING);
+                assert!((column.y as f64 - z).abs() <= BIOME_BLEND_SPACING);
+            }
+        }
+
+        // Walking over a border from a plain biome into a foggy one never moves more than a step's
+        // worth at a time
+        let plain = BiomeAtmosphere::default();
+        let foggy = BiomeAtmosphere {
+            fog_density: 3.0,
+            ..Default::default()
+        };
+        let density_at = |x: f64| {
+            BiomeAtmosphere::blend(
+                biome_blend_weights(x, 4.0)
+                    .into_iter()
+                    .map(|(column, weight)| (weight, if column.x >= 16 { foggy } else { plain })),
+            )
+            .fog_density
+        };
+        let mut last = density_at(0.0);
+        assert_eq!(last, 1.0);
+        let mut x = 0.0;
+        while x < 32.0 {
+            x += 0.25;
+            let density = density_at(x);
+            assert!(
+                density >= last && density - last <= 0.1,
+                "{last} to {density}"
+            );
+            last = density;
+        }
+        assert_eq!(last, 3.0);
+
+        // Nothing known is neutral, a known column alone decides
+        assert_eq!(BiomeAtmosphere::blend([]), BiomeAtmosphere::default());
+        assert_eq!(BiomeAtmosphere::blend([(0.25, foggy)]), foggy);
+    }
+}
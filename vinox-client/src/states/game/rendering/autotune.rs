@@ -0,0 +1,458 @@
+use bevy::prelude::*;
+use vinox_common::world::chunks::ecs::ViewRadius;
+
+use crate::states::{
+    components::GameOptions,
+    game::{
+        networking::components::{ChatLine, ChatMessages},
+        rendering::memory::MemoryBudget,
+        ui::profiler::RingBuffer,
+    },
+};
+
+pub const DEFAULT_FRAME_TARGET: f32 = 16.6;
+// Frames the average and p95 are taken over
+pub const WINDOW: usize = 120;
+// Joining meshes the whole view at once, nothing is judged until that has had time to settle
+pub const WARMUP_SECS: f64 = 15.0;
+// How long the average has to stay over the target before anything is turned down
+pub const OVER_SECS: f64 = 4.0;
+// How long the p95 has to stay well under the target before anything comes back
+pub const HEADROOM_SECS: f64 = 30.0;
+// What counts as well under
+pub const HEADROOM_FRACTION: f32 = 0.6;
+// Seconds after a change before the next, its effect takes a while to show in the window
+pub const CHANGE_COOLDOWN: f64 = 5.0;
+// Halvings of the mesh upload budget, never below MIN_MESHES_FRAME
+pub const MAX_UPLOAD_STEPS: u8 = 2;
+pub const MIN_MESHES_FRAME: usize = 64;
+// Auto-tune never pulls the view in closer than this, memory pressure can go further
+pub const AUTO_MIN_VIEW_RADIUS: i32 = 6;
+
+// Cheapest to lose first. There are no particles yet, a cap on them would go at the front
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Knob {
+    ChunkFadeIn,
+    AmbientOcclusion,
+    MeshUpload,
+    ViewRadius,
+}
+
+impl Knob {
+    pub const LADDER: [Knob; 4] = [
+        Knob::ChunkFadeIn,
+        Knob::AmbientOcclusion,
+        Knob::MeshUpload,
+        Knob::ViewRadius,
+    ];
+}
+
+// The knobs as the player set them, what auto-tune steps down from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnobValues {
+    pub chunk_fade_in: bool,
+    pub ambient_occlusion: bool,
+    pub meshes_frame: usize,
+}
+
+impl KnobValues {
+    pub fn from_options(options: &GameOptions) -> Self {
+        Self {
+            chunk_fade_in: options.chunk_fade_in,
+            ambient_occlusion: options.ambient_occlusion,
+            meshes_frame: options.meshes_frame,
+        }
+    }
+}
+
+// What the rest of the client should use in place of the options
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tuned {
+    pub chunk_fade_in: bool,
+    pub ambient_occlusion: bool,
+    pub meshes_frame: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adjustment {
+    pub knob: Knob,
+    pub reduced: bool,
+}
+
+// Frame time controller. Only ever changes the effective values it hands out, the saved options
+// stay as the player left them so turning it off puts everything back
+#[derive(Resource, Debug, Default)]
+pub struct AutoTune {
+    frames: RingBuffer<f32, WINDOW>,
+    steps: [u8; Knob::LADDER.len()],
+    // Knobs the player changed by hand, left alone for the rest of the session
+    manual: [bool; Knob::LADDER.len()],
+    seen: Option<KnobValues>,
+    started: Option<f64>,
+    over_since: Option<f64>,
+    headroom_since: Option<f64>,
+    last_change: Option<f64>,
+    last_raised: bool,
+    // Fewest steps known to hold the target. Set when raising went straight back over, so the
+    // same step isn't tried again and again
+    floor: u32,
+}
+
+impl AutoTune {
+    pub fn steps(&self, knob: Knob) -> u8 {
+        self.steps[knob as usize]
+    }
+
+    pub fn level(&self) -> u32 {
+        self.steps.iter().map(|steps| *steps as u32).sum()
+    }
+
+    pub fn is_manual(&self, knob: Knob) -> bool {
+        self.manual[knob as usize]
+    }
+
+    pub fn tuned(&self, values: KnobValues) -> Tuned {
+        Tuned {
+            chunk_fade_in: values.chunk_fade_in && self.steps(Knob::ChunkFadeIn) == 0,
+            ambient_occlusion: values.ambient_occlusion && self.steps(Knob::AmbientOcclusion) == 0,
+            meshes_frame: values.meshes_frame >> self.steps(Knob::MeshUpload),
+        }
+    }
+
+    pub fn tuned_options(&self, options: &GameOptions) -> Tuned {
+        self.tuned(KnobValues::from_options(options))
+    }
+
+    // The view radius before memory pressure takes its share
+    pub fn view_radius(&self, base: i32) -> i32 {
+        base - self.steps(Knob::ViewRadius) as i32
+    }
+
+    // Forgets everything, every knob back to what the options say
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    // A knob the player changed since last time stops being tuned
+    pub fn observe(&mut self, values: KnobValues) {
+        if let Some(seen) = self.seen {
+            let changed = [
+                (
+                    Knob::ChunkFadeIn,
+                    seen.chunk_fade_in != values.chunk_fade_in,
+                ),
+                (
+                    Knob::AmbientOcclusion,
+                    seen.ambient_occlusion != values.ambient_occlusion,
+                ),
+                (Knob::MeshUpload, seen.meshes_frame != values.meshes_frame),
+            ];
+            for (knob, changed) in changed {
+                if changed {
+                    self.manual[knob as usize] = true;
+                    self.steps[knob as usize] = 0;
+                }
+            }
+        }
+        self.seen = Some(values);
+    }
+
+    fn can_reduce(&self, knob: Knob, values: KnobValues, base_radius: i32, memory: bool) -> bool {
+        let steps = self.steps(knob);
+        match knob {
+            Knob::ChunkFadeIn => values.chunk_fade_in && steps == 0,
+            Knob::AmbientOcclusion => values.ambient_occlusion && steps == 0,
+            Knob::MeshUpload => {
+                steps < MAX_UPLOAD_STEPS && values.meshes_frame >> (steps + 1) >= MIN_MESHES_FRAME
+            }
+            // Memory wins, its steps would move under ours
+            Knob::ViewRadius => !memory && self.view_radius(base_radius) > AUTO_MIN_VIEW_RADIUS,
+        }
+    }
+
+    fn step_down(&mut self, values: KnobValues, base_radius: i32, memory: bool) -> Option<Knob> {
+        let knob = Knob::LADDER.into_iter().find(|knob| {
+            !self.is_manual(*knob) && self.can_reduce(*knob, values, base_radius, memory)
+        })?;
+        self.steps[knob as usize] += 1;
+        Some(knob)
+    }
+
+    // Back up the ladder in the order it came down. When the top knob can't move, nothing
+    // under it does either
+    fn step_up(&mut self, memory: bool) -> Option<Knob> {
+        if self.level() <= self.floor {
+            return None;
+        }
+        let knob = Knob::LADDER
+            .into_iter()
+            .rev()
+            .find(|knob| self.steps(*knob) > 0)?;
+        if knob == Knob::ViewRadius && memory {
+            return None;
+        }
+        self.steps[knob as usize] -= 1;
+        Some(knob)
+    }
+
+    // frame is in milliseconds. memory is whether the memory governor has anything pulled in
+    pub fn update(
+        &mut self,
+        now: f64,
+        frame: f32,
+        target: f32,
+        values: KnobValues,
+        (base_radius, memory): (i32, bool),
+    ) -> Option<Adjustment> {
+        let started = *self.started.get_or_insert(now);
+        self.frames.push(frame);
+        if now - started < WARMUP_SECS || self.frames.len() < WINDOW {
+            return None;
+        }
+        let average = self.frames.iter().sum::<f32>() / self.frames.len() as f32;
+        let p95 = self.frames.percentile(0.95, |frame| *frame);
+        self.over_since = (average > target).then(|| self.over_since.unwrap_or(now));
+        self.headroom_since =
+            (p95 < target * HEADROOM_FRACTION).then(|| self.headroom_since.unwrap_or(now));
+        if self
+            .last_change
+            .is_some_and(|last| now - last < CHANGE_COOLDOWN)
+        {
+            return None;
+        }
+
+        let adjustment = if self
+            .over_since
+            .is_some_and(|since| now - since >= OVER_SECS)
+        {
+            let knob = self.step_down(values, base_radius, memory)?;
+            if self.last_raised {
+                self.floor = self.floor.max(self.level());
+            }
+            Adjustment {
+                knob,
+                reduced: true,
+            }
+        } else if self
+            .headroom_since
+            .is_some_and(|since| now - since >= HEADROOM_SECS)
+        {
+            Adjustment {
+                knob: self.step_up(memory)?,
+                reduced: false,
+            }
+        } else {
+            return None;
+        };
+        self.last_raised = !adjustment.reduced;
+        self.last_change = Some(now);
+        self.frames = RingBuffer::default();
+        self.over_since = None;
+        self.headroom_since = None;
+        Some(adjustment)
+    }
+
+    pub fn describe(&self, adjustment: Adjustment, values: KnobValues, base_radius: i32) -> String {
+        let tuned = self.tuned(values);
+        let change = if adjustment.reduced {
+            "reduced"
+        } else {
+            "raised"
+        };
+        let state = if adjustment.reduced { "off" } else { "back on" };
+        match adjustment.knob {
+            Knob::ChunkFadeIn => format!("Auto-tune: turned chunk fade-in {state}"),
+            Knob::AmbientOcclusion => format!("Auto-tune: turned ambient occlusion {state}"),
+            Knob::MeshUpload => format!(
+                "Auto-tune: {change} meshes per frame to {}",
+                tuned.meshes_frame
+            ),
+            Knob::ViewRadius => format!(
+                "Auto-tune: {change} view distance to {}",
+                self.view_radius(base_radius)
+            ),
+        }
+    }
+
+    // Shown next to a knob in the options while auto-tune has it turned down
+    pub fn badge(&self, knob: Knob, options: &GameOptions) -> Option<String> {
+        if !options.auto_tune || self.steps(knob) == 0 {
+            return None;
+        }
+        let tuned = self.tuned_options(options);
+        Some(match knob {
+            Knob::ChunkFadeIn | Knob::AmbientOcclusion => "auto: off".to_string(),
+            Knob::MeshUpload => format!("auto: {}", tuned.meshes_frame),
+            Knob::ViewRadius => format!("auto: {} closer", self.steps(knob)),
+        })
+    }
+}
+
+pub fn auto_tune(
+    options: Res<GameOptions>,
+    time: Res<Time>,
+    mut tune: ResMut<AutoTune>,
+    (budget, view_radius): (Res<MemoryBudget>, Res<ViewRadius>),
+    mut messages: ResMut<ChatMessages>,
+) {
+    if !options.auto_tune {
+        if tune.level() > 0 || tune.seen.is_some() {
+            tune.reset();
+        }
+        return;
+    }
+    let values = KnobValues::from_options(&options);
+    tune.observe(values);
+    let Some(adjustment) = tune.update(
+        time.elapsed_seconds_f64(),
+        time.delta_seconds() * 1000.0,
+        options.frame_target_ms,
+        values,
+        (view_radius.horizontal, budget.steps() > 0),
+    ) else {
+        return;
+    };
+    let message = tune.describe(adjustment, values, view_radius.horizontal);
+    messages.push(ChatLine::toast(message));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_TARGET: f32 = DEFAULT_FRAME_TARGET;
+    const BASE_RADIUS: i32 = 12;
+
+    fn values() -> KnobValues {
+        KnobValues {
+            chunk_fade_in: true,
+            ambient_occlusion: true,
+            meshes_frame: 256,
+        }
+    }
+
+    // Same every run, a cheap hash of the frame number spread over 0..1
+    fn noise(frame: usize) -> f32 {
+        let hashed = (frame as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 40;
+        hashed as f32 / (1u64 << 24) as f32
+    }
+
+    // Runs a trace whose frame time depends on how far down the ladder it is, returns every
+    // adjustment with when it happened
+    fn simulate(
+        tune: &mut AutoTune,
+        (from, until): (f64, f64),
+        memory: bool,
+        frame_time: impl Fn(&AutoTune, usize) -> f32,
+    ) -> Vec<(f64, Adjustment)> {
+        let mut now = from;
+        let mut frame = 0;
+        let mut adjustments = Vec::new();
+        while now < until {
+            let time = frame_time(tune, frame);
+            if let Some(adjustment) =
+                tune.update(now, time, FRAME_TARGET, values(), (BASE_RADIUS, memory))
+            {
+                adjustments.push((now, adjustment));
+            }
+            now += time as f64 / 1000.0;
+            frame += 1;
+        }
+        adjustments
+    }
+
+    #[test]
+    fn noisy_but_fine_frames_change_nothing() {
+        let mut tune = AutoTune::default();
+        // 11 to 15ms with a 40ms hitch every couple of seconds
+        let adjustments = simulate(&mut tune, (0.0, 600.0), false, |_, frame| {
+            if frame % 157 == 0 {
+                40.0
+            } else {
+                11.0 + noise(frame) * 4.0
+            }
+        });
+        assert_eq!(adjustments, Vec::new());
+        assert_eq!(tune.level(), 0);
+    }
+
+    #[test]
+    fn overload_settles_and_stays() {
+        let mut tune = AutoTune::default();
+        // Each step takes 6ms off, three of them bring it under the target
+        let adjustments = simulate(&mut tune, (0.0, 600.0), false, |tune, frame| {
+            30.0 - 6.0 * tune.level() as f32 + noise(frame) * 3.0
+        });
+        let knobs: Vec<_> = adjustments
+            .iter()
+            .map(|(_, adjustment)| *adjustment)
+            .collect();
+        assert_eq!(
+            knobs,
+            [Knob::ChunkFadeIn, Knob::AmbientOcclusion, Knob::MeshUpload].map(|knob| Adjustment {
+                knob,
+                reduced: true
+            })
+        );
+        assert!(adjustments.last().unwrap().0 < 60.0);
+        let tuned = tune.tuned(values());
+        assert!(!tuned.chunk_fade_in && !tuned.ambient_occlusion);
+        assert_eq!(tuned.meshes_frame, 128);
+        assert_eq!(tune.view_radius(BASE_RADIUS), BASE_RADIUS);
+        assert_eq!(
+            tune.describe(adjustments[2].1, values(), BASE_RADIUS),
+            "Auto-tune: reduced meshes per frame to 128"
+        );
+    }
+
+    #[test]
+    fn raising_that_goes_back_over_is_not_tried_again() {
+        let mut tune = AutoTune::default();
+        // Over with fewer than two steps, plenty of headroom with two
+        let adjustments = simulate(&mut tune, (0.0, 900.0), false, |tune, frame| {
+            let base = if tune.level() < 2 { 20.0 } else { 8.0 };
+            base + noise(frame)
+        });
+        let directions: Vec<_> = adjustments
+            .iter()
+            .map(|(_, adjustment)| adjustment.reduced)
+            .collect();
+        assert_eq!(directions, [true, true, false, true]);
+        assert_eq!(tune.level(), 2);
+    }
+
+    #[test]
+    fn view_distance_is_left_to_memory_pressure() {
+        let overloaded = |_: &AutoTune, frame: usize| 40.0 + noise(frame);
+        let mut tune = AutoTune::default();
+        simulate(&mut tune, (0.0, 300.0), true, overloaded);
+        assert_eq!(tune.steps(Knob::ViewRadius), 0);
+        assert_eq!(tune.level(), 4);
+
+        let adjustments = simulate(&mut tune, (300.0, 600.0), false, overloaded);
+        assert_eq!(tune.view_radius(BASE_RADIUS), AUTO_MIN_VIEW_RADIUS);
+        assert_eq!(
+            tune.describe(adjustments.last().unwrap().1, values(), BASE_RADIUS),
+            format!("Auto-tune: reduced view distance to {AUTO_MIN_VIEW_RADIUS}")
+        );
+    }
+
+    #[test]
+    fn a_knob_changed_by_hand_is_left_alone() {
+        let overloaded = |_: &AutoTune, frame: usize| 30.0 + noise(frame);
+        let mut tune = AutoTune::default();
+        tune.observe(values());
+        simulate(&mut tune, (0.0, 60.0), false, overloaded);
+        assert_eq!(tune.steps(Knob::AmbientOcclusion), 1);
+
+        // Turned off and back on by hand, it stays on even though it's still too slow
+        let mut changed = values();
+        changed.ambient_occlusion = false;
+        tune.observe(changed);
+        tune.observe(values());
+        assert!(tune.is_manual(Knob::AmbientOcclusion));
+        simulate(&mut tune, (60.0, 360.0), false, overloaded);
+        assert_eq!(tune.steps(Knob::AmbientOcclusion), 0);
+        assert!(tune.tuned(values()).ambient_occlusion);
+    }
+}
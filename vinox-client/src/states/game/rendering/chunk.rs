@@ -3,7 +3,9 @@ use ndshape::{ConstShape, ConstShape3usize};
 use serde_big_array::Array;
 use vinox_common::{
     storage::geometry::descriptor::BlockGeo,
-    world::chunks::storage::{trim_geo_identifier, BlockTable, ChunkData, RenderedBlockData},
+    world::chunks::storage::{
+        name_to_identifier, trim_geo_identifier, BlockTable, ChunkData, RenderedBlockData,
+    },
 };
 
 use crate::states::assets::load::LoadableAssets;
@@ -424,8 +426,9 @@ pub fn get_rend(
     let (x, y, z) = (x as u32, y as u32, z as u32);
     // return RenderedBlockData::default();
     let voxel = chunk.get(x, y, z);
-    let identifier = chunk.get_identifier(x, y, z);
-    let block_data = block_table.get(&identifier).unwrap();
+    let block_data = block_table.get_or_unknown(&chunk.get_identifier(x, y, z));
+    // The placeholder's own identifier when the block is unknown, so it gets its textures
+    let identifier = name_to_identifier(block_data.namespace.clone(), block_data.name.clone());
     let geo_data = geo_table.get(
         &block_data
             .clone()
@@ -472,6 +475,12 @@ pub fn get_rend(
             .unwrap_or_default();
     }
 
+    let decoration = block_data.decoration.as_ref().and_then(|decoration| {
+        let texture = loadable_assets.decoration_textures.get(&identifier)?;
+        let density = (decoration.density.clamp(0.0, 1.0) * 100.0).round() as u8;
+        Some((texture_atlas.get_texture_index(texture)?, density))
+    });
+
     RenderedBlockData {
         // identifier,
         geo_index,
@@ -486,5 +495,78 @@ pub fn get_rend(
         tex_variance,
         blocks: geo_data.unwrap().blocks,
         light: chunk.get_light(x, y, z),
+        tint: block_data.tint,
+        decoration,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{asset::HandleId, prelude::*, utils::HashMap};
+    use vinox_common::{
+        storage::geometry::descriptor::GeometryDescriptor,
+        testing::{self, descriptor},
+        world::chunks::storage::{BlockData, VoxelVisibility, UNKNOWN_BLOCK},
+    };
+
+    use super::*;
+
+    #[test]
+    fn unknown_blocks_mesh_as_the_placeholder() {
+        let block_table = testing::block_table([descriptor("air", VoxelVisibility::Empty)]);
+        let mut geo_table = GeometryTable::default();
+        geo_table.insert(
+            "vinox:block".to_string(),
+            GeometryDescriptor {
+                namespace: "vinox".to_string(),
+                name: "block".to_string(),
+                blocks: [true; 6],
+                element: BlockGeo::default(),
+            },
+        );
+        // The placeholder's texture is the second one in the atlas
+        let [air, magenta] = [(); 2].map(|_| Handle::<Image>::weak(HandleId::random::<Image>()));
+        let mut loadable_assets = LoadableAssets::default();
+        for (identifier, handle) in [
+            ("vinox:air".to_string(), &air),
+            (
+                name_to_identifier(UNKNOWN_BLOCK.0.to_string(), UNKNOWN_BLOCK.1.to_string()),
+                &magenta,
+            ),
+        ] {
+            loadable_assets
+                .block_textures
+                .insert(identifier, [(); 6].map(|_| handle.clone()));
+        }
+        let mut texture_atlas = TextureAtlas::new_empty(Handle::default(), Vec2::new(32.0, 16.0));
+        texture_atlas.add_texture(Rect::new(0.0, 0.0, 16.0, 16.0));
+        texture_atlas.add_texture(Rect::new(16.0, 0.0, 32.0, 16.0));
+        texture_atlas.texture_handles = Some(HashMap::from_iter([(air, 0), (magenta, 1)]));
+
+        let mut chunk = ChunkData::default();
+        chunk.set(
+            1,
+            2,
+            3,
+            BlockData::new("modded".to_string(), "gone".to_string()),
+            &block_table,
+        );
+        let (mut pal, mut matching) = (Vec::new(), Vec::new());
+        let rendered = get_rend(
+            &chunk,
+            1,
+            2,
+            3,
+            &geo_table,
+            &block_table,
+            &loadable_assets,
+            &mut pal,
+            &texture_atlas,
+            &mut matching,
+        );
+        assert_eq!(rendered.visibility, VoxelVisibility::Opaque);
+        assert_eq!(rendered.textures, [1; 6]);
+        assert_eq!(rendered.blocks, [true; 6]);
+        assert_eq!(matching, vec!["vinox:unknown".to_string()]);
     }
 }
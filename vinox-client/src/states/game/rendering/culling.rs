@@ -0,0 +1,100 @@
+use bevy::{
+    math::Vec3A,
+    prelude::*,
+    render::primitives::{Aabb, Frustum},
+};
+use vinox_common::world::chunks::positions::ChunkPos;
+
+use crate::states::game::input::player::FPSCamera;
+
+// Blocks added around each chunk before testing it. The frustum is from the end of last frame,
+// so without it a quick turn shows a chunk a frame late at the edge of the screen
+pub const CULL_MARGIN: f32 = 2.0;
+
+// Chunks with a mesh and how many of those were in view, last frame
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkCulling {
+    pub rendered: usize,
+    pub total: usize,
+}
+
+// aabb is in the chunk's own space, translation puts it in the world
+pub fn chunk_in_view(frustum: &Frustum, aabb: &Aabb, translation: Vec3) -> bool {
+    let padded = Aabb {
+        center: aabb.center + Vec3A::from(translation),
+        half_extents: aabb.half_extents + Vec3A::splat(CULL_MARGIN),
+    };
+    frustum.intersects_obb(&padded, &Mat4::IDENTITY, true, true)
+}
+
+// Hides whole chunks, the transparent mesh under each one goes with it. Bevy culls each mesh on
+// its own too, this keeps it from having to and gives the count. Every frame, since turning the
+// camera changes what's in view without the player chunk changing
+pub fn cull_chunks(
+    camera: Query<&Frustum, With<FPSCamera>>,
+    mut chunks: Query<(&GlobalTransform, &Aabb, &mut Visibility), With<ChunkPos>>,
+    mut culling: ResMut<ChunkCulling>,
+) {
+    let Ok(frustum) = camera.get_single() else {
+        return;
+    };
+    let mut counted = ChunkCulling::default();
+    for (transform, aabb, mut visibility) in chunks.iter_mut() {
+        let in_view = chunk_in_view(frustum, aabb, transform.translation());
+        let wanted = if in_view {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        // Only written when it changes so Bevy doesn't redo visibility for every chunk each frame
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+        counted.total += 1;
+        counted.rendered += in_view as usize;
+    }
+    *culling = counted;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::camera::CameraProjection;
+    use vinox_common::world::chunks::storage::CHUNK_SIZE;
+
+    #[test]
+    fn chunks_behind_the_camera_are_culled() {
+        let projection = PerspectiveProjection {
+            fov: 70.0f32.to_radians(),
+            near: 0.001,
+            far: 1000.0,
+            aspect_ratio: 16.0 / 9.0,
+        };
+        let half = (CHUNK_SIZE / 2) as f32;
+        let aabb = Aabb {
+            center: Vec3A::splat(half),
+            half_extents: Vec3A::splat(half),
+        };
+        let chunk = |x: i32, y: i32, z: i32| Vec3::new(x as f32, y as f32, z as f32) * 16.0;
+        let frustum_for = |view: Transform| {
+            Frustum::from_view_projection(
+                &(projection.get_projection_matrix() * view.compute_matrix().inverse()),
+            )
+        };
+
+        // Looking down -z from the middle of chunk 0,0,0
+        let camera = Transform::from_xyz(half, half, half);
+        let frustum = frustum_for(camera);
+        assert!(chunk_in_view(&frustum, &aabb, chunk(0, 0, 0)));
+        assert!(chunk_in_view(&frustum, &aabb, chunk(0, 0, -5)));
+        assert!(!chunk_in_view(&frustum, &aabb, chunk(0, 0, 3)));
+        assert!(!chunk_in_view(&frustum, &aabb, chunk(8, 0, -1)));
+        // Off to the side but reaching into the edge of the view
+        assert!(chunk_in_view(&frustum, &aabb, chunk(3, 0, -4)));
+
+        // Turning around swaps them without moving
+        let turned = frustum_for(camera.with_rotation(Quat::from_rotation_y(std::f32::consts::PI)));
+        assert!(!chunk_in_view(&turned, &aabb, chunk(0, 0, -5)));
+        assert!(chunk_in_view(&turned, &aabb, chunk(0, 0, 3)));
+    }
+}
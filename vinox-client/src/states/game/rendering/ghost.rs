@@ -0,0 +1,158 @@
+use bevy::{
+    pbr::{NotShadowCaster, NotShadowReceiver},
+    prelude::*,
+    render::mesh::VertexAttributeValues,
+};
+use vinox_common::world::chunks::{
+    positions::WorldOffset,
+    storage::{BlockData, BlockTable},
+};
+
+use crate::states::{
+    assets::load::LoadableAssets,
+    components::SessionScoped,
+    game::input::{look::CursorGrab, player::PlacementPreview},
+};
+
+use super::meshing::{block_mesh, ChunkMaterial, GeometryTable};
+
+pub const GHOST_ALPHA: f32 = 0.45;
+// Multiplied into the block's own colours when a click wouldn't place it
+pub const BLOCKED_TINT: Color = Color::rgba(1.0, 0.3, 0.3, GHOST_ALPHA);
+// A little bigger than the block so it doesn't fight with the faces around it
+pub const GHOST_SCALE: f32 = 1.002;
+
+// The block a right click would put down, drawn where it would go
+#[derive(Component, Default)]
+pub struct PlacementGhost {
+    shown: Option<BlockData>,
+}
+
+// Built from the chunk material the first time there's a ghost to draw, the atlas only exists
+// once the game is running
+#[derive(Resource, Default)]
+pub struct GhostMaterials {
+    clear: Handle<StandardMaterial>,
+    blocked: Handle<StandardMaterial>,
+}
+
+fn ghost_material(
+    materials: &mut Assets<StandardMaterial>,
+    template: &Handle<StandardMaterial>,
+    color: Color,
+) -> Handle<StandardMaterial> {
+    let base = materials.get(template).cloned().unwrap_or_default();
+    materials.add(StandardMaterial {
+        base_color: color,
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..base
+    })
+}
+
+// Only the mesh is rebuilt when the block changes, moving it or turning it red is free. The
+// preview is set every frame by interact so switching slots or variants shows straight away
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub fn update_placement_ghost(
+    mut commands: Commands,
+    mut ghosts: Query<(
+        &mut PlacementGhost,
+        &mut Handle<Mesh>,
+        &mut Handle<StandardMaterial>,
+        &mut Transform,
+        &mut Visibility,
+    )>,
+    (preview, grab, offset): (Res<PlacementPreview>, Res<CursorGrab>, Res<WorldOffset>),
+    (block_table, geo_table): (Res<BlockTable>, Res<GeometryTable>),
+    (loadable_assets, atlases, chunk_material): (
+        Res<LoadableAssets>,
+        Res<Assets<TextureAtlas>>,
+        Res<ChunkMaterial>,
+    ),
+    mut ghost_materials: ResMut<GhostMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let Ok((mut ghost, mut mesh_handle, mut material_handle, mut transform, mut visibility)) =
+        ghosts.get_single_mut()
+    else {
+        commands.spawn((
+            PbrBundle {
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            NotShadowCaster,
+            NotShadowReceiver,
+            PlacementGhost::default(),
+            SessionScoped,
+        ));
+        return;
+    };
+    let preview = preview.0.as_ref().filter(|_| grab.is_grabbed());
+    let block = preview.map(|preview| preview.block.clone());
+    if block != ghost.shown {
+        let Some(atlas) = atlases.get(&loadable_assets.block_atlas) else {
+            return;
+        };
+        ghost.shown = block.clone();
+        *mesh_handle = Handle::default();
+        if let Some(block) = block {
+            let (opaque, transparent) = block_mesh(
+                block,
+                IVec3::ZERO,
+                &block_table,
+                &geo_table,
+                &loadable_assets,
+                atlas,
+            );
+            let mut mesh = if opaque.count_vertices() > 0 {
+                opaque
+            } else {
+                transparent
+            };
+            // Centered so the scale grows it evenly
+            if let Some(VertexAttributeValues::Float32x3(positions)) =
+                mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+            {
+                for position in positions.iter_mut() {
+                    for axis in position.iter_mut() {
+                        *axis -= 0.5;
+                    }
+                }
+            }
+            *mesh_handle = meshes.add(mesh);
+        }
+    }
+    let wanted = match preview {
+        Some(preview) if *mesh_handle != Handle::default() => {
+            if ghost_materials.clear == Handle::default() {
+                *ghost_materials = GhostMaterials {
+                    clear: ghost_material(
+                        &mut materials,
+                        &chunk_material.opaque,
+                        Color::rgba(1.0, 1.0, 1.0, GHOST_ALPHA),
+                    ),
+                    blocked: ghost_material(&mut materials, &chunk_material.opaque, BLOCKED_TINT),
+                };
+            }
+            let material = if preview.blocked {
+                &ghost_materials.blocked
+            } else {
+                &ghost_materials.clear
+            };
+            if *material_handle != *material {
+                *material_handle = material.clone();
+            }
+            *transform = Transform::from_translation(
+                offset.voxel_to_render(preview.voxel).as_vec3() + Vec3::splat(0.5),
+            )
+            .with_scale(Vec3::splat(GHOST_SCALE));
+            Visibility::Visible
+        }
+        _ => Visibility::Hidden,
+    };
+    if *visibility != wanted {
+        *visibility = wanted;
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, MeshVertexAttributeId, VertexAttributeValues},
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use bevy_egui::EguiUserTextures;
+use vinox_common::world::chunks::storage::{BlockData, BlockTable, ItemTable};
+
+use crate::states::assets::load::LoadableAssets;
+
+use super::{
+    memory::{image_bytes, MemoryBudget},
+    meshing::{block_mesh, GeometryTable},
+};
+
+pub const ICON_SIZE: u32 = 64;
+// A few per frame keeps loading responsive, each one runs the mesher on its own
+pub const ICONS_PER_FRAME: usize = 8;
+
+// Block items get a picture of the block itself, everything else keeps its flat texture
+#[derive(Resource, Default)]
+pub struct ItemIconCache {
+    icons: HashMap<String, Handle<Image>>,
+    // Item identifier and the block it places, for everything still waiting on an icon
+    pending: Vec<(String, String)>,
+    total: usize,
+    queued: bool,
+}
+
+impl ItemIconCache {
+    pub fn get<'a>(
+        &'a self,
+        identifier: &str,
+        loadable_assets: &'a LoadableAssets,
+    ) -> Option<&'a Handle<Image>> {
+        self.icons
+            .get(identifier)
+            .or_else(|| loadable_assets.item_textures.get(identifier))
+    }
+
+    // Queues every block item again, handing back the old icons so they can be let go of
+    pub fn invalidate(&mut self, item_table: &ItemTable) -> Vec<Handle<Image>> {
+        self.pending = item_table
+            .iter()
+            .filter_map(|(identifier, item)| {
+                Some((identifier.clone(), item.associated_block.clone()?))
+            })
+            .collect();
+        self.total = self.pending.len();
+        self.queued = true;
+        self.icons.drain().map(|(_, icon)| icon).collect()
+    }
+
+    pub fn is_baked(&self) -> bool {
+        self.queued && self.pending.is_empty()
+    }
+
+    pub fn progress(&self) -> (usize, usize) {
+        (self.total - self.pending.len(), self.total)
+    }
+
+    // Works through up to budget pending items, anything bake turns down keeps its flat icon
+    pub fn bake_step(
+        &mut self,
+        budget: usize,
+        mut bake: impl FnMut(&str) -> Option<Handle<Image>>,
+    ) {
+        for _ in 0..budget {
+            let Some((identifier, block)) = self.pending.pop() else {
+                return;
+            };
+            if let Some(icon) = bake(&block) {
+                self.icons.insert(identifier, icon);
+            }
+        }
+    }
+}
+
+// Looking down at the corner between the east and south faces, so the top and two sides show
+fn view_basis() -> (Vec3, Vec3, Vec3) {
+    let toward_camera = Vec3::ONE.normalize();
+    let right = Vec3::Y.cross(toward_camera).normalize();
+    let up = toward_camera.cross(right);
+    (right, up, toward_camera)
+}
+
+// Sides a little darker than the top so the edges read without any lighting
+fn face_shade(normal: Vec3) -> f32 {
+    0.55 + 0.45 * normal.y.max(0.0) + 0.25 * normal.x.max(0.0)
+}
+
+fn attribute_3(mesh: &Mesh, attribute: impl Into<MeshVertexAttributeId>) -> Option<&Vec<[f32; 3]>> {
+    match mesh.attribute(attribute)? {
+        VertexAttributeValues::Float32x3(values) => Some(values),
+        _ => None,
+    }
+}
+
+// Software rasterizes the mesh into pixels, which is small enough to not be worth a render pass.
+// Texels under half alpha are left out like the chunk material's mask
+fn rasterize(mesh: &Mesh, atlas_image: &Image, pixels: &mut [u8], depth: &mut [f32]) {
+    let (Some(positions), Some(normals)) = (
+        attribute_3(mesh, Mesh::ATTRIBUTE_POSITION),
+        attribute_3(mesh, Mesh::ATTRIBUTE_NORMAL),
+    ) else {
+        return;
+    };
+    let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0) else {
+        return;
+    };
+    let colors = match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+        Some(VertexAttributeValues::Float32x4(colors)) => Some(colors),
+        _ => None,
+    };
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(Indices::U32(indices)) => indices.iter().map(|index| *index as usize).collect(),
+        Some(Indices::U16(indices)) => indices.iter().map(|index| *index as usize).collect(),
+        None => (0..positions.len()).collect(),
+    };
+    let atlas_size = atlas_image.size();
+    let (atlas_width, atlas_height) = (atlas_size.x as usize, atlas_size.y as usize);
+    if atlas_image.data.len() < atlas_width * atlas_height * 4 {
+        return;
+    }
+
+    let (right, up, toward_camera) = view_basis();
+    let size = ICON_SIZE as f32;
+    // The cube's corners land within 0.82 of the middle, leave a pixel or two of border
+    let scale = size / 2.0 / 0.85;
+    let project = |position: [f32; 3]| {
+        let centered = Vec3::from(position) - Vec3::splat(0.5);
+        Vec3::new(
+            size / 2.0 + centered.dot(right) * scale,
+            size / 2.0 - centered.dot(up) * scale,
+            -centered.dot(toward_camera),
+        )
+    };
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+        if [a, b, c].iter().any(|index| *index >= positions.len()) {
+            continue;
+        }
+        let normal = Vec3::from(normals[a]);
+        if normal.dot(toward_camera) <= 0.0 {
+            continue;
+        }
+        let shade = face_shade(normal);
+        let (pa, pb, pc) = (
+            project(positions[a]),
+            project(positions[b]),
+            project(positions[c]),
+        );
+        let area = (pb.x - pa.x) * (pc.y - pa.y) - (pb.y - pa.y) * (pc.x - pa.x);
+        if area.abs() < f32::EPSILON {
+            continue;
+        }
+        let min_x = pa.x.min(pb.x).min(pc.x).floor().max(0.0) as u32;
+        let max_x = pa.x.max(pb.x).max(pc.x).ceil().min(size - 1.0) as u32;
+        let min_y = pa.y.min(pb.y).min(pc.y).floor().max(0.0) as u32;
+        let max_y = pa.y.max(pb.y).max(pc.y).ceil().min(size - 1.0) as u32;
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                // Barycentric weights, divided by the area so either winding works
+                let wa = ((pb.x - px) * (pc.y - py) - (pb.y - py) * (pc.x - px)) / area;
+                let wb = ((pc.x - px) * (pa.y - py) - (pc.y - py) * (pa.x - px)) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let pixel = (y * ICON_SIZE + x) as usize;
+                let z = pa.z * wa + pb.z * wb + pc.z * wc;
+                if z >= depth[pixel] {
+                    continue;
+                }
+                let u = uvs[a][0] * wa + uvs[b][0] * wb + uvs[c][0] * wc;
+                let v = uvs[a][1] * wa + uvs[b][1] * wb + uvs[c][1] * wc;
+                let texel_x = ((u * atlas_width as f32) as usize).min(atlas_width - 1);
+                let texel_y = ((v * atlas_height as f32) as usize).min(atlas_height - 1);
+                let texel = (texel_y * atlas_width + texel_x) * 4;
+                let texel = &atlas_image.data[texel..texel + 4];
+                if texel[3] < 128 {
+                    continue;
+                }
+                // Vertex colors carry the tint, a lone block has no light or AO to fold in
+                let tint = colors.map_or([1.0; 3], |colors| {
+                    [0, 1, 2].map(|channel| {
+                        (colors[a][channel] * wa
+                            + colors[b][channel] * wb
+                            + colors[c][channel] * wc)
+                            .clamp(0.0, 1.0)
+                    })
+                });
+                depth[pixel] = z;
+                for channel in 0..3 {
+                    pixels[pixel * 4 + channel] =
+                        (texel[channel] as f32 * tint[channel] * shade) as u8;
+                }
+                pixels[pixel * 4 + 3] = 255;
+            }
+        }
+    }
+}
+
+pub fn block_icon(
+    block: BlockData,
+    block_table: &BlockTable,
+    geo_table: &GeometryTable,
+    loadable_assets: &LoadableAssets,
+    atlas: &TextureAtlas,
+    atlas_image: &Image,
+) -> Image {
+    let (opaque, transparent) = block_mesh(
+        block,
+        IVec3::ZERO,
+        block_table,
+        geo_table,
+        loadable_assets,
+        atlas,
+    );
+    let texels = (ICON_SIZE * ICON_SIZE) as usize;
+    let mut pixels = vec![0; texels * 4];
+    let mut depth = vec![f32::INFINITY; texels];
+    for mesh in [&opaque, &transparent] {
+        rasterize(mesh, atlas_image, &mut pixels, &mut depth);
+    }
+    Image::new(
+        Extent3d {
+            width: ICON_SIZE,
+            height: ICON_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        pixels,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+// Needs the block atlas, so this waits on loading to build it and then bakes while the loading
+// screen is still up. Loading refills the tables, which is what queues the icons, and anything
+// swapping descriptors in game gets them rebaked the same way
+#[allow(clippy::too_many_arguments)]
+pub fn bake_item_icons(
+    mut cache: ResMut<ItemIconCache>,
+    item_table: Res<ItemTable>,
+    block_table: Res<BlockTable>,
+    geo_table: Res<GeometryTable>,
+    loadable_assets: Res<LoadableAssets>,
+    atlases: Res<Assets<TextureAtlas>>,
+    mut images: ResMut<Assets<Image>>,
+    mut egui_textures: ResMut<EguiUserTextures>,
+    mut budget: ResMut<MemoryBudget>,
+) {
+    if item_table.is_changed() || block_table.is_changed() {
+        for icon in cache.invalidate(&item_table) {
+            egui_textures.remove_image(&icon);
+        }
+        budget.clear_icons();
+    }
+    if cache.is_baked() {
+        return;
+    }
+    let Some(atlas) = atlases.get(&loadable_assets.block_atlas) else {
+        return;
+    };
+    let Some(atlas_image) = images.get(&atlas.texture).cloned() else {
+        return;
+    };
+    cache.bake_step(ICONS_PER_FRAME, |block| {
+        // The mesher expects every block it sees to have textures, anything else stays flat
+        let descriptor = block_table.get(block)?;
+        loadable_assets.block_textures.get(block)?;
+        let icon = block_icon(
+            BlockData::new(descriptor.namespace.clone(), descriptor.name.clone()),
+            &block_table,
+            &geo_table,
+            &loadable_assets,
+            atlas,
+            &atlas_image,
+        );
+        budget.add_icon(image_bytes(&icon));
+        let handle = images.add(icon);
+        egui_textures.add_image(handle.clone_weak());
+        Some(handle)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::asset::HandleId;
+    use vinox_common::{
+        storage::{
+            blocks::descriptor::{BlockDescriptor, BlockGeometry},
+            geometry::descriptor::{BlockGeo, GeometryDescriptor},
+            items::descriptor::ItemDescriptor,
+        },
+        world::chunks::storage::VoxelVisibility,
+    };
+
+    const GREEN: [u8; 4] = [60, 170, 50, 255];
+    const BROWN: [u8; 4] = [120, 80, 40, 255];
+
+    #[test]
+    fn block_items_get_their_faces() {
+        // A flat green for grass tops and a flat brown for everything dirt, packed like loading does
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Image>();
+        let mut images = app.world.resource_mut::<Assets<Image>>();
+        let [grass, dirt] = [GREEN, BROWN].map(|color| {
+            images.add(Image::new_fill(
+                Extent3d {
+                    width: 16,
+                    height: 16,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                &color,
+                TextureFormat::Rgba8UnormSrgb,
+            ))
+        });
+        let mut builder = TextureAtlasBuilder::default();
+        for texture in [&grass, &dirt] {
+            let image = images.get(texture).unwrap().clone();
+            builder.add_texture(texture.clone(), &image);
+        }
+        let atlas = builder.finish(&mut images).unwrap();
+        let atlas_image = images.get(&atlas.texture).unwrap().clone();
+
+        let mut block_table = BlockTable::default();
+        let mut item_table = ItemTable::default();
+        let mut loadable_assets = LoadableAssets::default();
+        for (name, textures) in [
+            ("air", std::array::from_fn(|_| dirt.clone())),
+            (
+                "grass",
+                [
+                    grass.clone(),
+                    dirt.clone(),
+                    dirt.clone(),
+                    dirt.clone(),
+                    dirt.clone(),
+                    dirt.clone(),
+                ],
+            ),
+            ("dirt", std::array::from_fn(|_| dirt.clone())),
+        ] {
+            let identifier = format!("vinox:{name}");
+            block_table.insert(
+                identifier.clone(),
+                BlockDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    visibility: Some(if name == "air" {
+                        VoxelVisibility::Empty
+                    } else {
+                        VoxelVisibility::Opaque
+                    }),
+                    ..Default::default()
+                },
+            );
+            loadable_assets
+                .block_textures
+                .insert(identifier.clone(), textures);
+            if name != "air" {
+                item_table.insert(
+                    identifier.clone(),
+                    ItemDescriptor {
+                        namespace: "vinox".to_string(),
+                        name: name.to_string(),
+                        associated_block: Some(identifier),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        item_table.insert(
+            "vinox:stick".to_string(),
+            ItemDescriptor {
+                namespace: "vinox".to_string(),
+                name: "stick".to_string(),
+                ..Default::default()
+            },
+        );
+        let stick_texture: Handle<Image> = Handle::weak(HandleId::random::<Image>());
+        loadable_assets
+            .item_textures
+            .insert("vinox:stick".to_string(), stick_texture.clone());
+        let mut geo_table = GeometryTable::default();
+        geo_table.insert(
+            BlockGeometry::Block.get_geo_namespace(),
+            GeometryDescriptor {
+                namespace: "vinox".to_string(),
+                name: "block".to_string(),
+                blocks: [true; 6],
+                element: BlockGeo::default(),
+            },
+        );
+
+        let mut cache = ItemIconCache::default();
+        assert!(!cache.is_baked());
+        cache.invalidate(&item_table);
+        assert_eq!(cache.progress(), (0, 2));
+        let mut baked = HashMap::new();
+        while !cache.is_baked() {
+            cache.bake_step(1, |block| {
+                let icon = block_icon(
+                    BlockData::new(
+                        "vinox".to_string(),
+                        block.trim_start_matches("vinox:").into(),
+                    ),
+                    &block_table,
+                    &geo_table,
+                    &loadable_assets,
+                    &atlas,
+                    &atlas_image,
+                );
+                let handle = Handle::weak(HandleId::random::<Image>());
+                baked.insert(block.to_string(), (handle.clone(), icon));
+                Some(handle)
+            });
+        }
+        assert_eq!(cache.progress(), (2, 2));
+        for block in ["vinox:grass", "vinox:dirt"] {
+            assert_eq!(cache.get(block, &loadable_assets), Some(&baked[block].0));
+        }
+        // No block, so the flat texture it already had
+        assert_eq!(
+            cache.get("vinox:stick", &loadable_assets),
+            Some(&stick_texture)
+        );
+
+        // Green on top, brown down the sides, and nothing drawn in the corners
+        let pixels = &baked["vinox:grass"].1.data;
+        let opaque: Vec<&[u8]> = pixels
+            .chunks_exact(4)
+            .filter(|pixel| pixel[3] == 255)
+            .collect();
+        assert!(opaque.iter().any(|pixel| pixel[1] > pixel[0] + 40));
+        assert!(opaque.iter().any(|pixel| pixel[0] > pixel[1] + 20));
+        assert!(opaque.len() < pixels.len() / 4);
+        assert!(opaque.iter().any(|pixel| pixel[..3] != opaque[0][..3]));
+        // Dirt is one texture all round, only the shading tells its faces apart
+        assert!(baked["vinox:dirt"]
+            .1
+            .data
+            .chunks_exact(4)
+            .filter(|pixel| pixel[3] == 255)
+            .all(|pixel| pixel[0] > pixel[1]));
+
+        // Changing the tables starts over
+        cache.invalidate(&item_table);
+        assert!(!cache.is_baked());
+        assert_eq!(cache.get("vinox:grass", &loadable_assets), None);
+    }
+}
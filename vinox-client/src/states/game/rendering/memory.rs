@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    render::mesh::{Indices, Mesh},
+};
+use bevy_egui::{egui, EguiContexts};
+use sysinfo::{System, SystemExt};
+use vinox_common::world::chunks::{
+    ecs::{NeedsMesh, ViewRadius},
+    positions::ChunkPos,
+};
+
+use crate::states::{components::GameOptions, game::world::chunks::PlayerChunk};
+
+use super::autotune::AutoTune;
+
+pub const MIB: u64 = 1024 * 1024;
+// Pressure never pulls the view in closer than this
+pub const MIN_VIEW_RADIUS: i32 = 3;
+// A ring only comes back once usage with it back in would be this far under the cap, so a
+// radius that sits right at the cap doesn't flip back and forth
+pub const RECOVER_FRACTION: f64 = 0.85;
+// Seconds between steps, what the last one let go of takes a few frames to actually go
+pub const STEP_COOLDOWN: f64 = 2.0;
+
+// A quarter of the machine, the rest is the OS, the server thread and the CPU side of everything
+pub fn default_soft_cap(total_memory: u64) -> u64 {
+    (total_memory / 4).clamp(512 * MIB, 4096 * MIB)
+}
+
+pub fn mesh_bytes(mesh: &Mesh) -> u64 {
+    let indices = match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.len() * 2,
+        Some(Indices::U32(indices)) => indices.len() * 4,
+        None => 0,
+    };
+    mesh.count_vertices() as u64 * mesh.get_vertex_size() + indices as u64
+}
+
+pub fn image_bytes(image: &Image) -> u64 {
+    image.data.len() as u64
+}
+
+fn chunks_in(horizontal: i32, vertical: i32) -> u64 {
+    let (horizontal, vertical) = (horizontal.max(0) as u64, vertical.max(0) as u64);
+    (2 * horizontal + 1).pow(2) * (2 * vertical + 1)
+}
+
+// Estimated bytes of what's been handed to the GPU. Counted where things get created and let go
+// of rather than measured, so it's only as good as those few call sites
+#[derive(Resource, Debug, Clone)]
+pub struct MemoryBudget {
+    auto_cap: u64,
+    cap_override: Option<u64>,
+    textures: u64,
+    icons: u64,
+    meshes: HashMap<IVec3, u64>,
+    mesh_total: u64,
+    // Rings pulled in, each drops the far meshes and shrinks the view with them so nothing inside
+    // the fog is left unmeshed
+    steps: i32,
+    last_step: Option<f64>,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        let mut system = System::new();
+        system.refresh_memory();
+        Self::with_cap(default_soft_cap(system.total_memory()))
+    }
+}
+
+impl MemoryBudget {
+    pub fn with_cap(auto_cap: u64) -> Self {
+        Self {
+            auto_cap,
+            cap_override: None,
+            textures: 0,
+            icons: 0,
+            meshes: HashMap::new(),
+            mesh_total: 0,
+            steps: 0,
+            last_step: None,
+        }
+    }
+
+    pub fn soft_cap(&self) -> u64 {
+        self.cap_override.unwrap_or(self.auto_cap)
+    }
+
+    // 0 goes back to the cap worked out from system memory
+    pub fn set_cap_mib(&mut self, mib: u32) {
+        self.cap_override = (mib > 0).then_some(mib as u64 * MIB);
+    }
+
+    pub fn used(&self) -> u64 {
+        self.textures + self.icons + self.mesh_total
+    }
+
+    pub fn set_textures(&mut self, bytes: u64) {
+        self.textures = bytes;
+    }
+
+    pub fn add_icon(&mut self, bytes: u64) {
+        self.icons += bytes;
+    }
+
+    pub fn clear_icons(&mut self) {
+        self.icons = 0;
+    }
+
+    // Replaces whatever the chunk had before, remeshing doesn't add up
+    pub fn set_mesh(&mut self, chunk: IVec3, bytes: u64) {
+        let old = self.meshes.insert(chunk, bytes).unwrap_or_default();
+        self.mesh_total = self.mesh_total - old + bytes;
+    }
+
+    pub fn free_mesh(&mut self, chunk: IVec3) {
+        if let Some(old) = self.meshes.remove(&chunk) {
+            self.mesh_total -= old;
+        }
+    }
+
+    pub fn clear_meshes(&mut self) {
+        self.meshes.clear();
+        self.mesh_total = 0;
+    }
+
+    // Textures and icons belong to loading, they stay counted until it replaces them
+    pub fn end_session(&mut self) {
+        self.clear_meshes();
+        self.steps = 0;
+        self.last_step = None;
+    }
+
+    pub fn steps(&self) -> i32 {
+        self.steps
+    }
+
+    fn max_steps(base: i32) -> i32 {
+        (base - MIN_VIEW_RADIUS).max(0)
+    }
+
+    // Meshes past this are let go of, the chunk data stays
+    pub fn view_radius(&self, base: i32) -> i32 {
+        base - self.steps
+    }
+
+    pub fn view_reduced(&self) -> bool {
+        self.steps > 0
+    }
+
+    // Usage with one more ring meshed, at the meshes' current average size. Every chunk in the
+    // ring is counted even though empty ones have no mesh, erring toward staying pulled in
+    pub fn projected_used(&self, base: &ViewRadius) -> u64 {
+        if self.meshes.is_empty() {
+            return self.used();
+        }
+        let average = self.mesh_total / self.meshes.len() as u64;
+        let radius = self.view_radius(base.horizontal);
+        let ring = chunks_in(radius + 1, base.vertical) - chunks_in(radius, base.vertical);
+        self.used() + average * ring
+    }
+
+    // Moves at most one step, returns whether it did
+    pub fn govern(&mut self, now: f64, base: &ViewRadius) -> bool {
+        if self
+            .last_step
+            .is_some_and(|last| now - last < STEP_COOLDOWN)
+        {
+            return false;
+        }
+        let cap = self.soft_cap();
+        if self.used() > cap && self.steps < Self::max_steps(base.horizontal) {
+            self.steps += 1;
+        } else if self.steps > 0
+            && (self.projected_used(base) as f64) < cap as f64 * RECOVER_FRACTION
+        {
+            self.steps -= 1;
+        } else {
+            return false;
+        }
+        self.last_step = Some(now);
+        true
+    }
+}
+
+#[derive(Component)]
+pub struct Evicted;
+
+pub fn apply_memory_options(options: Res<GameOptions>, mut budget: ResMut<MemoryBudget>) {
+    if options.is_changed() {
+        budget.set_cap_mib(options.memory_cap_mib);
+    }
+}
+
+// The fog follows the radius through apply_ambience. Works from what auto-tune leaves, which
+// stays put while there's anything pulled in here
+pub fn govern_memory(
+    mut budget: ResMut<MemoryBudget>,
+    time: Res<Time>,
+    (view_radius, tune): (Res<ViewRadius>, Res<AutoTune>),
+) {
+    let base = ViewRadius {
+        horizontal: tune.view_radius(view_radius.horizontal),
+        vertical: view_radius.vertical,
+    };
+    budget.govern(time.elapsed_seconds_f64(), &base);
+}
+
+// Meshes past the meshed radius go and come back once it grows again. The chunks themselves
+// stay loaded, the server has no way to send them again without the player walking off
+pub fn evict_far_meshes(
+    mut commands: Commands,
+    mut budget: ResMut<MemoryBudget>,
+    (view_radius, tune): (Res<ViewRadius>, Res<AutoTune>),
+    player_chunk: Res<PlayerChunk>,
+    meshed: Query<(Entity, &ChunkPos), With<Handle<Mesh>>>,
+    evicted: Query<(Entity, &ChunkPos), With<Evicted>>,
+    mut applied: Local<Option<(i32, IVec3)>>,
+) {
+    let radius = ViewRadius {
+        horizontal: budget.view_radius(tune.view_radius(view_radius.horizontal)),
+        vertical: view_radius.vertical,
+    };
+    if *applied == Some((radius.horizontal, player_chunk.chunk_pos)) {
+        return;
+    }
+    *applied = Some((radius.horizontal, player_chunk.chunk_pos));
+    for (entity, chunk_pos) in meshed.iter() {
+        if !player_chunk.is_in_radius(**chunk_pos, &radius) {
+            commands
+                .entity(entity)
+                .despawn_descendants()
+                .remove::<Handle<Mesh>>()
+                .insert(Evicted);
+            budget.free_mesh(**chunk_pos);
+        }
+    }
+    for (entity, chunk_pos) in evicted.iter() {
+        if player_chunk.is_in_radius(**chunk_pos, &radius) {
+            commands
+                .entity(entity)
+                .remove::<Evicted>()
+                .insert(NeedsMesh);
+        }
+    }
+}
+
+pub fn memory_notice(mut contexts: EguiContexts, budget: Res<MemoryBudget>) {
+    if !budget.view_reduced() {
+        return;
+    }
+    egui::Area::new("memory_notice")
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 8.0])
+        .interactable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.colored_label(
+                egui::Color32::from_rgb(249, 226, 175),
+                "View distance reduced due to memory pressure",
+            );
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounting_follows_allocations() {
+        let mut budget = MemoryBudget::with_cap(100 * MIB);
+        budget.set_textures(10 * MIB);
+        budget.add_icon(MIB);
+        budget.add_icon(MIB);
+        budget.set_mesh(IVec3::ZERO, 4 * MIB);
+        budget.set_mesh(IVec3::X, 2 * MIB);
+        assert_eq!(budget.used(), 18 * MIB);
+
+        // Remeshing replaces, freeing twice or something never counted does nothing
+        budget.set_mesh(IVec3::ZERO, 3 * MIB);
+        budget.free_mesh(IVec3::X);
+        budget.free_mesh(IVec3::X);
+        budget.free_mesh(IVec3::Y);
+        assert_eq!(budget.used(), 15 * MIB);
+
+        budget.clear_icons();
+        budget.end_session();
+        assert_eq!(budget.used(), 10 * MIB);
+
+        assert_eq!(budget.soft_cap(), 100 * MIB);
+        budget.set_cap_mib(64);
+        assert_eq!(budget.soft_cap(), 64 * MIB);
+        budget.set_cap_mib(0);
+        assert_eq!(budget.soft_cap(), 100 * MIB);
+        assert_eq!(default_soft_cap(4096 * MIB), 1024 * MIB);
+        assert_eq!(default_soft_cap(MIB), 512 * MIB);
+    }
+
+    // Every chunk inside the meshed radius has a mesh of the same size, like a flat world would
+    fn simulate(
+        budget: &mut MemoryBudget,
+        base: &ViewRadius,
+        from: f64,
+        frames: usize,
+    ) -> Vec<i32> {
+        const CHUNK_MESH: u64 = 256 * 1024;
+        let mut steps = Vec::new();
+        for frame in 0..frames {
+            budget.govern(from + frame as f64 * 0.1, base);
+            let radius = budget.view_radius(base.horizontal);
+            for x in -base.horizontal..=base.horizontal {
+                for z in -base.horizontal..=base.horizontal {
+                    for y in -base.vertical..=base.vertical {
+                        let chunk = IVec3::new(x, y, z);
+                        if x.abs().max(z.abs()) <= radius {
+                            budget.set_mesh(chunk, CHUNK_MESH);
+                        } else {
+                            budget.free_mesh(chunk);
+                        }
+                    }
+                }
+            }
+            steps.push(budget.steps());
+        }
+        steps
+    }
+
+    #[test]
+    fn steps_down_once_and_holds() {
+        let base = ViewRadius {
+            horizontal: 10,
+            vertical: 4,
+        };
+        // Radius 8 comes to 650 MiB and 7 to 506, so 7 is where it should settle
+        let mut budget = MemoryBudget::with_cap(600 * MIB);
+        let steps = simulate(&mut budget, &base, 0.0, 300);
+        let changes = steps.windows(2).filter(|pair| pair[0] != pair[1]).count();
+        assert_eq!(changes, 3);
+        assert!(steps.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_eq!(budget.view_radius(base.horizontal), 7);
+        assert!(budget.view_reduced());
+        assert!(budget.used() <= budget.soft_cap());
+
+        // More room and it works its way back out, one ring at a time
+        budget.set_cap_mib(2048);
+        let steps = simulate(&mut budget, &base, 30.0, 300);
+        assert!(steps.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(budget.steps(), 0);
+        assert!(!budget.view_reduced());
+    }
+
+    #[test]
+    fn never_pulls_in_past_the_minimum() {
+        let base = ViewRadius {
+            horizontal: 5,
+            vertical: 2,
+        };
+        // Textures alone are over, nothing meshes can do about it
+        let mut budget = MemoryBudget::with_cap(MIB);
+        budget.set_textures(2 * MIB);
+        simulate(&mut budget, &base, 0.0, 200);
+        assert_eq!(budget.view_radius(base.horizontal), MIN_VIEW_RADIUS);
+    }
+}
@@ -15,30 +15,50 @@ use bevy_tweening::{lens::TransformPositionLens, *};
 use futures_lite::future;
 use itertools::Itertools;
 use rand::{rngs::StdRng, Rng, SeedableRng};
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 // use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
 use serde_big_array::Array;
-use std::{ops::Deref, time::Duration};
+use std::{
+    ops::Deref,
+    time::{Duration, Instant},
+};
 
 use vinox_common::{
-    storage::geometry::descriptor::{BlockGeo, GeometryDescriptor},
-    world::chunks::{
-        ecs::{ChunkManager, CurrentChunks, NeedsMesh, PriorityMesh},
-        positions::{voxel_to_world, world_to_global_voxel, ChunkPos},
-        storage::{
-            self, trim_geo_identifier, BlockTable, ChunkData, RenderedBlockData, VoxelVisibility,
-            CHUNK_SIZE,
+    storage::{
+        biomes::climate::{climate_at, tint_color, Climate},
+        blocks::descriptor::TintKind,
+        geometry::descriptor::{BlockGeo, GeometryDescriptor},
+    },
+    world::{
+        chunks::{
+            ecs::{ChunkManager, CurrentChunks, NeedsMesh, PriorityMesh, ViewRadius},
+            positions::{world_to_global_voxel, ChunkPos, WorldOffset},
+            storage::{
+                self, trim_geo_identifier, BlockData, BlockTable, ChunkData, RenderedBlockData,
+                VoxelVisibility, CHUNK_SIZE,
+            },
         },
+        decoration::{decoration_offset, is_decorated},
     },
 };
 
 use crate::states::{
     assets::load::LoadableAssets,
-    components::GameOptions,
-    game::world::chunks::{PlayerBlock, PlayerChunk},
+    components::{GameOptions, SessionScoped},
+    game::{
+        input::player::FPSCamera,
+        networking::components::WorldSeed,
+        world::chunks::{PlayerBlock, PlayerChunk},
+    },
 };
 
-use super::chunk::ChunkBoundary;
+use super::{
+    autotune::AutoTune,
+    chunk::ChunkBoundary,
+    memory::{mesh_bytes, Evicted, MemoryBudget},
+    remesh::{MeshGeneration, MeshSettings},
+};
 
 #[derive(Resource, Clone, Default, Deref, DerefMut)]
 pub struct GeometryTable(pub FxHashMap<String, GeometryDescriptor>);
@@ -892,6 +912,80 @@ impl<'a> Face<'a> {
     pub fn voxel(&self) -> [usize; 3] {
         self.quad.voxel
     }
+
+    // Which of the block's six textures this side shows
+    pub fn texture_slot(&self) -> usize {
+        match (self.side.axis, self.side.positive) {
+            (Axis::X, false) => 2,
+            (Axis::X, true) => 3,
+            (Axis::Y, false) => 1,
+            (Axis::Y, true) => 0,
+            (Axis::Z, false) => 5,
+            (Axis::Z, true) => 4,
+        }
+    }
+
+    // Seeds the texture flip for blocks with tex_variance
+    pub fn world_voxel(&self) -> IVec3 {
+        let [x, y, z] = self.voxel();
+        world_to_global_voxel(Vec3::new(x as f32, y as f32, z as f32))
+            .as_vec3()
+            .as_ivec3()
+    }
+
+    // Light in the block the face looks into
+    pub fn light(&self, chunk: &ChunkBoundary) -> u8 {
+        let [x, y, z] = self.voxel();
+        let (x, y, z) = match (self.side.axis, self.side.positive) {
+            (Axis::X, false) => (x - 1, y, z),
+            (Axis::X, true) => (x + 1, y, z),
+            (Axis::Y, false) => (x, y - 1, z),
+            (Axis::Y, true) => (x, y + 1, z),
+            (Axis::Z, false) => (x, y, z - 1),
+            (Axis::Z, true) => (x, y, z + 1),
+        };
+        chunk.voxels()[ChunkBoundary::linearize(x, y, z)].light
+    }
+
+    // Per vertex so the colour blends across a biome border instead of stepping per block
+    pub fn tints(&self, positions: &[[f32; 3]; 4], columns: &TintColumns) -> [[f32; 3]; 4] {
+        let untinted = [[1.0; 3]; 4];
+        let Some(kind) = self.quad.data.tint else {
+            return untinted;
+        };
+        if kind == TintKind::Grass && self.side != Side::new(Axis::Y, true) {
+            return untinted;
+        }
+        positions.map(|position| tint_color(kind, columns.climate(position)))
+    }
+}
+
+// Climate at every vertex column of one chunk, looked up once per mesh build.
+// Column CHUNK_SIZE is the neighbour's column 0 so both sides of a border agree
+pub struct TintColumns {
+    climates: Vec<Climate>,
+}
+
+impl TintColumns {
+    pub fn new(chunk_pos: IVec3) -> Self {
+        let origin = chunk_pos * CHUNK_SIZE as i32;
+        let climates = (0..=CHUNK_SIZE as i32)
+            .flat_map(|z| (0..=CHUNK_SIZE as i32).map(move |x| (x, z)))
+            .map(|(x, z)| climate_at(origin.x + x, origin.z + z))
+            .collect();
+        Self { climates }
+    }
+
+    // Custom geometry can put vertices between columns, those snap to the closest one
+    pub fn column(position: [f32; 3]) -> (usize, usize) {
+        let snap = |value: f32| value.round().clamp(0.0, CHUNK_SIZE as f32) as usize;
+        (snap(position[0]), snap(position[2]))
+    }
+
+    pub fn climate(&self, position: [f32; 3]) -> Climate {
+        let (x, z) = Self::column(position);
+        self.climates[z * (CHUNK_SIZE + 1) + x]
+    }
 }
 
 #[derive(AsBindGroup, TypeUuid, Debug, Clone)]
@@ -917,6 +1011,28 @@ impl Material for BasicMaterial {
     }
 }
 
+// Draws the faces greedy meshing merged, wrapping each one's uvs back into its tile. Lit and
+// fogged like the opaque StandardMaterial so the two halves of a chunk match
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "4b0d6c7e-2f52-4f0b-9a43-8d1f6e3c5a21"]
+pub struct GreedyMaterial {
+    #[uniform(0)]
+    pub atlas_size: Vec2,
+    #[texture(1)]
+    #[sampler(2)]
+    pub color_texture: Option<Handle<Image>>,
+}
+
+impl Material for GreedyMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/greedy_material.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Mask(0.5)
+    }
+}
+
 #[derive(Bundle)]
 pub struct RenderedChunk {
     #[bundle]
@@ -926,34 +1042,99 @@ pub struct RenderedChunk {
 
 #[derive(Default, Resource)]
 pub struct MeshQueue {
-    pub mesh: Vec<(IVec3, ChunkData, Box<Array<ChunkData, 26>>)>,
-    pub priority: Vec<(IVec3, ChunkData, Box<Array<ChunkData, 26>>)>,
+    // Nearest first, each with the stamp it was given
+    pub mesh: Vec<(IVec3, ChunkData, Box<Array<ChunkData, 26>>, u64)>,
+    pub priority: Vec<(IVec3, ChunkData, Box<Array<ChunkData, 26>>, u64)>,
+    // Since joining, chunks build_mesh queued and ones it let go without a task
+    pub queued: usize,
+    pub skipped: usize,
+    last_stamp: u64,
+}
+
+impl MeshQueue {
+    fn stamp(&mut self) -> u64 {
+        self.last_stamp += 1;
+        self.last_stamp
+    }
 }
 
+// The stamp of the last mesh queued for a chunk. A task finishing with any other one, or while
+// the chunk is waiting on NeedsMesh again, was built from old data and gets dropped
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MeshStamp(pub u64);
+
+// How many ComputeMesh tasks build_mesh lets run at once
+pub fn mesh_task_cap(options: &GameOptions) -> usize {
+    if options.mesh_tasks > 0 {
+        return options.mesh_tasks;
+    }
+    std::thread::available_parallelism().map_or(4, |cpus| cpus.get())
+}
+
+// None when a full remesh started before the task did
 #[derive(Component)]
-pub struct ComputeMesh(Task<MeshedChunk>);
+pub struct ComputeMesh(Task<Option<MeshedChunk>>);
 
 #[derive(Component)]
-pub struct PriorityComputeMesh(Task<MeshedChunk>);
+pub struct PriorityComputeMesh(Task<Option<MeshedChunk>>);
+
+// The greedy and decoration meshes, children of the chunk after the transparent one
+fn spawn_section<M: Material>(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    material: Handle<M>,
+    mesh: Mesh,
+) -> Entity {
+    commands
+        .spawn((
+            MaterialMeshBundle {
+                mesh: meshes.add(mesh),
+                material,
+                ..Default::default()
+            },
+            Aabb {
+                center: Vec3A::splat((CHUNK_SIZE / 2) as f32),
+                half_extents: Vec3A::splat((CHUNK_SIZE / 2) as f32),
+            },
+            NotShadowCaster,
+            NotShadowReceiver,
+        ))
+        .id()
+}
 
+#[allow(clippy::too_many_arguments)]
 pub fn process_priority_task(
     mut commands: Commands,
     mut mesh_tasks: Query<(Entity, &mut PriorityComputeMesh)>,
     mut meshes: ResMut<Assets<Mesh>>,
     chunk_material: Res<ChunkMaterial>,
     current_chunks: Res<CurrentChunks>,
+    stamps: Query<&MeshStamp, Without<NeedsMesh>>,
+    offset: Res<WorldOffset>,
+    mut budget: ResMut<MemoryBudget>,
 ) {
     mesh_tasks.for_each_mut(|(entity, mut task)| {
-        if let Some(chunk) = future::block_on(future::poll_once(&mut task.0)) {
-            if let Some(chunk_entity) = current_chunks.get_entity(chunk.pos) {
+        if let Some(meshed) = future::block_on(future::poll_once(&mut task.0)) {
+            let Some(chunk) = meshed else {
+                commands.entity(entity).despawn_recursive();
+                return;
+            };
+            if let Some(chunk_entity) = current_chunks
+                .get_entity(chunk.pos)
+                .filter(|chunk_entity| chunk.is_current(stamps.get(*chunk_entity).ok()))
+            {
                 commands.entity(chunk_entity).despawn_descendants();
-
-                let chunk_pos = Vec3::new(
-                    (chunk.pos.x * (CHUNK_SIZE) as i32) as f32,
-                    (chunk.pos.y * (CHUNK_SIZE) as i32) as f32,
-                    (chunk.pos.z * (CHUNK_SIZE) as i32) as f32,
+                commands.entity(chunk_entity).insert(chunk.stats.clone());
+                budget.set_mesh(
+                    *chunk.pos,
+                    mesh_bytes(&chunk.chunk_mesh)
+                        + mesh_bytes(&chunk.transparent_mesh)
+                        + chunk.greedy_mesh.as_ref().map_or(0, mesh_bytes)
+                        + chunk.decoration_mesh.as_ref().map_or(0, mesh_bytes),
                 );
 
+                let chunk_pos = offset.chunk_to_render(*chunk.pos);
+
                 let trans_entity = commands
                     .spawn((
                         RenderedChunk {
@@ -1005,7 +1186,26 @@ pub fn process_priority_task(
                     NotShadowReceiver,
                 ));
 
+                // The transparent mesh stays the first child, sort_faces looks for it there
                 commands.entity(chunk_entity).push_children(&[trans_entity]);
+                if let Some(greedy_mesh) = chunk.greedy_mesh {
+                    let greedy_entity = spawn_section(
+                        &mut commands,
+                        &mut meshes,
+                        chunk_material.greedy.clone(),
+                        greedy_mesh,
+                    );
+                    commands.entity(chunk_entity).add_child(greedy_entity);
+                }
+                if let Some(decoration_mesh) = chunk.decoration_mesh {
+                    let decoration_entity = spawn_section(
+                        &mut commands,
+                        &mut meshes,
+                        chunk_material.decoration.clone(),
+                        decoration_mesh,
+                    );
+                    commands.entity(chunk_entity).add_child(decoration_entity);
+                }
                 commands.entity(entity).despawn_recursive();
             } else {
                 commands.entity(entity).despawn_recursive();
@@ -1014,26 +1214,42 @@ pub fn process_priority_task(
     });
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn process_task(
     mut commands: Commands,
     mut mesh_tasks: Query<(Entity, &mut ComputeMesh)>,
     mut meshes: ResMut<Assets<Mesh>>,
     chunk_material: Res<ChunkMaterial>,
-    chunks: Query<&ChunkPos, With<NeedsMesh>>,
+    stamps: Query<&MeshStamp, Without<NeedsMesh>>,
     player_chunk: Res<PlayerChunk>,
     current_chunks: Res<CurrentChunks>,
+    offset: Res<WorldOffset>,
+    mut budget: ResMut<MemoryBudget>,
+    (options, tune): (Res<GameOptions>, Res<AutoTune>),
 ) {
+    let fade_in = tune.tuned_options(&options).chunk_fade_in;
     mesh_tasks.for_each_mut(|(entity, mut task)| {
-        if let Some(chunk) = future::block_on(future::poll_once(&mut task.0)) {
-            if let Some(chunk_entity) = current_chunks.get_entity(chunk.pos) {
+        if let Some(meshed) = future::block_on(future::poll_once(&mut task.0)) {
+            let Some(chunk) = meshed else {
+                commands.entity(entity).despawn_recursive();
+                return;
+            };
+            if let Some(chunk_entity) = current_chunks
+                .get_entity(chunk.pos)
+                .filter(|chunk_entity| chunk.is_current(stamps.get(*chunk_entity).ok()))
+            {
                 commands.entity(chunk_entity).despawn_descendants();
-
-                let chunk_pos = Vec3::new(
-                    (chunk.pos.x * (CHUNK_SIZE) as i32) as f32,
-                    (chunk.pos.y * (CHUNK_SIZE) as i32) as f32,
-                    (chunk.pos.z * (CHUNK_SIZE) as i32) as f32,
+                commands.entity(chunk_entity).insert(chunk.stats.clone());
+                budget.set_mesh(
+                    *chunk.pos,
+                    mesh_bytes(&chunk.chunk_mesh)
+                        + mesh_bytes(&chunk.transparent_mesh)
+                        + chunk.greedy_mesh.as_ref().map_or(0, mesh_bytes)
+                        + chunk.decoration_mesh.as_ref().map_or(0, mesh_bytes),
                 );
 
+                let chunk_pos = offset.chunk_to_render(*chunk.pos);
+
                 let tween = Tween::new(
                     EaseFunction::QuadraticInOut,
                     Duration::from_secs(1),
@@ -1044,7 +1260,7 @@ pub fn process_task(
                 )
                 .with_repeat_count(RepeatCount::Finite(1));
 
-                let chunk_pos = if chunks.get(chunk_entity).is_err()
+                let chunk_pos = if fade_in
                     && chunk
                         .pos
                         .as_vec3()
@@ -1108,7 +1324,26 @@ pub fn process_task(
                     NotShadowReceiver,
                 ));
 
+                // The transparent mesh stays the first child, sort_faces looks for it there
                 commands.entity(chunk_entity).push_children(&[trans_entity]);
+                if let Some(greedy_mesh) = chunk.greedy_mesh {
+                    let greedy_entity = spawn_section(
+                        &mut commands,
+                        &mut meshes,
+                        chunk_material.greedy.clone(),
+                        greedy_mesh,
+                    );
+                    commands.entity(chunk_entity).add_child(greedy_entity);
+                }
+                if let Some(decoration_mesh) = chunk.decoration_mesh {
+                    let decoration_entity = spawn_section(
+                        &mut commands,
+                        &mut meshes,
+                        chunk_material.decoration.clone(),
+                        decoration_mesh,
+                    );
+                    commands.entity(chunk_entity).add_child(decoration_entity);
+                }
                 commands.entity(entity).despawn_recursive();
             } else {
                 commands.entity(entity).despawn_recursive();
@@ -1143,6 +1378,55 @@ pub fn process_task(
 //     }
 // }
 
+pub fn face_visible(
+    voxel: &RenderedBlockData,
+    neighbor: &RenderedBlockData,
+    culled: bool,
+    blocked: bool,
+    solid_pass: bool,
+) -> bool {
+    let visibility = voxel.visibility;
+    let other = neighbor.visibility;
+    if culled && blocked {
+        if solid_pass {
+            match (visibility, other) {
+                (OPAQUE, EMPTY) | (OPAQUE, TRANSPARENT) => true,
+
+                (TRANSPARENT, TRANSPARENT) => voxel.match_index != neighbor.match_index,
+
+                (_, _) => false,
+            }
+        } else {
+            match (visibility, other) {
+                (TRANSPARENT, EMPTY) => true,
+
+                (TRANSPARENT, TRANSPARENT) => voxel.match_index != neighbor.match_index,
+
+                (_, _) => false,
+            }
+        }
+    } else {
+        (visibility == OPAQUE && solid_pass)
+            || (visibility == TRANSPARENT && !solid_pass) && !blocked
+    }
+}
+
+// Two different transparent blocks both want the face between them. Across a chunk border only
+// the chunk lower on that axis emits it, so the pair never z-fights and never both drops it.
+// `coord` is the voxel's boundary coordinate on the face's axis
+pub fn owns_boundary_face(
+    voxel: &RenderedBlockData,
+    neighbor: &RenderedBlockData,
+    side: usize,
+    coord: usize,
+) -> bool {
+    let ambiguous = voxel.visibility == TRANSPARENT
+        && neighbor.visibility == TRANSPARENT
+        && voxel.match_index != neighbor.match_index;
+    let on_lower_border = side.is_multiple_of(2) && coord == 1;
+    !(ambiguous && on_lower_border)
+}
+
 // Possibly have this just fully generate the mesh
 pub fn generate_mesh(chunk: &ChunkBoundary, solid_pass: bool, buffer: &mut QuadGroups) {
     buffer.clear();
@@ -1152,7 +1436,7 @@ pub fn generate_mesh(chunk: &ChunkBoundary, solid_pass: bool, buffer: &mut QuadG
                 let voxel = chunk.voxels()[ChunkBoundary::linearize(x, y, z)];
                 match voxel.visibility {
                     EMPTY => continue,
-                    visibility => {
+                    _ => {
                         let neighbor_block = [
                             chunk.voxels()[ChunkBoundary::linearize(x - 1, y, z)],
                             chunk.voxels()[ChunkBoundary::linearize(x + 1, y, z)],
@@ -1177,33 +1461,12 @@ pub fn generate_mesh(chunk: &ChunkBoundary, solid_pass: bool, buffer: &mut QuadG
                                     5 => neighbor_block[i].blocks[4],
                                     _ => true,
                                 };
-                                let other = neighbor.visibility;
-                                let generate = if culled && blocked {
-                                    if solid_pass {
-                                        match (visibility, other) {
-                                            (OPAQUE, EMPTY) | (OPAQUE, TRANSPARENT) => true,
-
-                                            (TRANSPARENT, TRANSPARENT) => {
-                                                voxel.match_index != neighbor.match_index
-                                            }
-
-                                            (_, _) => false,
-                                        }
-                                    } else {
-                                        match (visibility, other) {
-                                            (TRANSPARENT, EMPTY) => true,
-
-                                            (TRANSPARENT, TRANSPARENT) => {
-                                                voxel.match_index != neighbor.match_index
-                                            }
-
-                                            (_, _) => false,
-                                        }
-                                    }
-                                } else {
-                                    (visibility == OPAQUE && solid_pass)
-                                        || (visibility == TRANSPARENT && !solid_pass) && !blocked
-                                };
+                                let coord = [x, y, z][i / 2];
+                                // Partial shapes don't share a face with whatever is next to them
+                                let generate =
+                                    face_visible(&voxel, neighbor, culled, blocked, solid_pass)
+                                        && (!(culled && blocked)
+                                            || owns_boundary_face(&voxel, neighbor, i, coord));
                                 let origin_one = match i {
                                     0 => cube.origin.1,
                                     1 => cube.origin.1,
@@ -1279,81 +1542,268 @@ pub fn generate_mesh(chunk: &ChunkBoundary, solid_pass: bool, buffer: &mut QuadG
     // buffer
 }
 
+// Vertices of the opaque mesh, shaded by ao, light and tint once they're all in
+#[derive(Default)]
+struct OpaqueVertices {
+    positions: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    ao: Vec<u32>,
+    light: Vec<u8>,
+    tints: Vec<[f32; 3]>,
+}
+
+impl OpaqueVertices {
+    fn push(
+        &mut self,
+        face: &FaceWithAO,
+        positions: [[f32; 3]; 4],
+        uvs: [[f32; 2]; 4],
+        light: u8,
+        columns: &TintColumns,
+    ) {
+        self.indices
+            .extend_from_slice(&face.indices(self.positions.len() as u32));
+        self.tints
+            .extend_from_slice(&face.tints(&positions, columns));
+        self.positions.extend_from_slice(&positions);
+        self.normals.extend_from_slice(&face.normals());
+        self.uvs.extend_from_slice(&uvs);
+        self.ao.extend_from_slice(&face.aos());
+        self.light.extend_from_slice(&[light; 4]);
+    }
+
+    fn into_mesh(self, settings: MeshSettings) -> Mesh {
+        let final_ao = if settings.ambient_occlusion {
+            ao_convert(self.ao)
+        } else {
+            vec![[1.0; 4]; self.ao.len()]
+        };
+        let mut final_color = Vec::new();
+        for (idx, color) in final_ao.iter().enumerate() {
+            let light_level = light_to_inten(self.light[idx]);
+            let tint = self.tints[idx];
+            final_color.extend_from_slice(&[[
+                color[0] * light_level * tint[0],
+                color[1] * light_level * tint[1],
+                color[2] * light_level * tint[2],
+                color[3],
+            ]]);
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, final_color);
+        mesh
+    }
+}
+
+// How greedy quads pack their uvs, greedy_material.wgsl has to agree. Each one is the tile's
+// corner in the atlas in pixels times GREEDY_CELL, plus GREEDY_PAD, plus how many blocks across
+// the quad it is. A quad is at most a chunk wide so that never reaches the next cell, and the
+// shader wraps it back into the one GREEDY_TILE pixel tile
+pub const GREEDY_CELL: f32 = 32.0;
+pub const GREEDY_PAD: f32 = 8.0;
+pub const GREEDY_TILE: f32 = 16.0;
+
+// The two axes in a side's plane, in the order Face::positions calls them one and two
+const PLANE_AXES: [[usize; 2]; 3] = [[1, 2], [0, 2], [0, 1]];
+
+// Faces only merge when every vertex would come out the same
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct GreedyKey {
+    data: RenderedBlockData,
+    aos: [u32; 4],
+    light: u8,
+}
+
+// Opaque faces waiting to be merged, by side then layer along the side's axis then position in
+// its plane
+struct GreedyFaces<'a> {
+    cells: Vec<Option<(GreedyKey, &'a Quad)>>,
+}
+
+impl<'a> Default for GreedyFaces<'a> {
+    fn default() -> Self {
+        Self {
+            cells: vec![None; 6 * CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE],
+        }
+    }
+}
+
+impl<'a> GreedyFaces<'a> {
+    fn cell(side: usize, layer: usize, one: usize, two: usize) -> usize {
+        ((side * CHUNK_SIZE + layer) * CHUNK_SIZE + two) * CHUNK_SIZE + one
+    }
+
+    // Full plain cubes only. Anything shaped, rotated or see through keeps a quad per face, and
+    // so do faces with tex_variance since a merged quad can only show the tile one way round
+    fn take(
+        &mut self,
+        face: &FaceWithAO<'a>,
+        light: u8,
+        chunk: &ChunkBoundary,
+        default_geo: &BlockGeo,
+    ) -> bool {
+        let data = face.quad.data;
+        let side = face.side.axis as usize * 2 + face.side.positive as usize;
+        if data.visibility != OPAQUE
+            || data.tex_variance[side]
+            || chunk.geometry_pal.get(data.geo_index) != Some(default_geo)
+        {
+            return false;
+        }
+        let voxel = face.voxel();
+        let [one, two] = PLANE_AXES[face.side.axis as usize];
+        let key = GreedyKey {
+            data,
+            aos: face.aos(),
+            light,
+        };
+        let cell = Self::cell(
+            side,
+            voxel[face.side.axis as usize] - 1,
+            voxel[one] - 1,
+            voxel[two] - 1,
+        );
+        self.cells[cell] = Some((key, face.face.quad));
+        true
+    }
+
+    fn matches(&self, cell: usize, key: &GreedyKey) -> bool {
+        self.cells[cell].is_some_and(|(other, _)| other == *key)
+    }
+
+    // Grows each quad along one as far as it goes, then along two while every row still matches
+    fn merge(
+        mut self,
+        chunk: &ChunkBoundary,
+        texture_atlas: &TextureAtlas,
+        columns: &TintColumns,
+    ) -> OpaqueVertices {
+        let mut vertices = OpaqueVertices::default();
+        for (side, layer) in (0..6).cartesian_product(0..CHUNK_SIZE) {
+            for (two, one) in (0..CHUNK_SIZE).cartesian_product(0..CHUNK_SIZE) {
+                let Some((key, quad)) = self.cells[Self::cell(side, layer, one, two)] else {
+                    continue;
+                };
+                let mut width = 1;
+                while one + width < CHUNK_SIZE
+                    && self.matches(Self::cell(side, layer, one + width, two), &key)
+                {
+                    width += 1;
+                }
+                let mut height = 1;
+                while two + height < CHUNK_SIZE
+                    && (one..one + width)
+                        .all(|one| self.matches(Self::cell(side, layer, one, two + height), &key))
+                {
+                    height += 1;
+                }
+                for (two, one) in (two..two + height).cartesian_product(one..one + width) {
+                    self.cells[Self::cell(side, layer, one, two)] = None;
+                }
+                let face = FaceWithAO {
+                    face: Face {
+                        side: side.into(),
+                        quad,
+                    },
+                    aos: key.aos,
+                };
+                let (positions, uvs) = greedy_quad(&face, (width, height), chunk, texture_atlas);
+                vertices.push(&face, positions, uvs, key.light, columns);
+            }
+        }
+        vertices
+    }
+}
+
+// The face at the quad's first corner stretched over width by height blocks, with its uvs packed
+// for greedy_material.wgsl
+fn greedy_quad(
+    face: &FaceWithAO,
+    (width, height): (usize, usize),
+    chunk: &ChunkBoundary,
+    texture_atlas: &TextureAtlas,
+) -> ([[f32; 3]; 4], [[f32; 2]; 4]) {
+    let [one, two] = PLANE_AXES[face.side.axis as usize];
+    let mut positions = face.positions(1.0, chunk);
+    let lowest = |axis: usize| {
+        positions
+            .iter()
+            .map(|position| position[axis])
+            .fold(f32::MAX, f32::min)
+    };
+    let (low_one, low_two) = (lowest(one), lowest(two));
+    let far = positions.map(|position| [position[one] > low_one, position[two] > low_two]);
+    for (position, far) in positions.iter_mut().zip(far) {
+        if far[0] {
+            position[one] += (width - 1) as f32;
+        }
+        if far[1] {
+            position[two] += (height - 1) as f32;
+        }
+    }
+
+    // Where each corner sits in the tile, 0 or 1 each way, the same as an unmerged face
+    let slot = face.texture_slot();
+    let tile = texture_atlas.textures[face.quad.data.textures[slot]].min;
+    let corners = face
+        .uvs(texture_atlas, slot, IVec3::ZERO, chunk)
+        .map(|uv| ((Vec2::from(uv) * texture_atlas.size - tile) / GREEDY_TILE).round());
+    // Each way across the tile runs along whichever side of the quad it changes with
+    let repeats = |component: usize| {
+        let along_one = (0..4).all(|corner| {
+            (corners[corner][component] == corners[0][component]) == (far[corner][0] == far[0][0])
+        });
+        if along_one {
+            width as f32
+        } else {
+            height as f32
+        }
+    };
+    let repeats = Vec2::new(repeats(0), repeats(1));
+    let uvs = corners.map(|corner| (tile * GREEDY_CELL + GREEDY_PAD + corner * repeats).into());
+    (positions, uvs)
+}
+
 fn full_mesh(
     raw_chunk: &ChunkBoundary,
     texture_atlas: &TextureAtlas,
     chunk_pos: IVec3,
+    seed: u32,
+    settings: MeshSettings,
 ) -> MeshedChunk {
     let mut buffer = QuadGroups::default();
     generate_mesh(raw_chunk, true, &mut buffer);
-    let mut positions = Vec::new();
-    let mut indices = Vec::new();
-    let mut normals = Vec::new();
-    let mut uvs = Vec::new();
-    let mut ao = Vec::new();
-    let mut light = Vec::new();
+    let columns = TintColumns::new(chunk_pos);
+    let default_geo = BlockGeo::default();
+    let mut opaque = OpaqueVertices::default();
+    let mut greedy = settings.greedy.then(GreedyFaces::default);
     for face in buffer.iter_with_ao(raw_chunk) {
-        indices.extend_from_slice(&face.indices(positions.len() as u32));
-        positions.extend_from_slice(&face.positions(1.0, raw_chunk)); // Voxel size is 1m
-        normals.extend_from_slice(&face.normals());
-        ao.extend_from_slice(&face.aos());
-        let matched_index = match (face.side.axis, face.side.positive) {
-            (Axis::X, false) => 2,
-            (Axis::X, true) => 3,
-            (Axis::Y, false) => 1,
-            (Axis::Y, true) => 0,
-            (Axis::Z, false) => 5,
-            (Axis::Z, true) => 4,
-        };
-        let matched_neighbor = match (face.side.axis, face.side.positive) {
-            (Axis::X, false) => (face.voxel()[0] - 1, face.voxel()[1], face.voxel()[2]),
-            (Axis::X, true) => (face.voxel()[0] + 1, face.voxel()[1], face.voxel()[2]),
-            (Axis::Y, false) => (face.voxel()[0], face.voxel()[1] - 1, face.voxel()[2]),
-            (Axis::Y, true) => (face.voxel()[0], face.voxel()[1] + 1, face.voxel()[2]),
-            (Axis::Z, false) => (face.voxel()[0], face.voxel()[1], face.voxel()[2] - 1),
-            (Axis::Z, true) => (face.voxel()[0], face.voxel()[1], face.voxel()[2] + 1),
-        };
-        let light_val = raw_chunk.voxels()
-            [ChunkBoundary::linearize(matched_neighbor.0, matched_neighbor.1, matched_neighbor.2)]
-        .light;
-
-        light.extend_from_slice(&[light_val, light_val, light_val, light_val]);
-
-        uvs.extend_from_slice(
-            &face.uvs(
-                texture_atlas,
-                matched_index,
-                world_to_global_voxel(Vec3::new(
-                    face.voxel()[0] as f32,
-                    face.voxel()[1] as f32,
-                    face.voxel()[2] as f32,
-                ))
-                .as_vec3()
-                .as_ivec3(),
-                raw_chunk,
-            ),
+        let light = face.light(raw_chunk);
+        if let Some(greedy) = greedy.as_mut() {
+            if greedy.take(&face, light, raw_chunk, &default_geo) {
+                continue;
+            }
+        }
+        let positions = face.positions(1.0, raw_chunk); // Voxel size is 1m
+        let uvs = face.uvs(
+            texture_atlas,
+            face.texture_slot(),
+            face.world_voxel(),
+            raw_chunk,
         );
+        opaque.push(&face, positions, uvs, light, &columns);
     }
-    let final_ao = ao_convert(ao);
-    let mut final_color = Vec::new();
-    for (idx, color) in final_ao.iter().enumerate() {
-        let light_level = light_to_inten(light[idx]);
-        // let light_level_red = light_to_color(light[idx].r);
-        // let light_level_green = light_to_color(light[idx].g);
-        // let light_level_blue = light_to_color(light[idx].b);
-        final_color.extend_from_slice(&[[
-            color[0] * light_level,
-            color[1] * light_level,
-            color[2] * light_level,
-            color[3],
-        ]]);
-    }
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.set_indices(Some(Indices::U32(indices)));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions.clone());
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, final_color);
+    let mesh = opaque.into_mesh(settings);
+    let greedy_mesh = greedy
+        .map(|greedy| greedy.merge(raw_chunk, texture_atlas, &columns))
+        .filter(|merged| !merged.positions.is_empty())
+        .map(|merged| merged.into_mesh(settings));
     buffer.clear();
     //Transparent Mesh
     generate_mesh(raw_chunk, false, &mut buffer);
@@ -1362,35 +1812,24 @@ fn full_mesh(
     let mut indices = Vec::new();
     let mut normals = Vec::new();
     let mut uvs = Vec::new();
+    let mut colors = Vec::new();
     for face in buffer.iter_with_ao(raw_chunk) {
         indices.extend_from_slice(&face.indices(positions.len() as u32));
 
-        positions.extend_from_slice(&face.positions(1.0, raw_chunk)); // Voxel size is 1m
+        let face_positions = face.positions(1.0, raw_chunk); // Voxel size is 1m
+        colors.extend(
+            face.tints(&face_positions, &columns)
+                .map(|[r, g, b]| [r, g, b, 1.0]),
+        );
+        positions.extend_from_slice(&face_positions);
         normals.extend_from_slice(&face.normals());
         ao.extend_from_slice(&face.aos());
-        let matched_index = match (face.side.axis, face.side.positive) {
-            (Axis::X, false) => 2,
-            (Axis::X, true) => 3,
-            (Axis::Y, false) => 1,
-            (Axis::Y, true) => 0,
-            (Axis::Z, false) => 5,
-            (Axis::Z, true) => 4,
-        };
-
-        uvs.extend_from_slice(
-            &face.uvs(
-                texture_atlas,
-                matched_index,
-                world_to_global_voxel(Vec3::new(
-                    face.voxel()[0] as f32,
-                    face.voxel()[1] as f32,
-                    face.voxel()[2] as f32,
-                ))
-                .as_vec3()
-                .as_ivec3(),
-                raw_chunk,
-            ),
-        );
+        uvs.extend_from_slice(&face.uvs(
+            texture_atlas,
+            face.texture_slot(),
+            face.world_voxel(),
+            raw_chunk,
+        ));
     }
 
     let mut transparent_mesh = Mesh::new(PrimitiveTopology::TriangleList);
@@ -1398,11 +1837,109 @@ fn full_mesh(
     transparent_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions.clone());
     transparent_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
     transparent_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    // Only the tint, these faces have never been shaded by ao or light
+    transparent_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    let decoration_mesh = decoration_mesh(raw_chunk, texture_atlas, chunk_pos, seed, settings);
     MeshedChunk {
+        stats: MeshStats {
+            opaque: SectionStats::of(&mesh),
+            transparent: SectionStats::of(&transparent_mesh),
+            greedy: greedy_mesh.as_ref().map(SectionStats::of),
+            decoration: decoration_mesh.as_ref().map(SectionStats::of),
+            ..Default::default()
+        },
         chunk_mesh: mesh,
         transparent_mesh,
+        greedy_mesh,
+        decoration_mesh,
         pos: ChunkPos(chunk_pos),
+        stamp: 0,
+    }
+}
+
+// Two quads crossed through the top of each decorated block, both sides drawn. They face up so
+// they're lit like the ground they stand on, and take the whole tile like the cross geometry
+fn decoration_mesh(
+    raw_chunk: &ChunkBoundary,
+    texture_atlas: &TextureAtlas,
+    chunk_pos: IVec3,
+    seed: u32,
+    settings: MeshSettings,
+) -> Option<Mesh> {
+    if settings.decoration_density == 0 {
+        return None;
+    }
+    let scale = settings.decoration_density as f32 / 100.0;
+    let origin = chunk_pos * CHUNK_SIZE as i32;
+    let columns = TintColumns::new(chunk_pos);
+    let voxels = raw_chunk.voxels();
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    for (x, y, z) in (1..=CHUNK_SIZE)
+        .cartesian_product(1..=CHUNK_SIZE)
+        .cartesian_product(1..=CHUNK_SIZE)
+        .map(|((x, y), z)| (x, y, z))
+    {
+        let voxel = &voxels[ChunkBoundary::linearize(x, y, z)];
+        let Some((texture, density)) = voxel.decoration else {
+            continue;
+        };
+        // Anything at all above covers it, even another block's decoration can't go there
+        let above = &voxels[ChunkBoundary::linearize(x, y + 1, z)];
+        if above.visibility != EMPTY {
+            continue;
+        }
+        let local = IVec3::new(x as i32 - 1, y as i32 - 1, z as i32 - 1);
+        let world = origin + local;
+        if !is_decorated(seed, world, density as f32 / 100.0 * scale) {
+            continue;
+        }
+        let Some(rect) = texture_atlas.textures.get(texture) else {
+            continue;
+        };
+        let (min, max) = (rect.min / texture_atlas.size, rect.max / texture_atlas.size);
+        let center = Vec2::new(local.x as f32, local.z as f32)
+            + Vec2::splat(0.5)
+            + decoration_offset(seed, world);
+        let bottom = local.y as f32 + 1.0;
+        let light = light_to_inten(above.light);
+        let tint = voxel.tint.map_or([1.0; 3], |kind| {
+            tint_color(kind, columns.climate([center.x, bottom, center.y]))
+        });
+        let color = [light * tint[0], light * tint[1], light * tint[2], 1.0];
+        for diagonal in [Vec2::new(1.0, 1.0), Vec2::new(1.0, -1.0)] {
+            let half = diagonal.normalize() * 0.5;
+            let (start, end) = (center - half, center + half);
+            let first = positions.len() as u32;
+            indices.extend([0, 2, 1, 1, 2, 3].map(|corner| first + corner));
+            positions.extend_from_slice(&[
+                [start.x, bottom, start.y],
+                [end.x, bottom, end.y],
+                [start.x, bottom + 1.0, start.y],
+                [end.x, bottom + 1.0, end.y],
+            ]);
+            uvs.extend_from_slice(&[
+                [min.x, max.y],
+                [max.x, max.y],
+                [min.x, min.y],
+                [max.x, min.y],
+            ]);
+            colors.extend_from_slice(&[color; 4]);
+        }
     }
+    if positions.is_empty() {
+        return None;
+    }
+    let normals = vec![[0.0, 1.0, 0.0]; positions.len()];
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    Some(mesh)
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -1414,19 +1951,31 @@ pub fn process_priority_queue(
     geo_table: Res<GeometryTable>,
     texture_atlas: Res<Assets<TextureAtlas>>,
     _current_chunks: ResMut<CurrentChunks>,
+    (generation, options, tune, seed): (
+        Res<MeshGeneration>,
+        Res<GameOptions>,
+        Res<AutoTune>,
+        Res<WorldSeed>,
+    ),
 ) {
     let task_pool = ComputeTaskPool::get();
+    let seed = **seed;
     let block_atlas: TextureAtlas = texture_atlas
         .get(&loadable_assets.block_atlas)
         .unwrap()
         .clone();
-    for (chunk_pos, center_chunk, neighbors) in chunk_queue.priority.drain(..) {
+    for (chunk_pos, center_chunk, neighbors, stamp) in chunk_queue.priority.drain(..) {
         let cloned_table: BlockTable = block_table.clone();
         let cloned_geo_table: GeometryTable = geo_table.clone();
         let cloned_assets: LoadableAssets = loadable_assets.clone();
         let clone_atlas: TextureAtlas = block_atlas.clone();
+        let ticket = generation.ticket(MeshSettings::from_options(&options, &tune));
 
         let task = task_pool.spawn(async move {
+            if ticket.is_stale() {
+                return None;
+            }
+            let started = Instant::now();
             let raw_chunk = ChunkBoundary::new(
                 center_chunk,
                 neighbors,
@@ -1435,12 +1984,16 @@ pub fn process_priority_queue(
                 &cloned_assets,
                 &clone_atlas,
             );
-            full_mesh(&raw_chunk, &clone_atlas, chunk_pos)
+            let meshed = full_mesh(&raw_chunk, &clone_atlas, chunk_pos, seed, ticket.settings);
+            Some(MeshedChunk {
+                stamp,
+                ..meshed.timed(started.elapsed(), ticket.generation())
+            })
         });
         // commands
         //     .entity(current_chunks.get_entity(ChunkPos(chunk_pos)).unwrap())
         //     .insert(PriorityComputeMesh(task));
-        commands.spawn(PriorityComputeMesh(task));
+        commands.spawn((PriorityComputeMesh(task), SessionScoped));
     }
 }
 
@@ -1449,19 +2002,30 @@ pub fn priority_mesh(
     chunks: Query<&ChunkPos, With<PriorityMesh>>,
     chunk_manager: ChunkManager,
     mut chunk_queue: ResMut<MeshQueue>,
+    versions: Query<&ChunkVersion>,
 ) {
     for chunk in chunks.iter() {
         if let Some(neighbors) = chunk_manager.get_neighbors(*chunk) {
             if let Ok(neighbors) = neighbors.try_into() {
                 if let Some(chunk_entity) = chunk_manager.current_chunks.get_entity(*chunk) {
                     if let Some(chunk_data) = chunk_manager.get_chunk(chunk_entity) {
+                        let stamp = chunk_queue.stamp();
                         chunk_queue.priority.push((
                             **chunk,
                             chunk_data,
                             Box::new(Array(neighbors)),
+                            stamp,
                         ));
                         commands.entity(chunk_entity).remove::<PriorityMesh>();
                         commands.entity(chunk_entity).remove::<NeedsMesh>();
+                        commands.entity(chunk_entity).insert(MeshStamp(stamp));
+                        commands
+                            .entity(chunk_entity)
+                            .insert(MeshedNeighbors::record(
+                                *chunk,
+                                &chunk_manager.current_chunks,
+                                &versions,
+                            ));
                     }
                 }
             }
@@ -1469,14 +2033,41 @@ pub fn priority_mesh(
     }
 }
 
+// An all air chunk has nothing to show and a solid one only shows where one of the six
+// chunks it touches isn't solid too. Neighbors are in `ChunkPos::neighbors` order
+pub fn needs_mesh(chunk: &ChunkData, neighbors: &[ChunkData], block_table: &BlockTable) -> bool {
+    if chunk.is_empty(block_table) {
+        return false;
+    }
+    !chunk.is_uniform_solid(block_table)
+        || ChunkPos::new(0, 0, 0)
+            .neighbors()
+            .iter()
+            .zip(neighbors)
+            .filter(|(offset, _)| offset.x.abs() + offset.y.abs() + offset.z.abs() == 1)
+            .any(|(_, neighbor)| !neighbor.is_uniform_solid(block_table))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn build_mesh(
     mut commands: Commands,
     mut chunk_queue: ResMut<MeshQueue>,
     chunk_manager: ChunkManager,
     chunks: Query<&ChunkPos, With<NeedsMesh>>,
     player_chunk: Res<PlayerChunk>,
-    options: Res<GameOptions>,
+    (options, tune): (Res<GameOptions>, Res<AutoTune>),
+    (versions, tasks): (Query<&ChunkVersion>, Query<(), With<ComputeMesh>>),
+    (budget, view_radius): (Res<MemoryBudget>, Res<ViewRadius>),
 ) {
+    // Past the meshed radius they wait until memory pressure or auto-tune lets them back in
+    let meshed_radius = ViewRadius {
+        horizontal: budget.view_radius(tune.view_radius(view_radius.horizontal)),
+        vertical: view_radius.vertical,
+    };
+    let meshes_frame = tune.tuned_options(&options).meshes_frame;
+    // Ones still queued from last frame count too, their tasks just haven't started
+    let mut room =
+        mesh_task_cap(&options).saturating_sub(tasks.iter().count() + chunk_queue.mesh.len());
     for (count, chunk) in chunks
         .iter()
         .sorted_unstable_by_key(|key| {
@@ -1484,21 +2075,58 @@ pub fn build_mesh(
         })
         .enumerate()
     {
-        if count > options.meshes_frame {
+        if count > meshes_frame {
             return;
         }
+        if !player_chunk.is_in_radius(**chunk, &meshed_radius) {
+            if let Some(chunk_entity) = chunk_manager.current_chunks.get_entity(*chunk) {
+                // Nothing still in flight for it gets shown either
+                commands
+                    .entity(chunk_entity)
+                    .remove::<NeedsMesh>()
+                    .insert((Evicted, MeshStamp(chunk_queue.stamp())));
+            }
+            continue;
+        }
         if chunk_manager.current_chunks.all_neighbors_exist(*chunk) {
             if let Some(neighbors) = chunk_manager.get_neighbors(*chunk) {
-                if let Ok(neighbors) = neighbors.try_into() {
-                    if let Some(chunk_entity) = chunk_manager.current_chunks.get_entity(*chunk) {
-                        if let Some(chunk_data) = chunk_manager.get_chunk(chunk_entity) {
+                if let Some(chunk_entity) = chunk_manager.current_chunks.get_entity(*chunk) {
+                    if let Some(chunk_data) = chunk_manager.get_chunk(chunk_entity) {
+                        if needs_mesh(&chunk_data, &neighbors, &chunk_manager.block_table) {
+                            // Keeps NeedsMesh and goes with fresh data once a task finishes
+                            if room == 0 {
+                                continue;
+                            }
+                            let Ok(neighbors) = neighbors.try_into() else {
+                                continue;
+                            };
+                            room -= 1;
+                            let stamp = chunk_queue.stamp();
                             chunk_queue.mesh.push((
                                 **chunk,
                                 chunk_data,
                                 Box::new(Array(neighbors)),
+                                stamp,
                             ));
-                            commands.entity(chunk_entity).remove::<NeedsMesh>();
+                            chunk_queue.queued += 1;
+                            commands.entity(chunk_entity).insert(MeshStamp(stamp));
+                        } else {
+                            // Whatever it showed before a neighbor filled back in goes too
+                            commands.entity(chunk_entity).despawn_descendants();
+                            commands
+                                .entity(chunk_entity)
+                                .insert(MeshStamp(chunk_queue.stamp()));
+                            chunk_queue.skipped += 1;
                         }
+                        commands.entity(chunk_entity).remove::<NeedsMesh>();
+                        // Recorded either way so a neighbor opening up queues it again
+                        commands
+                            .entity(chunk_entity)
+                            .insert(MeshedNeighbors::record(
+                                *chunk,
+                                &chunk_manager.current_chunks,
+                                &versions,
+                            ));
                     }
                 }
             }
@@ -1506,25 +2134,175 @@ pub fn build_mesh(
     }
 }
 
+// Bumped from a shared counter whenever the chunk's data changes, so a chunk that unloads and
+// comes back never reuses a version its neighbors have already seen
+#[derive(Component, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChunkVersion(pub u64);
+
+#[derive(Resource, Default)]
+pub struct NextChunkVersion(pub u64);
+
+// Versions of the 26 neighbors in `ChunkPos::neighbors` order at the time the mesh was queued
+#[derive(Component, Clone, PartialEq, Eq, Debug)]
+pub struct MeshedNeighbors(pub Vec<Option<ChunkVersion>>);
+
+impl MeshedNeighbors {
+    pub fn record(
+        pos: ChunkPos,
+        current_chunks: &CurrentChunks,
+        versions: &Query<&ChunkVersion>,
+    ) -> Self {
+        MeshedNeighbors(Self::current(pos, current_chunks, versions))
+    }
+
+    pub fn current(
+        pos: ChunkPos,
+        current_chunks: &CurrentChunks,
+        versions: &Query<&ChunkVersion>,
+    ) -> Vec<Option<ChunkVersion>> {
+        pos.neighbors()
+            .iter()
+            .map(|neighbor| {
+                current_chunks
+                    .get_entity(*neighbor)
+                    .and_then(|entity| versions.get(entity).ok().copied())
+            })
+            .collect()
+    }
+
+    // A neighbor that went away isn't stale, the border just stays as it was until it returns
+    pub fn is_stale(&self, current: &[Option<ChunkVersion>]) -> bool {
+        self.0
+            .iter()
+            .zip(current)
+            .any(|(recorded, current)| current.is_some() && recorded != current)
+    }
+}
+
+pub fn bump_chunk_versions(
+    mut chunks: Query<&mut ChunkVersion, Changed<ChunkData>>,
+    mut next_version: ResMut<NextChunkVersion>,
+) {
+    for mut version in chunks.iter_mut() {
+        next_version.0 += 1;
+        version.0 = next_version.0;
+    }
+}
+
+// Only the chunks bordering something that changed get looked at, and only the ones whose
+// recorded snapshot no longer matches get meshed again
+pub fn requeue_stale_meshes(
+    mut commands: Commands,
+    changed: Query<&ChunkPos, Changed<ChunkVersion>>,
+    meshed: Query<&MeshedNeighbors, Without<NeedsMesh>>,
+    versions: Query<&ChunkVersion>,
+    current_chunks: Res<CurrentChunks>,
+) {
+    let mut checked = FxHashSet::default();
+    for chunk_pos in changed.iter() {
+        for neighbor in chunk_pos.neighbors() {
+            if !checked.insert(neighbor) {
+                continue;
+            }
+            let Some(entity) = current_chunks.get_entity(neighbor) else {
+                continue;
+            };
+            let Ok(recorded) = meshed.get(entity) else {
+                continue;
+            };
+            if recorded.is_stale(&MeshedNeighbors::current(
+                neighbor,
+                &current_chunks,
+                &versions,
+            )) {
+                commands.entity(entity).insert(NeedsMesh);
+            }
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct MeshedChunk {
     chunk_mesh: Mesh,
     transparent_mesh: Mesh,
+    // Faces merged by greedy meshing, drawn with ChunkMaterial::greedy
+    greedy_mesh: Option<Mesh>,
+    // Grass tufts and the like, nothing in the chunk's data and nothing to collide with or target
+    decoration_mesh: Option<Mesh>,
     pos: ChunkPos,
+    stamp: u64,
+    stats: MeshStats,
+}
+
+impl MeshedChunk {
+    fn is_current(&self, stamp: Option<&MeshStamp>) -> bool {
+        stamp.map_or(false, |stamp| stamp.0 == self.stamp)
+    }
+
+    // full_mesh only knows what it built, the task knows how long it took
+    fn timed(mut self, took: Duration, generation: u64) -> Self {
+        self.stats.build_micros = took.as_micros() as u64;
+        self.stats.generation = generation;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SectionStats {
+    pub vertices: usize,
+    pub indices: usize,
+}
+
+impl SectionStats {
+    pub fn of(mesh: &Mesh) -> Self {
+        Self {
+            vertices: mesh.count_vertices(),
+            indices: mesh.indices().map_or(0, |indices| indices.len()),
+        }
+    }
+}
+
+// What the chunk's current mesh came out as, kept on the chunk for /dumpchunk
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MeshStats {
+    pub opaque: SectionStats,
+    pub transparent: SectionStats,
+    pub greedy: Option<SectionStats>,
+    pub decoration: Option<SectionStats>,
+    pub build_micros: u64,
+    // MeshGeneration the task was handed
+    pub generation: u64,
 }
 
 #[derive(Resource, Default)]
 pub struct ChunkMaterial {
-    opaque: Handle<StandardMaterial>,
-    transparent: Handle<StandardMaterial>,
+    pub opaque: Handle<StandardMaterial>,
+    pub transparent: Handle<StandardMaterial>,
+    pub greedy: Handle<GreedyMaterial>,
+    pub decoration: Handle<StandardMaterial>,
 }
 
 pub fn create_chunk_material(
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut greedy_materials: ResMut<Assets<GreedyMaterial>>,
     mut chunk_material: ResMut<ChunkMaterial>,
     texture_atlas: Res<Assets<TextureAtlas>>,
     loadable_assets: ResMut<LoadableAssets>,
 ) {
+    let block_atlas = texture_atlas.get(&loadable_assets.block_atlas).unwrap();
+    chunk_material.greedy = greedy_materials.add(GreedyMaterial {
+        atlas_size: block_atlas.size,
+        color_texture: Some(block_atlas.texture.clone()),
+    });
+    chunk_material.decoration = materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        base_color_texture: Some(block_atlas.texture.clone()),
+        alpha_mode: AlphaMode::Mask(0.5),
+        perceptual_roughness: 1.0,
+        double_sided: true,
+        cull_mode: None,
+        ..Default::default()
+    });
     chunk_material.transparent = materials.add(StandardMaterial {
         base_color: Color::WHITE,
         base_color_texture: Some(
@@ -1553,6 +2331,41 @@ pub fn create_chunk_material(
     });
 }
 
+// A lone block run through the chunk mesher, unlit and in [0, 1] on every axis. Hands back
+// the opaque and transparent halves like a chunk does
+pub fn block_mesh(
+    block: BlockData,
+    chunk_pos: IVec3,
+    block_table: &BlockTable,
+    geo_table: &GeometryTable,
+    loadable_assets: &LoadableAssets,
+    texture_atlas: &TextureAtlas,
+) -> (Mesh, Mesh) {
+    let mut center = ChunkData::default();
+    center.set(0, 0, 0, block, block_table);
+    let neighbors = Box::new(Array(std::array::from_fn(|_| ChunkData::default())));
+    let raw_chunk = ChunkBoundary::new(
+        center,
+        neighbors,
+        block_table,
+        geo_table,
+        loadable_assets,
+        texture_atlas,
+    );
+    // Nothing grows on a block in the hand or in an icon
+    let meshed = full_mesh(
+        &raw_chunk,
+        texture_atlas,
+        chunk_pos,
+        0,
+        MeshSettings {
+            decoration_density: 0,
+            ..Default::default()
+        },
+    );
+    (meshed.chunk_mesh, meshed.transparent_mesh)
+}
+
 pub fn priority_player(
     player_chunk: Res<PlayerChunk>,
     current_chunks: Res<CurrentChunks>,
@@ -1574,19 +2387,31 @@ pub fn process_queue(
     block_table: Res<BlockTable>,
     geo_table: Res<GeometryTable>,
     texture_atlas: Res<Assets<TextureAtlas>>,
+    (generation, options, tune, seed): (
+        Res<MeshGeneration>,
+        Res<GameOptions>,
+        Res<AutoTune>,
+        Res<WorldSeed>,
+    ),
 ) {
     let task_pool = AsyncComputeTaskPool::get();
+    let seed = **seed;
     let block_atlas: TextureAtlas = texture_atlas
         .get(&loadable_assets.block_atlas)
         .unwrap()
         .clone();
-    for (chunk_pos, center_chunk, neighbors) in chunk_queue.mesh.drain(..).rev() {
+    for (chunk_pos, center_chunk, neighbors, stamp) in chunk_queue.mesh.drain(..) {
         let cloned_table: BlockTable = block_table.clone();
         let cloned_geo_table: GeometryTable = geo_table.clone();
         let cloned_assets: LoadableAssets = loadable_assets.clone();
         let clone_atlas: TextureAtlas = block_atlas.clone();
+        let ticket = generation.ticket(MeshSettings::from_options(&options, &tune));
 
         let task = task_pool.spawn(async move {
+            if ticket.is_stale() {
+                return None;
+            }
+            let started = Instant::now();
             let raw_chunk = ChunkBoundary::new(
                 center_chunk,
                 neighbors,
@@ -1595,9 +2420,13 @@ pub fn process_queue(
                 &cloned_assets,
                 &clone_atlas,
             );
-            full_mesh(&raw_chunk, &clone_atlas, chunk_pos)
+            let meshed = full_mesh(&raw_chunk, &clone_atlas, chunk_pos, seed, ticket.settings);
+            Some(MeshedChunk {
+                stamp,
+                ..meshed.timed(started.elapsed(), ticket.generation())
+            })
         });
-        commands.spawn(ComputeMesh(task));
+        commands.spawn((ComputeMesh(task), SessionScoped));
     }
 }
 
@@ -1622,7 +2451,7 @@ pub fn process_queue(
 //         _ => 10.0,
 //     }
 // }
-fn light_to_inten(color: u8) -> f32 {
+pub fn light_to_inten(color: u8) -> f32 {
     match color {
         0 => 1.0,
         1 => 1.25,
@@ -1688,8 +2517,9 @@ pub fn sort_faces(
     handles: Query<&Handle<Mesh>>,
     chunks: Query<&Children, With<ChunkData>>,
     mut meshes: ResMut<Assets<Mesh>>,
-    camera_transform: Query<&GlobalTransform, With<Camera>>,
+    camera_transform: Query<&GlobalTransform, With<FPSCamera>>,
     mut events: EventReader<SortFaces>,
+    offset: Res<WorldOffset>,
 ) {
     for evt in events.iter() {
         if let Ok(camera_transform) = camera_transform.get_single() {
@@ -1722,10 +2552,10 @@ pub fn sort_faces(
                                                     + raw_array[vec_ind[4]][2]
                                                     + raw_array[vec_ind[5]][2])
                                                     / 4.0;
-                                                let real_pos = voxel_to_world(
-                                                    UVec3::new(x as u32, y as u32, z as u32),
-                                                    evt.chunk_pos,
-                                                );
+                                                let real_pos = offset
+                                                    .chunk_to_render(evt.chunk_pos)
+                                                    + UVec3::new(x as u32, y as u32, z as u32)
+                                                        .as_vec3();
                                                 let dist = camera_transform
                                                     .translation()
                                                     .distance(real_pos);
@@ -1799,3 +2629,482 @@ pub fn sort_chunks(
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{asset::HandleId, render::mesh::VertexAttributeValues, utils::HashMap};
+    use vinox_common::{
+        storage::blocks::descriptor::{BlockDescriptor, DecorationDescriptor},
+        world::{
+            chunks::{
+                ecs::ViewRadius,
+                light::{VoxelAddedEvent, VoxelRemovedEvent},
+            },
+            decoration::MAX_OFFSET,
+        },
+    };
+
+    fn block(visibility: VoxelVisibility, match_index: usize) -> RenderedBlockData {
+        RenderedBlockData {
+            visibility,
+            match_index,
+            blocks: [visibility != EMPTY; 6],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tint_columns_meet_at_chunk_borders() {
+        assert_eq!(TintColumns::column([0.0, 5.0, 0.0]), (0, 0));
+        assert_eq!(TintColumns::column([16.0, 0.0, 3.0]), (16, 3));
+        // Custom geometry between columns snaps, anything poking out clamps to the chunk
+        assert_eq!(TintColumns::column([2.4, 0.0, 7.6]), (2, 8));
+        assert_eq!(TintColumns::column([-0.3, 0.0, 16.2]), (0, 16));
+
+        let edge = CHUNK_SIZE as f32;
+        for (chunk, neighbor, here, there) in [
+            (IVec3::ZERO, IVec3::X, [edge, 0.0, 4.0], [0.0, 0.0, 4.0]),
+            (IVec3::ZERO, IVec3::Z, [9.0, 0.0, edge], [9.0, 0.0, 0.0]),
+            (
+                IVec3::new(-1, 0, -1),
+                IVec3::new(0, 0, -1),
+                [edge, 2.0, 11.0],
+                [0.0, 2.0, 11.0],
+            ),
+            (
+                IVec3::new(-1, 2, -1),
+                IVec3::ZERO,
+                [edge, 0.0, edge],
+                [0.0, 0.0, 0.0],
+            ),
+        ] {
+            assert_eq!(
+                TintColumns::new(chunk).climate(here),
+                TintColumns::new(neighbor).climate(there)
+            );
+        }
+        let columns = TintColumns::new(IVec3::new(3, 0, -2));
+        assert_eq!(
+            columns.climate([5.0, 0.0, 6.0]),
+            climate_at(3 * CHUNK_SIZE as i32 + 5, -2 * CHUNK_SIZE as i32 + 6)
+        );
+    }
+
+    #[test]
+    fn boundary_faces_have_one_owner() {
+        let blocks = [
+            block(EMPTY, 0),
+            block(OPAQUE, 1),
+            block(TRANSPARENT, 2),
+            block(TRANSPARENT, 3),
+        ];
+        let low_edge = 1;
+        let high_edge = ChunkBoundary::edge() - 2;
+        for axis in 0..3 {
+            for lower in blocks.iter() {
+                for upper in blocks.iter() {
+                    for solid_pass in [true, false] {
+                        // The lower chunk's voxel looks up the axis, the upper chunk's looks down
+                        let lower_emits =
+                            face_visible(lower, upper, true, upper.blocks[0], solid_pass)
+                                && owns_boundary_face(lower, upper, axis * 2 + 1, high_edge);
+                        let upper_emits =
+                            face_visible(upper, lower, true, lower.blocks[1], solid_pass)
+                                && owns_boundary_face(upper, lower, axis * 2, low_edge);
+                        let wanted = face_visible(lower, upper, true, upper.blocks[0], solid_pass)
+                            || face_visible(upper, lower, true, lower.blocks[1], solid_pass);
+                        assert_eq!(
+                            lower_emits as u8 + upper_emits as u8,
+                            wanted as u8,
+                            "{:?} below {:?} on axis {axis}",
+                            lower.visibility,
+                            upper.visibility
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn mesh_app(fill: &str) -> App {
+        let mut app = App::new();
+        app.insert_resource(CurrentChunks::default());
+        let mut block_table = BlockTable::default();
+        for (name, visibility) in [
+            ("air", VoxelVisibility::Empty),
+            ("stone", VoxelVisibility::Opaque),
+        ] {
+            block_table.insert(
+                format!("vinox:{name}"),
+                BlockDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    visibility: Some(visibility),
+                    ..Default::default()
+                },
+            );
+        }
+        let fill = BlockData::new("vinox".to_string(), fill.to_string());
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let pos = ChunkPos::new(x, y, z);
+                    let entity = app
+                        .world
+                        .spawn((
+                            ChunkData::uniform(fill.clone(), &block_table),
+                            pos,
+                            ChunkVersion::default(),
+                        ))
+                        .id();
+                    app.world
+                        .resource_mut::<CurrentChunks>()
+                        .insert_entity(pos, entity);
+                }
+            }
+        }
+        app.insert_resource(block_table)
+            .insert_resource(ViewRadius {
+                horizontal: 2,
+                vertical: 2,
+            })
+            .insert_resource(MemoryBudget::with_cap(u64::MAX))
+            .insert_resource(PlayerChunk::default())
+            .insert_resource(GameOptions::default())
+            .insert_resource(AutoTune::default())
+            .insert_resource(MeshQueue::default())
+            .insert_resource(NextChunkVersion::default())
+            .add_event::<VoxelAddedEvent>()
+            .add_event::<VoxelRemovedEvent>()
+            .add_systems(
+                (
+                    bump_chunk_versions,
+                    requeue_stale_meshes,
+                    apply_system_buffers,
+                    build_mesh,
+                )
+                    .chain(),
+            );
+        app
+    }
+
+    fn chunk_at(app: &App, x: i32, y: i32, z: i32) -> Entity {
+        app.world
+            .resource::<CurrentChunks>()
+            .get_entity(ChunkPos::new(x, y, z))
+            .unwrap()
+    }
+
+    #[test]
+    fn air_chunks_get_no_mesh_task() {
+        let mut app = mesh_app("air");
+        let center = chunk_at(&app, 0, 0, 0);
+        app.world.entity_mut(center).insert(NeedsMesh);
+        app.update();
+        let queue = app.world.resource::<MeshQueue>();
+        assert!(queue.mesh.is_empty());
+        assert_eq!((queue.queued, queue.skipped), (0, 1));
+        assert!(app.world.get::<NeedsMesh>(center).is_none());
+    }
+
+    #[test]
+    fn buried_chunks_wait_for_a_hole() {
+        let mut app = mesh_app("stone");
+        let center = chunk_at(&app, 0, 0, 0);
+        app.world.entity_mut(center).insert(NeedsMesh);
+        app.update();
+        let queue = app.world.resource::<MeshQueue>();
+        assert!(queue.mesh.is_empty());
+        assert_eq!((queue.queued, queue.skipped), (0, 1));
+
+        // A hole in a chunk that only touches the center at a corner changes nothing
+        let corner = chunk_at(&app, 1, 1, 1);
+        let table = app.world.resource::<BlockTable>().clone();
+        app.world
+            .get_mut::<ChunkData>(corner)
+            .unwrap()
+            .set(0, 0, 0, BlockData::default(), &table);
+        app.update();
+        let queue = app.world.resource::<MeshQueue>();
+        assert!(queue.mesh.is_empty());
+        assert_eq!(queue.skipped, 2);
+
+        let above = chunk_at(&app, 0, 1, 0);
+        app.world
+            .get_mut::<ChunkData>(above)
+            .unwrap()
+            .set(4, 0, 4, BlockData::default(), &table);
+        app.update();
+        let queue = app.world.resource::<MeshQueue>();
+        assert_eq!(queue.mesh.len(), 1);
+        assert_eq!(queue.mesh[0].0, IVec3::ZERO);
+        assert_eq!(queue.queued, 1);
+    }
+
+    #[test]
+    fn mesh_tasks_wait_for_room_nearest_first() {
+        let mut app = mesh_app("air");
+        app.world.resource_mut::<GameOptions>().mesh_tasks = 1;
+        let table = app.world.resource::<BlockTable>().clone();
+        let stone = BlockData::new("vinox".to_string(), "stone".to_string());
+        let (center, far) = (chunk_at(&app, 0, 0, 0), chunk_at(&app, 1, 0, 1));
+        for chunk in [far, center] {
+            app.world
+                .get_mut::<ChunkData>(chunk)
+                .unwrap()
+                .set(8, 8, 8, stone.clone(), &table);
+            app.world.entity_mut(chunk).insert(NeedsMesh);
+        }
+        app.update();
+        let queue = app.world.resource::<MeshQueue>();
+        assert_eq!(queue.mesh.len(), 1);
+        assert_eq!(queue.mesh[0].0, IVec3::ZERO);
+        let first = queue.mesh[0].3;
+        assert_eq!(app.world.get::<MeshStamp>(center), Some(&MeshStamp(first)));
+        assert!(app.world.get::<NeedsMesh>(far).is_some());
+
+        // Still queued, so still no room
+        app.update();
+        assert_eq!(app.world.resource::<MeshQueue>().mesh.len(), 1);
+        assert!(app.world.get::<NeedsMesh>(far).is_some());
+
+        app.world.resource_mut::<MeshQueue>().mesh.clear();
+        app.update();
+        let queue = app.world.resource::<MeshQueue>();
+        assert_eq!(queue.mesh[0].0, IVec3::new(1, 0, 1));
+        assert!(queue.mesh[0].3 > first);
+        assert!(app.world.get::<NeedsMesh>(far).is_none());
+
+        // Dirtied again before its task came back, so what that task built goes unused
+        let (raw_chunk, texture_atlas) = boundary(&["stone"], |_, _, _| 0);
+        let meshed = MeshedChunk {
+            stamp: first,
+            ..full_mesh(
+                &raw_chunk,
+                &texture_atlas,
+                IVec3::ZERO,
+                0,
+                MeshSettings::default(),
+            )
+        };
+        assert!(meshed.is_current(app.world.get::<MeshStamp>(center)));
+        app.world.entity_mut(center).insert(NeedsMesh);
+        app.world.resource_mut::<MeshQueue>().mesh.clear();
+        app.update();
+        assert!(!meshed.is_current(app.world.get::<MeshStamp>(center)));
+    }
+
+    // Air all around a chunk of the named blocks, fill picks one for each voxel. Every block has
+    // its own 16 pixel tile, air's comes first. Grass gets a decoration on every block, drawn
+    // with its own tile
+    fn boundary(
+        names: &[&str],
+        fill: impl Fn(u32, u32, u32) -> usize,
+    ) -> (ChunkBoundary, TextureAtlas) {
+        let mut geo_table = GeometryTable::default();
+        geo_table.insert(
+            "vinox:block".to_string(),
+            GeometryDescriptor {
+                namespace: "vinox".to_string(),
+                name: "block".to_string(),
+                blocks: [true; 6],
+                element: BlockGeo::default(),
+            },
+        );
+        let mut block_table = BlockTable::default();
+        let mut loadable_assets = LoadableAssets::default();
+        let mut texture_atlas = TextureAtlas::new_empty(
+            Handle::default(),
+            Vec2::new(16.0 * (names.len() + 1) as f32, 16.0),
+        );
+        let mut handles = HashMap::new();
+        for (index, name) in ["air"].iter().chain(names).enumerate() {
+            block_table.insert(
+                format!("vinox:{name}"),
+                BlockDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    visibility: Some(if index == 0 { EMPTY } else { OPAQUE }),
+                    decoration: (*name == "grass").then(|| DecorationDescriptor {
+                        texture: "tuft.png".to_string(),
+                        density: 1.0,
+                    }),
+                    ..Default::default()
+                },
+            );
+            let handle = Handle::<Image>::weak(HandleId::random::<Image>());
+            if *name == "grass" {
+                loadable_assets
+                    .decoration_textures
+                    .insert(format!("vinox:{name}"), handle.clone());
+            }
+            let left = 16.0 * index as f32;
+            texture_atlas.add_texture(Rect::new(left, 0.0, left + 16.0, 16.0));
+            handles.insert(handle.clone(), index);
+            loadable_assets
+                .block_textures
+                .insert(format!("vinox:{name}"), [(); 6].map(|_| handle.clone()));
+        }
+        texture_atlas.texture_handles = Some(handles);
+
+        let mut center = ChunkData::default();
+        for (x, y, z) in (0..CHUNK_SIZE as u32)
+            .cartesian_product(0..CHUNK_SIZE as u32)
+            .cartesian_product(0..CHUNK_SIZE as u32)
+            .map(|((x, y), z)| (x, y, z))
+        {
+            let block = BlockData::new("vinox".to_string(), names[fill(x, y, z)].to_string());
+            center.set(x, y, z, block, &block_table);
+        }
+        let neighbors = Box::new(Array(std::array::from_fn(|_| ChunkData::default())));
+        let raw_chunk = ChunkBoundary::new(
+            center,
+            neighbors,
+            &block_table,
+            &geo_table,
+            &loadable_assets,
+            &texture_atlas,
+        );
+        (raw_chunk, texture_atlas)
+    }
+
+    fn triangles(mesh: Option<&Mesh>) -> usize {
+        mesh.and_then(Mesh::indices)
+            .map_or(0, |indices| indices.len() / 3)
+    }
+
+    #[test]
+    fn greedy_meshing_triangle_counts() {
+        let (raw_chunk, texture_atlas) = boundary(&["stone"], |_, _, _| 0);
+        let naive = full_mesh(
+            &raw_chunk,
+            &texture_atlas,
+            IVec3::ZERO,
+            0,
+            MeshSettings::default(),
+        );
+        let greedy_settings = MeshSettings {
+            greedy: true,
+            ..Default::default()
+        };
+        let greedy = full_mesh(&raw_chunk, &texture_atlas, IVec3::ZERO, 0, greedy_settings);
+        // Two triangles for each of the 256 faces on each side, against two for each side
+        assert_eq!(triangles(Some(&naive.chunk_mesh)), 3072);
+        assert!(naive.greedy_mesh.is_none());
+        assert_eq!(triangles(Some(&greedy.chunk_mesh)), 0);
+        assert_eq!(triangles(greedy.greedy_mesh.as_ref()), 12);
+
+        // Every corner of the one quad a side becomes packs stone's tile and 0 or 16 repeats
+        let merged = greedy.greedy_mesh.unwrap();
+        let Some(VertexAttributeValues::Float32x2(uvs)) = merged.attribute(Mesh::ATTRIBUTE_UV_0)
+        else {
+            panic!("no uvs");
+        };
+        for uv in uvs {
+            let uv = Vec2::from(*uv);
+            let tile = (uv / GREEDY_CELL).floor();
+            assert_eq!(tile, Vec2::new(16.0, 0.0));
+            let repeats = uv - tile * GREEDY_CELL - GREEDY_PAD;
+            assert!(repeats.x == 0.0 || repeats.x == 16.0, "{repeats}");
+            assert!(repeats.y == 0.0 || repeats.y == 16.0, "{repeats}");
+        }
+
+        // Stripes of two blocks only merge along the stripes
+        let (raw_chunk, texture_atlas) = boundary(&["stone", "dirt"], |x, _, _| x as usize % 2);
+        let striped = full_mesh(&raw_chunk, &texture_atlas, IVec3::ZERO, 0, greedy_settings);
+        // A quad each for the two ends, sixteen for each of the other four sides
+        assert_eq!(triangles(striped.greedy_mesh.as_ref()), (2 + 4 * 16) * 2);
+    }
+
+    #[test]
+    fn stale_neighbors_are_detected() {
+        let recorded = MeshedNeighbors(vec![Some(ChunkVersion(1)), Some(ChunkVersion(2)), None]);
+        assert!(!recorded.is_stale(&recorded.0));
+        assert!(recorded.is_stale(&[Some(ChunkVersion(1)), Some(ChunkVersion(5)), None]));
+        // A neighbor arriving after the mesh was built
+        assert!(recorded.is_stale(&[
+            Some(ChunkVersion(1)),
+            Some(ChunkVersion(2)),
+            Some(ChunkVersion(7))
+        ]));
+        // Unloading one doesn't force a remesh
+        assert!(!recorded.is_stale(&[None, Some(ChunkVersion(2)), None]));
+    }
+
+    fn positions(mesh: &Mesh) -> Vec<Vec3> {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("no positions");
+        };
+        positions
+            .iter()
+            .map(|position| Vec3::from(*position))
+            .collect()
+    }
+
+    #[test]
+    fn decorations_only_grow_with_air_above() {
+        // Grass all through, stone over the top layer's first half
+        let (raw_chunk, texture_atlas) = boundary(&["grass", "stone"], |x, y, _| {
+            (y == CHUNK_SIZE as u32 - 1 && x < 8) as usize
+        });
+        let meshed = full_mesh(
+            &raw_chunk,
+            &texture_atlas,
+            IVec3::ZERO,
+            7,
+            MeshSettings::default(),
+        );
+        let decorations = meshed.decoration_mesh.unwrap();
+        // Two crossed quads on each of the 8 by 16 grass blocks left uncovered
+        assert_eq!(triangles(Some(&decorations)), 8 * 16 * 2 * 2);
+        let top = CHUNK_SIZE as f32;
+        for position in positions(&decorations) {
+            assert!(position.y == top || position.y == top + 1.0, "{position}");
+            assert!(position.x >= 8.0 - MAX_OFFSET, "{position}");
+        }
+
+        let (raw_chunk, texture_atlas) = boundary(&["stone", "grass"], |_, _, _| 0);
+        let bare = full_mesh(
+            &raw_chunk,
+            &texture_atlas,
+            IVec3::ZERO,
+            7,
+            MeshSettings::default(),
+        );
+        assert!(bare.decoration_mesh.is_none());
+    }
+
+    #[test]
+    fn decorations_are_the_same_every_mesh() {
+        let (raw_chunk, texture_atlas) = boundary(&["grass"], |_, _, _| 0);
+        let half = MeshSettings {
+            decoration_density: 50,
+            ..Default::default()
+        };
+        let mesh = |seed: u32, settings: MeshSettings| {
+            full_mesh(
+                &raw_chunk,
+                &texture_atlas,
+                IVec3::new(3, 0, -2),
+                seed,
+                settings,
+            )
+            .decoration_mesh
+        };
+        let first = positions(&mesh(11, half).unwrap());
+        assert_eq!(first, positions(&mesh(11, half).unwrap()));
+        assert_ne!(first, positions(&mesh(12, half).unwrap()));
+        // Half of the top layer, give or take
+        let placed = first.len() / 8;
+        assert!((64..192).contains(&placed), "{placed} of 256");
+
+        let off = MeshSettings {
+            decoration_density: 0,
+            ..Default::default()
+        };
+        assert!(mesh(11, off).is_none());
+    }
+}
@@ -1,3 +1,13 @@
+pub mod ambience;
+pub mod autotune;
 pub mod chunk;
+pub mod culling;
+pub mod ghost;
+pub mod icons;
+pub mod memory;
 pub mod meshing;
 pub mod plugin;
+pub mod remesh;
+pub mod transitions;
+pub mod tween;
+pub mod view_model;
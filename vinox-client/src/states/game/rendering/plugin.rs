@@ -1,10 +1,28 @@
 use bevy::prelude::*;
 
-use crate::states::components::GameState;
+use crate::states::{
+    components::{GameSet, GameState},
+    game::session::SessionApp,
+    loading::ui::switch,
+};
 
-use super::meshing::{
-    create_chunk_material, process_priority_queue, process_priority_task, process_queue,
-    process_task, sort_chunks, sort_faces, ChunkMaterial, MeshQueue, SortFaces,
+use super::{
+    ambience::{apply_ambience, sample_ambience, smooth_ambience, ViewAmbience},
+    autotune::{auto_tune, AutoTune},
+    culling::{cull_chunks, ChunkCulling},
+    ghost::{update_placement_ghost, GhostMaterials},
+    icons::{bake_item_icons, ItemIconCache},
+    memory::{apply_memory_options, evict_far_meshes, govern_memory, memory_notice, MemoryBudget},
+    meshing::{
+        build_mesh, create_chunk_material, process_priority_queue, process_priority_task,
+        process_queue, process_task, sort_chunks, sort_faces, ChunkMaterial, MeshQueue, SortFaces,
+    },
+    remesh::{
+        start_remesh, track_remesh_sweep, watch_mesh_settings, MeshGeneration, MeshSettingsWatch,
+        RemeshAll, RemeshSweep,
+    },
+    transitions::{animate_transitions, spawn_transitions, BlockEditEvent, TransitionPool},
+    view_model::{swing_hand, update_held_item, HandSwing},
 };
 
 pub struct RenderingPlugin;
@@ -16,6 +34,7 @@ impl Plugin for RenderingPlugin {
             color: Color::WHITE,
         })
         .insert_resource(MeshQueue::default())
+        .reset_on_exit::<MeshQueue>()
         .insert_resource(ChunkMaterial::default())
         .add_system(create_chunk_material.in_schedule(OnEnter(GameState::Game)))
         .add_systems(
@@ -25,32 +44,98 @@ impl Plugin for RenderingPlugin {
                 process_task,
                 process_priority_task,
                 // priority_player,
-                sort_faces,
-                sort_chunks,
             )
+                .in_set(GameSet::Meshing)
                 .in_set(OnUpdate(GameState::Game)),
         )
-        .add_startup_system(|mut commands: Commands, assets: Res<AssetServer>| {
-            commands
-                .spawn(NodeBundle {
-                    style: Style {
-                        size: Size::new(Val::Percent(100.), Val::Auto),
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::Center,
-                        ..default()
-                    },
-                    ..default()
-                })
-                .with_children(|parent| {
-                    parent.spawn(ImageBundle {
-                        image: assets.load("crosshair.png").into(),
-                        ..default()
-                    });
-                });
-
-            // commands.insert_resource(PriorityMeshChannel::default());
-            // commands.insert_resource(MeshChannel::default());
-        })
-        .add_event::<SortFaces>();
+        .add_systems(
+            (sort_faces, sort_chunks)
+                .in_set(GameSet::RenderPrep)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .insert_resource(TransitionPool::default())
+        .reset_on_exit::<TransitionPool>()
+        .add_systems(
+            (spawn_transitions, animate_transitions)
+                .chain()
+                .in_set(GameSet::RenderPrep)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .insert_resource(ItemIconCache::default())
+        .init_resource::<MemoryBudget>()
+        .on_session_end(|world| world.resource_mut::<MemoryBudget>().end_session())
+        .add_system(apply_memory_options)
+        .add_systems(
+            (govern_memory, evict_far_meshes)
+                .chain()
+                .in_set(GameSet::Meshing)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .add_system(
+            memory_notice
+                .in_set(GameSet::Ui)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .add_system(
+            bake_item_icons
+                .before(switch)
+                .run_if(in_state(GameState::Loading).or_else(in_state(GameState::Game))),
+        )
+        .init_resource::<ViewAmbience>()
+        .reset_on_exit::<ViewAmbience>()
+        .add_systems(
+            (sample_ambience, smooth_ambience, apply_ambience)
+                .chain()
+                .in_set(GameSet::RenderPrep)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .init_resource::<HandSwing>()
+        .reset_on_exit::<HandSwing>()
+        .add_systems(
+            (update_held_item, swing_hand)
+                .chain()
+                .in_set(GameSet::RenderPrep)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .init_resource::<GhostMaterials>()
+        .reset_on_exit::<GhostMaterials>()
+        .add_system(
+            update_placement_ghost
+                .in_set(GameSet::RenderPrep)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .init_resource::<ChunkCulling>()
+        .reset_on_exit::<ChunkCulling>()
+        .add_system(
+            cull_chunks
+                .in_set(GameSet::RenderPrep)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .init_resource::<AutoTune>()
+        .reset_on_exit::<AutoTune>()
+        .add_system(
+            auto_tune
+                .in_set(GameSet::RenderPrep)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .init_resource::<MeshSettingsWatch>()
+        .init_resource::<MeshGeneration>()
+        .init_resource::<RemeshSweep>()
+        .reset_on_exit::<RemeshSweep>()
+        .add_systems(
+            (watch_mesh_settings, start_remesh)
+                .chain()
+                .before(build_mesh)
+                .in_set(GameSet::WorldUpdate)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .add_system(
+            track_remesh_sweep
+                .in_set(GameSet::Meshing)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .add_event::<RemeshAll>()
+        .add_event::<SortFaces>()
+        .add_event::<BlockEditEvent>();
     }
 }
@@ -0,0 +1,215 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bevy::prelude::*;
+use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
+use vinox_common::world::chunks::{ecs::NeedsMesh, storage::ChunkData};
+
+use crate::states::components::GameOptions;
+
+use super::autotune::AutoTune;
+
+// Everything in GameOptions the mesher reads, as auto-tune leaves it. Only these changing
+// remeshes the world
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MeshSettings {
+    pub ambient_occlusion: bool,
+    pub greedy: bool,
+    // Percent of each block's own decoration density, 0 when they're off
+    pub decoration_density: u8,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        Self {
+            ambient_occlusion: true,
+            greedy: false,
+            decoration_density: 100,
+        }
+    }
+}
+
+impl MeshSettings {
+    pub fn from_options(options: &GameOptions, tune: &AutoTune) -> Self {
+        Self {
+            ambient_occlusion: tune.tuned_options(options).ambient_occlusion,
+            greedy: options.greedy_meshing,
+            decoration_density: if options.decorations {
+                (options.decoration_density * 100.0).round() as u8
+            } else {
+                0
+            },
+        }
+    }
+
+    pub fn settings_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+// Every loaded chunk gets meshed again, nearest first through the usual per frame cap
+pub struct RemeshAll;
+
+// Hash of the settings the world was last meshed with
+#[derive(Resource, Default)]
+pub struct MeshSettingsWatch(Option<u64>);
+
+impl MeshSettingsWatch {
+    // The first look only records them, chunks meshed so far already used them
+    pub fn changed(&mut self, settings: &MeshSettings) -> bool {
+        let hash = settings.settings_hash();
+        let changed = self.0.is_some_and(|last| last != hash);
+        self.0 = Some(hash);
+        changed
+    }
+}
+
+// Moves on with every full remesh. Mesh tasks bail at the start when theirs is behind, the
+// sweep already queued their chunk again with the new settings
+#[derive(Resource, Default)]
+pub struct MeshGeneration(Arc<AtomicU64>);
+
+impl MeshGeneration {
+    pub fn current(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn bump(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn ticket(&self, settings: MeshSettings) -> MeshTicket {
+        MeshTicket {
+            generation: self.current(),
+            current: self.0.clone(),
+            settings,
+        }
+    }
+}
+
+// Handed to a mesh task, what to mesh with and whether that's still wanted
+#[derive(Debug, Clone)]
+pub struct MeshTicket {
+    generation: u64,
+    current: Arc<AtomicU64>,
+    pub settings: MeshSettings,
+}
+
+impl MeshTicket {
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.current.load(Ordering::Relaxed) != self.generation
+    }
+}
+
+// Chunks the last full remesh tagged that haven't been handed to the mesher yet
+#[derive(Resource, Default)]
+pub struct RemeshSweep {
+    pub total: usize,
+    pending: FxHashSet<Entity>,
+}
+
+impl RemeshSweep {
+    // Done and total while a sweep is running
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        (!self.pending.is_empty()).then(|| (self.total - self.pending.len(), self.total))
+    }
+}
+
+pub fn watch_mesh_settings(
+    (options, tune): (Res<GameOptions>, Res<AutoTune>),
+    mut watch: ResMut<MeshSettingsWatch>,
+    mut remesh: EventWriter<RemeshAll>,
+) {
+    if watch.changed(&MeshSettings::from_options(&options, &tune)) {
+        remesh.send(RemeshAll);
+    }
+}
+
+pub fn start_remesh(
+    mut commands: Commands,
+    mut events: EventReader<RemeshAll>,
+    chunks: Query<Entity, With<ChunkData>>,
+    generation: Res<MeshGeneration>,
+    mut sweep: ResMut<RemeshSweep>,
+) {
+    if events.is_empty() {
+        return;
+    }
+    events.clear();
+    generation.bump();
+    // A chunk edited mid sweep already has its tag, it still only gets meshed once
+    sweep.pending = chunks.iter().collect();
+    sweep.total = sweep.pending.len();
+    for entity in sweep.pending.iter() {
+        commands.entity(*entity).insert(NeedsMesh);
+    }
+}
+
+pub fn track_remesh_sweep(mut sweep: ResMut<RemeshSweep>, waiting: Query<(), With<NeedsMesh>>) {
+    // Unloaded or evicted ones count as done, there's nothing left to mesh
+    sweep.pending.retain(|entity| waiting.get(*entity).is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_meshing_options_trigger_a_remesh() {
+        let mut options = GameOptions::default();
+        let tune = AutoTune::default();
+        let mut watch = MeshSettingsWatch::default();
+        assert!(!watch.changed(&MeshSettings::from_options(&options, &tune)));
+
+        options.fov = 90.0;
+        options.reduce_motion = true;
+        assert!(!watch.changed(&MeshSettings::from_options(&options, &tune)));
+
+        options.ambient_occlusion = false;
+        assert!(watch.changed(&MeshSettings::from_options(&options, &tune)));
+        assert!(!watch.changed(&MeshSettings::from_options(&options, &tune)));
+        options.ambient_occlusion = true;
+        assert!(watch.changed(&MeshSettings::from_options(&options, &tune)));
+        options.greedy_meshing = true;
+        assert!(watch.changed(&MeshSettings::from_options(&options, &tune)));
+        options.decoration_density = 0.5;
+        assert!(watch.changed(&MeshSettings::from_options(&options, &tune)));
+        options.decorations = false;
+        assert!(watch.changed(&MeshSettings::from_options(&options, &tune)));
+        // Nothing to show either way
+        options.decoration_density = 2.0;
+        assert!(!watch.changed(&MeshSettings::from_options(&options, &tune)));
+    }
+
+    #[test]
+    fn tasks_from_before_a_remesh_are_stale() {
+        let generation = MeshGeneration::default();
+        let old = generation.ticket(MeshSettings::default());
+        assert!(!old.is_stale());
+
+        generation.bump();
+        let new = generation.ticket(MeshSettings {
+            ambient_occlusion: false,
+            ..Default::default()
+        });
+        assert!(old.is_stale());
+        assert!(!new.is_stale());
+        assert!(!new.settings.ambient_occlusion);
+        // Another sweep before it started catches it too
+        assert_eq!(generation.bump(), 2);
+        assert!(new.is_stale());
+    }
+}
@@ -0,0 +1,240 @@
+use bevy::{
+    pbr::{NotShadowCaster, NotShadowReceiver},
+    prelude::*,
+    render::mesh::VertexAttributeValues,
+};
+use vinox_common::world::chunks::{
+    ecs::CurrentChunks,
+    positions::{global_voxel_positions, ChunkPos, WorldOffset},
+    storage::{BlockData, BlockTable, ChunkData},
+};
+
+use crate::states::{
+    assets::load::LoadableAssets,
+    components::{GameOptions, SessionScoped},
+};
+
+use super::{
+    meshing::{block_mesh, light_to_inten, ChunkMaterial, GeometryTable},
+    tween::{ease_in_cubic, ease_out_cubic, lerp, progress},
+};
+
+pub const PLACE_DURATION: f32 = 0.12;
+pub const BREAK_DURATION: f32 = 0.15;
+pub const PLACE_FROM: f32 = 0.6;
+// Drawn a hair bigger than the block so its faces sit 5mm outside the chunk mesh instead of
+// fighting it for the same depth once the chunk remeshes
+pub const INFLATE: f32 = 1.01;
+// How far a broken block sinks while it collapses
+pub const BREAK_DROP: f32 = 0.25;
+// Anything past these just pops like it used to, an explosion shouldn't spawn hundreds
+pub const MAX_PER_FRAME: usize = 8;
+pub const MAX_ACTIVE: usize = 48;
+
+// A block changed in the loaded world, by the local player or anyone else
+pub struct BlockEditEvent {
+    pub voxel: IVec3,
+    pub before: BlockData,
+    pub after: BlockData,
+    // Made from this client, the crosshair pulses for those
+    pub local: bool,
+}
+
+#[derive(Component)]
+pub struct EditTransition {
+    active: bool,
+    placed: bool,
+    // Absolute, so a rebase mid animation doesn't leave it behind
+    voxel: IVec3,
+    started: f32,
+}
+
+// Overlay entities are kept around hidden rather than respawned for every edit
+#[derive(Resource, Default)]
+pub struct TransitionPool {
+    free: Vec<Entity>,
+    spawned: usize,
+}
+
+// Scale and drop `elapsed` seconds in, None once it's finished
+pub fn transition_shape(placed: bool, elapsed: f32) -> Option<(f32, f32)> {
+    if placed {
+        let t = progress(elapsed, PLACE_DURATION);
+        (t < 1.0).then(|| (lerp(PLACE_FROM, 1.0, ease_out_cubic(t)) * INFLATE, 0.0))
+    } else {
+        let t = progress(elapsed, BREAK_DURATION);
+        (t < 1.0).then(|| (INFLATE * (1.0 - ease_in_cubic(t)), BREAK_DROP * t))
+    }
+}
+
+// Brightest light next to the voxel, the voxel itself is dark while it's solid
+fn block_center(offset: &WorldOffset, voxel: IVec3) -> Vec3 {
+    offset.voxel_to_render(voxel).as_vec3() + Vec3::splat(0.5)
+}
+
+fn light_around(voxel: IVec3, current_chunks: &CurrentChunks, chunks: &Query<&ChunkData>) -> u8 {
+    [
+        IVec3::X,
+        IVec3::NEG_X,
+        IVec3::Y,
+        IVec3::NEG_Y,
+        IVec3::Z,
+        IVec3::NEG_Z,
+    ]
+    .iter()
+    .filter_map(|offset| {
+        let (chunk_pos, local) = global_voxel_positions(voxel + *offset);
+        let chunk = chunks
+            .get(current_chunks.get_entity(ChunkPos(chunk_pos))?)
+            .ok()?;
+        Some(chunk.get_light(local.x, local.y, local.z))
+    })
+    .max()
+    .unwrap_or(0)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub fn spawn_transitions(
+    mut commands: Commands,
+    mut edits: EventReader<BlockEditEvent>,
+    options: Res<GameOptions>,
+    (time, offset): (Res<Time>, Res<WorldOffset>),
+    mut pool: ResMut<TransitionPool>,
+    mut transitions: Query<(
+        &mut EditTransition,
+        &mut Transform,
+        &mut Visibility,
+        &mut Handle<Mesh>,
+        &Handle<StandardMaterial>,
+    )>,
+    (mut meshes, mut materials, chunk_material): (
+        ResMut<Assets<Mesh>>,
+        ResMut<Assets<StandardMaterial>>,
+        Res<ChunkMaterial>,
+    ),
+    (current_chunks, chunks): (Res<CurrentChunks>, Query<&ChunkData>),
+    (block_table, geo_table, loadable_assets, atlases): (
+        Res<BlockTable>,
+        Res<GeometryTable>,
+        Res<LoadableAssets>,
+        Res<Assets<TextureAtlas>>,
+    ),
+) {
+    if options.reduce_motion {
+        edits.clear();
+        return;
+    }
+    let Some(atlas) = atlases.get(&loadable_assets.block_atlas) else {
+        edits.clear();
+        return;
+    };
+    for edit in edits.iter().take(MAX_PER_FRAME) {
+        if edit.before == edit.after {
+            continue;
+        }
+        let placed = edit.after != BlockData::default();
+        let block = if placed { &edit.after } else { &edit.before };
+        let (chunk_pos, _) = global_voxel_positions(edit.voxel);
+        let (opaque, transparent) = block_mesh(
+            block.clone(),
+            chunk_pos,
+            &block_table,
+            &geo_table,
+            &loadable_assets,
+            atlas,
+        );
+        let (mut mesh, template) = if opaque.count_vertices() > 0 {
+            (opaque, &chunk_material.opaque)
+        } else if transparent.count_vertices() > 0 {
+            (transparent, &chunk_material.transparent)
+        } else {
+            continue;
+        };
+        // Centered on the origin so it scales in place
+        if let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+        {
+            for position in positions.iter_mut() {
+                for axis in position.iter_mut() {
+                    *axis -= 0.5;
+                }
+            }
+        }
+        let Some(mut material) = materials.get(template).cloned() else {
+            continue;
+        };
+        // The chunk mesh bakes light into vertex colors, the lone block was meshed in the dark
+        let intensity = light_to_inten(light_around(edit.voxel, &current_chunks, &chunks));
+        material.base_color = Color::rgb(intensity, intensity, intensity);
+
+        let center = block_center(&offset, edit.voxel);
+        let scale = if placed {
+            PLACE_FROM * INFLATE
+        } else {
+            INFLATE
+        };
+        let transform = Transform::from_translation(center).with_scale(Vec3::splat(scale));
+        let transition = EditTransition {
+            active: true,
+            placed,
+            voxel: edit.voxel,
+            started: time.elapsed_seconds(),
+        };
+
+        if let Some(entity) = pool.free.pop() {
+            if let Ok((mut old, mut old_transform, mut visibility, mut old_mesh, old_material)) =
+                transitions.get_mut(entity)
+            {
+                *old = transition;
+                *old_transform = transform;
+                *visibility = Visibility::Visible;
+                *old_mesh = meshes.add(mesh);
+                if let Some(old_material) = materials.get_mut(old_material) {
+                    *old_material = material;
+                }
+            }
+        } else if pool.spawned < MAX_ACTIVE {
+            pool.spawned += 1;
+            commands.spawn((
+                PbrBundle {
+                    mesh: meshes.add(mesh),
+                    material: materials.add(material),
+                    transform,
+                    ..default()
+                },
+                transition,
+                NotShadowCaster,
+                NotShadowReceiver,
+                SessionScoped,
+            ));
+        }
+    }
+    // Whatever went over the cap is dropped rather than spilling into the next frame
+    edits.clear();
+}
+
+pub fn animate_transitions(
+    time: Res<Time>,
+    offset: Res<WorldOffset>,
+    mut pool: ResMut<TransitionPool>,
+    mut transitions: Query<(Entity, &mut EditTransition, &mut Transform, &mut Visibility)>,
+) {
+    let now = time.elapsed_seconds();
+    for (entity, mut transition, mut transform, mut visibility) in transitions.iter_mut() {
+        if !transition.active {
+            continue;
+        }
+        match transition_shape(transition.placed, now - transition.started) {
+            Some((scale, drop)) => {
+                transform.scale = Vec3::splat(scale);
+                transform.translation = block_center(&offset, transition.voxel) - Vec3::Y * drop;
+            }
+            None => {
+                transition.active = false;
+                *visibility = Visibility::Hidden;
+                pool.free.push(entity);
+            }
+        }
+    }
+}
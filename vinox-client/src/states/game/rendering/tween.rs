@@ -0,0 +1,50 @@
+// Small timing helpers for one-off effects that don't want a whole bevy_tweening animator
+
+// 0 at the start and 1 once the duration has passed, anything with no duration is done
+pub fn progress(elapsed: f32, duration: f32) -> f32 {
+    if duration <= 0.0 {
+        return 1.0;
+    }
+    (elapsed / duration).clamp(0.0, 1.0)
+}
+
+pub fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+// Fast start that settles into the end
+pub fn ease_out_cubic(t: f32) -> f32 {
+    1.0 - (1.0 - t).powi(3)
+}
+
+// Slow start that speeds up into the end
+pub fn ease_in_cubic(t: f32) -> f32 {
+    t.powi(3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn easing_curves() {
+        assert_eq!(progress(-1.0, 0.12), 0.0);
+        assert_eq!(progress(0.06, 0.12), 0.5);
+        assert_eq!(progress(1.0, 0.12), 1.0);
+        assert_eq!(progress(0.0, 0.0), 1.0);
+
+        for ease in [ease_out_cubic, ease_in_cubic] {
+            assert_eq!(ease(0.0), 0.0);
+            assert_eq!(ease(1.0), 1.0);
+            let samples: Vec<f32> = (0..=10).map(|step| ease(step as f32 / 10.0)).collect();
+            assert!(samples.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        // Out covers most of the distance early, in holds back
+        assert!(ease_out_cubic(0.5) > 0.8);
+        assert!(ease_in_cubic(0.5) < 0.2);
+
+        assert_eq!(lerp(0.6, 1.0, 0.0), 0.6);
+        assert_eq!(lerp(0.6, 1.0, 1.0), 1.0);
+        assert_eq!(lerp(1.0, 0.0, 0.25), 0.75);
+    }
+}
@@ -0,0 +1,337 @@
+use std::f32::consts::PI;
+
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    pbr::{NotShadowCaster, NotShadowReceiver},
+    prelude::*,
+    render::{mesh::VertexAttributeValues, view::RenderLayers},
+};
+use leafwing_input_manager::prelude::*;
+use vinox_common::{
+    ecs::bundles::Inventory,
+    world::chunks::storage::{name_to_identifier, BlockData, BlockTable, ItemTable},
+};
+
+use crate::states::{
+    assets::load::LoadableAssets,
+    components::{GameActions, GameOptions},
+    game::{
+        input::{camera::CameraMode, look::CursorGrab},
+        world::chunks::ControlledPlayer,
+    },
+};
+
+use super::{
+    meshing::{block_mesh, ChunkMaterial, GeometryTable},
+    tween::progress,
+};
+
+// Only the view model camera draws this layer, the world camera keeps to the default one
+pub const VIEW_MODEL_LAYER: u8 = 1;
+// The held item is always drawn with this, whatever the FOV slider says
+pub const VIEW_MODEL_FOV: f32 = 70.0;
+pub const SWING_DURATION: f32 = 0.25;
+// Right hand, in the view model camera's space
+pub const HAND_OFFSET: Vec3 = Vec3::new(0.55, -0.45, -0.9);
+pub const HAND_YAW: f32 = -0.35;
+pub const HELD_SCALE: f32 = 0.4;
+
+// Draws over the world camera with its depth cleared, so the held item never goes into walls
+#[derive(Component)]
+pub struct ViewModelCamera;
+
+// What's in the hand right now, None keeps it hidden
+#[derive(Component, Default)]
+pub struct HeldItem {
+    shown: Option<BlockData>,
+}
+
+#[derive(Resource, Default)]
+pub struct HandSwing {
+    started: Option<f32>,
+}
+
+impl HandSwing {
+    pub fn start(&mut self, now: f32) {
+        self.started = Some(now);
+    }
+
+    // 0 to 1 through a swing, 0 when there isn't one
+    pub fn progress(&mut self, now: f32) -> f32 {
+        let Some(started) = self.started else {
+            return 0.0;
+        };
+        let t = progress(now - started, SWING_DURATION);
+        if t >= 1.0 {
+            self.started = None;
+            return 0.0;
+        }
+        t
+    }
+}
+
+pub fn view_model_projection() -> PerspectiveProjection {
+    PerspectiveProjection {
+        fov: VIEW_MODEL_FOV.to_radians(),
+        near: 0.05,
+        far: 10.0,
+        aspect_ratio: 1.0,
+    }
+}
+
+// Spawned as a child of the world camera so it looks the same way
+pub fn view_model_camera() -> impl Bundle {
+    (
+        Camera3dBundle {
+            camera: Camera {
+                order: 1,
+                ..default()
+            },
+            camera_3d: Camera3d {
+                clear_color: ClearColorConfig::None,
+                ..default()
+            },
+            projection: Projection::Perspective(view_model_projection()),
+            ..default()
+        },
+        VisibilityBundle::default(),
+        RenderLayers::layer(VIEW_MODEL_LAYER),
+        ViewModelCamera,
+    )
+}
+
+pub fn held_item() -> impl Bundle {
+    (
+        PbrBundle {
+            visibility: Visibility::Hidden,
+            transform: hand_transform(false, 0.0),
+            ..default()
+        },
+        RenderLayers::layer(VIEW_MODEL_LAYER),
+        NotShadowCaster,
+        NotShadowReceiver,
+        HeldItem::default(),
+    )
+}
+
+// Across the vertical axis of the screen. Only the placement flips, the mesh itself doesn't so
+// the block's faces aren't drawn backwards
+pub fn mirror(transform: Transform) -> Transform {
+    let rotation = transform.rotation;
+    Transform {
+        translation: transform.translation * Vec3::new(-1.0, 1.0, 1.0),
+        rotation: Quat::from_xyzw(rotation.x, -rotation.y, -rotation.z, rotation.w),
+        scale: transform.scale,
+    }
+}
+
+// Where the held item sits, swing goes 0 to 1 through a swing. The left hand is the right one
+// mirrored, swing and all
+pub fn hand_transform(left_handed: bool, swing: f32) -> Transform {
+    // Down and in toward the crosshair, then back out
+    let arc = (swing.clamp(0.0, 1.0) * PI).sin();
+    let right = Transform::from_translation(HAND_OFFSET + Vec3::new(-0.2, 0.1, -0.15) * arc)
+        .with_rotation(
+            Quat::from_rotation_y(HAND_YAW + 0.5 * arc) * Quat::from_rotation_x(-0.9 * arc),
+        )
+        .with_scale(Vec3::splat(HELD_SCALE));
+    if left_handed {
+        mirror(right)
+    } else {
+        right
+    }
+}
+
+fn held_block(inventory: &Inventory, item_table: &ItemTable) -> Option<BlockData> {
+    let item = inventory.hotbar[*inventory.current_bar][*inventory.current_item].clone()?;
+    let descriptor = item_table.get(&name_to_identifier(
+        item.namespace.clone(),
+        item.name.clone(),
+    ))?;
+    descriptor
+        .associated_block
+        .is_some()
+        .then(|| BlockData::new(item.namespace, item.name))
+}
+
+// Only blocks for now, anything else leaves the hand empty. Whether it shows is up to swing_hand
+#[allow(clippy::type_complexity)]
+pub fn update_held_item(
+    player: Query<&Inventory, With<ControlledPlayer>>,
+    mut held: Query<(
+        &mut HeldItem,
+        &mut Handle<Mesh>,
+        &mut Handle<StandardMaterial>,
+    )>,
+    (item_table, block_table, geo_table): (Res<ItemTable>, Res<BlockTable>, Res<GeometryTable>),
+    (loadable_assets, atlases, chunk_material): (
+        Res<LoadableAssets>,
+        Res<Assets<TextureAtlas>>,
+        Res<ChunkMaterial>,
+    ),
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let (Ok(inventory), Ok((mut held, mut mesh_handle, mut material_handle))) =
+        (player.get_single(), held.get_single_mut())
+    else {
+        return;
+    };
+    let block = held_block(inventory, &item_table);
+    if block == held.shown {
+        return;
+    }
+    let Some(atlas) = atlases.get(&loadable_assets.block_atlas) else {
+        return;
+    };
+    held.shown = block.clone();
+    // Lets go of the last one, a block that doesn't mesh leaves the hand looking empty
+    *mesh_handle = Handle::default();
+    let Some(block) = block else {
+        return;
+    };
+    let (opaque, transparent) = block_mesh(
+        block,
+        IVec3::ZERO,
+        &block_table,
+        &geo_table,
+        &loadable_assets,
+        atlas,
+    );
+    let (mut mesh, template) = if opaque.count_vertices() > 0 {
+        (opaque, &chunk_material.opaque)
+    } else if transparent.count_vertices() > 0 {
+        (transparent, &chunk_material.transparent)
+    } else {
+        return;
+    };
+    // Centered so it turns about its middle
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+        for position in positions.iter_mut() {
+            for axis in position.iter_mut() {
+                *axis -= 0.5;
+            }
+        }
+    }
+    *mesh_handle = meshes.add(mesh);
+    *material_handle = template.clone();
+}
+
+pub fn swing_hand(
+    player: Query<&ActionState<GameActions>, With<ControlledPlayer>>,
+    mut held: Query<(&HeldItem, &mut Transform, &mut Visibility)>,
+    mut swing: ResMut<HandSwing>,
+    (grab, options, time, mode): (
+        Res<CursorGrab>,
+        Res<GameOptions>,
+        Res<Time>,
+        Res<CameraMode>,
+    ),
+) {
+    let now = time.elapsed_seconds();
+    if let Ok(action_state) = player.get_single() {
+        let used = action_state.just_pressed(GameActions::PrimaryInteract)
+            || action_state.just_pressed(GameActions::SecondaryInteract);
+        if used && grab.is_grabbed() && !options.reduce_motion {
+            swing.start(now);
+        }
+    }
+    let Ok((item, mut transform, mut visibility)) = held.get_single_mut() else {
+        return;
+    };
+    *transform = hand_transform(options.left_handed, swing.progress(now));
+    // Goes with the rest of the HUD, and there's no first person hand to hold it in third person
+    let wanted = if item.shown.is_some() && options.show_hud && *mode == CameraMode::FirstPerson {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+    if *visibility != wanted {
+        *visibility = wanted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::camera::CameraProjection;
+
+    use crate::states::{
+        components::FOV_RANGE,
+        game::input::player::{update_fov, FPSCamera, FovKick},
+    };
+
+    fn flip(point: Vec3) -> Vec3 {
+        point * Vec3::new(-1.0, 1.0, 1.0)
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).abs().max_element() < 1e-5, "{a} and {b}");
+    }
+
+    fn on_screen(projection: &PerspectiveProjection, point: Vec3) -> Vec3 {
+        projection.get_projection_matrix().project_point3(point)
+    }
+
+    #[test]
+    fn left_hand_mirrors_the_right_through_the_swing() {
+        let corners = [Vec3::splat(0.5), Vec3::new(-0.5, 0.5, -0.5), Vec3::X];
+        for swing in [0.0, 0.25, 0.5, 0.9, 1.0] {
+            let right = hand_transform(false, swing);
+            let left = hand_transform(true, swing);
+            assert_eq!(mirror(right), left);
+            assert_near(mirror(left).translation, right.translation);
+            assert!(mirror(left).rotation.abs_diff_eq(right.rotation, 1e-6));
+            // Mirroring a placement is the same as flipping the world on both sides of it
+            for corner in corners {
+                assert_near(
+                    left.transform_point(corner),
+                    flip(right.transform_point(flip(corner))),
+                );
+            }
+            assert!(right.translation.x > 0.0 && left.translation.x < 0.0);
+        }
+        // A swing comes back to where it started
+        let (start, end) = (hand_transform(true, 0.0), hand_transform(true, 1.0));
+        assert_near(end.translation, start.translation);
+        assert!(end.rotation.abs_diff_eq(start.rotation, 1e-6));
+        assert_ne!(hand_transform(true, 0.5), start);
+    }
+
+    #[test]
+    fn held_item_ignores_the_fov_slider() {
+        let mut app = App::new();
+        app.init_resource::<GameOptions>()
+            .init_resource::<FovKick>()
+            .init_resource::<Time>()
+            .add_system(update_fov);
+        let world = app
+            .world
+            .spawn((FPSCamera::default(), Camera3dBundle::default()))
+            .id();
+        let view_model = app.world.spawn(view_model_camera()).id();
+        let perspective = |app: &App, entity: Entity| match app.world.get::<Projection>(entity) {
+            Some(Projection::Perspective(projection)) => projection.clone(),
+            _ => panic!("not a perspective camera"),
+        };
+
+        let mut seen = Vec::new();
+        for fov in [*FOV_RANGE.start(), *FOV_RANGE.end()] {
+            app.world.resource_mut::<GameOptions>().fov = fov;
+            app.update();
+            assert_eq!(perspective(&app, world).fov, fov.to_radians());
+            let projection = perspective(&app, view_model);
+            assert_eq!(projection.fov, VIEW_MODEL_FOV.to_radians());
+            for left_handed in [false, true] {
+                let hand = hand_transform(left_handed, 0.0).translation;
+                let point = on_screen(&projection, hand);
+                assert!(point.x.abs() < 1.0 && point.y.abs() < 1.0);
+                assert_eq!(point.x > 0.0, !left_handed);
+                seen.push(point);
+            }
+        }
+        // Same place on screen at both ends of the slider
+        assert_eq!(seen[..2], seen[2..]);
+    }
+}
@@ -0,0 +1,172 @@
+use bevy::prelude::*;
+
+use crate::states::components::{GameState, SessionScoped};
+
+type SessionReset = Box<dyn Fn(&mut World) + Send + Sync>;
+
+// Everything that has to go back to how it was before connecting, plugins add to this when they build
+#[derive(Resource, Default)]
+pub struct SessionResources {
+    resets: Vec<SessionReset>,
+}
+
+pub trait SessionApp {
+    // Puts R back to its default when the session ends
+    fn reset_on_exit<R: Resource + Default>(&mut self) -> &mut Self;
+    // For resources that only want part of themselves cleared
+    fn on_session_end(&mut self, reset: impl Fn(&mut World) + Send + Sync + 'static) -> &mut Self;
+}
+
+impl SessionApp for App {
+    fn reset_on_exit<R: Resource + Default>(&mut self) -> &mut Self {
+        self.on_session_end(|world| {
+            world.insert_resource(R::default());
+        })
+    }
+
+    fn on_session_end(&mut self, reset: impl Fn(&mut World) + Send + Sync + 'static) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(SessionResources::default)
+            .resets
+            .push(Box::new(reset));
+        self
+    }
+}
+
+pub fn end_session(world: &mut World) {
+    let entities: Vec<Entity> = world
+        .query_filtered::<Entity, With<SessionScoped>>()
+        .iter(world)
+        .collect();
+    for entity in entities {
+        // Children of an earlier entity in the list are already gone
+        if let Some(entity) = world.get_entity_mut(entity) {
+            entity.despawn_recursive();
+        }
+    }
+    world.resource_scope(|world, session: Mut<SessionResources>| {
+        for reset in session.resets.iter() {
+            reset(world);
+        }
+    });
+}
+
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SessionResources>()
+            .add_system(end_session.in_schedule(OnExit(GameState::Game)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::window::PrimaryWindow;
+    use vinox_common::world::chunks::{
+        ecs::CurrentChunks,
+        positions::{ChunkPos, DimensionId},
+    };
+
+    use crate::states::{
+        components::GameOptions,
+        game::{
+            input::{
+                look::CursorGrab,
+                player::{spawn_camera, CameraSpawned, FPSCamera},
+            },
+            rendering::view_model::ViewModelCamera,
+            world::chunks::{ControlledPlayer, PlayerBlock, PlayerChunk},
+        },
+    };
+
+    // Stands in for the server: a player shows up and a chunk streams in once we're in game
+    fn fake_server(
+        mut commands: Commands,
+        mut current_chunks: ResMut<CurrentChunks>,
+        mut player_chunk: ResMut<PlayerChunk>,
+        players: Query<(), With<ControlledPlayer>>,
+    ) {
+        if !players.is_empty() {
+            return;
+        }
+        commands.spawn((SpatialBundle::default(), ControlledPlayer, SessionScoped));
+        let pos = ChunkPos(IVec3::new(1, 0, 1));
+        let chunk = commands.spawn((pos, SessionScoped)).id();
+        current_chunks.active = DimensionId(1);
+        current_chunks.insert_entity(pos, chunk);
+        player_chunk.chunk_pos = IVec3::ONE;
+    }
+
+    fn offline_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_state::<GameState>()
+            .add_plugin(SessionPlugin)
+            .init_resource::<GameOptions>()
+            .init_resource::<ClearColor>()
+            .insert_resource(CurrentChunks::default())
+            .insert_resource(PlayerChunk::default())
+            .insert_resource(PlayerBlock::default())
+            .insert_resource(CameraSpawned::default())
+            .init_resource::<CursorGrab>()
+            .reset_on_exit::<CurrentChunks>()
+            .reset_on_exit::<PlayerChunk>()
+            .reset_on_exit::<PlayerBlock>()
+            .reset_on_exit::<CameraSpawned>()
+            .reset_on_exit::<ClearColor>()
+            .add_systems(
+                (fake_server, apply_system_buffers, spawn_camera)
+                    .chain()
+                    .in_set(OnUpdate(GameState::Game)),
+            );
+        app.world.spawn((Window::default(), PrimaryWindow));
+        app
+    }
+
+    fn set_state(app: &mut App, state: GameState) {
+        app.world.resource_mut::<NextState<GameState>>().set(state);
+        // One frame to switch and one for the OnUpdate systems to run
+        app.update();
+        app.update();
+    }
+
+    fn count<F: bevy::ecs::query::ReadOnlyWorldQuery>(app: &mut App) -> usize {
+        app.world.query_filtered::<(), F>().iter(&app.world).count()
+    }
+
+    #[test]
+    fn sessions_leave_nothing_behind() {
+        let mut app = offline_app();
+        app.update();
+        let baseline = app.world.entities().len();
+
+        for _ in 0..5 {
+            set_state(&mut app, GameState::Game);
+            assert!(app.world.entities().len() > baseline);
+            assert!(app.world.resource::<CameraSpawned>().0);
+            set_state(&mut app, GameState::Menu);
+
+            assert_eq!(app.world.entities().len(), baseline);
+            let current_chunks = app.world.resource::<CurrentChunks>();
+            assert!(current_chunks
+                .dimensions
+                .values()
+                .all(|chunks| chunks.is_empty()));
+            assert_eq!(current_chunks.active, DimensionId::default());
+            assert_eq!(app.world.resource::<PlayerChunk>().chunk_pos, IVec3::ZERO);
+            assert!(!app.world.resource::<CameraSpawned>().0);
+            assert_eq!(
+                app.world.resource::<ClearColor>().0,
+                ClearColor::default().0
+            );
+        }
+
+        set_state(&mut app, GameState::Game);
+        assert_eq!(count::<With<FPSCamera>>(&mut app), 1);
+        assert_eq!(count::<With<ViewModelCamera>>(&mut app), 1);
+        assert_eq!(count::<With<Camera>>(&mut app), 2);
+        assert_eq!(count::<With<ControlledPlayer>>(&mut app), 1);
+    }
+}
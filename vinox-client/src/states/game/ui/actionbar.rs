@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Align2, Color32, RichText},
+    EguiContexts,
+};
+
+use crate::states::{
+    components::GameOptions,
+    fonts::{set_text_styles, TextSizes},
+};
+
+pub const ACTIONBAR_SECONDS: f32 = 2.0;
+// The same line doesn't go up again any sooner than this
+pub const REPEAT_SECONDS: f32 = 2.0;
+
+// One short line above the hotbar, for things that matter right now and not in the chat log
+#[derive(Resource, Debug, Default)]
+pub struct ActionBar {
+    current: Option<(String, f32)>,
+    shown: HashMap<String, f32>,
+}
+
+impl ActionBar {
+    // False when the same text went up too recently, so mashing a click doesn't keep
+    // restarting it
+    pub fn show(&mut self, text: &str, now: f32) -> bool {
+        self.shown.retain(|_, at| now - *at < REPEAT_SECONDS);
+        if self.shown.contains_key(text) {
+            return false;
+        }
+        self.shown.insert(text.to_string(), now);
+        self.current = Some((text.to_string(), now));
+        true
+    }
+
+    pub fn current(&self, now: f32) -> Option<&str> {
+        self.current
+            .as_ref()
+            .filter(|(_, at)| now - at < ACTIONBAR_SECONDS)
+            .map(|(text, _)| text.as_str())
+    }
+}
+
+pub fn actionbar_ui(
+    mut contexts: EguiContexts,
+    action_bar: Res<ActionBar>,
+    options: Res<GameOptions>,
+    time: Res<Time>,
+) {
+    let Some(text) = action_bar.current(time.elapsed_seconds()) else {
+        return;
+    };
+    egui::Area::new("actionbar")
+        .anchor(Align2::CENTER_BOTTOM, [0.0, -90.0])
+        .interactable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            set_text_styles(ui.ctx(), TextSizes::GAME, options.ui_text_scale);
+            egui::Frame::popup(ui.style())
+                .fill(Color32::from_black_alpha(160))
+                .show(ui, |ui| {
+                    ui.label(RichText::new(text).color(Color32::WHITE));
+                });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_are_rate_limited() {
+        let mut action_bar = ActionBar::default();
+        assert!(action_bar.show("Something is already in the way", 0.0));
+        assert!(!action_bar.show("Something is already in the way", 0.5));
+        assert!(!action_bar.show("Something is already in the way", 1.9));
+        // A different line isn't held back by the first
+        assert!(action_bar.show("Too far away", 1.9));
+        assert_eq!(action_bar.current(1.9), Some("Too far away"));
+        assert!(action_bar.show("Something is already in the way", 2.0));
+        assert_eq!(
+            action_bar.current(2.0),
+            Some("Something is already in the way")
+        );
+    }
+
+    #[test]
+    fn lines_go_away_on_their_own() {
+        let mut action_bar = ActionBar::default();
+        assert_eq!(action_bar.current(0.0), None);
+        action_bar.show("Something is already in the way", 1.0);
+        assert!(action_bar.current(1.0 + ACTIONBAR_SECONDS - 0.1).is_some());
+        assert_eq!(action_bar.current(1.0 + ACTIONBAR_SECONDS), None);
+    }
+}
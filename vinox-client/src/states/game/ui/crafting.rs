@@ -1,127 +1,165 @@
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{HashMap, HashSet};
 
 use bevy::prelude::*;
 use bevy_egui::EguiContexts;
-use bevy_egui::{egui::FontId, *};
-use vinox_common::storage::items::descriptor::ItemData;
+use bevy_egui::*;
+use vinox_common::networking::protocol::ClientMessage;
+use vinox_common::storage::crafting::descriptor::{RecipeDescriptor, RecipeUnlock};
 use vinox_common::world::chunks::storage::{identifier_to_name, name_to_identifier, ItemTable};
 use vinox_common::{ecs::bundles::Inventory, world::chunks::storage::RecipeTable};
 
-use crate::states::{components::GameOptions, game::world::chunks::ControlledPlayer};
+use crate::states::{
+    components::GameOptions,
+    fonts::{set_text_styles, TextSizes},
+    game::{
+        networking::{
+            components::{Capabilities, ChatLine, ChatMessages},
+            connection::NetClient,
+        },
+        world::chunks::ControlledPlayer,
+    },
+};
 
-//TODO: This is pretty jank rn. Many edge cases aren't accounted for
-pub fn craft(
-    inventory: &mut Inventory,
-    required_items: &HashMap<String, u32>,
-    output_item: (String, u32),
-    item_table: &ItemTable,
+// What the server told us we can craft, it checks again whenever we actually craft
+#[derive(Resource, Default)]
+pub struct RecipeBook {
+    pub unlocked: HashSet<String>,
+    // Unlocked this session and not looked at yet
+    pub new: HashSet<String>,
+    pub show_all: bool,
+    // Items the server already heard about this session
+    reported: HashSet<String>,
+}
+
+impl RecipeBook {
+    pub fn is_unlocked(&self, identifier: &str, recipe: &RecipeDescriptor) -> bool {
+        matches!(recipe.unlocked_by, None | Some(RecipeUnlock::Always))
+            || self.unlocked.contains(identifier)
+    }
+}
+
+pub struct RecipesUnlockedEvent {
+    pub ids: Vec<String>,
+    pub announce: bool,
+}
+
+pub struct CraftResultEvent {
+    pub recipe: String,
+    pub accepted: bool,
+}
+
+pub fn receive_recipes(
+    mut unlocked_events: EventReader<RecipesUnlockedEvent>,
+    mut result_events: EventReader<CraftResultEvent>,
+    mut book: ResMut<RecipeBook>,
+    mut messages: ResMut<ChatMessages>,
+    recipe_table: Res<RecipeTable>,
+    item_table: Res<ItemTable>,
+    mut player_query: Query<&mut Inventory, With<ControlledPlayer>>,
 ) {
-    let mut modified_items = required_items.clone();
-    let mut new_inventory = inventory.clone();
-    for (row_num, bar_row) in new_inventory.clone().hotbar.0.iter().enumerate() {
-        for (item_num, bar_item) in bar_row.iter().cloned().enumerate() {
-            if let Some(bar_item) = bar_item {
-                let identifier =
-                    name_to_identifier(bar_item.clone().namespace, bar_item.clone().name);
-                if modified_items.contains_key(&identifier) {
-                    let amount_left = *modified_items.get(&identifier).unwrap();
-                    if amount_left < bar_item.stack_size {
-                        let mut new_item = bar_item.clone();
-                        new_item.stack_size -= amount_left;
-                        new_inventory.hotbar[row_num][item_num] = Some(new_item);
-                        modified_items.remove(&identifier);
-                    } else {
-                        new_inventory.hotbar[row_num][item_num] = None;
-                        if (amount_left as i32 - bar_item.stack_size as i32) <= 0 {
-                            modified_items.remove(&identifier);
-                        } else {
-                            modified_items
-                                .insert(identifier.clone(), amount_left - bar_item.stack_size);
-                        }
-                    }
-                }
-            }
+    for evt in unlocked_events.iter() {
+        book.unlocked.extend(evt.ids.iter().cloned());
+        if !evt.announce || evt.ids.is_empty() {
+            continue;
         }
+        book.new.extend(evt.ids.iter().cloned());
+        let names: Vec<String> = evt
+            .ids
+            .iter()
+            .map(|id| {
+                recipe_table
+                    .get(id)
+                    .map_or(id.clone(), |recipe| recipe.name.clone())
+            })
+            .collect();
+        messages.push(ChatLine::toast(format!(
+            "New recipes: {}",
+            names.join(", ")
+        )));
     }
-    for (row_num, inv_row) in new_inventory.clone().slots.iter().enumerate() {
-        for (item_num, inv_item) in inv_row.iter().cloned().enumerate() {
-            if let Some(inv_item) = inv_item {
-                let identifier =
-                    name_to_identifier(inv_item.clone().namespace, inv_item.clone().name);
-                if modified_items.contains_key(&identifier) {
-                    let amount_left = *modified_items.get(&identifier).unwrap();
-                    if amount_left < inv_item.stack_size {
-                        let mut new_item = inv_item.clone();
-                        new_item.stack_size -= amount_left;
-                        new_inventory.slots[row_num][item_num] = Some(new_item);
-                        modified_items.remove(&identifier);
-                    } else {
-                        new_inventory.slots[row_num][item_num] = None;
-                        if (amount_left as i32 - inv_item.stack_size as i32) <= 0 {
-                            modified_items.remove(&identifier);
-                        } else {
-                            modified_items
-                                .insert(identifier.clone(), amount_left - inv_item.stack_size);
-                        }
-                    }
-                }
-            }
+    for evt in result_events.iter() {
+        let (Some(recipe), Ok(mut inventory)) =
+            (recipe_table.get(&evt.recipe), player_query.get_single_mut())
+        else {
+            continue;
+        };
+        if !evt.accepted {
+            // The server checks the same way, so if it works here the recipe must be locked
+            let reason = if inventory.clone().craft(recipe, &item_table) {
+                "That recipe isn't unlocked yet"
+            } else {
+                "You don't have everything that recipe needs"
+            };
+            messages.push(ChatLine::toast(reason));
+            continue;
         }
+        book.new.remove(&evt.recipe);
+        inventory.craft(recipe, &item_table);
     }
-    if modified_items.is_empty() {
-        if new_inventory
-            .add_item(item_table.get(&output_item.0).unwrap())
-            .is_ok()
-        {
-            *inventory = new_inventory.clone();
+}
+
+// The first time each item turns up in the inventory, so ObtainItem unlocks can fire
+pub fn report_new_items(
+    mut client: NetClient,
+    mut book: ResMut<RecipeBook>,
+    player_query: Query<&Inventory, (With<ControlledPlayer>, Changed<Inventory>)>,
+) {
+    let Ok(inventory) = player_query.get_single() else {
+        return;
+    };
+    for item in inventory
+        .hotbar
+        .iter()
+        .flatten()
+        .chain(inventory.slots.iter().flatten())
+        .flatten()
+    {
+        let identifier = name_to_identifier(item.namespace.clone(), item.name.clone());
+        if book.reported.insert(identifier.clone()) {
+            client.send(ClientMessage::ObtainedItem { identifier });
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn crafting_ui(
     recipe_table: Res<RecipeTable>,
-    item_table: Res<ItemTable>,
-    mut player_query: Query<&mut Inventory, With<ControlledPlayer>>,
+    player_query: Query<&Inventory, With<ControlledPlayer>>,
     mut contexts: EguiContexts,
     options: Res<GameOptions>,
     mut current_search: Local<String>,
+    mut book: ResMut<RecipeBook>,
+    capabilities: Res<Capabilities>,
+    mut client: NetClient,
 ) {
     if !options.dark_theme {
         catppuccin_egui::set_theme(contexts.ctx_mut(), catppuccin_egui::MOCHA);
     }
-    if let Ok(mut inventory) = player_query.get_single_mut() {
+    if let Ok(inventory) = player_query.get_single() {
         if inventory.open {
             egui::SidePanel::left("crafting").show(contexts.ctx_mut(), |ui| {
                 ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
-                    ui.ctx().set_style(egui::Style {
-                        text_styles: {
-                            let mut texts = BTreeMap::new();
-                            texts.insert(egui::style::TextStyle::Small, FontId::proportional(18.0));
-                            texts.insert(egui::style::TextStyle::Body, FontId::proportional(18.0));
-                            texts.insert(
-                                egui::style::TextStyle::Heading,
-                                FontId::proportional(20.0),
-                            );
-                            texts
-                                .insert(egui::style::TextStyle::Monospace, FontId::monospace(18.0));
-                            texts
-                                .insert(egui::style::TextStyle::Button, FontId::proportional(18.0));
-                            texts
-                        },
-                        ..Default::default()
-                    });
+                    set_text_styles(ui.ctx(), TextSizes::GAME, options.ui_text_scale);
                     let mut sorted_recipe_table = Vec::new();
                     ui.horizontal(|ui| {
                         ui.label("Search: ");
                         ui.text_edit_singleline(&mut *current_search);
                     });
+                    if capabilities.creative {
+                        ui.checkbox(&mut book.show_all, "Show locked recipes");
+                    }
+                    let show_all = capabilities.creative && book.show_all;
                     let matcher = SkimMatcherV2::default();
 
-                    for recipe in recipe_table.values() {
+                    for (identifier, recipe) in recipe_table.iter() {
+                        let unlocked = book.is_unlocked(identifier, recipe);
+                        if !unlocked && !show_all {
+                            continue;
+                        }
                         let score = matcher.fuzzy_match(&recipe.name, &current_search);
-                        sorted_recipe_table.push((score, recipe));
+                        sorted_recipe_table.push((score, identifier, recipe, unlocked));
                     }
                     sorted_recipe_table.sort_unstable_by_key(|k| k.0);
 
@@ -129,33 +167,42 @@ pub fn crafting_ui(
                         .auto_shrink([false; 2])
                         .max_width(2000.0)
                         .show(ui, |ui| {
-                            for (_, recipe) in sorted_recipe_table.iter().rev() {
+                            for (_, identifier, recipe, unlocked) in
+                                sorted_recipe_table.iter().rev()
+                            {
                                 ui.horizontal(|ui| {
-                                    ui.label(format!("{}: x{}", recipe.name, recipe.output_item.1))
-                                        .on_hover_ui(|ui| {
-                                            for (required_item, item_amount) in recipe
-                                                .required_items
-                                                .clone()
-                                                .unwrap_or(HashMap::new())
-                                                .iter()
+                                    let label = ui.label(format!(
+                                        "{}: x{}",
+                                        recipe.name, recipe.output_item.1
+                                    ));
+                                    if label.hovered() {
+                                        book.new.remove(*identifier);
+                                    }
+                                    if book.new.contains(*identifier) {
+                                        ui.colored_label(egui::Color32::YELLOW, "new");
+                                    }
+                                    if !unlocked {
+                                        ui.weak("locked");
+                                    }
+                                    label.on_hover_ui(|ui| {
+                                        for (required_item, item_amount) in recipe
+                                            .required_items
+                                            .clone()
+                                            .unwrap_or(HashMap::new())
+                                            .iter()
+                                        {
+                                            if let Some((_, name)) =
+                                                identifier_to_name(required_item.clone())
                                             {
-                                                if let Some((_, name)) =
-                                                    identifier_to_name(required_item.clone())
-                                                {
-                                                    ui.label(format!("{name}: x{item_amount}"));
-                                                }
+                                                ui.label(format!("{name}: x{item_amount}"));
                                             }
-                                        });
-                                    if ui.button("Craft").clicked() {
-                                        if let Some(required_items) = recipe.required_items.clone()
-                                        {
-                                            craft(
-                                                &mut inventory,
-                                                &required_items,
-                                                recipe.output_item.clone(),
-                                                &item_table,
-                                            );
                                         }
+                                    });
+                                    // Items only change hands once the server says yes
+                                    if ui.button("Craft").clicked() {
+                                        client.send(ClientMessage::Craft {
+                                            recipe: (*identifier).clone(),
+                                        });
                                     }
                                 });
                             }
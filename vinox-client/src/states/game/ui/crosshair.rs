@@ -0,0 +1,416 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    window::PrimaryWindow,
+};
+use serde::{Deserialize, Serialize};
+use vinox_common::world::chunks::storage::{name_to_identifier, BlockData, BlockTable};
+
+use crate::states::{
+    components::{GameOptions, SessionScoped},
+    game::{
+        input::{denied::DeniedFlash, player::TargetedBlock},
+        rendering::{
+            transitions::BlockEditEvent,
+            tween::{ease_out_cubic, lerp, progress},
+        },
+    },
+    menu::ui::InOptions,
+};
+
+use super::plugin::InUi;
+
+// An edit going through makes the crosshair jump out this much and settle back
+pub const PULSE_DURATION: f32 = 0.15;
+pub const PULSE_SCALE: f32 = 1.35;
+// How far the fill leans toward the highlight over something that can be used
+pub const INTERACTABLE_TINT: f32 = 0.35;
+pub const INTERACTABLE_COLOR: Color = Color::rgb(1.0, 0.85, 0.35);
+// Looking at nothing fades it a little
+pub const NOTHING_ALPHA: f32 = 0.6;
+// The fill goes this red right after the server put one of our edits back
+pub const DENIED_COLOR: Color = Color::rgb(0.95, 0.2, 0.2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrosshairShape {
+    Cross,
+    Dot,
+    Circle,
+}
+
+impl CrosshairShape {
+    pub fn next(self) -> Self {
+        match self {
+            CrosshairShape::Cross => CrosshairShape::Dot,
+            CrosshairShape::Dot => CrosshairShape::Circle,
+            CrosshairShape::Circle => CrosshairShape::Cross,
+        }
+    }
+}
+
+// Sizes are in logical pixels so it comes out the same at any resolution or scale factor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrosshairStyle {
+    pub shape: CrosshairShape,
+    // Across the whole thing, the dot ignores it and is twice as wide as it is thick
+    pub size: f32,
+    pub thickness: f32,
+    // sRGB with alpha
+    pub color: [u8; 4],
+    // Width of the contrasting ring around the fill so it reads on anything, 0 turns it off
+    pub outline: f32,
+    // Shifts with what it's over, a plain block, one that can be used or nothing
+    pub tint_targets: bool,
+}
+
+impl Default for CrosshairStyle {
+    fn default() -> Self {
+        Self {
+            shape: CrosshairShape::Cross,
+            size: 16.0,
+            thickness: 2.0,
+            color: [255, 255, 255, 230],
+            outline: 1.0,
+            tint_targets: true,
+        }
+    }
+}
+
+impl CrosshairStyle {
+    pub fn extent(&self) -> f32 {
+        match self.shape {
+            CrosshairShape::Dot => self.thickness * 2.0,
+            CrosshairShape::Cross | CrosshairShape::Circle => self.size,
+        }
+    }
+
+    // Room for the shape, its outline and a pixel either side for the soft edge
+    pub fn side(&self) -> f32 {
+        self.extent() + 2.0 * self.outline.max(0.0) + 2.0
+    }
+
+    // Signed distance from the shape's edge, negative inside. `point` is from the center
+    pub fn distance(&self, point: Vec2) -> f32 {
+        let half_thickness = self.thickness / 2.0;
+        match self.shape {
+            CrosshairShape::Cross => {
+                let half = self.size / 2.0;
+                box_distance(point, Vec2::new(half, half_thickness))
+                    .min(box_distance(point, Vec2::new(half_thickness, half)))
+            }
+            CrosshairShape::Dot => point.length() - self.thickness,
+            CrosshairShape::Circle => {
+                (point.length() - (self.size / 2.0 - half_thickness)).abs() - half_thickness
+            }
+        }
+    }
+}
+
+fn box_distance(point: Vec2, half_extents: Vec2) -> f32 {
+    let outside = point.abs() - half_extents;
+    outside.max(Vec2::ZERO).length() + outside.max_element().min(0.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetKind {
+    Nothing,
+    Block,
+    // Containers, beds and anything else marked as usable
+    Interactable,
+}
+
+pub fn target_kind(block: Option<&BlockData>, block_table: &BlockTable) -> TargetKind {
+    let Some(block) = block else {
+        return TargetKind::Nothing;
+    };
+    let usable = block_table
+        .get(&name_to_identifier(
+            block.namespace.clone(),
+            block.name.clone(),
+        ))
+        .is_some_and(|descriptor| {
+            descriptor.container_size.is_some()
+                || descriptor.sleepable.unwrap_or(false)
+                || descriptor.display_frame.unwrap_or(false)
+                || descriptor.seat.is_some()
+                || descriptor.interactable.unwrap_or(false)
+        });
+    if usable {
+        TargetKind::Interactable
+    } else {
+        TargetKind::Block
+    }
+}
+
+// Fill and outline. The outline goes dark under a light fill and light under a dark one
+pub fn crosshair_colors(style: &CrosshairStyle, kind: TargetKind) -> [Color; 2] {
+    let [r, g, b, a] = style.color;
+    let base = Color::rgba_u8(r, g, b, a);
+    let fill = match kind {
+        _ if !style.tint_targets => base,
+        TargetKind::Block => base,
+        TargetKind::Nothing => Color::rgba(base.r(), base.g(), base.b(), base.a() * NOTHING_ALPHA),
+        TargetKind::Interactable => Color::rgba(
+            lerp(base.r(), INTERACTABLE_COLOR.r(), INTERACTABLE_TINT),
+            lerp(base.g(), INTERACTABLE_COLOR.g(), INTERACTABLE_TINT),
+            lerp(base.b(), INTERACTABLE_COLOR.b(), INTERACTABLE_TINT),
+            base.a(),
+        ),
+    };
+    let luminance = 0.2126 * base.r() + 0.7152 * base.g() + 0.0722 * base.b();
+    let shade = if luminance > 0.5 { 0.0 } else { 1.0 };
+    [fill, Color::rgba(shade, shade, shade, fill.a() * 0.8)]
+}
+
+// White masks for the fill and the outline at `scale_factor` pixels per logical one, so they
+// stay sharp on any display. Color goes on the nodes and never needs a redraw
+pub fn rasterize(style: &CrosshairStyle, scale_factor: f32) -> (u32, [Vec<u8>; 2]) {
+    let side = style.side();
+    let pixels = (side * scale_factor).ceil().max(1.0) as u32;
+    let coverage = |distance: f32| (0.5 - distance * scale_factor).clamp(0.0, 1.0);
+    let mut fill = Vec::with_capacity((pixels * pixels * 4) as usize);
+    let mut outline = Vec::with_capacity((pixels * pixels * 4) as usize);
+    for y in 0..pixels {
+        for x in 0..pixels {
+            let point = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) / scale_factor - side / 2.0;
+            let distance = style.distance(point);
+            let outlined = if style.outline > 0.0 {
+                coverage(distance - style.outline)
+            } else {
+                0.0
+            };
+            fill.extend([255, 255, 255, (coverage(distance) * 255.0).round() as u8]);
+            outline.extend([255, 255, 255, (outlined * 255.0).round() as u8]);
+        }
+    }
+    (pixels, [fill, outline])
+}
+
+#[derive(Component)]
+pub struct Crosshair;
+
+#[derive(Component)]
+pub struct CrosshairLayer {
+    pub outline: bool,
+}
+
+pub fn spawn_crosshair(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            SessionScoped,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((NodeBundle::default(), Crosshair))
+                .with_children(|parent| {
+                    // The outline first so the fill draws over it
+                    for outline in [true, false] {
+                        parent.spawn((
+                            ImageBundle {
+                                style: Style {
+                                    size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                                    position_type: PositionType::Absolute,
+                                    ..default()
+                                },
+                                ..default()
+                            },
+                            CrosshairLayer { outline },
+                        ));
+                    }
+                });
+        });
+}
+
+// Redrawn when the style or the window's scale factor changes, or a new session spawns it again
+pub fn bake_crosshair(
+    options: Res<GameOptions>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut images: ResMut<Assets<Image>>,
+    mut layers: Query<(&mut UiImage, &CrosshairLayer)>,
+    added: Query<(), Added<CrosshairLayer>>,
+    mut baked: Local<Option<(CrosshairStyle, f64)>>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let current = (options.crosshair.clone(), window.scale_factor());
+    if added.is_empty() && baked.as_ref() == Some(&current) {
+        return;
+    }
+    let (pixels, [fill, outline]) = rasterize(&current.0, current.1 as f32);
+    let mut upload = |data| {
+        images.add(Image::new(
+            Extent3d {
+                width: pixels,
+                height: pixels,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        ))
+    };
+    let (fill, outline) = (upload(fill), upload(outline));
+    for (mut image, layer) in layers.iter_mut() {
+        image.texture = if layer.outline {
+            outline.clone()
+        } else {
+            fill.clone()
+        };
+    }
+    *baked = Some(current);
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_crosshair(
+    options: Res<GameOptions>,
+    (in_ui, in_options): (Res<InUi>, Res<InOptions>),
+    targeted: Res<TargetedBlock>,
+    block_table: Res<BlockTable>,
+    (time, flash): (Res<Time>, Res<DeniedFlash>),
+    mut edits: EventReader<BlockEditEvent>,
+    mut pulse_started: Local<Option<f32>>,
+    mut crosshair: Query<(&mut Style, &mut Visibility), With<Crosshair>>,
+    mut layers: Query<(&mut BackgroundColor, &CrosshairLayer)>,
+) {
+    let now = time.elapsed_seconds();
+    if edits.iter().any(|edit| edit.local) && !options.reduce_motion {
+        *pulse_started = Some(now);
+    }
+    let pulse = match *pulse_started {
+        Some(started) => {
+            let t = progress(now - started, PULSE_DURATION);
+            if t >= 1.0 {
+                *pulse_started = None;
+            }
+            lerp(PULSE_SCALE, 1.0, ease_out_cubic(t))
+        }
+        None => 1.0,
+    };
+
+    let style = &options.crosshair;
+    let shown = options.show_hud && !**in_ui && !**in_options;
+    for (mut node, mut visibility) in crosshair.iter_mut() {
+        *visibility = if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        let side = Val::Px(style.side() * pulse);
+        node.size = Size::new(side, side);
+    }
+
+    let kind = target_kind(
+        targeted.0.as_ref().map(|target| &target.block),
+        &block_table,
+    );
+    let [fill, outline] = crosshair_colors(style, kind);
+    let denied = flash.strength(now);
+    let fill = Color::rgba(
+        lerp(fill.r(), DENIED_COLOR.r(), denied),
+        lerp(fill.g(), DENIED_COLOR.g(), denied),
+        lerp(fill.b(), DENIED_COLOR.b(), denied),
+        fill.a(),
+    );
+    for (mut color, layer) in layers.iter_mut() {
+        color.0 = if layer.outline { outline } else { fill };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::storage::blocks::descriptor::BlockDescriptor;
+
+    #[test]
+    fn style_round_trips_and_fills_in_missing_fields() {
+        let style = CrosshairStyle {
+            shape: CrosshairShape::Circle,
+            size: 24.0,
+            thickness: 3.0,
+            color: [40, 200, 90, 255],
+            outline: 0.0,
+            tint_targets: false,
+        };
+        let text = ron::to_string(&style).unwrap();
+        assert_eq!(ron::from_str::<CrosshairStyle>(&text).unwrap(), style);
+
+        // Configs saved before a field existed keep working
+        let partial: CrosshairStyle = ron::from_str("(shape: Dot, size: 10.0)").unwrap();
+        assert_eq!(
+            partial,
+            CrosshairStyle {
+                shape: CrosshairShape::Dot,
+                size: 10.0,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn classifies_targets_by_descriptor_flags() {
+        let mut block_table = BlockTable::default();
+        for (name, descriptor) in [
+            ("stone", BlockDescriptor::default()),
+            (
+                "chest",
+                BlockDescriptor {
+                    container_size: Some(27),
+                    ..Default::default()
+                },
+            ),
+            (
+                "bed",
+                BlockDescriptor {
+                    sleepable: Some(true),
+                    ..Default::default()
+                },
+            ),
+            (
+                "lever",
+                BlockDescriptor {
+                    interactable: Some(true),
+                    ..Default::default()
+                },
+            ),
+            (
+                "hay",
+                BlockDescriptor {
+                    sleepable: Some(false),
+                    interactable: Some(false),
+                    ..Default::default()
+                },
+            ),
+        ] {
+            block_table.insert(
+                name_to_identifier("vinox".to_string(), name.to_string()),
+                descriptor,
+            );
+        }
+        let kind = |name: &str| {
+            target_kind(
+                Some(&BlockData::new("vinox".to_string(), name.to_string())),
+                &block_table,
+            )
+        };
+        assert_eq!(kind("stone"), TargetKind::Block);
+        assert_eq!(kind("hay"), TargetKind::Block);
+        assert_eq!(kind("chest"), TargetKind::Interactable);
+        assert_eq!(kind("bed"), TargetKind::Interactable);
+        assert_eq!(kind("lever"), TargetKind::Interactable);
+        // Something the table doesn't know about is still a block
+        assert_eq!(kind("mystery"), TargetKind::Block);
+        assert_eq!(target_kind(None, &block_table), TargetKind::Nothing);
+    }
+}
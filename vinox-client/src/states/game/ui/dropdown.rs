@@ -1,39 +1,63 @@
-use bevy_quinnet::client::Client;
 use brigadier_rs::*;
-use egui_notify::Toasts;
-use std::{collections::BTreeMap, convert::Infallible};
-use vinox_common::networking::protocol::ClientMessage;
+use std::convert::Infallible;
+use vinox_common::networking::protocol::{ChatCategory, ClientMessage, NetworkIP, MAX_CHAT_CHARS};
 
 use bevy::{pbr::wireframe::WireframeConfig, prelude::*};
 use bevy_egui::{
-    egui::{Align2, FontId},
+    egui::{Align2, Sense, TextStyle},
     *,
 };
 
-use crate::states::{components::GameOptions, game::networking::components::ChatMessages};
+use crate::states::{
+    components::GameOptions,
+    fonts::{set_text_styles, with_fallback_glyphs, TextSizes},
+    game::{
+        networking::{
+            components::{ChatLine, ChatMessages},
+            connection::NetClient,
+            replay::{parse_replay, ReplayEvent},
+        },
+        rendering::remesh::RemeshAll,
+        ui::notifications::{apply_mute, muted_on, parse_mute, route, MuteCommand},
+        world::{
+            chunk_dump::{parse_dumpchunk, DumpChunkEvent},
+            finder::{parse_find, FindEvent},
+            schematic::{parse_schem, SchemEvent},
+        },
+    },
+};
+#[cfg(any(debug_assertions, feature = "netsim"))]
+use {
+    crate::states::game::networking::netsim::netsim_command,
+    vinox_common::networking::netsim::NetworkConditions,
+};
 
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct ConsoleOpen(pub bool);
 
-#[derive(Resource, Default, Deref, DerefMut)]
-pub struct Toast(pub Toasts);
-
 #[allow(clippy::too_many_arguments)]
 pub fn create_ui(
     // mut commands: Commands,
-    mut client: ResMut<Client>,
+    mut client: NetClient,
     is_open: Res<ConsoleOpen>, // mut username_res: ResMut<UserName>,
     mut current_message: Local<String>,
     mut messages: ResMut<ChatMessages>,
     mut contexts: EguiContexts,
-    mut toast: ResMut<Toast>,
-    options: Res<GameOptions>,
+    mut options: ResMut<GameOptions>,
+    ip: Res<NetworkIP>,
     mut wireframe_config: ResMut<WireframeConfig>,
+    (mut find_events, mut schem_events, mut replay_events, mut remesh_events, mut dump_events): (
+        EventWriter<FindEvent>,
+        EventWriter<SchemEvent>,
+        EventWriter<ReplayEvent>,
+        EventWriter<RemeshAll>,
+        EventWriter<DumpChunkEvent>,
+    ),
+    #[cfg(any(debug_assertions, feature = "netsim"))] mut conditions: ResMut<NetworkConditions>,
 ) {
     if !options.dark_theme {
         catppuccin_egui::set_theme(contexts.ctx_mut(), catppuccin_egui::MOCHA);
     }
-    toast.show(contexts.ctx_mut());
     if **is_open {
         let parser = literal("/wireframe")
             .then(boolean("bool").build_exec(|_ctx: (), bar| {
@@ -55,58 +79,134 @@ pub fn create_ui(
             .vscroll(true)
             .show(contexts.ctx_mut(), |ui| {
                 ui.with_layout(egui::Layout::top_down(egui::Align::LEFT), |ui| {
-                    ui.ctx().set_style(egui::Style {
-                        text_styles: {
-                            let mut texts = BTreeMap::new();
-                            texts.insert(egui::style::TextStyle::Small, FontId::proportional(16.0));
-                            texts.insert(egui::style::TextStyle::Body, FontId::proportional(16.0));
-                            texts.insert(
-                                egui::style::TextStyle::Heading,
-                                FontId::proportional(20.0),
-                            );
-                            texts
-                                .insert(egui::style::TextStyle::Monospace, FontId::monospace(16.0));
-                            texts
-                                .insert(egui::style::TextStyle::Button, FontId::proportional(16.0));
-                            texts
-                        },
-                        ..Default::default()
-                    });
+                    set_text_styles(ui.ctx(), TextSizes::CONSOLE, options.ui_text_scale);
 
                     egui::TopBottomPanel::bottom("text_box")
                         .resizable(false)
                         .show_inside(ui, |ui| {
                             ui.horizontal(|ui| {
                                 ui.label("Type: ");
-                                let response = ui.text_edit_singleline(&mut *current_message);
+                                let response = ui.add(
+                                    egui::TextEdit::singleline(&mut *current_message)
+                                        .char_limit(MAX_CHAT_CHARS),
+                                );
 
                                 // Pressing enter makes we lose focus
                                 let input_send = response.lost_focus()
                                     && ui.input(|input| input.key_pressed(egui::Key::Enter));
                                 if input_send {
+                                    #[cfg(any(debug_assertions, feature = "netsim"))]
+                                    let local = netsim_command(&current_message, &mut conditions);
+                                    #[cfg(not(any(debug_assertions, feature = "netsim")))]
+                                    let local: Option<String> = None;
                                     if let Ok((result, _)) = parser.parse((), &current_message) {
-                                        messages.push(("Console".to_string(), result.to_string()));
+                                        messages.push(ChatLine::console(result.to_string()));
                                         wireframe_config.global = !wireframe_config.global;
+                                    } else if let Some(reply) = local {
+                                        messages.push(ChatLine::console(reply));
+                                        current_message.clear();
+                                    } else if let Some(find) = parse_find(&current_message) {
+                                        match find {
+                                            Ok(command) => find_events.send(FindEvent(command)),
+                                            Err(usage) => messages.push(ChatLine::console(usage)),
+                                        }
+                                        current_message.clear();
+                                    } else if let Some(schem) = parse_schem(&current_message) {
+                                        match schem {
+                                            Ok(command) => schem_events.send(SchemEvent(command)),
+                                            Err(usage) => messages.push(ChatLine::console(usage)),
+                                        }
+                                        current_message.clear();
+                                    } else if let Some(replay) = parse_replay(&current_message) {
+                                        match replay {
+                                            Ok(command) => replay_events.send(ReplayEvent(command)),
+                                            Err(usage) => messages.push(ChatLine::console(usage)),
+                                        }
+                                        current_message.clear();
+                                    } else if let Some(dump) = parse_dumpchunk(&current_message) {
+                                        match dump {
+                                            Ok(at) => dump_events.send(DumpChunkEvent(at)),
+                                            Err(usage) => messages.push(ChatLine::console(usage)),
+                                        }
+                                        current_message.clear();
+                                    } else if current_message.trim() == "/remesh" {
+                                        remesh_events.send(RemeshAll);
+                                        messages.push(ChatLine::console(
+                                            "Remeshing every loaded chunk",
+                                        ));
+                                        current_message.clear();
+                                    } else if let Some(mute) = parse_mute(&current_message) {
+                                        let reply = match mute {
+                                            Ok(command) => apply_mute(
+                                                &command,
+                                                options.muted.entry(ip.0.clone()).or_default(),
+                                            ),
+                                            Err(usage) => usage,
+                                        };
+                                        messages.push(ChatLine::console(reply));
+                                        current_message.clear();
                                     } else {
-                                        client.connection_mut().try_send_message(
-                                            ClientMessage::ChatMessage {
-                                                message: current_message.to_string(),
-                                            },
-                                        );
+                                        client.send(ClientMessage::ChatMessage {
+                                            message: current_message.to_string(),
+                                        });
                                         current_message.clear();
                                     }
                                 }
                             });
                         });
 
+                    // Muted and filtered lines are only skipped here, unmuting brings them back
+                    let mut mute_request = None;
+                    let muted = muted_on(&options.muted, &ip);
+                    // Follows new lines unless scrolled up through the history
                     egui::ScrollArea::vertical()
                         .auto_shrink([false; 2])
+                        .stick_to_bottom(true)
                         .max_width(2000.0)
                         .show(ui, |ui| {
-                            for (username, message) in messages.iter() {
-                                ui.label(format!("{username}: {message}"));
+                            let font_id = TextStyle::Body.resolve(ui.style());
+                            let drawable = |ui: &egui::Ui, text: &str| {
+                                ui.ctx().fonts(|fonts| {
+                                    with_fallback_glyphs(text, |c| fonts.has_glyph(&font_id, c))
+                                        .into_owned()
+                                })
+                            };
+                            for line in messages
+                                .iter()
+                                .filter(|line| route(line, &options.notifications, muted).chat)
+                            {
+                                if line.user_name.is_empty() {
+                                    ui.label(drawable(ui, &line.message));
+                                    continue;
+                                }
+                                ui.horizontal_wrapped(|ui| {
+                                    ui.spacing_mut().item_spacing.x = 4.0;
+                                    let name = ui.add(
+                                        egui::Label::new(drawable(
+                                            ui,
+                                            &format!("{}:", line.user_name),
+                                        ))
+                                        .sense(Sense::click()),
+                                    );
+                                    if line.category == ChatCategory::PlayerChat && !line.own {
+                                        name.context_menu(|ui| {
+                                            if ui.button("Mute").clicked() {
+                                                mute_request = Some(line.user_name.clone());
+                                                ui.close_menu();
+                                            }
+                                        });
+                                    }
+                                    ui.label(drawable(ui, &line.message));
+                                });
                             }
                         });
+                    if let Some(name) = mute_request {
+                        let reply = apply_mute(
+                            &MuteCommand::Mute(name),
+                            options.muted.entry(ip.0.clone()).or_default(),
+                        );
+                        messages.push(ChatLine::console(reply));
+                    }
                 });
             });
     }
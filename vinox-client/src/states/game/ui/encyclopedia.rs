@@ -0,0 +1,834 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use vinox_common::{
+    storage::{
+        blocks::descriptor::BlockDescriptor,
+        items::descriptor::{ItemCategory, ItemDescriptor, MAX_STACK_SIZE},
+    },
+    world::chunks::storage::{trim_geo_identifier, BlockTable, ItemTable, RecipeTable},
+};
+
+use crate::states::{
+    assets::load::LoadableAssets,
+    components::GameOptions,
+    fonts::{set_text_styles, TextSizes},
+    game::rendering::icons::ItemIconCache,
+};
+
+use super::palette::{display_name, match_rank};
+
+pub const ENCYCLOPEDIA_ICON_SIZE: f32 = 48.0;
+pub const ENCYCLOPEDIA_LIST_WIDTH: f32 = 220.0;
+// Following links in a circle shouldn't grow the back stack forever
+pub const BACK_LIMIT: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Topic {
+    Block(String),
+    Item(String),
+}
+
+impl Topic {
+    pub fn identifier(&self) -> &str {
+        match self {
+            Topic::Block(identifier) | Topic::Item(identifier) => identifier,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shelf {
+    Blocks,
+    Items(ItemCategory),
+}
+
+pub struct IndexEntry {
+    pub topic: Topic,
+    pub display_name: String,
+    pub shelf: Shelf,
+    // Something a recipe or item points at that this client has no descriptor for
+    pub unknown: bool,
+    // Lowercased display name, identifier and namespace, same as the palette
+    keys: [String; 3],
+}
+
+impl IndexEntry {
+    pub fn new(topic: Topic, shelf: Shelf, unknown: bool) -> Self {
+        let (namespace, name) = topic
+            .identifier()
+            .split_once(':')
+            .unwrap_or(("", topic.identifier()));
+        let display_name = topic_display_name(name);
+        Self {
+            keys: [
+                display_name.to_lowercase(),
+                topic.identifier().to_lowercase(),
+                namespace.to_lowercase(),
+            ],
+            display_name,
+            shelf,
+            unknown,
+            topic,
+        }
+    }
+
+    pub fn rank(&self, query: &str) -> Option<u8> {
+        self.keys
+            .iter()
+            .filter_map(|key| match_rank(query, key))
+            .min()
+    }
+}
+
+// stone.slab -> Stone Slab
+pub fn topic_display_name(name: &str) -> String {
+    display_name(&name.replace('.', "_"))
+}
+
+// Built from the tables when they load, the window only ever reads it
+#[derive(Resource, Default)]
+pub struct EncyclopediaIndex {
+    pub entries: Vec<IndexEntry>,
+    // Item identifier to the recipes that take it and the ones that make it
+    pub used_in: HashMap<String, Vec<String>>,
+    pub made_by: HashMap<String, Vec<String>>,
+    // Breaking a block hands out the item with its name minus the variant, like interact does
+    pub drops: HashMap<String, Vec<String>>,
+    pub dropped_by: HashMap<String, Vec<String>>,
+    // Block identifier to the items that place it
+    pub placed_by: HashMap<String, Vec<String>>,
+}
+
+impl EncyclopediaIndex {
+    pub fn build(
+        block_table: &BlockTable,
+        item_table: &ItemTable,
+        recipe_table: &RecipeTable,
+    ) -> Self {
+        let mut index = Self::default();
+        for (identifier, recipe) in recipe_table.iter() {
+            for input in recipe
+                .required_items
+                .iter()
+                .flat_map(|inputs| inputs.keys())
+            {
+                index
+                    .used_in
+                    .entry(input.clone())
+                    .or_default()
+                    .push(identifier.clone());
+            }
+            index
+                .made_by
+                .entry(recipe.output_item.0.clone())
+                .or_default()
+                .push(identifier.clone());
+        }
+        for identifier in block_table.keys() {
+            let item = trim_geo_identifier(identifier.clone());
+            if item_table.contains_key(&item) {
+                index
+                    .drops
+                    .entry(identifier.clone())
+                    .or_default()
+                    .push(item.clone());
+                index
+                    .dropped_by
+                    .entry(item)
+                    .or_default()
+                    .push(identifier.clone());
+            }
+        }
+        for (identifier, item) in item_table.iter() {
+            if let Some(block) = &item.associated_block {
+                index
+                    .placed_by
+                    .entry(block.clone())
+                    .or_default()
+                    .push(identifier.clone());
+            }
+        }
+        for list in index
+            .used_in
+            .values_mut()
+            .chain(index.made_by.values_mut())
+            .chain(index.drops.values_mut())
+            .chain(index.dropped_by.values_mut())
+            .chain(index.placed_by.values_mut())
+        {
+            list.sort();
+            list.dedup();
+        }
+
+        for identifier in block_table.keys() {
+            index.entries.push(IndexEntry::new(
+                Topic::Block(identifier.clone()),
+                Shelf::Blocks,
+                false,
+            ));
+        }
+        for (identifier, item) in item_table.iter() {
+            index.entries.push(IndexEntry::new(
+                Topic::Item(identifier.clone()),
+                Shelf::Items(item.category()),
+                false,
+            ));
+        }
+        let unknown_items: HashSet<&String> = index
+            .used_in
+            .keys()
+            .chain(index.made_by.keys())
+            .filter(|identifier| !item_table.contains_key(*identifier))
+            .collect();
+        let unknown_blocks: HashSet<&String> = index
+            .placed_by
+            .keys()
+            .filter(|identifier| !block_table.contains_key(*identifier))
+            .collect();
+        let unknown: Vec<IndexEntry> = unknown_items
+            .into_iter()
+            .map(|identifier| {
+                IndexEntry::new(
+                    Topic::Item(identifier.clone()),
+                    Shelf::Items(ItemCategory::Misc),
+                    true,
+                )
+            })
+            .chain(unknown_blocks.into_iter().map(|identifier| {
+                IndexEntry::new(Topic::Block(identifier.clone()), Shelf::Blocks, true)
+            }))
+            .collect();
+        index.entries.extend(unknown);
+        index.entries.sort_by(|entry, other| {
+            entry
+                .display_name
+                .cmp(&other.display_name)
+                .then_with(|| entry.topic.cmp(&other.topic))
+        });
+        index
+    }
+
+    // Items whose recipes go on a page, a block goes by what it drops
+    fn recipe_items(&self, topic: &Topic) -> Vec<String> {
+        match topic {
+            Topic::Item(identifier) => vec![identifier.clone()],
+            Topic::Block(identifier) => self.drops.get(identifier).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+pub fn search(entries: &[IndexEntry], query: &str, shelf: Option<Shelf>) -> Vec<usize> {
+    let query = query.trim().to_lowercase();
+    let mut results: Vec<(u8, usize)> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| shelf.is_none_or(|shelf| entry.shelf == shelf))
+        .filter_map(|(index, entry)| entry.rank(&query).map(|rank| (rank, index)))
+        .collect();
+    // Entries are already sorted by name so a stable sort keeps that within a rank
+    results.sort_by_key(|(rank, _)| *rank);
+    results.into_iter().map(|(_, index)| index).collect()
+}
+
+#[derive(Resource, Default)]
+pub struct EncyclopediaState {
+    pub open: bool,
+    pub search: String,
+    // None shows every shelf
+    pub shelf: Option<Shelf>,
+    pub current: Option<Topic>,
+    // Where links were followed from, newest last
+    pub back: Vec<Topic>,
+    pub results: Vec<usize>,
+    searched: Option<(String, Option<Shelf>)>,
+}
+
+impl EncyclopediaState {
+    pub fn open_topic(&mut self, topic: Topic) {
+        if self.current.as_ref() == Some(&topic) {
+            return;
+        }
+        if let Some(previous) = self.current.replace(topic) {
+            if self.back.len() == BACK_LIMIT {
+                self.back.remove(0);
+            }
+            self.back.push(previous);
+        }
+    }
+
+    pub fn go_back(&mut self) {
+        if let Some(previous) = self.back.pop() {
+            self.current = Some(previous);
+        }
+    }
+}
+
+pub fn build_encyclopedia(
+    block_table: Res<BlockTable>,
+    item_table: Res<ItemTable>,
+    recipe_table: Res<RecipeTable>,
+    mut index: ResMut<EncyclopediaIndex>,
+    mut state: ResMut<EncyclopediaState>,
+) {
+    if !block_table.is_changed() && !item_table.is_changed() && !recipe_table.is_changed() {
+        return;
+    }
+    *index = EncyclopediaIndex::build(&block_table, &item_table, &recipe_table);
+    state.searched = None;
+}
+
+struct Tables<'a> {
+    blocks: &'a BlockTable,
+    items: &'a ItemTable,
+    recipes: &'a RecipeTable,
+    index: &'a EncyclopediaIndex,
+}
+
+impl Tables<'_> {
+    fn name(&self, topic: &Topic) -> String {
+        let known = match topic {
+            Topic::Block(identifier) => self
+                .blocks
+                .get(identifier)
+                .map(|block| topic_display_name(&block.name)),
+            Topic::Item(identifier) => self
+                .items
+                .get(identifier)
+                .map(|item| topic_display_name(&item.name)),
+        };
+        known.unwrap_or_else(|| format!("Unknown ({})", topic.identifier()))
+    }
+
+    fn link(&self, ui: &mut egui::Ui, topic: Topic, clicked: &mut Option<Topic>) {
+        if ui
+            .link(self.name(&topic))
+            .on_hover_text(topic.identifier())
+            .clicked()
+        {
+            *clicked = Some(topic);
+        }
+    }
+
+    fn links(
+        &self,
+        ui: &mut egui::Ui,
+        heading: &str,
+        topics: impl Iterator<Item = Topic>,
+        clicked: &mut Option<Topic>,
+    ) {
+        let topics: Vec<Topic> = topics.collect();
+        if topics.is_empty() {
+            return;
+        }
+        ui.horizontal_wrapped(|ui| {
+            ui.strong(heading);
+            for topic in topics {
+                self.link(ui, topic, clicked);
+            }
+        });
+    }
+
+    fn recipe(&self, ui: &mut egui::Ui, identifier: &str, clicked: &mut Option<Topic>) {
+        let Some(recipe) = self.recipes.get(identifier) else {
+            ui.weak(format!("Unknown recipe {identifier}"));
+            return;
+        };
+        ui.horizontal_wrapped(|ui| {
+            ui.label(format!("{}:", topic_display_name(&recipe.name)));
+            let mut inputs: Vec<(&String, &u32)> = recipe.required_items.iter().flatten().collect();
+            inputs.sort();
+            for (input, count) in inputs {
+                ui.label(format!("{count}x"));
+                self.link(ui, Topic::Item(input.clone()), clicked);
+            }
+            ui.label("->");
+            ui.label(format!("{}x", recipe.output_item.1));
+            self.link(ui, Topic::Item(recipe.output_item.0.clone()), clicked);
+        });
+    }
+
+    fn recipes(&self, ui: &mut egui::Ui, topic: &Topic, clicked: &mut Option<Topic>) {
+        let items = self.index.recipe_items(topic);
+        for (heading, recipes) in [
+            ("Made by", &self.index.made_by),
+            ("Used in", &self.index.used_in),
+        ] {
+            let mut identifiers: Vec<&String> = items
+                .iter()
+                .filter_map(|item| recipes.get(item))
+                .flatten()
+                .collect();
+            identifiers.dedup();
+            if identifiers.is_empty() {
+                continue;
+            }
+            ui.separator();
+            ui.strong(heading);
+            for identifier in identifiers {
+                self.recipe(ui, identifier, clicked);
+            }
+        }
+    }
+}
+
+fn properties(ui: &mut egui::Ui, rows: Vec<(&str, String)>) {
+    egui::Grid::new("encyclopedia_properties")
+        .striped(true)
+        .show(ui, |ui| {
+            for (name, value) in rows {
+                ui.label(name);
+                ui.label(value);
+                ui.end_row();
+            }
+        });
+}
+
+fn block_details(
+    ui: &mut egui::Ui,
+    identifier: &str,
+    block: &BlockDescriptor,
+    tables: &Tables,
+    clicked: &mut Option<Topic>,
+) {
+    let mut rows = vec![
+        ("Identifier", identifier.to_string()),
+        ("Namespace", block.namespace.clone()),
+        (
+            "Hardness",
+            block
+                .durability
+                .map_or("None".to_string(), |durability| durability.to_string()),
+        ),
+        (
+            "Tool",
+            block
+                .tool_type
+                .as_ref()
+                .map_or("Any".to_string(), |tool| format!("{tool:?}")),
+        ),
+    ];
+    if let Some((red, green, blue, intensity)) = block.light {
+        rows.push(("Light", format!("{intensity} ({red}, {green}, {blue})")));
+    }
+    if let Some(size) = block.container_size {
+        rows.push(("Container", format!("{size} slots")));
+    }
+    let flags: Vec<&str> = [
+        (block.fluid, "Fluid"),
+        (block.climbable, "Climbable"),
+        (block.interactable, "Interactable"),
+        (block.sleepable, "Sets respawn"),
+        (block.display_frame, "Displays an item"),
+        (block.seat.map(|_| true), "Seat"),
+    ]
+    .into_iter()
+    .filter_map(|(flag, name)| (flag == Some(true)).then_some(name))
+    .collect();
+    if !flags.is_empty() {
+        rows.push(("Flags", flags.join(", ")));
+    }
+    properties(ui, rows);
+    ui.separator();
+    match tables.index.drops.get(identifier) {
+        Some(items) => tables.links(ui, "Drops", items.iter().cloned().map(Topic::Item), clicked),
+        None => {
+            ui.horizontal(|ui| {
+                ui.strong("Drops");
+                ui.label("Nothing");
+            });
+        }
+    }
+    tables.links(
+        ui,
+        "Placed by",
+        tables
+            .index
+            .placed_by
+            .get(identifier)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .map(Topic::Item),
+        clicked,
+    );
+}
+
+fn item_details(
+    ui: &mut egui::Ui,
+    identifier: &str,
+    item: &ItemDescriptor,
+    tables: &Tables,
+    clicked: &mut Option<Topic>,
+) {
+    let mut rows = vec![
+        ("Identifier", identifier.to_string()),
+        ("Namespace", item.namespace.clone()),
+        ("Category", item.category().name().to_string()),
+        (
+            "Stack size",
+            item.max_stack_size.unwrap_or(MAX_STACK_SIZE).to_string(),
+        ),
+    ];
+    if let Some(tool) = &item.tool_type {
+        rows.push(("Tool", format!("{tool:?}")));
+    }
+    if let Some(durability) = item.max_durability {
+        rows.push(("Durability", durability.to_string()));
+    }
+    let timing = item.use_timing();
+    if !timing.is_instant() {
+        rows.push(("Use time", format!("{:.2} s", timing.duration.as_secs())));
+    }
+    if timing.cooldown.as_secs() > 0.0 {
+        rows.push(("Cooldown", format!("{:.2} s", timing.cooldown.as_secs())));
+    }
+    properties(ui, rows);
+    ui.separator();
+    tables.links(
+        ui,
+        "Places",
+        item.associated_block.iter().cloned().map(Topic::Block),
+        clicked,
+    );
+    tables.links(
+        ui,
+        "Dropped by",
+        tables
+            .index
+            .dropped_by
+            .get(identifier)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .map(Topic::Block),
+        clicked,
+    );
+}
+
+pub fn encyclopedia_ui(
+    mut contexts: EguiContexts,
+    mut state: ResMut<EncyclopediaState>,
+    index: Res<EncyclopediaIndex>,
+    (block_table, item_table, recipe_table): (Res<BlockTable>, Res<ItemTable>, Res<RecipeTable>),
+    loadable_assets: Res<LoadableAssets>,
+    icon_cache: Res<ItemIconCache>,
+    options: Res<GameOptions>,
+) {
+    if !state.open {
+        return;
+    }
+    if !options.dark_theme {
+        catppuccin_egui::set_theme(contexts.ctx_mut(), catppuccin_egui::MOCHA);
+    }
+    let query = (state.search.clone(), state.shelf);
+    if state.searched.as_ref() != Some(&query) {
+        state.results = search(&index.entries, &query.0, query.1);
+        state.searched = Some(query);
+    }
+    // A block shows the icon of the item it drops or is placed with
+    let icon = state
+        .current
+        .as_ref()
+        .and_then(|topic| match topic {
+            Topic::Item(identifier) => Some(identifier.clone()),
+            Topic::Block(identifier) => index
+                .drops
+                .get(identifier)
+                .or_else(|| index.placed_by.get(identifier))
+                .and_then(|items| items.first().cloned()),
+        })
+        .and_then(|identifier| icon_cache.get(&identifier, &loadable_assets))
+        .or_else(|| loadable_assets.item_textures.get("empty"))
+        .and_then(|handle| contexts.image_id(handle));
+
+    let tables = Tables {
+        blocks: &block_table,
+        items: &item_table,
+        recipes: &recipe_table,
+        index: &index,
+    };
+    let state = &mut *state;
+    let mut clicked = None;
+    let mut go_back = false;
+    egui::Window::new("Encyclopedia")
+        .collapsible(false)
+        .default_size([720.0, 480.0])
+        .show(contexts.ctx_mut(), |ui| {
+            set_text_styles(ui.ctx(), TextSizes::GAME, options.ui_text_scale);
+            ui.horizontal(|ui| {
+                ui.label("Search: ");
+                ui.text_edit_singleline(&mut state.search);
+            });
+            ui.horizontal_wrapped(|ui| {
+                ui.selectable_value(&mut state.shelf, None, "All");
+                ui.selectable_value(&mut state.shelf, Some(Shelf::Blocks), "Blocks");
+                for category in ItemCategory::ALL {
+                    ui.selectable_value(
+                        &mut state.shelf,
+                        Some(Shelf::Items(category)),
+                        category.name(),
+                    );
+                }
+            });
+            ui.separator();
+            ui.horizontal_top(|ui| {
+                ui.vertical(|ui| {
+                    ui.set_width(ENCYCLOPEDIA_LIST_WIDTH);
+                    ui.label(format!("{} entries", state.results.len()));
+                    egui::ScrollArea::vertical()
+                        .id_source("encyclopedia_list")
+                        .auto_shrink([false; 2])
+                        .show_rows(
+                            ui,
+                            ui.spacing().interact_size.y,
+                            state.results.len(),
+                            |ui, rows| {
+                                for result in &state.results[rows] {
+                                    let entry = &index.entries[*result];
+                                    let selected = state.current.as_ref() == Some(&entry.topic);
+                                    let label = if entry.unknown {
+                                        egui::RichText::new(&entry.display_name).weak()
+                                    } else {
+                                        egui::RichText::new(&entry.display_name)
+                                    };
+                                    if ui
+                                        .selectable_label(selected, label)
+                                        .on_hover_text(entry.topic.identifier())
+                                        .clicked()
+                                    {
+                                        clicked = Some(entry.topic.clone());
+                                    }
+                                }
+                            },
+                        );
+                });
+                ui.separator();
+                ui.vertical(|ui| {
+                    if ui
+                        .add_enabled(!state.back.is_empty(), egui::Button::new("Back"))
+                        .clicked()
+                    {
+                        go_back = true;
+                    }
+                    let Some(topic) = &state.current else {
+                        ui.label("Pick something from the list");
+                        return;
+                    };
+                    egui::ScrollArea::vertical()
+                        .id_source("encyclopedia_entry")
+                        .show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                if let Some(icon) = icon {
+                                    ui.image(
+                                        icon,
+                                        [ENCYCLOPEDIA_ICON_SIZE, ENCYCLOPEDIA_ICON_SIZE],
+                                    );
+                                }
+                                ui.heading(tables.name(topic));
+                            });
+                            match topic {
+                                Topic::Block(identifier) => match block_table.get(identifier) {
+                                    Some(block) => {
+                                        block_details(ui, identifier, block, &tables, &mut clicked)
+                                    }
+                                    None => unknown_details(ui, "block", identifier),
+                                },
+                                Topic::Item(identifier) => match item_table.get(identifier) {
+                                    Some(item) => {
+                                        item_details(ui, identifier, item, &tables, &mut clicked)
+                                    }
+                                    None => unknown_details(ui, "item", identifier),
+                                },
+                            }
+                            tables.recipes(ui, topic, &mut clicked);
+                        });
+                });
+            });
+        });
+
+    if go_back {
+        state.go_back();
+    }
+    if let Some(topic) = clicked {
+        state.open_topic(topic);
+    }
+}
+
+fn unknown_details(ui: &mut egui::Ui, kind: &str, identifier: &str) {
+    ui.label(identifier);
+    ui.weak(format!(
+        "No descriptor for this {kind} here, it likely comes from content this client doesn't have"
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::storage::crafting::descriptor::RecipeDescriptor;
+
+    fn tables() -> (BlockTable, ItemTable, RecipeTable) {
+        let mut block_table = BlockTable::default();
+        for name in ["stone", "stone.slab", "planks", "lamp"] {
+            block_table.insert(
+                format!("vinox:{name}"),
+                BlockDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+        let mut item_table = ItemTable::default();
+        for (name, block) in [
+            ("stone", Some("vinox:stone")),
+            ("planks", Some("vinox:planks")),
+            ("stick", None),
+            ("torch", Some("modded:torch")),
+        ] {
+            item_table.insert(
+                format!("vinox:{name}"),
+                ItemDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    associated_block: block.map(str::to_string),
+                    ..Default::default()
+                },
+            );
+        }
+        let mut recipe_table = RecipeTable::default();
+        for (name, inputs, output) in [
+            ("sticks", vec![("vinox:planks", 2)], "vinox:stick"),
+            (
+                "torches",
+                vec![("vinox:stick", 1), ("modded:coal", 1)],
+                "vinox:torch",
+            ),
+            (
+                "cobbled_planks",
+                vec![("vinox:stone", 1), ("vinox:planks", 1)],
+                "vinox:planks",
+            ),
+        ] {
+            recipe_table.insert(
+                format!("vinox:{name}"),
+                RecipeDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    required_items: Some(
+                        inputs
+                            .into_iter()
+                            .map(|(input, count)| (input.to_string(), count))
+                            .collect(),
+                    ),
+                    output_item: (output.to_string(), 1),
+                    ..Default::default()
+                },
+            );
+        }
+        (block_table, item_table, recipe_table)
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn recipes_are_listed_under_every_item_they_touch() {
+        let (block_table, item_table, recipe_table) = tables();
+        let index = EncyclopediaIndex::build(&block_table, &item_table, &recipe_table);
+        for (identifier, recipe) in recipe_table.iter() {
+            for input in recipe
+                .required_items
+                .iter()
+                .flat_map(|inputs| inputs.keys())
+            {
+                assert!(index.used_in[input].contains(identifier), "{input}");
+            }
+            assert!(index.made_by[&recipe.output_item.0].contains(identifier));
+        }
+        assert_eq!(
+            index.used_in["vinox:planks"],
+            strings(&["vinox:cobbled_planks", "vinox:sticks"])
+        );
+        assert_eq!(
+            index.made_by["vinox:planks"],
+            strings(&["vinox:cobbled_planks"])
+        );
+        assert!(!index.used_in.contains_key("vinox:torch"));
+    }
+
+    #[test]
+    fn drops_map_back_to_their_blocks() {
+        let (block_table, item_table, recipe_table) = tables();
+        let index = EncyclopediaIndex::build(&block_table, &item_table, &recipe_table);
+        // Variants drop the base item
+        assert_eq!(index.drops["vinox:stone.slab"], strings(&["vinox:stone"]));
+        assert_eq!(
+            index.dropped_by["vinox:stone"],
+            strings(&["vinox:stone", "vinox:stone.slab"])
+        );
+        assert!(!index.drops.contains_key("vinox:lamp"));
+        for (block, items) in &index.drops {
+            for item in items {
+                assert!(index.dropped_by[item].contains(block));
+            }
+        }
+        assert_eq!(index.placed_by["vinox:planks"], strings(&["vinox:planks"]));
+        assert_eq!(
+            index.recipe_items(&Topic::Block("vinox:stone.slab".to_string())),
+            strings(&["vinox:stone"])
+        );
+    }
+
+    #[test]
+    fn missing_descriptors_get_placeholders() {
+        let (block_table, item_table, recipe_table) = tables();
+        let index = EncyclopediaIndex::build(&block_table, &item_table, &recipe_table);
+        let unknown: Vec<&Topic> = index
+            .entries
+            .iter()
+            .filter(|entry| entry.unknown)
+            .map(|entry| &entry.topic)
+            .collect();
+        assert_eq!(unknown.len(), 2);
+        assert!(unknown.contains(&&Topic::Item("modded:coal".to_string())));
+        assert!(unknown.contains(&&Topic::Block("modded:torch".to_string())));
+        assert_eq!(
+            index.entries.len(),
+            block_table.len() + item_table.len() + 2
+        );
+
+        let slab = search(&index.entries, "stone slab", None);
+        assert_eq!(
+            index.entries[slab[0]].topic,
+            Topic::Block("vinox:stone.slab".to_string())
+        );
+        let modded = search(&index.entries, "modded", Some(Shelf::Blocks));
+        assert_eq!(modded.len(), 1);
+        assert!(index.entries[modded[0]].unknown);
+    }
+
+    #[test]
+    fn back_stack_follows_links() {
+        let mut state = EncyclopediaState::default();
+        let stone = Topic::Block("vinox:stone".to_string());
+        let item = Topic::Item("vinox:stone".to_string());
+        state.go_back();
+        assert!(state.current.is_none());
+        state.open_topic(stone.clone());
+        state.open_topic(item.clone());
+        // Clicking what's already open doesn't stack it twice
+        state.open_topic(item.clone());
+        assert_eq!(state.back, vec![stone.clone()]);
+        state.go_back();
+        assert_eq!(state.current, Some(stone.clone()));
+        assert!(state.back.is_empty());
+        for _ in 0..BACK_LIMIT + 10 {
+            state.open_topic(item.clone());
+            state.open_topic(stone.clone());
+        }
+        assert_eq!(state.back.len(), BACK_LIMIT);
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs,
+};
+
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Align2, Color32, RichText},
+    EguiContexts,
+};
+use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
+use vinox_common::{
+    ecs::bundles::Health,
+    world::chunks::storage::{name_to_identifier, BlockTable},
+};
+
+use crate::states::{
+    components::{GameActions, GameOptions, ProjectPath},
+    fonts::{set_text_styles, TextSizes},
+    game::{
+        input::{drop::PickedUpEvent, player::TargetedBlock},
+        world::chunks::ControlledPlayer,
+    },
+};
+
+use super::hud::StatsUpdateEvent;
+
+// Goes away on its own after this if nobody dismisses it, the cursor is usually grabbed
+pub const HINT_SECONDS: f32 = 12.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum HintTrigger {
+    EnteredGame,
+    TargetedBlock,
+    PickedUpItem,
+    TookDamage,
+    TargetedContainer,
+}
+
+// From hints.ron. {Action} in the text is replaced with whatever that action is bound to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HintDescriptor {
+    pub id: String,
+    pub trigger: HintTrigger,
+    pub text: String,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct HintBook(pub Vec<HintDescriptor>);
+
+pub struct HintTriggered(pub HintTrigger);
+
+// The text stays as written until it's drawn, so rebinding a key shows up straight away
+#[derive(Resource, Debug, Default)]
+pub struct Hints {
+    queue: VecDeque<HintDescriptor>,
+    shown_at: Option<f32>,
+}
+
+impl Hints {
+    // Every hint for the triggers that hasn't been shown yet, once each however many times a
+    // trigger fired. Returns whether shown changed
+    pub fn queue(
+        &mut self,
+        book: &HintBook,
+        triggers: &BTreeSet<HintTrigger>,
+        shown: &mut BTreeSet<String>,
+    ) -> bool {
+        let mut changed = false;
+        for hint in book
+            .0
+            .iter()
+            .filter(|hint| triggers.contains(&hint.trigger))
+        {
+            if shown.insert(hint.id.clone()) {
+                self.queue.push_back(hint.clone());
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // Starts timing the front hint the first time it's asked for
+    pub fn current(&mut self, now: f32) -> Option<&HintDescriptor> {
+        if self
+            .shown_at
+            .is_some_and(|shown_at| now - shown_at >= HINT_SECONDS)
+        {
+            self.dismiss();
+        }
+        let hint = self.queue.front()?;
+        self.shown_at.get_or_insert(now);
+        Some(hint)
+    }
+
+    pub fn dismiss(&mut self) {
+        self.queue.pop_front();
+        self.shown_at = None;
+    }
+}
+
+// Keys and buttons joined up for a chord, None when nothing is bound
+pub fn binding_label(input_map: &InputMap<GameActions>, action: GameActions) -> Option<String> {
+    let raw = input_map.get(action).get_at(0)?.raw_inputs();
+    let keys = raw.keycodes.iter().map(|key| format!("{key:?}"));
+    let buttons = raw
+        .mouse_buttons
+        .iter()
+        .map(|button| format!("{button:?} mouse"));
+    let label: Vec<String> = keys.chain(buttons).collect();
+    (!label.is_empty()).then(|| label.join("+"))
+}
+
+// Anything in braces that isn't an action is left alone
+pub fn fill_bindings(text: &str, input_map: &InputMap<GameActions>) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        filled.push_str(&rest[..start]);
+        let name = &rest[start + 1..end];
+        match GameActions::variants().find(|action| format!("{action:?}") == name) {
+            Some(action) => filled.push_str(
+                &binding_label(input_map, action).unwrap_or_else(|| "unbound".to_string()),
+            ),
+            None => filled.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+pub fn load_hints(mut commands: Commands, project_path: Res<ProjectPath>) {
+    let path = project_path.join("hints.ron");
+    let hints = match fs::read_to_string(&path) {
+        Ok(text) => ron::from_str(&text).unwrap_or_else(|e| {
+            println!("Couldn't read {}: {e}", path.display());
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    };
+    commands.insert_resource(HintBook(hints));
+}
+
+pub fn entered_game_hint(mut triggers: EventWriter<HintTriggered>) {
+    triggers.send(HintTriggered(HintTrigger::EnteredGame));
+}
+
+// Everything here already exists for something else, nothing gets scanned just for hints
+pub fn detect_hints(
+    targeted: Res<TargetedBlock>,
+    block_table: Res<BlockTable>,
+    player: Query<&Health, With<ControlledPlayer>>,
+    (mut picked_up, mut stats): (EventReader<PickedUpEvent>, EventReader<StatsUpdateEvent>),
+    mut triggers: EventWriter<HintTriggered>,
+) {
+    if let Some(target) = &targeted.0 {
+        triggers.send(HintTriggered(HintTrigger::TargetedBlock));
+        let container = block_table
+            .get(&name_to_identifier(
+                target.block.namespace.clone(),
+                target.block.name.clone(),
+            ))
+            .is_some_and(|descriptor| descriptor.container_size.is_some());
+        if container {
+            triggers.send(HintTriggered(HintTrigger::TargetedContainer));
+        }
+    }
+    if picked_up.iter().count() > 0 {
+        triggers.send(HintTriggered(HintTrigger::PickedUpItem));
+    }
+    // Runs before update_stats so the player still has the old health
+    let health = player.get_single().map(|health| health.current);
+    if stats
+        .iter()
+        .any(|evt| health.is_ok_and(|health| evt.health.current < health))
+    {
+        triggers.send(HintTriggered(HintTrigger::TookDamage));
+    }
+}
+
+pub fn queue_hints(
+    mut events: EventReader<HintTriggered>,
+    book: Res<HintBook>,
+    mut hints: ResMut<Hints>,
+    mut options: ResMut<GameOptions>,
+) {
+    let triggers: BTreeSet<HintTrigger> = events.iter().map(|evt| evt.0).collect();
+    if triggers.is_empty() || !options.hints {
+        return;
+    }
+    // Only touched when something new went up, every change gets saved
+    let mut shown = options.shown_hints.clone();
+    if hints.queue(&book, &triggers, &mut shown) {
+        options.shown_hints = shown;
+    }
+}
+
+pub fn hints_ui(
+    mut contexts: EguiContexts,
+    mut hints: ResMut<Hints>,
+    options: Res<GameOptions>,
+    time: Res<Time>,
+) {
+    if !options.hints {
+        return;
+    }
+    let Some(hint) = hints.current(time.elapsed_seconds()) else {
+        return;
+    };
+    let text = fill_bindings(&hint.text, &options.input);
+    let mut dismissed = false;
+    egui::Area::new("hints")
+        .anchor(Align2::CENTER_BOTTOM, [0.0, -130.0])
+        .show(contexts.ctx_mut(), |ui| {
+            set_text_styles(ui.ctx(), TextSizes::GAME, options.ui_text_scale);
+            egui::Frame::popup(ui.style())
+                .fill(Color32::from_black_alpha(200))
+                .show(ui, |ui| {
+                    ui.set_max_width(360.0);
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new(text).color(Color32::WHITE));
+                        dismissed = ui.small_button("Got it").clicked();
+                    });
+                });
+        });
+    if dismissed {
+        hints.dismiss();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hint(id: &str, trigger: HintTrigger) -> HintDescriptor {
+        HintDescriptor {
+            id: id.to_string(),
+            trigger,
+            text: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn placeholders_follow_the_bindings() {
+        let mut input_map = GameOptions::default().input;
+        let text = "Press {Jump} to jump, {PrimaryInteract} to break, {Nothing} stays";
+        assert_eq!(
+            fill_bindings(text, &input_map),
+            "Press Space to jump, Left mouse to break, {Nothing} stays"
+        );
+        input_map.clear_action(GameActions::Jump);
+        input_map.insert(KeyCode::K, GameActions::Jump);
+        assert_eq!(fill_bindings("{Jump}", &input_map), "K");
+        input_map.clear_action(GameActions::Jump);
+        assert_eq!(fill_bindings("{Jump}", &input_map), "unbound");
+        assert_eq!(fill_bindings("{Profiler}", &input_map), "F3+P");
+        assert_eq!(fill_bindings("{unclosed", &input_map), "{unclosed");
+    }
+
+    #[test]
+    fn hints_only_ever_show_once() {
+        let book = HintBook(vec![
+            hint("move", HintTrigger::EnteredGame),
+            hint("break", HintTrigger::TargetedBlock),
+            hint("open", HintTrigger::TargetedContainer),
+        ]);
+        let mut shown = BTreeSet::new();
+        let mut hints = Hints::default();
+        // Targeting a chest fires both in the same frame, and the target fires every frame
+        let triggers = BTreeSet::from([HintTrigger::TargetedBlock, HintTrigger::TargetedContainer]);
+        assert!(hints.queue(&book, &triggers, &mut shown));
+        assert!(!hints.queue(&book, &triggers, &mut shown));
+        assert_eq!(hints.current(0.0).unwrap().id, "break");
+        hints.dismiss();
+        assert_eq!(hints.current(1.0).unwrap().id, "open");
+        // Not dismissed, it times out
+        assert!(hints.current(1.0 + HINT_SECONDS).is_none());
+
+        // Another session with the same profile only gets what's left
+        let mut hints = Hints::default();
+        let triggers = BTreeSet::from([HintTrigger::EnteredGame, HintTrigger::TargetedBlock]);
+        assert!(hints.queue(&book, &triggers, &mut shown));
+        assert_eq!(hints.current(0.0).unwrap().id, "move");
+        hints.dismiss();
+        assert!(hints.current(0.0).is_none());
+        assert_eq!(shown.len(), 3);
+    }
+}
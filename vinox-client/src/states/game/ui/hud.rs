@@ -0,0 +1,349 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Color32, Pos2, Rect, Sense, TextureId},
+    EguiContexts,
+};
+use vinox_common::{
+    ecs::{
+        bundles::{Health, Hunger},
+        gameplay::GameplayRules,
+        time::GameClock,
+    },
+    storage::{
+        biomes::climate::{climate_at, tint_color},
+        blocks::descriptor::{BlockDescriptor, TintKind},
+    },
+};
+
+use crate::states::{
+    assets::load::LoadableAssets,
+    components::GameOptions,
+    game::{rendering::ambience::ViewAmbience, world::chunks::ControlledPlayer},
+};
+
+pub const HUD_ICONS: [&str; 7] = [
+    "heart_full",
+    "heart_half",
+    "heart_empty",
+    "heart_flash",
+    "food_full",
+    "food_half",
+    "food_empty",
+];
+
+// Each icon stands for two points so a half icon is a single point
+pub const POINTS_PER_ICON: f32 = 2.0;
+pub const ICON_SIZE: f32 = 18.0;
+pub const SHAKE_TIME: f32 = 0.4;
+pub const LOW_HEALTH: f32 = 0.2;
+// Strongest the flash pulses while health is coming back, well short of a hit
+pub const REGEN_FLASH: f32 = 0.3;
+pub const UNDERWATER_COLOR: [f32; 3] = [0.2, 0.35, 0.8];
+pub const UNDERWATER_ALPHA: f32 = 0.35;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarSegment {
+    Full,
+    Half,
+    Empty,
+}
+
+pub struct StatsUpdateEvent {
+    pub health: Health,
+    pub hunger: Hunger,
+}
+
+#[derive(Resource, Default)]
+pub struct HealthShake {
+    pub time_left: f32,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct BarStyle {
+    pub scale: f32,
+    pub offset: egui::Vec2,
+    // Per icon wobble driven by time, none when the bar should stay still
+    pub jiggle: Option<f32>,
+    // White overlay texture and how opaque it is
+    pub flash: Option<(TextureId, f32)>,
+}
+
+// Partial points round up so anything above zero still shows a half icon.
+// The bool is true when the value was out of range and had to be clamped.
+pub fn bar_segments(current: f32, max: f32) -> (Vec<BarSegment>, bool) {
+    let max = max.max(0.0);
+    let clamped = !(0.0..=max).contains(&current);
+    let current = current.clamp(0.0, max);
+    let icons = (max / POINTS_PER_ICON).ceil() as usize;
+    let halves = current.ceil() as usize;
+    let segments = (0..icons)
+        .map(|icon| match halves.saturating_sub(icon * 2) {
+            0 => BarSegment::Empty,
+            1 => BarSegment::Half,
+            _ => BarSegment::Full,
+        })
+        .collect();
+    (segments, clamped)
+}
+
+pub fn segmented_bar(
+    ui: &mut egui::Ui,
+    current: f32,
+    max: f32,
+    icon_full: TextureId,
+    icon_half: TextureId,
+    icon_empty: TextureId,
+) -> egui::Response {
+    segmented_bar_styled(
+        ui,
+        current,
+        max,
+        [icon_full, icon_half, icon_empty],
+        BarStyle {
+            scale: 1.0,
+            ..Default::default()
+        },
+    )
+}
+
+pub fn segmented_bar_styled(
+    ui: &mut egui::Ui,
+    current: f32,
+    max: f32,
+    [icon_full, icon_half, icon_empty]: [TextureId; 3],
+    style: BarStyle,
+) -> egui::Response {
+    let (segments, _) = bar_segments(current, max);
+    let size = ICON_SIZE * style.scale;
+    let (rect, response) = ui.allocate_exact_size(
+        egui::vec2(size * segments.len() as f32, size),
+        Sense::hover(),
+    );
+    let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+    let painter = ui.painter();
+    for (index, segment) in segments.iter().enumerate() {
+        let mut icon_rect = Rect::from_min_size(
+            rect.min + egui::vec2(size * index as f32, 0.0) + style.offset,
+            egui::vec2(size, size),
+        );
+        if let Some(time) = style.jiggle {
+            let wobble = (time * 18.0 + index as f32 * 2.3).sin();
+            // Only the peaks move so hearts twitch instead of floating
+            if wobble > 0.8 {
+                icon_rect = icon_rect.translate(egui::vec2(0.0, -size * 0.12));
+            }
+        }
+        // The container always draws so a lower max still reads as empty slots
+        painter.image(icon_empty, icon_rect, uv, Color32::WHITE);
+        match segment {
+            BarSegment::Full => painter.image(icon_full, icon_rect, uv, Color32::WHITE),
+            BarSegment::Half => painter.image(icon_half, icon_rect, uv, Color32::WHITE),
+            BarSegment::Empty => {}
+        }
+        if let Some((icon_flash, alpha)) = style.flash {
+            painter.image(
+                icon_flash,
+                icon_rect,
+                uv,
+                Color32::from_white_alpha((alpha.clamp(0.0, 1.0) * 255.0) as u8),
+            );
+        }
+    }
+    response
+}
+
+// Only reacts to server updates so the shake isn't tied to frame diffs
+pub fn update_stats(
+    mut events: EventReader<StatsUpdateEvent>,
+    mut player_query: Query<(&mut Health, &mut Hunger), With<ControlledPlayer>>,
+    mut shake: ResMut<HealthShake>,
+) {
+    for evt in events.iter() {
+        if let Ok((mut health, mut hunger)) = player_query.get_single_mut() {
+            if evt.health.current < health.current {
+                shake.time_left = SHAKE_TIME;
+            }
+            *health = evt.health;
+            *hunger = evt.hunger;
+        }
+    }
+}
+
+pub fn stats_hud(
+    mut contexts: EguiContexts,
+    player_query: Query<(&Health, &Hunger), With<ControlledPlayer>>,
+    options: Res<GameOptions>,
+    loadable_assets: Res<LoadableAssets>,
+    mut shake: ResMut<HealthShake>,
+    (clock, rules): (Res<GameClock>, Res<GameplayRules>),
+    mut logged_clamp: Local<[bool; 2]>,
+) {
+    shake.time_left = (shake.time_left - clock.delta_seconds()).max(0.0);
+    if !options.show_hud {
+        return;
+    }
+    let Ok((health, hunger)) = player_query.get_single() else {
+        return;
+    };
+    let mut icons = Vec::with_capacity(HUD_ICONS.len());
+    for icon in HUD_ICONS {
+        let Some(id) = loadable_assets
+            .hud_textures
+            .get(icon)
+            .and_then(|handle| contexts.image_id(handle))
+        else {
+            return;
+        };
+        icons.push(id);
+    }
+    for (index, (name, current, max)) in [
+        ("Health", health.current, health.max),
+        ("Hunger", hunger.current, hunger.max),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if bar_segments(current, max).1 && !logged_clamp[index] {
+            logged_clamp[index] = true;
+            println!("{name} {current} is outside of 0..={max}, clamping");
+        }
+    }
+
+    let scale = options.hud_scale;
+    let elapsed = clock.now().as_secs();
+    let shaking = shake.time_left > 0.0;
+    let heart_style = BarStyle {
+        scale,
+        offset: if shaking && !options.reduce_motion {
+            egui::vec2((elapsed * 60.0).sin() * 2.0 * scale, 0.0)
+        } else {
+            egui::Vec2::ZERO
+        },
+        jiggle: (health.current < health.max * LOW_HEALTH && !options.reduce_motion)
+            .then_some(elapsed),
+        flash: if shaking {
+            Some((icons[3], shake.time_left / SHAKE_TIME))
+        } else if rules.regenerating(health.current, health.max, hunger.current) {
+            // Held steady rather than pulsing for anyone who'd rather things didn't move
+            let pulse = if options.reduce_motion {
+                0.5
+            } else {
+                (elapsed * 3.0).sin() * 0.5 + 0.5
+            };
+            Some((icons[3], pulse * REGEN_FLASH))
+        } else {
+            None
+        },
+    };
+
+    egui::TopBottomPanel::bottom("stats_hud")
+        .frame(egui::Frame::none())
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                segmented_bar_styled(
+                    ui,
+                    health.current,
+                    health.max,
+                    [icons[0], icons[1], icons[2]],
+                    heart_style,
+                );
+                ui.add_space(ICON_SIZE * scale);
+                segmented_bar_styled(
+                    ui,
+                    hunger.current,
+                    hunger.max,
+                    [icons[4], icons[5], icons[6]],
+                    BarStyle {
+                        scale,
+                        ..Default::default()
+                    },
+                );
+            });
+        });
+}
+
+// The water's own tint shifts the overlay so a swamp doesn't look like the open ocean
+pub fn underwater_color(descriptor: &BlockDescriptor, voxel: IVec3) -> Option<[f32; 3]> {
+    let tinted_water = descriptor.tint == Some(TintKind::Water);
+    if !tinted_water && !descriptor.fluid.unwrap_or(false) {
+        return None;
+    }
+    let tint = descriptor
+        .tint
+        .map(|kind| tint_color(kind, climate_at(voxel.x, voxel.z)))
+        .unwrap_or([1.0; 3]);
+    Some([
+        UNDERWATER_COLOR[0] * tint[0],
+        UNDERWATER_COLOR[1] * tint[1],
+        UNDERWATER_COLOR[2] * tint[2],
+    ])
+}
+
+// Fades in and out with the rest of the view, see rendering::ambience
+pub fn underwater_overlay(mut contexts: EguiContexts, ambience: Res<ViewAmbience>) {
+    let applied = ambience.applied();
+    let alpha = UNDERWATER_ALPHA * applied.underwater;
+    if alpha * 255.0 < 1.0 {
+        return;
+    }
+    let ctx = contexts.ctx_mut();
+    let [r, g, b] = applied
+        .underwater_color
+        .to_array()
+        .map(|channel| (channel * 255.0) as u8);
+    ctx.layer_painter(egui::LayerId::background()).rect_filled(
+        ctx.screen_rect(),
+        0.0,
+        Color32::from_rgba_unmultiplied(r, g, b, (alpha * 255.0) as u8),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BarSegment::*;
+
+    #[test]
+    fn segments_round_halves_up() {
+        assert_eq!(bar_segments(0.0, 4.0), (vec![Empty, Empty], false));
+        assert_eq!(bar_segments(0.25, 4.0), (vec![Half, Empty], false));
+        assert_eq!(bar_segments(1.0, 4.0), (vec![Half, Empty], false));
+        assert_eq!(bar_segments(1.5, 4.0), (vec![Full, Empty], false));
+        assert_eq!(bar_segments(2.0, 4.0), (vec![Full, Empty], false));
+        assert_eq!(bar_segments(2.01, 4.0), (vec![Full, Half], false));
+        assert_eq!(bar_segments(3.0, 4.0), (vec![Full, Half], false));
+        assert_eq!(bar_segments(4.0, 4.0), (vec![Full, Full], false));
+    }
+
+    #[test]
+    fn segments_every_boundary() {
+        for quarter in 0..=80 {
+            let current = quarter as f32 / 4.0;
+            let (segments, clamped) = bar_segments(current, 20.0);
+            assert!(!clamped);
+            assert_eq!(segments.len(), 10);
+            let halves: usize = segments
+                .iter()
+                .map(|segment| match segment {
+                    Full => 2,
+                    Half => 1,
+                    Empty => 0,
+                })
+                .sum();
+            assert_eq!(halves, current.ceil() as usize);
+            // Filled icons are always packed at the front
+            assert!(segments
+                .windows(2)
+                .all(|pair| pair[0] != Empty || pair[1] == Empty));
+            assert!(segments.iter().filter(|segment| **segment == Half).count() <= 1);
+        }
+    }
+
+    #[test]
+    fn segments_clamp_out_of_range() {
+        assert_eq!(bar_segments(30.0, 20.0), (vec![Full; 10], true));
+        assert_eq!(bar_segments(-3.0, 20.0), (vec![Empty; 10], true));
+        // Odd max adds a container for the last point
+        assert_eq!(bar_segments(5.0, 5.0), (vec![Full, Full, Half], false));
+    }
+}
@@ -1,28 +1,60 @@
 use egui_extras::{Size, StripBuilder};
-use std::collections::BTreeMap;
 
 use bevy::prelude::*;
 use bevy_egui::{
-    egui::{Color32, FontId, Sense},
+    egui::{Align2, Color32, FontId, LayerId, Order, PointerButton, Sense},
     *,
 };
 use vinox_common::{
-    ecs::bundles::{CurrentInvBar, CurrentInvItem, Inventory},
+    ecs::{
+        arrange::InventoryOp,
+        bundles::{Inventory, InventorySection, SlotRef},
+        time::GameClock,
+    },
     storage::items::descriptor::ItemData,
-    world::chunks::storage::name_to_identifier,
+    world::chunks::storage::{name_to_identifier, ItemTable},
 };
 
 use crate::states::{
-    assets::load::LoadableAssets, components::GameOptions, game::world::chunks::ControlledPlayer,
+    assets::load::LoadableAssets,
+    components::GameOptions,
+    fonts::{set_text_styles, TextSizes},
+    game::{
+        input::{
+            arrange::ArrangeEvent,
+            drop::HoveredSlot,
+            item_use::ItemUseState,
+            look::CursorGrab,
+            template::HeldBlockTemplate,
+            variant::{variant_label, PlacementVariant, VariantMenu},
+        },
+        rendering::icons::ItemIconCache,
+        ui::{
+            palette::display_name,
+            radial::{radial_menu, RadialEntry},
+        },
+        world::chunks::ControlledPlayer,
+    },
 };
 
+const TEMPLATE_BORDER: Color32 = Color32::from_rgb(220, 120, 255);
+const UTILITY_MARK: Color32 = Color32::from_rgb(240, 190, 60);
+
+#[allow(clippy::too_many_arguments)]
 pub fn status_bar(
     mut player_query: Query<&mut Inventory, With<ControlledPlayer>>,
     mut contexts: EguiContexts,
     options: Res<GameOptions>,
-    mut held_items: ResMut<CurrentItemsHeld>,
-    mut holding: ResMut<Holding>,
-    loadable_assets: Res<LoadableAssets>,
+    mut held: ResMut<HeldStack>,
+    (loadable_assets, icon_cache): (Res<LoadableAssets>, Res<ItemIconCache>),
+    (use_state, clock, variant, template): (
+        Res<ItemUseState>,
+        Res<GameClock>,
+        Res<PlacementVariant>,
+        Res<HeldBlockTemplate>,
+    ),
+    mut hovered: ResMut<HoveredSlot>,
+    (mut arrange, item_table, grab): (EventWriter<ArrangeEvent>, Res<ItemTable>, Res<CursorGrab>),
 ) {
     if !options.dark_theme {
         catppuccin_egui::set_theme(contexts.ctx_mut(), catppuccin_egui::MOCHA);
@@ -33,18 +65,7 @@ pub fn status_bar(
         .max_height(75.0)
         .show(&ctx, |ui| {
             ui.with_layout(egui::Layout::left_to_right(egui::Align::Center), |ui| {
-                ui.ctx().set_style(egui::Style {
-                    text_styles: {
-                        let mut texts = BTreeMap::new();
-                        texts.insert(egui::style::TextStyle::Small, FontId::proportional(18.0));
-                        texts.insert(egui::style::TextStyle::Body, FontId::proportional(18.0));
-                        texts.insert(egui::style::TextStyle::Heading, FontId::proportional(20.0));
-                        texts.insert(egui::style::TextStyle::Monospace, FontId::monospace(18.0));
-                        texts.insert(egui::style::TextStyle::Button, FontId::proportional(18.0));
-                        texts
-                    },
-                    ..Default::default()
-                });
+                set_text_styles(ui.ctx(), TextSizes::GAME, options.ui_text_scale);
                 if let Ok(mut inventory) = player_query.get_single_mut() {
                     StripBuilder::new(ui)
                         .size(Size::exact(50.0))
@@ -62,24 +83,40 @@ pub fn status_bar(
                             {
                                 for (item_num, item) in hotbar_section.iter().clone().enumerate() {
                                     strip.cell(|ui| {
-                                        let color = if *inventory.current_item == item_num
-                                            && *inventory.current_bar == hotbar_num
+                                        let slot_ref = SlotRef {
+                                            section: InventorySection::Hotbar,
+                                            bar: hotbar_num,
+                                            slot: item_num,
+                                        };
+                                        let lifted = lifted_from(&held, slot_ref);
+                                        // Moving things around only works with the inventory open
+                                        let arranging = inventory.open && !grab.is_grabbed();
+                                        let color = if (*inventory.current_item == item_num
+                                            && *inventory.current_bar == hotbar_num)
+                                            || lifted > 0
                                         {
                                             Color32::from_white_alpha(128)
                                         } else {
                                             Color32::WHITE
                                         };
-                                        egui::Frame::none().outer_margin(2.0).fill(color).show(
-                                            ui,
-                                            |ui| {
+                                        // Placing an exact copy instead of what's in the slot
+                                        let stroke = if template.held_in((hotbar_num, item_num)) {
+                                            egui::Stroke::new(3.0, TEMPLATE_BORDER)
+                                        } else {
+                                            egui::Stroke::NONE
+                                        };
+                                        let slot_frame = egui::Frame::none()
+                                            .outer_margin(2.0)
+                                            .fill(color)
+                                            .stroke(stroke)
+                                            .show(ui, |ui| {
                                                 if let Some(item) = item {
                                                     let image = ui
                                                         .add(
                                                             egui::widgets::Image::new(
                                                                 contexts
                                                                     .image_id(
-                                                                        loadable_assets
-                                                                            .item_textures
+                                                                        icon_cache
                                                                             .get(
                                                                                 &name_to_identifier(
                                                                                     item.namespace
@@ -87,6 +124,7 @@ pub fn status_bar(
                                                                                     item.name
                                                                                         .clone(),
                                                                                 ),
+                                                                                &loadable_assets,
                                                                             )
                                                                             .unwrap(),
                                                                     )
@@ -96,37 +134,33 @@ pub fn status_bar(
                                                             .tint(color)
                                                             .sense(Sense::click()),
                                                         )
-                                                        .on_hover_ui(|ui| {
-                                                            ui.label(format!(
-                                                                "{}: x{}",
-                                                                item.name.clone(),
-                                                                item.stack_size
-                                                            ));
-                                                        });
-                                                    let mut modified_rect = image.rect.clone();
-                                                    modified_rect.min.y +=
-                                                        modified_rect.size().y / 2.0;
-                                                    ui.allocate_ui_at_rect(modified_rect, |ui| {
-                                                        egui::Frame::none()
-                                                            .fill(Color32::from_rgba_unmultiplied(
-                                                                0, 0, 0, 164,
-                                                            ))
-                                                            .show(ui, |ui| {
-                                                                ui.add(egui::Label::new(format!(
-                                                                    "{}",
-                                                                    item.stack_size
-                                                                )));
-                                                            });
-                                                    });
-                                                    if image.clicked() {
-                                                        grab_stack(
-                                                            &mut held_items,
+                                                        .on_hover_ui(|ui| item_tooltip(ui, &item));
+                                                    draw_durability(ui, image.rect, &item);
+                                                    if image.hovered() {
+                                                        **hovered = Some(slot_ref);
+                                                    }
+                                                    draw_use_timing(
+                                                        ui,
+                                                        image.rect,
+                                                        &use_state,
+                                                        &clock,
+                                                        (hotbar_num, item_num),
+                                                    );
+                                                    draw_count(
+                                                        ui,
+                                                        image.rect,
+                                                        item.stack_size.saturating_sub(lifted),
+                                                    );
+                                                    if image.clicked() && arranging {
+                                                        if let Some(op) = click_slot(
+                                                            &mut held,
                                                             &mut inventory,
-                                                            &mut holding,
-                                                            hotbar_num,
-                                                            item_num,
-                                                            true,
-                                                        );
+                                                            slot_ref,
+                                                            false,
+                                                            &item_table,
+                                                        ) {
+                                                            arrange.send(ArrangeEvent(op));
+                                                        }
                                                     }
                                                 } else if ui
                                                     .add(
@@ -145,310 +179,501 @@ pub fn status_bar(
                                                         .sense(Sense::click()),
                                                     )
                                                     .clicked()
+                                                    && arranging
                                                 {
-                                                    grab_stack(
-                                                        &mut held_items,
+                                                    if let Some(op) = click_slot(
+                                                        &mut held,
                                                         &mut inventory,
-                                                        &mut holding,
-                                                        hotbar_num,
-                                                        item_num,
-                                                        true,
-                                                    );
+                                                        slot_ref,
+                                                        false,
+                                                        &item_table,
+                                                    ) {
+                                                        arrange.send(ArrangeEvent(op));
+                                                    }
                                                 }
-                                            },
-                                        );
+                                            });
+                                        if options.utility_slot == Some(hotbar_num * 3 + item_num) {
+                                            draw_utility_mark(ui, slot_frame.response.rect);
+                                        }
                                     });
                                 }
                             }
                         });
                 }
+                // Only worth showing once something other than the plain block is picked
+                if variant.is_some() {
+                    ui.separator();
+                    ui.label(variant_label(&variant.0, ""));
+                }
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                     ui.separator();
                     ui.label(format!("Thirst: {}", 100.0));
                     ui.separator();
-                    ui.label(format!("Hunger: {}", 100.0));
-                    ui.separator();
-                    ui.label(format!("Health: {}", 100.0));
-                    ui.separator();
                 });
             });
         });
 }
 
-#[derive(Resource, Default, Clone, Debug, Deref, DerefMut)]
-pub struct CurrentItemsHeld(pub Vec<(ItemData, &'static str, usize, usize)>);
-#[derive(Resource, Default, Clone, Debug, Deref, DerefMut)]
-pub struct Holding(pub bool);
+pub fn variant_menu_ui(
+    mut contexts: EguiContexts,
+    menu: Res<VariantMenu>,
+    loadable_assets: Res<LoadableAssets>,
+    icon_cache: Res<ItemIconCache>,
+) {
+    if !menu.open {
+        return;
+    }
+    let Some(held) = &menu.held else {
+        return;
+    };
+    let held_icon = icon_cache
+        .get(
+            &name_to_identifier(held.namespace.clone(), held.name.clone()),
+            &loadable_assets,
+        )
+        .and_then(|handle| contexts.image_id(handle));
+    let entries: Vec<RadialEntry> = menu
+        .variants
+        .iter()
+        .map(|variant| RadialEntry {
+            label: variant_label(variant, &held.name),
+            // Variants aren't items so only the base block has an icon to show
+            icon: variant.is_none().then_some(held_icon).flatten(),
+        })
+        .collect();
+    radial_menu(contexts.ctx_mut(), "variant_menu", &entries, menu.hovered);
+}
 
-// Item data is the items we are moving importantly also how much we are moving. So to move half a stack we set the item data to half of the source item.
-// Right now we only ever have one held but this is a vec for the future for bulk moving
+// The custom name when it has one
+pub fn item_label(item: &ItemData) -> String {
+    item.custom_name()
+        .map_or_else(|| display_name(&item.name), str::to_string)
+}
 
-// TODO: Change bar and inventory slots possible to be one big array instead of two seperate. Would make it cleaner to access items
-pub fn grab_stack(
-    held_items: &mut CurrentItemsHeld,
-    inventory: &mut Inventory,
-    holding: &mut Holding,
-    row_index: usize,
-    row_item: usize,
-    bar: bool,
+fn item_tooltip(ui: &mut egui::Ui, item: &ItemData) {
+    ui.label(format!("{}: x{}", item_label(item), item.stack_size));
+    // A renamed item still says what it is
+    if item.custom_name().is_some() {
+        ui.weak(display_name(&item.name));
+    }
+    if let Some(durability) = item.worn_durability() {
+        ui.label(format!(
+            "Durability: {}/{}",
+            durability.current, durability.max
+        ));
+    }
+}
+
+// Thin bar along the bottom once a tool has taken wear, green going to red
+fn draw_durability(ui: &egui::Ui, rect: egui::Rect, item: &ItemData) {
+    let Some(durability) = item.worn_durability() else {
+        return;
+    };
+    let fraction = durability.fraction().clamp(0.0, 1.0);
+    let mut bar = rect;
+    bar.min.y = rect.max.y - 3.0;
+    ui.painter()
+        .rect_filled(bar, 0.0, Color32::from_black_alpha(200));
+    bar.max.x = bar.min.x + bar.width() * fraction;
+    let color = Color32::from_rgb(
+        ((1.0 - fraction) * 255.0) as u8,
+        (fraction * 255.0) as u8,
+        0,
+    );
+    ui.painter().rect_filled(bar, 0.0, color);
+}
+
+// How many are left in the lower half of the slot, nothing once the whole stack is picked up
+fn draw_count(ui: &mut egui::Ui, rect: egui::Rect, count: u32) {
+    if count == 0 {
+        return;
+    }
+    let mut lower = rect;
+    lower.min.y += rect.height() / 2.0;
+    ui.allocate_ui_at_rect(lower, |ui| {
+        egui::Frame::none()
+            .fill(Color32::from_rgba_unmultiplied(0, 0, 0, 164))
+            .show(ui, |ui| {
+                ui.add(egui::Label::new(format!("{count}")));
+            });
+    });
+}
+
+// A small gold corner on the slot QuickUse acts with
+fn draw_utility_mark(ui: &egui::Ui, rect: egui::Rect) {
+    let corner = rect.right_top();
+    ui.painter().add(egui::Shape::convex_polygon(
+        vec![
+            corner,
+            corner + egui::vec2(0.0, 12.0),
+            corner + egui::vec2(-12.0, 0.0),
+        ],
+        UTILITY_MARK,
+        egui::Stroke::NONE,
+    ));
+}
+
+// A shade that shrinks upwards as the cooldown runs out and a fill that rises while a use charges
+fn draw_use_timing(
+    ui: &egui::Ui,
+    rect: egui::Rect,
+    use_state: &ItemUseState,
+    clock: &GameClock,
+    slot: (usize, usize),
 ) {
-    if !**holding {
-        if bar {
-            if inventory.hotbar[row_index][row_item].is_some() {
-                held_items.insert(
-                    0,
-                    (
-                        inventory.hotbar[row_index][row_item].clone().unwrap(),
-                        "bar",
-                        row_index,
-                        row_item,
-                    ),
-                );
-                inventory.hotbar[row_index][row_item] = None;
-                **holding = true;
-            }
-        } else if inventory.slots[row_index][row_item].is_some() {
-            held_items.insert(
-                0,
-                (
-                    inventory.slots[row_index][row_item].clone().unwrap(),
-                    "inventory",
-                    row_index,
-                    row_item,
-                ),
-            );
-            inventory.slots[row_index][row_item] = None;
-            **holding = true;
-        }
+    let now = clock.now();
+    let cooldown = use_state.cooldown_fraction(slot, now);
+    if cooldown > 0.0 {
+        let mut shade = rect;
+        shade.min.y = rect.max.y - rect.height() * cooldown;
+        ui.painter()
+            .rect_filled(shade, 0.0, Color32::from_black_alpha(160));
+    }
+    if let Some(charge) = use_state.charge(slot, now) {
+        let mut fill = rect;
+        fill.min.y = rect.max.y - rect.height() * charge;
+        ui.painter()
+            .rect_filled(fill, 0.0, Color32::from_white_alpha(96));
+    }
+}
+
+// A stack picked up in the inventory. It stays in its slot until it's put down, so closing the
+// window can't lose it and the server only hears about the op it turns into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Held {
+    pub from: SlotRef,
+    pub count: u32,
+    // Picked up by dragging, so letting go puts it down
+    pub dragging: bool,
+}
+
+#[derive(Resource, Default, Clone, Debug, Deref, DerefMut)]
+pub struct HeldStack(pub Option<Held>);
+
+fn lifted_from(held: &HeldStack, slot: SlotRef) -> u32 {
+    held.filter(|held| held.from == slot)
+        .map_or(0, |held| held.count)
+}
+
+// The whole stack, or half of it rounding up
+pub fn pick_up(inventory: &Inventory, from: SlotRef, half: bool, dragging: bool) -> Option<Held> {
+    let item = inventory.slot(from)?.as_ref()?;
+    let count = if half {
+        (item.stack_size + 1) / 2
     } else {
-        if bar {
-            if inventory.hotbar[row_index][row_item].is_none() {
-                inventory.hotbar[row_index][row_item] =
-                    Some(held_items.0.get(0).unwrap().0.clone());
-                held_items.remove(0);
-            } else {
-                let held_item = held_items.get(0).unwrap().clone();
-                held_items.remove(0);
-                let temp_item = inventory.hotbar[row_index][row_item].clone();
-                inventory.hotbar[row_index][row_item] = Some(held_item.0.clone());
-                match held_item.1 {
-                    "inventory" => {
-                        inventory.slots[held_item.2][held_item.3] = temp_item;
-                    }
-                    "bar" => {
-                        inventory.hotbar[held_item.2][held_item.3] = temp_item;
-                    }
-                    _ => {}
-                }
-            }
-        } else if inventory.slots[row_index][row_item].is_none() {
-            inventory.slots[row_index][row_item] = Some(held_items.0.get(0).unwrap().0.clone());
-            held_items.remove(0);
-        } else {
-            let held_item = held_items.get(0).unwrap().clone();
-            held_items.remove(0);
-            let temp_item = inventory.slots[row_index][row_item].clone();
-            inventory.slots[row_index][row_item] = Some(held_item.0.clone());
-            match held_item.1 {
-                "inventory" => {
-                    inventory.slots[held_item.2][held_item.3] = temp_item;
-                }
-                "bar" => {
-                    inventory.hotbar[held_item.2][held_item.3] = temp_item;
-                }
-                _ => {}
-            }
-        }
-        **holding = false;
+        item.stack_size
+    };
+    Some(Held {
+        from,
+        count,
+        dragging,
+    })
+}
+
+// Picks up with nothing held, otherwise puts down. Clicking where it came from puts it back.
+// Whatever was put down is already applied here and comes back to be sent
+pub fn click_slot(
+    held: &mut HeldStack,
+    inventory: &mut Inventory,
+    slot: SlotRef,
+    half: bool,
+    item_table: &ItemTable,
+) -> Option<InventoryOp> {
+    let Some(current) = **held else {
+        **held = pick_up(inventory, slot, half, false);
+        return None;
+    };
+    if current.from == slot {
+        **held = None;
+        return None;
     }
+    let op = InventoryOp::place(inventory, current.from, current.count, slot, item_table)?;
+    if !inventory.apply(&op, item_table) {
+        return None;
+    }
+    **held = None;
+    Some(op)
+}
+
+// The server or a drop can take from the stack while it's held
+fn still_held(held: Held, inventory: &Inventory) -> Option<Held> {
+    let item = inventory.slot(held.from)?.as_ref()?;
+    Some(Held {
+        count: held.count.min(item.stack_size),
+        ..held
+    })
 }
 
-// TODO: Actually make this work
-// pub fn grab_half_stack(
-//     held_items: &mut CurrentItemsHeld,
-//     inventory: &mut Inventory,
-//     holding: &mut Holding,
-//     row_index: usize,
-//     row_item: usize,
-//     bar: bool,
-// ) {
-// }
+fn slot_texture(
+    contexts: &EguiContexts,
+    (loadable_assets, icon_cache): (&LoadableAssets, &ItemIconCache),
+    item: Option<&ItemData>,
+) -> egui::TextureId {
+    let handle = match item {
+        Some(item) => icon_cache.get(
+            &name_to_identifier(item.namespace.clone(), item.name.clone()),
+            loadable_assets,
+        ),
+        None => loadable_assets.item_textures.get("empty"),
+    };
+    contexts.image_id(handle.unwrap()).unwrap()
+}
 
+// One slot of the inventory window, dimmed with what's left while its stack is held
+fn inventory_slot(
+    ui: &mut egui::Ui,
+    contexts: &EguiContexts,
+    icons: (&LoadableAssets, &ItemIconCache),
+    item: Option<&ItemData>,
+    lifted: u32,
+) -> egui::Response {
+    let color = if lifted > 0 {
+        Color32::from_white_alpha(128)
+    } else {
+        Color32::WHITE
+    };
+    egui::Frame::none()
+        .outer_margin(2.0)
+        .fill(color)
+        .show(ui, |ui| {
+            let image = ui.add(
+                egui::widgets::Image::new(slot_texture(contexts, icons, item), [48.0, 48.0])
+                    .tint(color)
+                    .sense(Sense::click_and_drag()),
+            );
+            let Some(item) = item else {
+                return image;
+            };
+            draw_durability(ui, image.rect, item);
+            draw_count(ui, image.rect, item.stack_size.saturating_sub(lifted));
+            image.on_hover_ui(|ui| item_tooltip(ui, item))
+        })
+        .inner
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn inventory(
     mut player_query: Query<&mut Inventory, With<ControlledPlayer>>,
-    mut held_items: ResMut<CurrentItemsHeld>,
-    mut holding: ResMut<Holding>,
+    mut held: ResMut<HeldStack>,
     mut contexts: EguiContexts,
     options: Res<GameOptions>,
-    loadable_assets: Res<LoadableAssets>,
+    (loadable_assets, icon_cache): (Res<LoadableAssets>, Res<ItemIconCache>),
+    (mut hovered, grab): (ResMut<HoveredSlot>, Res<CursorGrab>),
+    (mut arrange, item_table): (EventWriter<ArrangeEvent>, Res<ItemTable>),
 ) {
     if !options.dark_theme {
         catppuccin_egui::set_theme(contexts.ctx_mut(), catppuccin_egui::MOCHA);
     }
+    let Ok(mut inventory) = player_query.get_single_mut() else {
+        return;
+    };
+    // Nothing has moved yet, so closing just lets go of it
+    if !inventory.open {
+        **held = None;
+        return;
+    }
+    **held = held.and_then(|current| still_held(current, &inventory));
 
     let ctx = contexts.ctx_mut().clone();
-    if let Ok(mut inventory) = player_query.get_single_mut() {
-        if inventory.open {
-            egui::Window::new("inventory")
-                .resizable(false)
-                .constrain(true)
-                .show(&ctx, |ui| {
-                    ui.ctx().set_style(egui::Style {
-                        text_styles: {
-                            let mut texts = BTreeMap::new();
-                            texts.insert(egui::style::TextStyle::Small, FontId::proportional(18.0));
-                            texts.insert(egui::style::TextStyle::Body, FontId::proportional(18.0));
-                            texts.insert(
-                                egui::style::TextStyle::Heading,
-                                FontId::proportional(20.0),
-                            );
-                            texts
-                                .insert(egui::style::TextStyle::Monospace, FontId::monospace(18.0));
-                            texts
-                                .insert(egui::style::TextStyle::Button, FontId::proportional(18.0));
-                            texts
-                        },
-                        ..Default::default()
-                    });
-                    let cloned_inv = inventory.clone();
-                    StripBuilder::new(ui)
-                        .size(Size::exact(50.0))
-                        .size(Size::exact(50.0))
-                        .size(Size::exact(50.0))
-                        .size(Size::exact(50.0))
-                        .size(Size::exact(50.0))
-                        .vertical(|mut strip| {
-                            for (row_num, row_section) in
-                                cloned_inv.slots.iter().cloned().enumerate()
-                            {
-                                strip.cell(|ui| {
-                                    StripBuilder::new(ui)
-                                        .size(Size::exact(50.0))
-                                        .size(Size::exact(50.0))
-                                        .size(Size::exact(50.0))
-                                        .size(Size::exact(50.0))
-                                        .size(Size::exact(50.0))
-                                        .size(Size::exact(50.0))
-                                        .size(Size::exact(50.0))
-                                        .size(Size::exact(50.0))
-                                        .size(Size::exact(50.0))
-                                        .horizontal(|mut strip| {
-                                            for (item_num, item) in
-                                                row_section.iter().clone().enumerate()
-                                            {
-                                                strip.cell(|ui| {
-                                                    let color = if *inventory.current_inv_item
-                                                        == item_num
-                                                        && *inventory.current_inv_bar == row_num
-                                                        && **holding
-                                                    {
-                                                        Color32::from_white_alpha(128)
-                                                    } else {
-                                                        Color32::WHITE
-                                                    };
-                                                    egui::Frame::none()
-                                                        .outer_margin(2.0)
-                                                        .fill(color)
-                                                        .show(ui, |ui| {
-                                                            if let Some(item) = item {
-                                                            let image = ui
-                                                    .add(
-                                                        egui::widgets::Image::new(
-                                                            contexts
-                                                                .image_id(
-                                                                    loadable_assets
-                                                                        .item_textures
-                                                                        .get(&name_to_identifier(
-                                                                            item.namespace.clone(),
-                                                                            item.name.clone(),
-                                                                        ))
-                                                                        .unwrap(),
-                                                                )
-                                                                .unwrap(),
-                                                            [48.0, 48.0],
-                                                        )
-                                                        .tint(color)
-                                                        .sense(Sense::click()),
-                                                    )
-                                                    .on_hover_ui(|ui| {
-                                                        ui.label(format!(
-                                                            "{}: x{}",
-                                                            item.name.clone(),
-                                                            item.stack_size
-                                                        ));
-                                                    });
-                                                            let mut modified_rect =
-                                                                image.rect.clone();
-                                                            modified_rect.min.y +=
-                                                                modified_rect.size().y / 2.0;
-                                                            ui.allocate_ui_at_rect(
-                                                                modified_rect,
-                                                                |ui| {
-                                                                    egui::Frame::none()
-                                                        .fill(Color32::from_rgba_unmultiplied(
-                                                            0, 0, 0, 164,
-                                                        ))
-                                                        .show(ui, |ui| {
-                                                            ui.add(egui::Label::new(format!(
-                                                                "{}",
-                                                                item.stack_size
-                                                            )));
-                                                        });
-                                                                },
-                                                            );
-                                                            if image.clicked() {
-                                                                inventory.current_inv_item = CurrentInvItem(item_num);
-                                                                inventory.current_inv_bar = CurrentInvBar(row_num);
-                                                                grab_stack(
-                                                                    &mut held_items,
-                                                                    &mut inventory,
-                                                                    &mut holding,
-                                                                    row_num,
-                                                                    item_num,
-                                                                    false,
-                                                                );
-                                                            }
-                                                        } else if ui
-                                                            .add(
-                                                                egui::widgets::Image::new(
-                                                                    contexts
-                                                                        .image_id(
-                                                                            loadable_assets
-                                                                                .item_textures
-                                                                                .get(
-                                                                                    &"empty"
-                                                                                        .to_string(
-                                                                                        ),
-                                                                                )
-                                                                                .unwrap(),
-                                                                        )
-                                                                        .unwrap(),
-                                                                    [48.0, 48.0],
-                                                                )
-                                                                .tint(color)
-                                                                .sense(Sense::click()),
-                                                            )
-                                                            .clicked()
-                                                        {
-                                                            inventory.current_inv_item = CurrentInvItem(item_num);
-                                                            inventory.current_inv_bar = CurrentInvBar(row_num);
-                                                            grab_stack(
-                                                                &mut held_items,
-                                                                &mut inventory,
-                                                                &mut holding,
-                                                                row_num,
-                                                                item_num,
-                                                                false,
-                                                            );
-                                                        }
-                                                        });
-                                                });
-                                            }
-                                        });
-                                });
-                            }
-                        });
+    let icons = (&*loadable_assets, &*icon_cache);
+    let shown = inventory.clone();
+    let mut slots = Vec::new();
+    let mut sort = false;
+    egui::Window::new("inventory")
+        .resizable(false)
+        .constrain(true)
+        .show(&ctx, |ui| {
+            set_text_styles(ui.ctx(), TextSizes::GAME, options.ui_text_scale);
+            // Sorting would move the held stack out from under it
+            sort = ui
+                .add_enabled(held.is_none(), egui::Button::new("Sort"))
+                .clicked();
+            let mut draw = |ui: &mut egui::Ui, slot: SlotRef| {
+                let item = shown.slot(slot).and_then(Option::as_ref);
+                let response = inventory_slot(ui, &contexts, icons, item, lifted_from(&held, slot));
+                slots.push((slot, response));
+            };
+            for (bar, row) in shown.slots.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    for slot in 0..row.len() {
+                        draw(
+                            ui,
+                            SlotRef {
+                                section: InventorySection::Slots,
+                                bar,
+                                slot,
+                            },
+                        );
+                    }
                 });
+            }
+            ui.separator();
+            // Every hotbar in one row, the same as the status bar
+            ui.horizontal(|ui| {
+                for (bar, row) in shown.hotbar.iter().enumerate() {
+                    for slot in 0..row.len() {
+                        draw(
+                            ui,
+                            SlotRef {
+                                section: InventorySection::Hotbar,
+                                bar,
+                                slot,
+                            },
+                        );
+                    }
+                }
+            });
+        });
+    if sort && inventory.apply(&InventoryOp::Sort, &item_table) {
+        arrange.send(ArrangeEvent(InventoryOp::Sort));
+    }
+
+    if let Some(current) = **held {
+        let item = inventory.slot(current.from).and_then(Option::as_ref);
+        if let (Some(pointer), Some(item)) = (ctx.pointer_hover_pos(), item) {
+            let painter = ctx.layer_painter(LayerId::new(Order::Tooltip, egui::Id::new("held")));
+            let rect = egui::Rect::from_center_size(pointer, egui::vec2(40.0, 40.0));
+            painter.image(
+                slot_texture(&contexts, icons, Some(item)),
+                rect,
+                egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                Color32::WHITE,
+            );
+            painter.text(
+                rect.right_bottom(),
+                Align2::RIGHT_BOTTOM,
+                current.count.to_string(),
+                FontId::proportional(14.0),
+                Color32::WHITE,
+            );
         }
     }
+
+    // Looking around again, nothing in the window should react
+    if grab.is_grabbed() {
+        return;
+    }
+    let mut ops = Vec::new();
+    for (slot, response) in &slots {
+        if response.hovered() {
+            **hovered = Some(*slot);
+        }
+        if response.drag_started() && response.dragged_by(PointerButton::Primary) {
+            if held.is_none() {
+                **held = pick_up(&inventory, *slot, false, true);
+            }
+        } else if response.clicked() || response.secondary_clicked() {
+            let half = response.secondary_clicked();
+            ops.extend(click_slot(
+                &mut held,
+                &mut inventory,
+                *slot,
+                half,
+                &item_table,
+            ));
+        }
+    }
+    // Letting go of a drag puts it down on the slot under the pointer. Back on its own slot it
+    // stays held like it had been clicked, anywhere else nothing moves
+    if let Some(dragged) = held.filter(|held| held.dragging) {
+        if ctx.input(|input| input.pointer.any_released()) {
+            let pointer = ctx.input(|input| input.pointer.interact_pos());
+            let over = slots
+                .iter()
+                .find(|(_, response)| {
+                    pointer.is_some_and(|pointer| response.rect.contains(pointer))
+                })
+                .map(|(slot, _)| *slot);
+            match over {
+                Some(slot) if slot != dragged.from => ops.extend(click_slot(
+                    &mut held,
+                    &mut inventory,
+                    slot,
+                    false,
+                    &item_table,
+                )),
+                Some(_) => {}
+                None => **held = None,
+            }
+            if let Some(current) = &mut **held {
+                current.dragging = false;
+            }
+        }
+    }
+    for op in ops {
+        arrange.send(ArrangeEvent(op));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(section: InventorySection, bar: usize, slot: usize) -> SlotRef {
+        SlotRef { section, bar, slot }
+    }
+
+    fn stack(name: &str, stack_size: u32) -> Option<ItemData> {
+        Some(ItemData {
+            namespace: "vinox".to_string(),
+            name: name.to_string(),
+            stack_size,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn right_click_splits_and_the_rest_stays_put() {
+        let item_table = ItemTable::default();
+        let hand = slot(InventorySection::Hotbar, 0, 0);
+        let empty = slot(InventorySection::Slots, 2, 4);
+        let mut inventory = Inventory::default();
+        *inventory.slot_mut(hand).unwrap() = stack("stone", 7);
+        let mut held = HeldStack::default();
+
+        assert_eq!(
+            click_slot(&mut held, &mut inventory, empty, true, &item_table),
+            None
+        );
+        assert_eq!(held.0, None);
+        assert_eq!(
+            click_slot(&mut held, &mut inventory, hand, true, &item_table),
+            None
+        );
+        // Half rounds up and nothing has left the slot yet
+        assert_eq!(held.map(|held| held.count), Some(4));
+        assert_eq!(*inventory.slot(hand).unwrap(), stack("stone", 7));
+
+        let op = click_slot(&mut held, &mut inventory, empty, false, &item_table).unwrap();
+        assert_eq!(
+            op,
+            InventoryOp::Move {
+                from: hand,
+                to: empty,
+                count: 4
+            }
+        );
+        assert_eq!(held.0, None);
+        assert_eq!(*inventory.slot(empty).unwrap(), stack("stone", 4));
+        assert_eq!(*inventory.slot(hand).unwrap(), stack("stone", 3));
+
+        // Clicking where it came from puts it back untouched
+        click_slot(&mut held, &mut inventory, hand, false, &item_table);
+        assert_eq!(
+            click_slot(&mut held, &mut inventory, hand, false, &item_table),
+            None
+        );
+        assert_eq!(held.0, None);
+        assert_eq!(*inventory.slot(hand).unwrap(), stack("stone", 3));
+
+        // Something else taking from the stack shrinks what's held
+        let picked = pick_up(&inventory, hand, false, true).unwrap();
+        *inventory.slot_mut(hand).unwrap() = stack("stone", 1);
+        assert_eq!(
+            still_held(picked, &inventory).map(|held| held.count),
+            Some(1)
+        );
+        *inventory.slot_mut(hand).unwrap() = None;
+        assert_eq!(still_held(picked, &inventory), None);
+    }
 }
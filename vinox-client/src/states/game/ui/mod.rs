@@ -1,5 +1,15 @@
+pub mod actionbar;
 pub mod crafting;
+pub mod crosshair;
 pub mod dropdown;
+pub mod encyclopedia;
+pub mod hints;
+pub mod hud;
 pub mod inventory;
+pub mod notifications;
+pub mod palette;
 pub mod pause;
 pub mod plugin;
+#[cfg(feature = "profiler")]
+pub mod profiler;
+pub mod radial;
@@ -0,0 +1,422 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Align2, Color32, RichText},
+    EguiContexts,
+};
+use serde::{Deserialize, Serialize};
+use vinox_common::networking::protocol::{ChatCategory, NetworkIP};
+
+use crate::states::{
+    audio::{PlaySound, SoundCategory},
+    components::GameOptions,
+    fonts::{set_text_styles, TextSizes},
+    game::networking::components::{ChatLine, ChatMessages},
+};
+
+pub const TOAST_SECONDS: f32 = 5.0;
+pub const MAX_TOASTS: usize = 4;
+pub const NOTIFICATION_SOUND: &str = "sounds/notification.ogg";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Route {
+    pub chat: bool,
+    pub toast: bool,
+    pub sound: bool,
+}
+
+impl Route {
+    const fn new(chat: bool, toast: bool, sound: bool) -> Self {
+        Self { chat, toast, sound }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationRoutes {
+    pub player_chat: Route,
+    pub system: Route,
+    pub death: Route,
+    pub command_output: Route,
+    pub toast: Route,
+}
+
+impl Default for NotificationRoutes {
+    fn default() -> Self {
+        Self {
+            player_chat: Route::new(true, true, false),
+            system: Route::new(true, true, false),
+            death: Route::new(true, true, true),
+            command_output: Route::new(true, false, false),
+            // Turning chat on for these keeps a history of every popup
+            toast: Route::new(false, true, false),
+        }
+    }
+}
+
+impl NotificationRoutes {
+    pub fn get(&self, category: ChatCategory) -> Route {
+        match category {
+            ChatCategory::PlayerChat => self.player_chat,
+            ChatCategory::System => self.system,
+            ChatCategory::Death => self.death,
+            ChatCategory::CommandOutput => self.command_output,
+            ChatCategory::Toast => self.toast,
+        }
+    }
+
+    pub fn get_mut(&mut self, category: ChatCategory) -> &mut Route {
+        match category {
+            ChatCategory::PlayerChat => &mut self.player_chat,
+            ChatCategory::System => &mut self.system,
+            ChatCategory::Death => &mut self.death,
+            ChatCategory::CommandOutput => &mut self.command_output,
+            ChatCategory::Toast => &mut self.toast,
+        }
+    }
+}
+
+pub fn category_label(category: ChatCategory) -> &'static str {
+    match category {
+        ChatCategory::PlayerChat => "Player chat",
+        ChatCategory::System => "System",
+        ChatCategory::Death => "Deaths",
+        ChatCategory::CommandOutput => "Command output",
+        ChatCategory::Toast => "Popups",
+    }
+}
+
+// Nobody can tell Steve from steve in chat so mutes don't either
+fn same_name(name: &str, other: &str) -> bool {
+    name.to_lowercase() == other.to_lowercase()
+}
+
+pub fn is_muted(muted: &[String], user_name: &str) -> bool {
+    muted.iter().any(|name| same_name(name, user_name))
+}
+
+// False when they were already muted
+pub fn mute(muted: &mut Vec<String>, user_name: &str) -> bool {
+    if is_muted(muted, user_name) {
+        return false;
+    }
+    muted.push(user_name.to_string());
+    true
+}
+
+// False when they weren't muted to begin with
+pub fn unmute(muted: &mut Vec<String>, user_name: &str) -> bool {
+    let before = muted.len();
+    muted.retain(|name| !same_name(name, user_name));
+    muted.len() != before
+}
+
+// Mutes are kept per server address, the same name elsewhere can be someone else
+pub fn muted_on<'a>(muted: &'a HashMap<String, Vec<String>>, server: &str) -> &'a [String] {
+    muted.get(server).map_or(&[], Vec::as_slice)
+}
+
+// Where a line ends up. Nothing is ever deleted for a mute, so unmuting brings the history back
+pub fn route(line: &ChatLine, routes: &NotificationRoutes, muted: &[String]) -> Route {
+    if line.category == ChatCategory::PlayerChat && is_muted(muted, &line.user_name) {
+        return Route::default();
+    }
+    let route = routes.get(line.category);
+    if line.own {
+        return Route {
+            toast: false,
+            sound: false,
+            ..route
+        };
+    }
+    route
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MuteCommand {
+    Mute(String),
+    Unmute(String),
+}
+
+// None when the line isn't a /mute or /unmute, otherwise the command or its usage
+pub fn parse_mute(line: &str) -> Option<Result<MuteCommand, String>> {
+    let line = line.trim();
+    let (command, name) = line.split_once(' ').unwrap_or((line, ""));
+    let name = name.trim();
+    let (command, usage): (fn(String) -> MuteCommand, _) = match command {
+        "/mute" => (MuteCommand::Mute, "Usage: /mute <name>"),
+        "/unmute" => (MuteCommand::Unmute, "Usage: /unmute <name>"),
+        _ => return None,
+    };
+    if name.is_empty() {
+        return Some(Err(usage.to_string()));
+    }
+    Some(Ok(command(name.to_string())))
+}
+
+// Changes the mute list for the server and says what happened
+pub fn apply_mute(command: &MuteCommand, muted: &mut Vec<String>) -> String {
+    match command {
+        MuteCommand::Mute(name) if mute(muted, name) => format!("Muted {name}"),
+        MuteCommand::Mute(name) => format!("{name} is already muted"),
+        MuteCommand::Unmute(name) if unmute(muted, name) => format!("Unmuted {name}"),
+        MuteCommand::Unmute(name) => format!("{name} isn't muted"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toast {
+    pub text: String,
+    // Starts once it's actually on screen, the ones waiting behind the stack don't time out
+    pub shown_at: Option<f32>,
+}
+
+#[derive(Resource, Default)]
+pub struct Notifications {
+    pub toasts: VecDeque<Toast>,
+    // How many chat lines have been routed so far
+    routed: usize,
+}
+
+impl Notifications {
+    pub fn push(&mut self, text: String) {
+        self.toasts.push_back(Toast {
+            text,
+            shown_at: None,
+        });
+    }
+
+    // Drops toasts that have been up long enough and starts the clock on the ones moving up
+    pub fn update(&mut self, now: f32) {
+        self.toasts.retain(|toast| {
+            toast
+                .shown_at
+                .is_none_or(|shown| now - shown < TOAST_SECONDS)
+        });
+        for toast in self.toasts.iter_mut().take(MAX_TOASTS) {
+            toast.shown_at.get_or_insert(now);
+        }
+    }
+
+    pub fn visible(&self) -> impl Iterator<Item = &Toast> {
+        self.toasts.iter().take(MAX_TOASTS)
+    }
+
+    pub fn overflow(&self) -> usize {
+        self.toasts.len().saturating_sub(MAX_TOASTS)
+    }
+}
+
+fn toast_text(line: &ChatLine) -> String {
+    if line.user_name.is_empty() {
+        line.message.clone()
+    } else {
+        format!("{}: {}", line.user_name, line.message)
+    }
+}
+
+// Lines only ever get added to the end, so everything numbered past the last routed one is new
+pub fn route_notifications(
+    messages: Res<ChatMessages>,
+    mut notifications: ResMut<Notifications>,
+    options: Res<GameOptions>,
+    ip: Res<NetworkIP>,
+    mut sounds: EventWriter<PlaySound>,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+) {
+    let muted = muted_on(&options.muted, &ip);
+    let mut sound = false;
+    for line in messages.since(notifications.routed) {
+        let route = route(line, &options.notifications, muted);
+        if route.toast {
+            notifications.push(toast_text(line));
+        }
+        sound |= route.sound;
+    }
+    notifications.routed = messages.pushed();
+    // One sound for a burst of lines
+    if sound {
+        sounds.send(PlaySound::new(
+            asset_server.load(NOTIFICATION_SOUND),
+            SoundCategory::Ui,
+        ));
+    }
+    notifications.update(time.elapsed_seconds());
+}
+
+pub fn toasts_ui(
+    mut contexts: EguiContexts,
+    notifications: Res<Notifications>,
+    options: Res<GameOptions>,
+) {
+    if notifications.toasts.is_empty() {
+        return;
+    }
+    egui::Area::new("toasts")
+        .anchor(Align2::RIGHT_TOP, [-8.0, 8.0])
+        .interactable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            set_text_styles(ui.ctx(), TextSizes::GAME, options.ui_text_scale);
+            ui.with_layout(egui::Layout::top_down(egui::Align::RIGHT), |ui| {
+                for toast in notifications.visible() {
+                    egui::Frame::popup(ui.style())
+                        .fill(Color32::from_black_alpha(200))
+                        .show(ui, |ui| {
+                            ui.set_max_width(320.0);
+                            ui.label(RichText::new(&toast.text).color(Color32::WHITE));
+                        });
+                }
+                let overflow = notifications.overflow();
+                if overflow > 0 {
+                    ui.label(RichText::new(format!("+{overflow} more")).color(Color32::LIGHT_GRAY));
+                }
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(user_name: &str, category: ChatCategory) -> ChatLine {
+        ChatLine::new(user_name, "hello", category)
+    }
+
+    #[test]
+    fn routes_follow_the_settings() {
+        let mut routes = NotificationRoutes::default();
+        for category in ChatCategory::ALL {
+            *routes.get_mut(category) = Route::default();
+        }
+        for category in ChatCategory::ALL {
+            for (chat, toast, sound) in [
+                (true, false, false),
+                (false, true, false),
+                (false, false, true),
+                (true, true, true),
+            ] {
+                *routes.get_mut(category) = Route::new(chat, toast, sound);
+                assert_eq!(
+                    route(&line("Alex", category), &routes, &[]),
+                    Route::new(chat, toast, sound)
+                );
+                // Every other category is left alone
+                for other in ChatCategory::ALL
+                    .into_iter()
+                    .filter(|other| *other != category)
+                {
+                    assert_eq!(routes.get(other), Route::default());
+                }
+            }
+            *routes.get_mut(category) = Route::default();
+        }
+        // Our own messages still show up in chat but never pop up
+        let routes = NotificationRoutes::default();
+        let own = ChatLine {
+            own: true,
+            ..line("Alex", ChatCategory::PlayerChat)
+        };
+        assert_eq!(route(&own, &routes, &[]), Route::new(true, false, false));
+        assert_eq!(
+            route(&ChatLine::toast("New recipes"), &routes, &[]),
+            Route::new(false, true, false)
+        );
+        assert_eq!(
+            route(&ChatLine::console("Cleared"), &routes, &[]),
+            Route::new(true, false, false)
+        );
+    }
+
+    #[test]
+    fn mutes_only_hide_player_chat() {
+        let mut muted = Vec::new();
+        assert!(mute(&mut muted, "Steve"));
+        assert!(!mute(&mut muted, "STEVE"));
+        assert_eq!(muted, vec!["Steve".to_string()]);
+        let routes = NotificationRoutes::default();
+        for name in ["Steve", "steve", "sTeVe"] {
+            assert_eq!(
+                route(&line(name, ChatCategory::PlayerChat), &routes, &muted),
+                Route::default()
+            );
+        }
+        assert_eq!(
+            route(&line("Steven", ChatCategory::PlayerChat), &routes, &muted),
+            routes.player_chat
+        );
+        // A /say from them is still the server talking
+        assert_eq!(
+            route(&line("steve", ChatCategory::System), &routes, &muted),
+            routes.system
+        );
+        // Case folding isn't only ASCII
+        assert!(mute(&mut muted, "Дмитрий"));
+        assert!(is_muted(&muted, "ДМИТРИЙ"));
+        assert!(unmute(&mut muted, "steve"));
+        assert!(!unmute(&mut muted, "steve"));
+        assert_eq!(
+            route(&line("Steve", ChatCategory::PlayerChat), &routes, &muted),
+            routes.player_chat
+        );
+    }
+
+    #[test]
+    fn mutes_are_per_server() {
+        let mut servers = HashMap::new();
+        mute(
+            servers.entry("127.0.0.1:25565".to_string()).or_default(),
+            "Steve",
+        );
+        assert!(is_muted(muted_on(&servers, "127.0.0.1:25565"), "steve"));
+        assert!(!is_muted(muted_on(&servers, "example.org:25565"), "steve"));
+    }
+
+    #[test]
+    fn parses_mute_commands() {
+        assert_eq!(
+            parse_mute("/mute Steve"),
+            Some(Ok(MuteCommand::Mute("Steve".to_string())))
+        );
+        assert_eq!(
+            parse_mute("  /unmute  名前 "),
+            Some(Ok(MuteCommand::Unmute("名前".to_string())))
+        );
+        assert!(matches!(parse_mute("/mute"), Some(Err(_))));
+        assert_eq!(parse_mute("/muted Steve"), None);
+        assert_eq!(parse_mute("mute Steve"), None);
+        let mut muted = Vec::new();
+        let command = MuteCommand::Mute("Steve".to_string());
+        assert_eq!(apply_mute(&command, &mut muted), "Muted Steve");
+        assert_eq!(apply_mute(&command, &mut muted), "Steve is already muted");
+        let command = MuteCommand::Unmute("steve".to_string());
+        assert_eq!(apply_mute(&command, &mut muted), "Unmuted steve");
+        assert_eq!(apply_mute(&command, &mut muted), "steve isn't muted");
+    }
+
+    #[test]
+    fn toasts_stack_and_time_out() {
+        let mut notifications = Notifications::default();
+        for index in 0..6 {
+            notifications.push(format!("toast {index}"));
+        }
+        notifications.update(0.0);
+        assert_eq!(notifications.visible().count(), MAX_TOASTS);
+        assert_eq!(notifications.overflow(), 2);
+        // The waiting ones only start counting once they move up
+        notifications.update(TOAST_SECONDS - 0.1);
+        assert_eq!(notifications.toasts.len(), 6);
+        notifications.update(TOAST_SECONDS);
+        assert_eq!(notifications.overflow(), 0);
+        let texts: Vec<&str> = notifications
+            .visible()
+            .map(|toast| toast.text.as_str())
+            .collect();
+        assert_eq!(texts, ["toast 4", "toast 5"]);
+        notifications.update(TOAST_SECONDS * 2.0 - 0.1);
+        assert_eq!(notifications.toasts.len(), 2);
+        notifications.update(TOAST_SECONDS * 2.0);
+        assert!(notifications.toasts.is_empty());
+    }
+}
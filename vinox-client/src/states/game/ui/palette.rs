@@ -0,0 +1,306 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use vinox_common::{
+    ecs::bundles::Inventory,
+    networking::protocol::ClientMessage,
+    storage::items::descriptor::{ItemCategory, ItemData},
+    world::chunks::storage::ItemTable,
+};
+
+use crate::states::{
+    assets::load::LoadableAssets,
+    components::GameOptions,
+    game::{
+        networking::{components::Capabilities, connection::NetClient},
+        rendering::icons::ItemIconCache,
+        world::chunks::ControlledPlayer,
+    },
+};
+
+// Only one page worth of widgets exists per frame no matter how many items there are
+pub const PAGE_SIZE: usize = 48;
+pub const PALETTE_COLUMNS: usize = 8;
+pub const PALETTE_ICON_SIZE: f32 = 40.0;
+
+pub struct GiveStackEvent {
+    pub item: ItemData,
+}
+
+pub struct PaletteEntry {
+    pub identifier: String,
+    pub display_name: String,
+    pub category: ItemCategory,
+    // Lowercased display name, identifier and namespace so searching doesn't allocate
+    keys: [String; 3],
+}
+
+impl PaletteEntry {
+    pub fn new(identifier: &str, namespace: &str, name: &str, category: ItemCategory) -> Self {
+        let display_name = display_name(name);
+        Self {
+            keys: [
+                display_name.to_lowercase(),
+                identifier.to_lowercase(),
+                namespace.to_lowercase(),
+            ],
+            identifier: identifier.to_string(),
+            display_name,
+            category,
+        }
+    }
+
+    pub fn rank(&self, query: &str) -> Option<u8> {
+        self.keys
+            .iter()
+            .filter_map(|key| match_rank(query, key))
+            .min()
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PaletteState {
+    pub open: bool,
+    pub search: String,
+    // None shows every tab at once
+    pub category: Option<ItemCategory>,
+    pub page: usize,
+    pub entries: Vec<PaletteEntry>,
+    pub results: Vec<usize>,
+    searched: Option<(String, Option<ItemCategory>)>,
+}
+
+// oak_log -> Oak Log
+pub fn display_name(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Lower is better: prefix, start of a later word, anywhere, then the letters in order with gaps.
+// Both sides are expected to be lowercase already
+pub fn match_rank(query: &str, key: &str) -> Option<u8> {
+    if query.is_empty() {
+        return Some(0);
+    }
+    if key.starts_with(query) {
+        return Some(0);
+    }
+    let word_start = key
+        .match_indices(query)
+        .any(|(index, _)| key[..index].ends_with([' ', '_', ':']));
+    if word_start {
+        return Some(1);
+    }
+    if key.contains(query) {
+        return Some(2);
+    }
+    let mut key_chars = key.chars();
+    query
+        .chars()
+        .all(|query_char| key_chars.any(|key_char| key_char == query_char))
+        .then_some(3)
+}
+
+pub fn search(entries: &[PaletteEntry], query: &str, category: Option<ItemCategory>) -> Vec<usize> {
+    let query = query.trim().to_lowercase();
+    let mut results: Vec<(u8, usize)> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| category.is_none_or(|category| entry.category == category))
+        .filter_map(|(index, entry)| entry.rank(&query).map(|rank| (rank, index)))
+        .collect();
+    results.sort_by(|(rank, index), (other_rank, other_index)| {
+        rank.cmp(other_rank).then_with(|| {
+            entries[*index]
+                .display_name
+                .cmp(&entries[*other_index].display_name)
+        })
+    });
+    results.into_iter().map(|(_, index)| index).collect()
+}
+
+pub fn build_palette(item_table: Res<ItemTable>, mut palette: ResMut<PaletteState>) {
+    if !item_table.is_changed() {
+        return;
+    }
+    palette.entries = item_table
+        .iter()
+        .map(|(identifier, item)| {
+            PaletteEntry::new(identifier, &item.namespace, &item.name, item.category())
+        })
+        .collect();
+    palette.searched = None;
+}
+
+// Same placement as the server's copy, the held item moves aside rather than being replaced
+pub fn receive_stacks(
+    mut events: EventReader<GiveStackEvent>,
+    mut player_query: Query<&mut Inventory, With<ControlledPlayer>>,
+    item_table: Res<ItemTable>,
+) {
+    for evt in events.iter() {
+        if let Ok(mut inventory) = player_query.get_single_mut() {
+            inventory.hold_stack(evt.item.clone(), &item_table);
+        }
+    }
+}
+
+pub fn palette_ui(
+    mut contexts: EguiContexts,
+    mut palette: ResMut<PaletteState>,
+    capabilities: Res<Capabilities>,
+    loadable_assets: Res<LoadableAssets>,
+    icon_cache: Res<ItemIconCache>,
+    mut client: NetClient,
+    options: Res<GameOptions>,
+) {
+    if !palette.open || !capabilities.creative {
+        return;
+    }
+    if !options.dark_theme {
+        catppuccin_egui::set_theme(contexts.ctx_mut(), catppuccin_egui::MOCHA);
+    }
+    let query = (palette.search.clone(), palette.category);
+    if palette.searched.as_ref() != Some(&query) {
+        palette.results = search(&palette.entries, &query.0, query.1);
+        palette.page = 0;
+        palette.searched = Some(query);
+    }
+    let pages = palette.results.len().div_ceil(PAGE_SIZE).max(1);
+    palette.page = palette.page.min(pages - 1);
+
+    let page_items: Vec<(String, String)> = palette
+        .results
+        .iter()
+        .skip(palette.page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .map(|index| {
+            let entry = &palette.entries[*index];
+            (entry.identifier.clone(), entry.display_name.clone())
+        })
+        .collect();
+    let icons: Vec<Option<egui::TextureId>> = page_items
+        .iter()
+        .map(|(identifier, _)| {
+            icon_cache
+                .get(identifier, &loadable_assets)
+                .or_else(|| loadable_assets.item_textures.get("empty"))
+                .and_then(|handle| contexts.image_id(handle))
+        })
+        .collect();
+
+    let mut picked = None;
+    egui::Window::new("Creative")
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Search: ");
+                ui.text_edit_singleline(&mut palette.search).request_focus();
+            });
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut palette.category, None, "All");
+                for category in ItemCategory::ALL {
+                    ui.selectable_value(&mut palette.category, Some(category), category.name());
+                }
+            });
+            ui.separator();
+            egui::Grid::new("palette_grid").show(ui, |ui| {
+                for (index, ((identifier, name), icon)) in page_items.iter().zip(&icons).enumerate()
+                {
+                    let response = match icon {
+                        Some(icon) => ui.add(egui::ImageButton::new(
+                            *icon,
+                            [PALETTE_ICON_SIZE, PALETTE_ICON_SIZE],
+                        )),
+                        None => ui.button(name),
+                    };
+                    if response
+                        .on_hover_text(format!("{name}\n{identifier}"))
+                        .clicked()
+                    {
+                        picked = Some(identifier.clone());
+                    }
+                    if (index + 1) % PALETTE_COLUMNS == 0 {
+                        ui.end_row();
+                    }
+                }
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(palette.page > 0, egui::Button::new("<"))
+                    .clicked()
+                {
+                    palette.page -= 1;
+                }
+                ui.label(format!("{} / {pages}", palette.page + 1));
+                if ui
+                    .add_enabled(palette.page + 1 < pages, egui::Button::new(">"))
+                    .clicked()
+                {
+                    palette.page += 1;
+                }
+                ui.label(format!("{} items", palette.results.len()));
+            });
+        });
+
+    if let Some(identifier) = picked {
+        client.send(ClientMessage::PickItem { identifier });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(namespace: &str, name: &str, category: ItemCategory) -> PaletteEntry {
+        PaletteEntry::new(&format!("{namespace}:{name}"), namespace, name, category)
+    }
+
+    #[test]
+    fn prefix_ranks_above_substring() {
+        assert_eq!(match_rank("sto", "stone"), Some(0));
+        assert_eq!(match_rank("sto", "cobble stone"), Some(1));
+        assert_eq!(match_rank("one", "stone"), Some(2));
+        assert_eq!(match_rank("stn", "stone"), Some(3));
+        assert_eq!(match_rank("xyz", "stone"), None);
+
+        let entries = vec![
+            entry("vinox", "cobblestone", ItemCategory::Building),
+            entry("vinox", "mossy_stone", ItemCategory::Building),
+            entry("vinox", "stone", ItemCategory::Building),
+            entry("vinox", "stone_bricks", ItemCategory::Building),
+            entry("vinox", "shovel", ItemCategory::Tools),
+        ];
+        let names: Vec<&str> = search(&entries, "Stone", None)
+            .into_iter()
+            .map(|index| entries[index].display_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["Stone", "Stone Bricks", "Mossy Stone", "Cobblestone"]
+        );
+    }
+
+    #[test]
+    fn search_filters_by_category_and_namespace() {
+        let entries = vec![
+            entry("vinox", "stone", ItemCategory::Building),
+            entry("vinox", "shovel", ItemCategory::Tools),
+            entry("modded", "spade", ItemCategory::Tools),
+        ];
+        assert_eq!(search(&entries, "", Some(ItemCategory::Tools)), vec![1, 2]);
+        assert_eq!(search(&entries, "modded", None), vec![2]);
+        assert_eq!(search(&entries, "s", Some(ItemCategory::Building)), vec![0]);
+        assert!(search(&entries, "", Some(ItemCategory::Misc)).is_empty());
+    }
+}
@@ -1,9 +1,26 @@
-use crate::states::components::GameState;
+use crate::states::{
+    components::{GameSet, GameState},
+    crash::{RecoverableApp, RecoverableSystem},
+    game::session::SessionApp,
+};
 
 use super::{
-    crafting::crafting_ui,
-    dropdown::{create_ui, ConsoleOpen, Toast},
-    inventory::{inventory, status_bar, CurrentItemsHeld, Holding},
+    actionbar::{actionbar_ui, ActionBar},
+    crafting::{
+        crafting_ui, receive_recipes, report_new_items, CraftResultEvent, RecipeBook,
+        RecipesUnlockedEvent,
+    },
+    crosshair::{bake_crosshair, spawn_crosshair, update_crosshair},
+    dropdown::{create_ui, ConsoleOpen},
+    encyclopedia::{build_encyclopedia, encyclopedia_ui, EncyclopediaIndex, EncyclopediaState},
+    hints::{
+        detect_hints, entered_game_hint, hints_ui, load_hints, queue_hints, HintBook,
+        HintTriggered, Hints,
+    },
+    hud::{stats_hud, underwater_overlay, update_stats, HealthShake, StatsUpdateEvent},
+    inventory::{inventory, status_bar, variant_menu_ui, HeldStack},
+    notifications::{route_notifications, toasts_ui, Notifications},
+    palette::{build_palette, palette_ui, receive_stacks, GiveStackEvent, PaletteState},
 };
 use bevy::prelude::*;
 
@@ -15,14 +32,131 @@ pub struct InUi(pub bool);
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ConsoleOpen(false))
-            .insert_resource(CurrentItemsHeld::default())
-            .insert_resource(Holding(false))
+            .insert_resource(HeldStack::default())
             .insert_resource(InUi(false))
-            .insert_resource(Toast::default())
+            .insert_resource(ActionBar::default())
+            .insert_resource(Notifications::default())
+            .insert_resource(HealthShake::default())
+            .insert_resource(PaletteState::default())
+            .insert_resource(RecipeBook::default())
+            .insert_resource(EncyclopediaIndex::default())
+            .insert_resource(EncyclopediaState::default())
+            .insert_resource(HintBook::default())
+            .insert_resource(Hints::default())
+            .reset_on_exit::<ConsoleOpen>()
+            .reset_on_exit::<HeldStack>()
+            .reset_on_exit::<InUi>()
+            .reset_on_exit::<ActionBar>()
+            .reset_on_exit::<Notifications>()
+            .reset_on_exit::<HealthShake>()
+            .reset_on_exit::<RecipeBook>()
+            .reset_on_exit::<Hints>()
+            // The entries and the encyclopedia index come from the tables and are only rebuilt
+            // when those change
+            .on_session_end(|world| {
+                let mut palette = world.resource_mut::<PaletteState>();
+                palette.open = false;
+                palette.search.clear();
+                palette.category = None;
+                palette.page = 0;
+                let mut encyclopedia = world.resource_mut::<EncyclopediaState>();
+                encyclopedia.open = false;
+                encyclopedia.search.clear();
+                encyclopedia.shelf = None;
+                encyclopedia.current = None;
+                encyclopedia.back.clear();
+            })
+            .add_event::<StatsUpdateEvent>()
+            .add_event::<GiveStackEvent>()
+            .add_event::<RecipesUnlockedEvent>()
+            .add_event::<CraftResultEvent>()
+            .add_event::<HintTriggered>()
+            // A broken window shouldn't cost anyone their session
+            .recoverable_set(GameSet::Ui)
+            .add_systems(
+                (
+                    underwater_overlay.recoverable(GameSet::Ui),
+                    create_ui.recoverable(GameSet::Ui),
+                    status_bar.recoverable(GameSet::Ui),
+                    actionbar_ui.recoverable(GameSet::Ui),
+                    hints_ui.recoverable(GameSet::Ui),
+                    variant_menu_ui.recoverable(GameSet::Ui),
+                    stats_hud.recoverable(GameSet::Ui),
+                    inventory.recoverable(GameSet::Ui),
+                    crafting_ui.recoverable(GameSet::Ui),
+                    palette_ui.recoverable(GameSet::Ui),
+                    encyclopedia_ui.recoverable(GameSet::Ui),
+                    toasts_ui.recoverable(GameSet::Ui),
+                )
+                    .chain()
+                    .in_set(GameSet::Ui)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
             .add_systems(
-                (create_ui, status_bar, inventory, crafting_ui)
+                (
+                    build_palette.recoverable(GameSet::Ui),
+                    receive_stacks.recoverable(GameSet::Ui),
+                )
+                    .before(palette_ui)
+                    .in_set(GameSet::Ui)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(spawn_crosshair.in_schedule(OnEnter(GameState::Game)))
+            // Read again on every join so edits show up without a restart
+            .add_systems((load_hints, entered_game_hint).in_schedule(OnEnter(GameState::Game)))
+            .add_systems(
+                (
+                    detect_hints.recoverable(GameSet::Ui),
+                    queue_hints.recoverable(GameSet::Ui),
+                )
                     .chain()
+                    .before(update_stats)
+                    .before(hints_ui)
+                    .in_set(GameSet::Ui)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_systems(
+                (
+                    bake_crosshair.recoverable(GameSet::Ui),
+                    update_crosshair.recoverable(GameSet::Ui),
+                )
+                    .chain()
+                    .in_set(GameSet::Ui)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                build_encyclopedia
+                    .recoverable(GameSet::Ui)
+                    .before(encyclopedia_ui)
+                    .in_set(GameSet::Ui)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_systems(
+                (
+                    receive_recipes.recoverable(GameSet::Ui),
+                    report_new_items.recoverable(GameSet::Ui),
+                )
+                    .chain()
+                    .before(crafting_ui)
+                    .in_set(GameSet::Ui)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                route_notifications
+                    .recoverable(GameSet::Ui)
+                    .after(receive_recipes)
+                    .before(toasts_ui)
+                    .in_set(GameSet::Ui)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                update_stats
+                    .recoverable(GameSet::Ui)
+                    .before(stats_hud)
+                    .in_set(GameSet::Ui)
                     .in_set(OnUpdate(GameState::Game)),
             );
+        #[cfg(feature = "profiler")]
+        app.add_plugin(super::profiler::ProfilerPlugin);
     }
 }
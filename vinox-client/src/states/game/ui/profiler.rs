@@ -0,0 +1,474 @@
+use std::{fmt::Write, time::Instant};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use bevy_quinnet::client::Client;
+use leafwing_input_manager::prelude::*;
+use vinox_common::{networking::protocol::ServerHealth, world::chunks::ecs::ViewRadius};
+
+use crate::states::{
+    components::{GameActions, GameSet, GameState},
+    crash::{RecoverableApp, RecoverableSystem},
+    game::{
+        networking::components::ServerStatus,
+        rendering::{
+            autotune::AutoTune,
+            culling::ChunkCulling,
+            memory::{MemoryBudget, MIB},
+            meshing::MeshQueue,
+            remesh::RemeshSweep,
+        },
+        world::chunks::{ChunkQueue, ControlledPlayer},
+    },
+};
+
+pub const HISTORY: usize = 120;
+// Milliseconds, what a frame gets at 60 fps
+pub const FRAME_BUDGET: f32 = 1000.0 / 60.0;
+pub const GRAPH_WIDTH: f32 = 360.0;
+pub const GRAPH_HEIGHT: f32 = 64.0;
+pub const SETS: [(GameSet, &str, egui::Color32); 6] = [
+    (
+        GameSet::Input,
+        "Input",
+        egui::Color32::from_rgb(137, 180, 250),
+    ),
+    (
+        GameSet::Networking,
+        "Networking",
+        egui::Color32::from_rgb(166, 227, 161),
+    ),
+    (
+        GameSet::WorldUpdate,
+        "World update",
+        egui::Color32::from_rgb(249, 226, 175),
+    ),
+    (
+        GameSet::Meshing,
+        "Meshing",
+        egui::Color32::from_rgb(250, 179, 135),
+    ),
+    (
+        GameSet::RenderPrep,
+        "Render prep",
+        egui::Color32::from_rgb(203, 166, 247),
+    ),
+    (GameSet::Ui, "UI", egui::Color32::from_rgb(148, 226, 213)),
+];
+const OTHER_COLOR: egui::Color32 = egui::Color32::from_rgb(108, 112, 134);
+const OVER_BUDGET_COLOR: egui::Color32 = egui::Color32::from_rgb(250, 179, 135);
+// The overlay itself, recording keeps going if it breaks
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProfilerOverlay;
+
+const SPIKE_COLOR: egui::Color32 = egui::Color32::from_rgb(243, 139, 168);
+
+// Everything is in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameSample {
+    pub sets: [f32; SETS.len()],
+    pub frame: f32,
+    pub mesh_queue: usize,
+    pub chunks_pending: usize,
+    pub net_bytes: u64,
+}
+
+impl FrameSample {
+    // Whatever the frame spent outside the timed sets: physics, lighting, rendering and vsync
+    pub fn other(&self) -> f32 {
+        (self.frame - self.sets.iter().sum::<f32>()).max(0.0)
+    }
+}
+
+// Fixed size so recording a frame never allocates
+#[derive(Debug, Clone)]
+pub struct RingBuffer<T, const N: usize> {
+    items: [T; N],
+    next: usize,
+    len: usize,
+}
+
+impl<T: Copy + Default, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self {
+            items: [T::default(); N],
+            next: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<T: Copy + Default, const N: usize> RingBuffer<T, N> {
+    pub fn push(&mut self, item: T) {
+        self.items[self.next] = item;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Oldest first
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).map(move |index| self.items[(self.next + N - self.len + index) % N])
+    }
+
+    pub fn max_by(&self, value: impl Fn(&T) -> f32) -> Option<T> {
+        self.iter().max_by(|a, b| value(a).total_cmp(&value(b)))
+    }
+
+    // Nearest rank, sorts a copy on the stack
+    pub fn percentile(&self, percentile: f32, value: impl Fn(&T) -> f32) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let mut sorted = [0.0; N];
+        for (slot, item) in sorted.iter_mut().zip(self.iter()) {
+            *slot = value(&item);
+        }
+        let sorted = &mut sorted[..self.len];
+        sorted.sort_unstable_by(f32::total_cmp);
+        let rank = ((percentile * self.len as f32).ceil() as usize).clamp(1, self.len);
+        sorted[rank - 1]
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct FrameProfiler {
+    pub open: bool,
+    pub history: RingBuffer<FrameSample, HISTORY>,
+    current: FrameSample,
+    started: [Option<Instant>; SETS.len()],
+    last_net_bytes: Option<u64>,
+}
+
+impl FrameProfiler {
+    pub fn begin(&mut self, set: usize) {
+        self.started[set] = Some(Instant::now());
+    }
+
+    pub fn end(&mut self, set: usize) {
+        if let Some(started) = self.started[set].take() {
+            self.current.sets[set] = started.elapsed().as_secs_f32() * 1000.0;
+        }
+    }
+
+    pub fn finish_frame(&mut self, frame: f32) {
+        self.current.frame = frame;
+        self.history.push(self.current);
+        self.current = FrameSample::default();
+    }
+
+    pub fn worst(&self) -> Option<FrameSample> {
+        self.history.max_by(|sample| sample.frame)
+    }
+
+    // Kibibytes per second over the whole window
+    pub fn network_rate(&self) -> f32 {
+        let (bytes, millis) = self
+            .history
+            .iter()
+            .fold((0, 0.0), |(bytes, millis), sample| {
+                (bytes + sample.net_bytes, millis + sample.frame)
+            });
+        if millis <= 0.0 {
+            return 0.0;
+        }
+        bytes as f32 / 1024.0 / (millis / 1000.0)
+    }
+}
+
+// Goes straight into a bug report so it's plain text and lines up in a monospace font
+pub fn format_spike(sample: &FrameSample, history: &RingBuffer<FrameSample, HISTORY>) -> String {
+    let mut report = String::new();
+    writeln!(
+        report,
+        "Frame spike: {:.2} ms (budget {FRAME_BUDGET:.2} ms, p95 {:.2} ms over {} frames)",
+        sample.frame,
+        history.percentile(0.95, |sample| sample.frame),
+        history.len()
+    )
+    .ok();
+    for ((_, name, _), millis) in SETS.iter().zip(sample.sets) {
+        writeln!(report, "  {name:<14}{millis:>8.2} ms").ok();
+    }
+    writeln!(report, "  {:<14}{:>8.2} ms", "Other", sample.other()).ok();
+    write!(
+        report,
+        "Mesh queue: {}, chunks pending: {}, network: {} bytes",
+        sample.mesh_queue, sample.chunks_pending, sample.net_bytes
+    )
+    .ok();
+    report
+}
+
+pub fn begin_set(set: usize) -> impl FnMut(ResMut<FrameProfiler>) {
+    move |mut profiler: ResMut<FrameProfiler>| profiler.begin(set)
+}
+
+pub fn end_set(set: usize) -> impl FnMut(ResMut<FrameProfiler>) {
+    move |mut profiler: ResMut<FrameProfiler>| profiler.end(set)
+}
+
+pub fn finish_frame(
+    mut profiler: ResMut<FrameProfiler>,
+    time: Res<Time>,
+    mesh_queue: Res<MeshQueue>,
+    chunk_queue: Res<ChunkQueue>,
+    client: Res<Client>,
+) {
+    let net_total = client
+        .get_connection()
+        .and_then(|connection| connection.stats())
+        .map(|stats| stats.udp_rx.bytes + stats.udp_tx.bytes);
+    profiler.current.net_bytes = match (net_total, profiler.last_net_bytes) {
+        (Some(total), Some(last)) => total.saturating_sub(last),
+        _ => 0,
+    };
+    profiler.last_net_bytes = net_total;
+    profiler.current.mesh_queue = mesh_queue.mesh.len() + mesh_queue.priority.len();
+    profiler.current.chunks_pending = chunk_queue.mesh.len();
+    profiler.finish_frame(time.raw_delta_seconds() * 1000.0);
+}
+
+pub fn profiler_input(
+    mut profiler: ResMut<FrameProfiler>,
+    player_actions: Query<&ActionState<GameActions>, With<ControlledPlayer>>,
+) {
+    if let Ok(action_state) = player_actions.get_single() {
+        if action_state.just_pressed(GameActions::Profiler) {
+            profiler.open = !profiler.open;
+        }
+    }
+}
+
+fn stacked_bar(ui: &mut egui::Ui, sample: &FrameSample) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(GRAPH_WIDTH, 16.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(120));
+    // Scaled to the budget, or to the frame if it went over so the overrun stays visible
+    let scale = rect.width() / sample.frame.max(FRAME_BUDGET);
+    let mut left = rect.left();
+    let segments = SETS
+        .iter()
+        .map(|(_, _, color)| *color)
+        .zip(sample.sets)
+        .chain([(OTHER_COLOR, sample.other())]);
+    for (color, millis) in segments {
+        let right = (left + millis * scale).min(rect.right());
+        painter.rect_filled(
+            egui::Rect::from_x_y_ranges(left..=right, rect.y_range()),
+            0.0,
+            color,
+        );
+        left = right;
+    }
+    let budget_x = rect.left() + FRAME_BUDGET * scale;
+    painter.vline(
+        budget_x,
+        rect.y_range(),
+        egui::Stroke::new(2.0f32, egui::Color32::WHITE),
+    );
+}
+
+fn history_graph(ui: &mut egui::Ui, profiler: &FrameProfiler) {
+    let (rect, _) =
+        ui.allocate_exact_size(egui::vec2(GRAPH_WIDTH, GRAPH_HEIGHT), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(120));
+    // Two budgets tall, anything past that is clipped at the top
+    let scale = rect.height() / (FRAME_BUDGET * 2.0);
+    let column = rect.width() / HISTORY as f32;
+    let worst = profiler.worst().map(|sample| sample.frame);
+    let offset = HISTORY - profiler.history.len();
+    for (index, sample) in profiler.history.iter().enumerate() {
+        let left = rect.left() + (offset + index) as f32 * column;
+        let top = (rect.bottom() - sample.frame * scale).max(rect.top());
+        let color = if Some(sample.frame) == worst {
+            SPIKE_COLOR
+        } else if sample.frame > FRAME_BUDGET {
+            OVER_BUDGET_COLOR
+        } else {
+            OTHER_COLOR
+        };
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(left, top),
+                egui::pos2(left + column, rect.bottom()),
+            ),
+            0.0,
+            color,
+        );
+    }
+    painter.hline(
+        rect.x_range(),
+        rect.bottom() - FRAME_BUDGET * scale,
+        egui::Stroke::new(1.0f32, egui::Color32::WHITE),
+    );
+}
+
+pub fn profiler_ui(
+    mut contexts: EguiContexts,
+    profiler: Res<FrameProfiler>,
+    server_status: Res<ServerStatus>,
+    mesh_queue: Res<MeshQueue>,
+    (budget, view_radius, sweep, culling, tune): (
+        Res<MemoryBudget>,
+        Res<ViewRadius>,
+        Res<RemeshSweep>,
+        Res<ChunkCulling>,
+        Res<AutoTune>,
+    ),
+) {
+    if !profiler.open {
+        return;
+    }
+    let latest = profiler.history.iter().last().unwrap_or_default();
+    let mut captured = None;
+    egui::Window::new("Profiler")
+        .anchor(egui::Align2::RIGHT_TOP, [-8.0, 8.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.vertical(|ui| {
+                    ui.label(format!("{:.2} ms / {FRAME_BUDGET:.2} ms", latest.frame));
+                    stacked_bar(ui, &latest);
+                    ui.label(format!(
+                        "Last {} frames, p95 {:.2} ms, worst {:.2} ms",
+                        profiler.history.len(),
+                        profiler.history.percentile(0.95, |sample| sample.frame),
+                        profiler
+                            .worst()
+                            .map(|sample| sample.frame)
+                            .unwrap_or_default()
+                    ));
+                    history_graph(ui, &profiler);
+                });
+                ui.separator();
+                ui.vertical(|ui| {
+                    for ((_, name, color), millis) in SETS.iter().zip(latest.sets) {
+                        ui.colored_label(*color, format!("{name}: {millis:.2} ms"));
+                    }
+                    ui.colored_label(OTHER_COLOR, format!("Other: {:.2} ms", latest.other()));
+                    ui.separator();
+                    ui.label(format!("Mesh queue: {}", latest.mesh_queue));
+                    ui.label(format!("Chunks pending: {}", latest.chunks_pending));
+                    ui.label(format!(
+                        "Meshed since joining: {} ({} skipped)",
+                        mesh_queue.queued, mesh_queue.skipped
+                    ));
+                    ui.label(format!(
+                        "Chunks drawn: {} / {}",
+                        culling.rendered, culling.total
+                    ));
+                    if let Some((done, total)) = sweep.progress() {
+                        ui.label(format!("Remeshing: {done}/{total} chunks"));
+                    }
+                    ui.label(format!("Network: {:.1} KiB/s", profiler.network_rate()));
+                    let memory = format!(
+                        "GPU memory: ~{} / {} MiB",
+                        budget.used() / MIB,
+                        budget.soft_cap() / MIB
+                    );
+                    if budget.used() > budget.soft_cap() {
+                        ui.colored_label(OVER_BUDGET_COLOR, memory);
+                    } else {
+                        ui.label(memory);
+                    }
+                    let view = tune.view_radius(view_radius.horizontal);
+                    ui.label(format!("View radius: {}", budget.view_radius(view)));
+                    if tune.level() > 0 {
+                        ui.label(format!("Auto-tune steps: {}", tune.level()));
+                    }
+                    let server = format!("Server: {:?}", **server_status);
+                    match **server_status {
+                        ServerHealth::Healthy => ui.label(server),
+                        ServerHealth::Degraded => ui.colored_label(OVER_BUDGET_COLOR, server),
+                        ServerHealth::Overloaded => ui.colored_label(SPIKE_COLOR, server),
+                    };
+                    if ui.button("Capture last spike").clicked() {
+                        captured = profiler.worst();
+                    }
+                });
+            });
+        });
+
+    if let Some(worst) = captured {
+        let report = format_spike(&worst, &profiler.history);
+        println!("{report}");
+        contexts
+            .ctx_mut()
+            .output_mut(|output| output.copied_text = report);
+    }
+}
+
+pub struct ProfilerPlugin;
+
+impl Plugin for ProfilerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FrameProfiler>()
+            .add_system(finish_frame.in_base_set(CoreSet::Last))
+            .recoverable_set(ProfilerOverlay)
+            .add_systems(
+                (
+                    profiler_input.recoverable(ProfilerOverlay),
+                    profiler_ui.recoverable(ProfilerOverlay),
+                )
+                    .chain()
+                    .in_set(ProfilerOverlay)
+                    .after(GameSet::Ui)
+                    .in_set(OnUpdate(GameState::Game)),
+            );
+        for (index, (set, _, _)) in SETS.iter().enumerate() {
+            app.add_system(
+                begin_set(index)
+                    .before(*set)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(end_set(index).after(*set).in_set(OnUpdate(GameState::Game)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_buffer_statistics() {
+        let mut history: RingBuffer<f32, HISTORY> = RingBuffer::default();
+        assert_eq!(history.percentile(0.95, |value| *value), 0.0);
+        assert_eq!(history.max_by(|value| *value), None);
+
+        // Only the newest 120 of these survive: 81 through 200
+        for value in 1..=200 {
+            history.push(value as f32);
+        }
+        assert_eq!(history.len(), HISTORY);
+        assert_eq!(history.iter().next(), Some(81.0));
+        assert_eq!(history.iter().last(), Some(200.0));
+        assert_eq!(history.max_by(|value| *value), Some(200.0));
+        // Rank 114 of 120
+        assert_eq!(history.percentile(0.95, |value| *value), 194.0);
+        assert_eq!(history.percentile(1.0, |value| *value), 200.0);
+        assert_eq!(history.percentile(0.0, |value| *value), 81.0);
+
+        let mut profiler = FrameProfiler::default();
+        for frame in [12.0, 48.0, 15.0] {
+            profiler.begin(GameSet::Meshing as usize);
+            profiler.end(GameSet::Meshing as usize);
+            profiler.finish_frame(frame);
+        }
+        let worst = profiler.worst().unwrap();
+        assert_eq!(worst.frame, 48.0);
+        let report = format_spike(&worst, &profiler.history);
+        assert!(report.starts_with("Frame spike: 48.00 ms"));
+        assert!(report.contains("Meshing"));
+    }
+}
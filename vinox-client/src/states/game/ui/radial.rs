@@ -0,0 +1,136 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Align2, Color32, FontId, LayerId, Order, Pos2, Rect, TextureId};
+
+pub const RADIAL_RADIUS: f32 = 120.0;
+pub const RADIAL_ICON_SIZE: f32 = 36.0;
+// Fraction of the radius the pointer has to leave before anything is picked
+pub const RADIAL_DEADZONE: f32 = 0.25;
+
+pub struct RadialEntry {
+    pub label: String,
+    pub icon: Option<TextureId>,
+}
+
+// Clockwise from straight up, y pointing up
+fn segment_angle(index: usize, count: usize) -> f32 {
+    index as f32 * TAU / count as f32
+}
+
+// Which of count equal slices direction falls in. The first is centred straight up and the
+// rest go clockwise, y points up. None inside the deadzone
+pub fn segment_at(direction: Vec2, count: usize, deadzone: f32) -> Option<usize> {
+    if count == 0 || direction.is_nan() || direction.length() <= deadzone {
+        return None;
+    }
+    let angle = direction.x.atan2(direction.y).rem_euclid(TAU);
+    let width = TAU / count as f32;
+    // Half a slice of offset so the wrap past straight down lands back on the first one
+    Some(((angle + width / 2.0) / width) as usize % count)
+}
+
+// Only draws, whoever owns the menu works out what's hovered with segment_at
+pub fn radial_menu(ctx: &egui::Context, id: &str, entries: &[RadialEntry], hovered: Option<usize>) {
+    let center = ctx.screen_rect().center();
+    let painter = ctx.layer_painter(LayerId::new(Order::Foreground, egui::Id::new(id)));
+    painter.circle_filled(
+        center,
+        RADIAL_RADIUS,
+        Color32::from_rgba_unmultiplied(0, 0, 0, 164),
+    );
+    let count = entries.len();
+    let on_screen =
+        |angle: f32, distance: f32| center + egui::vec2(angle.sin(), -angle.cos()) * distance;
+    if count > 1 {
+        let half_slice = TAU / count as f32 / 2.0;
+        for index in 0..count {
+            let angle = segment_angle(index, count) + half_slice;
+            painter.line_segment(
+                [
+                    on_screen(angle, RADIAL_RADIUS * RADIAL_DEADZONE),
+                    on_screen(angle, RADIAL_RADIUS),
+                ],
+                (1.0, Color32::from_white_alpha(48)),
+            );
+        }
+    }
+    let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+    for (index, entry) in entries.iter().enumerate() {
+        let position = on_screen(segment_angle(index, count), RADIAL_RADIUS * 0.65);
+        if hovered == Some(index) {
+            painter.circle_filled(
+                position,
+                RADIAL_ICON_SIZE * 0.9,
+                Color32::from_white_alpha(64),
+            );
+        }
+        let label_offset = if let Some(icon) = entry.icon {
+            painter.image(
+                icon,
+                Rect::from_center_size(position, egui::vec2(RADIAL_ICON_SIZE, RADIAL_ICON_SIZE)),
+                uv,
+                Color32::WHITE,
+            );
+            RADIAL_ICON_SIZE * 0.5 + 8.0
+        } else {
+            0.0
+        };
+        painter.text(
+            position + egui::vec2(0.0, label_offset),
+            Align2::CENTER_CENTER,
+            &entry.label,
+            FontId::proportional(16.0),
+            Color32::WHITE,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_slice_the_direction_points_into() {
+        assert_eq!(segment_at(Vec2::Y, 4, 0.1), Some(0));
+        assert_eq!(segment_at(Vec2::X, 4, 0.1), Some(1));
+        assert_eq!(segment_at(Vec2::NEG_Y, 4, 0.1), Some(2));
+        assert_eq!(segment_at(Vec2::NEG_X, 4, 0.1), Some(3));
+        // Either side of a boundary between up and right
+        assert_eq!(segment_at(Vec2::new(0.9, 1.0), 4, 0.1), Some(0));
+        assert_eq!(segment_at(Vec2::new(1.0, 0.9), 4, 0.1), Some(1));
+        assert_eq!(segment_at(Vec2::new(1.0, -0.5), 3, 0.1), Some(1));
+        // A single entry takes the whole circle
+        assert_eq!(segment_at(Vec2::NEG_Y, 1, 0.1), Some(0));
+    }
+
+    #[test]
+    fn deadzone_and_degenerate_input() {
+        assert_eq!(segment_at(Vec2::ZERO, 4, 0.0), None);
+        assert_eq!(segment_at(Vec2::new(0.05, 0.05), 4, 0.1), None);
+        assert_eq!(segment_at(Vec2::new(0.0, 0.11), 4, 0.1), Some(0));
+        assert_eq!(segment_at(Vec2::Y, 0, 0.1), None);
+        assert_eq!(segment_at(Vec2::new(f32::NAN, 1.0), 4, 0.1), None);
+    }
+
+    #[test]
+    fn wraps_around_straight_down() {
+        // Just either side of 180 degrees, which is where atan2 flips sign
+        let left_of_down = Vec2::new(-0.001, -1.0);
+        let right_of_down = Vec2::new(0.001, -1.0);
+        assert_eq!(segment_at(left_of_down, 2, 0.1), Some(1));
+        assert_eq!(segment_at(right_of_down, 2, 0.1), Some(1));
+        // With three slices the first one straddles up, the bottom is split by the other two
+        assert_eq!(segment_at(left_of_down, 3, 0.1), Some(2));
+        assert_eq!(segment_at(right_of_down, 3, 0.1), Some(1));
+        // Just left of straight up comes back around to the first
+        assert_eq!(segment_at(Vec2::new(-0.001, 1.0), 8, 0.1), Some(0));
+        for count in 1..=12 {
+            for step in 0..360 {
+                let angle = (step as f32).to_radians();
+                let index = segment_at(Vec2::new(angle.sin(), angle.cos()), count, 0.1);
+                assert!(index.is_some_and(|index| index < count));
+            }
+        }
+    }
+}
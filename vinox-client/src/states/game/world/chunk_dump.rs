@@ -0,0 +1,569 @@
+use std::{
+    env, fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+use vinox_common::{
+    ecs::bundles::Inventory,
+    networking::protocol::{ServerMessage, PROTOCOL_VERSION},
+    physics::movement::MovementState,
+    storage::content::{canonical_hash, ContentManifest},
+    world::chunks::{
+        biome_map::ChunkBiomes,
+        ecs::CurrentChunks,
+        positions::{ChunkPos, DimensionId},
+        storage::{BlockData, BlockTable, ChunkData, PaletteSummary, RawChunk, CHUNK_SIZE},
+    },
+};
+use zstd::stream::copy_encode;
+
+use crate::states::{
+    components::{GameOptions, GameSet, GameState, ProjectPath},
+    game::{
+        networking::{
+            components::{Capabilities, ChatLine, ChatMessages, WorldSeed},
+            replay::{Replay, ReplayFrame, ReplayHeader, ReplayPlayback},
+        },
+        rendering::{
+            autotune::AutoTune,
+            meshing::{ChunkVersion, MeshStats, MeshedNeighbors},
+            remesh::MeshSettings,
+        },
+        world::chunks::{ControlledPlayer, PlayerChunk},
+    },
+};
+
+pub const DUMP_VERSION: u32 = 1;
+const USAGE: &str = "Usage: /dumpchunk [chunk x y z], the chunk you're in without one";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DumpSettings {
+    pub mesh: MeshSettings,
+    // Texture flips aren't an option, blocks that have them always pick one from their position
+    pub variants: bool,
+    // Where decorations go
+    pub seed: u32,
+}
+
+// The one voxel thick layer of a neighbor that the mesher reads
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BorderLayer {
+    // From the dumped chunk, -1, 0 or 1 on each axis
+    pub offset: IVec3,
+    // Its version isn't the one the current mesh was built against
+    pub changed_since_mesh: bool,
+    pub palette: Vec<BlockData>,
+    // In border_voxels order
+    pub indices: Vec<u16>,
+}
+
+// The voxels of the neighbor at `offset` that touch the chunk, y then z then x
+pub fn border_voxels(offset: IVec3) -> Vec<UVec3> {
+    let edge = CHUNK_SIZE as u32;
+    let range = |axis: i32| match axis {
+        -1 => edge - 1..edge,
+        1 => 0..1,
+        _ => 0..edge,
+    };
+    let mut voxels = Vec::new();
+    for y in range(offset.y) {
+        for z in range(offset.z) {
+            for x in range(offset.x) {
+                voxels.push(UVec3::new(x, y, z));
+            }
+        }
+    }
+    voxels
+}
+
+impl BorderLayer {
+    pub fn capture(offset: IVec3, chunk: &ChunkData, changed_since_mesh: bool) -> Self {
+        let mut palette: Vec<BlockData> = Vec::new();
+        let indices = border_voxels(offset)
+            .into_iter()
+            .map(|voxel| {
+                let block = chunk.get(voxel.x, voxel.y, voxel.z);
+                match palette.iter().position(|known| *known == block) {
+                    Some(idx) => idx as u16,
+                    None => {
+                        palette.push(block);
+                        palette.len() as u16 - 1
+                    }
+                }
+            })
+            .collect();
+        Self {
+            offset,
+            changed_since_mesh,
+            palette,
+            indices,
+        }
+    }
+
+    // Air everywhere but the layer
+    pub fn to_chunk(&self) -> ChunkData {
+        let block_table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        for (voxel, idx) in border_voxels(self.offset).into_iter().zip(&self.indices) {
+            if let Some(block) = self.palette.get(*idx as usize) {
+                chunk.set(voxel.x, voxel.y, voxel.z, block.clone(), &block_table);
+            }
+        }
+        chunk.trim();
+        chunk
+    }
+}
+
+// Everything needed to look at a chunk's mesh away from the world it came from. Nothing about
+// players goes in, these get attached to bug reports
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChunkDump {
+    pub dump_version: u32,
+    pub game_version: String,
+    pub pos: IVec3,
+    // Of the content we had loaded, what the chunk's identifiers mean
+    pub content_hash: u64,
+    pub chunk: RawChunk,
+    pub palette: PaletteSummary,
+    // Only the loaded ones
+    pub neighbors: Vec<BorderLayer>,
+    // None when it hasn't been meshed yet
+    pub mesh: Option<MeshStats>,
+    pub settings: DumpSettings,
+}
+
+impl ChunkDump {
+    pub fn capture(
+        pos: IVec3,
+        chunk: &ChunkData,
+        neighbors: Vec<BorderLayer>,
+        mesh: Option<MeshStats>,
+        settings: DumpSettings,
+        content_hash: u64,
+    ) -> Self {
+        Self {
+            dump_version: DUMP_VERSION,
+            game_version: env!("CARGO_PKG_VERSION").to_string(),
+            pos,
+            content_hash,
+            chunk: chunk.to_raw(),
+            palette: chunk.palette_summary(),
+            neighbors,
+            mesh,
+            settings,
+        }
+    }
+
+    pub fn to_ron(&self) -> Result<String, String> {
+        to_string_pretty(self, PrettyConfig::new().depth_limit(4)).map_err(|e| e.to_string())
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, String> {
+        let dump: ChunkDump = ron::from_str(text).map_err(|e| e.to_string())?;
+        if dump.dump_version != DUMP_VERSION {
+            return Err(format!(
+                "made by a different version of the game (dump format {}, this game reads {DUMP_VERSION})",
+                dump.dump_version
+            ));
+        }
+        Ok(dump)
+    }
+
+    // Plays back like a replay with only the chunk, its neighbors' borders and us in it. We stand
+    // off to the side of it, the neighbors keep everything else from meshing
+    pub fn to_replay(&self, client_id: u64) -> Result<Replay, String> {
+        let edge = CHUNK_SIZE as f64;
+        let corner = self.pos.as_dvec3() * edge;
+        let mut frames = vec![
+            ReplayFrame::Message {
+                at: 0.0,
+                message: ServerMessage::Capabilities { creative: true },
+            },
+            ReplayFrame::Message {
+                at: 0.0,
+                message: ServerMessage::FlightPermission {
+                    fly: true,
+                    noclip: true,
+                },
+            },
+            ReplayFrame::Message {
+                at: 0.0,
+                message: ServerMessage::PlayerCreate {
+                    entity: Entity::from_raw(0),
+                    id: client_id,
+                    translation: corner + DVec3::new(edge / 2.0, edge / 2.0, edge * 1.75),
+                    yaw: 0.0,
+                    head_pitch: 0.0,
+                    user_name: "inspector".to_string(),
+                    init: true,
+                    inventory: Box::<Inventory>::default(),
+                },
+            },
+        ];
+        let chunks = std::iter::once((self.pos, ChunkData::from_raw(self.chunk.clone()))).chain(
+            self.neighbors
+                .iter()
+                .map(|layer| (self.pos + layer.offset, layer.to_chunk())),
+        );
+        for (pos, chunk) in chunks {
+            frames.push(ReplayFrame::Message {
+                at: 0.0,
+                message: ServerMessage::LevelData {
+                    chunk_data: level_payload(&chunk.to_raw())?,
+                    pos,
+                    dimension: DimensionId::default(),
+                    hash: 0,
+                    biomes: ChunkBiomes::default(),
+                },
+            });
+        }
+        Ok(Replay {
+            header: ReplayHeader {
+                protocol: PROTOCOL_VERSION,
+                client_id,
+                content: ContentManifest::default(),
+            },
+            frames,
+            footer: None,
+        })
+    }
+}
+
+// What the server puts in LevelData
+fn level_payload(raw_chunk: &RawChunk) -> Result<Vec<u8>, String> {
+    let bytes = bincode::serialize(raw_chunk).map_err(|e| e.to_string())?;
+    let mut output = Cursor::new(Vec::new());
+    copy_encode(&mut Cursor::new(bytes), &mut output, 0).map_err(|e| e.to_string())?;
+    Ok(output.into_inner())
+}
+
+// None means the chunk we're in
+pub struct DumpChunkEvent(pub Option<IVec3>);
+
+// None when the line isn't a /dumpchunk at all, otherwise where or what was wrong with it
+pub fn parse_dumpchunk(line: &str) -> Option<Result<Option<IVec3>, String>> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("/dumpchunk") {
+        return None;
+    }
+    let args: Vec<&str> = words.collect();
+    Some(match args.as_slice() {
+        [] => Ok(None),
+        [x, y, z] => match (x.parse(), y.parse(), z.parse()) {
+            (Ok(x), Ok(y), Ok(z)) => Ok(Some(IVec3::new(x, y, z))),
+            _ => Err(USAGE.to_string()),
+        },
+        _ => Err(USAGE.to_string()),
+    })
+}
+
+pub fn debug_dir(project_path: &ProjectPath) -> PathBuf {
+    project_path
+        .0
+        .parent()
+        .unwrap_or(&project_path.0)
+        .join("debug")
+}
+
+fn write_dump(dir: &Path, dump: &ChunkDump) -> Result<PathBuf, String> {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    let IVec3 { x, y, z } = dump.pos;
+    let path = dir.join(format!("chunk_{x}_{y}_{z}_{seconds}.ron"));
+    let text = dump.to_ron()?;
+    fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&path, text))
+        .map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn handle_dump_chunk(
+    mut events: EventReader<DumpChunkEvent>,
+    mut messages: ResMut<ChatMessages>,
+    capabilities: Res<Capabilities>,
+    player_chunk: Res<PlayerChunk>,
+    current_chunks: Res<CurrentChunks>,
+    chunks: Query<(&ChunkData, Option<&MeshStats>, Option<&MeshedNeighbors>)>,
+    versions: Query<&ChunkVersion>,
+    (options, tune, seed, content): (
+        Res<GameOptions>,
+        Res<AutoTune>,
+        Res<WorldSeed>,
+        Res<ContentManifest>,
+    ),
+    project_path: Res<ProjectPath>,
+) {
+    for DumpChunkEvent(at) in events.iter() {
+        // Local games make us an operator so this is always there offline
+        if !capabilities.creative {
+            messages.push(ChatLine::console(
+                "Only creative players can use /dumpchunk",
+            ));
+            continue;
+        }
+        let pos = at.unwrap_or(player_chunk.chunk_pos);
+        let Some((chunk, mesh, meshed)) = current_chunks
+            .get_entity(ChunkPos(pos))
+            .and_then(|entity| chunks.get(entity).ok())
+        else {
+            messages.push(ChatLine::console(format!(
+                "Chunk {} {} {} isn't loaded",
+                pos.x, pos.y, pos.z
+            )));
+            continue;
+        };
+        let current = MeshedNeighbors::current(ChunkPos(pos), &current_chunks, &versions);
+        let neighbors = ChunkPos(pos)
+            .neighbors()
+            .into_iter()
+            .enumerate()
+            .filter_map(|(idx, neighbor)| {
+                let (neighbor_chunk, _, _) = current_chunks
+                    .get_entity(neighbor)
+                    .and_then(|entity| chunks.get(entity).ok())?;
+                let changed = meshed.is_some_and(|meshed| meshed.0.get(idx) != current.get(idx));
+                Some(BorderLayer::capture(
+                    *neighbor - pos,
+                    neighbor_chunk,
+                    changed,
+                ))
+            })
+            .collect();
+        let settings = DumpSettings {
+            mesh: MeshSettings::from_options(&options, &tune),
+            variants: true,
+            seed: **seed,
+        };
+        let dump = ChunkDump::capture(
+            pos,
+            chunk,
+            neighbors,
+            mesh.cloned(),
+            settings,
+            canonical_hash(&*content),
+        );
+        let reply = match write_dump(&debug_dir(&project_path), &dump) {
+            Ok(path) => format!(
+                "Dumped chunk {} {} {} to {}",
+                pos.x,
+                pos.y,
+                pos.z,
+                path.display()
+            ),
+            Err(e) => format!("Couldn't dump the chunk: {e}"),
+        };
+        messages.push(ChatLine::console(reply));
+    }
+}
+
+// Set by --inspect-chunk
+#[derive(Resource)]
+pub struct InspectedDump(pub ChunkDump);
+
+// There's nothing to stand on around a lone chunk
+pub fn fly_while_inspecting(
+    mut player: Query<&mut MovementState, Added<ControlledPlayer>>,
+    inspected: Option<Res<InspectedDump>>,
+    mut messages: ResMut<ChatMessages>,
+    (options, tune): (Res<GameOptions>, Res<AutoTune>),
+) {
+    let (Some(inspected), Ok(mut state)) = (inspected, player.get_single_mut()) else {
+        return;
+    };
+    *state = MovementState::Flying;
+    let dumped = inspected.0.settings.mesh;
+    if dumped != MeshSettings::from_options(&options, &tune) {
+        messages.push(ChatLine::console(format!(
+            "The dump was meshed with different settings: {dumped:?}"
+        )));
+    }
+    if let Some(mesh) = &inspected.0.mesh {
+        messages.push(ChatLine::console(format!("Dumped mesh: {mesh:?}")));
+    }
+}
+
+pub struct ChunkDumpPlugin;
+
+impl Plugin for ChunkDumpPlugin {
+    fn build(&self, app: &mut App) {
+        let args: Vec<String> = env::args().collect();
+        if let Some(path) = args
+            .iter()
+            .position(|arg| arg == "--inspect-chunk")
+            .and_then(|idx| args.get(idx + 1))
+        {
+            let inspect = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| ChunkDump::from_ron(&text))
+                .and_then(|dump| Ok((dump.to_replay(1)?, dump)));
+            match inspect {
+                Ok((replay, dump)) => {
+                    app.insert_resource(WorldSeed(dump.settings.seed))
+                        .insert_resource(ReplayPlayback::inspect(PathBuf::from(path), replay))
+                        .insert_resource(InspectedDump(dump))
+                        .insert_resource(NextState(Some(GameState::Loading)));
+                }
+                Err(e) => {
+                    eprintln!("Couldn't inspect {path}: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        app.add_event::<DumpChunkEvent>().add_systems(
+            (handle_dump_chunk, fly_while_inspecting)
+                .in_set(GameSet::WorldUpdate)
+                .in_set(OnUpdate(GameState::Game)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::testing::block;
+
+    #[test]
+    fn parses_dumpchunk() {
+        assert_eq!(parse_dumpchunk("/dumpchunk"), Some(Ok(None)));
+        assert_eq!(
+            parse_dumpchunk("/dumpchunk 1 -2 3"),
+            Some(Ok(Some(IVec3::new(1, -2, 3))))
+        );
+        assert_eq!(
+            parse_dumpchunk("/dumpchunk 1 2"),
+            Some(Err(USAGE.to_string()))
+        );
+        assert_eq!(
+            parse_dumpchunk("/dumpchunk a b c"),
+            Some(Err(USAGE.to_string()))
+        );
+        assert_eq!(parse_dumpchunk("/dumpchunks"), None);
+        assert_eq!(parse_dumpchunk("hello"), None);
+    }
+
+    #[test]
+    fn borders_are_the_touching_layer() {
+        let edge = CHUNK_SIZE as u32;
+        // A face, an edge and a corner
+        let face = border_voxels(IVec3::new(0, -1, 0));
+        assert_eq!(face.len(), (edge * edge) as usize);
+        assert!(face.iter().all(|voxel| voxel.y == edge - 1));
+        let side = border_voxels(IVec3::new(1, 0, -1));
+        assert_eq!(side.len(), edge as usize);
+        assert!(side.iter().all(|voxel| voxel.x == 0 && voxel.z == edge - 1));
+        assert_eq!(border_voxels(IVec3::ONE), vec![UVec3::ZERO]);
+    }
+
+    #[test]
+    fn dumps_round_trip_through_ron() {
+        let table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        chunk.set(1, 2, 3, block("stone"), &table);
+        chunk.set(4, 5, 6, block("dirt"), &table);
+        let mut below = ChunkData::default();
+        below.set(7, CHUNK_SIZE as u32 - 1, 8, block("grass"), &table);
+        // Deep under the border, not part of the dump
+        below.set(7, 0, 8, block("stone"), &table);
+        let layer = BorderLayer::capture(IVec3::NEG_Y, &below, true);
+        assert_eq!(layer.palette, vec![block("air"), block("grass")]);
+        assert_eq!(layer.indices.iter().filter(|idx| **idx == 1).count(), 1);
+
+        let mesh = MeshStats {
+            build_micros: 1500,
+            generation: 3,
+            ..Default::default()
+        };
+        let settings = DumpSettings {
+            mesh: MeshSettings::default(),
+            variants: true,
+            seed: 42,
+        };
+        let dump = ChunkDump::capture(
+            IVec3::new(-2, 0, 5),
+            &chunk,
+            vec![layer.clone()],
+            Some(mesh.clone()),
+            settings,
+            7,
+        );
+        let text = dump.to_ron().unwrap();
+        let read = ChunkDump::from_ron(&text).unwrap();
+        assert_eq!(read.pos, IVec3::new(-2, 0, 5));
+        assert_eq!(read.neighbors, vec![layer.clone()]);
+        assert_eq!(read.mesh, Some(mesh));
+        assert_eq!(read.settings, settings);
+        assert_eq!(read.palette, chunk.palette_summary());
+        assert_eq!(read.chunk.palette_summary(), chunk.palette_summary());
+        let restored = ChunkData::from_raw(read.chunk);
+        assert_eq!(restored.get(1, 2, 3), block("stone"));
+        assert_eq!(restored.get(4, 5, 6), block("dirt"));
+
+        // Only the border comes back for a neighbor
+        let rebuilt = layer.to_chunk();
+        assert_eq!(rebuilt.get(7, CHUNK_SIZE as u32 - 1, 8), block("grass"));
+        assert_eq!(rebuilt.get(7, 0, 8), block("air"));
+
+        let mut newer = text.replace("dump_version: 1", "dump_version: 2");
+        assert!(ChunkDump::from_ron(&newer).is_err());
+        newer.truncate(20);
+        assert!(ChunkDump::from_ron(&newer).is_err());
+    }
+
+    #[test]
+    fn inspecting_sends_the_chunk_and_its_borders() {
+        let table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        chunk.set(0, 0, 0, block("stone"), &table);
+        let layer = BorderLayer::capture(IVec3::X, &ChunkData::default(), false);
+        let dump = ChunkDump::capture(
+            IVec3::new(3, 1, 0),
+            &chunk,
+            vec![layer],
+            None,
+            DumpSettings {
+                mesh: MeshSettings::default(),
+                variants: true,
+                seed: 0,
+            },
+            0,
+        );
+        let replay = dump.to_replay(1).unwrap();
+        assert_eq!(replay.header.client_id, 1);
+        let sent: Vec<IVec3> = replay
+            .frames
+            .iter()
+            .filter_map(|frame| match frame {
+                ReplayFrame::Message {
+                    message: ServerMessage::LevelData { pos, .. },
+                    ..
+                } => Some(*pos),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent, vec![IVec3::new(3, 1, 0), IVec3::new(4, 1, 0)]);
+        // Nobody but us
+        let players = replay
+            .frames
+            .iter()
+            .filter(|frame| {
+                matches!(
+                    frame,
+                    ReplayFrame::Message {
+                        message: ServerMessage::PlayerCreate { .. },
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(players, 1);
+    }
+}
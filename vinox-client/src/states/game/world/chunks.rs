@@ -1,20 +1,38 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
 use bevy_tweening::*;
-use vinox_common::world::chunks::{
-    ecs::{
-        update_chunk_lights, update_priority_chunk_lights, ChunkManager, ChunkUpdate,
-        CurrentChunks, RemoveChunk, SimulationRadius, ViewRadius,
+use vinox_common::{
+    networking::protocol::ClientMessage,
+    world::chunks::{
+        biome_map::ChunkBiomes,
+        ecs::{
+            update_chunk_lights, update_priority_chunk_lights, ChunkManager, ChunkUpdate,
+            CurrentChunks, RemoveChunk, SimulationRadius, ViewRadius,
+        },
+        positions::{voxel_to_global_voxel, world_to_chunk, ChunkPos, DimensionId, WorldOffset},
+        storage::{
+            BlockData, BlockTable, ChunkData, RawChunk, HORIZONTAL_DISTANCE, VERTICAL_DISTANCE,
+        },
     },
-    positions::{voxel_to_global_voxel, world_to_chunk, ChunkPos},
-    storage::{BlockData, BlockTable, ChunkData, RawChunk, HORIZONTAL_DISTANCE, VERTICAL_DISTANCE},
 };
 
 use crate::states::{
-    components::GameState,
-    game::rendering::meshing::{build_mesh, priority_mesh},
+    components::{GameOptions, GameSet, GameState, SessionScoped},
+    game::{
+        networking::connection::NetClient,
+        rendering::{
+            memory::MemoryBudget,
+            meshing::{
+                build_mesh, bump_chunk_versions, priority_mesh, requeue_stale_meshes, ChunkVersion,
+                NextChunkVersion,
+            },
+            transitions::BlockEditEvent,
+        },
+        session::SessionApp,
+        world::origin::recenter_world,
+    },
 };
 
 #[derive(Component)]
@@ -30,15 +48,32 @@ pub struct PlayerBlock {
     pub pos: IVec3,
 }
 
+// The biomes the server sent with each chunk. Kept apart from the chunk entities, a cached chunk
+// is only read back after its biomes came in
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ChunkBiomeMap(pub HashMap<IVec3, ChunkBiomes>);
+
 pub struct CreateChunkEvent {
     pub pos: IVec3,
     pub raw_chunk: RawChunk,
+    pub dimension: DimensionId,
+}
+
+// The server's word that a chunk left our radius
+pub struct UnloadChunkEvent {
+    pub pos: IVec3,
+    pub dimension: DimensionId,
 }
 
 pub struct SetBlockEvent {
     pub chunk_pos: IVec3,
     pub voxel_pos: UVec3,
     pub block_type: BlockData,
+    pub dimension: DimensionId,
+}
+
+pub struct ChangeDimensionEvent {
+    pub dimension: DimensionId,
 }
 pub struct UpdateChunkEvent {
     pub pos: IVec3,
@@ -47,6 +82,7 @@ pub struct UpdateChunkEvent {
 #[derive(Default, Resource)]
 pub struct ChunkQueue {
     pub mesh: Vec<(IVec3, RawChunk)>,
+    // Unloaded while still being lit, dropped when the lighting comes back
     pub remove: HashSet<IVec3>,
 }
 
@@ -65,14 +101,17 @@ pub fn update_player_location(
     player_query: Query<&Transform, With<ControlledPlayer>>,
     mut player_chunk: ResMut<PlayerChunk>,
     mut player_block: ResMut<PlayerBlock>,
+    offset: Res<WorldOffset>,
 ) {
+    // Both are in world space, the transform is relative to the render origin
     if let Ok(player_transform) = player_query.get_single() {
-        let new_chunk = world_to_chunk(player_transform.translation);
+        let new_chunk = offset.chunk_to_world(world_to_chunk(player_transform.translation));
         if new_chunk != player_chunk.chunk_pos {
             player_chunk.chunk_pos = new_chunk;
         }
-        if player_transform.translation.floor().as_ivec3() != player_block.pos {
-            player_block.pos = player_transform.translation.floor().as_ivec3();
+        let new_block = offset.voxel_to_world(player_transform.translation.floor().as_ivec3());
+        if new_block != player_block.pos {
+            player_block.pos = new_block;
         }
     }
 }
@@ -80,9 +119,13 @@ pub fn unload_chunks(
     mut commands: Commands,
     remove_chunks: Query<(&ChunkPos, Entity), With<RemoveChunk>>,
     mut current_chunks: ResMut<CurrentChunks>,
+    mut budget: ResMut<MemoryBudget>,
+    mut biomes: ResMut<ChunkBiomeMap>,
 ) {
     for (chunk, entity) in remove_chunks.iter() {
         current_chunks.remove_entity(*chunk).ok_or(0).ok();
+        budget.free_mesh(**chunk);
+        biomes.remove(&**chunk);
         commands.entity(entity).despawn_recursive();
 
         // if current_chunks.get_entity(*chunk).is_some() {
@@ -118,8 +161,9 @@ pub fn unload_chunks(
 
 #[derive(Resource)]
 pub struct LightingChannel {
-    pub tx: Sender<(ChunkData, IVec3)>,
-    pub rx: Receiver<(ChunkData, IVec3)>,
+    // The bool is whether the chunk is all empty blocks
+    pub tx: Sender<(ChunkData, IVec3, DimensionId, bool)>,
+    pub rx: Receiver<(ChunkData, IVec3, DimensionId, bool)>,
 }
 
 impl Default for LightingChannel {
@@ -137,55 +181,83 @@ pub fn destroy_chunks(mut commands: Commands, mut query_event: EventReader<Tween
     }
 }
 
+// Only the server decides what leaves, going by our own radius we could drop a chunk it still
+// thinks we have and never get it again
 pub fn clear_unloaded_chunks(
     mut commands: Commands,
-    chunks: Query<(&ChunkPos, Entity)>,
-    player_chunk: Res<PlayerChunk>,
-    view_radius: Res<ViewRadius>,
+    mut events: EventReader<UnloadChunkEvent>,
+    current_chunks: Res<CurrentChunks>,
+    mut chunk_queue: ResMut<ChunkQueue>,
 ) {
-    for (chunk, entity) in chunks.iter() {
-        if !player_chunk.is_in_radius(**chunk, &view_radius) {
-            commands.entity(entity).insert(RemoveChunk);
+    for evt in events.iter() {
+        if evt.dimension != current_chunks.active {
+            continue;
+        }
+        match current_chunks.get_entity(ChunkPos(evt.pos)) {
+            Some(entity) => {
+                commands.entity(entity).insert(RemoveChunk);
+            }
+            None => {
+                chunk_queue.remove.insert(evt.pos);
+            }
         }
     }
 }
 
-#[allow(clippy::nonminimal_bool)]
 pub fn receive_chunks(
     mut current_chunks: ResMut<CurrentChunks>,
     mut commands: Commands,
     mut event: EventReader<CreateChunkEvent>,
-    player_chunk: Res<PlayerChunk>,
-    view_radius: Res<ViewRadius>,
+    mut chunk_queue: ResMut<ChunkQueue>,
     block_table: Res<BlockTable>,
     mut light_channel: ResMut<LightingChannel>,
 ) {
     let task_pool = AsyncComputeTaskPool::get();
     for evt in event.iter() {
-        if player_chunk.is_in_radius(evt.pos, &view_radius)
+        if evt.dimension == current_chunks.active
             && current_chunks.get_entity(ChunkPos(evt.pos)).is_none()
         {
+            // Sent again after it was unloaded, wanted after all
+            chunk_queue.remove.remove(&evt.pos);
+            let empty = evt.raw_chunk.is_empty(&block_table);
             let mut chunk_data = ChunkData::from_raw(evt.raw_chunk.clone());
             let cloned_sender = light_channel.tx.clone();
             let cloned_table = block_table.clone();
             let pos = evt.pos;
+            let dimension = evt.dimension;
             task_pool
                 .spawn(async move {
                     cloned_sender
-                        .send((chunk_data.complete_relight(&cloned_table), pos))
+                        .send((
+                            chunk_data.complete_relight(&cloned_table),
+                            pos,
+                            dimension,
+                            empty,
+                        ))
                         .await
                         .ok();
                 })
                 .detach();
         }
     }
-    while let Ok((chunk, pos)) = light_channel.rx.try_recv() {
-        let chunk_id = commands.spawn(chunk.clone()).insert(ChunkPos(pos)).id();
+    while let Ok((chunk, pos, dimension, empty)) = light_channel.rx.try_recv() {
+        // Lighting may finish after we already left the dimension it was sent for, or after the
+        // server unloaded it
+        if dimension != current_chunks.active
+            || current_chunks.get_entity(ChunkPos(pos)).is_some()
+            || chunk_queue.remove.remove(&pos)
+        {
+            continue;
+        }
+        let chunk_id = commands
+            .spawn(chunk.clone())
+            .insert((ChunkPos(pos), ChunkVersion::default(), SessionScoped))
+            .id();
 
         current_chunks.insert_entity(ChunkPos(pos), chunk_id);
 
         // Don't mark chunks that won't create any blocks
-        if !chunk.is_empty(&block_table) {
+        if !empty {
             commands.entity(chunk_id).insert(ChunkUpdate);
         }
     }
@@ -198,17 +270,81 @@ pub fn set_block(
     // mut chunks: Query<&mut ChunkData>,
     // block_table: Res<BlockTable>,
     mut chunk_manager: ChunkManager,
+    mut block_edits: EventWriter<BlockEditEvent>,
 ) {
     for evt in event.iter() {
-        chunk_manager.set_block(
-            voxel_to_global_voxel(evt.voxel_pos, evt.chunk_pos),
-            evt.block_type.clone(),
-        );
+        if evt.dimension != chunk_manager.current_chunks.active {
+            continue;
+        }
+        let voxel = voxel_to_global_voxel(evt.voxel_pos, evt.chunk_pos);
+        // Our own edits come back from the server too, those already played
+        let before = chunk_manager.get_block(voxel);
+        chunk_manager.set_block(voxel, evt.block_type.clone());
+        if let Some(before) = before {
+            if before != evt.block_type {
+                block_edits.send(BlockEditEvent {
+                    voxel,
+                    before,
+                    after: evt.block_type.clone(),
+                    local: false,
+                });
+            }
+        }
     }
 }
 
-pub fn should_update_chunks(player_chunk: Res<PlayerChunk>) -> bool {
-    player_chunk.is_changed()
+// Drops every loaded chunk so nothing from the old dimension lingers around
+pub fn clear_chunks(
+    mut commands: Commands,
+    chunks: Query<Entity, With<ChunkPos>>,
+    mut current_chunks: ResMut<CurrentChunks>,
+    mut chunk_queue: ResMut<ChunkQueue>,
+    mut player_chunk: ResMut<PlayerChunk>,
+    mut player_block: ResMut<PlayerBlock>,
+    (mut budget, mut biomes): (ResMut<MemoryBudget>, ResMut<ChunkBiomeMap>),
+) {
+    for entity in chunks.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    budget.clear_meshes();
+    biomes.clear();
+    let active = current_chunks.active;
+    current_chunks.clear_dimension(active);
+    current_chunks.active = DimensionId::default();
+    chunk_queue.mesh.clear();
+    chunk_queue.remove.clear();
+    *player_chunk = PlayerChunk::default();
+    *player_block = PlayerBlock::default();
+}
+
+pub fn change_dimension(
+    mut events: EventReader<ChangeDimensionEvent>,
+    mut current_chunks: ResMut<CurrentChunks>,
+) {
+    if let Some(evt) = events.iter().last() {
+        current_chunks.active = evt.dimension;
+    }
+}
+
+pub fn has_changed_dimension(events: EventReader<ChangeDimensionEvent>) -> bool {
+    !events.is_empty()
+}
+
+// Follows the option. The server answers a smaller radius with UnloadChunk for what's past it
+// and a bigger one with the new ring. The fog goes by ViewRadius already
+pub fn apply_view_distance(
+    options: Res<GameOptions>,
+    mut view_radius: ResMut<ViewRadius>,
+    mut client: NetClient,
+) {
+    let horizontal = options.view_distance as i32;
+    if view_radius.horizontal == horizontal {
+        return;
+    }
+    view_radius.horizontal = horizontal;
+    client.send(ClientMessage::ViewDistance {
+        horizontal: options.view_distance as u8,
+    });
 }
 
 pub struct ChunkPlugin;
@@ -220,6 +356,17 @@ impl Plugin for ChunkPlugin {
             .insert_resource(PlayerChunk::default())
             .insert_resource(PlayerBlock::default())
             .insert_resource(LightingChannel::default())
+            .insert_resource(NextChunkVersion::default())
+            .insert_resource(ChunkBiomeMap::default())
+            .reset_on_exit::<CurrentChunks>()
+            .reset_on_exit::<ChunkQueue>()
+            .reset_on_exit::<PlayerChunk>()
+            .reset_on_exit::<PlayerBlock>()
+            // Lighting still running for the old world sends into a channel nobody reads
+            .reset_on_exit::<LightingChannel>()
+            .reset_on_exit::<NextChunkVersion>()
+            .reset_on_exit::<ChunkBiomeMap>()
+            .reset_on_exit::<WorldOffset>()
             .insert_resource(ViewRadius {
                 horizontal: HORIZONTAL_DISTANCE as i32,
                 vertical: VERTICAL_DISTANCE as i32,
@@ -228,52 +375,207 @@ impl Plugin for ChunkPlugin {
                 horizontal: 4,
                 vertical: 4,
             })
-            .add_system(update_player_location.in_set(OnUpdate(GameState::Game)))
+            .add_systems(
+                (clear_chunks, change_dimension)
+                    .chain()
+                    .distributive_run_if(has_changed_dimension)
+                    .before(update_player_location)
+                    .in_set(GameSet::WorldUpdate)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                recenter_world
+                    .after(AnimationSystem::AnimationUpdate)
+                    .before(update_player_location)
+                    .in_set(GameSet::WorldUpdate)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                update_player_location
+                    .in_set(GameSet::WorldUpdate)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
             .add_systems(
                 (receive_chunks, set_block)
                     .chain()
                     .after(update_player_location)
+                    .in_set(GameSet::WorldUpdate)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_system(
+                apply_view_distance
+                    .before(clear_unloaded_chunks)
+                    .in_set(GameSet::WorldUpdate)
                     .in_set(OnUpdate(GameState::Game)),
             )
             .add_system(
                 clear_unloaded_chunks
                     .after(receive_chunks)
-                    .run_if(should_update_chunks)
+                    .in_set(GameSet::WorldUpdate)
                     .in_set(OnUpdate(GameState::Game)),
             )
             .add_system(
                 update_chunk_lights
                     .after(clear_unloaded_chunks)
+                    .in_set(GameSet::WorldUpdate)
                     .in_set(OnUpdate(GameState::Game)),
             )
             .add_system(
                 update_priority_chunk_lights
                     .after(clear_unloaded_chunks)
+                    .in_set(GameSet::WorldUpdate)
+                    .in_set(OnUpdate(GameState::Game)),
+            )
+            .add_systems(
+                (bump_chunk_versions, requeue_stale_meshes)
+                    .chain()
+                    .after(update_chunk_lights)
+                    .after(update_priority_chunk_lights)
+                    .before(build_mesh)
+                    .before(priority_mesh)
+                    .in_set(GameSet::WorldUpdate)
                     .in_set(OnUpdate(GameState::Game)),
             )
             .add_system(
                 build_mesh
                     .after(update_chunk_lights)
+                    .in_set(GameSet::WorldUpdate)
                     .in_set(OnUpdate(GameState::Game)),
             )
             .add_system(
                 priority_mesh
                     .after(update_chunk_lights)
+                    .in_set(GameSet::WorldUpdate)
                     .in_set(OnUpdate(GameState::Game)),
             )
             .add_system(
                 unload_chunks
                     .after(build_mesh)
+                    .in_set(GameSet::WorldUpdate)
                     .in_set(OnUpdate(GameState::Game)),
             )
             .add_system(
                 destroy_chunks
                     .after(unload_chunks)
                     // .after(build_mesh)
+                    .in_set(GameSet::WorldUpdate)
                     .in_set(OnUpdate(GameState::Game)),
             )
             .add_event::<UpdateChunkEvent>()
             .add_event::<SetBlockEvent>()
-            .add_event::<CreateChunkEvent>();
+            .add_event::<ChangeDimensionEvent>()
+            .add_event::<CreateChunkEvent>()
+            .add_event::<UnloadChunkEvent>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimension_switch_clears_chunks() {
+        let mut app = App::new();
+        app.insert_resource(CurrentChunks::default())
+            .insert_resource(ChunkQueue::default())
+            .insert_resource(PlayerChunk::default())
+            .insert_resource(PlayerBlock::default())
+            .insert_resource(MemoryBudget::default())
+            .insert_resource(ChunkBiomeMap::default())
+            .add_event::<ChangeDimensionEvent>()
+            .add_systems(
+                (clear_chunks, change_dimension)
+                    .chain()
+                    .distributive_run_if(has_changed_dimension),
+            );
+        for x in 0..4 {
+            let pos = ChunkPos::new(x, 0, 0);
+            let entity = app.world.spawn((ChunkData::default(), pos)).id();
+            app.world
+                .resource_mut::<CurrentChunks>()
+                .insert_entity(pos, entity);
+        }
+
+        app.update();
+        assert_eq!(app.world.resource::<CurrentChunks>().len(), 4);
+
+        app.world.send_event(ChangeDimensionEvent {
+            dimension: DimensionId(1),
+        });
+        app.update();
+
+        let current_chunks = app.world.resource::<CurrentChunks>();
+        assert_eq!(current_chunks.active, DimensionId(1));
+        assert!(current_chunks.is_empty());
+        assert_eq!(
+            app.world
+                .query_filtered::<Entity, With<ChunkPos>>()
+                .iter(&app.world)
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn only_the_server_unloads_chunks() {
+        let mut app = App::new();
+        app.insert_resource(CurrentChunks::default())
+            .insert_resource(ChunkQueue::default())
+            .insert_resource(MemoryBudget::default())
+            .insert_resource(ChunkBiomeMap::default())
+            .insert_resource(ViewRadius {
+                horizontal: 8,
+                vertical: 2,
+            })
+            .add_event::<UnloadChunkEvent>()
+            .add_systems((clear_unloaded_chunks, apply_system_buffers, unload_chunks).chain());
+        for x in 0..8 {
+            let pos = ChunkPos::new(x, 0, 0);
+            let entity = app.world.spawn((ChunkData::default(), pos)).id();
+            app.world
+                .resource_mut::<CurrentChunks>()
+                .insert_entity(pos, entity);
+        }
+        // Shrinking the radius on our side alone keeps everything until the server says
+        app.world.resource_mut::<ViewRadius>().horizontal = 4;
+        app.update();
+        assert_eq!(app.world.resource::<CurrentChunks>().len(), 8);
+
+        for x in 5..8 {
+            app.world.send_event(UnloadChunkEvent {
+                pos: IVec3::new(x, 0, 0),
+                dimension: DimensionId::default(),
+            });
+        }
+        // Still being lit, dropped once that's done
+        app.world.send_event(UnloadChunkEvent {
+            pos: IVec3::new(9, 0, 0),
+            dimension: DimensionId::default(),
+        });
+        // Left over from a dimension we're no longer in
+        app.world.send_event(UnloadChunkEvent {
+            pos: IVec3::new(0, 0, 0),
+            dimension: DimensionId(1),
+        });
+        app.update();
+        let current_chunks = app.world.resource::<CurrentChunks>();
+        assert_eq!(current_chunks.len(), 5);
+        assert!(current_chunks.get_entity(ChunkPos::new(5, 0, 0)).is_none());
+        assert!(current_chunks.get_entity(ChunkPos::new(0, 0, 0)).is_some());
+        assert_eq!(
+            app.world
+                .query_filtered::<Entity, With<ChunkPos>>()
+                .iter(&app.world)
+                .count(),
+            5
+        );
+        let pending: Vec<_> = app
+            .world
+            .resource::<ChunkQueue>()
+            .remove
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(pending, vec![IVec3::new(9, 0, 0)]);
     }
 }
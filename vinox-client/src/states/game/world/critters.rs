@@ -0,0 +1,176 @@
+use bevy::prelude::*;
+use vinox_common::{
+    ecs::time::GameClock, networking::protocol::EntityKind, storage::items::descriptor::ItemData,
+    world::chunks::storage::name_to_identifier,
+};
+
+use crate::states::{
+    assets::load::LoadableAssets,
+    components::{GameOptions, GameSet, GameState, SessionScoped},
+    game::networking::components::NetworkMapping,
+};
+
+pub struct EntityCreateEvent {
+    pub entity: Entity,
+    pub kind: EntityKind,
+    pub translation: Vec3,
+    pub yaw: f32,
+    pub item: Option<ItemData>,
+}
+
+#[derive(Component, Default)]
+pub struct CritterModel {
+    pub last_translation: Vec3,
+    pub phase: f32,
+}
+
+#[derive(Component)]
+pub struct CritterLegs;
+
+#[derive(Component)]
+pub struct DroppedItemModel {
+    pub server_entity: Entity,
+    pub item: ItemData,
+    // Last time we asked the server for it
+    pub requested_at: Option<f32>,
+}
+
+#[derive(Component)]
+pub struct DroppedItemSpin;
+
+pub fn spawn_entities(
+    mut commands: Commands,
+    mut event: EventReader<EntityCreateEvent>,
+    mut network_mapping: ResMut<NetworkMapping>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    loadable_assets: Res<LoadableAssets>,
+) {
+    for evt in event.iter() {
+        match evt.kind {
+            EntityKind::DroppedItem => {
+                let Some(item) = evt.item.clone() else {
+                    continue;
+                };
+                let texture = loadable_assets
+                    .item_textures
+                    .get(&name_to_identifier(
+                        item.namespace.clone(),
+                        item.name.clone(),
+                    ))
+                    .cloned();
+                let client_entity = commands
+                    .spawn(SpatialBundle::from_transform(Transform::from_translation(
+                        evt.translation,
+                    )))
+                    .insert((
+                        DroppedItemModel {
+                            server_entity: evt.entity,
+                            item,
+                            requested_at: None,
+                        },
+                        SessionScoped,
+                    ))
+                    .with_children(|parent| {
+                        parent
+                            .spawn(PbrBundle {
+                                mesh: meshes.add(Mesh::from(shape::Cube { size: 0.25 })),
+                                material: materials.add(StandardMaterial {
+                                    base_color_texture: texture,
+                                    alpha_mode: AlphaMode::Mask(0.5),
+                                    ..default()
+                                }),
+                                transform: Transform::from_xyz(0.0, 0.125, 0.0),
+                                ..default()
+                            })
+                            .insert(DroppedItemSpin);
+                    })
+                    .id();
+                network_mapping.insert(evt.entity, client_entity);
+            }
+            EntityKind::Critter => {
+                let client_entity = commands
+                    .spawn(SpatialBundle::from_transform(
+                        Transform::from_translation(evt.translation)
+                            .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.0, evt.yaw, 0.0)),
+                    ))
+                    .insert((
+                        CritterModel {
+                            last_translation: evt.translation,
+                            phase: 0.0,
+                        },
+                        SessionScoped,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn(PbrBundle {
+                            mesh: meshes.add(Mesh::from(shape::Box::new(0.5, 0.3, 0.6))),
+                            material: materials.add(Color::rgb(0.76, 0.6, 0.42).into()),
+                            transform: Transform::from_xyz(0.0, 0.35, 0.0),
+                            ..default()
+                        });
+                        parent
+                            .spawn(PbrBundle {
+                                mesh: meshes.add(Mesh::from(shape::Box::new(0.4, 0.2, 0.4))),
+                                material: materials.add(Color::rgb(0.45, 0.34, 0.24).into()),
+                                transform: Transform::from_xyz(0.0, 0.1, 0.0),
+                                ..default()
+                            })
+                            .insert(CritterLegs);
+                    })
+                    .id();
+                network_mapping.insert(evt.entity, client_entity);
+            }
+        }
+    }
+}
+
+// Legs rock back and forth faster the quicker the critter is moving
+pub fn animate_critters(
+    mut critters: Query<(&mut CritterModel, &Transform, &Children)>,
+    mut legs: Query<&mut Transform, (With<CritterLegs>, Without<CritterModel>)>,
+    clock: Res<GameClock>,
+) {
+    let delta = clock.delta_seconds();
+    if delta <= 0.0 {
+        return;
+    }
+    for (mut model, transform, children) in critters.iter_mut() {
+        let speed = ((transform.translation - model.last_translation) * Vec3::new(1.0, 0.0, 1.0))
+            .length()
+            / delta;
+        model.last_translation = transform.translation;
+        model.phase += speed * 8.0 * delta;
+        for child in children.iter() {
+            if let Ok(mut leg_transform) = legs.get_mut(*child) {
+                leg_transform.rotation =
+                    Quat::from_rotation_x(model.phase.sin() * 0.4 * speed.min(1.0));
+            }
+        }
+    }
+}
+
+// Turns slowly so it reads as something to pick up rather than a tiny block
+pub fn spin_dropped_items(
+    mut spinning: Query<&mut Transform, With<DroppedItemSpin>>,
+    clock: Res<GameClock>,
+    options: Res<GameOptions>,
+) {
+    if options.reduce_motion {
+        return;
+    }
+    for mut transform in spinning.iter_mut() {
+        transform.rotate_y(clock.delta_seconds() * 1.5);
+    }
+}
+
+pub struct CritterPlugin;
+
+impl Plugin for CritterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<EntityCreateEvent>().add_systems(
+            (spawn_entities, animate_critters, spin_dropped_items)
+                .in_set(GameSet::WorldUpdate)
+                .in_set(OnUpdate(GameState::Game)),
+        );
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::MeshVertexBufferLayout,
+        render_resource::{
+            AsBindGroup, CompareFunction, RenderPipelineDescriptor, ShaderRef,
+            SpecializedMeshPipelineError,
+        },
+    },
+};
+use vinox_common::world::chunks::{
+    ecs::CurrentChunks,
+    positions::{voxel_to_global_voxel, ChunkPos, WorldOffset},
+    storage::{BlockTable, ChunkData},
+};
+
+use crate::states::{
+    components::{GameOptions, GameSet, GameState, SessionScoped},
+    game::{
+        networking::components::{Capabilities, ChatLine, ChatMessages},
+        session::SessionApp,
+        world::chunks::PlayerChunk,
+    },
+};
+
+pub const DEFAULT_FIND_RADIUS: i32 = 4;
+pub const MAX_FIND_RADIUS: i32 = 16;
+pub const MAX_FIND_RESULTS: usize = 500;
+// Most chunks get skipped on the palette alone so this can be fairly generous
+pub const FIND_CHUNKS_PER_FRAME: usize = 8;
+// Only the busiest chunks get their own line in chat, the rest are summed up
+const CHUNK_LINES: usize = 8;
+const HIGHLIGHT_COLOR: Color = Color::rgba(1.0, 0.85, 0.2, 0.35);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FindCommand {
+    Search { identifier: String, radius: i32 },
+    Clear,
+}
+
+pub struct FindEvent(pub FindCommand);
+
+// None when the line isn't a /find at all, otherwise the command or what was wrong with it
+pub fn parse_find(line: &str) -> Option<Result<FindCommand, String>> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("/find") {
+        return None;
+    }
+    Some(match (words.next(), words.next(), words.next()) {
+        (Some("clear"), None, None) => Ok(FindCommand::Clear),
+        (Some(identifier), radius, None) => {
+            // Bare names mean the base game's blocks
+            let identifier = if identifier.contains(':') {
+                identifier.to_string()
+            } else {
+                format!("vinox:{identifier}")
+            };
+            match radius.map(str::parse::<i32>) {
+                None => Ok(FindCommand::Search {
+                    identifier,
+                    radius: DEFAULT_FIND_RADIUS,
+                }),
+                Some(Ok(radius)) if (0..=MAX_FIND_RADIUS).contains(&radius) => {
+                    Ok(FindCommand::Search { identifier, radius })
+                }
+                _ => Err(format!(
+                    "The radius is in chunks, from 0 to {MAX_FIND_RADIUS}"
+                )),
+            }
+        }
+        _ => Err("Usage: /find <namespace:name> [radius] or /find clear".to_string()),
+    })
+}
+
+pub struct FindScan {
+    pub identifier: String,
+    pending: VecDeque<IVec3>,
+    total: usize,
+    // Bottom corners of the highlighted blocks, at most MAX_FIND_RESULTS of them
+    pub found: Vec<IVec3>,
+    pub per_chunk: Vec<(IVec3, usize)>,
+    pub matches: usize,
+    // Chat line the progress gets rewritten into
+    line: usize,
+}
+
+impl FindScan {
+    // Nearest chunks go first so hitting the cap keeps the closest results
+    pub fn new(identifier: String, center: IVec3, radius: i32, line: usize) -> Self {
+        let mut pending: Vec<IVec3> = (-radius..=radius)
+            .flat_map(|x| {
+                (-radius..=radius)
+                    .flat_map(move |y| (-radius..=radius).map(move |z| IVec3::new(x, y, z)))
+            })
+            .collect();
+        pending.sort_by_key(|offset| offset.dot(*offset));
+        FindScan {
+            identifier,
+            total: pending.len(),
+            pending: pending.into_iter().map(|offset| center + offset).collect(),
+            found: Vec::new(),
+            per_chunk: Vec::new(),
+            matches: 0,
+            line,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.matches > self.found.len()
+    }
+
+    pub fn progress(&self) -> String {
+        format!(
+            "Searching for {}... {}/{} chunks",
+            self.identifier,
+            self.total - self.pending.len(),
+            self.total
+        )
+    }
+
+    // Looks at up to budget chunks, get_chunk hands back None for ones that aren't loaded
+    pub fn step<'a>(
+        &mut self,
+        budget: usize,
+        mut get_chunk: impl FnMut(IVec3) -> Option<&'a ChunkData>,
+    ) {
+        for _ in 0..budget {
+            let Some(chunk_pos) = self.pending.pop_front() else {
+                return;
+            };
+            let Some(chunk) = get_chunk(chunk_pos) else {
+                continue;
+            };
+            if !chunk.palette_contains(&self.identifier) {
+                continue;
+            }
+            let positions = chunk.positions_of(&self.identifier);
+            if positions.is_empty() {
+                continue;
+            }
+            self.matches += positions.len();
+            self.per_chunk.push((chunk_pos, positions.len()));
+            let room = MAX_FIND_RESULTS.saturating_sub(self.found.len());
+            self.found.extend(
+                positions
+                    .into_iter()
+                    .take(room)
+                    .map(|voxel| voxel_to_global_voxel(voxel, chunk_pos)),
+            );
+        }
+    }
+
+    pub fn summary(&self) -> Vec<String> {
+        if self.matches == 0 {
+            return vec![format!("No {} in the loaded chunks", self.identifier)];
+        }
+        let mut per_chunk = self.per_chunk.clone();
+        per_chunk.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        let mut lines = vec![format!(
+            "Found {} {} in {} chunks",
+            self.matches,
+            self.identifier,
+            per_chunk.len()
+        )];
+        lines.extend(
+            per_chunk
+                .iter()
+                .take(CHUNK_LINES)
+                .map(|(pos, count)| format!("  chunk {} {} {}: {count}", pos.x, pos.y, pos.z)),
+        );
+        if per_chunk.len() > CHUNK_LINES {
+            lines.push(format!(
+                "  and {} more chunks",
+                per_chunk.len() - CHUNK_LINES
+            ));
+        }
+        if self.truncated() {
+            lines.push(format!(
+                "Too many to show, only the nearest {MAX_FIND_RESULTS} are highlighted"
+            ));
+        }
+        lines
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct BlockFinder {
+    pub scan: Option<FindScan>,
+    // Elapsed seconds the highlights go away at, None while nothing is shown
+    expires: Option<f32>,
+    assets: Option<(Handle<Mesh>, Handle<XrayMaterial>)>,
+}
+
+#[derive(Component)]
+pub struct FindHighlight;
+
+// Drawn over everything so the results show through terrain
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "3c1b6f0e-8a47-4d2b-9f5e-6b0d2e7a91c4"]
+pub struct XrayMaterial {
+    #[uniform(0)]
+    pub color: Color,
+}
+
+impl Material for XrayMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/xray_material.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        if let Some(depth_stencil) = descriptor.depth_stencil.as_mut() {
+            depth_stencil.depth_compare = CompareFunction::Always;
+            depth_stencil.depth_write_enabled = false;
+        }
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn handle_find(
+    mut commands: Commands,
+    mut events: EventReader<FindEvent>,
+    mut finder: ResMut<BlockFinder>,
+    mut messages: ResMut<ChatMessages>,
+    capabilities: Res<Capabilities>,
+    block_table: Res<BlockTable>,
+    player_chunk: Res<PlayerChunk>,
+    highlights: Query<Entity, With<FindHighlight>>,
+) {
+    for FindEvent(command) in events.iter() {
+        // Local games make us an operator so this is always there offline
+        if !capabilities.creative {
+            messages.push(ChatLine::console("Only creative players can use /find"));
+            continue;
+        }
+        if let FindCommand::Search { identifier, .. } = command {
+            if !block_table.contains_key(identifier) {
+                messages.push(ChatLine::console(format!(
+                    "There is no block called {identifier}"
+                )));
+                continue;
+            }
+        }
+        // Whatever was shown before goes away, a new search replaces it
+        for entity in highlights.iter() {
+            commands.entity(entity).despawn();
+        }
+        finder.expires = None;
+        match command {
+            FindCommand::Clear => {
+                finder.scan = None;
+                messages.push(ChatLine::console("Cleared the find results"));
+            }
+            FindCommand::Search { identifier, radius } => {
+                let scan = FindScan::new(
+                    identifier.clone(),
+                    player_chunk.chunk_pos,
+                    *radius,
+                    messages.pushed(),
+                );
+                messages.push(ChatLine::console(scan.progress()));
+                finder.scan = Some(scan);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn scan_for_blocks(
+    mut commands: Commands,
+    mut finder: ResMut<BlockFinder>,
+    mut messages: ResMut<ChatMessages>,
+    current_chunks: Res<CurrentChunks>,
+    chunks: Query<&ChunkData>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<XrayMaterial>>,
+    options: Res<GameOptions>,
+    (time, offset): (Res<Time>, Res<WorldOffset>),
+) {
+    let finder = &mut *finder;
+    let Some(scan) = finder.scan.as_mut() else {
+        return;
+    };
+    scan.step(FIND_CHUNKS_PER_FRAME, |chunk_pos| {
+        current_chunks
+            .get_entity(ChunkPos(chunk_pos))
+            .and_then(|entity| chunks.get(entity).ok())
+    });
+    if let Some(line) = messages.get_mut(scan.line) {
+        line.message = scan.progress();
+    }
+    if !scan.is_done() {
+        return;
+    }
+    let Some(scan) = finder.scan.take() else {
+        return;
+    };
+    messages.extend(scan.summary().into_iter().map(ChatLine::console));
+    if scan.found.is_empty() {
+        return;
+    }
+    let (mesh, material) = finder
+        .assets
+        .get_or_insert_with(|| {
+            (
+                meshes.add(shape::Cube { size: 1.02 }.into()),
+                materials.add(XrayMaterial {
+                    color: HIGHLIGHT_COLOR,
+                }),
+            )
+        })
+        .clone();
+    for corner in scan.found {
+        commands.spawn((
+            MaterialMeshBundle {
+                mesh: mesh.clone(),
+                material: material.clone(),
+                transform: Transform::from_translation(
+                    offset.voxel_to_render(corner).as_vec3() + Vec3::splat(0.5),
+                ),
+                ..default()
+            },
+            FindHighlight,
+            SessionScoped,
+        ));
+    }
+    finder.expires = Some(time.elapsed_seconds() + options.find_highlight.max(1.0));
+}
+
+pub fn expire_highlights(
+    mut commands: Commands,
+    mut finder: ResMut<BlockFinder>,
+    highlights: Query<Entity, With<FindHighlight>>,
+    time: Res<Time>,
+) {
+    if finder
+        .expires
+        .is_some_and(|expires| time.elapsed_seconds() >= expires)
+    {
+        finder.expires = None;
+        for entity in highlights.iter() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub struct FinderPlugin;
+
+impl Plugin for FinderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BlockFinder>()
+            .reset_on_exit::<BlockFinder>()
+            .add_event::<FindEvent>()
+            .add_systems(
+                (handle_find, scan_for_blocks, expire_highlights)
+                    .chain()
+                    .in_set(GameSet::WorldUpdate)
+                    .in_set(OnUpdate(GameState::Game)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::world::chunks::storage::BlockData;
+
+    #[test]
+    fn finds_nearest_first_and_caps() {
+        assert_eq!(parse_find("hello"), None);
+        assert_eq!(parse_find("/find clear"), Some(Ok(FindCommand::Clear)));
+        assert_eq!(
+            parse_find("/find gold_ore 2"),
+            Some(Ok(FindCommand::Search {
+                identifier: "vinox:gold_ore".to_string(),
+                radius: 2
+            }))
+        );
+        assert!(matches!(parse_find("/find"), Some(Err(_))));
+        assert!(matches!(parse_find("/find vinox:stone 99"), Some(Err(_))));
+
+        let table = BlockTable::default();
+        let stone = BlockData::new("vinox".to_string(), "stone".to_string());
+        let mut near = ChunkData::default();
+        near.set(0, 0, 0, stone.clone(), &table);
+        near.set(3, 4, 5, stone.clone(), &table);
+        // A far chunk full of it, way over the cap
+        let mut far = ChunkData::default();
+        for x in 0..16 {
+            for y in 0..4 {
+                for z in 0..16 {
+                    far.set(x, y, z, stone.clone(), &table);
+                }
+            }
+        }
+        let empty = ChunkData::default();
+
+        let mut scan = FindScan::new("vinox:stone".to_string(), IVec3::ZERO, 1, 0);
+        let mut looked_at = Vec::new();
+        while !scan.is_done() {
+            scan.step(FIND_CHUNKS_PER_FRAME, |pos| {
+                looked_at.push(pos);
+                match pos {
+                    IVec3::ZERO => Some(&near),
+                    pos if pos == IVec3::ONE => Some(&far),
+                    pos if pos.x == -1 => None,
+                    _ => Some(&empty),
+                }
+            });
+        }
+        assert_eq!(looked_at.len(), 27);
+        assert_eq!(looked_at[0], IVec3::ZERO);
+        assert_eq!(scan.matches, 2 + 16 * 4 * 16);
+        assert_eq!(scan.found.len(), MAX_FIND_RESULTS);
+        assert!(scan.truncated());
+        // The close chunk made it in before the cap was hit
+        assert_eq!(scan.found[0], IVec3::ZERO);
+        assert_eq!(scan.found[1], IVec3::new(3, 4, 5));
+        assert_eq!(scan.per_chunk, vec![(IVec3::ZERO, 2), (IVec3::ONE, 1024)]);
+        let summary = scan.summary();
+        assert!(summary[0].contains("1026"));
+        assert!(summary.last().unwrap().contains("500"));
+    }
+}
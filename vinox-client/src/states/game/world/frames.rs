@@ -0,0 +1,209 @@
+use std::f32::consts::FRAC_PI_4;
+
+use bevy::{prelude::*, utils::HashMap};
+use vinox_common::{
+    ecs::bundles::{Inventory, SlotRef},
+    networking::protocol::FrameRequest,
+    storage::items::descriptor::{ItemData, MAX_STACK_SIZE},
+    world::{
+        chunks::{
+            ecs::CurrentChunks,
+            positions::{global_voxel_positions, voxel_to_global_voxel, ChunkPos, WorldOffset},
+            storage::{BlockData, BlockTable, ChunkData, ItemTable},
+        },
+        frames::{displayed_item, frame_normal, frame_rotation, is_display_frame, FrameUse},
+    },
+};
+
+use crate::states::{
+    assets::load::LoadableAssets,
+    components::{GameSet, GameState, SessionScoped},
+    game::{
+        input::drop::take_from_slot, rendering::transitions::BlockEditEvent, session::SessionApp,
+    },
+};
+
+// Just off the block the frame hangs on so the item doesn't z-fight with it
+const DISPLAY_INSET: f32 = 0.43;
+const DISPLAY_SIZE: f32 = 0.6;
+
+// The item quads shown in frames, keyed by the frame's voxel
+#[derive(Resource, Default)]
+pub struct FrameDisplays {
+    displays: HashMap<IVec3, Entity>,
+    quad: Option<Handle<Mesh>>,
+    materials: HashMap<String, Handle<StandardMaterial>>,
+}
+
+// Turns what a click on a frame means into what gets sent, None when there's nothing to send.
+// Inserts take one out of the slot right away, takes wait until it would fit
+pub fn frame_request(
+    action: FrameUse,
+    inventory: &mut Inventory,
+    slot: SlotRef,
+    displayed: Option<&str>,
+    item_table: &ItemTable,
+) -> Option<FrameRequest> {
+    match action {
+        FrameUse::Insert => {
+            let (item, _) = take_from_slot(inventory, slot, 1)?;
+            Some(FrameRequest::Insert {
+                slot,
+                item: ItemData {
+                    stack_size: 1,
+                    ..item
+                },
+            })
+        }
+        FrameUse::Take => {
+            let descriptor = item_table.get(displayed?)?;
+            let item = ItemData {
+                namespace: descriptor.namespace.clone(),
+                name: descriptor.name.clone(),
+                stack_size: 1,
+                ..Default::default()
+            };
+            let max_stack_size = descriptor.max_stack_size.unwrap_or(MAX_STACK_SIZE);
+            (inventory.clone().add_stack(&item, max_stack_size) == 0).then_some(FrameRequest::Take)
+        }
+        FrameUse::Rotate => Some(FrameRequest::Rotate),
+    }
+}
+
+fn display_transform(voxel: IVec3, block: &BlockData, offset: &WorldOffset) -> Transform {
+    let normal = frame_normal(block).as_vec3();
+    let facing = Quat::from_rotation_arc(Vec3::Z, normal);
+    let turn = Quat::from_axis_angle(normal, frame_rotation(block) as f32 * FRAC_PI_4);
+    Transform::from_translation(
+        offset.voxel_to_render(voxel).as_vec3() + Vec3::splat(0.5) - normal * DISPLAY_INSET,
+    )
+    .with_rotation(turn * facing)
+}
+
+impl FrameDisplays {
+    fn remove(&mut self, commands: &mut Commands, voxel: IVec3) {
+        if let Some(entity) = self.displays.remove(&voxel) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn show(
+        &mut self,
+        commands: &mut Commands,
+        voxel: IVec3,
+        block: &BlockData,
+        offset: &WorldOffset,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        loadable_assets: &LoadableAssets,
+    ) {
+        self.remove(commands, voxel);
+        let Some(identifier) = displayed_item(block) else {
+            return;
+        };
+        let mesh = self
+            .quad
+            .get_or_insert_with(|| {
+                meshes.add(Mesh::from(shape::Quad::new(Vec2::splat(DISPLAY_SIZE))))
+            })
+            .clone();
+        let material = self
+            .materials
+            .entry(identifier.to_string())
+            .or_insert_with(|| {
+                materials.add(StandardMaterial {
+                    base_color_texture: loadable_assets.item_textures.get(identifier).cloned(),
+                    alpha_mode: AlphaMode::Mask(0.5),
+                    ..default()
+                })
+            })
+            .clone();
+        let entity = commands
+            .spawn((
+                PbrBundle {
+                    mesh,
+                    material,
+                    transform: display_transform(voxel, block, offset),
+                    ..default()
+                },
+                SessionScoped,
+            ))
+            .id();
+        self.displays.insert(voxel, entity);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn sync_frame_displays(
+    mut commands: Commands,
+    mut displays: ResMut<FrameDisplays>,
+    added: Query<(&ChunkData, &ChunkPos), Added<ChunkData>>,
+    mut block_edits: EventReader<BlockEditEvent>,
+    (block_table, current_chunks, offset): (Res<BlockTable>, Res<CurrentChunks>, Res<WorldOffset>),
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    loadable_assets: Res<LoadableAssets>,
+) {
+    let displays = &mut *displays;
+    // Unloaded chunks and old dimensions take their displays with them
+    displays.displays.retain(|voxel, entity| {
+        let loaded = current_chunks
+            .get_entity(ChunkPos(global_voxel_positions(*voxel).0))
+            .is_some();
+        if !loaded {
+            commands.entity(*entity).despawn_recursive();
+        }
+        loaded
+    });
+    let frames: Vec<&String> = block_table
+        .iter()
+        .filter(|(_, descriptor)| descriptor.display_frame.unwrap_or(false))
+        .map(|(identifier, _)| identifier)
+        .collect();
+    for (chunk, chunk_pos) in added.iter() {
+        for identifier in &frames {
+            for voxel_pos in chunk.positions_of(identifier) {
+                let block = chunk.get(voxel_pos.x, voxel_pos.y, voxel_pos.z);
+                displays.show(
+                    &mut commands,
+                    voxel_to_global_voxel(voxel_pos, **chunk_pos),
+                    &block,
+                    &offset,
+                    &mut meshes,
+                    &mut materials,
+                    &loadable_assets,
+                );
+            }
+        }
+    }
+    for evt in block_edits.iter() {
+        if is_display_frame(&evt.after, &block_table) {
+            displays.show(
+                &mut commands,
+                evt.voxel,
+                &evt.after,
+                &offset,
+                &mut meshes,
+                &mut materials,
+                &loadable_assets,
+            );
+        } else if is_display_frame(&evt.before, &block_table) {
+            displays.remove(&mut commands, evt.voxel);
+        }
+    }
+}
+
+pub struct FramePlugin;
+
+impl Plugin for FramePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FrameDisplays>()
+            .reset_on_exit::<FrameDisplays>()
+            .add_system(
+                sync_frame_displays
+                    .in_set(GameSet::RenderPrep)
+                    .in_set(OnUpdate(GameState::Game)),
+            );
+    }
+}
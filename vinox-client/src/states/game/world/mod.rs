@@ -1 +1,8 @@
+pub mod chunk_dump;
 pub mod chunks;
+pub mod critters;
+pub mod finder;
+pub mod frames;
+pub mod origin;
+pub mod remote_players;
+pub mod schematic;
@@ -0,0 +1,231 @@
+use bevy::{ecs::system::SystemParam, math::Vec3A, prelude::*, render::primitives::Aabb};
+use bevy_tweening::Animator;
+use vinox_common::{
+    physics::simulate::Velocity,
+    world::chunks::{
+        positions::{world_to_chunk, ChunkPos, WorldOffset},
+        storage::CHUNK_SIZE,
+    },
+};
+
+use crate::states::components::SessionScoped;
+
+use super::{chunks::ControlledPlayer, critters::CritterModel};
+
+// How many chunks the player can get from the render origin before everything gets moved back.
+// f32 is still good to well under a millimeter this close in
+pub const REBASE_DISTANCE: i32 = 8;
+
+pub fn needs_rebase(render_chunk: IVec3) -> bool {
+    render_chunk.abs().max_element() > REBASE_DISTANCE
+}
+
+// Everything drawn in render space. Chunks are roots, their transparent half is a child and
+// moves with them, so only roots get shifted
+#[derive(SystemParam)]
+pub struct RenderSpace<'w, 's> {
+    commands: Commands<'w, 's>,
+    offset: ResMut<'w, WorldOffset>,
+    roots: Query<
+        'w,
+        's,
+        (Entity, &'static mut Transform, Option<&'static ChunkPos>),
+        (With<SessionScoped>, Without<Parent>, Without<Node>),
+    >,
+    animated: Query<'w, 's, (), With<Animator<Transform>>>,
+    bodies: Query<'w, 's, &'static mut Aabb, With<Velocity>>,
+    critters: Query<'w, 's, &'static mut CritterModel>,
+}
+
+impl RenderSpace<'_, '_> {
+    pub fn offset(&self) -> WorldOffset {
+        *self.offset
+    }
+
+    pub fn needs_rebase(&self, world_chunk: IVec3) -> bool {
+        needs_rebase(world_chunk - **self.offset)
+    }
+
+    pub fn translation(&self, entity: Entity) -> Option<Vec3> {
+        self.roots
+            .get(entity)
+            .ok()
+            .map(|(_, transform, _)| transform.translation)
+    }
+
+    // Puts the render origin on `world_chunk`. Everything moves the other way by whole chunks so
+    // nothing visibly changes and voxel offsets inside a chunk stay exactly the same
+    pub fn rebase(&mut self, world_chunk: IVec3) {
+        let delta = world_chunk - **self.offset;
+        if delta == IVec3::ZERO {
+            return;
+        }
+        **self.offset = world_chunk;
+        let shift = -(delta * CHUNK_SIZE as i32).as_vec3();
+        for (entity, mut transform, chunk) in self.roots.iter_mut() {
+            match chunk {
+                // Lands where it would have finished rising
+                Some(chunk) => transform.translation = self.offset.chunk_to_render(**chunk),
+                None => transform.translation += shift,
+            }
+            // A tween in flight would drag it back to where it was
+            if self.animated.contains(entity) {
+                self.commands.entity(entity).remove::<Animator<Transform>>();
+            }
+        }
+        for mut aabb in self.bodies.iter_mut() {
+            aabb.center += Vec3A::from(shift);
+        }
+        for mut critter in self.critters.iter_mut() {
+            critter.last_translation += shift;
+        }
+    }
+
+    // Moves a physics body, the transform follows its AABB so both go
+    pub fn place_body(&mut self, entity: Entity, translation: Vec3) {
+        if let Ok(mut aabb) = self.bodies.get_mut(entity) {
+            aabb.center = Vec3A::from(translation) + Vec3A::Y * aabb.half_extents.y;
+        }
+        if let Ok((_, mut transform, _)) = self.roots.get_mut(entity) {
+            transform.translation = translation;
+        }
+    }
+}
+
+pub fn recenter_world(mut space: RenderSpace, player: Query<Entity, With<ControlledPlayer>>) {
+    let Ok(entity) = player.get_single() else {
+        return;
+    };
+    let Some(translation) = space.translation(entity) else {
+        return;
+    };
+    let chunk = space.offset().chunk_to_world(world_to_chunk(translation));
+    if space.needs_rebase(chunk) {
+        space.rebase(chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::DVec3;
+    use vinox_common::{
+        physics::collision::raycast::raycast_voxels,
+        world::chunks::positions::{global_voxel_positions, voxel_to_global_voxel},
+    };
+
+    use crate::states::game::input::player::{teleport_player, TeleportEvent};
+
+    fn spawn_player(app: &mut App, translation: Vec3) -> Entity {
+        let half_extents = Vec3A::new(0.4, 0.9, 0.4);
+        app.world
+            .spawn((
+                Transform::from_translation(translation),
+                Aabb {
+                    center: Vec3A::from(translation) + Vec3A::Y * half_extents.y,
+                    half_extents,
+                },
+                Velocity(Vec3::ZERO),
+                ControlledPlayer,
+                SessionScoped,
+            ))
+            .id()
+    }
+
+    #[test]
+    fn rebase_moves_everything_by_the_offset() {
+        let mut app = App::new();
+        app.init_resource::<WorldOffset>()
+            .add_system(recenter_world);
+        let player = spawn_player(&mut app, Vec3::new(150.3, 70.2, -5.5));
+        let chunk_pos = ChunkPos::new(9, 4, -1);
+        let chunk = app
+            .world
+            .spawn((
+                Transform::from_translation(chunk_pos.as_vec3() * CHUNK_SIZE as f32),
+                chunk_pos,
+                SessionScoped,
+            ))
+            .id();
+        let item = app
+            .world
+            .spawn((
+                Transform::from_translation(Vec3::new(131.9, 71.1, 2.25)),
+                SessionScoped,
+            ))
+            .id();
+        let child = app
+            .world
+            .spawn(Transform::from_translation(Vec3::new(0.0, 1.8, 0.0)))
+            .id();
+        app.world.entity_mut(player).add_child(child);
+        let translations = |app: &App| {
+            [player, chunk, item, child]
+                .map(|entity| app.world.get::<Transform>(entity).unwrap().translation)
+        };
+        let before = translations(&app);
+        let aabb_before = app.world.get::<Aabb>(player).unwrap().center;
+
+        app.update();
+        let offset = *app.world.resource::<WorldOffset>();
+        assert_eq!(offset, WorldOffset(IVec3::new(9, 4, -1)));
+        let after = translations(&app);
+        let shift = -offset.voxels().as_vec3();
+        for (before, after) in before.iter().zip(after).take(3) {
+            assert_eq!(after - *before, shift);
+        }
+        // Children move with their parent
+        assert_eq!(after[3], before[3]);
+        assert_eq!(
+            app.world.get::<Aabb>(player).unwrap().center - aabb_before,
+            Vec3A::from(shift)
+        );
+        // The world positions didn't change at all
+        assert_eq!(offset.to_world(after[0]), before[0].as_dvec3());
+        assert_eq!(after[1], Vec3::ZERO);
+
+        // Close to the new origin nothing happens
+        app.update();
+        assert_eq!(*app.world.resource::<WorldOffset>(), offset);
+        assert_eq!(translations(&app), after);
+    }
+
+    #[test]
+    fn places_exactly_ten_million_blocks_out() {
+        let mut app = App::new();
+        app.init_resource::<WorldOffset>()
+            .add_event::<TeleportEvent>()
+            .add_system(teleport_player);
+        let player = spawn_player(&mut app, Vec3::new(0.5, 70.0, 0.5));
+        app.world.send_event(TeleportEvent {
+            translation: DVec3::new(10_000_000.5, 70.0, 0.5),
+        });
+        app.update();
+
+        let offset = *app.world.resource::<WorldOffset>();
+        assert_eq!(offset, WorldOffset(IVec3::new(625_000, 4, 0)));
+        let translation = app.world.get::<Transform>(player).unwrap().translation;
+        assert_eq!(translation, Vec3::new(0.5, 6.0, 0.5));
+
+        // Looking down -x at a wall three blocks away, placing goes on its near face
+        let wall = IVec3::new(9_999_997, 71, 0);
+        let camera = translation + Vec3::new(0.0, 1.6, 0.0);
+        let (chunk_pos, voxel_pos, normal, _) =
+            raycast_voxels(offset.to_world(camera), Vec3::NEG_X, 50.0, |voxel| {
+                voxel == wall
+            })
+            .unwrap();
+        let hit_voxel = voxel_to_global_voxel(voxel_pos, *chunk_pos);
+        assert_eq!(hit_voxel, wall);
+        let (chunk_pos, voxel_pos) = global_voxel_positions(hit_voxel + normal.as_ivec3());
+        assert_eq!(
+            voxel_to_global_voxel(voxel_pos, chunk_pos),
+            IVec3::new(9_999_998, 71, 0)
+        );
+        // And the highlight is drawn right where that block is
+        assert_eq!(
+            offset.voxel_to_render(hit_voxel).as_vec3(),
+            Vec3::new(-3.0, 7.0, 0.0)
+        );
+    }
+}
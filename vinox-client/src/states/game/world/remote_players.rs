@@ -0,0 +1,314 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Align2, Color32, FontId, LayerId, Order},
+    EguiContexts,
+};
+use vinox_common::{
+    ecs::{bundles::ClientName, time::GameClock},
+    networking::protocol::{Player, PlayerFlags},
+};
+
+use crate::states::{
+    components::{GameSet, GameState},
+    crash::RecoverableSystem,
+    fonts::with_fallback_glyphs,
+    game::{input::player::FPSCamera, world::chunks::ControlledPlayer},
+};
+
+// Sneaking players sink this far, and their name only shows up close
+pub const SNEAK_DROP: f32 = 0.3;
+pub const SNEAK_NAMEPLATE_RANGE: f32 = 8.0;
+pub const NAMEPLATE_RANGE: f32 = 64.0;
+// Above the feet, clear of the head
+pub const NAMEPLATE_HEIGHT: f32 = 2.2;
+// How quickly a pose closes in on what the flags ask for, per second. A dropped update only
+// delays it instead of snapping
+pub const POSE_BLEND_RATE: f32 = 10.0;
+// Radians either side at a full stride, which is walking pace or faster
+pub const LIMB_SWING: f32 = 0.6;
+pub const FULL_STRIDE_SPEED: f32 = 5.0;
+// Swings a second walking and sprinting
+pub const WALK_STRIDE: f32 = 8.0;
+pub const SPRINT_STRIDE: f32 = 13.0;
+
+// The nodes in base_player.gltf that move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limb {
+    Body,
+    RightArm,
+    LeftArm,
+    RightLeg,
+    LeftLeg,
+}
+
+impl Limb {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "player" => Some(Limb::Body),
+            "right_arm" => Some(Limb::RightArm),
+            "left_arm" => Some(Limb::LeftArm),
+            "right_leg" => Some(Limb::RightLeg),
+            "left_leg" => Some(Limb::LeftLeg),
+            _ => None,
+        }
+    }
+}
+
+// Tagged once the scene has spawned, rest is how the model was exported
+#[derive(Component)]
+pub struct RigPart {
+    pub limb: Limb,
+    pub rest: Transform,
+}
+
+// Weights from 0 to 1 that follow the latest PlayerFlags
+#[derive(Component, Debug, Default, Clone)]
+pub struct RemotePose {
+    pub crouch: f32,
+    pub swim: f32,
+    pub sprint: f32,
+    // Fraction of a full stride, from how fast the model is actually moving
+    pub stride: f32,
+    pub phase: f32,
+    pub last_translation: Option<Vec3>,
+}
+
+fn weight(on: bool) -> f32 {
+    if on {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl RemotePose {
+    pub fn blend(&mut self, flags: PlayerFlags, speed: f32, delta: f32) {
+        let amount = 1.0 - (-POSE_BLEND_RATE * delta).exp();
+        let toward = |current: &mut f32, target: f32| *current += (target - *current) * amount;
+        toward(&mut self.crouch, weight(flags.sneaking()));
+        toward(&mut self.swim, weight(flags.swimming()));
+        toward(&mut self.sprint, weight(flags.sprinting()));
+        toward(&mut self.stride, (speed / FULL_STRIDE_SPEED).min(1.0));
+        let rate = WALK_STRIDE + (SPRINT_STRIDE - WALK_STRIDE) * self.sprint;
+        self.phase = (self.phase + rate * self.stride * delta) % std::f32::consts::TAU;
+    }
+
+    pub fn limb_transform(&self, limb: Limb, rest: Transform) -> Transform {
+        let swing = self.phase.sin() * LIMB_SWING * self.stride;
+        let turn = |angle: f32| rest.with_rotation(rest.rotation * Quat::from_rotation_x(angle));
+        match limb {
+            Limb::Body => Transform {
+                translation: rest.translation - Vec3::Y * SNEAK_DROP * self.crouch,
+                // Face down along where the player is looking
+                rotation: Quat::from_rotation_x(-FRAC_PI_2 * self.swim) * rest.rotation,
+                ..rest
+            },
+            Limb::RightArm | Limb::LeftLeg => turn(swing),
+            Limb::LeftArm | Limb::RightLeg => turn(-swing),
+        }
+    }
+}
+
+pub fn nameplate_visible(flags: PlayerFlags, distance: f32) -> bool {
+    let range = if flags.sneaking() {
+        SNEAK_NAMEPLATE_RANGE
+    } else {
+        NAMEPLATE_RANGE
+    };
+    distance <= range
+}
+
+pub fn tag_rig_parts(
+    mut commands: Commands,
+    named: Query<(Entity, &Name, &Transform), Added<Name>>,
+) {
+    for (entity, name, transform) in named.iter() {
+        if let Some(limb) = Limb::from_name(name.as_str()) {
+            commands.entity(entity).insert(RigPart {
+                limb,
+                rest: *transform,
+            });
+        }
+    }
+}
+
+pub fn animate_remote_players(
+    mut players: Query<(Entity, &mut RemotePose, &Transform, Option<&PlayerFlags>)>,
+    children: Query<&Children>,
+    mut parts: Query<(&RigPart, &mut Transform), Without<RemotePose>>,
+    clock: Res<GameClock>,
+) {
+    let delta = clock.delta_seconds();
+    if delta <= 0.0 {
+        return;
+    }
+    for (entity, mut pose, transform, flags) in players.iter_mut() {
+        let speed = pose.last_translation.map_or(0.0, |last| {
+            ((transform.translation - last) * Vec3::new(1.0, 0.0, 1.0)).length() / delta
+        });
+        pose.last_translation = Some(transform.translation);
+        pose.blend(flags.copied().unwrap_or_default(), speed, delta);
+        // The rig is a few levels down inside the scene
+        let mut below = vec![entity];
+        while let Some(next) = below.pop() {
+            if let Ok((rig, mut part_transform)) = parts.get_mut(next) {
+                *part_transform = pose.limb_transform(rig.limb, rig.rest);
+            }
+            if let Ok(next_children) = children.get(next) {
+                below.extend(next_children.iter());
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn nameplates_ui(
+    mut contexts: EguiContexts,
+    players: Query<
+        (&GlobalTransform, &ClientName, Option<&PlayerFlags>),
+        (With<Player>, Without<ControlledPlayer>),
+    >,
+    camera: Query<(&Camera, &GlobalTransform), With<FPSCamera>>,
+) {
+    let Ok((camera, camera_transform)) = camera.get_single() else {
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+    let height = ctx.screen_rect().height();
+    let painter = ctx.layer_painter(LayerId::new(Order::Background, egui::Id::new("nameplates")));
+    for (transform, name, flags) in players.iter() {
+        let feet = transform.translation();
+        let distance = feet.distance(camera_transform.translation());
+        if !nameplate_visible(flags.copied().unwrap_or_default(), distance) {
+            continue;
+        }
+        // Behind the camera comes back as None
+        let Some(at) =
+            camera.world_to_viewport(camera_transform, feet + Vec3::Y * NAMEPLATE_HEIGHT)
+        else {
+            continue;
+        };
+        let font_id = FontId::proportional(14.0);
+        let name = ctx.fonts(|fonts| {
+            with_fallback_glyphs(&name.0, |c| fonts.has_glyph(&font_id, c)).into_owned()
+        });
+        let galley = painter.layout_no_wrap(name, font_id, Color32::WHITE);
+        // The viewport counts up from the bottom, egui down from the top
+        let rect = Align2::CENTER_BOTTOM.anchor_rect(egui::Rect::from_min_size(
+            egui::pos2(at.x, height - at.y),
+            galley.size(),
+        ));
+        painter.rect_filled(rect.expand(2.0), 2.0, Color32::from_black_alpha(96));
+        painter.galley(rect.min, galley);
+    }
+}
+
+pub struct RemotePlayerPlugin;
+
+impl Plugin for RemotePlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            (tag_rig_parts, animate_remote_players)
+                .chain()
+                .in_set(GameSet::WorldUpdate)
+                .in_set(OnUpdate(GameState::Game)),
+        )
+        .add_system(
+            nameplates_ui
+                .recoverable(GameSet::Ui)
+                .in_set(GameSet::Ui)
+                .in_set(OnUpdate(GameState::Game)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sneaking() -> PlayerFlags {
+        PlayerFlags::from_bits(PlayerFlags::SNEAKING | PlayerFlags::ON_GROUND)
+    }
+
+    #[test]
+    fn sneaking_hides_the_name_past_eight_blocks() {
+        assert!(nameplate_visible(sneaking(), 0.0));
+        assert!(nameplate_visible(sneaking(), SNEAK_NAMEPLATE_RANGE));
+        assert!(!nameplate_visible(sneaking(), SNEAK_NAMEPLATE_RANGE + 0.1));
+        assert!(nameplate_visible(PlayerFlags::default(), 30.0));
+        assert!(!nameplate_visible(
+            PlayerFlags::default(),
+            NAMEPLATE_RANGE + 1.0
+        ));
+        // Bits from a newer client change nothing
+        let newer = PlayerFlags::from_bits(0b1000_0000);
+        assert!(nameplate_visible(newer, 30.0));
+    }
+
+    #[test]
+    fn poses_blend_instead_of_snapping() {
+        let mut pose = RemotePose::default();
+        pose.blend(sneaking(), 0.0, 1.0 / 60.0);
+        // Part of the way after a frame, not all of it
+        assert!(pose.crouch > 0.0 && pose.crouch < 0.5, "{}", pose.crouch);
+        for _ in 0..60 {
+            pose.blend(sneaking(), 0.0, 1.0 / 60.0);
+        }
+        assert!(pose.crouch > 0.99);
+        let rest = Transform::from_xyz(0.0, 0.125, 0.0);
+        let body = pose.limb_transform(Limb::Body, rest);
+        assert!((body.translation.y - (0.125 - SNEAK_DROP)).abs() < 0.01);
+
+        // An update without the sneak that gets dropped for a frame barely moves it
+        pose.blend(PlayerFlags::default(), 0.0, 1.0 / 60.0);
+        assert!(pose.crouch > 0.8);
+    }
+
+    #[test]
+    fn limbs_swing_opposite_and_rest_when_still() {
+        let mut pose = RemotePose::default();
+        for _ in 0..30 {
+            pose.blend(PlayerFlags::default(), FULL_STRIDE_SPEED, 1.0 / 60.0);
+        }
+        let rest = Transform::default();
+        let right_arm = pose.limb_transform(Limb::RightArm, rest).rotation;
+        let left_arm = pose.limb_transform(Limb::LeftArm, rest).rotation;
+        assert!(right_arm.angle_between(Quat::IDENTITY) > 0.01);
+        assert!(right_arm.angle_between(left_arm.inverse()) < 1e-4);
+        assert_eq!(pose.limb_transform(Limb::RightLeg, rest).rotation, left_arm);
+
+        // Sprinting goes through the swing faster
+        let mut sprinter = RemotePose::default();
+        let mut walker = RemotePose::default();
+        let sprinting = PlayerFlags::from_bits(PlayerFlags::SPRINTING);
+        for _ in 0..10 {
+            sprinter.blend(sprinting, FULL_STRIDE_SPEED, 1.0 / 60.0);
+            walker.blend(PlayerFlags::default(), FULL_STRIDE_SPEED, 1.0 / 60.0);
+        }
+        assert!(sprinter.phase > walker.phase);
+
+        for _ in 0..120 {
+            pose.blend(PlayerFlags::default(), 0.0, 1.0 / 60.0);
+        }
+        let still = pose.limb_transform(Limb::LeftLeg, rest).rotation;
+        assert!(still.angle_between(Quat::IDENTITY) < 0.01);
+    }
+
+    #[test]
+    fn swimming_lies_the_model_down() {
+        let mut pose = RemotePose::default();
+        for _ in 0..120 {
+            pose.blend(
+                PlayerFlags::from_bits(PlayerFlags::SWIMMING),
+                0.0,
+                1.0 / 60.0,
+            );
+        }
+        let body = pose.limb_transform(Limb::Body, Transform::default());
+        // The head end points along the ground
+        let up = body.rotation * Vec3::Y;
+        assert!(up.y.abs() < 0.01 && up.z < -0.99, "{up}");
+    }
+}
@@ -0,0 +1,807 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use vinox_common::{
+    ecs::gameplay::GameplayRules,
+    networking::protocol::ClientMessage,
+    world::chunks::{
+        ecs::ChunkManager,
+        positions::{global_voxel_positions, WorldOffset},
+        storage::{name_to_identifier, BlockData, BlockTable},
+    },
+};
+use zstd::stream::{read::Decoder, write::Encoder};
+
+use crate::states::{
+    components::{GameSet, GameState, ProjectPath},
+    game::{
+        networking::{
+            components::{Capabilities, ChatLine, ChatMessages},
+            connection::NetClient,
+            syncing::missing_block,
+        },
+        session::SessionApp,
+        world::chunks::ControlledPlayer,
+    },
+};
+
+pub const SCHEMATIC_MAGIC: &[u8; 4] = b"VSCH";
+pub const SCHEMATIC_VERSION: u32 = 1;
+pub const SCHEMATIC_EXTENSION: &str = "vschem";
+// Checked against the header before anything gets decompressed, so a hostile file can't make
+// us allocate more than a few megabytes
+pub const MAX_SCHEMATIC_EDGE: u32 = 256;
+pub const MAX_SCHEMATIC_VOLUME: u64 = 1 << 22;
+// Room for the palette on top of the indices
+const MAX_PALETTE_BYTES: u64 = 1 << 20;
+// Pastes trickle out at this many blocks a frame instead of flooding the connection
+pub const PASTE_PER_FRAME: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchematicError {
+    NotASchematic,
+    Version { found: u32 },
+    TooLarge { size: [u32; 3] },
+    Corrupt(String),
+    // Part of the selection isn't loaded, saving it would fill the gap with nothing
+    Unloaded,
+    Io(String),
+}
+
+impl fmt::Display for SchematicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchematicError::NotASchematic => write!(f, "not a schematic file"),
+            SchematicError::Version { found } => write!(
+                f,
+                "made by a different version of the game (schematic format {found}, this game reads {SCHEMATIC_VERSION})"
+            ),
+            SchematicError::TooLarge { size: [x, y, z] } => write!(
+                f,
+                "{x}x{y}x{z} is too large, schematics go up to {MAX_SCHEMATIC_EDGE} blocks a side and {MAX_SCHEMATIC_VOLUME} blocks in all"
+            ),
+            SchematicError::Corrupt(reason) => write!(f, "file is corrupt: {reason}"),
+            SchematicError::Unloaded => write!(f, "part of the selection isn't loaded"),
+            SchematicError::Io(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+fn corrupt(error: impl fmt::Display) -> SchematicError {
+    SchematicError::Corrupt(error.to_string())
+}
+
+fn check_size(size: [u32; 3]) -> Result<(), SchematicError> {
+    let volume = size.iter().map(|edge| *edge as u64).product::<u64>();
+    if size
+        .iter()
+        .any(|edge| *edge == 0 || *edge > MAX_SCHEMATIC_EDGE)
+        || volume > MAX_SCHEMATIC_VOLUME
+    {
+        return Err(SchematicError::TooLarge { size });
+    }
+    Ok(())
+}
+
+// Readable without decompressing anything, for listing
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchematicHeader {
+    pub version: u32,
+    pub size: [u32; 3],
+    // Everything but air
+    pub block_count: u64,
+}
+
+impl SchematicHeader {
+    // The header and where the compressed body starts
+    pub fn read(bytes: &[u8]) -> Result<(Self, usize), SchematicError> {
+        let rest = bytes
+            .strip_prefix(SCHEMATIC_MAGIC)
+            .ok_or(SchematicError::NotASchematic)?;
+        let mut cursor = Cursor::new(rest);
+        let header: SchematicHeader = bincode::deserialize_from(&mut cursor)
+            .map_err(|_| corrupt("the header is cut short"))?;
+        Ok((header, SCHEMATIC_MAGIC.len() + cursor.position() as usize))
+    }
+}
+
+fn is_air(block: &BlockData) -> bool {
+    block.has_identifier("vinox:air")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Schematic {
+    pub size: UVec3,
+    // Where whoever saved it stood from the lowest corner, pasting lines it up the same way
+    // around whoever pastes it
+    pub anchor: IVec3,
+    // The same block data chunks keep in their palettes
+    pub palette: Vec<BlockData>,
+    // x first, then z, then y
+    pub indices: Vec<u16>,
+}
+
+impl Schematic {
+    // Everything between the two corners, both included
+    pub fn capture(
+        corners: [IVec3; 2],
+        origin: IVec3,
+        get: impl Fn(IVec3) -> Option<BlockData>,
+    ) -> Result<Self, SchematicError> {
+        let min = corners[0].min(corners[1]);
+        let size = (corners[0].max(corners[1]) - min + IVec3::ONE).as_uvec3();
+        check_size(size.to_array())?;
+        let mut palette: Vec<BlockData> = Vec::new();
+        let mut lookup: HashMap<BlockData, u16> = HashMap::new();
+        let mut indices = Vec::with_capacity((size.x * size.y * size.z) as usize);
+        for y in 0..size.y {
+            for z in 0..size.z {
+                for x in 0..size.x {
+                    let mut block = get(min + UVec3::new(x, y, z).as_ivec3())
+                        .ok_or(SchematicError::Unloaded)?;
+                    // Chest contents and growth timers belong to this world, not to the build
+                    block.container = None;
+                    block.last_tick = None;
+                    let index = *lookup.entry(block.clone()).or_insert_with(|| {
+                        palette.push(block);
+                        palette.len() as u16 - 1
+                    });
+                    indices.push(index);
+                }
+            }
+        }
+        Ok(Self {
+            size,
+            anchor: origin - min,
+            palette,
+            indices,
+        })
+    }
+
+    pub fn block_count(&self) -> u64 {
+        self.indices
+            .iter()
+            .filter(|index| !is_air(&self.palette[**index as usize]))
+            .count() as u64
+    }
+
+    pub fn header(&self) -> SchematicHeader {
+        SchematicHeader {
+            version: SCHEMATIC_VERSION,
+            size: self.size.to_array(),
+            block_count: self.block_count(),
+        }
+    }
+
+    // Offsets from the lowest corner of everything that isn't air
+    pub fn blocks(&self) -> impl Iterator<Item = (IVec3, &BlockData)> {
+        let (width, depth) = (self.size.x as usize, self.size.z as usize);
+        self.indices
+            .iter()
+            .enumerate()
+            .map(move |(i, index)| {
+                let offset = IVec3::new(
+                    (i % width) as i32,
+                    (i / (width * depth)) as i32,
+                    (i / width % depth) as i32,
+                );
+                (offset, &self.palette[*index as usize])
+            })
+            .filter(|(_, block)| !is_air(block))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, SchematicError> {
+        let mut bytes = SCHEMATIC_MAGIC.to_vec();
+        bytes.extend(bincode::serialize(&self.header()).map_err(corrupt)?);
+        let body = bincode::serialize(self).map_err(corrupt)?;
+        // The checksum catches a damaged body that would otherwise still decompress
+        let mut encoder = Encoder::new(bytes, 0).map_err(corrupt)?;
+        encoder.include_checksum(true).map_err(corrupt)?;
+        encoder.write_all(&body).map_err(corrupt)?;
+        encoder.finish().map_err(corrupt)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, SchematicError> {
+        let (header, start) = SchematicHeader::read(bytes)?;
+        if header.version != SCHEMATIC_VERSION {
+            return Err(SchematicError::Version {
+                found: header.version,
+            });
+        }
+        check_size(header.size)?;
+        let volume = header.size.iter().map(|edge| *edge as u64).product::<u64>();
+        // Whatever the body claims, it never gets to decompress past what the header allows
+        let limit = volume * 2 + MAX_PALETTE_BYTES;
+        let mut body = Vec::new();
+        Decoder::new(&bytes[start..])
+            .map_err(corrupt)?
+            .take(limit + 1)
+            .read_to_end(&mut body)
+            .map_err(corrupt)?;
+        if body.len() as u64 > limit {
+            return Err(corrupt("the body is larger than its header says"));
+        }
+        let schematic: Schematic = bincode::deserialize(&body).map_err(corrupt)?;
+        if schematic.size.to_array() != header.size
+            || schematic.indices.len() as u64 != volume
+            || schematic
+                .indices
+                .iter()
+                .any(|index| *index as usize >= schematic.palette.len())
+        {
+            return Err(corrupt("the body doesn't match its header"));
+        }
+        if schematic.block_count() != header.block_count {
+            return Err(corrupt("the block count is wrong"));
+        }
+        Ok(schematic)
+    }
+
+    // Swaps in the placeholder for blocks this game doesn't have, and says which they were
+    pub fn replace_unknown(
+        &mut self,
+        block_table: &BlockTable,
+        placeholder: &BlockData,
+    ) -> Vec<String> {
+        let mut unknown = Vec::new();
+        for block in self.palette.iter_mut() {
+            let identifier = name_to_identifier(block.namespace.clone(), block.name.clone());
+            if !block_table.contains_key(&identifier) {
+                *block = placeholder.clone();
+                unknown.push(identifier);
+            }
+        }
+        unknown.sort();
+        unknown.dedup();
+        unknown
+    }
+}
+
+// Where each block of a paste lands with its lowest corner at min. Blocks past the build limits
+// are left out the same way a placement there would be refused, along with how many that was
+pub fn paste_blocks(
+    schematic: &Schematic,
+    min: IVec3,
+    rules: &GameplayRules,
+) -> (Vec<(IVec3, BlockData)>, usize) {
+    let mut left_out = 0;
+    let blocks = schematic
+        .blocks()
+        .map(|(offset, block)| (min + offset, block.clone()))
+        .filter(|(voxel, _)| {
+            let within = rules.within_build_limits(*voxel);
+            left_out += !within as usize;
+            within
+        })
+        .collect();
+    (blocks, left_out)
+}
+
+// Letters, digits, - and _, so a name can never reach outside the schematics folder
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn schematic_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.{SCHEMATIC_EXTENSION}"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemCommand {
+    // None is wherever the player is standing
+    Corner(usize, Option<IVec3>),
+    Save(String),
+    Load(String),
+    List,
+    Paste,
+    Stats,
+}
+
+pub struct SchemEvent(pub SchemCommand);
+
+const USAGE: &str = "Usage: /schem pos1|pos2 [x y z], /schem save|load <name>, /schem list, /schem paste or /schem stats";
+
+// None when the line isn't a /schem at all, otherwise the command or what was wrong with it
+pub fn parse_schem(line: &str) -> Option<Result<SchemCommand, String>> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("/schem") {
+        return None;
+    }
+    let args: Vec<&str> = words.collect();
+    Some(match args.as_slice() {
+        [corner @ ("pos1" | "pos2"), rest @ ..] => {
+            let corner = (*corner == "pos2") as usize;
+            match rest {
+                [] => Ok(SchemCommand::Corner(corner, None)),
+                [x, y, z] => match (x.parse(), y.parse(), z.parse()) {
+                    (Ok(x), Ok(y), Ok(z)) => {
+                        Ok(SchemCommand::Corner(corner, Some(IVec3::new(x, y, z))))
+                    }
+                    _ => Err(USAGE.to_string()),
+                },
+                _ => Err(USAGE.to_string()),
+            }
+        }
+        [command @ ("save" | "load"), name] => {
+            if !valid_name(name) {
+                Err("Schematic names are letters, digits, - and _".to_string())
+            } else if *command == "save" {
+                Ok(SchemCommand::Save(name.to_string()))
+            } else {
+                Ok(SchemCommand::Load(name.to_string()))
+            }
+        }
+        ["list"] => Ok(SchemCommand::List),
+        ["paste"] => Ok(SchemCommand::Paste),
+        ["stats"] => Ok(SchemCommand::Stats),
+        _ => Err(USAGE.to_string()),
+    })
+}
+
+// The two corners of the box /schem save and /schem stats work on
+#[derive(Resource, Debug, Default)]
+pub struct SchematicSelection(pub [Option<IVec3>; 2]);
+
+impl SchematicSelection {
+    pub fn corners(&self) -> Option<[IVec3; 2]> {
+        Some([self.0[0]?, self.0[1]?])
+    }
+}
+
+// The last schematic loaded, what /schem paste puts down
+#[derive(Resource, Debug, Default)]
+pub struct Clipboard(pub Option<Schematic>);
+
+// Blocks a paste still has to send, in world space
+#[derive(Resource, Debug, Default)]
+pub struct PasteQueue(pub VecDeque<(IVec3, BlockData)>);
+
+fn list_schematics(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut lines: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == SCHEMATIC_EXTENSION)
+        })
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().to_string();
+            let bytes = fs::read(&path).ok()?;
+            let kib = bytes.len() as f32 / 1024.0;
+            Some(match SchematicHeader::read(&bytes) {
+                Ok((header, _)) if header.version == SCHEMATIC_VERSION => {
+                    let [x, y, z] = header.size;
+                    format!(
+                        "{name}: {x}x{y}x{z}, {} blocks, {kib:.1} KiB",
+                        header.block_count
+                    )
+                }
+                Ok((header, _)) => format!(
+                    "{name}: {}, {kib:.1} KiB",
+                    SchematicError::Version {
+                        found: header.version
+                    }
+                ),
+                Err(e) => format!("{name}: {e}"),
+            })
+        })
+        .collect();
+    lines.sort();
+    lines
+}
+
+fn schematics_dir(project_path: &ProjectPath) -> PathBuf {
+    project_path
+        .0
+        .parent()
+        .unwrap_or(&project_path.0)
+        .join("schematics")
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn handle_schem(
+    mut events: EventReader<SchemEvent>,
+    mut messages: ResMut<ChatMessages>,
+    (mut selection, mut clipboard, mut paste): (
+        ResMut<SchematicSelection>,
+        ResMut<Clipboard>,
+        ResMut<PasteQueue>,
+    ),
+    player: Query<&Transform, With<ControlledPlayer>>,
+    (offset, rules, capabilities): (Res<WorldOffset>, Res<GameplayRules>, Res<Capabilities>),
+    chunk_manager: ChunkManager,
+    project_path: Res<ProjectPath>,
+) {
+    let dir = schematics_dir(&project_path);
+    let standing = player
+        .get_single()
+        .ok()
+        .map(|transform| offset.to_world(transform.translation).floor().as_ivec3());
+    for SchemEvent(command) in events.iter() {
+        let reply = match command {
+            SchemCommand::Corner(corner, at) => match at.or(standing) {
+                Some(voxel) => {
+                    selection.0[*corner] = Some(voxel);
+                    format!(
+                        "Corner {} is at {} {} {}",
+                        corner + 1,
+                        voxel.x,
+                        voxel.y,
+                        voxel.z
+                    )
+                }
+                None => "Nowhere to put the corner yet".to_string(),
+            },
+            SchemCommand::Stats | SchemCommand::Save(_) => {
+                let (Some(corners), Some(standing)) = (selection.corners(), standing) else {
+                    messages.push(ChatLine::console(
+                        "Set both corners with /schem pos1 and /schem pos2 first",
+                    ));
+                    continue;
+                };
+                match Schematic::capture(corners, standing, |voxel| chunk_manager.get_block(voxel))
+                {
+                    Err(e) => format!("Couldn't read the selection: {e}"),
+                    Ok(schematic) => match command {
+                        SchemCommand::Save(name) => {
+                            let path = schematic_path(&dir, name);
+                            match schematic.encode().and_then(|bytes| {
+                                fs::create_dir_all(&dir)
+                                    .and_then(|_| fs::write(&path, &bytes))
+                                    .map_err(|e| SchematicError::Io(e.to_string()))
+                                    .map(|_| bytes.len())
+                            }) {
+                                Ok(len) => format!(
+                                    "Saved {} blocks to {name} ({:.1} KiB)",
+                                    schematic.block_count(),
+                                    len as f32 / 1024.0
+                                ),
+                                Err(e) => format!("Couldn't save {name}: {e}"),
+                            }
+                        }
+                        _ => {
+                            let mut counts: HashMap<String, u64> = HashMap::new();
+                            for (_, block) in schematic.blocks() {
+                                *counts
+                                    .entry(name_to_identifier(
+                                        block.namespace.clone(),
+                                        block.name.clone(),
+                                    ))
+                                    .or_default() += 1;
+                            }
+                            let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+                            counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                            let top: Vec<String> = counts
+                                .iter()
+                                .take(5)
+                                .map(|(identifier, count)| format!("{identifier} {count}"))
+                                .collect();
+                            let size = schematic.size;
+                            format!(
+                                "{}x{}x{}, {} blocks: {}",
+                                size.x,
+                                size.y,
+                                size.z,
+                                schematic.block_count(),
+                                top.join(", ")
+                            )
+                        }
+                    },
+                }
+            }
+            SchemCommand::Load(name) => {
+                let loaded = fs::read(schematic_path(&dir, name))
+                    .map_err(|e| SchematicError::Io(e.to_string()))
+                    .and_then(|bytes| Schematic::decode(&bytes));
+                match loaded {
+                    Ok(mut schematic) => {
+                        let unknown =
+                            schematic.replace_unknown(&chunk_manager.block_table, &missing_block());
+                        let size = schematic.size;
+                        let mut reply = format!(
+                            "Loaded {name}, {}x{}x{}, paste it with /schem paste",
+                            size.x, size.y, size.z
+                        );
+                        if !unknown.is_empty() {
+                            reply.push_str(&format!(
+                                ". Missing blocks stand in for {}",
+                                unknown.join(", ")
+                            ));
+                        }
+                        clipboard.0 = Some(schematic);
+                        reply
+                    }
+                    Err(e) => format!("Couldn't load {name}: {e}"),
+                }
+            }
+            SchemCommand::List => {
+                let lines = list_schematics(&dir);
+                if lines.is_empty() {
+                    "No schematics saved yet".to_string()
+                } else {
+                    lines.join("\n")
+                }
+            }
+            // The server only takes pasted blocks from operators, so don't queue up a refusal
+            SchemCommand::Paste if !capabilities.creative => {
+                "Only creative players can paste schematics".to_string()
+            }
+            SchemCommand::Paste => match (&clipboard.0, standing) {
+                (Some(schematic), Some(standing)) => {
+                    let (blocks, left_out) =
+                        paste_blocks(schematic, standing - schematic.anchor, &rules);
+                    let count = blocks.len();
+                    paste.0.extend(blocks);
+                    if left_out > 0 {
+                        format!("Pasting {count} blocks, {left_out} past the build limit left out")
+                    } else {
+                        format!("Pasting {count} blocks")
+                    }
+                }
+                (None, _) => "Nothing to paste, load a schematic first".to_string(),
+                (_, None) => "Nowhere to paste yet".to_string(),
+            },
+        };
+        messages.push(ChatLine::console(reply));
+    }
+}
+
+// Goes out as templates, nothing in the inventory pays for them so the server checks each one
+// like a copied block
+pub fn send_paste(mut paste: ResMut<PasteQueue>, mut client: NetClient) {
+    let count = paste.0.len().min(PASTE_PER_FRAME);
+    for (voxel, block) in paste.0.drain(..count) {
+        let (chunk_pos, voxel_pos) = global_voxel_positions(voxel);
+        client.send(ClientMessage::PlaceTemplate {
+            chunk_pos,
+            voxel_pos: [voxel_pos.x as u8, voxel_pos.y as u8, voxel_pos.z as u8],
+            block,
+        });
+    }
+}
+
+pub struct SchematicPlugin;
+
+impl Plugin for SchematicPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SchematicSelection>()
+            .init_resource::<Clipboard>()
+            .init_resource::<PasteQueue>()
+            .reset_on_exit::<SchematicSelection>()
+            .reset_on_exit::<PasteQueue>()
+            .add_event::<SchemEvent>()
+            .add_systems(
+                (handle_schem, send_paste)
+                    .chain()
+                    .in_set(GameSet::WorldUpdate)
+                    .in_set(OnUpdate(GameState::Game)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::{
+        testing::{self, block, descriptor},
+        world::chunks::storage::VoxelVisibility,
+    };
+
+    // A 5x4x3 box with a bit of everything in it
+    fn sample() -> Schematic {
+        let world = |voxel: IVec3| {
+            Some(match (voxel.x + voxel.y * 3 + voxel.z * 7) % 4 {
+                0 => block("air"),
+                1 => block("stone"),
+                2 => {
+                    let mut log = block("log");
+                    log.top = Some(true);
+                    log
+                }
+                _ => block("glass"),
+            })
+        };
+        Schematic::capture(
+            [IVec3::new(14, 60, -3), IVec3::new(10, 63, -1)],
+            IVec3::new(12, 60, 0),
+            world,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trips_voxel_for_voxel() {
+        let schematic = sample();
+        assert_eq!(schematic.size, UVec3::new(5, 4, 3));
+        assert_eq!(schematic.anchor, IVec3::new(2, 0, 3));
+        let decoded = Schematic::decode(&schematic.encode().unwrap()).unwrap();
+        assert_eq!(decoded, schematic);
+        let before: Vec<_> = schematic.blocks().collect();
+        let after: Vec<_> = decoded.blocks().collect();
+        assert_eq!(before, after);
+        assert_eq!(after.len() as u64, decoded.header().block_count);
+        // Offsets come back in world space the way they went in
+        let (offset, log) = after.iter().find(|(_, block)| block.name == "log").unwrap();
+        let voxel = IVec3::new(10, 60, -3) + *offset;
+        assert_eq!((voxel.x + voxel.y * 3 + voxel.z * 7) % 4, 2);
+        assert_eq!(log.top, Some(true));
+
+        // Saving across something unloaded fails instead of leaving holes
+        assert_eq!(
+            Schematic::capture([IVec3::ZERO, IVec3::ONE], IVec3::ZERO, |voxel| {
+                (voxel != IVec3::ONE).then(|| block("stone"))
+            }),
+            Err(SchematicError::Unloaded)
+        );
+    }
+
+    #[test]
+    fn pastes_stop_at_the_build_limit() {
+        let schematic = sample();
+        let rules = GameplayRules {
+            max_build_y: 64.0,
+            ..Default::default()
+        };
+        // The top layer lands one above the limit, the one under it right on it
+        let min = IVec3::new(0, 61, 0);
+        let (blocks, left_out) = paste_blocks(&schematic, min, &rules);
+        let top = schematic
+            .blocks()
+            .filter(|(offset, _)| offset.y == 3)
+            .count();
+        assert!(top > 0);
+        assert_eq!(left_out, top);
+        assert_eq!(blocks.len() + left_out, schematic.block_count() as usize);
+        assert!(blocks.iter().any(|(voxel, _)| voxel.y == 64));
+        assert!(blocks
+            .iter()
+            .all(|(voxel, _)| rules.within_build_limits(*voxel)));
+
+        let (blocks, left_out) = paste_blocks(&schematic, min - IVec3::Y, &rules);
+        assert_eq!(
+            (blocks.len(), left_out),
+            (schematic.block_count() as usize, 0)
+        );
+    }
+
+    #[test]
+    fn corrupted_files_are_rejected() {
+        let bytes = sample().encode().unwrap();
+        assert_eq!(
+            Schematic::decode(b"not a schematic"),
+            Err(SchematicError::NotASchematic)
+        );
+        assert!(matches!(
+            Schematic::decode(&bytes[..bytes.len() / 2]),
+            Err(SchematicError::Corrupt(_))
+        ));
+        assert!(matches!(
+            Schematic::decode(&bytes[..6]),
+            Err(SchematicError::Corrupt(_))
+        ));
+        let mut flipped = bytes.clone();
+        let last = flipped.len() - 8;
+        flipped[last] ^= 0xff;
+        assert!(Schematic::decode(&flipped).is_err());
+
+        // A header promising a huge box is turned away before decompressing anything
+        let (_, start) = SchematicHeader::read(&bytes).unwrap();
+        let mut huge = SCHEMATIC_MAGIC.to_vec();
+        huge.extend(
+            bincode::serialize(&SchematicHeader {
+                version: SCHEMATIC_VERSION,
+                size: [4096, 4096, 4096],
+                block_count: 0,
+            })
+            .unwrap(),
+        );
+        huge.extend(&bytes[start..]);
+        assert_eq!(
+            Schematic::decode(&huge),
+            Err(SchematicError::TooLarge {
+                size: [4096, 4096, 4096]
+            })
+        );
+        // And a body that doesn't match the header it came with
+        let mut lying = SCHEMATIC_MAGIC.to_vec();
+        lying.extend(
+            bincode::serialize(&SchematicHeader {
+                size: [2, 2, 2],
+                ..sample().header()
+            })
+            .unwrap(),
+        );
+        lying.extend(&bytes[start..]);
+        assert!(matches!(
+            Schematic::decode(&lying),
+            Err(SchematicError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn other_versions_say_so() {
+        let bytes = sample().encode().unwrap();
+        let (_, start) = SchematicHeader::read(&bytes).unwrap();
+        let mut newer = SCHEMATIC_MAGIC.to_vec();
+        newer.extend(
+            bincode::serialize(&SchematicHeader {
+                version: SCHEMATIC_VERSION + 1,
+                ..sample().header()
+            })
+            .unwrap(),
+        );
+        newer.extend(&bytes[start..]);
+        let error = Schematic::decode(&newer).unwrap_err();
+        assert_eq!(
+            error,
+            SchematicError::Version {
+                found: SCHEMATIC_VERSION + 1
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "made by a different version of the game (schematic format {}, this game reads {SCHEMATIC_VERSION})",
+                SCHEMATIC_VERSION + 1
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_blocks_become_placeholders() {
+        let block_table = testing::block_table([
+            descriptor("air", VoxelVisibility::Empty),
+            descriptor("stone", VoxelVisibility::Opaque),
+            descriptor("log", VoxelVisibility::Opaque),
+        ]);
+        let mut schematic = sample();
+        let glass = schematic
+            .blocks()
+            .filter(|(_, b)| b.name == "glass")
+            .count();
+        let unknown = schematic.replace_unknown(&block_table, &missing_block());
+        assert_eq!(unknown, vec!["vinox:glass".to_string()]);
+        assert_eq!(
+            schematic
+                .blocks()
+                .filter(|(_, b)| **b == missing_block())
+                .count(),
+            glass
+        );
+    }
+
+    #[test]
+    fn commands_parse() {
+        assert_eq!(parse_schem("/find stone"), None);
+        assert_eq!(
+            parse_schem("/schem pos2 1 -2 3"),
+            Some(Ok(SchemCommand::Corner(1, Some(IVec3::new(1, -2, 3)))))
+        );
+        assert_eq!(
+            parse_schem("/schem pos1"),
+            Some(Ok(SchemCommand::Corner(0, None)))
+        );
+        assert_eq!(
+            parse_schem("/schem save tower_2"),
+            Some(Ok(SchemCommand::Save("tower_2".to_string())))
+        );
+        assert!(matches!(
+            parse_schem("/schem load ../../config"),
+            Some(Err(_))
+        ));
+        assert!(matches!(parse_schem("/schem"), Some(Err(_))));
+    }
+}
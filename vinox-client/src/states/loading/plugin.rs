@@ -1,13 +1,20 @@
 use bevy::prelude::*;
-use vinox_common::world::chunks::storage::{BlockTable, ItemTable, RecipeTable};
+use vinox_common::world::chunks::storage::{BiomeTable, BlockTable, ItemTable, RecipeTable};
 
 use crate::states::{
     assets::load::LoadableAssets,
     components::{despawn_with, GameState, Loading},
-    game::{networking::components::ClientData, rendering::meshing::GeometryTable},
+    game::{
+        networking::{
+            components::ClientData,
+            handshake::{start_connection, watch_connection},
+        },
+        rendering::meshing::GeometryTable,
+        session::SessionApp,
+    },
 };
 
-use super::ui::{load_blocks, new_client, setup_resources, switch, timeout, AssetsLoading};
+use super::ui::{load_blocks, loading_screen, setup_resources, switch, AssetsLoading};
 
 pub struct LoadingPlugin;
 
@@ -16,16 +23,25 @@ impl Plugin for LoadingPlugin {
         app.insert_resource(ClientData::default())
             .insert_resource(GeometryTable::default())
             .insert_resource(BlockTable::default())
+            .insert_resource(BiomeTable::default())
             .insert_resource(RecipeTable::default())
             .insert_resource(ItemTable::default())
             .insert_resource(LoadableAssets::default())
             .insert_resource(AssetsLoading::default())
+            // Loading fills these again for the next connection
+            .reset_on_exit::<LoadableAssets>()
+            .reset_on_exit::<AssetsLoading>()
             .add_systems(
-                (setup_resources, new_client)
+                // The content manifest has to be in place for a replay to check against
+                (setup_resources, apply_system_buffers, start_connection)
                     .chain()
                     .in_schedule(OnEnter(GameState::Loading)),
             )
-            .add_systems((load_blocks, switch, timeout).in_set(OnUpdate(GameState::Loading)))
+            .add_systems(
+                (load_blocks, watch_connection, switch, loading_screen)
+                    .chain()
+                    .in_set(OnUpdate(GameState::Loading)),
+            )
             .add_system(despawn_with::<Loading>.in_schedule(OnExit(GameState::Loading)));
     }
 }
@@ -1,48 +1,55 @@
-use bevy::{asset::LoadState, math::Vec3A, prelude::*, render::primitives::Aabb};
-use bevy_egui::EguiUserTextures;
-use bevy_quinnet::client::{
-    certificate::CertificateVerificationMode,
-    connection::{ConnectionConfiguration, ConnectionEvent},
-    Client,
+use bevy::{
+    asset::LoadState,
+    math::Vec3A,
+    prelude::*,
+    render::{
+        primitives::Aabb,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
 };
-use std::time::Duration;
+use bevy_egui::{egui, EguiContexts, EguiUserTextures};
+use bevy_quinnet::client::Client;
 use vinox_common::{
     ecs::bundles::PlayerBundleBuilder,
     networking::protocol::NetworkIP,
     storage::{
+        biomes::load::load_all_biomes,
         blocks::load::load_all_blocks,
+        content::ContentManifest,
         crafting::load::load_all_recipes,
         geometry::load::load_all_geo,
         items::load::{item_from_block, load_all_items},
     },
-    world::chunks::storage::{trim_geo_identifier, BlockTable, ItemTable, RecipeTable},
+    world::chunks::{
+        occlusion::LightOcclusion,
+        storage::{
+            name_to_identifier, trim_geo_identifier, BiomeTable, BlockTable, ItemTable,
+            RecipeTable, UNKNOWN_BLOCK,
+        },
+    },
 };
 
 use crate::states::{
-    assets::load::LoadableAssets, components::GameState, game::rendering::meshing::GeometryTable,
+    assets::load::LoadableAssets,
+    components::GameState,
+    game::{
+        networking::{
+            components::{ClientData, ConnectionPhase},
+            handshake::connect,
+            replay::ReplayPlayback,
+        },
+        rendering::{
+            icons::ItemIconCache,
+            memory::{image_bytes, MemoryBudget},
+            meshing::GeometryTable,
+        },
+        ui::hud::HUD_ICONS,
+    },
 };
 
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct AssetsLoading(pub Vec<HandleUntyped>);
 
-//TODO: Right now we are building the client only as a multiplayer client. This is fine but eventually we need to have singleplayer.
-// To achieve this we will just have the client start up a server. But for now I am just going to use a dedicated one for testing
-pub fn new_client(ip_res: Res<NetworkIP>, mut client: ResMut<Client>) {
-    let ip = if ip_res.0 == "localhost" {
-        "127.0.0.1".to_string()
-    } else {
-        ip_res.0.clone()
-    }
-    .parse()
-    .unwrap();
-    client
-        .open_connection(
-            ConnectionConfiguration::from_ips(ip, 25565, "0.0.0.0".to_string().parse().unwrap(), 0),
-            CertificateVerificationMode::SkipVerification,
-        )
-        .unwrap();
-}
-
 #[allow(clippy::too_many_arguments)]
 pub fn switch(
     mut commands: Commands,
@@ -51,34 +58,58 @@ pub fn switch(
     mut loadable_assets: ResMut<LoadableAssets>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut textures: ResMut<Assets<Image>>,
-    mut client: ResMut<Client>,
-    mut connected_event: EventReader<ConnectionEvent>,
+    phase: Res<ConnectionPhase>,
+    icon_cache: Res<ItemIconCache>,
+    mut budget: ResMut<MemoryBudget>,
 ) {
     match asset_server.get_group_load_state(loading.iter().map(|h| h.id())) {
         LoadState::Failed => {
             commands.insert_resource(NextState(Some(GameState::Menu)));
         }
-        LoadState::Loaded => {
-            for _ in connected_event.iter() {
-                client.connection_mut().set_default_channel(
-                    bevy_quinnet::shared::channel::ChannelId::UnorderedReliable,
-                );
-
-                let mut texture_atlas_builder = TextureAtlasBuilder::default();
-                for handle in loadable_assets.block_textures.values() {
-                    for item in handle {
-                        let Some(texture) = textures.get(item) else {
-            warn!("{:?} did not resolve to an `Image` asset.", asset_server.get_handle_path(item));
-            continue;
+        LoadState::Loaded if *phase == ConnectionPhase::Joined => {
+            // Item icons are baked from the atlas, hang around until they're done too
+            if texture_atlases.get(&loadable_assets.block_atlas).is_some() {
+                if icon_cache.is_baked() {
+                    commands.insert_resource(NextState(Some(GameState::Game)));
+                }
+                return;
+            }
+            let mut texture_atlas_builder = TextureAtlasBuilder::default();
+            for handle in loadable_assets.block_textures.values() {
+                for item in handle {
+                    let Some(texture) = textures.get(item) else {
+                        warn!("{:?} did not resolve to an `Image` asset.", asset_server.get_handle_path(item));
+                        continue;
                     };
-                        texture_atlas_builder.add_texture(item.clone(), texture);
-                    }
+                    texture_atlas_builder.add_texture(item.clone(), texture);
                 }
-                let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
-                let atlas_handle = texture_atlases.add(texture_atlas);
-                loadable_assets.block_atlas = atlas_handle;
-                commands.insert_resource(NextState(Some(GameState::Game)));
             }
+            for handle in loadable_assets.decoration_textures.values() {
+                let Some(texture) = textures.get(handle) else {
+                    warn!(
+                        "{:?} did not resolve to an `Image` asset.",
+                        asset_server.get_handle_path(handle)
+                    );
+                    continue;
+                };
+                texture_atlas_builder.add_texture(handle.clone(), texture);
+            }
+            let texture_atlas = texture_atlas_builder.finish(&mut textures).unwrap();
+            // The sources stay loaded next to the atlas built out of them
+            let sources: u64 = loadable_assets
+                .block_textures
+                .values()
+                .flatten()
+                .filter_map(|handle| textures.get(handle))
+                .map(image_bytes)
+                .sum();
+            let atlas = textures
+                .get(&texture_atlas.texture)
+                .map(image_bytes)
+                .unwrap_or_default();
+            budget.set_textures(sources + atlas);
+            let atlas_handle = texture_atlases.add(texture_atlas);
+            loadable_assets.block_atlas = atlas_handle;
         }
         _ => {
             // NotLoaded/Loading: not fully ready yet
@@ -86,20 +117,67 @@ pub fn switch(
     }
 }
 
-pub fn timeout(
+// Says which half of loading is still going, or why connecting failed with a way out
+#[allow(clippy::too_many_arguments)]
+pub fn loading_screen(
     mut commands: Commands,
-    mut timer: Local<Timer>,
-    time: Res<Time>,
+    mut contexts: EguiContexts,
+    loading: Res<AssetsLoading>,
+    asset_server: Res<AssetServer>,
+    mut phase: ResMut<ConnectionPhase>,
     mut client: ResMut<Client>,
+    mut client_data: ResMut<ClientData>,
+    (ip, time, icon_cache): (Res<NetworkIP>, Res<Time>, Res<ItemIconCache>),
+    playback: Option<Res<ReplayPlayback>>,
 ) {
-    timer.set_mode(TimerMode::Repeating);
-    timer.set_duration(Duration::from_secs_f32(10.));
-
-    timer.tick(time.delta());
-    if timer.just_finished() {
-        client.close_all_connections().ok();
-        commands.insert_resource(NextState(Some(GameState::Menu)));
-    }
+    let loaded = loading
+        .iter()
+        .filter(|handle| asset_server.get_load_state(handle.id()) == LoadState::Loaded)
+        .count();
+    egui::Window::new("Loading")
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            if let ConnectionPhase::Failed(failure) = &*phase {
+                match &playback {
+                    Some(playback) => {
+                        ui.heading(format!("Couldn't play {}", playback.path.display()))
+                    }
+                    None => ui.heading(format!("Couldn't join {}", ip.0)),
+                };
+                ui.colored_label(egui::Color32::from_rgb(243, 139, 168), failure.reason());
+                ui.horizontal(|ui| {
+                    // Trying again won't make the replay's content any different
+                    if playback.is_none() && ui.button("Retry").clicked() {
+                        **client_data = 0;
+                        *phase = connect(&ip, &mut client, time.raw_elapsed());
+                    }
+                    if ui.button("Back").clicked() {
+                        client.close_all_connections().ok();
+                        *phase = ConnectionPhase::Idle;
+                        commands.remove_resource::<ReplayPlayback>();
+                        commands.insert_resource(NextState(Some(GameState::Menu)));
+                    }
+                });
+                return;
+            }
+            let connection = match *phase {
+                ConnectionPhase::Idle | ConnectionPhase::Connecting { .. } => "Connecting…",
+                ConnectionPhase::Authenticating { .. } => "Authenticating…",
+                _ => "Joined",
+            };
+            ui.label(connection);
+            if loaded < loading.len() {
+                ui.label(format!("Loading assets… {loaded}/{}", loading.len()));
+            } else if *phase == ConnectionPhase::Joined && !icon_cache.is_baked() {
+                let (baked, total) = icon_cache.progress();
+                ui.label(format!("Baking item icons… {baked}/{total}"));
+            } else {
+                ui.label("Assets loaded");
+            }
+        });
 }
 
 pub fn setup_resources(
@@ -109,7 +187,7 @@ pub fn setup_resources(
     mut block_table: ResMut<BlockTable>,
     mut item_table: ResMut<ItemTable>,
     mut recipe_table: ResMut<RecipeTable>,
-    mut geo_table: ResMut<GeometryTable>,
+    (mut geo_table, mut biome_table): (ResMut<GeometryTable>, ResMut<BiomeTable>),
     mut loadable_assets: ResMut<LoadableAssets>,
     mut egui_textures: ResMut<EguiUserTextures>,
 ) {
@@ -141,12 +219,21 @@ pub fn setup_resources(
         name.push_str(&recipe.name);
         recipe_table.insert(name, recipe);
     }
-    for geo in load_all_geo() {
+    // Only for how they look, the server says which biome each column is in
+    for biome in load_all_biomes() {
+        biome_table.insert(
+            name_to_identifier(biome.namespace.clone(), biome.name.clone()),
+            biome,
+        );
+    }
+    let geometry = load_all_geo();
+    for geo in geometry.iter() {
         let mut name = geo.clone().namespace;
         name.push(':');
         name.push_str(&geo.name);
-        geo_table.insert(name, geo);
+        geo_table.insert(name, geo.clone());
     }
+    commands.insert_resource(LightOcclusion::new(&block_table, &geometry));
     for item in load_all_items() {
         let mut name = item.clone().namespace;
         name.push(':');
@@ -154,6 +241,13 @@ pub fn setup_resources(
 
         item_table.insert(name, item);
     }
+    // Checked against the server's as soon as it takes our join
+    commands.insert_resource(ContentManifest::new(
+        &block_table,
+        &item_table,
+        &recipe_table,
+        &geometry,
+    ));
 
     for item in item_table.values() {
         let mut name = item.clone().namespace;
@@ -179,6 +273,14 @@ pub fn setup_resources(
     for item_texture in loadable_assets.item_textures.values() {
         egui_textures.add_image(item_texture.clone_weak());
     }
+    for icon in HUD_ICONS {
+        let texture_handle: Handle<Image> = asset_server.load(format!("hud/{icon}.png"));
+        loading.push(texture_handle.clone_untyped());
+        egui_textures.add_image(texture_handle.clone_weak());
+        loadable_assets
+            .hud_textures
+            .insert(icon.to_string(), texture_handle);
+    }
 }
 
 pub fn load_blocks(
@@ -186,9 +288,24 @@ pub fn load_blocks(
     mut loading: ResMut<AssetsLoading>,
     block_table: Res<BlockTable>,
     mut loadable_assets: ResMut<LoadableAssets>,
-    mut has_ran: Local<bool>,
+    mut images: ResMut<Assets<Image>>,
 ) {
-    if !(*has_ran) && block_table.is_changed() {
+    if loadable_assets.block_textures.is_empty() && block_table.is_changed() {
+        // Made here rather than loaded, so it's there even when the assets folder isn't complete
+        let magenta = images.add(Image::new_fill(
+            Extent3d {
+                width: 16,
+                height: 16,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[255, 0, 255, 255],
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+        loadable_assets.block_textures.insert(
+            name_to_identifier(UNKNOWN_BLOCK.0.to_string(), UNKNOWN_BLOCK.1.to_string()),
+            [(); 6].map(|_| magenta.clone()),
+        );
         for block_pair in &**block_table {
             let block = block_pair.1;
             let mut texture_array: Vec<Handle<Image>> = Vec::with_capacity(6);
@@ -259,10 +376,21 @@ pub fn load_blocks(
                             texture_array.len()
                         )
                     });
+            if let Some(decoration) = &block.decoration {
+                let path = format!(
+                    "blocks/{}/{}",
+                    trim_geo_identifier(block.name.clone()),
+                    decoration.texture
+                );
+                let texture_handle: Handle<Image> = asset_server.load(path.as_str());
+                loading.push(texture_handle.clone_untyped());
+                loadable_assets
+                    .decoration_textures
+                    .insert(block_identifier.clone(), texture_handle);
+            }
             loadable_assets
                 .block_textures
                 .insert(block_identifier, texture_array);
         }
-        *has_ran = true;
     }
 }
@@ -4,11 +4,14 @@ use bevy::prelude::*;
 use bevy_egui::EguiPlugin;
 use vinox_common::networking::protocol::NetworkIP;
 
-use crate::states::components::{despawn_with, GameState, Menu};
+use crate::states::{
+    components::{despawn_with, GameState, Menu},
+    fonts::{install_fonts, UiFonts},
+};
 
 use super::ui::{
-    configure_visuals, create_ui, options, save_options, start, ui_events, update_ui_scale_factor,
-    InOptions,
+    configure_visuals, create_ui, options, save_options, start, suppress_while_rebinding,
+    ui_events, update_ui_scale_factor, InOptions, Rebinding,
 };
 
 pub struct MenuPlugin;
@@ -27,7 +30,10 @@ impl Plugin for MenuPlugin {
         }
 
         app.add_plugin(EguiPlugin)
+            .init_resource::<UiFonts>()
+            .add_startup_system(install_fonts)
             .insert_resource(InOptions(false))
+            .insert_resource(Rebinding(None))
             .insert_resource(NetworkIP(ip))
             .add_systems(
                 (
@@ -40,6 +46,7 @@ impl Plugin for MenuPlugin {
                     .in_set(OnUpdate(GameState::Menu)),
             )
             .add_systems((save_options, options))
+            .add_system(suppress_while_rebinding.after(options))
             .add_system(start.in_schedule(OnEnter(GameState::Menu)))
             .add_system(despawn_with::<Menu>.in_schedule(OnExit(GameState::Menu)));
     }
@@ -1,4 +1,5 @@
-use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
+
 use vinox_server::create_server;
 
 use bevy::{
@@ -7,18 +8,76 @@ use bevy::{
     window::{PresentMode, PrimaryWindow},
 };
 use bevy_egui::{
-    egui::{self, FontId, Rounding},
+    egui::{self, Color32, RichText, Rounding},
     EguiContexts, EguiSettings,
 };
-use vinox_common::networking::protocol::NetworkIP;
+use leafwing_input_manager::prelude::*;
+use vinox_common::networking::protocol::{ChatCategory, NetworkIP, MAX_NAME_CHARS};
 
-use crate::states::components::{
-    save_game_options, GameActions, GameOptions, GameState, Menu, ProjectPath,
+use crate::states::{
+    audio::SoundCategory,
+    components::{
+        save_game_options, GameActions, GameOptions, GameState, Menu, ProjectPath,
+        DECORATION_DENSITY_RANGE, FLY_SPEED_RANGE, FOV_RANGE, FRAME_TARGET_RANGE,
+        SENSITIVITY_RANGE, THIRD_PERSON_RANGE, VIEW_DISTANCE_RANGE,
+    },
+    fonts::{set_text_styles, TextSizes, UI_TEXT_SCALE},
+    game::{
+        networking::chunk_cache::clear_chunk_cache,
+        rendering::{
+            autotune::{AutoTune, Knob},
+            memory::{MemoryBudget, MIB},
+        },
+        ui::{
+            hints::binding_label,
+            notifications::{category_label, unmute},
+        },
+    },
 };
 
+const AUTO_BADGE_COLOR: Color32 = Color32::from_rgb(137, 180, 250);
+
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct InOptions(pub bool);
 
+// The action waiting for its next key or button, gameplay input is off until it gets one
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Rebinding(pub Option<GameActions>);
+
+// Actions that share an input with another action
+pub fn binding_conflicts(input_map: &InputMap<GameActions>) -> HashSet<GameActions> {
+    let mut owners: HashMap<&UserInput, Vec<GameActions>> = HashMap::new();
+    for action in GameActions::variants() {
+        for input in input_map.get(action).iter() {
+            owners.entry(input).or_default().push(action);
+        }
+    }
+    owners
+        .into_values()
+        .filter(|actions| actions.len() > 1)
+        .flatten()
+        .collect()
+}
+
+// Next to an option auto-tune is holding under what it's set to
+fn auto_badge(ui: &mut egui::Ui, badge: Option<String>) {
+    if let Some(badge) = badge {
+        ui.colored_label(AUTO_BADGE_COLOR, badge)
+            .on_hover_text("Turned down by auto-tune, changing it here hands it back to you");
+    }
+}
+
+// Nothing bound to the old key should fire while a new one is picked
+pub fn suppress_while_rebinding(
+    rebinding: Res<Rebinding>,
+    mut toggle: ResMut<ToggleActions<GameActions>>,
+) {
+    let enabled = rebinding.is_none();
+    if toggle.enabled != enabled {
+        toggle.enabled = enabled;
+    }
+}
+
 pub fn configure_visuals(mut contexts: EguiContexts) {
     contexts.ctx_mut().set_visuals(egui::Visuals {
         window_rounding: Rounding::from(0.0),
@@ -26,6 +85,8 @@ pub fn configure_visuals(mut contexts: EguiContexts) {
     });
 }
 
+// Flips egui between logical and physical pixels. Text sizes already carry ui_text_scale and
+// egui multiplies its scale factor on top, so the option holds either way
 pub fn update_ui_scale_factor(
     keyboard_input: Res<Input<KeyCode>>,
     mut toggle_scale_factor: Local<Option<bool>>,
@@ -52,32 +113,46 @@ pub fn save_options(options: Res<GameOptions>, project_path: Res<ProjectPath>) {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn options(
     mut contexts: EguiContexts,
     mut in_options: ResMut<InOptions>,
     mut options: ResMut<GameOptions>,
-    mut current_change: Local<Option<GameActions>>,
+    ip: Res<NetworkIP>,
+    mut rebinding: ResMut<Rebinding>,
     mut keys: EventReader<KeyboardInput>,
     mut mouse_buttons: EventReader<MouseButtonInput>,
     mut windows: Query<&mut Window>,
+    (budget, project_path, tune): (Res<MemoryBudget>, Res<ProjectPath>, Res<AutoTune>),
 ) {
+    // Read every frame, otherwise the release of the click that started listening binds itself
+    let released_key = keys
+        .iter()
+        .filter(|key| key.state == ButtonState::Released)
+        .find_map(|key| key.key_code);
+    let released_button = mouse_buttons
+        .iter()
+        .find(|button| button.state == ButtonState::Released)
+        .map(|button| button.button);
+    if !**in_options && rebinding.is_some() {
+        **rebinding = None;
+    }
     if **in_options {
-        if let Some(current_action) = *current_change {
-            if let Some(keyboard_input) = keys.iter().next() {
-                if keyboard_input.state == ButtonState::Released {
-                    if let Some(key_code) = keyboard_input.key_code {
-                        options.input.clear_action(current_action);
-                        options.input.insert(key_code, current_action);
-                        *current_change = None;
-                    }
+        // Bound on release so the key being held doesn't carry into gameplay
+        if let Some(action) = **rebinding {
+            match (released_key, released_button) {
+                (Some(KeyCode::Escape), _) => **rebinding = None,
+                (Some(key_code), _) => {
+                    options.input.clear_action(action);
+                    options.input.insert(key_code, action);
+                    **rebinding = None;
                 }
-            }
-            if let Some(mouse_input) = mouse_buttons.iter().next() {
-                if mouse_input.state == ButtonState::Released {
-                    options.input.clear_action(current_action);
-                    options.input.insert(mouse_input.button, current_action);
-                    *current_change = None;
+                (None, Some(button)) => {
+                    options.input.clear_action(action);
+                    options.input.insert(button, action);
+                    **rebinding = None;
                 }
+                (None, None) => {}
             }
         }
         if !options.dark_theme {
@@ -87,43 +162,37 @@ pub fn options(
             .open(&mut in_options)
             .show(contexts.ctx_mut(), |ui| {
                 ui.with_layout(egui::Layout::top_down(egui::Align::LEFT), |ui| {
-                    ui.ctx().set_style(egui::Style {
-                        text_styles: {
-                            let mut texts = BTreeMap::new();
-                            texts.insert(egui::style::TextStyle::Small, FontId::proportional(16.0));
-                            texts.insert(egui::style::TextStyle::Body, FontId::proportional(16.0));
-                            texts.insert(
-                                egui::style::TextStyle::Heading,
-                                FontId::proportional(36.0),
-                            );
-                            texts
-                                .insert(egui::style::TextStyle::Monospace, FontId::monospace(16.0));
-                            texts
-                                .insert(egui::style::TextStyle::Button, FontId::proportional(26.0));
-                            texts
-                        },
-                        ..Default::default()
-                    });
+                    set_text_styles(ui.ctx(), TextSizes::MENU, options.ui_text_scale);
                     egui::ScrollArea::vertical()
                         .auto_shrink([false; 2])
                         .max_width(2000.0)
                         .show(ui, |ui| {
-                            for (input, action) in options.input.iter() {
+                            let conflicts = binding_conflicts(&options.input);
+                            ui.horizontal(|ui| {
+                                ui.label("Controls: ");
+                                if ui.small_button("Reset to defaults").clicked() {
+                                    options.input = GameOptions::default().input;
+                                    **rebinding = None;
+                                }
+                            });
+                            ui.separator();
+                            for action in GameActions::variants() {
                                 ui.horizontal(|ui| {
-                                    ui.label(format!("{action:?}"));
-                                    if let Some(key) =
-                                        input.get_at(0).unwrap().raw_inputs().keycodes.get(0)
-                                    {
-                                        if ui.small_button(format!("{key:?}")).clicked() {
-                                            *current_change = Some(action);
-                                        }
-                                    } else if let Some(mouse) =
-                                        input.get_at(0).unwrap().raw_inputs().mouse_buttons.get(0)
-                                    {
-                                        if ui.small_button(format!("{mouse:?}")).clicked() {
-                                            *current_change = Some(action);
-                                        }
+                                    let name = RichText::new(format!("{action:?}"));
+                                    ui.label(if conflicts.contains(&action) {
+                                        name.color(Color32::RED)
+                                    } else {
+                                        name
+                                    });
+                                    let binding = if **rebinding == Some(action) {
+                                        "Press a key or button, escape cancels".to_string()
+                                    } else {
+                                        binding_label(&options.input, action)
+                                            .unwrap_or_else(|| "Unbound".to_string())
                                     };
+                                    if ui.small_button(binding).clicked() {
+                                        **rebinding = Some(action);
+                                    }
                                 });
                                 ui.separator();
                             }
@@ -143,15 +212,80 @@ pub fn options(
                                     options.standard_bar = !options.standard_bar;
                                 }
                             });
+                            ui.horizontal(|ui| {
+                                ui.label("Utility slot: ");
+                                ui.selectable_value(&mut options.utility_slot, None, "None");
+                                for slot in 0..9 {
+                                    ui.selectable_value(
+                                        &mut options.utility_slot,
+                                        Some(slot),
+                                        format!("{}", slot + 1),
+                                    );
+                                }
+                            });
                             ui.separator();
                             ui.horizontal(|ui| {
                                 ui.label("FOV: ");
-                                ui.add(egui::Slider::new(&mut options.fov, 30.0..=120.0));
+                                ui.add(egui::Slider::new(&mut options.fov, FOV_RANGE));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Third person distance: ");
+                                ui.add(egui::Slider::new(
+                                    &mut options.third_person_distance,
+                                    THIRD_PERSON_RANGE,
+                                ));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Fly speed: ");
+                                ui.add(egui::Slider::new(&mut options.fly_speed, FLY_SPEED_RANGE));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("View distance: ");
+                                ui.add(egui::Slider::new(
+                                    &mut options.view_distance,
+                                    VIEW_DISTANCE_RANGE,
+                                ));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Mouse sensitivity: ");
+                                ui.add(egui::Slider::new(
+                                    &mut options.mouse_sensitivity,
+                                    SENSITIVITY_RANGE,
+                                ));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Raw mouse input: ");
+                                if ui
+                                    .small_button(format!("{}", options.raw_mouse_input))
+                                    .clicked()
+                                {
+                                    options.raw_mouse_input = !options.raw_mouse_input;
+                                }
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Mouse smoothing: ");
+                                if ui
+                                    .small_button(format!("{}", options.mouse_smoothing))
+                                    .clicked()
+                                {
+                                    options.mouse_smoothing = !options.mouse_smoothing;
+                                }
                             });
                             ui.separator();
                             ui.horizontal(|ui| {
                                 ui.label("Max meshes per frame: ");
                                 ui.add(egui::Slider::new(&mut options.meshes_frame, 64..=2048));
+                                auto_badge(ui, tune.badge(Knob::MeshUpload, &options));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Mesh tasks (0 is one per CPU): ");
+                                ui.add(egui::Slider::new(&mut options.mesh_tasks, 0..=64));
                             });
                             ui.separator();
                             ui.horizontal(|ui| {
@@ -167,6 +301,255 @@ pub fn options(
                                 }
                             });
                             ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Show HUD: ");
+                                if ui.small_button(format!("{}", options.show_hud)).clicked() {
+                                    options.show_hud = !options.show_hud;
+                                }
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("HUD scale: ");
+                                ui.add(egui::Slider::new(&mut options.hud_scale, 0.5..=3.0));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Text scale: ");
+                                ui.add(egui::Slider::new(
+                                    &mut options.ui_text_scale,
+                                    UI_TEXT_SCALE,
+                                ));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Reduce motion: ");
+                                if ui
+                                    .small_button(format!("{}", options.reduce_motion))
+                                    .clicked()
+                                {
+                                    options.reduce_motion = !options.reduce_motion;
+                                }
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Sprint FOV: ");
+                                if ui.small_button(format!("{}", options.sprint_fov)).clicked() {
+                                    options.sprint_fov = !options.sprint_fov;
+                                }
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Left handed: ");
+                                if ui
+                                    .small_button(format!("{}", options.left_handed))
+                                    .clicked()
+                                {
+                                    options.left_handed = !options.left_handed;
+                                }
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Ambient occlusion: ");
+                                if ui
+                                    .small_button(format!("{}", options.ambient_occlusion))
+                                    .clicked()
+                                {
+                                    options.ambient_occlusion = !options.ambient_occlusion;
+                                }
+                                auto_badge(ui, tune.badge(Knob::AmbientOcclusion, &options));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Greedy meshing: ");
+                                if ui
+                                    .small_button(format!("{}", options.greedy_meshing))
+                                    .clicked()
+                                {
+                                    options.greedy_meshing = !options.greedy_meshing;
+                                }
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Decorations: ");
+                                if ui
+                                    .small_button(format!("{}", options.decorations))
+                                    .clicked()
+                                {
+                                    options.decorations = !options.decorations;
+                                }
+                                ui.add_enabled(
+                                    options.decorations,
+                                    egui::Slider::new(
+                                        &mut options.decoration_density,
+                                        DECORATION_DENSITY_RANGE,
+                                    )
+                                    .text("density"),
+                                );
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Chunk fade-in: ");
+                                if ui
+                                    .small_button(format!("{}", options.chunk_fade_in))
+                                    .clicked()
+                                {
+                                    options.chunk_fade_in = !options.chunk_fade_in;
+                                }
+                                auto_badge(ui, tune.badge(Knob::ChunkFadeIn, &options));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Auto-tune: ");
+                                if ui.small_button(format!("{}", options.auto_tune)).clicked() {
+                                    options.auto_tune = !options.auto_tune;
+                                }
+                                ui.label("Frame target (ms): ");
+                                ui.add_enabled(
+                                    options.auto_tune,
+                                    egui::Slider::new(
+                                        &mut options.frame_target_ms,
+                                        FRAME_TARGET_RANGE,
+                                    ),
+                                );
+                                auto_badge(ui, tune.badge(Knob::ViewRadius, &options));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Crosshair: ");
+                                let shape = options.crosshair.shape;
+                                if ui.small_button(format!("{shape:?}")).clicked() {
+                                    options.crosshair.shape = shape.next();
+                                }
+                                ui.color_edit_button_srgba_unmultiplied(
+                                    &mut options.crosshair.color,
+                                );
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Size: ");
+                                ui.add(egui::Slider::new(&mut options.crosshair.size, 4.0..=64.0));
+                                ui.label("Thickness: ");
+                                ui.add(egui::Slider::new(
+                                    &mut options.crosshair.thickness,
+                                    1.0..=8.0,
+                                ));
+                                ui.label("Outline: ");
+                                ui.add(egui::Slider::new(
+                                    &mut options.crosshair.outline,
+                                    0.0..=4.0,
+                                ));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Tint over blocks: ");
+                                if ui
+                                    .small_button(format!("{}", options.crosshair.tint_targets))
+                                    .clicked()
+                                {
+                                    options.crosshair.tint_targets =
+                                        !options.crosshair.tint_targets;
+                                }
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Master volume: ");
+                                ui.add(egui::Slider::new(&mut options.audio.master, 0.0..=1.0));
+                            });
+                            egui::Grid::new("sound_categories").show(ui, |ui| {
+                                for category in SoundCategory::ALL {
+                                    ui.label(category.label());
+                                    ui.add(egui::Slider::new(
+                                        options.audio.category_mut(category),
+                                        0.0..=1.0,
+                                    ));
+                                    ui.end_row();
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("World sounds in menus: ");
+                                ui.add(egui::Slider::new(
+                                    &mut options.audio.menu_muffle,
+                                    0.0..=1.0,
+                                ));
+                            });
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Memory cap (MiB, 0 is automatic): ");
+                                ui.add(egui::Slider::new(&mut options.memory_cap_mib, 0..=16384));
+                            });
+                            ui.label(format!(
+                                "Using ~{} of {} MiB",
+                                budget.used() / MIB,
+                                budget.soft_cap() / MIB
+                            ));
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Chunk cache: ");
+                                if ui
+                                    .small_button(format!("{}", options.chunk_cache))
+                                    .clicked()
+                                {
+                                    options.chunk_cache = !options.chunk_cache;
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Chunk cache size (MiB): ");
+                                ui.add(egui::Slider::new(&mut options.chunk_cache_mib, 16..=4096));
+                            });
+                            if ui.button("Clear chunk cache").clicked() {
+                                clear_chunk_cache(&project_path);
+                            }
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.label("Tutorial hints: ");
+                                if ui.small_button(format!("{}", options.hints)).clicked() {
+                                    options.hints = !options.hints;
+                                }
+                                if ui.small_button("Reset hints").clicked() {
+                                    options.shown_hints.clear();
+                                }
+                            });
+                            ui.separator();
+                            ui.label("Notifications (chat, popup, sound): ");
+                            egui::Grid::new("notification_routes").show(ui, |ui| {
+                                for category in ChatCategory::ALL {
+                                    ui.label(category_label(category));
+                                    // Only a click should count as a change, it's saved on every one
+                                    let mut clicked = false;
+                                    let route = options
+                                        .bypass_change_detection()
+                                        .notifications
+                                        .get_mut(category);
+                                    for shown in
+                                        [&mut route.chat, &mut route.toast, &mut route.sound]
+                                    {
+                                        if ui.small_button(format!("{shown}")).clicked() {
+                                            *shown = !*shown;
+                                            clicked = true;
+                                        }
+                                    }
+                                    if clicked {
+                                        options.set_changed();
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                            ui.separator();
+                            // Only the server in the IP box, mutes elsewhere are kept for there
+                            let muted = options.muted.get(&**ip).cloned().unwrap_or_default();
+                            ui.label(format!("Muted on {}: ", **ip));
+                            if muted.is_empty() {
+                                ui.label("Nobody");
+                            }
+                            for name in muted {
+                                ui.horizontal(|ui| {
+                                    ui.label(&name);
+                                    if ui.small_button("Unmute").clicked() {
+                                        if let Some(muted) = options.muted.get_mut(&**ip) {
+                                            unmute(muted, &name);
+                                        }
+                                    }
+                                });
+                            }
+                            ui.separator();
                         });
                 });
             });
@@ -196,18 +579,7 @@ pub fn create_ui(
         .default_width(250.0)
         .show(contexts.ctx_mut(), |ui| {
             ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
-                ui.ctx().set_style(egui::Style {
-                    text_styles: {
-                        let mut texts = BTreeMap::new();
-                        texts.insert(egui::style::TextStyle::Small, FontId::proportional(16.0));
-                        texts.insert(egui::style::TextStyle::Body, FontId::proportional(16.0));
-                        texts.insert(egui::style::TextStyle::Heading, FontId::proportional(36.0));
-                        texts.insert(egui::style::TextStyle::Monospace, FontId::monospace(16.0));
-                        texts.insert(egui::style::TextStyle::Button, FontId::proportional(26.0));
-                        texts
-                    },
-                    ..Default::default()
-                });
+                set_text_styles(ui.ctx(), TextSizes::MENU, options.ui_text_scale);
                 ui.heading("Vinox");
 
                 ui.allocate_space(egui::Vec2::new(1.0, 100.0));
@@ -219,7 +591,11 @@ pub fn create_ui(
 
                 ui.horizontal(|ui| {
                     ui.label("Username: ");
-                    ui.text_edit_singleline(&mut options.user_name);
+                    // The server counts characters, so does this
+                    ui.add(
+                        egui::TextEdit::singleline(&mut options.user_name)
+                            .char_limit(MAX_NAME_CHARS),
+                    );
                 });
 
                 ui.allocate_space(egui::Vec2::new(1.0, 26.0));
@@ -273,3 +649,23 @@ pub fn ui_events() {}
 pub fn start(mut commands: Commands) {
     commands.spawn((Camera2dBundle::default(), Menu));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_inputs_conflict() {
+        let mut input_map = GameOptions::default().input;
+        assert!(binding_conflicts(&input_map).is_empty());
+        input_map.insert(KeyCode::W, GameActions::Jump);
+        assert_eq!(
+            binding_conflicts(&input_map),
+            HashSet::from([GameActions::Forward, GameActions::Jump])
+        );
+        // Part of a chord on its own isn't the chord
+        input_map.clear_action(GameActions::Jump);
+        input_map.insert(KeyCode::P, GameActions::Jump);
+        assert!(binding_conflicts(&input_map).is_empty());
+    }
+}
@@ -1,5 +1,8 @@
 pub mod assets;
+pub mod audio;
 pub mod components;
+pub mod crash;
+pub mod fonts;
 pub mod game;
 pub mod loading;
 pub mod menu;
@@ -0,0 +1,295 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ecs::bundles::{Inventory, SlotRef},
+    storage::items::descriptor::{ItemData, MAX_STACK_SIZE},
+    world::chunks::storage::{name_to_identifier, ItemTable},
+};
+
+// Rearranging what's already in the inventory, nothing comes in or goes out. Each one builds on
+// the last so they're sent through an IntentChannel and applied strictly in order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InventoryOp {
+    Swap {
+        a: SlotRef,
+        b: SlotRef,
+    },
+    // Part of a stack onto an empty slot or one it stacks with
+    Move {
+        from: SlotRef,
+        to: SlotRef,
+        count: u32,
+    },
+    // Merges what stacks and packs the inventory rows by identifier, the hotbar stays put
+    Sort,
+}
+
+pub fn max_stack_size(item: &ItemData, item_table: &ItemTable) -> u32 {
+    item_table
+        .get(&name_to_identifier(
+            item.namespace.clone(),
+            item.name.clone(),
+        ))
+        .and_then(|descriptor| descriptor.max_stack_size)
+        .unwrap_or(MAX_STACK_SIZE)
+}
+
+impl InventoryOp {
+    // Putting down `count` of the stack picked up from `from` onto `to`. A whole stack trades
+    // places with whatever is there, part of one only goes somewhere empty or onto the same item.
+    // Either way it stops at the item's max stack size. None keeps it held
+    pub fn place(
+        inventory: &Inventory,
+        from: SlotRef,
+        count: u32,
+        to: SlotRef,
+        item_table: &ItemTable,
+    ) -> Option<InventoryOp> {
+        let moving = inventory.slot(from)?.as_ref()?;
+        if from == to || count == 0 {
+            return None;
+        }
+        let whole = count >= moving.stack_size;
+        match inventory.slot(to)? {
+            Some(target) if target.can_stack_with(moving) => {
+                let room = max_stack_size(target, item_table).saturating_sub(target.stack_size);
+                let count = count.min(moving.stack_size).min(room);
+                (count > 0).then_some(InventoryOp::Move { from, to, count })
+            }
+            None if !whole => Some(InventoryOp::Move { from, to, count }),
+            Some(_) if !whole => None,
+            _ => Some(InventoryOp::Swap { a: from, b: to }),
+        }
+    }
+}
+
+impl Inventory {
+    // False when the op doesn't fit this inventory, which is then left as it was
+    pub fn apply(&mut self, op: &InventoryOp, item_table: &ItemTable) -> bool {
+        match *op {
+            InventoryOp::Swap { a, b } => {
+                if self.slot_mut(a).is_none() || self.slot_mut(b).is_none() {
+                    return false;
+                }
+                let first = self.slot_mut(a).unwrap().take();
+                let second = std::mem::replace(self.slot_mut(b).unwrap(), first);
+                *self.slot_mut(a).unwrap() = second;
+                true
+            }
+            InventoryOp::Move { from, to, count } => {
+                let Some(moving) = self.slot_mut(from).and_then(|slot| slot.clone()) else {
+                    return false;
+                };
+                if from == to || count == 0 || count > moving.stack_size {
+                    return false;
+                }
+                let max = max_stack_size(&moving, item_table);
+                let fits = match self.slot_mut(to) {
+                    Some(None) => count <= max,
+                    Some(Some(target)) => {
+                        target.can_stack_with(&moving)
+                            && target
+                                .stack_size
+                                .checked_add(count)
+                                .is_some_and(|total| total <= max)
+                    }
+                    None => false,
+                };
+                if !fits {
+                    return false;
+                }
+                let target = self.slot_mut(to).unwrap();
+                if let Some(existing) = target.as_mut() {
+                    existing.stack_size += count;
+                } else {
+                    *target = Some(ItemData {
+                        stack_size: count,
+                        ..moving.clone()
+                    });
+                }
+                let source = self.slot_mut(from).unwrap();
+                *source = (moving.stack_size > count).then(|| ItemData {
+                    stack_size: moving.stack_size - count,
+                    ..moving
+                });
+                true
+            }
+            InventoryOp::Sort => {
+                let mut items: Vec<ItemData> = self
+                    .slots
+                    .iter_mut()
+                    .flatten()
+                    .filter_map(Option::take)
+                    .collect();
+                // Stable, so stacks of the same item keep their order
+                items.sort_by_key(|item| {
+                    name_to_identifier(item.namespace.clone(), item.name.clone())
+                });
+                let mut sorted: Vec<ItemData> = Vec::new();
+                for mut item in items {
+                    let max = max_stack_size(&item, item_table);
+                    let plain = item.clone();
+                    for existing in sorted
+                        .iter_mut()
+                        .filter(|existing| existing.can_stack_with(&plain))
+                    {
+                        let moved = item.stack_size.min(max.saturating_sub(existing.stack_size));
+                        existing.stack_size += moved;
+                        item.stack_size -= moved;
+                    }
+                    if item.stack_size > 0 {
+                        sorted.push(item);
+                    }
+                }
+                for (slot, item) in self.slots.iter_mut().flatten().zip(sorted) {
+                    *slot = Some(item);
+                }
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::bundles::InventorySection;
+
+    fn stack(name: &str, stack_size: u32) -> Option<ItemData> {
+        Some(ItemData {
+            namespace: "vinox".to_string(),
+            name: name.to_string(),
+            stack_size,
+            ..Default::default()
+        })
+    }
+
+    fn slot(section: InventorySection, bar: usize, slot: usize) -> SlotRef {
+        SlotRef { section, bar, slot }
+    }
+
+    #[test]
+    fn ops_rearrange_without_creating_anything() {
+        let item_table = ItemTable::default();
+        let hand = slot(InventorySection::Hotbar, 0, 0);
+        let row = slot(InventorySection::Slots, 2, 4);
+        let mut inventory = Inventory::default();
+        *inventory.slot_mut(hand).unwrap() = stack("stone", 10);
+
+        assert!(inventory.apply(&InventoryOp::Swap { a: hand, b: row }, &item_table));
+        assert_eq!(*inventory.slot_mut(hand).unwrap(), None);
+        assert_eq!(*inventory.slot_mut(row).unwrap(), stack("stone", 10));
+
+        let moved = InventoryOp::Move {
+            from: row,
+            to: hand,
+            count: 4,
+        };
+        assert!(inventory.apply(&moved, &item_table));
+        assert_eq!(*inventory.slot_mut(hand).unwrap(), stack("stone", 4));
+        assert_eq!(*inventory.slot_mut(row).unwrap(), stack("stone", 6));
+        // More than is left turns the whole move down
+        let too_many = InventoryOp::Move {
+            from: row,
+            to: hand,
+            count: 7,
+        };
+        assert!(!inventory.apply(&too_many, &item_table));
+        assert_eq!(*inventory.slot_mut(row).unwrap(), stack("stone", 6));
+
+        let outside = slot(InventorySection::Hotbar, 3, 0);
+        assert!(!inventory.apply(
+            &InventoryOp::Swap {
+                a: hand,
+                b: outside
+            },
+            &item_table
+        ));
+        assert_eq!(*inventory.slot_mut(hand).unwrap(), stack("stone", 4));
+
+        // A stack already past the max turns the move down rather than wrapping around
+        *inventory.slot_mut(hand).unwrap() = stack("stone", u32::MAX);
+        let onto_full = InventoryOp::Move {
+            from: row,
+            to: hand,
+            count: 1,
+        };
+        assert!(!inventory.apply(&onto_full, &item_table));
+        assert_eq!(*inventory.slot_mut(row).unwrap(), stack("stone", 6));
+    }
+
+    #[test]
+    fn placing_merges_splits_and_swaps() {
+        let item_table = ItemTable::default();
+        let hand = slot(InventorySection::Hotbar, 0, 0);
+        let row = slot(InventorySection::Slots, 0, 0);
+        let empty = slot(InventorySection::Slots, 0, 1);
+        let apples = slot(InventorySection::Slots, 0, 2);
+        let mut inventory = Inventory::default();
+        *inventory.slot_mut(hand).unwrap() = stack("stone", 10);
+        *inventory.slot_mut(row).unwrap() = stack("stone", MAX_STACK_SIZE - 4);
+        *inventory.slot_mut(apples).unwrap() = stack("apple", 1);
+        let place = |inventory: &Inventory, from, count, to| {
+            InventoryOp::place(inventory, from, count, to, &item_table)
+        };
+
+        // Tops the other stack up to the max, the rest stays where it came from
+        let merge = place(&inventory, hand, 10, row).unwrap();
+        assert_eq!(
+            merge,
+            InventoryOp::Move {
+                from: hand,
+                to: row,
+                count: 4
+            }
+        );
+        assert!(inventory.apply(&merge, &item_table));
+        assert_eq!(
+            *inventory.slot(row).unwrap(),
+            stack("stone", MAX_STACK_SIZE)
+        );
+        assert_eq!(*inventory.slot(hand).unwrap(), stack("stone", 6));
+        // Full, so nothing to do
+        assert_eq!(place(&inventory, hand, 6, row), None);
+
+        // Half a stack goes somewhere empty but can't trade places with something else
+        let half = place(&inventory, hand, 3, empty).unwrap();
+        assert!(inventory.apply(&half, &item_table));
+        assert_eq!(*inventory.slot(empty).unwrap(), stack("stone", 3));
+        assert_eq!(*inventory.slot(hand).unwrap(), stack("stone", 3));
+        assert_eq!(place(&inventory, hand, 1, apples), None);
+
+        // A whole one does
+        let swap = place(&inventory, hand, 3, apples).unwrap();
+        assert_eq!(swap, InventoryOp::Swap { a: hand, b: apples });
+        assert!(inventory.apply(&swap, &item_table));
+        assert_eq!(*inventory.slot(hand).unwrap(), stack("apple", 1));
+
+        assert_eq!(place(&inventory, hand, 1, hand), None);
+        assert_eq!(
+            place(&inventory, slot(InventorySection::Slots, 4, 8), 1, hand),
+            None
+        );
+    }
+
+    #[test]
+    fn sorting_merges_and_packs_the_rows() {
+        let mut inventory = Inventory::default();
+        inventory.slots[0][3] = stack("stone", MAX_STACK_SIZE - 1);
+        inventory.slots[1][0] = stack("dirt", 3);
+        inventory.slots[4][8] = stack("stone", 5);
+        inventory.hotbar[0][0] = stack("apple", 1);
+        assert!(inventory.apply(&InventoryOp::Sort, &ItemTable::default()));
+        let rows: Vec<Option<ItemData>> = inventory.slots.iter().flatten().cloned().collect();
+        assert_eq!(
+            rows[..4],
+            [
+                stack("dirt", 3),
+                stack("stone", MAX_STACK_SIZE),
+                stack("stone", 4),
+                None
+            ]
+        );
+        assert_eq!(inventory.hotbar[0][0], stack("apple", 1));
+    }
+}
@@ -2,8 +2,13 @@ use bevy::{math::Vec3A, prelude::*, render::primitives::Aabb};
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    ecs::arrange::max_stack_size,
     networking::protocol::Player,
-    storage::items::descriptor::{ItemData, ItemDescriptor, MAX_STACK_SIZE},
+    storage::{
+        crafting::descriptor::RecipeDescriptor,
+        items::descriptor::{ItemData, ItemDescriptor, MAX_STACK_SIZE},
+    },
+    world::chunks::storage::{name_to_identifier, ItemTable},
 };
 
 #[derive(Default, Deref, DerefMut, Serialize, Deserialize, Debug, Clone)]
@@ -20,6 +25,20 @@ pub struct CurrentInvBar(pub usize);
 #[derive(Default, Deref, DerefMut, Serialize, Deserialize, Debug, Clone)]
 pub struct CurrentInvItem(pub usize);
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InventorySection {
+    Hotbar,
+    Slots,
+}
+
+// Points at one slot, bar is the hotbar section or the inventory row
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotRef {
+    pub section: InventorySection,
+    pub bar: usize,
+    pub slot: usize,
+}
+
 #[derive(Component, Default, Serialize, Deserialize, Clone, Debug)]
 pub struct Inventory {
     pub username: String,
@@ -33,6 +52,207 @@ pub struct Inventory {
 }
 
 impl Inventory {
+    // None when the reference is out of bounds, Some(None) for an empty slot
+    pub fn slot(&self, slot_ref: SlotRef) -> Option<&Option<ItemData>> {
+        match slot_ref.section {
+            InventorySection::Hotbar => self.hotbar.get(slot_ref.bar)?.get(slot_ref.slot),
+            InventorySection::Slots => self.slots.get(slot_ref.bar)?.get(slot_ref.slot),
+        }
+    }
+
+    pub fn slot_mut(&mut self, slot_ref: SlotRef) -> Option<&mut Option<ItemData>> {
+        match slot_ref.section {
+            InventorySection::Hotbar => self.hotbar.get_mut(slot_ref.bar)?.get_mut(slot_ref.slot),
+            InventorySection::Slots => self.slots.get_mut(slot_ref.bar)?.get_mut(slot_ref.slot),
+        }
+    }
+
+    // The selected hotbar slot, what counts as held
+    pub fn held_slot(&self) -> SlotRef {
+        SlotRef {
+            section: InventorySection::Hotbar,
+            bar: *self.current_bar,
+            slot: *self.current_item,
+        }
+    }
+
+    // Takes one off the stack in the slot if it still holds the identifier, false otherwise
+    pub fn use_one(&mut self, slot_ref: SlotRef, identifier: &str) -> bool {
+        let Some(slot) = self.slot_mut(slot_ref) else {
+            return false;
+        };
+        let Some(item) = slot.as_mut().filter(|item| {
+            name_to_identifier(item.namespace.clone(), item.name.clone()) == identifier
+        }) else {
+            return false;
+        };
+        if item.stack_size > 1 {
+            item.stack_size -= 1;
+        } else {
+            *slot = None;
+        }
+        true
+    }
+
+    // Up to count off the stack in the slot if it stacks with item. What came off, as it was
+    // held here rather than as the caller described it
+    pub fn take(&mut self, slot_ref: SlotRef, item: &ItemData, count: u32) -> Option<ItemData> {
+        let slot = self.slot_mut(slot_ref)?;
+        let held = slot.as_mut().filter(|held| held.can_stack_with(item))?;
+        let taken = count.min(held.stack_size);
+        if taken == 0 {
+            return None;
+        }
+        let removed = ItemData {
+            stack_size: taken,
+            ..held.clone()
+        };
+        held.stack_size -= taken;
+        if held.stack_size == 0 {
+            *slot = None;
+        }
+        Some(removed)
+    }
+
+    // Tops up matching stacks first, then takes empty slots. Hands back whatever didn't fit
+    pub fn add_stack(&mut self, item: &ItemData, max_stack_size: u32) -> u32 {
+        let mut left = item.stack_size;
+        for slot in self
+            .hotbar
+            .iter_mut()
+            .flatten()
+            .chain(self.slots.iter_mut().flatten())
+        {
+            if left == 0 {
+                break;
+            }
+            if let Some(existing) = slot
+                .as_mut()
+                .filter(|existing| existing.can_stack_with(item))
+            {
+                let moved = left.min(max_stack_size.saturating_sub(existing.stack_size));
+                existing.stack_size += moved;
+                left -= moved;
+            }
+        }
+        for slot in self
+            .hotbar
+            .iter_mut()
+            .flatten()
+            .chain(self.slots.iter_mut().flatten())
+        {
+            if left == 0 {
+                break;
+            }
+            if slot.is_none() {
+                let moved = left.min(max_stack_size);
+                *slot = Some(ItemData {
+                    stack_size: moved,
+                    ..item.clone()
+                });
+                left -= moved;
+            }
+        }
+        left
+    }
+
+    // Puts item in the held slot and moves whatever was there to wherever add_stack finds room.
+    // Hands back how much of that didn't fit anywhere
+    pub fn hold_stack(&mut self, item: ItemData, item_table: &ItemTable) -> u32 {
+        let held = self.held_slot();
+        let Some(displaced) = self.slot_mut(held).and_then(|slot| slot.replace(item)) else {
+            return 0;
+        };
+        self.add_stack(&displaced, max_stack_size(&displaced, item_table))
+    }
+
+    // How many of the identifier there are across every stack
+    pub fn count(&self, identifier: &str) -> u32 {
+        self.stacks()
+            .filter(|item| {
+                name_to_identifier(item.namespace.clone(), item.name.clone()) == identifier
+            })
+            .map(|item| item.stack_size)
+            .sum()
+    }
+
+    // Nothing here that other doesn't have at least as much of, however it's arranged, and no
+    // stack bigger than its item allows
+    pub fn fits_within(&self, other: &Inventory, item_table: &ItemTable) -> bool {
+        // Summed wide, so stacks claiming near u32::MAX can't wrap back around to nothing
+        let total = |inventory: &Inventory, item: &ItemData| -> u64 {
+            inventory
+                .stacks()
+                .filter(|stack| stack.can_stack_with(item))
+                .map(|stack| u64::from(stack.stack_size))
+                .sum()
+        };
+        self.stacks().all(|item| {
+            item.stack_size <= max_stack_size(item, item_table)
+                && total(self, item) <= total(other, item)
+        })
+    }
+
+    // Takes the ingredients and adds what the recipe makes. Leaves everything as it was and
+    // hands back false when something's missing or the output has nowhere to go
+    pub fn craft(&mut self, recipe: &RecipeDescriptor, item_table: &ItemTable) -> bool {
+        let Some(descriptor) = item_table.get(&recipe.output_item.0) else {
+            return false;
+        };
+        let mut crafted = self.clone();
+        for (identifier, amount) in recipe.required_items.iter().flatten() {
+            if crafted.remove(identifier, *amount) < *amount {
+                return false;
+            }
+        }
+        let output = ItemData {
+            namespace: descriptor.namespace.clone(),
+            name: descriptor.name.clone(),
+            stack_size: recipe.output_item.1,
+            ..Default::default()
+        };
+        if crafted.add_stack(&output, max_stack_size(&output, item_table)) > 0 {
+            return false;
+        }
+        *self = crafted;
+        true
+    }
+
+    // Up to count of the identifier from wherever it is, hands back how many came off
+    fn remove(&mut self, identifier: &str, count: u32) -> u32 {
+        let mut left = count;
+        for slot in self
+            .hotbar
+            .iter_mut()
+            .flatten()
+            .chain(self.slots.iter_mut().flatten())
+        {
+            if left == 0 {
+                break;
+            }
+            let Some(item) = slot.as_mut().filter(|item| {
+                name_to_identifier(item.namespace.clone(), item.name.clone()) == identifier
+            }) else {
+                continue;
+            };
+            let taken = left.min(item.stack_size);
+            item.stack_size -= taken;
+            left -= taken;
+            if item.stack_size == 0 {
+                *slot = None;
+            }
+        }
+        count - left
+    }
+
+    fn stacks(&self) -> impl Iterator<Item = &ItemData> {
+        self.hotbar
+            .iter()
+            .flatten()
+            .chain(self.slots.iter().flatten())
+            .flatten()
+    }
+
     // String says whether int the hotbar array or slots
     pub fn get_first_slot(&self) -> Option<(&str, usize, usize)> {
         for (hotbar_num, hotbar_sect) in self.hotbar.iter().cloned().enumerate() {
@@ -52,14 +272,17 @@ impl Inventory {
         }
         None
     }
+    // A stack the plain item from item_comp could go on
     pub fn get_first_item(&self, item_comp: &ItemDescriptor) -> Option<(&str, usize, usize, u32)> {
+        let plain = ItemData {
+            namespace: item_comp.namespace.clone(),
+            name: item_comp.name.clone(),
+            ..Default::default()
+        };
         for (hotbar_num, hotbar_sect) in self.hotbar.iter().cloned().enumerate() {
             for (item_num, item) in hotbar_sect.iter().cloned().enumerate() {
                 if let Some(item) = item {
-                    if item.name + &item.namespace
-                        == item_comp.clone().name + &item_comp.clone().namespace
-                        && item.stack_size < MAX_STACK_SIZE
-                    {
+                    if item.can_stack_with(&plain) && item.stack_size < MAX_STACK_SIZE {
                         return Some(("hotbar", hotbar_num, item_num, item.stack_size));
                     }
                 }
@@ -69,10 +292,7 @@ impl Inventory {
         for (row_num, row) in self.slots.iter().cloned().enumerate() {
             for (item_num, item) in row.iter().cloned().enumerate() {
                 if let Some(item) = item {
-                    if item.name + &item.namespace
-                        == item_comp.clone().name + &item_comp.clone().namespace
-                        && item.stack_size < MAX_STACK_SIZE
-                    {
+                    if item.can_stack_with(&plain) && item.stack_size < MAX_STACK_SIZE {
                         return Some(("inventory", row_num, item_num, item.stack_size));
                     }
                 }
@@ -158,6 +378,36 @@ impl Inventory {
     }
 }
 
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            current: 20.0,
+            max: 20.0,
+        }
+    }
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Hunger {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Default for Hunger {
+    fn default() -> Self {
+        Self {
+            current: 20.0,
+            max: 20.0,
+        }
+    }
+}
+
 #[derive(Component, Default, Deref, DerefMut)]
 pub struct ClientName(pub String);
 
@@ -204,3 +454,188 @@ impl PlayerBundleBuilder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn using_one_only_touches_the_named_slot() {
+        let torch = ItemData {
+            namespace: "vinox".to_string(),
+            name: "torch".to_string(),
+            stack_size: 2,
+            ..Default::default()
+        };
+        let utility = SlotRef {
+            section: InventorySection::Hotbar,
+            bar: 2,
+            slot: 1,
+        };
+        let mut inventory = Inventory::default();
+        inventory.hotbar[0][0] = Some(ItemData {
+            name: "stone".to_string(),
+            ..torch.clone()
+        });
+        inventory.hotbar[2][1] = Some(torch);
+
+        assert!(inventory.use_one(utility, "vinox:torch"));
+        assert_eq!(inventory.hotbar[2][1].as_ref().unwrap().stack_size, 1);
+        assert_eq!(inventory.hotbar[0][0].as_ref().unwrap().stack_size, 2);
+        // Not what the slot holds, so nothing comes off it
+        assert!(!inventory.use_one(utility, "vinox:stone"));
+        assert!(inventory.use_one(utility, "vinox:torch"));
+        assert_eq!(inventory.hotbar[2][1], None);
+        assert!(!inventory.use_one(utility, "vinox:torch"));
+    }
+
+    #[test]
+    fn taking_never_hands_out_more_than_the_slot_holds() {
+        let dirt = ItemData {
+            namespace: "vinox".to_string(),
+            name: "dirt".to_string(),
+            stack_size: 3,
+            ..Default::default()
+        };
+        let slot = SlotRef {
+            section: InventorySection::Slots,
+            bar: 1,
+            slot: 4,
+        };
+        let mut inventory = Inventory::default();
+        inventory.slots[1][4] = Some(dirt.clone());
+
+        let asked = ItemData {
+            stack_size: 64,
+            ..dirt.clone()
+        };
+        assert_eq!(inventory.take(slot, &asked, 2).unwrap().stack_size, 2);
+        assert_eq!(inventory.take(slot, &asked, 64).unwrap().stack_size, 1);
+        assert_eq!(inventory.slots[1][4], None);
+        assert_eq!(inventory.take(slot, &asked, 1), None);
+
+        // A different kind of item in the slot stays put
+        inventory.slots[1][4] = Some(dirt.clone());
+        let stone = ItemData {
+            name: "stone".to_string(),
+            ..dirt
+        };
+        assert_eq!(inventory.take(slot, &stone, 1), None);
+        assert_eq!(inventory.slots[1][4].as_ref().unwrap().stack_size, 3);
+    }
+
+    fn item(name: &str, stack_size: u32) -> ItemData {
+        ItemData {
+            namespace: "vinox".to_string(),
+            name: name.to_string(),
+            stack_size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn holding_a_stack_moves_the_old_one_aside() {
+        let item_table = ItemTable::default();
+        let mut inventory = Inventory::default();
+        *inventory.current_bar = 1;
+        *inventory.current_item = 2;
+        inventory.hotbar[0][0] = Some(item("dirt", 1));
+        inventory.hotbar[1][2] = Some(item("stone", 5));
+
+        assert_eq!(inventory.hold_stack(item("planks", 64), &item_table), 0);
+        assert_eq!(inventory.hotbar[1][2], Some(item("planks", 64)));
+        // Nowhere else stacks with it, so it takes the first empty slot
+        assert_eq!(inventory.hotbar[0][1], Some(item("stone", 5)));
+        assert_eq!(inventory.count("vinox:dirt"), 1);
+
+        // An empty hand just takes it
+        *inventory.current_item = 0;
+        inventory.hotbar[1][0] = None;
+        assert_eq!(inventory.hold_stack(item("log", 3), &item_table), 0);
+        assert_eq!(inventory.hotbar[1][0], Some(item("log", 3)));
+        assert_eq!(inventory.count("vinox:stone"), 5);
+    }
+
+    #[test]
+    fn crafting_needs_every_ingredient() {
+        let mut item_table = ItemTable::default();
+        for name in ["log", "stick", "planks"] {
+            item_table.insert(
+                format!("vinox:{name}"),
+                ItemDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+        let recipe = RecipeDescriptor {
+            required_items: Some(
+                [("vinox:log".to_string(), 3), ("vinox:stick".to_string(), 1)]
+                    .into_iter()
+                    .collect(),
+            ),
+            output_item: ("vinox:planks".to_string(), 4),
+            ..Default::default()
+        };
+        let mut inventory = Inventory::default();
+        inventory.hotbar[0][0] = Some(item("log", 2));
+        inventory.slots[0][0] = Some(item("log", 2));
+
+        // No stick, nothing changes
+        assert!(!inventory.craft(&recipe, &item_table));
+        assert_eq!(inventory.count("vinox:log"), 4);
+
+        inventory.slots[3][3] = Some(item("stick", 1));
+        assert!(inventory.craft(&recipe, &item_table));
+        assert_eq!(inventory.count("vinox:log"), 1);
+        assert_eq!(inventory.count("vinox:stick"), 0);
+        assert_eq!(inventory.count("vinox:planks"), 4);
+    }
+
+    #[test]
+    fn rearranging_fits_but_adding_does_not() {
+        let item_table = ItemTable::default();
+        let mut ours = Inventory::default();
+        ours.hotbar[0][0] = Some(item("dirt", 10));
+        ours.slots[2][2] = Some(item("stone", 1));
+
+        let mut theirs = Inventory::default();
+        theirs.slots[0][0] = Some(item("dirt", 4));
+        theirs.slots[0][1] = Some(item("dirt", 6));
+        theirs.hotbar[1][1] = Some(item("stone", 1));
+        assert!(theirs.fits_within(&ours, &item_table));
+
+        // Fewer is fine, they're the ones losing out
+        theirs.hotbar[1][1] = None;
+        assert!(theirs.fits_within(&ours, &item_table));
+
+        theirs.hotbar[2][2] = Some(item("dirt", 1));
+        assert!(!theirs.fits_within(&ours, &item_table));
+
+        // A renamed stack isn't the same kind of item as a plain one
+        let mut named = item("stone", 1);
+        named.rename(Some("Pebble".to_string()));
+        theirs.hotbar[2][2] = Some(named);
+        assert!(!theirs.fits_within(&ours, &item_table));
+    }
+
+    #[test]
+    fn oversized_stacks_never_fit() {
+        let item_table = ItemTable::default();
+        let mut ours = Inventory::default();
+        ours.hotbar[0][0] = Some(item("dirt", 1));
+
+        // Two stacks that wrap a u32 sum back around to 1
+        let mut theirs = Inventory::default();
+        theirs.hotbar[0][0] = Some(item("dirt", u32::MAX));
+        theirs.hotbar[0][1] = Some(item("dirt", 2));
+        assert!(!theirs.fits_within(&ours, &item_table));
+
+        // Even with enough of them on our side, one stack can't go past the max
+        ours.slots[0][0] = Some(item("dirt", MAX_STACK_SIZE));
+        theirs = Inventory::default();
+        theirs.hotbar[0][0] = Some(item("dirt", MAX_STACK_SIZE + 1));
+        assert!(!theirs.fits_within(&ours, &item_table));
+    }
+}
@@ -0,0 +1,245 @@
+use std::ops::RangeInclusive;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Every rule by the name it has in gameplay.ron and /gamerule, in the order files are written
+pub const RULES: [&str; 14] = [
+    "regen_per_sec",
+    "regen_min_hunger",
+    "hunger_per_sec",
+    "idle_hunger",
+    "walking_hunger",
+    "sprinting_hunger",
+    "fall_damage_height",
+    "fall_damage_per_block",
+    "invulnerable_secs",
+    "starvation",
+    "starvation_per_sec",
+    "keep_inventory",
+    "min_build_y",
+    "max_build_y",
+];
+
+// Server owned, clients get a copy on joining and again whenever it changes
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameplayRules {
+    // Health points a second while well fed
+    pub regen_per_sec: f32,
+    // Hunger has to be at least this for health to come back
+    pub regen_min_hunger: f32,
+    // Hunger points a second, times the multiplier for whatever the player is doing
+    pub hunger_per_sec: f32,
+    pub idle_hunger: f32,
+    pub walking_hunger: f32,
+    pub sprinting_hunger: f32,
+    // Blocks a player can drop without getting hurt
+    pub fall_damage_height: f32,
+    // Health points for every block past fall_damage_height
+    pub fall_damage_per_block: f32,
+    // Any damage taken within this long of the last hit is ignored
+    pub invulnerable_secs: f32,
+    // Whether an empty hunger bar hurts
+    pub starvation: bool,
+    pub starvation_per_sec: f32,
+    // Nothing reads this yet, inventories belong to the client and the server only mirrors them
+    // so it has nothing to drop on death
+    pub keep_inventory: bool,
+    // Lowest and highest y anything can be placed or broken at. Nothing to do with how far the
+    // generator goes, building can carry on above the terrain
+    pub min_build_y: f32,
+    pub max_build_y: f32,
+}
+
+impl Default for GameplayRules {
+    fn default() -> Self {
+        Self {
+            regen_per_sec: 0.5,
+            regen_min_hunger: 18.0,
+            hunger_per_sec: 0.02,
+            idle_hunger: 1.0,
+            walking_hunger: 2.0,
+            sprinting_hunger: 4.0,
+            fall_damage_height: 3.0,
+            fall_damage_per_block: 1.0,
+            invulnerable_secs: 0.5,
+            starvation: true,
+            starvation_per_sec: 0.25,
+            keep_inventory: true,
+            min_build_y: -512.0,
+            max_build_y: 512.0,
+        }
+    }
+}
+
+impl GameplayRules {
+    fn number(&mut self, name: &str) -> Option<(&mut f32, RangeInclusive<f32>)> {
+        Some(match name {
+            "regen_per_sec" => (&mut self.regen_per_sec, 0.0..=10.0),
+            "regen_min_hunger" => (&mut self.regen_min_hunger, 0.0..=20.0),
+            "hunger_per_sec" => (&mut self.hunger_per_sec, 0.0..=1.0),
+            "idle_hunger" => (&mut self.idle_hunger, 0.0..=10.0),
+            "walking_hunger" => (&mut self.walking_hunger, 0.0..=10.0),
+            "sprinting_hunger" => (&mut self.sprinting_hunger, 0.0..=10.0),
+            "fall_damage_height" => (&mut self.fall_damage_height, 0.0..=256.0),
+            "fall_damage_per_block" => (&mut self.fall_damage_per_block, 0.0..=20.0),
+            "invulnerable_secs" => (&mut self.invulnerable_secs, 0.0..=10.0),
+            "starvation_per_sec" => (&mut self.starvation_per_sec, 0.0..=10.0),
+            "min_build_y" => (&mut self.min_build_y, -4096.0..=4096.0),
+            "max_build_y" => (&mut self.max_build_y, -4096.0..=4096.0),
+            _ => return None,
+        })
+    }
+
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "starvation" => Some(&mut self.starvation),
+            "keep_inventory" => Some(&mut self.keep_inventory),
+            _ => None,
+        }
+    }
+
+    // Written the way gameplay.ron spells it
+    pub fn value(&self, name: &str) -> Option<String> {
+        let mut rules = *self;
+        if let Some(flag) = rules.flag(name) {
+            return Some(flag.to_string());
+        }
+        rules.number(name).map(|(number, _)| format!("{number:?}"))
+    }
+
+    // Nothing changes unless the value is in range
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if let Some(flag) = self.flag(name) {
+            *flag = value
+                .parse()
+                .map_err(|_| format!("{name} is either true or false"))?;
+            return Ok(());
+        }
+        let Some((number, range)) = self.number(name) else {
+            return Err(format!("There's no rule called {name}"));
+        };
+        let parsed: f32 = value
+            .parse()
+            .map_err(|_| format!("{name} needs a number, not {value}"))?;
+        if !range.contains(&parsed) {
+            return Err(format!(
+                "{name} goes from {:?} to {:?}",
+                range.start(),
+                range.end()
+            ));
+        }
+        let previous = *number;
+        *number = parsed;
+        if let Err(e) = self.check_build_limits() {
+            self.set_number(name, previous);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn set_number(&mut self, name: &str, value: f32) {
+        if let Some((number, _)) = self.number(name) {
+            *number = value;
+        }
+    }
+
+    fn check_build_limits(&self) -> Result<(), String> {
+        if self.min_build_y > self.max_build_y {
+            return Err(format!(
+                "min_build_y is {:?} but can't be above max_build_y, {:?}",
+                self.min_build_y, self.max_build_y
+            ));
+        }
+        Ok(())
+    }
+
+    // Everything that changes blocks goes through this, placements, pastes and templates alike
+    pub fn within_build_limits(&self, voxel: IVec3) -> bool {
+        let y = voxel.y as f32;
+        y >= self.min_build_y && y <= self.max_build_y
+    }
+
+    // The first rule that's out of range, for files edited by hand
+    pub fn validate(&self) -> Result<(), String> {
+        let mut rules = *self;
+        for name in RULES {
+            if let Some((&mut number, range)) = rules.number(name) {
+                if !range.contains(&number) {
+                    return Err(format!(
+                        "{name} is {number:?} but goes from {:?} to {:?}",
+                        range.start(),
+                        range.end()
+                    ));
+                }
+            }
+        }
+        self.check_build_limits()
+    }
+
+    // Whether health is coming back on its own right now
+    pub fn regenerating(&self, health: f32, max_health: f32, hunger: f32) -> bool {
+        self.regen_per_sec > 0.0
+            && health > 0.0
+            && health < max_health
+            && hunger >= self.regen_min_hunger
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_are_checked_by_name() {
+        let mut rules = GameplayRules::default();
+        assert!(rules.validate().is_ok());
+        for name in RULES {
+            let value = rules.value(name).unwrap();
+            assert!(rules.set(name, &value).is_ok(), "{name} = {value}");
+        }
+        assert_eq!(rules, GameplayRules::default());
+
+        assert!(rules.set("invulnerable_secs", "1.5").is_ok());
+        assert_eq!(rules.invulnerable_secs, 1.5);
+        assert!(rules.set("keep_inventory", "false").is_ok());
+        assert!(!rules.keep_inventory);
+        // A bad value leaves the old one
+        assert!(rules.set("invulnerable_secs", "11").is_err());
+        assert!(rules.set("invulnerable_secs", "NaN").is_err());
+        assert!(rules.set("starvation", "yes").is_err());
+        assert!(rules.set("gravity", "2").is_err());
+        assert_eq!(rules.invulnerable_secs, 1.5);
+
+        rules.regen_min_hunger = -1.0;
+        assert!(rules
+            .validate()
+            .unwrap_err()
+            .starts_with("regen_min_hunger"));
+    }
+
+    #[test]
+    fn build_limits_include_both_ends() {
+        let mut rules = GameplayRules::default();
+        assert!(rules.set("max_build_y", "64").is_ok());
+        assert!(rules.set("min_build_y", "-8").is_ok());
+        assert!(rules.within_build_limits(IVec3::new(3, 64, -9)));
+        assert!(!rules.within_build_limits(IVec3::new(3, 65, -9)));
+        assert!(rules.within_build_limits(IVec3::new(0, -8, 0)));
+        assert!(!rules.within_build_limits(IVec3::new(0, -9, 0)));
+
+        // The two can't cross, and a refused change leaves the old limit
+        assert!(rules.set("min_build_y", "65").is_err());
+        assert_eq!(rules.min_build_y, -8.0);
+        assert!(rules.set("max_build_y", "-9").is_err());
+        assert_eq!(rules.max_build_y, 64.0);
+        // Both at the same height leaves a single layer
+        assert!(rules.set("min_build_y", "64").is_ok());
+        assert!(rules.within_build_limits(IVec3::new(0, 64, 0)));
+        assert!(!rules.within_build_limits(IVec3::new(0, 63, 0)));
+
+        rules.min_build_y = 100.0;
+        assert!(rules.validate().unwrap_err().starts_with("min_build_y"));
+    }
+}
@@ -1 +1,5 @@
+pub mod arrange;
 pub mod bundles;
+pub mod gameplay;
+pub mod rng;
+pub mod time;
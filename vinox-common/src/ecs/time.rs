@@ -0,0 +1,274 @@
+use std::ops::{Add, Sub};
+
+use bevy::{prelude::*, time::TimeSystem};
+use serde::{Deserialize, Serialize};
+
+// A long hitch, a suspended laptop or a debugger pause all show up as one huge frame.
+// Gameplay only ever sees this much of it
+pub const DEFAULT_MAX_DELTA: f32 = 0.1;
+pub const TICKS_PER_SECOND: u32 = 20;
+
+// Seconds of game time, only moves while the game runs and never jumps with the OS clock
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default, Serialize, Deserialize)]
+pub struct GameInstant(f64);
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default, Serialize, Deserialize)]
+pub struct GameDuration(f64);
+
+impl GameDuration {
+    pub const ZERO: Self = Self(0.0);
+
+    pub fn from_secs(secs: f32) -> Self {
+        Self(secs.max(0.0) as f64)
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        Self(millis as f64 / 1000.0)
+    }
+
+    pub fn as_secs(&self) -> f32 {
+        self.0 as f32
+    }
+}
+
+impl GameInstant {
+    // Seconds since the clock started, meant for animation phases
+    pub fn as_secs(&self) -> f32 {
+        self.0 as f32
+    }
+}
+
+impl Sub for GameInstant {
+    type Output = GameDuration;
+
+    // Saturates so an instant from a previous session can't make a negative duration
+    fn sub(self, earlier: Self) -> GameDuration {
+        GameDuration((self.0 - earlier.0).max(0.0))
+    }
+}
+
+impl Add<GameDuration> for GameInstant {
+    type Output = GameInstant;
+
+    fn add(self, duration: GameDuration) -> GameInstant {
+        GameInstant(self.0 + duration.0)
+    }
+}
+
+impl Add for GameDuration {
+    type Output = GameDuration;
+
+    fn add(self, other: Self) -> GameDuration {
+        GameDuration(self.0 + other.0)
+    }
+}
+
+// The only clock gameplay systems should read, Time itself is left for rendering and networking
+#[derive(Resource, Debug, Clone)]
+pub struct GameClock {
+    pub max_delta: f32,
+    pub paused: bool,
+    delta: f32,
+    elapsed: GameInstant,
+}
+
+impl Default for GameClock {
+    fn default() -> Self {
+        Self {
+            max_delta: DEFAULT_MAX_DELTA,
+            paused: false,
+            delta: 0.0,
+            elapsed: GameInstant::default(),
+        }
+    }
+}
+
+impl GameClock {
+    pub fn advance(&mut self, real_delta: f32) {
+        self.delta = if self.paused {
+            0.0
+        } else {
+            real_delta.clamp(0.0, self.max_delta)
+        };
+        self.elapsed = self.elapsed + GameDuration::from_secs(self.delta);
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta
+    }
+
+    pub fn delta(&self) -> GameDuration {
+        GameDuration::from_secs(self.delta)
+    }
+
+    pub fn now(&self) -> GameInstant {
+        self.elapsed
+    }
+
+    pub fn since(&self, instant: GameInstant) -> GameDuration {
+        self.now() - instant
+    }
+}
+
+// Counts fixed steps of game time on the server. Anything that gets saved or compared across
+// sessions (growth, cooldowns) is stamped with this instead of unix time
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq, Deref, Serialize, Deserialize)]
+pub struct ServerTick(pub u64);
+
+#[derive(Resource, Default)]
+pub struct TickAccumulator(pub f32);
+
+impl ServerTick {
+    pub const LENGTH: f32 = 1.0 / TICKS_PER_SECOND as f32;
+
+    // Returns how many ticks passed, bounded by the clock's clamp so a suspend can't fast forward
+    pub fn advance(&mut self, accumulator: &mut TickAccumulator, delta: f32) -> u64 {
+        accumulator.0 += delta;
+        let mut ticks = 0;
+        while accumulator.0 >= Self::LENGTH {
+            accumulator.0 -= Self::LENGTH;
+            ticks += 1;
+        }
+        self.0 += ticks;
+        ticks
+    }
+}
+
+pub fn update_game_clock(time: Res<Time>, mut clock: ResMut<GameClock>) {
+    clock.advance(time.delta_seconds());
+}
+
+pub fn update_server_tick(
+    clock: Res<GameClock>,
+    mut tick: ResMut<ServerTick>,
+    mut accumulator: ResMut<TickAccumulator>,
+) {
+    tick.advance(&mut accumulator, clock.delta_seconds());
+}
+
+pub struct GameClockPlugin;
+
+impl Plugin for GameClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameClock>().add_system(
+            update_game_clock
+                .in_base_set(CoreSet::First)
+                .after(TimeSystem),
+        );
+    }
+}
+
+// Only the server owns ticks, clients get told about anything tick stamped
+pub struct ServerTickPlugin;
+
+impl Plugin for ServerTickPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerTick>()
+            .init_resource::<TickAccumulator>()
+            .add_system(
+                update_server_tick
+                    .in_base_set(CoreSet::First)
+                    .after(update_game_clock),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::chunks::storage::{BlockData, GrowthState};
+    use std::time::{Duration, Instant};
+
+    const BREAK_TIME: f32 = 1.5;
+    const AFK_LIMIT: f32 = 300.0;
+    const TICKS_PER_STAGE: u64 = 200;
+
+    #[derive(Resource)]
+    struct Gameplay {
+        break_progress: GameDuration,
+        last_active: GameInstant,
+        crop: BlockData,
+        kicked: bool,
+    }
+
+    // Stand ins for breaking, afk kicking and crop growth, each reading time the way they should
+    fn run_gameplay(clock: Res<GameClock>, tick: Res<ServerTick>, mut gameplay: ResMut<Gameplay>) {
+        gameplay.break_progress = gameplay.break_progress + clock.delta();
+        if clock.since(gameplay.last_active).as_secs() > AFK_LIMIT {
+            gameplay.kicked = true;
+        }
+        gameplay.crop.grow(*tick, TICKS_PER_STAGE);
+    }
+
+    fn harness() -> (App, Instant) {
+        let start = Instant::now();
+        let mut app = App::new();
+        let mut crop = BlockData::new("vinox".to_string(), "wheat".to_string());
+        crop.growth_state = Some(GrowthState::Planted);
+        app.insert_resource(Time::new(start))
+            .add_plugin(GameClockPlugin)
+            .add_plugin(ServerTickPlugin)
+            .insert_resource(Gameplay {
+                break_progress: GameDuration::ZERO,
+                last_active: GameInstant::default(),
+                crop,
+                kicked: false,
+            })
+            .add_system(run_gameplay);
+        (app, start)
+    }
+
+    fn step(app: &mut App, at: Instant) {
+        app.world.resource_mut::<Time>().update_with_instant(at);
+        app.update();
+    }
+
+    #[test]
+    fn suspend_is_one_clamped_frame() {
+        let (mut app, start) = harness();
+        // Someone starts mining, the crop has been waiting almost a whole stage
+        step(&mut app, start);
+        app.world.resource_mut::<ServerTick>().0 = TICKS_PER_STAGE - 1;
+        app.world.resource_mut::<Gameplay>().crop.last_tick = Some(0);
+
+        let resume = start + Duration::from_secs(3600);
+        step(&mut app, resume);
+        let clock = app.world.resource::<GameClock>();
+        assert_eq!(clock.delta_seconds(), DEFAULT_MAX_DELTA);
+        let gameplay = app.world.resource::<Gameplay>();
+        assert!(gameplay.break_progress.as_secs() <= DEFAULT_MAX_DELTA);
+        assert!(gameplay.break_progress.as_secs() < BREAK_TIME);
+        assert!(!gameplay.kicked);
+        assert_eq!(gameplay.crop.growth_state, Some(GrowthState::Sapling));
+        let ticks = **app.world.resource::<ServerTick>();
+        assert!(ticks - (TICKS_PER_STAGE - 1) <= 2);
+
+        // Plenty of normal frames later the crop has only moved one stage
+        let mut now = resume;
+        for _ in 0..20 {
+            now += Duration::from_millis(16);
+            step(&mut app, now);
+        }
+        let gameplay = app.world.resource::<Gameplay>();
+        assert_eq!(gameplay.crop.growth_state, Some(GrowthState::Sapling));
+        assert!(!gameplay.kicked);
+
+        // Idling for real still gets you kicked eventually
+        for _ in 0..(AFK_LIMIT / DEFAULT_MAX_DELTA) as u32 + 10 {
+            now += Duration::from_millis(100);
+            step(&mut app, now);
+        }
+        assert!(app.world.resource::<Gameplay>().kicked);
+    }
+
+    #[test]
+    fn pause_stops_the_clock() {
+        let mut clock = GameClock::default();
+        clock.advance(0.05);
+        let before = clock.now();
+        clock.paused = true;
+        clock.advance(0.05);
+        assert_eq!(clock.since(before), GameDuration::ZERO);
+        assert_eq!(clock.delta_seconds(), 0.0);
+    }
+}
@@ -3,4 +3,7 @@ pub mod networking;
 pub mod physics;
 pub mod scripting;
 pub mod storage;
+// Test builders, other crates get them through the testing feature
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod world;
@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+
+// How many unacknowledged intents a client keeps around to send again. Past this the oldest
+// are lost and only a full resync can bring the server back in line
+pub const MAX_PENDING_INTENTS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingIntent<T> {
+    pub seq: u32,
+    pub intent: T,
+    pub sent: f32,
+}
+
+// Things the client already applied and sent off, oldest first, until the server settles them
+#[derive(Debug, Clone)]
+pub struct IntentChannel<T> {
+    next: u32,
+    pending: VecDeque<PendingIntent<T>>,
+    capacity: usize,
+    overflowed: bool,
+}
+
+impl<T> Default for IntentChannel<T> {
+    fn default() -> Self {
+        Self::with_capacity(MAX_PENDING_INTENTS)
+    }
+}
+
+impl<T> IntentChannel<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            // 0 is what the server has applied before anything arrived
+            next: 1,
+            pending: VecDeque::new(),
+            capacity,
+            overflowed: false,
+        }
+    }
+
+    pub fn push(&mut self, intent: T, now: f32) -> u32 {
+        let seq = self.next;
+        self.next += 1;
+        if self.pending.len() >= self.capacity {
+            self.pending.pop_front();
+            self.overflowed = true;
+        }
+        self.pending.push_back(PendingIntent {
+            seq,
+            intent,
+            sent: now,
+        });
+        seq
+    }
+
+    pub fn last_seq(&self) -> u32 {
+        self.next - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PendingIntent<T>> {
+        self.pending.iter()
+    }
+
+    // For intents the server applies strictly in order, one ack covers everything before it
+    pub fn ack_through(&mut self, seq: u32) {
+        while self
+            .pending
+            .front()
+            .is_some_and(|pending| pending.seq <= seq)
+        {
+            self.pending.pop_front();
+        }
+    }
+
+    // For intents the server takes independently, settles the oldest one that matches
+    pub fn settle(&mut self, matches: impl Fn(&T) -> bool) -> Option<T> {
+        let index = self
+            .pending
+            .iter()
+            .position(|pending| matches(&pending.intent))?;
+        self.pending.remove(index).map(|pending| pending.intent)
+    }
+
+    // Drops whatever has waited longer than max_age, it isn't getting an answer anymore
+    pub fn expire(&mut self, now: f32, max_age: f32) {
+        self.pending.retain(|pending| now - pending.sent < max_age);
+    }
+
+    // Everything from seq on goes out again, None when some of it already fell out of the
+    // journal and only a resync will do
+    pub fn resend_from(&mut self, seq: u32, now: f32) -> Option<Vec<&PendingIntent<T>>> {
+        if self.needs_resync(seq) {
+            return None;
+        }
+        let resend: Vec<_> = self
+            .pending
+            .iter_mut()
+            .filter(|pending| pending.seq >= seq)
+            .map(|pending| {
+                pending.sent = now;
+                &*pending
+            })
+            .collect();
+        Some(resend)
+    }
+
+    // Whether the intents from seq on are all still here
+    pub fn needs_resync(&self, seq: u32) -> bool {
+        self.overflowed
+            && self
+                .pending
+                .front()
+                .map_or(seq < self.next, |oldest| seq < oldest.seq)
+    }
+
+    // A full snapshot covers everything pending, returns the seq it stands in for
+    pub fn resynced(&mut self) -> u32 {
+        self.pending.clear();
+        self.overflowed = false;
+        self.last_seq()
+    }
+
+    // Oldest send time among what's pending, to tell when an ack is overdue
+    pub fn oldest_sent(&self) -> Option<f32> {
+        self.pending
+            .iter()
+            .map(|pending| pending.sent)
+            .reduce(f32::min)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arrival {
+    Apply,
+    // Already applied, a resend crossing the ack
+    Duplicate,
+    // Something before it went missing, ask for everything from expected
+    Gap { expected: u32 },
+    // Still missing the same one, it was already asked for
+    Waiting,
+}
+
+// The server's side of an IntentChannel whose intents build on each other
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntentCursor {
+    applied: u32,
+    gap_reported: bool,
+}
+
+impl IntentCursor {
+    pub fn applied(&self) -> u32 {
+        self.applied
+    }
+
+    // Only the next one in line is ever applied, never one that skipped ahead
+    pub fn arrive(&mut self, seq: u32) -> Arrival {
+        if seq <= self.applied {
+            Arrival::Duplicate
+        } else if seq == self.applied + 1 {
+            self.applied = seq;
+            self.gap_reported = false;
+            Arrival::Apply
+        } else if self.gap_reported {
+            Arrival::Waiting
+        } else {
+            self.gap_reported = true;
+            Arrival::Gap {
+                expected: self.applied + 1,
+            }
+        }
+    }
+
+    // A full snapshot replaced whatever was applied up to seq
+    pub fn resync(&mut self, seq: u32) {
+        self.applied = seq;
+        self.gap_reported = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Delivers to the cursor, keeping what got applied
+    fn deliver(
+        cursor: &mut IntentCursor,
+        applied: &mut Vec<char>,
+        seq: u32,
+        intent: char,
+    ) -> Arrival {
+        let arrival = cursor.arrive(seq);
+        if arrival == Arrival::Apply {
+            applied.push(intent);
+        }
+        arrival
+    }
+
+    #[test]
+    fn gaps_are_reported_once_and_nothing_skips_ahead() {
+        let mut cursor = IntentCursor::default();
+        let mut applied = Vec::new();
+        assert_eq!(deliver(&mut cursor, &mut applied, 1, 'a'), Arrival::Apply);
+        // 2 got lost on the way
+        assert_eq!(
+            deliver(&mut cursor, &mut applied, 3, 'c'),
+            Arrival::Gap { expected: 2 }
+        );
+        assert_eq!(deliver(&mut cursor, &mut applied, 4, 'd'), Arrival::Waiting);
+        assert_eq!(applied, vec!['a']);
+        assert_eq!(cursor.applied(), 1);
+
+        // The resend from 2 fills it in, in order
+        for (seq, intent) in [(2, 'b'), (3, 'c'), (4, 'd')] {
+            assert_eq!(
+                deliver(&mut cursor, &mut applied, seq, intent),
+                Arrival::Apply
+            );
+        }
+        assert_eq!(applied, vec!['a', 'b', 'c', 'd']);
+        // A late copy of one that already went through changes nothing
+        assert_eq!(
+            deliver(&mut cursor, &mut applied, 3, 'c'),
+            Arrival::Duplicate
+        );
+        assert_eq!(applied.len(), 4);
+        // A second gap gets reported again
+        assert_eq!(
+            deliver(&mut cursor, &mut applied, 6, 'f'),
+            Arrival::Gap { expected: 5 }
+        );
+    }
+
+    #[test]
+    fn acks_clear_everything_up_to_them() {
+        let mut channel = IntentChannel::default();
+        for intent in ['a', 'b', 'c'] {
+            channel.push(intent, 0.0);
+        }
+        channel.ack_through(2);
+        let left: Vec<char> = channel.iter().map(|pending| pending.intent).collect();
+        assert_eq!(left, vec!['c']);
+        // Settling out of order, like block edits do
+        channel.push('d', 1.0);
+        assert_eq!(channel.settle(|intent| *intent == 'd'), Some('d'));
+        assert_eq!(channel.settle(|intent| *intent == 'd'), None);
+        channel.expire(10.0, 5.0);
+        assert!(channel.is_empty());
+    }
+
+    #[test]
+    fn reconnecting_replays_what_was_never_acked() {
+        let mut channel = IntentChannel::default();
+        let mut cursor = IntentCursor::default();
+        let mut applied = Vec::new();
+        for intent in ['a', 'b', 'c', 'd'] {
+            channel.push(intent, 0.0);
+        }
+        // Only the first two made it before the connection dropped
+        for pending in channel.iter().take(2) {
+            deliver(&mut cursor, &mut applied, pending.seq, pending.intent);
+        }
+        channel.ack_through(1);
+
+        // The ack for 2 was lost too, so the replay starts there and the server skips it
+        let replay: Vec<(u32, char)> = channel
+            .resend_from(2, 3.0)
+            .unwrap()
+            .into_iter()
+            .map(|pending| (pending.seq, pending.intent))
+            .collect();
+        assert_eq!(replay, vec![(2, 'b'), (3, 'c'), (4, 'd')]);
+        for (seq, intent) in replay {
+            deliver(&mut cursor, &mut applied, seq, intent);
+        }
+        assert_eq!(applied, vec!['a', 'b', 'c', 'd']);
+        channel.ack_through(cursor.applied());
+        assert!(channel.is_empty());
+        assert_eq!(channel.oldest_sent(), None);
+    }
+
+    #[test]
+    fn overflowing_the_journal_falls_back_to_a_resync() {
+        let mut channel = IntentChannel::with_capacity(3);
+        let mut cursor = IntentCursor::default();
+        for intent in ['a', 'b', 'c', 'd', 'e'] {
+            channel.push(intent, 0.0);
+        }
+        assert_eq!(channel.len(), 3);
+        // The server still wants 1, which is gone
+        let Arrival::Gap { expected } = cursor.arrive(3) else {
+            panic!("3 should have left a gap");
+        };
+        assert!(channel.needs_resync(expected));
+        assert!(channel.resend_from(expected, 1.0).is_none());
+        // What's still here can be replayed on its own
+        assert!(!channel.needs_resync(3));
+
+        // The snapshot stands in for all five
+        let seq = channel.resynced();
+        assert_eq!(seq, 5);
+        cursor.resync(seq);
+        assert!(channel.is_empty());
+        assert_eq!(channel.push('f', 2.0), 6);
+        assert_eq!(cursor.arrive(6), Arrival::Apply);
+    }
+}
@@ -1 +1,6 @@
+pub mod intents;
+// Only in debug builds unless asked for, release players should never be able to lag themselves
+#[cfg(any(debug_assertions, feature = "netsim"))]
+pub mod netsim;
 pub mod protocol;
+pub mod schema;
@@ -0,0 +1,275 @@
+use std::{collections::VecDeque, fmt, time::Duration};
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// One direction of a simulated link, all off by default
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkConditions {
+    pub latency: u32,
+    pub jitter: u32,
+    // Percent of messages thrown away
+    pub loss: f32,
+    // Lets messages overtake each other inside the jitter window
+    pub reorder: bool,
+}
+
+impl LinkConditions {
+    pub fn is_active(&self) -> bool {
+        self.latency > 0 || self.jitter > 0 || self.loss > 0.0
+    }
+}
+
+impl fmt::Display for LinkConditions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}±{} ms, {}% loss",
+            self.latency, self.jitter, self.loss
+        )?;
+        if self.reorder {
+            write!(f, ", reordering")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkConditions {
+    pub outgoing: LinkConditions,
+    pub incoming: LinkConditions,
+}
+
+impl NetworkConditions {
+    pub fn is_active(&self) -> bool {
+        self.outgoing.is_active() || self.incoming.is_active()
+    }
+}
+
+#[derive(Debug)]
+pub enum NetsimError {
+    UnknownSetting(String),
+    MissingValue(String),
+    BadValue(String),
+}
+
+impl fmt::Display for NetsimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetsimError::UnknownSetting(setting) => write!(f, "Unknown setting {setting}"),
+            NetsimError::MissingValue(setting) => write!(f, "{setting} needs a value"),
+            NetsimError::BadValue(value) => write!(f, "{value} isn't a valid value"),
+        }
+    }
+}
+
+// Arguments after /netsim: an optional `in` or `out` then setting value pairs, or `off`.
+// Without a direction the settings go to both, each keeping its other values
+pub fn parse_netsim(args: &str, conditions: &mut NetworkConditions) -> Result<(), NetsimError> {
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let (outgoing, incoming) = match words.first().copied() {
+        Some("out") => (true, false),
+        Some("in") => (false, true),
+        _ => (true, true),
+    };
+    if outgoing != incoming {
+        words.remove(0);
+    }
+    let out_link = apply_settings(&words, conditions.outgoing)?;
+    let in_link = apply_settings(&words, conditions.incoming)?;
+    if outgoing {
+        conditions.outgoing = out_link;
+    }
+    if incoming {
+        conditions.incoming = in_link;
+    }
+    Ok(())
+}
+
+fn apply_settings(words: &[&str], mut link: LinkConditions) -> Result<LinkConditions, NetsimError> {
+    let mut words = words.iter().copied();
+    while let Some(setting) = words.next() {
+        if setting == "off" {
+            link = LinkConditions::default();
+            continue;
+        }
+        let value = words
+            .next()
+            .ok_or_else(|| NetsimError::MissingValue(setting.to_string()))?;
+        let bad_value = || NetsimError::BadValue(value.to_string());
+        match setting {
+            "latency" => link.latency = value.parse().map_err(|_| bad_value())?,
+            "jitter" => link.jitter = value.parse().map_err(|_| bad_value())?,
+            "loss" => {
+                let loss: f32 = value.parse().map_err(|_| bad_value())?;
+                if !(0.0..=100.0).contains(&loss) {
+                    return Err(bad_value());
+                }
+                link.loss = loss;
+            }
+            "reorder" => link.reorder = value.parse().map_err(|_| bad_value())?,
+            _ => return Err(NetsimError::UnknownSetting(setting.to_string())),
+        }
+    }
+    Ok(link)
+}
+
+// Holds messages back until they're due, drained by elapsed time rather than frames
+pub struct Conditioner<T> {
+    queue: VecDeque<(Duration, T)>,
+    last_due: Duration,
+    rng: StdRng,
+}
+
+impl<T> Default for Conditioner<T> {
+    fn default() -> Self {
+        Self::with_rng(StdRng::from_entropy())
+    }
+}
+
+impl<T> Conditioner<T> {
+    pub fn new(seed: u64) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(rng: StdRng) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            last_due: Duration::ZERO,
+            rng,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // False when the message was dropped
+    pub fn push(&mut self, conditions: &LinkConditions, now: Duration, message: T) -> bool {
+        if conditions.loss > 0.0 && self.rng.gen_range(0.0..100.0) < conditions.loss {
+            return false;
+        }
+        let jitter = conditions.jitter as i64;
+        let delay = conditions.latency as i64 + self.rng.gen_range(-jitter..=jitter);
+        let due = now + Duration::from_millis(delay.max(0) as u64);
+        if conditions.reorder {
+            let index = self.queue.partition_point(|(other, _)| *other <= due);
+            self.queue.insert(index, (due, message));
+        } else {
+            // Never ahead of anything already queued so the order survives any jitter
+            let due = due.max(self.last_due);
+            self.last_due = due;
+            self.queue.push_back((due, message));
+        }
+        true
+    }
+
+    pub fn pop(&mut self, now: Duration) -> Option<T> {
+        match self.queue.front() {
+            Some((due, _)) if *due <= now => self.queue.pop_front().map(|(_, message)| message),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sends one message per millisecond and drains every millisecond,
+    // returning (sent, delivered) times for everything that arrived
+    fn run(conditions: LinkConditions, count: u64) -> Vec<(u64, u64)> {
+        let mut conditioner = Conditioner::new(7);
+        let mut delivered = Vec::new();
+        for millis in 0..count + 1000 {
+            let now = Duration::from_millis(millis);
+            if millis < count {
+                conditioner.push(&conditions, now, millis);
+            }
+            while let Some(sent) = conditioner.pop(now) {
+                delivered.push((sent, millis));
+            }
+        }
+        assert!(conditioner.is_empty());
+        delivered
+    }
+
+    #[test]
+    fn delivery_within_bounds() {
+        let conditions = LinkConditions {
+            latency: 120,
+            jitter: 30,
+            ..default()
+        };
+        let delivered = run(conditions, 500);
+        assert_eq!(delivered.len(), 500);
+        for (sent, at) in &delivered {
+            assert!((90..=150).contains(&(at - sent)), "{sent} arrived at {at}");
+        }
+        // Ordering is kept when reordering is off
+        assert!(delivered.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let reordered = run(
+            LinkConditions {
+                reorder: true,
+                ..conditions
+            },
+            500,
+        );
+        assert!(reordered.windows(2).any(|pair| pair[0].0 > pair[1].0));
+        for (sent, at) in &reordered {
+            assert!((90..=150).contains(&(at - sent)));
+        }
+
+        // Nothing set delivers on the same tick
+        assert!(run(LinkConditions::default(), 50)
+            .iter()
+            .all(|(sent, at)| sent == at));
+    }
+
+    #[test]
+    fn drops_match_loss() {
+        let delivered = run(
+            LinkConditions {
+                loss: 2.0,
+                ..default()
+            },
+            20000,
+        );
+        let dropped = 20000 - delivered.len();
+        // 400 expected, about five standard deviations either way
+        assert!((300..=500).contains(&dropped), "{dropped} dropped");
+    }
+
+    #[test]
+    fn parses_commands() {
+        let mut conditions = NetworkConditions::default();
+        parse_netsim("latency 120 jitter 30 loss 2", &mut conditions).unwrap();
+        assert_eq!(conditions.outgoing, conditions.incoming);
+        assert_eq!(conditions.outgoing.latency, 120);
+        assert_eq!(conditions.outgoing.loss, 2.0);
+
+        parse_netsim("in latency 300 reorder true", &mut conditions).unwrap();
+        assert_eq!(conditions.incoming.latency, 300);
+        assert!(conditions.incoming.reorder);
+        assert_eq!(conditions.outgoing.latency, 120);
+
+        parse_netsim("out off", &mut conditions).unwrap();
+        assert!(!conditions.outgoing.is_active());
+        assert!(conditions.incoming.is_active());
+
+        parse_netsim("jitter 10", &mut conditions).unwrap();
+        assert_eq!(conditions.incoming.latency, 300);
+        assert!(conditions.incoming.reorder);
+        assert_eq!(conditions.incoming.jitter, 10);
+        assert_eq!(conditions.outgoing.jitter, 10);
+
+        assert!(parse_netsim("loss 200", &mut conditions).is_err());
+        assert!(parse_netsim("latency", &mut conditions).is_err());
+        assert!(parse_netsim("bandwidth 10", &mut conditions).is_err());
+    }
+}
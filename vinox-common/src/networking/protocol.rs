@@ -1,28 +1,157 @@
-use bevy::prelude::*;
+use bevy::{math::DVec3, prelude::*};
 use bevy_quinnet::shared::ClientId;
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct NetworkIP(pub String);
 
+// Bump whenever a message changes shape, clients on a different version are turned away on join.
+// New messages and fields also go into the mirror in schema.rs, whose tests hold the goldens
+pub const PROTOCOL_VERSION: u32 = 28;
+
+// Limits are in characters, not bytes, a CJK name takes three bytes a character
+pub const MAX_NAME_CHARS: usize = 32;
+pub const MAX_CHAT_CHARS: usize = 256;
+pub const MAX_ITEM_NAME_CHARS: usize = 48;
+// Chunks a client can list in one CachedChunks, longer lists go over several
+pub const CACHED_CHUNKS_PER_MESSAGE: usize = 1024;
+
+pub fn valid_user_name(user_name: &str) -> bool {
+    !user_name.trim().is_empty()
+        && user_name.chars().count() <= MAX_NAME_CHARS
+        && !user_name.chars().any(char::is_control)
+}
+
+// Cuts on a character boundary so multi byte text is never split
+pub fn truncate_chars(text: &str, max_chars: usize) -> &str {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => &text[..index],
+        None => text,
+    }
+}
+
 use serde::{Deserialize, Serialize};
 
-use crate::{ecs::bundles::Inventory, world::chunks::storage::BlockData};
+use crate::{
+    ecs::{
+        arrange::InventoryOp,
+        bundles::{Health, Hunger, Inventory, SlotRef},
+        gameplay::GameplayRules,
+    },
+    physics::movement::{MovementInput, MovementState},
+    storage::{
+        content::{ContentManifest, ContentPolicy},
+        items::descriptor::ItemData,
+    },
+    world::chunks::{biome_map::ChunkBiomes, positions::DimensionId, storage::BlockData},
+};
 
 #[derive(Component)]
 pub struct NetworkedEntity;
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EntityKind {
+    #[default]
+    Critter,
+    DroppedItem,
+}
+
+// What gets written into saved_entities when the chunk an entity is in unloads
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SavedEntity {
+    pub kind: EntityKind,
+    pub translation: Vec3,
+    // The stack lying there when kind is DroppedItem
+    #[serde(default)]
+    pub item: Option<ItemData>,
+}
+
 #[derive(Debug, Component, Default)]
 pub struct Player {
     pub id: ClientId,
 }
 
+// On players that hold things in their left hand, only changes how they're drawn
+#[derive(Debug, Component, Default, Clone, Copy)]
+pub struct LeftHanded;
+
+// What a player is doing that changes how everyone else draws them, one byte on the wire. Bits
+// this version doesn't know about are carried but never read, so new ones can be added without
+// breaking older clients
+#[derive(Debug, Component, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PlayerFlags(u8);
+
+impl PlayerFlags {
+    pub const SNEAKING: u8 = 1;
+    pub const SPRINTING: u8 = 1 << 1;
+    pub const SWIMMING: u8 = 1 << 2;
+    pub const ON_GROUND: u8 = 1 << 3;
+    pub const KNOWN: u8 = Self::SNEAKING | Self::SPRINTING | Self::SWIMMING | Self::ON_GROUND;
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    // Only the bits this version understands, what the server passes on
+    pub fn known(self) -> Self {
+        Self(self.0 & Self::KNOWN)
+    }
+
+    pub fn contains(self, flag: u8) -> bool {
+        self.0 & flag == flag
+    }
+
+    pub fn with(self, flag: u8, on: bool) -> Self {
+        if on {
+            Self(self.0 | flag)
+        } else {
+            Self(self.0 & !flag)
+        }
+    }
+
+    pub fn sneaking(self) -> bool {
+        self.contains(Self::SNEAKING)
+    }
+
+    pub fn sprinting(self) -> bool {
+        self.contains(Self::SPRINTING)
+    }
+
+    pub fn swimming(self) -> bool {
+        self.contains(Self::SWIMMING)
+    }
+
+    pub fn on_ground(self) -> bool {
+        self.contains(Self::ON_GROUND)
+    }
+
+    // Sneaking in water or a ladder means going down rather than creeping, and holding sprint
+    // while standing still isn't sprinting
+    pub fn from_movement(state: MovementState, input: &MovementInput) -> Self {
+        let moving = input.direction != Vec3::ZERO;
+        let walking = matches!(state, MovementState::Grounded | MovementState::Airborne);
+        Self::default()
+            .with(Self::SNEAKING, input.sneak && walking)
+            .with(Self::SPRINTING, input.sprint && moving && !input.sneak)
+            .with(Self::SWIMMING, state == MovementState::Swimming)
+            .with(Self::ON_GROUND, state == MovementState::Grounded)
+    }
+}
+
 // Networking related
+// Positions on the wire are f64 so a client far from the origin gets them exactly, it keeps
+// its own render space near zero and converts with WorldOffset
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct NetworkedEntities {
     pub entities: Vec<Entity>,
-    pub translations: Vec<Vec3>,
+    pub translations: Vec<DVec3>,
     pub yaws: Vec<f32>,
     pub head_pitchs: Vec<f32>,
+    pub left_handed: Vec<bool>,
+    pub flags: Vec<PlayerFlags>,
 }
 
 #[derive(Default, Resource)]
@@ -30,12 +159,71 @@ pub struct EntityBuffer {
     pub entities: [NetworkedEntities; 30],
 }
 
+// Positions travel in 1/256ths of a block and angles in tenths of a degree. Both ends unpack
+// with the same functions so the server ends up with exactly what the client sent
+pub const POSITION_STEPS: f64 = 256.0;
+pub const ANGLE_STEPS: f32 = 10.0;
+
+// Wrapped into -180..180 degrees first, so any angle fits
+fn pack_angle(radians: f32) -> i16 {
+    let degrees = (radians.to_degrees() + 180.0).rem_euclid(360.0) - 180.0;
+    (degrees * ANGLE_STEPS).round() as i16
+}
+
+fn unpack_angle(steps: i16) -> f32 {
+    (steps as f32 / ANGLE_STEPS).to_radians()
+}
+
+// Covers a little over 8 million blocks out from the origin on each axis
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PackedPose {
+    pub position: [i32; 3],
+    pub yaw: i16,
+    pub head_pitch: i16,
+}
+
+impl PackedPose {
+    pub fn pack(position: DVec3, yaw: f32, head_pitch: f32) -> Self {
+        Self {
+            position: (position * POSITION_STEPS)
+                .round()
+                .to_array()
+                .map(|axis| axis as i32),
+            yaw: pack_angle(yaw),
+            head_pitch: pack_angle(head_pitch),
+        }
+    }
+
+    pub fn position(&self) -> DVec3 {
+        DVec3::from_array(self.position.map(|axis| axis as f64)) / POSITION_STEPS
+    }
+
+    pub fn yaw(&self) -> f32 {
+        unpack_angle(self.yaw)
+    }
+
+    pub fn head_pitch(&self) -> f32 {
+        unpack_angle(self.head_pitch)
+    }
+}
+
+// A chunk the client has on disk, hash is the one it came with in LevelData
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct CachedChunk {
+    pub dimension: DimensionId,
+    pub pos: IVec3,
+    pub hash: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ClientMessage {
+    // Sent at a rate that follows how much the player is moving, see the client's sender
     Position {
-        player_pos: Vec3,
-        yaw: f32,
-        head_pitch: f32,
+        pose: PackedPose,
+        // Cosmetic, everyone else mirrors this player's arm
+        left_handed: bool,
+        // Cosmetic too, drives how everyone else animates this player
+        flags: PlayerFlags,
     },
     Interact {
         entity: Entity,
@@ -46,10 +234,26 @@ pub enum ClientMessage {
         chunk_pos: IVec3,
         voxel_pos: [u8; 3],
         block_type: BlockData,
+        // Identifier of the held item that made the edit, the server times it by its own copy
+        // and only uses this to notice the two have drifted
+        item: Option<String>,
+        // Slot of the held tool, the server wears down whatever its copy holds there
+        tool: Option<SlotRef>,
+        // Where a placed block came out of, which isn't always the selected slot
+        slot: Option<SlotRef>,
     },
     Join {
         user_name: String, //TODO: Make sure client doesn't send same user_name as another. Also add some sort of password system
         id: ClientId,
+        protocol: u32,
+        // The server holds off on chunks until the client has listed what it has cached
+        chunk_cache: bool,
+        // Chunks out from the player horizontally, the server keeps it within its limits
+        view_distance: u8,
+    },
+    // The view distance changed mid game, chunks past it stop coming and closer ones start
+    ViewDistance {
+        horizontal: u8,
     },
     Leave {
         id: ClientId,
@@ -57,6 +261,145 @@ pub enum ClientMessage {
     ChatMessage {
         message: String,
     },
+    // Creative palette asking for a full stack of an item
+    PickItem {
+        identifier: String,
+    },
+    // The server answers with a CraftResult, items are only taken once it accepts
+    Craft {
+        recipe: String,
+    },
+    // First time this session an item showed up in the inventory, for recipe unlocks
+    ObtainedItem {
+        identifier: String,
+    },
+    // Throws count items out of the slot, item is what the client thinks is in it. Answered
+    // with a DropResult either way
+    DropItem {
+        slot: SlotRef,
+        count: u32,
+        item: ItemData,
+    },
+    // Asks for a dropped item the client is standing next to and has room for
+    PickUp {
+        entity: Entity,
+    },
+    // Right clicked a block that does something on its own, like a bed or a seat
+    UseBlock {
+        voxel: IVec3,
+    },
+    // Jumped or sneaked while seated
+    Dismount,
+    // Right clicked a display frame. An insert has already left the slot, a DropResult puts it
+    // back if the frame turns it down and a PickedUp hands over whatever comes out
+    UseFrame {
+        voxel: IVec3,
+        request: FrameRequest,
+    },
+    // A rearrangement the client already made, seq comes from its IntentChannel
+    InventoryOp {
+        seq: u32,
+        op: InventoryOp,
+    },
+    // Stands in for every op up to seq once the ones the server is missing are gone
+    InventoryResync {
+        seq: u32,
+        inventory: Box<Inventory>,
+    },
+    // What the client has cached from this world, done is set on the last batch
+    CachedChunks {
+        chunks: Vec<CachedChunk>,
+        done: bool,
+    },
+    // A chunk the server said was still valid couldn't be read back, it has to come again
+    ChunkCacheMiss {
+        pos: IVec3,
+        dimension: DimensionId,
+    },
+    // Ctrl+pick, operators get a BlockTemplate back with everything the block carries
+    PickBlockFull {
+        chunk_pos: IVec3,
+        voxel_pos: [u8; 3],
+    },
+    // Places a held template as it is instead of the block the held item makes. The server
+    // strips what a player couldn't set and puts it back with Forbidden if that isn't enough
+    PlaceTemplate {
+        chunk_pos: IVec3,
+        voxel_pos: [u8; 3],
+        block: BlockData,
+    },
+    // Scrolled or pressed to another hotbar slot, so the server knows what's held
+    SelectSlot {
+        bar: u8,
+        slot: u8,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum FrameRequest {
+    // One of what's in the slot goes into an empty frame
+    Insert { slot: SlotRef, item: ItemData },
+    Take,
+    Rotate,
+}
+
+// Why the server won't let a client in, it disconnects them shortly after sending this
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum JoinRejection {
+    VersionMismatch { server: u32 },
+    ServerFull,
+    // Bans and whitelists, with whatever the server wants to tell them
+    Denied { reason: String },
+}
+
+// How well the server is keeping up with its ticks
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServerHealth {
+    #[default]
+    Healthy,
+    // Shedding ambient simulation to catch up
+    Degraded,
+    // Dropping ticks, the world runs slower than real time
+    Overloaded,
+}
+
+// What a chat line is about, clients decide per category where it shows up
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ChatCategory {
+    #[default]
+    PlayerChat,
+    // Joins, leaves, /say and the server going down
+    System,
+    Death,
+    // Replies to a command, only ever sent to whoever ran it
+    CommandOutput,
+    // Client side popups like recipe unlocks, the server never sends these
+    Toast,
+}
+
+impl ChatCategory {
+    pub const ALL: [ChatCategory; 5] = [
+        ChatCategory::PlayerChat,
+        ChatCategory::System,
+        ChatCategory::Death,
+        ChatCategory::CommandOutput,
+        ChatCategory::Toast,
+    ];
+}
+
+// Why the server put a block back instead of taking a client's edit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DenyReason {
+    // Used again before the held item's cooldown was up, or a block broken quicker than holding
+    // the button down could have
+    TooFast,
+    // Placed into a voxel something else already filled
+    Occupied,
+    // A template from someone who can't place them, something illegal in a placed block, or a
+    // block placed without holding it
+    Forbidden,
+    // Above max_build_y or below min_build_y
+    OutsideBuildLimit,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -65,6 +408,7 @@ pub enum ServerMessage {
         user_name: String,
         message: String,
         id: u64,
+        category: ChatCategory,
     },
     ClientId {
         id: ClientId,
@@ -72,7 +416,7 @@ pub enum ServerMessage {
     PlayerCreate {
         entity: Entity,
         id: ClientId,
-        translation: Vec3,
+        translation: DVec3,
         yaw: f32,
         head_pitch: f32,
         user_name: String,
@@ -86,12 +430,317 @@ pub enum ServerMessage {
         chunk_pos: IVec3,
         voxel_pos: [u8; 3],
         block_type: BlockData,
+        dimension: DimensionId,
+        // Set only on the reply to a client whose own edit got put back
+        denied: Option<DenyReason>,
     },
     NetworkedEntities {
         networked_entities: NetworkedEntities,
     },
+    EntityCreate {
+        entity: Entity,
+        kind: EntityKind,
+        translation: DVec3,
+        yaw: f32,
+        item: Option<ItemData>,
+    },
+    EntityRemove {
+        entity: Entity,
+    },
     LevelData {
         chunk_data: Vec<u8>,
         pos: IVec3,
+        dimension: DimensionId,
+        // Of chunk_data, what clients key their chunk caches on
+        hash: u64,
+        // Not part of the hash, cached chunks get theirs with ChunkStillValid
+        biomes: ChunkBiomes,
+    },
+    // Stands in for LevelData when the client has the chunk cached with the same hash
+    ChunkStillValid {
+        pos: IVec3,
+        dimension: DimensionId,
+        biomes: ChunkBiomes,
+    },
+    // Client should drop everything it has loaded and wait for chunks from the new dimension
+    ChangeDimension {
+        dimension: DimensionId,
+    },
+    PlayerStats {
+        health: Health,
+        hunger: Hunger,
+    },
+    // What this client is allowed to do, sent once after joining
+    Capabilities {
+        creative: bool,
+    },
+    // Sent after Capabilities on joining and again whenever the server's gameplay.ron changes
+    GameplayRules {
+        rules: GameplayRules,
+    },
+    // Goes into whichever hotbar slot the client has selected
+    GiveStack {
+        item: ItemData,
+    },
+    // Announce is false for the full list sent on join so it doesn't toast everything again
+    RecipesUnlocked {
+        ids: Vec<String>,
+        announce: bool,
+    },
+    CraftResult {
+        recipe: String,
+        accepted: bool,
+    },
+    JoinRejected {
+        reason: JoinRejection,
+    },
+    // First thing after a join is taken, the client checks its own content against it
+    ContentManifest {
+        manifest: Box<ContentManifest>,
+        policy: ContentPolicy,
+        // Changes whenever the world behind an address does, cached chunks only count for
+        // the same one
+        world_id: String,
+        // Only for what the client works out for itself, like where decorations go
+        seed: u32,
+    },
+    // The client already took requested out of the slot, whatever wasn't dropped goes back
+    DropResult {
+        slot: SlotRef,
+        item: ItemData,
+        requested: u32,
+        dropped: u32,
+    },
+    PickedUp {
+        item: ItemData,
+    },
+    // Sent when it changes, and on join if it isn't healthy
+    ServerLoad {
+        health: ServerHealth,
+    },
+    // Moves the controlled player, for respawning and /spawn
+    Teleport {
+        translation: DVec3,
+    },
+    // Holds the controlled player at a seat, None lets them go and comes after the Teleport
+    // off it
+    Seated {
+        seat: Option<DVec3>,
+    },
+    // What the tool a break was made with turned into, None when it broke
+    ToolWorn {
+        slot: SlotRef,
+        tool: ItemData,
+        worn: Option<ItemData>,
+    },
+    // From /rename, applies to whatever the client is holding. None clears the name
+    RenameHeld {
+        name: Option<String>,
+    },
+    // Every inventory op up to seq has been applied
+    InventoryAck {
+        seq: u32,
+    },
+    // An op skipped ahead, everything from this seq on has to come again
+    ResendInventoryOps {
+        from: u32,
+    },
+    // The server's copy couldn't take an op, only the whole inventory sorts that out
+    RequestInventoryResync,
+    // Answers PickBlockFull, exactly what's in the voxel
+    BlockTemplate {
+        block: BlockData,
+    },
+    // Whether this client may fly and, while flying, pass through blocks. Sent after
+    // Capabilities on joining and again whenever /fly changes it
+    FlightPermission {
+        fly: bool,
+        noclip: bool,
+    },
+    // Stands in for LevelData when every voxel is the same block, nothing to serialize or
+    // compress. Never cached, it's about the size of a ChunkStillValid anyway
+    UniformChunk {
+        pos: IVec3,
+        dimension: DimensionId,
+        block: BlockData,
+        biomes: ChunkBiomes,
+    },
+    // Left the client's radius, it drops the chunk and gets it sent again if it comes back
+    UnloadChunk {
+        pos: IVec3,
+        dimension: DimensionId,
+    },
+    // A resync held things our copy doesn't have, the client takes this one over its own
+    InventoryCorrection {
+        inventory: Box<Inventory>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_count_characters() {
+        let cjk_name: String = "名".repeat(MAX_NAME_CHARS);
+        assert_eq!(cjk_name.len(), MAX_NAME_CHARS * 3);
+        assert!(valid_user_name(&cjk_name));
+        assert!(valid_user_name("Владимир"));
+        assert!(!valid_user_name(&format!("{cjk_name}名")));
+        assert!(!valid_user_name("  "));
+        assert!(!valid_user_name("new\nline"));
+
+        assert_eq!(truncate_chars("привет", 3), "при");
+        assert_eq!(truncate_chars("日本語", 5), "日本語");
+        assert_eq!(truncate_chars("a🙂b", 2), "a🙂");
+        assert_eq!(truncate_chars("", 0), "");
+        let long_chat = "字".repeat(MAX_CHAT_CHARS + 10);
+        assert_eq!(
+            truncate_chars(&long_chat, MAX_CHAT_CHARS).chars().count(),
+            MAX_CHAT_CHARS
+        );
+    }
+
+    #[test]
+    fn chat_round_trips_any_text() {
+        for (user_name, message) in [
+            ("名前", "こんにちは世界"),
+            ("Дмитрий", "Привет всем"),
+            ("user", "👋🏽 family: 👨‍👩‍👧 flag: 🇯🇵"),
+        ] {
+            let sent = ServerMessage::ChatMessage {
+                user_name: user_name.to_string(),
+                message: message.to_string(),
+                id: 1,
+                category: ChatCategory::PlayerChat,
+            };
+            let bytes = bincode::serialize(&sent).unwrap();
+            let ServerMessage::ChatMessage {
+                user_name: received_name,
+                message: received,
+                category,
+                ..
+            } = bincode::deserialize(&bytes).unwrap()
+            else {
+                panic!("came back as a different message");
+            };
+            assert_eq!(received_name, user_name);
+            assert_eq!(received, message);
+            assert_eq!(category, ChatCategory::PlayerChat);
+        }
+    }
+
+    #[test]
+    fn packed_poses_stay_within_a_step() {
+        let angle_error = |a: f32, b: f32| {
+            let difference = (a - b).rem_euclid(std::f32::consts::TAU);
+            difference.min(std::f32::consts::TAU - difference)
+        };
+        for (position, yaw, head_pitch) in [
+            (DVec3::ZERO, 0.0, 0.0),
+            (DVec3::new(12.3456, 70.001, -99.999), 1.234, -0.7),
+            (DVec3::new(-5_000_000.77, -64.5, 3_141_592.65), -3.1, 1.5707),
+            // Angles that went around more than once
+            (DVec3::new(0.001, 0.0039, -0.0019), 7.0, -7.5),
+        ] {
+            let pose = PackedPose::pack(position, yaw, head_pitch);
+            // Over the wire and back, as the server would see it
+            let bytes = bincode::serialize(&ClientMessage::Position {
+                pose,
+                left_handed: true,
+                flags: PlayerFlags::default(),
+            })
+            .unwrap();
+            let ClientMessage::Position {
+                pose: received,
+                left_handed,
+                ..
+            } = bincode::deserialize(&bytes).unwrap()
+            else {
+                panic!("came back as a different message");
+            };
+            assert_eq!(received, pose);
+            assert!(left_handed);
+            let error = (received.position() - position).abs().max_element();
+            assert!(error <= 0.5 / POSITION_STEPS, "{position} off by {error}");
+            let half_step = (0.5 / ANGLE_STEPS).to_radians() + 1e-5;
+            assert!(angle_error(received.yaw(), yaw) <= half_step);
+            assert!(angle_error(received.head_pitch(), head_pitch) <= half_step);
+            // Packing what came out gives the same thing again
+            assert_eq!(
+                PackedPose::pack(received.position(), received.yaw(), received.head_pitch()),
+                received
+            );
+        }
+        // Half the size it used to be, plus a byte for the hand and one for the flags
+        let bytes = bincode::serialize(&ClientMessage::Position {
+            pose: PackedPose::default(),
+            left_handed: false,
+            flags: PlayerFlags::default(),
+        })
+        .unwrap();
+        assert_eq!(bytes.len(), 22);
+    }
+
+    #[test]
+    fn player_flags_round_trip_in_a_byte() {
+        let flags = PlayerFlags::default()
+            .with(PlayerFlags::SNEAKING, true)
+            .with(PlayerFlags::ON_GROUND, true);
+        let bytes = bincode::serialize(&flags).unwrap();
+        assert_eq!(bytes, vec![PlayerFlags::SNEAKING | PlayerFlags::ON_GROUND]);
+        let received: PlayerFlags = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(received, flags);
+        assert!(received.sneaking() && received.on_ground());
+        assert!(!received.sprinting() && !received.swimming());
+        assert!(!received.with(PlayerFlags::SNEAKING, false).sneaking());
+
+        let mut input = MovementInput {
+            direction: Vec3::X,
+            sneak: true,
+            sprint: true,
+            ..Default::default()
+        };
+        let sneaking = PlayerFlags::from_movement(MovementState::Grounded, &input);
+        assert!(sneaking.sneaking() && !sneaking.sprinting() && sneaking.on_ground());
+        // Going down in water isn't sneaking
+        let diving = PlayerFlags::from_movement(MovementState::Swimming, &input);
+        assert!(diving.swimming() && !diving.sneaking() && !diving.on_ground());
+        input.sneak = false;
+        assert!(PlayerFlags::from_movement(MovementState::Airborne, &input).sprinting());
+        input.direction = Vec3::ZERO;
+        assert!(!PlayerFlags::from_movement(MovementState::Grounded, &input).sprinting());
+    }
+
+    #[test]
+    fn unknown_flag_bits_are_ignored() {
+        // From a newer client that knows about a couple more states
+        let newer = PlayerFlags::from_bits(0b1100_0000 | PlayerFlags::SPRINTING);
+        let received: PlayerFlags =
+            bincode::deserialize(&bincode::serialize(&newer).unwrap()).unwrap();
+        assert!(received.sprinting());
+        assert!(!received.sneaking() && !received.swimming() && !received.on_ground());
+        // The server only passes on what it knows
+        assert_eq!(received.known().bits(), PlayerFlags::SPRINTING);
+        assert_eq!(
+            PlayerFlags::from_bits(u8::MAX).known().bits(),
+            PlayerFlags::KNOWN
+        );
+    }
+
+    #[test]
+    fn gameplay_rules_survive_the_handshake() {
+        let mut rules = GameplayRules::default();
+        rules.set("fall_damage_height", "5.5").unwrap();
+        rules.set("keep_inventory", "false").unwrap();
+        let bytes = bincode::serialize(&ServerMessage::GameplayRules { rules }).unwrap();
+        let ServerMessage::GameplayRules { rules: received } =
+            bincode::deserialize(&bytes).unwrap()
+        else {
+            panic!("came back as a different message");
+        };
+        assert_eq!(received, rules);
+        assert_eq!(received.fall_damage_height, 5.5);
+        assert!(!received.keep_inventory);
+    }
+}
@@ -0,0 +1,801 @@
+use bevy::{math::DVec3, prelude::*};
+use serde::Serialize;
+
+use crate::{
+    ecs::{
+        arrange::InventoryOp,
+        bundles::{Health, Hunger, Inventory, SlotRef},
+        gameplay::GameplayRules,
+    },
+    storage::{
+        content::{ContentManifest, ContentPolicy},
+        items::descriptor::ItemData,
+    },
+    world::chunks::{biome_map::ChunkBiomes, positions::DimensionId, storage::BlockData},
+};
+
+use super::protocol::{
+    CachedChunk, ChatCategory, ClientMessage, DenyReason, EntityKind, FrameRequest, JoinRejection,
+    NetworkedEntities, PackedPose, PlayerFlags, ServerHealth, ServerMessage, PROTOCOL_VERSION,
+};
+
+// Messages go through bincode 1 with its default options, fixed width little endian integers,
+// u32 variant tags and u64 lengths
+pub const ENCODING: &str = "bincode-1-fixint-le";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldSchema {
+    pub name: &'static str,
+    // The Rust type as it's written in the message, whitespace taken out
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MessageSchema {
+    pub name: &'static str,
+    // What goes on the wire ahead of the fields, its place in the enum
+    pub tag: u32,
+    // In the order they're encoded
+    pub fields: Vec<FieldSchema>,
+}
+
+// Everything a third party needs to talk to a server of this version, see --export-schema
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProtocolSchema {
+    pub version: u32,
+    pub encoding: &'static str,
+    pub client: Vec<MessageSchema>,
+    pub server: Vec<MessageSchema>,
+}
+
+impl ProtocolSchema {
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            encoding: ENCODING,
+            client: ClientMessage::schema(),
+            server: ServerMessage::schema(),
+        }
+    }
+}
+
+fn message_schema(
+    tag: usize,
+    name: &'static str,
+    fields: Vec<(&'static str, &str)>,
+) -> MessageSchema {
+    MessageSchema {
+        name,
+        tag: tag as u32,
+        fields: fields
+            .into_iter()
+            .map(|(name, ty)| FieldSchema {
+                name,
+                ty: ty.split_whitespace().collect(),
+            })
+            .collect(),
+    }
+}
+
+// Mirrors an enum by hand, client ids are written as the u64 they are. variant_name matches on
+// every variant with every field bound and checked against the type written here, so a variant
+// or field missing from the list doesn't compile. Only the order can't be checked that way, the
+// tests do that
+macro_rules! mirror_messages {
+    ($message:ident { $($variant:ident $({ $($field:ident: $ty:ty),* $(,)? })?),* $(,)? }) => {
+        impl $message {
+            pub fn schema() -> Vec<MessageSchema> {
+                let variants: Vec<(&'static str, Vec<(&'static str, &'static str)>)> = vec![
+                    $((
+                        stringify!($variant),
+                        vec![$($((stringify!($field), stringify!($ty))),*)?],
+                    )),*
+                ];
+                variants
+                    .into_iter()
+                    .enumerate()
+                    .map(|(tag, (name, fields))| message_schema(tag, name, fields))
+                    .collect()
+            }
+
+            pub fn variant_name(&self) -> &'static str {
+                match self {
+                    $($message::$variant $({ $($field),* })? => {
+                        $($(let _: &$ty = $field;)*)?
+                        stringify!($variant)
+                    })*
+                }
+            }
+        }
+    };
+}
+
+mirror_messages!(ClientMessage {
+    Position {
+        pose: PackedPose,
+        left_handed: bool,
+        flags: PlayerFlags,
+    },
+    Interact {
+        entity: Entity,
+        attack: bool,
+    },
+    SentBlock {
+        chunk_pos: IVec3,
+        voxel_pos: [u8; 3],
+        block_type: BlockData,
+        item: Option<String>,
+        tool: Option<SlotRef>,
+        slot: Option<SlotRef>,
+    },
+    Join {
+        user_name: String,
+        id: u64,
+        protocol: u32,
+        chunk_cache: bool,
+        view_distance: u8,
+    },
+    ViewDistance {
+        horizontal: u8,
+    },
+    Leave {
+        id: u64,
+    },
+    ChatMessage {
+        message: String,
+    },
+    PickItem {
+        identifier: String,
+    },
+    Craft {
+        recipe: String,
+    },
+    ObtainedItem {
+        identifier: String,
+    },
+    DropItem {
+        slot: SlotRef,
+        count: u32,
+        item: ItemData,
+    },
+    PickUp {
+        entity: Entity,
+    },
+    UseBlock {
+        voxel: IVec3,
+    },
+    Dismount,
+    UseFrame {
+        voxel: IVec3,
+        request: FrameRequest,
+    },
+    InventoryOp {
+        seq: u32,
+        op: InventoryOp,
+    },
+    InventoryResync {
+        seq: u32,
+        inventory: Box<Inventory>,
+    },
+    CachedChunks {
+        chunks: Vec<CachedChunk>,
+        done: bool,
+    },
+    ChunkCacheMiss {
+        pos: IVec3,
+        dimension: DimensionId,
+    },
+    PickBlockFull {
+        chunk_pos: IVec3,
+        voxel_pos: [u8; 3],
+    },
+    PlaceTemplate {
+        chunk_pos: IVec3,
+        voxel_pos: [u8; 3],
+        block: BlockData,
+    },
+    SelectSlot {
+        bar: u8,
+        slot: u8,
+    },
+});
+
+mirror_messages!(ServerMessage {
+    ChatMessage {
+        user_name: String,
+        message: String,
+        id: u64,
+        category: ChatCategory,
+    },
+    ClientId {
+        id: u64,
+    },
+    PlayerCreate {
+        entity: Entity,
+        id: u64,
+        translation: DVec3,
+        yaw: f32,
+        head_pitch: f32,
+        user_name: String,
+        init: bool,
+        inventory: Box<Inventory>,
+    },
+    PlayerRemove {
+        id: u64,
+    },
+    SentBlock {
+        chunk_pos: IVec3,
+        voxel_pos: [u8; 3],
+        block_type: BlockData,
+        dimension: DimensionId,
+        denied: Option<DenyReason>,
+    },
+    NetworkedEntities {
+        networked_entities: NetworkedEntities,
+    },
+    EntityCreate {
+        entity: Entity,
+        kind: EntityKind,
+        translation: DVec3,
+        yaw: f32,
+        item: Option<ItemData>,
+    },
+    EntityRemove {
+        entity: Entity,
+    },
+    LevelData {
+        chunk_data: Vec<u8>,
+        pos: IVec3,
+        dimension: DimensionId,
+        hash: u64,
+        biomes: ChunkBiomes,
+    },
+    ChunkStillValid {
+        pos: IVec3,
+        dimension: DimensionId,
+        biomes: ChunkBiomes,
+    },
+    ChangeDimension {
+        dimension: DimensionId,
+    },
+    PlayerStats {
+        health: Health,
+        hunger: Hunger,
+    },
+    Capabilities {
+        creative: bool,
+    },
+    GameplayRules {
+        rules: GameplayRules,
+    },
+    GiveStack {
+        item: ItemData,
+    },
+    RecipesUnlocked {
+        ids: Vec<String>,
+        announce: bool,
+    },
+    CraftResult {
+        recipe: String,
+        accepted: bool,
+    },
+    JoinRejected {
+        reason: JoinRejection,
+    },
+    ContentManifest {
+        manifest: Box<ContentManifest>,
+        policy: ContentPolicy,
+        world_id: String,
+        seed: u32,
+    },
+    DropResult {
+        slot: SlotRef,
+        item: ItemData,
+        requested: u32,
+        dropped: u32,
+    },
+    PickedUp {
+        item: ItemData,
+    },
+    ServerLoad {
+        health: ServerHealth,
+    },
+    Teleport {
+        translation: DVec3,
+    },
+    Seated {
+        seat: Option<DVec3>,
+    },
+    ToolWorn {
+        slot: SlotRef,
+        tool: ItemData,
+        worn: Option<ItemData>,
+    },
+    RenameHeld {
+        name: Option<String>,
+    },
+    InventoryAck {
+        seq: u32,
+    },
+    ResendInventoryOps {
+        from: u32,
+    },
+    RequestInventoryResync,
+    BlockTemplate {
+        block: BlockData,
+    },
+    FlightPermission {
+        fly: bool,
+        noclip: bool,
+    },
+    UniformChunk {
+        pos: IVec3,
+        dimension: DimensionId,
+        block: BlockData,
+        biomes: ChunkBiomes,
+    },
+    UnloadChunk {
+        pos: IVec3,
+        dimension: DimensionId,
+    },
+    InventoryCorrection {
+        inventory: Box<Inventory>,
+    },
+});
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashSet},
+        fmt::Write,
+    };
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::ecs::bundles::InventorySection;
+
+    // Regenerate with VINOX_BLESS_PROTOCOL=1 cargo test -p vinox-common, which only goes
+    // through when PROTOCOL_VERSION has been bumped or nothing on the wire changed
+    const GOLDENS: &str = include_str!("goldens.ron");
+    const BLESS: &str = "VINOX_BLESS_PROTOCOL";
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Goldens {
+        version: u32,
+        // Variant name to its sample's bytes in hex
+        client: BTreeMap<String, String>,
+        server: BTreeMap<String, String>,
+    }
+
+    fn entity() -> Entity {
+        Entity::from_raw(7)
+    }
+
+    fn block() -> BlockData {
+        BlockData::new("vinox".to_string(), "stone".to_string())
+    }
+
+    fn item() -> ItemData {
+        ItemData {
+            namespace: "vinox".to_string(),
+            name: "stone".to_string(),
+            stack_size: 3,
+            arbitary_data: None,
+            metadata: None,
+        }
+    }
+
+    fn slot() -> SlotRef {
+        SlotRef {
+            section: InventorySection::Hotbar,
+            bar: 1,
+            slot: 2,
+        }
+    }
+
+    fn translation() -> DVec3 {
+        DVec3::new(0.5, 64.0, -0.5)
+    }
+
+    // Every field filled in so nothing on the wire is left out, and nothing that goes through a
+    // HashMap so the bytes come out the same every run
+    fn client_samples() -> Vec<ClientMessage> {
+        let chunk_pos = IVec3::new(1, -2, 3);
+        vec![
+            ClientMessage::Position {
+                pose: PackedPose {
+                    position: [256, -512, 1024],
+                    yaw: 900,
+                    head_pitch: -450,
+                },
+                left_handed: true,
+                flags: PlayerFlags::from_bits(PlayerFlags::SNEAKING | PlayerFlags::ON_GROUND),
+            },
+            ClientMessage::Interact {
+                entity: entity(),
+                attack: true,
+            },
+            ClientMessage::SentBlock {
+                chunk_pos,
+                voxel_pos: [4, 5, 6],
+                block_type: block(),
+                item: Some("vinox:pickaxe".to_string()),
+                tool: Some(slot()),
+                slot: Some(slot()),
+            },
+            ClientMessage::Join {
+                user_name: "player".to_string(),
+                id: 42,
+                protocol: PROTOCOL_VERSION,
+                chunk_cache: true,
+                view_distance: 8,
+            },
+            ClientMessage::ViewDistance { horizontal: 12 },
+            ClientMessage::Leave { id: 42 },
+            ClientMessage::ChatMessage {
+                message: "hello".to_string(),
+            },
+            ClientMessage::PickItem {
+                identifier: "vinox:stone".to_string(),
+            },
+            ClientMessage::Craft {
+                recipe: "vinox:planks".to_string(),
+            },
+            ClientMessage::ObtainedItem {
+                identifier: "vinox:stone".to_string(),
+            },
+            ClientMessage::DropItem {
+                slot: slot(),
+                count: 2,
+                item: item(),
+            },
+            ClientMessage::PickUp { entity: entity() },
+            ClientMessage::UseBlock { voxel: chunk_pos },
+            ClientMessage::Dismount,
+            ClientMessage::UseFrame {
+                voxel: chunk_pos,
+                request: FrameRequest::Insert {
+                    slot: slot(),
+                    item: item(),
+                },
+            },
+            ClientMessage::InventoryOp {
+                seq: 9,
+                op: InventoryOp::Move {
+                    from: slot(),
+                    to: SlotRef {
+                        section: InventorySection::Slots,
+                        bar: 4,
+                        slot: 8,
+                    },
+                    count: 1,
+                },
+            },
+            ClientMessage::InventoryResync {
+                seq: 9,
+                inventory: Box::default(),
+            },
+            ClientMessage::CachedChunks {
+                chunks: vec![CachedChunk {
+                    dimension: DimensionId(1),
+                    pos: chunk_pos,
+                    hash: 0xdead_beef,
+                }],
+                done: true,
+            },
+            ClientMessage::ChunkCacheMiss {
+                pos: chunk_pos,
+                dimension: DimensionId(1),
+            },
+            ClientMessage::PickBlockFull {
+                chunk_pos,
+                voxel_pos: [4, 5, 6],
+            },
+            ClientMessage::PlaceTemplate {
+                chunk_pos,
+                voxel_pos: [4, 5, 6],
+                block: block(),
+            },
+            ClientMessage::SelectSlot { bar: 2, slot: 1 },
+        ]
+    }
+
+    fn server_samples() -> Vec<ServerMessage> {
+        let chunk_pos = IVec3::new(1, -2, 3);
+        vec![
+            ServerMessage::ChatMessage {
+                user_name: "player".to_string(),
+                message: "hello".to_string(),
+                id: 3,
+                category: ChatCategory::System,
+            },
+            ServerMessage::ClientId { id: 42 },
+            ServerMessage::PlayerCreate {
+                entity: entity(),
+                id: 42,
+                translation: translation(),
+                yaw: 0.5,
+                head_pitch: -0.25,
+                user_name: "player".to_string(),
+                init: true,
+                inventory: Box::default(),
+            },
+            ServerMessage::PlayerRemove { id: 42 },
+            ServerMessage::SentBlock {
+                chunk_pos,
+                voxel_pos: [4, 5, 6],
+                block_type: block(),
+                dimension: DimensionId(1),
+                denied: Some(DenyReason::Occupied),
+            },
+            ServerMessage::NetworkedEntities {
+                networked_entities: NetworkedEntities {
+                    entities: vec![entity()],
+                    translations: vec![translation()],
+                    yaws: vec![0.5],
+                    head_pitchs: vec![-0.25],
+                    left_handed: vec![true],
+                    flags: vec![PlayerFlags::from_bits(PlayerFlags::SPRINTING)],
+                },
+            },
+            ServerMessage::EntityCreate {
+                entity: entity(),
+                kind: EntityKind::DroppedItem,
+                translation: translation(),
+                yaw: 0.5,
+                item: Some(item()),
+            },
+            ServerMessage::EntityRemove { entity: entity() },
+            ServerMessage::LevelData {
+                chunk_data: vec![1, 2, 3],
+                pos: chunk_pos,
+                dimension: DimensionId(1),
+                hash: 0xdead_beef,
+                biomes: ChunkBiomes::default(),
+            },
+            ServerMessage::ChunkStillValid {
+                pos: chunk_pos,
+                dimension: DimensionId(1),
+                biomes: ChunkBiomes::default(),
+            },
+            ServerMessage::ChangeDimension {
+                dimension: DimensionId(1),
+            },
+            ServerMessage::PlayerStats {
+                health: Health {
+                    current: 15.0,
+                    max: 20.0,
+                },
+                hunger: Hunger {
+                    current: 10.0,
+                    max: 20.0,
+                },
+            },
+            ServerMessage::Capabilities { creative: true },
+            ServerMessage::GameplayRules {
+                // Spelled out so changing a default doesn't look like a layout change
+                rules: GameplayRules {
+                    regen_per_sec: 0.5,
+                    regen_min_hunger: 18.0,
+                    hunger_per_sec: 0.25,
+                    idle_hunger: 1.0,
+                    walking_hunger: 2.0,
+                    sprinting_hunger: 4.0,
+                    fall_damage_height: 3.0,
+                    fall_damage_per_block: 1.0,
+                    invulnerable_secs: 0.5,
+                    starvation: true,
+                    starvation_per_sec: 0.25,
+                    keep_inventory: false,
+                    min_build_y: -512.0,
+                    max_build_y: 512.0,
+                },
+            },
+            ServerMessage::GiveStack { item: item() },
+            ServerMessage::RecipesUnlocked {
+                ids: vec!["vinox:planks".to_string()],
+                announce: true,
+            },
+            ServerMessage::CraftResult {
+                recipe: "vinox:planks".to_string(),
+                accepted: true,
+            },
+            ServerMessage::JoinRejected {
+                reason: JoinRejection::VersionMismatch {
+                    server: PROTOCOL_VERSION,
+                },
+            },
+            ServerMessage::ContentManifest {
+                manifest: Box::default(),
+                policy: ContentPolicy::Warn,
+                world_id: "world".to_string(),
+                seed: 1234,
+            },
+            ServerMessage::DropResult {
+                slot: slot(),
+                item: item(),
+                requested: 3,
+                dropped: 2,
+            },
+            ServerMessage::PickedUp { item: item() },
+            ServerMessage::ServerLoad {
+                health: ServerHealth::Degraded,
+            },
+            ServerMessage::Teleport {
+                translation: translation(),
+            },
+            ServerMessage::Seated {
+                seat: Some(translation()),
+            },
+            ServerMessage::ToolWorn {
+                slot: slot(),
+                tool: item(),
+                worn: None,
+            },
+            ServerMessage::RenameHeld {
+                name: Some("rock".to_string()),
+            },
+            ServerMessage::InventoryAck { seq: 9 },
+            ServerMessage::ResendInventoryOps { from: 9 },
+            ServerMessage::RequestInventoryResync,
+            ServerMessage::BlockTemplate { block: block() },
+            ServerMessage::FlightPermission {
+                fly: true,
+                noclip: false,
+            },
+            ServerMessage::UniformChunk {
+                pos: chunk_pos,
+                dimension: DimensionId(1),
+                block: block(),
+                biomes: ChunkBiomes::default(),
+            },
+            ServerMessage::UnloadChunk {
+                pos: chunk_pos,
+                dimension: DimensionId(1),
+            },
+            ServerMessage::InventoryCorrection {
+                inventory: Box::default(),
+            },
+        ]
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        let mut hex = String::with_capacity(bytes.len() * 2);
+        for byte in bytes {
+            write!(hex, "{byte:02x}").unwrap();
+        }
+        hex
+    }
+
+    fn encode<T: Serialize>(
+        samples: &[T],
+        name: impl Fn(&T) -> &'static str,
+    ) -> BTreeMap<String, String> {
+        samples
+            .iter()
+            .map(|sample| {
+                (
+                    name(sample).to_string(),
+                    hex(&bincode::serialize(sample).unwrap()),
+                )
+            })
+            .collect()
+    }
+
+    fn current() -> Goldens {
+        Goldens {
+            version: PROTOCOL_VERSION,
+            client: encode(&client_samples(), ClientMessage::variant_name),
+            server: encode(&server_samples(), ServerMessage::variant_name),
+        }
+    }
+
+    // Every variant has a sample and the schema's tags are the ones bincode writes
+    fn check_samples<T: Serialize>(
+        schema: &[MessageSchema],
+        samples: &[T],
+        name: impl Fn(&T) -> &'static str,
+    ) {
+        let sampled: HashSet<&str> = samples.iter().map(&name).collect();
+        assert_eq!(sampled.len(), samples.len(), "a variant is sampled twice");
+        for message in schema {
+            assert!(
+                sampled.contains(message.name),
+                "no sample of {}",
+                message.name
+            );
+        }
+        for sample in samples {
+            let bytes = bincode::serialize(sample).unwrap();
+            let tag = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+            let message = schema.iter().find(|message| message.name == name(sample));
+            assert_eq!(
+                message.map(|message| message.tag),
+                Some(tag),
+                "{} is out of order in the schema",
+                name(sample)
+            );
+        }
+    }
+
+    #[test]
+    fn schema_covers_every_variant_in_order() {
+        let schema = ProtocolSchema::current();
+        check_samples(
+            &schema.client,
+            &client_samples(),
+            ClientMessage::variant_name,
+        );
+        check_samples(
+            &schema.server,
+            &server_samples(),
+            ServerMessage::variant_name,
+        );
+
+        let join = &schema.client[3];
+        assert_eq!(join.name, "Join");
+        assert_eq!(join.fields[1].name, "id");
+        assert_eq!(join.fields[1].ty, "u64");
+        let sent = &schema.client[2];
+        assert_eq!(sent.fields[4].ty, "Option<(SlotRef,ItemData)>");
+        assert_eq!(
+            schema.server.last().unwrap().tag as usize,
+            schema.server.len() - 1
+        );
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        for sample in client_samples() {
+            let bytes = bincode::serialize(&sample).unwrap();
+            let back: ClientMessage = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(bincode::serialize(&back).unwrap(), bytes);
+        }
+        for sample in server_samples() {
+            let bytes = bincode::serialize(&sample).unwrap();
+            let back: ServerMessage = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(bincode::serialize(&back).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn wire_format_matches_the_goldens() {
+        let goldens: Goldens = ron::from_str(GOLDENS).expect("goldens.ron doesn't parse");
+        let current = current();
+        let changed =
+            |side: &str, golden: &BTreeMap<String, String>, current: &BTreeMap<String, String>| {
+                let mut names: Vec<&String> = golden.keys().chain(current.keys()).collect();
+                names.sort();
+                names.dedup();
+                names
+                    .into_iter()
+                    .filter(|name| golden.get(*name) != current.get(*name))
+                    .map(|name| format!("{side}::{name}"))
+                    .collect::<Vec<_>>()
+            };
+        let mut changes = changed("ClientMessage", &goldens.client, &current.client);
+        changes.extend(changed("ServerMessage", &goldens.server, &current.server));
+
+        if std::env::var_os(BLESS).is_some() {
+            assert!(
+                changes.is_empty() || goldens.version != PROTOCOL_VERSION,
+                "{changes:?} changed on the wire, bump PROTOCOL_VERSION before regenerating"
+            );
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/networking/goldens.ron");
+            let pretty = ron::ser::PrettyConfig::new().depth_limit(2);
+            std::fs::write(path, ron::ser::to_string_pretty(&current, pretty).unwrap()).unwrap();
+            return;
+        }
+        assert!(
+            changes.is_empty() || goldens.version != PROTOCOL_VERSION,
+            "{changes:?} changed on the wire without a PROTOCOL_VERSION bump. If that's on \
+             purpose bump it and rerun with {BLESS}=1"
+        );
+        assert_eq!(
+            goldens.version, PROTOCOL_VERSION,
+            "PROTOCOL_VERSION was bumped, rerun with {BLESS}=1 to regenerate goldens.ron"
+        );
+    }
+}
@@ -6,10 +6,13 @@ use std::{
 
 use bevy::{math::Vec3A, prelude::*, render::primitives::Aabb, utils::FloatOrd};
 
-use crate::world::chunks::{
-    ecs::CurrentChunks,
-    positions::{voxel_to_global_voxel, world_to_voxel, ChunkPos},
-    storage::{BlockData, BlockTable, ChunkData},
+use crate::{
+    physics::movement::block_flags,
+    world::chunks::{
+        ecs::CurrentChunks,
+        positions::{voxel_to_global_voxel, world_to_voxel, ChunkPos, WorldOffset},
+        storage::{BlockData, BlockTable, ChunkData},
+    },
 };
 
 const MARGIN: Vec3A = Vec3A::new(0.001, 0.001, 0.001);
@@ -33,12 +36,14 @@ impl std::fmt::Display for CollisionInfo {
     }
 }
 
+// The aabb and the collisions are in render space, only the chunk lookups add the offset
 pub fn aabb_vs_world(
     aabb: &Aabb,
     chunks: &Query<&ChunkData>,
     velocity: Vec3,
     current_chunks: &CurrentChunks,
     block_table: &BlockTable,
+    offset: &WorldOffset,
 ) -> Option<Vec<CollisionInfo>> {
     let mut collisions: Vec<CollisionInfo> = Vec::new();
     let area_to_check = (
@@ -59,12 +64,14 @@ pub fn aabb_vs_world(
                 let (check_chunk_pos, check_block_cpos) = world_to_voxel(
                     Vec3::from(aabb.center) + Vec3::new(x as f32, y as f32, z as f32),
                 );
-                if let Some(chunk_entity) = current_chunks.get_entity(ChunkPos(check_chunk_pos)) {
+                if let Some(chunk_entity) =
+                    current_chunks.get_entity(ChunkPos(offset.chunk_to_world(check_chunk_pos)))
+                {
                     if let Ok(chunk) = chunks.get(chunk_entity) {
                         let block_data: BlockData =
                             chunk.get(check_block_cpos.x, check_block_cpos.y, check_block_cpos.z);
                         let voxel_pos = voxel_to_global_voxel(check_block_cpos, check_chunk_pos);
-                        if !block_data.is_empty(block_table) {
+                        if block_flags(&block_data, block_table).solid {
                             let block_aabb = Aabb {
                                 center: voxel_pos.as_vec3a() + Vec3A::new(0.5, 0.5, 0.5),
                                 half_extents: Vec3A::new(0.5, 0.5, 0.5),
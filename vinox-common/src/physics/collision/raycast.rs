@@ -1,37 +1,130 @@
-use bevy::prelude::*;
+use bevy::{math::DVec3, prelude::*};
 
-use crate::world::chunks::{
-    ecs::ChunkManager,
-    positions::{world_to_global_voxel, world_to_voxel, ChunkPos},
+use crate::{
+    storage::blocks::descriptor::{BlockDescriptor, BlockGeometry},
+    world::chunks::{
+        ecs::ChunkManager,
+        positions::{global_voxel_positions, world_to_global_voxel_f64, ChunkPos},
+        storage::VoxelVisibility,
+    },
 };
+
 // Takes in absolute world positions returns a chunk pos and a voxel pos for whatever face it hits and a normal
 pub fn raycast_world(
-    origin: Vec3,
+    origin: DVec3,
     direction: Vec3,
     radius: f32,
     chunk_manager: &ChunkManager,
 ) -> Option<(ChunkPos, UVec3, Vec3, f32)> {
+    raycast_voxels(origin, direction, radius, |voxel| {
+        chunk_manager
+            .get_block(voxel)
+            .is_some_and(|block| !block.is_empty(&chunk_manager.block_table))
+    })
+}
+
+// Walks the voxels along the ray in f64, an f32 origin millions of blocks out has no fraction
+// left for tmax to start from
+pub fn raycast_voxels(
+    origin: DVec3,
+    direction: Vec3,
+    radius: f32,
+    solid: impl Fn(IVec3) -> bool,
+) -> Option<(ChunkPos, UVec3, Vec3, f32)> {
+    walk_voxels(origin, direction, radius, |voxel, face, toi| {
+        solid(voxel).then(|| {
+            let (chunk_pos, voxel_pos) = global_voxel_positions(voxel);
+            (ChunkPos(chunk_pos), voxel_pos, face, toi)
+        })
+    })
+}
+
+// How much of a voxel a ray passing through it runs into, a full opaque block is 1. Anything
+// that lets light through counts for half of what its shape would
+pub fn blocking_weight(descriptor: &BlockDescriptor) -> f32 {
+    let shape = match &descriptor.geometry {
+        None | Some(BlockGeometry::Block | BlockGeometry::BorderedBlock) => 1.0,
+        Some(BlockGeometry::Stairs) => 0.75,
+        Some(BlockGeometry::Slab | BlockGeometry::Custom(_)) => 0.5,
+        Some(BlockGeometry::Fence) => 0.25,
+        Some(BlockGeometry::Flat | BlockGeometry::Cross) => 0.1,
+    };
+    match descriptor.visibility.unwrap_or_default() {
+        VoxelVisibility::Empty => 0.0,
+        VoxelVisibility::Opaque => shape,
+        VoxelVisibility::Transparent => shape * 0.5,
+    }
+}
+
+// Doesn't stop at the first hit, adds up the weight of every voxel along the way instead. Stops
+// early once the total reaches cap, which is also the most it returns
+pub fn accumulate_voxels(
+    origin: DVec3,
+    direction: Vec3,
+    radius: f32,
+    cap: f32,
+    mut weight: impl FnMut(IVec3) -> f32,
+) -> f32 {
+    let mut total = 0.0;
+    // The walk only gives up on its own after a number of steps that assumes a unit direction
+    walk_voxels(
+        origin,
+        direction.normalize_or_zero(),
+        radius,
+        |voxel, _, _| {
+            total += weight(voxel);
+            (total >= cap).then_some(())
+        },
+    );
+    total.min(cap)
+}
+
+// raycast_world's accumulating mode, weight gets each voxel with a block in a loaded chunk
+pub fn accumulate_world(
+    origin: DVec3,
+    direction: Vec3,
+    radius: f32,
+    cap: f32,
+    chunk_manager: &ChunkManager,
+    weight: impl Fn(IVec3, &BlockDescriptor) -> f32,
+) -> f32 {
+    accumulate_voxels(origin, direction, radius, cap, |voxel| {
+        chunk_manager
+            .get_identifier(voxel)
+            .map_or(0.0, |identifier| {
+                weight(voxel, chunk_manager.block_table.get_or_unknown(&identifier))
+            })
+    })
+}
+
+// Visits every voxel the ray passes through in order, starting with the one origin is in, along
+// with the face it was entered through and how far along the ray that was. Stops at the first
+// visit that returns something
+fn walk_voxels<R>(
+    origin: DVec3,
+    direction: Vec3,
+    radius: f32,
+    mut visit: impl FnMut(IVec3, Vec3, f32) -> Option<R>,
+) -> Option<R> {
+    if direction == Vec3::ZERO {
+        return None;
+    }
+    let direction = direction.as_dvec3();
     // TMax needs the fractional part of origin to work.
-    let mut tmax = Vec3::new(
+    let mut tmax = DVec3::new(
         intbound(origin.x, direction.x),
         intbound(origin.y, direction.y),
         intbound(origin.z, direction.z),
     );
 
-    let mut current_block = world_to_global_voxel(origin).as_vec3();
+    let mut current_block = world_to_global_voxel_f64(origin);
     let step = direction.signum();
 
     let tdelta = step / direction;
 
     let mut face = Vec3::ZERO;
 
-    if direction == Vec3::ZERO {
-        return None;
-    }
-
-    let radius = radius
-        / (direction.x * direction.x + direction.y * direction.y + direction.z * direction.z)
-            .sqrt();
+    let radius = radius as f64 / direction.length();
     let mut lastmax = 0.0;
     let mut counter = 0;
     loop {
@@ -40,12 +133,9 @@ pub fn raycast_world(
         if counter > (radius * 4.0) as u32 {
             break;
         }
-        let (chunk_pos, voxel_pos) = world_to_voxel(current_block);
-        if let Some(block) = chunk_manager.get_block(world_to_global_voxel(current_block)) {
-            if !block.is_empty(&chunk_manager.block_table) {
-                let toi = lastmax * direction.length();
-                return Some((ChunkPos(chunk_pos), voxel_pos, face, toi));
-            }
+        let toi = (lastmax * direction.length()) as f32;
+        if let Some(found) = visit(current_block, face, toi) {
+            return Some(found);
         }
 
         if tmax.x < tmax.y {
@@ -54,49 +144,41 @@ pub fn raycast_world(
                     break;
                 }
                 lastmax = tmax.x;
-                current_block.x += step.x;
+                current_block.x += step.x as i32;
                 tmax.x += tdelta.x;
-                face.x = -step.x;
-                face.y = 0.0;
-                face.z = 0.0;
+                face = Vec3::new(-step.x as f32, 0.0, 0.0);
             } else {
                 if tmax.z > radius {
                     break;
                 }
                 lastmax = tmax.z;
-                current_block.z += step.z;
+                current_block.z += step.z as i32;
                 tmax.z += tdelta.z;
-                face.x = 0.0;
-                face.y = 0.0;
-                face.z = -step.z;
+                face = Vec3::new(0.0, 0.0, -step.z as f32);
             }
         } else if tmax.y < tmax.z {
             if tmax.y > radius {
                 break;
             }
             lastmax = tmax.y;
-            current_block.y += step.y;
+            current_block.y += step.y as i32;
             tmax.y += tdelta.y;
-            face.x = 0.0;
-            face.y = -step.y;
-            face.z = 0.0;
+            face = Vec3::new(0.0, -step.y as f32, 0.0);
         } else {
             if tmax.z > radius {
                 break;
             }
             lastmax = tmax.z;
-            current_block.z += step.z;
+            current_block.z += step.z as i32;
             tmax.z += tdelta.z;
-            face.x = 0.0;
-            face.y = 0.0;
-            face.z = -step.z;
+            face = Vec3::new(0.0, 0.0, -step.z as f32);
         }
         counter += 1;
     }
     None
 }
 
-fn intbound(s: f32, ds: f32) -> f32 {
+fn intbound(s: f64, ds: f64) -> f64 {
     if ds < 0.0 {
         intbound(-s, -ds)
     } else {
@@ -104,3 +186,116 @@ fn intbound(s: f32, ds: f32) -> f32 {
         (1.0 - s) / ds
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, block, descriptor},
+        world::chunks::{
+            positions::voxel_to_global_voxel,
+            storage::{BlockTable, ChunkData, CHUNK_SIZE},
+        },
+    };
+
+    #[test]
+    fn hits_the_same_voxel_far_out() {
+        for base in [IVec3::ZERO, IVec3::new(10_000_000, 0, -10_000_000)] {
+            let wall = base + IVec3::new(5, 1, 0);
+            let origin = base.as_dvec3() + DVec3::new(0.5, 1.62, 0.3);
+            // Slightly down and to the side so the fraction of the origin matters
+            let direction = Vec3::new(1.0, -0.02, 0.05).normalize();
+            let (chunk_pos, voxel, normal, _) =
+                raycast_voxels(origin, direction, 10.0, |voxel| voxel == wall).unwrap();
+            assert_eq!(voxel_to_global_voxel(voxel, *chunk_pos), wall);
+            assert_eq!(normal, Vec3::NEG_X);
+            // The block placed against that face
+            assert_eq!(wall + normal.as_ivec3(), base + IVec3::new(4, 1, 0));
+        }
+    }
+
+    #[test]
+    fn misses_past_the_radius() {
+        let wall = IVec3::new(20, 0, 0);
+        assert!(raycast_voxels(DVec3::splat(0.5), Vec3::X, 10.0, |voxel| voxel == wall).is_none());
+        assert!(raycast_voxels(DVec3::splat(0.5), Vec3::ZERO, 10.0, |_| true).is_none());
+    }
+
+    fn block_table() -> BlockTable {
+        testing::block_table([
+            descriptor("air", VoxelVisibility::Empty),
+            descriptor("stone", VoxelVisibility::Opaque),
+            descriptor("glass", VoxelVisibility::Transparent),
+            BlockDescriptor {
+                geometry: Some(BlockGeometry::Slab),
+                ..descriptor("slab", VoxelVisibility::Opaque)
+            },
+        ])
+    }
+
+    fn chunk(blocks: &[(u32, u32, &str)], table: &BlockTable) -> ChunkData {
+        let mut chunk = ChunkData::default();
+        for (x, y, name) in blocks {
+            chunk.set(*x, *y, 0, block(name), table);
+        }
+        chunk
+    }
+
+    // Between the centers of two voxels in the chunk at the origin, neither end counting
+    fn between(chunk: &ChunkData, table: &BlockTable, from: (i32, i32), to: (i32, i32)) -> f32 {
+        let (from, to) = (IVec3::new(from.0, from.1, 0), IVec3::new(to.0, to.1, 0));
+        let center = |voxel: IVec3| voxel.as_dvec3() + DVec3::splat(0.5);
+        let span = center(to) - center(from);
+        accumulate_voxels(
+            center(from),
+            span.as_vec3(),
+            span.length() as f32,
+            8.0,
+            |voxel| {
+                let inside = voxel.cmpge(IVec3::ZERO).all()
+                    && voxel.cmplt(IVec3::splat(CHUNK_SIZE as i32)).all();
+                if !inside || voxel == from || voxel == to {
+                    return 0.0;
+                }
+                let identifier = chunk.get_identifier(voxel.x as u32, voxel.y as u32, 0);
+                table.get(&identifier).map_or(0.0, blocking_weight)
+            },
+        )
+    }
+
+    #[test]
+    fn accumulates_every_wall_along_the_way() {
+        let table = block_table();
+        let open = chunk(&[], &table);
+        assert_eq!(between(&open, &table, (0, 0), (15, 0)), 0.0);
+
+        let one = chunk(&[(7, 0, "stone")], &table);
+        assert_eq!(between(&one, &table, (0, 0), (15, 0)), 1.0);
+        // Off to the side of the ray it's not in the way
+        assert_eq!(between(&one, &table, (0, 1), (15, 1)), 0.0);
+
+        let walls: Vec<_> = [2, 4, 6, 8, 10]
+            .into_iter()
+            .map(|x| (x, 0, "stone"))
+            .chain([(12, 0, "glass"), (13, 0, "slab")])
+            .collect();
+        let many = chunk(&walls, &table);
+        assert_eq!(between(&many, &table, (0, 0), (15, 0)), 6.0);
+        // The sound's own block doesn't muffle it
+        assert_eq!(between(&many, &table, (0, 0), (10, 0)), 4.0);
+
+        let solid: Vec<_> = (1..15).map(|x| (x, 0, "stone")).collect();
+        let solid = chunk(&solid, &table);
+        assert_eq!(between(&solid, &table, (0, 0), (15, 0)), 8.0);
+    }
+
+    #[test]
+    fn diagonals_dont_slip_between_corners() {
+        let table = block_table();
+        // Blocks that only touch at their corners, straight across the diagonal
+        let wall: Vec<_> = (0..=5).map(|x| (x, 5 - x, "stone")).collect();
+        let wall = chunk(&wall, &table);
+        assert_eq!(between(&wall, &table, (0, 0), (6, 6)), 1.0);
+        assert_eq!(between(&wall, &table, (6, 6), (0, 0)), 1.0);
+    }
+}
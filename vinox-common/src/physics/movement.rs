@@ -0,0 +1,627 @@
+use bevy::prelude::*;
+
+use crate::world::chunks::storage::{BlockData, BlockTable, VoxelVisibility};
+
+// Player feet are at the transform, these are offsets from there
+pub const HEAD_OFFSET: f32 = 1.6;
+pub const GROUND_PROBE: f32 = 0.05;
+pub const CLIMB_REACH: f32 = 0.35;
+// Half a player's width, see PlayerBundleBuilder. Sneaking keeps some of this over solid ground
+pub const PLAYER_HALF_WIDTH: f32 = 0.3;
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MovementState {
+    Grounded,
+    #[default]
+    Airborne,
+    Swimming,
+    Flying,
+    Climbing,
+    // Held in place by the server until it lets the player up, only looking around works
+    Seated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovementParams {
+    // Horizontal speed change per second, infinite snaps straight to the target speed
+    pub acceleration: f32,
+    pub max_speed: f32,
+    pub sprint_multiplier: f32,
+    pub gravity_scale: f32,
+    // Fraction of vertical speed lost per second
+    pub drag: f32,
+}
+
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct MovementConfig {
+    pub gravity: f32,
+    pub jump_speed: f32,
+    pub swim_speed: f32,
+    pub climb_speed: f32,
+    pub fly_vertical_speed: f32,
+    // Walking speed while sneaking, sprinting doesn't add to it
+    pub sneak_multiplier: f32,
+    pub grounded: MovementParams,
+    pub airborne: MovementParams,
+    pub swimming: MovementParams,
+    pub flying: MovementParams,
+    pub climbing: MovementParams,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        let walking = MovementParams {
+            acceleration: f32::INFINITY,
+            max_speed: 5.0,
+            sprint_multiplier: 2.0,
+            gravity_scale: 1.0,
+            drag: 0.0,
+        };
+        Self {
+            gravity: 35.0,
+            jump_speed: 10.0,
+            swim_speed: 4.0,
+            climb_speed: 3.0,
+            fly_vertical_speed: 8.0,
+            sneak_multiplier: 0.5,
+            grounded: walking,
+            airborne: walking,
+            swimming: MovementParams {
+                acceleration: 20.0,
+                max_speed: 3.0,
+                sprint_multiplier: 1.5,
+                gravity_scale: 0.3,
+                drag: 2.0,
+            },
+            flying: MovementParams {
+                acceleration: 40.0,
+                max_speed: 10.0,
+                sprint_multiplier: 2.0,
+                gravity_scale: 0.0,
+                drag: 0.0,
+            },
+            climbing: MovementParams {
+                acceleration: f32::INFINITY,
+                max_speed: 2.0,
+                sprint_multiplier: 1.0,
+                gravity_scale: 0.0,
+                drag: 0.0,
+            },
+        }
+    }
+}
+
+impl MovementConfig {
+    pub fn params(&self, state: MovementState) -> &MovementParams {
+        match state {
+            MovementState::Grounded | MovementState::Seated => &self.grounded,
+            MovementState::Airborne => &self.airborne,
+            MovementState::Swimming => &self.swimming,
+            MovementState::Flying => &self.flying,
+            MovementState::Climbing => &self.climbing,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlockFlags {
+    pub solid: bool,
+    pub fluid: bool,
+    pub climbable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Environment {
+    pub on_ground: bool,
+    pub in_fluid: bool,
+    pub on_climbable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MovementInput {
+    // Horizontal wish direction, normalized or zero
+    pub direction: Vec3,
+    pub jump: bool,
+    pub sneak: bool,
+    pub sprint: bool,
+    pub toggle_fly: bool,
+}
+
+pub fn block_flags(block: &BlockData, block_table: &BlockTable) -> BlockFlags {
+    block_table
+        .get(&format!("{}:{}", block.namespace, block.name))
+        .map(|descriptor| {
+            let fluid = descriptor.fluid.unwrap_or(false);
+            BlockFlags {
+                solid: !fluid
+                    && descriptor.visibility.unwrap_or_default() != VoxelVisibility::Empty,
+                fluid,
+                climbable: descriptor.climbable.unwrap_or(false),
+            }
+        })
+        .unwrap_or_default()
+}
+
+// The blocks just under each corner of the player's footprint standing at `position`
+fn footprint(position: Vec3) -> [IVec3; 4] {
+    // Just inside it so standing flush against a block doesn't count the next one over
+    let reach = PLAYER_HALF_WIDTH - 0.001;
+    [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)].map(|(x, z)| {
+        (position + Vec3::new(x * reach, -GROUND_PROBE, z * reach))
+            .floor()
+            .as_ivec3()
+    })
+}
+
+// Unloaded blocks count as nothing so the player just falls until the chunk shows up. Any corner
+// of the footprint holds the player up, same as the collision does
+pub fn sample_environment(
+    position: Vec3,
+    velocity: Vec3,
+    sample: impl Fn(IVec3) -> Option<BlockFlags>,
+) -> Environment {
+    let at = |pos: Vec3| sample(pos.floor().as_ivec3()).unwrap_or_default();
+    let feet = at(position);
+    let head = at(position + Vec3::Y * HEAD_OFFSET);
+    let below = footprint(position)
+        .iter()
+        .any(|voxel| sample(*voxel).unwrap_or_default().solid);
+    let on_climbable = feet.climbable
+        || head.climbable
+        || [Vec3::X, Vec3::NEG_X, Vec3::Z, Vec3::NEG_Z]
+            .iter()
+            .any(|side| at(position + *side * CLIMB_REACH).climbable);
+    Environment {
+        on_ground: below && velocity.y <= 0.0,
+        in_fluid: feet.fluid || head.fluid,
+        on_climbable,
+    }
+}
+
+pub fn next_state(
+    current: MovementState,
+    environment: &Environment,
+    input: &MovementInput,
+) -> MovementState {
+    // Only the server lets a seated player up
+    if current == MovementState::Seated {
+        return MovementState::Seated;
+    }
+    // Flight only ends when toggled off, landing just stops the player
+    if (current == MovementState::Flying) != input.toggle_fly {
+        MovementState::Flying
+    } else if environment.in_fluid {
+        MovementState::Swimming
+    } else if environment.on_climbable && !(environment.on_ground && !input.jump) {
+        MovementState::Climbing
+    } else if environment.on_ground {
+        MovementState::Grounded
+    } else {
+        MovementState::Airborne
+    }
+}
+
+fn move_towards(current: Vec2, target: Vec2, max_delta: f32) -> Vec2 {
+    let difference = target - current;
+    let distance = difference.length();
+    if distance <= max_delta {
+        target
+    } else {
+        current + difference / distance * max_delta
+    }
+}
+
+// Applies the state's input mapping then gravity and drag, returns the new velocity
+pub fn integrate(
+    state: MovementState,
+    config: &MovementConfig,
+    input: &MovementInput,
+    velocity: Vec3,
+    delta: f32,
+) -> Vec3 {
+    if state == MovementState::Seated {
+        return Vec3::ZERO;
+    }
+    let params = config.params(state);
+    let mut velocity = velocity;
+    velocity.y -= config.gravity * params.gravity_scale * delta;
+    velocity.y *= (1.0 - params.drag * delta).max(0.0);
+
+    let walking = matches!(state, MovementState::Grounded | MovementState::Airborne);
+    let speed = if input.sneak && walking {
+        params.max_speed * config.sneak_multiplier
+    } else if input.sprint {
+        params.max_speed * params.sprint_multiplier
+    } else {
+        params.max_speed
+    };
+    let target = Vec2::new(input.direction.x, input.direction.z) * speed;
+    let horizontal = if params.acceleration.is_infinite() {
+        target
+    } else {
+        move_towards(
+            Vec2::new(velocity.x, velocity.z),
+            target,
+            params.acceleration * delta,
+        )
+    };
+    velocity.x = horizontal.x;
+    velocity.z = horizontal.y;
+
+    match state {
+        MovementState::Grounded => {
+            if input.jump {
+                velocity.y = config.jump_speed;
+            }
+        }
+        MovementState::Airborne | MovementState::Seated => {}
+        MovementState::Swimming => {
+            if input.jump {
+                velocity.y = config.swim_speed;
+            } else if input.sneak {
+                velocity.y = -config.swim_speed;
+            }
+        }
+        MovementState::Flying => {
+            velocity.y = if input.jump {
+                config.fly_vertical_speed
+            } else if input.sneak {
+                -config.fly_vertical_speed
+            } else {
+                0.0
+            };
+        }
+        MovementState::Climbing => {
+            // Pushing into the ladder or jumping goes up, sneaking holds on
+            velocity.y = if input.jump || input.direction != Vec3::ZERO {
+                config.climb_speed
+            } else if input.sneak {
+                0.0
+            } else {
+                -config.climb_speed
+            };
+        }
+    }
+    velocity
+}
+
+// Unlike standing on it, unloaded blocks count as solid here so a chunk that hasn't arrived can't
+// pin the player in place
+fn supported(position: Vec3, sample: &impl Fn(IVec3) -> Option<BlockFlags>) -> bool {
+    footprint(position)
+        .iter()
+        .any(|voxel| sample(*voxel).is_none_or(|flags| flags.solid))
+}
+
+// Stops the horizontal velocity that would take a sneaking player off an edge this frame. Each
+// axis is checked on its own so they can still slide along the edge
+pub fn guard_edge(
+    position: Vec3,
+    velocity: Vec3,
+    delta: f32,
+    sample: impl Fn(IVec3) -> Option<BlockFlags>,
+) -> Vec3 {
+    let step = velocity * delta;
+    let mut velocity = velocity;
+    if !supported(position + Vec3::new(step.x, 0.0, 0.0), &sample) {
+        velocity.x = 0.0;
+    }
+    if !supported(position + Vec3::new(0.0, 0.0, step.z), &sample) {
+        velocity.z = 0.0;
+    }
+    // Fine along both axes but not diagonally, off the outside of a corner
+    let step = velocity * delta;
+    if !supported(position + Vec3::new(step.x, 0.0, step.z), &sample) {
+        velocity.x = 0.0;
+        velocity.z = 0.0;
+    }
+    velocity
+}
+
+// Determine state, apply the state's input mapping, integrate. Sneaking on the ground stays on
+// the ground, the player is never walked off into Airborne
+pub fn step_movement(
+    state: &mut MovementState,
+    config: &MovementConfig,
+    input: &MovementInput,
+    position: Vec3,
+    velocity: Vec3,
+    delta: f32,
+    sample: impl Fn(IVec3) -> Option<BlockFlags>,
+) -> Vec3 {
+    let environment = sample_environment(position, velocity, &sample);
+    *state = next_state(*state, &environment, input);
+    let velocity = integrate(*state, config, input, velocity, delta);
+    if *state == MovementState::Grounded && input.sneak {
+        guard_edge(position, velocity, delta, sample)
+    } else {
+        velocity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::time::GameClock;
+    use MovementState::*;
+
+    const DELTA: f32 = 1.0 / 60.0;
+
+    // The movement code from before the state machine, kept to pin its behaviour
+    fn legacy_step(velocity: Vec3, direction: Vec3, run: bool, jump: bool, delta: f32) -> Vec3 {
+        let mut velocity = velocity;
+        velocity += 35.0 * Vec3::NEG_Y * delta.clamp(0.0, 0.1);
+        let movement = direction.normalize_or_zero() * if run { 10.0 } else { 5.0 };
+        if jump {
+            velocity.y = 10.0;
+        }
+        Vec3::new(movement.x, velocity.y, movement.z)
+    }
+
+    fn input(direction: Vec3, jump: bool, sprint: bool) -> MovementInput {
+        MovementInput {
+            direction,
+            jump,
+            sprint,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn walking_matches_legacy() {
+        let config = MovementConfig::default();
+        for (direction, run) in [
+            (Vec3::X, false),
+            (Vec3::new(1.0, 0.0, 1.0).normalize(), true),
+            (Vec3::ZERO, false),
+        ] {
+            for state in [Grounded, Airborne] {
+                let velocity = Vec3::new(2.0, -1.0, 0.5);
+                let new = integrate(
+                    state,
+                    &config,
+                    &input(direction, false, run),
+                    velocity,
+                    DELTA,
+                );
+                let old = legacy_step(velocity, direction, run, false, DELTA);
+                assert!((new - old).length() < 1e-5, "{state:?} {new} {old}");
+            }
+        }
+    }
+
+    #[test]
+    fn jump_matches_legacy() {
+        let config = MovementConfig::default();
+        let mut new = integrate(
+            Grounded,
+            &config,
+            &input(Vec3::ZERO, true, false),
+            Vec3::ZERO,
+            DELTA,
+        );
+        let mut old = legacy_step(Vec3::ZERO, Vec3::ZERO, false, true, DELTA);
+        let (mut new_height, mut old_height) = (0.0, 0.0);
+        for _ in 0..120 {
+            assert!((new - old).length() < 1e-5);
+            new_height += new.y.max(0.0) * DELTA;
+            old_height += old.y.max(0.0) * DELTA;
+            new = integrate(Airborne, &config, &MovementInput::default(), new, DELTA);
+            old = legacy_step(old, Vec3::ZERO, false, false, DELTA);
+        }
+        assert!((new_height - old_height).abs() < 1e-4);
+        assert!((new_height - 1.43).abs() < 0.1);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        // The clamp lives in the game clock now, integrate trusts whatever it's given
+        let config = MovementConfig::default();
+        let mut clock = GameClock::default();
+        clock.advance(1.0);
+        let velocity = integrate(
+            Airborne,
+            &config,
+            &MovementInput::default(),
+            Vec3::ZERO,
+            clock.delta_seconds(),
+        );
+        assert_eq!(
+            velocity,
+            legacy_step(Vec3::ZERO, Vec3::ZERO, false, false, 1.0)
+        );
+    }
+
+    #[test]
+    fn transition_table() {
+        let ground = Environment {
+            on_ground: true,
+            ..Default::default()
+        };
+        let air = Environment::default();
+        let fluid = Environment {
+            in_fluid: true,
+            ..Default::default()
+        };
+        let ladder = Environment {
+            on_climbable: true,
+            ..Default::default()
+        };
+        let ladder_ground = Environment {
+            on_ground: true,
+            on_climbable: true,
+            ..Default::default()
+        };
+        let none = MovementInput::default();
+        let jump = MovementInput {
+            jump: true,
+            ..Default::default()
+        };
+        let fly = MovementInput {
+            toggle_fly: true,
+            ..Default::default()
+        };
+        let cases = [
+            (Grounded, air, none, Airborne),
+            (Grounded, ground, none, Grounded),
+            (Grounded, fluid, none, Swimming),
+            (Grounded, ladder_ground, none, Grounded),
+            (Grounded, ladder_ground, jump, Climbing),
+            (Grounded, ground, fly, Flying),
+            (Airborne, ground, none, Grounded),
+            (Airborne, air, none, Airborne),
+            (Airborne, fluid, none, Swimming),
+            (Airborne, ladder, none, Climbing),
+            (Airborne, air, fly, Flying),
+            (Swimming, fluid, none, Swimming),
+            (Swimming, ground, none, Grounded),
+            (Swimming, air, none, Airborne),
+            (Swimming, ladder, none, Climbing),
+            (Swimming, fluid, fly, Flying),
+            (Flying, air, none, Flying),
+            (Flying, fluid, none, Flying),
+            (Flying, ground, none, Flying),
+            (Flying, ground, fly, Grounded),
+            (Flying, air, fly, Airborne),
+            (Flying, ladder, fly, Climbing),
+            (Flying, fluid, fly, Swimming),
+            (Climbing, ladder, none, Climbing),
+            (Climbing, ladder_ground, none, Grounded),
+            (Climbing, air, none, Airborne),
+            (Climbing, ground, none, Grounded),
+            (Climbing, fluid, none, Swimming),
+            (Climbing, ladder, fly, Flying),
+            (Seated, air, jump, Seated),
+            (Seated, fluid, fly, Seated),
+        ];
+        for (from, environment, input, to) in cases {
+            assert_eq!(
+                next_state(from, &environment, &input),
+                to,
+                "{from:?} with {environment:?} and {input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn environment_sampling() {
+        // Floor at y = -1, a ladder column at x = 1 and water at z = 5
+        let sample = |pos: IVec3| {
+            Some(BlockFlags {
+                solid: pos.y < 0 || pos.x == 1,
+                fluid: pos.z == 5 && pos.y >= 0,
+                climbable: pos.x == 1,
+            })
+        };
+        let standing = sample_environment(Vec3::new(0.5, 0.0, 0.5), Vec3::ZERO, sample);
+        assert!(standing.on_ground && !standing.in_fluid && !standing.on_climbable);
+        let by_ladder = sample_environment(Vec3::new(0.7, 3.0, 0.5), Vec3::ZERO, sample);
+        assert!(by_ladder.on_climbable && !by_ladder.on_ground);
+        let swimming = sample_environment(Vec3::new(0.5, 0.0, 5.5), Vec3::ZERO, sample);
+        assert!(swimming.in_fluid);
+        // Moving up off the floor isn't standing on it
+        let rising = sample_environment(Vec3::new(0.5, 0.0, 0.5), Vec3::Y, sample);
+        assert!(!rising.on_ground);
+    }
+
+    #[test]
+    fn climbing_and_flying_vertical() {
+        let config = MovementConfig::default();
+        let up = integrate(
+            Climbing,
+            &config,
+            &input(Vec3::X, false, false),
+            Vec3::ZERO,
+            DELTA,
+        );
+        assert_eq!(up.y, config.climb_speed);
+        let down = integrate(
+            Climbing,
+            &config,
+            &MovementInput::default(),
+            Vec3::ZERO,
+            DELTA,
+        );
+        assert_eq!(down.y, -config.climb_speed);
+        let hover = integrate(
+            Flying,
+            &config,
+            &MovementInput::default(),
+            Vec3::Y * 5.0,
+            DELTA,
+        );
+        assert_eq!(hover.y, 0.0);
+        // No gravity and no walking off while seated
+        let seated = integrate(
+            Seated,
+            &config,
+            &input(Vec3::X, true, true),
+            Vec3::NEG_Y * 5.0,
+            DELTA,
+        );
+        assert_eq!(seated, Vec3::ZERO);
+    }
+
+    #[test]
+    fn sneaking_is_slower_and_stays_on_the_edge() {
+        let config = MovementConfig::default();
+        let sneak = MovementInput {
+            direction: Vec3::X,
+            sneak: true,
+            sprint: true,
+            ..Default::default()
+        };
+        let slow = integrate(Grounded, &config, &sneak, Vec3::ZERO, DELTA);
+        assert_eq!(slow.x, config.grounded.max_speed * config.sneak_multiplier);
+        // Going down in water is still full speed
+        let diving = integrate(Swimming, &config, &sneak, Vec3::ZERO, DELTA);
+        assert!(diving.x > slow.x);
+
+        // A floor at y = -1 for x < 1, a drop past it and no chunk at all past z = 3
+        let sample = |pos: IVec3| {
+            (pos.z < 3).then_some(BlockFlags {
+                solid: pos.y < 0 && pos.x < 1,
+                ..Default::default()
+            })
+        };
+        let mut state = Grounded;
+        // Far enough over that one more step would leave nothing underfoot
+        let at_edge = Vec3::new(1.0 + PLAYER_HALF_WIDTH - 0.01, 0.0, 0.5);
+        let wish = Vec3::new(1.0, 0.0, 1.0).normalize();
+        let velocity = step_movement(
+            &mut state,
+            &config,
+            &MovementInput {
+                direction: wish,
+                ..sneak
+            },
+            at_edge,
+            Vec3::ZERO,
+            DELTA,
+            sample,
+        );
+        assert_eq!(state, Grounded);
+        // Held back off the edge but still sliding along it
+        assert_eq!(velocity.x, 0.0);
+        assert!(velocity.z > 0.0);
+        // Without sneaking nothing holds them
+        let walking = step_movement(
+            &mut state,
+            &config,
+            &input(Vec3::X, false, false),
+            at_edge,
+            Vec3::ZERO,
+            DELTA,
+            sample,
+        );
+        assert!(walking.x > 0.0);
+        // The same edge with an unloaded chunk past it instead of a drop doesn't hold them
+        let unloaded = guard_edge(
+            Vec3::new(0.5, 0.0, 3.0 + PLAYER_HALF_WIDTH - 0.01),
+            Vec3::Z,
+            DELTA,
+            sample,
+        );
+        assert_eq!(unloaded, Vec3::Z);
+    }
+}
@@ -1,14 +1,20 @@
 use bevy::prelude::*;
 
-use crate::physics::simulate::move_no_collide;
+use crate::{physics::simulate::move_no_collide, world::chunks::positions::WorldOffset};
 
-use super::simulate::{move_and_collide, VoxelCollisionEvent};
+use super::{
+    movement::MovementConfig,
+    simulate::{move_and_collide, VoxelCollisionEvent},
+};
 
 pub struct PhysicsPlugin;
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems((move_and_collide, move_no_collide))
+        app.init_resource::<MovementConfig>()
+            // Only the client ever moves it
+            .init_resource::<WorldOffset>()
+            .add_systems((move_and_collide, move_no_collide))
             .add_event::<VoxelCollisionEvent>();
     }
 }
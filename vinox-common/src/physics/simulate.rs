@@ -2,14 +2,14 @@ use bevy::{
     math::Vec3A,
     prelude::{Component, Entity, EventWriter, IVec3, Query, Res, Transform, Vec3, With, Without},
     render::primitives::Aabb,
-    time::Time,
 };
 
 use crate::{
+    ecs::time::GameClock,
     physics::collision::aabb::{get_collision_info, CollisionInfo},
     world::chunks::{
         ecs::CurrentChunks,
-        positions::{world_to_chunk, ChunkPos},
+        positions::{world_to_chunk, ChunkPos, WorldOffset},
         storage::{BlockTable, ChunkData},
     },
 };
@@ -25,16 +25,17 @@ pub struct Velocity(pub Vec3);
 #[derive(Debug)]
 pub struct VoxelCollisionEvent {
     pub entity: Entity,
+    // World voxel, not render space
     pub voxel_pos: IVec3,
     pub normal: Vec3,
 }
 
 pub fn move_no_collide(
     mut moving_entities: Query<(Entity, &mut Aabb, &Velocity), Without<CollidesWithWorld>>,
-    time: Res<Time>,
+    clock: Res<GameClock>,
 ) {
     for (_entity, mut aabb, velocity) in moving_entities.iter_mut() {
-        aabb.center += Vec3A::from(velocity.0 * time.delta().as_secs_f32());
+        aabb.center += Vec3A::from(velocity.0 * clock.delta_seconds());
     }
 }
 
@@ -43,25 +44,33 @@ pub fn move_and_collide(
         (Entity, &mut Aabb, &mut Velocity, &mut Transform),
         With<CollidesWithWorld>,
     >,
-    time: Res<Time>,
+    clock: Res<GameClock>,
     chunks: Query<&ChunkData>,
     current_chunks: Res<CurrentChunks>,
     block_table: Res<BlockTable>,
+    offset: Res<WorldOffset>,
     mut collision_event_writer: EventWriter<VoxelCollisionEvent>,
 ) {
     for (entity, mut aabb, mut velocity, mut transform) in moving_entities.iter_mut() {
         if current_chunks
-            .get_entity(ChunkPos(world_to_chunk(Vec3::from(aabb.center))))
+            .get_entity(ChunkPos(
+                offset.chunk_to_world(world_to_chunk(Vec3::from(aabb.center))),
+            ))
             .is_none()
         {
-            return;
+            continue;
         }
-        let movement = velocity.0 * time.delta().as_secs_f32();
+        let movement = velocity.0 * clock.delta_seconds();
         let mut v_after = movement;
         let mut max_move = v_after.abs();
-        if let Some(mut aabb_collisions) =
-            aabb_vs_world(&aabb, &chunks, movement, &current_chunks, &block_table)
-        {
+        if let Some(mut aabb_collisions) = aabb_vs_world(
+            &aabb,
+            &chunks,
+            movement,
+            &current_chunks,
+            &block_table,
+            &offset,
+        ) {
             // First pass to evaluate all collisions
             for col in aabb_collisions.iter() {
                 if col.normal.x != 0.0 {
@@ -127,13 +136,15 @@ pub fn move_and_collide(
                 }
                 collision_event_writer.send(VoxelCollisionEvent {
                     entity,
-                    voxel_pos: col.collision_aabb.center.floor().as_ivec3(),
+                    voxel_pos: offset.voxel_to_world(col.collision_aabb.center.floor().as_ivec3()),
                     normal: col.normal,
                 });
             }
         }
-        // Apply updated velocity
-        velocity.0 = v_after / time.delta().as_secs_f32();
+        // Apply updated velocity, a paused clock has nothing to divide by
+        if clock.delta_seconds() > 0.0 {
+            velocity.0 = v_after / clock.delta_seconds();
+        }
         let final_move = max_move.copysign(movement);
         aabb.center += Vec3A::from(final_move);
         transform.translation = Vec3::from(aabb.center - Vec3A::Y * aabb.half_extents)
@@ -0,0 +1,256 @@
+use crate::{storage::blocks::descriptor::TintKind, world::chunks::storage::BiomeTable};
+
+// Blocks over which heat and humidity drift from one extreme to the other
+pub const CLIMATE_SCALE: f32 = 384.0;
+
+// Both go from 0 to 1, the same range biome descriptors use
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Climate {
+    pub heat: f32,
+    pub humidity: f32,
+}
+
+fn lattice(x: i32, z: i32, salt: u32) -> f32 {
+    let mut hash = (x as u32)
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add((z as u32).wrapping_mul(0xd816_3841))
+        .wrapping_add(salt.wrapping_mul(0xcb1a_b31f));
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2c1b_3c6d);
+    hash ^= hash >> 12;
+    (hash & 0xffff) as f32 / 65535.0
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Interpolated lattice noise, continuous everywhere so neighbouring columns never jump
+fn value_noise(x: f32, z: f32, salt: u32) -> f32 {
+    let (cell_x, cell_z) = (x.floor(), z.floor());
+    let (tx, tz) = (smooth(x - cell_x), smooth(z - cell_z));
+    let (cell_x, cell_z) = (cell_x as i32, cell_z as i32);
+    let top = lerp(
+        lattice(cell_x, cell_z, salt),
+        lattice(cell_x + 1, cell_z, salt),
+        tx,
+    );
+    let bottom = lerp(
+        lattice(cell_x, cell_z + 1, salt),
+        lattice(cell_x + 1, cell_z + 1, salt),
+        tx,
+    );
+    lerp(top, bottom, tz)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// Shared by the mesher and anything on the server that wants to agree with it
+pub fn climate_at(x: i32, z: i32) -> Climate {
+    let (x, z) = (x as f32 / CLIMATE_SCALE, z as f32 / CLIMATE_SCALE);
+    let octaves =
+        |salt: u32| (value_noise(x, z, salt) * 2.0 + value_noise(x * 3.0, z * 3.0, salt + 1)) / 3.0;
+    Climate {
+        heat: octaves(0),
+        humidity: octaves(2),
+    }
+}
+
+// The nearest of the biomes whose ranges hold the climate, or of all of them when none do.
+// Ties go to the lower identifier so it never depends on the table's order
+pub fn closest_biome(climate: Climate, biomes: &BiomeTable) -> Option<&str> {
+    let nearest = |covering: bool| {
+        biomes
+            .iter()
+            .filter(|(_, biome)| !covering || biome.covers(climate.heat, climate.humidity))
+            .map(|(identifier, biome)| {
+                let distance = (biome.heat - climate.heat).powi(2)
+                    + (biome.humidity - climate.humidity).powi(2);
+                (distance, identifier)
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1)))
+            .map(|(_, identifier)| identifier.as_str())
+    };
+    nearest(true).or_else(|| nearest(false))
+}
+
+// Corners of the colormap as (cold dry, cold wet, hot dry, hot wet).
+// The textures already carry their own colour so these only nudge them
+const GRASS: [[f32; 3]; 4] = [
+    [0.80, 0.95, 0.90],
+    [0.70, 0.95, 0.80],
+    [1.00, 0.95, 0.65],
+    [0.70, 1.00, 0.60],
+];
+const FOLIAGE: [[f32; 3]; 4] = [
+    [0.75, 0.90, 0.85],
+    [0.60, 0.85, 0.70],
+    [0.95, 0.90, 0.55],
+    [0.55, 0.90, 0.45],
+];
+const WATER: [[f32; 3]; 4] = [
+    [0.80, 0.90, 1.00],
+    [0.70, 0.80, 0.95],
+    [0.70, 1.00, 1.00],
+    [0.60, 0.70, 0.45],
+];
+
+fn colormap(corners: &[[f32; 3]; 4], climate: Climate) -> [f32; 3] {
+    let heat = climate.heat.clamp(0.0, 1.0);
+    let humidity = climate.humidity.clamp(0.0, 1.0);
+    let mut color = [0.0; 3];
+    for (channel, value) in color.iter_mut().enumerate() {
+        let cold = lerp(corners[0][channel], corners[1][channel], humidity);
+        let hot = lerp(corners[2][channel], corners[3][channel], humidity);
+        *value = lerp(cold, hot, heat);
+    }
+    color
+}
+
+pub fn tint_color(kind: TintKind, climate: Climate) -> [f32; 3] {
+    match kind {
+        TintKind::Grass => colormap(&GRASS, climate),
+        TintKind::Foliage => colormap(&FOLIAGE, climate),
+        TintKind::Water => colormap(&WATER, climate),
+        TintKind::Fixed(r, g, b) => [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::biomes::descriptor::{BiomeDescriptor, ClimateRange};
+
+    fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn colormap_has_no_steps() {
+        const STEPS: usize = 100;
+        for kind in [TintKind::Grass, TintKind::Foliage, TintKind::Water] {
+            for heat in 0..=STEPS {
+                for humidity in 0..=STEPS {
+                    let at = |heat: usize, humidity: usize| {
+                        tint_color(
+                            kind,
+                            Climate {
+                                heat: heat as f32 / STEPS as f32,
+                                humidity: humidity as f32 / STEPS as f32,
+                            },
+                        )
+                    };
+                    let here = at(heat, humidity);
+                    assert!(here.iter().all(|channel| (0.0..=1.0).contains(channel)));
+                    // The steepest edge of the map changes by under 0.6 across the whole range
+                    if heat < STEPS {
+                        assert!(distance(here, at(heat + 1, humidity)) < 0.6 / STEPS as f32);
+                    }
+                    if humidity < STEPS {
+                        assert!(distance(here, at(heat, humidity + 1)) < 0.6 / STEPS as f32);
+                    }
+                }
+            }
+        }
+        // Out of range climates clamp to the edge instead of extrapolating
+        let edge = tint_color(
+            TintKind::Grass,
+            Climate {
+                heat: 1.0,
+                humidity: 0.0,
+            },
+        );
+        let past = tint_color(
+            TintKind::Grass,
+            Climate {
+                heat: 3.0,
+                humidity: -1.0,
+            },
+        );
+        assert_eq!(edge, past);
+    }
+
+    #[test]
+    fn biomes_go_by_the_nearest_climate() {
+        let mut biomes = BiomeTable::default();
+        for (name, heat, humidity) in [
+            ("desert", 0.9, 0.1),
+            ("swamp", 0.6, 0.9),
+            ("tundra", 0.1, 0.3),
+            ("also_tundra", 0.1, 0.3),
+        ] {
+            biomes.insert(
+                format!("vinox:{name}"),
+                BiomeDescriptor {
+                    name: name.to_string(),
+                    heat,
+                    humidity,
+                    ..Default::default()
+                },
+            );
+        }
+        let at = |heat, humidity| closest_biome(Climate { heat, humidity }, &biomes);
+        assert_eq!(at(1.0, 0.0), Some("vinox:desert"));
+        assert_eq!(at(0.5, 1.0), Some("vinox:swamp"));
+        assert_eq!(at(0.0, 0.2), Some("vinox:also_tundra"));
+        assert_eq!(
+            closest_biome(Climate::default(), &BiomeTable::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn biomes_go_by_their_ranges_first() {
+        let mut biomes = BiomeTable::default();
+        let range = |min, max| Some(ClimateRange { min, max });
+        for (name, heat, humidity, heat_range, humidity_range) in [
+            ("desert", 0.9, 0.1, range(0.55, 1.0), range(0.0, 0.4)),
+            ("savanna", 0.7, 0.3, range(0.5, 0.8), range(0.2, 0.5)),
+            ("jungle", 0.8, 0.9, range(0.6, 1.0), None),
+            ("plains", 0.5, 0.3, None, None),
+        ] {
+            biomes.insert(
+                format!("vinox:{name}"),
+                BiomeDescriptor {
+                    name: name.to_string(),
+                    heat,
+                    humidity,
+                    heat_range,
+                    humidity_range,
+                    ..Default::default()
+                },
+            );
+        }
+        let at = |heat, humidity| closest_biome(Climate { heat, humidity }, &biomes);
+        // Only the desert covers it though plains is nearer
+        assert_eq!(at(0.56, 0.15), Some("vinox:desert"));
+        // Desert and savanna overlap, the nearer point wins
+        assert_eq!(at(0.75, 0.35), Some("vinox:savanna"));
+        assert_eq!(at(0.85, 0.25), Some("vinox:desert"));
+        // Jungle covers every humidity within its heat
+        assert_eq!(at(0.65, 0.6), Some("vinox:jungle"));
+        // Nothing covers it, plains is the nearest point
+        assert_eq!(at(0.3, 0.6), Some("vinox:plains"));
+        assert_eq!(at(-1.0, 0.0), Some("vinox:plains"));
+        assert_eq!(at(2.0, -1.0), Some("vinox:desert"));
+    }
+
+    #[test]
+    fn neighbouring_columns_are_close() {
+        for x in -600..600 {
+            let z = x * 7 / 3;
+            let here = climate_at(x, z);
+            for other in [climate_at(x + 1, z), climate_at(x, z + 1)] {
+                assert!((here.heat - other.heat).abs() < 0.02);
+                assert!((here.humidity - other.humidity).abs() < 0.02);
+            }
+            assert!((0.0..=1.0).contains(&here.heat));
+            assert!((0.0..=1.0).contains(&here.humidity));
+        }
+    }
+}
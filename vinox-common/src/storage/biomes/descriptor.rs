@@ -14,15 +14,92 @@ pub enum TerrainCarver {
     Overhangs,
 }
 
+// How the air looks while in the biome. Each one multiplies what the view would be otherwise,
+// so the defaults leave it alone
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct Atmosphere {
+    pub fog_tint: [f32; 3],
+    // Divides the fog distances like the view's own fog density
+    pub fog_density: f32,
+    pub sky_tint: [f32; 3],
+    // Sound set to loop while in the biome
+    pub ambient_sounds: Option<String>,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            fog_tint: [1.0; 3],
+            fog_density: 1.0,
+            sky_tint: [1.0; 3],
+            ambient_sounds: None,
+        }
+    }
+}
+
+// Dropped on top of the biome's surface, each open spot rolls once against every entry in turn
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct BiomeDecoration {
+    pub block: String,
+    // Out of 1, per open spot
+    pub chance: f32,
+}
+
+// Inclusive, on the same 0 to 1 scale as the climate
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct ClimateRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl ClimateRange {
+    pub fn contains(&self, value: f32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
 // Anything optional here that is necessary for the game to function but we have a default value for ie texture or geometry
 #[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
 pub struct BiomeDescriptor {
     pub namespace: String,
     pub name: String,
     // pub terrain_carver: TerrainCarver,
+    // Where the biome sits when no biome's ranges hold the climate, and which one wins when
+    // several do
     pub heat: f32,
     pub humidity: f32,
+    // Climates the biome covers, a missing one covers the whole axis. A biome with neither
+    // only turns up through the nearest point
+    #[serde(default)]
+    pub heat_range: Option<ClimateRange>,
+    #[serde(default)]
+    pub humidity_range: Option<ClimateRange>,
+    // The top block of every column, the main block when there isn't one
     pub surface_block: Option<String>,
+    // The few blocks under the surface
     pub main_block: String,
+    // Everything deeper than that, stone when there isn't one
+    #[serde(default)]
+    pub fill_block: Option<String>,
+    // What the sea is made of, water when there isn't one
+    #[serde(default)]
+    pub sea_block: Option<String>,
+    #[serde(default)]
+    pub decorations: Vec<BiomeDecoration>,
     pub structures: Option<Vec<StructureBlocks>>,
+    #[serde(default)]
+    pub atmosphere: Atmosphere,
+}
+
+impl BiomeDescriptor {
+    pub fn covers(&self, heat: f32, humidity: f32) -> bool {
+        if self.heat_range.is_none() && self.humidity_range.is_none() {
+            return false;
+        }
+        self.heat_range.map_or(true, |range| range.contains(heat))
+            && self
+                .humidity_range
+                .map_or(true, |range| range.contains(humidity))
+    }
 }
@@ -1,2 +1,3 @@
+pub mod climate;
 pub mod descriptor;
 pub mod load;
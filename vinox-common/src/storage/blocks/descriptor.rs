@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use bevy::prelude::Vec3;
+
 use crate::{
     storage::items::descriptor::ToolType,
     world::chunks::storage::{identifier_to_just_name, VoxelVisibility},
@@ -55,9 +57,64 @@ impl BlockGeometry {
     }
 }
 
+// Colour multiplied into the faces depending on the climate where the block sits.
+// Grass only tints its top since the sides are mostly dirt
+#[derive(Deserialize, Serialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum TintKind {
+    Grass,
+    Foliage,
+    Water,
+    Fixed(u8, u8, u8),
+}
+
+// What makes a block turn into its target on its own, see world::transitions
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub enum TransitionTrigger {
+    // Chance of one in this many each time a random tick lands on the block
+    RandomTick { one_in: u32 },
+    // Some block of that identifier at most this many voxels away on every axis
+    NeighborIs { block: String, within: u8 },
+    // Server ticks since the block was placed or turned into what it is
+    TimeElapsed { ticks: u64 },
+    // Brightest light on the block, 0 to 15
+    LightAbove { level: u8 },
+    LightBelow { level: u8 },
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct TransitionRule {
+    pub target: String,
+    pub trigger: TransitionTrigger,
+}
+
+// Where a seated player's feet go, from the block's corner
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy)]
+pub struct SeatDescriptor {
+    pub offset: Vec3,
+}
+
+// Cross quads drawn on top of the block wherever there's air above it, only ever in the mesh.
+// texture is in the block's own folder, density from 0 for none to 1 for every block
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone)]
+pub struct DecorationDescriptor {
+    pub texture: String,
+    pub density: f32,
+}
+
+// Veins of the block laid through the ground while it generates, only between the two heights
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone)]
+pub struct OreDescriptor {
+    pub spawn_min_y: i32,
+    pub spawn_max_y: i32,
+    // Steps of the walk that lays a vein, it covers fewer blocks than that
+    pub vein_size: u32,
+    // Chunks per vein on average, under 1 for more than one in every chunk
+    pub rarity: f32,
+}
+
 // Anything optional here that is necessary for the game to function but we have a default value for ie texture or geometry
 // NOTE: We will also take in any children blocks this block may have. ie any slab, fence, stair variant etc
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
 pub struct BlockDescriptor {
     pub namespace: String, // TODO: Make sure that we only allow one namespace:name pair
     pub name: String,
@@ -66,6 +123,7 @@ pub struct BlockDescriptor {
     pub auto_geo: Option<Vec<BlockGeometry>>, // Contains strings of geometry we wan't to auto generate
     pub tex_variance: Option<[Option<bool>; 6]>,
     pub durability: Option<u32>,
+    pub break_time: Option<f32>, // Seconds of holding the button to break it, instant if unset
     pub tool_type: Option<ToolType>,
     pub friction: Option<u32>,
     pub walk_sound: Option<String>,
@@ -76,7 +134,17 @@ pub struct BlockDescriptor {
     pub has_direction: Option<bool>,       // Also affects up and down
     pub exclusive_direction: Option<bool>, // If this block needs only either top and bottom or direction. Or if it needs both top and bottom and direction
     pub light: Option<(u8, u8, u8, u8)>,   //Red, Green, Blue, Intensity
+    pub light_faces: Option<[bool; 6]>, // Which faces a light shines out of, all of them if unset
     pub interactable: Option<bool>,
     pub gui: Option<String>,
     pub has_item: Option<bool>, // Basically whether or not we should auto generate an item for this block
+    pub climbable: Option<bool>,
+    pub fluid: Option<bool>, // Fluids don't collide and make players swim
+    pub tint: Option<TintKind>,
+    pub sleepable: Option<bool>, // Beds and anchors, using one sets where the player respawns
+    pub display_frame: Option<bool>, // Holds one item and shows it on the face it hangs on
+    pub transitions: Option<Vec<TransitionRule>>, // Checked in order, the first that fires wins
+    pub seat: Option<SeatDescriptor>, // Using it with an empty hand sits the player down
+    pub decoration: Option<DecorationDescriptor>, // Grass tufts and the like, see world::decoration
+    pub ore: Option<OreDescriptor>, // Generated in veins through the ground
 }
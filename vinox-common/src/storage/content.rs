@@ -0,0 +1,697 @@
+use std::{collections::BTreeMap, fmt};
+
+use bevy::prelude::Resource;
+use serde::{ser, Deserialize, Serialize};
+
+use crate::{
+    ecs::rng::hash_bytes,
+    storage::geometry::descriptor::GeometryDescriptor,
+    world::chunks::storage::{name_to_identifier, BlockTable, ItemTable, RecipeTable},
+};
+
+// What blocks the client doesn't know about turn into when the server lets it in anyway
+pub const MISSING_BLOCK: (&str, &str) = ("vinox", "missing");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Registry {
+    Blocks,
+    Items,
+    Recipes,
+    Geometry,
+}
+
+impl Registry {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Registry::Blocks => "block",
+            Registry::Items => "item",
+            Registry::Recipes => "recipe",
+            Registry::Geometry => "geometry",
+        }
+    }
+}
+
+// What the server does with a client whose content doesn't match its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ContentPolicy {
+    // Any difference at all turns the client away
+    #[default]
+    Strict,
+    // Lets them in with a warning, blocks they don't have show up as the missing block
+    Warn,
+}
+
+// Every identifier in each registry with a hash of its definition, sorted by identifier. Small
+// enough to send on every join where the definitions themselves wouldn't be
+#[derive(Resource, Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ContentManifest {
+    pub registries: BTreeMap<Registry, Vec<(String, u64)>>,
+}
+
+impl ContentManifest {
+    pub fn new(
+        blocks: &BlockTable,
+        items: &ItemTable,
+        recipes: &RecipeTable,
+        geometry: &[GeometryDescriptor],
+    ) -> Self {
+        let mut manifest = Self::default();
+        manifest.insert(Registry::Blocks, blocks.iter());
+        manifest.insert(Registry::Items, items.iter());
+        manifest.insert(Registry::Recipes, recipes.iter());
+        let geometry: Vec<(String, &GeometryDescriptor)> = geometry
+            .iter()
+            .map(|geo| {
+                (
+                    name_to_identifier(geo.namespace.clone(), geo.name.clone()),
+                    geo,
+                )
+            })
+            .collect();
+        manifest.insert(
+            Registry::Geometry,
+            geometry.iter().map(|(identifier, geo)| (identifier, *geo)),
+        );
+        manifest
+    }
+
+    pub fn insert<'a, T: Serialize + 'a>(
+        &mut self,
+        registry: Registry,
+        entries: impl IntoIterator<Item = (&'a String, &'a T)>,
+    ) {
+        let mut hashes: Vec<(String, u64)> = entries
+            .into_iter()
+            .map(|(identifier, descriptor)| (identifier.clone(), canonical_hash(descriptor)))
+            .collect();
+        hashes.sort();
+        self.registries.insert(registry, hashes);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ContentDiff {
+    // The server has these and the client doesn't
+    pub missing_on_client: Vec<(Registry, String)>,
+    pub missing_on_server: Vec<(Registry, String)>,
+    // Both have them but they're defined differently
+    pub mismatched: Vec<(Registry, String)>,
+}
+
+impl ContentDiff {
+    pub fn between(server: &ContentManifest, client: &ContentManifest) -> Self {
+        let mut diff = Self::default();
+        let mut registries: Vec<Registry> = server
+            .registries
+            .keys()
+            .chain(client.registries.keys())
+            .copied()
+            .collect();
+        registries.sort();
+        registries.dedup();
+        for registry in registries {
+            let server = server
+                .registries
+                .get(&registry)
+                .map_or(&[][..], Vec::as_slice);
+            let client = client
+                .registries
+                .get(&registry)
+                .map_or(&[][..], Vec::as_slice);
+            // Both sides are sorted so one pass over each is enough
+            let (mut s, mut c) = (0, 0);
+            while s < server.len() || c < client.len() {
+                match (server.get(s), client.get(c)) {
+                    (Some((server_id, server_hash)), Some((client_id, client_hash)))
+                        if server_id == client_id =>
+                    {
+                        if server_hash != client_hash {
+                            diff.mismatched.push((registry, server_id.clone()));
+                        }
+                        s += 1;
+                        c += 1;
+                    }
+                    (Some((server_id, _)), Some((client_id, _))) if server_id < client_id => {
+                        diff.missing_on_client.push((registry, server_id.clone()));
+                        s += 1;
+                    }
+                    (Some((server_id, _)), None) => {
+                        diff.missing_on_client.push((registry, server_id.clone()));
+                        s += 1;
+                    }
+                    (_, Some((client_id, _))) => {
+                        diff.missing_on_server.push((registry, client_id.clone()));
+                        c += 1;
+                    }
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.missing_on_client.is_empty()
+            && self.missing_on_server.is_empty()
+            && self.mismatched.is_empty()
+    }
+
+    pub fn missing_blocks(&self) -> bool {
+        self.missing_on_client
+            .iter()
+            .any(|(registry, _)| *registry == Registry::Blocks)
+    }
+
+    // A line per category with up to `shown` identifiers each, the rest only get counted
+    pub fn summary(&self, shown: usize) -> Vec<String> {
+        [
+            ("Missing here", &self.missing_on_client),
+            ("Missing on the server", &self.missing_on_server),
+            ("Defined differently", &self.mismatched),
+        ]
+        .into_iter()
+        .filter(|(_, entries)| !entries.is_empty())
+        .map(|(heading, entries)| {
+            let mut listed: Vec<String> = entries
+                .iter()
+                .take(shown)
+                .map(|(registry, identifier)| format!("{} {identifier}", registry.label()))
+                .collect();
+            if entries.len() > shown {
+                listed.push(format!("{} more", entries.len() - shown));
+            }
+            format!("{heading}: {}", listed.join(", "))
+        })
+        .collect()
+    }
+}
+
+// Hash of a definition that's the same on every platform and every run. Maps and structs are
+// encoded sorted by key so HashMap order never leaks in, and FNV doesn't change between releases
+// the way std's hasher can
+pub fn canonical_hash<T: Serialize + ?Sized>(value: &T) -> u64 {
+    hash_bytes(&encode(value).expect("definitions are plain data"))
+}
+
+#[derive(Debug)]
+pub struct CanonicalError(String);
+
+impl fmt::Display for CanonicalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CanonicalError {}
+
+impl ser::Error for CanonicalError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        CanonicalError(msg.to_string())
+    }
+}
+
+// Every value starts with a tag and everything variable is length prefixed, so no two different
+// values encode to the same bytes
+#[derive(Default)]
+struct Canonical {
+    bytes: Vec<u8>,
+}
+
+impl Canonical {
+    fn tag(&mut self, tag: u8) {
+        self.bytes.push(tag);
+    }
+
+    fn count(&mut self, len: usize) {
+        self.bytes.extend((len as u64).to_le_bytes());
+    }
+
+    fn text(&mut self, text: &str) {
+        self.count(text.len());
+        self.bytes.extend(text.as_bytes());
+    }
+}
+
+fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CanonicalError> {
+    let mut canonical = Canonical::default();
+    value.serialize(&mut canonical)?;
+    Ok(canonical.bytes)
+}
+
+// Sequences keep their order, maps and structs get their entries sorted
+struct Compound<'a> {
+    out: &'a mut Canonical,
+    sorted: bool,
+    entries: Vec<Vec<u8>>,
+    key: Option<Vec<u8>>,
+}
+
+impl<'a> Compound<'a> {
+    fn new(out: &'a mut Canonical, sorted: bool) -> Self {
+        Self {
+            out,
+            sorted,
+            entries: Vec::new(),
+            key: None,
+        }
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CanonicalError> {
+        self.entries.push(encode(value)?);
+        Ok(())
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), CanonicalError> {
+        let mut entry = encode(key)?;
+        entry.extend(encode(value)?);
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), CanonicalError> {
+        if self.sorted {
+            self.entries.sort();
+        }
+        self.out.count(self.entries.len());
+        for entry in self.entries {
+            self.out.bytes.extend(entry);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut Canonical {
+    type Ok = ();
+    type Error = CanonicalError;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), CanonicalError> {
+        self.tag(b'b');
+        self.bytes.push(v as u8);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), CanonicalError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), CanonicalError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), CanonicalError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), CanonicalError> {
+        self.tag(b'i');
+        self.bytes.extend(v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), CanonicalError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), CanonicalError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), CanonicalError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), CanonicalError> {
+        self.tag(b'u');
+        self.bytes.extend(v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), CanonicalError> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), CanonicalError> {
+        self.tag(b'f');
+        self.bytes.extend(v.to_bits().to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), CanonicalError> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), CanonicalError> {
+        self.tag(b's');
+        self.text(v);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), CanonicalError> {
+        self.tag(b'x');
+        self.count(v.len());
+        self.bytes.extend(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), CanonicalError> {
+        self.tag(b'n');
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), CanonicalError> {
+        self.tag(b'y');
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), CanonicalError> {
+        self.tag(b'0');
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), CanonicalError> {
+        self.serialize_unit()
+    }
+
+    // Variants go by name, reordering an enum doesn't change anything that uses it
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), CanonicalError> {
+        self.tag(b'v');
+        self.text(variant);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), CanonicalError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), CanonicalError> {
+        self.tag(b'V');
+        self.text(variant);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a>, CanonicalError> {
+        self.tag(b'[');
+        Ok(Compound::new(self, false))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a>, CanonicalError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, CanonicalError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, CanonicalError> {
+        self.tag(b'V');
+        self.text(variant);
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a>, CanonicalError> {
+        self.tag(b'{');
+        Ok(Compound::new(self, true))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, CanonicalError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, CanonicalError> {
+        self.tag(b'V');
+        self.text(variant);
+        self.serialize_map(Some(len))
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_> {
+    type Ok = ();
+    type Error = CanonicalError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_> {
+    type Ok = ();
+    type Error = CanonicalError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_> {
+    type Ok = ();
+    type Error = CanonicalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_> {
+    type Ok = ();
+    type Error = CanonicalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeMap for Compound<'_> {
+    type Ok = ();
+    type Error = CanonicalError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        self.key = Some(encode(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let mut entry = self.key.take().unwrap_or_default();
+        entry.extend(encode(value)?);
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_> {
+    type Ok = ();
+    type Error = CanonicalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_> {
+    type Ok = ();
+    type Error = CanonicalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::storage::{
+        blocks::descriptor::{BlockDescriptor, TintKind},
+        crafting::descriptor::RecipeDescriptor,
+    };
+
+    fn block(name: &str, faces: &[&str]) -> BlockDescriptor {
+        BlockDescriptor {
+            namespace: "vinox".to_string(),
+            name: name.to_string(),
+            textures: Some(
+                faces
+                    .iter()
+                    .map(|face| (Some(face.to_string()), Some(format!("{name}_{face}.png"))))
+                    .collect::<HashMap<_, _>>(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn blocks(blocks: Vec<BlockDescriptor>) -> BlockTable {
+        let mut table = BlockTable::default();
+        for block in blocks {
+            table.insert(
+                name_to_identifier(block.namespace.clone(), block.name.clone()),
+                block,
+            );
+        }
+        table
+    }
+
+    #[test]
+    fn hashes_ignore_insertion_order() {
+        let faces = ["up", "down", "left", "right", "front", "back"];
+        let mut reversed = faces;
+        reversed.reverse();
+        let first = blocks(vec![block("stone", &faces), block("dirt", &faces)]);
+        let second = blocks(vec![block("dirt", &reversed), block("stone", &reversed)]);
+        let mut recipes = RecipeTable::default();
+        recipes.insert(
+            "vinox:planks".to_string(),
+            RecipeDescriptor {
+                required_items: Some(HashMap::from([
+                    ("vinox:log".to_string(), 1),
+                    ("vinox:stick".to_string(), 2),
+                ])),
+                ..Default::default()
+            },
+        );
+        let manifest = |table| ContentManifest::new(table, &ItemTable::default(), &recipes, &[]);
+        assert_eq!(manifest(&first), manifest(&second));
+
+        // Anything that changes the definition changes its hash
+        let stone = block("stone", &faces);
+        let tinted = BlockDescriptor {
+            tint: Some(TintKind::Grass),
+            ..stone.clone()
+        };
+        let foliage = BlockDescriptor {
+            tint: Some(TintKind::Foliage),
+            ..stone.clone()
+        };
+        assert_ne!(canonical_hash(&stone), canonical_hash(&tinted));
+        assert_ne!(canonical_hash(&tinted), canonical_hash(&foliage));
+        assert_ne!(
+            canonical_hash(&stone),
+            canonical_hash(&block("stone", &faces[..5]))
+        );
+    }
+
+    #[test]
+    fn diff_sorts_out_every_kind_of_mismatch() {
+        let shared = block("stone", &["front"]);
+        let server = blocks(vec![
+            shared.clone(),
+            block("marble", &["front"]),
+            block("glass", &["front"]),
+        ]);
+        let client = blocks(vec![
+            shared,
+            block("glass", &["front", "up"]),
+            block("basalt", &["front"]),
+        ]);
+        let mut items = ItemTable::default();
+        items.insert("vinox:stick".to_string(), Default::default());
+        let server = ContentManifest::new(&server, &items, &RecipeTable::default(), &[]);
+        let client =
+            ContentManifest::new(&client, &ItemTable::default(), &RecipeTable::default(), &[]);
+
+        let diff = ContentDiff::between(&server, &client);
+        assert_eq!(
+            diff.missing_on_client,
+            vec![
+                (Registry::Blocks, "vinox:marble".to_string()),
+                (Registry::Items, "vinox:stick".to_string()),
+            ]
+        );
+        assert_eq!(
+            diff.missing_on_server,
+            vec![(Registry::Blocks, "vinox:basalt".to_string())]
+        );
+        assert_eq!(
+            diff.mismatched,
+            vec![(Registry::Blocks, "vinox:glass".to_string())]
+        );
+        assert!(diff.missing_blocks());
+        assert_eq!(
+            diff.summary(1),
+            vec![
+                "Missing here: block vinox:marble, 1 more".to_string(),
+                "Missing on the server: block vinox:basalt".to_string(),
+                "Defined differently: block vinox:glass".to_string(),
+            ]
+        );
+
+        assert!(ContentDiff::between(&server, &server).is_empty());
+    }
+}
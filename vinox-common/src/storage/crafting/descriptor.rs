@@ -2,6 +2,15 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
+// What a player has to do before a recipe shows up in their book
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub enum RecipeUnlock {
+    #[default]
+    Always,
+    ObtainItem(String),
+    Crafted(String), // Identifier of the other recipe
+}
+
 // Anything optional here that is necessary for the game to function but we have a default value for ie texture or geometry
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
 pub struct RecipeDescriptor {
@@ -10,4 +19,5 @@ pub struct RecipeDescriptor {
     pub required_items: Option<HashMap<String, u32>>,
     pub output_item: (String, u32),
     pub script: Option<String>,
+    pub unlocked_by: Option<RecipeUnlock>,
 }
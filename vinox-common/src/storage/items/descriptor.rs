@@ -1,6 +1,10 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use strum::EnumString;
 
+use crate::ecs::time::{GameDuration, ServerTick};
+
 pub const MAX_STACK_SIZE: u32 = 1000;
 
 #[derive(EnumString, Default, Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
@@ -25,6 +29,75 @@ pub struct ItemDescriptor {
     pub tool_type: Option<ToolType>, // Basically for blocks we just do associated_block with no tool and vice versa for tools. But this allows people to make a tool that places a block for example. Scripts will also allow for people to add different functionality to items
     pub script: Option<String>,
     pub associated_block: Option<String>, // String should be an identifier in form of namespace:name, Potentially may change this to be block data instead so people could choose a certain state of a block to put down but we will see
+    pub category: Option<String>, // Which creative palette tab this shows up in, anything unknown goes to Misc
+    pub use_duration_ms: Option<u32>, // How long the button has to be held before the use goes through, eating or charging a bow
+    pub cooldown_ms: Option<u32>, // Wait after a use before the same slot can be used again, tool swing recovery
+}
+
+// Both sides read these from the same descriptor so the server can hold the client to them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct UseTiming {
+    pub duration: GameDuration,
+    pub cooldown: GameDuration,
+}
+
+impl UseTiming {
+    pub fn is_instant(&self) -> bool {
+        self.duration == GameDuration::ZERO
+    }
+
+    // The shortest gap a well behaved client can leave between two uses of the same item
+    pub fn interval_ticks(&self) -> u64 {
+        ((self.duration + self.cooldown).as_secs() / ServerTick::LENGTH).round() as u64
+    }
+}
+
+#[derive(Default, Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum ItemCategory {
+    Building,
+    Decoration,
+    Tools,
+    #[default]
+    Misc,
+}
+
+impl ItemCategory {
+    pub const ALL: [ItemCategory; 4] = [
+        ItemCategory::Building,
+        ItemCategory::Decoration,
+        ItemCategory::Tools,
+        ItemCategory::Misc,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ItemCategory::Building => "Building",
+            ItemCategory::Decoration => "Decoration",
+            ItemCategory::Tools => "Tools",
+            ItemCategory::Misc => "Misc",
+        }
+    }
+}
+
+impl ItemDescriptor {
+    pub fn category(&self) -> ItemCategory {
+        let Some(category) = &self.category else {
+            return ItemCategory::Misc;
+        };
+        ItemCategory::ALL
+            .into_iter()
+            .find(|known| known.name().eq_ignore_ascii_case(category.trim()))
+            .unwrap_or_default()
+    }
+
+    pub fn use_timing(&self) -> UseTiming {
+        let millis =
+            |millis: Option<u32>| GameDuration::from_millis(millis.unwrap_or_default() as u64);
+        UseTiming {
+            duration: millis(self.use_duration_ms),
+            cooldown: millis(self.cooldown_ms),
+        }
+    }
 }
 
 // Instance of a item with some data
@@ -33,6 +106,225 @@ pub struct ItemData {
     pub namespace: String,
     pub name: String,
     pub stack_size: u32,
-    pub durability: u32,
     pub arbitary_data: Option<String>,
+    // None for a plain item straight from its descriptor, older inventories don't have it
+    #[serde(default)]
+    pub metadata: Option<ItemMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Default, Clone, Copy)]
+pub struct Durability {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Durability {
+    pub fn fraction(&self) -> f32 {
+        if self.max == 0 {
+            return 0.0;
+        }
+        self.current as f32 / self.max as f32
+    }
+}
+
+// What sets one item apart from others of its kind. Only stacks with the same metadata merge
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Default, Clone)]
+#[serde(default)]
+pub struct ItemMetadata {
+    pub name: Option<String>,
+    // None until the first use wears it down, a fresh tool is at full
+    pub durability: Option<Durability>,
+    // For scripts and whatever comes later, book contents and the like
+    pub data: BTreeMap<String, String>,
+}
+
+impl ItemMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl ItemData {
+    // The one place deciding whether two stacks can become one
+    pub fn can_stack_with(&self, other: &ItemData) -> bool {
+        self.namespace == other.namespace
+            && self.name == other.name
+            && self.metadata == other.metadata
+    }
+
+    pub fn custom_name(&self) -> Option<&str> {
+        self.metadata.as_ref()?.name.as_deref()
+    }
+
+    pub fn worn_durability(&self) -> Option<Durability> {
+        self.metadata.as_ref()?.durability
+    }
+
+    // None clears the name. Metadata that ends up empty goes away so it stacks with plain items again
+    pub fn rename(&mut self, name: Option<String>) {
+        let mut metadata = self.metadata.take().unwrap_or_default();
+        metadata.name = name.filter(|name| !name.trim().is_empty());
+        self.metadata = (!metadata.is_empty()).then_some(metadata);
+    }
+
+    // One use worth of wear on a tool with `max` durability, None once it breaks
+    pub fn wear(mut self, max: u32) -> Option<ItemData> {
+        let mut metadata = self.metadata.take().unwrap_or_default();
+        let current = metadata
+            .durability
+            .map_or(max, |durability| durability.current.min(max))
+            .saturating_sub(1);
+        if current == 0 {
+            return None;
+        }
+        metadata.durability = Some(Durability { current, max });
+        self.metadata = Some(metadata);
+        Some(self)
+    }
+}
+
+// Only the wear changes, the slot keeps its own stack and name. None for worn means it broke,
+// that one comes off the stack and is handed back
+pub fn wear_slot(slot: &mut Option<ItemData>, worn: Option<&ItemData>) -> Option<ItemData> {
+    let held = slot.as_mut()?;
+    match worn {
+        Some(worn) => {
+            let durability = worn.worn_durability();
+            let mut metadata = held.metadata.take().unwrap_or_default();
+            metadata.durability = durability;
+            held.metadata = (!metadata.is_empty()).then_some(metadata);
+            None
+        }
+        None => {
+            let broke = ItemData {
+                stack_size: 1,
+                ..held.clone()
+            };
+            if held.stack_size > 1 {
+                // The next one in the stack starts out fresh
+                held.stack_size -= 1;
+                held.metadata = held
+                    .metadata
+                    .take()
+                    .map(|metadata| ItemMetadata {
+                        durability: None,
+                        ..metadata
+                    })
+                    .filter(|metadata| !metadata.is_empty());
+            } else {
+                *slot = None;
+            }
+            Some(broke)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn category_falls_back_to_misc() {
+        let mut item = ItemDescriptor::default();
+        assert_eq!(item.category(), ItemCategory::Misc);
+        item.category = Some("tools".to_string());
+        assert_eq!(item.category(), ItemCategory::Tools);
+        item.category = Some(" Decoration ".to_string());
+        assert_eq!(item.category(), ItemCategory::Decoration);
+        item.category = Some("Weapons".to_string());
+        assert_eq!(item.category(), ItemCategory::Misc);
+    }
+
+    fn pickaxe() -> ItemData {
+        ItemData {
+            namespace: "vinox".to_string(),
+            name: "pickaxe".to_string(),
+            stack_size: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_matching_metadata_stacks() {
+        let plain = pickaxe();
+        assert!(plain.can_stack_with(&pickaxe()));
+        let mut named = pickaxe();
+        named.rename(Some("Digger".to_string()));
+        assert_eq!(named.custom_name(), Some("Digger"));
+        assert!(!named.can_stack_with(&plain));
+        let worn = pickaxe().wear(10).unwrap();
+        assert!(!worn.can_stack_with(&plain));
+        assert!(!worn.can_stack_with(&named));
+        assert!(worn.can_stack_with(&pickaxe().wear(10).unwrap()));
+        // Clearing the name makes it plain again
+        named.rename(None);
+        assert!(named.metadata.is_none());
+        assert!(named.can_stack_with(&plain));
+    }
+
+    #[test]
+    fn wears_down_and_breaks() {
+        let mut item = Some(pickaxe());
+        for left in (1..3).rev() {
+            item = item.unwrap().wear(3);
+            assert_eq!(
+                item.as_ref().unwrap().worn_durability(),
+                Some(Durability {
+                    current: left,
+                    max: 3
+                })
+            );
+        }
+        assert!(item.unwrap().wear(3).is_none());
+        // Wear keeps the name it already had
+        let mut named = pickaxe();
+        named.rename(Some("Digger".to_string()));
+        let worn = named.wear(5).unwrap();
+        assert_eq!(worn.custom_name(), Some("Digger"));
+        assert_eq!(worn.worn_durability().unwrap().fraction(), 0.8);
+    }
+
+    #[test]
+    fn wear_keeps_the_slot_and_breaking_empties_it() {
+        let mut named = pickaxe();
+        named.rename(Some("Digger".to_string()));
+        let mut slot = Some(named.clone());
+        let worn = named.wear(2).unwrap();
+        assert_eq!(wear_slot(&mut slot, Some(&worn)), None);
+        let held = slot.as_ref().unwrap();
+        assert_eq!(held.custom_name(), Some("Digger"));
+        assert_eq!(
+            held.worn_durability(),
+            Some(Durability { current: 1, max: 2 })
+        );
+
+        let broke = wear_slot(&mut slot, None).unwrap();
+        assert_eq!(broke.custom_name(), Some("Digger"));
+        assert!(slot.is_none());
+
+        // A stack only loses the one that broke
+        let mut stack = Some(ItemData {
+            stack_size: 3,
+            ..worn
+        });
+        assert_eq!(wear_slot(&mut stack, None).unwrap().stack_size, 1);
+        let rest = stack.unwrap();
+        assert_eq!(rest.stack_size, 2);
+        assert_eq!(rest.worn_durability(), None);
+        assert_eq!(rest.custom_name(), Some("Digger"));
+        assert_eq!(wear_slot(&mut None, None), None);
+    }
+
+    #[test]
+    fn reads_items_from_before_metadata() {
+        let old = r#"(namespace: "vinox", name: "dirt", stack_size: 5, durability: 0, arbitary_data: None)"#;
+        let item: ItemData = ron::from_str(old).unwrap();
+        assert_eq!(item.stack_size, 5);
+        assert!(item.metadata.is_none());
+        // Partial metadata fills in the rest
+        let named = r#"(namespace: "vinox", name: "dirt", stack_size: 1, durability: 0, arbitary_data: None, metadata: Some((name: Some("Soil"))))"#;
+        let item: ItemData = ron::from_str(named).unwrap();
+        assert_eq!(item.custom_name(), Some("Soil"));
+        assert!(item.metadata.unwrap().data.is_empty());
+    }
 }
@@ -58,5 +58,8 @@ pub fn item_from_block(block: BlockDescriptor) -> ItemDescriptor {
         tool_type: None,
         script: None,
         associated_block: Some(name),
+        category: Some("Building".to_string()),
+        use_duration_ms: None,
+        cooldown_ms: None,
     }
 }
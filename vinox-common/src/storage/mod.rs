@@ -1,5 +1,6 @@
 pub mod biomes;
 pub mod blocks;
+pub mod content;
 pub mod crafting;
 pub mod entities;
 pub mod geometry;
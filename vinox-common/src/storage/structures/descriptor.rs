@@ -1,4 +1,48 @@
 use serde::{Deserialize, Serialize};
 
+// What a structure has to stand on, read from the ground the surface pass leaves behind
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub enum StructureAnchor {
+    // Any open top of the ground
+    #[default]
+    Surface,
+    // Only open tops of this block
+    OnBlock(String),
+}
+
+// One block of a structure, offset from the voxel right above the ground it stands on
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct StructureBlock {
+    pub offset: (i32, i32, i32),
+    pub block: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub struct StructureDescriptor {
+    pub namespace: String,
+    pub name: String,
+    #[serde(default)]
+    pub anchor: StructureAnchor,
+    pub blocks: Vec<StructureBlock>,
+}
+
+impl StructureDescriptor {
+    // Furthest any block is from the anchor along one axis
+    pub fn reach(&self) -> u32 {
+        self.blocks
+            .iter()
+            .map(|block| {
+                let (x, y, z) = block.offset;
+                x.unsigned_abs().max(y.unsigned_abs()).max(z.unsigned_abs())
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+// A structure a biome scatters, `chance` is per column of ground
 #[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
-pub struct StructureBlocks {}
+pub struct StructureBlocks {
+    pub structure: String,
+    pub chance: f32,
+}
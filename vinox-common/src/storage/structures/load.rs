@@ -0,0 +1,28 @@
+use directories::ProjectDirs;
+use std::fs;
+
+use walkdir::WalkDir;
+
+use super::descriptor::StructureDescriptor;
+
+pub fn load_all_structures() -> Vec<StructureDescriptor> {
+    let mut result = Vec::new();
+    if let Some(proj_dirs) = ProjectDirs::from("com", "vinox", "vinox") {
+        for entry in WalkDir::new(proj_dirs.data_dir().join("assets/structures"))
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if entry.path().extension().unwrap_or_default() == "ron" {
+                if let Ok(ron_string) = fs::read_to_string(entry.path()) {
+                    let ron_result = ron::from_str(ron_string.as_str());
+                    if let Ok(structure) = ron_result {
+                        result.push(structure);
+                    } else {
+                        println!("{ron_result:?}");
+                    }
+                }
+            }
+        }
+    }
+    result
+}
@@ -0,0 +1,33 @@
+use crate::{
+    storage::blocks::descriptor::BlockDescriptor,
+    world::chunks::storage::{name_to_identifier, BlockData, BlockTable, VoxelVisibility},
+};
+
+// Builders every crate's tests share, each test only spells out what it's actually about
+
+// A vinox block with nothing else set
+pub fn block(name: &str) -> BlockData {
+    BlockData::new("vinox".to_string(), name.to_string())
+}
+
+// Whatever else a test needs goes on top with ..descriptor(name, visibility)
+pub fn descriptor(name: &str, visibility: VoxelVisibility) -> BlockDescriptor {
+    BlockDescriptor {
+        namespace: "vinox".to_string(),
+        name: name.to_string(),
+        visibility: Some(visibility),
+        ..Default::default()
+    }
+}
+
+// Keyed by identifier, same as loaded content
+pub fn block_table(descriptors: impl IntoIterator<Item = BlockDescriptor>) -> BlockTable {
+    let mut block_table = BlockTable::default();
+    for descriptor in descriptors {
+        block_table.insert(
+            name_to_identifier(descriptor.namespace.clone(), descriptor.name.clone()),
+            descriptor,
+        );
+    }
+    block_table
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, Bytes};
+
+use crate::storage::biomes::climate::{climate_at, closest_biome};
+
+use super::storage::{BiomeTable, CHUNK_SIZE};
+
+const COLUMNS: usize = CHUNK_SIZE * CHUNK_SIZE;
+
+// The biome of every column in a chunk. The server works it out and sends it along with the chunk
+// so the client never has to agree with it on how biomes are picked. An empty palette is a
+// chunk nobody knows the biomes of
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkBiomes {
+    palette: Vec<String>,
+    #[serde_as(as = "Bytes")]
+    columns: [u8; COLUMNS],
+}
+
+impl Default for ChunkBiomes {
+    fn default() -> Self {
+        Self {
+            palette: Vec::new(),
+            columns: [0; COLUMNS],
+        }
+    }
+}
+
+impl ChunkBiomes {
+    pub fn compute(chunk_pos: IVec3, biome_table: &BiomeTable) -> Self {
+        let mut biomes = Self::default();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let climate = climate_at(
+                    chunk_pos.x * CHUNK_SIZE as i32 + x as i32,
+                    chunk_pos.z * CHUNK_SIZE as i32 + z as i32,
+                );
+                if let Some(identifier) = closest_biome(climate, biome_table) {
+                    biomes.set(x, z, identifier);
+                }
+            }
+        }
+        biomes
+    }
+
+    // Past 256 biomes in one chunk the rest share the last slot
+    pub fn set(&mut self, x: usize, z: usize, identifier: &str) {
+        let index = match self.palette.iter().position(|entry| entry == identifier) {
+            Some(index) => index,
+            None if self.palette.len() <= u8::MAX as usize => {
+                self.palette.push(identifier.to_string());
+                self.palette.len() - 1
+            }
+            None => u8::MAX as usize,
+        };
+        self.columns[z * CHUNK_SIZE + x] = index as u8;
+    }
+
+    pub fn get(&self, x: usize, z: usize) -> Option<&str> {
+        let index = *self.columns.get(z * CHUNK_SIZE + x)?;
+        self.palette.get(index as usize).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        networking::protocol::ServerMessage, storage::biomes::descriptor::BiomeDescriptor,
+        world::chunks::positions::DimensionId,
+    };
+
+    #[test]
+    fn biomes_survive_the_chunk_message() {
+        let mut biome_table = BiomeTable::default();
+        for (name, heat) in [("cold", 0.0), ("warm", 0.5), ("hot", 1.0)] {
+            biome_table.insert(
+                format!("vinox:{name}"),
+                BiomeDescriptor {
+                    heat,
+                    ..Default::default()
+                },
+            );
+        }
+        let pos = IVec3::new(-7, 2, 30);
+        let biomes = ChunkBiomes::compute(pos, &biome_table);
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                assert!(biomes.get(x, z).is_some());
+            }
+        }
+        let message = ServerMessage::LevelData {
+            chunk_data: vec![1, 2, 3],
+            pos,
+            dimension: DimensionId(1),
+            hash: 9,
+            biomes: biomes.clone(),
+        };
+        let bytes = bincode::serialize(&message).unwrap();
+        // The columns go as bytes, not a length prefixed number each
+        assert!(bytes.len() < COLUMNS + 128);
+        let ServerMessage::LevelData {
+            biomes: received, ..
+        } = bincode::deserialize(&bytes).unwrap()
+        else {
+            panic!("not LevelData");
+        };
+        assert_eq!(received, biomes);
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                assert_eq!(received.get(x, z), biomes.get(x, z));
+            }
+        }
+
+        assert_eq!(ChunkBiomes::default().get(3, 4), None);
+        assert_eq!(
+            ChunkBiomes::compute(pos, &BiomeTable::default()),
+            ChunkBiomes::default()
+        );
+    }
+}
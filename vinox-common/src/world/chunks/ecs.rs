@@ -6,34 +6,70 @@ use rustc_hash::FxHashSet;
 use crate::storage::blocks::descriptor::BlockDescriptor;
 
 use super::{
+    heightmap::SurfaceKind,
     light::{VoxelAddedEvent, VoxelRemovedEvent},
-    positions::{global_voxel_positions, ChunkPos},
-    storage::{BlockData, BlockTable, ChunkData},
+    positions::{global_voxel_positions, ChunkPos, DimensionId},
+    storage::{BlockData, BlockTable, ChunkData, CHUNK_SIZE},
 };
 
 #[derive(Component, Default)]
 pub struct RemoveChunk;
 
+// One chunk map per dimension. The plain accessors work on the active dimension which is
+// always 0 on the server and whatever dimension the player is in on the client
 #[derive(Resource, Default)]
 pub struct CurrentChunks {
-    pub chunks: HashMap<ChunkPos, Entity>,
+    pub dimensions: HashMap<DimensionId, HashMap<ChunkPos, Entity>>,
+    pub active: DimensionId,
 }
 
 impl CurrentChunks {
     pub fn insert_entity(&mut self, pos: ChunkPos, entity: Entity) {
-        self.chunks.insert(pos, entity);
+        self.insert_entity_in(self.active, pos, entity);
     }
 
     pub fn remove_entity(&mut self, pos: ChunkPos) -> Option<Entity> {
-        self.chunks.remove(&pos)
+        self.remove_entity_in(self.active, pos)
     }
 
     pub fn get_entity(&self, pos: ChunkPos) -> Option<Entity> {
-        self.chunks.get(&pos).copied()
+        self.get_entity_in(self.active, pos)
     }
+
+    pub fn insert_entity_in(&mut self, dimension: DimensionId, pos: ChunkPos, entity: Entity) {
+        self.dimensions
+            .entry(dimension)
+            .or_default()
+            .insert(pos, entity);
+    }
+
+    pub fn remove_entity_in(&mut self, dimension: DimensionId, pos: ChunkPos) -> Option<Entity> {
+        self.dimensions.get_mut(&dimension)?.remove(&pos)
+    }
+
+    pub fn get_entity_in(&self, dimension: DimensionId, pos: ChunkPos) -> Option<Entity> {
+        self.dimensions.get(&dimension)?.get(&pos).copied()
+    }
+
+    // Empties a dimension's map handing back every entity that was in it so the caller can despawn them
+    pub fn clear_dimension(&mut self, dimension: DimensionId) -> Vec<Entity> {
+        self.dimensions
+            .remove(&dimension)
+            .map(|chunks| chunks.into_values().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.dimensions.values().map(|chunks| chunks.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn all_neighbors_exist(&self, pos: ChunkPos) -> bool {
         for chunk in pos.neighbors().iter() {
-            if !self.chunks.contains_key(chunk) {
+            if self.get_entity(*chunk).is_none() {
                 return false;
             }
         }
@@ -42,18 +78,18 @@ impl CurrentChunks {
     pub fn get_all_neighbors(&self, pos: ChunkPos) -> Vec<Entity> {
         pos.neighbors()
             .iter()
-            .filter_map(|this_pos| self.chunks.get(this_pos).copied())
+            .filter_map(|this_pos| self.get_entity(*this_pos))
             .collect()
     }
     pub fn get_unique_loaded_chunks_and_neighbors(&self, pos_list: &[ChunkPos]) -> Vec<Entity> {
         let mut set: HashSet<Entity> = pos_list
             .iter()
-            .filter_map(|pos| self.chunks.get(pos).copied())
+            .filter_map(|pos| self.get_entity(*pos))
             .collect();
         pos_list
             .iter()
             .flat_map(|pos| pos.neighbors())
-            .filter_map(|pos| self.chunks.get(&pos).copied())
+            .filter_map(|pos| self.get_entity(pos))
             .for_each(|entity| {
                 set.insert(entity);
             });
@@ -62,12 +98,33 @@ impl CurrentChunks {
     }
 }
 
-#[derive(Default, Resource)]
+#[derive(Default, Resource, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ViewRadius {
     pub horizontal: i32,
     pub vertical: i32,
 }
 
+impl ViewRadius {
+    // Every chunk position in the box around center
+    pub fn chunks_around(&self, center: ChunkPos) -> Vec<ChunkPos> {
+        let mut chunks = Vec::new();
+        for z in -self.horizontal..=self.horizontal {
+            for x in -self.horizontal..=self.horizontal {
+                for y in -self.vertical..=self.vertical {
+                    chunks.push(ChunkPos(*center + IVec3::new(x, y, z)));
+                }
+            }
+        }
+        chunks
+    }
+
+    // Same box as chunks_around
+    pub fn contains(&self, center: ChunkPos, pos: ChunkPos) -> bool {
+        let offset = (*pos - *center).abs();
+        offset.x <= self.horizontal && offset.z <= self.horizontal && offset.y <= self.vertical
+    }
+}
+
 #[derive(Default, Resource)]
 pub struct SimulationRadius {
     pub horizontal: i32,
@@ -86,9 +143,41 @@ pub struct ChunkManager<'w, 's> {
     pub light_add_event: EventWriter<'w, VoxelAddedEvent>,
 }
 
-#[derive(Component, Clone)]
+// What a client has been sent and still holds. Only ever what's in its radius, so it stays the
+// size of the radius however far the player goes
+#[derive(Component, Clone, Default)]
 pub struct SentChunks {
     pub chunks: FxHashSet<ChunkPos>,
+    // Unloaded on the server while the client still had them, told to drop them with the rest
+    forgotten: Vec<ChunkPos>,
+}
+
+impl SentChunks {
+    // The server's copy is going away and may not come back the same, it's sent again in full
+    pub fn forget(&mut self, pos: ChunkPos) {
+        if self.chunks.remove(&pos) {
+            self.forgotten.push(pos);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.forgotten.clear();
+    }
+
+    // Forgets everything outside radius of center and returns it along with anything forgotten
+    // since, the client is told to drop those
+    pub fn leave(&mut self, center: ChunkPos, radius: &ViewRadius) -> Vec<ChunkPos> {
+        let mut left = std::mem::take(&mut self.forgotten);
+        self.chunks.retain(|pos| {
+            let kept = radius.contains(center, *pos);
+            if !kept {
+                left.push(*pos);
+            }
+            kept
+        });
+        left
+    }
 }
 
 #[derive(Component, Default)]
@@ -205,36 +294,82 @@ impl<'w, 's> ChunkManager<'w, 's> {
         }
         None
     }
-    pub fn get_chunk_positions(&mut self, chunk_pos: ChunkPos) -> Vec<ChunkPos> {
-        let mut chunks = Vec::new();
-        for z in -self.view_radius.horizontal..=self.view_radius.horizontal {
-            for x in -self.view_radius.horizontal..=self.view_radius.horizontal {
-                for y in -self.view_radius.vertical..=self.view_radius.vertical {
-                    let pos = *chunk_pos + IVec3::new(x, y, z);
-                    chunks.push(ChunkPos(pos));
+    // Topmost voxel of that kind at or below voxel_pos, walking down the chunks stacked under
+    // it. Gives up at the first chunk that isn't loaded
+    pub fn surface_below(&self, voxel_pos: IVec3, kind: SurfaceKind) -> Option<i32> {
+        let (mut chunk_pos, local_pos) = global_voxel_positions(voxel_pos);
+        let mut ceiling = local_pos.y;
+        loop {
+            let entity = self.current_chunks.get_entity(ChunkPos(chunk_pos))?;
+            let chunk = self.chunk_query.get(entity).ok()?;
+            let top = match chunk.column_top(local_pos.x, local_pos.z, kind) {
+                Some(top) if top <= ceiling => Some(top),
+                // Starting partway down the column, only the part under voxel_pos counts
+                Some(_) => (0..=ceiling).rev().find(|y| {
+                    kind.matches(&chunk.get(local_pos.x, *y, local_pos.z), &self.block_table)
+                }),
+                None => None,
+            };
+            if let Some(top) = top {
+                return Some(chunk_pos.y * CHUNK_SIZE as i32 + top as i32);
+            }
+            chunk_pos.y -= 1;
+            ceiling = CHUNK_SIZE as u32 - 1;
+        }
+    }
+
+    // Whether anything at all is above voxel_pos, as far up as chunks are loaded
+    pub fn is_covered(&self, voxel_pos: IVec3) -> bool {
+        let (mut chunk_pos, local_pos) = global_voxel_positions(voxel_pos);
+        let mut floor = Some(local_pos.y);
+        while let Some(chunk) = self
+            .current_chunks
+            .get_entity(ChunkPos(chunk_pos))
+            .and_then(|entity| self.chunk_query.get(entity).ok())
+        {
+            if let Some(top) = chunk.column_top(local_pos.x, local_pos.z, SurfaceKind::Any) {
+                if floor.is_none_or(|floor| top > floor) {
+                    return true;
                 }
             }
+            chunk_pos.y += 1;
+            floor = None;
         }
+        false
+    }
+
+    pub fn get_chunk_positions(&mut self, chunk_pos: ChunkPos) -> Vec<ChunkPos> {
         // chunks
         //     .sort_unstable_by_key(|key| (key.x - chunk_pos.x).abs() + (key.z - chunk_pos.z).abs());
-        chunks
+        self.view_radius.chunks_around(chunk_pos)
     }
     pub fn get_chunks_around_chunk(
         &mut self,
         pos: ChunkPos,
         sent_chunks: Option<&SentChunks>,
+    ) -> Vec<(&ChunkData, ChunkPos)> {
+        let (active, radius) = (self.current_chunks.active, *self.view_radius);
+        self.get_chunks_around_chunk_in(active, pos, sent_chunks, &radius)
+    }
+    // Within radius instead of the shared view radius, for players that picked their own
+    pub fn get_chunks_around_chunk_in(
+        &mut self,
+        dimension: DimensionId,
+        pos: ChunkPos,
+        sent_chunks: Option<&SentChunks>,
+        radius: &ViewRadius,
     ) -> Vec<(&ChunkData, ChunkPos)> {
         let mut res = Vec::new();
-        for chunk_pos in self.get_chunk_positions(pos).iter() {
+        for chunk_pos in radius.chunks_around(pos).iter() {
             if let Some(sent_chunks) = sent_chunks {
                 if !sent_chunks.chunks.contains(chunk_pos) {
-                    if let Some(entity) = self.current_chunks.get_entity(*chunk_pos) {
+                    if let Some(entity) = self.current_chunks.get_entity_in(dimension, *chunk_pos) {
                         if let Ok(chunk) = self.chunk_query.get(entity) {
                             res.push((chunk, *chunk_pos));
                         }
                     }
                 }
-            } else if let Some(entity) = self.current_chunks.get_entity(*chunk_pos) {
+            } else if let Some(entity) = self.current_chunks.get_entity_in(dimension, *chunk_pos) {
                 if let Ok(chunk) = self.chunk_query.get(entity) {
                     res.push((chunk, *chunk_pos));
                 }
@@ -280,3 +415,45 @@ pub fn update_priority_chunk_lights(
         commands.entity(entity).insert(PriorityMesh);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_a_chunk_leaves_only_the_far_ring() {
+        let radius = ViewRadius {
+            horizontal: 2,
+            vertical: 1,
+        };
+        let start = ChunkPos::new(0, 0, 0);
+        let mut sent = SentChunks {
+            chunks: radius.chunks_around(start).into_iter().collect(),
+            ..default()
+        };
+        assert!(sent.leave(start, &radius).is_empty());
+
+        let moved = ChunkPos::new(1, 0, 0);
+        let left = sent.leave(moved, &radius);
+        // The x = -2 face, 5 wide and 3 tall
+        assert_eq!(left.len(), 15);
+        assert!(left.iter().all(|pos| pos.x == -2));
+        let new: Vec<_> = radius
+            .chunks_around(moved)
+            .into_iter()
+            .filter(|pos| !sent.chunks.contains(pos))
+            .collect();
+        assert_eq!(new.len(), 15);
+        assert!(new.iter().all(|pos| pos.x == 3));
+
+        // Unloaded under the client, it hears about it even though it's still in range
+        sent.forget(moved);
+        sent.forget(ChunkPos::new(50, 0, 0));
+        assert_eq!(sent.leave(moved, &radius), vec![moved]);
+        assert!(sent.leave(moved, &radius).is_empty());
+
+        // Teleporting far away leaves everything
+        assert_eq!(sent.leave(ChunkPos::new(100, 0, 0), &radius).len(), 59);
+        assert!(sent.chunks.is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, Bytes};
+
+use crate::physics::movement::block_flags;
+
+use super::storage::{name_to_identifier, BlockData, BlockTable, VoxelVisibility, CHUNK_SIZE};
+
+const COLUMNS: usize = CHUNK_SIZE * CHUNK_SIZE;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SurfaceKind {
+    // Anything that isn't air, water included
+    Any,
+    // Only what a body can stand on, so the ground under the water
+    MotionBlocking,
+}
+
+impl SurfaceKind {
+    pub const ALL: [SurfaceKind; 2] = [SurfaceKind::Any, SurfaceKind::MotionBlocking];
+
+    pub fn matches(&self, block: &BlockData, block_table: &BlockTable) -> bool {
+        match self {
+            SurfaceKind::Any => block_table
+                .get(&name_to_identifier(
+                    block.namespace.clone(),
+                    block.name.clone(),
+                ))
+                .is_some_and(|descriptor| {
+                    descriptor.visibility.unwrap_or_default() != VoxelVisibility::Empty
+                }),
+            SurfaceKind::MotionBlocking => block_flags(block, block_table).solid,
+        }
+    }
+
+    fn offset(&self) -> usize {
+        match self {
+            SurfaceKind::Any => 0,
+            SurfaceKind::MotionBlocking => COLUMNS,
+        }
+    }
+}
+
+// Topmost voxel of each kind in every column of a chunk. Stored as y + 1 so 0 can be a
+// column with nothing in it
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heightmap {
+    #[serde_as(as = "Bytes")]
+    tops: [u8; COLUMNS * 2],
+}
+
+impl Default for Heightmap {
+    fn default() -> Self {
+        Self {
+            tops: [0; COLUMNS * 2],
+        }
+    }
+}
+
+impl Heightmap {
+    #[inline]
+    fn index(x: u32, z: u32, kind: SurfaceKind) -> usize {
+        kind.offset() + z as usize * CHUNK_SIZE + x as usize
+    }
+
+    pub fn top(&self, x: u32, z: u32, kind: SurfaceKind) -> Option<u32> {
+        match self.tops[Self::index(x, z, kind)] {
+            0 => None,
+            top => Some(top as u32 - 1),
+        }
+    }
+
+    fn set_top(&mut self, x: u32, z: u32, kind: SurfaceKind, top: Option<u32>) {
+        self.tops[Self::index(x, z, kind)] = top.map_or(0, |top| top as u8 + 1);
+    }
+
+    // Every column from scratch, sample gives the block at a local position
+    pub fn measure(block_table: &BlockTable, sample: impl Fn(u32, u32, u32) -> BlockData) -> Self {
+        let mut heightmap = Self::default();
+        for z in 0..CHUNK_SIZE as u32 {
+            for x in 0..CHUNK_SIZE as u32 {
+                for kind in SurfaceKind::ALL {
+                    let top = (0..CHUNK_SIZE as u32)
+                        .rev()
+                        .find(|y| kind.matches(&sample(x, *y, z), block_table));
+                    heightmap.set_top(x, z, kind, top);
+                }
+            }
+        }
+        heightmap
+    }
+
+    // Call after the voxel at x y z became block. Raising a column is free, only clearing
+    // what was its top walks down and then only that one column
+    pub fn update(
+        &mut self,
+        x: u32,
+        y: u32,
+        z: u32,
+        block: &BlockData,
+        block_table: &BlockTable,
+        sample: impl Fn(u32, u32, u32) -> BlockData,
+    ) {
+        for kind in SurfaceKind::ALL {
+            let top = self.top(x, z, kind);
+            if kind.matches(block, block_table) {
+                if top.is_none_or(|top| y > top) {
+                    self.set_top(x, z, kind, Some(y));
+                }
+            } else if top == Some(y) {
+                let below = (0..y)
+                    .rev()
+                    .find(|below| kind.matches(&sample(x, *below, z), block_table));
+                self.set_top(x, z, kind, below);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::blocks::descriptor::BlockDescriptor,
+        testing::{self, block, descriptor},
+        world::chunks::storage::ChunkData,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const BLOCKS: [&str; 3] = ["air", "stone", "water"];
+
+    fn block_table() -> BlockTable {
+        testing::block_table([
+            descriptor("air", VoxelVisibility::Empty),
+            descriptor("stone", VoxelVisibility::Opaque),
+            BlockDescriptor {
+                fluid: Some(true),
+                ..descriptor("water", VoxelVisibility::Transparent)
+            },
+        ])
+    }
+
+    fn brute_force(
+        chunk: &ChunkData,
+        x: u32,
+        z: u32,
+        kind: SurfaceKind,
+        table: &BlockTable,
+    ) -> Option<u32> {
+        (0..CHUNK_SIZE as u32)
+            .rev()
+            .find(|y| kind.matches(&chunk.get(x, *y, z), table))
+    }
+
+    fn assert_column(chunk: &ChunkData, x: u32, z: u32, table: &BlockTable) {
+        for kind in SurfaceKind::ALL {
+            assert_eq!(
+                chunk.column_top(x, z, kind),
+                brute_force(chunk, x, z, kind, table),
+                "column {x} {z} {kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn edges_of_the_column() {
+        let table = block_table();
+        let mut chunk = ChunkData::default();
+        assert_column(&chunk, 0, 0, &table);
+        assert_eq!(chunk.column_top(0, 0, SurfaceKind::Any), None);
+
+        let top = CHUNK_SIZE as u32 - 1;
+        chunk.set(3, top, 5, block("water"), &table);
+        chunk.set(3, 0, 5, block("stone"), &table);
+        assert_eq!(chunk.column_top(3, 5, SurfaceKind::Any), Some(top));
+        assert_eq!(chunk.column_top(3, 5, SurfaceKind::MotionBlocking), Some(0));
+        // Clearing the very top falls all the way back to the floor
+        chunk.set(3, top, 5, block("air"), &table);
+        assert_eq!(chunk.column_top(3, 5, SurfaceKind::Any), Some(0));
+        chunk.set(3, 0, 5, block("air"), &table);
+        assert_column(&chunk, 3, 5, &table);
+        assert_eq!(chunk.column_top(3, 5, SurfaceKind::Any), None);
+        // Neighbouring columns never noticed
+        assert_eq!(chunk.column_top(4, 5, SurfaceKind::Any), None);
+    }
+
+    #[test]
+    fn random_edits_match_a_full_scan() {
+        let table = block_table();
+        for seed in 0..32 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut chunk = ChunkData::default();
+            for _ in 0..500 {
+                // Few columns so edits keep landing on each other's tops
+                let (x, z) = (rng.gen_range(0..3), rng.gen_range(0..3));
+                let y = rng.gen_range(0..CHUNK_SIZE as u32);
+                let name = BLOCKS[rng.gen_range(0..BLOCKS.len())];
+                chunk.set(x, y, z, block(name), &table);
+                assert_column(&chunk, x, z, &table);
+            }
+            let measured = Heightmap::measure(&table, |x, y, z| chunk.get(x, y, z));
+            assert_eq!(chunk.heightmap(), Some(&measured), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn survives_raw_round_trip() {
+        let table = block_table();
+        let mut chunk = ChunkData::default();
+        chunk.set(1, 7, 2, block("stone"), &table);
+        let loaded = ChunkData::from_raw(
+            bincode::deserialize(&bincode::serialize(&chunk.to_raw()).unwrap()).unwrap(),
+        );
+        assert_eq!(
+            loaded.column_top(1, 2, SurfaceKind::MotionBlocking),
+            Some(7)
+        );
+
+        let mut remeasured = loaded.clone();
+        remeasured.remeasure_heights(&table);
+        assert_eq!(remeasured.heightmap(), loaded.heightmap());
+    }
+}
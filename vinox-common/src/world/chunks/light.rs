@@ -7,6 +7,7 @@ use serde_with::{serde_as, Bytes};
 
 use super::{
     ecs::{CurrentChunks, PriorityMesh},
+    occlusion::{opposite_face, LightOcclusion, DOWN, EAST, NORTH, SOUTH, UP, WEST},
     positions::{global_voxel_positions, ChunkPos},
     storage::{BlockData, BlockTable, ChunkData},
 };
@@ -112,6 +113,7 @@ pub fn propagate_lighting(
     mut voxel_add_event: EventReader<VoxelAddedEvent>,
     mut voxel_rem_event: EventReader<VoxelRemovedEvent>,
     block_table: Res<BlockTable>,
+    occlusion: Res<LightOcclusion>,
 ) {
     let mut added_queue = VecDeque::new();
     let mut rem_queue = VecDeque::new();
@@ -172,6 +174,7 @@ pub fn propagate_lighting(
         &mut chunks,
         &loaded_chunks,
         block_table,
+        &occlusion,
     );
 
     let changed: Vec<ChunkPos> = changed.into_iter().collect();
@@ -186,14 +189,16 @@ fn handle_added(
     chunks: &mut Query<(&ChunkPos, &mut ChunkData)>,
     loaded_chunks: &CurrentChunks,
     block_table: Res<BlockTable>,
+    occlusion: &LightOcclusion,
 ) {
     while !added_queue.is_empty() {
         let node = added_queue.pop_front().unwrap();
 
         let (x, y, z) = ChunkData::delinearize(node.idx);
-        let (pos, source_level) = {
+        let (pos, source_level, exits) = {
             let Ok((pos, chunk_data)) = chunks.get(node.chunk) else { continue; };
-            (*pos, chunk_data.get_torchlight(x, y, z))
+            let exits = occlusion.exits(&chunk_data.get(x, y, z), &block_table);
+            (*pos, chunk_data.get_torchlight(x, y, z), exits)
         };
         let new_level = source_level.saturating_sub(1);
 
@@ -214,6 +219,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                WEST,
             );
 
             check_neighbor_simple_add(
@@ -226,6 +234,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                EAST,
             );
         } else if x == 0 {
             check_neighbor_complex_add(
@@ -239,6 +250,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                WEST,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -252,6 +266,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                EAST,
             );
         } else if x == MAX {
             check_neighbor_complex_add(
@@ -265,6 +282,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                EAST,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -278,6 +298,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                WEST,
             );
         }
 
@@ -294,6 +317,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                DOWN,
             );
 
             check_neighbor_simple_add(
@@ -306,6 +332,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                UP,
             );
         } else if y == 0 {
             check_neighbor_complex_add(
@@ -319,6 +348,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                DOWN,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -332,6 +364,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                UP,
             );
         } else if y == MAX {
             check_neighbor_complex_add(
@@ -345,6 +380,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                UP,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -358,6 +396,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                DOWN,
             );
         }
 
@@ -374,6 +415,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                SOUTH,
             );
 
             check_neighbor_simple_add(
@@ -386,6 +430,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                NORTH,
             );
         } else if z == 0 {
             check_neighbor_complex_add(
@@ -399,6 +446,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                SOUTH,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -412,6 +462,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                NORTH,
             );
         } else if z == MAX {
             check_neighbor_complex_add(
@@ -425,6 +478,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                NORTH,
             );
 
             let Ok((_pos, mut chunk_data)) = chunks.get_mut(node.chunk) else { continue; };
@@ -438,6 +494,9 @@ fn handle_added(
                 source_level,
                 new_level,
                 &block_table,
+                occlusion,
+                exits,
+                SOUTH,
             );
         }
     }
@@ -454,8 +513,14 @@ fn check_neighbor_simple_add(
     source_level: u8,
     new_level: u8,
     block_table: &BlockTable,
+    occlusion: &LightOcclusion,
+    exits: u8,
+    face: usize,
 ) {
-    if chunk_data.get(x, y, z).is_true_empty(block_table)
+    // Has to get out of the source through this face and into the neighbour through the opposite one
+    let entries = !occlusion.blocks(&chunk_data.get(x, y, z), block_table);
+    if exits & (1 << face) != 0
+        && entries & (1 << opposite_face(face)) != 0
         && chunk_data.get_torchlight(x, y, z) + 2 < source_level
     {
         chunk_data.set_torchlight(x, y, z, new_level);
@@ -478,6 +543,9 @@ fn check_neighbor_complex_add(
     source_level: u8,
     new_level: u8,
     block_table: &BlockTable,
+    occlusion: &LightOcclusion,
+    exits: u8,
+    face: usize,
 ) {
     let (chunk_entity, mut chunk_data) = {
         let Some(chunk_entity) = loaded_chunks.get_entity(pos) else { return; };
@@ -495,6 +563,9 @@ fn check_neighbor_complex_add(
         source_level,
         new_level,
         block_table,
+        occlusion,
+        exits,
+        face,
     );
 }
 
@@ -806,8 +877,92 @@ pub struct LightPlugin;
 impl Plugin for LightPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_event::<VoxelAddedEvent>()
-            .add_event::<VoxelRemovedEvent>();
+            .add_event::<VoxelRemovedEvent>()
+            .init_resource::<LightOcclusion>();
         app.add_system(propagate_lighting);
         // app.add_system(update_chunk_lights);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{
+        blocks::descriptor::BlockGeometry,
+        geometry::descriptor::{BlockGeo, FaceDescript, GeometryDescriptor},
+    };
+    use crate::testing::{self, descriptor};
+    use crate::world::chunks::storage::VoxelVisibility;
+
+    // Whatever block sits at 4 8 8 with a torch somewhere near it
+    fn light_around(block: &str, torch_pos: UVec3) -> ChunkData {
+        let mut torch = descriptor("torch", VoxelVisibility::Transparent);
+        torch.light = Some((255, 255, 255, 15));
+        let mut slab = descriptor("slab", VoxelVisibility::Opaque);
+        slab.geometry = Some(BlockGeometry::Slab);
+        let block_table = testing::block_table([
+            descriptor("air", VoxelVisibility::Empty),
+            descriptor("stone", VoxelVisibility::Opaque),
+            slab,
+            torch,
+        ]);
+        let geometry = [GeometryDescriptor {
+            namespace: "vinox".to_string(),
+            name: "slab".to_string(),
+            blocks: [false; 6],
+            element: BlockGeo {
+                pivot: (0, 0, 0),
+                rotation: (0, 0, 0),
+                cubes: vec![FaceDescript {
+                    origin: (0, 0, 0),
+                    end: (16, 8, 16),
+                    ..Default::default()
+                }],
+            },
+        }];
+
+        let mut chunk_data = ChunkData::default();
+        chunk_data.set(
+            4,
+            8,
+            8,
+            BlockData::new("vinox".to_string(), block.to_string()),
+            &block_table,
+        );
+        let torch = BlockData::new("vinox".to_string(), "torch".to_string());
+        chunk_data.set(torch_pos.x, torch_pos.y, torch_pos.z, torch.clone(), &block_table);
+
+        let mut app = App::new();
+        app.insert_resource(LightOcclusion::new(&block_table, &geometry))
+            .insert_resource(block_table)
+            .init_resource::<CurrentChunks>()
+            .add_event::<VoxelAddedEvent>()
+            .add_event::<VoxelRemovedEvent>()
+            .add_system(propagate_lighting);
+        let chunk = app.world.spawn((ChunkPos(IVec3::ZERO), chunk_data)).id();
+        app.world
+            .resource_mut::<CurrentChunks>()
+            .insert_entity(ChunkPos(IVec3::ZERO), chunk);
+        app.world
+            .send_event(VoxelAddedEvent::new(torch_pos.as_ivec3(), torch));
+        app.update();
+        app.world.get::<ChunkData>(chunk).unwrap().clone()
+    }
+
+    #[test]
+    fn light_passes_over_slabs() {
+        let slab = light_around("slab", UVec3::new(2, 8, 8));
+        // Straight through the open top half of the slab
+        assert_eq!(slab.get_torchlight(4, 8, 8), 13);
+        assert_eq!(slab.get_torchlight(5, 8, 8), 12);
+
+        // From above it gets in but not out the bottom, under it is lit the long way round
+        let slab = light_around("slab", UVec3::new(4, 9, 8));
+        assert_eq!(slab.get_torchlight(4, 8, 8), 14);
+        assert_eq!(slab.get_torchlight(4, 7, 8), 11);
+
+        let stone = light_around("stone", UVec3::new(2, 8, 8));
+        assert_eq!(stone.get_torchlight(4, 8, 8), 0);
+        assert_eq!(stone.get_torchlight(5, 8, 8), 10);
+    }
+}
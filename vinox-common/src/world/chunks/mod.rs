@@ -1,4 +1,7 @@
+pub mod biome_map;
 pub mod ecs;
+pub mod heightmap;
 pub mod light;
+pub mod occlusion;
 pub mod positions;
 pub mod storage;
@@ -0,0 +1,293 @@
+use bevy::prelude::*;
+use rustc_hash::FxHashMap;
+
+use crate::storage::geometry::descriptor::{BlockGeo, GeometryDescriptor};
+
+use super::storage::{name_to_identifier, BlockData, BlockTable, Direction, VoxelVisibility};
+
+// Face bits follow the geometry files: West, East, Down, Up, South, North
+pub const ALL_FACES: u8 = 0b11_1111;
+pub const WEST: usize = 0;
+pub const EAST: usize = 1;
+pub const DOWN: usize = 2;
+pub const UP: usize = 3;
+pub const SOUTH: usize = 4;
+pub const NORTH: usize = 5;
+// No direction or one of the four, each with and without top
+pub const ORIENTATIONS: usize = 10;
+
+const NORMALS: [[i32; 3]; 6] = [
+    [-1, 0, 0],
+    [1, 0, 0],
+    [0, -1, 0],
+    [0, 1, 0],
+    [0, 0, -1],
+    [0, 0, 1],
+];
+
+#[inline]
+pub fn opposite_face(face: usize) -> usize {
+    face ^ 1
+}
+
+pub fn orientation_index(direction: Option<Direction>, top: Option<bool>) -> usize {
+    let direction = match direction {
+        None => 0,
+        Some(Direction::North) => 1,
+        Some(Direction::South) => 2,
+        Some(Direction::West) => 3,
+        Some(Direction::East) => 4,
+    };
+    direction * 2 + (top == Some(true)) as usize
+}
+
+fn face_from_normal(normal: [i32; 3]) -> usize {
+    NORMALS.iter().position(|other| *other == normal).unwrap()
+}
+
+// Same quarter turns the mesher gives directional blocks, direction first and then top
+fn rotate_normal(normal: [i32; 3], direction: Option<Direction>, top: Option<bool>) -> [i32; 3] {
+    let [x, y, z] = normal;
+    let [x, y, z] = match direction {
+        None => [x, y, z],
+        Some(Direction::North) => [x, z, -y],
+        Some(Direction::South) => [x, -z, y],
+        Some(Direction::West) => [-y, x, z],
+        Some(Direction::East) => [y, -x, z],
+    };
+    if top == Some(true) {
+        [x, -y, -z]
+    } else {
+        [x, y, z]
+    }
+}
+
+pub fn rotate_mask(mask: u8, direction: Option<Direction>, top: Option<bool>) -> u8 {
+    (0..6)
+        .filter(|face| mask & (1 << face) != 0)
+        .fold(0, |rotated, face| {
+            rotated | 1 << face_from_normal(rotate_normal(NORMALS[face], direction, top))
+        })
+}
+
+// A face only blocks light when the cuboids cover every texel of it, so a slab's sides
+// which are half open let light through. Anything rotated off the grid (crosses) never blocks
+pub fn face_mask(geo: &BlockGeo) -> u8 {
+    if geo.rotation != (0, 0, 0) {
+        return 0;
+    }
+    let mut mask = 0;
+    for face in 0..6 {
+        let axis = face / 2;
+        let positive = face % 2 == 1;
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut covered = [0u16; 16];
+        for cube in geo.cubes.iter().filter(|cube| cube.rotation == (0, 0, 0)) {
+            let origin = [cube.origin.0, cube.origin.1, cube.origin.2].map(i32::from);
+            let end = [cube.end.0, cube.end.1, cube.end.2].map(i32::from);
+            let min = |i: usize| origin[i].min(end[i]).clamp(0, 16);
+            let max = |i: usize| origin[i].max(end[i]).clamp(0, 16);
+            // Flat planes have no volume to stop anything
+            if min(axis) == max(axis) {
+                continue;
+            }
+            if (positive && max(axis) < 16) || (!positive && min(axis) > 0) {
+                continue;
+            }
+            for row in &mut covered[min(u) as usize..max(u) as usize] {
+                for cell in min(v)..max(v) {
+                    *row |= 1 << cell;
+                }
+            }
+        }
+        if covered.iter().all(|row| *row == u16::MAX) {
+            mask |= 1 << face;
+        }
+    }
+    mask
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceLight {
+    // Faces light can't pass through, per orientation
+    pub blocks: [u8; ORIENTATIONS],
+    // Faces an emitter shines out of, per orientation
+    pub emits: [u8; ORIENTATIONS],
+}
+
+impl FaceLight {
+    pub fn new(blocks: u8, emits: u8) -> Self {
+        let mut face_light = FaceLight {
+            blocks: [0; ORIENTATIONS],
+            emits: [0; ORIENTATIONS],
+        };
+        for direction in [
+            None,
+            Some(Direction::North),
+            Some(Direction::South),
+            Some(Direction::West),
+            Some(Direction::East),
+        ] {
+            for top in [None, Some(true)] {
+                let index = orientation_index(direction, top);
+                face_light.blocks[index] = rotate_mask(blocks, direction, top);
+                face_light.emits[index] = rotate_mask(emits, direction, top);
+            }
+        }
+        face_light
+    }
+}
+
+// Built once the block and geometry tables are loaded, keyed by block identifier
+#[derive(Resource, Clone, Default, Deref, DerefMut)]
+pub struct LightOcclusion(pub FxHashMap<String, FaceLight>);
+
+impl LightOcclusion {
+    pub fn new(block_table: &BlockTable, geometry: &[GeometryDescriptor]) -> Self {
+        let geometry: FxHashMap<String, &BlockGeo> = geometry
+            .iter()
+            .map(|geo| {
+                (
+                    name_to_identifier(geo.namespace.clone(), geo.name.clone()),
+                    &geo.element,
+                )
+            })
+            .collect();
+        let mut occlusion = LightOcclusion::default();
+        for (identifier, descriptor) in block_table.iter() {
+            // Glass and leaves have always let light through whatever their shape
+            let blocks = if descriptor.visibility.unwrap_or_default() == VoxelVisibility::Opaque {
+                let geo_name = descriptor
+                    .geometry
+                    .clone()
+                    .unwrap_or_default()
+                    .get_geo_namespace();
+                match geometry.get(&geo_name) {
+                    Some(geo) => face_mask(geo),
+                    None if geo_name == "vinox:block" => face_mask(&BlockGeo::default()),
+                    None => 0,
+                }
+            } else {
+                0
+            };
+            let emits = match descriptor.light_faces {
+                Some(faces) => (0..6)
+                    .filter(|face| faces[*face])
+                    .fold(0, |mask, face| mask | 1 << face),
+                None => ALL_FACES,
+            };
+            occlusion.insert(identifier.clone(), FaceLight::new(blocks, emits));
+        }
+        occlusion
+    }
+
+    fn face_light(&self, block: &BlockData) -> Option<(&FaceLight, usize)> {
+        let face_light = self.get(&name_to_identifier(
+            block.namespace.clone(),
+            block.name.clone(),
+        ))?;
+        Some((face_light, orientation_index(block.direction, block.top)))
+    }
+
+    // Faces light can't come in through
+    pub fn blocks(&self, block: &BlockData, block_table: &BlockTable) -> u8 {
+        match self.face_light(block) {
+            Some((face_light, orientation)) => face_light.blocks[orientation],
+            None if block.is_true_empty(block_table) => 0,
+            None => ALL_FACES,
+        }
+    }
+
+    // Faces light can leave through, an emitter only shines out of its lit faces
+    pub fn exits(&self, block: &BlockData, block_table: &BlockTable) -> u8 {
+        let emitting = block_table
+            .get(&name_to_identifier(
+                block.namespace.clone(),
+                block.name.clone(),
+            ))
+            .and_then(|descriptor| descriptor.light)
+            .is_some_and(|light| light.3 > 0);
+        match self.face_light(block) {
+            Some((face_light, orientation)) if emitting => face_light.emits[orientation],
+            _ if emitting => ALL_FACES,
+            _ => !self.blocks(block, block_table) & ALL_FACES,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::geometry::descriptor::FaceDescript;
+
+    fn cuboid(origin: (i8, i8, i8), end: (i8, i8, i8)) -> FaceDescript {
+        FaceDescript {
+            origin,
+            end,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn masks_from_geometry() {
+        assert_eq!(face_mask(&BlockGeo::default()), ALL_FACES);
+
+        let slab = BlockGeo {
+            pivot: (0, 0, 0),
+            rotation: (0, 0, 0),
+            cubes: vec![cuboid((0, 0, 0), (16, 8, 16))],
+        };
+        assert_eq!(face_mask(&slab), 1 << 2);
+
+        let cross = BlockGeo {
+            pivot: (8, 0, 8),
+            rotation: (0, 45, 0),
+            cubes: vec![
+                cuboid((8, 0, 0), (8, 16, 16)),
+                cuboid((0, 0, 8), (16, 16, 8)),
+            ],
+        };
+        assert_eq!(face_mask(&cross), 0);
+        // Even without the block turned the planes are too thin to cover anything
+        assert_eq!(
+            face_mask(&BlockGeo {
+                rotation: (0, 0, 0),
+                ..cross
+            }),
+            0
+        );
+
+        // Two halves covering the top between them still block it
+        let stair = BlockGeo {
+            pivot: (0, 0, 0),
+            rotation: (0, 0, 0),
+            cubes: vec![
+                cuboid((0, 0, 0), (16, 8, 16)),
+                cuboid((0, 8, 0), (8, 16, 16)),
+            ],
+        };
+        assert_eq!(face_mask(&stair), 1 << 0 | 1 << 2);
+    }
+
+    #[test]
+    fn orientations_rotate_masks() {
+        let down = 1 << 2;
+        assert_eq!(rotate_mask(down, None, Some(true)), 1 << 3);
+        assert_eq!(rotate_mask(down, None, Some(false)), down);
+        for direction in [
+            Direction::North,
+            Direction::South,
+            Direction::West,
+            Direction::East,
+        ] {
+            // A wall slab blocks one horizontal face, never up or down
+            let rotated = rotate_mask(down, Some(direction), None);
+            assert_eq!(rotated.count_ones(), 1);
+            assert_eq!(rotated & (1 << 2 | 1 << 3), 0);
+        }
+        assert_eq!(
+            rotate_mask(ALL_FACES, Some(Direction::West), Some(true)),
+            ALL_FACES
+        );
+    }
+}
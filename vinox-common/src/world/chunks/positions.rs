@@ -1,4 +1,5 @@
-use bevy::prelude::*;
+use bevy::{math::DVec3, prelude::*};
+use serde::{Deserialize, Serialize};
 
 use super::{ecs::ViewRadius, storage::CHUNK_SIZE};
 
@@ -20,64 +21,104 @@ pub fn circle_points(view_radius: &ViewRadius) -> Vec<IVec2> {
     points
 }
 
-pub fn world_to_chunk(pos: Vec3) -> IVec3 {
+// Everything below is integer math once the position is floored, so it stays exact as far
+// out as an i32 voxel reaches. Floats only lose it in the position handed in
+const CHUNK: i32 = CHUNK_SIZE as i32;
+
+fn chunk_of(voxel: IVec3) -> IVec3 {
     IVec3::new(
-        (pos.x / (CHUNK_SIZE as f32)).floor() as i32,
-        (pos.y / (CHUNK_SIZE as f32)).floor() as i32,
-        (pos.z / (CHUNK_SIZE as f32)).floor() as i32,
+        voxel.x.div_euclid(CHUNK),
+        voxel.y.div_euclid(CHUNK),
+        voxel.z.div_euclid(CHUNK),
+    )
+}
+
+fn offset_in_chunk(voxel: IVec3) -> UVec3 {
+    UVec3::new(
+        voxel.x.rem_euclid(CHUNK) as u32,
+        voxel.y.rem_euclid(CHUNK) as u32,
+        voxel.z.rem_euclid(CHUNK) as u32,
     )
 }
 
+pub fn world_to_chunk(pos: Vec3) -> IVec3 {
+    chunk_of(world_to_global_voxel(pos))
+}
+
 pub fn world_to_global_voxel(voxel_pos: Vec3) -> IVec3 {
     voxel_pos.floor().as_ivec3()
 }
 
+// For positions far enough out that an f32 can't hold where in the block they are
+pub fn world_to_global_voxel_f64(voxel_pos: DVec3) -> IVec3 {
+    voxel_pos.floor().as_ivec3()
+}
+
+pub fn world_to_chunk_f64(pos: DVec3) -> IVec3 {
+    chunk_of(world_to_global_voxel_f64(pos))
+}
+
 pub fn voxel_to_global_voxel(voxel_pos: UVec3, chunk_pos: IVec3) -> IVec3 {
-    let world_chunk = chunk_pos * IVec3::splat(CHUNK_SIZE as i32);
-    Vec3::new(
-        (world_chunk.x as f32) + voxel_pos.x as f32,
-        (world_chunk.y as f32) + voxel_pos.y as f32,
-        (world_chunk.z as f32) + voxel_pos.z as f32,
-    )
-    .floor()
-    .as_ivec3()
+    chunk_pos * CHUNK + voxel_pos.as_ivec3()
 }
 
 pub fn world_to_offsets(voxel_pos: Vec3) -> UVec3 {
-    UVec3::new(
-        voxel_pos.floor().x.rem_euclid(CHUNK_SIZE as f32) as u32,
-        voxel_pos.floor().y.rem_euclid(CHUNK_SIZE as f32) as u32,
-        voxel_pos.floor().z.rem_euclid(CHUNK_SIZE as f32) as u32,
-    )
+    offset_in_chunk(world_to_global_voxel(voxel_pos))
 }
 
 pub fn world_to_voxel(voxel_pos: Vec3) -> (IVec3, UVec3) {
-    (world_to_chunk(voxel_pos), world_to_offsets(voxel_pos))
+    global_voxel_positions(world_to_global_voxel(voxel_pos))
 }
 
 pub fn global_voxel_positions(voxel_pos: IVec3) -> (IVec3, UVec3) {
-    (
-        world_to_chunk(voxel_pos.as_vec3()),
-        world_to_offsets(voxel_pos.as_vec3()),
-    )
+    (chunk_of(voxel_pos), offset_in_chunk(voxel_pos))
 }
 
+// Exact up to 2^24 blocks out, past that use voxel_to_global_voxel and stay in integers
 pub fn voxel_to_world(voxel_pos: UVec3, chunk_pos: IVec3) -> Vec3 {
-    let world_chunk = chunk_pos * IVec3::splat(CHUNK_SIZE as i32);
-    Vec3::new(
-        (world_chunk.x as f32) + voxel_pos.x as f32,
-        (world_chunk.y as f32) + voxel_pos.y as f32,
-        (world_chunk.z as f32) + voxel_pos.z as f32,
-    )
+    voxel_to_global_voxel(voxel_pos, chunk_pos).as_vec3()
 }
 
 pub fn relative_voxel_to_world(voxel_pos: IVec3, chunk_pos: IVec3) -> Vec3 {
-    let world_chunk = chunk_pos * IVec3::splat(CHUNK_SIZE as i32);
-    Vec3::new(
-        (world_chunk.x as f32) + voxel_pos.x as f32,
-        (world_chunk.y as f32) + voxel_pos.y as f32,
-        (world_chunk.z as f32) + voxel_pos.z as f32,
-    )
+    (chunk_pos * CHUNK + voxel_pos).as_vec3()
+}
+
+// Whole chunks the client has moved its render origin by so nothing it draws is ever far from
+// zero. World positions are render positions plus this, chunk and voxel positions are always
+// world ones. The server never moves it
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq, Deref, DerefMut)]
+pub struct WorldOffset(pub IVec3);
+
+impl WorldOffset {
+    // The offset in blocks
+    pub fn voxels(&self) -> IVec3 {
+        self.0 * CHUNK
+    }
+
+    pub fn to_world(&self, render_pos: Vec3) -> DVec3 {
+        render_pos.as_dvec3() + self.voxels().as_dvec3()
+    }
+
+    pub fn to_render(&self, world_pos: DVec3) -> Vec3 {
+        (world_pos - self.voxels().as_dvec3()).as_vec3()
+    }
+
+    pub fn voxel_to_world(&self, render_voxel: IVec3) -> IVec3 {
+        render_voxel + self.voxels()
+    }
+
+    pub fn voxel_to_render(&self, voxel: IVec3) -> IVec3 {
+        voxel - self.voxels()
+    }
+
+    pub fn chunk_to_world(&self, render_chunk: IVec3) -> IVec3 {
+        render_chunk + self.0
+    }
+
+    // Where a chunk's corner gets drawn
+    pub fn chunk_to_render(&self, chunk_pos: IVec3) -> Vec3 {
+        ((chunk_pos - self.0) * CHUNK).as_vec3()
+    }
 }
 
 // #[cfg(test)]
@@ -263,6 +304,23 @@ pub fn relative_voxel_to_world(voxel_pos: IVec3, chunk_pos: IVec3) -> Vec3 {
 #[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Deref, DerefMut)]
 pub struct ChunkPos(pub IVec3);
 
+// 0 is the overworld, anything else only exists if the world info configures it
+#[derive(
+    Component,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Default,
+    Deref,
+    DerefMut,
+    Serialize,
+    Deserialize,
+)]
+pub struct DimensionId(pub u16);
+
 impl ChunkPos {
     pub fn new(x: i32, y: i32, z: i32) -> Self {
         ChunkPos(IVec3::new(x, y, z))
@@ -310,3 +368,66 @@ impl ChunkPos {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_round_trip() {
+        for voxel in [
+            IVec3::ZERO,
+            IVec3::new(15, 16, 17),
+            IVec3::new(-1, -16, -17),
+            IVec3::new(2, -15, 5),
+        ] {
+            let (chunk_pos, local) = global_voxel_positions(voxel);
+            assert!(local.max_element() < CHUNK_SIZE as u32);
+            assert_eq!(voxel_to_global_voxel(local, chunk_pos), voxel);
+            assert_eq!(world_to_voxel(voxel.as_vec3() + 0.5), (chunk_pos, local));
+        }
+        assert_eq!(
+            world_to_chunk(Vec3::new(2.0, -15.9, 5.0)),
+            IVec3::new(0, -1, 0)
+        );
+        assert_eq!(world_to_offsets(Vec3::splat(-0.1)), UVec3::splat(15));
+        assert_eq!(
+            voxel_to_world(UVec3::new(0, 1, 15), IVec3::splat(-1)),
+            Vec3::new(-16.0, -15.0, -1.0)
+        );
+    }
+
+    #[test]
+    fn exact_far_from_the_origin() {
+        // Well past 2^24, where going through an f32 used to round to the nearest even block
+        let far = IVec3::new(100_000_001, -3, -100_000_001);
+        let (chunk_pos, local) = global_voxel_positions(far);
+        assert_eq!(chunk_pos, IVec3::new(6_250_000, -1, -6_250_001));
+        assert_eq!(local, UVec3::new(1, 13, 15));
+        assert_eq!(voxel_to_global_voxel(local, chunk_pos), far);
+        let inside = far.as_dvec3() + DVec3::new(0.999, 0.5, 0.001);
+        assert_eq!(world_to_global_voxel_f64(inside), far);
+        assert_eq!(world_to_chunk_f64(inside), chunk_pos);
+    }
+
+    #[test]
+    fn offset_moves_whole_chunks() {
+        let offset = WorldOffset(IVec3::new(625_000, 0, -2));
+        assert_eq!(offset.voxels(), IVec3::new(10_000_000, 0, -32));
+        let world = DVec3::new(10_000_003.25, 70.5, -40.75);
+        let render = offset.to_render(world);
+        assert_eq!(render, Vec3::new(3.25, 70.5, -8.75));
+        assert_eq!(offset.to_world(render), world);
+        let voxel = world_to_global_voxel_f64(world);
+        assert_eq!(offset.voxel_to_world(world_to_global_voxel(render)), voxel);
+        assert_eq!(offset.voxel_to_render(voxel), world_to_global_voxel(render));
+        assert_eq!(
+            offset.chunk_to_world(world_to_chunk(render)),
+            world_to_chunk_f64(world)
+        );
+        assert_eq!(
+            offset.chunk_to_render(IVec3::new(625_001, 4, -2)),
+            Vec3::new(16.0, 64.0, 0.0)
+        );
+    }
+}
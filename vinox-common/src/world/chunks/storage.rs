@@ -1,5 +1,10 @@
 use bitvec::prelude::*;
 use rustc_hash::FxHashMap;
+use std::collections::BTreeSet;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex, OnceLock,
+};
 
 use bevy::prelude::*;
 use itertools::*;
@@ -7,15 +12,24 @@ use ndshape::{ConstShape, ConstShape3usize};
 use serde::{Deserialize, Serialize};
 use strum::EnumString;
 
+use crate::ecs::time::ServerTick;
+use crate::storage::blocks::descriptor::TintKind;
 use crate::storage::{
     biomes::descriptor::BiomeDescriptor, blocks::descriptor::BlockDescriptor,
     crafting::descriptor::RecipeDescriptor, items::descriptor::ItemDescriptor,
+    structures::descriptor::StructureDescriptor,
 };
 
-use super::light::LightStorage;
+use super::{
+    heightmap::{Heightmap, SurfaceKind},
+    light::LightStorage,
+};
 
 pub const HORIZONTAL_DISTANCE: usize = 10;
 pub const VERTICAL_DISTANCE: usize = 10;
+// What a client can ask the server for, horizontally. Vertical stays at VERTICAL_DISTANCE
+pub const MIN_VIEW_DISTANCE: usize = 4;
+pub const MAX_VIEW_DISTANCE: usize = 32;
 pub const CHUNK_SIZE: usize = 16;
 pub const CHUNK_SIZE_ARR: u32 = CHUNK_SIZE as u32 - 1;
 pub const TOTAL_CHUNK_SIZE: usize = (CHUNK_SIZE) * (CHUNK_SIZE) * (CHUNK_SIZE);
@@ -28,12 +42,47 @@ pub struct RecipeTable(pub FxHashMap<String, RecipeDescriptor>);
 #[derive(Resource, Clone, Default, Deref, DerefMut)]
 pub struct BlockTable(pub FxHashMap<String, BlockDescriptor>);
 
+// Stands in for a block the table doesn't have, a stale or misspelled identifier in a save. Never
+// in the table itself, the client gives it a magenta texture of its own
+pub const UNKNOWN_BLOCK: (&str, &str) = ("vinox", "unknown");
+
+pub fn unknown_descriptor() -> &'static BlockDescriptor {
+    static UNKNOWN: OnceLock<BlockDescriptor> = OnceLock::new();
+    UNKNOWN.get_or_init(|| BlockDescriptor {
+        namespace: UNKNOWN_BLOCK.0.to_string(),
+        name: UNKNOWN_BLOCK.1.to_string(),
+        // Solid so it still shows up and can be mined out
+        visibility: Some(VoxelVisibility::Opaque),
+        ..Default::default()
+    })
+}
+
+impl BlockTable {
+    // Anything the table doesn't have gets the unknown block, with a warning the first time each
+    // identifier turns up rather than every frame it's looked at
+    pub fn get_or_unknown(&self, identifier: &str) -> &BlockDescriptor {
+        if let Some(descriptor) = self.get(identifier) {
+            return descriptor;
+        }
+        static WARNED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+        if let Ok(mut warned) = WARNED.lock() {
+            if warned.insert(identifier.to_string()) {
+                warn!("Unknown block {identifier}, showing it as a placeholder");
+            }
+        }
+        unknown_descriptor()
+    }
+}
+
 #[derive(Resource, Clone, Default, Deref, DerefMut)]
 pub struct ItemTable(pub FxHashMap<String, ItemDescriptor>);
 
 #[derive(Resource, Clone, Default, Deref, DerefMut)]
 pub struct BiomeTable(pub FxHashMap<String, BiomeDescriptor>);
 
+#[derive(Resource, Clone, Default, Deref, DerefMut)]
+pub struct StructureTable(pub FxHashMap<String, StructureDescriptor>);
+
 #[derive(EnumString, Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone, Copy, Hash)]
 pub enum VoxelVisibility {
     #[default]
@@ -72,6 +121,18 @@ pub enum GrowthState {
     Spoiled,
 }
 
+impl GrowthState {
+    pub fn next(&self) -> Option<GrowthState> {
+        match self {
+            GrowthState::Planted => Some(GrowthState::Sapling),
+            GrowthState::Sapling => Some(GrowthState::Young),
+            GrowthState::Young => Some(GrowthState::Ripe),
+            GrowthState::Ripe => Some(GrowthState::Spoiled),
+            GrowthState::Spoiled => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Container {
     pub items: Vec<String>, // Hashmap would be better and may do more into implementing hashmyself at some point but this approach works for now
@@ -93,6 +154,9 @@ pub struct RenderedBlockData {
     pub tex_variance: [bool; 6],
     pub blocks: [bool; 6],
     pub light: u8,
+    pub tint: Option<TintKind>,
+    // Atlas index of the decoration's texture and its density in percent
+    pub decoration: Option<(usize, u8)>,
 }
 
 pub fn name_to_identifier(namespace: String, name: String) -> String {
@@ -140,6 +204,8 @@ impl Default for RenderedBlockData {
             match_index: 0,
             // geo: block_geo().unwrap(),
             light: 0,
+            tint: None,
+            decoration: None,
         }
     }
 }
@@ -151,41 +217,57 @@ pub struct BlockData {
     pub direction: Option<Direction>,
     pub container: Option<Container>,
     pub growth_state: Option<GrowthState>,
+    // ServerTick of the last growth stage, never unix time
     pub last_tick: Option<u64>,
     pub arbitary_data: Option<String>,
     pub top: Option<bool>,
 }
 
 impl BlockData {
+    // Same as comparing against name_to_identifier without building the string
+    pub fn has_identifier(&self, identifier: &str) -> bool {
+        identifier
+            .strip_prefix(self.namespace.as_str())
+            .and_then(|rest| rest.strip_prefix(':'))
+            == Some(self.name.as_str())
+    }
+
+    // Moves at most one stage per call however many ticks were missed, returns whether it grew
+    pub fn grow(&mut self, tick: ServerTick, ticks_per_stage: u64) -> bool {
+        let Some(state) = &self.growth_state else {
+            return false;
+        };
+        let Some(last_tick) = self.last_tick else {
+            // Placed before anything stamped it, start counting from now
+            self.last_tick = Some(*tick);
+            return false;
+        };
+        let Some(next) = state.next() else {
+            return false;
+        };
+        if tick.saturating_sub(last_tick) < ticks_per_stage {
+            return false;
+        }
+        self.growth_state = Some(next);
+        self.last_tick = Some(*tick);
+        true
+    }
+
+    fn descriptor<'a>(&self, block_table: &'a BlockTable) -> &'a BlockDescriptor {
+        block_table.get_or_unknown(&name_to_identifier(
+            self.namespace.clone(),
+            self.name.clone(),
+        ))
+    }
+
     pub fn is_empty(&self, block_table: &BlockTable) -> bool {
-        block_table
-            .get(&name_to_identifier(
-                self.namespace.clone(),
-                self.name.clone(),
-            ))
-            .unwrap()
-            .visibility
-            .unwrap_or_default()
-            == VoxelVisibility::Empty
+        self.descriptor(block_table).visibility.unwrap_or_default() == VoxelVisibility::Empty
     }
     pub fn is_opaque(&self, block_table: &BlockTable) -> bool {
-        block_table
-            .get(&name_to_identifier(
-                self.namespace.clone(),
-                self.name.clone(),
-            ))
-            .unwrap()
-            .visibility
-            .unwrap_or_default()
-            == VoxelVisibility::Opaque
+        self.descriptor(block_table).visibility.unwrap_or_default() == VoxelVisibility::Opaque
     }
     pub fn is_true_empty(&self, block_table: &BlockTable) -> bool {
-        let descriptor = block_table
-            .get(&name_to_identifier(
-                self.namespace.clone(),
-                self.name.clone(),
-            ))
-            .unwrap();
+        let descriptor = self.descriptor(block_table);
         !(descriptor.visibility.unwrap_or_default() == VoxelVisibility::Opaque
             && descriptor
                 .geometry
@@ -194,6 +276,12 @@ impl BlockData {
                 .get_geo_namespace()
                 == "vinox:block")
     }
+    // How long it takes to break, None for blocks that go with a single click
+    pub fn break_time(&self, block_table: &BlockTable) -> Option<f32> {
+        self.descriptor(block_table)
+            .break_time
+            .filter(|seconds| *seconds > 0.0)
+    }
 }
 
 impl Default for BlockData {
@@ -399,6 +487,14 @@ impl Storage {
         }
     }
 
+    // The one block a single storage holds, None as soon as there are two kinds
+    pub fn uniform_voxel(&self) -> Option<&BlockData> {
+        match self {
+            Storage::Single(storage) => Some(&storage.voxel),
+            Storage::Multi(_) => None,
+        }
+    }
+
     pub fn get(&self, idx: usize) -> BlockData {
         match self {
             Storage::Single(storage) => storage.voxel.clone(),
@@ -417,6 +513,132 @@ impl Storage {
         }
     }
 
+    // Whether any voxel is this block, in any state. Only looks at the palette, entries nothing
+    // refers to anymore don't count
+    pub fn palette_contains(&self, identifier: &str) -> bool {
+        match self {
+            Storage::Single(storage) => storage.voxel.has_identifier(identifier),
+            Storage::Multi(storage) => storage
+                .palette
+                .iter()
+                .any(|entry| entry.ref_count > 0 && entry.voxel_type.has_identifier(identifier)),
+        }
+    }
+
+    // Every block with at least one voxel, in any state. Two of these diffed say what a change
+    // added to or took out of the palette
+    pub fn palette_identifiers(&self) -> BTreeSet<String> {
+        match self {
+            Storage::Single(storage) => BTreeSet::from([name_to_identifier(
+                storage.voxel.namespace.clone(),
+                storage.voxel.name.clone(),
+            )]),
+            Storage::Multi(storage) => storage
+                .palette
+                .iter()
+                .filter(|entry| entry.ref_count > 0)
+                .map(|entry| {
+                    name_to_identifier(
+                        entry.voxel_type.namespace.clone(),
+                        entry.voxel_type.name.clone(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    // Every index holding this block, reading palette indices instead of cloning each voxel
+    pub fn indices_of(&self, identifier: &str) -> Vec<usize> {
+        match self {
+            Storage::Single(storage) => {
+                if storage.voxel.has_identifier(identifier) {
+                    (0..storage.size).collect()
+                } else {
+                    Vec::new()
+                }
+            }
+            Storage::Multi(storage) => {
+                let matching: Vec<usize> = storage
+                    .palette
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| {
+                        entry.ref_count > 0 && entry.voxel_type.has_identifier(identifier)
+                    })
+                    .map(|(idx, _)| idx)
+                    .collect();
+                if matching.is_empty() {
+                    return Vec::new();
+                }
+                (0..storage.size)
+                    .filter(|idx| {
+                        matching.contains(
+                            &storage
+                                .data
+                                .get(idx * storage.indices_length, storage.indices_length),
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // Points every block the table doesn't know at `placeholder`, returns how many kinds changed
+    pub fn replace_unknown(&mut self, block_table: &BlockTable, placeholder: &BlockData) -> usize {
+        let unknown = |voxel: &BlockData| {
+            !block_table.contains_key(&name_to_identifier(
+                voxel.namespace.clone(),
+                voxel.name.clone(),
+            ))
+        };
+        match self {
+            Storage::Single(storage) => {
+                if unknown(&storage.voxel) {
+                    storage.voxel = placeholder.clone();
+                    1
+                } else {
+                    0
+                }
+            }
+            Storage::Multi(storage) => {
+                let mut replaced = 0;
+                for entry in storage.palette.iter_mut() {
+                    if entry.ref_count > 0 && unknown(&entry.voxel_type) {
+                        entry.voxel_type = placeholder.clone();
+                        replaced += 1;
+                    }
+                }
+                replaced
+            }
+        }
+    }
+
+    // Every slot in palette order, stale ones included, for debug dumps
+    pub fn palette_summary(&self) -> PaletteSummary {
+        match self {
+            Storage::Single(storage) => PaletteSummary {
+                entries: vec![PaletteSlot {
+                    voxel: storage.voxel.clone(),
+                    ref_count: storage.size,
+                }],
+                capacity: 1,
+                bits_per_index: 0,
+            },
+            Storage::Multi(storage) => PaletteSummary {
+                entries: storage
+                    .palette
+                    .iter()
+                    .map(|entry| PaletteSlot {
+                        voxel: entry.voxel_type.clone(),
+                        ref_count: entry.ref_count,
+                    })
+                    .collect(),
+                capacity: storage.palette_capacity,
+                bits_per_index: storage.indices_length,
+            },
+        }
+    }
+
     pub fn trim(&mut self) {
         match self {
             Storage::Single(_) => (),
@@ -429,6 +651,33 @@ impl Storage {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PaletteSlot {
+    pub voxel: BlockData,
+    // 0 is a free slot, the next new block reuses it
+    pub ref_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PaletteSummary {
+    pub entries: Vec<PaletteSlot>,
+    // How many blocks fit before the indices have to grow
+    pub capacity: usize,
+    pub bits_per_index: usize,
+}
+
+impl PaletteSummary {
+    // Stale entries plus room that was never used
+    pub fn free_slots(&self) -> usize {
+        self.capacity
+            - self
+                .entries
+                .iter()
+                .filter(|slot| slot.ref_count > 0)
+                .count()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct PaletteEntry {
     voxel_type: BlockData,
@@ -465,14 +714,53 @@ impl BitBuffer {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct RawChunk {
     voxels: Storage,
+    // Missing on chunks saved before heightmaps existed
+    heightmap: Option<Heightmap>,
+}
+
+impl RawChunk {
+    // What a UniformChunk message turns back into
+    pub fn uniform(voxel: BlockData, block_table: &BlockTable) -> Self {
+        ChunkData::uniform(voxel, block_table).to_raw()
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        self.voxels.uniform_voxel().is_some()
+    }
+
+    pub fn uniform_voxel(&self) -> Option<&BlockData> {
+        self.voxels.uniform_voxel()
+    }
+
+    pub fn replace_unknown(&mut self, block_table: &BlockTable, placeholder: &BlockData) -> usize {
+        self.voxels.replace_unknown(block_table, placeholder)
+    }
+
+    pub fn palette_summary(&self) -> PaletteSummary {
+        self.voxels.palette_summary()
+    }
+
+    pub fn is_empty(&self, block_table: &BlockTable) -> bool {
+        self.voxels
+            .uniform_voxel()
+            .is_some_and(|voxel| voxel.is_empty(block_table))
+    }
+}
+
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
 }
 
 #[derive(Component, Clone, Debug)]
 pub struct ChunkData {
     voxels: Storage,
     lights: LightStorage,
+    heightmap: Option<Heightmap>,
     change_count: u16,
     dirty: bool,
+    revision: u64,
 }
 
 impl Default for ChunkData {
@@ -481,13 +769,28 @@ impl Default for ChunkData {
             voxels: Storage::new(ChunkShape::USIZE),
             change_count: 0,
             dirty: true,
+            revision: next_revision(),
             lights: LightStorage::new(),
+            heightmap: Some(Heightmap::default()),
         }
     }
 }
 
 #[allow(dead_code)]
 impl ChunkData {
+    /// Straight to single storage without going through set for every voxel
+    pub fn uniform(voxel: BlockData, block_table: &BlockTable) -> Self {
+        let heightmap = Heightmap::measure(block_table, |_, _, _| voxel.clone());
+        Self {
+            voxels: Storage::Single(SingleStorage {
+                size: ChunkShape::USIZE,
+                voxel,
+            }),
+            heightmap: Some(heightmap),
+            ..Default::default()
+        }
+    }
+
     pub fn get(&self, x: u32, y: u32, z: u32) -> BlockData {
         self.voxels.get(Self::linearize(x, y, z))
     }
@@ -497,9 +800,16 @@ impl ChunkData {
         name_to_identifier(voxel.namespace, voxel.name)
     }
 
-    pub fn set(&mut self, x: u32, y: u32, z: u32, voxel: BlockData, _block_table: &BlockTable) {
+    pub fn set(&mut self, x: u32, y: u32, z: u32, voxel: BlockData, block_table: &BlockTable) {
+        if let Some(heightmap) = &mut self.heightmap {
+            let voxels = &self.voxels;
+            heightmap.update(x, y, z, &voxel, block_table, |x, y, z| {
+                voxels.get(Self::linearize(x, y, z))
+            });
+        }
         self.voxels.set(Self::linearize(x, y, z), voxel);
         self.change_count += 1;
+        self.revision = next_revision();
         self.set_dirty(true);
 
         if self.change_count > 500 {
@@ -543,10 +853,14 @@ impl ChunkData {
     }
 
     pub fn is_uniform(&self) -> bool {
-        match self.voxels {
-            Storage::Single(_) => true,
-            Storage::Multi(_) => false,
-        }
+        self.voxels.uniform_voxel().is_some()
+    }
+
+    /// Every voxel is the same full opaque cube, nothing inside can ever be seen
+    pub fn is_uniform_solid(&self, block_table: &BlockTable) -> bool {
+        self.voxels
+            .uniform_voxel()
+            .is_some_and(|voxel| !voxel.is_true_empty(block_table))
     }
     pub fn complete_relight(&mut self, _block_table: &BlockTable) -> ChunkData {
         // for x in 0..CHUNK_SIZE {
@@ -573,7 +887,9 @@ impl ChunkData {
         self.clone()
     }
     pub fn is_empty(&self, block_table: &BlockTable) -> bool {
-        self.is_uniform() && self.get(0, 0, 0).is_empty(block_table)
+        self.voxels
+            .uniform_voxel()
+            .is_some_and(|voxel| voxel.is_empty(block_table))
     }
 
     pub fn is_dirty(&self) -> bool {
@@ -584,10 +900,39 @@ impl ChunkData {
         self.dirty = dirty;
     }
 
+    /// Never handed out twice in a run, so two chunks with the same one hold the same voxels
+    /// and heightmap. Anything that changes what to_raw returns takes a new one
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
     pub fn trim(&mut self) {
         self.voxels.trim();
     }
 
+    pub fn palette_contains(&self, identifier: &str) -> bool {
+        self.voxels.palette_contains(identifier)
+    }
+
+    pub fn palette_identifiers(&self) -> BTreeSet<String> {
+        self.voxels.palette_identifiers()
+    }
+
+    pub fn palette_summary(&self) -> PaletteSummary {
+        self.voxels.palette_summary()
+    }
+
+    pub fn positions_of(&self, identifier: &str) -> Vec<UVec3> {
+        self.voxels
+            .indices_of(identifier)
+            .into_iter()
+            .map(|idx| {
+                let (x, y, z) = Self::delinearize(idx);
+                UVec3::new(x, y, z)
+            })
+            .collect()
+    }
+
     pub const fn size() -> u32 {
         ChunkShape::USIZE as u32
     }
@@ -616,16 +961,41 @@ impl ChunkData {
             voxels: raw_chunk.voxels,
             change_count: 0,
             dirty: false,
+            revision: next_revision(),
             lights: LightStorage::new(),
+            heightmap: raw_chunk.heightmap,
         }
     }
 
     pub fn to_raw(&self) -> RawChunk {
         RawChunk {
             voxels: self.voxels.clone(),
+            heightmap: self.heightmap.clone(),
         }
     }
 
+    /// Highest voxel of that kind in the column, None when there isn't one or when the
+    /// chunk came from an old save and measure_heights hasn't run on it yet
+    pub fn column_top(&self, x: u32, z: u32, kind: SurfaceKind) -> Option<u32> {
+        self.heightmap.as_ref()?.top(x, z, kind)
+    }
+
+    pub fn heightmap(&self) -> Option<&Heightmap> {
+        self.heightmap.as_ref()
+    }
+
+    /// Only does anything for chunks loaded without a heightmap
+    pub fn measure_heights(&mut self, block_table: &BlockTable) {
+        if self.heightmap.is_none() {
+            self.remeasure_heights(block_table);
+        }
+    }
+
+    pub fn remeasure_heights(&mut self, block_table: &BlockTable) {
+        self.heightmap = Some(Heightmap::measure(block_table, |x, y, z| self.get(x, y, z)));
+        self.revision = next_revision();
+    }
+
     pub fn get_light(&self, x: u32, y: u32, z: u32) -> u8 {
         self.lights.get_light(Self::linearize(x, y, z))
     }
@@ -650,3 +1020,148 @@ impl ChunkData {
         self.lights.set_sunlight(Self::linearize(x, y, z), value);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::blocks::descriptor::BlockGeometry,
+        testing::{self, block, descriptor},
+    };
+
+    #[test]
+    fn palette_lookup_ignores_stale_entries() {
+        let table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        // Single storage, everything is air and nothing else
+        assert!(chunk.is_uniform());
+        assert!(chunk.palette_contains("vinox:air"));
+        assert!(!chunk.palette_contains("vinox:stone"));
+        assert!(!chunk.palette_contains("vinox:ai"));
+        assert_eq!(chunk.positions_of("vinox:air").len(), ChunkData::usize());
+
+        chunk.set(1, 2, 3, block("stone"), &table);
+        chunk.set(4, 5, 6, block("dirt"), &table);
+        assert!(!chunk.is_uniform());
+        assert!(chunk.palette_contains("vinox:stone"));
+        assert_eq!(chunk.positions_of("vinox:stone"), vec![UVec3::new(1, 2, 3)]);
+        // A different state of the same block still counts
+        let mut grown = block("dirt");
+        grown.growth_state = Some(GrowthState::Sapling);
+        chunk.set(7, 7, 7, grown, &table);
+        assert_eq!(chunk.positions_of("vinox:dirt").len(), 2);
+
+        // Mined out, the palette keeps the entry around with nothing pointing at it
+        chunk.set(1, 2, 3, block("air"), &table);
+        assert!(!chunk.palette_contains("vinox:stone"));
+        assert!(chunk.positions_of("vinox:stone").is_empty());
+        chunk.trim();
+        assert!(!chunk.is_uniform());
+        assert!(!chunk.palette_contains("vinox:stone"));
+        assert!(chunk.palette_contains("vinox:dirt"));
+        assert!(!chunk.palette_contains("vinox:gold_ore"));
+        // Both states of dirt are the one block
+        assert_eq!(
+            chunk.palette_identifiers(),
+            BTreeSet::from(["vinox:air".to_string(), "vinox:dirt".to_string()])
+        );
+        assert_eq!(
+            ChunkData::default().palette_identifiers(),
+            BTreeSet::from(["vinox:air".to_string()])
+        );
+    }
+
+    #[test]
+    fn palette_summary_shows_free_slots() {
+        let table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        let single = chunk.palette_summary();
+        assert_eq!(single.entries.len(), 1);
+        assert_eq!(single.entries[0].ref_count, ChunkData::usize());
+        assert_eq!(single.free_slots(), 0);
+
+        chunk.set(1, 2, 3, block("stone"), &table);
+        chunk.set(4, 5, 6, block("dirt"), &table);
+        chunk.set(1, 2, 3, block("air"), &table);
+        let summary = chunk.palette_summary();
+        assert_eq!(summary.bits_per_index, 2);
+        assert_eq!(summary.capacity, 4);
+        let counts: Vec<(String, usize)> = summary
+            .entries
+            .iter()
+            .map(|slot| (slot.voxel.name.clone(), slot.ref_count))
+            .collect();
+        // Stone's slot is still there with nothing in it
+        assert_eq!(
+            counts,
+            vec![
+                ("air".to_string(), ChunkData::usize() - 1),
+                ("stone".to_string(), 0),
+                ("dirt".to_string(), 1)
+            ]
+        );
+        assert_eq!(summary.free_slots(), 2);
+        assert_eq!(chunk.to_raw().palette_summary(), summary);
+    }
+
+    #[test]
+    fn uniform_chunks_match_built_ones() {
+        let table = testing::block_table([
+            descriptor("air", VoxelVisibility::Empty),
+            descriptor("stone", VoxelVisibility::Opaque),
+            BlockDescriptor {
+                geometry: Some(BlockGeometry::Slab),
+                ..descriptor("stone.slab", VoxelVisibility::Opaque)
+            },
+            descriptor("glass", VoxelVisibility::Transparent),
+        ]);
+        let mut built = ChunkData::default();
+        for idx in 0..ChunkData::usize() {
+            let (x, y, z) = ChunkData::delinearize(idx);
+            built.set(x, y, z, block("stone"), &table);
+        }
+        let uniform = ChunkData::uniform(block("stone"), &table);
+        assert!(uniform.is_uniform());
+        assert_eq!(uniform.heightmap(), built.heightmap());
+        assert_eq!(uniform.get(15, 15, 15), built.get(15, 15, 15));
+        assert!(uniform.is_uniform_solid(&table));
+
+        assert!(ChunkData::default().to_raw().is_empty(&table));
+        assert!(ChunkData::uniform(block("air"), &table)
+            .to_raw()
+            .is_empty(&table));
+        assert!(!uniform.to_raw().is_empty(&table));
+        assert!(uniform.to_raw().is_uniform());
+        // Slabs and glass leave gaps, a chunk of them still gets looked at
+        assert!(!ChunkData::uniform(block("stone.slab"), &table).is_uniform_solid(&table));
+        assert!(!ChunkData::uniform(block("glass"), &table).is_uniform_solid(&table));
+        assert!(!built.to_raw().is_empty(&table));
+        let mut holed = ChunkData::uniform(block("stone"), &table);
+        holed.set(3, 4, 5, block("air"), &table);
+        assert!(!holed.is_uniform_solid(&table));
+        assert!(!holed.to_raw().is_uniform());
+    }
+
+    #[test]
+    fn unknown_blocks_fall_back_to_the_placeholder() {
+        let table = testing::block_table([descriptor("air", VoxelVisibility::Empty)]);
+        // Saved by a build that had a block this one doesn't
+        let mut saved = ChunkData::default();
+        saved.set(1, 2, 3, block("marble"), &table);
+        let bytes = bincode::serialize(&saved.to_raw()).unwrap();
+        let chunk = ChunkData::from_raw(bincode::deserialize(&bytes).unwrap());
+
+        let marble = chunk.get(1, 2, 3);
+        assert!(!marble.is_empty(&table));
+        assert!(marble.is_opaque(&table));
+        assert!(!marble.is_true_empty(&table));
+        assert!(chunk.get(0, 0, 0).is_empty(&table));
+        // Asking again doesn't need the table to have learned anything
+        assert_eq!(
+            table.get_or_unknown("vinox:marble"),
+            table.get_or_unknown("modded:marble")
+        );
+        assert_eq!(table.get_or_unknown("vinox:marble").name, UNKNOWN_BLOCK.1);
+        assert_eq!(table.get_or_unknown("vinox:air").name, "air");
+    }
+}
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+
+// How far a decoration can sit from the middle of its block, so they don't line up in rows
+pub const MAX_OFFSET: f32 = 0.25;
+
+fn hash(seed: u32, voxel: IVec3, salt: u32) -> u32 {
+    let mut hash = (voxel.x as u32)
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add((voxel.y as u32).wrapping_mul(0xd816_3841))
+        .wrapping_add((voxel.z as u32).wrapping_mul(0xcb1a_b31f))
+        .wrapping_add(seed.wrapping_mul(0x9e37_79b9))
+        .wrapping_add(salt.wrapping_mul(0x85eb_ca6b));
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2c1b_3c6d);
+    hash ^= hash >> 12;
+    hash = hash.wrapping_mul(0x297a_2d39);
+    hash ^= hash >> 15;
+    hash
+}
+
+// 0 to 1, only ever depends on the world seed and the voxel
+fn roll(seed: u32, voxel: IVec3, salt: u32) -> f32 {
+    (hash(seed, voxel, salt) & 0xffff) as f32 / 65536.0
+}
+
+// Whether the block at `voxel` gets a decoration on top, the same answer on every client in the
+// same world whatever order its chunks were meshed in. Having air above is up to the caller
+pub fn is_decorated(seed: u32, voxel: IVec3, density: f32) -> bool {
+    roll(seed, voxel, 0) < density
+}
+
+// Where in the block it stands, from the middle on x and z
+pub fn decoration_offset(seed: u32, voxel: IVec3) -> Vec2 {
+    Vec2::new(roll(seed, voxel, 1), roll(seed, voxel, 2)) * 2.0 * MAX_OFFSET - MAX_OFFSET
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placement_only_depends_on_seed_and_position() {
+        let voxels: Vec<IVec3> = (-20..20)
+            .flat_map(|x| (-20..20).map(move |z| IVec3::new(x, 64, z)))
+            .collect();
+        let place = |seed: u32| -> Vec<(bool, Vec2)> {
+            voxels
+                .iter()
+                .map(|voxel| {
+                    (
+                        is_decorated(seed, *voxel, 0.3),
+                        decoration_offset(seed, *voxel),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(place(42), place(42));
+        assert_ne!(place(42), place(43));
+        // Backwards gives the same answer for each voxel too
+        let reversed: Vec<bool> = voxels
+            .iter()
+            .rev()
+            .map(|voxel| is_decorated(42, *voxel, 0.3))
+            .collect();
+        let forwards: Vec<bool> = place(42).iter().rev().map(|(placed, _)| *placed).collect();
+        assert_eq!(reversed, forwards);
+
+        let placed = place(42).iter().filter(|(placed, _)| *placed).count();
+        assert!((300..660).contains(&placed), "{placed} of 1600");
+        for (_, offset) in place(42) {
+            assert!(offset.abs().max_element() <= MAX_OFFSET);
+        }
+    }
+
+    #[test]
+    fn density_ends() {
+        for x in -50..50 {
+            let voxel = IVec3::new(x, x * 3, -x);
+            assert!(!is_decorated(7, voxel, 0.0));
+            assert!(is_decorated(7, voxel, 1.0));
+        }
+    }
+}
@@ -1 +1,8 @@
 pub mod chunks;
+pub mod decoration;
+pub mod frames;
+pub mod placement;
+pub mod seats;
+pub mod spawn;
+pub mod templates;
+pub mod transitions;
@@ -0,0 +1,143 @@
+use bevy::prelude::*;
+
+const NEIGHBORS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildLockMode {
+    // Every placement lands on the line through the first block along its face normal
+    Line,
+    // Every placement lands in the layer of the first block across its face normal
+    Plane,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildLock {
+    pub origin: IVec3,
+    // 0, 1 or 2 for x, y or z
+    pub axis: usize,
+    pub mode: BuildLockMode,
+}
+
+impl BuildLock {
+    // The axis is whichever the normal points along the most
+    pub fn new(origin: IVec3, normal: IVec3, mode: BuildLockMode) -> Self {
+        let normal = normal.abs();
+        let axis = if normal.x >= normal.y && normal.x >= normal.z {
+            0
+        } else if normal.y >= normal.z {
+            1
+        } else {
+            2
+        };
+        Self { origin, axis, mode }
+    }
+
+    // The voxel closest to `target` that the lock allows
+    pub fn snap(&self, target: IVec3) -> IVec3 {
+        match self.mode {
+            BuildLockMode::Line => {
+                let mut snapped = self.origin;
+                snapped[self.axis] = target[self.axis];
+                snapped
+            }
+            BuildLockMode::Plane => {
+                let mut snapped = target;
+                snapped[self.axis] = self.origin[self.axis];
+                snapped
+            }
+        }
+    }
+
+    // Where the block the raycast resolved to really goes. None when the snapped voxel is taken
+    // or has nothing next to it to hold on to, better to place nothing than the wrong thing
+    pub fn snap_placement(&self, target: IVec3, solid: impl Fn(IVec3) -> bool) -> Option<IVec3> {
+        let snapped = self.snap(target);
+        if solid(snapped) {
+            return None;
+        }
+        NEIGHBORS
+            .iter()
+            .any(|offset| solid(snapped + *offset))
+            .then_some(snapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn world(solid: &[IVec3]) -> impl Fn(IVec3) -> bool {
+        let solid: HashSet<IVec3> = solid.iter().copied().collect();
+        move |voxel| solid.contains(&voxel)
+    }
+
+    #[test]
+    fn lines_follow_the_normal_on_every_axis() {
+        let origin = IVec3::new(3, 10, -4);
+        for (axis, normal) in [IVec3::X, IVec3::Y, IVec3::Z].into_iter().enumerate() {
+            let lock = BuildLock::new(origin, normal, BuildLockMode::Line);
+            assert_eq!(lock.axis, axis);
+            // Aimed one over to the side, it still ends up on the line
+            let next = origin + normal;
+            let aimed = next + IVec3::ONE - normal;
+            assert_eq!(lock.snap(aimed), next);
+            let solid = world(&[origin]);
+            assert_eq!(lock.snap_placement(aimed, &solid), Some(next));
+        }
+        // A diagonal-ish normal goes with its biggest part
+        let lock = BuildLock::new(origin, IVec3::new(1, -3, 2), BuildLockMode::Line);
+        assert_eq!(lock.axis, 1);
+    }
+
+    #[test]
+    fn runs_work_in_the_negative_direction() {
+        let origin = IVec3::new(0, 64, 0);
+        let lock = BuildLock::new(origin, IVec3::NEG_X, BuildLockMode::Line);
+        let mut placed = vec![origin];
+        for step in 1..5 {
+            // Drifting up and sideways the further the run goes
+            let aimed = IVec3::new(-step, 64 + step / 2, step % 2);
+            let solid = world(&placed);
+            let voxel = lock.snap_placement(aimed, solid).unwrap();
+            assert_eq!(voxel, IVec3::new(-step, 64, 0));
+            placed.push(voxel);
+        }
+    }
+
+    #[test]
+    fn planes_keep_the_layer() {
+        let origin = IVec3::new(5, 20, 5);
+        let lock = BuildLock::new(origin, IVec3::Y, BuildLockMode::Plane);
+        let solid = world(&[origin, origin + IVec3::X]);
+        // Aimed at the top of the floor, goes next to it instead
+        assert_eq!(
+            lock.snap_placement(IVec3::new(5, 21, 6), &solid),
+            Some(IVec3::new(5, 20, 6))
+        );
+        let lock = BuildLock::new(origin, IVec3::NEG_Z, BuildLockMode::Plane);
+        assert_eq!(lock.snap(IVec3::new(9, 30, 2)), IVec3::new(9, 30, 5));
+    }
+
+    #[test]
+    fn unreachable_snaps_are_suppressed() {
+        let origin = IVec3::new(0, 0, 0);
+        let lock = BuildLock::new(origin, IVec3::Z, BuildLockMode::Line);
+        let solid = world(&[origin, IVec3::new(0, 0, 1)]);
+        // Two past the end of the run, nothing to hold it up
+        assert_eq!(lock.snap_placement(IVec3::new(2, 0, 3), &solid), None);
+        // Right where a block already is
+        assert_eq!(lock.snap_placement(IVec3::new(1, 1, 1), &solid), None);
+        assert_eq!(
+            lock.snap_placement(IVec3::new(1, 1, 2), &solid),
+            Some(IVec3::new(0, 0, 2))
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use bevy::prelude::*;
+
+use crate::{
+    storage::blocks::descriptor::SeatDescriptor,
+    world::{
+        chunks::storage::{name_to_identifier, BlockData, BlockTable},
+        spawn::find_safe_beside,
+    },
+};
+
+pub fn seat_descriptor(block: &BlockData, block_table: &BlockTable) -> Option<SeatDescriptor> {
+    block_table
+        .get(&name_to_identifier(
+            block.namespace.clone(),
+            block.name.clone(),
+        ))
+        .and_then(|descriptor| descriptor.seat)
+}
+
+pub fn is_seat(block: &BlockData, block_table: &BlockTable) -> bool {
+    seat_descriptor(block, block_table).is_some()
+}
+
+// Where a seated player's feet are held
+pub fn seat_position(voxel: IVec3, seat: SeatDescriptor) -> Vec3 {
+    voxel.as_vec3() + seat.offset
+}
+
+// Getting up looks for room the same way getting out of bed does. With nowhere to go they're put
+// on top of the seat, or back where it was if it's gone
+pub fn dismount_position(
+    voxel: IVec3,
+    block_table: &BlockTable,
+    mut sample: impl FnMut(IVec3) -> Option<BlockData>,
+) -> Vec3 {
+    find_safe_beside(voxel, block_table, &mut sample).unwrap_or_else(|| {
+        let still_there = sample(voxel).is_some_and(|block| is_seat(&block, block_table));
+        voxel.as_vec3() + Vec3::new(0.5, if still_there { 1.0 } else { 0.0 }, 0.5)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::blocks::descriptor::BlockDescriptor,
+        testing::{self, block, descriptor},
+        world::chunks::storage::VoxelVisibility,
+    };
+    use bevy::utils::HashMap;
+
+    fn block_table() -> BlockTable {
+        testing::block_table([
+            descriptor("air", VoxelVisibility::Empty),
+            descriptor("stone", VoxelVisibility::Opaque),
+            BlockDescriptor {
+                seat: Some(SeatDescriptor {
+                    offset: Vec3::new(0.5, 0.5, 0.5),
+                }),
+                ..descriptor("stool", VoxelVisibility::Opaque)
+            },
+        ])
+    }
+
+    // Flat stone floor below y 0, anything listed overrides it
+    fn world(overrides: &[(IVec3, &str)]) -> impl FnMut(IVec3) -> Option<BlockData> {
+        let overrides: HashMap<IVec3, String> = overrides
+            .iter()
+            .map(|(pos, name)| (*pos, name.to_string()))
+            .collect();
+        move |pos| {
+            let name = overrides
+                .get(&pos)
+                .cloned()
+                .unwrap_or_else(|| if pos.y < 0 { "stone" } else { "air" }.to_string());
+            Some(block(&name))
+        }
+    }
+
+    #[test]
+    fn seats_hold_players_at_their_offset() {
+        let block_table = block_table();
+        let stool = block("stool");
+        let seat = seat_descriptor(&stool, &block_table).unwrap();
+        assert_eq!(
+            seat_position(IVec3::new(4, 2, -3), seat),
+            Vec3::new(4.5, 2.5, -2.5)
+        );
+        let stone = block("stone");
+        assert!(!is_seat(&stone, &block_table));
+    }
+
+    #[test]
+    fn broken_seat_drops_the_player_beside_it() {
+        // Broken, so air where it was, the first open side wins like it does for beds
+        assert_eq!(
+            dismount_position(IVec3::ZERO, &block_table(), world(&[])),
+            Vec3::new(1.5, 0.0, 0.5)
+        );
+        // East is walled off and west has no floor
+        assert_eq!(
+            dismount_position(
+                IVec3::ZERO,
+                &block_table(),
+                world(&[
+                    (IVec3::new(1, 0, 0), "stone"),
+                    (IVec3::new(-1, -1, 0), "air"),
+                ])
+            ),
+            Vec3::new(0.5, 0.0, 1.5)
+        );
+    }
+
+    #[test]
+    fn boxed_in_seat_puts_the_player_on_top_or_where_it_was() {
+        let block_table = block_table();
+        let mut walls: Vec<(IVec3, &str)> = (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |z| IVec3::new(x, 0, z)))
+            .filter(|pos| *pos != IVec3::ZERO)
+            .map(|pos| (pos, "stone"))
+            .collect();
+        walls.push((IVec3::new(0, 2, 0), "stone"));
+        // Still standing with no room anywhere
+        walls.push((IVec3::ZERO, "stool"));
+        assert_eq!(
+            dismount_position(IVec3::ZERO, &block_table, world(&walls)),
+            Vec3::new(0.5, 1.0, 0.5)
+        );
+        // Broken out from under them, the hole it left is the only way out
+        walls.pop();
+        assert_eq!(
+            dismount_position(IVec3::ZERO, &block_table, world(&walls)),
+            Vec3::new(0.5, 0.0, 0.5)
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    physics::movement::{block_flags, BlockFlags},
+    world::chunks::{
+        positions::DimensionId,
+        storage::{name_to_identifier, BlockData, BlockTable},
+    },
+};
+
+// The sides first since those look the most like getting out of bed, then the corners
+const BESIDE: [IVec3; 8] = [
+    IVec3::new(1, 0, 0),
+    IVec3::new(-1, 0, 0),
+    IVec3::new(0, 0, 1),
+    IVec3::new(0, 0, -1),
+    IVec3::new(1, 0, 1),
+    IVec3::new(1, 0, -1),
+    IVec3::new(-1, 0, 1),
+    IVec3::new(-1, 0, -1),
+];
+
+// A player's own respawn point, the voxel of the bed they last used
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnPoint {
+    pub dimension: DimensionId,
+    pub anchor: IVec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Respawn {
+    // Where the player's feet go
+    At(Vec3),
+    // Nothing sleepable at the anchor anymore
+    Missing,
+    // Still there but nowhere around it to stand
+    Obstructed,
+}
+
+pub fn is_sleepable(block: &BlockData, block_table: &BlockTable) -> bool {
+    block_table
+        .get(&name_to_identifier(
+            block.namespace.clone(),
+            block.name.clone(),
+        ))
+        .and_then(|descriptor| descriptor.sleepable)
+        .unwrap_or(false)
+}
+
+// Room for a body and something to stand on. Unloaded voxels are never safe
+fn is_safe(
+    feet: IVec3,
+    block_table: &BlockTable,
+    sample: &mut impl FnMut(IVec3) -> Option<BlockData>,
+) -> bool {
+    let mut flags = |pos: IVec3| sample(pos).map(|block| block_flags(&block, block_table));
+    let open = |flags: Option<BlockFlags>| flags.is_some_and(|flags| !flags.solid && !flags.fluid);
+    open(flags(feet))
+        && open(flags(feet + IVec3::Y))
+        && flags(feet - IVec3::Y).is_some_and(|flags| flags.solid)
+}
+
+// Checked when respawning rather than when the bed breaks, sample gives the block at a voxel
+pub fn find_respawn(
+    anchor: IVec3,
+    block_table: &BlockTable,
+    mut sample: impl FnMut(IVec3) -> Option<BlockData>,
+) -> Respawn {
+    if !sample(anchor).is_some_and(|block| is_sleepable(&block, block_table)) {
+        return Respawn::Missing;
+    }
+    find_safe_beside(anchor, block_table, &mut sample).map_or(Respawn::Obstructed, Respawn::At)
+}
+
+// Where the feet go when getting out of or off something at anchor, on top of it as a last resort
+pub fn find_safe_beside(
+    anchor: IVec3,
+    block_table: &BlockTable,
+    sample: &mut impl FnMut(IVec3) -> Option<BlockData>,
+) -> Option<Vec3> {
+    BESIDE
+        .iter()
+        .map(|offset| anchor + *offset)
+        .chain([anchor + IVec3::Y])
+        .find(|feet| is_safe(*feet, block_table, sample))
+        .map(|feet| feet.as_vec3() + Vec3::new(0.5, 0.0, 0.5))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::blocks::descriptor::BlockDescriptor,
+        testing::{self, block, descriptor},
+        world::chunks::storage::VoxelVisibility,
+    };
+    use bevy::utils::HashMap;
+
+    fn block_table() -> BlockTable {
+        testing::block_table([
+            descriptor("air", VoxelVisibility::Empty),
+            descriptor("stone", VoxelVisibility::Opaque),
+            BlockDescriptor {
+                sleepable: Some(true),
+                ..descriptor("bed", VoxelVisibility::Opaque)
+            },
+        ])
+    }
+
+    // Flat stone floor below y 0 with a bed at the origin, anything listed overrides it
+    fn world(overrides: &[(IVec3, &str)]) -> impl FnMut(IVec3) -> Option<BlockData> {
+        let overrides: HashMap<IVec3, String> = overrides
+            .iter()
+            .map(|(pos, name)| (*pos, name.to_string()))
+            .collect();
+        move |pos| {
+            let name = overrides.get(&pos).cloned().unwrap_or_else(|| {
+                match pos {
+                    IVec3::ZERO => "bed",
+                    pos if pos.y < 0 => "stone",
+                    _ => "air",
+                }
+                .to_string()
+            });
+            Some(block(&name))
+        }
+    }
+
+    #[test]
+    fn happy_path_gets_out_beside_the_bed() {
+        assert_eq!(
+            find_respawn(IVec3::ZERO, &block_table(), world(&[])),
+            Respawn::At(Vec3::new(1.5, 0.0, 0.5))
+        );
+    }
+
+    #[test]
+    fn destroyed_bed_is_missing() {
+        let block_table = block_table();
+        assert_eq!(
+            find_respawn(IVec3::ZERO, &block_table, world(&[(IVec3::ZERO, "air")])),
+            Respawn::Missing
+        );
+        // Something else built where it was doesn't count either
+        assert_eq!(
+            find_respawn(IVec3::ZERO, &block_table, world(&[(IVec3::ZERO, "stone")])),
+            Respawn::Missing
+        );
+        // Neither does a chunk we can't see
+        assert_eq!(
+            find_respawn(IVec3::ZERO, &block_table, |_| None),
+            Respawn::Missing
+        );
+    }
+
+    #[test]
+    fn buried_bed_is_obstructed() {
+        let block_table = block_table();
+        let mut walls: Vec<(IVec3, &str)> = BESIDE
+            .iter()
+            .flat_map(|offset| [(*offset, "stone"), (*offset + IVec3::Y, "stone")])
+            .collect();
+        // Walled in but open above, the top of the bed is still fine
+        assert_eq!(
+            find_respawn(IVec3::ZERO, &block_table, world(&walls)),
+            Respawn::At(Vec3::new(0.5, 1.0, 0.5))
+        );
+        walls.push((IVec3::new(0, 2, 0), "stone"));
+        assert_eq!(
+            find_respawn(IVec3::ZERO, &block_table, world(&walls)),
+            Respawn::Obstructed
+        );
+    }
+
+    #[test]
+    fn cliff_edge_picks_the_side_with_ground() {
+        // Everything east and north of the bed drops away
+        let cliff: Vec<(IVec3, &str)> = (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |z| IVec3::new(x, -1, z)))
+            .filter(|pos| pos.x >= 0 && pos.z >= 0 && *pos != IVec3::new(0, -1, 0))
+            .map(|pos| (pos, "air"))
+            .collect();
+        assert_eq!(
+            find_respawn(IVec3::ZERO, &block_table(), world(&cliff)),
+            Respawn::At(Vec3::new(-0.5, 0.0, 0.5))
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+
+use crate::world::{
+    chunks::storage::{name_to_identifier, BlockData, BlockTable, Container, ItemTable},
+    frames::is_display_frame,
+};
+
+// Longest text a copied block can carry, sign text and frame rotations are far shorter
+pub const MAX_TEMPLATE_TEXT: usize = 256;
+
+// Why the server turned a copied block away instead of placing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    UnknownBlock(String),
+    UnknownItem(String),
+    Overstuffed { items: usize, max: usize },
+    TextTooLong,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnknownBlock(identifier) => write!(f, "there's no block {identifier}"),
+            TemplateError::UnknownItem(identifier) => write!(f, "there's no item {identifier}"),
+            TemplateError::Overstuffed { items, max } => {
+                write!(f, "{items} items don't fit in {max} slots")
+            }
+            TemplateError::TextTooLong => {
+                write!(f, "its text is over {MAX_TEMPLATE_TEXT} characters")
+            }
+        }
+    }
+}
+
+// What's left of a copied block once everything a player couldn't have set by placing it is
+// stripped. Whatever stripping can't fix turns the whole template away
+pub fn sanitize_template(
+    mut block: BlockData,
+    block_table: &BlockTable,
+    item_table: &ItemTable,
+) -> Result<BlockData, TemplateError> {
+    let identifier = name_to_identifier(block.namespace.clone(), block.name.clone());
+    let Some(descriptor) = block_table.get(&identifier) else {
+        return Err(TemplateError::UnknownBlock(identifier));
+    };
+    // Growth counts from when the server stamps it, not from whenever it was copied
+    block.last_tick = None;
+    if !descriptor.has_direction.unwrap_or(false) {
+        block.direction = None;
+        block.top = None;
+    }
+    // Frames only ever get filled through UseFrame
+    if is_display_frame(&block, block_table) {
+        block.container = None;
+        block.arbitary_data = None;
+    }
+    if let Some(container) = block.container.take() {
+        // Blocks without storage of their own don't get any
+        if let Some(max) = descriptor.container_size {
+            if container.items.len() > max as usize {
+                return Err(TemplateError::Overstuffed {
+                    items: container.items.len(),
+                    max: max as usize,
+                });
+            }
+            if let Some(unknown) = container
+                .items
+                .iter()
+                .find(|identifier| !item_table.contains_key(identifier.as_str()))
+            {
+                return Err(TemplateError::UnknownItem(unknown.clone()));
+            }
+            // The block says how big it is, never the message
+            block.container = Some(Container {
+                items: container.items,
+                max_size: max,
+            });
+        }
+    }
+    if block
+        .arbitary_data
+        .as_ref()
+        .is_some_and(|text| text.chars().count() > MAX_TEMPLATE_TEXT)
+    {
+        return Err(TemplateError::TextTooLong);
+    }
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::{blocks::descriptor::BlockDescriptor, items::descriptor::ItemDescriptor},
+        testing::{self, block, descriptor},
+        world::chunks::storage::{Direction, GrowthState, VoxelVisibility},
+    };
+
+    fn tables() -> (BlockTable, ItemTable) {
+        let block_table = testing::block_table([
+            descriptor("stone", VoxelVisibility::Opaque),
+            BlockDescriptor {
+                has_direction: Some(true),
+                container_size: Some(3),
+                ..descriptor("chest", VoxelVisibility::Opaque)
+            },
+            BlockDescriptor {
+                has_direction: Some(true),
+                display_frame: Some(true),
+                ..descriptor("frame", VoxelVisibility::Transparent)
+            },
+        ]);
+        let mut item_table = ItemTable::default();
+        item_table.insert(
+            "vinox:coal".to_string(),
+            ItemDescriptor {
+                namespace: "vinox".to_string(),
+                name: "coal".to_string(),
+                ..Default::default()
+            },
+        );
+        (block_table, item_table)
+    }
+
+    fn chest(items: &[&str], max_size: u8) -> BlockData {
+        BlockData {
+            container: Some(Container {
+                items: items.iter().map(|item| item.to_string()).collect(),
+                max_size,
+            }),
+            ..block("chest")
+        }
+    }
+
+    #[test]
+    fn stamps_and_orientation_are_stripped() {
+        let (block_table, item_table) = tables();
+        let copied = BlockData {
+            direction: Some(Direction::East),
+            top: Some(true),
+            growth_state: Some(GrowthState::Sapling),
+            last_tick: Some(4000),
+            ..block("stone")
+        };
+        let placed = sanitize_template(copied, &block_table, &item_table).unwrap();
+        assert_eq!(placed.last_tick, None);
+        // Stone can't face anywhere
+        assert_eq!(placed.direction, None);
+        assert_eq!(placed.top, None);
+        assert_eq!(placed.growth_state, Some(GrowthState::Sapling));
+
+        let turned = BlockData {
+            direction: Some(Direction::East),
+            top: Some(true),
+            ..chest(&[], 3)
+        };
+        let placed = sanitize_template(turned, &block_table, &item_table).unwrap();
+        assert_eq!(placed.direction, Some(Direction::East));
+        assert_eq!(placed.top, Some(true));
+
+        assert_eq!(
+            sanitize_template(block("marble"), &block_table, &item_table),
+            Err(TemplateError::UnknownBlock("vinox:marble".to_string()))
+        );
+    }
+
+    #[test]
+    fn containers_are_checked_against_the_block() {
+        let (block_table, item_table) = tables();
+        let placed = sanitize_template(chest(&["vinox:coal"], 200), &block_table, &item_table);
+        // The message doesn't get to say how big the chest is
+        assert_eq!(placed, Ok(chest(&["vinox:coal"], 3)));
+
+        assert_eq!(
+            sanitize_template(chest(&["vinox:coal"; 4], 200), &block_table, &item_table),
+            Err(TemplateError::Overstuffed { items: 4, max: 3 })
+        );
+        assert_eq!(
+            sanitize_template(
+                chest(&["vinox:coal", "vinox:bedrock"], 3),
+                &block_table,
+                &item_table
+            ),
+            Err(TemplateError::UnknownItem("vinox:bedrock".to_string()))
+        );
+
+        // Stone has nowhere to keep anything
+        let stuffed = BlockData {
+            container: chest(&["vinox:coal"], 3).container,
+            ..block("stone")
+        };
+        let placed = sanitize_template(stuffed, &block_table, &item_table).unwrap();
+        assert_eq!(placed.container, None);
+
+        let filled_frame = BlockData {
+            container: chest(&["vinox:coal"], 1).container,
+            arbitary_data: Some("3".to_string()),
+            ..block("frame")
+        };
+        let placed = sanitize_template(filled_frame, &block_table, &item_table).unwrap();
+        assert_eq!(placed.container, None);
+        assert_eq!(placed.arbitary_data, None);
+    }
+
+    #[test]
+    fn text_has_a_limit() {
+        let (block_table, item_table) = tables();
+        let sign = |text: String| BlockData {
+            arbitary_data: Some(text),
+            ..block("stone")
+        };
+        let longest = "名".repeat(MAX_TEMPLATE_TEXT);
+        assert!(sanitize_template(sign(longest.clone()), &block_table, &item_table).is_ok());
+        assert_eq!(
+            sanitize_template(sign(format!("{longest}!")), &block_table, &item_table),
+            Err(TemplateError::TextTooLong)
+        );
+    }
+}
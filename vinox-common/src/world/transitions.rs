@@ -0,0 +1,683 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    mem::{self, Discriminant},
+};
+
+use bevy::prelude::*;
+use rand::{Rng, RngCore};
+use rustc_hash::FxHashMap;
+
+use crate::{
+    ecs::time::ServerTick,
+    storage::blocks::descriptor::{TransitionRule, TransitionTrigger},
+    world::chunks::storage::{identifier_to_name, name_to_identifier, BlockData, BlockTable},
+};
+
+// How many voxels of every simulated chunk get a random tick each server tick
+pub const RANDOM_TICKS_PER_CHUNK: usize = 3;
+// NeighborIs can't look further than this, a neighbor update checks everything this close
+pub const MAX_NEIGHBOR_DISTANCE: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionCause {
+    RandomTick,
+    // Something close by changed, only NeighborIs rules can have started to hold
+    NeighborUpdate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransitionError {
+    // A target or neighbor that isn't a block
+    Unresolved { block: String, reference: String },
+    TooFar { block: String, within: u8 },
+    // Starts and ends on the same block
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransitionError::Unresolved { block, reference } => {
+                write!(f, "{block} transitions with {reference}, which isn't a block")
+            }
+            TransitionError::TooFar { block, within } => write!(
+                f,
+                "{block} looks for a neighbor {within} away, at most {MAX_NEIGHBOR_DISTANCE} is allowed"
+            ),
+            TransitionError::Cycle(path) => write!(
+                f,
+                "{} turns back into itself: {}",
+                path[0],
+                path.join(" -> ")
+            ),
+        }
+    }
+}
+
+// What a rule gets to see of the world around the block
+pub struct Surroundings<F: Fn(&str, u8) -> bool> {
+    pub tick: ServerTick,
+    // Brightest light on the block
+    pub light: u8,
+    // Whether a block with that identifier is at most that many voxels away
+    pub near: F,
+}
+
+// Every voxel at most within away on each axis, not counting the voxel itself
+pub fn neighbors_within(voxel: IVec3, within: u8) -> impl Iterator<Item = IVec3> {
+    let within = within as i32;
+    (-within..=within)
+        .flat_map(move |x| {
+            (-within..=within)
+                .flat_map(move |y| (-within..=within).map(move |z| IVec3::new(x, y, z)))
+        })
+        .filter(|offset| *offset != IVec3::ZERO)
+        .map(move |offset| voxel + offset)
+}
+
+// Auto geometry variants carry their base block's rules, a slab turns into the target's slab
+// and never into a full block. None when the target doesn't come in that shape
+fn variant_target(identifier: &str, target: &str, block_table: &BlockTable) -> Option<String> {
+    let target = match identifier.split_once('.') {
+        Some((_, geo)) => format!("{target}.{geo}"),
+        None => target.to_string(),
+    };
+    block_table.contains_key(&target).then_some(target)
+}
+
+// Every block's rules with their targets resolved, only built from content that validated
+#[derive(Resource, Debug, Clone, Default)]
+pub struct TransitionTable(FxHashMap<String, Vec<TransitionRule>>);
+
+impl TransitionTable {
+    pub fn new(block_table: &BlockTable) -> Result<Self, TransitionError> {
+        let mut identifiers: Vec<&String> = block_table.keys().collect();
+        // Sorted so the same broken content always names the same block
+        identifiers.sort();
+        let mut rules = FxHashMap::default();
+        for identifier in identifiers {
+            let Some(transitions) = &block_table[identifier].transitions else {
+                continue;
+            };
+            let mut resolved = Vec::new();
+            for rule in transitions {
+                let unresolved = |reference: &str| TransitionError::Unresolved {
+                    block: identifier.clone(),
+                    reference: reference.to_string(),
+                };
+                if !block_table.contains_key(&rule.target) {
+                    return Err(unresolved(&rule.target));
+                }
+                if let TransitionTrigger::NeighborIs { block, within } = &rule.trigger {
+                    if !block_table.contains_key(block) {
+                        return Err(unresolved(block));
+                    }
+                    if *within > MAX_NEIGHBOR_DISTANCE {
+                        return Err(TransitionError::TooFar {
+                            block: identifier.clone(),
+                            within: *within,
+                        });
+                    }
+                }
+                if let Some(target) = variant_target(identifier, &rule.target, block_table) {
+                    resolved.push(TransitionRule {
+                        target,
+                        trigger: rule.trigger.clone(),
+                    });
+                }
+            }
+            if !resolved.is_empty() {
+                rules.insert(identifier.clone(), resolved);
+            }
+        }
+        let table = Self(rules);
+        match table.find_cycle() {
+            Some(path) => Err(TransitionError::Cycle(path)),
+            None => Ok(table),
+        }
+    }
+
+    // A block that changes back into itself would flip forever, chains that end are fine. So
+    // is a pair that turns into each other on different kinds of trigger, like dirt getting wet
+    // next to water and drying out on random ticks
+    fn find_cycle(&self) -> Option<Vec<String>> {
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        enum Mark {
+            Visiting,
+            Done,
+        }
+
+        fn visit<'a>(
+            rules: &'a FxHashMap<String, Vec<TransitionRule>>,
+            identifier: &'a str,
+            marks: &mut HashMap<&'a str, Mark>,
+            stack: &mut Vec<&'a str>,
+        ) -> Option<Vec<String>> {
+            match marks.get(identifier) {
+                Some(Mark::Done) => return None,
+                Some(Mark::Visiting) => {
+                    let start = stack
+                        .iter()
+                        .position(|block| *block == identifier)
+                        .unwrap_or(0);
+                    let mut path: Vec<String> = stack[start..]
+                        .iter()
+                        .map(|block| block.to_string())
+                        .collect();
+                    path.push(identifier.to_string());
+                    return Some(path);
+                }
+                None => {}
+            }
+            marks.insert(identifier, Mark::Visiting);
+            stack.push(identifier);
+            for rule in rules.get(identifier).into_iter().flatten() {
+                let back = stack.len() >= 2 && stack[stack.len() - 2] == rule.target;
+                if back && settles(rules, identifier, &rule.target) {
+                    continue;
+                }
+                if let Some(path) = visit(rules, &rule.target, marks, stack) {
+                    return Some(path);
+                }
+            }
+            stack.pop();
+            marks.insert(identifier, Mark::Done);
+            None
+        }
+
+        fn settles(rules: &FxHashMap<String, Vec<TransitionRule>>, from: &str, to: &str) -> bool {
+            let triggers = |from: &str, to: &str| -> Vec<Discriminant<TransitionTrigger>> {
+                rules
+                    .get(from)
+                    .into_iter()
+                    .flatten()
+                    .filter(|rule| rule.target == to)
+                    .map(|rule| mem::discriminant(&rule.trigger))
+                    .collect()
+            };
+            let back = triggers(to, from);
+            triggers(from, to)
+                .iter()
+                .all(|trigger| !back.contains(trigger))
+        }
+
+        let mut identifiers: Vec<&String> = self.0.keys().collect();
+        identifiers.sort();
+        let mut marks = HashMap::new();
+        identifiers
+            .into_iter()
+            .find_map(|identifier| visit(&self.0, identifier, &mut marks, &mut Vec::new()))
+    }
+
+    pub fn rules(&self, identifier: &str) -> &[TransitionRule] {
+        self.0.get(identifier).map_or(&[][..], Vec::as_slice)
+    }
+
+    fn counts_time(&self, identifier: &str) -> bool {
+        self.rules(identifier)
+            .iter()
+            .any(|rule| matches!(rule.trigger, TransitionTrigger::TimeElapsed { .. }))
+    }
+
+    // Starts the clock TimeElapsed goes by, only for blocks that have one so placing anything
+    // else doesn't give every block its own palette entry
+    pub fn stamp(&self, block: &mut BlockData, tick: ServerTick) -> bool {
+        let identifier = name_to_identifier(block.namespace.clone(), block.name.clone());
+        if !self.counts_time(&identifier) {
+            return false;
+        }
+        block.last_tick = Some(*tick);
+        true
+    }
+
+    // Blocks from generation or from before this existed, their clock starts when first seen
+    pub fn needs_stamp(&self, block: &BlockData) -> bool {
+        block.last_tick.is_none()
+            && self.counts_time(&name_to_identifier(
+                block.namespace.clone(),
+                block.name.clone(),
+            ))
+    }
+
+    // What the block turns into right now, if anything. RandomTick rules each roll once in
+    // order until one fires, so the rolls depend only on the stream and the block
+    pub fn transition<F: Fn(&str, u8) -> bool>(
+        &self,
+        block: &BlockData,
+        cause: TransitionCause,
+        surroundings: &Surroundings<F>,
+        rng: &mut impl RngCore,
+        block_table: &BlockTable,
+    ) -> Option<BlockData> {
+        let identifier = name_to_identifier(block.namespace.clone(), block.name.clone());
+        let rule = self
+            .rules(&identifier)
+            .iter()
+            .find(|rule| match (&rule.trigger, cause) {
+                (TransitionTrigger::NeighborIs { block, within }, _) => {
+                    (surroundings.near)(block, *within)
+                }
+                (_, TransitionCause::NeighborUpdate) => false,
+                (TransitionTrigger::RandomTick { one_in }, _) => rng.gen_ratio(1, (*one_in).max(1)),
+                (TransitionTrigger::TimeElapsed { ticks }, _) => block
+                    .last_tick
+                    .is_some_and(|last_tick| surroundings.tick.saturating_sub(last_tick) >= *ticks),
+                (TransitionTrigger::LightAbove { level }, _) => surroundings.light > *level,
+                (TransitionTrigger::LightBelow { level }, _) => surroundings.light < *level,
+            })?;
+        Some(self.transitioned(block, &rule.target, surroundings.tick, block_table))
+    }
+
+    // Keeps which way it faces when the target can face somewhere too, anything else the old
+    // block held is gone
+    fn transitioned(
+        &self,
+        block: &BlockData,
+        target: &str,
+        tick: ServerTick,
+        block_table: &BlockTable,
+    ) -> BlockData {
+        let (namespace, name) = identifier_to_name(target.to_string()).unwrap_or_default();
+        let mut next = BlockData::new(namespace, name);
+        let has_direction = block_table
+            .get(target)
+            .and_then(|descriptor| descriptor.has_direction)
+            .unwrap_or(false);
+        if has_direction {
+            next.direction = block.direction;
+            next.top = block.top;
+        }
+        self.stamp(&mut next, tick);
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ecs::rng::WorldRng,
+        storage::blocks::descriptor::{BlockDescriptor, BlockGeometry},
+        testing::{self, block, descriptor},
+        world::chunks::{
+            positions::DimensionId,
+            storage::{Direction, VoxelVisibility},
+        },
+    };
+
+    fn rule(target: &str, trigger: TransitionTrigger) -> TransitionRule {
+        TransitionRule {
+            target: format!("vinox:{target}"),
+            trigger,
+        }
+    }
+
+    fn block_table(blocks: Vec<(&str, Vec<TransitionRule>)>) -> BlockTable {
+        testing::block_table(
+            blocks
+                .into_iter()
+                .map(|(name, transitions)| BlockDescriptor {
+                    has_direction: Some(name.contains("stone")),
+                    transitions: (!transitions.is_empty()).then_some(transitions),
+                    ..descriptor(name, VoxelVisibility::Opaque)
+                }),
+        )
+    }
+
+    // For rules that don't roll, a fresh stream every time is as good as any
+    fn settle<F: Fn(&str, u8) -> bool>(
+        table: &TransitionTable,
+        block: &BlockData,
+        cause: TransitionCause,
+        surroundings: &Surroundings<F>,
+        block_table: &BlockTable,
+    ) -> Option<BlockData> {
+        let mut rng = WorldRng::new(1).stream("random_tick");
+        table.transition(block, cause, surroundings, &mut rng, block_table)
+    }
+
+    fn surroundings(tick: u64, light: u8) -> Surroundings<impl Fn(&str, u8) -> bool> {
+        Surroundings {
+            tick: ServerTick(tick),
+            light,
+            near: |_: &str, _: u8| false,
+        }
+    }
+
+    #[test]
+    fn random_ticks_roll_on_the_world_stream() {
+        let block_table = block_table(vec![
+            (
+                "dirt",
+                vec![rule("sand", TransitionTrigger::RandomTick { one_in: 4 })],
+            ),
+            ("sand", vec![]),
+        ]);
+        let table = TransitionTable::new(&block_table).unwrap();
+        let rng = WorldRng::new(42);
+        let roll = |tick: u64| {
+            let mut stream =
+                rng.chunk_tick_stream("random_tick", DimensionId(0), IVec3::ZERO, ServerTick(tick));
+            table.transition(
+                &block("dirt"),
+                TransitionCause::RandomTick,
+                &surroundings(tick, 0),
+                &mut stream,
+                &block_table,
+            )
+        };
+        let fired: Vec<u64> = (0..1000).filter(|tick| roll(*tick).is_some()).collect();
+        assert!((200..300).contains(&fired.len()));
+        // Same seed and tick, same outcome
+        assert_eq!(
+            fired,
+            (0..1000)
+                .filter(|tick| roll(*tick).is_some())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(roll(fired[0]), Some(block("sand")));
+
+        // Only NeighborIs rules listen to neighbor updates
+        let mut stream = rng.stream("random_tick");
+        assert_eq!(
+            table.transition(
+                &block("dirt"),
+                TransitionCause::NeighborUpdate,
+                &surroundings(fired[0], 0),
+                &mut stream,
+                &block_table,
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn neighbors_time_and_light_trigger_their_rules() {
+        let block_table = block_table(vec![
+            (
+                "dirt",
+                vec![rule(
+                    "mud",
+                    TransitionTrigger::NeighborIs {
+                        block: "vinox:water".to_string(),
+                        within: 2,
+                    },
+                )],
+            ),
+            (
+                "mud",
+                vec![rule("clay", TransitionTrigger::TimeElapsed { ticks: 100 })],
+            ),
+            (
+                "ice",
+                vec![rule("water", TransitionTrigger::LightAbove { level: 11 })],
+            ),
+            (
+                "water",
+                vec![rule("ice", TransitionTrigger::LightBelow { level: 2 })],
+            ),
+            ("clay", vec![]),
+        ]);
+        // Ice and water flip on light, which only ever holds one way round at a time
+        assert!(matches!(
+            TransitionTable::new(&block_table),
+            Err(TransitionError::Cycle(_))
+        ));
+        let mut block_table = block_table;
+        block_table.get_mut("vinox:water").unwrap().transitions = None;
+        let table = TransitionTable::new(&block_table).unwrap();
+
+        let water_at = |distance: u8| Surroundings {
+            tick: ServerTick(0),
+            light: 0,
+            near: move |block: &str, within: u8| block == "vinox:water" && distance <= within,
+        };
+        assert_eq!(
+            settle(
+                &table,
+                &block("dirt"),
+                TransitionCause::NeighborUpdate,
+                &water_at(3),
+                &block_table
+            ),
+            None
+        );
+        let mud = settle(
+            &table,
+            &block("dirt"),
+            TransitionCause::NeighborUpdate,
+            &water_at(2),
+            &block_table,
+        )
+        .unwrap();
+        // Turning into something with a clock starts it
+        assert_eq!(mud.last_tick, Some(0));
+
+        assert_eq!(
+            settle(
+                &table,
+                &mud,
+                TransitionCause::RandomTick,
+                &surroundings(99, 0),
+                &block_table
+            ),
+            None
+        );
+        assert_eq!(
+            settle(
+                &table,
+                &mud,
+                TransitionCause::RandomTick,
+                &surroundings(100, 0),
+                &block_table
+            ),
+            Some(block("clay"))
+        );
+        // Never stamped, so it never counts as elapsed until it is
+        let mut unstamped = block("mud");
+        assert!(table.needs_stamp(&unstamped));
+        assert_eq!(
+            settle(
+                &table,
+                &unstamped,
+                TransitionCause::RandomTick,
+                &surroundings(500, 0),
+                &block_table
+            ),
+            None
+        );
+        assert!(table.stamp(&mut unstamped, ServerTick(500)));
+        assert!(!table.needs_stamp(&unstamped));
+        assert!(!table.stamp(&mut block("dirt"), ServerTick(500)));
+
+        assert_eq!(
+            settle(
+                &table,
+                &block("ice"),
+                TransitionCause::RandomTick,
+                &surroundings(0, 11),
+                &block_table
+            ),
+            None
+        );
+        assert_eq!(
+            settle(
+                &table,
+                &block("ice"),
+                TransitionCause::RandomTick,
+                &surroundings(0, 12),
+                &block_table
+            ),
+            Some(block("water"))
+        );
+    }
+
+    #[test]
+    fn facing_and_shape_survive_the_transition() {
+        let mut block_table = block_table(vec![
+            (
+                "cobblestone",
+                vec![rule(
+                    "mossy_stone",
+                    TransitionTrigger::RandomTick { one_in: 1 },
+                )],
+            ),
+            ("mossy_stone", vec![]),
+            (
+                "dirt",
+                vec![rule("grass", TransitionTrigger::RandomTick { one_in: 1 })],
+            ),
+            ("grass", vec![]),
+        ]);
+        // What load_all_blocks makes out of auto_geo
+        for name in ["cobblestone", "mossy_stone"] {
+            let mut slab = block_table[&format!("vinox:{name}")].clone();
+            slab.name = BlockGeometry::Slab.geo_new_block(name.to_string());
+            block_table.insert(format!("vinox:{}", slab.name), slab);
+        }
+        let mut stair = block_table["vinox:cobblestone"].clone();
+        stair.name = BlockGeometry::Stairs.geo_new_block("cobblestone".to_string());
+        block_table.insert(format!("vinox:{}", stair.name), stair);
+        let table = TransitionTable::new(&block_table).unwrap();
+        let mut rng = WorldRng::new(1).stream("random_tick");
+        let mut next = |block: &BlockData| {
+            table.transition(
+                block,
+                TransitionCause::RandomTick,
+                &surroundings(0, 0),
+                &mut rng,
+                &block_table,
+            )
+        };
+
+        let slab = BlockData {
+            direction: Some(Direction::East),
+            top: Some(true),
+            ..block("cobblestone.slab")
+        };
+        let mossy = next(&slab).unwrap();
+        assert_eq!(mossy.name, "mossy_stone.slab");
+        assert_eq!(
+            (mossy.direction, mossy.top),
+            (Some(Direction::East), Some(true))
+        );
+        // There's no mossy stair to turn into
+        assert_eq!(next(&block("cobblestone.stair")), None);
+        // Grass can't face anywhere
+        let dirt = BlockData {
+            direction: Some(Direction::West),
+            ..block("dirt")
+        };
+        assert_eq!(next(&dirt), Some(block("grass")));
+    }
+
+    #[test]
+    fn chains_work_and_cycles_are_rejected() {
+        let always = || TransitionTrigger::RandomTick { one_in: 1 };
+        let chain = block_table(vec![
+            ("a", vec![rule("b", always())]),
+            ("b", vec![rule("c", always())]),
+            ("c", vec![]),
+        ]);
+        let table = TransitionTable::new(&chain).unwrap();
+        let mut rng = WorldRng::new(1).stream("random_tick");
+        let mut current = block("a");
+        while let Some(next) = table.transition(
+            &current,
+            TransitionCause::RandomTick,
+            &surroundings(0, 0),
+            &mut rng,
+            &chain,
+        ) {
+            current = next;
+        }
+        assert_eq!(current, block("c"));
+
+        let cycle = block_table(vec![
+            ("a", vec![rule("b", always())]),
+            ("b", vec![rule("c", always())]),
+            ("c", vec![rule("z", always()), rule("a", always())]),
+            ("z", vec![]),
+        ]);
+        let error = TransitionTable::new(&cycle).unwrap_err();
+        assert_eq!(
+            error,
+            TransitionError::Cycle(
+                ["a", "b", "c", "a"]
+                    .iter()
+                    .map(|name| format!("vinox:{name}"))
+                    .collect()
+            )
+        );
+        assert_eq!(
+            error.to_string(),
+            "vinox:a turns back into itself: vinox:a -> vinox:b -> vinox:c -> vinox:a"
+        );
+        // Wet next to water and drying out on its own settles, drying out both ways doesn't
+        let water = || TransitionTrigger::NeighborIs {
+            block: "vinox:water".to_string(),
+            within: 1,
+        };
+        let pair = block_table(vec![
+            ("dirt", vec![rule("wet_dirt", water())]),
+            ("wet_dirt", vec![rule("dirt", always())]),
+            ("water", vec![]),
+        ]);
+        let table = TransitionTable::new(&pair).unwrap();
+        assert_eq!(table.rules("vinox:wet_dirt").len(), 1);
+        let flip = block_table(vec![
+            ("dirt", vec![rule("wet_dirt", always())]),
+            ("wet_dirt", vec![rule("dirt", always())]),
+        ]);
+        assert!(matches!(
+            TransitionTable::new(&flip),
+            Err(TransitionError::Cycle(_))
+        ));
+        // The pair doesn't hide a longer way round
+        let around = block_table(vec![
+            ("dirt", vec![rule("wet_dirt", water())]),
+            (
+                "wet_dirt",
+                vec![rule("dirt", always()), rule("mud", always())],
+            ),
+            ("mud", vec![rule("dirt", always())]),
+            ("water", vec![]),
+        ]);
+        assert!(matches!(
+            TransitionTable::new(&around),
+            Err(TransitionError::Cycle(_))
+        ));
+        let itself = block_table(vec![("a", vec![rule("a", always())])]);
+        assert!(matches!(
+            TransitionTable::new(&itself),
+            Err(TransitionError::Cycle(_))
+        ));
+
+        let unknown = block_table(vec![("a", vec![rule("nothing", always())])]);
+        assert_eq!(
+            TransitionTable::new(&unknown).unwrap_err(),
+            TransitionError::Unresolved {
+                block: "vinox:a".to_string(),
+                reference: "vinox:nothing".to_string()
+            }
+        );
+        let too_far = block_table(vec![
+            (
+                "a",
+                vec![rule(
+                    "b",
+                    TransitionTrigger::NeighborIs {
+                        block: "vinox:b".to_string(),
+                        within: MAX_NEIGHBOR_DISTANCE + 1,
+                    },
+                )],
+            ),
+            ("b", vec![]),
+        ]);
+        assert!(matches!(
+            TransitionTable::new(&too_far),
+            Err(TransitionError::TooFar { .. })
+        ));
+    }
+}
@@ -0,0 +1,556 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{error::TrySendError, Receiver, Sender};
+
+use super::{
+    networking::commands::{ChatCommandEvent, CommandSender},
+    world::edits::now_secs,
+};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+// Events waiting on the writer before new ones get dropped instead
+pub const AUDIT_QUEUE: usize = 4096;
+
+// Which kinds of event get written, a busy creative server might only want commands and
+// moderation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditCategories {
+    pub block_edits: bool,
+    pub containers: bool,
+    pub commands: bool,
+    // Joins and leaves
+    pub sessions: bool,
+    // Rollbacks
+    pub moderation: bool,
+}
+
+impl Default for AuditCategories {
+    fn default() -> Self {
+        Self {
+            block_edits: true,
+            containers: true,
+            commands: true,
+            sessions: true,
+            moderation: true,
+        }
+    }
+}
+
+// audit.ron next to the world files, shared by every world in the folder like schedule.ron.
+// Only read at startup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    // Day files older than this are deleted whenever a new day starts, 0 keeps them all
+    pub retention_days: u32,
+    pub categories: AuditCategories,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_days: 30,
+            categories: AuditCategories::default(),
+        }
+    }
+}
+
+impl AuditConfig {
+    // Written out once when missing so owners can see what there is to turn on
+    pub fn load(path: &Path) -> Self {
+        let Ok(text) = fs::read_to_string(path) else {
+            let config = AuditConfig::default();
+            let pretty = ron::ser::PrettyConfig::new();
+            if let Ok(text) = ron::ser::to_string_pretty(&config, pretty) {
+                if let Err(e) = fs::write(path, text) {
+                    println!("Couldn't write {}: {e}", path.display());
+                }
+            }
+            return config;
+        };
+        ron::from_str(&text).unwrap_or_else(|e| {
+            println!(
+                "Not writing an audit log, {} isn't valid: {e}",
+                path.display()
+            );
+            AuditConfig::default()
+        })
+    }
+
+    pub fn allows(&self, event: &AuditEvent) -> bool {
+        let categories = &self.categories;
+        self.enabled
+            && match event {
+                AuditEvent::BlockEdit(_) => categories.block_edits,
+                AuditEvent::Container(_) => categories.containers,
+                AuditEvent::Command(_) => categories.commands,
+                AuditEvent::Join(_) | AuditEvent::Leave(_) => categories.sessions,
+                AuditEvent::Rollback(_) => categories.moderation,
+            }
+    }
+}
+
+// One line of an audit file, `kind` says which of the entries below the rest of it is. Tools
+// read these files, so fields only ever get added to an entry, never renamed or taken away.
+// Players are always by storage key, what they were shown as is in `name` where there is one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    // Unix seconds, the file it's in is the UTC day of this
+    pub time: u64,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEvent {
+    BlockEdit(BlockEditEntry),
+    Container(ContainerEntry),
+    Command(CommandEntry),
+    Join(JoinEntry),
+    Leave(LeaveEntry),
+    Rollback(RollbackEntry),
+}
+
+// An edit the server accepted, denied ones never change anything
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockEditEntry {
+    pub actor: String,
+    pub dimension: u16,
+    pub position: [i32; 3],
+    pub old: String,
+    pub new: String,
+    pub tick: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerAction {
+    Insert,
+    Take,
+}
+
+// Display frames are the only blocks that hold items so far
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerEntry {
+    pub actor: String,
+    pub dimension: u16,
+    pub position: [i32; 3],
+    pub action: ContainerAction,
+    pub item: String,
+}
+
+// Whatever was typed, allowed or not. The console's actor is "Console"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandEntry {
+    pub actor: String,
+    pub name: String,
+    pub console: bool,
+    pub command: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinEntry {
+    pub player: String,
+    pub name: String,
+    pub client: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaveReason {
+    Left,
+    ConnectionLost,
+    // Someone joined with the name while this session was stale
+    Replaced,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaveEntry {
+    pub player: String,
+    pub name: String,
+    pub client: u64,
+    pub reason: LeaveReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RollbackEntry {
+    pub moderator: String,
+    pub target: String,
+    // Unix seconds the rollback went back to
+    pub since: u64,
+    pub dimension: u16,
+    // Blocks around the moderator, None for everywhere
+    pub radius: Option<f32>,
+    pub reverted: usize,
+    pub conflicts: usize,
+}
+
+impl AuditEvent {
+    // Everyone the line is about, what --audit-grep matches on
+    pub fn players(&self) -> Vec<&str> {
+        match self {
+            AuditEvent::BlockEdit(entry) => vec![&entry.actor],
+            AuditEvent::Container(entry) => vec![&entry.actor],
+            AuditEvent::Command(entry) => vec![&entry.actor, &entry.name],
+            AuditEvent::Join(entry) => vec![&entry.player, &entry.name],
+            AuditEvent::Leave(entry) => vec![&entry.player, &entry.name],
+            AuditEvent::Rollback(entry) => vec![&entry.moderator, &entry.target],
+        }
+    }
+}
+
+// Days since the unix epoch from a UTC date and back, for the file names
+pub fn day_to_date(day: u64) -> String {
+    let z = day as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + (m <= 2) as i64;
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+pub fn date_to_day(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let y: i64 = parts.next()?.parse().ok()?;
+    let m: i64 = parts.next()?.parse().ok()?;
+    let d: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    u64::try_from(era * 146_097 + doe - 719_468).ok()
+}
+
+// Lives on its own thread, the only thing that touches the files
+pub struct AuditWriter {
+    dir: PathBuf,
+    retention_days: u32,
+    // The day the open file is for
+    file: Option<(u64, fs::File)>,
+}
+
+impl AuditWriter {
+    pub fn new(dir: PathBuf, retention_days: u32) -> Self {
+        Self {
+            dir,
+            retention_days,
+            file: None,
+        }
+    }
+
+    // Goes by the record's time rather than the clock, so one written just before midnight
+    // but only picked up after still lands in the day it happened
+    pub fn write(&mut self, record: &AuditRecord) -> io::Result<()> {
+        let day = record.time / SECS_PER_DAY;
+        let (_, file) = match self.file.take() {
+            Some((open, file)) if open == day => self.file.insert((open, file)),
+            _ => {
+                fs::create_dir_all(&self.dir)?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.dir.join(format!("{}.jsonl", day_to_date(day))))?;
+                if let Err(e) = self.prune(day) {
+                    println!("Couldn't clear out old audit logs: {e}");
+                }
+                self.file.insert((day, file))
+            }
+        };
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())
+    }
+
+    // Deletes day files more than retention_days before today, anything else in the folder
+    // is left alone
+    pub fn prune(&self, today: u64) -> io::Result<usize> {
+        if self.retention_days == 0 {
+            return Ok(0);
+        }
+        let cutoff = today.saturating_sub(self.retention_days as u64);
+        let mut deleted = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let day = path
+                .file_name()
+                .and_then(|name| name.to_str()?.strip_suffix(".jsonl"))
+                .and_then(date_to_day);
+            if day.is_some_and(|day| day < cutoff) {
+                fs::remove_file(&path)?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    pub fn run(mut self, mut rx: Receiver<AuditRecord>) {
+        while let Some(record) = rx.blocking_recv() {
+            if let Err(e) = self.write(&record) {
+                println!("Couldn't write to the audit log: {e}");
+            }
+        }
+    }
+}
+
+// Only the server binary sets this up, it stays off for the server embedded in the client
+#[derive(Resource)]
+pub struct AuditLog {
+    config: AuditConfig,
+    tx: Option<Sender<AuditRecord>>,
+    // Thrown away because the writer fell behind, shown by /status
+    pub dropped: u64,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig, tx: Option<Sender<AuditRecord>>) -> Self {
+        Self {
+            config,
+            tx,
+            dropped: 0,
+        }
+    }
+
+    // The writer thread is never joined, whatever it hasn't written when the server exits is lost
+    pub fn start(dir: PathBuf, config: AuditConfig) -> Self {
+        if !config.enabled {
+            return Self::new(config, None);
+        }
+        let (tx, rx) = tokio::sync::mpsc::channel(AUDIT_QUEUE);
+        let writer = AuditWriter::new(dir, config.retention_days);
+        std::thread::spawn(move || writer.run(rx));
+        Self::new(config, Some(tx))
+    }
+
+    // Never waits on the writer, a full queue costs the event instead of the tick
+    pub fn record(&mut self, record: AuditRecord) {
+        if !self.config.allows(&record.event) {
+            return;
+        }
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        match tx.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    println!("The audit log can't keep up, dropping events until it does");
+                }
+                self.dropped += 1;
+            }
+            Err(TrySendError::Closed(_)) => {
+                println!("The audit log writer stopped, nothing more will be written");
+                self.tx = None;
+            }
+        }
+    }
+}
+
+pub fn record_audit(
+    mut audit: ResMut<AuditLog>,
+    mut events: EventReader<AuditEvent>,
+    mut commands: EventReader<ChatCommandEvent>,
+) {
+    let time = now_secs();
+    for evt in commands.iter() {
+        audit.record(AuditRecord {
+            time,
+            event: AuditEvent::Command(CommandEntry {
+                actor: evt.storage_key.clone(),
+                name: evt.user_name.clone(),
+                console: evt.sender == CommandSender::Console,
+                command: evt.command.clone(),
+            }),
+        });
+    }
+    for event in events.iter() {
+        audit.record(AuditRecord {
+            time,
+            event: event.clone(),
+        });
+    }
+}
+
+// `from..to` with both ends included, or a single date
+fn date_range(range: &str) -> Option<(u64, u64)> {
+    let (from, to) = range.split_once("..").unwrap_or((range, range));
+    Some((date_to_day(from)?, date_to_day(to)?))
+}
+
+// vinox-server --audit-grep <player> <date-range>. Every line has to read back as a record, so
+// this doubles as a check that the files still match the schema. Returns how many matched
+pub fn audit_grep(
+    dir: &Path,
+    player: &str,
+    range: &str,
+    out: &mut impl Write,
+) -> Result<usize, String> {
+    let (from, to) = date_range(range)
+        .ok_or_else(|| format!("{range} isn't a date like 2023-04-01 or a range of them"))?;
+    let mut matched = 0;
+    for day in from..=to {
+        let path = dir.join(format!("{}.jsonl", day_to_date(day)));
+        let Ok(file) = fs::File::open(&path) else {
+            continue;
+        };
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("Couldn't read {}: {e}", path.display()))?;
+            let record: AuditRecord = serde_json::from_str(&line).map_err(|e| {
+                format!(
+                    "Line {} of {} isn't an audit record: {e}",
+                    index + 1,
+                    path.display()
+                )
+            })?;
+            if record.event.players().contains(&player) {
+                writeln!(out, "{line}").map_err(|e| e.to_string())?;
+                matched += 1;
+            }
+        }
+    }
+    Ok(matched)
+}
+
+pub struct AuditPlugin;
+
+impl Plugin for AuditPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AuditEvent>()
+            .add_system(record_audit.run_if(resource_exists::<AuditLog>()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vinox-audit-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn edit(time: u64, actor: &str) -> AuditRecord {
+        AuditRecord {
+            time,
+            event: AuditEvent::BlockEdit(BlockEditEntry {
+                actor: actor.to_string(),
+                dimension: 0,
+                position: [1, -2, 3],
+                old: "vinox:air".to_string(),
+                new: "vinox:stone".to_string(),
+                tick: 40,
+            }),
+        }
+    }
+
+    #[test]
+    fn days_roll_over_at_utc_midnight() {
+        let dir = temp_dir("rotation");
+        let midnight = date_to_day("2023-03-01").unwrap() * SECS_PER_DAY;
+        assert_eq!(day_to_date(midnight / SECS_PER_DAY), "2023-03-01");
+        assert_eq!(day_to_date(midnight / SECS_PER_DAY - 1), "2023-02-28");
+        let mut writer = AuditWriter::new(dir.clone(), 0);
+        writer.write(&edit(midnight - 1, "before")).unwrap();
+        writer.write(&edit(midnight, "after")).unwrap();
+        writer.write(&edit(midnight + 1, "after")).unwrap();
+
+        let read = |date: &str| fs::read_to_string(dir.join(format!("{date}.jsonl"))).unwrap();
+        let before = read("2023-02-28");
+        let after = read("2023-03-01");
+        assert_eq!(before.lines().count(), 1);
+        assert!(before.contains("\"kind\":\"block_edit\""));
+        assert!(before.contains("\"actor\":\"before\""));
+        assert_eq!(after.lines().count(), 2);
+
+        let mut out = Vec::new();
+        let matched = audit_grep(&dir, "after", "2023-02-28..2023-03-01", &mut out).unwrap();
+        assert_eq!(matched, 2);
+        assert_eq!(String::from_utf8(out).unwrap(), after);
+        assert_eq!(
+            audit_grep(&dir, "before", "2023-03-01", &mut Vec::new()),
+            Ok(0)
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn old_days_are_deleted() {
+        let dir = temp_dir("retention");
+        fs::create_dir_all(&dir).unwrap();
+        for date in ["2023-01-01", "2023-01-07", "2023-01-08"] {
+            fs::write(dir.join(format!("{date}.jsonl")), "").unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        // A week kept, so the 8th is the oldest day that stays
+        let mut writer = AuditWriter::new(dir.clone(), 7);
+        let today = date_to_day("2023-01-15").unwrap();
+        writer
+            .write(&edit(today * SECS_PER_DAY, "someone"))
+            .unwrap();
+
+        let mut left: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            vec!["2023-01-08.jsonl", "2023-01-15.jsonl", "notes.txt"]
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn a_full_queue_drops_instead_of_waiting() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        let config = AuditConfig {
+            enabled: true,
+            categories: AuditCategories {
+                block_edits: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut audit = AuditLog::new(config, Some(tx));
+        let join = |client| AuditRecord {
+            time: 0,
+            event: AuditEvent::Join(JoinEntry {
+                player: "someone".to_string(),
+                name: "someone".to_string(),
+                client,
+            }),
+        };
+        // Filtered out before it gets anywhere near the queue
+        audit.record(edit(0, "builder"));
+        for client in 0..5 {
+            audit.record(join(client));
+        }
+        assert_eq!(audit.dropped, 3);
+        assert_eq!(rx.try_recv().unwrap(), join(0));
+        assert_eq!(rx.try_recv().unwrap(), join(1));
+        assert!(rx.try_recv().is_err());
+
+        // Room again once the writer catches up
+        audit.record(join(5));
+        assert_eq!(rx.try_recv().unwrap(), join(5));
+        assert_eq!(audit.dropped, 3);
+    }
+}
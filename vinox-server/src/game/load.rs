@@ -0,0 +1,371 @@
+use std::time::{Duration, Instant};
+
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
+use bevy_quinnet::server::Server;
+use vinox_common::networking::protocol::{Player, ServerHealth, ServerMessage};
+
+// Same rate FixedUpdate ran at, chunk and entity sending happen this often
+pub const FIXED_STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+pub const DEFAULT_MAX_CATCH_UP: u32 = 3;
+// Further behind than this and owed ticks get thrown away instead of caught up
+pub const DEFAULT_DROP_AFTER: Duration = Duration::from_secs(1);
+// Keeping up for this long steps the shedding back down one level
+pub const DEFAULT_RECOVERY: Duration = Duration::from_secs(2);
+// Skipped ticks are added up and logged at most this often so an overloaded server doesn't
+// also flood its own console
+pub const REPORT_EVERY: Duration = Duration::from_secs(5);
+
+// Runs at FIXED_STEP, as many times a frame as the load policy allows
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ServerFixedUpdate;
+
+// What gets given up when the server can't keep up, in the order it's given up. Each level
+// keeps everything the ones before it shed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Shedding {
+    #[default]
+    None,
+    // Nothing in LoadSet::Ambient runs, critters stop spawning and wandering
+    Ambient,
+    // LoadSet::Stepped only runs every other fixed tick
+    Stepped,
+    // Owed ticks are dropped instead of caught up
+    Dropping,
+}
+
+impl Shedding {
+    fn lower(self) -> Self {
+        match self {
+            Shedding::None | Shedding::Ambient => Shedding::None,
+            Shedding::Stepped => Shedding::Ambient,
+            Shedding::Dropping => Shedding::Stepped,
+        }
+    }
+}
+
+// Systems that get shed under load. Ambient is for anything the world is fine without for a
+// while, Stepped for slow world simulation in ServerFixedUpdate like random ticks. Crops grow
+// from their tick stamps, fluids would belong in Stepped too
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoadSet {
+    Ambient,
+    Stepped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TickPlan {
+    pub run: u32,
+    pub skipped: u32,
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct ServerLoad {
+    pub max_catch_up: u32,
+    pub drop_after: Duration,
+    pub recovery: Duration,
+    pub shedding: Shedding,
+    // Fixed ticks run since start, Stepped goes by this
+    pub ticks: u64,
+    // Everything dropped since start
+    pub skipped_ticks: u64,
+    // Rolling average of how long one fixed tick takes to run
+    pub tick_time: Duration,
+    // Real time the fixed ticks haven't covered yet
+    owed: Duration,
+    calm: Duration,
+    unreported: u64,
+    since_report: Duration,
+}
+
+impl Default for ServerLoad {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CATCH_UP)
+    }
+}
+
+impl ServerLoad {
+    pub fn new(max_catch_up: u32) -> Self {
+        Self {
+            max_catch_up: max_catch_up.max(1),
+            drop_after: DEFAULT_DROP_AFTER,
+            recovery: DEFAULT_RECOVERY,
+            shedding: Shedding::None,
+            ticks: 0,
+            skipped_ticks: 0,
+            tick_time: Duration::ZERO,
+            owed: Duration::ZERO,
+            calm: Duration::ZERO,
+            unreported: 0,
+            since_report: REPORT_EVERY,
+        }
+    }
+
+    pub fn health(&self) -> ServerHealth {
+        match self.shedding {
+            Shedding::None => ServerHealth::Healthy,
+            Shedding::Ambient | Shedding::Stepped => ServerHealth::Degraded,
+            Shedding::Dropping => ServerHealth::Overloaded,
+        }
+    }
+
+    pub fn record_tick(&mut self, took: Duration) {
+        self.tick_time = self.tick_time.mul_f32(0.9) + took.mul_f32(0.1);
+    }
+
+    // Whole ticks still owed after this frame's catch up
+    pub fn behind(&self) -> Duration {
+        FIXED_STEP * (self.owed.as_nanos() / FIXED_STEP.as_nanos()) as u32
+    }
+
+    // Decides how many fixed ticks this frame gets and escalates or recovers the shedding
+    pub fn plan(&mut self, real_delta: Duration) -> TickPlan {
+        self.owed += real_delta;
+        self.since_report += real_delta;
+        let owed_ticks = (self.owed.as_nanos() / FIXED_STEP.as_nanos()) as u32;
+        let run = owed_ticks.min(self.max_catch_up);
+        self.owed -= FIXED_STEP * run;
+        let behind = owed_ticks - run;
+
+        let needed = if behind == 0 {
+            Shedding::None
+        } else if self.owed >= self.drop_after {
+            Shedding::Dropping
+        } else if behind >= self.max_catch_up {
+            Shedding::Stepped
+        } else {
+            Shedding::Ambient
+        };
+        if needed == Shedding::None {
+            self.calm += real_delta;
+            if self.calm >= self.recovery && self.shedding != Shedding::None {
+                self.shedding = self.shedding.lower();
+                self.calm = Duration::ZERO;
+            }
+        } else {
+            self.calm = Duration::ZERO;
+            self.shedding = self.shedding.max(needed);
+        }
+
+        let skipped = if self.shedding == Shedding::Dropping {
+            behind
+        } else {
+            0
+        };
+        self.owed -= FIXED_STEP * skipped;
+        self.skipped_ticks += skipped as u64;
+        self.unreported += skipped as u64;
+        TickPlan { run, skipped }
+    }
+
+    // The warning for whatever was skipped since the last one, if it's been long enough
+    pub fn report(&mut self) -> Option<String> {
+        if self.unreported == 0 || self.since_report < REPORT_EVERY {
+            return None;
+        }
+        let skipped = std::mem::take(&mut self.unreported);
+        self.since_report = Duration::ZERO;
+        Some(format!(
+            "Server is running {:.1} seconds behind, skipped {skipped} ticks",
+            (FIXED_STEP * skipped as u32).as_secs_f32()
+        ))
+    }
+}
+
+pub fn ambient_allowed(load: Res<ServerLoad>) -> bool {
+    load.shedding < Shedding::Ambient
+}
+
+pub fn stepped_allowed(load: Res<ServerLoad>) -> bool {
+    load.shedding < Shedding::Stepped || load.ticks.is_multiple_of(2)
+}
+
+// Stands in for bevy's fixed timestep runner, which keeps running ticks until it has caught up
+// no matter how long that makes the frame
+pub fn run_server_ticks(world: &mut World) {
+    let delta = world.resource::<Time>().delta();
+    let mut load = world.resource_mut::<ServerLoad>();
+    let plan = load.plan(delta);
+    if let Some(warning) = load.report() {
+        println!("{warning}");
+    }
+    for _ in 0..plan.run {
+        let start = Instant::now();
+        world.run_schedule(ServerFixedUpdate);
+        let mut load = world.resource_mut::<ServerLoad>();
+        load.ticks += 1;
+        load.record_tick(start.elapsed());
+    }
+}
+
+// Tells everyone when the health changes and anyone joining what it is now
+pub fn announce_load(
+    load: Res<ServerLoad>,
+    mut server: ResMut<Server>,
+    joined: Query<&Player, Added<Player>>,
+    mut last: Local<ServerHealth>,
+) {
+    let health = load.health();
+    let Some(endpoint) = server.get_endpoint_mut() else {
+        *last = health;
+        return;
+    };
+    if health != *last {
+        *last = health;
+        println!("Server health is now {health:?}");
+        endpoint.try_broadcast_message(ServerMessage::ServerLoad { health });
+        return;
+    }
+    if health == ServerHealth::Healthy {
+        return;
+    }
+    for player in joined.iter() {
+        endpoint.try_send_message(player.id, ServerMessage::ServerLoad { health });
+    }
+}
+
+pub struct LoadPlugin;
+
+impl Plugin for LoadPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerLoad>()
+            .init_schedule(ServerFixedUpdate)
+            .edit_schedule(ServerFixedUpdate, |schedule| {
+                schedule.configure_set(LoadSet::Stepped.run_if(stepped_allowed));
+            })
+            .configure_set(LoadSet::Ambient.run_if(ambient_allowed))
+            .add_system(run_server_ticks.in_base_set(CoreSet::FixedUpdate))
+            .add_system(announce_load);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::time::TimePlugin;
+
+    fn frames(load: &mut ServerLoad, delta: Duration, count: u32) -> Vec<TickPlan> {
+        (0..count).map(|_| load.plan(delta)).collect()
+    }
+
+    #[test]
+    fn shedding_escalates_in_order_and_recovers() {
+        let mut load = ServerLoad::default();
+        // One tick a frame keeps up fine
+        assert!(frames(&mut load, FIXED_STEP, 10)
+            .iter()
+            .all(|plan| *plan == TickPlan { run: 1, skipped: 0 }));
+        assert_eq!(load.health(), ServerHealth::Healthy);
+
+        // Four ticks owed a frame, one more than the cap, so debt builds up a tick at a time
+        let slow = FIXED_STEP * 4;
+        let mut seen = vec![load.shedding];
+        for _ in 0..70 {
+            assert_eq!(load.plan(slow).run, DEFAULT_MAX_CATCH_UP);
+            if *seen.last().unwrap() != load.shedding {
+                seen.push(load.shedding);
+            }
+        }
+        assert_eq!(
+            seen,
+            vec![
+                Shedding::None,
+                Shedding::Ambient,
+                Shedding::Stepped,
+                Shedding::Dropping
+            ]
+        );
+        assert_eq!(load.health(), ServerHealth::Overloaded);
+        assert!(load.skipped_ticks > 0);
+        // Dropping throws the debt away instead of carrying it
+        assert_eq!(load.behind(), Duration::ZERO);
+        let warning = load.report().unwrap();
+        assert!(warning.contains(&format!("skipped {} ticks", load.skipped_ticks)));
+        assert_eq!(load.report(), None);
+
+        // Back to normal frames, one level down per recovery period
+        let per_level = (DEFAULT_RECOVERY.as_nanos() / FIXED_STEP.as_nanos()) as u32 + 1;
+        frames(&mut load, FIXED_STEP, per_level);
+        assert_eq!(load.shedding, Shedding::Stepped);
+        assert_eq!(load.health(), ServerHealth::Degraded);
+        frames(&mut load, FIXED_STEP, per_level);
+        assert_eq!(load.shedding, Shedding::Ambient);
+        frames(&mut load, FIXED_STEP, per_level);
+        assert_eq!(load.health(), ServerHealth::Healthy);
+
+        // A single stall goes straight to dropping and never runs more than the cap
+        let plan = load.plan(Duration::from_secs(3));
+        assert_eq!(plan.run, DEFAULT_MAX_CATCH_UP);
+        assert_eq!(plan.run + plan.skipped, 180);
+        assert_eq!(load.health(), ServerHealth::Overloaded);
+    }
+
+    #[derive(Resource, Default)]
+    struct Counts {
+        fixed: u32,
+        ambient: u32,
+        stepped: u32,
+    }
+
+    #[derive(Resource)]
+    struct Stall(Duration);
+
+    fn stall(stall: Res<Stall>, mut counts: ResMut<Counts>) {
+        counts.fixed += 1;
+        std::thread::sleep(stall.0);
+    }
+
+    #[test]
+    fn slow_ticks_are_capped() {
+        let mut app = App::new();
+        app.add_plugin(TimePlugin)
+            .init_resource::<Server>()
+            .insert_resource(ServerLoad {
+                recovery: Duration::from_millis(50),
+                ..default()
+            })
+            .add_plugin(LoadPlugin)
+            .init_resource::<Counts>()
+            .insert_resource(Stall(Duration::from_millis(40)))
+            .add_system((|mut counts: ResMut<Counts>| counts.ambient += 1).in_set(LoadSet::Ambient))
+            .add_system(stall.in_schedule(ServerFixedUpdate))
+            .add_system(
+                (|mut counts: ResMut<Counts>| counts.stepped += 1)
+                    .in_set(LoadSet::Stepped)
+                    .in_schedule(ServerFixedUpdate),
+            );
+        let take = |app: &mut App| std::mem::take(&mut *app.world.resource_mut::<Counts>());
+
+        // Every tick sleeps longer than two ticks last, each frame owes more than the cap
+        app.update();
+        std::thread::sleep(FIXED_STEP * 2);
+        for _ in 0..4 {
+            app.update();
+            assert!(take(&mut app).fixed <= DEFAULT_MAX_CATCH_UP);
+        }
+        let load = app.world.resource::<ServerLoad>();
+        assert!(load.shedding >= Shedding::Stepped, "{:?}", load.shedding);
+        app.update();
+        let counts = take(&mut app);
+        assert_eq!(counts.fixed, DEFAULT_MAX_CATCH_UP);
+        assert_eq!(counts.ambient, 0);
+        assert!(counts.stepped < counts.fixed);
+
+        // Fast ticks again, it finds its way back without anyone resetting it
+        app.insert_resource(Stall(Duration::ZERO));
+        for _ in 0..200 {
+            if app.world.resource::<ServerLoad>().health() == ServerHealth::Healthy {
+                break;
+            }
+            std::thread::sleep(FIXED_STEP);
+            app.update();
+        }
+        assert_eq!(
+            app.world.resource::<ServerLoad>().health(),
+            ServerHealth::Healthy
+        );
+        take(&mut app);
+        std::thread::sleep(FIXED_STEP);
+        app.update();
+        assert_eq!(take(&mut app).ambient, 1);
+    }
+}
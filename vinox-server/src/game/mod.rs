@@ -1,3 +1,6 @@
+pub mod audit;
+pub mod load;
 pub mod networking;
 pub mod plugin;
+pub mod schedule;
 pub mod world;
@@ -0,0 +1,85 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_quinnet::server::Server;
+use vinox_common::{
+    ecs::{arrange::InventoryOp, bundles::Inventory},
+    networking::{
+        intents::{Arrival, IntentCursor},
+        protocol::ServerMessage,
+    },
+    world::chunks::storage::ItemTable,
+};
+
+pub enum InventoryIntent {
+    Op { seq: u32, op: InventoryOp },
+    Resync { seq: u32, inventory: Box<Inventory> },
+}
+
+pub struct InventoryIntentEvent {
+    pub client_id: u64,
+    pub entity: Entity,
+    pub intent: InventoryIntent,
+}
+
+// How far into a player's arrangement ops we are. What's in their Inventory here is up to us,
+// the client only gets to say how it's arranged
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct ArrangeCursor(pub IntentCursor);
+
+pub fn apply_arrangements(
+    mut server: ResMut<Server>,
+    mut events: EventReader<InventoryIntentEvent>,
+    mut players: Query<(&mut Inventory, &mut ArrangeCursor)>,
+    item_table: Res<ItemTable>,
+) {
+    let endpoint = server.endpoint_mut();
+    // One ack per client covers everything applied this frame
+    let mut acks = HashMap::new();
+    for evt in events.iter() {
+        let Ok((mut inventory, mut cursor)) = players.get_mut(evt.entity) else {
+            continue;
+        };
+        match &evt.intent {
+            InventoryIntent::Op { seq, op } => match cursor.arrive(*seq) {
+                Arrival::Apply => {
+                    if !inventory.apply(op, &item_table) {
+                        // The two copies drifted apart, nothing after this would line up either
+                        endpoint
+                            .try_send_message(evt.client_id, ServerMessage::RequestInventoryResync);
+                    }
+                    acks.insert(evt.client_id, cursor.applied());
+                }
+                Arrival::Duplicate => {
+                    acks.insert(evt.client_id, cursor.applied());
+                }
+                Arrival::Gap { expected } => {
+                    endpoint.try_send_message(
+                        evt.client_id,
+                        ServerMessage::ResendInventoryOps { from: expected },
+                    );
+                }
+                Arrival::Waiting => {}
+            },
+            InventoryIntent::Resync {
+                seq,
+                inventory: snapshot,
+            } => {
+                // Anything more than we have would be made up, so ours wins and goes back
+                if snapshot.fits_within(&inventory, &item_table) {
+                    *inventory = (**snapshot).clone();
+                } else {
+                    endpoint.try_send_message(
+                        evt.client_id,
+                        ServerMessage::InventoryCorrection {
+                            inventory: Box::new(inventory.clone()),
+                        },
+                    );
+                }
+                cursor.resync(*seq);
+                acks.insert(evt.client_id, cursor.applied());
+            }
+        }
+    }
+    for (client_id, seq) in acks {
+        endpoint.try_send_message(client_id, ServerMessage::InventoryAck { seq });
+    }
+}
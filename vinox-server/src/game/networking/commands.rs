@@ -0,0 +1,1075 @@
+use bevy::{app::AppExit, prelude::*};
+use bevy_quinnet::server::*;
+use vinox_common::{
+    ecs::{
+        bundles::{ClientName, Inventory},
+        gameplay::{GameplayRules, RULES},
+    },
+    networking::protocol::{
+        truncate_chars, ChatCategory, EntityKind, Player, ServerMessage, MAX_ITEM_NAME_CHARS,
+    },
+    world::chunks::{
+        ecs::CurrentChunks,
+        positions::{world_to_chunk, world_to_global_voxel, ChunkPos, DimensionId},
+        storage::{BlockTable, ChunkData, RecipeTable, CHUNK_SIZE},
+    },
+};
+
+use crate::game::{
+    audit::{AuditEvent, AuditLog, RollbackEntry},
+    load::ServerLoad,
+    world::{
+        block_index::BlockIndex,
+        critter::Critter,
+        edits::{now_secs, rollback_chunk, EditLog, RollbackReport, MAX_EDITS_PER_CHUNK},
+        gameplay::{GameplayPath, GameplaySource},
+        lifecycle::ChunkLifecycleStats,
+        snapshots::{restore_chunk, ChunkSnapshots},
+        spawn::{PersonalSpawn, RespawnEvent},
+        spawn_rules::{Candidate, NearBlock, SpawnRules, SpawnStats, WorldInfoPath},
+        storage::{ChunksToSave, EditLogsToSave, RecipesToSave, SpawnPointsToSave, WorldInfo},
+    },
+};
+
+use super::{
+    components::{Flight, LocalGame},
+    identity::{PlayerIdentity, Sessions},
+    recipes::{announce_unlocks, UnlockedRecipes},
+};
+
+pub const COMMANDS: [&str; 13] = [
+    "find",
+    "fly",
+    "forceload",
+    "gamerule",
+    "recipe",
+    "rename",
+    "rollback",
+    "say",
+    "spawn",
+    "spawnpoint",
+    "spawnrules",
+    "status",
+    "stop",
+];
+
+// In blocks, /find only ever looks through loaded chunks
+pub const DEFAULT_FIND_RADIUS: i32 = 64;
+pub const MAX_FIND_RADIUS: i32 = 256;
+pub const FIND_LIMIT: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandSender {
+    Player { client_id: u64, entity: Entity },
+    // Whoever is at the server's terminal, always allowed everything
+    Console,
+}
+
+// Chat messages starting with a slash, without the slash, or lines typed into the console
+pub struct ChatCommandEvent {
+    pub sender: CommandSender,
+    pub user_name: String,
+    // What permissions and saved data go by, see PlayerIdentity
+    pub storage_key: String,
+    pub command: String,
+}
+
+// Saves whatever is pending and closes the app at the end of the frame, everyone online is told
+// the reason before they're disconnected
+pub struct ShutdownEvent {
+    pub reason: String,
+}
+
+pub fn is_operator(user_name: &str, world_info: &WorldInfo, local_game: &LocalGame) -> bool {
+    // Whoever hosts a local game owns the world
+    **local_game || world_info.moderators.iter().any(|name| name == user_name)
+}
+
+pub fn is_moderator(
+    evt: &ChatCommandEvent,
+    world_info: &WorldInfo,
+    local_game: &LocalGame,
+) -> bool {
+    evt.sender == CommandSender::Console || is_operator(&evt.storage_key, world_info, local_game)
+}
+
+pub fn reply(server: &mut Server, sender: CommandSender, message: String) {
+    match sender {
+        CommandSender::Player { client_id, .. } => {
+            server.endpoint_mut().try_send_message_on(
+                client_id,
+                bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                ServerMessage::ChatMessage {
+                    user_name: "Server".to_string(),
+                    message,
+                    id: 0,
+                    category: ChatCategory::CommandOutput,
+                },
+            );
+        }
+        CommandSender::Console => println!("{message}"),
+    }
+}
+
+// A chat line from the server to everyone online
+pub fn announce(server: &mut Server, message: String) {
+    println!("{message}");
+    if let Some(endpoint) = server.get_endpoint_mut() {
+        endpoint.try_broadcast_message_on(
+            bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+            ServerMessage::ChatMessage {
+                user_name: "Server".to_string(),
+                message,
+                id: 0,
+                category: ChatCategory::System,
+            },
+        );
+    }
+}
+
+pub fn unknown_command(mut server: ResMut<Server>, mut events: EventReader<ChatCommandEvent>) {
+    for evt in events.iter() {
+        let name = evt.command.split_whitespace().next().unwrap_or_default();
+        if !COMMANDS.contains(&name) {
+            reply(&mut server, evt.sender, format!("Unknown command /{name}"));
+        }
+    }
+}
+
+// /say <message>
+pub fn say_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    world_info: Res<WorldInfo>,
+    local_game: Res<LocalGame>,
+) {
+    for evt in events.iter() {
+        let Some(message) = evt.command.strip_prefix("say") else {
+            continue;
+        };
+        if !message.is_empty() && !message.starts_with(' ') {
+            continue;
+        }
+        // It goes out as a system message, so only moderators get to speak with that voice
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only moderators can use /say".to_string(),
+            );
+            continue;
+        }
+        let message = message.trim();
+        if message.is_empty() {
+            reply(&mut server, evt.sender, "Usage: /say <message>".to_string());
+            continue;
+        }
+        if evt.sender == CommandSender::Console {
+            println!("[{}] {message}", evt.user_name);
+        }
+        if let Some(endpoint) = server.get_endpoint_mut() {
+            endpoint.try_broadcast_message_on(
+                bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                ServerMessage::ChatMessage {
+                    user_name: evt.user_name.clone(),
+                    message: message.to_string(),
+                    id: 0,
+                    category: ChatCategory::System,
+                },
+            );
+        }
+    }
+}
+
+// /status, anyone can ask how the server is keeping up
+pub fn status_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    load: Res<ServerLoad>,
+    snapshots: Query<&ChunkSnapshots>,
+    lifecycle: Res<ChunkLifecycleStats>,
+    audit: Option<Res<AuditLog>>,
+) {
+    for evt in events.iter() {
+        if evt.command.split_whitespace().next() != Some("status") {
+            continue;
+        }
+        let (count, bytes) = snapshots.iter().fold((0, 0), |(count, bytes), snapshots| {
+            (count + snapshots.len(), bytes + snapshots.bytes())
+        });
+        let dropped = audit.as_ref().map_or(0, |audit| audit.dropped);
+        reply(
+            &mut server,
+            evt.sender,
+            format!(
+                "Server is {:?}: shedding {:?}, {:.1}s behind, {} ticks skipped since start, ticks take {:.2} ms. {} chunks resident, {} unloaded in the last minute ({} dirty). Loaded chunks hold {count} snapshots in {:.1} KiB. {dropped} audit events dropped",
+                load.health(),
+                load.shedding,
+                load.behind().as_secs_f32(),
+                load.skipped_ticks,
+                load.tick_time.as_secs_f32() * 1000.0,
+                lifecycle.resident,
+                lifecycle.last_unloaded,
+                lifecycle.last_dirty_at_unload,
+                bytes as f32 / 1024.0
+            ),
+        );
+    }
+}
+
+// /stop
+pub fn stop_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    mut shutdown: EventWriter<ShutdownEvent>,
+    world_info: Res<WorldInfo>,
+    local_game: Res<LocalGame>,
+) {
+    for evt in events.iter() {
+        if evt.command.split_whitespace().next() != Some("stop") {
+            continue;
+        }
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only moderators can use /stop".to_string(),
+            );
+            continue;
+        }
+        println!("Stopping the server, requested by {}", evt.user_name);
+        shutdown.send(ShutdownEvent {
+            reason: "The server is stopping".to_string(),
+        });
+    }
+}
+
+pub fn shutdown(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ShutdownEvent>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(evt) = events.iter().last() else {
+        return;
+    };
+    announce(&mut server, evt.reason.clone());
+    if let Some(endpoint) = server.get_endpoint_mut() {
+        endpoint.disconnect_all_clients().ok();
+    }
+    // Anything still queued gets written by process_save later this frame
+    exit.send(AppExit);
+}
+
+// /rollback <player> <minutes> [radius]
+#[allow(clippy::too_many_arguments)]
+pub fn rollback_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    players: Query<(&Transform, &DimensionId), With<Player>>,
+    chunk_positions: Query<(Entity, &ChunkPos, &DimensionId)>,
+    mut chunks: Query<(&mut ChunkData, &mut EditLog, &ChunkSnapshots)>,
+    mut chunks_to_save: ResMut<ChunksToSave>,
+    mut edit_logs_to_save: ResMut<EditLogsToSave>,
+    (world_info, local_game, block_table, sessions, mut audit): (
+        Res<WorldInfo>,
+        Res<LocalGame>,
+        Res<BlockTable>,
+        Res<Sessions>,
+        EventWriter<AuditEvent>,
+    ),
+) {
+    for evt in events.iter() {
+        let args: Vec<&str> = evt.command.split_whitespace().collect();
+        if args.first() != Some(&"rollback") {
+            continue;
+        }
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only moderators can use /rollback".to_string(),
+            );
+            continue;
+        }
+        let (Some(actor), Some(Ok(minutes))) =
+            (args.get(1), args.get(2).map(|arg| arg.parse::<u64>()))
+        else {
+            reply(
+                &mut server,
+                evt.sender,
+                "Usage: /rollback <player> <minutes> [radius]".to_string(),
+            );
+            continue;
+        };
+        // Someone online is looked up by what they're shown as, their edits are under their
+        // storage key
+        let actor = sessions.storage_key_of(actor);
+        let radius = args.get(3).and_then(|arg| arg.parse::<f32>().ok());
+        // The console has no position so it always works on the whole overworld
+        let (origin, dimension) = match evt.sender {
+            CommandSender::Player { entity, .. } => {
+                let Ok((transform, dimension)) = players.get(entity) else {
+                    continue;
+                };
+                (Some(transform.translation), *dimension)
+            }
+            CommandSender::Console => (None, DimensionId::default()),
+        };
+        if radius.is_some() && origin.is_none() {
+            reply(
+                &mut server,
+                evt.sender,
+                "A radius needs a position, leave it out from the console".to_string(),
+            );
+            continue;
+        }
+        let now = now_secs();
+        let since = now.saturating_sub(minutes.saturating_mul(60));
+        let mut report = RollbackReport::default();
+        // The first chunk that needed a snapshot and couldn't use one, and how many did
+        let mut refused = None;
+        let mut refused_count = 0;
+        let mut from_snapshot = 0;
+        for (chunk_entity, chunk_pos, chunk_dimension) in chunk_positions.iter() {
+            if *chunk_dimension != dimension {
+                continue;
+            }
+            if let (Some(radius), Some(origin)) = (radius, origin) {
+                let center = (chunk_pos.as_vec3() + Vec3::splat(0.5)) * CHUNK_SIZE as f32;
+                // Count chunks that overlap the radius at all
+                let half_diagonal = CHUNK_SIZE as f32 * 0.87;
+                if center.distance(origin) > radius + half_diagonal {
+                    continue;
+                }
+            }
+            let Ok((mut chunk, mut edit_log, snapshots)) = chunks.get_mut(chunk_entity) else {
+                continue;
+            };
+            let incomplete = edit_log.truncated_at.is_some_and(|time| time >= since);
+            if !incomplete
+                && !edit_log
+                    .edits
+                    .iter()
+                    .any(|edit| edit.time >= since && edit.actor == actor)
+            {
+                continue;
+            }
+            // The log is exact while it covers the window. Past a cut the chunk goes back to
+            // a snapshot, which undoes everything the log lost whoever did it
+            let restored = if incomplete {
+                restore_chunk(
+                    &mut chunk,
+                    &mut edit_log,
+                    snapshots,
+                    actor,
+                    since,
+                    &evt.storage_key,
+                    now,
+                    &block_table,
+                )
+                .map_err(|error| {
+                    refused_count += 1;
+                    if refused.is_none() {
+                        refused = Some((*chunk_pos, error));
+                    }
+                })
+                .ok()
+            } else {
+                None
+            };
+            if restored.is_some() {
+                from_snapshot += 1;
+            }
+            let (chunk_report, reverted) = restored.unwrap_or_else(|| {
+                rollback_chunk(
+                    &mut chunk,
+                    &mut edit_log,
+                    actor,
+                    since,
+                    &evt.storage_key,
+                    &block_table,
+                )
+            });
+            report += chunk_report;
+            if reverted.is_empty() {
+                continue;
+            }
+            chunks_to_save.push((dimension, *chunk_pos, chunk.to_raw()));
+            edit_logs_to_save.push((dimension, *chunk_pos, edit_log.clone()));
+            for voxel_pos in reverted {
+                server
+                    .endpoint_mut()
+                    .try_broadcast_message(ServerMessage::SentBlock {
+                        chunk_pos: **chunk_pos,
+                        voxel_pos,
+                        block_type: chunk.get(
+                            voxel_pos[0] as u32,
+                            voxel_pos[1] as u32,
+                            voxel_pos[2] as u32,
+                        ),
+                        dimension,
+                        denied: None,
+                    });
+            }
+        }
+        audit.send(AuditEvent::Rollback(RollbackEntry {
+            moderator: evt.storage_key.clone(),
+            target: actor.to_string(),
+            since,
+            dimension: dimension.0,
+            radius,
+            reverted: report.reverted,
+            conflicts: report.conflicts,
+        }));
+        let mut message = format!(
+            "Reverted {} blocks by {actor}, skipped {} that were built over since",
+            report.reverted, report.conflicts
+        );
+        if report.history_incomplete {
+            message.push_str(&format!(
+                ". Some edits could not be checked, history only goes back {} hours and holds at most {MAX_EDITS_PER_CHUNK} edits per chunk",
+                world_info.edit_retention_hours
+            ));
+        }
+        if from_snapshot > 0 {
+            message.push_str(&format!(
+                ". {from_snapshot} chunks had lost history and went back to a snapshot, anything else changed there since it was taken was undone too"
+            ));
+        }
+        if let Some((chunk_pos, error)) = refused {
+            message.push_str(&format!(
+                ". {refused_count} chunks couldn't be restored from a snapshot either, chunk {} because {error}",
+                **chunk_pos
+            ));
+        }
+        reply(&mut server, evt.sender, message);
+    }
+}
+
+// /fly <player> [off|on|noclip]. Only the client is told, positions aren't checked against it so
+// this keeps honest players on the ground rather than stopping a modified client
+pub fn fly_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    mut players: Query<(&Player, &ClientName, &mut Flight)>,
+    (world_info, local_game): (Res<WorldInfo>, Res<LocalGame>),
+) {
+    for evt in events.iter() {
+        let args: Vec<&str> = evt.command.split_whitespace().collect();
+        if args.first() != Some(&"fly") {
+            continue;
+        }
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only moderators can use /fly".to_string(),
+            );
+            continue;
+        }
+        let usage = "Usage: /fly <player> [off|on|noclip]".to_string();
+        let Some(target) = args.get(1) else {
+            reply(&mut server, evt.sender, usage);
+            continue;
+        };
+        let Some((player, _, mut flight)) = players
+            .iter_mut()
+            .find(|(_, user_name, _)| user_name.as_str() == *target)
+        else {
+            reply(&mut server, evt.sender, format!("{target} is not online"));
+            continue;
+        };
+        let changed = match args.get(2..) {
+            Some([]) => None,
+            Some(["off"]) => Some(Flight::default()),
+            Some(["on"]) => Some(Flight {
+                fly: true,
+                noclip: false,
+            }),
+            Some(["noclip"]) => Some(Flight {
+                fly: true,
+                noclip: true,
+            }),
+            _ => {
+                reply(&mut server, evt.sender, usage);
+                continue;
+            }
+        };
+        if let Some(changed) = changed {
+            *flight = changed;
+            server
+                .endpoint_mut()
+                .try_send_message(player.id, flight.message());
+        }
+        let state = match (flight.fly, flight.noclip) {
+            (false, _) => "can't fly",
+            (true, false) => "can fly",
+            (true, true) => "can fly and pass through blocks",
+        };
+        reply(&mut server, evt.sender, format!("{target} {state}"));
+    }
+}
+
+// /recipe grant <player> <id|all>
+pub fn recipe_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    mut players: Query<(&Player, &ClientName, &PlayerIdentity, &mut UnlockedRecipes)>,
+    recipe_table: Res<RecipeTable>,
+    mut recipes_to_save: ResMut<RecipesToSave>,
+    (world_info, local_game): (Res<WorldInfo>, Res<LocalGame>),
+) {
+    for evt in events.iter() {
+        let args: Vec<&str> = evt.command.split_whitespace().collect();
+        if args.first() != Some(&"recipe") {
+            continue;
+        }
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only moderators can use /recipe".to_string(),
+            );
+            continue;
+        }
+        let (Some(&"grant"), Some(target), Some(recipe)) = (args.get(1), args.get(2), args.get(3))
+        else {
+            reply(
+                &mut server,
+                evt.sender,
+                "Usage: /recipe grant <player> <id|all>".to_string(),
+            );
+            continue;
+        };
+        let granted: Vec<String> = if *recipe == "all" {
+            recipe_table.keys().cloned().collect()
+        } else if recipe_table.contains_key(*recipe) {
+            vec![recipe.to_string()]
+        } else {
+            reply(&mut server, evt.sender, format!("Unknown recipe {recipe}"));
+            continue;
+        };
+        let Some((player, _, identity, mut unlocked)) = players
+            .iter_mut()
+            .find(|(_, user_name, _, _)| user_name.as_str() == *target)
+        else {
+            reply(&mut server, evt.sender, format!("{target} is not online"));
+            continue;
+        };
+        let newly_unlocked: Vec<String> = granted
+            .into_iter()
+            .filter(|recipe| unlocked.insert(recipe.clone()))
+            .collect();
+        let count = newly_unlocked.len();
+        if count > 0 {
+            recipes_to_save.push((identity.storage_key().to_string(), unlocked.0.clone()));
+            announce_unlocks(&mut server, player.id, newly_unlocked);
+        }
+        reply(
+            &mut server,
+            evt.sender,
+            format!("Granted {count} recipes to {target}"),
+        );
+    }
+}
+
+// /rename [name], until there's an anvil. Without a name it clears the held item's name
+pub fn rename_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    mut inventories: Query<&mut Inventory>,
+    (world_info, local_game): (Res<WorldInfo>, Res<LocalGame>),
+) {
+    for evt in events.iter() {
+        let Some(name) = evt.command.strip_prefix("rename") else {
+            continue;
+        };
+        if !name.is_empty() && !name.starts_with(' ') {
+            continue;
+        }
+        let CommandSender::Player { client_id, entity } = evt.sender else {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only players can hold items".to_string(),
+            );
+            continue;
+        };
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only creative players can use /rename".to_string(),
+            );
+            continue;
+        }
+        let name = truncate_chars(name.trim(), MAX_ITEM_NAME_CHARS);
+        let name = (!name.is_empty()).then(|| name.to_string());
+        // Our copy gets the name first, the client just follows along
+        let renamed = inventories.get_mut(entity).is_ok_and(|mut inventory| {
+            let held = inventory.held_slot();
+            inventory
+                .slot_mut(held)
+                .and_then(|slot| slot.as_mut())
+                .map(|item| item.rename(name.clone()))
+                .is_some()
+        });
+        if !renamed {
+            reply(
+                &mut server,
+                evt.sender,
+                "There's nothing in your hand to rename".to_string(),
+            );
+            continue;
+        }
+        let message = match &name {
+            Some(name) => format!("Renamed the held item to {name}"),
+            None => "Cleared the held item's name".to_string(),
+        };
+        server
+            .endpoint_mut()
+            .try_send_message(client_id, ServerMessage::RenameHeld { name });
+        reply(&mut server, evt.sender, message);
+    }
+}
+
+// /spawn
+pub fn spawn_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    mut respawns: EventWriter<RespawnEvent>,
+) {
+    for evt in events.iter() {
+        if evt.command.split_whitespace().next() != Some("spawn") {
+            continue;
+        }
+        match evt.sender {
+            CommandSender::Player { client_id, entity } => {
+                respawns.send(RespawnEvent { client_id, entity })
+            }
+            CommandSender::Console => reply(
+                &mut server,
+                evt.sender,
+                "Only players can use /spawn".to_string(),
+            ),
+        }
+    }
+}
+
+// /spawnpoint [clear]
+pub fn spawnpoint_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    mut players: Query<(&PlayerIdentity, &mut PersonalSpawn)>,
+    mut spawn_points_to_save: ResMut<SpawnPointsToSave>,
+) {
+    for evt in events.iter() {
+        let args: Vec<&str> = evt.command.split_whitespace().collect();
+        if args.first() != Some(&"spawnpoint") {
+            continue;
+        }
+        let CommandSender::Player { entity, .. } = evt.sender else {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only players have a spawn point".to_string(),
+            );
+            continue;
+        };
+        let Ok((identity, mut personal)) = players.get_mut(entity) else {
+            continue;
+        };
+        let message = match (args.get(1), **personal) {
+            (Some(&"clear"), _) => {
+                **personal = None;
+                spawn_points_to_save.push((identity.storage_key().to_string(), None));
+                "Your spawn point is back at world spawn".to_string()
+            }
+            (None, Some(spawn_point)) => format!(
+                "Your spawn point is the bed at {} {} {}",
+                spawn_point.anchor.x, spawn_point.anchor.y, spawn_point.anchor.z
+            ),
+            (None, None) => "You respawn at world spawn, use a bed to change that".to_string(),
+            _ => "Usage: /spawnpoint [clear]".to_string(),
+        };
+        reply(&mut server, evt.sender, message);
+    }
+}
+
+// /spawnrules [test <x> <y> <z> <kind>]
+pub fn spawnrules_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    (rules, stats): (Res<SpawnRules>, Res<SpawnStats>),
+    players: Query<(&Transform, &DimensionId), With<Player>>,
+    critters: Query<&Transform, With<Critter>>,
+    (world_info, local_game): (Res<WorldInfo>, Res<LocalGame>),
+    (block_index, current_chunks, chunks): (Res<BlockIndex>, Res<CurrentChunks>, Query<&ChunkData>),
+) {
+    for evt in events.iter() {
+        let args: Vec<&str> = evt.command.split_whitespace().collect();
+        if args.first() != Some(&"spawnrules") {
+            continue;
+        }
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only moderators can use /spawnrules".to_string(),
+            );
+            continue;
+        }
+        if args.len() == 1 {
+            let rejected: Vec<String> = stats
+                .rejected
+                .iter()
+                .map(|(rule, count)| format!("{rule:?} {count}"))
+                .collect();
+            reply(
+                &mut server,
+                evt.sender,
+                format!(
+                    "{} spawn candidates considered, {} spawned, rejected by: {}",
+                    stats.considered,
+                    stats.spawned,
+                    if rejected.is_empty() {
+                        "nothing".to_string()
+                    } else {
+                        rejected.join(", ")
+                    }
+                ),
+            );
+            continue;
+        }
+        let usage = "Usage: /spawnrules [test <x> <y> <z> <critter>]".to_string();
+        let (Some(&"test"), Some(x), Some(y), Some(z), Some(kind)) = (
+            args.get(1),
+            args.get(2).and_then(|x| x.parse::<i32>().ok()),
+            args.get(3).and_then(|y| y.parse::<i32>().ok()),
+            args.get(4).and_then(|z| z.parse::<i32>().ok()),
+            args.get(5),
+        ) else {
+            reply(&mut server, evt.sender, usage);
+            continue;
+        };
+        let kind = match *kind {
+            "critter" => EntityKind::Critter,
+            "dropped_item" => EntityKind::DroppedItem,
+            _ => {
+                reply(&mut server, evt.sender, usage);
+                continue;
+            }
+        };
+        let dimension = match evt.sender {
+            CommandSender::Player { entity, .. } => players
+                .get(entity)
+                .map(|(_, dimension)| *dimension)
+                .unwrap_or_default(),
+            CommandSender::Console => DimensionId::default(),
+        };
+        let voxel = IVec3::new(x, y, z);
+        let candidate = Candidate {
+            dimension,
+            voxel,
+            ..Default::default()
+        };
+        // Counted around whoever is closest, the same as a real candidate rolled for them
+        let position = voxel.as_vec3();
+        let player = players
+            .iter()
+            .filter(|(_, player_dimension)| **player_dimension == dimension)
+            .map(|(transform, _)| transform.translation)
+            .min_by(|a, b| a.distance(position).total_cmp(&b.distance(position)))
+            .unwrap_or(position);
+        let near = |radius: f32| {
+            critters
+                .iter()
+                .filter(|critter| critter.translation.distance(player) < radius)
+                .count()
+        };
+        let block_near = |near_block: &NearBlock| {
+            !block_index
+                .find_blocks_near(
+                    dimension,
+                    voxel,
+                    &near_block.identifier,
+                    near_block.radius,
+                    1,
+                    |chunk_pos| {
+                        let entity =
+                            current_chunks.get_entity_in(dimension, ChunkPos(chunk_pos))?;
+                        chunks.get(entity).ok()
+                    },
+                )
+                .is_empty()
+        };
+        let message = match rules.check(kind, &candidate, block_near, near) {
+            Ok(()) => format!("The rules let a {} spawn at {x} {y} {z}", args[5]),
+            Err(rule) => format!(
+                "No {} at {x} {y} {z}, {:?} turns it away: {}",
+                args[5],
+                rule,
+                rule.reason()
+            ),
+        };
+        reply(&mut server, evt.sender, message);
+    }
+}
+
+// /find <namespace:name> [radius] [<x> <y> <z>], the nearest of a block in the loaded chunks.
+// Players search around themselves by default, the console has to say where
+pub fn find_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    players: Query<(&Transform, &DimensionId), With<Player>>,
+    (world_info, local_game): (Res<WorldInfo>, Res<LocalGame>),
+    (block_index, current_chunks, chunks): (Res<BlockIndex>, Res<CurrentChunks>, Query<&ChunkData>),
+) {
+    for evt in events.iter() {
+        let args: Vec<&str> = evt.command.split_whitespace().collect();
+        if args.first() != Some(&"find") {
+            continue;
+        }
+        // Nobody else gets to go looking for ores
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only moderators can use /find".to_string(),
+            );
+            continue;
+        }
+        let sender = match evt.sender {
+            CommandSender::Player { entity, .. } => players.get(entity).ok(),
+            CommandSender::Console => None,
+        };
+        let numbers: Option<Vec<i32>> = args.iter().skip(2).map(|arg| arg.parse().ok()).collect();
+        let (radius, at) = match numbers.as_deref() {
+            Some([]) => (DEFAULT_FIND_RADIUS, None),
+            Some([radius]) => (*radius, None),
+            Some([x, y, z]) => (DEFAULT_FIND_RADIUS, Some(IVec3::new(*x, *y, *z))),
+            Some([radius, x, y, z]) => (*radius, Some(IVec3::new(*x, *y, *z))),
+            _ => (-1, None),
+        };
+        let target = match (at, sender) {
+            (Some(at), _) => Some((
+                sender.map(|(_, dimension)| *dimension).unwrap_or_default(),
+                at,
+            )),
+            (None, Some((transform, dimension))) => {
+                Some((*dimension, world_to_global_voxel(transform.translation)))
+            }
+            (None, None) => None,
+        };
+        let (Some(identifier), Some((dimension, center)), true) =
+            (args.get(1), target, (0..=MAX_FIND_RADIUS).contains(&radius))
+        else {
+            reply(
+                &mut server,
+                evt.sender,
+                format!(
+                    "Usage: /find <namespace:name> [radius up to {MAX_FIND_RADIUS}] [<x> <y> <z>]"
+                ),
+            );
+            continue;
+        };
+        // Bare names mean the base game's blocks, the same as the client's /find
+        let identifier = if identifier.contains(':') {
+            identifier.to_string()
+        } else {
+            format!("vinox:{identifier}")
+        };
+        let found = block_index.find_blocks_near(
+            dimension,
+            center,
+            &identifier,
+            radius,
+            FIND_LIMIT,
+            |chunk_pos| {
+                let entity = current_chunks.get_entity_in(dimension, ChunkPos(chunk_pos))?;
+                chunks.get(entity).ok()
+            },
+        );
+        let holding = block_index
+            .chunks_containing(dimension, &identifier)
+            .count();
+        let message = if found.is_empty() {
+            format!(
+                "No {identifier} within {radius} blocks of {} {} {}, {holding} loaded chunks have some",
+                center.x, center.y, center.z
+            )
+        } else {
+            let nearest: Vec<String> = found
+                .iter()
+                .map(|voxel| {
+                    format!(
+                        "{} {} {} ({:.0}m)",
+                        voxel.x,
+                        voxel.y,
+                        voxel.z,
+                        voxel.as_vec3().distance(center.as_vec3())
+                    )
+                })
+                .collect();
+            format!(
+                "Nearest {identifier} within {radius} blocks: {}. {holding} loaded chunks have some",
+                nearest.join(", ")
+            )
+        };
+        reply(&mut server, evt.sender, message);
+    }
+}
+
+// /forceload [add|remove [<x> <y> <z>]], block coordinates, where the sender stands by default
+pub fn forceload_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    players: Query<(&Transform, &DimensionId), With<Player>>,
+    (mut world_info, local_game): (ResMut<WorldInfo>, Res<LocalGame>),
+    path: Option<Res<WorldInfoPath>>,
+) {
+    for evt in events.iter() {
+        let args: Vec<&str> = evt.command.split_whitespace().collect();
+        if args.first() != Some(&"forceload") {
+            continue;
+        }
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only moderators can use /forceload".to_string(),
+            );
+            continue;
+        }
+        if args.len() == 1 {
+            let forced: Vec<String> = world_info
+                .chunk_lifecycle
+                .force_loaded
+                .iter()
+                .map(|forced| {
+                    format!(
+                        "{} {} {} in {}",
+                        forced.chunk.x, forced.chunk.y, forced.chunk.z, *forced.dimension
+                    )
+                })
+                .collect();
+            reply(
+                &mut server,
+                evt.sender,
+                if forced.is_empty() {
+                    "No chunks are force loaded".to_string()
+                } else {
+                    format!("Force loaded chunks: {}", forced.join(", "))
+                },
+            );
+            continue;
+        }
+        let sender = match evt.sender {
+            CommandSender::Player { entity, .. } => players.get(entity).ok(),
+            CommandSender::Console => None,
+        };
+        let coordinates: Vec<i32> = args[2..]
+            .iter()
+            .filter_map(|arg| arg.parse().ok())
+            .collect();
+        let target = match (coordinates.as_slice(), sender) {
+            ([x, y, z], _) if args.len() == 5 => Some((
+                sender.map(|(_, dimension)| *dimension).unwrap_or_default(),
+                world_to_chunk(Vec3::new(*x as f32, *y as f32, *z as f32)),
+            )),
+            ([], Some((transform, dimension))) if args.len() == 2 => {
+                Some((*dimension, world_to_chunk(transform.translation)))
+            }
+            _ => None,
+        };
+        let (Some(action @ ("add" | "remove")), Some((dimension, chunk))) =
+            (args.get(1).copied(), target)
+        else {
+            reply(
+                &mut server,
+                evt.sender,
+                "Usage: /forceload [add|remove [<x> <y> <z>]]".to_string(),
+            );
+            continue;
+        };
+        let at = format!("{} {} {}", chunk.x, chunk.y, chunk.z);
+        let lifecycle = &mut world_info.chunk_lifecycle;
+        let message = match action {
+            "add" if lifecycle.force(dimension, ChunkPos(chunk)) => {
+                format!("Chunk {at} is now force loaded")
+            }
+            "add" => format!("Chunk {at} was already force loaded"),
+            _ if lifecycle.unforce(dimension, ChunkPos(chunk)) => {
+                format!("Chunk {at} unloads normally again")
+            }
+            _ => format!("Chunk {at} wasn't force loaded"),
+        };
+        // Kept in the world file so they come back on the next start
+        if let Some(path) = &path {
+            crate::save_world_info((*world_info).clone(), path.0.clone());
+        }
+        reply(&mut server, evt.sender, message);
+    }
+}
+
+// /gamerule [<name> [<value>]], changes are checked against the rule's range and saved to
+// gameplay.ron
+pub fn gamerule_command(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChatCommandEvent>,
+    (mut rules, mut source, path): (
+        ResMut<GameplayRules>,
+        ResMut<GameplaySource>,
+        Option<Res<GameplayPath>>,
+    ),
+    (world_info, local_game): (Res<WorldInfo>, Res<LocalGame>),
+) {
+    for evt in events.iter() {
+        let args: Vec<&str> = evt.command.split_whitespace().collect();
+        if args.first() != Some(&"gamerule") {
+            continue;
+        }
+        if !is_moderator(evt, &world_info, &local_game) {
+            reply(
+                &mut server,
+                evt.sender,
+                "Only moderators can use /gamerule".to_string(),
+            );
+            continue;
+        }
+        let message = match args[1..] {
+            [] => {
+                let all: Vec<String> = RULES
+                    .iter()
+                    .map(|name| format!("{name} {}", rules.value(name).unwrap_or_default()))
+                    .collect();
+                format!("Gameplay rules: {}", all.join(", "))
+            }
+            [name] => match rules.value(name) {
+                Some(value) => format!("{name} is {value}"),
+                None => format!("There's no rule called {name}"),
+            },
+            [name, value] => {
+                let mut changed = *rules;
+                match changed.set(name, value) {
+                    Err(e) => e,
+                    Ok(()) => {
+                        // Replaced whole like a reload, the next tick sees every change at once
+                        *rules = changed;
+                        let saved = match &path {
+                            Some(path) => source.save(&path.0, changed).err(),
+                            None => None,
+                        };
+                        let value = changed.value(name).unwrap_or_default();
+                        match saved {
+                            Some(e) => format!("{name} is now {value} but couldn't be saved: {e}"),
+                            None => format!("{name} is now {value}"),
+                        }
+                    }
+                }
+            }
+            _ => "Usage: /gamerule [<name> [<value>]]".to_string(),
+        };
+        reply(&mut server, evt.sender, message);
+    }
+}
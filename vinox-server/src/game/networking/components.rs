@@ -1,6 +1,20 @@
 use std::collections::HashMap;
 
 use bevy::prelude::*;
+use bevy_quinnet::server::Endpoint;
+use rustc_data_structures::stable_set::FxHashSet;
+use vinox_common::{
+    ecs::time::ServerTick,
+    networking::protocol::{JoinRejection, ServerMessage},
+    storage::items::descriptor::UseTiming,
+};
+
+use super::identity::SessionId;
+
+// A use arriving this many ticks early still counts, packets don't arrive evenly spaced
+pub const USE_TOLERANCE_TICKS: u64 = 1;
+// Same for breaks, a little looser since they're timed on the client's frames
+pub const BREAK_TOLERANCE_TICKS: u64 = 2;
 
 // TODO: Not networking move to different file
 #[derive(Debug, Resource, Deref, DerefMut)]
@@ -22,3 +36,156 @@ pub struct ServerLobby {
 
 #[derive(Debug, Default, Resource, Deref, DerefMut)]
 pub struct ChunkLimit(pub usize);
+
+pub const MAX_PLAYERS: usize = 8;
+
+// Clients that were told why they can't join, cut off once that has had time to reach them
+#[derive(Debug, Default, Resource, Deref, DerefMut)]
+pub struct RejectedClients(pub Vec<(u64, f32)>);
+
+impl RejectedClients {
+    pub fn reject(&mut self, endpoint: &mut Endpoint, id: u64, reason: JoinRejection, now: f32) {
+        endpoint.try_send_message(id, ServerMessage::JoinRejected { reason });
+        self.push((id, now + 1.0));
+    }
+}
+
+// What this player's client is allowed to do with ServerMessage::FlightPermission. Creative
+// players start with flight, /fly changes it until they leave
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Flight {
+    pub fly: bool,
+    pub noclip: bool,
+}
+
+impl Flight {
+    pub fn message(self) -> ServerMessage {
+        ServerMessage::FlightPermission {
+            fly: self.fly,
+            noclip: self.noclip,
+        }
+    }
+}
+
+// Non-player entities this client has been told about
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct KnownEntities(pub FxHashSet<Entity>);
+
+// Tick of the last accepted use of each item in each session
+#[derive(Debug, Default, Resource)]
+pub struct ItemUses(pub HashMap<(SessionId, String), ServerTick>);
+
+impl ItemUses {
+    pub fn try_use(
+        &mut self,
+        session: SessionId,
+        identifier: &str,
+        timing: UseTiming,
+        now: ServerTick,
+    ) -> bool {
+        let interval = timing.interval_ticks();
+        if interval == 0 {
+            return true;
+        }
+        let key = (session, identifier.to_string());
+        if let Some(last) = self.0.get(&key) {
+            if *now + USE_TOLERANCE_TICKS < **last + interval {
+                return false;
+            }
+        }
+        self.0.insert(key, now);
+        true
+    }
+
+    pub fn forget(&mut self, session: SessionId) {
+        self.0.retain(|(id, _), _| *id != session);
+    }
+}
+
+// Tick of each session's last accepted break. Holding the button down breaks one block after
+// another, so a slow block can't come any sooner after the last break than its break_time. The
+// first break after a pause can't be checked this way, it's the instant streams this stops
+#[derive(Debug, Default, Resource)]
+pub struct BlockBreaks(pub HashMap<SessionId, ServerTick>);
+
+impl BlockBreaks {
+    // `break_time` in seconds, None for blocks that break on the click
+    pub fn try_break(
+        &mut self,
+        session: SessionId,
+        break_time: Option<f32>,
+        now: ServerTick,
+    ) -> bool {
+        let ticks = break_time.map_or(0, |seconds| (seconds / ServerTick::LENGTH).round() as u64);
+        if let Some(last) = self.0.get(&session) {
+            if *now + BREAK_TOLERANCE_TICKS < **last + ticks {
+                return false;
+            }
+        }
+        self.0.insert(session, now);
+        true
+    }
+
+    pub fn forget(&mut self, session: SessionId) {
+        self.0.remove(&session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::ecs::time::GameDuration;
+
+    #[test]
+    fn early_second_use_is_rejected() {
+        let bread = UseTiming {
+            duration: GameDuration::from_millis(1000),
+            cooldown: GameDuration::from_millis(500),
+        };
+        assert_eq!(bread.interval_ticks(), 30);
+        let mut uses = ItemUses::default();
+        assert!(uses.try_use(SessionId(1), "vinox:bread", bread, ServerTick(100)));
+        // A client skipping the hold sends the next one right away
+        assert!(!uses.try_use(SessionId(1), "vinox:bread", bread, ServerTick(101)));
+        assert!(!uses.try_use(SessionId(1), "vinox:bread", bread, ServerTick(128)));
+        // Rejected attempts don't push the window back
+        assert!(uses.try_use(SessionId(1), "vinox:bread", bread, ServerTick(129)));
+        // Other players and other items keep their own timestamps
+        assert!(uses.try_use(SessionId(2), "vinox:bread", bread, ServerTick(130)));
+        assert!(uses.try_use(SessionId(1), "vinox:apple", bread, ServerTick(130)));
+        // Items without timing are never held back
+        for tick in 0..5 {
+            assert!(uses.try_use(
+                SessionId(1),
+                "vinox:stone",
+                UseTiming::default(),
+                ServerTick(tick)
+            ));
+        }
+
+        uses.forget(SessionId(1));
+        assert!(uses.try_use(SessionId(1), "vinox:bread", bread, ServerTick(131)));
+    }
+
+    #[test]
+    fn slow_blocks_cant_be_broken_back_to_back() {
+        let mut breaks = BlockBreaks::default();
+        // One second of stone is 20 ticks
+        let stone = Some(1.0);
+        assert!(breaks.try_break(SessionId(1), stone, ServerTick(100)));
+        // A client skipping the hold sends the next one right away
+        assert!(!breaks.try_break(SessionId(1), stone, ServerTick(101)));
+        assert!(!breaks.try_break(SessionId(1), stone, ServerTick(117)));
+        // Rejected breaks don't push the window back, and a little early still counts
+        assert!(breaks.try_break(SessionId(1), stone, ServerTick(118)));
+        // Instant blocks are never held back, but they still count as the last break
+        assert!(breaks.try_break(SessionId(1), None, ServerTick(119)));
+        assert!(breaks.try_break(SessionId(1), None, ServerTick(119)));
+        assert!(!breaks.try_break(SessionId(1), stone, ServerTick(120)));
+        // Everyone has their own
+        assert!(breaks.try_break(SessionId(2), stone, ServerTick(120)));
+
+        breaks.forget(SessionId(1));
+        assert!(breaks.try_break(SessionId(1), stone, ServerTick(121)));
+    }
+}
@@ -0,0 +1,190 @@
+use std::io::BufRead;
+
+use bevy::prelude::*;
+use tokio::sync::mpsc::{error::TryRecvError, Receiver, Sender};
+
+use super::commands::{ChatCommandEvent, CommandSender};
+
+pub const CONSOLE_NAME: &str = "Console";
+
+// Lines typed into the server's terminal
+#[derive(Resource)]
+pub struct ConsoleChannel {
+    pub rx: Receiver<String>,
+    // Every sender is gone so there is nothing left to poll
+    pub closed: bool,
+}
+
+// Only the server binary sets this up, the copy of the server embedded in the client never does
+#[allow(dead_code)]
+impl ConsoleChannel {
+    pub fn new(rx: Receiver<String>) -> Self {
+        Self { rx, closed: false }
+    }
+
+    // The threads are never joined, a blocking stdin read can't be interrupted and they die with the process
+    pub fn stdin() -> Self {
+        let (tx, rx) = tokio::sync::mpsc::channel(64);
+        let stdin_tx = tx.clone();
+        std::thread::spawn(move || read_lines(std::io::stdin().lock(), stdin_tx));
+        std::thread::spawn(move || watch_ctrl_c(tx));
+        Self::new(rx)
+    }
+}
+
+// Stops at EOF so piping a script in runs it once instead of spinning on an empty stdin
+#[allow(dead_code)]
+pub fn read_lines(reader: impl BufRead, tx: Sender<String>) {
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if tx.blocking_send(line).is_err() {
+            break;
+        }
+    }
+}
+
+// Ctrl-c goes through the same stop command, pressing it again skips saving
+#[allow(dead_code)]
+fn watch_ctrl_c(tx: Sender<String>) {
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    else {
+        return;
+    };
+    runtime.block_on(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            tx.send("stop".to_string()).await.ok();
+        }
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+}
+
+pub fn read_console(
+    mut console: ResMut<ConsoleChannel>,
+    mut command_event: EventWriter<ChatCommandEvent>,
+) {
+    if console.closed {
+        return;
+    }
+    loop {
+        match console.rx.try_recv() {
+            Ok(line) => {
+                // The slash is optional so commands copied from chat work too
+                let line = line.trim();
+                let command = line.strip_prefix('/').unwrap_or(line);
+                if command.is_empty() {
+                    continue;
+                }
+                command_event.send(ChatCommandEvent {
+                    sender: CommandSender::Console,
+                    user_name: CONSOLE_NAME.to_string(),
+                    storage_key: CONSOLE_NAME.to_string(),
+                    command: command.to_string(),
+                });
+            }
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                console.closed = true;
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_quinnet::server::Server;
+    use std::io::Cursor;
+
+    use crate::game::{
+        networking::{
+            commands::{stop_command, unknown_command, ShutdownEvent},
+            components::LocalGame,
+            identity::DuplicateNames,
+        },
+        world::{
+            generation::TerrainShape, lifecycle::ChunkLifecycle, noise_graph::NoiseSelection,
+            safe_spawn::SpawnSearch, snapshots::SnapshotPolicy, spawn_rules::SpawnRules,
+            storage::WorldInfo,
+        },
+    };
+    use vinox_common::storage::content::ContentPolicy;
+
+    fn console_app(rx: Receiver<String>) -> App {
+        let mut app = App::new();
+        app.add_event::<ChatCommandEvent>()
+            .add_event::<ShutdownEvent>()
+            .insert_resource(ConsoleChannel::new(rx))
+            .insert_resource(LocalGame(false))
+            .insert_resource(WorldInfo {
+                name: "test".to_string(),
+                seed: 0,
+                damage: false,
+                dimensions: Vec::new(),
+                moderators: Vec::new(),
+                edit_retention_hours: 24,
+                snapshots: SnapshotPolicy::default(),
+                format_version: 0,
+                content_policy: ContentPolicy::default(),
+                spawn_rules: SpawnRules::default(),
+                noise: NoiseSelection::default(),
+                terrain: TerrainShape::default(),
+                chunk_lifecycle: ChunkLifecycle::default(),
+                duplicate_names: DuplicateNames::default(),
+                world_id: String::new(),
+                spawn_search: SpawnSearch::default(),
+                spawn: None,
+            })
+            .init_resource::<Server>()
+            .add_systems((read_console, stop_command, unknown_command).chain());
+        app
+    }
+
+    #[test]
+    fn console_dispatches_commands() {
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        let mut app = console_app(rx);
+        for line in ["   ", "/bogus", "stop"] {
+            tx.try_send(line.to_string()).unwrap();
+        }
+        app.update();
+
+        let events = app.world.resource::<Events<ChatCommandEvent>>();
+        let commands: Vec<_> = events
+            .iter_current_update_events()
+            .map(|evt| (evt.sender, evt.command.as_str()))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                (CommandSender::Console, "bogus"),
+                (CommandSender::Console, "stop")
+            ]
+        );
+        // Nobody is a moderator but the console doesn't need to be
+        assert_eq!(app.world.resource::<Events<ShutdownEvent>>().len(), 1);
+        assert!(!app.world.resource::<ConsoleChannel>().closed);
+
+        drop(tx);
+        app.update();
+        assert!(app.world.resource::<ConsoleChannel>().closed);
+    }
+
+    #[test]
+    fn piped_script_ends_at_eof() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        read_lines(Cursor::new("say hi\n\nstop\n"), tx);
+        let mut lines = Vec::new();
+        while let Ok(line) = rx.try_recv() {
+            lines.push(line);
+        }
+        assert_eq!(lines, vec!["say hi", "", "stop"]);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use vinox_common::networking::protocol::{truncate_chars, JoinRejection, MAX_NAME_CHARS};
+
+// Idle players still send a position about once a second, this much silence means the
+// connection is gone even if it hasn't timed out yet
+pub const SESSION_TIMEOUT_SECS: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deref)]
+pub struct SessionId(pub u64);
+
+// What happens when someone joins under a name that's already playing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DuplicateNames {
+    #[default]
+    Reject,
+    // name(2), name(3) and so on
+    Suffix,
+}
+
+// Who a player is. The session keys everything while they're connected, ClientName is only what
+// gets shown, and saved data goes through storage_key
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct PlayerIdentity {
+    pub session: SessionId,
+    name: String,
+}
+
+impl PlayerIdentity {
+    // The name they joined with, moving saved data to accounts only has to change this
+    pub fn storage_key(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub client_id: u64,
+    pub identity: PlayerIdentity,
+    pub display_name: String,
+    pub last_seen: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Admitted {
+    pub identity: PlayerIdentity,
+    // What they'll be shown as, suffixed if that's what it took
+    pub name: String,
+    // A ghost that held the name, its player still has to be despawned
+    pub evicted: Option<Session>,
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct Sessions {
+    next: u64,
+    sessions: HashMap<SessionId, Session>,
+    by_name: HashMap<String, SessionId>,
+}
+
+impl Sessions {
+    // A name is taken while anyone shows it or keeps their saved data under it
+    fn in_use(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+            || self
+                .sessions
+                .values()
+                .any(|session| session.identity.storage_key() == name)
+    }
+
+    fn free_suffix(&self, requested: &str) -> String {
+        (2..)
+            .map(|n| {
+                let suffix = format!("({n})");
+                let base = truncate_chars(requested, MAX_NAME_CHARS - suffix.chars().count());
+                format!("{base}{suffix}")
+            })
+            .find(|name| !self.in_use(name))
+            .unwrap()
+    }
+
+    // Whoever still holds the name is evicted first if they've gone quiet or lost their
+    // connection, so a reconnect after a crash isn't locked out by its own ghost
+    pub fn admit(
+        &mut self,
+        client_id: u64,
+        requested: &str,
+        policy: DuplicateNames,
+        now: f32,
+        connected: impl Fn(u64) -> bool,
+    ) -> Result<Admitted, JoinRejection> {
+        let ghost = self
+            .sessions
+            .values()
+            .find(|session| {
+                session.display_name == requested || session.identity.storage_key() == requested
+            })
+            .filter(|session| {
+                !connected(session.client_id) || now - session.last_seen > SESSION_TIMEOUT_SECS
+            })
+            .map(|session| session.client_id);
+        let evicted = ghost.and_then(|ghost| self.end(ghost));
+        let name = if !self.in_use(requested) {
+            requested.to_string()
+        } else {
+            match policy {
+                DuplicateNames::Reject => {
+                    return Err(JoinRejection::Denied {
+                        reason: "name already connected".to_string(),
+                    })
+                }
+                DuplicateNames::Suffix => self.free_suffix(requested),
+            }
+        };
+        self.next += 1;
+        let identity = PlayerIdentity {
+            session: SessionId(self.next),
+            name: name.clone(),
+        };
+        self.by_name.insert(name.clone(), identity.session);
+        self.sessions.insert(
+            identity.session,
+            Session {
+                client_id,
+                identity: identity.clone(),
+                display_name: name.clone(),
+                last_seen: now,
+            },
+        );
+        Ok(Admitted {
+            identity,
+            name,
+            evicted,
+        })
+    }
+
+    pub fn touch(&mut self, client_id: u64, now: f32) {
+        if let Some(session) = self
+            .sessions
+            .values_mut()
+            .find(|session| session.client_id == client_id)
+        {
+            session.last_seen = now;
+        }
+    }
+
+    pub fn end(&mut self, client_id: u64) -> Option<Session> {
+        let id = self.session_of(client_id)?;
+        let session = self.sessions.remove(&id)?;
+        self.by_name.remove(&session.display_name);
+        Some(session)
+    }
+
+    pub fn session_of(&self, client_id: u64) -> Option<SessionId> {
+        self.sessions
+            .values()
+            .find(|session| session.client_id == client_id)
+            .map(|session| session.identity.session)
+    }
+
+    // Who to look for in saved data when someone is named, whatever they're shown as right now
+    pub fn storage_key_of<'a>(&'a self, name: &'a str) -> &'a str {
+        self.by_name
+            .get(name)
+            .and_then(|id| self.sessions.get(id))
+            .map_or(name, |session| session.identity.storage_key())
+    }
+
+    // Only the shown name changes, the session and saved data stay where they are
+    #[allow(dead_code)]
+    pub fn rename(&mut self, id: SessionId, name: &str) -> bool {
+        if self.in_use(name) {
+            return false;
+        }
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return false;
+        };
+        self.by_name.remove(&session.display_name);
+        session.display_name = name.to_string();
+        self.by_name.insert(name.to_string(), id);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::world::{
+        dropped::DroppedItem,
+        edits::{rollback_chunk, BlockEdit, EditLog},
+    };
+    use vinox_common::{
+        storage::items::descriptor::ItemData,
+        world::chunks::storage::{BlockData, BlockTable, ChunkData},
+    };
+
+    fn admit(
+        sessions: &mut Sessions,
+        client_id: u64,
+        name: &str,
+        policy: DuplicateNames,
+    ) -> Result<Admitted, JoinRejection> {
+        sessions.admit(client_id, name, policy, 0.0, |_| true)
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected_by_default() {
+        let mut sessions = Sessions::default();
+        let alice = admit(&mut sessions, 1, "alice", DuplicateNames::default()).unwrap();
+        assert_eq!(alice.identity.storage_key(), "alice");
+        assert_eq!(
+            admit(&mut sessions, 2, "alice", DuplicateNames::Reject),
+            Err(JoinRejection::Denied {
+                reason: "name already connected".to_string()
+            })
+        );
+        // The one already playing keeps their session
+        assert_eq!(sessions.session_of(1), Some(alice.identity.session));
+        assert_eq!(sessions.session_of(2), None);
+
+        // Once they leave the name is free again, under a new session
+        sessions.end(1);
+        let again = admit(&mut sessions, 2, "alice", DuplicateNames::Reject).unwrap();
+        assert_ne!(again.identity.session, alice.identity.session);
+    }
+
+    #[test]
+    fn duplicate_names_get_the_next_free_suffix() {
+        let mut sessions = Sessions::default();
+        let names: Vec<String> = (1..=3)
+            .map(|client_id| {
+                admit(&mut sessions, client_id, "bob", DuplicateNames::Suffix)
+                    .unwrap()
+                    .identity
+                    .storage_key()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(names, vec!["bob", "bob(2)", "bob(3)"]);
+
+        // The suffix still fits in a name
+        let long = "x".repeat(MAX_NAME_CHARS);
+        admit(&mut sessions, 4, &long, DuplicateNames::Suffix).unwrap();
+        let suffixed = admit(&mut sessions, 5, &long, DuplicateNames::Suffix).unwrap();
+        let name = suffixed.identity.storage_key();
+        assert!(name.ends_with("(2)"));
+        assert_eq!(name.chars().count(), MAX_NAME_CHARS);
+    }
+
+    #[test]
+    fn ghost_sessions_are_evicted_by_the_same_name() {
+        let mut sessions = Sessions::default();
+        let ghost = admit(&mut sessions, 1, "carol", DuplicateNames::Reject).unwrap();
+        sessions.touch(1, 5.0);
+        // Still talking, so a second carol is turned away
+        assert!(sessions
+            .admit(
+                2,
+                "carol",
+                DuplicateNames::Reject,
+                5.0 + SESSION_TIMEOUT_SECS,
+                |_| true
+            )
+            .is_err());
+
+        let rejoined = sessions
+            .admit(
+                3,
+                "carol",
+                DuplicateNames::Reject,
+                6.0 + SESSION_TIMEOUT_SECS,
+                |_| true,
+            )
+            .unwrap();
+        assert_eq!(rejoined.evicted.map(|session| session.client_id), Some(1));
+        assert_ne!(rejoined.identity.session, ghost.identity.session);
+        assert_eq!(rejoined.identity.storage_key(), "carol");
+        assert_eq!(sessions.session_of(1), None);
+
+        // A connection that's already gone doesn't have to wait out the timeout
+        let rejoined = sessions
+            .admit(4, "carol", DuplicateNames::Reject, 17.0, |client_id| {
+                client_id != 3
+            })
+            .unwrap();
+        assert_eq!(rejoined.evicted.map(|session| session.client_id), Some(3));
+    }
+
+    #[test]
+    fn attribution_follows_the_session_across_a_rename() {
+        let mut sessions = Sessions::default();
+        let alice = admit(&mut sessions, 1, "alice", DuplicateNames::Reject)
+            .unwrap()
+            .identity;
+        let session = alice.session;
+
+        let block_table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        let mut log = EditLog::default();
+        let stone = BlockData::new("vinox".to_string(), "stone".to_string());
+        chunk.set(1, 1, 1, stone.clone(), &block_table);
+        log.push(
+            BlockEdit {
+                actor: alice.storage_key().to_string(),
+                time: 10,
+                voxel: [1, 1, 1],
+                previous: BlockData::default(),
+                new: stone,
+            },
+            u64::MAX,
+        );
+        let dropped = DroppedItem {
+            item: ItemData::default(),
+            dropped_by: Some(session),
+            age: 0.0,
+        };
+
+        assert!(sessions.rename(session, "bob"));
+        assert_eq!(sessions.session_of(1), Some(session));
+        assert!(!dropped.can_pick_up(session));
+        // Nobody else can take the old name while their saved data is still under it
+        assert!(admit(&mut sessions, 2, "alice", DuplicateNames::Reject).is_err());
+
+        let actor = sessions.storage_key_of("bob");
+        assert_eq!(actor, "alice");
+        let (report, _) = rollback_chunk(&mut chunk, &mut log, actor, 0, "mod", &block_table);
+        assert_eq!(report.reverted, 1);
+        assert_eq!(chunk.get(1, 1, 1), BlockData::default());
+    }
+}
@@ -1,4 +1,10 @@
+pub mod arrange;
+pub mod commands;
 pub mod components;
+pub mod console;
+pub mod identity;
+pub mod outgoing;
 pub mod plugin;
+pub mod recipes;
 pub mod start;
 pub mod syncing;
@@ -0,0 +1,490 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    sync::Arc,
+};
+
+use bevy::{prelude::*, tasks::Task};
+use bevy_quinnet::server::Server;
+use futures_lite::future;
+use vinox_common::{
+    ecs::rng::hash_bytes,
+    networking::protocol::{CachedChunk, Player, ServerMessage},
+    world::chunks::{
+        biome_map::ChunkBiomes,
+        ecs::{CurrentChunks, SentChunks},
+        positions::{ChunkPos, DimensionId},
+        storage::{BlockData, ChunkData, RawChunk},
+    },
+};
+use zstd::stream::copy_encode;
+
+use super::components::ServerLobby;
+
+// Chunks one client can have waiting on the pool. A player flying over fresh terrain would
+// otherwise fill it with their own chunks before anyone else got a turn
+pub const MAX_IN_FLIGHT: usize = 16;
+// Cached chunks one client can list, anything past it just gets sent in full
+pub const MAX_CLAIMS: usize = 65_536;
+
+pub type ChunkKey = (DimensionId, ChunkPos);
+
+// What LevelData carries and its hash. Uniform chunks skip all that and go out as just the block
+#[derive(Debug, PartialEq, Eq)]
+pub struct Prepared {
+    pub payload: Vec<u8>,
+    pub hash: u64,
+    pub biomes: ChunkBiomes,
+    pub uniform: Option<BlockData>,
+}
+
+// Run on the pool so the tick never waits on bincode or zstd
+pub fn prepare_chunk(raw_chunk: &RawChunk, biomes: ChunkBiomes) -> Option<Prepared> {
+    if let Some(block) = raw_chunk.uniform_voxel() {
+        return Some(Prepared {
+            payload: Vec::new(),
+            hash: 0,
+            biomes,
+            uniform: Some(block.clone()),
+        });
+    }
+    let raw_chunk_bin = bincode::serialize(raw_chunk).ok()?;
+    let mut output = Cursor::new(Vec::new());
+    copy_encode(&mut Cursor::new(raw_chunk_bin), &mut output, 0).ok()?;
+    let payload = output.into_inner();
+    Some(Prepared {
+        hash: hash_bytes(&payload),
+        payload,
+        biomes,
+        uniform: None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Queued {
+    // Prepared from this exact revision before, goes out right away
+    Ready(Arc<Prepared>),
+    // Someone else asked first, this client gets it when that job is done
+    Waiting,
+    // Nobody is working on it, the caller snapshots the chunk and starts a job
+    Start,
+}
+
+#[derive(Resource, Default)]
+pub struct OutgoingChunks {
+    // The latest payload of each loaded chunk with the revision it was made from
+    cache: HashMap<ChunkKey, (u64, Arc<Prepared>)>,
+    // Clients waiting on each running job
+    waiting: HashMap<(ChunkKey, u64), Vec<u64>>,
+    in_flight: HashMap<u64, HashSet<ChunkKey>>,
+    // What each client said it has cached and with which hash, used up as chunks go out
+    claims: HashMap<u64, HashMap<ChunkKey, u64>>,
+    // Joined with a chunk cache and hasn't finished listing it yet
+    awaiting_claims: HashSet<u64>,
+    // Said still valid but the client couldn't read them back
+    missed: HashMap<u64, Vec<ChunkKey>>,
+}
+
+impl OutgoingChunks {
+    pub fn room(&self, client: u64) -> usize {
+        if self.awaiting_claims.contains(&client) {
+            return 0;
+        }
+        MAX_IN_FLIGHT.saturating_sub(self.in_flight.get(&client).map_or(0, HashSet::len))
+    }
+
+    // Nothing goes to the client until its last CachedChunks
+    pub fn await_claims(&mut self, client: u64) {
+        self.awaiting_claims.insert(client);
+    }
+
+    pub fn claim(&mut self, client: u64, chunks: &[CachedChunk], done: bool) {
+        let claims = self.claims.entry(client).or_default();
+        for chunk in chunks {
+            if claims.len() >= MAX_CLAIMS {
+                break;
+            }
+            claims.insert((chunk.dimension, ChunkPos(chunk.pos)), chunk.hash);
+        }
+        if done {
+            self.awaiting_claims.remove(&client);
+        }
+    }
+
+    // Still valid when the client has exactly this payload cached, each claim only counts once.
+    // Uniform chunks are never cached so they always go out as themselves
+    pub fn message_for(
+        &mut self,
+        client: u64,
+        key: ChunkKey,
+        prepared: &Prepared,
+    ) -> ServerMessage {
+        let (dimension, pos) = key;
+        let claimed = self
+            .claims
+            .get_mut(&client)
+            .and_then(|claims| claims.remove(&key));
+        if let Some(block) = &prepared.uniform {
+            ServerMessage::UniformChunk {
+                pos: *pos,
+                dimension,
+                block: block.clone(),
+                biomes: prepared.biomes.clone(),
+            }
+        } else if claimed == Some(prepared.hash) {
+            ServerMessage::ChunkStillValid {
+                pos: *pos,
+                dimension,
+                biomes: prepared.biomes.clone(),
+            }
+        } else {
+            ServerMessage::LevelData {
+                chunk_data: prepared.payload.clone(),
+                pos: *pos,
+                dimension,
+                hash: prepared.hash,
+                biomes: prepared.biomes.clone(),
+            }
+        }
+    }
+
+    pub fn miss(&mut self, client: u64, key: ChunkKey) {
+        self.missed.entry(client).or_default().push(key);
+    }
+
+    // Chunks to send the client again in full
+    pub fn take_missed(&mut self, client: u64) -> Vec<ChunkKey> {
+        self.missed.remove(&client).unwrap_or_default()
+    }
+
+    pub fn is_pending(&self, client: u64, key: ChunkKey) -> bool {
+        self.in_flight
+            .get(&client)
+            .is_some_and(|pending| pending.contains(&key))
+    }
+
+    pub fn request(&mut self, client: u64, key: ChunkKey, revision: u64) -> Queued {
+        if let Some((cached, payload)) = self.cache.get(&key) {
+            if *cached == revision {
+                return Queued::Ready(payload.clone());
+            }
+        }
+        self.in_flight.entry(client).or_default().insert(key);
+        let waiting = self.waiting.entry((key, revision)).or_default();
+        waiting.push(client);
+        if waiting.len() == 1 {
+            Queued::Start
+        } else {
+            Queued::Waiting
+        }
+    }
+
+    // Frees up everyone waiting on the job. The payload only goes out when the chunk is still
+    // at the revision it was snapshotted at, an edit since then means it gets picked again
+    pub fn finish(
+        &mut self,
+        key: ChunkKey,
+        revision: u64,
+        payload: Option<Prepared>,
+        current: Option<u64>,
+    ) -> Option<(Arc<Prepared>, Vec<u64>)> {
+        let clients = self.waiting.remove(&(key, revision)).unwrap_or_default();
+        for client in clients.iter() {
+            if let Some(pending) = self.in_flight.get_mut(client) {
+                pending.remove(&key);
+            }
+        }
+        let payload = Arc::new(payload?);
+        // Revisions only go up, a slow job doesn't get to replace a newer one
+        if self
+            .cache
+            .get(&key)
+            .is_none_or(|(cached, _)| *cached < revision)
+        {
+            self.cache.insert(key, (revision, payload.clone()));
+        }
+        (current == Some(revision)).then_some((payload, clients))
+    }
+
+    pub fn retain_chunks(&mut self, mut loaded: impl FnMut(&ChunkKey) -> bool) {
+        self.cache.retain(|key, _| loaded(key));
+    }
+
+    pub fn retain_clients(&mut self, mut connected: impl FnMut(u64) -> bool) {
+        self.in_flight.retain(|client, _| connected(*client));
+        self.claims.retain(|client, _| connected(*client));
+        self.awaiting_claims.retain(|client| connected(*client));
+        self.missed.retain(|client, _| connected(*client));
+        for clients in self.waiting.values_mut() {
+            clients.retain(|client| connected(*client));
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PrepareTask(pub Task<(ChunkKey, u64, Option<Prepared>)>);
+
+pub fn drain_chunks(
+    mut commands: Commands,
+    mut server: ResMut<Server>,
+    mut outgoing: ResMut<OutgoingChunks>,
+    mut tasks: Query<(Entity, &mut PrepareTask)>,
+    lobby: Res<ServerLobby>,
+    mut players: Query<(&mut SentChunks, &DimensionId), With<Player>>,
+    (current_chunks, chunks): (Res<CurrentChunks>, Query<&ChunkData>),
+) {
+    outgoing.retain_clients(|client| lobby.players.contains_key(&client));
+    outgoing
+        .retain_chunks(|(dimension, pos)| current_chunks.get_entity_in(*dimension, *pos).is_some());
+    let endpoint = server.endpoint_mut();
+    for (entity, mut task) in tasks.iter_mut() {
+        let Some((key, revision, payload)) = future::block_on(future::poll_once(&mut task.0))
+        else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        let (dimension, pos) = key;
+        let current = current_chunks
+            .get_entity_in(dimension, pos)
+            .and_then(|chunk| chunks.get(chunk).ok())
+            .map(ChunkData::revision);
+        let Some((payload, clients)) = outgoing.finish(key, revision, payload, current) else {
+            continue;
+        };
+        for client_id in clients {
+            let Some(player_entity) = lobby.players.get(&client_id) else {
+                continue;
+            };
+            let Ok((mut sent_chunks, player_dimension)) = players.get_mut(*player_entity) else {
+                continue;
+            };
+            // Changed dimension while it was being prepared
+            if *player_dimension != dimension {
+                continue;
+            }
+            let message = outgoing.message_for(client_id, key, &payload);
+            if endpoint.send_message(client_id, message).is_ok() {
+                sent_chunks.chunks.insert(pos);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::{testing::block, world::chunks::storage::BlockTable};
+
+    fn decode(payload: &[u8]) -> ChunkData {
+        let raw_chunk_bin = zstd::stream::decode_all(payload).unwrap();
+        ChunkData::from_raw(bincode::deserialize(&raw_chunk_bin).unwrap())
+    }
+
+    #[test]
+    fn one_preparation_serves_every_client() {
+        let block_table = BlockTable::default();
+        let mut outgoing = OutgoingChunks::default();
+        let mut chunk = ChunkData::default();
+        chunk.set(1, 2, 3, block("stone"), &block_table);
+        let key = (DimensionId::default(), ChunkPos::new(0, 0, 0));
+        let revision = chunk.revision();
+
+        assert_eq!(outgoing.request(1, key, revision), Queued::Start);
+        for client in 2..=5 {
+            assert_eq!(outgoing.request(client, key, revision), Queued::Waiting);
+            assert!(outgoing.is_pending(client, key));
+        }
+        assert_eq!(outgoing.room(1), MAX_IN_FLIGHT - 1);
+        let payload = prepare_chunk(&chunk.to_raw(), ChunkBiomes::default());
+        let (payload, clients) = outgoing
+            .finish(key, revision, payload, Some(revision))
+            .unwrap();
+        assert_eq!(clients, vec![1, 2, 3, 4, 5]);
+        assert_eq!(outgoing.room(1), MAX_IN_FLIGHT);
+        assert_eq!(decode(&payload.payload).get(1, 2, 3), block("stone"));
+
+        // Asked again later it comes straight out of the cache
+        assert_eq!(
+            outgoing.request(6, key, revision),
+            Queued::Ready(payload.clone())
+        );
+        assert!(!outgoing.is_pending(6, key));
+
+        // Any edit is a new revision and misses
+        chunk.set(1, 2, 3, block("dirt"), &block_table);
+        assert_ne!(chunk.revision(), revision);
+        assert_eq!(outgoing.request(6, key, chunk.revision()), Queued::Start);
+
+        // Unloading the chunk drops what was cached for it
+        outgoing.retain_chunks(|_| false);
+        assert_eq!(outgoing.request(7, key, revision), Queued::Start);
+    }
+
+    #[test]
+    fn claimed_chunks_go_out_as_still_valid() {
+        let block_table = BlockTable::default();
+        let mut outgoing = OutgoingChunks::default();
+        let mut chunk = ChunkData::default();
+        chunk.set(1, 2, 3, block("stone"), &block_table);
+        let prepared = prepare_chunk(&chunk.to_raw(), ChunkBiomes::default()).unwrap();
+        let dimension = DimensionId::default();
+        let fresh = (dimension, ChunkPos::new(0, 0, 0));
+        let stale = (dimension, ChunkPos::new(1, 0, 0));
+        let unclaimed = (dimension, ChunkPos::new(2, 0, 0));
+        let cached = |(dimension, pos): ChunkKey, hash| CachedChunk {
+            dimension,
+            pos: *pos,
+            hash,
+        };
+
+        // Nothing goes out until the last of the list is in
+        outgoing.await_claims(1);
+        assert_eq!(outgoing.room(1), 0);
+        outgoing.claim(1, &[cached(fresh, prepared.hash)], false);
+        assert_eq!(outgoing.room(1), 0);
+        outgoing.claim(1, &[cached(stale, prepared.hash ^ 1)], true);
+        assert_eq!(outgoing.room(1), MAX_IN_FLIGHT);
+
+        assert!(matches!(
+            outgoing.message_for(1, fresh, &prepared),
+            ServerMessage::ChunkStillValid { pos, .. } if pos == *fresh.1
+        ));
+        for key in [stale, unclaimed] {
+            let ServerMessage::LevelData {
+                chunk_data, hash, ..
+            } = outgoing.message_for(1, key, &prepared)
+            else {
+                panic!("{key:?} wasn't sent in full");
+            };
+            assert_eq!(chunk_data, prepared.payload);
+            assert_eq!(hash, hash_bytes(&chunk_data));
+        }
+        // A claim only counts once, the client's copy may be behind by the next time
+        assert!(matches!(
+            outgoing.message_for(1, fresh, &prepared),
+            ServerMessage::LevelData { .. }
+        ));
+        // Claims are per client and per dimension
+        outgoing.claim(2, &[cached((DimensionId(1), fresh.1), prepared.hash)], true);
+        assert!(matches!(
+            outgoing.message_for(2, fresh, &prepared),
+            ServerMessage::LevelData { .. }
+        ));
+
+        // A client that couldn't read one back gets it again, once
+        outgoing.miss(1, fresh);
+        assert_eq!(outgoing.take_missed(1), vec![fresh]);
+        assert!(outgoing.take_missed(1).is_empty());
+
+        // Leaving before the list was done doesn't leave anything waiting
+        outgoing.await_claims(3);
+        outgoing.claim(3, &[cached(fresh, prepared.hash)], false);
+        outgoing.retain_clients(|client| client != 3);
+        assert_eq!(outgoing.room(3), MAX_IN_FLIGHT);
+        assert!(matches!(
+            outgoing.message_for(3, fresh, &prepared),
+            ServerMessage::LevelData { .. }
+        ));
+    }
+
+    #[test]
+    fn edits_during_preparation_never_tear() {
+        let block_table = BlockTable::default();
+        let mut outgoing = OutgoingChunks::default();
+        let mut chunk = ChunkData::default();
+        for x in 0..ChunkData::edge() as u32 {
+            chunk.set(x, 0, 0, block("stone"), &block_table);
+        }
+        let key = (DimensionId::default(), ChunkPos::new(4, -1, 2));
+        let revision = chunk.revision();
+        assert_eq!(outgoing.request(1, key, revision), Queued::Start);
+
+        // The job owns its copy, edits to the live chunk can't reach it
+        let snapshot = chunk.to_raw();
+        let job = std::thread::spawn(move || prepare_chunk(&snapshot, ChunkBiomes::default()));
+        for x in 0..ChunkData::edge() as u32 {
+            chunk.set(x, 0, 0, block("dirt"), &block_table);
+        }
+        let payload = job.join().unwrap();
+        let old = decode(&payload.as_ref().unwrap().payload);
+        for x in 0..ChunkData::edge() as u32 {
+            assert_eq!(old.get(x, 0, 0), block("stone"));
+        }
+
+        // The chunk moved on, so it isn't sent and the client is free to ask again
+        assert_eq!(
+            outgoing.finish(key, revision, payload, Some(chunk.revision())),
+            None
+        );
+        assert!(!outgoing.is_pending(1, key));
+        assert_eq!(outgoing.request(1, key, chunk.revision()), Queued::Start);
+        let payload = prepare_chunk(&chunk.to_raw(), ChunkBiomes::default());
+        let (payload, clients) = outgoing
+            .finish(key, chunk.revision(), payload, Some(chunk.revision()))
+            .unwrap();
+        assert_eq!(clients, vec![1]);
+        let new = decode(&payload.payload);
+        for x in 0..ChunkData::edge() as u32 {
+            assert_eq!(new.get(x, 0, 0), block("dirt"));
+        }
+
+        // A stale job finishing late doesn't push the newer payload out of the cache
+        assert_eq!(outgoing.finish(key, revision, None, None), None);
+        assert_eq!(
+            outgoing.request(2, key, chunk.revision()),
+            Queued::Ready(payload)
+        );
+    }
+
+    #[test]
+    fn chunks_survive_the_wire() {
+        let block_table = BlockTable::default();
+        let mut outgoing = OutgoingChunks::default();
+        let key = (DimensionId(1), ChunkPos::new(0, -2, 5));
+        let wire = |message: ServerMessage| -> ServerMessage {
+            bincode::deserialize(&bincode::serialize(&message).unwrap()).unwrap()
+        };
+
+        let mut mixed = ChunkData::default();
+        mixed.set(1, 2, 3, block("stone"), &block_table);
+        mixed.set(15, 0, 9, block("dirt"), &block_table);
+        let prepared = prepare_chunk(&mixed.to_raw(), ChunkBiomes::default()).unwrap();
+        let ServerMessage::LevelData { chunk_data, .. } =
+            wire(outgoing.message_for(1, key, &prepared))
+        else {
+            panic!("a mixed chunk wasn't sent in full");
+        };
+        let received = decode(&chunk_data);
+        assert_eq!(received.get(1, 2, 3), block("stone"));
+        assert_eq!(received.get(15, 0, 9), block("dirt"));
+        assert_eq!(received.get(0, 0, 0), mixed.get(0, 0, 0));
+
+        // Air and ocean chunks go out as just the block, even to a client claiming them
+        let ocean = ChunkData::uniform(block("water"), &block_table);
+        let prepared = prepare_chunk(&ocean.to_raw(), ChunkBiomes::default()).unwrap();
+        assert!(prepared.payload.is_empty());
+        outgoing.claim(
+            1,
+            &[CachedChunk {
+                dimension: key.0,
+                pos: *key.1,
+                hash: prepared.hash,
+            }],
+            true,
+        );
+        let ServerMessage::UniformChunk {
+            pos,
+            dimension,
+            block: sent,
+            ..
+        } = wire(outgoing.message_for(1, key, &prepared))
+        else {
+            panic!("a uniform chunk was sent in full");
+        };
+        assert_eq!((dimension, pos), (key.0, *key.1));
+        let received = ChunkData::from_raw(RawChunk::uniform(sent, &block_table));
+        assert!(received.is_uniform());
+        assert_eq!(received.get(7, 7, 7), block("water"));
+        assert_eq!(received.heightmap(), ocean.heightmap());
+    }
+}
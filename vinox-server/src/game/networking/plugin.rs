@@ -1,9 +1,25 @@
 use bevy::prelude::*;
 
+use crate::game::{load::ServerFixedUpdate, world::chunk::process_save};
+
 use super::{
-    components::ServerLobby,
+    arrange::{apply_arrangements, InventoryIntentEvent},
+    commands::{
+        find_command, fly_command, forceload_command, gamerule_command, recipe_command,
+        rename_command, rollback_command, say_command, shutdown, spawn_command, spawnpoint_command,
+        spawnrules_command, status_command, stop_command, unknown_command, ChatCommandEvent,
+        ShutdownEvent,
+    },
+    components::{BlockBreaks, ItemUses, RejectedClients, ServerLobby},
+    console::{read_console, ConsoleChannel},
+    identity::Sessions,
+    outgoing::{drain_chunks, OutgoingChunks},
+    recipes::{load_recipe_books, recipe_triggers, RecipeTriggerEvent},
     start::{new_server, setup_loadables},
-    syncing::{connections, get_messages, send_chunks, send_entities},
+    syncing::{
+        change_dimension, connections, get_messages, send_chunks, send_entities, send_stats,
+        sync_entities, unsend_chunks, ChangeDimensionEvent,
+    },
 };
 
 pub struct NetworkingPlugin;
@@ -11,13 +27,59 @@ pub struct NetworkingPlugin;
 impl Plugin for NetworkingPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ServerLobby::default())
+            .insert_resource(ItemUses::default())
+            .insert_resource(BlockBreaks::default())
+            .insert_resource(RejectedClients::default())
+            .insert_resource(Sessions::default())
+            .insert_resource(OutgoingChunks::default())
             .add_startup_system(setup_loadables)
             .add_startup_system(new_server)
             .add_systems(
-                (send_chunks, send_entities)
+                (
+                    unsend_chunks,
+                    send_chunks,
+                    drain_chunks,
+                    sync_entities,
+                    send_entities,
+                )
                     .chain()
-                    .in_schedule(CoreSchedule::FixedUpdate),
+                    .in_schedule(ServerFixedUpdate),
+            )
+            .add_event::<ChangeDimensionEvent>()
+            .add_event::<ChatCommandEvent>()
+            .add_event::<ShutdownEvent>()
+            .add_event::<RecipeTriggerEvent>()
+            .add_event::<InventoryIntentEvent>()
+            .add_systems((get_messages, connections, change_dimension, send_stats))
+            .add_systems((load_recipe_books, recipe_triggers.after(get_messages)))
+            .add_system(apply_arrangements.after(get_messages))
+            // Only the dedicated server reads stdin
+            .add_system(read_console.run_if(resource_exists::<ConsoleChannel>()))
+            .add_systems(
+                (
+                    find_command,
+                    fly_command,
+                    forceload_command,
+                    gamerule_command,
+                    recipe_command,
+                    rename_command,
+                    rollback_command,
+                    say_command,
+                    spawn_command,
+                    spawnpoint_command,
+                    spawnrules_command,
+                    status_command,
+                    stop_command,
+                    unknown_command,
+                )
+                    .after(get_messages)
+                    .after(read_console),
             )
-            .add_systems((get_messages, connections));
+            .add_system(
+                shutdown
+                    .after(stop_command)
+                    .after(rollback_command)
+                    .before(process_save),
+            );
     }
 }
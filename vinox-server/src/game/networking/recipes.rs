@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_quinnet::server::*;
+use vinox_common::{
+    ecs::bundles::Inventory,
+    networking::protocol::{Player, ServerMessage},
+    storage::crafting::descriptor::RecipeUnlock,
+    world::chunks::storage::{ItemTable, RecipeTable},
+};
+
+use crate::game::world::storage::{load_unlocked_recipes, RecipesToSave, WorldDatabase};
+
+use super::identity::PlayerIdentity;
+
+// Recipes a player has unlocked, saved under their storage key. Always recipes aren't stored
+#[derive(Component, Default, Debug, Clone, PartialEq, Eq, Deref, DerefMut)]
+pub struct UnlockedRecipes(pub HashSet<String>);
+
+// Triggers keyed by whatever fires them so an inventory change never walks every recipe
+#[derive(Resource, Default, Debug)]
+pub struct RecipeIndex {
+    always: HashSet<String>,
+    by_item: HashMap<String, Vec<String>>,
+    by_recipe: HashMap<String, Vec<String>>,
+}
+
+impl RecipeIndex {
+    pub fn new(recipe_table: &RecipeTable) -> Self {
+        let mut index = RecipeIndex::default();
+        for (identifier, recipe) in recipe_table.iter() {
+            match recipe.unlocked_by.clone().unwrap_or_default() {
+                RecipeUnlock::Always => {
+                    index.always.insert(identifier.clone());
+                }
+                RecipeUnlock::ObtainItem(item) => {
+                    index
+                        .by_item
+                        .entry(item)
+                        .or_default()
+                        .push(identifier.clone());
+                }
+                RecipeUnlock::Crafted(other) => {
+                    index
+                        .by_recipe
+                        .entry(other)
+                        .or_default()
+                        .push(identifier.clone());
+                }
+            }
+        }
+        index
+    }
+
+    pub fn is_unlocked(&self, recipe: &str, unlocked: &UnlockedRecipes) -> bool {
+        self.always.contains(recipe) || unlocked.contains(recipe)
+    }
+
+    // Both return only what wasn't unlocked before
+    pub fn obtained(&self, item: &str, unlocked: &mut UnlockedRecipes) -> Vec<String> {
+        unlock(self.by_item.get(item), unlocked)
+    }
+
+    pub fn crafted(&self, recipe: &str, unlocked: &mut UnlockedRecipes) -> Vec<String> {
+        unlock(self.by_recipe.get(recipe), unlocked)
+    }
+}
+
+fn unlock(recipes: Option<&Vec<String>>, unlocked: &mut UnlockedRecipes) -> Vec<String> {
+    let mut newly_unlocked = Vec::new();
+    for recipe in recipes.into_iter().flatten() {
+        if unlocked.insert(recipe.clone()) {
+            newly_unlocked.push(recipe.clone());
+        }
+    }
+    newly_unlocked
+}
+
+pub enum RecipeTrigger {
+    Obtained(String),
+    Craft(String),
+}
+
+pub struct RecipeTriggerEvent {
+    pub client_id: u64,
+    pub entity: Entity,
+    pub trigger: RecipeTrigger,
+}
+
+pub fn announce_unlocks(server: &mut Server, client_id: u64, ids: Vec<String>) {
+    server.endpoint_mut().try_send_message(
+        client_id,
+        ServerMessage::RecipesUnlocked {
+            ids,
+            announce: true,
+        },
+    );
+}
+
+pub fn load_recipe_books(
+    mut commands: Commands,
+    mut server: ResMut<Server>,
+    players: Query<(Entity, &Player, &PlayerIdentity), Without<UnlockedRecipes>>,
+    database: Res<WorldDatabase>,
+) {
+    for (entity, player, identity) in players.iter() {
+        let unlocked =
+            load_unlocked_recipes(identity.storage_key(), &database.connection.get().unwrap());
+        server.endpoint_mut().try_send_message(
+            player.id,
+            ServerMessage::RecipesUnlocked {
+                ids: unlocked.iter().cloned().collect(),
+                announce: false,
+            },
+        );
+        commands.entity(entity).insert(UnlockedRecipes(unlocked));
+    }
+}
+
+pub fn recipe_triggers(
+    mut server: ResMut<Server>,
+    mut events: EventReader<RecipeTriggerEvent>,
+    mut players: Query<(&PlayerIdentity, &mut UnlockedRecipes, &mut Inventory)>,
+    index: Res<RecipeIndex>,
+    (recipe_table, item_table): (Res<RecipeTable>, Res<ItemTable>),
+    mut recipes_to_save: ResMut<RecipesToSave>,
+) {
+    for evt in events.iter() {
+        let Ok((identity, mut unlocked, mut inventory)) = players.get_mut(evt.entity) else {
+            continue;
+        };
+        let newly_unlocked = match &evt.trigger {
+            RecipeTrigger::Obtained(item) => index.obtained(item, &mut unlocked),
+            RecipeTrigger::Craft(recipe) => {
+                // Whatever the client shows, a locked recipe or a missing ingredient never goes
+                // through. The ingredients come out of our copy of the inventory
+                let descriptor = recipe_table.get(recipe);
+                let accepted = index.is_unlocked(recipe, &unlocked)
+                    && descriptor
+                        .is_some_and(|descriptor| inventory.craft(descriptor, &item_table));
+                server.endpoint_mut().try_send_message(
+                    evt.client_id,
+                    ServerMessage::CraftResult {
+                        recipe: recipe.clone(),
+                        accepted,
+                    },
+                );
+                match descriptor.filter(|_| accepted) {
+                    Some(descriptor) => {
+                        let mut newly_unlocked = index.crafted(recipe, &mut unlocked);
+                        newly_unlocked
+                            .extend(index.obtained(&descriptor.output_item.0, &mut unlocked));
+                        newly_unlocked
+                    }
+                    None => Vec::new(),
+                }
+            }
+        };
+        if !newly_unlocked.is_empty() {
+            recipes_to_save.push((identity.storage_key().to_string(), unlocked.0.clone()));
+            announce_unlocks(&mut server, evt.client_id, newly_unlocked);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::storage::crafting::descriptor::RecipeDescriptor;
+
+    fn recipe_table() -> RecipeTable {
+        let mut recipe_table = RecipeTable::default();
+        for (name, unlocked_by) in [
+            ("planks", None),
+            (
+                "stick",
+                Some(RecipeUnlock::ObtainItem("vinox:wood".to_string())),
+            ),
+            (
+                "torch",
+                Some(RecipeUnlock::Crafted("vinox:stick".to_string())),
+            ),
+            (
+                "chest",
+                Some(RecipeUnlock::ObtainItem("vinox:wood".to_string())),
+            ),
+        ] {
+            recipe_table.insert(
+                format!("vinox:{name}"),
+                RecipeDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    unlocked_by,
+                    ..Default::default()
+                },
+            );
+        }
+        recipe_table
+    }
+
+    #[test]
+    fn always_recipes_need_nothing() {
+        let index = RecipeIndex::new(&recipe_table());
+        let unlocked = UnlockedRecipes::default();
+        assert!(index.is_unlocked("vinox:planks", &unlocked));
+        assert!(!index.is_unlocked("vinox:stick", &unlocked));
+        assert!(!index.is_unlocked("vinox:missing", &unlocked));
+    }
+
+    #[test]
+    fn obtaining_items_unlocks_once() {
+        let index = RecipeIndex::new(&recipe_table());
+        let mut unlocked = UnlockedRecipes::default();
+        assert!(index.obtained("vinox:dirt", &mut unlocked).is_empty());
+        let mut newly_unlocked = index.obtained("vinox:wood", &mut unlocked);
+        newly_unlocked.sort();
+        assert_eq!(newly_unlocked, ["vinox:chest", "vinox:stick"]);
+        assert!(index.is_unlocked("vinox:stick", &unlocked));
+        // Picking up more wood doesn't announce anything again
+        assert!(index.obtained("vinox:wood", &mut unlocked).is_empty());
+    }
+
+    #[test]
+    fn crafting_unlocks_followups() {
+        let index = RecipeIndex::new(&recipe_table());
+        let mut unlocked = UnlockedRecipes::default();
+        assert!(index.crafted("vinox:planks", &mut unlocked).is_empty());
+        assert_eq!(index.crafted("vinox:stick", &mut unlocked), ["vinox:torch"]);
+        assert!(index.is_unlocked("vinox:torch", &unlocked));
+        assert!(index.crafted("vinox:stick", &mut unlocked).is_empty());
+    }
+}
@@ -4,17 +4,36 @@ use bevy::prelude::*;
 use bevy_quinnet::server::*;
 use vinox_common::{
     storage::{
+        biomes::load::load_all_biomes,
         blocks::load::load_all_blocks,
+        content::ContentManifest,
         crafting::load::load_all_recipes,
+        geometry::load::load_all_geo,
         items::load::{item_from_block, load_all_items},
+        structures::load::load_all_structures,
+    },
+    world::{
+        chunks::{
+            occlusion::LightOcclusion,
+            storage::{
+                name_to_identifier, BiomeTable, BlockTable, ItemTable, RecipeTable, StructureTable,
+            },
+        },
+        transitions::TransitionTable,
     },
-    world::chunks::storage::{BlockTable, ItemTable, RecipeTable},
 };
 
+use crate::game::world::generation::DECORATION_MARGIN;
+
+use super::recipes::RecipeIndex;
+
 pub fn setup_loadables(
+    mut commands: Commands,
     mut block_table: ResMut<BlockTable>,
     mut item_table: ResMut<ItemTable>,
     mut recipe_table: ResMut<RecipeTable>,
+    mut biome_table: ResMut<BiomeTable>,
+    mut structure_table: ResMut<StructureTable>,
 ) {
     for block in load_all_blocks() {
         let mut name = block.clone().namespace;
@@ -33,12 +52,41 @@ pub fn setup_loadables(
         name.push_str(&recipe.name);
         recipe_table.insert(name, recipe);
     }
+    for biome in load_all_biomes() {
+        biome_table.insert(
+            name_to_identifier(biome.namespace.clone(), biome.name.clone()),
+            biome,
+        );
+    }
+    // Anything reaching further than decorations can write would come out cut off
+    for structure in load_all_structures() {
+        let identifier = name_to_identifier(structure.namespace.clone(), structure.name.clone());
+        if structure.reach() >= DECORATION_MARGIN {
+            println!("Skipping structure {identifier}, it reaches past {DECORATION_MARGIN} blocks");
+            continue;
+        }
+        structure_table.insert(identifier, structure);
+    }
     for item in load_all_items() {
         let mut name = item.clone().namespace;
         name.push(':');
         name.push_str(&item.name);
         item_table.insert(name, item);
     }
+    // Broken rules only turn transitions off, the blocks themselves still load
+    match TransitionTable::new(&block_table) {
+        Ok(transitions) => commands.insert_resource(transitions),
+        Err(e) => println!("Block transitions are turned off: {e}"),
+    }
+    let geometry = load_all_geo();
+    commands.insert_resource(LightOcclusion::new(&block_table, &geometry));
+    commands.insert_resource(RecipeIndex::new(&recipe_table));
+    commands.insert_resource(ContentManifest::new(
+        &block_table,
+        &item_table,
+        &recipe_table,
+        &geometry,
+    ));
 }
 
 pub fn new_server(mut server: ResMut<Server>) {
@@ -1,25 +1,78 @@
-use std::io::Cursor;
-
 use rand::seq::SliceRandom;
-use rustc_data_structures::stable_set::FxHashSet;
 
-use bevy::{app::AppExit, prelude::*};
+use bevy::{app::AppExit, prelude::*, tasks::AsyncComputeTaskPool};
 use bevy_quinnet::server::*;
 use vinox_common::{
-    ecs::bundles::{ClientName, Inventory, PlayerBundleBuilder},
-    networking::protocol::{ClientMessage, NetworkedEntities, Player, ServerMessage},
-    world::chunks::{
-        ecs::{ChunkManager, CurrentChunks, SentChunks},
-        positions::{world_to_chunk, ChunkPos},
-        storage::{BlockTable, ChunkData},
+    ecs::{
+        arrange::max_stack_size,
+        bundles::{
+            ClientName, Health, Hunger, Inventory, InventorySection, PlayerBundleBuilder, SlotRef,
+        },
+        gameplay::GameplayRules,
+        rng::WorldRng,
+        time::ServerTick,
+    },
+    networking::protocol::{
+        truncate_chars, valid_user_name, ChatCategory, ClientMessage, DenyReason, EntityKind,
+        JoinRejection, LeftHanded, NetworkedEntities, Player, PlayerFlags, ServerMessage,
+        MAX_CHAT_CHARS, MAX_NAME_CHARS, PROTOCOL_VERSION,
+    },
+    storage::{
+        content::ContentManifest,
+        items::descriptor::{wear_slot, ItemData, MAX_STACK_SIZE},
+    },
+    world::{
+        chunks::{
+            biome_map::ChunkBiomes,
+            ecs::{ChunkManager, CurrentChunks, SentChunks, ViewRadius},
+            positions::{voxel_to_global_voxel, world_to_chunk, ChunkPos, DimensionId},
+            storage::{
+                name_to_identifier, trim_geo_identifier, BiomeTable, BlockData, BlockTable,
+                ChunkData, ItemTable, VoxelVisibility,
+            },
+        },
+        frames::broken_frame_drop,
+        templates::sanitize_template,
+        transitions::TransitionTable,
     },
 };
-use zstd::stream::copy_encode;
 
-use crate::game::world::{chunk::LoadPoint, storage::ChunksToSave};
+use crate::game::{
+    audit::{AuditEvent, BlockEditEntry, JoinEntry, LeaveEntry, LeaveReason},
+    schedule::{Scheduler, RESTARTING_SOON},
+    world::{
+        chunk::{LoadPoint, PlayerViewRadius},
+        critter::Critter,
+        dropped::{
+            clamp_drop, spawn_dropped_item, DroppedItem, DROP_DISTANCE, DROP_LIFT, DROP_SPEED,
+            EYE_HEIGHT, PICKUP_RADIUS, PICKUP_SLACK,
+        },
+        edits::{now_secs, BlockEdit, EditLog},
+        frames::{frame_item, UseFrameEvent},
+        safe_spawn::world_spawn,
+        seats::{DismountEvent, Seats},
+        snapshots::ChunkSnapshots,
+        spawn::UseBlockEvent,
+        storage::{ChunksToSave, EditLogsToSave, SnapshotsToSave, WorldInfo},
+        tools::wear_on_edit,
+        transitions::BlockChangedEvent,
+        vitals::Vitals,
+    },
+};
 
-use super::components::{ChunkLimit, LocalGame, ServerLobby};
+use super::{
+    arrange::{ArrangeCursor, InventoryIntent, InventoryIntentEvent},
+    commands::{is_operator, ChatCommandEvent, CommandSender},
+    components::{
+        BlockBreaks, ChunkLimit, Flight, ItemUses, KnownEntities, LocalGame, RejectedClients,
+        ServerLobby, MAX_PLAYERS,
+    },
+    identity::{PlayerIdentity, Session, Sessions},
+    outgoing::{prepare_chunk, OutgoingChunks, PrepareTask, Queued},
+    recipes::{RecipeTrigger, RecipeTriggerEvent},
+};
 
+#[allow(clippy::too_many_arguments)]
 pub fn connections(
     mut commands: Commands,
     mut server: ResMut<Server>,
@@ -28,13 +81,34 @@ pub fn connections(
     mut connection_lost_events: EventReader<ConnectionLostEvent>,
     local_game: Res<LocalGame>,
     mut exit: EventWriter<AppExit>,
+    mut rejected: ResMut<RejectedClients>,
+    time: Res<Time>,
+    (mut sessions, mut item_uses, mut block_breaks, mut audit): (
+        ResMut<Sessions>,
+        ResMut<ItemUses>,
+        ResMut<BlockBreaks>,
+        EventWriter<AuditEvent>,
+    ),
 ) {
+    let now = time.elapsed_seconds();
+    rejected.retain(|(id, deadline)| {
+        if *deadline > now {
+            return true;
+        }
+        server.endpoint_mut().disconnect_client(*id).ok();
+        false
+    });
     for client in connection_lost_events.iter() {
         let id = client.id;
         if **local_game {
             exit.send(AppExit);
         } else {
             println!("Player {id} disconnected.");
+            if let Some(session) = sessions.end(id) {
+                item_uses.forget(session.identity.session);
+                block_breaks.forget(session.identity.session);
+                audit.send(leave_event(&session, LeaveReason::ConnectionLost));
+            }
             if let Some(player_entity) = lobby.players.remove(&id) {
                 commands.entity(player_entity).despawn();
             }
@@ -45,9 +119,13 @@ pub fn connections(
         }
     }
     for client in connection_events.iter() {
-        // Refuse connection once we already have two players
-        if lobby.players.len() >= 8 {
-            server.endpoint_mut().disconnect_client(client.id).unwrap();
+        if lobby.players.len() >= MAX_PLAYERS {
+            rejected.reject(
+                server.endpoint_mut(),
+                client.id,
+                JoinRejection::ServerFull,
+                now,
+            );
         } else {
             server
                 .endpoint_mut()
@@ -58,32 +136,205 @@ pub fn connections(
 
 // So i dont forget this is actually fine this is just receiving we are just sending out response packets which dont need to be limited since they only happen once per receive
 #[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
 pub fn get_messages(
     mut server: ResMut<Server>,
     mut commands: Commands,
     mut lobby: ResMut<ServerLobby>,
-    mut players: Query<(Entity, &Player, &Transform, &ClientName)>,
+    mut players: Query<(Entity, &Player, &Transform, &ClientName, &PlayerIdentity)>,
+    (dimensions, healths, dropped_items, mut inventories): (
+        Query<&DimensionId, With<Player>>,
+        Query<&Health, With<Player>>,
+        Query<(&DroppedItem, &Transform, &DimensionId)>,
+        Query<&mut Inventory, With<Player>>,
+    ),
     player_builder: Res<PlayerBundleBuilder>,
-    mut chunks: Query<&mut ChunkData>,
+    mut chunks: Query<(&mut ChunkData, &mut EditLog, &mut ChunkSnapshots)>,
     current_chunks: Res<CurrentChunks>,
-    mut chunks_to_save: ResMut<ChunksToSave>,
+    (mut chunks_to_save, mut edit_logs_to_save, mut snapshots_to_save): (
+        ResMut<ChunksToSave>,
+        ResMut<EditLogsToSave>,
+        ResMut<SnapshotsToSave>,
+    ),
     block_table: Res<BlockTable>,
+    world_info: Res<WorldInfo>,
+    mut command_event: EventWriter<ChatCommandEvent>,
+    (item_table, local_game, manifest, rules, view_radius): (
+        Res<ItemTable>,
+        Res<LocalGame>,
+        Res<ContentManifest>,
+        Res<GameplayRules>,
+        Res<ViewRadius>,
+    ),
+    (mut item_uses, mut block_breaks, tick, transitions, seats): (
+        ResMut<ItemUses>,
+        ResMut<BlockBreaks>,
+        Res<ServerTick>,
+        Res<TransitionTable>,
+        Res<Seats>,
+    ),
+    (
+        mut recipe_triggers,
+        mut use_block,
+        mut use_frame,
+        mut arrangements,
+        mut block_changes,
+        mut dismounts,
+        mut audit,
+    ): (
+        EventWriter<RecipeTriggerEvent>,
+        EventWriter<UseBlockEvent>,
+        EventWriter<UseFrameEvent>,
+        EventWriter<InventoryIntentEvent>,
+        EventWriter<BlockChangedEvent>,
+        EventWriter<DismountEvent>,
+        EventWriter<AuditEvent>,
+    ),
+    (mut rejected, time, mut sessions, scheduler, mut outgoing): (
+        ResMut<RejectedClients>,
+        Res<Time>,
+        ResMut<Sessions>,
+        Res<Scheduler>,
+        ResMut<OutgoingChunks>,
+    ),
 ) {
     let endpoint = server.endpoint_mut();
+    // Despawns wait for commands to apply, so two pickups of the same item in one frame would both win
+    let mut picked_up = Vec::new();
     for client_id in endpoint.clients() {
         while let Some(message) = endpoint.try_receive_message_from::<ClientMessage>(client_id) {
+            sessions.touch(client_id, time.elapsed_seconds());
+            // Templates go through the same placement as anything else once they're cleaned up,
+            // or get put back like any other denied edit when they can't be
+            let (mut forbidden, mut template) = (false, false);
+            let message = match message {
+                ClientMessage::PlaceTemplate {
+                    chunk_pos,
+                    voxel_pos,
+                    block,
+                } => {
+                    template = true;
+                    let operator = lobby
+                        .players
+                        .get(&client_id)
+                        .and_then(|player_entity| players.get(*player_entity).ok())
+                        .is_some_and(|(_, _, _, _, identity)| {
+                            is_operator(identity.storage_key(), &world_info, &local_game)
+                        });
+                    let block_type = match sanitize_template(block, &block_table, &item_table) {
+                        Ok(block) if operator => block,
+                        result => {
+                            if let Err(e) = result {
+                                println!("Turned away a template from {client_id}, {e}");
+                            }
+                            forbidden = true;
+                            BlockData::default()
+                        }
+                    };
+                    ClientMessage::SentBlock {
+                        chunk_pos,
+                        voxel_pos,
+                        block_type,
+                        item: None,
+                        tool: None,
+                        slot: None,
+                    }
+                }
+                message => message,
+            };
             match message {
-                ClientMessage::Join { id, user_name } => {
+                ClientMessage::Join {
+                    id,
+                    user_name,
+                    protocol,
+                    chunk_cache,
+                    view_distance,
+                } => {
+                    let rejection = if protocol != PROTOCOL_VERSION {
+                        Some(JoinRejection::VersionMismatch {
+                            server: PROTOCOL_VERSION,
+                        })
+                    } else if lobby.players.len() >= MAX_PLAYERS {
+                        // Someone else joined between connecting and joining
+                        Some(JoinRejection::ServerFull)
+                    } else if scheduler.joins_closed(now_secs()) {
+                        Some(JoinRejection::Denied {
+                            reason: RESTARTING_SOON.to_string(),
+                        })
+                    } else if !valid_user_name(&user_name) {
+                        Some(JoinRejection::Denied {
+                            reason: format!(
+                                "Usernames have to be 1 to {MAX_NAME_CHARS} characters without line breaks"
+                            ),
+                        })
+                    } else {
+                        None
+                    };
+                    let clients = endpoint.clients();
+                    let admitted = match rejection {
+                        Some(reason) => Err(reason),
+                        None => sessions.admit(
+                            id,
+                            &user_name,
+                            world_info.duplicate_names,
+                            time.elapsed_seconds(),
+                            |client| clients.contains(&client),
+                        ),
+                    };
+                    let admitted = match admitted {
+                        Ok(admitted) => admitted,
+                        Err(reason) => {
+                            println!("Turned {user_name} away: {reason:?}");
+                            rejected.reject(endpoint, id, reason, time.elapsed_seconds());
+                            continue;
+                        }
+                    };
+                    // Whoever held the name before a crash, still around until it timed out
+                    let ghost = admitted.evicted.and_then(|ghost| {
+                        println!("Evicted the stale session of {}", ghost.display_name);
+                        audit.send(leave_event(&ghost, LeaveReason::Replaced));
+                        item_uses.forget(ghost.identity.session);
+                        block_breaks.forget(ghost.identity.session);
+                        endpoint.disconnect_client(ghost.client_id).ok();
+                        endpoint.try_broadcast_message(&ServerMessage::PlayerRemove {
+                            id: ghost.client_id,
+                        });
+                        let entity = lobby.players.remove(&ghost.client_id)?;
+                        commands.entity(entity).despawn();
+                        Some(entity)
+                    });
+                    let (identity, user_name) = (admitted.identity, admitted.name);
                     println!("Player {user_name} connected.");
+                    audit.send(AuditEvent::Join(JoinEntry {
+                        player: identity.storage_key().to_string(),
+                        name: user_name.clone(),
+                        client: id,
+                    }));
+                    // The client decides for itself whether it can play with what we have
+                    endpoint.try_send_message(
+                        id,
+                        ServerMessage::ContentManifest {
+                            manifest: Box::new(manifest.clone()),
+                            policy: world_info.content_policy,
+                            world_id: world_info.world_id.clone(),
+                            seed: world_info.seed,
+                        },
+                    );
+                    if chunk_cache {
+                        outgoing.await_claims(id);
+                    }
 
                     // Initialize other players for this new client
-                    for (entity, player, transform, client_name) in players.iter_mut() {
+                    for (entity, player, transform, client_name, _) in players.iter_mut() {
+                        if ghost == Some(entity) {
+                            continue;
+                        }
                         endpoint.try_send_message(
                             id,
                             ServerMessage::PlayerCreate {
                                 id: player.id,
                                 entity,
-                                translation: transform.translation,
+                                translation: transform.translation.as_dvec3(),
                                 yaw: transform.rotation.to_euler(EulerRot::XYZ).1,
                                 head_pitch: transform.rotation.to_euler(EulerRot::XYZ).0,
                                 user_name: (*client_name).clone(),
@@ -94,7 +345,12 @@ pub fn get_messages(
                     }
 
                     // Spawn new player
-                    let transform = Transform::from_xyz(0.0, 75.0, 0.0);
+                    let creative = is_operator(identity.storage_key(), &world_info, &local_game);
+                    let flight = Flight {
+                        fly: creative,
+                        noclip: creative,
+                    };
+                    let transform = Transform::from_translation(world_spawn(&world_info));
                     let player_entity = commands
                         .spawn(player_builder.build(
                             transform.translation,
@@ -102,77 +358,609 @@ pub fn get_messages(
                             false,
                             user_name.clone(),
                         ))
-                        .insert(SentChunks {
-                            chunks: FxHashSet::default(),
-                        })
+                        .insert(SentChunks::default())
                         .insert(LoadPoint(world_to_chunk(transform.translation)))
+                        .insert(PlayerViewRadius::requested(view_distance, &view_radius))
+                        .insert(KnownEntities::default())
+                        .insert(DimensionId::default())
+                        .insert(Health::default())
+                        .insert(Hunger::default())
+                        .insert(Vitals::default())
+                        .insert(Inventory::default())
+                        .insert(ArrangeCursor::default())
+                        .insert(flight)
+                        .insert(identity)
                         .id();
                     lobby.players.insert(id, player_entity);
 
                     endpoint.try_broadcast_message(&ServerMessage::PlayerCreate {
                         id,
                         entity: player_entity,
-                        translation: transform.translation,
+                        translation: transform.translation.as_dvec3(),
                         yaw: transform.rotation.to_euler(EulerRot::XYZ).1,
                         head_pitch: transform.rotation.to_euler(EulerRot::XYZ).0,
-                        user_name,
+                        user_name: user_name.clone(),
                         init: true,
                         inventory: Box::<Inventory>::default(),
                     });
+                    endpoint.try_send_message(id, ServerMessage::Capabilities { creative });
+                    endpoint.try_send_message(id, flight.message());
                 }
                 ClientMessage::Leave { id } => {
                     println!("Player {id} disconnected.");
+                    if let Some(session) = sessions.end(id) {
+                        item_uses.forget(session.identity.session);
+                        block_breaks.forget(session.identity.session);
+                        audit.send(leave_event(&session, LeaveReason::Left));
+                    }
                     if let Some(player_entity) = lobby.players.remove(&id) {
                         commands.entity(player_entity).despawn();
                     }
 
                     endpoint.try_broadcast_message(&ServerMessage::PlayerRemove { id });
                 }
+                // Idle players only send about once a second, the last pose simply holds until then
+                ClientMessage::ViewDistance { horizontal } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        commands
+                            .entity(*player_entity)
+                            .insert(PlayerViewRadius::requested(horizontal, &view_radius));
+                    }
+                }
                 ClientMessage::Position {
-                    player_pos,
-                    yaw,
-                    head_pitch: _,
+                    pose,
+                    left_handed,
+                    flags,
                 } => {
                     if let Some(player_entity) = lobby.players.get(&client_id) {
-                        commands.entity(*player_entity).insert(
-                            Transform::from_translation(player_pos)
-                                .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.0, yaw, 0.0)),
+                        // Seated players only get to look around, the seat says where they are
+                        let translation = seats
+                            .seat_of(*player_entity)
+                            .map_or(pose.position().as_vec3(), |seat| seat.position);
+                        let mut player = commands.entity(*player_entity);
+                        player.insert(
+                            Transform::from_translation(translation).with_rotation(
+                                Quat::from_euler(EulerRot::XYZ, 0.0, pose.yaw(), 0.0),
+                            ),
                         );
+                        if left_handed {
+                            player.insert(LeftHanded);
+                        } else {
+                            player.remove::<LeftHanded>();
+                        }
+                        player.insert(flags.known());
                     }
                 }
 
                 ClientMessage::SentBlock {
                     chunk_pos,
                     voxel_pos,
-                    block_type,
+                    mut block_type,
+                    item,
+                    tool,
+                    slot,
                 } => {
-                    if let Some(chunk_entity) = current_chunks.get_entity(ChunkPos(chunk_pos)) {
-                        if let Ok(mut chunk) = chunks.get_mut(chunk_entity) {
-                            chunk.set(
-                                voxel_pos[0] as u32,
-                                voxel_pos[1] as u32,
-                                voxel_pos[2] as u32,
-                                block_type.clone(),
-                                &block_table,
+                    // A plain placement only holds what placing the block could have set, same as
+                    // a template
+                    if !forbidden {
+                        match sanitize_template(block_type.clone(), &block_table, &item_table) {
+                            Ok(block) => block_type = block,
+                            Err(e) => {
+                                println!("Turned away a block from {client_id}, {e}");
+                                forbidden = true;
+                            }
+                        }
+                    }
+                    let Some(player_entity) = lobby.players.get(&client_id) else {
+                        continue;
+                    };
+                    let Ok((_, _, _, _, identity)) = players.get(*player_entity) else {
+                        continue;
+                    };
+                    // Anyone but an operator places out of what our copy holds, whatever the
+                    // message says it came from
+                    let free =
+                        template || is_operator(identity.storage_key(), &world_info, &local_game);
+                    let (session, actor) = (identity.session, identity.storage_key().to_string());
+                    let dimension = dimensions.get(*player_entity).copied().unwrap_or_default();
+                    // Timed by what our copy holds, leaving item out doesn't skip the cooldown
+                    let held = inventories.get(*player_entity).ok().and_then(|inventory| {
+                        let slot = slot.or(tool).unwrap_or_else(|| inventory.held_slot());
+                        let held = inventory.slot(slot)?.as_ref()?;
+                        Some(name_to_identifier(
+                            held.namespace.clone(),
+                            held.name.clone(),
+                        ))
+                    });
+                    let too_early = held.as_ref().is_some_and(|identifier| {
+                        let timing = item_table
+                            .get(identifier)
+                            .map(|descriptor| descriptor.use_timing())
+                            .unwrap_or_default();
+                        !item_uses.try_use(session, identifier, timing, *tick)
+                    });
+                    if let Some(chunk_entity) =
+                        current_chunks.get_entity_in(dimension, ChunkPos(chunk_pos))
+                    {
+                        if let Ok((mut chunk, mut edit_log, mut snapshots)) =
+                            chunks.get_mut(chunk_entity)
+                        {
+                            let [x, y, z] = voxel_pos.map(|axis| axis as u32);
+                            let previous = chunk.get(x, y, z);
+                            let fills = |block: &BlockData| {
+                                block_table
+                                    .get(&name_to_identifier(
+                                        block.namespace.clone(),
+                                        block.name.clone(),
+                                    ))
+                                    .is_some_and(|descriptor| {
+                                        descriptor.visibility.unwrap_or_default()
+                                            != VoxelVisibility::Empty
+                                    })
+                            };
+                            let voxel = voxel_to_global_voxel(UVec3::new(x, y, z), chunk_pos);
+                            let item = if free {
+                                item
+                            } else {
+                                fills(&block_type).then(|| {
+                                    trim_geo_identifier(name_to_identifier(
+                                        block_type.namespace.clone(),
+                                        block_type.name.clone(),
+                                    ))
+                                })
+                            };
+                            let unheld = !free
+                                && item.as_ref().is_some_and(|identifier| {
+                                    !slot.is_some_and(|slot| {
+                                        inventories.get(*player_entity).is_ok_and(|inventory| {
+                                            inventory
+                                                .slot(slot)
+                                                .and_then(Option::as_ref)
+                                                .is_some_and(|held| {
+                                                    name_to_identifier(
+                                                        held.namespace.clone(),
+                                                        held.name.clone(),
+                                                    ) == *identifier
+                                                })
+                                        })
+                                    })
+                                });
+                            let denied = if forbidden || unheld {
+                                Some(DenyReason::Forbidden)
+                            } else if !rules.within_build_limits(voxel) {
+                                Some(DenyReason::OutsideBuildLimit)
+                            } else if too_early {
+                                Some(DenyReason::TooFast)
+                            } else if fills(&block_type) && fills(&previous) {
+                                // The client's ray went through here, it's out of date
+                                Some(DenyReason::Occupied)
+                            } else if !fills(&block_type)
+                                && fills(&previous)
+                                && !block_breaks.try_break(
+                                    session,
+                                    previous.break_time(&block_table),
+                                    *tick,
+                                )
+                            {
+                                // Broken sooner than holding the button down could have
+                                Some(DenyReason::TooFast)
+                            } else {
+                                None
+                            };
+                            // Put the client's prediction back to what we actually have
+                            if denied.is_some() {
+                                endpoint.try_send_message(
+                                    client_id,
+                                    ServerMessage::SentBlock {
+                                        chunk_pos,
+                                        voxel_pos,
+                                        block_type: previous,
+                                        dimension,
+                                        denied,
+                                    },
+                                );
+                                continue;
+                            }
+                            let now = now_secs();
+                            // Whatever our copy holds in the slot wears down, not what the
+                            // client says it holds
+                            if let (Some(slot), Ok(mut inventory)) =
+                                (tool, inventories.get_mut(*player_entity))
+                            {
+                                let wear = inventory.slot_mut(slot).and_then(|held| {
+                                    let tool = held.clone()?;
+                                    let worn =
+                                        wear_on_edit(&previous, &block_type, &tool, &item_table)?;
+                                    wear_slot(held, worn.as_ref());
+                                    Some((tool, worn))
+                                });
+                                if let Some((tool, worn)) = wear {
+                                    endpoint.try_send_message(
+                                        client_id,
+                                        ServerMessage::ToolWorn { slot, tool, worn },
+                                    );
+                                }
+                            }
+                            // The stack it came out of, so the copy here stays in line. When it
+                            // doesn't hold the item anymore the copy has drifted
+                            if let (Some(slot), Some(identifier)) = (slot, &item) {
+                                let used = inventories
+                                    .get_mut(*player_entity)
+                                    .is_ok_and(|mut inventory| inventory.use_one(slot, identifier));
+                                if !used {
+                                    endpoint.try_send_message(
+                                        client_id,
+                                        ServerMessage::RequestInventoryResync,
+                                    );
+                                }
+                            }
+                            // Breaking a block hands over its item, same as on the client
+                            if fills(&previous) && !fills(&block_type) {
+                                let mined = trim_geo_identifier(name_to_identifier(
+                                    previous.namespace.clone(),
+                                    previous.name.clone(),
+                                ));
+                                if let (Some(descriptor), Ok(mut inventory)) =
+                                    (item_table.get(&mined), inventories.get_mut(*player_entity))
+                                {
+                                    let item = ItemData {
+                                        namespace: descriptor.namespace.clone(),
+                                        name: descriptor.name.clone(),
+                                        stack_size: 1,
+                                        ..Default::default()
+                                    };
+                                    inventory.add_stack(&item, max_stack_size(&item, &item_table));
+                                    recipe_triggers.send(RecipeTriggerEvent {
+                                        client_id,
+                                        entity: *player_entity,
+                                        trigger: RecipeTrigger::Obtained(mined),
+                                    });
+                                }
+                            }
+                            if let Some(item) =
+                                broken_frame_drop(&previous, &block_type, &block_table)
+                                    .and_then(|identifier| frame_item(&identifier, &item_table))
+                            {
+                                spawn_dropped_item(
+                                    &mut commands,
+                                    item,
+                                    voxel.as_vec3() + Vec3::splat(0.5),
+                                    Vec3::Y * DROP_LIFT,
+                                    None,
+                                    dimension,
+                                );
+                            }
+                            transitions.stamp(&mut block_type, *tick);
+                            audit.send(AuditEvent::BlockEdit(BlockEditEntry {
+                                actor: actor.clone(),
+                                dimension: dimension.0,
+                                position: voxel.to_array(),
+                                old: name_to_identifier(
+                                    previous.namespace.clone(),
+                                    previous.name.clone(),
+                                ),
+                                new: name_to_identifier(
+                                    block_type.namespace.clone(),
+                                    block_type.name.clone(),
+                                ),
+                                tick: tick.0,
+                            }));
+                            edit_log.push(
+                                BlockEdit {
+                                    actor,
+                                    time: now,
+                                    voxel: voxel_pos,
+                                    previous,
+                                    new: block_type.clone(),
+                                },
+                                world_info.edit_retention_secs(),
                             );
-                            chunks_to_save.push((ChunkPos(chunk_pos), chunk.to_raw()));
+                            chunk.set(x, y, z, block_type.clone(), &block_table);
+                            if snapshots.record(&chunk, &edit_log, now, &world_info.snapshots) {
+                                snapshots_to_save.push((
+                                    dimension,
+                                    ChunkPos(chunk_pos),
+                                    snapshots.clone(),
+                                ));
+                            }
+                            chunks_to_save.push((dimension, ChunkPos(chunk_pos), chunk.to_raw()));
+                            edit_logs_to_save.push((
+                                dimension,
+                                ChunkPos(chunk_pos),
+                                edit_log.clone(),
+                            ));
                             endpoint.try_broadcast_message(ServerMessage::SentBlock {
                                 chunk_pos,
                                 voxel_pos,
                                 block_type,
+                                dimension,
+                                denied: None,
                             });
+                            block_changes.send(BlockChangedEvent { dimension, voxel });
+                        }
+                    }
+                }
+                ClientMessage::PickItem { identifier } => {
+                    let Some(player_entity) = lobby.players.get(&client_id) else {
+                        continue;
+                    };
+                    let Ok((_, _, _, _, identity)) = players.get(*player_entity) else {
+                        continue;
+                    };
+                    if !is_operator(identity.storage_key(), &world_info, &local_game) {
+                        continue;
+                    }
+                    if let Some(item) = item_table.get(&identifier) {
+                        recipe_triggers.send(RecipeTriggerEvent {
+                            client_id,
+                            entity: *player_entity,
+                            trigger: RecipeTrigger::Obtained(identifier.clone()),
+                        });
+                        let stack = ItemData {
+                            namespace: item.namespace.clone(),
+                            name: item.name.clone(),
+                            stack_size: item.max_stack_size.unwrap_or(MAX_STACK_SIZE),
+                            ..Default::default()
+                        };
+                        // Goes in the hand it was picked for, the client does the same with
+                        // GiveStack
+                        if let Ok(mut inventory) = inventories.get_mut(*player_entity) {
+                            inventory.hold_stack(stack.clone(), &item_table);
                         }
+                        endpoint
+                            .try_send_message(client_id, ServerMessage::GiveStack { item: stack });
+                    }
+                }
+                ClientMessage::PickBlockFull {
+                    chunk_pos,
+                    voxel_pos,
+                } => {
+                    let Some(player_entity) = lobby.players.get(&client_id) else {
+                        continue;
+                    };
+                    let Ok((_, _, _, _, identity)) = players.get(*player_entity) else {
+                        continue;
+                    };
+                    if !is_operator(identity.storage_key(), &world_info, &local_game) {
+                        continue;
+                    }
+                    let dimension = dimensions.get(*player_entity).copied().unwrap_or_default();
+                    let Some(chunk) = current_chunks
+                        .get_entity_in(dimension, ChunkPos(chunk_pos))
+                        .and_then(|chunk_entity| chunks.get(chunk_entity).ok())
+                    else {
+                        continue;
+                    };
+                    if voxel_pos
+                        .iter()
+                        .any(|axis| *axis as usize >= ChunkData::edge())
+                    {
+                        continue;
+                    }
+                    let [x, y, z] = voxel_pos.map(|axis| axis as u32);
+                    endpoint.try_send_message(
+                        client_id,
+                        ServerMessage::BlockTemplate {
+                            block: chunk.0.get(x, y, z),
+                        },
+                    );
+                }
+                ClientMessage::Craft { recipe } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        recipe_triggers.send(RecipeTriggerEvent {
+                            client_id,
+                            entity: *player_entity,
+                            trigger: RecipeTrigger::Craft(recipe),
+                        });
+                    }
+                }
+                // Only counts once our copy of the inventory actually has one
+                ClientMessage::ObtainedItem { identifier } => {
+                    let Some(player_entity) = lobby.players.get(&client_id) else {
+                        continue;
+                    };
+                    let obtained = inventories
+                        .get(*player_entity)
+                        .is_ok_and(|inventory| inventory.count(&identifier) > 0);
+                    if obtained {
+                        recipe_triggers.send(RecipeTriggerEvent {
+                            client_id,
+                            entity: *player_entity,
+                            trigger: RecipeTrigger::Obtained(identifier),
+                        });
                     }
                 }
+                // Only what our copy of the slot holds can leave it, how many and where they go is
+                // up to us too
+                ClientMessage::DropItem { slot, count, item } => {
+                    let Some(player_entity) = lobby.players.get(&client_id) else {
+                        continue;
+                    };
+                    let max_stack_size = item_table
+                        .get(&name_to_identifier(
+                            item.namespace.clone(),
+                            item.name.clone(),
+                        ))
+                        .map(|descriptor| descriptor.max_stack_size.unwrap_or(MAX_STACK_SIZE));
+                    let alive = healths
+                        .get(*player_entity)
+                        .is_ok_and(|health| health.current > 0.0);
+                    let mut dropped = 0;
+                    if let (Some(max_stack_size), Ok((_, _, transform, _, identity)), true) =
+                        (max_stack_size, players.get(*player_entity), alive)
+                    {
+                        let wanted = clamp_drop(count, item.stack_size, max_stack_size);
+                        let taken = inventories
+                            .get_mut(*player_entity)
+                            .ok()
+                            .and_then(|mut inventory| inventory.take(slot, &item, wanted));
+                        if let Some(taken) = taken {
+                            dropped = taken.stack_size;
+                            let forward = transform.forward();
+                            spawn_dropped_item(
+                                &mut commands,
+                                taken,
+                                transform.translation
+                                    + Vec3::Y * EYE_HEIGHT
+                                    + forward * DROP_DISTANCE,
+                                forward * DROP_SPEED + Vec3::Y * DROP_LIFT,
+                                Some(identity.session),
+                                dimensions.get(*player_entity).copied().unwrap_or_default(),
+                            );
+                        }
+                        if dropped < wanted {
+                            endpoint
+                                .try_send_message(client_id, ServerMessage::RequestInventoryResync);
+                        }
+                    }
+                    endpoint.try_send_message(
+                        client_id,
+                        ServerMessage::DropResult {
+                            slot,
+                            item,
+                            requested: count,
+                            dropped,
+                        },
+                    );
+                }
+                ClientMessage::PickUp { entity } => {
+                    let Some(player_entity) = lobby.players.get(&client_id) else {
+                        continue;
+                    };
+                    let (
+                        Ok((_, _, transform, _, identity)),
+                        Ok((dropped, item_transform, item_dimension)),
+                    ) = (players.get(*player_entity), dropped_items.get(entity))
+                    else {
+                        continue;
+                    };
+                    let in_reach = dimensions.get(*player_entity).ok() == Some(item_dimension)
+                        && item_transform
+                            .translation
+                            .distance(transform.translation + Vec3::Y * EYE_HEIGHT / 2.0)
+                            <= PICKUP_RADIUS + PICKUP_SLACK;
+                    let alive = healths
+                        .get(*player_entity)
+                        .is_ok_and(|health| health.current > 0.0);
+                    if !in_reach
+                        || !alive
+                        || !dropped.can_pick_up(identity.session)
+                        || picked_up.contains(&entity)
+                    {
+                        continue;
+                    }
+                    picked_up.push(entity);
+                    commands.entity(entity).despawn_recursive();
+                    if let Ok(mut inventory) = inventories.get_mut(*player_entity) {
+                        inventory
+                            .add_stack(&dropped.item, max_stack_size(&dropped.item, &item_table));
+                    }
+                    recipe_triggers.send(RecipeTriggerEvent {
+                        client_id,
+                        entity: *player_entity,
+                        trigger: RecipeTrigger::Obtained(name_to_identifier(
+                            dropped.item.namespace.clone(),
+                            dropped.item.name.clone(),
+                        )),
+                    });
+                    endpoint.try_send_message(
+                        client_id,
+                        ServerMessage::PickedUp {
+                            item: dropped.item.clone(),
+                        },
+                    );
+                }
+                ClientMessage::UseBlock { voxel } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        use_block.send(UseBlockEvent {
+                            client_id,
+                            entity: *player_entity,
+                            voxel,
+                        });
+                    }
+                }
+                ClientMessage::Dismount => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        dismounts.send(DismountEvent {
+                            client_id,
+                            entity: *player_entity,
+                        });
+                    }
+                }
+                ClientMessage::UseFrame { voxel, request } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        use_frame.send(UseFrameEvent {
+                            client_id,
+                            entity: *player_entity,
+                            voxel,
+                            request,
+                        });
+                    }
+                }
+                ClientMessage::InventoryOp { seq, op } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        arrangements.send(InventoryIntentEvent {
+                            client_id,
+                            entity: *player_entity,
+                            intent: InventoryIntent::Op { seq, op },
+                        });
+                    }
+                }
+                ClientMessage::InventoryResync { seq, inventory } => {
+                    if let Some(player_entity) = lobby.players.get(&client_id) {
+                        arrangements.send(InventoryIntentEvent {
+                            client_id,
+                            entity: *player_entity,
+                            intent: InventoryIntent::Resync { seq, inventory },
+                        });
+                    }
+                }
+                ClientMessage::SelectSlot { bar, slot } => {
+                    let held = SlotRef {
+                        section: InventorySection::Hotbar,
+                        bar: bar as usize,
+                        slot: slot as usize,
+                    };
+                    let inventory = lobby
+                        .players
+                        .get(&client_id)
+                        .and_then(|player_entity| inventories.get_mut(*player_entity).ok());
+                    if let Some(mut inventory) =
+                        inventory.filter(|inventory| inventory.slot(held).is_some())
+                    {
+                        *inventory.current_bar = held.bar;
+                        *inventory.current_item = held.slot;
+                    }
+                }
+                ClientMessage::CachedChunks { chunks, done } => {
+                    outgoing.claim(client_id, &chunks, done);
+                }
+                ClientMessage::ChunkCacheMiss { pos, dimension } => {
+                    outgoing.miss(client_id, (dimension, ChunkPos(pos)));
+                }
                 ClientMessage::ChatMessage { message } => {
+                    let message = truncate_chars(&message, MAX_CHAT_CHARS).to_string();
                     if let Some(player_entity) = lobby.players.get(&client_id) {
-                        if let Ok((_, _, _, username)) = players.get(*player_entity) {
+                        if let Ok((_, _, _, username, identity)) = players.get(*player_entity) {
+                            if let Some(command) = message.strip_prefix('/') {
+                                command_event.send(ChatCommandEvent {
+                                    sender: CommandSender::Player {
+                                        client_id,
+                                        entity: *player_entity,
+                                    },
+                                    user_name: (*username).clone(),
+                                    storage_key: identity.storage_key().to_string(),
+                                    command: command.to_string(),
+                                });
+                                continue;
+                            }
                             endpoint.try_broadcast_message_on(
                                 bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
                                 ServerMessage::ChatMessage {
                                     user_name: (*username).clone(),
                                     message,
                                     id: client_id,
+                                    category: ChatCategory::PlayerChat,
                                 },
                             );
                         }
@@ -184,16 +972,39 @@ pub fn get_messages(
     }
 }
 
+fn leave_event(session: &Session, reason: LeaveReason) -> AuditEvent {
+    AuditEvent::Leave(LeaveEntry {
+        player: session.identity.storage_key().to_string(),
+        name: session.display_name.clone(),
+        client: session.client_id,
+        reason,
+    })
+}
+
 #[allow(clippy::type_complexity)]
 //This would eventually take in any networkedentity for now just player
-pub fn send_entities(mut server: ResMut<Server>, query: Query<(Entity, &Transform)>) {
+pub fn send_entities(
+    mut server: ResMut<Server>,
+    query: Query<(
+        Entity,
+        &Transform,
+        Option<&LeftHanded>,
+        Option<&PlayerFlags>,
+    )>,
+) {
     let mut networked_entities = NetworkedEntities::default();
-    for (entity, transform) in query.iter() {
+    for (entity, transform, left_handed, flags) in query.iter() {
         networked_entities.entities.push(entity);
-        networked_entities.translations.push(transform.translation);
+        networked_entities
+            .translations
+            .push(transform.translation.as_dvec3());
         networked_entities
             .yaws
             .push(transform.rotation.to_euler(EulerRot::XYZ).1);
+        networked_entities.left_handed.push(left_handed.is_some());
+        networked_entities
+            .flags
+            .push(flags.copied().unwrap_or_default());
     }
     server.endpoint_mut().try_broadcast_message_on(
         bevy_quinnet::shared::channel::ChannelId::Unreliable,
@@ -201,47 +1012,238 @@ pub fn send_entities(mut server: ResMut<Server>, query: Query<(Entity, &Transfor
     );
 }
 
+#[allow(clippy::type_complexity)]
+pub fn send_stats(
+    mut server: ResMut<Server>,
+    players: Query<(&Player, &Health, &Hunger), Or<(Changed<Health>, Changed<Hunger>)>>,
+) {
+    for (player, health, hunger) in players.iter() {
+        server.endpoint_mut().try_send_message(
+            player.id,
+            ServerMessage::PlayerStats {
+                health: *health,
+                hunger: *hunger,
+            },
+        );
+    }
+}
+
+// Tells each client which chunks left its radius or were unloaded since the last tick. Runs before
+// send_chunks so whatever comes back into range on the same move is sent again
+#[allow(clippy::type_complexity)]
+pub fn unsend_chunks(
+    mut server: ResMut<Server>,
+    lobby: Res<ServerLobby>,
+    mut players: Query<
+        (
+            &Transform,
+            &mut SentChunks,
+            &DimensionId,
+            Option<&PlayerViewRadius>,
+        ),
+        With<Player>,
+    >,
+    view_radius: Res<ViewRadius>,
+) {
+    let endpoint = server.endpoint_mut();
+    for client_id in endpoint.clients() {
+        let Some(player_entity) = lobby.players.get(&client_id) else {
+            continue;
+        };
+        let Ok((player_transform, mut sent_chunks, dimension, own_radius)) =
+            players.get_mut(*player_entity)
+        else {
+            continue;
+        };
+        let radius = PlayerViewRadius::or_default(own_radius, &view_radius);
+        let chunk_pos = ChunkPos(world_to_chunk(player_transform.translation));
+        for pos in sent_chunks.leave(chunk_pos, &radius) {
+            endpoint.try_send_message(
+                client_id,
+                ServerMessage::UnloadChunk {
+                    pos: *pos,
+                    dimension: *dimension,
+                },
+            );
+        }
+    }
+}
+
+// Only hands chunks to the pool, drain_chunks sends them once they're ready
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
 pub fn send_chunks(
     mut commands: Commands,
     mut server: ResMut<Server>,
     lobby: ResMut<ServerLobby>,
-    mut players: Query<(&Transform, &mut SentChunks), With<Player>>,
+    mut players: Query<
+        (
+            &Transform,
+            &mut SentChunks,
+            &DimensionId,
+            Option<&PlayerViewRadius>,
+        ),
+        With<Player>,
+    >,
     mut chunk_manager: ChunkManager,
     chunk_limit: Res<ChunkLimit>,
+    (world_rng, tick, biome_table): (Res<WorldRng>, Res<ServerTick>, Res<BiomeTable>),
+    mut outgoing: ResMut<OutgoingChunks>,
 ) {
-    let mut rng = rand::thread_rng();
+    let mut rng = world_rng.tick_stream("send_chunks", *tick);
+    let task_pool = AsyncComputeTaskPool::get();
     let endpoint = server.endpoint_mut();
     for client_id in endpoint.clients() {
         if let Some(player_entity) = lobby.players.get(&client_id) {
-            if let Ok((player_transform, mut sent_chunks)) = players.get_mut(*player_entity) {
+            if let Ok((player_transform, mut sent_chunks, dimension, own_radius)) =
+                players.get_mut(*player_entity)
+            {
+                let radius = PlayerViewRadius::or_default(own_radius, &chunk_manager.view_radius);
                 let chunk_pos = world_to_chunk(player_transform.translation);
                 let load_point = LoadPoint(chunk_pos);
                 commands.entity(*player_entity).insert(load_point.clone());
-                for chunk in chunk_manager
-                    .get_chunks_around_chunk(ChunkPos(chunk_pos), Some(&sent_chunks))
-                    .choose_multiple(&mut rng, **chunk_limit)
-                {
-                    let raw_chunk = chunk.0.to_raw();
-                    if let Ok(raw_chunk_bin) = bincode::serialize(&raw_chunk) {
-                        let mut final_chunk = Cursor::new(raw_chunk_bin);
-                        let mut output = Cursor::new(Vec::new());
-                        copy_encode(&mut final_chunk, &mut output, 0).unwrap();
-                        if server
-                            .endpoint_mut()
-                            .send_message(
-                                client_id,
-                                ServerMessage::LevelData {
-                                    chunk_data: output.get_ref().clone(),
-                                    pos: *chunk.1,
-                                },
-                            )
-                            .is_ok()
-                        {
-                            sent_chunks.chunks.insert(chunk.1);
+                // Gone from the client's cache after all, they go out again in full
+                for (missed_dimension, pos) in outgoing.take_missed(client_id) {
+                    if missed_dimension == *dimension {
+                        sent_chunks.chunks.remove(&pos);
+                    }
+                }
+                let limit = (**chunk_limit).min(outgoing.room(client_id));
+                let mut chunks = chunk_manager.get_chunks_around_chunk_in(
+                    *dimension,
+                    ChunkPos(chunk_pos),
+                    Some(&sent_chunks),
+                    &radius,
+                );
+                chunks.retain(|(_, pos)| !outgoing.is_pending(client_id, (*dimension, *pos)));
+                for (chunk, pos) in chunks.choose_multiple(&mut rng, limit) {
+                    let key = (*dimension, *pos);
+                    match outgoing.request(client_id, key, chunk.revision()) {
+                        Queued::Ready(payload) => {
+                            let message = outgoing.message_for(client_id, key, &payload);
+                            if endpoint.send_message(client_id, message).is_ok() {
+                                sent_chunks.chunks.insert(*pos);
+                            }
                         }
+                        Queued::Waiting => {}
+                        Queued::Start => {
+                            // An owned copy, edits after this can't show up half applied
+                            let raw_chunk = chunk.to_raw();
+                            let revision = chunk.revision();
+                            let biomes = ChunkBiomes::compute(**pos, &biome_table);
+                            let task = task_pool.spawn(async move {
+                                (key, revision, prepare_chunk(&raw_chunk, biomes))
+                            });
+                            commands.spawn(PrepareTask(task));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Clients only hear about critters and dropped items in chunks they have been sent
+#[allow(clippy::type_complexity)]
+pub fn sync_entities(
+    mut server: ResMut<Server>,
+    lobby: Res<ServerLobby>,
+    mut players: Query<(&SentChunks, &mut KnownEntities, &DimensionId), With<Player>>,
+    entities: Query<
+        (
+            Entity,
+            &Transform,
+            Option<&DimensionId>,
+            Option<&DroppedItem>,
+        ),
+        Or<(With<Critter>, With<DroppedItem>)>,
+    >,
+) {
+    let endpoint = server.endpoint_mut();
+    for client_id in endpoint.clients() {
+        if let Some(player_entity) = lobby.players.get(&client_id) {
+            if let Ok((sent_chunks, mut known_entities, dimension)) =
+                players.get_mut(*player_entity)
+            {
+                // Critters don't carry a dimension since they only exist in the overworld
+                let visible = |transform: &Transform, entity_dimension: Option<&DimensionId>| {
+                    entity_dimension.copied().unwrap_or_default() == *dimension
+                        && sent_chunks
+                            .chunks
+                            .contains(&ChunkPos(world_to_chunk(transform.translation)))
+                };
+                known_entities.retain(|entity| {
+                    let still_visible =
+                        entities
+                            .get(*entity)
+                            .is_ok_and(|(_, transform, entity_dimension, _)| {
+                                visible(transform, entity_dimension)
+                            });
+                    if !still_visible {
+                        endpoint.try_send_message(
+                            client_id,
+                            ServerMessage::EntityRemove { entity: *entity },
+                        );
+                    }
+                    still_visible
+                });
+                for (entity, transform, entity_dimension, dropped) in entities.iter() {
+                    if !known_entities.contains(&entity) && visible(transform, entity_dimension) {
+                        endpoint.try_send_message(
+                            client_id,
+                            ServerMessage::EntityCreate {
+                                entity,
+                                kind: if dropped.is_some() {
+                                    EntityKind::DroppedItem
+                                } else {
+                                    EntityKind::Critter
+                                },
+                                translation: transform.translation.as_dvec3(),
+                                yaw: transform.rotation.to_euler(EulerRot::XYZ).1,
+                                item: dropped.map(|dropped| dropped.item.clone()),
+                            },
+                        );
+                        known_entities.insert(entity);
                     }
                 }
             }
         }
     }
 }
+
+pub struct ChangeDimensionEvent {
+    pub player: Entity,
+    pub dimension: DimensionId,
+}
+
+pub fn change_dimension(
+    mut server: ResMut<Server>,
+    mut events: EventReader<ChangeDimensionEvent>,
+    mut players: Query<(
+        &Player,
+        &mut DimensionId,
+        &mut SentChunks,
+        &mut KnownEntities,
+    )>,
+    world_info: Res<WorldInfo>,
+) {
+    for event in events.iter() {
+        if world_info.generator(event.dimension).is_none() {
+            println!("Dimension {:?} is not configured", event.dimension);
+            continue;
+        }
+        if let Ok((player, mut dimension, mut sent_chunks, mut known_entities)) =
+            players.get_mut(event.player)
+        {
+            *dimension = event.dimension;
+            sent_chunks.clear();
+            known_entities.clear();
+            server.endpoint_mut().try_send_message(
+                player.id,
+                ServerMessage::ChangeDimension {
+                    dimension: event.dimension,
+                },
+            );
+        }
+    }
+}
@@ -1,13 +1,27 @@
 use bevy::prelude::*;
 use vinox_common::{
-    ecs::bundles::PlayerBundleBuilder,
+    ecs::{
+        bundles::PlayerBundleBuilder,
+        time::{GameClockPlugin, ServerTickPlugin},
+    },
+    physics::plugin::PhysicsPlugin,
     world::chunks::{
         light::LightPlugin,
-        storage::{BlockTable, ItemTable, RecipeTable},
+        storage::{BiomeTable, BlockTable, ItemTable, RecipeTable, StructureTable},
     },
 };
 
-use super::{networking::plugin::NetworkingPlugin, world::chunk::ChunkPlugin};
+use super::{
+    audit::AuditPlugin,
+    load::LoadPlugin,
+    networking::plugin::NetworkingPlugin,
+    schedule::SchedulePlugin,
+    world::{
+        chunk::ChunkPlugin, critter::CritterPlugin, dropped::DroppedItemPlugin,
+        frames::FramePlugin, gameplay::GameplayPlugin, seats::SeatPlugin, spawn::SpawnPlugin,
+        transitions::TransitionPlugin, vitals::VitalsPlugin,
+    },
+};
 
 pub struct GamePlugin;
 
@@ -15,10 +29,26 @@ impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ItemTable::default())
             .insert_resource(BlockTable::default())
+            .insert_resource(BiomeTable::default())
+            .insert_resource(StructureTable::default())
             .insert_resource(RecipeTable::default())
             .insert_resource(PlayerBundleBuilder::default())
             .add_plugin(ChunkPlugin)
             .add_plugin(NetworkingPlugin)
-            .add_plugin(LightPlugin);
+            .add_plugin(LightPlugin)
+            .add_plugin(GameClockPlugin)
+            .add_plugin(ServerTickPlugin)
+            .add_plugin(LoadPlugin)
+            .add_plugin(SchedulePlugin)
+            .add_plugin(PhysicsPlugin)
+            .add_plugin(CritterPlugin)
+            .add_plugin(DroppedItemPlugin)
+            .add_plugin(SpawnPlugin)
+            .add_plugin(FramePlugin)
+            .add_plugin(SeatPlugin)
+            .add_plugin(TransitionPlugin)
+            .add_plugin(GameplayPlugin)
+            .add_plugin(AuditPlugin)
+            .add_plugin(VitalsPlugin);
     }
 }
@@ -0,0 +1,467 @@
+use std::{fmt, fs, path::PathBuf, time::SystemTime};
+
+use bevy::{prelude::*, time::common_conditions::on_timer};
+use bevy_quinnet::server::Server;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    networking::{
+        commands::{announce, ChatCommandEvent, CommandSender, ShutdownEvent},
+        console::{read_console, CONSOLE_NAME},
+    },
+    world::{edits::now_secs, lifecycle::SaveAllEvent},
+};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+// Joins are turned away this close to a scheduled restart
+pub const JOINS_CLOSE_SECS: u64 = 60;
+pub const RESTARTING_SOON: &str = "the server is restarting soon";
+
+// When a task runs, in UTC since the server has no idea what time zone its owner is in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskTime {
+    // Lined up with the clock, every 30 runs on the hour and the half hour
+    Every { minutes: u32 },
+    Daily { hour: u8, minute: u8 },
+}
+
+impl TaskTime {
+    // First time strictly after after that it's due, both in unix seconds
+    pub fn next_after(&self, after: u64) -> u64 {
+        match *self {
+            TaskTime::Every { minutes } => {
+                let period = minutes.max(1) as u64 * 60;
+                (after / period + 1) * period
+            }
+            TaskTime::Daily { hour, minute } => {
+                let today = after - after % SECS_PER_DAY + hour as u64 * 3600 + minute as u64 * 60;
+                if today > after {
+                    today
+                } else {
+                    today + SECS_PER_DAY
+                }
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match *self {
+            TaskTime::Every { minutes: 0 } => Err("Every needs at least one minute".to_string()),
+            TaskTime::Daily { hour, minute } if hour > 23 || minute > 59 => {
+                Err(format!("{hour:02}:{minute:02} isn't a time of day"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskAction {
+    // A chat line from the server to everyone online
+    Broadcast { message: String },
+    SaveAll,
+    // The task's time is when the server goes down, everyone is warned this many minutes before
+    Shutdown { warn_minutes: Vec<u8> },
+    // Run as if typed into the console
+    RunCommand { command: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledTask {
+    pub at: TaskTime,
+    pub action: TaskAction,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    pub tasks: Vec<ScheduledTask>,
+}
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    Parse(ron::error::SpannedError),
+    Invalid { task: usize, reason: String },
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::Parse(e) => write!(f, "it isn't valid: {e}"),
+            ScheduleError::Invalid { task, reason } => write!(f, "task {task}: {reason}"),
+        }
+    }
+}
+
+impl ScheduleConfig {
+    pub fn parse(text: &str) -> Result<Self, ScheduleError> {
+        let config: ScheduleConfig = ron::from_str(text).map_err(ScheduleError::Parse)?;
+        for (task, scheduled) in config.tasks.iter().enumerate() {
+            scheduled
+                .at
+                .validate()
+                .map_err(|reason| ScheduleError::Invalid { task, reason })?;
+        }
+        Ok(config)
+    }
+}
+
+// What the scheduler wants done this tick
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Due {
+    Broadcast(String),
+    SaveAll,
+    // Minutes left until the restart, rounded up
+    RestartWarning(u64),
+    Restart,
+    // A restart was taken out of the schedule while counting down to it
+    RestartCalledOff,
+    RunCommand(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Countdown {
+    task: ScheduledTask,
+    at: u64,
+    // Marks still to announce, furthest from the restart first
+    warnings: Vec<u8>,
+}
+
+// Tasks with when they're next due, the clock is always passed in so tests can drive it
+#[derive(Resource, Debug, Default)]
+pub struct Scheduler {
+    tasks: Vec<(ScheduledTask, u64)>,
+    countdown: Option<Countdown>,
+    // Of the file the tasks came from, a different one means it was edited
+    modified: Option<SystemTime>,
+}
+
+impl Scheduler {
+    // Tasks that didn't change keep their due time, new ones are due next time they come around
+    pub fn load(&mut self, config: ScheduleConfig, now: u64) -> Vec<Due> {
+        let mut old = std::mem::take(&mut self.tasks);
+        self.tasks = config
+            .tasks
+            .into_iter()
+            .map(|task| {
+                let due = match old.iter().position(|(old_task, _)| *old_task == task) {
+                    Some(index) => old.swap_remove(index).1,
+                    None => task.at.next_after(now),
+                };
+                (task, due)
+            })
+            .collect();
+        let kept = self
+            .countdown
+            .as_ref()
+            .is_none_or(|countdown| self.tasks.iter().any(|(task, _)| *task == countdown.task));
+        if kept {
+            return Vec::new();
+        }
+        self.countdown = None;
+        vec![Due::RestartCalledOff]
+    }
+
+    pub fn joins_closed(&self, now: u64) -> bool {
+        self.countdown
+            .as_ref()
+            .is_some_and(|countdown| now + JOINS_CLOSE_SECS >= countdown.at)
+    }
+
+    // Anything missed while the server was stuck runs once, not once for every time it was due
+    pub fn poll(&mut self, now: u64) -> Vec<Due> {
+        let mut due = Vec::new();
+        for (task, next) in self.tasks.iter_mut() {
+            if let TaskAction::Shutdown { warn_minutes } = &task.action {
+                let lead = warn_minutes.iter().max().copied().unwrap_or_default() as u64 * 60;
+                if self.countdown.is_some() || now + lead.max(JOINS_CLOSE_SECS) < *next {
+                    continue;
+                }
+                let mut warnings = warn_minutes.clone();
+                warnings.retain(|minutes| *minutes > 0);
+                warnings.sort_unstable_by(|a, b| b.cmp(a));
+                warnings.dedup();
+                self.countdown = Some(Countdown {
+                    task: task.clone(),
+                    at: *next,
+                    warnings,
+                });
+                *next = task.at.next_after(now.max(*next));
+                continue;
+            }
+            if now < *next {
+                continue;
+            }
+            *next = task.at.next_after(now);
+            due.push(match &task.action {
+                TaskAction::Broadcast { message } => Due::Broadcast(message.clone()),
+                TaskAction::SaveAll => Due::SaveAll,
+                TaskAction::RunCommand { command } => Due::RunCommand(command.clone()),
+                // Counted down to above
+                TaskAction::Shutdown { .. } => continue,
+            });
+        }
+        let Some(countdown) = &mut self.countdown else {
+            return due;
+        };
+        if now >= countdown.at {
+            self.countdown = None;
+            due.push(Due::Restart);
+            return due;
+        }
+        // Several marks passing in one tick only get the one warning with the real time left
+        let passed = countdown
+            .warnings
+            .iter()
+            .take_while(|minutes| now + **minutes as u64 * 60 >= countdown.at)
+            .count();
+        if passed > 0 {
+            countdown.warnings.drain(..passed);
+            due.push(Due::RestartWarning((countdown.at - now + 59) / 60));
+        }
+        due
+    }
+}
+
+// Only the server binary has a schedule file, an embedded server runs nothing on its own
+#[derive(Resource)]
+#[allow(dead_code)]
+pub struct SchedulePath(pub PathBuf);
+
+// Also the startup load, a file that appears later is picked up the same way as an edit
+pub fn watch_schedule(
+    mut scheduler: ResMut<Scheduler>,
+    mut server: ResMut<Server>,
+    path: Option<Res<SchedulePath>>,
+) {
+    let Some(path) = path else {
+        return;
+    };
+    let Ok(modified) = fs::metadata(&path.0).and_then(|metadata| metadata.modified()) else {
+        return;
+    };
+    if scheduler.modified.replace(modified) == Some(modified) {
+        return;
+    }
+    let Ok(text) = fs::read_to_string(&path.0) else {
+        return;
+    };
+    match ScheduleConfig::parse(&text) {
+        Ok(config) => {
+            println!("Loaded {} scheduled tasks", config.tasks.len());
+            for due in scheduler.load(config, now_secs()) {
+                if due == Due::RestartCalledOff {
+                    announce(&mut server, "The scheduled restart is off".to_string());
+                }
+            }
+        }
+        Err(e) => println!("Keeping the old schedule, {e}"),
+    }
+}
+
+pub fn run_schedule(
+    mut scheduler: ResMut<Scheduler>,
+    mut server: ResMut<Server>,
+    mut save_all: EventWriter<SaveAllEvent>,
+    mut shutdown: EventWriter<ShutdownEvent>,
+    mut commands: EventWriter<ChatCommandEvent>,
+) {
+    for due in scheduler.poll(now_secs()) {
+        match due {
+            Due::Broadcast(message) => announce(&mut server, message),
+            Due::SaveAll => save_all.send(SaveAllEvent),
+            Due::RestartWarning(minutes) => announce(
+                &mut server,
+                match minutes {
+                    1 => "The server restarts in 1 minute".to_string(),
+                    minutes => format!("The server restarts in {minutes} minutes"),
+                },
+            ),
+            Due::Restart => {
+                println!("Restarting as scheduled");
+                shutdown.send(ShutdownEvent {
+                    reason: "The server is restarting".to_string(),
+                });
+            }
+            Due::RestartCalledOff => {}
+            Due::RunCommand(command) => commands.send(ChatCommandEvent {
+                sender: CommandSender::Console,
+                user_name: CONSOLE_NAME.to_string(),
+                storage_key: CONSOLE_NAME.to_string(),
+                command,
+            }),
+        }
+    }
+}
+
+pub struct SchedulePlugin;
+
+impl Plugin for SchedulePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Scheduler>()
+            .add_startup_system(watch_schedule)
+            .add_system(watch_schedule.run_if(on_timer(std::time::Duration::from_secs(2))))
+            // Commands it runs are read in the same frame as console ones
+            .add_system(run_schedule.before(read_console));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2023-03-14 10:20:00 UTC
+    const NOW: u64 = 1_678_789_200;
+
+    fn task(at: TaskTime, action: TaskAction) -> ScheduledTask {
+        ScheduledTask { at, action }
+    }
+
+    fn loaded(tasks: Vec<ScheduledTask>, now: u64) -> Scheduler {
+        let mut scheduler = Scheduler::default();
+        assert!(scheduler.load(ScheduleConfig { tasks }, now).is_empty());
+        scheduler
+    }
+
+    #[test]
+    fn due_times_line_up_with_the_clock() {
+        let half_hourly = TaskTime::Every { minutes: 30 };
+        assert_eq!(half_hourly.next_after(NOW), NOW + 10 * 60);
+        // Exactly on the mark is the next one
+        assert_eq!(half_hourly.next_after(NOW + 10 * 60), NOW + 40 * 60);
+
+        let morning = TaskTime::Daily { hour: 4, minute: 0 };
+        let evening = TaskTime::Daily {
+            hour: 22,
+            minute: 30,
+        };
+        // Today's evening is still ahead, this morning's has gone so it's tomorrow's
+        assert_eq!(evening.next_after(NOW), NOW + (12 * 60 + 10) * 60);
+        assert_eq!(morning.next_after(NOW), NOW + (17 * 60 + 40) * 60);
+        let next = morning.next_after(NOW);
+        assert_eq!(morning.next_after(next), next + SECS_PER_DAY);
+
+        assert!(
+            ScheduleConfig::parse("(tasks: [(at: Every(minutes: 0), action: SaveAll)])").is_err()
+        );
+        assert!(ScheduleConfig::parse(
+            "(tasks: [(at: Daily(hour: 24, minute: 0), action: SaveAll)])"
+        )
+        .is_err());
+        let config = ScheduleConfig::parse(
+            r#"(tasks: [(at: Daily(hour: 4, minute: 0), action: Shutdown(warn_minutes: [10, 5, 1])),
+                (at: Every(minutes: 60), action: Broadcast(message: "Vote for the server!"))])"#,
+        )
+        .unwrap();
+        assert_eq!(config.tasks.len(), 2);
+    }
+
+    #[test]
+    fn missed_runs_catch_up_once() {
+        let mut scheduler = loaded(
+            vec![
+                task(TaskTime::Every { minutes: 5 }, TaskAction::SaveAll),
+                task(
+                    TaskTime::Every { minutes: 60 },
+                    TaskAction::RunCommand {
+                        command: "say hi".to_string(),
+                    },
+                ),
+            ],
+            NOW,
+        );
+        assert!(scheduler.poll(NOW + 60).is_empty());
+        assert_eq!(scheduler.poll(NOW + 5 * 60), vec![Due::SaveAll]);
+        assert!(scheduler.poll(NOW + 5 * 60 + 1).is_empty());
+
+        // Stuck for an hour and a half, each runs once and then keeps to the clock again
+        let late = NOW + 95 * 60;
+        assert_eq!(
+            scheduler.poll(late),
+            vec![Due::SaveAll, Due::RunCommand("say hi".to_string())]
+        );
+        assert!(scheduler.poll(late + 60).is_empty());
+        assert_eq!(
+            scheduler.poll(NOW + 100 * 60),
+            vec![Due::SaveAll, Due::RunCommand("say hi".to_string())]
+        );
+
+        // Reloading the same tasks doesn't reset them, a new one waits for its first time
+        let mut config = ScheduleConfig {
+            tasks: scheduler
+                .tasks
+                .iter()
+                .map(|(task, _)| task.clone())
+                .collect(),
+        };
+        config.tasks.push(task(
+            TaskTime::Every { minutes: 1 },
+            TaskAction::Broadcast {
+                message: "new".to_string(),
+            },
+        ));
+        assert!(scheduler.load(config, NOW + 100 * 60 + 30).is_empty());
+        assert!(scheduler.poll(NOW + 100 * 60 + 30).is_empty());
+        assert_eq!(
+            scheduler.poll(NOW + 101 * 60),
+            vec![Due::Broadcast("new".to_string())]
+        );
+    }
+
+    #[test]
+    fn restart_warnings_count_down_in_order() {
+        let restart = task(
+            TaskTime::Daily {
+                hour: 10,
+                minute: 30,
+            },
+            TaskAction::Shutdown {
+                warn_minutes: vec![1, 10, 5, 5, 0],
+            },
+        );
+        let at = NOW + 10 * 60;
+        let mut scheduler = loaded(vec![restart.clone()], NOW);
+        let mut warnings = Vec::new();
+        let mut closed_at = None;
+        for now in NOW..=at {
+            if closed_at.is_none() && scheduler.joins_closed(now) {
+                closed_at = Some(now);
+            }
+            warnings.extend(scheduler.poll(now).into_iter().map(|due| (at - now, due)));
+        }
+        assert_eq!(
+            warnings,
+            vec![
+                (600, Due::RestartWarning(10)),
+                (300, Due::RestartWarning(5)),
+                (60, Due::RestartWarning(1)),
+                (0, Due::Restart),
+            ]
+        );
+        assert_eq!(closed_at, Some(at - JOINS_CLOSE_SECS));
+        assert!(!scheduler.joins_closed(at + 1));
+
+        // Stuck through the 10 and 5 minute marks, one warning says how long is really left
+        let mut scheduler = loaded(vec![restart.clone()], NOW - 60);
+        assert!(scheduler.poll(NOW - 60).is_empty());
+        assert_eq!(
+            scheduler.poll(at - 4 * 60 - 30),
+            vec![Due::RestartWarning(5)]
+        );
+        assert_eq!(scheduler.poll(at - 60), vec![Due::RestartWarning(1)]);
+        // Stuck past the restart itself, it still only happens once
+        assert_eq!(scheduler.poll(at + 600), vec![Due::Restart]);
+        assert!(scheduler.poll(at + 601).is_empty());
+
+        // Taking it out of the file calls it off and opens joins again
+        let mut scheduler = loaded(vec![restart], NOW);
+        assert_eq!(scheduler.poll(at - 60), vec![Due::RestartWarning(1)]);
+        assert!(scheduler.joins_closed(at - 60));
+        assert_eq!(
+            scheduler.load(ScheduleConfig::default(), at - 30),
+            vec![Due::RestartCalledOff]
+        );
+        assert!(!scheduler.joins_closed(at - 30));
+        assert!(scheduler.poll(at).is_empty());
+    }
+}
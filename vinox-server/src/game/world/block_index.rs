@@ -0,0 +1,377 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bevy::prelude::*;
+use vinox_common::world::chunks::{
+    positions::{voxel_to_global_voxel, ChunkPos, DimensionId},
+    storage::{ChunkData, CHUNK_SIZE},
+};
+
+type ChunkKey = (DimensionId, IVec3);
+
+struct IndexedChunk {
+    key: ChunkKey,
+    revision: u64,
+    identifiers: BTreeSet<String>,
+}
+
+// Which loaded chunks hold at least one of a block, read off each chunk's palette. Entries
+// nothing refers to anymore don't count, so a block mined out of a chunk leaves the index even
+// while its palette entry lingers
+#[derive(Resource, Default)]
+pub struct BlockIndex {
+    containing: HashMap<String, HashSet<ChunkKey>>,
+    indexed: HashMap<Entity, IndexedChunk>,
+}
+
+impl BlockIndex {
+    // Only what the palette gained or lost since the chunk was last indexed gets touched
+    pub fn update(&mut self, entity: Entity, key: ChunkKey, chunk: &ChunkData) {
+        if self
+            .indexed
+            .get(&entity)
+            .is_some_and(|indexed| indexed.key == key && indexed.revision == chunk.revision())
+        {
+            return;
+        }
+        let identifiers = chunk.palette_identifiers();
+        let mut old = BTreeSet::new();
+        if let Some(indexed) = self.indexed.remove(&entity) {
+            if indexed.key == key {
+                old = indexed.identifiers;
+            } else {
+                self.forget(indexed.key, &indexed.identifiers);
+            }
+        }
+        let gone: Vec<String> = old.difference(&identifiers).cloned().collect();
+        self.forget(key, &gone);
+        for identifier in identifiers.difference(&old) {
+            self.containing
+                .entry(identifier.clone())
+                .or_default()
+                .insert(key);
+        }
+        self.indexed.insert(
+            entity,
+            IndexedChunk {
+                key,
+                revision: chunk.revision(),
+                identifiers,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(indexed) = self.indexed.remove(&entity) {
+            self.forget(indexed.key, &indexed.identifiers);
+        }
+    }
+
+    fn forget<'a>(&mut self, key: ChunkKey, identifiers: impl IntoIterator<Item = &'a String>) {
+        for identifier in identifiers {
+            if let Some(chunks) = self.containing.get_mut(identifier) {
+                chunks.remove(&key);
+                if chunks.is_empty() {
+                    self.containing.remove(identifier);
+                }
+            }
+        }
+    }
+
+    pub fn chunks_containing<'a>(
+        &'a self,
+        dimension: DimensionId,
+        identifier: &str,
+    ) -> impl Iterator<Item = IVec3> + 'a {
+        self.containing
+            .get(identifier)
+            .into_iter()
+            .flatten()
+            .filter(move |(chunk_dimension, _)| *chunk_dimension == dimension)
+            .map(|(_, chunk_pos)| *chunk_pos)
+    }
+
+    // Up to limit of the block within radius of center, nearest first. Only chunks the index
+    // says hold it get scanned, nearest first, and the scan stops once no chunk left could
+    // hold anything closer than what's been found
+    pub fn find_blocks_near<'a>(
+        &self,
+        dimension: DimensionId,
+        center: IVec3,
+        identifier: &str,
+        radius: i32,
+        limit: usize,
+        chunk_at: impl Fn(IVec3) -> Option<&'a ChunkData>,
+    ) -> Vec<IVec3> {
+        let radius_squared = radius as i64 * radius as i64;
+        let mut chunks: Vec<(i64, IVec3)> = self
+            .chunks_containing(dimension, identifier)
+            .map(|chunk_pos| (chunk_distance_squared(center, chunk_pos), chunk_pos))
+            .filter(|(distance, _)| *distance <= radius_squared)
+            .collect();
+        chunks.sort_by_key(|(distance, chunk_pos)| (*distance, chunk_pos.to_array()));
+        let mut found: Vec<(i64, IVec3)> = Vec::new();
+        for (distance, chunk_pos) in chunks {
+            if limit == 0 || (found.len() == limit && found[limit - 1].0 < distance) {
+                break;
+            }
+            let Some(chunk) = chunk_at(chunk_pos) else {
+                continue;
+            };
+            found.extend(
+                chunk
+                    .positions_of(identifier)
+                    .into_iter()
+                    .map(|voxel| {
+                        let voxel = voxel_to_global_voxel(voxel, chunk_pos);
+                        (distance_squared(center, voxel), voxel)
+                    })
+                    .filter(|(distance, _)| *distance <= radius_squared),
+            );
+            found.sort_by_key(|(distance, voxel)| (*distance, voxel.to_array()));
+            found.truncate(limit);
+        }
+        found.into_iter().map(|(_, voxel)| voxel).collect()
+    }
+}
+
+fn distance_squared(a: IVec3, b: IVec3) -> i64 {
+    (a - b)
+        .to_array()
+        .into_iter()
+        .map(|axis| axis as i64 * axis as i64)
+        .sum()
+}
+
+// To the nearest voxel of the chunk
+fn chunk_distance_squared(center: IVec3, chunk_pos: IVec3) -> i64 {
+    let min = chunk_pos * CHUNK_SIZE as i32;
+    let max = min + IVec3::splat(CHUNK_SIZE as i32 - 1);
+    distance_squared(center, center.clamp(min, max))
+}
+
+// Chunks only ever change through their ChunkData, so the index only looks at ones that did
+pub fn index_chunks(
+    mut index: ResMut<BlockIndex>,
+    changed: Query<(Entity, &ChunkPos, &DimensionId, &ChunkData), Changed<ChunkData>>,
+    mut removed: RemovedComponents<ChunkData>,
+) {
+    for entity in removed.iter() {
+        index.remove(entity);
+    }
+    for (entity, chunk_pos, dimension, chunk) in changed.iter() {
+        index.update(entity, (*dimension, **chunk_pos), chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use vinox_common::{
+        testing::block,
+        world::chunks::storage::{name_to_identifier, BlockTable},
+    };
+
+    const BLOCKS: [&str; 4] = ["air", "stone", "dirt", "gold_ore"];
+
+    // What the index should hold, from every voxel of every chunk
+    fn brute_force(
+        chunks: &BTreeMap<Entity, (ChunkKey, ChunkData)>,
+    ) -> HashMap<String, HashSet<ChunkKey>> {
+        let mut containing: HashMap<String, HashSet<ChunkKey>> = HashMap::new();
+        for (key, chunk) in chunks.values() {
+            for idx in 0..ChunkData::usize() {
+                let (x, y, z) = ChunkData::delinearize(idx);
+                let voxel = chunk.get(x, y, z);
+                containing
+                    .entry(name_to_identifier(voxel.namespace, voxel.name))
+                    .or_default()
+                    .insert(*key);
+            }
+        }
+        containing
+    }
+
+    #[test]
+    fn random_edits_match_a_full_scan() {
+        let table = BlockTable::default();
+        for seed in 0..4 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut index = BlockIndex::default();
+            let mut loaded: BTreeMap<Entity, (ChunkKey, ChunkData)> = BTreeMap::new();
+            let mut next_entity = 0;
+            for step in 0..150 {
+                match rng.gen_range(0..10) {
+                    // Load or generate a chunk, there's only ever one entity for a position
+                    0 => {
+                        let key = (
+                            DimensionId(rng.gen_range(0..2)),
+                            IVec3::new(rng.gen_range(-1..=1), 0, 0),
+                        );
+                        if loaded.values().any(|(loaded_key, _)| *loaded_key == key) {
+                            continue;
+                        }
+                        let mut chunk = ChunkData::default();
+                        if rng.gen_bool(0.5) {
+                            chunk = ChunkData::uniform(block("stone"), &table);
+                        }
+                        let entity = Entity::from_raw(next_entity);
+                        next_entity += 1;
+                        index.update(entity, key, &chunk);
+                        loaded.insert(entity, (key, chunk));
+                    }
+                    // Unload
+                    1 => {
+                        let Some(&entity) =
+                            loaded.keys().nth(rng.gen_range(0..loaded.len().max(1)))
+                        else {
+                            continue;
+                        };
+                        loaded.remove(&entity);
+                        index.remove(entity);
+                    }
+                    // Compact, which never changes what's in the chunk
+                    2 => {
+                        for (entity, (key, chunk)) in loaded.iter_mut() {
+                            chunk.trim();
+                            index.update(*entity, *key, chunk);
+                        }
+                    }
+                    // Saved and read back, the same voxels under a new revision
+                    3 => {
+                        for (entity, (key, chunk)) in loaded.iter_mut() {
+                            *chunk = ChunkData::from_raw(chunk.to_raw());
+                            index.update(*entity, *key, chunk);
+                        }
+                    }
+                    // Edits kept to a corner so blocks come and go from the palette often
+                    _ => {
+                        let Some(&entity) =
+                            loaded.keys().nth(rng.gen_range(0..loaded.len().max(1)))
+                        else {
+                            continue;
+                        };
+                        let (key, chunk) = loaded.get_mut(&entity).unwrap();
+                        for _ in 0..rng.gen_range(1..4) {
+                            let name = BLOCKS[rng.gen_range(0..BLOCKS.len())];
+                            let (x, y, z) = (
+                                rng.gen_range(0..2),
+                                rng.gen_range(0..2),
+                                rng.gen_range(0..2),
+                            );
+                            chunk.set(x, y, z, block(name), &table);
+                        }
+                        index.update(entity, *key, chunk);
+                    }
+                }
+                assert_eq!(
+                    index.containing,
+                    brute_force(&loaded),
+                    "seed {seed}, step {step}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mined_out_blocks_leave_the_index() {
+        let table = BlockTable::default();
+        let entity = Entity::from_raw(0);
+        let key = (DimensionId(0), IVec3::ZERO);
+        let mut index = BlockIndex::default();
+        let mut chunk = ChunkData::default();
+        chunk.set(1, 1, 1, block("gold_ore"), &table);
+        index.update(entity, key, &chunk);
+        assert_eq!(
+            index
+                .chunks_containing(DimensionId(0), "vinox:gold_ore")
+                .collect::<Vec<_>>(),
+            vec![IVec3::ZERO]
+        );
+        assert_eq!(
+            index
+                .chunks_containing(DimensionId(1), "vinox:gold_ore")
+                .count(),
+            0
+        );
+        // The palette still has gold in it, with nothing pointing at it
+        chunk.set(1, 1, 1, block("air"), &table);
+        index.update(entity, key, &chunk);
+        assert_eq!(
+            index
+                .chunks_containing(DimensionId(0), "vinox:gold_ore")
+                .count(),
+            0
+        );
+        // And the entry getting reused for something else brings in only that
+        chunk.set(2, 2, 2, block("dirt"), &table);
+        index.update(entity, key, &chunk);
+        assert_eq!(
+            index.containing.keys().collect::<BTreeSet<_>>(),
+            BTreeSet::from([&"vinox:air".to_string(), &"vinox:dirt".to_string()])
+        );
+    }
+
+    #[test]
+    fn nearest_blocks_come_first() {
+        let table = BlockTable::default();
+        let mut index = BlockIndex::default();
+        let mut chunks = HashMap::new();
+        for (i, (chunk_pos, voxels)) in [
+            (IVec3::ZERO, vec![UVec3::new(4, 0, 0), UVec3::new(10, 0, 0)]),
+            (IVec3::new(1, 0, 0), vec![UVec3::new(0, 0, 0)]),
+            (IVec3::new(-4, 0, 0), vec![UVec3::new(15, 0, 0)]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut chunk = ChunkData::default();
+            for voxel in voxels {
+                chunk.set(voxel.x, voxel.y, voxel.z, block("gold_ore"), &table);
+            }
+            index.update(
+                Entity::from_raw(i as u32),
+                (DimensionId(0), chunk_pos),
+                &chunk,
+            );
+            chunks.insert(chunk_pos, chunk);
+        }
+        let find = |radius, limit| {
+            index.find_blocks_near(
+                DimensionId(0),
+                IVec3::ZERO,
+                "vinox:gold_ore",
+                radius,
+                limit,
+                |chunk_pos| chunks.get(&chunk_pos),
+            )
+        };
+        assert_eq!(
+            find(64, 10),
+            vec![
+                IVec3::new(4, 0, 0),
+                IVec3::new(10, 0, 0),
+                IVec3::new(16, 0, 0),
+                IVec3::new(-49, 0, 0)
+            ]
+        );
+        assert_eq!(find(64, 2), vec![IVec3::new(4, 0, 0), IVec3::new(10, 0, 0)]);
+        assert_eq!(
+            find(12, 10),
+            vec![IVec3::new(4, 0, 0), IVec3::new(10, 0, 0)]
+        );
+        assert!(find(64, 0).is_empty());
+        assert!(index
+            .find_blocks_near(
+                DimensionId(1),
+                IVec3::ZERO,
+                "vinox:gold_ore",
+                64,
+                10,
+                |chunk_pos| chunks.get(&chunk_pos)
+            )
+            .is_empty());
+    }
+}
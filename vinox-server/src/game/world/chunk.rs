@@ -4,17 +4,49 @@ use bevy::{
 };
 use futures_lite::future;
 use tokio::sync::mpsc::{Receiver, Sender};
-use vinox_common::world::chunks::{
-    ecs::{ChunkManager, CurrentChunks, RemoveChunk, SentChunks, SimulationRadius, ViewRadius},
-    positions::ChunkPos,
-    storage::{BlockTable, ChunkData, HORIZONTAL_DISTANCE, VERTICAL_DISTANCE},
+use vinox_common::{
+    ecs::{rng::WorldRng, time::ServerTick},
+    networking::protocol::EntityKind,
+    world::chunks::{
+        ecs::{ChunkManager, CurrentChunks, RemoveChunk, SentChunks, SimulationRadius, ViewRadius},
+        positions::{ChunkPos, DimensionId},
+        storage::{
+            BiomeTable, BlockTable, ChunkData, StructureTable, HORIZONTAL_DISTANCE,
+            MAX_VIEW_DISTANCE, MIN_VIEW_DISTANCE, VERTICAL_DISTANCE,
+        },
+    },
 };
 
-use crate::game::networking::components::SaveGame;
+use crate::game::networking::{commands::shutdown, components::SaveGame};
 
 use super::{
-    generation::generate_chunk,
-    storage::{load_chunk, save_chunks, ChunksToSave, WorldDatabase, WorldInfo},
+    block_index::{index_chunks, BlockIndex},
+    critter::critter_bundle,
+    dropped::spawn_dropped_item,
+    edits::{now_secs, EditLog},
+    generation::{
+        column_heights, decorate_chunk, generate_dimension_chunk, ChunkPhase, GenerationRegion,
+        GenerationScheduler, DECORATION_MARGIN,
+    },
+    lifecycle::{
+        autosave_chunks, mark_dirty_chunks, save_all_chunks, track_chunk_activity,
+        unload_idle_chunks, ChunkActivity, ChunkLifecycleStats, SaveAllEvent,
+    },
+    noise_graph::{load_noise_graphs, GenerationNoise},
+    snapshots::ChunkSnapshots,
+    storage::{
+        load_chunk, load_edit_log, load_snapshots, save_chunks, save_edit_logs, save_entities,
+        save_snapshots, save_spawn_points, save_unlocked_recipes, take_entities, ChunksToSave,
+        EditLogsToSave, EntitiesToSave, RecipesToSave, SnapshotsToSave, SpawnPointsToSave,
+        UnreadableChunks, WorldDatabase, WorldInfo,
+    },
+};
+
+// Noise graphs only reload in debug builds
+#[cfg(debug_assertions)]
+use {
+    super::noise_graph::reload_noise_graphs, bevy::time::common_conditions::on_timer,
+    std::time::Duration,
 };
 
 #[derive(Component, Default, Clone, Deref, DerefMut)]
@@ -31,84 +63,159 @@ impl LoadPoint {
     }
 }
 
+// The view distance a player asked for. Load points without one go by the ViewRadius resource
+#[derive(Component, Debug, Clone, Copy, Deref)]
+pub struct PlayerViewRadius(pub ViewRadius);
+
+impl PlayerViewRadius {
+    // Only the horizontal distance is up to the client
+    pub fn requested(horizontal: u8, default: &ViewRadius) -> Self {
+        Self(ViewRadius {
+            horizontal: (horizontal as usize).clamp(MIN_VIEW_DISTANCE, MAX_VIEW_DISTANCE) as i32,
+            vertical: default.vertical,
+        })
+    }
+
+    pub fn or_default(own: Option<&Self>, default: &ViewRadius) -> ViewRadius {
+        own.map_or(*default, |own| own.0)
+    }
+}
+
 #[derive(Default, Resource, Debug)]
 pub struct ChunkQueue {
-    pub create: Vec<ChunkPos>,
+    pub create: Vec<(DimensionId, ChunkPos)>,
     pub remove: Vec<ChunkPos>,
 }
 
 pub fn generate_chunks_world(
-    load_points: Query<&LoadPoint>,
+    load_points: Query<(&LoadPoint, &DimensionId, Option<&PlayerViewRadius>)>,
     mut chunk_queue: ResMut<ChunkQueue>,
     mut commands: Commands,
     mut chunk_manager: ChunkManager,
     database: Res<WorldDatabase>,
     save: Res<SaveGame>,
+    world_info: Res<WorldInfo>,
+    (mut chunks_to_save, mut unreadable, tick): (
+        ResMut<ChunksToSave>,
+        ResMut<UnreadableChunks>,
+        Res<ServerTick>,
+    ),
 ) {
-    for point in load_points.iter() {
-        for pos in chunk_manager.get_chunk_positions(ChunkPos(**point)) {
-            if chunk_manager.current_chunks.get_entity(pos).is_none() {
-                let data = database.connection.get().unwrap();
-                if let Some(chunk) = load_chunk(pos, &data) {
-                    if **save {
-                        let chunk_id = commands.spawn(ChunkData::from_raw(chunk)).insert(pos).id();
-                        chunk_manager.current_chunks.insert_entity(pos, chunk_id);
-                        continue;
+    let mut wanted = Vec::new();
+    for (point, dimension, own_radius) in load_points.iter() {
+        let radius = PlayerViewRadius::or_default(own_radius, &chunk_manager.view_radius);
+        wanted.extend(
+            radius
+                .chunks_around(ChunkPos(**point))
+                .into_iter()
+                .map(|pos| (*dimension, pos)),
+        );
+    }
+    // Force loaded chunks come in with nobody around
+    wanted.extend(
+        world_info
+            .chunk_lifecycle
+            .force_loaded
+            .iter()
+            .map(|forced| (forced.dimension, ChunkPos(forced.chunk))),
+    );
+    for (dimension, pos) in wanted {
+        if world_info.generator(dimension).is_none() {
+            continue;
+        }
+        if chunk_manager
+            .current_chunks
+            .get_entity_in(dimension, pos)
+            .is_none()
+        {
+            let data = database.connection.get().unwrap();
+            match load_chunk(dimension, pos, &data) {
+                Ok(Some(loaded)) if **save => {
+                    let mut chunk_data = ChunkData::from_raw(loaded.chunk);
+                    chunk_data.measure_heights(&chunk_manager.block_table);
+                    // Written back in the current format, only chunks that get loaded
+                    if loaded.migrated {
+                        chunks_to_save.push((dimension, pos, chunk_data.to_raw()));
+                    }
+                    let mut edit_log = load_edit_log(dimension, pos, &data);
+                    edit_log.prune(now_secs(), world_info.edit_retention_secs());
+                    let snapshots = load_snapshots(dimension, pos, &data);
+                    let chunk_id = commands
+                        .spawn(chunk_data)
+                        .insert((
+                            pos,
+                            dimension,
+                            edit_log,
+                            snapshots,
+                            ChunkActivity::new(*tick),
+                        ))
+                        .id();
+                    chunk_manager
+                        .current_chunks
+                        .insert_entity_in(dimension, pos, chunk_id);
+                    for saved_entity in take_entities(dimension, pos, &data) {
+                        match (saved_entity.kind, saved_entity.item) {
+                            (EntityKind::Critter, _) => {
+                                commands.spawn(critter_bundle(saved_entity.translation));
+                            }
+                            (EntityKind::DroppedItem, Some(item)) => {
+                                spawn_dropped_item(
+                                    &mut commands,
+                                    item,
+                                    saved_entity.translation,
+                                    Vec3::ZERO,
+                                    None,
+                                    dimension,
+                                );
+                            }
+                            (EntityKind::DroppedItem, None) => {}
+                        }
+                    }
+                    continue;
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    if unreadable.insert((dimension, pos)) {
+                        println!(
+                            "Chunk {:?} in dimension {} couldn't be loaded and won't be saved over: {error}",
+                            *pos, *dimension
+                        );
                     }
                 }
-                let chunk_id = commands.spawn(pos).id();
-                chunk_manager.current_chunks.insert_entity(pos, chunk_id);
-                chunk_queue.create.push(pos);
             }
+            let chunk_id = commands
+                .spawn((
+                    pos,
+                    dimension,
+                    EditLog::default(),
+                    ChunkSnapshots::default(),
+                    ChunkActivity::new(*tick),
+                ))
+                .id();
+            chunk_manager
+                .current_chunks
+                .insert_entity_in(dimension, pos, chunk_id);
+            chunk_queue.create.push((dimension, pos));
         }
     }
 }
 
+// Anyone still holding the chunk is told to drop it by unsend_chunks. Without saving it comes
+// back regenerated, so it has to be sent again rather than assumed to match
 pub fn destroy_chunks(
     mut commands: Commands,
     mut current_chunks: ResMut<CurrentChunks>,
-    remove_chunks: Query<&ChunkPos, With<RemoveChunk>>,
-    mut load_points: Query<&mut SentChunks>,
-) {
-    for chunk in remove_chunks.iter() {
-        for mut sent_chunks in load_points.iter_mut() {
-            sent_chunks.chunks.remove(chunk);
-        }
-        commands
-            .entity(current_chunks.remove_entity(*chunk).unwrap())
-            .despawn_recursive();
-    }
-}
-
-pub fn clear_unloaded_chunks(
-    mut commands: Commands,
-    chunks: Query<(&ChunkPos, Entity)>,
-    load_points: Query<&LoadPoint>,
-    view_radius: Res<ViewRadius>,
+    remove_chunks: Query<(&ChunkPos, &DimensionId), With<RemoveChunk>>,
+    mut load_points: Query<(&mut SentChunks, &DimensionId)>,
 ) {
-    for (chunk, entity) in chunks.iter() {
-        for load_point in load_points.iter() {
-            if load_point.is_in_radius(**chunk, &view_radius) {
-                continue;
-            } else {
-                commands.entity(entity).insert(RemoveChunk);
+    for (chunk, dimension) in remove_chunks.iter() {
+        for (mut sent_chunks, player_dimension) in load_points.iter_mut() {
+            if player_dimension == dimension {
+                sent_chunks.forget(*chunk);
             }
         }
-    }
-}
-
-pub fn unsend_chunks(
-    chunks: Query<&ChunkPos>,
-    mut load_points: Query<(&LoadPoint, &mut SentChunks)>,
-    view_radius: Res<ViewRadius>,
-) {
-    for (load_point, mut sent_chunks) in load_points.iter_mut() {
-        for chunk in chunks.iter() {
-            if !load_point.is_in_radius(**chunk, &view_radius) {
-                sent_chunks.chunks.remove(chunk);
-            } else {
-                continue;
-            }
+        if let Some(chunk_entity) = current_chunks.remove_entity_in(*dimension, *chunk) {
+            commands.entity(chunk_entity).despawn_recursive();
         }
     }
 }
@@ -127,48 +234,163 @@ pub fn unsend_chunks(
 //     }
 // }
 
-pub fn process_save(mut chunks_to_save: ResMut<ChunksToSave>, database: Res<WorldDatabase>) {
+pub fn process_save(
+    mut chunks_to_save: ResMut<ChunksToSave>,
+    mut entities_to_save: ResMut<EntitiesToSave>,
+    (mut edit_logs_to_save, mut snapshots_to_save): (
+        ResMut<EditLogsToSave>,
+        ResMut<SnapshotsToSave>,
+    ),
+    (mut recipes_to_save, mut spawn_points_to_save): (
+        ResMut<RecipesToSave>,
+        ResMut<SpawnPointsToSave>,
+    ),
+    database: Res<WorldDatabase>,
+    unreadable: Res<UnreadableChunks>,
+) {
+    if !unreadable.is_empty() {
+        chunks_to_save.retain(|(dimension, pos, _)| !unreadable.contains(&(*dimension, *pos)));
+        edit_logs_to_save.retain(|(dimension, pos, _)| !unreadable.contains(&(*dimension, *pos)));
+        snapshots_to_save.retain(|(dimension, pos, _)| !unreadable.contains(&(*dimension, *pos)));
+    }
     save_chunks(&chunks_to_save, &database.connection.get().unwrap());
     chunks_to_save.clear();
+    // Goes after the blocks so the row already exists for fresh chunks
+    if !edit_logs_to_save.is_empty() {
+        save_edit_logs(&edit_logs_to_save, &database.connection.get().unwrap());
+        edit_logs_to_save.clear();
+    }
+    if !snapshots_to_save.is_empty() {
+        save_snapshots(&snapshots_to_save, &database.connection.get().unwrap());
+        snapshots_to_save.clear();
+    }
+    if !entities_to_save.is_empty() {
+        save_entities(&entities_to_save, &database.connection.get().unwrap());
+        entities_to_save.clear();
+    }
+    if !recipes_to_save.is_empty() {
+        save_unlocked_recipes(&recipes_to_save, &database.connection.get().unwrap());
+        recipes_to_save.clear();
+    }
+    if !spawn_points_to_save.is_empty() {
+        save_spawn_points(&spawn_points_to_save, &database.connection.get().unwrap());
+        spawn_points_to_save.clear();
+    }
 }
 
 #[derive(Component)]
-pub struct GenTask(Task<(ChunkData, ChunkPos)>);
+pub struct GenTask(Task<(ChunkPhase, ChunkData, ChunkPos, DimensionId)>);
 
 pub fn process_queue(
     mut commands: Commands,
     mut chunk_queue: ResMut<ChunkQueue>,
+    mut scheduler: ResMut<GenerationScheduler>,
     mut gen_task: Query<(Entity, &mut GenTask)>,
-    // mut chunk_channel: ResMut<ChunkChannel>,
     current_chunks: Res<CurrentChunks>,
-    world_info: Res<WorldInfo>,
+    (world_info, world_rng, noise): (Res<WorldInfo>, Res<WorldRng>, Res<GenerationNoise>),
     mut chunks_to_save: ResMut<ChunksToSave>,
-    block_table: Res<BlockTable>,
+    (block_table, biome_table, structure_table): (
+        Res<BlockTable>,
+        Res<BiomeTable>,
+        Res<StructureTable>,
+    ),
     save: Res<SaveGame>,
+    load_points: Query<(&LoadPoint, &DimensionId, Option<&PlayerViewRadius>)>,
+    view_radius: Res<ViewRadius>,
 ) {
-    let cloned_seed = world_info.seed;
+    gen_task.for_each_mut(|(entity, mut task)| {
+        if let Some((phase, chunk_data, chunk_pos, dimension)) =
+            future::block_on(future::poll_once(&mut task.0))
+        {
+            if phase == ChunkPhase::Terrain {
+                scheduler.finish_terrain((dimension, chunk_pos), chunk_data);
+            } else {
+                scheduler.finish_decoration((dimension, chunk_pos));
+                if **save {
+                    chunks_to_save.push((dimension, chunk_pos, chunk_data.to_raw()));
+                }
+                // Only now does the chunk get sent to anyone
+                if let Some(chunk_entity) = current_chunks.get_entity_in(dimension, chunk_pos) {
+                    commands.entity(chunk_entity).insert(chunk_data);
+                }
+            }
+            commands.entity(entity).despawn_recursive();
+        }
+    });
+
+    for key in chunk_queue.create.drain(..) {
+        scheduler.request(key);
+    }
+    scheduler
+        .cancel_unless(|(dimension, pos)| current_chunks.get_entity_in(*dimension, *pos).is_some());
+
+    let seed = world_info.seed;
     let task_pool = AsyncComputeTaskPool::get();
-    for chunk_pos in chunk_queue.create.drain(..) {
+    for (dimension, chunk_pos) in scheduler.start_terrain() {
         let cloned_table = block_table.clone();
+        let biomes = biome_table.clone();
+        let generator = world_info.generator(dimension).unwrap_or_default();
+        let graphs = noise.0.clone();
+        let shape = world_info.terrain.clone();
         let task = task_pool.spawn(async move {
             (
-                ChunkData::from_raw(generate_chunk(*chunk_pos, cloned_seed, &cloned_table)),
+                ChunkPhase::Terrain,
+                ChunkData::from_raw(generate_dimension_chunk(
+                    *chunk_pos,
+                    seed,
+                    generator,
+                    &graphs,
+                    &shape,
+                    &biomes,
+                    &cloned_table,
+                )),
                 chunk_pos,
+                dimension,
             )
         });
         commands.spawn(GenTask(task));
     }
-    gen_task.for_each_mut(|(entity, mut task)| {
-        if let Some(chunk) = future::block_on(future::poll_once(&mut task.0)) {
-            let chunk_pos = chunk.1;
-            if **save {
-                chunks_to_save.push((chunk_pos, chunk.0.to_raw()));
-            }
-            if let Some(chunk_entity) = current_chunks.get_entity(chunk_pos) {
-                commands.entity(chunk_entity).insert(chunk);
-            }
-            commands.entity(entity).despawn_recursive();
-        }
+    for ((dimension, chunk_pos), base) in scheduler.start_decoration() {
+        let cloned_table = block_table.clone();
+        let biomes = biome_table.clone();
+        let generator = world_info.generator(dimension).unwrap_or_default();
+        let structures = structure_table.clone();
+        let world_rng = *world_rng;
+        let graphs = noise.0.clone();
+        let shape = world_info.terrain.clone();
+        let task = task_pool.spawn(async move {
+            let region = GenerationRegion::new(*chunk_pos, DECORATION_MARGIN, base).unwrap();
+            let heights = column_heights(*chunk_pos, seed, &graphs, &shape);
+            (
+                ChunkPhase::Decorating,
+                decorate_chunk(
+                    region,
+                    generator,
+                    &heights,
+                    &world_rng,
+                    &biomes,
+                    &structures,
+                    &cloned_table,
+                ),
+                chunk_pos,
+                dimension,
+            )
+        });
+        commands.spawn(GenTask(task));
+    }
+
+    // Bases one chunk past the view radius are kept, the edge needs them again as a player walks
+    scheduler.prune(|(dimension, pos)| {
+        load_points
+            .iter()
+            .any(|(point, point_dimension, own_radius)| {
+                let radius = PlayerViewRadius::or_default(own_radius, &view_radius);
+                let reach = ViewRadius {
+                    horizontal: radius.horizontal + 1,
+                    vertical: radius.vertical + 1,
+                };
+                point_dimension == dimension && point.is_in_radius(**pos, &reach)
+            })
     });
 }
 
@@ -177,8 +399,15 @@ pub struct ChunkPlugin;
 impl Plugin for ChunkPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(ChunksToSave::default())
+            .insert_resource(EditLogsToSave::default())
+            .insert_resource(SnapshotsToSave::default())
+            .insert_resource(RecipesToSave::default())
+            .insert_resource(SpawnPointsToSave::default())
+            .insert_resource(UnreadableChunks::default())
             .insert_resource(CurrentChunks::default())
             .insert_resource(ChunkQueue::default())
+            .insert_resource(GenerationScheduler::default())
+            .init_resource::<GenerationNoise>()
             .insert_resource(ViewRadius {
                 horizontal: HORIZONTAL_DISTANCE as i32,
                 vertical: VERTICAL_DISTANCE as i32,
@@ -187,12 +416,27 @@ impl Plugin for ChunkPlugin {
                 vertical: 4,
                 horizontal: 4,
             })
-            .add_systems((clear_unloaded_chunks, unsend_chunks, generate_chunks_world))
-            .add_system(process_queue.after(clear_unloaded_chunks))
+            .init_resource::<ChunkLifecycleStats>()
+            .init_resource::<BlockIndex>()
+            .add_event::<SaveAllEvent>()
+            .add_system(generate_chunks_world)
+            .add_systems((track_chunk_activity, mark_dirty_chunks, unload_idle_chunks).chain())
+            .add_system(autosave_chunks.before(save_all_chunks))
+            .add_system(
+                save_all_chunks
+                    .after(unload_idle_chunks)
+                    .after(shutdown)
+                    .before(process_save),
+            )
+            .add_system(process_queue.after(unload_idle_chunks))
             .add_system(process_save.after(process_queue))
             // .add_startup_system(|mut commands: Commands| {
             //     commands.insert_resource(ChunkChannel::default());
             // })
-            .add_system(destroy_chunks.after(process_queue));
+            .add_system(destroy_chunks.after(process_queue))
+            .add_system(index_chunks.after(destroy_chunks))
+            .add_startup_system(load_noise_graphs);
+        #[cfg(debug_assertions)]
+        app.add_system(reload_noise_graphs.run_if(on_timer(Duration::from_secs(2))));
     }
 }
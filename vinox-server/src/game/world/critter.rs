@@ -0,0 +1,552 @@
+use std::{collections::HashMap, time::Duration};
+
+use bevy::{math::Vec3A, prelude::*, render::primitives::Aabb, time::common_conditions::on_timer};
+use rand::Rng;
+use vinox_common::{
+    ecs::{
+        rng::WorldRng,
+        time::{GameClock, ServerTick},
+    },
+    networking::protocol::{EntityKind, Player, SavedEntity},
+    physics::simulate::{CollidesWithWorld, Velocity},
+    world::chunks::{
+        ecs::{ChunkManager, RemoveChunk, SimulationRadius},
+        heightmap::SurfaceKind,
+        positions::{world_to_chunk, world_to_global_voxel, ChunkPos, DimensionId},
+        storage::CHUNK_SIZE,
+    },
+};
+
+use crate::game::{load::LoadSet, networking::components::SaveGame};
+
+use super::{
+    block_index::BlockIndex,
+    chunk::{destroy_chunks, LoadPoint},
+    dropped::DroppedItem,
+    lifecycle::ChunkActivity,
+    spawn_rules::{
+        load_spawn_rules, reload_spawn_rules, Candidate, NearBlock, SpawnRules, SpawnStats,
+    },
+    storage::EntitiesToSave,
+};
+
+pub const CRITTERS_PER_CHUNK: usize = 2;
+pub const MAX_CRITTERS: usize = 32;
+pub const DESPAWN_DISTANCE: f32 = 48.0;
+pub const DESPAWN_TIME: f32 = 30.0;
+pub const WANDER_RADIUS: i32 = 6;
+pub const WANDER_ATTEMPTS: usize = 8;
+pub const WALK_SPEED: f32 = 1.5;
+pub const JUMP_SPEED: f32 = 10.0;
+pub const GRAVITY: f32 = 35.0;
+// Worley is what the generator places for now, grass will take over once surfaces are generated
+pub const SPAWN_SURFACES: [&str; 2] = ["vinox:grass", "vinox:worley"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CritterState {
+    Idle(f32),
+    Walking { target: IVec3, time_left: f32 },
+}
+
+impl Default for CritterState {
+    fn default() -> Self {
+        CritterState::Idle(0.0)
+    }
+}
+
+#[derive(Component, Default)]
+pub struct Critter {
+    pub state: CritterState,
+    pub despawn_timer: f32,
+}
+
+pub fn critter_bundle(translation: Vec3) -> impl Bundle {
+    let half_extents = Vec3A::new(0.3, 0.25, 0.3);
+    (
+        Critter::default(),
+        Transform::from_translation(translation),
+        GlobalTransform::default(),
+        Aabb {
+            center: Vec3A::from(translation) + Vec3A::Y * half_extents.y,
+            half_extents,
+        },
+        Velocity(Vec3::ZERO),
+        CollidesWithWorld,
+    )
+}
+
+// Unloaded voxels report None so nothing ever targets or stands on a chunk we don't have yet
+pub fn is_solid(chunk_manager: &ChunkManager, voxel_pos: IVec3) -> Option<bool> {
+    chunk_manager
+        .get_block(voxel_pos)
+        .map(|block| !block.is_empty(&chunk_manager.block_table))
+}
+
+pub fn is_standable(pos: IVec3, is_solid: &impl Fn(IVec3) -> Option<bool>) -> bool {
+    is_solid(pos - IVec3::Y) == Some(true)
+        && is_solid(pos) == Some(false)
+        && is_solid(pos + IVec3::Y) == Some(false)
+}
+
+pub fn pick_wander_target(
+    origin: IVec3,
+    rng: &mut impl Rng,
+    is_solid: impl Fn(IVec3) -> Option<bool>,
+) -> Option<IVec3> {
+    for _ in 0..WANDER_ATTEMPTS {
+        let offset = IVec3::new(
+            rng.gen_range(-WANDER_RADIUS..=WANDER_RADIUS),
+            0,
+            rng.gen_range(-WANDER_RADIUS..=WANDER_RADIUS),
+        );
+        // Only look one block up so the target is always reachable with a single jump
+        for dy in [0, 1, -1, -2] {
+            let candidate = origin + offset + IVec3::Y * dy;
+            if is_standable(candidate, &is_solid) {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+pub fn find_spawn_surface(
+    chunk_pos: IVec3,
+    x: i32,
+    z: i32,
+    chunk_manager: &ChunkManager,
+) -> Option<IVec3> {
+    let base = chunk_pos * CHUNK_SIZE as i32;
+    // Only the ground the column tops out at, nothing spawns under overhangs or in caves
+    let y = chunk_manager.surface_below(
+        base + IVec3::new(x, CHUNK_SIZE as i32 - 1, z),
+        SurfaceKind::MotionBlocking,
+    )?;
+    if y < base.y {
+        return None;
+    }
+    let surface = IVec3::new(base.x + x, y, base.z + z);
+    let identifier = chunk_manager.get_identifier(surface)?;
+    (SPAWN_SURFACES.contains(&identifier.as_str())
+        && is_standable(surface + IVec3::Y, &|pos| is_solid(chunk_manager, pos)))
+    .then_some(surface + IVec3::Y)
+}
+
+pub fn spawn_critters(
+    mut commands: Commands,
+    load_points: Query<(&LoadPoint, &DimensionId, Option<&Transform>), With<Player>>,
+    critters: Query<&Transform, With<Critter>>,
+    chunk_manager: ChunkManager,
+    simulation_radius: Res<SimulationRadius>,
+    (world_rng, tick): (Res<WorldRng>, Res<ServerTick>),
+    (rules, mut stats, block_index): (Res<SpawnRules>, ResMut<SpawnStats>, Res<BlockIndex>),
+) {
+    let mut rng = world_rng.tick_stream("critter_spawn", *tick);
+    let mut positions: Vec<Vec3> = critters
+        .iter()
+        .map(|transform| transform.translation)
+        .collect();
+    let mut total = positions.len();
+    let mut per_chunk: HashMap<IVec3, usize> = HashMap::new();
+    for position in positions.iter() {
+        *per_chunk.entry(world_to_chunk(*position)).or_default() += 1;
+    }
+    // Critters only live in the overworld for now
+    for (load_point, dimension, transform) in load_points
+        .iter()
+        .filter(|(_, dimension, _)| ***dimension == 0)
+    {
+        if total >= MAX_CRITTERS {
+            return;
+        }
+        let chunk_pos = **load_point
+            + IVec3::new(
+                rng.gen_range(-simulation_radius.horizontal..=simulation_radius.horizontal),
+                rng.gen_range(-simulation_radius.vertical..=simulation_radius.vertical),
+                rng.gen_range(-simulation_radius.horizontal..=simulation_radius.horizontal),
+            );
+        let count = per_chunk.entry(chunk_pos).or_default();
+        if *count >= CRITTERS_PER_CHUNK {
+            continue;
+        }
+        let (x, z) = (
+            rng.gen_range(0..CHUNK_SIZE as i32),
+            rng.gen_range(0..CHUNK_SIZE as i32),
+        );
+        let Some(spawn_pos) = find_spawn_surface(chunk_pos, x, z, &chunk_manager) else {
+            continue;
+        };
+        stats.considered += 1;
+        // Players that haven't sent a position yet are somewhere in their load point
+        let player = transform.map_or(
+            (**load_point * CHUNK_SIZE as i32).as_vec3() + Vec3::splat(CHUNK_SIZE as f32 / 2.0),
+            |transform| transform.translation,
+        );
+        let candidate = Candidate {
+            dimension: *dimension,
+            voxel: spawn_pos,
+            ..Default::default()
+        };
+        let near = |radius: f32| {
+            positions
+                .iter()
+                .filter(|position| position.distance(player) < radius)
+                .count()
+        };
+        let block_near = |near_block: &NearBlock| {
+            !block_index
+                .find_blocks_near(
+                    *dimension,
+                    spawn_pos,
+                    &near_block.identifier,
+                    near_block.radius,
+                    1,
+                    |chunk_pos| {
+                        let entity = chunk_manager
+                            .current_chunks
+                            .get_entity_in(*dimension, ChunkPos(chunk_pos))?;
+                        chunk_manager.chunk_query.get(entity).ok()
+                    },
+                )
+                .is_empty()
+        };
+        if let Err(rule) = rules.check(EntityKind::Critter, &candidate, block_near, near) {
+            stats.reject(rule);
+            continue;
+        }
+        let translation = spawn_pos.as_vec3() + Vec3::new(0.5, 0.0, 0.5);
+        commands.spawn(critter_bundle(translation));
+        positions.push(translation);
+        stats.spawned += 1;
+        *count += 1;
+        total += 1;
+    }
+}
+
+pub fn wander_critters(
+    mut critters: Query<(&mut Critter, &mut Velocity, &Transform)>,
+    chunk_manager: ChunkManager,
+    activity: Query<&ChunkActivity>,
+    clock: Res<GameClock>,
+    (world_rng, tick): (Res<WorldRng>, Res<ServerTick>),
+) {
+    let mut rng = world_rng.tick_stream("critter_wander", *tick);
+    let delta = clock.delta_seconds();
+    for (mut critter, mut velocity, transform) in critters.iter_mut() {
+        // Nobody close enough to see it, it waits where it is
+        let simulated = chunk_manager
+            .current_chunks
+            .get_entity_in(
+                DimensionId::default(),
+                ChunkPos(world_to_chunk(transform.translation)),
+            )
+            .and_then(|entity| activity.get(entity).ok())
+            .is_some_and(|activity| activity.simulated);
+        if !simulated {
+            velocity.0 = Vec3::ZERO;
+            continue;
+        }
+        let feet = world_to_global_voxel(transform.translation);
+        let below = is_solid(&chunk_manager, feet - IVec3::Y);
+        // Hold still until the chunks around us exist so we never fall through a border
+        if below.is_none() || is_solid(&chunk_manager, feet).is_none() {
+            velocity.0 = Vec3::ZERO;
+            continue;
+        }
+        let on_ground = below == Some(true) && velocity.0.y <= 0.0;
+        velocity.0.y -= GRAVITY * delta;
+
+        let mut horizontal = Vec3::ZERO;
+        let mut next_state = None;
+        match &mut critter.state {
+            CritterState::Idle(timer) => {
+                *timer -= delta;
+                if *timer <= 0.0 {
+                    next_state = Some(
+                        match pick_wander_target(feet, &mut rng, |pos| {
+                            is_solid(&chunk_manager, pos)
+                        }) {
+                            Some(target) => CritterState::Walking {
+                                target,
+                                time_left: 8.0,
+                            },
+                            None => CritterState::Idle(rng.gen_range(2.0..6.0)),
+                        },
+                    );
+                } else if on_ground && rng.gen_bool(0.002) {
+                    velocity.0.y = JUMP_SPEED * 0.6;
+                }
+            }
+            CritterState::Walking { target, time_left } => {
+                *time_left -= delta;
+                let to_target = (target.as_vec3() + Vec3::new(0.5, 0.0, 0.5)
+                    - transform.translation)
+                    * Vec3::new(1.0, 0.0, 1.0);
+                if to_target.length() < 0.3 || *time_left <= 0.0 {
+                    next_state = Some(CritterState::Idle(rng.gen_range(2.0..6.0)));
+                } else {
+                    horizontal = to_target.normalize() * WALK_SPEED;
+                    let ahead =
+                        world_to_global_voxel(transform.translation + to_target.normalize() * 0.6);
+                    if on_ground
+                        && is_solid(&chunk_manager, ahead) == Some(true)
+                        && is_solid(&chunk_manager, ahead + IVec3::Y) == Some(false)
+                    {
+                        velocity.0.y = JUMP_SPEED;
+                    }
+                }
+            }
+        }
+        if let Some(state) = next_state {
+            critter.state = state;
+        }
+        velocity.0.x = horizontal.x;
+        velocity.0.z = horizontal.z;
+    }
+}
+
+pub fn despawn_critters(
+    mut commands: Commands,
+    mut critters: Query<(Entity, &mut Critter, &Transform)>,
+    players: Query<&Transform, With<Player>>,
+    clock: Res<GameClock>,
+) {
+    for (entity, mut critter, transform) in critters.iter_mut() {
+        let near_player = players
+            .iter()
+            .any(|player| player.translation.distance(transform.translation) < DESPAWN_DISTANCE);
+        if near_player {
+            critter.despawn_timer = 0.0;
+        } else {
+            critter.despawn_timer += clock.delta_seconds();
+            if critter.despawn_timer > DESPAWN_TIME {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
+
+// Runs before the chunk is despawned so the entities inside it go to saved_entities in the same pass
+pub fn store_entities(
+    mut commands: Commands,
+    removed_chunks: Query<(&ChunkPos, &DimensionId), With<RemoveChunk>>,
+    critters: Query<(Entity, &Transform), With<Critter>>,
+    dropped_items: Query<(Entity, &Transform, &DroppedItem, &DimensionId)>,
+    mut entities_to_save: ResMut<EntitiesToSave>,
+    save: Res<SaveGame>,
+) {
+    for (chunk_pos, dimension) in removed_chunks.iter() {
+        let in_chunk = |transform: &Transform| world_to_chunk(transform.translation) == **chunk_pos;
+        let mut saved_entities = Vec::new();
+        // Critters only live in the overworld
+        if **dimension == 0 {
+            for (entity, transform) in critters.iter() {
+                if in_chunk(transform) {
+                    saved_entities.push(SavedEntity {
+                        kind: EntityKind::Critter,
+                        translation: transform.translation,
+                        item: None,
+                    });
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+        }
+        for (entity, transform, dropped, item_dimension) in dropped_items.iter() {
+            if item_dimension == dimension && in_chunk(transform) {
+                saved_entities.push(SavedEntity {
+                    kind: EntityKind::DroppedItem,
+                    translation: transform.translation,
+                    item: Some(dropped.item.clone()),
+                });
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        if **save && !saved_entities.is_empty() {
+            entities_to_save.push((*dimension, *chunk_pos, saved_entities));
+        }
+    }
+}
+
+pub struct CritterPlugin;
+
+impl Plugin for CritterPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(EntitiesToSave::default())
+            .init_resource::<SpawnRules>()
+            .init_resource::<SpawnStats>()
+            .add_startup_system(load_spawn_rules)
+            .add_system(reload_spawn_rules.run_if(on_timer(Duration::from_secs(2))))
+            .add_system(
+                spawn_critters
+                    .run_if(on_timer(Duration::from_secs(2)))
+                    .in_set(LoadSet::Ambient),
+            )
+            .add_system(wander_critters.in_set(LoadSet::Ambient))
+            .add_system(despawn_critters)
+            .add_system(store_entities.before(destroy_chunks));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::world::{block_index::index_chunks, spawn_rules::SpawnRule};
+    use rand::{rngs::StdRng, SeedableRng};
+    use vinox_common::{
+        testing::{self, descriptor},
+        world::chunks::{
+            ecs::{CurrentChunks, ViewRadius},
+            light::{VoxelAddedEvent, VoxelRemovedEvent},
+            storage::{BlockData, ChunkData, VoxelVisibility},
+        },
+    };
+
+    // A floor at y = 0 with a pillar at the origin column
+    fn fixture(pos: IVec3) -> Option<bool> {
+        if pos.x.abs() > 16 || pos.z.abs() > 16 {
+            return None;
+        }
+        Some(pos.y <= 0 || (pos.x == 0 && pos.z == 0 && pos.y <= 3))
+    }
+
+    #[test]
+    fn wander_target_never_solid() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..1000 {
+            if let Some(target) = pick_wander_target(IVec3::new(2, 1, 2), &mut rng, fixture) {
+                assert_eq!(fixture(target), Some(false));
+                assert_eq!(fixture(target + IVec3::Y), Some(false));
+                assert_eq!(fixture(target - IVec3::Y), Some(true));
+            }
+        }
+    }
+
+    // Thirty players in the middle of flat ground, run for 50 spawn passes
+    fn flat_world(rules: SpawnRules) -> App {
+        let block_table = testing::block_table([
+            descriptor("air", VoxelVisibility::Empty),
+            descriptor("worley", VoxelVisibility::Opaque),
+        ]);
+        let mut app = App::new();
+        app.insert_resource(ViewRadius::default())
+            .insert_resource(SimulationRadius {
+                horizontal: 2,
+                vertical: 1,
+            })
+            .add_event::<VoxelAddedEvent>()
+            .add_event::<VoxelRemovedEvent>()
+            .insert_resource(WorldRng::new(7))
+            .init_resource::<ServerTick>()
+            .insert_resource(rules)
+            .init_resource::<SpawnStats>()
+            .init_resource::<BlockIndex>()
+            .add_systems((index_chunks, spawn_critters).chain());
+        let mut current_chunks = CurrentChunks::default();
+        for x in -2..=2 {
+            for y in -1..=1 {
+                for z in -2..=2 {
+                    let mut chunk = ChunkData::default();
+                    for voxel in 0..CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
+                        let (vx, vy, vz) = ChunkData::delinearize(voxel);
+                        if y < 0 || (y == 0 && vy < 4) {
+                            chunk.set(
+                                vx,
+                                vy,
+                                vz,
+                                BlockData::new("vinox".to_string(), "worley".to_string()),
+                                &block_table,
+                            );
+                        }
+                    }
+                    let pos = ChunkPos(IVec3::new(x, y, z));
+                    let entity = app.world.spawn((chunk, pos, DimensionId::default())).id();
+                    current_chunks.insert_entity(pos, entity);
+                }
+            }
+        }
+        app.insert_resource(current_chunks)
+            .insert_resource(block_table);
+        for _ in 0..30 {
+            app.world.spawn((
+                Player::default(),
+                LoadPoint(IVec3::ZERO),
+                DimensionId::default(),
+            ));
+        }
+        for _ in 0..50 {
+            app.update();
+            app.world.resource_mut::<ServerTick>().0 += 1;
+        }
+        app
+    }
+
+    fn critters_per_chunk(app: &mut App) -> HashMap<IVec3, usize> {
+        let mut per_chunk: HashMap<IVec3, usize> = HashMap::new();
+        let mut query = app.world.query_filtered::<&Transform, With<Critter>>();
+        for transform in query.iter(&app.world) {
+            *per_chunk
+                .entry(world_to_chunk(transform.translation))
+                .or_default() += 1;
+        }
+        per_chunk
+    }
+
+    #[test]
+    fn spawn_cap_holds() {
+        let mut app = flat_world(SpawnRules::default());
+        let per_chunk = critters_per_chunk(&mut app);
+        assert!(!per_chunk.is_empty());
+        assert!(per_chunk.values().sum::<usize>() <= MAX_CRITTERS);
+        assert!(per_chunk.values().all(|count| *count <= CRITTERS_PER_CHUNK));
+    }
+
+    #[test]
+    fn per_player_cap_accounts_for_new_spawns() {
+        let mut rules = SpawnRules::default();
+        rules.critter.per_player_cap = Some(3);
+        rules.critter.per_player_radius = 1000.0;
+        let mut app = flat_world(rules);
+        // Everyone stands in the same spot, so they all share the one cap
+        let spawned: usize = critters_per_chunk(&mut app).values().sum();
+        assert_eq!(spawned, 3);
+        let stats = app.world.resource::<SpawnStats>();
+        assert_eq!(stats.spawned, 3);
+        assert!(stats.rejected[&SpawnRule::PlayerCap] > 0);
+        assert_eq!(
+            stats.considered,
+            stats.spawned + stats.rejected.values().sum::<u64>()
+        );
+
+        let mut rules = SpawnRules::default();
+        rules.critter.enabled = false;
+        let mut app = flat_world(rules);
+        assert!(critters_per_chunk(&mut app).is_empty());
+        assert_eq!(app.world.resource::<SpawnStats>().spawned, 0);
+    }
+
+    #[test]
+    fn near_block_rule_goes_by_the_index() {
+        let mut rules = SpawnRules::default();
+        rules.critter.near_block = Some(NearBlock {
+            identifier: "vinox:gold_ore".to_string(),
+            radius: 8,
+        });
+        let mut app = flat_world(rules.clone());
+        assert!(critters_per_chunk(&mut app).is_empty());
+        let stats = app.world.resource::<SpawnStats>();
+        assert_eq!(stats.rejected[&SpawnRule::NearBlock], stats.considered);
+
+        // Every spot stands on worley
+        rules.critter.near_block = Some(NearBlock {
+            identifier: "vinox:worley".to_string(),
+            radius: 2,
+        });
+        let mut app = flat_world(rules);
+        assert!(!critters_per_chunk(&mut app).is_empty());
+        assert!(!app
+            .world
+            .resource::<SpawnStats>()
+            .rejected
+            .contains_key(&SpawnRule::NearBlock));
+    }
+}
@@ -0,0 +1,136 @@
+use bevy::{math::Vec3A, prelude::*, render::primitives::Aabb};
+use vinox_common::{
+    ecs::time::GameClock,
+    physics::simulate::{CollidesWithWorld, Velocity},
+    storage::items::descriptor::ItemData,
+    world::chunks::positions::DimensionId,
+};
+
+use crate::game::networking::identity::SessionId;
+
+// Where the client's camera sits above the player's feet
+pub const EYE_HEIGHT: f32 = 1.8;
+pub const DROP_DISTANCE: f32 = 0.5;
+pub const DROP_SPEED: f32 = 4.0;
+pub const DROP_LIFT: f32 = 2.0;
+pub const PICKUP_IMMUNITY: f32 = 1.0;
+pub const PICKUP_RADIUS: f32 = 1.5;
+// Clients measure from where they last saw the item, give them a bit of room for lag
+pub const PICKUP_SLACK: f32 = 1.0;
+pub const DROPPED_ITEM_LIFETIME: f32 = 300.0;
+pub const ITEM_GRAVITY: f32 = 20.0;
+pub const ITEM_FRICTION: f32 = 6.0;
+pub const MAX_FALL_SPEED: f32 = 30.0;
+
+#[derive(Component)]
+pub struct DroppedItem {
+    pub item: ItemData,
+    pub dropped_by: Option<SessionId>,
+    pub age: f32,
+}
+
+impl DroppedItem {
+    // Whoever threw it has to wait a moment, everyone else can grab it straight away
+    pub fn can_pick_up(&self, session: SessionId) -> bool {
+        self.dropped_by != Some(session) || self.age >= PICKUP_IMMUNITY
+    }
+}
+
+// How many actually leave the slot, never more than is in it or than one stack holds
+pub fn clamp_drop(requested: u32, in_slot: u32, max_stack_size: u32) -> u32 {
+    requested.min(in_slot).min(max_stack_size)
+}
+
+pub fn spawn_dropped_item(
+    commands: &mut Commands,
+    item: ItemData,
+    translation: Vec3,
+    velocity: Vec3,
+    dropped_by: Option<SessionId>,
+    dimension: DimensionId,
+) -> Entity {
+    let half_extents = Vec3A::splat(0.125);
+    let mut entity = commands.spawn((
+        DroppedItem {
+            item,
+            dropped_by,
+            age: 0.0,
+        },
+        dimension,
+        Transform::from_translation(translation),
+        GlobalTransform::default(),
+        Aabb {
+            center: Vec3A::from(translation) + Vec3A::Y * half_extents.y,
+            half_extents,
+        },
+    ));
+    // Server physics only sees overworld chunks, anywhere else the item stays where it was thrown
+    if *dimension == 0 {
+        entity.insert((Velocity(velocity), CollidesWithWorld));
+    }
+    entity.id()
+}
+
+pub fn settle_dropped_items(
+    mut commands: Commands,
+    mut dropped_items: Query<(Entity, &mut DroppedItem, Option<&mut Velocity>)>,
+    clock: Res<GameClock>,
+) {
+    let delta = clock.delta_seconds();
+    for (entity, mut dropped, velocity) in dropped_items.iter_mut() {
+        dropped.age += delta;
+        if dropped.age > DROPPED_ITEM_LIFETIME {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        if let Some(mut velocity) = velocity {
+            velocity.0.y = (velocity.0.y - ITEM_GRAVITY * delta).max(-MAX_FALL_SPEED);
+            let friction = (1.0 - ITEM_FRICTION * delta).max(0.0);
+            velocity.0.x *= friction;
+            velocity.0.z *= friction;
+        }
+    }
+}
+
+pub struct DroppedItemPlugin;
+
+impl Plugin for DroppedItemPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(settle_dropped_items);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thrower_waits_out_immunity() {
+        let mut dropped = DroppedItem {
+            item: ItemData::default(),
+            dropped_by: Some(SessionId(7)),
+            age: 0.0,
+        };
+        assert!(!dropped.can_pick_up(SessionId(7)));
+        assert!(dropped.can_pick_up(SessionId(8)));
+        dropped.age = PICKUP_IMMUNITY - 0.01;
+        assert!(!dropped.can_pick_up(SessionId(7)));
+        dropped.age = PICKUP_IMMUNITY;
+        assert!(dropped.can_pick_up(SessionId(7)));
+        // Loaded back from a save, nobody threw it
+        dropped.dropped_by = None;
+        dropped.age = 0.0;
+        assert!(dropped.can_pick_up(SessionId(7)));
+    }
+
+    #[test]
+    fn drops_clamp_to_the_stack() {
+        assert_eq!(clamp_drop(1, 12, 64), 1);
+        assert_eq!(clamp_drop(12, 12, 64), 12);
+        assert_eq!(clamp_drop(50, 12, 64), 12);
+        // A client claiming more than a stack can hold only gets a stack's worth out
+        assert_eq!(clamp_drop(u32::MAX, 5000, 64), 64);
+        assert_eq!(clamp_drop(3, 0, 64), 0);
+        assert_eq!(clamp_drop(0, 12, 64), 0);
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use vinox_common::world::chunks::storage::{BlockData, BlockTable, ChunkData};
+
+// Hard cap so a busy chunk can't grow its log forever inside the retention window
+pub const MAX_EDITS_PER_CHUNK: usize = 8192;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockEdit {
+    pub actor: String,
+    pub time: u64,
+    pub voxel: [u8; 3],
+    pub previous: BlockData,
+    pub new: BlockData,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EditLog {
+    pub edits: VecDeque<BlockEdit>,
+    // Newest time that was dropped, anything before this can't be rolled back
+    pub truncated_at: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RollbackReport {
+    pub reverted: usize,
+    pub conflicts: usize,
+    pub history_incomplete: bool,
+}
+
+impl std::ops::AddAssign for RollbackReport {
+    fn add_assign(&mut self, other: Self) {
+        self.reverted += other.reverted;
+        self.conflicts += other.conflicts;
+        self.history_incomplete |= other.history_incomplete;
+    }
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+impl EditLog {
+    pub fn push(&mut self, edit: BlockEdit, retention_secs: u64) {
+        let now = edit.time;
+        self.edits.push_back(edit);
+        self.prune(now, retention_secs);
+    }
+
+    pub fn prune(&mut self, now: u64, retention_secs: u64) {
+        let cutoff = now.saturating_sub(retention_secs);
+        while let Some(oldest) = self.edits.front() {
+            if oldest.time >= cutoff && self.edits.len() <= MAX_EDITS_PER_CHUNK {
+                break;
+            }
+            let dropped = self.edits.pop_front().unwrap();
+            self.truncated_at = Some(self.truncated_at.unwrap_or(0).max(dropped.time));
+        }
+    }
+}
+
+// Walks the log newest first undoing everything `actor` did since `since`.
+// Edits that someone else has since built over are left alone and counted as conflicts.
+pub fn rollback_chunk(
+    chunk: &mut ChunkData,
+    log: &mut EditLog,
+    actor: &str,
+    since: u64,
+    moderator: &str,
+    block_table: &BlockTable,
+) -> (RollbackReport, Vec<[u8; 3]>) {
+    let mut report = RollbackReport {
+        history_incomplete: log.truncated_at.is_some_and(|time| time >= since),
+        ..Default::default()
+    };
+    let now = now_secs();
+    let mut reverted = Vec::new();
+    let mut repairs = Vec::new();
+    for (index, edit) in log.edits.iter().enumerate().rev() {
+        if edit.time < since || edit.actor != actor {
+            continue;
+        }
+        let built_over = log
+            .edits
+            .iter()
+            .skip(index + 1)
+            .any(|later| later.voxel == edit.voxel && later.actor != actor);
+        let [x, y, z] = edit.voxel;
+        if built_over || chunk.get(x as u32, y as u32, z as u32) != edit.new {
+            report.conflicts += 1;
+            continue;
+        }
+        chunk.set(
+            x as u32,
+            y as u32,
+            z as u32,
+            edit.previous.clone(),
+            block_table,
+        );
+        report.reverted += 1;
+        reverted.push(edit.voxel);
+        repairs.push(BlockEdit {
+            actor: moderator.to_string(),
+            time: now,
+            voxel: edit.voxel,
+            previous: edit.new.clone(),
+            new: edit.previous.clone(),
+        });
+    }
+    // Logged afterwards so the repairs don't count as someone building over the griefer
+    log.edits.extend(repairs);
+    (report, reverted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::testing::block;
+
+    fn edit(
+        chunk: &mut ChunkData,
+        log: &mut EditLog,
+        actor: &str,
+        time: u64,
+        voxel: [u8; 3],
+        new: &str,
+    ) {
+        let [x, y, z] = voxel;
+        let previous = chunk.get(x as u32, y as u32, z as u32);
+        chunk.set(
+            x as u32,
+            y as u32,
+            z as u32,
+            block(new),
+            &BlockTable::default(),
+        );
+        log.push(
+            BlockEdit {
+                actor: actor.to_string(),
+                time,
+                voxel,
+                previous,
+                new: block(new),
+            },
+            u64::MAX,
+        );
+    }
+
+    #[test]
+    fn rollback_skips_built_over() {
+        let table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        let mut log = EditLog::default();
+        edit(&mut chunk, &mut log, "builder", 100, [1, 1, 1], "stone");
+        edit(&mut chunk, &mut log, "builder", 100, [2, 1, 1], "stone");
+        // Griefer tears down both blocks then the builder patches one back up
+        edit(&mut chunk, &mut log, "griefer", 200, [1, 1, 1], "air");
+        edit(&mut chunk, &mut log, "griefer", 200, [2, 1, 1], "air");
+        edit(&mut chunk, &mut log, "builder", 300, [2, 1, 1], "planks");
+
+        let (report, reverted) =
+            rollback_chunk(&mut chunk, &mut log, "griefer", 150, "mod", &table);
+        assert_eq!(
+            report,
+            RollbackReport {
+                reverted: 1,
+                conflicts: 1,
+                history_incomplete: false,
+            }
+        );
+        assert_eq!(reverted, vec![[1, 1, 1]]);
+        assert_eq!(chunk.get_identifier(1, 1, 1), "vinox:stone");
+        assert_eq!(chunk.get_identifier(2, 1, 1), "vinox:planks");
+    }
+
+    #[test]
+    fn rollback_reverses_in_order() {
+        let table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        let mut log = EditLog::default();
+        edit(&mut chunk, &mut log, "builder", 100, [3, 3, 3], "stone");
+        edit(&mut chunk, &mut log, "griefer", 200, [3, 3, 3], "dirt");
+        edit(&mut chunk, &mut log, "griefer", 210, [3, 3, 3], "sand");
+
+        let (report, _) = rollback_chunk(&mut chunk, &mut log, "griefer", 0, "mod", &table);
+        assert_eq!(report.reverted, 2);
+        assert_eq!(chunk.get_identifier(3, 3, 3), "vinox:stone");
+
+        // Running it again finds the moderator's repair on top
+        let (report, _) = rollback_chunk(&mut chunk, &mut log, "griefer", 0, "mod", &table);
+        assert_eq!(report.reverted, 0);
+        assert_eq!(report.conflicts, 2);
+    }
+
+    #[test]
+    fn truncated_history_is_reported() {
+        let mut chunk = ChunkData::default();
+        let mut log = EditLog::default();
+        edit(&mut chunk, &mut log, "griefer", 100, [0, 0, 0], "dirt");
+        edit(&mut chunk, &mut log, "griefer", 5000, [1, 0, 0], "dirt");
+        log.prune(5000, 1000);
+        assert_eq!(log.edits.len(), 1);
+        assert_eq!(log.truncated_at, Some(100));
+
+        let table = BlockTable::default();
+        let (report, _) = rollback_chunk(&mut chunk, &mut log, "griefer", 50, "mod", &table);
+        assert!(report.history_incomplete);
+        assert_eq!(report.reverted, 1);
+        let (report, _) = rollback_chunk(&mut chunk, &mut log, "griefer", 4000, "mod", &table);
+        assert!(!report.history_incomplete);
+    }
+}
@@ -0,0 +1,196 @@
+use bevy::prelude::*;
+use bevy_quinnet::server::{Endpoint, Server};
+use vinox_common::{
+    ecs::{
+        arrange::max_stack_size,
+        bundles::{Health, Inventory},
+    },
+    networking::protocol::{FrameRequest, ServerMessage},
+    storage::items::descriptor::ItemData,
+    world::{
+        chunks::{
+            ecs::CurrentChunks,
+            positions::{global_voxel_positions, ChunkPos, DimensionId},
+            storage::{name_to_identifier, BlockTable, ChunkData, ItemTable},
+        },
+        frames::{insert_into_frame, is_display_frame, rotate_frame, take_from_frame},
+    },
+};
+
+use crate::game::{
+    audit::{AuditEvent, ContainerAction, ContainerEntry},
+    networking::{
+        identity::PlayerIdentity,
+        recipes::{RecipeTrigger, RecipeTriggerEvent},
+    },
+};
+
+use super::{
+    dropped::EYE_HEIGHT,
+    edits::{now_secs, BlockEdit, EditLog},
+    spawn::USE_REACH,
+    storage::{ChunksToSave, EditLogsToSave, WorldInfo},
+};
+
+pub struct UseFrameEvent {
+    pub client_id: u64,
+    pub entity: Entity,
+    pub voxel: IVec3,
+    pub request: FrameRequest,
+}
+
+// What comes back out of a frame, a single fresh item since the frame only kept the identifier
+pub fn frame_item(identifier: &str, item_table: &ItemTable) -> Option<ItemData> {
+    let descriptor = item_table.get(identifier)?;
+    Some(ItemData {
+        namespace: descriptor.namespace.clone(),
+        name: descriptor.name.clone(),
+        stack_size: 1,
+        ..Default::default()
+    })
+}
+
+pub fn use_frames(
+    mut server: ResMut<Server>,
+    mut events: EventReader<UseFrameEvent>,
+    mut players: Query<(
+        &Transform,
+        &DimensionId,
+        &Health,
+        &PlayerIdentity,
+        &mut Inventory,
+    )>,
+    current_chunks: Res<CurrentChunks>,
+    mut chunks: Query<(&mut ChunkData, &mut EditLog)>,
+    (block_table, item_table, world_info): (Res<BlockTable>, Res<ItemTable>, Res<WorldInfo>),
+    (mut chunks_to_save, mut edit_logs_to_save, mut audit, mut recipe_triggers): (
+        ResMut<ChunksToSave>,
+        ResMut<EditLogsToSave>,
+        EventWriter<AuditEvent>,
+        EventWriter<RecipeTriggerEvent>,
+    ),
+) {
+    let endpoint = server.endpoint_mut();
+    for evt in events.iter() {
+        // Anything turned away goes back to the slot it came out of
+        let refund = |endpoint: &mut Endpoint| {
+            if let FrameRequest::Insert { slot, item } = &evt.request {
+                endpoint.try_send_message(
+                    evt.client_id,
+                    ServerMessage::DropResult {
+                        slot: *slot,
+                        item: item.clone(),
+                        requested: 1,
+                        dropped: 0,
+                    },
+                );
+            }
+        };
+        let Ok((transform, dimension, health, identity, mut inventory)) =
+            players.get_mut(evt.entity)
+        else {
+            refund(endpoint);
+            continue;
+        };
+        let in_reach = (evt.voxel.as_vec3() + Vec3::splat(0.5))
+            .distance(transform.translation + Vec3::Y * EYE_HEIGHT)
+            <= USE_REACH;
+        let (chunk_pos, voxel_pos) = global_voxel_positions(evt.voxel);
+        let chunk = current_chunks
+            .get_entity_in(*dimension, ChunkPos(chunk_pos))
+            .and_then(|entity| chunks.get_mut(entity).ok());
+        let Some((mut chunk, mut edit_log)) = chunk.filter(|_| in_reach && health.current > 0.0)
+        else {
+            refund(endpoint);
+            continue;
+        };
+        let previous = chunk.get(voxel_pos.x, voxel_pos.y, voxel_pos.z);
+        let mut block = previous.clone();
+        if !is_display_frame(&block, &block_table) {
+            refund(endpoint);
+            continue;
+        }
+        // What went in or came out, for the audit log
+        let mut moved = None;
+        let changed = match &evt.request {
+            FrameRequest::Insert { slot, item } => {
+                let identifier = name_to_identifier(item.namespace.clone(), item.name.clone());
+                let fits = item_table.contains_key(&identifier)
+                    && insert_into_frame(&mut block, &identifier);
+                // It has to come out of our copy of the slot, not just the client's
+                let inserted = fits && inventory.take(*slot, item, 1).is_some();
+                if fits && !inserted {
+                    endpoint.try_send_message(evt.client_id, ServerMessage::RequestInventoryResync);
+                }
+                moved = Some((ContainerAction::Insert, identifier));
+                inserted
+            }
+            FrameRequest::Take => match take_from_frame(&mut block) {
+                Some(identifier) => {
+                    // An item removed from the content since just disappears with the frame empty
+                    if let Some(item) = frame_item(&identifier, &item_table) {
+                        inventory.add_stack(&item, max_stack_size(&item, &item_table));
+                        recipe_triggers.send(RecipeTriggerEvent {
+                            client_id: evt.client_id,
+                            entity: evt.entity,
+                            trigger: RecipeTrigger::Obtained(identifier.clone()),
+                        });
+                        endpoint.try_send_message(evt.client_id, ServerMessage::PickedUp { item });
+                    }
+                    moved = Some((ContainerAction::Take, identifier));
+                    true
+                }
+                None => false,
+            },
+            FrameRequest::Rotate => rotate_frame(&mut block).is_some(),
+        };
+        if !changed {
+            refund(endpoint);
+            continue;
+        }
+        if let Some((action, item)) = moved {
+            audit.send(AuditEvent::Container(ContainerEntry {
+                actor: identity.storage_key().to_string(),
+                dimension: dimension.0,
+                position: evt.voxel.to_array(),
+                action,
+                item,
+            }));
+        }
+        // Logged like any placement so a rollback puts the frame back the way it was
+        edit_log.push(
+            BlockEdit {
+                actor: identity.storage_key().to_string(),
+                time: now_secs(),
+                voxel: [voxel_pos.x as u8, voxel_pos.y as u8, voxel_pos.z as u8],
+                previous,
+                new: block.clone(),
+            },
+            world_info.edit_retention_secs(),
+        );
+        chunk.set(
+            voxel_pos.x,
+            voxel_pos.y,
+            voxel_pos.z,
+            block.clone(),
+            &block_table,
+        );
+        chunks_to_save.push((*dimension, ChunkPos(chunk_pos), chunk.to_raw()));
+        edit_logs_to_save.push((*dimension, ChunkPos(chunk_pos), edit_log.clone()));
+        endpoint.try_broadcast_message(ServerMessage::SentBlock {
+            chunk_pos,
+            voxel_pos: [voxel_pos.x as u8, voxel_pos.y as u8, voxel_pos.z as u8],
+            block_type: block,
+            dimension: *dimension,
+            denied: None,
+        });
+    }
+}
+
+pub struct FramePlugin;
+
+impl Plugin for FramePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<UseFrameEvent>().add_system(use_frames);
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    fmt::{self, Write as _},
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bevy::{prelude::*, time::common_conditions::on_timer};
+use bevy_quinnet::server::Server;
+use vinox_common::{
+    ecs::gameplay::{GameplayRules, RULES},
+    networking::protocol::{Player, ServerMessage},
+};
+
+// Next to the world files, shared by every world in the folder like schedule.ron
+#[derive(Resource)]
+pub struct GameplayPath(pub PathBuf);
+
+#[derive(Debug)]
+pub enum GameplayError {
+    Parse(ron::error::SpannedError),
+    Invalid(String),
+}
+
+impl fmt::Display for GameplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameplayError::Parse(e) => write!(f, "it isn't valid: {e}"),
+            GameplayError::Invalid(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameplayFile {
+    pub rules: GameplayRules,
+    // Entries this server doesn't know with their value as written, a newer server's or a typo
+    pub unknown: Vec<(String, String)>,
+}
+
+// Each key and its value's text in the outermost parentheses, comments left out
+fn top_level_entries(text: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut entry = String::new();
+    let mut depth = 0;
+    let mut chars = text.chars().peekable();
+    let mut finish = |entry: &mut String| {
+        let taken = std::mem::take(entry);
+        if let Some((key, value)) = taken.split_once(':') {
+            entries.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    if c == '*' && chars.peek() == Some(&'/') {
+                        chars.next();
+                        break;
+                    }
+                }
+                continue;
+            }
+            '"' => {
+                entry.push(c);
+                while let Some(c) = chars.next() {
+                    entry.push(c);
+                    match c {
+                        '\\' => entry.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            '(' | '[' | '{' => {
+                depth += 1;
+                if depth == 1 {
+                    continue;
+                }
+            }
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    finish(&mut entry);
+                    break;
+                }
+            }
+            ',' if depth == 1 => {
+                finish(&mut entry);
+                continue;
+            }
+            _ => {}
+        }
+        if depth > 0 {
+            entry.push(c);
+        }
+    }
+    entries
+}
+
+impl GameplayFile {
+    // Rules left out of the file keep their defaults, one out of range turns the whole file away
+    pub fn parse(text: &str) -> Result<Self, GameplayError> {
+        let rules: GameplayRules = ron::from_str(text).map_err(GameplayError::Parse)?;
+        rules.validate().map_err(GameplayError::Invalid)?;
+        let unknown = top_level_entries(text)
+            .into_iter()
+            .filter(|(key, _)| !RULES.contains(&key.as_str()))
+            .collect();
+        Ok(Self { rules, unknown })
+    }
+
+    // Every known rule in order, then whatever wasn't understood exactly as it was
+    pub fn write(&self) -> String {
+        let mut text = "(\n".to_string();
+        for name in RULES {
+            let value = self.rules.value(name).unwrap_or_default();
+            writeln!(text, "    {name}: {value},").ok();
+        }
+        for (key, value) in &self.unknown {
+            writeln!(text, "    {key}: {value},").ok();
+        }
+        text.push_str(")\n");
+        text
+    }
+}
+
+// Where the rules in force came from
+#[derive(Resource, Default)]
+pub struct GameplaySource {
+    // Of the file the rules came from, a different one means it was edited
+    modified: Option<SystemTime>,
+    unknown: Vec<(String, String)>,
+}
+
+impl GameplaySource {
+    // Written by /gamerule, comments in the file don't survive it
+    pub fn save(&mut self, path: &Path, rules: GameplayRules) -> io::Result<()> {
+        let file = GameplayFile {
+            rules,
+            unknown: self.unknown.clone(),
+        };
+        fs::write(path, file.write())?;
+        // Already in force, no need to load it again
+        self.modified = fs::metadata(path)?.modified().ok();
+        Ok(())
+    }
+}
+
+// Also the startup load. The rules are replaced in one go between frames, and ticks run
+// before Update, so a tick never sees half of an edit
+pub fn watch_gameplay(
+    mut rules: ResMut<GameplayRules>,
+    mut source: ResMut<GameplaySource>,
+    path: Option<Res<GameplayPath>>,
+) {
+    let Some(path) = path else {
+        return;
+    };
+    let Ok(modified) = fs::metadata(&path.0).and_then(|metadata| metadata.modified()) else {
+        // Written out once so owners can see what there is to tune
+        if source.modified.is_none() && !path.0.exists() {
+            if let Err(e) = source.save(&path.0, *rules) {
+                println!("Couldn't write {}: {e}", path.0.display());
+                source.modified = Some(SystemTime::UNIX_EPOCH);
+            }
+        }
+        return;
+    };
+    if source.modified.replace(modified) == Some(modified) {
+        return;
+    }
+    let Ok(text) = fs::read_to_string(&path.0) else {
+        return;
+    };
+    match GameplayFile::parse(&text) {
+        Ok(file) => {
+            for (key, _) in &file.unknown {
+                println!("Unknown gameplay rule {key}, keeping it in the file as it is");
+            }
+            source.unknown = file.unknown;
+            if *rules != file.rules {
+                println!("Loaded gameplay rules");
+                *rules = file.rules;
+            }
+        }
+        Err(e) => println!("Keeping the old gameplay rules, {e}"),
+    }
+}
+
+// Everyone gets the new rules when they change, anyone joining gets them after Capabilities
+pub fn send_gameplay_rules(
+    rules: Res<GameplayRules>,
+    mut server: ResMut<Server>,
+    joined: Query<&Player, Added<Player>>,
+) {
+    let Some(endpoint) = server.get_endpoint_mut() else {
+        return;
+    };
+    if rules.is_changed() {
+        endpoint.try_broadcast_message(ServerMessage::GameplayRules { rules: *rules });
+        return;
+    }
+    for player in joined.iter() {
+        endpoint.try_send_message(player.id, ServerMessage::GameplayRules { rules: *rules });
+    }
+}
+
+pub struct GameplayPlugin;
+
+impl Plugin for GameplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameplayRules>()
+            .init_resource::<GameplaySource>()
+            .add_startup_system(watch_gameplay)
+            .add_system(watch_gameplay.run_if(on_timer(std::time::Duration::from_secs(2))))
+            .add_system(send_gameplay_rules);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_keys_survive_a_rewrite() {
+        let text = r#"(
+    // Gentler than the default
+    regen_per_sec: 2.0,
+    keep_inventory: false,
+    mob_griefing: Some((radius: 4, blocks: ["vinox:dirt", "a,b"])),
+    motd: "hi, there // not a comment",
+)"#;
+        let file = GameplayFile::parse(text).unwrap();
+        assert_eq!(file.rules.regen_per_sec, 2.0);
+        assert!(!file.rules.keep_inventory);
+        assert_eq!(
+            file.rules.fall_damage_height,
+            GameplayRules::default().fall_damage_height
+        );
+        assert_eq!(
+            file.unknown,
+            vec![
+                (
+                    "mob_griefing".to_string(),
+                    r#"Some((radius: 4, blocks: ["vinox:dirt", "a,b"]))"#.to_string()
+                ),
+                (
+                    "motd".to_string(),
+                    r#""hi, there // not a comment""#.to_string()
+                ),
+            ]
+        );
+        // Reads back the same, unknown entries and all
+        assert_eq!(GameplayFile::parse(&file.write()).unwrap(), file);
+
+        assert!(GameplayFile::parse("(regen_per_sec: 200.0)").is_err());
+        assert!(GameplayFile::parse("(regen_per_sec: \"fast\")").is_err());
+    }
+
+    #[derive(Resource, Default)]
+    struct Seen(Vec<GameplayRules>);
+
+    // Stands in for a tick, which only ever reads the rules once
+    fn record(rules: Res<GameplayRules>, mut seen: ResMut<Seen>) {
+        seen.0.push(*rules);
+    }
+
+    #[test]
+    fn reloads_swap_every_rule_at_once() {
+        let dir = std::env::temp_dir().join(format!("vinox-gameplay-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gameplay.ron");
+        fs::remove_file(&path).ok();
+        let mut app = App::new();
+        app.init_resource::<GameplayRules>()
+            .init_resource::<GameplaySource>()
+            .init_resource::<Seen>()
+            .insert_resource(GameplayPath(path.clone()))
+            .add_systems((record, watch_gameplay, record).chain());
+
+        // A missing file is written out with the defaults
+        app.update();
+        let written = GameplayFile::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written.rules, GameplayRules::default());
+
+        let edited = GameplayRules {
+            regen_per_sec: 1.0,
+            invulnerable_secs: 2.0,
+            starvation: false,
+            ..default()
+        };
+        let mut reload = |text: String| {
+            fs::write(&path, text).unwrap();
+            // Some filesystems only keep whole seconds
+            app.world.resource_mut::<GameplaySource>().modified = None;
+            app.world.resource_mut::<Seen>().0.clear();
+            app.update();
+            std::mem::take(&mut app.world.resource_mut::<Seen>().0)
+        };
+        let seen = reload(
+            GameplayFile {
+                rules: edited,
+                unknown: Vec::new(),
+            }
+            .write(),
+        );
+        // Before the reload everything was old, after it everything is new
+        assert_eq!(seen, vec![GameplayRules::default(), edited]);
+
+        // One bad value keeps every old one, including the ones that were fine
+        let seen =
+            reload("(regen_per_sec: 3.0, invulnerable_secs: 0.1, hunger_per_sec: 5.0)".to_string());
+        assert_eq!(seen, vec![edited, edited]);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,66 +1,562 @@
 use bevy::prelude::*;
 use bracket_noise::prelude::*;
-use noise::{
-    BasicMulti, Billow, Blend, Cache, Clamp, Curve, Fbm, HybridMulti, Min, MultiFractal, NoiseFn,
-    OpenSimplex, Perlin, RidgedMulti, RotatePoint, ScaleBias, SuperSimplex, Worley,
+use noise::NoiseFn;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
 };
-use std::collections::HashMap;
 // use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use vinox_common::{
-    storage::blocks::descriptor::BlockDescriptor,
-    world::chunks::storage::{BlockData, BlockTable, ChunkData, RawChunk, CHUNK_SIZE},
+    ecs::rng::{RngStream, WorldRng},
+    storage::{biomes::descriptor::BiomeDescriptor, structures::descriptor::StructureAnchor},
+    world::chunks::{
+        biome_map::ChunkBiomes,
+        positions::{global_voxel_positions, ChunkPos, DimensionId},
+        storage::{
+            BiomeTable, BlockData, BlockTable, ChunkData, RawChunk, StructureTable, CHUNK_SIZE,
+            VERTICAL_DISTANCE,
+        },
+    },
 };
 
-#[derive(Resource, Default, Serialize, Deserialize)]
-pub struct ToBePlaced(HashMap<IVec3, Vec<(UVec3, BlockDescriptor)>>);
+use super::{noise_graph::NoiseGraphs, storage::GeneratorKind};
 
 pub const SEA_LEVEL: i32 = 0;
+// How far past its own chunk a decoration may write. Writes out there are thrown away, the
+// neighbor redoes them when it gets decorated itself
+pub const DECORATION_MARGIN: u32 = 8;
+
+// How deep a biome's main block goes under its surface
+pub const SUBSURFACE_DEPTH: i32 = 3;
+// What generation uses when no biomes are loaded or a biome leaves one out
+pub const FILL_BLOCK: &str = "vinox:worley";
+pub const SURFACE_BLOCK: &str = "vinox:grass";
+
+// As far up and down as a player standing at 0 loads, hills past it would be cut off
+pub const MAX_TERRAIN_HEIGHT: i32 = (VERTICAL_DISTANCE * CHUNK_SIZE) as i32;
+
+const CHUNK: i32 = CHUNK_SIZE as i32;
+// The ground and cave masks keep a column's voxels as the bits of a u16
+const _: () = assert!(CHUNK_SIZE == 16);
+
+// How the density graph becomes hills, kept in the world file. Changing it only affects chunks
+// generated afterwards
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct TerrainShape {
+    // Where the ground sits where the noise is 0
+    pub base_height: i32,
+    // How far the noise moves it up or down from there
+    pub amplitude: f32,
+}
+
+impl Default for TerrainShape {
+    fn default() -> Self {
+        Self {
+            base_height: 8,
+            amplitude: 40.0,
+        }
+    }
+}
+
+impl TerrainShape {
+    pub fn height(&self, noise: f64) -> i32 {
+        let height = self.base_height as f64 + noise * self.amplitude as f64;
+        (height.round() as i32).clamp(-MAX_TERRAIN_HEIGHT, MAX_TERRAIN_HEIGHT)
+    }
+}
+
+// The first y above the ground for every column of a chunk, z major like the caves
+pub type ColumnHeights = [i32; CHUNK_SIZE * CHUNK_SIZE];
+
+// The density graph sampled flat at world coordinates, so neighbouring chunks always meet and
+// every chunk in a column agrees
+pub fn column_heights(
+    chunk_pos: IVec3,
+    seed: u32,
+    noise: &NoiseGraphs,
+    shape: &TerrainShape,
+) -> ColumnHeights {
+    let density = noise.density.compile(seed);
+    let mut heights = [0; CHUNK_SIZE * CHUNK_SIZE];
+    for z in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            let full_x = x as i32 + CHUNK * chunk_pos.x;
+            let full_z = z as i32 + CHUNK * chunk_pos.z;
+            heights[z * CHUNK_SIZE + x] =
+                shape.height(density.get([full_x as f64, 0.0, full_z as f64]));
+        }
+    }
+    heights
+}
+
+// Content can name blocks that never loaded, those come out as the unknown block instead
+fn identifier_block(identifier: &str, block_table: &BlockTable) -> BlockData {
+    let (namespace, name) = identifier.split_once(':').unwrap_or(("vinox", identifier));
+    let descriptor = block_table.get_or_unknown(&format!("{namespace}:{name}"));
+    BlockData::new(descriptor.namespace.clone(), descriptor.name.clone())
+}
+
+// Picked the same way as the biomes sent along with the chunk, so the blocks always match
+// what the client shows
+fn column_biome<'a>(
+    biomes: &ChunkBiomes,
+    x: usize,
+    z: usize,
+    biome_table: &'a BiomeTable,
+) -> Option<&'a BiomeDescriptor> {
+    biomes
+        .get(x, z)
+        .and_then(|identifier| biome_table.get(identifier))
+}
+
+// The chunk and its 26 neighbors in a fixed order, phase 2 can't run before all of them
+// finished phase 1
+pub fn neighborhood(center: IVec3) -> impl Iterator<Item = IVec3> {
+    (-1..=1).flat_map(move |x| {
+        (-1..=1).flat_map(move |y| (-1..=1).map(move |z| center + IVec3::new(x, y, z)))
+    })
+}
+
+// Phase 2's view of the world, in world voxel coordinates. Reads reach the whole neighborhood,
+// writes only the center chunk plus the margin. Only the center is kept, so what lands in a
+// chunk is a pure function of its neighborhood's base terrain no matter which finished first
+pub struct GenerationRegion {
+    center: IVec3,
+    margin: u32,
+    base: HashMap<IVec3, Arc<ChunkData>>,
+    written: HashMap<IVec3, ChunkData>,
+}
 
-// Just some interesting stuff to look at while testing
-#[allow(clippy::type_complexity)]
-pub fn add_surface(raw_chunk: &mut ChunkData, pos: IVec3, block_table: &BlockTable) {
-    for z in 0..=CHUNK_SIZE - 1 {
-        for y in 0..=CHUNK_SIZE - 1 {
-            for x in 0..=CHUNK_SIZE - 1 {
-                let (x, y, z) = (x as u32, y as u32, z as u32);
-                if y == CHUNK_SIZE as u32 - 1 {
-                    let full_x = x as i32 + ((CHUNK_SIZE as i32) * pos.x);
-                    let full_z = z as i32 + ((CHUNK_SIZE as i32) * pos.z);
-                    let full_y = y as i32 + ((CHUNK_SIZE as i32) * pos.y) + 1;
-                    if raw_chunk.get_identifier(x, y, z) != "vinox:air" {
-                        // We need to add a vec for adding blocks in a new chunk when out of range
-                        let grass = BlockData::new("vinox".to_string(), "grass".to_string());
-                        raw_chunk.set(x, y, z, grass, block_table);
+impl GenerationRegion {
+    // None until every chunk in the neighborhood has its base terrain
+    pub fn new(center: IVec3, margin: u32, base: HashMap<IVec3, Arc<ChunkData>>) -> Option<Self> {
+        if !neighborhood(center).all(|pos| base.contains_key(&pos)) {
+            return None;
+        }
+        Some(Self {
+            center,
+            margin: margin.min(CHUNK_SIZE as u32),
+            base,
+            written: HashMap::new(),
+        })
+    }
+
+    pub fn center(&self) -> IVec3 {
+        self.center
+    }
+
+    pub fn center_voxels(&self) -> impl Iterator<Item = IVec3> {
+        let corner = self.center * CHUNK;
+        (0..ChunkData::usize()).map(move |idx| {
+            let (x, y, z) = ChunkData::delinearize(idx);
+            corner + UVec3::new(x, y, z).as_ivec3()
+        })
+    }
+
+    pub fn contains(&self, voxel: IVec3) -> bool {
+        let min = self.center * CHUNK - IVec3::splat(self.margin as i32);
+        let max = (self.center + IVec3::ONE) * CHUNK + IVec3::splat(self.margin as i32);
+        voxel.cmpge(min).all() && voxel.cmplt(max).all()
+    }
+
+    // Phase 1 terrain, decorations decide from this so they come out the same in every region
+    pub fn base(&self, voxel: IVec3) -> Option<BlockData> {
+        let (chunk_pos, local) = global_voxel_positions(voxel);
+        self.base
+            .get(&chunk_pos)
+            .map(|chunk| chunk.get(local.x, local.y, local.z))
+    }
+
+    pub fn get(&self, voxel: IVec3) -> Option<BlockData> {
+        let (chunk_pos, local) = global_voxel_positions(voxel);
+        match self.written.get(&chunk_pos) {
+            Some(chunk) => Some(chunk.get(local.x, local.y, local.z)),
+            None => self.base(voxel),
+        }
+    }
+
+    // False when the voxel is outside the writable part
+    pub fn set(&mut self, voxel: IVec3, block: BlockData, block_table: &BlockTable) -> bool {
+        if !self.contains(voxel) {
+            return false;
+        }
+        let (chunk_pos, local) = global_voxel_positions(voxel);
+        let Some(base) = self.base.get(&chunk_pos) else {
+            return false;
+        };
+        self.written
+            .entry(chunk_pos)
+            .or_insert_with(|| ChunkData::clone(base))
+            .set(local.x, local.y, local.z, block, block_table);
+        true
+    }
+
+    pub fn into_center(mut self) -> ChunkData {
+        self.written.remove(&self.center).unwrap_or_else(|| {
+            let base = self.base.remove(&self.center).unwrap();
+            Arc::try_unwrap(base).unwrap_or_else(|base| ChunkData::clone(&base))
+        })
+    }
+}
+
+// What add_surface puts on an open top
+fn surface_block(biome: Option<&BiomeDescriptor>) -> &str {
+    biome.map_or(SURFACE_BLOCK, |biome| {
+        biome.surface_block.as_deref().unwrap_or(&biome.main_block)
+    })
+}
+
+// Exposed tops get the biome's surface block and the few under them its main block. Only base
+// terrain is read, the voxels above can be in the chunk overhead
+pub fn add_surface(
+    region: &mut GenerationRegion,
+    biome_table: &BiomeTable,
+    block_table: &BlockTable,
+) {
+    let biomes = ChunkBiomes::compute(region.center(), biome_table);
+    let corner = region.center() * CHUNK;
+    let open = |region: &GenerationRegion, voxel: IVec3| {
+        region
+            .base(voxel)
+            .is_some_and(|block| block.has_identifier("vinox:air"))
+    };
+    for voxel in region.center_voxels() {
+        let solid = region
+            .base(voxel)
+            .is_some_and(|block| !block.has_identifier("vinox:air"));
+        if !solid {
+            continue;
+        }
+        let Some(depth) =
+            (1..=SUBSURFACE_DEPTH + 1).find(|up| open(region, voxel + IVec3::Y * *up))
+        else {
+            continue;
+        };
+        let local = voxel - corner;
+        let biome = column_biome(&biomes, local.x as usize, local.z as usize, biome_table);
+        let identifier = match (biome, depth) {
+            (_, 1) => surface_block(biome),
+            (None, _) => continue,
+            (Some(biome), _) => &biome.main_block,
+        };
+        region.set(
+            voxel,
+            identifier_block(identifier, block_table),
+            block_table,
+        );
+    }
+}
+
+// Open spots on the surface roll for the biome's decorations. One stream for the whole chunk
+// walked in a fixed order, every spot is decided from base terrain and the sea laid over it so
+// it comes out the same
+pub fn add_decorations(
+    region: &mut GenerationRegion,
+    biome_table: &BiomeTable,
+    world_rng: &WorldRng,
+    block_table: &BlockTable,
+) {
+    let biomes = ChunkBiomes::compute(region.center(), biome_table);
+    let corner = region.center() * CHUNK;
+    let mut stream = world_rng.chunk_stream("biome_decorations", region.center());
+    for voxel in region.center_voxels() {
+        // The sea is already in by now, water isn't somewhere to plant anything
+        let open = region
+            .get(voxel)
+            .is_some_and(|block| block.has_identifier("vinox:air"));
+        let ground = region
+            .base(voxel - IVec3::Y)
+            .is_some_and(|block| !block.has_identifier("vinox:air"));
+        if !open || !ground {
+            continue;
+        }
+        let local = voxel - corner;
+        let Some(biome) = column_biome(&biomes, local.x as usize, local.z as usize, biome_table)
+        else {
+            continue;
+        };
+        if biome.decorations.is_empty() {
+            continue;
+        }
+        let roll: f32 = stream.gen();
+        let mut threshold = 0.0;
+        for decoration in &biome.decorations {
+            threshold += decoration.chance;
+            if roll < threshold {
+                region.set(
+                    voxel,
+                    identifier_block(&decoration.block, block_table),
+                    block_table,
+                );
+                break;
+            }
+        }
+    }
+}
+
+// Every chunk in the neighborhood scatters its own structures from its own stream, so a tree
+// rooted near an edge comes out whole on both sides. Each column rolls once whether or not
+// anything can stand there
+pub fn place_structures(
+    region: &mut GenerationRegion,
+    biome_table: &BiomeTable,
+    structure_table: &StructureTable,
+    world_rng: &WorldRng,
+    block_table: &BlockTable,
+) {
+    if structure_table.is_empty() {
+        return;
+    }
+    for origin in neighborhood(region.center()) {
+        let biomes = ChunkBiomes::compute(origin, biome_table);
+        let mut stream = world_rng.chunk_stream("structures", origin);
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let roll: f32 = stream.gen();
+                let Some(biome) = column_biome(&biomes, x, z, biome_table) else {
+                    continue;
+                };
+                let mut threshold = 0.0;
+                let Some(structure) = biome
+                    .structures
+                    .iter()
+                    .flatten()
+                    .find(|entry| {
+                        threshold += entry.chance;
+                        roll < threshold
+                    })
+                    .and_then(|entry| structure_table.get(&entry.structure))
+                else {
+                    continue;
+                };
+                // The highest open top in the origin chunk, never under the sea
+                let column = origin * CHUNK + IVec3::new(x as i32, 0, z as i32);
+                let Some(ground) = (0..CHUNK)
+                    .rev()
+                    .map(|y| column + IVec3::Y * y)
+                    .find(|voxel| {
+                        let solid = region
+                            .base(*voxel)
+                            .is_some_and(|block| !block.has_identifier("vinox:air"));
+                        let open_above = region
+                            .base(*voxel + IVec3::Y)
+                            .is_some_and(|block| block.has_identifier("vinox:air"));
+                        solid && open_above
+                    })
+                else {
+                    continue;
+                };
+                let anchored = match &structure.anchor {
+                    StructureAnchor::Surface => true,
+                    StructureAnchor::OnBlock(identifier) => {
+                        surface_block(Some(biome)) == identifier
                     }
-                } else if raw_chunk.get_identifier(x, y + 1, z) == "vinox:air"
-                    && raw_chunk.get_identifier(x, y, z) != "vinox:air"
-                {
-                    let grass = BlockData::new("vinox".to_string(), "grass".to_string());
-                    raw_chunk.set(x, y, z, grass, block_table);
+                };
+                if ground.y < SEA_LEVEL || !anchored {
+                    continue;
+                }
+                for block in &structure.blocks {
+                    let (x, y, z) = block.offset;
+                    region.set(
+                        ground + IVec3::Y + IVec3::new(x, y, z),
+                        identifier_block(&block.block, block_table),
+                        block_table,
+                    );
                 }
             }
         }
     }
 }
 
-pub fn add_sea(raw_chunk: &mut ChunkData, pos: IVec3, block_table: &BlockTable) {
-    for z in 0..CHUNK_SIZE {
-        for y in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                let full_x = x as i32 + ((CHUNK_SIZE as i32) * pos.x);
-                let full_z = z as i32 + ((CHUNK_SIZE as i32) * pos.z);
-                let full_y = y as i32 + ((CHUNK_SIZE as i32) * pos.y);
-                let (x, y, z) = (x as u32, y as u32, z as u32);
-                if full_y == SEA_LEVEL && raw_chunk.get(x, y, z).is_empty(block_table) {
-                    let water = BlockData::new("vinox".to_string(), "water.divot".to_string());
-                    raw_chunk.set(x, y, z, water, block_table);
-                } else if full_y < SEA_LEVEL && raw_chunk.get(x, y, z).is_empty(block_table) {
-                    let water = BlockData::new("vinox".to_string(), "water".to_string());
-                    raw_chunk.set(x, y, z, water, block_table);
+// Plain water unless the biome has its own sea. Only over the ground, caves under the sea stay dry
+pub fn add_sea(
+    region: &mut GenerationRegion,
+    heights: &ColumnHeights,
+    biome_table: &BiomeTable,
+    block_table: &BlockTable,
+) {
+    let biomes = ChunkBiomes::compute(region.center(), biome_table);
+    let corner = region.center() * CHUNK;
+    for voxel in region.center_voxels() {
+        let local = voxel - corner;
+        if voxel.y > SEA_LEVEL
+            || voxel.y < heights[local.z as usize * CHUNK_SIZE + local.x as usize]
+            || !region
+                .get(voxel)
+                .is_some_and(|block| block.is_empty(block_table))
+        {
+            continue;
+        }
+        let sea = column_biome(&biomes, local.x as usize, local.z as usize, biome_table)
+            .and_then(|biome| biome.sea_block.as_deref());
+        let block = match sea {
+            Some(identifier) => identifier_block(identifier, block_table),
+            None if voxel.y == SEA_LEVEL => identifier_block("vinox:water.divot", block_table),
+            None => identifier_block("vinox:water", block_table),
+        };
+        region.set(voxel, block, block_table);
+    }
+}
+
+// Something rooted in one chunk, an ore vein, a tree, a structure. It runs with its origin's
+// own stream in every region it could reach into, so it has to decide from base terrain and
+// stay within DECORATION_MARGIN of its origin chunk
+pub struct Feature {
+    pub name: &'static str,
+    pub place: fn(&mut GenerationRegion, IVec3, &mut RngStream, &BlockTable),
+}
+
+pub const OVERWORLD_FEATURES: &[Feature] = &[];
+
+pub fn place_features(
+    region: &mut GenerationRegion,
+    features: &[Feature],
+    world_rng: &WorldRng,
+    block_table: &BlockTable,
+) {
+    for origin in neighborhood(region.center()) {
+        for feature in features {
+            let mut stream = world_rng.chunk_stream(feature.name, origin);
+            (feature.place)(region, origin, &mut stream, block_table);
+        }
+    }
+}
+
+// Phase 2. The heights are worked out again rather than kept from phase 1, it's cheap next to
+// holding on to them for every base
+pub fn decorate_chunk(
+    mut region: GenerationRegion,
+    generator: GeneratorKind,
+    heights: &ColumnHeights,
+    world_rng: &WorldRng,
+    biome_table: &BiomeTable,
+    structure_table: &StructureTable,
+    block_table: &BlockTable,
+) -> ChunkData {
+    match generator {
+        GeneratorKind::Overworld => {
+            add_surface(&mut region, biome_table, block_table);
+            add_sea(&mut region, heights, biome_table, block_table);
+            add_decorations(&mut region, biome_table, world_rng, block_table);
+            place_structures(
+                &mut region,
+                biome_table,
+                structure_table,
+                world_rng,
+                block_table,
+            );
+            place_features(&mut region, OVERWORLD_FEATURES, world_rng, block_table);
+        }
+        GeneratorKind::Void => {}
+    }
+    region.into_center()
+}
+
+pub type ChunkKey = (DimensionId, ChunkPos);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkPhase {
+    Terrain,
+    Base,
+    Decorating,
+}
+
+// Keeps track of where every chunk is in generation. A requested chunk waits until its whole
+// neighborhood has base terrain, bases stay around while something still needs them
+#[derive(Resource, Default)]
+pub struct GenerationScheduler {
+    waiting: Vec<ChunkKey>,
+    terrain: HashSet<ChunkKey>,
+    base: HashMap<ChunkKey, Arc<ChunkData>>,
+    decorating: HashSet<ChunkKey>,
+}
+
+impl GenerationScheduler {
+    pub fn request(&mut self, key: ChunkKey) {
+        if !self.decorating.contains(&key) && !self.waiting.contains(&key) {
+            self.waiting.push(key);
+        }
+    }
+
+    // Chunks that got unloaded before they were done
+    pub fn cancel_unless(&mut self, keep: impl Fn(&ChunkKey) -> bool) {
+        self.waiting.retain(|key| keep(key));
+    }
+
+    pub fn phase(&self, key: &ChunkKey) -> Option<ChunkPhase> {
+        if self.decorating.contains(key) {
+            Some(ChunkPhase::Decorating)
+        } else if self.base.contains_key(key) {
+            Some(ChunkPhase::Base)
+        } else if self.terrain.contains(key) {
+            Some(ChunkPhase::Terrain)
+        } else {
+            None
+        }
+    }
+
+    // Every chunk a waiting chunk depends on that has no base yet and isn't being generated
+    pub fn start_terrain(&mut self) -> Vec<ChunkKey> {
+        let mut started = Vec::new();
+        for (dimension, pos) in &self.waiting {
+            for neighbor in neighborhood(**pos) {
+                let key = (*dimension, ChunkPos(neighbor));
+                if !self.base.contains_key(&key) && self.terrain.insert(key) {
+                    started.push(key);
                 }
             }
         }
+        started
+    }
+
+    pub fn finish_terrain(&mut self, key: ChunkKey, chunk: ChunkData) {
+        self.terrain.remove(&key);
+        self.base.insert(key, Arc::new(chunk));
+    }
+
+    // Waiting chunks whose neighborhood is complete, handed out with it for phase 2
+    pub fn start_decoration(&mut self) -> Vec<(ChunkKey, HashMap<IVec3, Arc<ChunkData>>)> {
+        let mut started = Vec::new();
+        let base = &self.base;
+        let decorating = &mut self.decorating;
+        self.waiting.retain(|(dimension, pos)| {
+            let region: Option<HashMap<_, _>> = neighborhood(**pos)
+                .map(|neighbor| {
+                    let chunk = base.get(&(*dimension, ChunkPos(neighbor)))?;
+                    Some((neighbor, chunk.clone()))
+                })
+                .collect();
+            let Some(region) = region else {
+                return true;
+            };
+            decorating.insert((*dimension, *pos));
+            started.push(((*dimension, *pos), region));
+            false
+        });
+        started
+    }
+
+    pub fn finish_decoration(&mut self, key: ChunkKey) {
+        self.decorating.remove(&key);
+    }
+
+    // Drops bases no waiting chunk needs and `keep` doesn't want around for later
+    pub fn prune(&mut self, keep: impl Fn(&ChunkKey) -> bool) {
+        let needed: HashSet<ChunkKey> = self
+            .waiting
+            .iter()
+            .flat_map(|(dimension, pos)| {
+                neighborhood(**pos).map(|neighbor| (*dimension, ChunkPos(neighbor)))
+            })
+            .collect();
+        self.base.retain(|key, _| needed.contains(key) || keep(key));
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.waiting.is_empty() && self.terrain.is_empty() && self.decorating.is_empty()
     }
 }
 
@@ -106,109 +602,780 @@ pub fn add_sea(raw_chunk: &mut ChunkData, pos: IVec3, block_table: &BlockTable)
 //     }
 // }
 
-// pub fn add_missing_blocks(raw_chunk: &mut RawChunk, to_be_placed: &ToBePlaced) {}
-
-fn world_noise(seed: u32) -> impl NoiseFn<f64, 3> {
-    let ridged_noise: RidgedMulti<OpenSimplex> =
-        RidgedMulti::new(seed).set_octaves(4).set_frequency(0.00622);
-    let d_noise: RidgedMulti<OpenSimplex> = RidgedMulti::new(seed.wrapping_add(1))
-        .set_octaves(2)
-        .set_frequency(0.00781);
-    let final_noise = Blend::new(
-        RotatePoint {
-            source: ridged_noise,
-            x_angle: 0.212,
-            y_angle: 0.321,
-            z_angle: -0.1204,
-            u_angle: 0.11,
-        },
-        RotatePoint {
-            source: d_noise,
-            x_angle: -0.124,
-            y_angle: -0.564,
-            z_angle: 0.231,
-            u_angle: -0.1151,
-        },
-        BasicMulti::<OpenSimplex>::new(seed)
-            .set_octaves(1)
-            .set_frequency(0.003415),
-    );
-    final_noise
-}
-
-pub fn generate_chunk(pos: IVec3, seed: u32, block_table: &BlockTable) -> RawChunk {
-    //TODO: Switch to using ron files to determine biomes and what blocks they should use. For now hardcoding a simplex noise
-    let ridged_noise: HybridMulti<OpenSimplex> =
-        HybridMulti::new(seed).set_octaves(4).set_frequency(0.02122);
-    let d_noise: RidgedMulti<OpenSimplex> = RidgedMulti::new(seed.wrapping_add(1))
-        .set_octaves(4)
-        .set_frequency(0.01881);
-    let a_noise = Fbm::<OpenSimplex>::new(seed)
-        .set_octaves(3)
-        .set_persistence(0.5)
-        .set_frequency(0.02);
-
-    // let final_noise = Blend::new(
-    //     RotatePoint {
-    //         source: ridged_noise,
-    //         x_angle: 0.212,
-    //         y_angle: 0.321,
-    //         z_angle: -0.1204,
-    //         u_angle: 0.11,
-    //     },
-    //     RotatePoint {
-    //         source: d_noise,
-    //         x_angle: -0.124,
-    //         y_angle: -0.564,
-    //         z_angle: 0.231,
-    //         u_angle: -0.1151,
-    //     },
-    //     BasicMulti::<OpenSimplex>::new(seed)
-    //         .set_octaves(1)
-    //         .set_frequency(0.015415),
-    // );
-    let mut raw_chunk = ChunkData::default();
-    for x in 0..=CHUNK_SIZE - 1 {
-        for z in 0..=CHUNK_SIZE - 1 {
-            for y in 0..=CHUNK_SIZE - 1 {
+// Phase 1, a chunk's base terrain on its own
+pub fn generate_dimension_chunk(
+    pos: IVec3,
+    seed: u32,
+    generator: GeneratorKind,
+    noise: &NoiseGraphs,
+    shape: &TerrainShape,
+    biome_table: &BiomeTable,
+    block_table: &BlockTable,
+) -> RawChunk {
+    match generator {
+        GeneratorKind::Overworld => {
+            generate_chunk(pos, seed, noise, shape, biome_table, block_table)
+        }
+        GeneratorKind::Void => ChunkData::default().to_raw(),
+    }
+}
+
+// A block's ore settings pulled out of the table once per chunk instead of looked up per voxel
+pub struct OreConfig {
+    pub identifier: String,
+    pub block: BlockData,
+    pub min_y: i32,
+    pub max_y: i32,
+    pub vein_size: u32,
+    pub rarity: f32,
+}
+
+// Sorted so the veins never depend on the table's order
+pub fn ore_configs(block_table: &BlockTable) -> Vec<OreConfig> {
+    let mut ores: Vec<OreConfig> = block_table
+        .iter()
+        .filter_map(|(identifier, descriptor)| {
+            let ore = descriptor.ore.as_ref()?;
+            (ore.rarity > 0.0).then(|| OreConfig {
+                identifier: identifier.clone(),
+                block: identifier_block(identifier, block_table),
+                min_y: ore.spawn_min_y,
+                max_y: ore.spawn_max_y,
+                vein_size: ore.vein_size,
+                rarity: ore.rarity,
+            })
+        })
+        .collect();
+    ores.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+    ores
+}
+
+// Each ore walks its veins with its own stream so adding one never moves another. Veins stay in
+// the chunk they start in and only ever replace ground, the caves and the sky are left alone
+fn lay_ores(
+    pos: IVec3,
+    seed: u32,
+    ground: &[u16; CHUNK_SIZE * CHUNK_SIZE],
+    ores: &[OreConfig],
+) -> HashMap<UVec3, usize> {
+    let world_rng = WorldRng::new(seed as u64);
+    let mut placed = HashMap::new();
+    for (index, ore) in ores.iter().enumerate() {
+        let mut stream = world_rng.chunk_stream(&format!("ores:{}", ore.identifier), pos);
+        let expected = 1.0 / ore.rarity;
+        let veins = expected.floor() as u32 + u32::from(stream.gen::<f32>() < expected.fract());
+        for _ in 0..veins {
+            let mut voxel = IVec3::new(
+                stream.gen_range(0..CHUNK),
+                stream.gen_range(0..CHUNK),
+                stream.gen_range(0..CHUNK),
+            );
+            for _ in 0..ore.vein_size {
+                let full_y = voxel.y + CHUNK * pos.y;
+                let inside =
+                    voxel.cmpge(IVec3::ZERO).all() && voxel.cmplt(IVec3::splat(CHUNK)).all();
+                if inside
+                    && (ore.min_y..=ore.max_y).contains(&full_y)
+                    && ground[voxel.z as usize * CHUNK_SIZE + voxel.x as usize] & (1 << voxel.y)
+                        != 0
+                {
+                    placed.insert(voxel.as_uvec3(), index);
+                }
+                voxel += match stream.gen_range(0..6) {
+                    0 => IVec3::X,
+                    1 => IVec3::NEG_X,
+                    2 => IVec3::Y,
+                    3 => IVec3::NEG_Y,
+                    4 => IVec3::Z,
+                    _ => IVec3::NEG_Z,
+                };
+            }
+        }
+    }
+    placed
+}
+
+// Ground up to each column's height with the caves carved out of it and ore veins through it,
+// the surface and sea go on in phase 2
+pub fn generate_chunk(
+    pos: IVec3,
+    seed: u32,
+    noise: &NoiseGraphs,
+    shape: &TerrainShape,
+    biome_table: &BiomeTable,
+    block_table: &BlockTable,
+) -> RawChunk {
+    let heights = column_heights(pos, seed, noise, shape);
+    let carve = noise.caves.compile(seed);
+    // One pass over the columns to find the ground first, a chunk that's all ground or all air
+    // is built as a single block without touching the palette
+    let mut ground = [0u16; CHUNK_SIZE * CHUNK_SIZE];
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            let height = heights[z * CHUNK_SIZE + x];
+            for y in 0..CHUNK_SIZE {
                 let full_x = x as i32 + ((CHUNK_SIZE as i32) * pos.x);
                 let full_z = z as i32 + ((CHUNK_SIZE as i32) * pos.z);
                 let full_y = y as i32 + ((CHUNK_SIZE as i32) * pos.y);
-                let (x, y, z) = (x as u32, y as u32, z as u32);
-                let is_cave = ridged_noise
-                    .get([full_x as f64, full_y as f64, full_z as f64])
-                    .abs()
-                    < 0.1
-                    && d_noise
-                        .get([full_x as f64, full_y as f64, full_z as f64])
-                        .abs()
-                        < 0.1
-                    && (a_noise.get([full_x as f64, full_y as f64, full_z as f64]) < 0.45);
-                // let noise_val =
-                //     final_noise.get([full_x as f64, full_y as f64, full_z as f64]) * 45.152;
-                // let noise_val =
-                // world_noise(seed).get([full_x as f64, full_y as f64, full_z as f64]) * 45.152;
-                if !is_cave {
-                    raw_chunk.set(
-                        x,
-                        y,
-                        z,
-                        BlockData::new("vinox".to_string(), "worley".to_string()),
-                        block_table,
-                    );
-                } else {
-                    raw_chunk.set(
-                        x,
-                        y,
-                        z,
-                        BlockData::new("vinox".to_string(), "air".to_string()),
-                        block_table,
-                    );
+                if full_y < height
+                    && carve.get([full_x as f64, full_y as f64, full_z as f64]) >= 0.0
+                {
+                    ground[z * CHUNK_SIZE + x] |= 1 << y;
                 }
             }
         }
     }
-    // add_grass(&mut raw_chunk, &noise, pos, block_table);
-    // add_sea(&mut raw_chunk, pos, block_table);
+    let ores = ore_configs(block_table);
+    let veins = lay_ores(pos, seed, &ground, &ores);
+    let biomes = ChunkBiomes::compute(pos, biome_table);
+    let fills: Vec<BlockData> = (0..CHUNK_SIZE * CHUNK_SIZE)
+        .map(|column| {
+            let biome = column_biome(
+                &biomes,
+                column % CHUNK_SIZE,
+                column / CHUNK_SIZE,
+                biome_table,
+            );
+            identifier_block(
+                biome
+                    .and_then(|biome| biome.fill_block.as_deref())
+                    .unwrap_or(FILL_BLOCK),
+                block_table,
+            )
+        })
+        .collect();
+    let air = BlockData::new("vinox".to_string(), "air".to_string());
+    // Veins only ever replace ground, so an all air chunk can't have any
+    if veins.is_empty()
+        && ground.iter().all(|column| *column == u16::MAX)
+        && fills.iter().all(|fill| *fill == fills[0])
+    {
+        return ChunkData::uniform(fills[0].clone(), block_table).to_raw();
+    }
+    if ground.iter().all(|column| *column == 0) {
+        return ChunkData::uniform(air, block_table).to_raw();
+    }
+    let mut raw_chunk = ChunkData::default();
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            let column = ground[z * CHUNK_SIZE + x];
+            for y in 0..CHUNK_SIZE {
+                let local = UVec3::new(x as u32, y as u32, z as u32);
+                let voxel = if let Some(index) = veins.get(&local) {
+                    ores[*index].block.clone()
+                } else if column & (1 << y) != 0 {
+                    fills[z * CHUNK_SIZE + x].clone()
+                } else {
+                    air.clone()
+                };
+                raw_chunk.set(x as u32, y as u32, z as u32, voxel, block_table);
+            }
+        }
+    }
     raw_chunk.to_raw()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noise::{
+        BasicMulti, Blend, Fbm, HybridMulti, MultiFractal, OpenSimplex, RidgedMulti, RotatePoint,
+    };
+    use vinox_common::{
+        storage::{
+            biomes::descriptor::BiomeDecoration,
+            blocks::descriptor::{BlockDescriptor, OreDescriptor},
+            structures::descriptor::{StructureBlocks, StructureDescriptor},
+        },
+        testing::{self, block, descriptor},
+        world::chunks::storage::VoxelVisibility,
+    };
+
+    fn block_table() -> BlockTable {
+        testing::block_table([
+            descriptor("air", VoxelVisibility::Empty),
+            descriptor("worley", VoxelVisibility::Opaque),
+            descriptor("grass", VoxelVisibility::Opaque),
+            descriptor("sand", VoxelVisibility::Opaque),
+            descriptor("dirt", VoxelVisibility::Opaque),
+            descriptor("stone", VoxelVisibility::Opaque),
+            descriptor("oak_log", VoxelVisibility::Opaque),
+            descriptor("glass", VoxelVisibility::Transparent),
+            descriptor("water", VoxelVisibility::Transparent),
+            descriptor("water.divot", VoxelVisibility::Transparent),
+        ])
+    }
+
+    // Every column is the one biome
+    fn desert() -> BiomeTable {
+        let mut biome_table = BiomeTable::default();
+        biome_table.insert(
+            "vinox:desert".to_string(),
+            BiomeDescriptor {
+                namespace: "vinox".to_string(),
+                name: "desert".to_string(),
+                surface_block: Some("vinox:sand".to_string()),
+                main_block: "vinox:dirt".to_string(),
+                fill_block: Some("vinox:stone".to_string()),
+                decorations: vec![BiomeDecoration {
+                    block: "vinox:glass".to_string(),
+                    chance: 1.0,
+                }],
+                ..Default::default()
+            },
+        );
+        biome_table
+    }
+
+    #[test]
+    fn unloaded_blocks_come_out_unknown() {
+        let block_table = block_table();
+        assert!(identifier_block("vinox:sand", &block_table).has_identifier("vinox:sand"));
+        assert!(identifier_block("dirt", &block_table).has_identifier("vinox:dirt"));
+        assert!(identifier_block("vinox:marble", &block_table).has_identifier("vinox:unknown"));
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let block_table = block_table();
+        let generate = |seed: u32, pos: IVec3, biome_table: &BiomeTable| {
+            bincode::serialize(&generate_chunk(
+                pos,
+                seed,
+                &NoiseGraphs::default(),
+                &TerrainShape::default(),
+                biome_table,
+                &block_table,
+            ))
+            .unwrap()
+        };
+        let pos = IVec3::new(3, -1, -2);
+        for biome_table in [BiomeTable::default(), desert()] {
+            assert_eq!(
+                generate(42, pos, &biome_table),
+                generate(42, pos, &biome_table)
+            );
+            assert_ne!(
+                generate(42, pos, &biome_table),
+                generate(43, pos, &biome_table)
+            );
+        }
+    }
+
+    #[test]
+    fn biomes_pick_the_blocks() {
+        let block_table = block_table();
+        let biome_table = desert();
+        let chunk = ChunkData::from_raw(generate_chunk(
+            IVec3::new(3, -1, -2),
+            42,
+            &NoiseGraphs::default(),
+            &TerrainShape::default(),
+            &biome_table,
+            &block_table,
+        ));
+        assert!(!chunk.palette_contains("vinox:worley"));
+        assert!(chunk.palette_contains("vinox:stone"));
+
+        let base = |pos: IVec3| {
+            let name = if pos.y > 0 { "air" } else { "stone" };
+            Arc::new(ChunkData::uniform(block(name), &block_table))
+        };
+        let decorate = |center: IVec3| {
+            let full = neighborhood(center).map(|pos| (pos, base(pos))).collect();
+            let region = GenerationRegion::new(center, DECORATION_MARGIN, full).unwrap();
+            decorate_chunk(
+                region,
+                GeneratorKind::Overworld,
+                &[CHUNK; CHUNK_SIZE * CHUNK_SIZE],
+                &WorldRng::new(42),
+                &biome_table,
+                &StructureTable::default(),
+                &block_table,
+            )
+        };
+        let ground = decorate(IVec3::ZERO);
+        let above = decorate(IVec3::Y);
+        for x in 0..CHUNK_SIZE as u32 {
+            assert!(ground.get(x, 15, 3).has_identifier("vinox:sand"));
+            for y in 15 - SUBSURFACE_DEPTH as u32..15 {
+                assert!(ground.get(x, y, 3).has_identifier("vinox:dirt"));
+            }
+            assert!(ground.get(x, 11, 3).has_identifier("vinox:stone"));
+            // The decorations land in the chunk overhead, on top of the surface below it
+            assert!(above.get(x, 0, 3).has_identifier("vinox:glass"));
+            assert!(above.get(x, 1, 3).has_identifier("vinox:air"));
+        }
+
+        // Ground right under sea level gets the divot, not a decoration on top of the water
+        let full = neighborhood(IVec3::ZERO)
+            .map(|pos| {
+                let name = if pos.y >= 0 { "air" } else { "stone" };
+                (pos, Arc::new(ChunkData::uniform(block(name), &block_table)))
+            })
+            .collect();
+        let region = GenerationRegion::new(IVec3::ZERO, DECORATION_MARGIN, full).unwrap();
+        let sunken = decorate_chunk(
+            region,
+            GeneratorKind::Overworld,
+            &[0; CHUNK_SIZE * CHUNK_SIZE],
+            &WorldRng::new(42),
+            &biome_table,
+            &StructureTable::default(),
+            &block_table,
+        );
+        for x in 0..CHUNK_SIZE as u32 {
+            assert!(sunken.get(x, 0, 3).has_identifier("vinox:water.divot"));
+            assert!(sunken.get(x, 1, 3).has_identifier("vinox:air"));
+        }
+    }
+
+    // The hardcoded stacks from before the noise graphs, kept until the shipped RON files have
+    // been around for a while
+    fn hardcoded_chunk(pos: IVec3, seed: u32, block_table: &BlockTable) -> RawChunk {
+        let ridged_noise: HybridMulti<OpenSimplex> =
+            HybridMulti::new(seed).set_octaves(4).set_frequency(0.02122);
+        let d_noise: RidgedMulti<OpenSimplex> = RidgedMulti::new(seed.wrapping_add(1))
+            .set_octaves(4)
+            .set_frequency(0.01881);
+        let a_noise = Fbm::<OpenSimplex>::new(seed)
+            .set_octaves(3)
+            .set_persistence(0.5)
+            .set_frequency(0.02);
+        let mut raw_chunk = ChunkData::default();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    let point = [
+                        (x as i32 + CHUNK * pos.x) as f64,
+                        (y as i32 + CHUNK * pos.y) as f64,
+                        (z as i32 + CHUNK * pos.z) as f64,
+                    ];
+                    let is_cave = ridged_noise.get(point).abs() < 0.1
+                        && d_noise.get(point).abs() < 0.1
+                        && a_noise.get(point) < 0.45;
+                    let name = if is_cave { "air" } else { "worley" };
+                    raw_chunk.set(x as u32, y as u32, z as u32, block(name), block_table);
+                }
+            }
+        }
+        raw_chunk.to_raw()
+    }
+
+    fn hardcoded_world_noise(seed: u32) -> impl NoiseFn<f64, 3> {
+        let ridged_noise: RidgedMulti<OpenSimplex> =
+            RidgedMulti::new(seed).set_octaves(4).set_frequency(0.00622);
+        let d_noise: RidgedMulti<OpenSimplex> = RidgedMulti::new(seed.wrapping_add(1))
+            .set_octaves(2)
+            .set_frequency(0.00781);
+        Blend::new(
+            RotatePoint {
+                source: ridged_noise,
+                x_angle: 0.212,
+                y_angle: 0.321,
+                z_angle: -0.1204,
+                u_angle: 0.11,
+            },
+            RotatePoint {
+                source: d_noise,
+                x_angle: -0.124,
+                y_angle: -0.564,
+                z_angle: 0.231,
+                u_angle: -0.1151,
+            },
+            BasicMulti::<OpenSimplex>::new(seed)
+                .set_octaves(1)
+                .set_frequency(0.003415),
+        )
+    }
+
+    #[test]
+    fn shipped_graphs_match_the_old_stacks() {
+        let block_table = block_table();
+        let noise = NoiseGraphs::default();
+        // Everything compared is under the ground, only the caves are left to differ
+        let buried = TerrainShape {
+            base_height: MAX_TERRAIN_HEIGHT,
+            amplitude: 0.0,
+        };
+        let mut caves = 0;
+        for seed in [0, 42, u32::MAX] {
+            for x in -2..2 {
+                for y in -2..2 {
+                    for z in -2..2 {
+                        let pos = IVec3::new(x * 7, y, z * 5);
+                        let graph = ChunkData::from_raw(generate_chunk(
+                            pos,
+                            seed,
+                            &noise,
+                            &buried,
+                            &BiomeTable::default(),
+                            &block_table,
+                        ));
+                        let old = ChunkData::from_raw(hardcoded_chunk(pos, seed, &block_table));
+                        // Compared voxel by voxel, the new path may store a uniform chunk
+                        // differently
+                        for idx in 0..ChunkData::usize() {
+                            let (x, y, z) = ChunkData::delinearize(idx);
+                            assert_eq!(graph.get(x, y, z), old.get(x, y, z), "{pos} {seed}");
+                        }
+                        caves += old.positions_of("vinox:air").len();
+                    }
+                }
+            }
+        }
+        // Enough caves in there for the comparison to mean something
+        assert!(caves > 100);
+
+        let terrain = noise.density.compile(42);
+        let old = hardcoded_world_noise(42);
+        for i in 0..500 {
+            let point = [
+                i as f64 * 13.7 - 3000.0,
+                (i % 40) as f64 * 3.1,
+                i as f64 * -7.3,
+            ];
+            assert_eq!(terrain.get(point).to_bits(), old.get(point).to_bits());
+        }
+    }
+
+    #[test]
+    fn heightmap_meets_across_chunks_and_fills_the_sea() {
+        let block_table = block_table();
+        let noise = NoiseGraphs::default();
+        let shape = TerrainShape::default();
+        let heights = |pos: IVec3| column_heights(pos, 42, &noise, &shape);
+        let here = heights(IVec3::new(0, 0, 0));
+        // Every chunk in a column agrees and the next one over carries on from the edge
+        assert_eq!(here, heights(IVec3::new(0, -3, 0)));
+        let east = heights(IVec3::new(1, 0, 0));
+        for z in 0..CHUNK_SIZE {
+            let edge = here[z * CHUNK_SIZE + CHUNK_SIZE - 1];
+            assert!((edge - east[z * CHUNK_SIZE]).abs() <= 2);
+        }
+        assert_ne!(here, heights(IVec3::new(20, 0, -20)));
+
+        let towering = TerrainShape {
+            base_height: 0,
+            amplitude: 1e6,
+        };
+        let capped = column_heights(IVec3::ZERO, 42, &noise, &towering);
+        assert!(capped
+            .iter()
+            .all(|height| height.abs() <= MAX_TERRAIN_HEIGHT));
+
+        // Nothing but air over the heights, and the sea fills what's under sea level
+        let shallow = TerrainShape {
+            base_height: -4,
+            amplitude: 0.0,
+        };
+        let base = |pos: IVec3| {
+            Arc::new(ChunkData::from_raw(generate_chunk(
+                pos,
+                42,
+                &noise,
+                &shallow,
+                &BiomeTable::default(),
+                &block_table,
+            )))
+        };
+        let center = IVec3::new(5, 0, 5);
+        let region = GenerationRegion::new(
+            center,
+            DECORATION_MARGIN,
+            neighborhood(center).map(|pos| (pos, base(pos))).collect(),
+        )
+        .unwrap();
+        let heights = column_heights(center, 42, &noise, &shallow);
+        let chunk = decorate_chunk(
+            region,
+            GeneratorKind::Overworld,
+            &heights,
+            &WorldRng::new(42),
+            &BiomeTable::default(),
+            &StructureTable::default(),
+            &block_table,
+        );
+        for x in 0..CHUNK_SIZE as u32 {
+            assert!(chunk.get(x, 0, 7).has_identifier("vinox:water.divot"));
+            for y in 1..CHUNK_SIZE as u32 {
+                assert!(chunk.get(x, y, 7).has_identifier("vinox:air"));
+            }
+        }
+        let below = base(center - IVec3::Y);
+        for y in 12..CHUNK_SIZE as u32 {
+            assert!(below.get(3, y, 3).has_identifier("vinox:air"));
+        }
+    }
+
+    #[test]
+    fn trees_across_chunk_borders_come_out_whole() {
+        let block_table = block_table();
+        let mut biome_table = BiomeTable::default();
+        biome_table.insert(
+            "vinox:forest".to_string(),
+            BiomeDescriptor {
+                surface_block: Some("vinox:grass".to_string()),
+                main_block: "vinox:worley".to_string(),
+                structures: Some(vec![StructureBlocks {
+                    structure: "vinox:oak_tree".to_string(),
+                    chance: 0.3,
+                }]),
+                ..Default::default()
+            },
+        );
+        // The shipped tree, three logs up with an arm either side at the top
+        let tree: StructureDescriptor = ron::from_str(include_str!(
+            "../../../../vinox-client/assets/structures/oak_tree/oak_tree.ron"
+        ))
+        .unwrap();
+        assert_eq!(tree.blocks.len(), 5);
+        let mut structure_table = StructureTable::default();
+        structure_table.insert("vinox:oak_tree".to_string(), tree);
+
+        let base = |pos: IVec3| {
+            let name = if pos.y > 0 { "air" } else { "worley" };
+            Arc::new(ChunkData::uniform(block(name), &block_table))
+        };
+        let decorate = |center: IVec3| {
+            let full = neighborhood(center).map(|pos| (pos, base(pos))).collect();
+            let region = GenerationRegion::new(center, DECORATION_MARGIN, full).unwrap();
+            decorate_chunk(
+                region,
+                GeneratorKind::Overworld,
+                &[CHUNK; CHUNK_SIZE * CHUNK_SIZE],
+                &WorldRng::new(42),
+                &biome_table,
+                &structure_table,
+                &block_table,
+            )
+        };
+        // The ground's top is the last layer of y 0, trees grow from the bottom of y 1
+        let west = decorate(IVec3::new(0, 1, 0));
+        let east = decorate(IVec3::new(1, 1, 0));
+        let log = |x: i32, y: u32, z: u32| {
+            let block = if x < CHUNK {
+                west.get(x as u32, y, z)
+            } else {
+                east.get((x - CHUNK) as u32, y, z)
+            };
+            block.has_identifier("vinox:oak_log")
+        };
+        let mut across = 0;
+        for z in 0..CHUNK_SIZE as u32 {
+            for x in 0..CHUNK * 2 {
+                if !log(x, 0, z) {
+                    continue;
+                }
+                assert!(log(x, 1, z) && log(x, 2, z), "{x} {z}");
+                for arm in [x - 1, x + 1] {
+                    if (0..CHUNK * 2).contains(&arm) {
+                        assert!(log(arm, 2, z), "{x} {z}");
+                    }
+                }
+                if x == CHUNK - 1 || x == CHUNK {
+                    across += 1;
+                }
+            }
+        }
+        assert!(across > 0);
+    }
+
+    #[test]
+    fn ores_come_out_near_their_density() {
+        let plain = block_table();
+        let mut block_table = plain.clone();
+        for (name, spawn_min_y) in [("granite", -1000), ("marble", 1000)] {
+            block_table.insert(
+                format!("vinox:{name}"),
+                BlockDescriptor {
+                    ore: Some(OreDescriptor {
+                        spawn_min_y,
+                        spawn_max_y: 1000,
+                        vein_size: 8,
+                        rarity: 0.5,
+                    }),
+                    ..descriptor(name, VoxelVisibility::Opaque)
+                },
+            );
+        }
+        let noise = NoiseGraphs::default();
+        let buried = TerrainShape {
+            base_height: MAX_TERRAIN_HEIGHT,
+            amplitude: 0.0,
+        };
+        const CHUNKS: i32 = 64;
+        let mut ore = 0;
+        for i in 0..CHUNKS {
+            let pos = IVec3::new(i * 3, i % 4 - 2, -i);
+            let generate = |block_table: &BlockTable| {
+                ChunkData::from_raw(generate_chunk(
+                    pos,
+                    42,
+                    &noise,
+                    &buried,
+                    &BiomeTable::default(),
+                    block_table,
+                ))
+            };
+            let with_ores = generate(&block_table);
+            let again = generate(&block_table);
+            assert_eq!(
+                bincode::serialize(&with_ores.to_raw()).unwrap(),
+                bincode::serialize(&again.to_raw()).unwrap()
+            );
+            // Only ever where the ground would have been
+            let without = generate(&plain);
+            let veins = with_ores.positions_of("vinox:granite");
+            for local in &veins {
+                assert!(without
+                    .get(local.x, local.y, local.z)
+                    .has_identifier("vinox:worley"));
+            }
+            // Out of its heights
+            assert!(with_ores.positions_of("vinox:marble").is_empty());
+            ore += veins.len();
+        }
+        // Two walks of eight a chunk, they double back on themselves and some start in a cave
+        // or run off the edge
+        let per_chunk = ore as f32 / CHUNKS as f32;
+        assert!((6.0..=16.0).contains(&per_chunk), "{per_chunk}");
+    }
+
+    #[test]
+    fn region_reads_everywhere_and_writes_near_the_center() {
+        let block_table = block_table();
+        let center = IVec3::new(2, 0, -1);
+        let base = |pos: IVec3| {
+            let name = if pos.y > 0 { "air" } else { "worley" };
+            Arc::new(ChunkData::uniform(block(name), &block_table))
+        };
+        let partial: HashMap<_, _> = neighborhood(center)
+            .skip(1)
+            .map(|pos| (pos, base(pos)))
+            .collect();
+        assert!(GenerationRegion::new(center, DECORATION_MARGIN, partial).is_none());
+
+        let full = neighborhood(center).map(|pos| (pos, base(pos))).collect();
+        let mut region = GenerationRegion::new(center, DECORATION_MARGIN, full).unwrap();
+        let corner = center * CHUNK;
+        assert!(region.set(corner - IVec3::splat(8), block("glass"), &block_table));
+        assert!(!region.set(corner - IVec3::splat(9), block("glass"), &block_table));
+        assert!(region
+            .get(corner - IVec3::splat(8))
+            .unwrap()
+            .has_identifier("vinox:glass"));
+        assert!(region
+            .base(corner - IVec3::splat(8))
+            .unwrap()
+            .has_identifier("vinox:worley"));
+        assert!(region
+            .get(corner + IVec3::splat(CHUNK + 15))
+            .unwrap()
+            .has_identifier("vinox:air"));
+        assert!(region.get(corner + IVec3::splat(CHUNK * 2)).is_none());
+
+        // The top layer sees the air in the chunk above, no special case for it anymore
+        add_surface(&mut region, &BiomeTable::default(), &block_table);
+        let chunk = region.into_center();
+        for x in 0..CHUNK_SIZE as u32 {
+            assert!(chunk.get(x, 15, 3).has_identifier("vinox:grass"));
+            assert!(chunk.get(x, 14, 3).has_identifier("vinox:worley"));
+        }
+    }
+
+    // A bar that always starts near the +x side of its chunk and runs into the next one
+    fn place_bar(
+        region: &mut GenerationRegion,
+        origin: IVec3,
+        stream: &mut RngStream,
+        block_table: &BlockTable,
+    ) {
+        let start = origin * CHUNK
+            + IVec3::new(
+                stream.gen_range(10..16),
+                stream.gen_range(0..CHUNK),
+                stream.gen_range(0..CHUNK),
+            );
+        for x in 0..DECORATION_MARGIN as i32 {
+            region.set(start + IVec3::X * x, block("glass"), block_table);
+        }
+    }
+
+    const BARS: &[Feature] = &[Feature {
+        name: "bars",
+        place: place_bar,
+    }];
+
+    fn generate_area(
+        wanted: &[IVec3],
+        order: impl Fn(&mut Vec<ChunkKey>),
+    ) -> HashMap<IVec3, Vec<u8>> {
+        let block_table = block_table();
+        let world_rng = WorldRng::new(42);
+        let mut scheduler = GenerationScheduler::default();
+        for pos in wanted {
+            scheduler.request((DimensionId(0), ChunkPos(*pos)));
+        }
+        let mut finished = HashMap::new();
+        let mut terrain = scheduler.start_terrain();
+        order(&mut terrain);
+        for key in terrain {
+            assert_eq!(scheduler.phase(&key), Some(ChunkPhase::Terrain));
+            let chunk = generate_dimension_chunk(
+                *key.1,
+                42,
+                GeneratorKind::Overworld,
+                &NoiseGraphs::default(),
+                &TerrainShape::default(),
+                &BiomeTable::default(),
+                &block_table,
+            );
+            scheduler.finish_terrain(key, ChunkData::from_raw(chunk));
+            for ((dimension, pos), base) in scheduler.start_decoration() {
+                assert_eq!(
+                    scheduler.phase(&(dimension, pos)),
+                    Some(ChunkPhase::Decorating)
+                );
+                let mut region = GenerationRegion::new(*pos, DECORATION_MARGIN, base).unwrap();
+                add_surface(&mut region, &BiomeTable::default(), &block_table);
+                place_features(&mut region, BARS, &world_rng, &block_table);
+                let chunk = region.into_center();
+                scheduler.finish_decoration((dimension, pos));
+                finished.insert(*pos, bincode::serialize(&chunk.to_raw()).unwrap());
+            }
+        }
+        assert!(scheduler.is_idle());
+        scheduler.prune(|_| false);
+        assert!(scheduler.base.is_empty());
+        finished
+    }
+
+    #[test]
+    fn decoration_ignores_the_order_neighbors_finish_in() {
+        let wanted = [
+            IVec3::new(0, 0, 0),
+            IVec3::new(1, 0, 0),
+            IVec3::new(0, -1, 1),
+        ];
+        let forward = generate_area(&wanted, |_| {});
+        let backward = generate_area(&wanted, |keys| keys.reverse());
+        let shuffled = generate_area(&wanted, |keys| {
+            let mut stream = WorldRng::new(7).stream("shuffle");
+            for i in (1..keys.len()).rev() {
+                keys.swap(i, stream.gen_range(0..=i));
+            }
+        });
+        assert_eq!(forward.len(), wanted.len());
+        assert_eq!(forward, backward);
+        assert_eq!(forward, shuffled);
+
+        // The bar rooted in 0,0,0 made it across the border into 1,0,0
+        let chunk: RawChunk = bincode::deserialize(&forward[&IVec3::new(1, 0, 0)]).unwrap();
+        let chunk = ChunkData::from_raw(chunk);
+        assert!(chunk
+            .positions_of("vinox:glass")
+            .iter()
+            .any(|local| local.x < 4));
+    }
+}
@@ -0,0 +1,623 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use vinox_common::{
+    ecs::time::{ServerTick, TICKS_PER_SECOND},
+    world::chunks::{
+        ecs::{RemoveChunk, SimulationRadius, ViewRadius},
+        positions::{ChunkPos, DimensionId},
+        storage::ChunkData,
+    },
+};
+
+use crate::game::networking::{commands::ShutdownEvent, components::SaveGame};
+
+use super::{
+    chunk::{LoadPoint, PlayerViewRadius},
+    storage::{ChunksToSave, WorldInfo},
+};
+
+pub const DEFAULT_UNLOAD_GRACE_SECS: u64 = 60;
+pub const DEFAULT_AUTOSAVE_SECS: u64 = 300;
+// How often the unload counters in /status roll over
+pub const STATS_INTERVAL_SECS: u64 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForcedChunk {
+    pub dimension: DimensionId,
+    pub chunk: IVec3,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ChunkLifecycle {
+    // How long a chunk nobody is near stays resident before it's saved and dropped
+    pub unload_grace_secs: u64,
+    // Loaded with nobody around and never unloaded, for spawn areas and machines
+    pub force_loaded: Vec<ForcedChunk>,
+    // How often changed chunks that are still loaded get written, 0 leaves it to unloading
+    pub autosave_secs: u64,
+}
+
+impl Default for ChunkLifecycle {
+    fn default() -> Self {
+        Self {
+            unload_grace_secs: DEFAULT_UNLOAD_GRACE_SECS,
+            force_loaded: Vec::new(),
+            autosave_secs: DEFAULT_AUTOSAVE_SECS,
+        }
+    }
+}
+
+impl ChunkLifecycle {
+    pub fn grace_ticks(&self) -> u64 {
+        self.unload_grace_secs * TICKS_PER_SECOND as u64
+    }
+
+    pub fn autosave_ticks(&self) -> u64 {
+        self.autosave_secs * TICKS_PER_SECOND as u64
+    }
+
+    pub fn is_forced(&self, dimension: DimensionId, pos: ChunkPos) -> bool {
+        self.force_loaded
+            .iter()
+            .any(|forced| forced.dimension == dimension && forced.chunk == *pos)
+    }
+
+    // False when it was already forced
+    pub fn force(&mut self, dimension: DimensionId, pos: ChunkPos) -> bool {
+        if self.is_forced(dimension, pos) {
+            return false;
+        }
+        self.force_loaded.push(ForcedChunk {
+            dimension,
+            chunk: *pos,
+        });
+        true
+    }
+
+    // False when it wasn't forced to begin with
+    pub fn unforce(&mut self, dimension: DimensionId, pos: ChunkPos) -> bool {
+        let before = self.force_loaded.len();
+        self.force_loaded
+            .retain(|forced| !(forced.dimension == dimension && forced.chunk == *pos));
+        self.force_loaded.len() != before
+    }
+}
+
+// Every resident chunk has one, added when it's loaded or queued for generation
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ChunkActivity {
+    // Last tick any player's view radius covered it
+    pub last_relevant: ServerTick,
+    // Inside some player's simulation radius or force loaded, critters only move in these
+    pub simulated: bool,
+    // Changed since it was loaded, written once more on the way out
+    pub dirty: bool,
+}
+
+impl ChunkActivity {
+    pub fn new(tick: ServerTick) -> Self {
+        Self {
+            last_relevant: tick,
+            simulated: false,
+            dirty: false,
+        }
+    }
+
+    pub fn should_unload(&self, forced: bool, tick: ServerTick, grace_ticks: u64) -> bool {
+        !forced && tick.saturating_sub(*self.last_relevant) > grace_ticks
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct ChunkLifecycleStats {
+    pub resident: usize,
+    // Counted since the interval started, rolled into the last_ fields every STATS_INTERVAL_SECS
+    pub unloaded: u64,
+    pub dirty_at_unload: u64,
+    pub last_unloaded: u64,
+    pub last_dirty_at_unload: u64,
+    interval_start: ServerTick,
+}
+
+impl ChunkLifecycleStats {
+    fn roll(&mut self, tick: ServerTick) {
+        if tick.saturating_sub(*self.interval_start) < STATS_INTERVAL_SECS * TICKS_PER_SECOND as u64
+        {
+            return;
+        }
+        self.last_unloaded = std::mem::take(&mut self.unloaded);
+        self.last_dirty_at_unload = std::mem::take(&mut self.dirty_at_unload);
+        self.interval_start = tick;
+    }
+}
+
+pub fn track_chunk_activity(
+    mut chunks: Query<(&ChunkPos, &DimensionId, &mut ChunkActivity)>,
+    load_points: Query<(&LoadPoint, &DimensionId, Option<&PlayerViewRadius>)>,
+    (view_radius, simulation_radius): (Res<ViewRadius>, Res<SimulationRadius>),
+    world_info: Res<WorldInfo>,
+    tick: Res<ServerTick>,
+) {
+    let simulation_radius = ViewRadius {
+        horizontal: simulation_radius.horizontal,
+        vertical: simulation_radius.vertical,
+    };
+    for (pos, dimension, mut activity) in chunks.iter_mut() {
+        let forced = world_info.chunk_lifecycle.is_forced(*dimension, *pos);
+        // Each player keeps chunks out to their own view distance, simulation is the same for all
+        let near = |own_radius: bool| {
+            load_points.iter().any(|(point, point_dimension, own)| {
+                let radius = if own_radius {
+                    PlayerViewRadius::or_default(own, &view_radius)
+                } else {
+                    simulation_radius
+                };
+                point_dimension == dimension && point.is_in_radius(**pos, &radius)
+            })
+        };
+        if forced || near(true) {
+            activity.last_relevant = *tick;
+        }
+        activity.simulated = forced || near(false);
+    }
+}
+
+pub fn mark_dirty_chunks(
+    mut chunks: Query<(&mut ChunkActivity, Ref<ChunkData>), Changed<ChunkData>>,
+) {
+    for (mut activity, chunk) in chunks.iter_mut() {
+        // Generated chunks save as they finish and loaded ones match the database
+        if !chunk.is_added() {
+            activity.dirty = true;
+        }
+    }
+}
+
+// Changed chunks are written now instead of when they unload
+pub struct SaveAllEvent;
+
+// A crash only loses what changed since the last one
+pub fn autosave_chunks(
+    mut save_all: EventWriter<SaveAllEvent>,
+    world_info: Res<WorldInfo>,
+    tick: Res<ServerTick>,
+) {
+    let every = world_info.chunk_lifecycle.autosave_ticks();
+    if tick.is_changed() && every > 0 && **tick > 0 && **tick % every == 0 {
+        save_all.send(SaveAllEvent);
+    }
+}
+
+// Shutting down only writes what's already queued, so it saves everything first too
+pub fn save_all_chunks(
+    mut save_all: EventReader<SaveAllEvent>,
+    mut shutdown: EventReader<ShutdownEvent>,
+    mut chunks: Query<(&ChunkPos, &DimensionId, &mut ChunkActivity, &ChunkData)>,
+    (mut chunks_to_save, save): (ResMut<ChunksToSave>, Res<SaveGame>),
+) {
+    if save_all.iter().count() + shutdown.iter().count() == 0 || !**save {
+        return;
+    }
+    let mut saved = 0;
+    for (pos, dimension, mut activity, chunk) in chunks.iter_mut() {
+        if !activity.dirty {
+            continue;
+        }
+        chunks_to_save.push((*dimension, *pos, chunk.to_raw()));
+        activity.dirty = false;
+        saved += 1;
+    }
+    if saved > 0 {
+        println!("Saving {saved} changed chunks");
+    }
+}
+
+// Entities inside get captured by store_entities in the same pass that despawns the chunk
+pub fn unload_idle_chunks(
+    mut commands: Commands,
+    mut chunks: Query<
+        (
+            Entity,
+            &ChunkPos,
+            &DimensionId,
+            &mut ChunkActivity,
+            Option<&ChunkData>,
+        ),
+        Without<RemoveChunk>,
+    >,
+    world_info: Res<WorldInfo>,
+    tick: Res<ServerTick>,
+    (mut chunks_to_save, save): (ResMut<ChunksToSave>, Res<SaveGame>),
+    mut stats: ResMut<ChunkLifecycleStats>,
+) {
+    stats.roll(*tick);
+    stats.resident = chunks.iter().len();
+    let grace_ticks = world_info.chunk_lifecycle.grace_ticks();
+    for (entity, pos, dimension, mut activity, chunk) in chunks.iter_mut() {
+        let forced = world_info.chunk_lifecycle.is_forced(*dimension, *pos);
+        if !activity.should_unload(forced, *tick, grace_ticks) {
+            continue;
+        }
+        if activity.dirty {
+            if let (true, Some(chunk)) = (**save, chunk) {
+                chunks_to_save.push((*dimension, *pos, chunk.to_raw()));
+            }
+            activity.dirty = false;
+            stats.dirty_at_unload += 1;
+        }
+        stats.unloaded += 1;
+        commands.entity(entity).insert(RemoveChunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::{AsyncComputeTaskPool, TaskPool};
+    use r2d2::Pool;
+    use r2d2_sqlite::SqliteConnectionManager;
+    use vinox_common::{
+        ecs::rng::WorldRng,
+        networking::protocol::Player,
+        storage::content::ContentPolicy,
+        world::chunks::{
+            ecs::{CurrentChunks, SentChunks},
+            light::{VoxelAddedEvent, VoxelRemovedEvent},
+            storage::{
+                BiomeTable, BlockData, BlockTable, StructureTable, MAX_VIEW_DISTANCE,
+                MIN_VIEW_DISTANCE,
+            },
+        },
+    };
+
+    use crate::game::{
+        networking::identity::DuplicateNames,
+        world::{
+            chunk::{
+                destroy_chunks, generate_chunks_world, process_queue, process_save, ChunkQueue,
+            },
+            critter::store_entities,
+            generation::{GenerationScheduler, TerrainShape},
+            noise_graph::{GenerationNoise, NoiseSelection},
+            safe_spawn::SpawnSearch,
+            snapshots::SnapshotPolicy,
+            spawn_rules::SpawnRules,
+            storage::{
+                create_database, load_chunk, save_chunks, EditLogsToSave, EntitiesToSave,
+                RecipesToSave, SnapshotsToSave, SpawnPointsToSave, UnreadableChunks, WorldDatabase,
+            },
+        },
+    };
+
+    const HOME: ChunkPos = ChunkPos(IVec3::ZERO);
+
+    fn stone() -> BlockData {
+        BlockData::new("vinox".to_string(), "stone".to_string())
+    }
+
+    fn world_app(force_loaded: Vec<ForcedChunk>) -> App {
+        // One connection so every checkout sees the same in-memory database
+        let pool = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        create_database(&pool.get().unwrap());
+        // Chunks that are already saved load straight away instead of going through generation
+        let saved: Vec<_> = [HOME, ChunkPos(IVec3::new(40, 0, 0))]
+            .into_iter()
+            .chain(force_loaded.iter().map(|forced| ChunkPos(forced.chunk)))
+            .map(|pos| (DimensionId(0), pos, ChunkData::default().to_raw()))
+            .collect();
+        save_chunks(&ChunksToSave(saved), &pool.get().unwrap());
+
+        let mut app = App::new();
+        app.insert_resource(WorldDatabase { connection: pool })
+            .insert_resource(SaveGame(true))
+            .insert_resource(WorldInfo {
+                name: "test".to_string(),
+                seed: 0,
+                damage: false,
+                dimensions: Vec::new(),
+                moderators: Vec::new(),
+                edit_retention_hours: 24,
+                snapshots: SnapshotPolicy::default(),
+                format_version: 0,
+                content_policy: ContentPolicy::default(),
+                spawn_rules: SpawnRules::default(),
+                noise: NoiseSelection::default(),
+                terrain: TerrainShape::default(),
+                chunk_lifecycle: ChunkLifecycle {
+                    unload_grace_secs: 1,
+                    force_loaded,
+                    autosave_secs: 0,
+                },
+                duplicate_names: DuplicateNames::default(),
+                world_id: String::new(),
+                spawn_search: SpawnSearch::default(),
+                spawn: None,
+            })
+            .insert_resource(ViewRadius {
+                horizontal: 0,
+                vertical: 0,
+            })
+            .insert_resource(SimulationRadius::default())
+            .init_resource::<BlockTable>()
+            .init_resource::<CurrentChunks>()
+            .init_resource::<ChunkQueue>()
+            .init_resource::<ServerTick>()
+            .init_resource::<ChunkLifecycleStats>()
+            .init_resource::<ChunksToSave>()
+            .init_resource::<EditLogsToSave>()
+            .init_resource::<SnapshotsToSave>()
+            .init_resource::<EntitiesToSave>()
+            .init_resource::<RecipesToSave>()
+            .init_resource::<SpawnPointsToSave>()
+            .init_resource::<UnreadableChunks>()
+            .add_event::<SaveAllEvent>()
+            .add_event::<ShutdownEvent>()
+            .add_event::<VoxelAddedEvent>()
+            .add_event::<VoxelRemovedEvent>()
+            .add_systems(
+                (
+                    generate_chunks_world,
+                    track_chunk_activity,
+                    mark_dirty_chunks,
+                    unload_idle_chunks,
+                    save_all_chunks,
+                    store_entities,
+                    destroy_chunks,
+                    process_save,
+                )
+                    .chain(),
+            );
+        app.world.spawn((
+            Player::default(),
+            LoadPoint(IVec3::ZERO),
+            DimensionId(0),
+            SentChunks::default(),
+        ));
+        app
+    }
+
+    // Chunks nobody saved get generated instead of only coming out of the database
+    fn with_generation(app: &mut App) {
+        AsyncComputeTaskPool::init(TaskPool::default);
+        app.insert_resource(WorldRng::new(0))
+            .init_resource::<GenerationScheduler>()
+            .init_resource::<GenerationNoise>()
+            .init_resource::<BiomeTable>()
+            .init_resource::<StructureTable>()
+            .add_system(autosave_chunks.before(save_all_chunks))
+            .add_system(
+                process_queue
+                    .after(generate_chunks_world)
+                    .before(process_save),
+            );
+    }
+
+    fn step(app: &mut App, ticks: u64) {
+        for _ in 0..ticks {
+            app.world.resource_mut::<ServerTick>().0 += 1;
+            app.update();
+        }
+    }
+
+    fn resident(app: &App, pos: ChunkPos) -> Option<Entity> {
+        app.world
+            .resource::<CurrentChunks>()
+            .get_entity_in(DimensionId(0), pos)
+    }
+
+    fn walk_to(app: &mut App, chunk: IVec3) {
+        let mut players = app.world.query_filtered::<&mut LoadPoint, With<Player>>();
+        players.single_mut(&mut app.world).0 = chunk;
+    }
+
+    #[test]
+    fn walking_away_unloads_and_walking_back_keeps_edits() {
+        let mut app = world_app(Vec::new());
+        step(&mut app, 1);
+        let entity = resident(&app, HOME).expect("loaded from the database");
+        // Changed in place without going through the save queue, only the unload writes it
+        app.world.get_mut::<ChunkData>(entity).unwrap().set(
+            1,
+            2,
+            3,
+            stone(),
+            &BlockTable::default(),
+        );
+        step(&mut app, 1);
+
+        walk_to(&mut app, IVec3::new(40, 0, 0));
+        let grace = app
+            .world
+            .resource::<WorldInfo>()
+            .chunk_lifecycle
+            .grace_ticks();
+        step(&mut app, grace);
+        assert!(
+            resident(&app, HOME).is_some(),
+            "still inside the grace period"
+        );
+
+        step(&mut app, 3);
+        assert!(resident(&app, HOME).is_none());
+        let stats = app.world.resource::<ChunkLifecycleStats>();
+        assert_eq!((stats.unloaded, stats.dirty_at_unload), (1, 1));
+        let saved = load_chunk(
+            DimensionId(0),
+            HOME,
+            &app.world
+                .resource::<WorldDatabase>()
+                .connection
+                .get()
+                .unwrap(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(ChunkData::from_raw(saved.chunk).get(1, 2, 3), stone());
+
+        walk_to(&mut app, IVec3::ZERO);
+        step(&mut app, 1);
+        let entity = resident(&app, HOME).expect("loaded again");
+        assert_eq!(
+            app.world.get::<ChunkData>(entity).unwrap().get(1, 2, 3),
+            stone()
+        );
+        assert!(!app.world.get::<ChunkActivity>(entity).unwrap().dirty);
+    }
+
+    #[test]
+    fn saving_everything_writes_changed_chunks_in_place() {
+        let mut app = world_app(Vec::new());
+        step(&mut app, 1);
+        let entity = resident(&app, HOME).unwrap();
+        app.world.get_mut::<ChunkData>(entity).unwrap().set(
+            4,
+            5,
+            6,
+            stone(),
+            &BlockTable::default(),
+        );
+        step(&mut app, 1);
+        assert!(app.world.get::<ChunkActivity>(entity).unwrap().dirty);
+
+        app.world.send_event(SaveAllEvent);
+        step(&mut app, 1);
+        assert_eq!(resident(&app, HOME), Some(entity));
+        assert!(!app.world.get::<ChunkActivity>(entity).unwrap().dirty);
+        let saved = load_chunk(
+            DimensionId(0),
+            HOME,
+            &app.world
+                .resource::<WorldDatabase>()
+                .connection
+                .get()
+                .unwrap(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(ChunkData::from_raw(saved.chunk).get(4, 5, 6), stone());
+    }
+
+    #[test]
+    fn force_loaded_chunks_never_unload() {
+        let far = ChunkPos(IVec3::new(-20, 0, 5));
+        let mut app = world_app(vec![ForcedChunk {
+            dimension: DimensionId(0),
+            chunk: *far,
+        }]);
+        // Loaded with nobody anywhere near it
+        step(&mut app, 1);
+        let entity = resident(&app, far).expect("force loaded");
+        assert!(app.world.get::<ChunkActivity>(entity).unwrap().simulated);
+
+        walk_to(&mut app, IVec3::new(40, 0, 0));
+        let grace = app
+            .world
+            .resource::<WorldInfo>()
+            .chunk_lifecycle
+            .grace_ticks();
+        step(&mut app, grace * 5);
+        assert_eq!(resident(&app, far), Some(entity));
+        assert!(resident(&app, HOME).is_none());
+
+        // Dropping the force load starts the grace period from now
+        assert!(app
+            .world
+            .resource_mut::<WorldInfo>()
+            .chunk_lifecycle
+            .unforce(DimensionId(0), far));
+        step(&mut app, grace + 3);
+        assert!(resident(&app, far).is_none());
+    }
+
+    #[test]
+    fn players_keep_chunks_out_to_their_own_view_distance() {
+        let mut app = world_app(Vec::new());
+        let player = app
+            .world
+            .query_filtered::<Entity, With<Player>>()
+            .single(&app.world);
+        // Asking for less than the least still gets the least, and never more than the most
+        let own = PlayerViewRadius::requested(0, app.world.resource::<ViewRadius>());
+        assert_eq!(own.horizontal, MIN_VIEW_DISTANCE as i32);
+        assert_eq!(own.vertical, 0);
+        let most = PlayerViewRadius::requested(u8::MAX, app.world.resource::<ViewRadius>());
+        assert_eq!(most.horizontal, MAX_VIEW_DISTANCE as i32);
+        app.world.entity_mut(player).insert(own);
+        step(&mut app, 1);
+        let entity = resident(&app, HOME).unwrap();
+
+        walk_to(&mut app, IVec3::new(MIN_VIEW_DISTANCE as i32, 0, 0));
+        let grace = app
+            .world
+            .resource::<WorldInfo>()
+            .chunk_lifecycle
+            .grace_ticks();
+        step(&mut app, grace + 3);
+        assert_eq!(resident(&app, HOME), Some(entity));
+
+        // Back to the server's radius, which doesn't reach
+        app.world.entity_mut(player).remove::<PlayerViewRadius>();
+        step(&mut app, grace + 3);
+        assert!(resident(&app, HOME).is_none());
+    }
+
+    #[test]
+    fn generated_chunks_keep_edits_across_restarts() {
+        let far = ChunkPos(IVec3::new(0, 0, -30));
+        let mut app = world_app(Vec::new());
+        with_generation(&mut app);
+        app.world
+            .resource_mut::<WorldInfo>()
+            .chunk_lifecycle
+            .autosave_secs = 1;
+        walk_to(&mut app, *far);
+        // Not in the database, so it's generated and only gets its ChunkData once decorated
+        let mut generated = None;
+        for _ in 0..10_000 {
+            step(&mut app, 1);
+            generated =
+                resident(&app, far).filter(|entity| app.world.get::<ChunkData>(*entity).is_some());
+            if generated.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let entity = generated.expect("generated");
+        app.world.get_mut::<ChunkData>(entity).unwrap().set(
+            7,
+            8,
+            9,
+            stone(),
+            &BlockTable::default(),
+        );
+        step(&mut app, 1);
+        assert!(app.world.get::<ChunkActivity>(entity).unwrap().dirty);
+
+        // Written by the autosave while it's still loaded, no unloading or shutdown needed
+        step(&mut app, TICKS_PER_SECOND as u64);
+        assert_eq!(resident(&app, far), Some(entity));
+        assert!(!app.world.get::<ChunkActivity>(entity).unwrap().dirty);
+
+        // A new server on the same database loads it instead of generating over it
+        let connection = app.world.resource::<WorldDatabase>().connection.clone();
+        drop(app);
+        let mut restarted = world_app(Vec::new());
+        restarted.insert_resource(WorldDatabase { connection });
+        with_generation(&mut restarted);
+        walk_to(&mut restarted, *far);
+        step(&mut restarted, 1);
+        let entity = resident(&restarted, far).unwrap();
+        assert_eq!(
+            restarted
+                .world
+                .get::<ChunkData>(entity)
+                .expect("loaded straight from the database")
+                .get(7, 8, 9),
+            stone()
+        );
+    }
+}
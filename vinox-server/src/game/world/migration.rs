@@ -0,0 +1,168 @@
+use std::fmt;
+
+use zstd::stream::{copy_decode, copy_encode};
+
+// A migration takes a payload one version up, entry N in CHUNK_MIGRATIONS goes from N to N + 1
+pub type Migration = fn(Vec<u8>) -> Result<Vec<u8>, String>;
+
+// Append a step here whenever the saved chunk layout changes, never edit an old one
+pub const CHUNK_MIGRATIONS: &[Migration] = &[migrate_v0_to_v1, migrate_v1_to_v2];
+
+pub const CHUNK_FORMAT_VERSION: u32 = CHUNK_MIGRATIONS.len() as u32;
+
+// Records from before versioning are a bare zstd frame, which never starts with this
+const MAGIC: &[u8; 4] = b"VXCK";
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, PartialEq)]
+pub enum MigrationError {
+    Newer { found: u32, supported: u32 },
+    Truncated,
+    Failed { from: u32, reason: String },
+    Corrupt(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::Newer { found, supported } => write!(
+                f,
+                "world was created by a newer server version (format {found}, this server reads up to {supported})"
+            ),
+            MigrationError::Truncated => write!(f, "record is too short to have a header"),
+            MigrationError::Failed { from, reason } => {
+                write!(f, "migrating from version {from} failed: {reason}")
+            }
+            MigrationError::Corrupt(reason) => write!(f, "record is corrupt: {reason}"),
+        }
+    }
+}
+
+pub fn with_header(version: u32, payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(payload.len() + 8);
+    record.extend_from_slice(MAGIC);
+    record.extend_from_slice(&version.to_le_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+// Version and payload, anything without a header is version 0
+pub fn split_header(record: &[u8]) -> Result<(u32, &[u8]), MigrationError> {
+    if !record.starts_with(MAGIC) {
+        return Ok((0, record));
+    }
+    let version = record
+        .get(4..8)
+        .ok_or(MigrationError::Truncated)?
+        .try_into()
+        .map(u32::from_le_bytes)
+        .map_err(|_| MigrationError::Truncated)?;
+    Ok((version, &record[8..]))
+}
+
+// The world as a whole, refused up front so nothing half loads
+pub fn check_world_version(version: u32) -> Result<(), MigrationError> {
+    if version > CHUNK_FORMAT_VERSION {
+        return Err(MigrationError::Newer {
+            found: version,
+            supported: CHUNK_FORMAT_VERSION,
+        });
+    }
+    Ok(())
+}
+
+// Runs every step the record is behind on and hands back the newest payload, along with
+// whether anything ran so the caller knows to write it back
+pub fn migrate_record(
+    record: &[u8],
+    migrations: &[Migration],
+) -> Result<(Vec<u8>, bool), MigrationError> {
+    let supported = migrations.len() as u32;
+    let (version, payload) = split_header(record)?;
+    if version > supported {
+        return Err(MigrationError::Newer {
+            found: version,
+            supported,
+        });
+    }
+    let mut payload = payload.to_vec();
+    for (from, migration) in migrations.iter().enumerate().skip(version as usize) {
+        payload = migration(payload).map_err(|reason| MigrationError::Failed {
+            from: from as u32,
+            reason,
+        })?;
+    }
+    Ok((payload, version < supported))
+}
+
+// Version 0 is zstd compressed bincode with nothing in front. The payload stays the same,
+// the header is what's new, but anything that isn't zstd doesn't get to pretend it's a chunk
+fn migrate_v0_to_v1(payload: Vec<u8>) -> Result<Vec<u8>, String> {
+    if !payload.starts_with(&ZSTD_MAGIC) {
+        return Err("not a zstd frame".to_string());
+    }
+    Ok(payload)
+}
+
+// RawChunk grew a trailing Option<Heightmap>. A zero byte is bincode's None, the server
+// measures those chunks when it loads them since that needs the block table
+fn migrate_v1_to_v2(payload: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut chunk = Vec::new();
+    copy_decode(&payload[..], &mut chunk).map_err(|e| e.to_string())?;
+    chunk.push(0);
+    let mut output = Vec::new();
+    copy_encode(&chunk[..], &mut output, 0).map_err(|e| e.to_string())?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn append_one(mut payload: Vec<u8>) -> Result<Vec<u8>, String> {
+        payload.push(1);
+        Ok(payload)
+    }
+
+    fn double(payload: Vec<u8>) -> Result<Vec<u8>, String> {
+        if payload.is_empty() {
+            return Err("nothing to double".to_string());
+        }
+        Ok(payload.iter().map(|byte| byte * 2).collect())
+    }
+
+    #[test]
+    fn migrations_chain_in_order() {
+        let migrations: &[Migration] = &[append_one, double];
+
+        // Both steps from 0, in order: [3] -> [3, 1] -> [6, 2]
+        assert_eq!(migrate_record(&[3], migrations), Ok((vec![6, 2], true)));
+        // Only the second from 1
+        assert_eq!(
+            migrate_record(&with_header(1, &[3]), migrations),
+            Ok((vec![6], true))
+        );
+        assert_eq!(
+            migrate_record(&with_header(2, &[3]), migrations),
+            Ok((vec![3], false))
+        );
+        assert_eq!(
+            migrate_record(&with_header(3, &[3]), migrations),
+            Err(MigrationError::Newer {
+                found: 3,
+                supported: 2
+            })
+        );
+        assert_eq!(
+            migrate_record(&with_header(1, &[]), migrations),
+            Err(MigrationError::Failed {
+                from: 1,
+                reason: "nothing to double".to_string()
+            })
+        );
+        assert_eq!(
+            migrate_record(&MAGIC[..], migrations),
+            Err(MigrationError::Truncated)
+        );
+    }
+}
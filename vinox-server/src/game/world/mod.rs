@@ -1,3 +1,20 @@
+pub mod block_index;
 pub mod chunk;
+pub mod critter;
+pub mod dropped;
+pub mod edits;
+pub mod frames;
+pub mod gameplay;
 pub mod generation;
+pub mod lifecycle;
+pub mod migration;
+pub mod noise_graph;
+pub mod safe_spawn;
+pub mod seats;
+pub mod snapshots;
+pub mod spawn;
+pub mod spawn_rules;
 pub mod storage;
+pub mod tools;
+pub mod transitions;
+pub mod vitals;
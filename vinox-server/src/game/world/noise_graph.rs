@@ -0,0 +1,780 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bevy::prelude::*;
+use directories::ProjectDirs;
+use noise::{
+    core::worley::ReturnType, Abs, BasicMulti, Billow, Blend, Clamp, Curve, Fbm, HybridMulti, Max,
+    Min, MultiFractal, NoiseFn, OpenSimplex, Perlin, RidgedMulti, RotatePoint, ScaleBias,
+    SuperSimplex, Worley,
+};
+use serde::{
+    de::{self, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use super::storage::WorldInfo;
+
+// Shipped with the game, used when the assets folder doesn't have them
+pub const BUILTIN_GRAPHS: [(&str, &str); 2] = [
+    (
+        "terrain",
+        include_str!("../../../../vinox-client/assets/noise/terrain.ron"),
+    ),
+    (
+        "caves",
+        include_str!("../../../../vinox-client/assets/noise/caves.ron"),
+    ),
+];
+// The noise crate won't build more than this many octaves
+pub const MAX_OCTAVES: usize = 32;
+
+pub type BoxedNoise = Box<dyn NoiseFn<f64, 3>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BaseNoise {
+    #[default]
+    OpenSimplex,
+    Perlin,
+    SuperSimplex,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FractalKind {
+    Fbm,
+    Ridged,
+    HybridMulti,
+    BasicMulti,
+    Billow,
+}
+
+// Seeds are added to the world's seed, inputs are other nodes by name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum NoiseNode {
+    OpenSimplex {
+        #[serde(default)]
+        seed: u32,
+    },
+    Perlin {
+        #[serde(default)]
+        seed: u32,
+    },
+    Worley {
+        #[serde(default)]
+        seed: u32,
+        frequency: f64,
+        // Distance to the nearest point instead of a value per cell
+        #[serde(default)]
+        distance: bool,
+    },
+    Fractal {
+        kind: FractalKind,
+        #[serde(default)]
+        base: BaseNoise,
+        #[serde(default)]
+        seed: u32,
+        octaves: usize,
+        frequency: f64,
+        // None keeps the noise crate's own default for that kind
+        #[serde(default)]
+        lacunarity: Option<f64>,
+        #[serde(default)]
+        persistence: Option<f64>,
+    },
+    Abs(String),
+    Blend {
+        a: String,
+        b: String,
+        control: String,
+    },
+    Min(String, String),
+    Max(String, String),
+    ScaleBias {
+        source: String,
+        scale: f64,
+        bias: f64,
+    },
+    Clamp {
+        source: String,
+        min: f64,
+        max: f64,
+    },
+    // Input to output, at least four of them
+    Curve {
+        source: String,
+        points: Vec<(f64, f64)>,
+    },
+    // In degrees
+    RotatePoint {
+        source: String,
+        x: f64,
+        y: f64,
+        z: f64,
+        #[serde(default)]
+        u: f64,
+    },
+}
+
+// A parameter, its value and what it should have been
+type RangeError = (&'static str, String, &'static str);
+
+fn above_zero(parameter: &'static str, value: f64) -> Result<(), RangeError> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err((parameter, value.to_string(), "above 0"))
+    }
+}
+
+fn finite(parameter: &'static str, value: f64) -> Result<(), RangeError> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err((parameter, value.to_string(), "a finite number"))
+    }
+}
+
+impl NoiseNode {
+    pub fn inputs(&self) -> Vec<&str> {
+        match self {
+            NoiseNode::OpenSimplex { .. }
+            | NoiseNode::Perlin { .. }
+            | NoiseNode::Worley { .. }
+            | NoiseNode::Fractal { .. } => Vec::new(),
+            NoiseNode::Abs(source)
+            | NoiseNode::ScaleBias { source, .. }
+            | NoiseNode::Clamp { source, .. }
+            | NoiseNode::Curve { source, .. }
+            | NoiseNode::RotatePoint { source, .. } => vec![source.as_str()],
+            NoiseNode::Min(a, b) | NoiseNode::Max(a, b) => vec![a.as_str(), b.as_str()],
+            NoiseNode::Blend { a, b, control } => vec![a.as_str(), b.as_str(), control.as_str()],
+        }
+    }
+
+    // The first parameter that's out of range
+    fn check(&self) -> Result<(), RangeError> {
+        match self {
+            NoiseNode::Worley { frequency, .. } => above_zero("frequency", *frequency),
+            NoiseNode::Fractal {
+                octaves,
+                frequency,
+                lacunarity,
+                persistence,
+                ..
+            } => {
+                if !(1..=MAX_OCTAVES).contains(octaves) {
+                    return Err(("octaves", octaves.to_string(), "between 1 and 32"));
+                }
+                above_zero("frequency", *frequency)?;
+                if let Some(lacunarity) = lacunarity {
+                    above_zero("lacunarity", *lacunarity)?;
+                }
+                if let Some(persistence) = persistence {
+                    above_zero("persistence", *persistence)?;
+                }
+                Ok(())
+            }
+            NoiseNode::ScaleBias { scale, bias, .. } => {
+                finite("scale", *scale)?;
+                finite("bias", *bias)
+            }
+            NoiseNode::Clamp { min, max, .. } => {
+                finite("min", *min)?;
+                finite("max", *max)?;
+                if max < min {
+                    return Err(("max", max.to_string(), "at least min"));
+                }
+                Ok(())
+            }
+            NoiseNode::Curve { points, .. } => {
+                if points.len() < 4 {
+                    return Err((
+                        "points",
+                        points.len().to_string(),
+                        "at least 4 control points",
+                    ));
+                }
+                for (input, output) in points {
+                    finite("points", *input)?;
+                    finite("points", *output)?;
+                }
+                let mut inputs: Vec<f64> = points.iter().map(|(input, _)| *input).collect();
+                inputs.sort_by(f64::total_cmp);
+                if let Some(pair) = inputs.windows(2).find(|pair| pair[0] == pair[1]) {
+                    return Err((
+                        "points",
+                        pair[0].to_string(),
+                        "control points with different inputs",
+                    ));
+                }
+                Ok(())
+            }
+            NoiseNode::RotatePoint { x, y, z, u, .. } => {
+                for (parameter, angle) in [("x", x), ("y", y), ("z", z), ("u", u)] {
+                    if !(-360.0..=360.0).contains(angle) {
+                        return Err((parameter, angle.to_string(), "between -360 and 360"));
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoiseGraphError {
+    // Bad RON, a node that fails to parse is named in the message
+    Parse(String),
+    NotFound(String),
+    MissingOutput(String),
+    Unresolved {
+        node: String,
+        reference: String,
+    },
+    // Starts and ends on the same node
+    Cycle(Vec<String>),
+    OutOfRange {
+        node: String,
+        parameter: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for NoiseGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NoiseGraphError::Parse(error) => write!(f, "{error}"),
+            NoiseGraphError::NotFound(name) => write!(f, "there's no noise graph called {name}"),
+            NoiseGraphError::MissingOutput(output) => {
+                write!(f, "the output {output} isn't one of the nodes")
+            }
+            NoiseGraphError::Unresolved { node, reference } => {
+                write!(f, "node {node} uses {reference}, which isn't defined")
+            }
+            NoiseGraphError::Cycle(path) => write!(
+                f,
+                "node {} depends on itself: {}",
+                path[0],
+                path.join(" -> ")
+            ),
+            NoiseGraphError::OutOfRange {
+                node,
+                parameter,
+                value,
+                expected,
+            } => write!(
+                f,
+                "node {node} has {parameter} {value}, it has to be {expected}"
+            ),
+        }
+    }
+}
+
+// Wraps each node's parse error with the node's name, RON alone only gives a line and column
+fn named_nodes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, NoiseNode>, D::Error> {
+    struct Nodes;
+
+    impl<'de> Visitor<'de> for Nodes {
+        type Value = BTreeMap<String, NoiseNode>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a map of node names to nodes")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut nodes = BTreeMap::new();
+            while let Some(name) = map.next_key::<String>()? {
+                let node = map
+                    .next_value::<NoiseNode>()
+                    .map_err(|e| de::Error::custom(format!("node {name}: {e}")))?;
+                if nodes.insert(name.clone(), node).is_some() {
+                    return Err(de::Error::custom(format!("node {name} is defined twice")));
+                }
+            }
+            Ok(nodes)
+        }
+    }
+
+    deserializer.deserialize_map(Nodes)
+}
+
+// A small DAG of noise functions, `output` is the one generation reads
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct NoiseGraphDescriptor {
+    pub output: String,
+    #[serde(deserialize_with = "named_nodes")]
+    pub nodes: BTreeMap<String, NoiseNode>,
+}
+
+fn boxed(noise: impl NoiseFn<f64, 3> + 'static) -> BoxedNoise {
+    Box::new(noise)
+}
+
+fn fractal(
+    noise: impl MultiFractal + NoiseFn<f64, 3> + 'static,
+    octaves: usize,
+    frequency: f64,
+    lacunarity: Option<f64>,
+    persistence: Option<f64>,
+) -> BoxedNoise {
+    let mut noise = noise.set_octaves(octaves).set_frequency(frequency);
+    if let Some(lacunarity) = lacunarity {
+        noise = noise.set_lacunarity(lacunarity);
+    }
+    if let Some(persistence) = persistence {
+        noise = noise.set_persistence(persistence);
+    }
+    boxed(noise)
+}
+
+impl NoiseGraphDescriptor {
+    pub fn parse(text: &str) -> Result<Self, NoiseGraphError> {
+        let graph: Self = ron::from_str(text).map_err(|e| NoiseGraphError::Parse(e.to_string()))?;
+        graph.validate()?;
+        Ok(graph)
+    }
+
+    pub fn validate(&self) -> Result<(), NoiseGraphError> {
+        if !self.nodes.contains_key(&self.output) {
+            return Err(NoiseGraphError::MissingOutput(self.output.clone()));
+        }
+        for (name, node) in &self.nodes {
+            if let Some(reference) = node
+                .inputs()
+                .into_iter()
+                .find(|input| !self.nodes.contains_key(*input))
+            {
+                return Err(NoiseGraphError::Unresolved {
+                    node: name.clone(),
+                    reference: reference.to_string(),
+                });
+            }
+            node.check()
+                .map_err(|(parameter, value, expected)| NoiseGraphError::OutOfRange {
+                    node: name.clone(),
+                    parameter,
+                    value,
+                    expected,
+                })?;
+        }
+        match self.find_cycle() {
+            Some(path) => Err(NoiseGraphError::Cycle(path)),
+            None => Ok(()),
+        }
+    }
+
+    // Depth first over every node, not just what the output reaches, so a broken node never
+    // sits around unnoticed until someone points the output at it
+    fn find_cycle(&self) -> Option<Vec<String>> {
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        enum Mark {
+            Visiting,
+            Done,
+        }
+
+        fn visit<'a>(
+            nodes: &'a BTreeMap<String, NoiseNode>,
+            name: &'a str,
+            marks: &mut HashMap<&'a str, Mark>,
+            stack: &mut Vec<&'a str>,
+        ) -> Option<Vec<String>> {
+            match marks.get(name) {
+                Some(Mark::Done) => return None,
+                Some(Mark::Visiting) => {
+                    let start = stack.iter().position(|node| *node == name).unwrap_or(0);
+                    let mut path: Vec<String> =
+                        stack[start..].iter().map(|node| node.to_string()).collect();
+                    path.push(name.to_string());
+                    return Some(path);
+                }
+                None => {}
+            }
+            marks.insert(name, Mark::Visiting);
+            stack.push(name);
+            for input in nodes[name].inputs() {
+                if let Some(path) = visit(nodes, input, marks, stack) {
+                    return Some(path);
+                }
+            }
+            stack.pop();
+            marks.insert(name, Mark::Done);
+            None
+        }
+
+        let mut marks = HashMap::new();
+        self.nodes
+            .keys()
+            .find_map(|name| visit(&self.nodes, name, &mut marks, &mut Vec::new()))
+    }
+
+    // Only for validated graphs. Built fresh for every chunk like the old hardcoded stacks were,
+    // the noise crate's Worley can't be shared between threads
+    pub fn compile(&self, seed: u32) -> BoxedNoise {
+        self.compile_node(&self.output, seed)
+    }
+
+    fn compile_node(&self, name: &str, seed: u32) -> BoxedNoise {
+        let input = |name: &str| self.compile_node(name, seed);
+        match &self.nodes[name] {
+            NoiseNode::OpenSimplex { seed: offset } => {
+                boxed(OpenSimplex::new(seed.wrapping_add(*offset)))
+            }
+            NoiseNode::Perlin { seed: offset } => boxed(Perlin::new(seed.wrapping_add(*offset))),
+            NoiseNode::Worley {
+                seed: offset,
+                frequency,
+                distance,
+            } => boxed(
+                Worley::new(seed.wrapping_add(*offset))
+                    .set_frequency(*frequency)
+                    .set_return_type(if *distance {
+                        ReturnType::Distance
+                    } else {
+                        ReturnType::Value
+                    }),
+            ),
+            NoiseNode::Fractal {
+                kind,
+                base,
+                seed: offset,
+                octaves,
+                frequency,
+                lacunarity,
+                persistence,
+            } => {
+                let seed = seed.wrapping_add(*offset);
+                let (o, f, l, p) = (*octaves, *frequency, *lacunarity, *persistence);
+                match (kind, base) {
+                    (FractalKind::Fbm, BaseNoise::OpenSimplex) => {
+                        fractal(Fbm::<OpenSimplex>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::Fbm, BaseNoise::Perlin) => {
+                        fractal(Fbm::<Perlin>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::Fbm, BaseNoise::SuperSimplex) => {
+                        fractal(Fbm::<SuperSimplex>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::Ridged, BaseNoise::OpenSimplex) => {
+                        fractal(RidgedMulti::<OpenSimplex>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::Ridged, BaseNoise::Perlin) => {
+                        fractal(RidgedMulti::<Perlin>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::Ridged, BaseNoise::SuperSimplex) => {
+                        fractal(RidgedMulti::<SuperSimplex>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::HybridMulti, BaseNoise::OpenSimplex) => {
+                        fractal(HybridMulti::<OpenSimplex>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::HybridMulti, BaseNoise::Perlin) => {
+                        fractal(HybridMulti::<Perlin>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::HybridMulti, BaseNoise::SuperSimplex) => {
+                        fractal(HybridMulti::<SuperSimplex>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::BasicMulti, BaseNoise::OpenSimplex) => {
+                        fractal(BasicMulti::<OpenSimplex>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::BasicMulti, BaseNoise::Perlin) => {
+                        fractal(BasicMulti::<Perlin>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::BasicMulti, BaseNoise::SuperSimplex) => {
+                        fractal(BasicMulti::<SuperSimplex>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::Billow, BaseNoise::OpenSimplex) => {
+                        fractal(Billow::<OpenSimplex>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::Billow, BaseNoise::Perlin) => {
+                        fractal(Billow::<Perlin>::new(seed), o, f, l, p)
+                    }
+                    (FractalKind::Billow, BaseNoise::SuperSimplex) => {
+                        fractal(Billow::<SuperSimplex>::new(seed), o, f, l, p)
+                    }
+                }
+            }
+            NoiseNode::Abs(source) => boxed(Abs::new(input(source))),
+            NoiseNode::Blend { a, b, control } => {
+                boxed(Blend::new(input(a), input(b), input(control)))
+            }
+            NoiseNode::Min(a, b) => boxed(Min::new(input(a), input(b))),
+            NoiseNode::Max(a, b) => boxed(Max::new(input(a), input(b))),
+            NoiseNode::ScaleBias {
+                source,
+                scale,
+                bias,
+            } => boxed(
+                ScaleBias::new(input(source))
+                    .set_scale(*scale)
+                    .set_bias(*bias),
+            ),
+            NoiseNode::Clamp { source, min, max } => {
+                boxed(Clamp::new(input(source)).set_bounds(*min, *max))
+            }
+            NoiseNode::Curve { source, points } => boxed(
+                points
+                    .iter()
+                    .fold(Curve::new(input(source)), |curve, (from, to)| {
+                        curve.add_control_point(*from, *to)
+                    }),
+            ),
+            NoiseNode::RotatePoint { source, x, y, z, u } => boxed(RotatePoint {
+                source: input(source),
+                x_angle: *x,
+                y_angle: *y,
+                z_angle: *z,
+                u_angle: *u,
+            }),
+        }
+    }
+}
+
+// Which graph each part of generation reads, by file name in assets/noise
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct NoiseSelection {
+    pub density: String,
+    pub caves: String,
+    // Climate still comes from climate_at so the client's tinting agrees with it, these are
+    // loaded and checked but nothing reads them yet
+    pub temperature: Option<String>,
+    pub humidity: Option<String>,
+}
+
+impl Default for NoiseSelection {
+    fn default() -> Self {
+        Self {
+            density: "terrain".to_string(),
+            caves: "caves".to_string(),
+            temperature: None,
+            humidity: None,
+        }
+    }
+}
+
+pub fn noise_dir() -> Option<PathBuf> {
+    ProjectDirs::from("com", "vinox", "vinox")
+        .map(|proj_dirs| proj_dirs.data_dir().join("assets/noise"))
+}
+
+// The assets folder first, then what shipped with the game
+pub fn load_graph(dir: Option<&Path>, name: &str) -> Result<NoiseGraphDescriptor, NoiseGraphError> {
+    let text = dir.and_then(|dir| fs::read_to_string(dir.join(format!("{name}.ron"))).ok());
+    let builtin = BUILTIN_GRAPHS
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, text)| *text);
+    match text.as_deref().or(builtin) {
+        Some(text) => NoiseGraphDescriptor::parse(text),
+        None => Err(NoiseGraphError::NotFound(name.to_string())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseGraphs {
+    pub density: NoiseGraphDescriptor,
+    pub caves: NoiseGraphDescriptor,
+    pub temperature: Option<NoiseGraphDescriptor>,
+    pub humidity: Option<NoiseGraphDescriptor>,
+}
+
+impl NoiseGraphs {
+    // Either every graph the world asks for or the first one that's wrong
+    pub fn load(selection: &NoiseSelection, dir: Option<&Path>) -> Result<Self, String> {
+        let load = |name: &str| load_graph(dir, name).map_err(|e| format!("{name}: {e}"));
+        Ok(Self {
+            density: load(&selection.density)?,
+            caves: load(&selection.caves)?,
+            temperature: selection.temperature.as_deref().map(load).transpose()?,
+            humidity: selection.humidity.as_deref().map(load).transpose()?,
+        })
+    }
+}
+
+impl Default for NoiseGraphs {
+    fn default() -> Self {
+        Self::load(&NoiseSelection::default(), None).expect("the built in noise graphs are valid")
+    }
+}
+
+// Handed to generation tasks as is, swapping it only affects chunks generated afterwards
+#[derive(Resource, Clone, Default)]
+pub struct GenerationNoise(pub Arc<NoiseGraphs>);
+
+pub fn load_noise_graphs(mut noise: ResMut<GenerationNoise>, world_info: Res<WorldInfo>) {
+    match NoiseGraphs::load(&world_info.noise, noise_dir().as_deref()) {
+        Ok(graphs) => noise.0 = Arc::new(graphs),
+        Err(e) => println!("Generating with the built in noise graphs, {e}"),
+    }
+}
+
+// Debug builds only, tuning a graph shouldn't need a restart but a live world shouldn't
+// change shape under its players either
+#[cfg(debug_assertions)]
+pub fn reload_noise_graphs(
+    mut noise: ResMut<GenerationNoise>,
+    world_info: Res<WorldInfo>,
+    mut last_modified: Local<Option<std::time::SystemTime>>,
+) {
+    let Some(dir) = noise_dir() else {
+        return;
+    };
+    let Some(modified) = fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+        .max()
+    else {
+        return;
+    };
+    // The first look only notes the time, what's there was loaded at startup
+    if last_modified
+        .replace(modified)
+        .is_none_or(|last| last == modified)
+    {
+        return;
+    }
+    match NoiseGraphs::load(&world_info.noise, Some(&dir)) {
+        Ok(graphs) => {
+            noise.0 = Arc::new(graphs);
+            println!("Reloaded the noise graphs, chunks generated from now on use them");
+        }
+        Err(e) => println!("Keeping the old noise graphs, {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(nodes: &str) -> Result<NoiseGraphDescriptor, NoiseGraphError> {
+        NoiseGraphDescriptor::parse(&format!("(output: \"out\", nodes: {{ {nodes} }})"))
+    }
+
+    #[test]
+    fn builtin_graphs_are_valid() {
+        let graphs = NoiseGraphs::default();
+        assert_eq!(graphs.caves.output, "carve");
+        assert_eq!(graphs.density.output, "terrain");
+        // Every kind of node compiles and gives numbers back
+        let everything = graph(
+            r#""a": OpenSimplex(), "b": Perlin(seed: 3), "c": Worley(frequency: 0.1, distance: true),
+            "d": Fractal(kind: Billow, base: SuperSimplex, octaves: 2, frequency: 0.01,
+                lacunarity: Some(2.0), persistence: Some(0.4)),
+            "e": Blend(a: "a", b: "b", control: "c"), "f": Min("e", "d"), "g": Abs("f"),
+            "h": Clamp(source: "g", min: -0.5, max: 0.5),
+            "i": Curve(source: "h", points: [(-1.0, -1.0), (0.0, 0.2), (0.5, 0.4), (1.0, 1.0)]),
+            "j": RotatePoint(source: "i", x: 10.0, y: 0.0, z: -5.0),
+            "out": ScaleBias(source: "j", scale: 2.0, bias: 0.5)"#,
+        )
+        .unwrap();
+        let value = everything.compile(7).get([1.5, -20.0, 300.25]);
+        assert!(value.is_finite());
+    }
+
+    #[test]
+    fn cycles_are_caught_and_named() {
+        let error = graph(
+            r#""out": Abs("loop_a"), "loop_a": ScaleBias(source: "loop_b", scale: 1.0, bias: 0.0),
+            "loop_b": Max("loop_a", "base"), "base": OpenSimplex()"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            NoiseGraphError::Cycle(vec![
+                "loop_a".to_string(),
+                "loop_b".to_string(),
+                "loop_a".to_string()
+            ])
+        );
+        assert_eq!(
+            error.to_string(),
+            "node loop_a depends on itself: loop_a -> loop_b -> loop_a"
+        );
+        // Not reachable from the output, still not allowed
+        assert!(matches!(
+            graph(r#""out": OpenSimplex(), "alone": Abs("alone")"#),
+            Err(NoiseGraphError::Cycle(path)) if path == ["alone", "alone"]
+        ));
+    }
+
+    #[test]
+    fn unknown_types_and_references_name_the_node() {
+        let error = graph(r#""base": OpenSimplex(), "out": Smoothstep("base")"#).unwrap_err();
+        let NoiseGraphError::Parse(message) = &error else {
+            panic!("expected a parse error, got {error:?}");
+        };
+        assert!(message.contains("node out"), "{message}");
+        assert!(message.contains("Smoothstep"), "{message}");
+
+        assert_eq!(
+            graph(r#""out": Abs("missing")"#),
+            Err(NoiseGraphError::Unresolved {
+                node: "out".to_string(),
+                reference: "missing".to_string(),
+            })
+        );
+        assert_eq!(
+            graph(r#""base": OpenSimplex()"#),
+            Err(NoiseGraphError::MissingOutput("out".to_string()))
+        );
+    }
+
+    #[test]
+    fn parameters_out_of_range_name_the_node() {
+        let error =
+            graph(r#""out": Fractal(kind: Fbm, octaves: 40, frequency: 0.02)"#).unwrap_err();
+        assert_eq!(
+            error,
+            NoiseGraphError::OutOfRange {
+                node: "out".to_string(),
+                parameter: "octaves",
+                value: "40".to_string(),
+                expected: "between 1 and 32",
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "node out has octaves 40, it has to be between 1 and 32"
+        );
+        for (nodes, parameter) in [
+            (
+                r#""out": Fractal(kind: Ridged, octaves: 2, frequency: -1.0)"#,
+                "frequency",
+            ),
+            (
+                r#""base": OpenSimplex(), "out": Curve(source: "base", points: [(0.0, 0.0)])"#,
+                "points",
+            ),
+            (
+                r#""base": OpenSimplex(),
+                "out": Curve(source: "base", points: [(0.0, 0.0), (0.0, 1.0), (0.5, 0.0), (1.0, 1.0)])"#,
+                "points",
+            ),
+            (
+                r#""base": OpenSimplex(), "out": Clamp(source: "base", min: 1.0, max: -1.0)"#,
+                "max",
+            ),
+            (
+                r#""base": OpenSimplex(), "out": RotatePoint(source: "base", x: 720.0, y: 0.0, z: 0.0)"#,
+                "x",
+            ),
+        ] {
+            assert!(
+                matches!(
+                    graph(nodes),
+                    Err(NoiseGraphError::OutOfRange { node, parameter: p, .. })
+                        if node == "out" && p == parameter
+                ),
+                "{nodes}"
+            );
+        }
+    }
+}
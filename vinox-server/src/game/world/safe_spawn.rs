@@ -0,0 +1,587 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_quinnet::server::Server;
+use serde::{Deserialize, Serialize};
+use vinox_common::{
+    networking::protocol::ServerMessage,
+    physics::movement::{block_flags, BlockFlags},
+    world::chunks::{
+        ecs::CurrentChunks,
+        positions::{global_voxel_positions, ChunkPos, DimensionId},
+        storage::{BiomeTable, BlockData, BlockTable, ChunkData, CHUNK_SIZE},
+    },
+};
+
+use super::{
+    generation::{column_heights, generate_dimension_chunk, ColumnHeights, SEA_LEVEL},
+    noise_graph::GenerationNoise,
+    spawn::WORLD_SPAWN,
+    spawn_rules::WorldInfoPath,
+    storage::{load_chunk, ChunksToSave, WorldDatabase, WorldInfo},
+    transitions::BlockChangedEvent,
+};
+
+// Columns out from the center the search goes at most, and how many candidates it scores
+// before settling for the best so far
+pub const SPAWN_SEARCH_RADIUS: i32 = 32;
+pub const SPAWN_SEARCH_BUDGET: usize = 1024;
+// Respawning checks the recorded spawn is still fine and only looks a little way around it
+pub const RESPAWN_SEARCH_BUDGET: usize = 81;
+// Anything scoring less isn't worth spawning on, see score_spawn
+pub const SAFE_SCORE: f32 = 0.6;
+// Solid blocks from the surface down, thinner than this is a shell over a cave
+pub const MIN_SOLID_DEPTH: i32 = 3;
+// The neighborhood scored around a candidate, and the platform built when there's none
+pub const SPAWN_AREA: i32 = 5;
+pub const PLATFORM_RAISE: i32 = 2;
+
+const AREA_WEIGHT: f32 = 0.5;
+const FLATNESS_WEIGHT: f32 = 0.4;
+const DISTANCE_WEIGHT: f32 = 0.1;
+
+// Where the search for a spawn starts and what it does when it finds nothing
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct SpawnSearch {
+    pub center: IVec2,
+    // Only this band of y is looked at, it's all generated on a fresh world
+    pub max_y: i32,
+    pub min_y: i32,
+    pub platform_block: String,
+}
+
+impl Default for SpawnSearch {
+    fn default() -> Self {
+        Self {
+            center: IVec2::ZERO,
+            max_y: 128,
+            min_y: SEA_LEVEL - 32,
+            platform_block: "vinox:cobblestone".to_string(),
+        }
+    }
+}
+
+// A slab built because nowhere near the center was safe. It's kept in the world file so it's
+// only ever built once and nothing mistakes it for generated terrain
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SpawnPlatform {
+    // The middle block of the slab
+    pub center: IVec3,
+    pub block: String,
+    pub placed: bool,
+}
+
+// Picked the first time a world is opened and kept in its world file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WorldSpawn {
+    // The voxel a player's feet go in
+    pub feet: IVec3,
+    #[serde(default)]
+    pub platform: Option<SpawnPlatform>,
+}
+
+// Where new players appear and everyone without a bed respawns, before a spawn is chosen or in
+// a world that never got one it's the old fixed point
+pub fn world_spawn(world_info: &WorldInfo) -> Vec3 {
+    world_info.spawn.as_ref().map_or(WORLD_SPAWN, |spawn| {
+        spawn.feet.as_vec3() + Vec3::new(0.5, 0.0, 0.5)
+    })
+}
+
+// One column as the search sees it, surface is the highest solid block in the band
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SpawnColumn {
+    pub surface: Option<i32>,
+    // Less than two blocks under the top of the band, there's no telling what's over it
+    pub buried: bool,
+    pub water: bool,
+    // Counting the surface, up to MIN_SOLID_DEPTH
+    pub solid_depth: i32,
+}
+
+impl SpawnColumn {
+    // The surface when a player could stand on it
+    pub fn standable(&self) -> Option<i32> {
+        self.surface
+            .filter(|_| !self.buried && !self.water && self.solid_depth >= MIN_SOLID_DEPTH)
+    }
+}
+
+pub fn measure_column(
+    column: IVec2,
+    max_y: i32,
+    min_y: i32,
+    sample: &mut impl FnMut(IVec3) -> Option<BlockFlags>,
+) -> SpawnColumn {
+    let mut flags = |y: i32| sample(IVec3::new(column.x, y, column.y)).unwrap_or_default();
+    let Some(surface) = (min_y..=max_y).rev().find(|y| flags(*y).solid) else {
+        return SpawnColumn::default();
+    };
+    SpawnColumn {
+        surface: Some(surface),
+        buried: max_y - surface < 2,
+        water: flags(surface + 1).fluid,
+        solid_depth: 1
+            + (1..MIN_SOLID_DEPTH)
+                .take_while(|depth| flags(surface - depth).solid)
+                .count() as i32,
+    }
+}
+
+// 0 to 1, higher is better. Flat open ground all around scores best, the further from the
+// center the less. None when nobody could stand there at all, in water or on a cave's roof
+pub fn score_spawn(
+    candidate: IVec2,
+    origin: IVec2,
+    column: &mut impl FnMut(IVec2) -> SpawnColumn,
+) -> Option<f32> {
+    let ground = column(candidate).standable()?;
+    let half = SPAWN_AREA / 2;
+    let mut heights = Vec::with_capacity((SPAWN_AREA * SPAWN_AREA) as usize);
+    let mut standable = 0;
+    for x in -half..=half {
+        for z in -half..=half {
+            let neighbor = column(candidate + IVec2::new(x, z));
+            if let Some(surface) = neighbor.surface {
+                heights.push(surface as f32);
+            }
+            if neighbor
+                .standable()
+                .is_some_and(|surface| (surface - ground).abs() <= 1)
+            {
+                standable += 1;
+            }
+        }
+    }
+    let mean = heights.iter().sum::<f32>() / heights.len() as f32;
+    let variance = heights
+        .iter()
+        .map(|height| (height - mean).powi(2))
+        .sum::<f32>()
+        / heights.len() as f32;
+    // A one block step either way is still easy going
+    let flatness = 1.0 / (1.0 + variance / 4.0);
+    let area = standable as f32 / (SPAWN_AREA * SPAWN_AREA) as f32;
+    let distance = ((candidate - origin).as_vec2().length() / SPAWN_SEARCH_RADIUS as f32).min(1.0);
+    Some(AREA_WEIGHT * area + FLATNESS_WEIGHT * flatness + DISTANCE_WEIGHT * (1.0 - distance))
+}
+
+// Rings of columns going out from the center, the center first
+pub fn spiral(center: IVec2, radius: i32) -> impl Iterator<Item = IVec2> {
+    (0..=radius).flat_map(move |ring| {
+        (-ring..=ring).flat_map(move |x| {
+            (-ring..=ring)
+                .filter(move |z| x.abs() == ring || z.abs() == ring)
+                .map(move |z| center + IVec2::new(x, z))
+        })
+    })
+}
+
+// The best column within budget candidates of origin that scores at least SAFE_SCORE, as the
+// voxel feet go in
+pub fn find_safe_spawn(
+    origin: IVec2,
+    budget: usize,
+    column: impl FnMut(IVec2) -> SpawnColumn,
+) -> Option<IVec3> {
+    // Neighborhoods overlap, every column is only measured once
+    let mut measured: HashMap<IVec2, SpawnColumn> = HashMap::default();
+    let mut column = column;
+    let mut cached = |at: IVec2| *measured.entry(at).or_insert_with(|| column(at));
+    let mut best: Option<(f32, IVec3)> = None;
+    for candidate in spiral(origin, SPAWN_SEARCH_RADIUS).take(budget) {
+        // Nothing further out can beat what's been found, distance only takes away
+        let ring = (candidate - origin).abs().max_element() as f32;
+        let reachable = AREA_WEIGHT
+            + FLATNESS_WEIGHT
+            + DISTANCE_WEIGHT * (1.0 - ring / SPAWN_SEARCH_RADIUS as f32);
+        if best.is_some_and(|(score, _)| score >= reachable) {
+            break;
+        }
+        let Some(score) = score_spawn(candidate, origin, &mut cached) else {
+            continue;
+        };
+        if score >= SAFE_SCORE && best.is_none_or(|(best, _)| score > best) {
+            let ground = cached(candidate).surface.unwrap_or_default();
+            best = Some((score, IVec3::new(candidate.x, ground + 1, candidate.y)));
+        }
+    }
+    best.map(|(_, feet)| feet)
+}
+
+// Where the slab goes when nowhere was safe, over the sea or the ground at the center
+pub fn platform_center(center: IVec2, surface: Option<i32>) -> IVec3 {
+    let y = surface.unwrap_or(SEA_LEVEL).max(SEA_LEVEL) + PLATFORM_RAISE;
+    IVec3::new(center.x, y, center.y)
+}
+
+// The slab and two blocks of air over it, so it can be stood on even if it ends up in a hill
+pub fn platform_blocks(platform: &SpawnPlatform) -> Vec<(IVec3, BlockData)> {
+    let (namespace, name) = platform
+        .block
+        .split_once(':')
+        .unwrap_or(("vinox", &platform.block));
+    let slab = BlockData::new(namespace.to_string(), name.to_string());
+    let air = BlockData::new("vinox".to_string(), "air".to_string());
+    let half = SPAWN_AREA / 2;
+    let mut blocks = Vec::new();
+    for x in -half..=half {
+        for z in -half..=half {
+            let column = platform.center + IVec3::new(x, 0, z);
+            blocks.push((column, slab.clone()));
+            for above in 1..=2 {
+                blocks.push((column + IVec3::Y * above, air.clone()));
+            }
+        }
+    }
+    blocks
+}
+
+// Saved chunks are read as they are, anything else is generated the way the first phase would
+// with the sea filled in like add_sea does
+fn terrain_sampler<'a>(
+    world_info: &'a WorldInfo,
+    noise: &'a GenerationNoise,
+    biome_table: &'a BiomeTable,
+    block_table: &'a BlockTable,
+    database: &'a WorldDatabase,
+) -> impl FnMut(IVec3) -> Option<BlockFlags> + 'a {
+    let dimension = DimensionId::default();
+    let generator = world_info.generator(dimension).unwrap_or_default();
+    // Generated chunks keep their column heights to know where the sea would go
+    let mut chunks: HashMap<IVec3, (ChunkData, Option<ColumnHeights>)> = HashMap::default();
+    move |voxel| {
+        let (chunk_pos, local_pos) = global_voxel_positions(voxel);
+        let (chunk, heights) = chunks.entry(chunk_pos).or_insert_with(|| {
+            let saved = load_chunk(
+                dimension,
+                ChunkPos(chunk_pos),
+                &database.connection.get().unwrap(),
+            )
+            .ok()
+            .flatten();
+            match saved {
+                Some(saved) => (ChunkData::from_raw(saved.chunk), None),
+                None => (
+                    ChunkData::from_raw(generate_dimension_chunk(
+                        chunk_pos,
+                        world_info.seed,
+                        generator,
+                        &noise.0,
+                        &world_info.terrain,
+                        biome_table,
+                        block_table,
+                    )),
+                    Some(column_heights(
+                        chunk_pos,
+                        world_info.seed,
+                        &noise.0,
+                        &world_info.terrain,
+                    )),
+                ),
+            }
+        });
+        let mut flags = block_flags(
+            &chunk.get(local_pos.x, local_pos.y, local_pos.z),
+            block_table,
+        );
+        let over_ground = heights.is_some_and(|heights| {
+            voxel.y >= heights[local_pos.z as usize * CHUNK_SIZE + local_pos.x as usize]
+        });
+        if over_ground && !flags.solid && voxel.y <= SEA_LEVEL {
+            flags.fluid = true;
+        }
+        Some(flags)
+    }
+}
+
+// Once per world, runs after the blocks and noise graphs are loaded
+pub fn choose_world_spawn(
+    mut world_info: ResMut<WorldInfo>,
+    path: Option<Res<WorldInfoPath>>,
+    (noise, block_table, database): (Res<GenerationNoise>, Res<BlockTable>, Res<WorldDatabase>),
+    biome_table: Res<BiomeTable>,
+) {
+    if world_info.spawn.is_some() {
+        return;
+    }
+    let search = world_info.spawn_search.clone();
+    println!("Looking for a safe spawn around {}", search.center);
+    let mut sample = terrain_sampler(&world_info, &noise, (&biome_table, &block_table), &database);
+    let mut column =
+        |column: IVec2| measure_column(column, search.max_y, search.min_y, &mut sample);
+    let spawn = match find_safe_spawn(search.center, SPAWN_SEARCH_BUDGET, &mut column) {
+        Some(feet) => {
+            println!("Spawn is at {feet}");
+            WorldSpawn {
+                feet,
+                platform: None,
+            }
+        }
+        None => {
+            let center = platform_center(search.center, column(search.center).surface);
+            println!(
+                "Nowhere near {} is safe, building a platform at {center}",
+                search.center
+            );
+            WorldSpawn {
+                feet: center + IVec3::Y,
+                platform: Some(SpawnPlatform {
+                    center,
+                    block: search.platform_block,
+                    placed: false,
+                }),
+            }
+        }
+    };
+    drop(sample);
+    world_info.spawn = Some(spawn);
+    if let Some(path) = path {
+        crate::save_world_info(world_info.clone(), path.0.clone());
+    }
+}
+
+// Goes in like any other server side edit as soon as every chunk it touches is loaded, which the
+// first player to join makes sure of
+#[allow(clippy::too_many_arguments)]
+pub fn build_spawn_platform(
+    mut server: ResMut<Server>,
+    mut world_info: ResMut<WorldInfo>,
+    path: Option<Res<WorldInfoPath>>,
+    current_chunks: Res<CurrentChunks>,
+    mut chunks: Query<&mut ChunkData>,
+    block_table: Res<BlockTable>,
+    mut chunks_to_save: ResMut<ChunksToSave>,
+    mut changed: EventWriter<BlockChangedEvent>,
+) {
+    let Some(platform) = world_info
+        .spawn
+        .as_ref()
+        .and_then(|spawn| spawn.platform.as_ref())
+        .filter(|platform| !platform.placed)
+    else {
+        return;
+    };
+    let dimension = DimensionId::default();
+    let blocks = platform_blocks(platform);
+    let loaded = |voxel: IVec3| {
+        let (chunk_pos, _) = global_voxel_positions(voxel);
+        current_chunks
+            .get_entity_in(dimension, ChunkPos(chunk_pos))
+            .filter(|entity| chunks.contains(*entity))
+    };
+    if !blocks.iter().all(|(voxel, _)| loaded(*voxel).is_some()) {
+        return;
+    }
+    let mut changed_chunks = Vec::new();
+    for (voxel, block) in blocks {
+        let (chunk_pos, voxel_pos) = global_voxel_positions(voxel);
+        let Some(entity) = loaded(voxel) else {
+            continue;
+        };
+        let Ok(mut chunk) = chunks.get_mut(entity) else {
+            continue;
+        };
+        chunk.set(
+            voxel_pos.x,
+            voxel_pos.y,
+            voxel_pos.z,
+            block.clone(),
+            &block_table,
+        );
+        if !changed_chunks.contains(&(chunk_pos, entity)) {
+            changed_chunks.push((chunk_pos, entity));
+        }
+        if let Some(endpoint) = server.get_endpoint_mut() {
+            endpoint.try_broadcast_message(ServerMessage::SentBlock {
+                chunk_pos,
+                voxel_pos: [voxel_pos.x as u8, voxel_pos.y as u8, voxel_pos.z as u8],
+                block_type: block,
+                dimension,
+                denied: None,
+            });
+        }
+        changed.send(BlockChangedEvent { dimension, voxel });
+    }
+    for (chunk_pos, entity) in changed_chunks {
+        if let Ok(chunk) = chunks.get(entity) {
+            chunks_to_save.push((dimension, ChunkPos(chunk_pos), chunk.to_raw()));
+        }
+    }
+    if let Some(platform) = world_info
+        .spawn
+        .as_mut()
+        .and_then(|spawn| spawn.platform.as_mut())
+    {
+        platform.placed = true;
+    }
+    if let Some(path) = path {
+        crate::save_world_info(world_info.clone(), path.0.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ground(surface: i32) -> SpawnColumn {
+        SpawnColumn {
+            surface: Some(surface),
+            buried: false,
+            water: false,
+            solid_depth: MIN_SOLID_DEPTH,
+        }
+    }
+
+    fn ocean(_: IVec2) -> SpawnColumn {
+        SpawnColumn {
+            water: true,
+            ..ground(SEA_LEVEL - 10)
+        }
+    }
+
+    #[test]
+    fn flat_plains_score_best_nearest_the_center() {
+        let mut plain = |_: IVec2| ground(64);
+        let center = score_spawn(IVec2::ZERO, IVec2::ZERO, &mut plain).unwrap();
+        assert!((center - 1.0).abs() < 1e-5);
+        let far = score_spawn(IVec2::new(20, 0), IVec2::ZERO, &mut plain).unwrap();
+        assert!(far < center && far >= SAFE_SCORE);
+        assert_eq!(
+            find_safe_spawn(IVec2::new(3, -4), SPAWN_SEARCH_BUDGET, plain),
+            Some(IVec3::new(3, 65, -4))
+        );
+        // A gentle slope is still fine
+        let mut slope = |column: IVec2| ground(64 + column.x);
+        assert!(score_spawn(IVec2::ZERO, IVec2::ZERO, &mut slope).unwrap() >= SAFE_SCORE);
+    }
+
+    #[test]
+    fn water_and_spires_are_avoided() {
+        assert_eq!(score_spawn(IVec2::ZERO, IVec2::ZERO, &mut ocean), None);
+        assert_eq!(
+            find_safe_spawn(IVec2::ZERO, SPAWN_SEARCH_BUDGET, ocean),
+            None
+        );
+
+        let mut spikes = |column: IVec2| {
+            ground(if (column.x + column.y) % 2 == 0 {
+                64
+            } else {
+                70
+            })
+        };
+        assert!(score_spawn(IVec2::ZERO, IVec2::ZERO, &mut spikes).unwrap() < SAFE_SCORE);
+        assert_eq!(
+            find_safe_spawn(IVec2::ZERO, SPAWN_SEARCH_BUDGET, spikes),
+            None
+        );
+
+        // A lone spire on a plain, the plain right next to it wins
+        let mut spire = |column: IVec2| ground(if column == IVec2::ZERO { 80 } else { 64 });
+        assert!(score_spawn(IVec2::ZERO, IVec2::ZERO, &mut spire).unwrap() < SAFE_SCORE);
+        let feet = find_safe_spawn(IVec2::ZERO, SPAWN_SEARCH_BUDGET, spire).unwrap();
+        assert_eq!(feet.y, 65);
+        assert!(feet.x.abs() <= 3 && feet.z.abs() <= 3);
+
+        // An island in the sea, the spawn ends up on it
+        let island = |column: IVec2| {
+            if (column - IVec2::new(12, 0)).abs().max_element() <= 4 {
+                ground(5)
+            } else {
+                ocean(column)
+            }
+        };
+        let feet = find_safe_spawn(IVec2::ZERO, SPAWN_SEARCH_BUDGET, island).unwrap();
+        assert_eq!(feet.y, 6);
+        assert!((feet.x - 12).abs() <= 2 && feet.z.abs() <= 2);
+    }
+
+    #[test]
+    fn cave_shells_are_measured_and_avoided() {
+        // Stone down to 60, a cave from 61 to 69 and a one block roof at 70
+        let mut sample = |voxel: IVec3| {
+            Some(BlockFlags {
+                solid: voxel.y <= 60 || voxel.y == 70,
+                ..default()
+            })
+        };
+        let column = measure_column(IVec2::ZERO, 100, 0, &mut sample);
+        assert_eq!(column.surface, Some(70));
+        assert_eq!(column.solid_depth, 1);
+        assert_eq!(column.standable(), None);
+        let mut columns = |at: IVec2| measure_column(at, 100, 0, &mut sample);
+        assert_eq!(score_spawn(IVec2::ZERO, IVec2::ZERO, &mut columns), None);
+
+        // Thick ground measures fine, a column under the top of the band is buried
+        let mut solid = |voxel: IVec3| {
+            Some(BlockFlags {
+                solid: voxel.y <= 64,
+                ..default()
+            })
+        };
+        assert_eq!(
+            measure_column(IVec2::ZERO, 100, 0, &mut solid).standable(),
+            Some(64)
+        );
+        assert!(measure_column(IVec2::ZERO, 65, 0, &mut solid).buried);
+        // Water over the ground
+        let mut flooded = |voxel: IVec3| {
+            Some(BlockFlags {
+                solid: voxel.y <= 64,
+                fluid: (65..=70).contains(&voxel.y),
+                ..default()
+            })
+        };
+        assert!(measure_column(IVec2::ZERO, 100, 0, &mut flooded).water);
+    }
+
+    #[test]
+    fn platforms_sit_above_the_ground_or_sea() {
+        assert_eq!(
+            platform_center(IVec2::new(4, 9), Some(SEA_LEVEL - 20)),
+            IVec3::new(4, SEA_LEVEL + PLATFORM_RAISE, 9)
+        );
+        assert_eq!(
+            platform_center(IVec2::ZERO, Some(40)),
+            IVec3::new(0, 40 + PLATFORM_RAISE, 0)
+        );
+        assert_eq!(
+            platform_center(IVec2::ZERO, None).y,
+            SEA_LEVEL + PLATFORM_RAISE
+        );
+
+        let platform = SpawnPlatform {
+            center: IVec3::new(0, 2, 0),
+            block: "vinox:cobblestone".to_string(),
+            placed: false,
+        };
+        let blocks = platform_blocks(&platform);
+        let slab: Vec<&IVec3> = blocks
+            .iter()
+            .filter(|(_, block)| block.has_identifier("vinox:cobblestone"))
+            .map(|(voxel, _)| voxel)
+            .collect();
+        assert_eq!(slab.len(), 25);
+        assert!(slab
+            .iter()
+            .all(|voxel| voxel.y == 2 && voxel.x.abs() <= 2 && voxel.z.abs() <= 2));
+        assert_eq!(blocks.len(), 25 * 3);
+
+        // One block thick over the sea, so it would never pass as natural ground. Respawning goes
+        // by the record instead
+        let mut sample = |voxel: IVec3| {
+            let slab = blocks
+                .iter()
+                .any(|(at, block)| *at == voxel && block.has_identifier("vinox:cobblestone"));
+            Some(BlockFlags {
+                solid: slab,
+                fluid: !slab && voxel.y <= SEA_LEVEL,
+                ..default()
+            })
+        };
+        let column = measure_column(IVec2::ZERO, 64, -64, &mut sample);
+        assert_eq!(column.surface, Some(2));
+        assert_eq!(column.solid_depth, 1);
+        assert!(!column.water);
+    }
+}
@@ -0,0 +1,320 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_quinnet::server::{Endpoint, Server};
+use vinox_common::{
+    ecs::bundles::Health,
+    networking::protocol::{Player, ServerMessage},
+    world::{
+        chunks::{
+            ecs::CurrentChunks,
+            positions::{global_voxel_positions, ChunkPos, DimensionId},
+            storage::{BlockData, BlockTable, ChunkData},
+        },
+        seats::{dismount_position, is_seat, seat_descriptor, seat_position},
+    },
+};
+
+use crate::game::networking::syncing::get_messages;
+
+use super::{
+    dropped::EYE_HEIGHT,
+    spawn::{respawn_players, UseBlockEvent, USE_REACH},
+};
+
+pub struct DismountEvent {
+    pub client_id: u64,
+    pub entity: Entity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Seat {
+    pub dimension: DimensionId,
+    pub voxel: IVec3,
+    // Where the player's feet are held
+    pub position: Vec3,
+}
+
+// Who sits where. Only ever kept here and never in the block, so nothing saves a seat as taken
+#[derive(Resource, Default)]
+pub struct Seats {
+    occupants: HashMap<(DimensionId, IVec3), Entity>,
+    seated: HashMap<Entity, Seat>,
+}
+
+impl Seats {
+    // False when someone else got there first. Sitting down somewhere new gets up from the old seat
+    pub fn sit(&mut self, player: Entity, seat: Seat) -> bool {
+        if self
+            .occupant(seat.dimension, seat.voxel)
+            .is_some_and(|occupant| occupant != player)
+        {
+            return false;
+        }
+        self.stand(player);
+        self.occupants.insert((seat.dimension, seat.voxel), player);
+        self.seated.insert(player, seat);
+        true
+    }
+
+    pub fn stand(&mut self, player: Entity) -> Option<Seat> {
+        let seat = self.seated.remove(&player)?;
+        self.occupants.remove(&(seat.dimension, seat.voxel));
+        Some(seat)
+    }
+
+    pub fn seat_of(&self, player: Entity) -> Option<Seat> {
+        self.seated.get(&player).copied()
+    }
+
+    pub fn occupant(&self, dimension: DimensionId, voxel: IVec3) -> Option<Entity> {
+        self.occupants.get(&(dimension, voxel)).copied()
+    }
+
+    // Gets up everyone keep turns down, handing back where they were sitting
+    pub fn release(&mut self, mut keep: impl FnMut(Entity, &Seat) -> bool) -> Vec<(Entity, Seat)> {
+        let released: Vec<(Entity, Seat)> = self
+            .seated
+            .iter()
+            .filter(|(player, seat)| !keep(**player, seat))
+            .map(|(player, seat)| (*player, *seat))
+            .collect();
+        for (player, _) in released.iter() {
+            self.stand(*player);
+        }
+        released
+    }
+}
+
+// Seats are always right next to whoever uses them, so only loaded chunks count
+fn loaded_block(
+    chunks: &Query<&ChunkData>,
+    current_chunks: &CurrentChunks,
+    dimension: DimensionId,
+    voxel: IVec3,
+) -> Option<BlockData> {
+    let (chunk_pos, voxel_pos) = global_voxel_positions(voxel);
+    let entity = current_chunks.get_entity_in(dimension, ChunkPos(chunk_pos))?;
+    let chunk = chunks.get(entity).ok()?;
+    Some(chunk.get(voxel_pos.x, voxel_pos.y, voxel_pos.z))
+}
+
+// Puts the player somewhere they can stand and lets the client move again
+fn get_up(
+    endpoint: &mut Endpoint,
+    client_id: u64,
+    transform: &mut Transform,
+    seat: Seat,
+    chunks: &Query<&ChunkData>,
+    current_chunks: &CurrentChunks,
+    block_table: &BlockTable,
+) {
+    let translation = dismount_position(seat.voxel, block_table, |voxel| {
+        loaded_block(chunks, current_chunks, seat.dimension, voxel)
+    });
+    transform.translation = translation;
+    endpoint.try_send_message(
+        client_id,
+        ServerMessage::Teleport {
+            translation: translation.as_dvec3(),
+        },
+    );
+    endpoint.try_send_message(client_id, ServerMessage::Seated { seat: None });
+}
+
+pub fn sit_on_seats(
+    mut server: ResMut<Server>,
+    mut events: EventReader<UseBlockEvent>,
+    mut players: Query<(&mut Transform, &DimensionId, &Health), With<Player>>,
+    current_chunks: Res<CurrentChunks>,
+    chunks: Query<&ChunkData>,
+    block_table: Res<BlockTable>,
+    mut seats: ResMut<Seats>,
+) {
+    let endpoint = server.endpoint_mut();
+    for evt in events.iter() {
+        let Ok((mut transform, dimension, health)) = players.get_mut(evt.entity) else {
+            continue;
+        };
+        let in_reach = (evt.voxel.as_vec3() + Vec3::splat(0.5))
+            .distance(transform.translation + Vec3::Y * EYE_HEIGHT)
+            <= USE_REACH;
+        let descriptor = loaded_block(&chunks, &current_chunks, *dimension, evt.voxel)
+            .and_then(|block| seat_descriptor(&block, &block_table));
+        let Some(descriptor) = descriptor.filter(|_| in_reach && health.current > 0.0) else {
+            continue;
+        };
+        let seat = Seat {
+            dimension: *dimension,
+            voxel: evt.voxel,
+            position: seat_position(evt.voxel, descriptor),
+        };
+        if !seats.sit(evt.entity, seat) {
+            continue;
+        }
+        transform.translation = seat.position;
+        endpoint.try_send_message(
+            evt.client_id,
+            ServerMessage::Seated {
+                seat: Some(seat.position.as_dvec3()),
+            },
+        );
+    }
+}
+
+pub fn dismount_players(
+    mut server: ResMut<Server>,
+    mut events: EventReader<DismountEvent>,
+    mut players: Query<&mut Transform, With<Player>>,
+    current_chunks: Res<CurrentChunks>,
+    chunks: Query<&ChunkData>,
+    block_table: Res<BlockTable>,
+    mut seats: ResMut<Seats>,
+) {
+    let endpoint = server.endpoint_mut();
+    for evt in events.iter() {
+        let Ok(mut transform) = players.get_mut(evt.entity) else {
+            continue;
+        };
+        if let Some(seat) = seats.stand(evt.entity) {
+            get_up(
+                endpoint,
+                evt.client_id,
+                &mut transform,
+                seat,
+                &chunks,
+                &current_chunks,
+                &block_table,
+            );
+        }
+    }
+}
+
+// Seats whose player left, went elsewhere or whose chunk unloaded are just let go. A seat that was
+// broken or replaced throws whoever was on it off
+pub fn check_seats(
+    mut server: ResMut<Server>,
+    mut players: Query<(&Player, &DimensionId, &mut Transform)>,
+    current_chunks: Res<CurrentChunks>,
+    chunks: Query<&ChunkData>,
+    block_table: Res<BlockTable>,
+    mut seats: ResMut<Seats>,
+) {
+    let endpoint = server.endpoint_mut();
+    let abandoned = seats.release(|player, seat| {
+        players
+            .get(player)
+            .is_ok_and(|(_, dimension, _)| *dimension == seat.dimension)
+            && loaded_block(&chunks, &current_chunks, seat.dimension, seat.voxel).is_some()
+    });
+    for (player, _) in abandoned {
+        if let Ok((player, _, _)) = players.get(player) {
+            endpoint.try_send_message(player.id, ServerMessage::Seated { seat: None });
+        }
+    }
+    let broken = seats.release(|_, seat| {
+        loaded_block(&chunks, &current_chunks, seat.dimension, seat.voxel)
+            .is_some_and(|block| is_seat(&block, &block_table))
+    });
+    for (player, seat) in broken {
+        if let Ok((player, _, mut transform)) = players.get_mut(player) {
+            get_up(
+                endpoint,
+                player.id,
+                &mut transform,
+                seat,
+                &chunks,
+                &current_chunks,
+                &block_table,
+            );
+        }
+    }
+}
+
+pub struct SeatPlugin;
+
+impl Plugin for SeatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Seats>()
+            .add_event::<DismountEvent>()
+            .add_systems(
+                (sit_on_seats, dismount_players, check_seats)
+                    .chain()
+                    .after(get_messages)
+                    .before(respawn_players),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seat(x: i32) -> Seat {
+        Seat {
+            dimension: DimensionId::default(),
+            voxel: IVec3::new(x, 0, 0),
+            position: Vec3::new(x as f32 + 0.5, 0.5, 0.5),
+        }
+    }
+
+    #[test]
+    fn one_player_per_seat() {
+        let mut seats = Seats::default();
+        let (first, second) = (Entity::from_raw(1), Entity::from_raw(2));
+        assert!(seats.sit(first, seat(0)));
+        assert!(!seats.sit(second, seat(0)));
+        assert_eq!(
+            seats.occupant(DimensionId::default(), IVec3::ZERO),
+            Some(first)
+        );
+        assert_eq!(seats.seat_of(second), None);
+        // Sitting down again on your own seat is fine
+        assert!(seats.sit(first, seat(0)));
+
+        // Moving over frees the old one
+        assert!(seats.sit(first, seat(1)));
+        assert_eq!(seats.occupant(DimensionId::default(), IVec3::ZERO), None);
+        assert!(seats.sit(second, seat(0)));
+        assert_eq!(seats.stand(second), Some(seat(0)));
+        assert_eq!(seats.stand(second), None);
+    }
+
+    #[test]
+    fn disconnecting_frees_the_seat_for_someone_else() {
+        let mut seats = Seats::default();
+        let (leaving, staying, next) = (
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+            Entity::from_raw(3),
+        );
+        assert!(seats.sit(leaving, seat(0)));
+        assert!(seats.sit(staying, seat(1)));
+        assert!(!seats.sit(next, seat(0)));
+
+        // The leaving player's entity is gone by the time seats are checked
+        let released = seats.release(|player, _| player != leaving);
+        assert_eq!(released, vec![(leaving, seat(0))]);
+        assert_eq!(seats.seat_of(leaving), None);
+        assert_eq!(seats.seat_of(staying), Some(seat(1)));
+
+        assert!(seats.sit(next, seat(0)));
+        assert_eq!(
+            seats.occupant(DimensionId::default(), IVec3::ZERO),
+            Some(next)
+        );
+    }
+
+    #[test]
+    fn unloading_a_chunk_lets_everyone_in_it_go() {
+        let mut seats = Seats::default();
+        let (near, far) = (Entity::from_raw(1), Entity::from_raw(2));
+        let mut far_seat = seat(0);
+        far_seat.voxel.x = 100;
+        assert!(seats.sit(near, seat(0)));
+        assert!(seats.sit(far, far_seat));
+
+        let released = seats.release(|_, seat| seat.voxel.x < 32);
+        assert_eq!(released, vec![(far, far_seat)]);
+        assert_eq!(seats.occupant(DimensionId::default(), far_seat.voxel), None);
+        assert_eq!(seats.seat_of(near), Some(seat(0)));
+    }
+}
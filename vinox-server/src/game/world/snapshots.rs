@@ -0,0 +1,402 @@
+use std::{collections::VecDeque, fmt};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use vinox_common::world::chunks::storage::{BlockData, BlockTable, ChunkData};
+
+use super::{
+    edits::{BlockEdit, EditLog, RollbackReport},
+    migration::MigrationError,
+    storage::{decode_chunk, encode_chunk},
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotPolicy {
+    // Accepted edits since the last snapshot before a chunk gets another
+    pub every_edits: usize,
+    // Or this long since the last one, as long as something was edited in between
+    pub every_minutes: u64,
+    pub kept: usize,
+}
+
+impl Default for SnapshotPolicy {
+    fn default() -> Self {
+        Self {
+            every_edits: 200,
+            every_minutes: 30,
+            kept: 4,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChunkSnapshot {
+    pub time: u64,
+    // Edits logged at exactly `time` that are already in it, the rest get replayed
+    pub covered: usize,
+    // An encoded RawChunk, the same record the chunk itself is saved as
+    pub data: Vec<u8>,
+}
+
+// Oldest first, never more than the policy keeps
+#[derive(Component, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Deref, DerefMut)]
+pub struct ChunkSnapshots(pub VecDeque<ChunkSnapshot>);
+
+#[derive(Debug, PartialEq)]
+pub enum RestoreError {
+    NoSnapshots,
+    // Every snapshot was taken inside the window, restoring one would keep the griefing
+    NewerThanWindow { oldest: u64, since: u64, now: u64 },
+    Unreadable(MigrationError),
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestoreError::NoSnapshots => write!(f, "no snapshots were taken of it"),
+            RestoreError::NewerThanWindow { oldest, since, now } => write!(
+                f,
+                "its oldest snapshot is {} minutes old but the rollback reaches back {} minutes, try a shorter window",
+                now.saturating_sub(*oldest) / 60,
+                now.saturating_sub(*since) / 60
+            ),
+            RestoreError::Unreadable(error) => write!(f, "its snapshot can't be read: {error}"),
+        }
+    }
+}
+
+impl ChunkSnapshot {
+    pub fn take(chunk: &ChunkData, log: &EditLog, time: u64) -> Option<Self> {
+        Some(Self {
+            time,
+            covered: log
+                .edits
+                .iter()
+                .rev()
+                .take_while(|edit| edit.time >= time)
+                .filter(|edit| edit.time == time)
+                .count(),
+            data: encode_chunk(&chunk.to_raw())?,
+        })
+    }
+
+    // Where in the log the edits it doesn't have yet start, None if some were pruned
+    fn replay_from(&self, log: &EditLog) -> Option<usize> {
+        if log.truncated_at.is_some_and(|time| time >= self.time) {
+            return None;
+        }
+        let start = log.edits.partition_point(|edit| edit.time < self.time) + self.covered;
+        (start <= log.edits.len()).then_some(start)
+    }
+}
+
+impl ChunkSnapshots {
+    pub fn bytes(&self) -> usize {
+        self.iter().map(|snapshot| snapshot.data.len()).sum()
+    }
+
+    // Logged edits the newest snapshot doesn't have, or the whole log if there isn't one
+    pub fn pending(&self, log: &EditLog) -> usize {
+        let start = self
+            .back()
+            .and_then(|snapshot| snapshot.replay_from(log))
+            .unwrap_or(0);
+        log.edits.len() - start
+    }
+
+    // Takes a snapshot if the chunk has been edited enough or long enough since the last one,
+    // true when it did so the caller knows to save them
+    pub fn record(
+        &mut self,
+        chunk: &ChunkData,
+        log: &EditLog,
+        now: u64,
+        policy: &SnapshotPolicy,
+    ) -> bool {
+        let pending = self.pending(log);
+        if pending == 0 || policy.kept == 0 {
+            return false;
+        }
+        let last = self
+            .back()
+            .map(|snapshot| snapshot.time)
+            .or(log.edits.front().map(|edit| edit.time))
+            .unwrap_or(now);
+        let stale = now.saturating_sub(last) >= policy.every_minutes.saturating_mul(60);
+        if pending < policy.every_edits && !stale {
+            return false;
+        }
+        let Some(snapshot) = ChunkSnapshot::take(chunk, log, now) else {
+            return false;
+        };
+        self.push_back(snapshot);
+        while self.len() > policy.kept {
+            self.pop_front();
+        }
+        true
+    }
+}
+
+// For when the log was cut inside the window so walking it would miss some of what `actor`
+// did. Starts over from the newest snapshot taken before `since` and replays what's left of
+// the log on top of it leaving out what `actor` did since then. What the log lost can't be
+// told apart by who did it, so everything changed in that stretch goes back to the snapshot
+#[allow(clippy::too_many_arguments)]
+pub fn restore_chunk(
+    chunk: &mut ChunkData,
+    log: &mut EditLog,
+    snapshots: &ChunkSnapshots,
+    actor: &str,
+    since: u64,
+    moderator: &str,
+    now: u64,
+    block_table: &BlockTable,
+) -> Result<(RollbackReport, Vec<[u8; 3]>), RestoreError> {
+    let oldest = snapshots.front().ok_or(RestoreError::NoSnapshots)?;
+    let snapshot = snapshots
+        .iter()
+        .rev()
+        .find(|snapshot| snapshot.time < since)
+        .ok_or(RestoreError::NewerThanWindow {
+            oldest: oldest.time,
+            since,
+            now,
+        })?;
+    let (raw_chunk, _) = decode_chunk(&snapshot.data).map_err(RestoreError::Unreadable)?;
+    let mut restored = ChunkData::from_raw(raw_chunk);
+
+    let mut report = RollbackReport::default();
+    // Past a gap the same second edits can't be told apart, replaying ones the snapshot
+    // already has just sets the same blocks again
+    let start = snapshot
+        .replay_from(log)
+        .unwrap_or_else(|| log.edits.partition_point(|edit| edit.time < snapshot.time));
+    let replayed: Vec<&BlockEdit> = log.edits.iter().skip(start).collect();
+    for (index, edit) in replayed.iter().enumerate() {
+        let [x, y, z] = edit.voxel.map(|axis| axis as u32);
+        if edit.actor == actor && edit.time >= since {
+            // Someone else building there afterwards still wins, same as the slow path
+            if replayed[index + 1..]
+                .iter()
+                .any(|later| later.voxel == edit.voxel && later.actor != actor)
+            {
+                report.conflicts += 1;
+            }
+            continue;
+        }
+        restored.set(x, y, z, edit.new.clone(), block_table);
+    }
+
+    let mut reverted = Vec::new();
+    let mut repairs = Vec::new();
+    for index in 0..ChunkData::usize() {
+        let (x, y, z) = ChunkData::delinearize(index);
+        let (current, wanted) = (chunk.get(x, y, z), restored.get(x, y, z));
+        if same_placement(&current, &wanted) {
+            continue;
+        }
+        let voxel = [x as u8, y as u8, z as u8];
+        chunk.set(x, y, z, wanted.clone(), block_table);
+        reverted.push(voxel);
+        repairs.push(BlockEdit {
+            actor: moderator.to_string(),
+            time: now,
+            voxel,
+            previous: current,
+            new: wanted,
+        });
+    }
+    report.reverted = reverted.len();
+    log.edits.extend(repairs);
+    Ok((report, reverted))
+}
+
+// Growing and tick stamps change blocks without an edit, on their own they aren't undone
+fn same_placement(a: &BlockData, b: &BlockData) -> bool {
+    a.namespace == b.namespace
+        && a.name == b.name
+        && a.direction == b.direction
+        && a.container == b.container
+        && a.arbitary_data == b.arbitary_data
+        && a.top == b.top
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::testing::block;
+
+    fn edit(
+        chunk: &mut ChunkData,
+        log: &mut EditLog,
+        actor: &str,
+        time: u64,
+        voxel: [u8; 3],
+        new: &str,
+    ) {
+        let [x, y, z] = voxel.map(|axis| axis as u32);
+        let previous = chunk.get(x, y, z);
+        chunk.set(x, y, z, block(new), &BlockTable::default());
+        log.push(
+            BlockEdit {
+                actor: actor.to_string(),
+                time,
+                voxel,
+                previous,
+                new: block(new),
+            },
+            u64::MAX,
+        );
+    }
+
+    fn snapshot(snapshots: &mut ChunkSnapshots, chunk: &ChunkData, log: &EditLog, time: u64) {
+        snapshots.push_back(ChunkSnapshot::take(chunk, log, time).unwrap());
+    }
+
+    #[test]
+    fn restores_past_truncation_with_interleaved_actors() {
+        let table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        let mut log = EditLog::default();
+        let mut snapshots = ChunkSnapshots::default();
+        edit(&mut chunk, &mut log, "builder", 10, [6, 6, 6], "stone");
+        edit(&mut chunk, &mut log, "builder", 100, [1, 1, 1], "stone");
+        edit(&mut chunk, &mut log, "griefer", 100, [4, 4, 4], "dirt");
+        snapshot(&mut snapshots, &chunk, &log, 100);
+        // Same second as the snapshot but after it, the log loses it before it can be replayed
+        edit(&mut chunk, &mut log, "builder", 100, [2, 2, 2], "planks");
+        edit(&mut chunk, &mut log, "griefer", 200, [1, 1, 1], "air");
+        edit(&mut chunk, &mut log, "griefer", 200, [7, 7, 7], "dirt");
+        edit(&mut chunk, &mut log, "builder", 210, [3, 3, 3], "stone");
+        edit(&mut chunk, &mut log, "griefer", 220, [3, 3, 3], "air");
+        edit(&mut chunk, &mut log, "griefer", 220, [1, 1, 1], "sand");
+        edit(&mut chunk, &mut log, "griefer", 230, [5, 5, 5], "sand");
+        edit(&mut chunk, &mut log, "builder", 240, [5, 5, 5], "planks");
+        // Stamped without an edit, that alone isn't put back
+        let mut stamped = chunk.get(6, 6, 6);
+        stamped.last_tick = Some(5);
+        chunk.set(6, 6, 6, stamped, &table);
+        // Cut inside the window, the griefer's first edits are gone from the log
+        log.prune(250, 49);
+        assert_eq!(log.truncated_at, Some(200));
+
+        let (report, mut reverted) = restore_chunk(
+            &mut chunk, &mut log, &snapshots, "griefer", 150, "mod", 300, &table,
+        )
+        .unwrap();
+        reverted.sort();
+        assert_eq!(reverted, vec![[1, 1, 1], [2, 2, 2], [3, 3, 3], [7, 7, 7]]);
+        assert_eq!(report.reverted, 4);
+        assert!(!report.history_incomplete);
+        // The sand was built over with planks
+        assert_eq!(report.conflicts, 1);
+        assert_eq!(chunk.get_identifier(1, 1, 1), "vinox:stone");
+        // Lost from the log along with the griefing, so it goes back with it
+        assert_eq!(
+            chunk.get_identifier(2, 2, 2),
+            ChunkData::default().get_identifier(2, 2, 2)
+        );
+        assert_eq!(chunk.get_identifier(3, 3, 3), "vinox:stone");
+        assert_eq!(chunk.get_identifier(5, 5, 5), "vinox:planks");
+        assert_eq!(chunk.get_identifier(6, 6, 6), "vinox:stone");
+        // Their edit from before the window stays
+        assert_eq!(chunk.get_identifier(4, 4, 4), "vinox:dirt");
+        // Their edit the log lost goes back to the snapshot too
+        assert_eq!(
+            chunk.get_identifier(7, 7, 7),
+            ChunkData::default().get_identifier(7, 7, 7)
+        );
+        assert_eq!(chunk.get(6, 6, 6).last_tick, Some(5));
+        let repairs = log.edits.iter().filter(|edit| edit.actor == "mod").count();
+        assert_eq!(repairs, 4);
+
+        // Again from the same snapshot, the repairs get replayed and nothing changes
+        let (report, reverted) = restore_chunk(
+            &mut chunk, &mut log, &snapshots, "griefer", 150, "mod", 300, &table,
+        )
+        .unwrap();
+        assert!(reverted.is_empty());
+        assert_eq!(report.reverted, 0);
+    }
+
+    #[test]
+    fn refuses_snapshots_inside_the_window() {
+        let table = BlockTable::default();
+        let mut chunk = ChunkData::default();
+        let mut log = EditLog::default();
+        let mut snapshots = ChunkSnapshots::default();
+        assert_eq!(
+            restore_chunk(&mut chunk, &mut log, &snapshots, "griefer", 150, "mod", 600, &table),
+            Err(RestoreError::NoSnapshots)
+        );
+
+        edit(&mut chunk, &mut log, "griefer", 200, [1, 1, 1], "dirt");
+        snapshot(&mut snapshots, &chunk, &log, 200);
+        let before = chunk.get(1, 1, 1);
+        let error = restore_chunk(
+            &mut chunk, &mut log, &snapshots, "griefer", 150, "mod", 600, &table,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            RestoreError::NewerThanWindow {
+                oldest: 200,
+                since: 150,
+                now: 600
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "its oldest snapshot is 6 minutes old but the rollback reaches back 7 minutes, try a shorter window"
+        );
+        // Nothing was touched
+        assert_eq!(chunk.get(1, 1, 1), before);
+        assert_eq!(log.edits.len(), 1);
+    }
+
+    #[test]
+    fn snapshots_follow_the_policy() {
+        let policy = SnapshotPolicy {
+            every_edits: 3,
+            every_minutes: 10,
+            kept: 2,
+        };
+        let mut chunk = ChunkData::default();
+        let mut log = EditLog::default();
+        let mut snapshots = ChunkSnapshots::default();
+        for time in 0..2 {
+            edit(
+                &mut chunk,
+                &mut log,
+                "builder",
+                time,
+                [time as u8, 0, 0],
+                "stone",
+            );
+            assert!(!snapshots.record(&chunk, &log, time, &policy));
+        }
+        edit(&mut chunk, &mut log, "builder", 2, [2, 0, 0], "stone");
+        assert!(snapshots.record(&chunk, &log, 2, &policy));
+        assert_eq!(snapshots.pending(&log), 0);
+        // Nothing new, however long it's been
+        assert!(!snapshots.record(&chunk, &log, 10_000, &policy));
+
+        // One edit is enough once the last snapshot is old
+        edit(&mut chunk, &mut log, "builder", 100, [3, 0, 0], "stone");
+        assert!(!snapshots.record(&chunk, &log, 100, &policy));
+        edit(&mut chunk, &mut log, "builder", 700, [4, 0, 0], "stone");
+        assert!(snapshots.record(&chunk, &log, 700, &policy));
+        for time in 701..704 {
+            edit(&mut chunk, &mut log, "builder", time, [5, 0, 0], "dirt");
+        }
+        assert!(snapshots.record(&chunk, &log, 703, &policy));
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].time, 700);
+
+        // A uniform chunk costs next to nothing
+        let mut uniform = ChunkSnapshots::default();
+        snapshot(&mut uniform, &ChunkData::default(), &EditLog::default(), 0);
+        assert!(uniform.bytes() < 256);
+        assert!(uniform.bytes() < snapshots[1].data.len());
+    }
+}
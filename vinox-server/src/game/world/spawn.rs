@@ -0,0 +1,304 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_quinnet::server::Server;
+use vinox_common::{
+    ecs::bundles::{Health, Hunger},
+    networking::protocol::{Player, ServerMessage},
+    physics::movement::block_flags,
+    world::{
+        chunks::{
+            ecs::CurrentChunks,
+            positions::{global_voxel_positions, world_to_chunk, ChunkPos, DimensionId},
+            storage::{BlockData, BlockTable, ChunkData},
+        },
+        spawn::{find_respawn, is_sleepable, Respawn, SpawnPoint},
+    },
+};
+
+use crate::game::networking::{
+    commands::{reply, CommandSender},
+    identity::PlayerIdentity,
+    start::setup_loadables,
+    syncing::ChangeDimensionEvent,
+};
+
+use super::{
+    chunk::LoadPoint,
+    dropped::EYE_HEIGHT,
+    noise_graph::load_noise_graphs,
+    safe_spawn::{
+        build_spawn_platform, choose_world_spawn, find_safe_spawn, measure_column, score_spawn,
+        world_spawn, RESPAWN_SEARCH_BUDGET, SAFE_SCORE,
+    },
+    seats::Seats,
+    storage::{load_chunk, load_spawn_point, SpawnPointsToSave, WorldDatabase, WorldInfo},
+};
+
+// Only until the world has a spawn of its own, see safe_spawn
+pub const WORLD_SPAWN: Vec3 = Vec3::new(0.0, 75.0, 0.0);
+// A little past what the client lets you click so lag doesn't turn a fair use away
+pub const USE_REACH: f32 = 8.0;
+
+// Loaded on join, None respawns at world spawn
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct PersonalSpawn(pub Option<SpawnPoint>);
+
+pub struct UseBlockEvent {
+    pub client_id: u64,
+    pub entity: Entity,
+    pub voxel: IVec3,
+}
+
+// Dying and /spawn both come through here
+pub struct RespawnEvent {
+    pub client_id: u64,
+    pub entity: Entity,
+}
+
+// Loaded chunks where there are any, otherwise whatever was last saved since beds are usually
+// nowhere near where the player died
+fn block_sampler<'a>(
+    dimension: DimensionId,
+    loaded: impl Fn(ChunkPos) -> Option<&'a ChunkData> + 'a,
+    database: &'a WorldDatabase,
+) -> impl FnMut(IVec3) -> Option<BlockData> + 'a {
+    let mut unloaded: HashMap<IVec3, Option<ChunkData>> = HashMap::default();
+    move |voxel| {
+        let (chunk_pos, local_pos) = global_voxel_positions(voxel);
+        let chunk = match loaded(ChunkPos(chunk_pos)) {
+            Some(chunk) => chunk,
+            None => unloaded
+                .entry(chunk_pos)
+                .or_insert_with(|| {
+                    load_chunk(
+                        dimension,
+                        ChunkPos(chunk_pos),
+                        &database.connection.get().unwrap(),
+                    )
+                    .ok()
+                    .flatten()
+                    .map(|loaded| ChunkData::from_raw(loaded.chunk))
+                })
+                .as_ref()?,
+        };
+        Some(chunk.get(local_pos.x, local_pos.y, local_pos.z))
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn load_spawn_points(
+    mut commands: Commands,
+    players: Query<(Entity, &PlayerIdentity), (With<Player>, Without<PersonalSpawn>)>,
+    database: Res<WorldDatabase>,
+) {
+    for (entity, identity) in players.iter() {
+        let spawn_point =
+            load_spawn_point(identity.storage_key(), &database.connection.get().unwrap());
+        commands.entity(entity).insert(PersonalSpawn(spawn_point));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn use_blocks(
+    mut server: ResMut<Server>,
+    mut events: EventReader<UseBlockEvent>,
+    mut players: Query<(
+        &PlayerIdentity,
+        &Transform,
+        &DimensionId,
+        &Health,
+        &mut PersonalSpawn,
+    )>,
+    current_chunks: Res<CurrentChunks>,
+    chunks: Query<&ChunkData>,
+    block_table: Res<BlockTable>,
+    database: Res<WorldDatabase>,
+    mut spawn_points_to_save: ResMut<SpawnPointsToSave>,
+) {
+    for evt in events.iter() {
+        let Ok((identity, transform, dimension, health, mut personal)) =
+            players.get_mut(evt.entity)
+        else {
+            continue;
+        };
+        let in_reach = (evt.voxel.as_vec3() + Vec3::splat(0.5))
+            .distance(transform.translation + Vec3::Y * EYE_HEIGHT)
+            <= USE_REACH;
+        let mut sample = block_sampler(
+            *dimension,
+            |chunk_pos| {
+                current_chunks
+                    .get_entity_in(*dimension, chunk_pos)
+                    .and_then(|entity| chunks.get(entity).ok())
+            },
+            &database,
+        );
+        if !in_reach
+            || health.current <= 0.0
+            || !sample(evt.voxel).is_some_and(|block| is_sleepable(&block, &block_table))
+        {
+            continue;
+        }
+        let spawn_point = SpawnPoint {
+            dimension: *dimension,
+            anchor: evt.voxel,
+        };
+        **personal = Some(spawn_point);
+        spawn_points_to_save.push((identity.storage_key().to_string(), Some(spawn_point)));
+        reply(
+            &mut server,
+            CommandSender::Player {
+                client_id: evt.client_id,
+                entity: evt.entity,
+            },
+            "Your spawn point is set".to_string(),
+        );
+    }
+}
+
+// Falls and starvation, see vitals. Players come back fed as well as healed
+pub fn respawn_dead_players(
+    mut players: Query<(Entity, &Player, &mut Health, &mut Hunger), Changed<Health>>,
+    mut respawns: EventWriter<RespawnEvent>,
+) {
+    for (entity, player, mut health, mut hunger) in players.iter_mut() {
+        if health.current <= 0.0 {
+            *health = Health::default();
+            *hunger = Hunger::default();
+            respawns.send(RespawnEvent {
+                client_id: player.id,
+                entity,
+            });
+        }
+    }
+}
+
+// The recorded world spawn unless something built over it or dug it out since, then the best
+// spot close by. A platform is trusted as it is, it was built because nothing around it was safe
+fn safe_world_spawn(
+    world_info: &WorldInfo,
+    current_chunks: &CurrentChunks,
+    chunks: &Query<&ChunkData>,
+    block_table: &BlockTable,
+    database: &WorldDatabase,
+) -> Vec3 {
+    let spawn = world_spawn(world_info);
+    let Some(recorded) = world_info
+        .spawn
+        .as_ref()
+        .filter(|spawn| spawn.platform.is_none())
+    else {
+        return spawn;
+    };
+    let dimension = DimensionId::default();
+    let mut sample = block_sampler(
+        dimension,
+        |chunk_pos| {
+            current_chunks
+                .get_entity_in(dimension, chunk_pos)
+                .and_then(|entity| chunks.get(entity).ok())
+        },
+        database,
+    );
+    let mut flags = |voxel| sample(voxel).map(|block| block_flags(&block, block_table));
+    let search = &world_info.spawn_search;
+    let mut column = |at: IVec2| measure_column(at, search.max_y, search.min_y, &mut flags);
+    let origin = IVec2::new(recorded.feet.x, recorded.feet.z);
+    if score_spawn(origin, origin, &mut column).is_some_and(|score| score >= SAFE_SCORE) {
+        return spawn;
+    }
+    find_safe_spawn(origin, RESPAWN_SEARCH_BUDGET, column)
+        .map_or(spawn, |feet| feet.as_vec3() + Vec3::new(0.5, 0.0, 0.5))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn respawn_players(
+    mut server: ResMut<Server>,
+    mut events: EventReader<RespawnEvent>,
+    mut players: Query<(&DimensionId, &PersonalSpawn, &mut Transform, &mut LoadPoint)>,
+    current_chunks: Res<CurrentChunks>,
+    chunks: Query<&ChunkData>,
+    (block_table, world_info): (Res<BlockTable>, Res<WorldInfo>),
+    database: Res<WorldDatabase>,
+    mut dimension_events: EventWriter<ChangeDimensionEvent>,
+    mut seats: ResMut<Seats>,
+) {
+    for evt in events.iter() {
+        let Ok((dimension, personal, mut transform, mut load_point)) = players.get_mut(evt.entity)
+        else {
+            continue;
+        };
+        let mut destination = (
+            DimensionId::default(),
+            safe_world_spawn(
+                &world_info,
+                &current_chunks,
+                &chunks,
+                &block_table,
+                &database,
+            ),
+        );
+        if let Some(spawn_point) = **personal {
+            let sample = block_sampler(
+                spawn_point.dimension,
+                |chunk_pos| {
+                    current_chunks
+                        .get_entity_in(spawn_point.dimension, chunk_pos)
+                        .and_then(|entity| chunks.get(entity).ok())
+                },
+                &database,
+            );
+            match find_respawn(spawn_point.anchor, &block_table, sample) {
+                Respawn::At(feet) => destination = (spawn_point.dimension, feet),
+                Respawn::Missing | Respawn::Obstructed => reply(
+                    &mut server,
+                    CommandSender::Player {
+                        client_id: evt.client_id,
+                        entity: evt.entity,
+                    },
+                    "Your bed was missing or obstructed".to_string(),
+                ),
+            }
+        }
+        let (to_dimension, translation) = destination;
+        if to_dimension != *dimension {
+            dimension_events.send(ChangeDimensionEvent {
+                player: evt.entity,
+                dimension: to_dimension,
+            });
+        }
+        transform.translation = translation;
+        **load_point = world_to_chunk(translation);
+        server.endpoint_mut().try_send_message(
+            evt.client_id,
+            ServerMessage::Teleport {
+                translation: translation.as_dvec3(),
+            },
+        );
+        if seats.stand(evt.entity).is_some() {
+            server
+                .endpoint_mut()
+                .try_send_message(evt.client_id, ServerMessage::Seated { seat: None });
+        }
+    }
+}
+
+pub struct SpawnPlugin;
+
+impl Plugin for SpawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<UseBlockEvent>()
+            .add_event::<RespawnEvent>()
+            .add_systems((
+                load_spawn_points,
+                use_blocks,
+                respawn_dead_players,
+                respawn_players.after(respawn_dead_players),
+                build_spawn_platform,
+            ))
+            .add_startup_system(
+                choose_world_spawn
+                    .after(setup_loadables)
+                    .after(load_noise_graphs),
+            );
+    }
+}
@@ -0,0 +1,448 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, time::SystemTime};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use vinox_common::{networking::protocol::EntityKind, world::chunks::positions::DimensionId};
+
+use super::storage::WorldInfo;
+
+// Every rule a spawn candidate goes through, in the order they're checked. Cheap checks that
+// turn away the most come first, counting what's already around the player comes last
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpawnRule {
+    Disabled,
+    TimeOfDay,
+    Region,
+    Biome,
+    Light,
+    NearBlock,
+    PlayerCap,
+}
+
+impl SpawnRule {
+    pub fn reason(self) -> &'static str {
+        match self {
+            SpawnRule::Disabled => "spawning is turned off for it",
+            SpawnRule::TimeOfDay => "it's outside its time of day",
+            SpawnRule::Region => "that's inside a protected region",
+            SpawnRule::Biome => "its biome isn't allowed",
+            SpawnRule::Light => "the light level is out of range",
+            SpawnRule::NearBlock => "the block it needs isn't close enough",
+            SpawnRule::PlayerCap => "the nearest player already has as many around as allowed",
+        }
+    }
+}
+
+// A box of voxels, both corners included
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub name: String,
+    #[serde(default)]
+    pub dimension: DimensionId,
+    pub min: IVec3,
+    pub max: IVec3,
+}
+
+impl Region {
+    pub fn contains(&self, dimension: DimensionId, voxel: IVec3) -> bool {
+        self.dimension == dimension
+            && voxel.cmpge(self.min.min(self.max)).all()
+            && voxel.cmple(self.min.max(self.max)).all()
+    }
+}
+
+// Somewhere within radius blocks of the candidate has to be this block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NearBlock {
+    pub identifier: String,
+    pub radius: i32,
+}
+
+// Anything the world can't tell yet (time of day, light, biomes) is optional, a rule on it
+// lets everything through until it can
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KindRules {
+    pub enabled: bool,
+    // Fractions of a day from midnight, the window wraps past midnight when start is after end
+    pub time_of_day: Option<(f32, f32)>,
+    pub min_light: Option<u8>,
+    pub max_light: Option<u8>,
+    // Empty allows every biome
+    pub allow_biomes: Vec<String>,
+    pub deny_biomes: Vec<String>,
+    pub near_block: Option<NearBlock>,
+    // Most of this kind around any one player, counted within the radius in blocks
+    pub per_player_cap: Option<usize>,
+    pub per_player_radius: f32,
+    pub avoid_protected: bool,
+}
+
+impl Default for KindRules {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            time_of_day: None,
+            min_light: None,
+            max_light: None,
+            allow_biomes: Vec::new(),
+            deny_biomes: Vec::new(),
+            near_block: None,
+            per_player_cap: None,
+            per_player_radius: 48.0,
+            avoid_protected: true,
+        }
+    }
+}
+
+fn in_window(time: f32, (start, end): (f32, f32)) -> bool {
+    if start <= end {
+        (start..end).contains(&time)
+    } else {
+        time >= start || time < end
+    }
+}
+
+// Where something might spawn and what's known about the spot
+#[derive(Debug, Clone, Default)]
+pub struct Candidate<'a> {
+    pub dimension: DimensionId,
+    pub voxel: IVec3,
+    pub time_of_day: Option<f32>,
+    pub light: Option<u8>,
+    pub biome: Option<&'a str>,
+}
+
+#[derive(Resource, Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpawnRules {
+    pub critter: KindRules,
+    pub protected: Vec<Region>,
+}
+
+impl SpawnRules {
+    // Dropped items only ever come from players, nothing spawns them on its own
+    pub fn kind(&self, kind: EntityKind) -> Option<&KindRules> {
+        match kind {
+            EntityKind::Critter => Some(&self.critter),
+            EntityKind::DroppedItem => None,
+        }
+    }
+
+    // The first rule the candidate breaks. `block_near` looks for a block around the candidate
+    // and `near` counts the kind around the player it was rolled for, both only get called once
+    // the cheaper rules have passed
+    pub fn check(
+        &self,
+        kind: EntityKind,
+        candidate: &Candidate,
+        block_near: impl FnOnce(&NearBlock) -> bool,
+        near: impl FnOnce(f32) -> usize,
+    ) -> Result<(), SpawnRule> {
+        let rules = self
+            .kind(kind)
+            .filter(|rules| rules.enabled)
+            .ok_or(SpawnRule::Disabled)?;
+        if let (Some(window), Some(time)) = (rules.time_of_day, candidate.time_of_day) {
+            if !in_window(time, window) {
+                return Err(SpawnRule::TimeOfDay);
+            }
+        }
+        if rules.avoid_protected
+            && self
+                .protected
+                .iter()
+                .any(|region| region.contains(candidate.dimension, candidate.voxel))
+        {
+            return Err(SpawnRule::Region);
+        }
+        if let Some(biome) = candidate.biome {
+            let allowed = rules.allow_biomes.is_empty()
+                || rules.allow_biomes.iter().any(|allowed| allowed == biome);
+            if !allowed || rules.deny_biomes.iter().any(|denied| denied == biome) {
+                return Err(SpawnRule::Biome);
+            }
+        }
+        if let Some(light) = candidate.light {
+            if rules.min_light.is_some_and(|min| light < min)
+                || rules.max_light.is_some_and(|max| light > max)
+            {
+                return Err(SpawnRule::Light);
+            }
+        }
+        if rules
+            .near_block
+            .as_ref()
+            .is_some_and(|near_block| !block_near(near_block))
+        {
+            return Err(SpawnRule::NearBlock);
+        }
+        if let Some(cap) = rules.per_player_cap {
+            if near(rules.per_player_radius) >= cap {
+                return Err(SpawnRule::PlayerCap);
+            }
+        }
+        Ok(())
+    }
+
+    // The whole set comes from the file or none of it does, a typo never leaves half the old
+    // rules running with half the new ones
+    pub fn reload(&mut self, world_file: &str) -> Result<bool, ron::error::SpannedError> {
+        #[derive(Deserialize)]
+        struct Partial {
+            #[serde(default)]
+            spawn_rules: SpawnRules,
+        }
+        let Partial { spawn_rules } = ron::from_str(world_file)?;
+        if spawn_rules == *self {
+            return Ok(false);
+        }
+        *self = spawn_rules;
+        Ok(true)
+    }
+}
+
+// For owners wondering why nothing is spawning
+#[derive(Resource, Debug, Default)]
+pub struct SpawnStats {
+    pub considered: u64,
+    pub rejected: BTreeMap<SpawnRule, u64>,
+    pub spawned: u64,
+}
+
+impl SpawnStats {
+    pub fn reject(&mut self, rule: SpawnRule) {
+        *self.rejected.entry(rule).or_default() += 1;
+    }
+}
+
+// Where the world's info was loaded from, watched for rule changes
+#[derive(Resource, Debug, Clone)]
+pub struct WorldInfoPath(pub PathBuf);
+
+pub fn load_spawn_rules(mut rules: ResMut<SpawnRules>, world_info: Res<WorldInfo>) {
+    *rules = world_info.spawn_rules.clone();
+}
+
+pub fn reload_spawn_rules(
+    mut rules: ResMut<SpawnRules>,
+    mut world_info: ResMut<WorldInfo>,
+    path: Option<Res<WorldInfoPath>>,
+    mut last_modified: Local<Option<SystemTime>>,
+) {
+    let Some(path) = path else {
+        return;
+    };
+    let Ok(modified) = fs::metadata(&path.0).and_then(|metadata| metadata.modified()) else {
+        return;
+    };
+    // The first look only notes the time, what's in the file was loaded at startup
+    if last_modified
+        .replace(modified)
+        .is_none_or(|last| last == modified)
+    {
+        return;
+    }
+    let Ok(text) = fs::read_to_string(&path.0) else {
+        return;
+    };
+    match rules.reload(&text) {
+        Ok(true) => {
+            world_info.spawn_rules = rules.clone();
+            println!("Reloaded spawn rules");
+        }
+        Ok(false) => {}
+        Err(e) => println!("Keeping the old spawn rules, couldn't read the new ones: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate() -> Candidate<'static> {
+        Candidate {
+            voxel: IVec3::new(10, 40, 10),
+            time_of_day: Some(0.5),
+            light: Some(12),
+            biome: Some("vinox:plains"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rules_short_circuit_in_order() {
+        let mut rules = SpawnRules::default();
+        rules.critter = KindRules {
+            time_of_day: Some((0.25, 0.75)),
+            max_light: Some(7),
+            deny_biomes: vec!["vinox:plains".to_string()],
+            near_block: Some(NearBlock {
+                identifier: "vinox:water".to_string(),
+                radius: 8,
+            }),
+            per_player_cap: Some(0),
+            ..Default::default()
+        };
+        rules.protected.push(Region {
+            name: "spawn".to_string(),
+            dimension: DimensionId::default(),
+            min: IVec3::new(16, 48, 16),
+            max: IVec3::new(0, 0, 0),
+        });
+        let counted = std::cell::Cell::new(false);
+        let near = |_| {
+            counted.set(true);
+            0
+        };
+        let searched = std::cell::Cell::new(false);
+        let block_near = |_: &NearBlock| {
+            searched.set(true);
+            false
+        };
+        let mut candidate = candidate();
+        // Breaks every rule, the region is the first one it gets to
+        assert_eq!(
+            rules.check(EntityKind::Critter, &candidate, block_near, near),
+            Err(SpawnRule::Region)
+        );
+        assert!(!counted.get());
+        assert!(!searched.get());
+
+        let mut peeled = vec![];
+        let mut water = false;
+        candidate.voxel = IVec3::new(-5, 40, 10);
+        for fix in 0..4 {
+            let block_near = |near_block: &NearBlock| {
+                assert_eq!(near_block.identifier, "vinox:water");
+                water
+            };
+            let result = rules.check(EntityKind::Critter, &candidate, block_near, |_| 0);
+            peeled.push(result.unwrap_err());
+            match fix {
+                0 => candidate.biome = Some("vinox:forest"),
+                1 => candidate.light = Some(3),
+                2 => water = true,
+                _ => {}
+            }
+        }
+        assert_eq!(
+            peeled,
+            vec![
+                SpawnRule::Biome,
+                SpawnRule::Light,
+                SpawnRule::NearBlock,
+                SpawnRule::PlayerCap
+            ]
+        );
+
+        // Outside the window at night, which the window doesn't wrap into
+        candidate.time_of_day = Some(0.9);
+        assert_eq!(
+            rules.check(EntityKind::Critter, &candidate, |_| true, |_| 0),
+            Err(SpawnRule::TimeOfDay)
+        );
+        rules.critter.enabled = false;
+        assert_eq!(
+            rules.check(EntityKind::Critter, &candidate, |_| true, |_| 0),
+            Err(SpawnRule::Disabled)
+        );
+        assert_eq!(
+            rules.check(EntityKind::DroppedItem, &candidate, |_| true, |_| 0),
+            Err(SpawnRule::Disabled)
+        );
+    }
+
+    #[test]
+    fn unknown_conditions_pass() {
+        let rules = SpawnRules {
+            critter: KindRules {
+                time_of_day: Some((0.8, 0.2)),
+                min_light: Some(8),
+                allow_biomes: vec!["vinox:forest".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // Nothing tracks time, light or biomes yet, so none of those can turn it away
+        let unknown = Candidate::default();
+        assert_eq!(
+            rules.check(EntityKind::Critter, &unknown, |_| true, |_| 0),
+            Ok(())
+        );
+        // A window across midnight
+        let late = Candidate {
+            time_of_day: Some(0.95),
+            ..Default::default()
+        };
+        assert_eq!(
+            rules.check(EntityKind::Critter, &late, |_| true, |_| 0),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn player_cap_counts_within_the_radius() {
+        let rules = SpawnRules {
+            critter: KindRules {
+                per_player_cap: Some(2),
+                per_player_radius: 20.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let player = Vec3::ZERO;
+        let mut critters = vec![Vec3::new(30.0, 0.0, 0.0)];
+        let mut stats = SpawnStats::default();
+        for _ in 0..5 {
+            stats.considered += 1;
+            let near = |radius: f32| {
+                critters
+                    .iter()
+                    .filter(|critter| critter.distance(player) < radius)
+                    .count()
+            };
+            match rules.check(EntityKind::Critter, &candidate(), |_| true, near) {
+                Ok(()) => {
+                    critters.push(Vec3::new(5.0, 0.0, 0.0));
+                    stats.spawned += 1;
+                }
+                Err(rule) => stats.reject(rule),
+            }
+        }
+        // The one out past the radius doesn't use up the cap
+        assert_eq!(stats.spawned, 2);
+        assert_eq!(stats.rejected.get(&SpawnRule::PlayerCap), Some(&3));
+        assert_eq!(stats.considered, 5);
+    }
+
+    #[test]
+    fn reloads_swap_the_whole_set() {
+        let mut rules = SpawnRules::default();
+        let file = r#"(
+            name: "world",
+            seed: 1,
+            damage: false,
+            spawn_rules: (
+                critter: (enabled: false, per_player_cap: Some(4)),
+                protected: [(name: "spawn", min: (0, 0, 0), max: (8, 8, 8))],
+            ),
+        )"#;
+        assert!(rules.reload(file).unwrap());
+        assert!(!rules.critter.enabled);
+        assert_eq!(rules.critter.per_player_cap, Some(4));
+        assert_eq!(rules.protected.len(), 1);
+        // Saving the same rules again isn't a change
+        assert!(!rules.reload(file).unwrap());
+
+        // Broken partway through, nothing from it gets in
+        let before = rules.clone();
+        let broken = r#"(spawn_rules: (critter: (enabled: true, per_player_cap: Some("four"))))"#;
+        assert!(rules.reload(broken).is_err());
+        assert_eq!(rules, before);
+
+        // Taking the rules out of the file goes back to the defaults
+        assert!(rules.reload(r#"(name: "world")"#).unwrap());
+        assert_eq!(rules, SpawnRules::default());
+    }
+}
@@ -1,28 +1,254 @@
-use std::io::Cursor;
+use std::{collections::HashSet, io::Cursor};
 
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 
 use bevy::prelude::*;
+use rand::Rng;
 use rusqlite::*;
 use serde::{Deserialize, Serialize};
 use vinox_common::{
     ecs::bundles::Inventory,
-    world::chunks::{positions::ChunkPos, storage::RawChunk},
+    networking::protocol::{EntityKind, SavedEntity},
+    storage::{
+        content::ContentPolicy,
+        items::descriptor::{ItemData, ItemMetadata},
+    },
+    world::{
+        chunks::{
+            positions::{ChunkPos, DimensionId},
+            storage::RawChunk,
+        },
+        spawn::SpawnPoint,
+    },
 };
 use zstd::stream::{copy_decode, copy_encode};
 
+use crate::game::networking::identity::DuplicateNames;
+
+use super::{
+    edits::EditLog,
+    generation::TerrainShape,
+    lifecycle::ChunkLifecycle,
+    migration::{
+        migrate_record, split_header, with_header, MigrationError, CHUNK_FORMAT_VERSION,
+        CHUNK_MIGRATIONS,
+    },
+    noise_graph::NoiseSelection,
+    safe_spawn::{SpawnSearch, WorldSpawn},
+    snapshots::{ChunkSnapshots, SnapshotPolicy},
+    spawn_rules::SpawnRules,
+};
+
+// Stored as sqlite's user_version, bump with a migration step whenever the save layout changes
+pub const SAVE_VERSION: i32 = 4;
+
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct ChunksToSave(pub Vec<(DimensionId, ChunkPos, RawChunk)>);
+
+// Chunks whose saved record failed to load. They're generated fresh to keep the world playable
+// but never saved, so the original is still there for whoever wants to recover it
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct UnreadableChunks(pub HashSet<(DimensionId, ChunkPos)>);
+
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct EntitiesToSave(pub Vec<(DimensionId, ChunkPos, Vec<SavedEntity>)>);
+
+// Entity records got a header once dropped items needed to carry their stack, 2 added item
+// metadata and 3 dropped the durability that metadata took over
+pub const ENTITY_FORMAT_VERSION: u32 = 3;
+
+// Bare bincode with no header, from when everything saved was a critter
+#[derive(Deserialize)]
+struct SavedEntityV0 {
+    kind: EntityKind,
+    translation: Vec3,
+}
+
+// Dropped stacks from before items had metadata
+#[derive(Deserialize)]
+struct SavedEntityV1 {
+    kind: EntityKind,
+    translation: Vec3,
+    item: Option<ItemDataV1>,
+}
+
+#[derive(Deserialize)]
+struct ItemDataV1 {
+    namespace: String,
+    name: String,
+    stack_size: u32,
+    #[allow(dead_code)]
+    durability: u32,
+    arbitary_data: Option<String>,
+}
+
+impl From<ItemDataV1> for ItemData {
+    fn from(item: ItemDataV1) -> Self {
+        ItemData {
+            namespace: item.namespace,
+            name: item.name,
+            stack_size: item.stack_size,
+            arbitary_data: item.arbitary_data,
+            metadata: None,
+        }
+    }
+}
+
+// Dropped stacks from when items had a durability of their own next to the metadata one
+#[derive(Deserialize)]
+struct SavedEntityV2 {
+    kind: EntityKind,
+    translation: Vec3,
+    item: Option<ItemDataV2>,
+}
+
+#[derive(Deserialize)]
+struct ItemDataV2 {
+    namespace: String,
+    name: String,
+    stack_size: u32,
+    // Only read to get past it, wear lives in the metadata
+    #[allow(dead_code)]
+    durability: u32,
+    arbitary_data: Option<String>,
+    metadata: Option<ItemMetadata>,
+}
+
+impl From<ItemDataV2> for ItemData {
+    fn from(item: ItemDataV2) -> Self {
+        ItemData {
+            namespace: item.namespace,
+            name: item.name,
+            stack_size: item.stack_size,
+            arbitary_data: item.arbitary_data,
+            metadata: item.metadata,
+        }
+    }
+}
+
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct EditLogsToSave(pub Vec<(DimensionId, ChunkPos, EditLog)>);
+
 #[derive(Resource, Deref, DerefMut, Default)]
-pub struct ChunksToSave(pub Vec<(ChunkPos, RawChunk)>);
+pub struct SnapshotsToSave(pub Vec<(DimensionId, ChunkPos, ChunkSnapshots)>);
 
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct InventoriesToSave(pub Vec<(String, Inventory)>);
 
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct RecipesToSave(pub Vec<(String, HashSet<String>)>);
+
+// None clears a player's spawn point back to world spawn
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct SpawnPointsToSave(pub Vec<(String, Option<SpawnPoint>)>);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GeneratorKind {
+    #[default]
+    Overworld,
+    Void,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DimensionConfig {
+    pub id: DimensionId,
+    pub generator: GeneratorKind,
+}
+
 #[derive(Resource, Serialize, Deserialize, Clone)]
 pub struct WorldInfo {
     pub name: String,
     pub seed: u32,
     pub damage: bool,
+    #[serde(default)]
+    pub dimensions: Vec<DimensionConfig>,
+    #[serde(default)]
+    pub moderators: Vec<String>,
+    #[serde(default = "default_edit_retention")]
+    pub edit_retention_hours: u64,
+    #[serde(default)]
+    pub snapshots: SnapshotPolicy,
+    // Newest chunk format this world has been opened with, 0 for worlds from before versioning
+    #[serde(default)]
+    pub format_version: u32,
+    // Whether clients with different blocks, items, recipes or geometry get in
+    #[serde(default)]
+    pub content_policy: ContentPolicy,
+    // Picked up again whenever this file changes while the server runs
+    #[serde(default)]
+    pub spawn_rules: SpawnRules,
+    // Which noise graphs shape the terrain, older worlds get the ones that match how they
+    // were generated
+    #[serde(default)]
+    pub noise: NoiseSelection,
+    // How tall the hills get and where the ground sits
+    #[serde(default)]
+    pub terrain: TerrainShape,
+    // When idle chunks unload and which ones never do
+    #[serde(default)]
+    pub chunk_lifecycle: ChunkLifecycle,
+    // Turn away a second player with a name that's already connected, or suffix theirs
+    #[serde(default)]
+    pub duplicate_names: DuplicateNames,
+    // Clients key their chunk caches on it, so a different world behind the same address
+    // never loads terrain from the old one
+    #[serde(default)]
+    pub world_id: String,
+    // Where to look for a safe spawn, and what to build when there isn't one
+    #[serde(default)]
+    pub spawn_search: SpawnSearch,
+    // None until the first time the world is opened
+    #[serde(default)]
+    pub spawn: Option<WorldSpawn>,
+}
+
+// A random version 4 UUID
+pub fn new_world_id(rng: &mut impl Rng) -> String {
+    let bits: u128 = rng.gen();
+    let bits = bits & !(0xf_u128 << 76) | (0x4 << 76);
+    let bits = bits & !(0x3_u128 << 62) | (0x2 << 62);
+    let hex = format!("{bits:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn default_edit_retention() -> u64 {
+    24
+}
+
+impl WorldInfo {
+    // The overworld always exists even if older world files don't list it
+    pub fn generator(&self, dimension: DimensionId) -> Option<GeneratorKind> {
+        self.dimensions
+            .iter()
+            .find(|config| config.id == dimension)
+            .map(|config| config.generator)
+            .or(if *dimension == 0 {
+                Some(GeneratorKind::Overworld)
+            } else {
+                None
+            })
+    }
+
+    pub fn edit_retention_secs(&self) -> u64 {
+        self.edit_retention_hours.saturating_mul(60 * 60)
+    }
+
+    // Worlds from before there were ids get one the first time they're opened
+    pub fn assign_world_id(&mut self) -> bool {
+        if !self.world_id.is_empty() {
+            return false;
+        }
+        self.world_id = new_world_id(&mut rand::thread_rng());
+        true
+    }
 }
 
 #[derive(Resource)]
@@ -30,19 +256,41 @@ pub struct WorldDatabase {
     pub connection: Pool<SqliteConnectionManager>,
 }
 
-pub fn create_database(database: &Connection) {
-    database
-        .execute(
-            " create table if not exists blocks (
+// Dimension 0 keeps the original table names so existing worlds load as the overworld
+pub fn dimension_table(table: &str, dimension: DimensionId) -> String {
+    if *dimension == 0 {
+        table.to_string()
+    } else {
+        format!("{table}_dim{}", *dimension)
+    }
+}
+
+pub fn create_dimension_tables(database: &Connection, dimension: DimensionId) {
+    for (table, extra_columns) in [
+        ("blocks", "edits blob, snapshots blob,"),
+        ("saved_entities", ""),
+    ] {
+        database
+            .execute(
+                &format!(
+                    " create table if not exists {} (
             posx integer not null,
             posy integer not null,
             posz integer not null,
             data blob,
+            {extra_columns}
             PRIMARY KEY (posx, posy, posz)
         )",
-            [],
-        )
-        .unwrap();
+                    dimension_table(table, dimension)
+                ),
+                [],
+            )
+            .unwrap();
+    }
+}
+
+pub fn create_database(database: &Connection) {
+    create_dimension_tables(database, DimensionId::default());
     database
         .execute(
             " create table if not exists inventories (
@@ -53,24 +301,136 @@ pub fn create_database(database: &Connection) {
             [],
         )
         .unwrap();
+    migrate_database(database);
+}
+
+pub fn migrate_database(database: &Connection) {
+    let version: i32 = database
+        .query_row("PRAGMA user_version;", [], |row| row.get(0))
+        .unwrap_or(0);
+    let block_tables: Vec<String> = database
+        .prepare(
+            "SELECT name FROM sqlite_master WHERE type='table' AND (name='blocks' OR name LIKE 'blocks_dim%');",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>>>()
+        })
+        .unwrap_or_default();
+    if version < 1 {
+        // Version 1 keeps each chunk's edit log next to its blocks
+        for table in &block_tables {
+            // Fails harmlessly on tables that were just created with the column
+            database
+                .execute(&format!("ALTER TABLE {table} ADD COLUMN edits blob;"), [])
+                .ok();
+        }
+    }
+    if version < 2 {
+        // Version 2 remembers which recipes each player has unlocked
+        database
+            .execute(
+                " create table if not exists unlocked_recipes (
+            name varchar(255) not null,
+            data blob,
+            PRIMARY KEY (name)
+        )",
+                [],
+            )
+            .unwrap();
+    }
+    if version < 3 {
+        // Version 3 remembers the bed each player last used
+        database
+            .execute(
+                " create table if not exists spawn_points (
+            name varchar(255) not null,
+            data blob,
+            PRIMARY KEY (name)
+        )",
+                [],
+            )
+            .unwrap();
+    }
+    if version < 4 {
+        // Version 4 keeps the last few snapshots of each edited chunk for /rollback
+        for table in &block_tables {
+            database
+                .execute(
+                    &format!("ALTER TABLE {table} ADD COLUMN snapshots blob;"),
+                    [],
+                )
+                .ok();
+        }
+    }
+    if version != SAVE_VERSION {
+        println!("Migrated world save from version {version} to {SAVE_VERSION}");
+        database
+            .execute_batch(&format!("PRAGMA user_version = {SAVE_VERSION};"))
+            .unwrap();
+    }
 }
 
 pub fn save_chunks(chunks: &ChunksToSave, database: &Connection) {
     database.execute("BEGIN;", []).unwrap();
-    for (chunk_pos, raw_chunk) in chunks.iter() {
-        if let Ok(raw_chunk_bin) = bincode::serialize(raw_chunk) {
-            let mut final_chunk = Cursor::new(raw_chunk_bin);
-            let mut output = Cursor::new(Vec::new());
-            copy_encode(&mut final_chunk, &mut output, 0).unwrap();
+    for (dimension, chunk_pos, raw_chunk) in chunks.iter() {
+        if **dimension != 0 {
+            create_dimension_tables(database, *dimension);
+        }
+        if let Some(record) = encode_chunk(raw_chunk) {
+            database
+                .execute(
+                    // Upsert so the edit log column survives block saves
+                    &format!(
+                        "INSERT INTO {} (posx, posy, posz, data) values (?1, ?2, ?3, ?4)
+                        ON CONFLICT (posx, posy, posz) DO UPDATE SET data=excluded.data",
+                        dimension_table("blocks", *dimension)
+                    ),
+                    params![&chunk_pos.x, &chunk_pos.y, &chunk_pos.z, &record,],
+                )
+                .unwrap();
+        }
+    }
+    database.execute("COMMIT;", []).unwrap();
+}
+
+pub fn save_entities(entities: &EntitiesToSave, database: &Connection) {
+    database.execute("BEGIN;", []).unwrap();
+    for (dimension, chunk_pos, saved_entities) in entities.iter() {
+        if **dimension != 0 {
+            create_dimension_tables(database, *dimension);
+        }
+        if let Ok(entities_bin) = bincode::serialize(saved_entities) {
+            let entities_bin = with_header(ENTITY_FORMAT_VERSION, &entities_bin);
+            database
+                .execute(
+                    &format!(
+                        "REPLACE INTO {} (posx, posy, posz, data) values (?1, ?2, ?3, ?4)",
+                        dimension_table("saved_entities", *dimension)
+                    ),
+                    params![&chunk_pos.x, &chunk_pos.y, &chunk_pos.z, &entities_bin],
+                )
+                .unwrap();
+        }
+    }
+    database.execute("COMMIT;", []).unwrap();
+}
+
+pub fn save_edit_logs(edit_logs: &EditLogsToSave, database: &Connection) {
+    database.execute("BEGIN;", []).unwrap();
+    for (dimension, chunk_pos, edit_log) in edit_logs.iter() {
+        if **dimension != 0 {
+            create_dimension_tables(database, *dimension);
+        }
+        if let Ok(edits_bin) = bincode::serialize(edit_log) {
             database
                 .execute(
-                    "REPLACE INTO blocks (posx, posy, posz, data) values (?1, ?2, ?3, ?4)",
-                    params![
-                        &chunk_pos.x,
-                        &chunk_pos.y,
-                        &chunk_pos.z,
-                        &output.get_ref().clone(),
-                    ],
+                    &format!(
+                        "INSERT INTO {} (posx, posy, posz, edits) values (?1, ?2, ?3, ?4)
+                        ON CONFLICT (posx, posy, posz) DO UPDATE SET edits=excluded.edits",
+                        dimension_table("blocks", *dimension)
+                    ),
+                    params![&chunk_pos.x, &chunk_pos.y, &chunk_pos.z, &edits_bin],
                 )
                 .unwrap();
         }
@@ -78,6 +438,228 @@ pub fn save_chunks(chunks: &ChunksToSave, database: &Connection) {
     database.execute("COMMIT;", []).unwrap();
 }
 
+pub fn save_snapshots(snapshots: &SnapshotsToSave, database: &Connection) {
+    database.execute("BEGIN;", []).unwrap();
+    for (dimension, chunk_pos, chunk_snapshots) in snapshots.iter() {
+        if **dimension != 0 {
+            create_dimension_tables(database, *dimension);
+        }
+        if let Ok(snapshots_bin) = bincode::serialize(chunk_snapshots) {
+            database
+                .execute(
+                    &format!(
+                        "INSERT INTO {} (posx, posy, posz, snapshots) values (?1, ?2, ?3, ?4)
+                        ON CONFLICT (posx, posy, posz) DO UPDATE SET snapshots=excluded.snapshots",
+                        dimension_table("blocks", *dimension)
+                    ),
+                    params![&chunk_pos.x, &chunk_pos.y, &chunk_pos.z, &snapshots_bin],
+                )
+                .unwrap();
+        }
+    }
+    database.execute("COMMIT;", []).unwrap();
+}
+
+pub fn save_unlocked_recipes(recipes: &RecipesToSave, database: &Connection) {
+    database.execute("BEGIN;", []).unwrap();
+    for (user_name, unlocked) in recipes.iter() {
+        if let Ok(unlocked_bin) = bincode::serialize(unlocked) {
+            database
+                .execute(
+                    "REPLACE INTO unlocked_recipes (name, data) values (?1, ?2)",
+                    params![user_name, &unlocked_bin],
+                )
+                .unwrap();
+        }
+    }
+    database.execute("COMMIT;", []).unwrap();
+}
+
+// Nothing saved yet just means a new player
+pub fn load_unlocked_recipes(user_name: &str, database: &Connection) -> HashSet<String> {
+    let unlocked_result: Result<Vec<u8>, _> = database.query_row(
+        "SELECT data FROM unlocked_recipes WHERE name=?1;",
+        params![user_name],
+        |row| row.get(0),
+    );
+    if let Ok(unlocked_row) = unlocked_result {
+        match bincode::deserialize(&unlocked_row) {
+            Ok(unlocked) => return unlocked,
+            Err(e) => println!("Failed to load unlocked recipes for {user_name}: {e}"),
+        }
+    }
+    HashSet::new()
+}
+
+pub fn save_spawn_points(spawn_points: &SpawnPointsToSave, database: &Connection) {
+    database.execute("BEGIN;", []).unwrap();
+    for (user_name, spawn_point) in spawn_points.iter() {
+        match spawn_point {
+            Some(spawn_point) => {
+                if let Ok(spawn_bin) = bincode::serialize(spawn_point) {
+                    database
+                        .execute(
+                            "REPLACE INTO spawn_points (name, data) values (?1, ?2)",
+                            params![user_name, &spawn_bin],
+                        )
+                        .unwrap();
+                }
+            }
+            None => {
+                database
+                    .execute("DELETE FROM spawn_points WHERE name=?1", params![user_name])
+                    .unwrap();
+            }
+        }
+    }
+    database.execute("COMMIT;", []).unwrap();
+}
+
+pub fn load_spawn_point(user_name: &str, database: &Connection) -> Option<SpawnPoint> {
+    let spawn_result: Result<Vec<u8>, _> = database.query_row(
+        "SELECT data FROM spawn_points WHERE name=?1;",
+        params![user_name],
+        |row| row.get(0),
+    );
+    match bincode::deserialize(&spawn_result.ok()?) {
+        Ok(spawn_point) => Some(spawn_point),
+        Err(e) => {
+            println!("Failed to load the spawn point for {user_name}: {e}");
+            None
+        }
+    }
+}
+
+pub fn load_edit_log(
+    dimension: DimensionId,
+    chunk_pos: ChunkPos,
+    database: &Connection,
+) -> EditLog {
+    let stmt = database.prepare(&format!(
+        "SELECT edits FROM {} WHERE posx=:posx AND posy=:posy AND posz=:posz;",
+        dimension_table("blocks", dimension)
+    ));
+    if let Ok(mut stmt) = stmt {
+        let edits_result: Result<Option<Vec<u8>>, _> = stmt.query_row(
+            &[
+                (":posx", &chunk_pos.x),
+                (":posy", &chunk_pos.y),
+                (":posz", &chunk_pos.z),
+            ],
+            |row| row.get(0),
+        );
+        if let Ok(Some(edits_row)) = edits_result {
+            match bincode::deserialize(&edits_row) {
+                Ok(edit_log) => return edit_log,
+                Err(e) => println!("Failed to load edit log for chunk {chunk_pos:?}: {e}"),
+            }
+        }
+    }
+    EditLog::default()
+}
+
+pub fn load_snapshots(
+    dimension: DimensionId,
+    chunk_pos: ChunkPos,
+    database: &Connection,
+) -> ChunkSnapshots {
+    let stmt = database.prepare(&format!(
+        "SELECT snapshots FROM {} WHERE posx=:posx AND posy=:posy AND posz=:posz;",
+        dimension_table("blocks", dimension)
+    ));
+    if let Ok(mut stmt) = stmt {
+        let snapshots_result: Result<Option<Vec<u8>>, _> = stmt.query_row(
+            &[
+                (":posx", &chunk_pos.x),
+                (":posy", &chunk_pos.y),
+                (":posz", &chunk_pos.z),
+            ],
+            |row| row.get(0),
+        );
+        if let Ok(Some(snapshots_row)) = snapshots_result {
+            match bincode::deserialize(&snapshots_row) {
+                Ok(snapshots) => return snapshots,
+                Err(e) => println!("Failed to load snapshots for chunk {chunk_pos:?}: {e}"),
+            }
+        }
+    }
+    ChunkSnapshots::default()
+}
+
+// Entities are removed from the table once loaded since they are alive in the world again
+pub fn take_entities(
+    dimension: DimensionId,
+    chunk_pos: ChunkPos,
+    database: &Connection,
+) -> Vec<SavedEntity> {
+    let table = dimension_table("saved_entities", dimension);
+    let stmt = database.prepare(&format!(
+        "SELECT data FROM {table} WHERE posx=:posx AND posy=:posy AND posz=:posz;"
+    ));
+    let mut saved_entities = Vec::new();
+    if let Ok(mut stmt) = stmt {
+        let entities_result: Result<Vec<u8>, _> = stmt.query_row(
+            &[
+                (":posx", &chunk_pos.x),
+                (":posy", &chunk_pos.y),
+                (":posz", &chunk_pos.z),
+            ],
+            |row| row.get(0),
+        );
+        if let Ok(entities_row) = entities_result {
+            match decode_entities(&entities_row) {
+                Ok(entities) => saved_entities = entities,
+                Err(e) => println!("Failed to load entities in chunk {chunk_pos:?}: {e}"),
+            }
+            database
+                .execute(
+                    &format!("DELETE FROM {table} WHERE posx=?1 AND posy=?2 AND posz=?3"),
+                    params![&chunk_pos.x, &chunk_pos.y, &chunk_pos.z],
+                )
+                .ok();
+        }
+    }
+    saved_entities
+}
+
+pub fn decode_entities(record: &[u8]) -> Result<Vec<SavedEntity>, MigrationError> {
+    let corrupt = |e: bincode::Error| MigrationError::Corrupt(e.to_string());
+    match split_header(record)? {
+        (0, payload) => Ok(bincode::deserialize::<Vec<SavedEntityV0>>(payload)
+            .map_err(corrupt)?
+            .into_iter()
+            .map(|entity| SavedEntity {
+                kind: entity.kind,
+                translation: entity.translation,
+                item: None,
+            })
+            .collect()),
+        (1, payload) => Ok(bincode::deserialize::<Vec<SavedEntityV1>>(payload)
+            .map_err(corrupt)?
+            .into_iter()
+            .map(|entity| SavedEntity {
+                kind: entity.kind,
+                translation: entity.translation,
+                item: entity.item.map(ItemData::from),
+            })
+            .collect()),
+        (2, payload) => Ok(bincode::deserialize::<Vec<SavedEntityV2>>(payload)
+            .map_err(corrupt)?
+            .into_iter()
+            .map(|entity| SavedEntity {
+                kind: entity.kind,
+                translation: entity.translation,
+                item: entity.item.map(ItemData::from),
+            })
+            .collect()),
+        (ENTITY_FORMAT_VERSION, payload) => bincode::deserialize(payload).map_err(corrupt),
+        (found, _) => Err(MigrationError::Newer {
+            found,
+            supported: ENTITY_FORMAT_VERSION,
+        }),
+    }
+}
+
 // pub fn save_inventories(inventories: &InventoriesToSave, database: &Connection) {
 //     database.execute("BEGIN;", []).unwrap();
 //     for (user_name, inventory) in inventories.iter() {
@@ -107,26 +689,477 @@ pub fn save_chunks(chunks: &ChunksToSave, database: &Connection) {
 //     None
 // }
 
-pub fn load_chunk(chunk_pos: ChunkPos, database: &Connection) -> Option<RawChunk> {
-    let stmt = database.prepare(
-        "SELECT posx, posy, posz, data FROM blocks WHERE posx=:posx AND posy=:posy AND posz=:posz;",
-    );
+pub fn encode_chunk(raw_chunk: &RawChunk) -> Option<Vec<u8>> {
+    let raw_chunk_bin = bincode::serialize(raw_chunk).ok()?;
+    let mut output = Cursor::new(Vec::new());
+    copy_encode(&mut Cursor::new(raw_chunk_bin), &mut output, 0).ok()?;
+    Some(with_header(CHUNK_FORMAT_VERSION, output.get_ref()))
+}
+
+// The chunk and whether it had to be migrated to get there
+pub fn decode_chunk(record: &[u8]) -> Result<(RawChunk, bool), MigrationError> {
+    let (payload, migrated) = migrate_record(record, CHUNK_MIGRATIONS)?;
+    let mut temp_output = Cursor::new(Vec::new());
+    copy_decode(&payload[..], &mut temp_output)
+        .map_err(|e| MigrationError::Corrupt(e.to_string()))?;
+    let raw_chunk = bincode::deserialize(temp_output.get_ref())
+        .map_err(|e| MigrationError::Corrupt(e.to_string()))?;
+    Ok((raw_chunk, migrated))
+}
+
+pub struct LoadedChunk {
+    pub chunk: RawChunk,
+    // Saved in an older format, it gets written back in the current one on its next save
+    pub migrated: bool,
+}
+
+// Ok(None) is a chunk that was never saved, an error is one that was but can't be read
+pub fn load_chunk(
+    dimension: DimensionId,
+    chunk_pos: ChunkPos,
+    database: &Connection,
+) -> Result<Option<LoadedChunk>, MigrationError> {
+    let stmt = database.prepare(&format!(
+        "SELECT posx, posy, posz, data FROM {} WHERE posx=:posx AND posy=:posy AND posz=:posz;",
+        dimension_table("blocks", dimension)
+    ));
     if let Ok(mut stmt) = stmt {
-        let chunk_result: Result<Vec<u8>, _> = stmt.query_row(
+        let chunk_result: Result<Option<Vec<u8>>, _> = stmt.query_row(
             &[
                 (":posx", &chunk_pos.x),
                 (":posy", &chunk_pos.y),
                 (":posz", &chunk_pos.z),
             ],
-            |row| Ok(row.get(3).unwrap()),
+            |row| row.get(3),
         );
-        if let Ok(chunk_row) = chunk_result {
-            let mut temp_output = Cursor::new(Vec::new());
-            copy_decode(&chunk_row[..], &mut temp_output).unwrap();
-            let final_chunk = bincode::deserialize(temp_output.get_ref()).unwrap();
-            return Some(final_chunk);
+        // Rows written by an edit log save before their chunk have no data yet
+        if let Ok(Some(chunk_row)) = chunk_result {
+            let (chunk, migrated) = decode_chunk(&chunk_row)?;
+            return Ok(Some(LoadedChunk { chunk, migrated }));
         }
     }
 
-    None
+    Ok(None)
+}
+
+// Only the server binary's --upgrade-world runs this, the server embedded in the client never does
+#[allow(dead_code)]
+#[derive(Default, Debug)]
+pub struct UpgradeReport {
+    pub upgraded: usize,
+    pub current: usize,
+    pub failed: Vec<(String, ChunkPos, MigrationError)>,
+}
+
+// Offline pass over every saved chunk in every dimension, progress gets (done, total)
+#[allow(dead_code)]
+pub fn upgrade_world(
+    database: &Connection,
+    mut progress: impl FnMut(usize, usize),
+) -> UpgradeReport {
+    let mut report = UpgradeReport::default();
+    let tables: Vec<String> = database
+        .prepare(
+            "SELECT name FROM sqlite_master WHERE type='table' AND (name='blocks' OR name LIKE 'blocks_dim%');",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>>>()
+        })
+        .unwrap_or_default();
+    let mut rows = Vec::new();
+    for table in tables {
+        let table_rows = database
+            .prepare(&format!(
+                "SELECT posx, posy, posz, data FROM {table} WHERE data IS NOT NULL;"
+            ))
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| {
+                    Ok((
+                        ChunkPos::new(row.get(0)?, row.get(1)?, row.get(2)?),
+                        row.get::<_, Vec<u8>>(3)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>>>()
+            })
+            .unwrap_or_default();
+        rows.extend(
+            table_rows
+                .into_iter()
+                .map(|(pos, record)| (table.clone(), pos, record)),
+        );
+    }
+
+    let total = rows.len();
+    database.execute("BEGIN;", []).unwrap();
+    for (done, (table, pos, record)) in rows.into_iter().enumerate() {
+        match decode_chunk(&record) {
+            Ok((raw_chunk, true)) => {
+                if let Some(record) = encode_chunk(&raw_chunk) {
+                    database
+                        .execute(
+                            &format!(
+                                "UPDATE {table} SET data=?4 WHERE posx=?1 AND posy=?2 AND posz=?3"
+                            ),
+                            params![&pos.x, &pos.y, &pos.z, &record],
+                        )
+                        .unwrap();
+                    report.upgraded += 1;
+                }
+            }
+            Ok((_, false)) => report.current += 1,
+            Err(error) => report.failed.push((table, pos, error)),
+        }
+        progress(done + 1, total);
+    }
+    database.execute("COMMIT;", []).unwrap();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::world::snapshots::ChunkSnapshot;
+    use vinox_common::world::chunks::storage::{BlockData, BlockTable, ChunkData, Storage};
+
+    #[test]
+    fn dimension_round_trip() {
+        let database = Connection::open_in_memory().unwrap();
+        create_database(&database);
+        let mut chunk = ChunkData::default();
+        chunk.set(
+            1,
+            2,
+            3,
+            BlockData::new("vinox".to_string(), "stone".to_string()),
+            &BlockTable::default(),
+        );
+        let pos = ChunkPos::new(4, -1, 2);
+        save_chunks(
+            &ChunksToSave(vec![(DimensionId(1), pos, chunk.to_raw())]),
+            &database,
+        );
+
+        let loaded = load_chunk(DimensionId(1), pos, &database).unwrap().unwrap();
+        assert!(!loaded.migrated);
+        let loaded = ChunkData::from_raw(loaded.chunk);
+        assert_eq!(loaded.get_identifier(1, 2, 3), "vinox:stone");
+        assert!(load_chunk(DimensionId(0), pos, &database)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn later_edits_replace_the_saved_chunk() {
+        let database = Connection::open_in_memory().unwrap();
+        create_database(&database);
+        let block_table = BlockTable::default();
+        let stone = BlockData::new("vinox".to_string(), "stone".to_string());
+        let mut chunk = ChunkData::default();
+        chunk.set(0, 0, 0, stone.clone(), &block_table);
+        let pos = ChunkPos::new(-3, 0, 7);
+        let save = |chunk: &ChunkData| {
+            save_chunks(
+                &ChunksToSave(vec![(DimensionId(0), pos, chunk.to_raw())]),
+                &database,
+            )
+        };
+        save(&chunk);
+
+        // A player's edit, queued the same way SentBlock does
+        chunk.set(0, 0, 0, BlockData::default(), &block_table);
+        chunk.set(15, 15, 15, stone, &block_table);
+        save(&chunk);
+
+        let record: Vec<u8> = database
+            .query_row(
+                "SELECT data FROM blocks WHERE posx = ?1 AND posy = ?2 AND posz = ?3",
+                params![&pos.x, &pos.y, &pos.z],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(split_header(&record).unwrap().0, CHUNK_FORMAT_VERSION);
+        let loaded = ChunkData::from_raw(
+            load_chunk(DimensionId(0), pos, &database)
+                .unwrap()
+                .unwrap()
+                .chunk,
+        );
+        assert_eq!(loaded.get_identifier(0, 0, 0), "vinox:air");
+        assert_eq!(loaded.get_identifier(15, 15, 15), "vinox:stone");
+    }
+
+    #[test]
+    fn migrates_old_saves() {
+        let database = Connection::open_in_memory().unwrap();
+        database
+            .execute(
+                " create table blocks (
+            posx integer not null,
+            posy integer not null,
+            posz integer not null,
+            data blob,
+            PRIMARY KEY (posx, posy, posz)
+        )",
+                [],
+            )
+            .unwrap();
+        let pos = ChunkPos::new(0, 0, 0);
+        save_chunks(
+            &ChunksToSave(vec![(DimensionId(0), pos, ChunkData::default().to_raw())]),
+            &database,
+        );
+        create_database(&database);
+
+        let edit_log = EditLog {
+            truncated_at: Some(10),
+            ..Default::default()
+        };
+        save_edit_logs(
+            &EditLogsToSave(vec![(DimensionId(0), pos, edit_log.clone())]),
+            &database,
+        );
+        save_chunks(
+            &ChunksToSave(vec![(DimensionId(0), pos, ChunkData::default().to_raw())]),
+            &database,
+        );
+        assert!(load_chunk(DimensionId(0), pos, &database)
+            .unwrap()
+            .is_some());
+        assert_eq!(load_edit_log(DimensionId(0), pos, &database), edit_log);
+        assert_eq!(
+            load_snapshots(DimensionId(0), pos, &database),
+            ChunkSnapshots::default()
+        );
+        let mut snapshots = ChunkSnapshots::default();
+        snapshots.push_back(ChunkSnapshot::take(&ChunkData::default(), &edit_log, 20).unwrap());
+        save_snapshots(
+            &SnapshotsToSave(vec![(DimensionId(0), pos, snapshots.clone())]),
+            &database,
+        );
+        assert_eq!(load_snapshots(DimensionId(0), pos, &database), snapshots);
+        assert_eq!(load_edit_log(DimensionId(0), pos, &database), edit_log);
+        let version: i32 = database
+            .query_row("PRAGMA user_version;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SAVE_VERSION);
+        assert!(load_unlocked_recipes("someone", &database).is_empty());
+    }
+
+    #[test]
+    fn unlocked_recipes_round_trip() {
+        let database = Connection::open_in_memory().unwrap();
+        create_database(&database);
+        let unlocked: HashSet<String> = ["vinox:glass", "vinox:shovel"].map(str::to_string).into();
+        save_unlocked_recipes(
+            &RecipesToSave(vec![("player".to_string(), unlocked.clone())]),
+            &database,
+        );
+        assert_eq!(load_unlocked_recipes("player", &database), unlocked);
+        assert!(load_unlocked_recipes("other", &database).is_empty());
+
+        // Later saves replace the set instead of adding a row
+        let mut more = unlocked;
+        more.insert("vinox:pole".to_string());
+        save_unlocked_recipes(
+            &RecipesToSave(vec![("player".to_string(), more.clone())]),
+            &database,
+        );
+        assert_eq!(load_unlocked_recipes("player", &database), more);
+    }
+
+    #[test]
+    fn spawn_points_round_trip() {
+        let database = Connection::open_in_memory().unwrap();
+        create_database(&database);
+        let spawn_point = SpawnPoint {
+            dimension: DimensionId(1),
+            anchor: IVec3::new(4, 70, -12),
+        };
+        save_spawn_points(
+            &SpawnPointsToSave(vec![("player".to_string(), Some(spawn_point))]),
+            &database,
+        );
+        assert_eq!(load_spawn_point("player", &database), Some(spawn_point));
+        assert_eq!(load_spawn_point("other", &database), None);
+        save_spawn_points(
+            &SpawnPointsToSave(vec![("player".to_string(), None)]),
+            &database,
+        );
+        assert_eq!(load_spawn_point("player", &database), None);
+    }
+
+    #[test]
+    fn corrupt_chunk_fails_alone() {
+        let database = Connection::open_in_memory().unwrap();
+        create_database(&database);
+        let mut chunk = ChunkData::default();
+        chunk.set(
+            0,
+            0,
+            0,
+            BlockData::new("vinox".to_string(), "stone".to_string()),
+            &BlockTable::default(),
+        );
+        let raw_chunk = chunk.to_raw();
+        let (good, legacy, corrupt, newer) = (
+            ChunkPos::new(0, 0, 0),
+            ChunkPos::new(1, 0, 0),
+            ChunkPos::new(2, 0, 0),
+            ChunkPos::new(3, 0, 0),
+        );
+        save_chunks(
+            &ChunksToSave(vec![(DimensionId(0), good, raw_chunk.clone())]),
+            &database,
+        );
+        // Written the way chunks were before they had a header, which was also before they
+        // had a heightmap so the voxels were all there was
+        let mut voxels = Storage::new(ChunkData::usize());
+        voxels.set(
+            ChunkData::linearize(0, 0, 0),
+            BlockData::new("vinox".to_string(), "stone".to_string()),
+        );
+        let mut legacy_record = Cursor::new(Vec::new());
+        copy_encode(
+            &mut Cursor::new(bincode::serialize(&voxels).unwrap()),
+            &mut legacy_record,
+            0,
+        )
+        .unwrap();
+        let newer_record = with_header(CHUNK_FORMAT_VERSION + 1, &[0; 16]);
+        for (pos, record) in [
+            (legacy, legacy_record.into_inner()),
+            (corrupt, vec![7; 64]),
+            (newer, newer_record),
+        ] {
+            database
+                .execute(
+                    "INSERT INTO blocks (posx, posy, posz, data) values (?1, ?2, ?3, ?4)",
+                    params![&pos.x, &pos.y, &pos.z, &record],
+                )
+                .unwrap();
+        }
+
+        assert!(
+            !load_chunk(DimensionId(0), good, &database)
+                .unwrap()
+                .unwrap()
+                .migrated
+        );
+        let loaded = load_chunk(DimensionId(0), legacy, &database)
+            .unwrap()
+            .unwrap();
+        assert!(loaded.migrated);
+        let loaded = ChunkData::from_raw(loaded.chunk);
+        assert_eq!(loaded.get_identifier(0, 0, 0), "vinox:stone");
+        assert!(loaded.heightmap().is_none());
+        assert!(matches!(
+            load_chunk(DimensionId(0), corrupt, &database),
+            Err(MigrationError::Failed { from: 0, .. })
+        ));
+        assert!(matches!(
+            load_chunk(DimensionId(0), newer, &database),
+            Err(MigrationError::Newer { .. })
+        ));
+
+        let mut last_progress = (0, 0);
+        let report = upgrade_world(&database, |done, total| last_progress = (done, total));
+        assert_eq!(last_progress, (4, 4));
+        assert_eq!((report.upgraded, report.current), (1, 1));
+        assert_eq!(report.failed.len(), 2);
+        // The legacy chunk was rewritten in place, the broken ones were left alone
+        assert!(
+            !load_chunk(DimensionId(0), legacy, &database)
+                .unwrap()
+                .unwrap()
+                .migrated
+        );
+        assert!(load_chunk(DimensionId(0), corrupt, &database).is_err());
+    }
+
+    #[test]
+    fn entities_keep_their_items() {
+        let database = Connection::open_in_memory().unwrap();
+        create_database(&database);
+        let (legacy, current) = (ChunkPos::new(0, 0, 0), ChunkPos::new(1, 0, 0));
+        // Kind as its variant index and the translation, which is all the old format had
+        let legacy_record = bincode::serialize(&vec![(0u32, Vec3::new(1.0, 2.0, 3.0))]).unwrap();
+        database
+            .execute(
+                "INSERT INTO saved_entities (posx, posy, posz, data) values (?1, ?2, ?3, ?4)",
+                params![&legacy.x, &legacy.y, &legacy.z, &legacy_record],
+            )
+            .unwrap();
+        let item = ItemData {
+            namespace: "vinox".to_string(),
+            name: "dirt".to_string(),
+            stack_size: 12,
+            ..Default::default()
+        };
+        save_entities(
+            &EntitiesToSave(vec![(
+                DimensionId(0),
+                current,
+                vec![SavedEntity {
+                    kind: EntityKind::DroppedItem,
+                    translation: Vec3::ZERO,
+                    item: Some(item.clone()),
+                }],
+            )]),
+            &database,
+        );
+
+        let critters = take_entities(DimensionId(0), legacy, &database);
+        assert_eq!(critters.len(), 1);
+        assert_eq!(critters[0].kind, EntityKind::Critter);
+        assert_eq!(critters[0].translation, Vec3::new(1.0, 2.0, 3.0));
+        assert!(critters[0].item.is_none());
+        let dropped = take_entities(DimensionId(0), current, &database);
+        assert_eq!(dropped[0].item, Some(item));
+        // Taken means gone
+        assert!(take_entities(DimensionId(0), current, &database).is_empty());
+    }
+
+    #[test]
+    fn reads_items_saved_before_metadata() {
+        // DroppedItem and a stack laid out the way ItemData was before it had metadata
+        let old_item = (
+            "vinox".to_string(),
+            "dirt".to_string(),
+            7u32,
+            0u32,
+            None::<String>,
+        );
+        let payload =
+            bincode::serialize(&vec![(1u32, Vec3::new(4.0, 5.0, 6.0), Some(old_item))]).unwrap();
+        let entities = decode_entities(&with_header(1, &payload)).unwrap();
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].kind, EntityKind::DroppedItem);
+        assert_eq!(entities[0].translation, Vec3::new(4.0, 5.0, 6.0));
+        let item = entities[0].item.clone().unwrap();
+        assert_eq!((item.name.as_str(), item.stack_size), ("dirt", 7));
+        assert!(item.metadata.is_none());
+    }
+
+    #[test]
+    fn reads_items_saved_with_their_own_durability() {
+        let mut pickaxe = ItemData {
+            namespace: "vinox".to_string(),
+            name: "pickaxe".to_string(),
+            stack_size: 1,
+            ..Default::default()
+        };
+        pickaxe.rename(Some("Digger".to_string()));
+        let old_item = (
+            pickaxe.namespace.clone(),
+            pickaxe.name.clone(),
+            1u32,
+            250u32,
+            None::<String>,
+            pickaxe.metadata.clone(),
+        );
+        let payload = bincode::serialize(&vec![(1u32, Vec3::ZERO, Some(old_item))]).unwrap();
+        let entities = decode_entities(&with_header(2, &payload)).unwrap();
+        assert_eq!(entities[0].item, Some(pickaxe));
+    }
 }
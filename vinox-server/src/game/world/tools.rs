@@ -0,0 +1,79 @@
+use vinox_common::{
+    storage::items::descriptor::ItemData,
+    world::chunks::storage::{name_to_identifier, BlockData, ItemTable},
+};
+
+// What the held tool becomes after an accepted edit, None when the edit doesn't wear it.
+// Some(None) means it broke
+pub fn wear_on_edit(
+    previous: &BlockData,
+    new: &BlockData,
+    tool: &ItemData,
+    item_table: &ItemTable,
+) -> Option<Option<ItemData>> {
+    let broke_block = *new == BlockData::default() && *previous != BlockData::default();
+    if !broke_block {
+        return None;
+    }
+    let max = item_table
+        .get(&name_to_identifier(
+            tool.namespace.clone(),
+            tool.name.clone(),
+        ))?
+        .max_durability
+        .filter(|max| *max > 0)?;
+    Some(tool.clone().wear(max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vinox_common::storage::items::descriptor::{Durability, ItemDescriptor};
+
+    #[test]
+    fn breaks_wear_tools_down() {
+        let mut item_table = ItemTable::default();
+        for (name, max_durability) in [("pickaxe", Some(2)), ("dirt", None)] {
+            item_table.insert(
+                format!("vinox:{name}"),
+                ItemDescriptor {
+                    namespace: "vinox".to_string(),
+                    name: name.to_string(),
+                    max_durability,
+                    ..Default::default()
+                },
+            );
+        }
+        let item = |name: &str| ItemData {
+            namespace: "vinox".to_string(),
+            name: name.to_string(),
+            stack_size: 1,
+            ..Default::default()
+        };
+        let stone = BlockData::new("vinox".to_string(), "stone".to_string());
+        let air = BlockData::default();
+
+        let worn = wear_on_edit(&stone, &air, &item("pickaxe"), &item_table)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            worn.worn_durability(),
+            Some(Durability { current: 1, max: 2 })
+        );
+        assert_eq!(wear_on_edit(&stone, &air, &worn, &item_table), Some(None));
+        // Placing, breaking air and items without durability leave it alone
+        assert_eq!(
+            wear_on_edit(&air, &stone, &item("pickaxe"), &item_table),
+            None
+        );
+        assert_eq!(
+            wear_on_edit(&air, &air, &item("pickaxe"), &item_table),
+            None
+        );
+        assert_eq!(wear_on_edit(&stone, &air, &item("dirt"), &item_table), None);
+        assert_eq!(
+            wear_on_edit(&stone, &air, &item("mystery"), &item_table),
+            None
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use bevy::{prelude::*, utils::HashSet};
+use bevy_quinnet::server::Server;
+use rand::Rng;
+use vinox_common::{
+    ecs::{rng::WorldRng, time::ServerTick},
+    networking::protocol::ServerMessage,
+    world::{
+        chunks::{
+            ecs::CurrentChunks,
+            positions::{global_voxel_positions, voxel_to_global_voxel, ChunkPos, DimensionId},
+            storage::{name_to_identifier, BlockData, BlockTable, ChunkData, CHUNK_SIZE},
+        },
+        transitions::{
+            neighbors_within, Surroundings, TransitionCause, TransitionTable,
+            MAX_NEIGHBOR_DISTANCE, RANDOM_TICKS_PER_CHUNK,
+        },
+    },
+};
+
+use crate::game::{
+    load::{LoadSet, ServerFixedUpdate},
+    networking::syncing::get_messages,
+};
+
+use super::{
+    edits::{now_secs, BlockEdit, EditLog},
+    lifecycle::ChunkActivity,
+    storage::{ChunksToSave, EditLogsToSave, WorldInfo},
+};
+
+// Who transitions are logged as, a rollback of a player leaves what the world did alone
+pub const WORLD_ACTOR: &str = "World";
+
+// A block changed by anything but a transition, what's around it might transition now
+pub struct BlockChangedEvent {
+    pub dimension: DimensionId,
+    pub voxel: IVec3,
+}
+
+// A transition or a fresh stamp, only transitions are worth telling clients about
+struct Change {
+    dimension: DimensionId,
+    voxel: IVec3,
+    block: BlockData,
+    broadcast: bool,
+}
+
+const FACES: [IVec3; 7] = [
+    IVec3::ZERO,
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+fn block_at(
+    chunks: &Query<(&mut ChunkData, &mut EditLog)>,
+    current_chunks: &CurrentChunks,
+    dimension: DimensionId,
+    voxel: IVec3,
+) -> Option<BlockData> {
+    let (chunk_pos, voxel_pos) = global_voxel_positions(voxel);
+    let entity = current_chunks.get_entity_in(dimension, ChunkPos(chunk_pos))?;
+    let (chunk, _) = chunks.get(entity).ok()?;
+    Some(chunk.get(voxel_pos.x, voxel_pos.y, voxel_pos.z))
+}
+
+fn is_block(
+    chunks: &Query<(&mut ChunkData, &mut EditLog)>,
+    current_chunks: &CurrentChunks,
+    dimension: DimensionId,
+    voxel: IVec3,
+    identifier: &str,
+) -> bool {
+    block_at(chunks, current_chunks, dimension, voxel)
+        .is_some_and(|block| name_to_identifier(block.namespace, block.name) == identifier)
+}
+
+// Brightest light on the block or coming off any of its faces, a solid block is dark inside
+fn light_on(
+    chunks: &Query<(&mut ChunkData, &mut EditLog)>,
+    current_chunks: &CurrentChunks,
+    dimension: DimensionId,
+    voxel: IVec3,
+) -> u8 {
+    FACES
+        .iter()
+        .filter_map(|offset| {
+            let (chunk_pos, voxel_pos) = global_voxel_positions(voxel + *offset);
+            let entity = current_chunks.get_entity_in(dimension, ChunkPos(chunk_pos))?;
+            let (chunk, _) = chunks.get(entity).ok()?;
+            let (x, y, z) = (voxel_pos.x, voxel_pos.y, voxel_pos.z);
+            Some(
+                chunk
+                    .get_sunlight(x, y, z)
+                    .max(chunk.get_torchlight(x, y, z)),
+            )
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn has_rules(transitions: &TransitionTable, block: &BlockData) -> bool {
+    !transitions
+        .rules(&name_to_identifier(
+            block.namespace.clone(),
+            block.name.clone(),
+        ))
+        .is_empty()
+}
+
+// Transitions come from the world, nothing they change sets off further neighbor updates
+#[allow(clippy::too_many_arguments)]
+fn apply_changes(
+    changes: Vec<Change>,
+    server: &mut Server,
+    chunks: &mut Query<(&mut ChunkData, &mut EditLog)>,
+    current_chunks: &CurrentChunks,
+    block_table: &BlockTable,
+    chunks_to_save: &mut ChunksToSave,
+    edit_logs_to_save: &mut EditLogsToSave,
+    retention_secs: u64,
+) {
+    let mut changed_chunks = Vec::new();
+    for change in changes {
+        let (chunk_pos, voxel_pos) = global_voxel_positions(change.voxel);
+        let Some(entity) = current_chunks.get_entity_in(change.dimension, ChunkPos(chunk_pos))
+        else {
+            continue;
+        };
+        let Ok((mut chunk, mut edit_log)) = chunks.get_mut(entity) else {
+            continue;
+        };
+        if change.broadcast {
+            let previous = chunk.get(voxel_pos.x, voxel_pos.y, voxel_pos.z);
+            edit_log.push(
+                BlockEdit {
+                    actor: WORLD_ACTOR.to_string(),
+                    time: now_secs(),
+                    voxel: [voxel_pos.x as u8, voxel_pos.y as u8, voxel_pos.z as u8],
+                    previous,
+                    new: change.block.clone(),
+                },
+                retention_secs,
+            );
+        }
+        chunk.set(
+            voxel_pos.x,
+            voxel_pos.y,
+            voxel_pos.z,
+            change.block.clone(),
+            block_table,
+        );
+        // Stamps alone leave the log as it was
+        match changed_chunks
+            .iter_mut()
+            .find(|(_, _, changed, _)| *changed == entity)
+        {
+            Some((_, _, _, logged)) => *logged |= change.broadcast,
+            None => changed_chunks.push((change.dimension, chunk_pos, entity, change.broadcast)),
+        }
+        if !change.broadcast {
+            continue;
+        }
+        if let Some(endpoint) = server.get_endpoint_mut() {
+            endpoint.try_broadcast_message(ServerMessage::SentBlock {
+                chunk_pos,
+                voxel_pos: [voxel_pos.x as u8, voxel_pos.y as u8, voxel_pos.z as u8],
+                block_type: change.block,
+                dimension: change.dimension,
+                denied: None,
+            });
+        }
+    }
+    // Once per chunk however much changed in it
+    for (dimension, chunk_pos, entity, logged) in changed_chunks {
+        if let Ok((chunk, edit_log)) = chunks.get(entity) {
+            chunks_to_save.push((dimension, ChunkPos(chunk_pos), chunk.to_raw()));
+            if logged {
+                edit_logs_to_save.push((dimension, ChunkPos(chunk_pos), edit_log.clone()));
+            }
+        }
+    }
+}
+
+// Every simulated chunk gets a few voxels rolled per server tick, all off the chunk's own stream
+// so the same world seed grows the same way whatever order chunks come in
+pub fn random_ticks(
+    mut server: ResMut<Server>,
+    mut chunks: Query<(&mut ChunkData, &mut EditLog)>,
+    simulated: Query<(Entity, &ChunkPos, &DimensionId, &ChunkActivity)>,
+    current_chunks: Res<CurrentChunks>,
+    (transitions, block_table, world_info): (Res<TransitionTable>, Res<BlockTable>, Res<WorldInfo>),
+    (world_rng, tick, mut last_tick): (Res<WorldRng>, Res<ServerTick>, Local<Option<ServerTick>>),
+    (mut chunks_to_save, mut edit_logs_to_save): (ResMut<ChunksToSave>, ResMut<EditLogsToSave>),
+) {
+    // Fixed ticks come faster than server ticks, each server tick only rolls once
+    if *last_tick == Some(*tick) {
+        return;
+    }
+    *last_tick = Some(*tick);
+    let mut changes = Vec::new();
+    let mut seen = HashSet::new();
+    for (entity, chunk_pos, dimension, activity) in simulated.iter() {
+        if !activity.simulated {
+            continue;
+        }
+        let Ok((chunk, _)) = chunks.get(entity) else {
+            continue;
+        };
+        let mut rng = world_rng.chunk_tick_stream("random_tick", *dimension, **chunk_pos, *tick);
+        for _ in 0..RANDOM_TICKS_PER_CHUNK {
+            let voxel_pos = UVec3::new(
+                rng.gen_range(0..CHUNK_SIZE as u32),
+                rng.gen_range(0..CHUNK_SIZE as u32),
+                rng.gen_range(0..CHUNK_SIZE as u32),
+            );
+            let mut block = chunk.get(voxel_pos.x, voxel_pos.y, voxel_pos.z);
+            let voxel = voxel_to_global_voxel(voxel_pos, **chunk_pos);
+            if !has_rules(&transitions, &block) || !seen.insert((*dimension, voxel)) {
+                continue;
+            }
+            let surroundings = Surroundings {
+                tick: *tick,
+                light: light_on(&chunks, &current_chunks, *dimension, voxel),
+                near: |identifier: &str, within: u8| {
+                    neighbors_within(voxel, within).any(|neighbor| {
+                        is_block(&chunks, &current_chunks, *dimension, neighbor, identifier)
+                    })
+                },
+            };
+            let next = transitions.transition(
+                &block,
+                TransitionCause::RandomTick,
+                &surroundings,
+                &mut rng,
+                &block_table,
+            );
+            if let Some(next) = next {
+                changes.push(Change {
+                    dimension: *dimension,
+                    voxel,
+                    block: next,
+                    broadcast: true,
+                });
+            } else if transitions.needs_stamp(&block) {
+                // Its clock starts now, clients never see the stamp so it's only saved
+                transitions.stamp(&mut block, *tick);
+                changes.push(Change {
+                    dimension: *dimension,
+                    voxel,
+                    block,
+                    broadcast: false,
+                });
+            }
+        }
+    }
+    apply_changes(
+        changes,
+        &mut server,
+        &mut chunks,
+        &current_chunks,
+        &block_table,
+        &mut chunks_to_save,
+        &mut edit_logs_to_save,
+        world_info.edit_retention_secs(),
+    );
+}
+
+// Only NeighborIs rules can start to hold when something changes. The changed block looks all
+// around itself, everything else close by only looks at the changed block
+pub fn neighbor_transitions(
+    mut server: ResMut<Server>,
+    mut events: EventReader<BlockChangedEvent>,
+    mut chunks: Query<(&mut ChunkData, &mut EditLog)>,
+    current_chunks: Res<CurrentChunks>,
+    (transitions, block_table, world_info): (Res<TransitionTable>, Res<BlockTable>, Res<WorldInfo>),
+    (world_rng, tick): (Res<WorldRng>, Res<ServerTick>),
+    (mut chunks_to_save, mut edit_logs_to_save): (ResMut<ChunksToSave>, ResMut<EditLogsToSave>),
+) {
+    let mut changes = Vec::new();
+    let mut seen = HashSet::new();
+    // NeighborUpdate never rolls, the stream is only there to satisfy transition
+    let mut rng = world_rng.tick_stream("neighbor_update", *tick);
+    for evt in events.iter() {
+        let dimension = evt.dimension;
+        let Some(changed) = block_at(&chunks, &current_chunks, dimension, evt.voxel) else {
+            continue;
+        };
+        let changed_identifier =
+            name_to_identifier(changed.namespace.clone(), changed.name.clone());
+        let around =
+            std::iter::once(evt.voxel).chain(neighbors_within(evt.voxel, MAX_NEIGHBOR_DISTANCE));
+        for voxel in around {
+            if seen.contains(&(dimension, voxel)) {
+                continue;
+            }
+            let Some(block) = block_at(&chunks, &current_chunks, dimension, voxel) else {
+                continue;
+            };
+            if !has_rules(&transitions, &block) {
+                continue;
+            }
+            let next = if voxel == evt.voxel {
+                let near = |identifier: &str, within: u8| {
+                    neighbors_within(voxel, within).any(|neighbor| {
+                        is_block(&chunks, &current_chunks, dimension, neighbor, identifier)
+                    })
+                };
+                transitions.transition(
+                    &block,
+                    TransitionCause::NeighborUpdate,
+                    &Surroundings {
+                        tick: *tick,
+                        light: 0,
+                        near,
+                    },
+                    &mut rng,
+                    &block_table,
+                )
+            } else {
+                let distance = (evt.voxel - voxel).abs().max_element() as u8;
+                let near = |identifier: &str, within: u8| {
+                    distance <= within && changed_identifier == identifier
+                };
+                transitions.transition(
+                    &block,
+                    TransitionCause::NeighborUpdate,
+                    &Surroundings {
+                        tick: *tick,
+                        light: 0,
+                        near,
+                    },
+                    &mut rng,
+                    &block_table,
+                )
+            };
+            if let Some(next) = next {
+                seen.insert((dimension, voxel));
+                changes.push(Change {
+                    dimension,
+                    voxel,
+                    block: next,
+                    broadcast: true,
+                });
+            }
+        }
+    }
+    apply_changes(
+        changes,
+        &mut server,
+        &mut chunks,
+        &current_chunks,
+        &block_table,
+        &mut chunks_to_save,
+        &mut edit_logs_to_save,
+        world_info.edit_retention_secs(),
+    );
+}
+
+pub struct TransitionPlugin;
+
+impl Plugin for TransitionPlugin {
+    fn build(&self, app: &mut App) {
+        // Replaced once the blocks are loaded, empty until then
+        app.init_resource::<TransitionTable>()
+            .add_event::<BlockChangedEvent>()
+            .add_system(neighbor_transitions.after(get_messages))
+            .add_system(
+                random_ticks
+                    .in_set(LoadSet::Stepped)
+                    .in_schedule(ServerFixedUpdate),
+            );
+    }
+}
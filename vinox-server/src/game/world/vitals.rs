@@ -0,0 +1,389 @@
+use bevy::prelude::*;
+use bevy_quinnet::server::Server;
+use vinox_common::{
+    ecs::{
+        bundles::{ClientName, Health, Hunger},
+        gameplay::GameplayRules,
+    },
+    networking::protocol::{ChatCategory, Player, ServerMessage},
+    world::chunks::{
+        ecs::CurrentChunks,
+        positions::{global_voxel_positions, ChunkPos, DimensionId},
+        storage::{BlockTable, ChunkData},
+    },
+};
+
+use crate::game::{
+    load::{ServerFixedUpdate, FIXED_STEP},
+    networking::{commands::is_operator, components::LocalGame, identity::PlayerIdentity},
+};
+
+use super::storage::WorldInfo;
+
+// A player who hasn't moved for this long has landed on whatever they were falling onto
+pub const SETTLE_SECS: f32 = 0.25;
+// Anything faster is a teleport or a respawn rather than a fall
+pub const TELEPORT_SPEED: f32 = 100.0;
+// Blocks a second, between walking and sprinting on foot
+pub const MOVING_SPEED: f32 = 0.5;
+pub const SPRINTING_SPEED: f32 = 7.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DamageCause {
+    Fall,
+    Starvation,
+}
+
+impl DamageCause {
+    fn death_message(&self) -> &'static str {
+        match self {
+            DamageCause::Fall => "fell too far",
+            DamageCause::Starvation => "starved",
+        }
+    }
+}
+
+pub struct DamageEvent {
+    pub entity: Entity,
+    pub amount: f32,
+    pub cause: DamageCause,
+}
+
+// Server side bookkeeping behind a player's health and hunger, never sent anywhere
+#[derive(Component, Debug, Default)]
+pub struct Vitals {
+    last_position: Option<Vec3>,
+    // Seconds since the position last changed, updates only come in as often as the client sends
+    still: f32,
+    // Horizontal blocks a second, smoothed over a few updates
+    speed: f32,
+    // Where the current fall started, None on the ground or going up
+    fall_from: Option<f32>,
+    // Fractions of a point built up towards the next whole one
+    regen: f32,
+    exhaustion: f32,
+    starving: f32,
+    // Seconds left in which damage is ignored
+    invulnerable: f32,
+}
+
+// What a tick did to the player, damage still has to go through Vitals::hurt
+#[derive(Debug, Default, PartialEq)]
+pub struct TickOutcome {
+    pub healed: f32,
+    pub hunger_lost: f32,
+    pub damage: Vec<(f32, DamageCause)>,
+}
+
+// Whole points out of a running total, the remainder carries over to the next tick
+fn take_points(total: &mut f32) -> f32 {
+    let points = total.floor();
+    *total -= points;
+    points
+}
+
+impl Vitals {
+    // Takes the hit unless the last one landed too recently, true if it did
+    pub fn hurt(&mut self, health: &mut Health, amount: f32, rules: &GameplayRules) -> bool {
+        if amount <= 0.0 || health.current <= 0.0 || self.invulnerable > 0.0 {
+            return false;
+        }
+        health.current = (health.current - amount).max(0.0);
+        self.invulnerable = rules.invulnerable_secs;
+        true
+    }
+
+    // Cushioned is whether the player is in water or on a ladder, either of which ends a fall
+    pub fn tick(
+        &mut self,
+        position: Vec3,
+        cushioned: bool,
+        health: &Health,
+        hunger: &Hunger,
+        rules: &GameplayRules,
+        dt: f32,
+    ) -> TickOutcome {
+        let mut outcome = TickOutcome::default();
+        self.invulnerable = (self.invulnerable - dt).max(0.0);
+        if health.current <= 0.0 {
+            *self = Vitals {
+                invulnerable: self.invulnerable,
+                ..default()
+            };
+            return outcome;
+        }
+
+        let last = self.last_position.replace(position).unwrap_or(position);
+        let moved = position - last;
+        let mut landed = None;
+        if moved == Vec3::ZERO {
+            self.still += dt;
+            if self.still >= SETTLE_SECS {
+                self.speed = 0.0;
+                landed = self.fall_from.take();
+            }
+        } else if moved.length() / (self.still + dt) > TELEPORT_SPEED {
+            self.still = 0.0;
+            self.fall_from = None;
+        } else {
+            let speed = Vec2::new(moved.x, moved.z).length() / (self.still + dt);
+            self.speed += (speed - self.speed) * 0.25;
+            self.still = 0.0;
+            if moved.y < 0.0 {
+                self.fall_from.get_or_insert(last.y);
+            } else {
+                landed = self.fall_from.take();
+            }
+        }
+        if cushioned {
+            landed = None;
+            self.fall_from = None;
+        }
+        if let Some(from) = landed {
+            let past_safe = from - position.y - rules.fall_damage_height;
+            if past_safe > 0.0 && rules.fall_damage_per_block > 0.0 {
+                outcome
+                    .damage
+                    .push((past_safe * rules.fall_damage_per_block, DamageCause::Fall));
+            }
+        }
+
+        let activity = if self.speed >= SPRINTING_SPEED {
+            rules.sprinting_hunger
+        } else if self.speed >= MOVING_SPEED {
+            rules.walking_hunger
+        } else {
+            rules.idle_hunger
+        };
+        self.exhaustion += rules.hunger_per_sec * activity * dt;
+        outcome.hunger_lost = take_points(&mut self.exhaustion).min(hunger.current);
+
+        if rules.regenerating(health.current, health.max, hunger.current) {
+            self.regen += rules.regen_per_sec * dt;
+            outcome.healed = take_points(&mut self.regen).min(health.max - health.current);
+        } else {
+            self.regen = 0.0;
+        }
+
+        if rules.starvation && hunger.current <= 0.0 {
+            self.starving += rules.starvation_per_sec * dt;
+            let points = take_points(&mut self.starving);
+            if points > 0.0 {
+                outcome.damage.push((points, DamageCause::Starvation));
+            }
+        } else {
+            self.starving = 0.0;
+        }
+        outcome
+    }
+}
+
+// Water and ladders, loaded chunks only since players never stand in unloaded ones
+fn cushioned(
+    dimension: DimensionId,
+    position: Vec3,
+    current_chunks: &CurrentChunks,
+    chunks: &Query<&ChunkData>,
+    block_table: &BlockTable,
+) -> bool {
+    let (chunk_pos, local_pos) = global_voxel_positions(position.floor().as_ivec3());
+    let Some(chunk) = current_chunks
+        .get_entity_in(dimension, ChunkPos(chunk_pos))
+        .and_then(|entity| chunks.get(entity).ok())
+    else {
+        return false;
+    };
+    block_table
+        .get(&chunk.get_identifier(local_pos.x, local_pos.y, local_pos.z))
+        .is_some_and(|descriptor| {
+            descriptor.fluid.unwrap_or(false) || descriptor.climbable.unwrap_or(false)
+        })
+}
+
+// Every tick reads the rules once, a reload only ever lands between ticks
+#[allow(clippy::type_complexity)]
+pub fn tick_vitals(
+    rules: Res<GameplayRules>,
+    mut players: Query<
+        (
+            Entity,
+            &Transform,
+            &DimensionId,
+            &mut Health,
+            &mut Hunger,
+            &mut Vitals,
+        ),
+        With<Player>,
+    >,
+    (current_chunks, chunks, block_table): (Res<CurrentChunks>, Query<&ChunkData>, Res<BlockTable>),
+    mut damage: EventWriter<DamageEvent>,
+) {
+    let dt = FIXED_STEP.as_secs_f32();
+    for (entity, transform, dimension, mut health, mut hunger, mut vitals) in players.iter_mut() {
+        let position = transform.translation;
+        let in_cushion = cushioned(*dimension, position, &current_chunks, &chunks, &block_table);
+        let outcome = vitals.tick(position, in_cushion, &health, &hunger, &rules, dt);
+        // Only touched when a whole point changes so stats aren't resent every tick
+        if outcome.healed > 0.0 {
+            health.current += outcome.healed;
+        }
+        if outcome.hunger_lost > 0.0 {
+            hunger.current -= outcome.hunger_lost;
+        }
+        for (amount, cause) in outcome.damage {
+            damage.send(DamageEvent {
+                entity,
+                amount,
+                cause,
+            });
+        }
+    }
+}
+
+pub fn apply_damage(
+    rules: Res<GameplayRules>,
+    mut events: EventReader<DamageEvent>,
+    mut players: Query<(&PlayerIdentity, &ClientName, &mut Health, &mut Vitals)>,
+    (world_info, local_game): (Res<WorldInfo>, Res<LocalGame>),
+    mut server: ResMut<Server>,
+) {
+    for evt in events.iter() {
+        let Ok((identity, name, mut health, mut vitals)) = players.get_mut(evt.entity) else {
+            continue;
+        };
+        // Creative players can't be hurt and neither can anyone in a world with damage off
+        if !world_info.damage || is_operator(identity.storage_key(), &world_info, &local_game) {
+            continue;
+        }
+        // A copy so a hit that's ignored doesn't count as a change to the health
+        let mut after = *health;
+        if !vitals.hurt(&mut after, evt.amount, &rules) {
+            continue;
+        }
+        *health = after;
+        if health.current > 0.0 {
+            continue;
+        }
+        let message = format!("{} {}", **name, evt.cause.death_message());
+        println!("{message}");
+        if let Some(endpoint) = server.get_endpoint_mut() {
+            endpoint.try_broadcast_message_on(
+                bevy_quinnet::shared::channel::ChannelId::OrderedReliable(1),
+                ServerMessage::ChatMessage {
+                    user_name: "Server".to_string(),
+                    message,
+                    id: 0,
+                    category: ChatCategory::Death,
+                },
+            );
+        }
+    }
+}
+
+pub struct VitalsPlugin;
+
+impl Plugin for VitalsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageEvent>().add_systems(
+            (tick_vitals, apply_damage)
+                .chain()
+                .in_schedule(ServerFixedUpdate),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    #[test]
+    fn hits_inside_the_window_are_ignored() {
+        let rules = GameplayRules {
+            invulnerable_secs: 0.5,
+            ..default()
+        };
+        let mut vitals = Vitals::default();
+        let mut health = Health::default();
+        assert!(vitals.hurt(&mut health, 4.0, &rules));
+        assert_eq!(health.current, 16.0);
+        // Straight after and most of the way through the window, nothing gets through
+        assert!(!vitals.hurt(&mut health, 4.0, &rules));
+        for _ in 0..25 {
+            vitals.tick(Vec3::ZERO, false, &health, &Hunger::default(), &rules, DT);
+        }
+        assert!(!vitals.hurt(&mut health, 4.0, &rules));
+        assert_eq!(health.current, 16.0);
+        for _ in 0..6 {
+            vitals.tick(Vec3::ZERO, false, &health, &Hunger::default(), &rules, DT);
+        }
+        assert!(vitals.hurt(&mut health, 4.0, &rules));
+        assert_eq!(health.current, 12.0);
+
+        // No window at all lets every hit land
+        let rules = GameplayRules {
+            invulnerable_secs: 0.0,
+            ..default()
+        };
+        let mut vitals = Vitals::default();
+        assert!(vitals.hurt(&mut health, 1.0, &rules));
+        assert!(vitals.hurt(&mut health, 1.0, &rules));
+        assert_eq!(health.current, 10.0);
+    }
+
+    fn fall(vitals: &mut Vitals, from: f32, to: f32, cushioned: bool) -> Vec<(f32, DamageCause)> {
+        let (health, hunger, rules) = (Health::default(), Hunger::default(), default());
+        let mut damage = Vec::new();
+        let mut y = from;
+        while y > to {
+            damage.extend(
+                vitals
+                    .tick(Vec3::new(0.0, y, 0.0), false, &health, &hunger, &rules, DT)
+                    .damage,
+            );
+            y = (y - 0.5).max(to);
+        }
+        // Landed and standing still until the fall is settled
+        for _ in 0..30 {
+            let position = Vec3::new(0.0, to, 0.0);
+            damage.extend(
+                vitals
+                    .tick(position, cushioned, &health, &hunger, &rules, DT)
+                    .damage,
+            );
+        }
+        damage
+    }
+
+    #[test]
+    fn falls_hurt_past_the_safe_height() {
+        let mut vitals = Vitals::default();
+        assert_eq!(fall(&mut vitals, 73.0, 70.0, false), Vec::new());
+        assert_eq!(
+            fall(&mut vitals, 80.0, 70.0, false),
+            vec![(7.0, DamageCause::Fall)]
+        );
+        // Water breaks the fall
+        assert_eq!(fall(&mut vitals, 90.0, 70.0, true), Vec::new());
+
+        // A respawn far below isn't a fall
+        let (health, hunger, rules) = (Health::default(), Hunger::default(), default());
+        let mut vitals = Vitals::default();
+        for y in [200.0, 75.0, 75.0] {
+            let outcome = vitals.tick(Vec3::new(0.0, y, 0.0), false, &health, &hunger, &rules, DT);
+            assert!(outcome.damage.is_empty());
+        }
+        for _ in 0..30 {
+            let outcome = vitals.tick(
+                Vec3::new(0.0, 75.0, 0.0),
+                false,
+                &health,
+                &hunger,
+                &rules,
+                DT,
+            );
+            assert!(outcome.damage.is_empty());
+        }
+    }
+}
@@ -5,9 +5,24 @@ use bevy::{
 use bevy_quinnet::server::QuinnetServerPlugin;
 use directories::*;
 use game::{
-    networking::components::{ChunkLimit, LocalGame, SaveGame},
+    networking::{
+        components::{ChunkLimit, LocalGame, SaveGame},
+        identity::DuplicateNames,
+    },
     plugin::GamePlugin,
-    world::storage::{create_database, WorldDatabase, WorldInfo},
+    world::{
+        gameplay::GameplayPath,
+        generation::TerrainShape,
+        lifecycle::ChunkLifecycle,
+        migration::{check_world_version, CHUNK_FORMAT_VERSION},
+        noise_graph::NoiseSelection,
+        safe_spawn::SpawnSearch,
+        snapshots::SnapshotPolicy,
+        spawn_rules::{SpawnRules, WorldInfoPath},
+        storage::{
+            create_database, new_world_id, DimensionConfig, GeneratorKind, WorldDatabase, WorldInfo,
+        },
+    },
 };
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
@@ -21,7 +36,10 @@ use std::{
     path::PathBuf,
     time::Duration,
 };
-use vinox_common::networking::protocol::NetworkIP;
+use vinox_common::{
+    ecs::rng::WorldRng, networking::protocol::NetworkIP, storage::content::ContentPolicy,
+    world::chunks::positions::DimensionId,
+};
 
 // Server should always keep spawn chunks loaded and any chunks near players
 pub fn create_server() {
@@ -56,7 +74,7 @@ pub fn create_server() {
     let mut final_world_name = "worlds/".to_string();
     final_world_name.push_str(&world_name);
     asset_path.push(final_world_name);
-    let final_world_info = if let Some(world_info) =
+    let mut final_world_info = if let Some(world_info) =
         load_world_info(format!("{}.ron", asset_path.clone().display()).into())
     {
         world_info
@@ -65,6 +83,23 @@ pub fn create_server() {
             name: world_name.clone(),
             seed: rand::thread_rng().gen_range(0..=u32::MAX),
             damage: false,
+            dimensions: vec![DimensionConfig {
+                id: DimensionId(0),
+                generator: GeneratorKind::Overworld,
+            }],
+            moderators: Vec::new(),
+            edit_retention_hours: 24,
+            snapshots: SnapshotPolicy::default(),
+            format_version: CHUNK_FORMAT_VERSION,
+            content_policy: ContentPolicy::default(),
+            spawn_rules: SpawnRules::default(),
+            noise: NoiseSelection::default(),
+            terrain: TerrainShape::default(),
+            chunk_lifecycle: ChunkLifecycle::default(),
+            duplicate_names: DuplicateNames::default(),
+            world_id: new_world_id(&mut rand::thread_rng()),
+            spawn_search: SpawnSearch::default(),
+            spawn: None,
         };
         save_world_info(
             world.clone(),
@@ -72,6 +107,18 @@ pub fn create_server() {
         );
         world
     };
+    if let Err(error) = check_world_version(final_world_info.format_version) {
+        println!("Can't open {world_name}: {error}");
+        return;
+    }
+    let upgraded = final_world_info.format_version < CHUNK_FORMAT_VERSION;
+    final_world_info.format_version = final_world_info.format_version.max(CHUNK_FORMAT_VERSION);
+    if final_world_info.assign_world_id() || upgraded {
+        save_world_info(
+            final_world_info.clone(),
+            format!("{}.ron", asset_path.display()).into(),
+        );
+    }
     let manager = SqliteConnectionManager::file(format!("{}.db", asset_path.display()));
     let pool = Pool::builder()
         .max_size(30)
@@ -90,12 +137,17 @@ pub fn create_server() {
         .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
             1.0 / 60.0,
         )))
+        .insert_resource(WorldRng::new(final_world_info.seed as u64))
         .insert_resource(final_world_info)
+        .insert_resource(WorldInfoPath(
+            format!("{}.ron", asset_path.display()).into(),
+        ))
         .insert_resource(WorldDatabase { connection: pool })
         .insert_resource(NetworkIP(ip))
         .insert_resource(ChunkLimit(64))
         .insert_resource(LocalGame(true))
-        .insert_resource(SaveGame(false))
+        .insert_resource(SaveGame(true))
+        .insert_resource(GameplayPath(asset_path.with_file_name("gameplay.ron")))
         .add_plugins(MinimalPlugins)
         .add_plugin(DiagnosticsPlugin)
         .add_plugin(LogPlugin::default())
@@ -5,9 +5,29 @@ use bevy::{
 use bevy_quinnet::server::QuinnetServerPlugin;
 use directories::*;
 use game::{
-    networking::components::{ChunkLimit, LocalGame, SaveGame},
+    audit::{audit_grep, AuditConfig, AuditLog},
+    load::{ServerLoad, DEFAULT_MAX_CATCH_UP},
+    networking::{
+        components::{ChunkLimit, LocalGame, SaveGame},
+        console::ConsoleChannel,
+        identity::DuplicateNames,
+    },
     plugin::GamePlugin,
-    world::storage::{create_database, WorldDatabase, WorldInfo},
+    schedule::SchedulePath,
+    world::{
+        gameplay::GameplayPath,
+        generation::TerrainShape,
+        lifecycle::ChunkLifecycle,
+        migration::{check_world_version, CHUNK_FORMAT_VERSION},
+        noise_graph::NoiseSelection,
+        safe_spawn::SpawnSearch,
+        snapshots::SnapshotPolicy,
+        spawn_rules::{SpawnRules, WorldInfoPath},
+        storage::{
+            create_database, new_world_id, upgrade_world, DimensionConfig, GeneratorKind,
+            WorldDatabase, WorldInfo,
+        },
+    },
 };
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
@@ -17,11 +37,19 @@ use ron::ser::{to_string_pretty, PrettyConfig};
 use std::io::Write;
 use std::{
     env,
-    fs::{create_dir_all, File},
-    path::PathBuf,
-    time::Duration,
+    fs::{copy, create_dir_all, File},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use vinox_common::{
+    ecs::rng::WorldRng,
+    networking::{
+        protocol::{NetworkIP, PROTOCOL_VERSION},
+        schema::ProtocolSchema,
+    },
+    storage::content::ContentPolicy,
+    world::chunks::positions::DimensionId,
 };
-use vinox_common::networking::protocol::NetworkIP;
 
 // Server should always keep spawn chunks loaded and any chunks near players
 fn main() {
@@ -37,7 +65,46 @@ fn main() {
         path
     };
 
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    // Upgrades every saved chunk to the current format then exits instead of starting
+    let upgrade = args.iter().any(|arg| arg == "--upgrade-world");
+    args.retain(|arg| arg != "--upgrade-world");
+    // Throwaway worlds, nothing is written and every chunk is generated fresh
+    let save = !args.iter().any(|arg| arg == "--no-save");
+    args.retain(|arg| arg != "--no-save");
+    // Most fixed ticks one slow frame catches up on before the server starts shedding load
+    let max_catch_up = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--max-catch-up=")?.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CATCH_UP);
+    args.retain(|arg| !arg.starts_with("--max-catch-up="));
+    // Prints the audit log lines about a player then exits, the files are in worlds/logs/audit
+    if let Some(at) = args.iter().position(|arg| arg == "--audit-grep") {
+        let (Some(player), Some(range)) = (args.get(at + 1), args.get(at + 2)) else {
+            println!("Usage: vinox-server --audit-grep <player> <date or from..to>");
+            return;
+        };
+        let audit_dir = asset_path.join("worlds").join("logs").join("audit");
+        match audit_grep(&audit_dir, player, range, &mut std::io::stdout().lock()) {
+            Ok(0) => println!("Nothing about {player} in {range}"),
+            Ok(_) => {}
+            Err(e) => println!("{e}"),
+        }
+        return;
+    }
+    // For people writing their own clients and bots, writes every message this version speaks
+    // as JSON to the path given or stdout and exits
+    if let Some(at) = args.iter().position(|arg| arg == "--export-schema") {
+        let schema = serde_json::to_string_pretty(&ProtocolSchema::current()).unwrap();
+        match args.get(at + 1) {
+            Some(path) => match std::fs::write(path, schema) {
+                Ok(()) => println!("Wrote protocol {PROTOCOL_VERSION} to {path}"),
+                Err(e) => println!("Couldn't write the schema to {path}: {e}"),
+            },
+            None => println!("{schema}"),
+        }
+        return;
+    }
 
     let mut ip = "127.0.0.1".to_string();
     let mut world_name = "world".to_string();
@@ -56,7 +123,12 @@ fn main() {
     let mut final_world_name = "worlds/".to_string();
     final_world_name.push_str(&world_name);
     asset_path.push(final_world_name);
-    let final_world_info = if let Some(world_info) =
+    let database_path = PathBuf::from(format!("{}.db", asset_path.display()));
+    if upgrade && !database_path.exists() {
+        println!("There is no saved world named {world_name} to upgrade");
+        return;
+    }
+    let mut final_world_info = if let Some(world_info) =
         load_world_info(format!("{}.ron", asset_path.clone().display()).into())
     {
         world_info
@@ -65,6 +137,23 @@ fn main() {
             name: world_name.clone(),
             seed: rand::thread_rng().gen_range(0..=u32::MAX),
             damage: false,
+            dimensions: vec![DimensionConfig {
+                id: DimensionId(0),
+                generator: GeneratorKind::Overworld,
+            }],
+            moderators: Vec::new(),
+            edit_retention_hours: 24,
+            snapshots: SnapshotPolicy::default(),
+            format_version: CHUNK_FORMAT_VERSION,
+            content_policy: ContentPolicy::default(),
+            spawn_rules: SpawnRules::default(),
+            noise: NoiseSelection::default(),
+            terrain: TerrainShape::default(),
+            chunk_lifecycle: ChunkLifecycle::default(),
+            duplicate_names: DuplicateNames::default(),
+            world_id: new_world_id(&mut rand::thread_rng()),
+            spawn_search: SpawnSearch::default(),
+            spawn: None,
         };
         save_world_info(
             world.clone(),
@@ -72,7 +161,20 @@ fn main() {
         );
         world
     };
-    let manager = SqliteConnectionManager::file(format!("{}.db", asset_path.display()));
+    if let Err(error) = check_world_version(final_world_info.format_version) {
+        println!("Can't open {world_name}: {error}");
+        return;
+    }
+    if upgrade {
+        match backup_world(&asset_path) {
+            Ok(backup) => println!("Backed up {world_name} to {}", backup.display()),
+            Err(e) => {
+                println!("Not upgrading, backing up {world_name} failed: {e}");
+                return;
+            }
+        }
+    }
+    let manager = SqliteConnectionManager::file(&database_path);
     let pool = Pool::builder()
         .max_size(30)
         .test_on_check_out(false)
@@ -86,16 +188,57 @@ fn main() {
         )
         .ok();
     create_database(&pool.get().unwrap());
+    if upgrade {
+        let report = upgrade_world(&pool.get().unwrap(), |done, total| {
+            if done % 1000 == 0 || done == total {
+                println!("Upgraded {done}/{total} chunks");
+            }
+        });
+        for (table, pos, error) in &report.failed {
+            println!("Couldn't upgrade chunk {:?} in {table}: {error}", **pos);
+        }
+        println!(
+            "Upgrade finished: {} upgraded, {} already current, {} failed",
+            report.upgraded,
+            report.current,
+            report.failed.len()
+        );
+    }
+    // Chunks are written in the current format from here on
+    let upgraded = final_world_info.format_version < CHUNK_FORMAT_VERSION;
+    final_world_info.format_version = final_world_info.format_version.max(CHUNK_FORMAT_VERSION);
+    if final_world_info.assign_world_id() || upgraded {
+        save_world_info(
+            final_world_info.clone(),
+            format!("{}.ron", asset_path.display()).into(),
+        );
+    }
+    if upgrade {
+        return;
+    }
     App::new()
         .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
             1.0 / 60.0,
         )))
+        .insert_resource(WorldRng::new(final_world_info.seed as u64))
         .insert_resource(final_world_info)
+        .insert_resource(WorldInfoPath(
+            format!("{}.ron", asset_path.display()).into(),
+        ))
         .insert_resource(WorldDatabase { connection: pool })
         .insert_resource(ChunkLimit(16))
+        .insert_resource(ServerLoad::new(max_catch_up))
         .insert_resource(NetworkIP(ip))
         .insert_resource(LocalGame(false))
-        .insert_resource(SaveGame(false))
+        .insert_resource(SaveGame(save))
+        .insert_resource(ConsoleChannel::stdin())
+        // Shared by every world in the folder, it's the server being looked after
+        .insert_resource(SchedulePath(asset_path.with_file_name("schedule.ron")))
+        .insert_resource(GameplayPath(asset_path.with_file_name("gameplay.ron")))
+        .insert_resource(AuditLog::start(
+            asset_path.with_file_name("logs").join("audit"),
+            AuditConfig::load(&asset_path.with_file_name("audit.ron")),
+        ))
         .add_plugins(MinimalPlugins)
         .add_plugin(DiagnosticsPlugin)
         .add_plugin(LogPlugin::default())
@@ -121,6 +264,28 @@ pub fn save_world_info(world_info: WorldInfo, path: PathBuf) {
     }
 }
 
+// Copies the world's files into a fresh folder next to it, the database is closed at this point
+// so its write-ahead log is copied along rather than checkpointed
+fn backup_world(world_path: &Path) -> std::io::Result<PathBuf> {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let world_name = world_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let backup = world_path.with_file_name(format!("{world_name}-backup-{stamp}"));
+    create_dir_all(&backup)?;
+    for extension in ["db", "db-wal", "db-shm", "ron"] {
+        let file = PathBuf::from(format!("{}.{extension}", world_path.display()));
+        if file.exists() {
+            copy(&file, backup.join(format!("{world_name}.{extension}")))?;
+        }
+    }
+    Ok(backup)
+}
+
 fn load_world_info(path: PathBuf) -> Option<WorldInfo> {
     if let Ok(f) = File::open(path) {
         let world_info: Option<WorldInfo> = match from_reader(f) {